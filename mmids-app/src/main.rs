@@ -3,33 +3,85 @@ mod http_handlers;
 use hyper::Method;
 use mmids_core::config::{parse as parse_config_file, MmidsConfig};
 use mmids_core::event_hub::{start_event_hub, PublishEventRequest, SubscriptionRequest};
-use mmids_core::net::tcp::{start_socket_manager, TlsOptions};
+use mmids_core::net::tcp::{start_socket_manager, TcpSocketRequest, TlsOptions};
 use mmids_core::reactors::executors::simple_http_executor::SimpleHttpExecutorGenerator;
 use mmids_core::reactors::executors::ReactorExecutorFactory;
 use mmids_core::reactors::manager::{
     start_reactor_manager, CreateReactorResult, ReactorManagerRequest,
 };
+use mmids_core::url_signing::UrlSigner;
 use mmids_core::workflows::definitions::WorkflowStepType;
+use mmids_core::workflows::kv_source::{self, ConsulWatcher};
+use mmids_core::workflows::log_levels::{WorkflowLogFilter, WorkflowLogLevels};
 use mmids_core::workflows::manager::{
-    start_workflow_manager, WorkflowManagerRequest, WorkflowManagerRequestOperation,
+    start_workflow_manager, StreamReaperSettings, WorkflowManagerRequest,
+    WorkflowManagerRequestOperation,
 };
 use mmids_core::workflows::metadata::common_metadata::{
-    get_is_keyframe_metadata_key, get_pts_offset_metadata_key,
+    get_is_keyframe_metadata_key, get_payload_checksum_metadata_key, get_pts_offset_metadata_key,
 };
 use mmids_core::workflows::metadata::MetadataKeyMap;
+use mmids_core::workflows::steps::admission_control::AdmissionControlStepGenerator;
+use mmids_core::workflows::steps::caption_detect::CaptionDetectStepGenerator;
+use mmids_core::workflows::steps::caption_inject::CaptionInjectStepGenerator;
+use mmids_core::workflows::steps::chaos::ChaosStepGenerator;
+use mmids_core::workflows::steps::checksum::ChecksumStepGenerator;
 use mmids_core::workflows::steps::factory::WorkflowStepFactory;
+use mmids_core::workflows::steps::failover::FailoverStepGenerator;
+use mmids_core::workflows::steps::gop_segmenter::{CompletedGopSegment, GopSegmenterStepGenerator};
+use mmids_core::workflows::steps::h264_framing::H264FramingStepGenerator;
+use mmids_core::workflows::steps::heartbeat::HeartbeatStepGenerator;
+use mmids_core::workflows::steps::ingest_warmup::IngestWarmupStepGenerator;
+use mmids_core::workflows::steps::instant_replay::InstantReplayStepGenerator;
+use mmids_core::workflows::steps::keyframe_only::KeyframeOnlyStepGenerator;
+use mmids_core::workflows::steps::metadata_strip::MetadataStripStepGenerator;
+use mmids_core::workflows::steps::mpegts_demux::MpegTsDemuxStepGenerator;
+use mmids_core::workflows::steps::rate_shape::RateShapeStepGenerator;
+use mmids_core::workflows::steps::remote_forward::RemoteForwardStepGenerator;
+use mmids_core::workflows::steps::remote_ingest::RemoteIngestStepGenerator;
+use mmids_core::workflows::steps::resolution_guard::ResolutionGuardStepGenerator;
+use mmids_core::workflows::steps::session_record::SessionRecordStepGenerator;
+use mmids_core::workflows::steps::session_replay::SessionReplayStepGenerator;
+use mmids_core::workflows::steps::side_channel::SideChannelMessage;
+use mmids_core::workflows::steps::startup_delay::StartupDelayStepGenerator;
+use mmids_core::workflows::steps::startup_order::StartupOrderStepGenerator;
+use mmids_core::workflows::steps::stream_change_monitor::StreamChangeMonitorStepGenerator;
+use mmids_core::workflows::steps::stream_label::StreamLabelStepGenerator;
+use mmids_core::workflows::steps::stream_properties::StreamPropertiesStepGenerator;
+use mmids_core::workflows::steps::stream_tags::StreamTagsStepGenerator;
+use mmids_core::workflows::steps::timestamp_sanitize::TimestampSanitizeStepGenerator;
+use mmids_core::workflows::steps::whep_output::WhepOutputStepGenerator;
 use mmids_core::workflows::steps::workflow_forwarder::WorkflowForwarderStepGenerator;
 use mmids_ffmpeg::endpoint::{start_ffmpeg_endpoint, FfmpegEndpointRequest};
 use mmids_ffmpeg::workflow_steps::ffmpeg_hls::FfmpegHlsStepGenerator;
 use mmids_ffmpeg::workflow_steps::ffmpeg_pull::FfmpegPullStepGenerator;
+use mmids_ffmpeg::workflow_steps::ffmpeg_rtmp_multi_output::FfmpegRtmpMultiOutputStepGenerator;
 use mmids_ffmpeg::workflow_steps::ffmpeg_rtmp_push::FfmpegRtmpPushStepGenerator;
 use mmids_ffmpeg::workflow_steps::ffmpeg_transcode::FfmpegTranscoderStepGenerator;
 use mmids_gstreamer::encoders::{
     AudioCopyEncoderGenerator, AudioDropEncoderGenerator, AvencAacEncoderGenerator, EncoderFactory,
     VideoCopyEncoderGenerator, VideoDropEncoderGenerator, X264EncoderGenerator,
+    X265EncoderGenerator,
 };
 use mmids_gstreamer::endpoints::gst_transcoder::{start_gst_transcoder, GstTranscoderRequest};
+use mmids_gstreamer::steps::audio_channels::AudioChannelsStepGenerator;
+use mmids_gstreamer::steps::audio_levels::AudioLevelsStepGenerator;
+use mmids_gstreamer::steps::audio_mix::AudioMixStepGenerator;
+use mmids_gstreamer::steps::audio_resample::AudioResampleStepGenerator;
+use mmids_gstreamer::steps::audio_watermark::AudioWatermarkStepGenerator;
 use mmids_gstreamer::steps::basic_transcoder::BasicTranscodeStepGenerator;
+use mmids_gstreamer::steps::bitrate_normalize::BitrateNormalizeStepGenerator;
+use mmids_gstreamer::steps::cfr::CfrStepGenerator;
+use mmids_gstreamer::steps::conditional_transcode::ConditionalTranscodeStepGenerator;
+use mmids_gstreamer::steps::custom_gst::CustomGstStepGenerator;
+use mmids_gstreamer::steps::deinterlace::DeinterlaceStepGenerator;
+use mmids_gstreamer::steps::gop_normalize::GopNormalizeStepGenerator;
+use mmids_gstreamer::steps::hdr_tonemap::HdrTonemapStepGenerator;
+use mmids_gstreamer::steps::max_resolution::MaxResolutionStepGenerator;
+use mmids_gstreamer::steps::mjpeg_preview::MjpegPreviewStepGenerator;
+use mmids_gstreamer::steps::pip_composite::PipCompositeStepGenerator;
+use mmids_gstreamer::steps::qc_monitor::QcMonitorStepGenerator;
+use mmids_gstreamer::steps::quality_measure::QualityMeasureStepGenerator;
 use mmids_http_api::handlers;
 use mmids_http_api::routing::{PathPart, Route, RoutingTable};
 use mmids_http_api::HttpApiShutdownSignal;
@@ -40,29 +92,80 @@ use native_tls::Identity;
 use std::env;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::sync::oneshot::{channel, Sender};
+use tokio_util::sync::CancellationToken;
+use tracing::level_filters::LevelFilter;
 use tracing::{info, warn, Level};
-use tracing_subscriber::fmt::writer::MakeWriterExt;
-use tracing_subscriber::{fmt, layer::SubscriberExt};
+use tracing_subscriber::fmt;
+use tracing_subscriber::layer::{Layer, SubscriberExt};
 
 const RTMP_RECEIVE: &str = "rtmp_receive";
 const RTMP_WATCH: &str = "rtmp_watch";
 const FORWARD_STEP: &str = "forward_to_workflow";
 const BASIC_TRANSCODE_STEP: &str = "basic_transcode";
+const QUALITY_MEASURE_STEP: &str = "quality_measure";
+const QC_MONITOR_STEP: &str = "qc_monitor";
+const CUSTOM_GST_STEP: &str = "custom_gst";
+const REMOTE_FORWARD_STEP: &str = "remote_forward";
+const REMOTE_INGEST_STEP: &str = "remote_ingest";
+const SESSION_RECORD_STEP: &str = "session_record";
+const SESSION_REPLAY_STEP: &str = "session_replay";
+const GOP_SEGMENTER_STEP: &str = "gop_segmenter";
+const H264_FRAMING_STEP: &str = "h264_framing";
+const ADMISSION_CONTROL_STEP: &str = "admission_control";
+const RESOLUTION_GUARD_STEP: &str = "resolution_guard";
+const STREAM_LABEL_STEP: &str = "stream_label";
+const FAILOVER_STEP: &str = "failover";
+const INGEST_WARMUP_STEP: &str = "ingest_warmup";
+const STREAM_CHANGE_MONITOR_STEP: &str = "stream_change_monitor";
+const TIMESTAMP_SANITIZE_STEP: &str = "timestamp_sanitize";
+const AUDIO_RESAMPLE_STEP: &str = "audio_resample";
+const CAPTION_INJECT_STEP: &str = "caption_inject";
+const CFR_STEP: &str = "cfr";
+const DEINTERLACE_STEP: &str = "deinterlace";
+const MJPEG_PREVIEW_STEP: &str = "mjpeg_preview";
+const CHECKSUM_STEP: &str = "checksum";
+const AUDIO_MIX_STEP: &str = "audio_mix";
+const PIP_COMPOSITE_STEP: &str = "pip_composite";
+const AUDIO_CHANNELS_STEP: &str = "audio_channels";
+const HEARTBEAT_STEP: &str = "heartbeat";
+const KEYFRAME_ONLY_STEP: &str = "keyframe_only";
+const MPEGTS_DEMUX_STEP: &str = "mpegts_demux";
+const STARTUP_ORDER_STEP: &str = "startup_order";
+const STARTUP_DELAY_STEP: &str = "startup_delay";
+const METADATA_STRIP_STEP: &str = "metadata_strip";
+const RATE_SHAPE_STEP: &str = "rate_shape";
+const CONDITIONAL_TRANSCODE_STEP: &str = "conditional_transcode";
+const WHEP_OUTPUT_STEP: &str = "whep_output";
+const MAX_RESOLUTION_STEP: &str = "max_resolution";
+const CHAOS_STEP: &str = "chaos";
+const HDR_TONEMAP_STEP: &str = "hdr_tonemap";
+const STREAM_PROPERTIES_STEP: &str = "stream_properties";
+const STREAM_TAGS_STEP: &str = "stream_tags";
+const BITRATE_NORMALIZE_STEP: &str = "bitrate_normalize";
+const CAPTION_DETECT_STEP: &str = "caption_detect";
+const GOP_NORMALIZE_STEP: &str = "gop_normalize";
+const AUDIO_WATERMARK_STEP: &str = "audio_watermark";
+const AUDIO_LEVELS_STEP: &str = "audio_levels";
+const INSTANT_REPLAY_STEP: &str = "instant_replay";
 
 // ffmpeg steps will be depreciated at some point
 const FFMPEG_TRANSCODE: &str = "ffmpeg_transcode";
 const FFMPEG_HLS: &str = "ffmpeg_hls";
 const FFMPEG_PUSH: &str = "ffmpeg_push";
 const FFMPEG_PULL: &str = "ffmpeg_pull";
+const RTMP_MULTI_OUTPUT: &str = "rtmp_multi_output";
 
 struct Endpoints {
+    socket_manager: UnboundedSender<TcpSocketRequest>,
     rtmp: UnboundedSender<RtmpEndpointRequest>,
     ffmpeg: UnboundedSender<FfmpegEndpointRequest>,
     gst_transcoder: UnboundedSender<GstTranscoderRequest>,
+    encoder_factory: Arc<EncoderFactory>,
 }
 
 #[tokio::main]
@@ -87,12 +190,27 @@ pub async fn main() {
 
     let appender = tracing_appender::rolling::hourly(app_log_path.clone(), "application.log");
     let (non_blocking, _guard) = tracing_appender::non_blocking(appender);
-    let stdout_writer = std::io::stdout.with_max_level(log_level);
-    let json_writer = non_blocking.with_max_level(log_level);
+
+    // Individual workflows can have their log level raised above the application's at runtime
+    let workflow_log_levels = WorkflowLogLevels::default();
+    let log_filter = WorkflowLogFilter::new(
+        LevelFilter::from_level(log_level),
+        workflow_log_levels.clone(),
+    );
 
     let subscriber = tracing_subscriber::registry()
-        .with(fmt::Layer::new().with_writer(stdout_writer).pretty())
-        .with(fmt::Layer::new().with_writer(json_writer).json());
+        .with(
+            fmt::Layer::new()
+                .with_writer(std::io::stdout)
+                .pretty()
+                .with_filter(log_filter.clone()),
+        )
+        .with(
+            fmt::Layer::new()
+                .with_writer(non_blocking)
+                .json()
+                .with_filter(log_filter),
+        );
 
     tracing::subscriber::set_global_default(subscriber).expect("Unable to set a global collector");
 
@@ -104,24 +222,60 @@ pub async fn main() {
     let config = read_config();
     let tls_options = load_tls_options(&config).await;
     let endpoints = start_endpoints(&config, tls_options, log_dir, &mut metadata_key_map);
-    let (pub_sender, sub_sender) = start_event_hub();
-    let reactor_manager = start_reactor(&config, sub_sender.clone()).await;
+    let shutdown_token = CancellationToken::new();
+    let (pub_sender, sub_sender) = start_event_hub(shutdown_token.child_token());
+    let reactor_manager =
+        start_reactor(&config, sub_sender.clone(), shutdown_token.child_token()).await;
     let step_factory = register_steps(
         endpoints,
         sub_sender,
+        pub_sender.clone(),
         reactor_manager,
         &mut metadata_key_map,
     );
-    let manager = start_workflows(&config, step_factory, pub_sender);
+    let manager = start_workflows(
+        &config,
+        step_factory,
+        pub_sender,
+        workflow_log_levels,
+        shutdown_token.child_token(),
+    );
+    start_kv_workflow_source(&config, manager.clone(), shutdown_token.child_token());
     let http_api_shutdown = start_http_api(&config, manager);
 
-    tokio::signal::ctrl_c()
-        .await
-        .expect("Failed to install ctrl+c signal handler");
+    wait_for_shutdown_signal().await;
+    info!("Shutting down");
 
     if let Some(sender) = http_api_shutdown {
         let _ = sender.send(HttpApiShutdownSignal {});
     }
+
+    shutdown_token.cancel();
+}
+
+/// Waits for ctrl+c, or for SIGTERM on platforms that have it (such as when a container is
+/// being stopped)
+async fn wait_for_shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate =
+            signal(SignalKind::terminate()).expect("Failed to install SIGTERM signal handler");
+
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => {
+                result.expect("Failed to install ctrl+c signal handler");
+            }
+
+            _ = terminate.recv() => (),
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to install ctrl+c signal handler");
 }
 
 fn read_config() -> MmidsConfig {
@@ -146,6 +300,7 @@ fn get_log_directory() -> String {
 fn register_steps(
     endpoints: Endpoints,
     subscription_sender: UnboundedSender<SubscriptionRequest>,
+    event_publisher: UnboundedSender<PublishEventRequest>,
     reactor_manager: UnboundedSender<ReactorManagerRequest>,
     metadata_key_map: &mut MetadataKeyMap,
 ) -> Arc<WorkflowStepFactory> {
@@ -153,13 +308,19 @@ fn register_steps(
     let is_keyframe_metadata_key = get_is_keyframe_metadata_key(metadata_key_map);
     let pts_offset_metadata_key = get_pts_offset_metadata_key(metadata_key_map);
 
+    let (side_channel_sender, side_channel_receiver) = unbounded_channel();
+    tokio::spawn(log_side_channel_data(side_channel_receiver));
+
     let mut step_factory = WorkflowStepFactory::new();
+    step_factory.set_side_channel(side_channel_sender);
+
     step_factory
         .register(
             WorkflowStepType(RTMP_RECEIVE.to_string()),
             Box::new(RtmpReceiverStepGenerator::new(
                 endpoints.rtmp.clone(),
                 reactor_manager.clone(),
+                event_publisher.clone(),
                 is_keyframe_metadata_key,
                 pts_offset_metadata_key,
             )),
@@ -226,11 +387,23 @@ fn register_steps(
         )
         .expect("Failed to register ffmpeg_push step");
 
+    step_factory
+        .register(
+            WorkflowStepType(RTMP_MULTI_OUTPUT.to_string()),
+            Box::new(FfmpegRtmpMultiOutputStepGenerator::new(
+                endpoints.rtmp.clone(),
+                endpoints.ffmpeg.clone(),
+                is_keyframe_metadata_key,
+                pts_offset_metadata_key,
+            )),
+        )
+        .expect("Failed to register rtmp_multi_output step");
+
     step_factory
         .register(
             WorkflowStepType(FORWARD_STEP.to_string()),
             Box::new(WorkflowForwarderStepGenerator::new(
-                subscription_sender,
+                subscription_sender.clone(),
                 reactor_manager,
             )),
         )
@@ -239,13 +412,404 @@ fn register_steps(
     step_factory
         .register(
             WorkflowStepType(BASIC_TRANSCODE_STEP.to_string()),
-            Box::new(BasicTranscodeStepGenerator::new(endpoints.gst_transcoder)),
+            Box::new(BasicTranscodeStepGenerator::new(
+                endpoints.gst_transcoder.clone(),
+                endpoints.encoder_factory.clone(),
+                is_keyframe_metadata_key,
+            )),
         )
         .expect("Failed to register the basic transcoder step");
 
+    step_factory
+        .register(
+            WorkflowStepType(CONDITIONAL_TRANSCODE_STEP.to_string()),
+            Box::new(ConditionalTranscodeStepGenerator::new(
+                endpoints.gst_transcoder.clone(),
+                endpoints.encoder_factory.clone(),
+            )),
+        )
+        .expect("Failed to register the conditional transcode step");
+
+    step_factory
+        .register(
+            WorkflowStepType(BITRATE_NORMALIZE_STEP.to_string()),
+            Box::new(BitrateNormalizeStepGenerator::new(
+                endpoints.gst_transcoder.clone(),
+                endpoints.encoder_factory.clone(),
+            )),
+        )
+        .expect("Failed to register the bitrate normalize step");
+
+    step_factory
+        .register(
+            WorkflowStepType(GOP_NORMALIZE_STEP.to_string()),
+            Box::new(GopNormalizeStepGenerator::new(
+                endpoints.gst_transcoder.clone(),
+                endpoints.encoder_factory.clone(),
+                is_keyframe_metadata_key,
+                pts_offset_metadata_key,
+            )),
+        )
+        .expect("Failed to register the gop normalize step");
+
+    step_factory
+        .register(
+            WorkflowStepType(MAX_RESOLUTION_STEP.to_string()),
+            Box::new(MaxResolutionStepGenerator::new(
+                endpoints.gst_transcoder,
+                endpoints.encoder_factory.clone(),
+            )),
+        )
+        .expect("Failed to register the max resolution step");
+
+    step_factory
+        .register(
+            WorkflowStepType(QUALITY_MEASURE_STEP.to_string()),
+            Box::new(QualityMeasureStepGenerator::new(pts_offset_metadata_key)),
+        )
+        .expect("Failed to register the quality_measure step");
+
+    step_factory
+        .register(
+            WorkflowStepType(QC_MONITOR_STEP.to_string()),
+            Box::new(QcMonitorStepGenerator::new(
+                is_keyframe_metadata_key,
+                pts_offset_metadata_key,
+                event_publisher.clone(),
+            )),
+        )
+        .expect("Failed to register the qc_monitor step");
+
+    step_factory
+        .register(
+            WorkflowStepType(CUSTOM_GST_STEP.to_string()),
+            Box::new(CustomGstStepGenerator::new(
+                is_keyframe_metadata_key,
+                pts_offset_metadata_key,
+            )),
+        )
+        .expect("Failed to register the custom gstreamer step");
+
+    step_factory
+        .register(
+            WorkflowStepType(REMOTE_FORWARD_STEP.to_string()),
+            Box::new(RemoteForwardStepGenerator::new()),
+        )
+        .expect("Failed to register the remote_forward step");
+
+    step_factory
+        .register(
+            WorkflowStepType(REMOTE_INGEST_STEP.to_string()),
+            Box::new(RemoteIngestStepGenerator::new(
+                endpoints.socket_manager,
+                event_publisher.clone(),
+            )),
+        )
+        .expect("Failed to register the remote_ingest step");
+
+    step_factory
+        .register(
+            WorkflowStepType(SESSION_RECORD_STEP.to_string()),
+            Box::new(SessionRecordStepGenerator::new()),
+        )
+        .expect("Failed to register the session_record step");
+
+    step_factory
+        .register(
+            WorkflowStepType(SESSION_REPLAY_STEP.to_string()),
+            Box::new(SessionReplayStepGenerator::new()),
+        )
+        .expect("Failed to register the session_replay step");
+
+    step_factory
+        .register(
+            WorkflowStepType(GOP_SEGMENTER_STEP.to_string()),
+            Box::new(GopSegmenterStepGenerator::new(is_keyframe_metadata_key)),
+        )
+        .expect("Failed to register the gop_segmenter step");
+
+    step_factory
+        .register(
+            WorkflowStepType(INSTANT_REPLAY_STEP.to_string()),
+            Box::new(InstantReplayStepGenerator::new(is_keyframe_metadata_key)),
+        )
+        .expect("Failed to register the instant_replay step");
+
+    step_factory
+        .register(
+            WorkflowStepType(H264_FRAMING_STEP.to_string()),
+            Box::new(H264FramingStepGenerator::new()),
+        )
+        .expect("Failed to register the h264_framing step");
+
+    step_factory
+        .register(
+            WorkflowStepType(ADMISSION_CONTROL_STEP.to_string()),
+            Box::new(AdmissionControlStepGenerator::new()),
+        )
+        .expect("Failed to register the admission_control step");
+
+    step_factory
+        .register(
+            WorkflowStepType(RESOLUTION_GUARD_STEP.to_string()),
+            Box::new(ResolutionGuardStepGenerator::new()),
+        )
+        .expect("Failed to register the resolution_guard step");
+
+    step_factory
+        .register(
+            WorkflowStepType(STREAM_LABEL_STEP.to_string()),
+            Box::new(StreamLabelStepGenerator::new()),
+        )
+        .expect("Failed to register the stream_label step");
+
+    step_factory
+        .register(
+            WorkflowStepType(FAILOVER_STEP.to_string()),
+            Box::new(FailoverStepGenerator::new()),
+        )
+        .expect("Failed to register the failover step");
+
+    step_factory
+        .register(
+            WorkflowStepType(INGEST_WARMUP_STEP.to_string()),
+            Box::new(IngestWarmupStepGenerator::new(is_keyframe_metadata_key)),
+        )
+        .expect("Failed to register the ingest_warmup step");
+
+    step_factory
+        .register(
+            WorkflowStepType(STREAM_CHANGE_MONITOR_STEP.to_string()),
+            Box::new(StreamChangeMonitorStepGenerator::new(
+                event_publisher.clone(),
+            )),
+        )
+        .expect("Failed to register the stream_change_monitor step");
+
+    step_factory
+        .register(
+            WorkflowStepType(TIMESTAMP_SANITIZE_STEP.to_string()),
+            Box::new(TimestampSanitizeStepGenerator::new()),
+        )
+        .expect("Failed to register the timestamp_sanitize step");
+
+    step_factory
+        .register(
+            WorkflowStepType(AUDIO_RESAMPLE_STEP.to_string()),
+            Box::new(AudioResampleStepGenerator::new(
+                endpoints.encoder_factory.clone(),
+            )),
+        )
+        .expect("Failed to register the audio_resample step");
+
+    step_factory
+        .register(
+            WorkflowStepType(CAPTION_INJECT_STEP.to_string()),
+            Box::new(CaptionInjectStepGenerator::new(subscription_sender)),
+        )
+        .expect("Failed to register the caption_inject step");
+
+    step_factory
+        .register(
+            WorkflowStepType(CFR_STEP.to_string()),
+            Box::new(CfrStepGenerator::new(
+                endpoints.encoder_factory.clone(),
+                pts_offset_metadata_key,
+            )),
+        )
+        .expect("Failed to register the cfr step");
+
+    step_factory
+        .register(
+            WorkflowStepType(DEINTERLACE_STEP.to_string()),
+            Box::new(DeinterlaceStepGenerator::new(
+                endpoints.encoder_factory.clone(),
+                pts_offset_metadata_key,
+            )),
+        )
+        .expect("Failed to register the deinterlace step");
+
+    step_factory
+        .register(
+            WorkflowStepType(MJPEG_PREVIEW_STEP.to_string()),
+            Box::new(MjpegPreviewStepGenerator::new(
+                is_keyframe_metadata_key,
+                pts_offset_metadata_key,
+            )),
+        )
+        .expect("Failed to register the mjpeg_preview step");
+
+    step_factory
+        .register(
+            WorkflowStepType(CHECKSUM_STEP.to_string()),
+            Box::new(ChecksumStepGenerator::new(
+                get_payload_checksum_metadata_key(metadata_key_map),
+            )),
+        )
+        .expect("Failed to register the checksum step");
+
+    step_factory
+        .register(
+            WorkflowStepType(AUDIO_MIX_STEP.to_string()),
+            Box::new(AudioMixStepGenerator::new(
+                endpoints.encoder_factory.clone(),
+            )),
+        )
+        .expect("Failed to register the audio_mix step");
+
+    step_factory
+        .register(
+            WorkflowStepType(PIP_COMPOSITE_STEP.to_string()),
+            Box::new(PipCompositeStepGenerator::new(
+                endpoints.encoder_factory.clone(),
+                is_keyframe_metadata_key,
+                pts_offset_metadata_key,
+            )),
+        )
+        .expect("Failed to register the pip_composite step");
+
+    step_factory
+        .register(
+            WorkflowStepType(AUDIO_CHANNELS_STEP.to_string()),
+            Box::new(AudioChannelsStepGenerator::new(
+                endpoints.encoder_factory.clone(),
+            )),
+        )
+        .expect("Failed to register the audio_channels step");
+
+    step_factory
+        .register(
+            WorkflowStepType(AUDIO_WATERMARK_STEP.to_string()),
+            Box::new(AudioWatermarkStepGenerator::new(
+                endpoints.encoder_factory.clone(),
+            )),
+        )
+        .expect("Failed to register the audio_watermark step");
+
+    step_factory
+        .register(
+            WorkflowStepType(AUDIO_LEVELS_STEP.to_string()),
+            Box::new(AudioLevelsStepGenerator::new()),
+        )
+        .expect("Failed to register the audio_levels step");
+
+    step_factory
+        .register(
+            WorkflowStepType(HEARTBEAT_STEP.to_string()),
+            Box::new(HeartbeatStepGenerator::new()),
+        )
+        .expect("Failed to register the heartbeat step");
+
+    step_factory
+        .register(
+            WorkflowStepType(KEYFRAME_ONLY_STEP.to_string()),
+            Box::new(KeyframeOnlyStepGenerator::new(is_keyframe_metadata_key)),
+        )
+        .expect("Failed to register the keyframe_only step");
+
+    step_factory
+        .register(
+            WorkflowStepType(MPEGTS_DEMUX_STEP.to_string()),
+            Box::new(MpegTsDemuxStepGenerator::new(
+                is_keyframe_metadata_key,
+                pts_offset_metadata_key,
+            )),
+        )
+        .expect("Failed to register the mpegts_demux step");
+
+    step_factory
+        .register(
+            WorkflowStepType(STARTUP_ORDER_STEP.to_string()),
+            Box::new(StartupOrderStepGenerator),
+        )
+        .expect("Failed to register the startup_order step");
+
+    step_factory
+        .register(
+            WorkflowStepType(STARTUP_DELAY_STEP.to_string()),
+            Box::new(StartupDelayStepGenerator),
+        )
+        .expect("Failed to register the startup_delay step");
+
+    step_factory
+        .register(
+            WorkflowStepType(METADATA_STRIP_STEP.to_string()),
+            Box::new(MetadataStripStepGenerator::new()),
+        )
+        .expect("Failed to register the metadata_strip step");
+
+    step_factory
+        .register(
+            WorkflowStepType(RATE_SHAPE_STEP.to_string()),
+            Box::new(RateShapeStepGenerator::new(is_keyframe_metadata_key)),
+        )
+        .expect("Failed to register the rate_shape step");
+
+    step_factory
+        .register(
+            WorkflowStepType(WHEP_OUTPUT_STEP.to_string()),
+            Box::new(WhepOutputStepGenerator::new(
+                is_keyframe_metadata_key,
+                pts_offset_metadata_key,
+            )),
+        )
+        .expect("Failed to register the whep_output step");
+
+    step_factory
+        .register(
+            WorkflowStepType(CHAOS_STEP.to_string()),
+            Box::new(ChaosStepGenerator),
+        )
+        .expect("Failed to register the chaos step");
+
+    step_factory
+        .register(
+            WorkflowStepType(HDR_TONEMAP_STEP.to_string()),
+            Box::new(HdrTonemapStepGenerator::new(
+                endpoints.encoder_factory.clone(),
+                pts_offset_metadata_key,
+            )),
+        )
+        .expect("Failed to register the hdr_tonemap step");
+
+    step_factory
+        .register(
+            WorkflowStepType(STREAM_PROPERTIES_STEP.to_string()),
+            Box::new(StreamPropertiesStepGenerator::new(event_publisher.clone())),
+        )
+        .expect("Failed to register the stream_properties step");
+
+    step_factory
+        .register(
+            WorkflowStepType(STREAM_TAGS_STEP.to_string()),
+            Box::new(StreamTagsStepGenerator::new()),
+        )
+        .expect("Failed to register the stream_tags step");
+
+    step_factory
+        .register(
+            WorkflowStepType(CAPTION_DETECT_STEP.to_string()),
+            Box::new(CaptionDetectStepGenerator::new(event_publisher)),
+        )
+        .expect("Failed to register the caption_detect step");
+
     Arc::new(step_factory)
 }
 
+async fn log_side_channel_data(mut receiver: UnboundedReceiver<SideChannelMessage>) {
+    while let Some(message) = receiver.recv().await {
+        if let Some(segment) = message.data_as::<CompletedGopSegment>() {
+            info!(
+                step_id = message.step_id.0,
+                stream_id = %segment.stream_id.0,
+                "GOP segment for stream {} completed: {}",
+                segment.stream_name,
+                segment.path.display()
+            );
+        }
+    }
+}
+
 async fn load_tls_options(config: &MmidsConfig) -> Option<TlsOptions> {
     info!("Loading TLS options");
     let cert_path = match config.settings.get("tls_cert_path") {
@@ -294,7 +858,7 @@ fn start_endpoints(
 
     let pts_offset_metadata_key = get_pts_offset_metadata_key(metadata_key_map);
     let socket_manager = start_socket_manager(tls_options);
-    let rtmp_endpoint = start_rtmp_server_endpoint(socket_manager);
+    let rtmp_endpoint = start_rtmp_server_endpoint(socket_manager.clone());
 
     let ffmpeg_path = config
         .settings
@@ -329,6 +893,15 @@ fn start_endpoints(
         )
         .expect("Failed to add the x264 encoder");
 
+    encoder_factory
+        .register_video_encoder(
+            "x265",
+            Box::new(X265EncoderGenerator {
+                pts_offset_metadata_key,
+            }),
+        )
+        .expect("Failed to add the x265 encoder");
+
     encoder_factory
         .register_audio_encoder("drop", Box::new(AudioDropEncoderGenerator {}))
         .expect("Failed to add the audio drop encoder");
@@ -341,13 +914,16 @@ fn start_endpoints(
         .register_audio_encoder("avenc_aac", Box::new(AvencAacEncoderGenerator {}))
         .expect("Failed to add the avenc_aac encoder");
 
-    let gst_transcoder = start_gst_transcoder(Arc::new(encoder_factory), pts_offset_metadata_key)
+    let encoder_factory = Arc::new(encoder_factory);
+    let gst_transcoder = start_gst_transcoder(encoder_factory.clone(), pts_offset_metadata_key)
         .expect("Failed to start gst transcoder");
 
     Endpoints {
+        socket_manager,
         rtmp: rtmp_endpoint,
         ffmpeg: ffmpeg_endpoint,
         gst_transcoder,
+        encoder_factory,
     }
 }
 
@@ -355,14 +931,24 @@ fn start_workflows(
     config: &MmidsConfig,
     step_factory: Arc<WorkflowStepFactory>,
     event_hub_publisher: UnboundedSender<PublishEventRequest>,
+    log_levels: WorkflowLogLevels,
+    shutdown_token: CancellationToken,
 ) -> UnboundedSender<WorkflowManagerRequest> {
     info!("Starting workflow manager");
-    let manager = start_workflow_manager(step_factory, event_hub_publisher);
+    let reaper_settings = get_stream_reaper_settings(config);
+    let manager = start_workflow_manager(
+        step_factory,
+        event_hub_publisher,
+        reaper_settings,
+        log_levels,
+        shutdown_token,
+    );
     for workflow in config.workflows.values() {
         let _ = manager.send(WorkflowManagerRequest {
             request_id: "mmids-app-startup".to_string(),
             operation: WorkflowManagerRequestOperation::UpsertWorkflow {
                 definition: workflow.clone(),
+                response_channel: None,
             },
         });
     }
@@ -370,6 +956,66 @@ fn start_workflows(
     manager
 }
 
+fn start_kv_workflow_source(
+    config: &MmidsConfig,
+    manager: UnboundedSender<WorkflowManagerRequest>,
+    shutdown_token: CancellationToken,
+) {
+    let address = match config.settings.get("consul_address") {
+        Some(Some(address)) => address,
+        _ => return,
+    };
+
+    let prefix = match config.settings.get("consul_workflow_prefix") {
+        Some(Some(prefix)) => prefix.as_str(),
+        _ => "mmids/workflows",
+    };
+
+    info!(
+        "Watching consul at {} for workflows under '{}'",
+        address, prefix
+    );
+
+    let watcher = ConsulWatcher::new(address, prefix);
+    kv_source::start_kv_workflow_source(
+        Box::new(watcher),
+        manager,
+        Duration::from_secs(5),
+        shutdown_token,
+    );
+}
+
+fn get_stream_reaper_settings(config: &MmidsConfig) -> Option<StreamReaperSettings> {
+    let threshold = match config.settings.get("dead_stream_threshold") {
+        Some(Some(value)) => match value.parse::<u64>() {
+            Ok(seconds) if seconds > 0 => Duration::from_secs(seconds),
+            _ => panic!(
+                "dead_stream_threshold value of '{}' is not a valid number of seconds",
+                value
+            ),
+        },
+
+        _ => return None,
+    };
+
+    let scan_interval = match config.settings.get("dead_stream_scan_interval") {
+        Some(Some(value)) => match value.parse::<u64>() {
+            Ok(seconds) if seconds > 0 => Duration::from_secs(seconds),
+            _ => panic!(
+                "dead_stream_scan_interval value of '{}' is not a valid number of seconds",
+                value
+            ),
+        },
+
+        _ => Duration::from_secs(30),
+    };
+
+    Some(StreamReaperSettings {
+        scan_interval,
+        inactivity_threshold: threshold,
+    })
+}
+
 fn start_http_api(
     config: &MmidsConfig,
     manager: UnboundedSender<WorkflowManagerRequest>,
@@ -445,6 +1091,16 @@ fn start_http_api(
         })
         .expect("Failed to register start workflow route");
 
+    routes
+        .register(Route {
+            method: Method::GET,
+            path: vec![PathPart::Exact {
+                value: "channels".to_string(),
+            }],
+            handler: Box::new(handlers::list_channel_depths::ListChannelDepthsHandler),
+        })
+        .expect("Failed to register list channel depths route");
+
     routes
         .register(Route {
             method: Method::GET,
@@ -453,6 +1109,32 @@ fn start_http_api(
         })
         .expect("Failed to register version route");
 
+    if let Some(Some(directory)) = config.settings.get("hls_serve_path") {
+        // The signer is only used to validate tokens, so its ttl isn't relevant
+        let url_signer = match config.settings.get("hls_signing_key") {
+            Some(Some(key)) => Some(UrlSigner::new(key.as_bytes(), Duration::default())),
+            _ => None,
+        };
+
+        routes
+            .register(Route {
+                method: Method::GET,
+                path: vec![
+                    PathPart::Exact {
+                        value: "hls".to_string(),
+                    },
+                    PathPart::Parameter {
+                        name: "file".to_string(),
+                    },
+                ],
+                handler: Box::new(handlers::serve_hls::ServeHlsHandler::new(
+                    PathBuf::from(directory),
+                    url_signer,
+                )),
+            })
+            .expect("Failed to register serve hls route");
+    }
+
     let addr = ([127, 0, 0, 1], port).into();
     Some(mmids_http_api::start_http_api(addr, routes))
 }
@@ -460,6 +1142,7 @@ fn start_http_api(
 async fn start_reactor(
     config: &MmidsConfig,
     event_hub_subscriber: UnboundedSender<SubscriptionRequest>,
+    shutdown_token: CancellationToken,
 ) -> UnboundedSender<ReactorManagerRequest> {
     let mut factory = ReactorExecutorFactory::new();
     factory
@@ -469,7 +1152,8 @@ async fn start_reactor(
         )
         .expect("Failed to add simple_http reactor executor");
 
-    let reactor_manager = start_reactor_manager(factory, event_hub_subscriber.clone());
+    let reactor_manager =
+        start_reactor_manager(factory, event_hub_subscriber.clone(), shutdown_token);
     for (name, definition) in &config.reactors {
         let (sender, receiver) = channel();
         let _ = reactor_manager.send(ReactorManagerRequest::CreateReactor {
@@ -0,0 +1,367 @@
+//! Benchmarks for the path media takes through a workflow, to catch performance regressions in
+//! the runner's media routing and caching.
+//!
+//! A representative stream (sequence headers up front, then keyframes, inter frames and audio) is
+//! pushed through a workflow of pass through steps. Payload data is shared between packets, so
+//! the only copies made along the measured path are reference count increments on `Bytes`.
+//!
+//! Allocations are counted by a wrapper around the system allocator, and the average number of
+//! allocations per packet is reported before each benchmark group runs.
+
+use bytes::{Bytes, BytesMut};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use mmids_core::workflows::definitions::{
+    WorkflowDefinition, WorkflowStepDefinition, WorkflowStepId, WorkflowStepType,
+};
+use mmids_core::workflows::metadata::MediaPayloadMetadataCollection;
+use mmids_core::workflows::steps::factory::{StepGenerator, WorkflowStepFactory};
+use mmids_core::workflows::steps::futures_channel::{
+    FuturesChannelInnerResult, WorkflowStepFuturesChannel,
+};
+use mmids_core::workflows::steps::{
+    StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use mmids_core::workflows::{
+    start_workflow, MediaNotification, MediaNotificationContent, MediaType, WorkflowRequest,
+    WorkflowRequestOperation,
+};
+use mmids_core::StreamId;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::iter;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+
+/// Number of pass through steps media flows through before reaching the output step
+const PASS_THROUGH_STEP_COUNT: usize = 5;
+
+/// Number of video frames in each group of pictures, including the keyframe
+const FRAMES_PER_GOP: u64 = 30;
+
+/// Number of AAC frames sent for every video frame, roughly matching 48khz audio at 30fps
+const AUDIO_FRAMES_PER_VIDEO_FRAME: u64 = 2;
+
+struct CountingAllocator;
+
+static ALLOCATION_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATION_COUNT.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATION_COUNT.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+struct PassThroughStepGenerator;
+
+struct PassThroughStep;
+
+impl StepGenerator for PassThroughStepGenerator {
+    fn generate(
+        &self,
+        _definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        Ok((Box::new(PassThroughStep), StepStatus::Active))
+    }
+}
+
+impl WorkflowStep for PassThroughStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        outputs.media.append(&mut inputs.media);
+        StepStatus::Active
+    }
+}
+
+struct OutputStepGenerator {
+    sender: UnboundedSender<MediaNotification>,
+}
+
+struct OutputStep {
+    sender: UnboundedSender<MediaNotification>,
+}
+
+impl StepGenerator for OutputStepGenerator {
+    fn generate(
+        &self,
+        _definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let step = OutputStep {
+            sender: self.sender.clone(),
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl WorkflowStep for OutputStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        _outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            let _ = self.sender.send(media);
+        }
+
+        StepStatus::Active
+    }
+}
+
+struct BenchWorkflow {
+    workflow: UnboundedSender<WorkflowRequest>,
+    output: UnboundedReceiver<MediaNotification>,
+    stream_id: StreamId,
+    cancellation_token: CancellationToken,
+}
+
+impl BenchWorkflow {
+    /// Starts the workflow and sends the stream's sequence headers through it, so later media
+    /// follows the same path it would for a live stream
+    async fn start() -> Self {
+        let (sender, output) = unbounded_channel();
+        let mut factory = WorkflowStepFactory::new();
+        factory
+            .register(
+                WorkflowStepType("pass_through".to_string()),
+                Box::new(PassThroughStepGenerator),
+            )
+            .expect("Failed to register pass through step");
+
+        factory
+            .register(
+                WorkflowStepType("output".to_string()),
+                Box::new(OutputStepGenerator { sender }),
+            )
+            .expect("Failed to register output step");
+
+        // Identical step definitions would share a step id, so each step gets a unique parameter
+        let steps = (0..PASS_THROUGH_STEP_COUNT)
+            .map(|index| ("pass_through", index))
+            .chain(iter::once(("output", PASS_THROUGH_STEP_COUNT)))
+            .map(|(step_type, index)| {
+                let mut parameters = HashMap::new();
+                parameters.insert("index".to_string(), Some(index.to_string()));
+
+                WorkflowStepDefinition {
+                    step_type: WorkflowStepType(step_type.to_string()),
+                    parameters,
+                }
+            })
+            .collect();
+
+        let definition = WorkflowDefinition {
+            name: Arc::new("bench".to_string()),
+            routed_by_reactor: false,
+            max_stream_lifetime: None,
+            max_cached_media_bytes: None,
+            watcher_limit: None,
+            steps,
+        };
+
+        let cancellation_token = CancellationToken::new();
+        let workflow = start_workflow(
+            definition,
+            Arc::new(factory),
+            cancellation_token.child_token(),
+        );
+
+        let mut bench_workflow = BenchWorkflow {
+            workflow,
+            output,
+            stream_id: StreamId(Arc::new("bench".to_string())),
+            cancellation_token,
+        };
+
+        let stream_id = bench_workflow.stream_id.clone();
+        let startup_media = vec![
+            MediaNotification {
+                stream_id: stream_id.clone(),
+                content: MediaNotificationContent::NewIncomingStream {
+                    stream_name: Arc::new("bench".to_string()),
+                },
+                annotations: Default::default(),
+            },
+            payload(&stream_id, MediaType::Video, 0, true, sequence_header()),
+            payload(&stream_id, MediaType::Audio, 0, true, sequence_header()),
+        ];
+
+        // Steps become active asynchronously, so wait for media to make it all the way through
+        // before measuring anything
+        tokio::time::timeout(Duration::from_secs(5), bench_workflow.send(startup_media))
+            .await
+            .expect("Timed out waiting for the workflow to start");
+
+        bench_workflow
+    }
+
+    /// Sends each packet into the workflow, and waits until they've all come out the other side
+    async fn send(&mut self, packets: Vec<MediaNotification>) {
+        let count = packets.len();
+        for media in packets {
+            self.workflow
+                .send(WorkflowRequest {
+                    request_id: String::new(),
+                    operation: WorkflowRequestOperation::MediaNotification { media },
+                })
+                .expect("Workflow stopped");
+        }
+
+        for _ in 0..count {
+            self.output.recv().await.expect("Output step stopped");
+        }
+    }
+}
+
+impl Drop for BenchWorkflow {
+    fn drop(&mut self) {
+        self.cancellation_token.cancel();
+    }
+}
+
+fn sequence_header() -> Bytes {
+    Bytes::from_static(&[0x01, 0x64, 0x00, 0x28, 0xff, 0xe1, 0x00, 0x04])
+}
+
+fn payload(
+    stream_id: &StreamId,
+    media_type: MediaType,
+    timestamp_millis: u64,
+    is_required_for_decoding: bool,
+    data: Bytes,
+) -> MediaNotification {
+    MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type,
+            payload_type: Arc::new("bench".to_string()),
+            timestamp: Duration::from_millis(timestamp_millis),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data,
+            is_required_for_decoding,
+        },
+        annotations: Default::default(),
+    }
+}
+
+/// Builds a single group of pictures worth of interleaved video and audio packets. Payloads of
+/// the same kind share their underlying buffer.
+fn gop(stream_id: &StreamId) -> Vec<MediaNotification> {
+    let keyframe = Bytes::from(vec![0u8; 50_000]);
+    let inter_frame = Bytes::from(vec![0u8; 5_000]);
+    let audio_frame = Bytes::from(vec![0u8; 300]);
+
+    let mut packets = Vec::new();
+    for frame in 0..FRAMES_PER_GOP {
+        let timestamp = frame * 33;
+        let data = if frame == 0 {
+            keyframe.clone()
+        } else {
+            inter_frame.clone()
+        };
+
+        packets.push(payload(stream_id, MediaType::Video, timestamp, false, data));
+        for audio in 0..AUDIO_FRAMES_PER_VIDEO_FRAME {
+            let timestamp = timestamp + audio * 16;
+            let data = audio_frame.clone();
+            packets.push(payload(stream_id, MediaType::Audio, timestamp, false, data));
+        }
+    }
+
+    packets
+}
+
+/// Counts the allocations made while sending packets through a workflow, so changes that add
+/// allocations to the hot path show up even when they don't move the timings much
+fn report_allocations_per_packet(runtime: &Runtime) {
+    runtime.block_on(async {
+        let mut workflow = BenchWorkflow::start().await;
+        let packets = gop(&workflow.stream_id);
+        let packet_count = packets.len();
+
+        let before = ALLOCATION_COUNT.load(Ordering::Relaxed);
+        workflow.send(packets).await;
+        let allocations = ALLOCATION_COUNT.load(Ordering::Relaxed) - before;
+
+        println!(
+            "workflow media path: {:.2} allocations per packet ({} packets through {} steps)",
+            allocations as f64 / packet_count as f64,
+            packet_count,
+            PASS_THROUGH_STEP_COUNT + 1,
+        );
+    });
+}
+
+fn workflow_media_path(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("Failed to create tokio runtime");
+    report_allocations_per_packet(&runtime);
+
+    let mut workflow = runtime.block_on(BenchWorkflow::start());
+    let packets = gop(&workflow.stream_id);
+
+    let mut group = c.benchmark_group("workflow_media_path");
+    group.throughput(Throughput::Elements(packets.len() as u64));
+    group.bench_function("gop_through_pass_through_steps", |b| {
+        b.iter_batched(
+            || packets.clone(),
+            |packets| runtime.block_on(workflow.send(packets)),
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+fn futures_channel_send(c: &mut Criterion) {
+    let (sender, mut receiver) = unbounded_channel();
+    let channel = WorkflowStepFuturesChannel::new(WorkflowStepId(0), sender);
+    let stream_id = StreamId(Arc::new("bench".to_string()));
+    let media = payload(
+        &stream_id,
+        MediaType::Video,
+        0,
+        false,
+        Bytes::from(vec![0u8; 5_000]),
+    );
+
+    let mut group = c.benchmark_group("futures_channel");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("send_media", |b| {
+        b.iter(|| {
+            let result = FuturesChannelInnerResult::Media(media.clone());
+            if channel.send(result).is_err() {
+                panic!("Futures channel closed");
+            }
+
+            receiver.try_recv().expect("Expected sent result")
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, workflow_media_path, futures_channel_send);
+criterion_main!(benches);
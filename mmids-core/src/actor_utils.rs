@@ -2,6 +2,7 @@
 
 use std::future::Future;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
 
 /// Watches a tokio `UnboundedReceiver` for a message, and when a message is received sends that
 /// message to the actor via the `received_message` transformation function.
@@ -64,6 +65,29 @@ pub fn notify_on_unbounded_closed<SenderMessage, ActorMessage>(
     });
 }
 
+/// Watches a cancellation token, such as the one used to shut the application down, and sends the
+/// specified message to the actor once the token is cancelled.
+pub fn notify_on_cancellation<ActorMessage>(
+    token: CancellationToken,
+    actor_channel: UnboundedSender<ActorMessage>,
+    cancelled_message: impl FnOnce() -> ActorMessage + Send + 'static,
+) where
+    ActorMessage: Send + 'static,
+{
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = token.cancelled() => {
+                let actor_msg = cancelled_message();
+                let _ = actor_channel.send(actor_msg);
+            }
+
+            _ = actor_channel.closed() => {
+                // Actor is already gone so there's nothing to notify
+            }
+        }
+    });
+}
+
 /// Allows notifying an actor when any arbitrary future is resolved.
 pub fn notify_on_future_completion<FutureResult, ActorMessage>(
     future: impl Future<Output = FutureResult> + Send + 'static,
@@ -0,0 +1,208 @@
+//! Encoding of CEA-608 captions for the first caption channel (CC1). Captions are encoded in
+//! pop-on mode, where the caption is loaded off screen and then displayed all at once, with every
+//! control code sent twice for redundancy as decoders expect.
+//!
+//! Each byte pair is meant to be sent with a separate video frame, so a caption takes one frame
+//! per pair to be fully delivered.
+
+use std::convert::TryFrom;
+
+/// The most characters that fit on a single caption row
+pub const MAX_ROW_LENGTH: usize = 32;
+
+/// The most rows a single caption is allowed to take up
+pub const MAX_ROWS: usize = 4;
+
+const RESUME_CAPTION_LOADING: [u8; 2] = [0x14, 0x20];
+const ERASE_DISPLAYED_MEMORY: [u8; 2] = [0x14, 0x2c];
+const ERASE_NON_DISPLAYED_MEMORY: [u8; 2] = [0x14, 0x2e];
+const END_OF_CAPTION: [u8; 2] = [0x14, 0x2f];
+
+/// Preamble address codes that position the cursor at the start of each row (1 through 15), with
+/// white text and no underline
+const ROW_PREAMBLE_ADDRESS_CODES: [[u8; 2]; 15] = [
+    [0x11, 0x40],
+    [0x11, 0x60],
+    [0x12, 0x40],
+    [0x12, 0x60],
+    [0x15, 0x40],
+    [0x15, 0x60],
+    [0x16, 0x40],
+    [0x16, 0x60],
+    [0x17, 0x40],
+    [0x17, 0x60],
+    [0x10, 0x40],
+    [0x13, 0x40],
+    [0x13, 0x60],
+    [0x14, 0x40],
+    [0x14, 0x60],
+];
+
+/// Characters in the basic character set that differ from their ASCII counterparts
+const SPECIAL_CHARACTERS: [(u8, char); 10] = [
+    (0x2a, 'á'),
+    (0x5c, 'é'),
+    (0x5e, 'í'),
+    (0x5f, 'ó'),
+    (0x60, 'ú'),
+    (0x7b, 'ç'),
+    (0x7c, '÷'),
+    (0x7d, 'Ñ'),
+    (0x7e, 'ñ'),
+    (0x7f, '█'),
+];
+
+/// Encodes the byte pairs that display the text as a pop-on caption, replacing any caption
+/// currently being displayed. Each line of the text is placed on its own row at the bottom of
+/// the screen. Lines past the maximum row count are dropped, lines are truncated to the
+/// maximum row length, and characters outside of the basic CEA-608 character set are skipped.
+pub fn encode_pop_on_caption(text: &str) -> Vec<[u8; 2]> {
+    let rows = text
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .take(MAX_ROWS)
+        .map(|line| {
+            line.chars()
+                .filter_map(encode_character)
+                .take(MAX_ROW_LENGTH)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut pairs = Vec::new();
+    push_control_code(&mut pairs, RESUME_CAPTION_LOADING);
+    push_control_code(&mut pairs, ERASE_NON_DISPLAYED_MEMORY);
+
+    let first_row = ROW_PREAMBLE_ADDRESS_CODES.len() - rows.len();
+    for (index, row) in rows.iter().enumerate() {
+        push_control_code(&mut pairs, ROW_PREAMBLE_ADDRESS_CODES[first_row + index]);
+        for characters in row.chunks(2) {
+            let second = characters.get(1).copied().unwrap_or_default();
+            pairs.push([with_odd_parity(characters[0]), with_odd_parity(second)]);
+        }
+    }
+
+    push_control_code(&mut pairs, END_OF_CAPTION);
+
+    pairs
+}
+
+/// Encodes the byte pairs that remove the currently displayed caption from the screen
+pub fn encode_erase_displayed_caption() -> Vec<[u8; 2]> {
+    let mut pairs = Vec::new();
+    push_control_code(&mut pairs, ERASE_DISPLAYED_MEMORY);
+
+    pairs
+}
+
+/// Extracts the text characters from CEA-608 byte pairs, with a newline wherever the pairs move
+/// the cursor to a new row. Other control codes are ignored, so this does not reproduce what a
+/// decoder would display, but is enough to tell what text a stream's captions contain.
+pub fn extract_text<'a>(pairs: impl IntoIterator<Item = &'a [u8; 2]>) -> String {
+    let mut text = String::new();
+    for pair in pairs {
+        let first = pair[0] & 0x7f;
+        let second = pair[1] & 0x7f;
+        if (0x10..=0x1f).contains(&first) {
+            let is_preamble_address_code = second >= 0x40;
+            if is_preamble_address_code && !text.is_empty() && !text.ends_with('\n') {
+                text.push('\n');
+            }
+
+            continue;
+        }
+
+        text.extend([first, second].iter().copied().filter_map(decode_character));
+    }
+
+    text
+}
+
+fn push_control_code(pairs: &mut Vec<[u8; 2]>, code: [u8; 2]) {
+    let pair = [with_odd_parity(code[0]), with_odd_parity(code[1])];
+    pairs.push(pair);
+    pairs.push(pair);
+}
+
+fn encode_character(character: char) -> Option<u8> {
+    if let Some((byte, _)) = SPECIAL_CHARACTERS.iter().find(|(_, x)| *x == character) {
+        return Some(*byte);
+    }
+
+    let byte = u8::try_from(character).ok()?;
+    let is_replaced = SPECIAL_CHARACTERS.iter().any(|(x, _)| *x == byte);
+    if (0x20..0x7f).contains(&byte) && !is_replaced {
+        Some(byte)
+    } else {
+        None
+    }
+}
+
+fn decode_character(byte: u8) -> Option<char> {
+    if let Some((_, character)) = SPECIAL_CHARACTERS.iter().find(|(x, _)| *x == byte) {
+        return Some(*character);
+    }
+
+    if (0x20..0x7f).contains(&byte) {
+        Some(byte as char)
+    } else {
+        None
+    }
+}
+
+/// CEA-608 bytes are 7 bit values, with the high bit set so the byte has odd parity
+fn with_odd_parity(byte: u8) -> u8 {
+    let byte = byte & 0x7f;
+    if byte.count_ones() % 2 == 1 {
+        byte
+    } else {
+        byte | 0x80
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pop_on_caption_loads_rows_then_displays_them() {
+        let pairs = encode_pop_on_caption("Hi\nthere");
+
+        assert_eq!(
+            pairs[0],
+            [0x94, 0x20],
+            "Expected resume caption loading first"
+        );
+        assert_eq!(pairs[1], pairs[0], "Expected control code to be repeated");
+        assert_eq!(
+            pairs[2],
+            [0x94, 0xae],
+            "Expected erase non-displayed memory"
+        );
+        assert_eq!(pairs[4], [0x94, 0x40], "Expected preamble for row 14");
+        assert_eq!(pairs[6], [0xc8, 0xe9], "Expected 'Hi' with odd parity");
+        assert_eq!(
+            pairs[pairs.len() - 1],
+            [0x94, 0x2f],
+            "Expected end of caption last"
+        );
+
+        assert_eq!(extract_text(&pairs), "Hi\nthere");
+    }
+
+    #[test]
+    fn special_characters_encoded_and_unsupported_characters_skipped() {
+        let pairs = encode_pop_on_caption("¿Qué? ñ*");
+
+        assert_eq!(extract_text(&pairs), "Qué? ñ");
+    }
+
+    #[test]
+    fn long_lines_truncated_to_row_length() {
+        let text = "a".repeat(40);
+        let pairs = encode_pop_on_caption(&text);
+
+        assert_eq!(extract_text(&pairs), "a".repeat(MAX_ROW_LENGTH));
+    }
+}
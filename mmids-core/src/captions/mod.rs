@@ -0,0 +1,169 @@
+//! Support for closed captions carried within video streams. Captions are encoded as CEA-608
+//! byte pairs, which are carried within the `cc_data` structure defined by ATSC A/53 (the same
+//! structure CEA-708 captions are carried in). For H264 video, the `cc_data` is placed in a
+//! registered user data SEI message attached to each video frame.
+
+pub mod cea608;
+pub mod webvtt;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use std::time::Duration;
+
+/// The SEI payload type for user data registered by ITU-T T.35, which carries ATSC caption data
+pub const SEI_PAYLOAD_TYPE_USER_DATA_REGISTERED: u32 = 4;
+
+const ITU_T_T35_COUNTRY_CODE_USA: u8 = 0xb5;
+const ITU_T_T35_PROVIDER_CODE_ATSC: u16 = 0x0031;
+const ATSC_USER_IDENTIFIER: &[u8; 4] = b"GA94";
+const CC_DATA_USER_DATA_TYPE: u8 = 0x03;
+
+/// The most caption data entries a single `cc_data` structure can hold
+const MAX_CC_COUNT: usize = 31;
+
+/// A caption to display over a span of a stream's timeline
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaptionCue {
+    pub start: Duration,
+    pub end: Duration,
+
+    /// The text of the caption, with each line of the caption separated by a newline
+    pub text: String,
+}
+
+/// The kind of caption data contained in a `cc_data` entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CcType {
+    Cea608Field1,
+    Cea608Field2,
+    DtvccPacketData,
+    DtvccPacketStart,
+}
+
+/// A single entry of caption data within a `cc_data` structure
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CcData {
+    pub cc_type: CcType,
+    pub data: [u8; 2],
+}
+
+impl CcData {
+    /// Returns true if the entry is CEA-608 padding (null bytes once parity is removed), which
+    /// encoders send to keep a constant caption data rate while no caption is being sent
+    pub fn is_padding(&self) -> bool {
+        matches!(self.cc_type, CcType::Cea608Field1 | CcType::Cea608Field2)
+            && self.data[0] & 0x7f == 0
+            && self.data[1] & 0x7f == 0
+    }
+}
+
+/// Creates the payload of a registered user data SEI message containing the specified CEA-608
+/// field 1 byte pairs. Only the first 31 pairs are included, as that's all a single `cc_data`
+/// structure can hold.
+pub fn create_cc_data_sei_payload(pairs: &[[u8; 2]]) -> Bytes {
+    let pairs = &pairs[..pairs.len().min(MAX_CC_COUNT)];
+
+    let mut buffer = BytesMut::new();
+    buffer.put_u8(ITU_T_T35_COUNTRY_CODE_USA);
+    buffer.put_u16(ITU_T_T35_PROVIDER_CODE_ATSC);
+    buffer.put_slice(ATSC_USER_IDENTIFIER);
+    buffer.put_u8(CC_DATA_USER_DATA_TYPE);
+
+    // process_em_data_flag = 0, process_cc_data_flag = 1, additional_data_flag = 0
+    buffer.put_u8(0x40 | pairs.len() as u8);
+    buffer.put_u8(0xff); // em_data
+
+    for pair in pairs {
+        // marker bits, cc_valid = 1, cc_type = 0 (field 1)
+        buffer.put_u8(0xfc);
+        buffer.put_slice(pair);
+    }
+
+    buffer.put_u8(0xff); // marker bits
+
+    buffer.freeze()
+}
+
+/// Reads the valid caption data entries out of a registered user data SEI message's payload.
+/// `None` is returned if the payload does not contain ATSC caption data.
+pub fn parse_cc_data_sei_payload(payload: &[u8]) -> Option<Vec<CcData>> {
+    if payload.len() < 10
+        || payload[0] != ITU_T_T35_COUNTRY_CODE_USA
+        || u16::from_be_bytes([payload[1], payload[2]]) != ITU_T_T35_PROVIDER_CODE_ATSC
+        || &payload[3..7] != ATSC_USER_IDENTIFIER
+        || payload[7] != CC_DATA_USER_DATA_TYPE
+    {
+        return None;
+    }
+
+    let cc_count = (payload[8] & 0x1f) as usize;
+    let entries = payload.get(10..10 + cc_count * 3)?;
+    let cc_data = entries
+        .chunks(3)
+        .filter(|entry| entry[0] & 0x04 != 0) // cc_valid
+        .map(|entry| CcData {
+            cc_type: match entry[0] & 0x03 {
+                0 => CcType::Cea608Field1,
+                1 => CcType::Cea608Field2,
+                2 => CcType::DtvccPacketData,
+                _ => CcType::DtvccPacketStart,
+            },
+            data: [entry[1], entry[2]],
+        })
+        .collect();
+
+    Some(cc_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cc_data_round_trips() {
+        let pairs = [[0x94, 0x20], [0xc1, 0x80]];
+        let payload = create_cc_data_sei_payload(&pairs);
+        let cc_data = parse_cc_data_sei_payload(&payload).unwrap();
+
+        assert_eq!(
+            cc_data,
+            vec![
+                CcData {
+                    cc_type: CcType::Cea608Field1,
+                    data: [0x94, 0x20],
+                },
+                CcData {
+                    cc_type: CcType::Cea608Field1,
+                    data: [0xc1, 0x80],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn non_atsc_user_data_is_not_parsed() {
+        let payload = [
+            0xb5, 0x00, 0x2f, 0x00, 0x00, 0x00, 0x00, 0x03, 0x41, 0xff, 0xfc, 0, 0,
+        ];
+
+        assert_eq!(parse_cc_data_sei_payload(&payload), None);
+    }
+
+    #[test]
+    fn cea608_null_pairs_are_padding() {
+        let padding = CcData {
+            cc_type: CcType::Cea608Field1,
+            data: [0x80, 0x80],
+        };
+
+        let caption = CcData {
+            cc_type: CcType::Cea608Field1,
+            data: [0x94, 0x20],
+        };
+
+        assert!(padding.is_padding(), "Expected null pair to be padding");
+        assert!(
+            !caption.is_padding(),
+            "Expected control code to not be padding"
+        );
+    }
+}
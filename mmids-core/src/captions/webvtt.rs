@@ -0,0 +1,140 @@
+//! Parsing of WebVTT caption files into caption cues
+
+use crate::captions::CaptionCue;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Errors that can occur when parsing WebVTT content
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum WebVttError {
+    #[error("The content does not start with a WEBVTT header")]
+    MissingHeader,
+
+    #[error("Invalid cue timing of '{0}'")]
+    InvalidCueTiming(String),
+}
+
+/// Parses the cues out of WebVTT content, ordered by their start time. Cue settings, styling, and
+/// regions are ignored, and any markup tags within a cue's text are removed.
+pub fn parse(content: &str) -> Result<Vec<CaptionCue>, WebVttError> {
+    let content = content.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    let mut blocks = content.split("\n\n");
+    match blocks.next() {
+        Some(header) if header.starts_with("WEBVTT") => (),
+        _ => return Err(WebVttError::MissingHeader),
+    }
+
+    let mut cues = Vec::new();
+    for block in blocks {
+        // Cues may start with an identifier line before their timing line, and blocks without a
+        // timing line (such as notes and styles) aren't cues.
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let timing = match lines.next() {
+            Some(timing) => timing,
+            None => continue,
+        };
+
+        let (start, end) = parse_cue_timing(timing)
+            .ok_or_else(|| WebVttError::InvalidCueTiming(timing.to_string()))?;
+
+        let text = lines.map(strip_markup).collect::<Vec<_>>().join("\n");
+        cues.push(CaptionCue { start, end, text });
+    }
+
+    cues.sort_by_key(|cue| cue.start);
+
+    Ok(cues)
+}
+
+fn parse_cue_timing(line: &str) -> Option<(Duration, Duration)> {
+    let (start, rest) = line.split_once("-->")?;
+    let end = rest.split_whitespace().next()?;
+
+    Some((parse_timestamp(start.trim())?, parse_timestamp(end)?))
+}
+
+/// Parses a `hh:mm:ss.ttt` or `mm:ss.ttt` timestamp
+fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    let (time, millis) = timestamp.split_once('.')?;
+    if millis.len() != 3 {
+        return None;
+    }
+
+    let mut seconds = 0;
+    for part in time.split(':') {
+        seconds = seconds * 60 + part.parse::<u64>().ok()?;
+    }
+
+    Some(Duration::from_secs(seconds) + Duration::from_millis(millis.parse().ok()?))
+}
+
+fn strip_markup(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut in_tag = false;
+    for character in line.chars() {
+        match character {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(character),
+            _ => (),
+        }
+    }
+
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_cues() {
+        let content = "WEBVTT - Example\r\n\r\n\
+            NOTE this is ignored\r\n\r\n\
+            intro\r\n\
+            00:00:01.500 --> 00:00:03.000 align:start\r\n\
+            <v Host>Hello</v> &amp; welcome\r\n\
+            to the show\r\n\r\n\
+            01:02.000 --> 01:04.250\r\n\
+            Bye\r\n";
+
+        let cues = parse(content).unwrap();
+        assert_eq!(
+            cues,
+            vec![
+                CaptionCue {
+                    start: Duration::from_millis(1500),
+                    end: Duration::from_secs(3),
+                    text: "Hello & welcome\nto the show".to_string(),
+                },
+                CaptionCue {
+                    start: Duration::from_secs(62),
+                    end: Duration::from_millis(64250),
+                    text: "Bye".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn content_without_header_returns_error() {
+        let result = parse("00:01.000 --> 00:02.000\nHello\n");
+
+        assert_eq!(result, Err(WebVttError::MissingHeader));
+    }
+
+    #[test]
+    fn invalid_timing_returns_error() {
+        let result = parse("WEBVTT\n\n00:01 --> 00:02.000\nHello\n");
+
+        assert_eq!(
+            result,
+            Err(WebVttError::InvalidCueTiming(
+                "00:01 --> 00:02.000".to_string()
+            ))
+        );
+    }
+}
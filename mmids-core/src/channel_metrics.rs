@@ -0,0 +1,113 @@
+//! Metrics about how backed up the channels feeding mmids' actors are. Actors process their
+//! messages one at a time, so an actor that can't keep up causes messages (and the media they
+//! carry) to pile up in its channel, eventually running the process out of memory. Recording each
+//! channel's depth makes these backlogs visible well before that happens.
+//!
+//! Actors forward the messages from all of their input channels (e.g. a workflow's media and its
+//! steps' future results) into a single channel they process messages from, so that channel is
+//! where any backlog builds up. Actors record that channel's depth to a `ChannelDepthGauge` each
+//! time they receive a message, which only costs a couple of atomic operations. The latest depth
+//! of every registered gauge can be read with `channel_depths()`.
+
+use lazy_static::lazy_static;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+lazy_static! {
+    static ref GAUGES: Mutex<Vec<Weak<GaugeInner>>> = Mutex::new(Vec::new());
+}
+
+struct GaugeInner {
+    name: String,
+    depth: AtomicUsize,
+    peak_depth: AtomicUsize,
+}
+
+/// Records the depth of a single channel. The gauge stops being reported once it's dropped.
+pub struct ChannelDepthGauge {
+    inner: Arc<GaugeInner>,
+}
+
+/// The most recently recorded depth of a channel
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelDepth {
+    pub name: String,
+
+    /// The number of messages that were waiting in the channel when it was last checked
+    pub depth: usize,
+
+    /// The largest depth ever recorded for the channel
+    pub peak_depth: usize,
+}
+
+impl ChannelDepthGauge {
+    /// Creates a gauge for the channel with the specified name, and registers it so its depth is
+    /// reported by `channel_depths()`. Names do not need to be unique.
+    pub fn register(name: impl Into<String>) -> Self {
+        let inner = Arc::new(GaugeInner {
+            name: name.into(),
+            depth: AtomicUsize::new(0),
+            peak_depth: AtomicUsize::new(0),
+        });
+
+        let mut gauges = GAUGES.lock().unwrap();
+        gauges.retain(|gauge| gauge.strong_count() > 0);
+        gauges.push(Arc::downgrade(&inner));
+
+        ChannelDepthGauge { inner }
+    }
+
+    /// Records the number of messages currently waiting in the channel
+    pub fn record(&self, depth: usize) {
+        self.inner.depth.store(depth, Ordering::Relaxed);
+        self.inner.peak_depth.fetch_max(depth, Ordering::Relaxed);
+    }
+}
+
+/// Returns the depth of every channel with a registered gauge, ordered by channel name
+pub fn channel_depths() -> Vec<ChannelDepth> {
+    let mut depths = GAUGES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|gauge| gauge.upgrade())
+        .map(|gauge| ChannelDepth {
+            name: gauge.name.clone(),
+            depth: gauge.depth.load(Ordering::Relaxed),
+            peak_depth: gauge.peak_depth.load(Ordering::Relaxed),
+        })
+        .collect::<Vec<_>>();
+
+    depths.sort_by(|first, second| first.name.cmp(&second.name));
+    depths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn depth_of(name: &str) -> Option<ChannelDepth> {
+        channel_depths()
+            .into_iter()
+            .find(|depth| depth.name == name)
+    }
+
+    #[test]
+    fn recorded_depth_and_peak_reported() {
+        let gauge = ChannelDepthGauge::register("recorded_depth_test");
+        gauge.record(5);
+        gauge.record(2);
+
+        let depth = depth_of("recorded_depth_test").expect("Gauge not reported");
+        assert_eq!(depth.depth, 2, "Unexpected depth");
+        assert_eq!(depth.peak_depth, 5, "Unexpected peak depth");
+    }
+
+    #[test]
+    fn dropped_gauge_no_longer_reported() {
+        let gauge = ChannelDepthGauge::register("dropped_gauge_test");
+        drop(gauge);
+
+        assert_eq!(depth_of("dropped_gauge_test"), None, "Expected no gauge");
+    }
+}
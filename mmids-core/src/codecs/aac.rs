@@ -0,0 +1,246 @@
+//! Helpers for reading AAC sequence headers, and for reading AAC frames carried with ADTS headers
+//! (as MPEG-TS does) so they can be converted to raw AAC.
+
+use super::nal::BitReader;
+use bytes::Bytes;
+use thiserror::Error;
+
+/// The sample rates that can be referred to by index in an audio specific config
+const SAMPLING_FREQUENCIES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Index signaling the sample rate is written out explicitly instead of being referred to by index
+const EXPLICIT_FREQUENCY_INDEX: u32 = 15;
+
+/// Errors that can occur when reading AAC data
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum AacError {
+    #[error("The audio specific config is truncated")]
+    TruncatedAudioSpecificConfig,
+
+    #[error("The audio specific config has an invalid sampling frequency index of {0}")]
+    InvalidSamplingFrequencyIndex(u8),
+
+    #[error("Expected an ADTS header but the sync word was not found")]
+    InvalidAdtsSyncWord,
+
+    #[error("The ADTS frame is truncated")]
+    TruncatedAdtsFrame,
+}
+
+/// A single AAC frame that was carried with an ADTS header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdtsFrame {
+    /// The audio specific config equivalent to the frame's ADTS header
+    pub config: AudioSpecificConfig,
+
+    /// The raw AAC frame, without its ADTS header
+    pub data: Bytes,
+}
+
+/// The fields of an AAC audio specific config, which is the sequence header for raw AAC audio.
+/// Only the fields common to all audio object types are read, so any object type specific
+/// configuration is ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioSpecificConfig {
+    pub audio_object_type: u8,
+    pub sample_rate: u32,
+    pub channel_configuration: u8,
+}
+
+impl AudioSpecificConfig {
+    /// Parses an audio specific config
+    pub fn parse(data: &[u8]) -> Result<Self, AacError> {
+        let mut reader = BitReader::new(data);
+        let mut audio_object_type = reader
+            .read_bits(5)
+            .ok_or(AacError::TruncatedAudioSpecificConfig)?;
+
+        if audio_object_type == 31 {
+            audio_object_type = 32
+                + reader
+                    .read_bits(6)
+                    .ok_or(AacError::TruncatedAudioSpecificConfig)?;
+        }
+
+        let frequency_index = reader
+            .read_bits(4)
+            .ok_or(AacError::TruncatedAudioSpecificConfig)?;
+
+        let sample_rate = if frequency_index == EXPLICIT_FREQUENCY_INDEX {
+            reader
+                .read_bits(24)
+                .ok_or(AacError::TruncatedAudioSpecificConfig)?
+        } else {
+            *SAMPLING_FREQUENCIES.get(frequency_index as usize).ok_or(
+                AacError::InvalidSamplingFrequencyIndex(frequency_index as u8),
+            )?
+        };
+
+        let channel_configuration = reader
+            .read_bits(4)
+            .ok_or(AacError::TruncatedAudioSpecificConfig)?;
+
+        Ok(AudioSpecificConfig {
+            audio_object_type: audio_object_type as u8,
+            sample_rate,
+            channel_configuration: channel_configuration as u8,
+        })
+    }
+
+    /// Writes the config in its binary form, so it can be used as a raw AAC sequence header
+    pub fn to_bytes(&self) -> Bytes {
+        let mut bits: Vec<(u32, usize)> = Vec::new();
+        if self.audio_object_type >= 31 {
+            bits.push((31, 5));
+            bits.push((u32::from(self.audio_object_type) - 32, 6));
+        } else {
+            bits.push((u32::from(self.audio_object_type), 5));
+        }
+
+        match SAMPLING_FREQUENCIES
+            .iter()
+            .position(|rate| *rate == self.sample_rate)
+        {
+            Some(index) => bits.push((index as u32, 4)),
+            None => {
+                bits.push((EXPLICIT_FREQUENCY_INDEX, 4));
+                bits.push((self.sample_rate, 24));
+            }
+        }
+
+        bits.push((u32::from(self.channel_configuration), 4));
+
+        let mut value = 0_u64;
+        let mut bit_count = 0;
+        for (bits, count) in bits {
+            value = (value << count) | u64::from(bits);
+            bit_count += count;
+        }
+
+        // Remaining bits (frame length flag, core coder and extension flags) are left as zero
+        let byte_count = bit_count.div_ceil(8);
+        value <<= byte_count * 8 - bit_count;
+
+        Bytes::from(value.to_be_bytes()[8 - byte_count..].to_vec())
+    }
+}
+
+/// Splits data containing one or more ADTS framed AAC frames into raw AAC frames
+pub fn split_adts(data: &Bytes) -> Result<Vec<AdtsFrame>, AacError> {
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let header = data
+            .get(offset..offset + 7)
+            .ok_or(AacError::TruncatedAdtsFrame)?;
+        if header[0] != 0xFF || header[1] & 0xF0 != 0xF0 {
+            return Err(AacError::InvalidAdtsSyncWord);
+        }
+
+        let protection_absent = header[1] & 0x01 == 1;
+        let profile = header[2] >> 6;
+        let frequency_index = (header[2] >> 2) & 0x0F;
+        let channel_configuration = ((header[2] & 0x01) << 2) | (header[3] >> 6);
+        let frame_length = (usize::from(header[3] & 0x03) << 11)
+            | (usize::from(header[4]) << 3)
+            | usize::from(header[5] >> 5);
+
+        let header_length = if protection_absent { 7 } else { 9 };
+        if frame_length < header_length || offset + frame_length > data.len() {
+            return Err(AacError::TruncatedAdtsFrame);
+        }
+
+        let sample_rate = *SAMPLING_FREQUENCIES
+            .get(frequency_index as usize)
+            .ok_or(AacError::InvalidSamplingFrequencyIndex(frequency_index))?;
+
+        frames.push(AdtsFrame {
+            config: AudioSpecificConfig {
+                audio_object_type: profile + 1,
+                sample_rate,
+                channel_configuration,
+            },
+            data: data.slice(offset + header_length..offset + frame_length),
+        });
+
+        offset += frame_length;
+    }
+
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_aac_lc_config() {
+        // AAC-LC, 44.1kHz, stereo
+        let config = AudioSpecificConfig::parse(&[0x12, 0x10]).unwrap();
+
+        assert_eq!(
+            config,
+            AudioSpecificConfig {
+                audio_object_type: 2,
+                sample_rate: 44100,
+                channel_configuration: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn can_parse_48khz_mono_config() {
+        let config = AudioSpecificConfig::parse(&[0x11, 0x88]).unwrap();
+
+        assert_eq!(config.sample_rate, 48000, "Unexpected sample rate");
+        assert_eq!(config.channel_configuration, 1, "Unexpected channels");
+    }
+
+    #[test]
+    fn config_written_as_bytes_round_trips() {
+        let config = AudioSpecificConfig::parse(&[0x12, 0x10]).unwrap();
+
+        assert_eq!(config.to_bytes().as_ref(), &[0x12, 0x10]);
+    }
+
+    #[test]
+    fn can_split_adts_frames() {
+        // AAC-LC, 48kHz, stereo, with a 2 byte and a 3 byte frame
+        let data = Bytes::from_static(&[
+            0xFF, 0xF1, 0x4C, 0x80, 0x01, 0x3F, 0xFC, 0xAA, 0xBB, 0xFF, 0xF1, 0x4C, 0x80, 0x01,
+            0x5F, 0xFC, 0xCC, 0xDD, 0xEE,
+        ]);
+
+        let frames = split_adts(&data).unwrap();
+
+        assert_eq!(frames.len(), 2, "Unexpected number of frames");
+        assert_eq!(
+            frames[0].data.as_ref(),
+            &[0xAA, 0xBB],
+            "Unexpected first frame"
+        );
+        assert_eq!(
+            frames[1].data.as_ref(),
+            &[0xCC, 0xDD, 0xEE],
+            "Unexpected second frame"
+        );
+        assert_eq!(
+            frames[0].config,
+            AudioSpecificConfig {
+                audio_object_type: 2,
+                sample_rate: 48000,
+                channel_configuration: 2,
+            },
+            "Unexpected config"
+        );
+    }
+
+    #[test]
+    fn truncated_config_returns_error() {
+        let result = AudioSpecificConfig::parse(&[0x12]);
+
+        assert_eq!(result, Err(AacError::TruncatedAudioSpecificConfig));
+    }
+}
@@ -0,0 +1,188 @@
+//! Helpers for working with AV1 video. AV1 is carried as a sequence of OBUs (open bitstream
+//! units), and its sequence header is an AV1 codec configuration record (`av1C`). The record
+//! holds the stream's profile, level, and color format, followed by any OBUs needed to configure
+//! the decoder (usually the sequence header OBU).
+
+use bytes::{BufMut, Bytes, BytesMut};
+use thiserror::Error;
+
+pub const OBU_TYPE_SEQUENCE_HEADER: u8 = 1;
+
+/// Number of bytes in the record before the configuration OBUs
+const CONFIGURATION_RECORD_HEADER_SIZE: usize = 4;
+
+/// The marker bit and version of the only record format defined
+const CONFIGURATION_RECORD_MARKER_AND_VERSION: u8 = 0x81;
+
+/// Errors that can occur when reading AV1 data
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum Av1Error {
+    #[error("The AV1 codec configuration record is truncated")]
+    TruncatedConfigurationRecord,
+
+    #[error("Unsupported AV1 codec configuration record marker and version byte of {0:#04x}")]
+    UnsupportedConfigurationVersion(u8),
+}
+
+/// Returns the type of the OBU, based on its header
+pub fn obu_type(obu: &[u8]) -> Option<u8> {
+    obu.first().map(|header| (header >> 3) & 0x0f)
+}
+
+/// The contents of an AV1 codec configuration record (`av1C`), which is the sequence header used
+/// for AV1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Av1CodecConfigurationRecord {
+    pub seq_profile: u8,
+    pub seq_level_idx_0: u8,
+    pub seq_tier_0: bool,
+    pub high_bitdepth: bool,
+    pub twelve_bit: bool,
+    pub monochrome: bool,
+    pub chroma_subsampling_x: bool,
+    pub chroma_subsampling_y: bool,
+    pub chroma_sample_position: u8,
+    pub initial_presentation_delay_minus_one: Option<u8>,
+
+    /// OBUs used to configure the decoder, kept as is
+    pub config_obus: Bytes,
+}
+
+impl Av1CodecConfigurationRecord {
+    /// Parses a codec configuration record
+    pub fn parse(data: &Bytes) -> Result<Self, Av1Error> {
+        if data.len() < CONFIGURATION_RECORD_HEADER_SIZE {
+            return Err(Av1Error::TruncatedConfigurationRecord);
+        }
+
+        if data[0] != CONFIGURATION_RECORD_MARKER_AND_VERSION {
+            return Err(Av1Error::UnsupportedConfigurationVersion(data[0]));
+        }
+
+        let initial_presentation_delay_minus_one = if data[3] & 0x10 != 0 {
+            Some(data[3] & 0x0f)
+        } else {
+            None
+        };
+
+        Ok(Av1CodecConfigurationRecord {
+            seq_profile: data[1] >> 5,
+            seq_level_idx_0: data[1] & 0x1f,
+            seq_tier_0: data[2] & 0x80 != 0,
+            high_bitdepth: data[2] & 0x40 != 0,
+            twelve_bit: data[2] & 0x20 != 0,
+            monochrome: data[2] & 0x10 != 0,
+            chroma_subsampling_x: data[2] & 0x08 != 0,
+            chroma_subsampling_y: data[2] & 0x04 != 0,
+            chroma_sample_position: data[2] & 0x03,
+            initial_presentation_delay_minus_one,
+            config_obus: data.slice(CONFIGURATION_RECORD_HEADER_SIZE..),
+        })
+    }
+
+    /// Serializes the codec configuration record
+    pub fn to_bytes(&self) -> Bytes {
+        let flag = |value: bool, bit: u8| if value { bit } else { 0 };
+
+        let mut buffer = BytesMut::new();
+        buffer.put_u8(CONFIGURATION_RECORD_MARKER_AND_VERSION);
+        buffer.put_u8((self.seq_profile << 5) | (self.seq_level_idx_0 & 0x1f));
+        buffer.put_u8(
+            flag(self.seq_tier_0, 0x80)
+                | flag(self.high_bitdepth, 0x40)
+                | flag(self.twelve_bit, 0x20)
+                | flag(self.monochrome, 0x10)
+                | flag(self.chroma_subsampling_x, 0x08)
+                | flag(self.chroma_subsampling_y, 0x04)
+                | (self.chroma_sample_position & 0x03),
+        );
+
+        buffer.put_u8(match self.initial_presentation_delay_minus_one {
+            Some(delay) => 0x10 | (delay & 0x0f),
+            None => 0,
+        });
+
+        buffer.put_slice(&self.config_obus);
+        buffer.freeze()
+    }
+
+    /// The number of bits per color sample
+    pub fn bit_depth(&self) -> u8 {
+        match (self.high_bitdepth, self.twelve_bit) {
+            (true, true) => 12,
+            (true, false) => 10,
+            _ => 8,
+        }
+    }
+
+    /// Returns true if the record's configuration OBUs start with a sequence header OBU
+    pub fn has_sequence_header(&self) -> bool {
+        obu_type(&self.config_obus) == Some(OBU_TYPE_SEQUENCE_HEADER)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequence_header_obu() -> Bytes {
+        Bytes::from_static(&[
+            0x0a, 0x0b, 0x00, 0x00, 0x00, 0x24, 0xc4, 0xff, 0xdf, 0x00, 0x68, 0x02,
+        ])
+    }
+
+    fn record_bytes() -> Bytes {
+        let mut buffer = BytesMut::new();
+        buffer.put_slice(&[0x81, 0x08, 0x4c, 0x00]);
+        buffer.put_slice(&sequence_header_obu());
+
+        buffer.freeze()
+    }
+
+    #[test]
+    fn configuration_record_can_be_parsed() {
+        let record = Av1CodecConfigurationRecord::parse(&record_bytes()).unwrap();
+
+        assert_eq!(record.seq_profile, 0, "Unexpected profile");
+        assert_eq!(record.seq_level_idx_0, 8, "Unexpected level");
+        assert!(!record.seq_tier_0, "Unexpected tier");
+        assert_eq!(record.bit_depth(), 10, "Unexpected bit depth");
+        assert!(record.chroma_subsampling_x, "Expected 4:2:0 subsampling");
+        assert!(record.chroma_subsampling_y, "Expected 4:2:0 subsampling");
+        assert_eq!(record.initial_presentation_delay_minus_one, None);
+        assert_eq!(record.config_obus, sequence_header_obu());
+        assert!(
+            record.has_sequence_header(),
+            "Expected a sequence header OBU"
+        );
+    }
+
+    #[test]
+    fn configuration_record_round_trips() {
+        let original = record_bytes();
+        let record = Av1CodecConfigurationRecord::parse(&original).unwrap();
+
+        assert_eq!(record.to_bytes(), original, "Record was not restored");
+    }
+
+    #[test]
+    fn truncated_configuration_record_returns_error() {
+        let truncated = record_bytes().slice(..3);
+
+        assert_eq!(
+            Av1CodecConfigurationRecord::parse(&truncated),
+            Err(Av1Error::TruncatedConfigurationRecord)
+        );
+    }
+
+    #[test]
+    fn unknown_configuration_version_returns_error() {
+        let mut data = BytesMut::from(&record_bytes()[..]);
+        data[0] = 0x82;
+
+        assert_eq!(
+            Av1CodecConfigurationRecord::parse(&data.freeze()),
+            Err(Av1Error::UnsupportedConfigurationVersion(0x82))
+        );
+    }
+}
@@ -0,0 +1,609 @@
+//! Helpers for working with H264 NAL units. H264 is commonly carried in two framings:
+//!
+//! * AVCC - Each NAL unit is prefixed with its length, and the SPS and PPS are carried in an AVC
+//!   decoder configuration record (`avcC`). This is what RTMP and MP4 use.
+//! * Annex-B - NAL units are separated by start codes, and the SPS and PPS are carried as regular
+//!   NAL units in the stream. This is what MPEG-TS uses.
+//!
+//! Converting NAL units between framings is done with the helpers in the `nal` module.
+
+use crate::codecs::nal::{
+    from_rbsp, read_parameter_sets, to_rbsp, validate_nal_length_size, BitReader, NalFramingError,
+};
+use crate::codecs::{FrameRate, VideoResolution};
+use bytes::{BufMut, Bytes, BytesMut};
+use thiserror::Error;
+
+pub const NAL_UNIT_TYPE_IDR: u8 = 5;
+pub const NAL_UNIT_TYPE_SEI: u8 = 6;
+pub const NAL_UNIT_TYPE_SPS: u8 = 7;
+pub const NAL_UNIT_TYPE_PPS: u8 = 8;
+pub const NAL_UNIT_TYPE_ACCESS_UNIT_DELIMITER: u8 = 9;
+
+/// Errors that can occur when reading or writing H264 data
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum H264Error {
+    #[error("Invalid NAL unit framing: {0}")]
+    Framing(#[from] NalFramingError),
+
+    #[error("The AVC decoder configuration record is truncated")]
+    TruncatedConfigurationRecord,
+
+    #[error("Unsupported AVC decoder configuration record version {0}")]
+    UnsupportedConfigurationVersion(u8),
+
+    #[error("No valid sequence parameter set was found")]
+    NoSequenceParameterSet,
+
+    #[error("The sequence parameter set could not be parsed")]
+    InvalidSequenceParameterSet,
+}
+
+/// A supplemental enhancement information message, which carries data alongside the video (such
+/// as closed captions) that isn't needed to decode it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeiMessage {
+    pub payload_type: u32,
+    pub payload: Bytes,
+}
+
+/// Profiles whose sequence parameter sets contain chroma format and bit depth information
+const EXTENDED_SAR: u32 = 255;
+const HIGH_PROFILES: [u32; 12] = [100, 110, 122, 244, 44, 83, 86, 118, 128, 138, 139, 134];
+
+/// Returns the type of the NAL unit, based on its header byte
+pub fn nal_unit_type(nal_unit: &[u8]) -> Option<u8> {
+    nal_unit.first().map(|header| header & 0x1f)
+}
+
+/// Returns true if the NAL unit contains the coded data of a picture (a slice), as opposed to
+/// parameter sets or other supplemental data. Supplemental NAL units must come before these
+/// within an access unit.
+pub fn is_video_coding_layer(nal_unit: &[u8]) -> bool {
+    matches!(nal_unit_type(nal_unit), Some(1..=NAL_UNIT_TYPE_IDR))
+}
+
+/// Creates an SEI NAL unit containing the specified messages
+pub fn create_sei_nal_unit(messages: &[SeiMessage]) -> Bytes {
+    let mut rbsp = Vec::new();
+    for message in messages {
+        write_sei_value(&mut rbsp, message.payload_type);
+        write_sei_value(&mut rbsp, message.payload.len() as u32);
+        rbsp.extend_from_slice(&message.payload);
+    }
+
+    rbsp.push(0x80); // rbsp trailing bits
+
+    let mut nal_unit = vec![NAL_UNIT_TYPE_SEI];
+    nal_unit.extend(from_rbsp(&rbsp));
+
+    Bytes::from(nal_unit)
+}
+
+/// Reads the messages out of an SEI NAL unit. Any messages following a truncated message are
+/// ignored, and no messages are returned if the NAL unit isn't an SEI NAL unit.
+pub fn parse_sei_nal_unit(nal_unit: &[u8]) -> Vec<SeiMessage> {
+    if nal_unit_type(nal_unit) != Some(NAL_UNIT_TYPE_SEI) {
+        return Vec::new();
+    }
+
+    let rbsp = to_rbsp(&nal_unit[1..]);
+    let mut messages = Vec::new();
+    let mut offset = 0;
+
+    // The final byte holds the rbsp trailing bits
+    while offset + 1 < rbsp.len() {
+        let payload_type = match read_sei_value(&rbsp, &mut offset) {
+            Some(value) => value,
+            None => break,
+        };
+
+        let payload_size = match read_sei_value(&rbsp, &mut offset) {
+            Some(value) => value as usize,
+            None => break,
+        };
+
+        if offset + payload_size > rbsp.len() {
+            break;
+        }
+
+        messages.push(SeiMessage {
+            payload_type,
+            payload: Bytes::copy_from_slice(&rbsp[offset..offset + payload_size]),
+        });
+
+        offset += payload_size;
+    }
+
+    messages
+}
+
+/// Reads the resolution of the video from a sequence parameter set NAL unit, taking any frame
+/// cropping into account.
+pub fn parse_sps_resolution(sps: &[u8]) -> Result<VideoResolution, H264Error> {
+    if nal_unit_type(sps) != Some(NAL_UNIT_TYPE_SPS) {
+        return Err(H264Error::InvalidSequenceParameterSet);
+    }
+
+    let rbsp = to_rbsp(&sps[1..]);
+    read_sps(&mut BitReader::new(&rbsp))
+        .map(|details| details.resolution)
+        .ok_or(H264Error::InvalidSequenceParameterSet)
+}
+
+/// Reads the frame rate of the video from a sequence parameter set NAL unit. `None` is returned
+/// if the SPS does not contain timing information.
+pub fn parse_sps_frame_rate(sps: &[u8]) -> Result<Option<FrameRate>, H264Error> {
+    if nal_unit_type(sps) != Some(NAL_UNIT_TYPE_SPS) {
+        return Err(H264Error::InvalidSequenceParameterSet);
+    }
+
+    let rbsp = to_rbsp(&sps[1..]);
+    read_sps(&mut BitReader::new(&rbsp))
+        .map(|details| details.frame_rate)
+        .ok_or(H264Error::InvalidSequenceParameterSet)
+}
+
+struct SpsDetails {
+    resolution: VideoResolution,
+    frame_rate: Option<FrameRate>,
+}
+
+fn read_sps(reader: &mut BitReader) -> Option<SpsDetails> {
+    let profile_idc = reader.read_bits(8)?;
+    reader.skip_bits(16)?; // constraint flags and level
+    reader.read_ue()?; // seq_parameter_set_id
+
+    let mut chroma_format_idc = 1;
+    if HIGH_PROFILES.contains(&profile_idc) {
+        chroma_format_idc = reader.read_ue()?;
+        if chroma_format_idc == 3 {
+            reader.read_bit()?; // separate_colour_plane_flag
+        }
+
+        reader.read_ue()?; // bit_depth_luma_minus8
+        reader.read_ue()?; // bit_depth_chroma_minus8
+        reader.read_bit()?; // qpprime_y_zero_transform_bypass_flag
+        if reader.read_bit()? == 1 {
+            let list_count = if chroma_format_idc == 3 { 12 } else { 8 };
+            for index in 0..list_count {
+                if reader.read_bit()? == 1 {
+                    skip_scaling_list(reader, if index < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    reader.read_ue()?; // log2_max_frame_num_minus4
+    match reader.read_ue()? {
+        0 => {
+            reader.read_ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+
+        1 => {
+            reader.read_bit()?; // delta_pic_order_always_zero_flag
+            reader.read_se()?; // offset_for_non_ref_pic
+            reader.read_se()?; // offset_for_top_to_bottom_field
+            for _ in 0..reader.read_ue()? {
+                reader.read_se()?; // offset_for_ref_frame
+            }
+        }
+
+        _ => (),
+    }
+
+    reader.read_ue()?; // max_num_ref_frames
+    reader.read_bit()?; // gaps_in_frame_num_value_allowed_flag
+    let width_in_mbs = reader.read_ue()? + 1;
+    let height_in_map_units = reader.read_ue()? + 1;
+    let frame_mbs_only = reader.read_bit()?;
+    if frame_mbs_only == 0 {
+        reader.read_bit()?; // mb_adaptive_frame_field_flag
+    }
+
+    reader.read_bit()?; // direct_8x8_inference_flag
+
+    let (mut crop_left, mut crop_right, mut crop_top, mut crop_bottom) = (0, 0, 0, 0);
+    if reader.read_bit()? == 1 {
+        crop_left = reader.read_ue()?;
+        crop_right = reader.read_ue()?;
+        crop_top = reader.read_ue()?;
+        crop_bottom = reader.read_ue()?;
+    }
+
+    let (crop_unit_x, crop_unit_y) = match chroma_format_idc {
+        0 => (1, 2 - frame_mbs_only),
+        1 => (2, 2 * (2 - frame_mbs_only)),
+        2 => (2, 2 - frame_mbs_only),
+        _ => (1, 2 - frame_mbs_only),
+    };
+
+    let width = (width_in_mbs * 16).checked_sub((crop_left + crop_right) * crop_unit_x)?;
+    let height = ((2 - frame_mbs_only) * height_in_map_units * 16)
+        .checked_sub((crop_top + crop_bottom) * crop_unit_y)?;
+
+    // A truncated or unusual VUI shouldn't prevent the resolution from being read
+    let frame_rate = match reader.read_bit() {
+        Some(1) => read_vui_frame_rate(reader),
+        _ => None,
+    };
+
+    Some(SpsDetails {
+        resolution: VideoResolution { width, height },
+        frame_rate,
+    })
+}
+
+fn read_vui_frame_rate(reader: &mut BitReader) -> Option<FrameRate> {
+    if reader.read_bit()? == 1 {
+        // aspect_ratio_idc, with an explicit sample aspect ratio for the extended value
+        if reader.read_bits(8)? == EXTENDED_SAR {
+            reader.skip_bits(32)?;
+        }
+    }
+
+    if reader.read_bit()? == 1 {
+        reader.read_bit()?; // overscan_appropriate_flag
+    }
+
+    if reader.read_bit()? == 1 {
+        reader.skip_bits(4)?; // video_format and video_full_range_flag
+        if reader.read_bit()? == 1 {
+            reader.skip_bits(24)?; // colour primaries, transfer and matrix coefficients
+        }
+    }
+
+    if reader.read_bit()? == 1 {
+        reader.read_ue()?; // chroma_sample_loc_type_top_field
+        reader.read_ue()?; // chroma_sample_loc_type_bottom_field
+    }
+
+    if reader.read_bit()? == 0 {
+        return None; // timing_info_present_flag
+    }
+
+    // Each frame is two ticks, one for each field
+    let num_units_in_tick = reader.read_bits(32)?;
+    let time_scale = reader.read_bits(32)?;
+
+    FrameRate::new(time_scale, num_units_in_tick.checked_mul(2)?)
+}
+
+fn skip_scaling_list(reader: &mut BitReader, size: usize) -> Option<()> {
+    let mut last_scale = 8;
+    let mut next_scale = 8;
+    for _ in 0..size {
+        if next_scale != 0 {
+            let delta = reader.read_se()?;
+            next_scale = (last_scale + delta + 256) % 256;
+        }
+
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+
+    Some(())
+}
+
+/// Creates an SPS NAL unit for the specified resolution, for use in tests
+#[cfg(test)]
+pub(crate) fn test_sps(width: u32, height: u32) -> Bytes {
+    test_sps_with_frame_rate(width, height, None)
+}
+
+/// Creates an SPS NAL unit for the specified resolution, with timing information for the specified
+/// frame rate if one is given, for use in tests
+#[cfg(test)]
+pub(crate) fn test_sps_with_frame_rate(
+    width: u32,
+    height: u32,
+    frame_rate: Option<FrameRate>,
+) -> Bytes {
+    use crate::codecs::nal::BitWriter;
+
+    let width_in_mbs = width.div_ceil(16);
+    let height_in_mbs = height.div_ceil(16);
+
+    let mut writer = BitWriter::default();
+    writer.write_bits(100, 8); // high profile
+    writer.write_bits(0, 8);
+    writer.write_bits(40, 8);
+    writer.write_ue(0); // seq_parameter_set_id
+    writer.write_ue(1); // chroma_format_idc (4:2:0)
+    writer.write_ue(0); // bit_depth_luma_minus8
+    writer.write_ue(0); // bit_depth_chroma_minus8
+    writer.write_bits(0, 1); // qpprime_y_zero_transform_bypass_flag
+    writer.write_bits(0, 1); // seq_scaling_matrix_present_flag
+    writer.write_ue(0); // log2_max_frame_num_minus4
+    writer.write_ue(0); // pic_order_cnt_type
+    writer.write_ue(2); // log2_max_pic_order_cnt_lsb_minus4
+    writer.write_ue(4); // max_num_ref_frames
+    writer.write_bits(0, 1); // gaps_in_frame_num_value_allowed_flag
+    writer.write_ue(width_in_mbs - 1);
+    writer.write_ue(height_in_mbs - 1);
+    writer.write_bits(1, 1); // frame_mbs_only_flag
+    writer.write_bits(1, 1); // direct_8x8_inference_flag
+
+    let crop_right = (width_in_mbs * 16 - width) / 2;
+    let crop_bottom = (height_in_mbs * 16 - height) / 2;
+    if crop_right > 0 || crop_bottom > 0 {
+        writer.write_bits(1, 1);
+        writer.write_ue(0);
+        writer.write_ue(crop_right);
+        writer.write_ue(0);
+        writer.write_ue(crop_bottom);
+    } else {
+        writer.write_bits(0, 1);
+    }
+
+    if let Some(frame_rate) = frame_rate {
+        writer.write_bits(1, 1); // vui_parameters_present_flag
+        writer.write_bits(0, 4); // no aspect ratio, overscan, video signal or chroma info
+        writer.write_bits(1, 1); // timing_info_present_flag
+        writer.write_bits(frame_rate.denominator, 32); // num_units_in_tick
+        writer.write_bits(frame_rate.numerator * 2, 32); // time_scale
+        writer.write_bits(1, 1); // fixed_frame_rate_flag
+        writer.write_bits(0, 5); // no hrd parameters, pic_struct or bitstream restrictions
+    } else {
+        writer.write_bits(0, 1); // vui_parameters_present_flag
+    }
+
+    writer.into_nal_unit(&[0x67])
+}
+
+/// The contents of an AVC decoder configuration record (`avcC`), which is the sequence header
+/// used for AVCC framed H264. Only the base record is supported, so any extensions for high
+/// profiles are ignored when parsing and not written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AvcDecoderConfigurationRecord {
+    pub profile_indication: u8,
+    pub profile_compatibility: u8,
+    pub level_indication: u8,
+    pub nal_length_size: u8,
+    pub sequence_parameter_sets: Vec<Bytes>,
+    pub picture_parameter_sets: Vec<Bytes>,
+}
+
+impl AvcDecoderConfigurationRecord {
+    /// Parses a decoder configuration record
+    pub fn parse(data: &Bytes) -> Result<Self, H264Error> {
+        if data.len() < 6 {
+            return Err(H264Error::TruncatedConfigurationRecord);
+        }
+
+        if data[0] != 1 {
+            return Err(H264Error::UnsupportedConfigurationVersion(data[0]));
+        }
+
+        let nal_length_size = (data[4] & 0x03) + 1;
+        validate_nal_length_size(nal_length_size)?;
+
+        let mut offset = 5;
+        let sps_count = (data[offset] & 0x1f) as usize;
+        offset += 1;
+        let sequence_parameter_sets = read_parameter_sets(data, &mut offset, sps_count)
+            .ok_or(H264Error::TruncatedConfigurationRecord)?;
+
+        let pps_count = *data
+            .get(offset)
+            .ok_or(H264Error::TruncatedConfigurationRecord)? as usize;
+
+        offset += 1;
+        let picture_parameter_sets = read_parameter_sets(data, &mut offset, pps_count)
+            .ok_or(H264Error::TruncatedConfigurationRecord)?;
+
+        Ok(AvcDecoderConfigurationRecord {
+            profile_indication: data[1],
+            profile_compatibility: data[2],
+            level_indication: data[3],
+            nal_length_size,
+            sequence_parameter_sets,
+            picture_parameter_sets,
+        })
+    }
+
+    /// Creates a decoder configuration record from parameter set NAL units, taking the profile
+    /// and level from the first sequence parameter set.
+    pub fn from_parameter_sets(
+        sequence_parameter_sets: Vec<Bytes>,
+        picture_parameter_sets: Vec<Bytes>,
+        nal_length_size: u8,
+    ) -> Result<Self, H264Error> {
+        validate_nal_length_size(nal_length_size)?;
+
+        // The three bytes following the SPS' NAL header are the profile, constraint flags, and level
+        let sps = match sequence_parameter_sets.first() {
+            Some(sps) if sps.len() >= 4 => sps,
+            _ => return Err(H264Error::NoSequenceParameterSet),
+        };
+
+        Ok(AvcDecoderConfigurationRecord {
+            profile_indication: sps[1],
+            profile_compatibility: sps[2],
+            level_indication: sps[3],
+            nal_length_size,
+            sequence_parameter_sets,
+            picture_parameter_sets,
+        })
+    }
+
+    /// Serializes the decoder configuration record
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buffer = BytesMut::new();
+        buffer.put_u8(1);
+        buffer.put_u8(self.profile_indication);
+        buffer.put_u8(self.profile_compatibility);
+        buffer.put_u8(self.level_indication);
+        buffer.put_u8(0xfc | (self.nal_length_size - 1));
+        buffer.put_u8(0xe0 | self.sequence_parameter_sets.len() as u8);
+        for sps in &self.sequence_parameter_sets {
+            buffer.put_u16(sps.len() as u16);
+            buffer.put_slice(sps);
+        }
+
+        buffer.put_u8(self.picture_parameter_sets.len() as u8);
+        for pps in &self.picture_parameter_sets {
+            buffer.put_u16(pps.len() as u16);
+            buffer.put_slice(pps);
+        }
+
+        buffer.freeze()
+    }
+
+    /// All sequence and picture parameter sets, in the order decoders expect them
+    pub fn parameter_sets(&self) -> Vec<Bytes> {
+        self.sequence_parameter_sets
+            .iter()
+            .chain(self.picture_parameter_sets.iter())
+            .cloned()
+            .collect()
+    }
+}
+
+/// Writes an SEI payload type or size, which are coded as a run of 0xff bytes followed by the
+/// remainder
+fn write_sei_value(buffer: &mut Vec<u8>, mut value: u32) {
+    while value >= 0xff {
+        buffer.push(0xff);
+        value -= 0xff;
+    }
+
+    buffer.push(value as u8);
+}
+
+fn read_sei_value(data: &[u8], offset: &mut usize) -> Option<u32> {
+    let mut value = 0;
+    loop {
+        let byte = *data.get(*offset)?;
+        *offset += 1;
+        value += byte as u32;
+
+        if byte != 0xff {
+            return Some(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sps() -> Bytes {
+        Bytes::from_static(&[0x67, 0x64, 0x00, 0x1f, 0xac, 0xd9, 0x40, 0x50])
+    }
+
+    fn pps() -> Bytes {
+        Bytes::from_static(&[0x68, 0xeb, 0xe3, 0xcb, 0x22, 0xc0])
+    }
+
+    #[test]
+    fn configuration_record_round_trips() {
+        let record =
+            AvcDecoderConfigurationRecord::from_parameter_sets(vec![sps()], vec![pps()], 4)
+                .unwrap();
+
+        assert_eq!(record.profile_indication, 0x64, "Unexpected profile");
+        assert_eq!(record.level_indication, 0x1f, "Unexpected level");
+
+        let parsed = AvcDecoderConfigurationRecord::parse(&record.to_bytes()).unwrap();
+        assert_eq!(parsed, record, "Parsed record did not match original");
+        assert_eq!(parsed.parameter_sets(), vec![sps(), pps()]);
+    }
+
+    #[test]
+    fn configuration_record_requires_sps() {
+        let result = AvcDecoderConfigurationRecord::from_parameter_sets(Vec::new(), vec![pps()], 4);
+
+        assert_eq!(result, Err(H264Error::NoSequenceParameterSet));
+    }
+
+    #[test]
+    fn resolution_read_from_sps() {
+        // Captured from an x264 encoded 1280x720 stream
+        let sps = [
+            0x67, 0x64, 0x00, 0x1f, 0xac, 0xd9, 0x40, 0x50, 0x05, 0xbb, 0x01, 0x10, 0x00, 0x00,
+            0x03, 0x00, 0x10, 0x00, 0x00, 0x03, 0x03, 0xc0, 0xf1, 0x83, 0x19, 0x60,
+        ];
+
+        assert_eq!(
+            parse_sps_resolution(&sps),
+            Ok(VideoResolution {
+                width: 1280,
+                height: 720
+            })
+        );
+    }
+
+    #[test]
+    fn cropped_resolution_read_from_sps() {
+        let sps = test_sps(1920, 1080);
+
+        assert_eq!(
+            parse_sps_resolution(&sps),
+            Ok(VideoResolution {
+                width: 1920,
+                height: 1080
+            })
+        );
+    }
+
+    #[test]
+    fn frame_rate_read_from_sps_timing_info() {
+        let frame_rate = FrameRate::new(30000, 2002).unwrap();
+        let sps = test_sps_with_frame_rate(1280, 720, Some(frame_rate));
+
+        assert_eq!(parse_sps_frame_rate(&sps), Ok(Some(frame_rate)));
+        assert_eq!(
+            parse_sps_resolution(&sps),
+            Ok(VideoResolution {
+                width: 1280,
+                height: 720
+            }),
+            "Timing information should not affect the resolution"
+        );
+    }
+
+    #[test]
+    fn no_frame_rate_without_timing_info() {
+        assert_eq!(parse_sps_frame_rate(&test_sps(1920, 1080)), Ok(None));
+    }
+
+    #[test]
+    fn non_sps_nal_unit_returns_error() {
+        assert_eq!(
+            parse_sps_resolution(&pps()),
+            Err(H264Error::InvalidSequenceParameterSet)
+        );
+    }
+
+    #[test]
+    fn nal_unit_type_read_from_header() {
+        assert_eq!(nal_unit_type(&sps()), Some(NAL_UNIT_TYPE_SPS));
+        assert_eq!(nal_unit_type(&pps()), Some(NAL_UNIT_TYPE_PPS));
+        assert_eq!(nal_unit_type(&[]), None);
+    }
+
+    #[test]
+    fn sei_messages_round_trip() {
+        let messages = vec![
+            SeiMessage {
+                payload_type: 4,
+                payload: Bytes::from_static(&[0xb5, 0x00, 0x00, 0x01, 0x02]),
+            },
+            SeiMessage {
+                payload_type: 5,
+                payload: Bytes::from(vec![0x42; 300]),
+            },
+        ];
+
+        let nal_unit = create_sei_nal_unit(&messages);
+        assert_eq!(nal_unit_type(&nal_unit), Some(NAL_UNIT_TYPE_SEI));
+        assert!(
+            !nal_unit.windows(3).any(|x| x == [0, 0, 1]),
+            "SEI NAL unit contains a start code"
+        );
+
+        assert_eq!(parse_sei_nal_unit(&nal_unit), messages);
+    }
+}
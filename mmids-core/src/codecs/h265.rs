@@ -0,0 +1,387 @@
+//! Helpers for working with H265 (HEVC) NAL units. Like H264, H265 is either carried with each NAL
+//! unit prefixed with its length (HVCC, used by RTMP and MP4) or separated by start codes (Annex-B,
+//! used by MPEG-TS), and the helpers in the `nal` module work for both codecs.
+//!
+//! Where H264 only has sequence and picture parameter sets, H265 streams also require a video
+//! parameter set (VPS) to be decoded. With HVCC framing all three are carried in an HEVC decoder
+//! configuration record (`hvcC`), which is the stream's sequence header.
+
+use crate::codecs::nal::{
+    read_parameter_sets, to_rbsp, validate_nal_length_size, BitReader, NalFramingError,
+};
+use crate::codecs::VideoResolution;
+use bytes::{BufMut, Bytes, BytesMut};
+use thiserror::Error;
+
+pub const NAL_UNIT_TYPE_IDR_W_RADL: u8 = 19;
+pub const NAL_UNIT_TYPE_IDR_N_LP: u8 = 20;
+pub const NAL_UNIT_TYPE_CRA: u8 = 21;
+pub const NAL_UNIT_TYPE_VPS: u8 = 32;
+pub const NAL_UNIT_TYPE_SPS: u8 = 33;
+pub const NAL_UNIT_TYPE_PPS: u8 = 34;
+pub const NAL_UNIT_TYPE_ACCESS_UNIT_DELIMITER: u8 = 35;
+
+/// Number of bytes in the record between the version and the array count
+const GENERAL_CONFIGURATION_SIZE: usize = 21;
+
+/// Errors that can occur when reading or writing H265 data
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum H265Error {
+    #[error("Invalid NAL unit framing: {0}")]
+    Framing(#[from] NalFramingError),
+
+    #[error("The HEVC decoder configuration record is truncated")]
+    TruncatedConfigurationRecord,
+
+    #[error("Unsupported HEVC decoder configuration record version {0}")]
+    UnsupportedConfigurationVersion(u8),
+
+    #[error("The sequence parameter set could not be parsed")]
+    InvalidSequenceParameterSet,
+}
+
+/// Returns the type of the NAL unit, based on its header
+pub fn nal_unit_type(nal_unit: &[u8]) -> Option<u8> {
+    nal_unit.first().map(|header| (header >> 1) & 0x3f)
+}
+
+/// Returns true if the NAL unit is the start of a random access point (IDR or CRA picture)
+pub fn is_random_access_point(nal_unit: &[u8]) -> bool {
+    matches!(
+        nal_unit_type(nal_unit),
+        Some(NAL_UNIT_TYPE_IDR_W_RADL | NAL_UNIT_TYPE_IDR_N_LP | NAL_UNIT_TYPE_CRA)
+    )
+}
+
+/// Reads the resolution of the video from a sequence parameter set NAL unit, taking the
+/// conformance window into account.
+pub fn parse_sps_resolution(sps: &[u8]) -> Result<VideoResolution, H265Error> {
+    if nal_unit_type(sps) != Some(NAL_UNIT_TYPE_SPS) || sps.len() < 2 {
+        return Err(H265Error::InvalidSequenceParameterSet);
+    }
+
+    let rbsp = to_rbsp(&sps[2..]);
+    read_sps_resolution(&mut BitReader::new(&rbsp)).ok_or(H265Error::InvalidSequenceParameterSet)
+}
+
+fn read_sps_resolution(reader: &mut BitReader) -> Option<VideoResolution> {
+    reader.read_bits(4)?; // sps_video_parameter_set_id
+    let max_sub_layers_minus1 = reader.read_bits(3)? as usize;
+    reader.read_bit()?; // sps_temporal_id_nesting_flag
+
+    // profile_tier_level, which starts with the 88 bit general profile and 8 bit general level
+    reader.skip_bits(88 + 8)?;
+    let mut sub_layer_flags = Vec::with_capacity(max_sub_layers_minus1);
+    for _ in 0..max_sub_layers_minus1 {
+        let profile_present = reader.read_bit()? == 1;
+        let level_present = reader.read_bit()? == 1;
+        sub_layer_flags.push((profile_present, level_present));
+    }
+
+    if max_sub_layers_minus1 > 0 {
+        reader.skip_bits((8 - max_sub_layers_minus1) * 2)?; // reserved_zero_2bits
+    }
+
+    for (profile_present, level_present) in sub_layer_flags {
+        if profile_present {
+            reader.skip_bits(88)?;
+        }
+
+        if level_present {
+            reader.skip_bits(8)?;
+        }
+    }
+
+    reader.read_ue()?; // sps_seq_parameter_set_id
+    let mut chroma_format_idc = reader.read_ue()?;
+    if chroma_format_idc == 3 && reader.read_bit()? == 1 {
+        // With separate colour planes each plane is coded as monochrome
+        chroma_format_idc = 0;
+    }
+
+    let width = reader.read_ue()?;
+    let height = reader.read_ue()?;
+
+    let (mut crop_left, mut crop_right, mut crop_top, mut crop_bottom) = (0, 0, 0, 0);
+    if reader.read_bit()? == 1 {
+        crop_left = reader.read_ue()?;
+        crop_right = reader.read_ue()?;
+        crop_top = reader.read_ue()?;
+        crop_bottom = reader.read_ue()?;
+    }
+
+    let (sub_width, sub_height) = match chroma_format_idc {
+        1 => (2, 2),
+        2 => (2, 1),
+        _ => (1, 1),
+    };
+
+    Some(VideoResolution {
+        width: width.checked_sub((crop_left + crop_right) * sub_width)?,
+        height: height.checked_sub((crop_top + crop_bottom) * sub_height)?,
+    })
+}
+
+/// Creates an SPS NAL unit for the specified resolution, for use in tests
+#[cfg(test)]
+pub(crate) fn test_sps(width: u32, height: u32) -> Bytes {
+    use crate::codecs::nal::BitWriter;
+
+    // Coded sizes must be a multiple of the minimum coding block size
+    let coded_width = width.div_ceil(8) * 8;
+    let coded_height = height.div_ceil(8) * 8;
+
+    let mut writer = BitWriter::default();
+    writer.write_bits(0, 4); // sps_video_parameter_set_id
+    writer.write_bits(0, 3); // sps_max_sub_layers_minus1
+    writer.write_bits(1, 1); // sps_temporal_id_nesting_flag
+    writer.write_bits(0x01, 8); // general profile space, tier, and profile (main)
+    writer.write_bits(0x6000_0000, 32); // general profile compatibility flags
+    writer.write_bits(0x9000, 16); // progressive and frame only constraint flags
+    writer.write_bits(0, 32);
+    writer.write_bits(120, 8); // general_level_idc (level 4)
+    writer.write_ue(0); // sps_seq_parameter_set_id
+    writer.write_ue(1); // chroma_format_idc (4:2:0)
+    writer.write_ue(coded_width);
+    writer.write_ue(coded_height);
+
+    if coded_width != width || coded_height != height {
+        writer.write_bits(1, 1);
+        writer.write_ue(0);
+        writer.write_ue((coded_width - width) / 2);
+        writer.write_ue(0);
+        writer.write_ue((coded_height - height) / 2);
+    } else {
+        writer.write_bits(0, 1);
+    }
+
+    // The remainder of the SPS isn't needed to read the resolution
+    writer.into_nal_unit(&[0x42, 0x01])
+}
+
+/// A set of NAL units of the same type within an HEVC decoder configuration record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NalUnitArray {
+    pub array_completeness: bool,
+    pub nal_unit_type: u8,
+    pub nal_units: Vec<Bytes>,
+}
+
+/// The contents of an HEVC decoder configuration record (`hvcC`), which is the sequence header
+/// used for HVCC framed H265.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HevcDecoderConfigurationRecord {
+    /// The profile, tier, level, and format fields of the record. These are kept as is, since
+    /// mmids does not need to interpret them. The length size bits of the final byte are replaced
+    /// by `nal_length_size` when serialized.
+    pub general_configuration: Bytes,
+    pub nal_length_size: u8,
+    pub arrays: Vec<NalUnitArray>,
+}
+
+impl HevcDecoderConfigurationRecord {
+    /// Parses a decoder configuration record
+    pub fn parse(data: &Bytes) -> Result<Self, H265Error> {
+        if data.len() < GENERAL_CONFIGURATION_SIZE + 2 {
+            return Err(H265Error::TruncatedConfigurationRecord);
+        }
+
+        if data[0] != 1 {
+            return Err(H265Error::UnsupportedConfigurationVersion(data[0]));
+        }
+
+        let general_configuration = data.slice(1..GENERAL_CONFIGURATION_SIZE + 1);
+        let nal_length_size = (general_configuration[GENERAL_CONFIGURATION_SIZE - 1] & 0x03) + 1;
+        validate_nal_length_size(nal_length_size)?;
+
+        let mut offset = GENERAL_CONFIGURATION_SIZE + 1;
+        let array_count = data[offset] as usize;
+        offset += 1;
+
+        let mut arrays = Vec::with_capacity(array_count);
+        for _ in 0..array_count {
+            if offset + 3 > data.len() {
+                return Err(H265Error::TruncatedConfigurationRecord);
+            }
+
+            let array_completeness = data[offset] & 0x80 != 0;
+            let nal_unit_type = data[offset] & 0x3f;
+            let count = u16::from_be_bytes([data[offset + 1], data[offset + 2]]) as usize;
+            offset += 3;
+
+            let nal_units = read_parameter_sets(data, &mut offset, count)
+                .ok_or(H265Error::TruncatedConfigurationRecord)?;
+
+            arrays.push(NalUnitArray {
+                array_completeness,
+                nal_unit_type,
+                nal_units,
+            });
+        }
+
+        Ok(HevcDecoderConfigurationRecord {
+            general_configuration,
+            nal_length_size,
+            arrays,
+        })
+    }
+
+    /// Serializes the decoder configuration record
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buffer = BytesMut::new();
+        buffer.put_u8(1);
+
+        let last_index = self.general_configuration.len().saturating_sub(1);
+        for (index, byte) in self.general_configuration.iter().enumerate() {
+            if index == last_index {
+                buffer.put_u8((byte & 0xfc) | (self.nal_length_size - 1));
+            } else {
+                buffer.put_u8(*byte);
+            }
+        }
+
+        buffer.put_u8(self.arrays.len() as u8);
+        for array in &self.arrays {
+            let completeness = if array.array_completeness { 0x80 } else { 0 };
+            buffer.put_u8(completeness | (array.nal_unit_type & 0x3f));
+            buffer.put_u16(array.nal_units.len() as u16);
+            for nal_unit in &array.nal_units {
+                buffer.put_u16(nal_unit.len() as u16);
+                buffer.put_slice(nal_unit);
+            }
+        }
+
+        buffer.freeze()
+    }
+
+    /// All NAL units in the record (e.g. VPS, SPS, and PPS), in the order they are stored
+    pub fn parameter_sets(&self) -> Vec<Bytes> {
+        self.arrays
+            .iter()
+            .flat_map(|array| array.nal_units.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Returns true if the record contains at least one VPS, SPS, and PPS, which are all required
+    /// for decoding.
+    pub fn has_required_parameter_sets(&self) -> bool {
+        [NAL_UNIT_TYPE_VPS, NAL_UNIT_TYPE_SPS, NAL_UNIT_TYPE_PPS]
+            .iter()
+            .all(|nal_type| {
+                self.arrays
+                    .iter()
+                    .any(|array| array.nal_unit_type == *nal_type && !array.nal_units.is_empty())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::nal::{split_annexb, write_annexb};
+
+    fn vps() -> Bytes {
+        Bytes::from_static(&[0x40, 0x01, 0x0c, 0x01, 0xff, 0xff, 0x01, 0x60])
+    }
+
+    fn sps() -> Bytes {
+        Bytes::from_static(&[0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90])
+    }
+
+    fn pps() -> Bytes {
+        Bytes::from_static(&[0x44, 0x01, 0xc1, 0x72, 0xb4, 0x62, 0x40])
+    }
+
+    fn record_bytes() -> Bytes {
+        let mut buffer = BytesMut::new();
+        buffer.put_u8(1);
+        buffer.put_slice(&[
+            0x01, 0x60, 0x00, 0x00, 0x00, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00, 0x5d, 0xf0, 0x00,
+            0xfc, 0xfd, 0xf8, 0xf8, 0x00, 0x00, 0x0f,
+        ]);
+
+        buffer.put_u8(3);
+        for (nal_type, nal_unit) in [
+            (NAL_UNIT_TYPE_VPS, vps()),
+            (NAL_UNIT_TYPE_SPS, sps()),
+            (NAL_UNIT_TYPE_PPS, pps()),
+        ] {
+            buffer.put_u8(0x80 | nal_type);
+            buffer.put_u16(1);
+            buffer.put_u16(nal_unit.len() as u16);
+            buffer.put_slice(&nal_unit);
+        }
+
+        buffer.freeze()
+    }
+
+    #[test]
+    fn configuration_record_can_be_parsed() {
+        let record = HevcDecoderConfigurationRecord::parse(&record_bytes()).unwrap();
+
+        assert_eq!(record.nal_length_size, 4, "Unexpected NAL length size");
+        assert_eq!(record.parameter_sets(), vec![vps(), sps(), pps()]);
+        assert!(
+            record.has_required_parameter_sets(),
+            "Expected VPS, SPS, and PPS to be present"
+        );
+    }
+
+    #[test]
+    fn configuration_record_round_trips() {
+        let original = record_bytes();
+        let record = HevcDecoderConfigurationRecord::parse(&original).unwrap();
+
+        assert_eq!(record.to_bytes(), original, "Record was not restored");
+    }
+
+    #[test]
+    fn truncated_configuration_record_returns_error() {
+        let data = record_bytes();
+        let truncated = data.slice(..data.len() - 3);
+
+        assert_eq!(
+            HevcDecoderConfigurationRecord::parse(&truncated),
+            Err(H265Error::TruncatedConfigurationRecord)
+        );
+    }
+
+    #[test]
+    fn parameter_sets_round_trip_through_annexb() {
+        let record = HevcDecoderConfigurationRecord::parse(&record_bytes()).unwrap();
+        let annexb = write_annexb(&record.parameter_sets());
+
+        assert_eq!(split_annexb(&annexb), vec![vps(), sps(), pps()]);
+    }
+
+    #[test]
+    fn resolution_read_from_sps() {
+        for (width, height) in [(1920, 1080), (3840, 2160), (1280, 720)] {
+            assert_eq!(
+                parse_sps_resolution(&test_sps(width, height)),
+                Ok(VideoResolution { width, height }),
+                "Unexpected resolution for {}x{}",
+                width,
+                height
+            );
+        }
+    }
+
+    #[test]
+    fn non_sps_nal_unit_returns_error() {
+        assert_eq!(
+            parse_sps_resolution(&pps()),
+            Err(H265Error::InvalidSequenceParameterSet)
+        );
+    }
+
+    #[test]
+    fn nal_unit_type_read_from_header() {
+        assert_eq!(nal_unit_type(&vps()), Some(NAL_UNIT_TYPE_VPS));
+        assert_eq!(nal_unit_type(&sps()), Some(NAL_UNIT_TYPE_SPS));
+        assert_eq!(nal_unit_type(&pps()), Some(NAL_UNIT_TYPE_PPS));
+        assert!(is_random_access_point(&[0x26, 0x01]), "Expected IDR");
+        assert!(!is_random_access_point(&[0x02, 0x01]), "Expected non-IDR");
+    }
+}
@@ -0,0 +1,132 @@
+//! Standard codec identifiers, and helpers for working with specific codecs
+pub mod aac;
+pub mod av1;
+pub mod h264;
+pub mod h265;
+pub mod nal;
+
+use bytes::Bytes;
+use h264::AvcDecoderConfigurationRecord;
+use h265::HevcDecoderConfigurationRecord;
+use lazy_static::lazy_static;
+use nal::split_annexb;
+use std::fmt;
+use std::sync::Arc;
+
+lazy_static! {
+    pub static ref VIDEO_CODEC_H264_AVC: Arc<String> = Arc::new("h264-avc".to_string());
+    pub static ref VIDEO_CODEC_H264_ANNEXB: Arc<String> = Arc::new("h264-annexb".to_string());
+    pub static ref VIDEO_CODEC_H265_HVCC: Arc<String> = Arc::new("h265-hvcc".to_string());
+    pub static ref VIDEO_CODEC_VP8: Arc<String> = Arc::new("vp8".to_string());
+    pub static ref VIDEO_CODEC_VP9: Arc<String> = Arc::new("vp9".to_string());
+    pub static ref VIDEO_CODEC_AV1: Arc<String> = Arc::new("av1".to_string());
+    pub static ref AUDIO_CODEC_AAC_RAW: Arc<String> = Arc::new("aac-raw".to_string());
+    pub static ref AUDIO_CODEC_OPUS: Arc<String> = Arc::new("opus".to_string());
+
+    /// Payload type of `Other` media payloads containing raw MPEG-TS packets
+    pub static ref CONTAINER_MPEG_TS: Arc<String> = Arc::new("mpegts".to_string());
+}
+
+/// The dimensions of decoded video frames, as described by a codec's sequence parameter set
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VideoResolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl fmt::Display for VideoResolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// The rate video frames are shown at, as a fraction of frames per second (e.g. `30000/1001` for
+/// 29.97 fps)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameRate {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl FrameRate {
+    /// Creates a frame rate, as long as both parts of the fraction are non-zero. The fraction is
+    /// reduced so equivalent frame rates compare as equal.
+    pub fn new(numerator: u32, denominator: u32) -> Option<Self> {
+        if numerator == 0 || denominator == 0 {
+            return None;
+        }
+
+        let (mut a, mut b) = (numerator, denominator);
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+
+        Some(FrameRate {
+            numerator: numerator / a,
+            denominator: denominator / a,
+        })
+    }
+
+    pub fn frames_per_second(&self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
+    }
+}
+
+impl fmt::Display for FrameRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.2}fps", self.frames_per_second())
+    }
+}
+
+/// Reads the resolution from the sequence parameter set contained in the video payload, if the
+/// codec is known and the payload has one.
+pub fn read_video_resolution(
+    payload_type: &str,
+    data: &Bytes,
+    is_required_for_decoding: bool,
+) -> Option<VideoResolution> {
+    if payload_type == VIDEO_CODEC_H264_AVC.as_str() && is_required_for_decoding {
+        let record = AvcDecoderConfigurationRecord::parse(data).ok()?;
+        let sps = record.sequence_parameter_sets.first()?;
+
+        h264::parse_sps_resolution(sps).ok()
+    } else if payload_type == VIDEO_CODEC_H264_ANNEXB.as_str() {
+        // Annex-B streams commonly carry their SPS in-band with keyframes
+        split_annexb(data)
+            .iter()
+            .filter(|nal_unit| h264::nal_unit_type(nal_unit) == Some(h264::NAL_UNIT_TYPE_SPS))
+            .find_map(|sps| h264::parse_sps_resolution(sps).ok())
+    } else if payload_type == VIDEO_CODEC_H265_HVCC.as_str() && is_required_for_decoding {
+        let record = HevcDecoderConfigurationRecord::parse(data).ok()?;
+        record
+            .parameter_sets()
+            .iter()
+            .filter(|nal_unit| h265::nal_unit_type(nal_unit) == Some(h265::NAL_UNIT_TYPE_SPS))
+            .find_map(|sps| h265::parse_sps_resolution(sps).ok())
+    } else {
+        None
+    }
+}
+
+/// Reads the frame rate from the timing information in the sequence parameter set contained in
+/// the video payload. Only H264 is supported, and `None` is returned if the payload does not have
+/// a sequence parameter set or the sequence parameter set has no timing information.
+pub fn read_video_frame_rate(
+    payload_type: &str,
+    data: &Bytes,
+    is_required_for_decoding: bool,
+) -> Option<FrameRate> {
+    if payload_type == VIDEO_CODEC_H264_AVC.as_str() && is_required_for_decoding {
+        let record = AvcDecoderConfigurationRecord::parse(data).ok()?;
+        let sps = record.sequence_parameter_sets.first()?;
+
+        h264::parse_sps_frame_rate(sps).ok().flatten()
+    } else if payload_type == VIDEO_CODEC_H264_ANNEXB.as_str() {
+        split_annexb(data)
+            .iter()
+            .filter(|nal_unit| h264::nal_unit_type(nal_unit) == Some(h264::NAL_UNIT_TYPE_SPS))
+            .find_map(|sps| h264::parse_sps_frame_rate(sps).ok().flatten())
+    } else {
+        None
+    }
+}
@@ -0,0 +1,457 @@
+//! Helpers for the two ways NAL units (the units of data H264 and H265 streams are made of) are
+//! framed:
+//!
+//! * Length prefixed - Each NAL unit is prefixed with its length. This is what RTMP and MP4 use,
+//!   and is referred to as AVCC for H264 and HVCC for H265.
+//! * Annex-B - NAL units are separated by start codes. This is what MPEG-TS uses.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use thiserror::Error;
+
+/// The NAL length size used when one isn't dictated by an existing decoder configuration record
+pub const DEFAULT_NAL_LENGTH_SIZE: u8 = 4;
+
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// Errors that can occur when reading or writing framed NAL units
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum NalFramingError {
+    #[error("Invalid NAL length size of {0}, it must be 1, 2, or 4")]
+    InvalidNalLengthSize(u8),
+
+    #[error(
+        "NAL unit at offset {offset} has a length of {length}, but only {remaining} bytes remain"
+    )]
+    TruncatedNalUnit {
+        offset: usize,
+        length: usize,
+        remaining: usize,
+    },
+
+    #[error("NAL unit of {length} bytes does not fit in a {nal_length_size} byte length prefix")]
+    NalUnitTooLarge { length: usize, nal_length_size: u8 },
+}
+
+/// Splits length prefixed data into its NAL units, based on the size of each unit's length prefix
+pub fn split_length_prefixed(
+    data: &Bytes,
+    nal_length_size: u8,
+) -> Result<Vec<Bytes>, NalFramingError> {
+    validate_nal_length_size(nal_length_size)?;
+
+    let prefix_size = nal_length_size as usize;
+    let mut nal_units = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let remaining = data.len() - offset;
+        if remaining < prefix_size {
+            return Err(NalFramingError::TruncatedNalUnit {
+                offset,
+                length: prefix_size,
+                remaining,
+            });
+        }
+
+        let length = data[offset..offset + prefix_size]
+            .iter()
+            .fold(0, |length, byte| (length << 8) | *byte as usize);
+
+        offset += prefix_size;
+        if length > data.len() - offset {
+            return Err(NalFramingError::TruncatedNalUnit {
+                offset,
+                length,
+                remaining: data.len() - offset,
+            });
+        }
+
+        nal_units.push(data.slice(offset..offset + length));
+        offset += length;
+    }
+
+    Ok(nal_units)
+}
+
+/// Writes the NAL units out with each unit prefixed by its length
+pub fn write_length_prefixed(
+    nal_units: &[Bytes],
+    nal_length_size: u8,
+) -> Result<Bytes, NalFramingError> {
+    validate_nal_length_size(nal_length_size)?;
+
+    let prefix_size = nal_length_size as usize;
+    let max_length = (1_u64 << (prefix_size * 8)) - 1;
+    let total_size = nal_units.iter().map(|n| n.len() + prefix_size).sum();
+    let mut buffer = BytesMut::with_capacity(total_size);
+    for nal_unit in nal_units {
+        if nal_unit.len() as u64 > max_length {
+            return Err(NalFramingError::NalUnitTooLarge {
+                length: nal_unit.len(),
+                nal_length_size,
+            });
+        }
+
+        buffer.put_uint(nal_unit.len() as u64, prefix_size);
+        buffer.put_slice(nal_unit);
+    }
+
+    Ok(buffer.freeze())
+}
+
+/// Splits Annex-B framed data into its NAL units. Both 3 and 4 byte start codes are supported, and
+/// any data before the first start code is ignored.
+pub fn split_annexb(data: &Bytes) -> Vec<Bytes> {
+    let mut nal_units = Vec::new();
+    let mut nal_start = None;
+    let mut index = 0;
+    while index + 3 <= data.len() {
+        if data[index] == 0 && data[index + 1] == 0 && data[index + 2] == 1 {
+            if let Some(start) = nal_start {
+                push_annexb_nal_unit(&mut nal_units, data.slice(start..index));
+            }
+
+            index += 3;
+            nal_start = Some(index);
+        } else {
+            index += 1;
+        }
+    }
+
+    if let Some(start) = nal_start {
+        push_annexb_nal_unit(&mut nal_units, data.slice(start..));
+    }
+
+    nal_units
+}
+
+/// Writes the NAL units out in Annex-B framing, with each unit preceded by a 4 byte start code
+pub fn write_annexb(nal_units: &[Bytes]) -> Bytes {
+    let total_size = nal_units.iter().map(|n| n.len() + START_CODE.len()).sum();
+    let mut buffer = BytesMut::with_capacity(total_size);
+    for nal_unit in nal_units {
+        buffer.put_slice(&START_CODE);
+        buffer.put_slice(nal_unit);
+    }
+
+    buffer.freeze()
+}
+
+pub(crate) fn validate_nal_length_size(nal_length_size: u8) -> Result<(), NalFramingError> {
+    match nal_length_size {
+        1 | 2 | 4 => Ok(()),
+        x => Err(NalFramingError::InvalidNalLengthSize(x)),
+    }
+}
+
+/// Reads the specified number of parameter sets, each prefixed with a 16 bit length, as they are
+/// stored in decoder configuration records. Returns `None` if the data is truncated.
+pub(crate) fn read_parameter_sets(
+    data: &Bytes,
+    offset: &mut usize,
+    count: usize,
+) -> Option<Vec<Bytes>> {
+    let mut parameter_sets = Vec::with_capacity(count);
+    for _ in 0..count {
+        if *offset + 2 > data.len() {
+            return None;
+        }
+
+        let length = u16::from_be_bytes([data[*offset], data[*offset + 1]]) as usize;
+        *offset += 2;
+        if *offset + length > data.len() {
+            return None;
+        }
+
+        parameter_sets.push(data.slice(*offset..*offset + length));
+        *offset += length;
+    }
+
+    Some(parameter_sets)
+}
+
+/// Removes the emulation prevention bytes from a NAL unit, producing its raw byte sequence payload
+/// (RBSP). This is required before any of the NAL unit's fields can be read.
+pub fn to_rbsp(nal_unit: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(nal_unit.len());
+    let mut zero_count = 0;
+    for byte in nal_unit {
+        if zero_count >= 2 && *byte == 3 {
+            // Emulation prevention byte, so skip it
+            zero_count = 0;
+            continue;
+        }
+
+        if *byte == 0 {
+            zero_count += 1;
+        } else {
+            zero_count = 0;
+        }
+
+        rbsp.push(*byte);
+    }
+
+    rbsp
+}
+
+/// Inserts emulation prevention bytes into a raw byte sequence payload, so no part of it can be
+/// mistaken for a start code once it's placed in a NAL unit. This is the inverse of `to_rbsp()`.
+pub fn from_rbsp(rbsp: &[u8]) -> Vec<u8> {
+    let mut nal_unit = Vec::with_capacity(rbsp.len());
+    let mut zero_count = 0;
+    for byte in rbsp {
+        if zero_count >= 2 && *byte <= 3 {
+            nal_unit.push(3);
+            zero_count = 0;
+        }
+
+        zero_count = if *byte == 0 { zero_count + 1 } else { 0 };
+        nal_unit.push(*byte);
+    }
+
+    nal_unit
+}
+
+/// Reads individual bits, and the exponential-Golomb coded values used by parameter sets, out of a
+/// NAL unit's RBSP. All reads return `None` once the end of the data has been reached.
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BitReader { data, position: 0 }
+    }
+
+    pub fn read_bit(&mut self) -> Option<u32> {
+        let byte = self.data.get(self.position / 8)?;
+        let bit = (byte >> (7 - (self.position % 8))) & 1;
+        self.position += 1;
+
+        Some(bit as u32)
+    }
+
+    pub fn read_bits(&mut self, count: u8) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()?;
+        }
+
+        Some(value)
+    }
+
+    pub fn skip_bits(&mut self, count: usize) -> Option<()> {
+        if self.position + count > self.data.len() * 8 {
+            return None;
+        }
+
+        self.position += count;
+        Some(())
+    }
+
+    /// Reads an unsigned exponential-Golomb coded value
+    pub fn read_ue(&mut self) -> Option<u32> {
+        let mut leading_zeros = 0;
+        while self.read_bit()? == 0 {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return None;
+            }
+        }
+
+        let suffix = self.read_bits(leading_zeros)?;
+        Some(((1_u64 << leading_zeros) - 1 + suffix as u64) as u32)
+    }
+
+    /// Reads a signed exponential-Golomb coded value
+    pub fn read_se(&mut self) -> Option<i32> {
+        let value = self.read_ue()? as i64;
+        let value = if value % 2 == 0 {
+            -(value / 2)
+        } else {
+            (value + 1) / 2
+        };
+
+        Some(value as i32)
+    }
+}
+
+/// Writes bits and exponential-Golomb coded values, for creating parameter sets in tests
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct BitWriter {
+    bits: Vec<bool>,
+}
+
+#[cfg(test)]
+impl BitWriter {
+    pub fn write_bits(&mut self, value: u32, count: u8) {
+        for index in (0..count).rev() {
+            self.bits.push((value >> index) & 1 == 1);
+        }
+    }
+
+    pub fn write_ue(&mut self, value: u32) {
+        let value = value as u64 + 1;
+        let length = 64 - value.leading_zeros() as u8;
+        self.write_bits(0, length - 1);
+        for index in (0..length).rev() {
+            self.bits.push((value >> index) & 1 == 1);
+        }
+    }
+
+    /// Creates a NAL unit with the specified header, the written bits as its payload, and
+    /// emulation prevention bytes inserted where needed
+    pub fn into_nal_unit(mut self, header: &[u8]) -> Bytes {
+        // rbsp trailing bits
+        self.bits.push(true);
+        while !self.bits.len().is_multiple_of(8) {
+            self.bits.push(false);
+        }
+
+        let rbsp = self
+            .bits
+            .chunks(8)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .fold(0_u8, |byte, bit| (byte << 1) | *bit as u8)
+            })
+            .collect::<Vec<_>>();
+
+        let mut nal_unit = header.to_vec();
+        nal_unit.extend(from_rbsp(&rbsp));
+
+        Bytes::from(nal_unit)
+    }
+}
+
+fn push_annexb_nal_unit(nal_units: &mut Vec<Bytes>, nal_unit: Bytes) {
+    // NAL units never end in a zero byte, so trailing zeros are either the leading byte of a 4 byte
+    // start code or trailing padding
+    let length = nal_unit
+        .iter()
+        .rposition(|byte| *byte != 0)
+        .map(|index| index + 1)
+        .unwrap_or_default();
+
+    if length > 0 {
+        nal_units.push(nal_unit.slice(..length));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_prefixed_round_trips_through_annexb() {
+        let nal_units = vec![
+            Bytes::from_static(&[0x09, 0xf0]),
+            Bytes::from_static(&[0x65, 0x88, 0x84, 0x00, 0x00, 0x03, 0x00, 0x21]),
+            Bytes::from_static(&[0x41, 0x9a, 0x00, 0x00, 0x03, 0x01, 0x02]),
+        ];
+
+        let prefixed = write_length_prefixed(&nal_units, 4).unwrap();
+        let annexb = write_annexb(&split_length_prefixed(&prefixed, 4).unwrap());
+        let restored = write_length_prefixed(&split_annexb(&annexb), 4).unwrap();
+
+        assert_eq!(restored, prefixed, "Length prefixed data was not restored");
+        assert_eq!(split_length_prefixed(&restored, 4).unwrap(), nal_units);
+    }
+
+    #[test]
+    fn annexb_split_supports_three_and_four_byte_start_codes() {
+        let data = Bytes::from_static(&[
+            0xff, 0x00, 0x00, 0x00, 0x01, 0x67, 0x01, 0x00, 0x00, 0x01, 0x68, 0x02, 0x00, 0x00,
+        ]);
+
+        let nal_units = split_annexb(&data);
+
+        assert_eq!(
+            nal_units,
+            vec![
+                Bytes::from_static(&[0x67, 0x01]),
+                Bytes::from_static(&[0x68, 0x02])
+            ]
+        );
+    }
+
+    #[test]
+    fn nal_units_with_smaller_length_prefixes_can_be_split() {
+        let nal_units = vec![
+            Bytes::from_static(&[0x67, 0x64, 0x00, 0x1f]),
+            Bytes::from_static(&[0x68, 0xeb, 0xe3]),
+        ];
+
+        for size in [1, 2] {
+            let prefixed = write_length_prefixed(&nal_units, size).unwrap();
+            assert_eq!(prefixed.len(), 7 + size as usize * 2);
+            assert_eq!(split_length_prefixed(&prefixed, size).unwrap(), nal_units);
+        }
+    }
+
+    #[test]
+    fn truncated_length_prefixed_data_returns_error() {
+        let data = Bytes::from_static(&[0x00, 0x00, 0x00, 0x05, 0x65, 0x88]);
+
+        assert_eq!(
+            split_length_prefixed(&data, 4),
+            Err(NalFramingError::TruncatedNalUnit {
+                offset: 4,
+                length: 5,
+                remaining: 2
+            })
+        );
+    }
+
+    #[test]
+    fn emulation_prevention_bytes_removed_from_rbsp() {
+        let nal_unit = [0x65, 0x00, 0x00, 0x03, 0x01, 0x00, 0x00, 0x03, 0x00, 0x03];
+
+        assert_eq!(
+            to_rbsp(&nal_unit),
+            vec![0x65, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03]
+        );
+    }
+
+    #[test]
+    fn emulation_prevention_bytes_added_to_rbsp() {
+        let rbsp = [0x06, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x80];
+        let nal_unit = from_rbsp(&rbsp);
+
+        assert_eq!(
+            nal_unit,
+            vec![0x06, 0x00, 0x00, 0x03, 0x01, 0x00, 0x00, 0x03, 0x00, 0x03, 0x80]
+        );
+
+        assert_eq!(to_rbsp(&nal_unit), rbsp.to_vec(), "Did not round trip");
+    }
+
+    #[test]
+    fn bit_reader_reads_exp_golomb_values() {
+        // 1 (ue 0), 010 (ue 1), 011 (ue 2), 00100 (se 2), 00101 (se -2)
+        let data = [0b1010_0110, 0b0100_0010, 0b1000_0000];
+        let mut reader = BitReader::new(&data);
+
+        assert_eq!(reader.read_ue(), Some(0));
+        assert_eq!(reader.read_ue(), Some(1));
+        assert_eq!(reader.read_ue(), Some(2));
+        assert_eq!(reader.read_se(), Some(2));
+        assert_eq!(reader.read_se(), Some(-2));
+        assert_eq!(reader.read_bits(8), None, "Expected end of data");
+    }
+
+    #[test]
+    fn nal_unit_too_large_for_length_prefix_returns_error() {
+        let nal_unit = Bytes::from(vec![0x65; 300]);
+
+        assert_eq!(
+            write_length_prefixed(&[nal_unit], 1),
+            Err(NalFramingError::NalUnitTooLarge {
+                length: 300,
+                nal_length_size: 1
+            })
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use crate::reactors::ReactorDefinition;
 use crate::workflows::definitions::{WorkflowDefinition, WorkflowStepDefinition, WorkflowStepType};
+use crate::workflows::watchers::{WatcherLimit, WatcherLimitBehavior};
 use pest::iterators::{Pair, Pairs};
 use pest::Parser;
 use std::collections::HashMap;
@@ -44,6 +45,18 @@ pub enum ConfigParseError {
     )]
     InvalidRoutedByReactorArgument { line: usize },
 
+    #[error("The workflow on line {line} has an invalid max_stream_lifetime value of '{argument}'. This value must be a number")]
+    InvalidMaxStreamLifetimeValue { line: usize, argument: String },
+
+    #[error("The workflow on line {line} has an invalid max_cached_media_bytes value of '{argument}'. This value must be a number greater than zero")]
+    InvalidMaxCachedMediaBytesValue { line: usize, argument: String },
+
+    #[error("The workflow on line {line} has an invalid max_watchers value of '{argument}'. This value must be a number")]
+    InvalidMaxWatchersValue { line: usize, argument: String },
+
+    #[error("The workflow on line {line} has an invalid watcher_limit_behavior value of '{argument}'. This value must be either 'reject' or 'slate'")]
+    InvalidWatcherLimitBehaviorValue { line: usize, argument: String },
+
     #[error("The workflow on line {line} did not have a name specified")]
     NoNameOnWorkflow { line: usize },
 
@@ -59,6 +72,12 @@ pub enum ConfigParseError {
     #[error("The reactor on line {line} has an invalid update_interval value of '{argument}'. This value must be a number")]
     InvalidUpdateIntervalValue { line: usize, argument: String },
 
+    #[error("The reactor on line {line} has an invalid executor_timeout value of '{argument}'. This value must be a number")]
+    InvalidExecutorTimeoutValue { line: usize, argument: String },
+
+    #[error("The reactor on line {line} has an invalid executor_retries value of '{argument}'. This value must be a number")]
+    InvalidExecutorRetriesValue { line: usize, argument: String },
+
     #[error(
         "The reactor parameter's value on line {line} is invalid. Equal signs are not allowed"
     )]
@@ -189,6 +208,10 @@ fn read_workflow(
     let mut steps = Vec::new();
     let mut workflow_name = None;
     let mut routed_by_reactor = false;
+    let mut max_stream_lifetime = 0;
+    let mut max_cached_media_bytes = None;
+    let mut max_watchers = None;
+    let mut watcher_limit_behavior = WatcherLimitBehavior::Reject;
     for pair in pairs {
         match pair.as_rule() {
             Rule::child_node => {
@@ -212,6 +235,53 @@ fn read_workflow(
                         }
 
                         routed_by_reactor = true;
+                    } else if &key == "max_stream_lifetime" {
+                        match value.as_ref().map(|value| value.parse()) {
+                            Some(Ok(num)) => max_stream_lifetime = num,
+                            _ => {
+                                return Err(Box::new(
+                                    ConfigParseError::InvalidMaxStreamLifetimeValue {
+                                        line: get_line_number(&pair),
+                                        argument: value.unwrap_or_default(),
+                                    },
+                                ));
+                            }
+                        }
+                    } else if &key == "max_cached_media_bytes" {
+                        match value.as_ref().map(|value| value.parse()) {
+                            Some(Ok(num)) if num > 0 => max_cached_media_bytes = Some(num),
+                            _ => {
+                                return Err(Box::new(
+                                    ConfigParseError::InvalidMaxCachedMediaBytesValue {
+                                        line: get_line_number(&pair),
+                                        argument: value.unwrap_or_default(),
+                                    },
+                                ));
+                            }
+                        }
+                    } else if &key == "max_watchers" {
+                        match value.as_ref().map(|value| value.parse()) {
+                            Some(Ok(num)) => max_watchers = Some(num),
+                            _ => {
+                                return Err(Box::new(ConfigParseError::InvalidMaxWatchersValue {
+                                    line: get_line_number(&pair),
+                                    argument: value.unwrap_or_default(),
+                                }));
+                            }
+                        }
+                    } else if &key == "watcher_limit_behavior" {
+                        watcher_limit_behavior = match value.as_deref() {
+                            Some("reject") => WatcherLimitBehavior::Reject,
+                            Some("slate") => WatcherLimitBehavior::Slate,
+                            _ => {
+                                return Err(Box::new(
+                                    ConfigParseError::InvalidWatcherLimitBehaviorValue {
+                                        line: get_line_number(&pair),
+                                        argument: value.unwrap_or_default(),
+                                    },
+                                ));
+                            }
+                        };
                     } else {
                         let line = get_line_number(&pair);
                         warn!(
@@ -254,6 +324,16 @@ fn read_workflow(
                 name,
                 steps,
                 routed_by_reactor,
+                max_stream_lifetime: if max_stream_lifetime > 0 {
+                    Some(Duration::from_secs(max_stream_lifetime))
+                } else {
+                    None
+                },
+                max_cached_media_bytes,
+                watcher_limit: max_watchers.map(|max_watchers| WatcherLimit {
+                    max_watchers,
+                    behavior: watcher_limit_behavior,
+                }),
             },
         );
     } else {
@@ -274,6 +354,8 @@ fn read_reactor(
     let mut parameters = HashMap::new();
     let mut executor_name = None;
     let mut update_interval = 0;
+    let mut executor_timeout = 0;
+    let mut executor_retries = 0;
 
     for pair in pairs {
         match pair.as_rule() {
@@ -309,6 +391,26 @@ fn read_reactor(
                             argument: "".to_string(),
                         }));
                     }
+                } else if key == "executor_timeout" {
+                    match value.as_ref().map(|value| value.parse()) {
+                        Some(Ok(num)) => executor_timeout = num,
+                        _ => {
+                            return Err(Box::new(ConfigParseError::InvalidExecutorTimeoutValue {
+                                line: get_line_number(&pair),
+                                argument: value.unwrap_or_default(),
+                            }));
+                        }
+                    }
+                } else if key == "executor_retries" {
+                    match value.as_ref().map(|value| value.parse()) {
+                        Some(Ok(num)) => executor_retries = num,
+                        _ => {
+                            return Err(Box::new(ConfigParseError::InvalidExecutorRetriesValue {
+                                line: get_line_number(&pair),
+                                argument: value.unwrap_or_default(),
+                            }));
+                        }
+                    }
                 } else {
                     let line = get_line_number(&pair);
                     warn!(
@@ -367,6 +469,12 @@ fn read_reactor(
                     parameters,
                     executor,
                     update_interval: Duration::from_secs(update_interval),
+                    executor_timeout: if executor_timeout > 0 {
+                        Some(Duration::from_secs(executor_timeout))
+                    } else {
+                        None
+                    },
+                    executor_retries,
                 },
             );
         } else {
@@ -645,6 +753,60 @@ reactor name executor=abc {
         );
     }
 
+    #[test]
+    fn can_read_reactor_executor_timeout_and_retries() {
+        let content = "
+reactor name executor=abc executor_timeout=5 executor_retries=2 {
+    param1 value
+}
+";
+        let config = parse(content).unwrap();
+        let reactor = &config.reactors[&Arc::new("name".to_string())];
+        assert_eq!(
+            reactor.executor_timeout,
+            Some(Duration::from_secs(5)),
+            "Unexpected executor timeout"
+        );
+
+        assert_eq!(reactor.executor_retries, 2, "Unexpected executor retries");
+    }
+
+    #[test]
+    fn reactor_without_executor_timeout_waits_indefinitely() {
+        let content = "
+reactor name executor=abc {
+    param1 value
+}
+";
+        let config = parse(content).unwrap();
+        let reactor = &config.reactors[&Arc::new("name".to_string())];
+        assert_eq!(
+            reactor.executor_timeout, None,
+            "Unexpected executor timeout"
+        );
+        assert_eq!(reactor.executor_retries, 0, "Unexpected executor retries");
+    }
+
+    #[test]
+    fn invalid_reactor_executor_timeout_returns_error() {
+        let content = "
+reactor name executor=abc executor_timeout=abc {
+    param1 value
+}
+";
+        match parse(content) {
+            Err(error) => match *error {
+                ConfigParseError::InvalidExecutorTimeoutValue { .. } => (),
+                other => panic!(
+                    "Expected invalid executor timeout error, instead got: {:?}",
+                    other
+                ),
+            },
+
+            Ok(_) => panic!("Received successful parse, but an error was expected"),
+        }
+    }
+
     #[test]
     fn duplicate_workflow_name_returns_error() {
         let content = "
@@ -714,6 +876,117 @@ workflow name routed_by_reactor {
         );
     }
 
+    #[test]
+    fn can_parse_max_stream_lifetime_argument_on_workflow() {
+        let content = "
+workflow name max_stream_lifetime=28800 {
+    rtmp_receive port=1935 app=receive stream_key=*
+}
+";
+
+        let config = parse(content).unwrap();
+        let workflow = config.workflows.get(&Arc::new("name".to_string())).unwrap();
+        assert_eq!(
+            workflow.max_stream_lifetime,
+            Some(Duration::from_secs(28800)),
+            "Unexpected max stream lifetime"
+        );
+    }
+
+    #[test]
+    fn can_parse_max_cached_media_bytes_argument_on_workflow() {
+        let content = "
+workflow name max_cached_media_bytes=4096 {
+    rtmp_receive port=1935 app=receive stream_key=*
+}
+";
+
+        let config = parse(content).unwrap();
+        let workflow = config.workflows.get(&Arc::new("name".to_string())).unwrap();
+        assert_eq!(
+            workflow.max_cached_media_bytes,
+            Some(4096),
+            "Unexpected max cached media bytes"
+        );
+    }
+
+    #[test]
+    fn can_parse_watcher_limit_arguments_on_workflow() {
+        let content = "
+workflow name max_watchers=50 watcher_limit_behavior=slate {
+    rtmp_receive port=1935 app=receive stream_key=*
+}
+";
+
+        let config = parse(content).unwrap();
+        let workflow = config.workflows.get(&Arc::new("name".to_string())).unwrap();
+        assert_eq!(
+            workflow.watcher_limit,
+            Some(WatcherLimit {
+                max_watchers: 50,
+                behavior: WatcherLimitBehavior::Slate,
+            }),
+            "Unexpected watcher limit"
+        );
+    }
+
+    #[test]
+    fn watcher_limit_defaults_to_rejecting() {
+        let content = "
+workflow name max_watchers=50 {
+    rtmp_receive port=1935 app=receive stream_key=*
+}
+";
+
+        let config = parse(content).unwrap();
+        let workflow = config.workflows.get(&Arc::new("name".to_string())).unwrap();
+        assert_eq!(
+            workflow.watcher_limit.map(|limit| limit.behavior),
+            Some(WatcherLimitBehavior::Reject),
+            "Unexpected watcher limit behavior"
+        );
+    }
+
+    #[test]
+    fn invalid_watcher_limit_behavior_returns_error() {
+        let content = "
+workflow name max_watchers=50 watcher_limit_behavior=redirect {
+    rtmp_receive port=1935 app=receive stream_key=*
+}
+";
+        match parse(content) {
+            Err(error) => match *error {
+                ConfigParseError::InvalidWatcherLimitBehaviorValue { .. } => (),
+                other => panic!(
+                    "Expected invalid watcher limit behavior error, instead got: {:?}",
+                    other
+                ),
+            },
+
+            Ok(_) => panic!("Received successful parse, but an error was expected"),
+        }
+    }
+
+    #[test]
+    fn invalid_max_stream_lifetime_returns_error() {
+        let content = "
+workflow name max_stream_lifetime=abc {
+    rtmp_receive port=1935 app=receive stream_key=*
+}
+";
+        match parse(content) {
+            Err(error) => match *error {
+                ConfigParseError::InvalidMaxStreamLifetimeValue { .. } => (),
+                other => panic!(
+                    "Expected invalid max stream lifetime error, instead got: {:?}",
+                    other
+                ),
+            },
+
+            Ok(_) => panic!("Received successful parse, but an error was expected"),
+        }
+    }
+
     #[test]
     fn comments_can_have_greater_than_or_less_than_signs() {
         let content = "
@@ -734,4 +1007,24 @@ settings {
 
         parse(content).unwrap();
     }
+
+    #[test]
+    fn quoted_values_can_contain_special_characters() {
+        let content = "
+workflow name {
+    custom_gst pipeline=\"appsrc name=video_src ! identity ! appsink name=video_sink\"
+}
+";
+        let config = parse(content).unwrap();
+        let workflow = config.workflows.get(&Arc::new("name".to_string())).unwrap();
+        let step = workflow.steps.first().unwrap();
+
+        assert_eq!(
+            step.parameters.get("pipeline"),
+            Some(&Some(
+                "appsrc name=video_src ! identity ! appsink name=video_sink".to_string()
+            )),
+            "Unexpected pipeline value"
+        );
+    }
 }
@@ -1,13 +1,22 @@
 //! The event hub is a central actor that receives events from all type of mmids subsystems and
 //! allows them to be published to interested subscribers.
 
-use crate::actor_utils::{notify_on_unbounded_closed, notify_on_unbounded_recv};
+use crate::actor_utils::{
+    notify_on_cancellation, notify_on_unbounded_closed, notify_on_unbounded_recv,
+};
+use crate::captions::CaptionCue;
+use crate::channel_metrics::ChannelDepthGauge;
+use crate::codecs::{FrameRate, VideoResolution};
 use crate::workflows::manager::WorkflowManagerRequest;
+use crate::workflows::stream_properties::StreamProperties;
 use crate::workflows::WorkflowRequest;
+use crate::StreamId;
 use std::collections::{HashMap, HashSet};
 use std::num::Wrapping;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
 use tracing::{info, instrument, warn};
 
 /// A request to publish a notification to the event hub
@@ -15,6 +24,12 @@ use tracing::{info, instrument, warn};
 pub enum PublishEventRequest {
     WorkflowStartedOrStopped(WorkflowStartedOrStoppedEvent),
     WorkflowManagerEvent(WorkflowManagerEvent),
+    StreamAlert(StreamAlertEvent),
+    StreamChange(StreamChangeEvent),
+    StreamParameters(StreamParametersEvent),
+    StreamProperties(StreamPropertiesEvent),
+    Caption(CaptionEvent),
+    CaptionPresence(CaptionPresenceEvent),
 }
 
 /// A request to subscribe to a category of events
@@ -27,6 +42,30 @@ pub enum SubscriptionRequest {
     WorkflowManagerEvents {
         channel: UnboundedSender<WorkflowManagerEvent>,
     },
+
+    StreamAlerts {
+        channel: UnboundedSender<StreamAlertEvent>,
+    },
+
+    StreamChanges {
+        channel: UnboundedSender<StreamChangeEvent>,
+    },
+
+    StreamParameters {
+        channel: UnboundedSender<StreamParametersEvent>,
+    },
+
+    StreamProperties {
+        channel: UnboundedSender<StreamPropertiesEvent>,
+    },
+
+    Captions {
+        channel: UnboundedSender<CaptionEvent>,
+    },
+
+    CaptionPresence {
+        channel: UnboundedSender<CaptionPresenceEvent>,
+    },
 }
 
 /// Events relating to workflows being started or stopped
@@ -40,6 +79,13 @@ pub enum WorkflowStartedOrStoppedEvent {
     WorkflowEnded {
         name: Arc<String>,
     },
+
+    /// A running workflow is now known by a different name, without having been restarted
+    WorkflowRenamed {
+        old_name: Arc<String>,
+        new_name: Arc<String>,
+        channel: UnboundedSender<WorkflowRequest>,
+    },
 }
 
 // Events relating to workflow managers
@@ -50,26 +96,138 @@ pub enum WorkflowManagerEvent {
     },
 }
 
-pub fn start_event_hub() -> (
+/// A problem with the content of a stream that monitoring steps can detect
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StreamAlert {
+    /// The stream's audio has been silent
+    AudioSilence,
+
+    /// The stream's video has not changed
+    VideoFreeze,
+
+    /// The stream's publisher is still connected, but has stopped sending media
+    IngestStalled,
+}
+
+/// Events raised by workflow steps that monitor the content of streams, for when a problem
+/// is detected with a stream and for when the stream recovers from it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StreamAlertEvent {
+    AlertRaised {
+        stream_id: StreamId,
+        stream_name: Arc<String>,
+        alert: StreamAlert,
+
+        /// How long the problem had been present before the alert was raised
+        duration: Duration,
+    },
+
+    AlertCleared {
+        stream_id: StreamId,
+        stream_name: Arc<String>,
+        alert: StreamAlert,
+
+        /// How long the problem was present in total
+        duration: Duration,
+    },
+}
+
+/// Events raised by workflow steps when a property of a stream changes significantly while the
+/// stream is flowing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StreamChangeEvent {
+    BitrateChanged {
+        stream_id: StreamId,
+        stream_name: Arc<String>,
+        previous_kbps: u64,
+        current_kbps: u64,
+    },
+
+    ResolutionChanged {
+        stream_id: StreamId,
+        stream_name: Arc<String>,
+        previous: VideoResolution,
+        current: VideoResolution,
+    },
+}
+
+/// The parameters of a stream's media, as read from its sequence headers, along with its measured
+/// bitrate. Raised by workflow steps when a stream's parameters are first determined and any time
+/// they change, so control planes can make decisions about a stream (e.g. how to route it) without
+/// parsing its media themselves. Parameters that are not known (yet) are `None`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamParametersEvent {
+    pub stream_id: StreamId,
+    pub stream_name: Arc<String>,
+    pub video_codec: Option<Arc<String>>,
+    pub resolution: Option<VideoResolution>,
+    pub frame_rate: Option<FrameRate>,
+    pub audio_codec: Option<Arc<String>>,
+    pub audio_sample_rate: Option<u32>,
+    pub audio_channels: Option<u8>,
+    pub bitrate_kbps: Option<u64>,
+}
+
+/// The properties of a stream as reported by its encoder's metadata. Raised by workflow steps
+/// when a stream's properties are first reported and any time they change.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamPropertiesEvent {
+    pub stream_id: StreamId,
+    pub stream_name: Arc<String>,
+    pub properties: StreamProperties,
+}
+
+/// A caption published to a named caption feed, for workflow steps that embed captions from the
+/// feed into streams
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaptionEvent {
+    pub feed: Arc<String>,
+    pub cue: CaptionCue,
+}
+
+/// Whether closed captions are present within a stream's video. Raised by workflow steps when
+/// captions are first detected in a stream, and when a stream stops carrying captions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaptionPresenceEvent {
+    pub stream_id: StreamId,
+    pub stream_name: Arc<String>,
+    pub captions_present: bool,
+
+    /// How many sampled video frames of the stream carried caption data so far
+    pub caption_count: u64,
+}
+
+/// Starts the event hub, which runs until all publishers are gone or the shutdown token is
+/// cancelled
+pub fn start_event_hub(
+    shutdown_token: CancellationToken,
+) -> (
     UnboundedSender<PublishEventRequest>,
     UnboundedSender<SubscriptionRequest>,
 ) {
     let (publish_sender, publish_receiver) = unbounded_channel();
     let (sub_sender, sub_receiver) = unbounded_channel();
     let (actor_sender, actor_receiver) = unbounded_channel();
-    let actor = Actor::new(publish_receiver, sub_receiver, actor_sender);
+    let actor = Actor::new(publish_receiver, sub_receiver, shutdown_token, actor_sender);
     tokio::spawn(actor.run(actor_receiver));
 
     (publish_sender, sub_sender)
 }
 
 enum FutureResult {
+    ShutdownRequested,
     AllPublishConsumersGone,
     AllSubscriptionRequestConsumersGone,
     NewPublishRequest(PublishEventRequest),
     NewSubscriptionRequest(SubscriptionRequest),
     WorkflowStartStopSubscriberGone(usize),
     WorkflowManagerSubscriberGone(usize),
+    StreamAlertSubscriberGone(usize),
+    StreamChangeSubscriberGone(usize),
+    StreamParametersSubscriberGone(usize),
+    StreamPropertiesSubscriberGone(usize),
+    CaptionSubscriberGone(usize),
+    CaptionPresenceSubscriberGone(usize),
 }
 
 struct Actor {
@@ -78,6 +236,12 @@ struct Actor {
     active_subscriber_ids: HashSet<usize>,
     workflow_start_stop_subscribers: HashMap<usize, UnboundedSender<WorkflowStartedOrStoppedEvent>>,
     workflow_manager_subscribers: HashMap<usize, UnboundedSender<WorkflowManagerEvent>>,
+    stream_alert_subscribers: HashMap<usize, UnboundedSender<StreamAlertEvent>>,
+    stream_change_subscribers: HashMap<usize, UnboundedSender<StreamChangeEvent>>,
+    stream_parameters_subscribers: HashMap<usize, UnboundedSender<StreamParametersEvent>>,
+    stream_properties_subscribers: HashMap<usize, UnboundedSender<StreamPropertiesEvent>>,
+    caption_subscribers: HashMap<usize, UnboundedSender<CaptionEvent>>,
+    caption_presence_subscribers: HashMap<usize, UnboundedSender<CaptionPresenceEvent>>,
     new_subscribers_can_join: bool,
     active_workflows: HashMap<Arc<String>, UnboundedSender<WorkflowRequest>>,
     active_workflow_manager: Option<UnboundedSender<WorkflowManagerRequest>>,
@@ -87,8 +251,13 @@ impl Actor {
     fn new(
         publish_receiver: UnboundedReceiver<PublishEventRequest>,
         subscribe_receiver: UnboundedReceiver<SubscriptionRequest>,
+        shutdown_token: CancellationToken,
         actor_sender: UnboundedSender<FutureResult>,
     ) -> Self {
+        notify_on_cancellation(shutdown_token, actor_sender.clone(), || {
+            FutureResult::ShutdownRequested
+        });
+
         notify_on_unbounded_recv(
             publish_receiver,
             actor_sender.clone(),
@@ -109,6 +278,12 @@ impl Actor {
             active_subscriber_ids: HashSet::new(),
             workflow_start_stop_subscribers: HashMap::new(),
             workflow_manager_subscribers: HashMap::new(),
+            stream_alert_subscribers: HashMap::new(),
+            stream_change_subscribers: HashMap::new(),
+            stream_parameters_subscribers: HashMap::new(),
+            stream_properties_subscribers: HashMap::new(),
+            caption_subscribers: HashMap::new(),
+            caption_presence_subscribers: HashMap::new(),
             new_subscribers_can_join: true,
             active_workflows: HashMap::new(),
             active_workflow_manager: None,
@@ -119,8 +294,15 @@ impl Actor {
     async fn run(mut self, mut receiver: UnboundedReceiver<FutureResult>) {
         info!("Starting event hub");
 
+        let channel_depth = ChannelDepthGauge::register("event_hub");
         while let Some(result) = receiver.recv().await {
+            channel_depth.record(receiver.len());
             match result {
+                FutureResult::ShutdownRequested => {
+                    info!("Shutdown requested");
+                    break;
+                }
+
                 FutureResult::AllPublishConsumersGone => {
                     info!("All publish request consumers are gone.  No new events can come in");
                     break;
@@ -145,6 +327,36 @@ impl Actor {
                     self.workflow_manager_subscribers.remove(&id);
                 }
 
+                FutureResult::StreamAlertSubscriberGone(id) => {
+                    self.active_subscriber_ids.remove(&id);
+                    self.stream_alert_subscribers.remove(&id);
+                }
+
+                FutureResult::StreamChangeSubscriberGone(id) => {
+                    self.active_subscriber_ids.remove(&id);
+                    self.stream_change_subscribers.remove(&id);
+                }
+
+                FutureResult::StreamParametersSubscriberGone(id) => {
+                    self.active_subscriber_ids.remove(&id);
+                    self.stream_parameters_subscribers.remove(&id);
+                }
+
+                FutureResult::StreamPropertiesSubscriberGone(id) => {
+                    self.active_subscriber_ids.remove(&id);
+                    self.stream_properties_subscribers.remove(&id);
+                }
+
+                FutureResult::CaptionSubscriberGone(id) => {
+                    self.active_subscriber_ids.remove(&id);
+                    self.caption_subscribers.remove(&id);
+                }
+
+                FutureResult::CaptionPresenceSubscriberGone(id) => {
+                    self.active_subscriber_ids.remove(&id);
+                    self.caption_presence_subscribers.remove(&id);
+                }
+
                 FutureResult::NewPublishRequest(request) => {
                     self.handle_publish_request(request);
                 }
@@ -180,6 +392,15 @@ impl Actor {
                     WorkflowStartedOrStoppedEvent::WorkflowEnded { name } => {
                         self.active_workflows.remove(&name);
                     }
+
+                    WorkflowStartedOrStoppedEvent::WorkflowRenamed {
+                        old_name,
+                        new_name,
+                        channel,
+                    } => {
+                        self.active_workflows.remove(&old_name);
+                        self.active_workflows.insert(new_name, channel);
+                    }
                 }
             }
 
@@ -194,6 +415,44 @@ impl Actor {
                     }
                 }
             }
+
+            PublishEventRequest::StreamAlert(event) => {
+                // Alerts are only relevant at the time they occur, so they aren't replayed to
+                // subscribers that join later
+                for subscriber in self.stream_alert_subscribers.values() {
+                    let _ = subscriber.send(event.clone());
+                }
+            }
+
+            PublishEventRequest::StreamChange(event) => {
+                for subscriber in self.stream_change_subscribers.values() {
+                    let _ = subscriber.send(event.clone());
+                }
+            }
+
+            PublishEventRequest::StreamParameters(event) => {
+                for subscriber in self.stream_parameters_subscribers.values() {
+                    let _ = subscriber.send(event.clone());
+                }
+            }
+
+            PublishEventRequest::StreamProperties(event) => {
+                for subscriber in self.stream_properties_subscribers.values() {
+                    let _ = subscriber.send(event.clone());
+                }
+            }
+
+            PublishEventRequest::Caption(event) => {
+                for subscriber in self.caption_subscribers.values() {
+                    let _ = subscriber.send(event.clone());
+                }
+            }
+
+            PublishEventRequest::CaptionPresence(event) => {
+                for subscriber in self.caption_presence_subscribers.values() {
+                    let _ = subscriber.send(event.clone());
+                }
+            }
         }
     }
 
@@ -242,6 +501,57 @@ impl Actor {
                     FutureResult::WorkflowManagerSubscriberGone(id.0)
                 });
             }
+
+            SubscriptionRequest::StreamAlerts { channel } => {
+                self.stream_alert_subscribers.insert(id.0, channel.clone());
+
+                notify_on_unbounded_closed(channel, self.internal_sender.clone(), move || {
+                    FutureResult::StreamAlertSubscriberGone(id.0)
+                });
+            }
+
+            SubscriptionRequest::StreamChanges { channel } => {
+                self.stream_change_subscribers.insert(id.0, channel.clone());
+
+                notify_on_unbounded_closed(channel, self.internal_sender.clone(), move || {
+                    FutureResult::StreamChangeSubscriberGone(id.0)
+                });
+            }
+
+            SubscriptionRequest::StreamParameters { channel } => {
+                self.stream_parameters_subscribers
+                    .insert(id.0, channel.clone());
+
+                notify_on_unbounded_closed(channel, self.internal_sender.clone(), move || {
+                    FutureResult::StreamParametersSubscriberGone(id.0)
+                });
+            }
+
+            SubscriptionRequest::StreamProperties { channel } => {
+                self.stream_properties_subscribers
+                    .insert(id.0, channel.clone());
+
+                notify_on_unbounded_closed(channel, self.internal_sender.clone(), move || {
+                    FutureResult::StreamPropertiesSubscriberGone(id.0)
+                });
+            }
+
+            SubscriptionRequest::Captions { channel } => {
+                self.caption_subscribers.insert(id.0, channel.clone());
+
+                notify_on_unbounded_closed(channel, self.internal_sender.clone(), move || {
+                    FutureResult::CaptionSubscriberGone(id.0)
+                });
+            }
+
+            SubscriptionRequest::CaptionPresence { channel } => {
+                self.caption_presence_subscribers
+                    .insert(id.0, channel.clone());
+
+                notify_on_unbounded_closed(channel, self.internal_sender.clone(), move || {
+                    FutureResult::CaptionPresenceSubscriberGone(id.0)
+                });
+            }
         }
     }
 
@@ -258,7 +568,7 @@ mod tests {
 
     #[tokio::test]
     async fn can_receive_workflow_started_notifications() {
-        let (publish_channel, subscribe_channel) = start_event_hub();
+        let (publish_channel, subscribe_channel) = start_event_hub(CancellationToken::new());
         let (subscriber_sender, mut subscriber_receiver) = unbounded_channel();
         let (workflow_sender, _workflow_receiver) = unbounded_channel();
 
@@ -291,7 +601,7 @@ mod tests {
 
     #[tokio::test]
     async fn can_receive_workflow_started_notification_when_subscribed_after_published() {
-        let (publish_channel, subscribe_channel) = start_event_hub();
+        let (publish_channel, subscribe_channel) = start_event_hub(CancellationToken::new());
         let (subscriber_sender, mut subscriber_receiver) = unbounded_channel();
         let (workflow_sender, _workflow_receiver) = unbounded_channel();
 
@@ -324,7 +634,7 @@ mod tests {
 
     #[tokio::test]
     async fn can_receive_workflow_stopped_notifications() {
-        let (publish_channel, subscribe_channel) = start_event_hub();
+        let (publish_channel, subscribe_channel) = start_event_hub(CancellationToken::new());
         let (subscriber_sender, mut subscriber_receiver) = unbounded_channel();
 
         subscribe_channel
@@ -355,7 +665,7 @@ mod tests {
 
     #[tokio::test]
     async fn no_events_when_workflow_started_and_stopped_prior_to_subscription() {
-        let (publish_channel, subscribe_channel) = start_event_hub();
+        let (publish_channel, subscribe_channel) = start_event_hub(CancellationToken::new());
         let (subscriber_sender, mut subscriber_receiver) = unbounded_channel();
         let (workflow_sender, _workflow_receiver) = unbounded_channel();
 
@@ -389,7 +699,7 @@ mod tests {
 
     #[tokio::test]
     async fn can_receive_workflow_manager_registered_event() {
-        let (publish_channel, subscribe_channel) = start_event_hub();
+        let (publish_channel, subscribe_channel) = start_event_hub(CancellationToken::new());
         let (subscriber_sender, mut subscriber_receiver) = unbounded_channel();
         let (manager_sender, _manager_receiver) = unbounded_channel();
 
@@ -414,4 +724,154 @@ mod tests {
             WorkflowManagerEvent::WorkflowManagerRegistered { channel: _ } => (),
         }
     }
+
+    #[tokio::test]
+    async fn can_receive_stream_alert_events() {
+        let (publish_channel, subscribe_channel) = start_event_hub(CancellationToken::new());
+        let (subscriber_sender, mut subscriber_receiver) = unbounded_channel();
+
+        subscribe_channel
+            .send(SubscriptionRequest::StreamAlerts {
+                channel: subscriber_sender,
+            })
+            .expect("Failed to subscribe to stream alert events");
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let event = StreamAlertEvent::AlertRaised {
+            stream_id: StreamId(Arc::new("abc".to_string())),
+            stream_name: Arc::new("def".to_string()),
+            alert: StreamAlert::VideoFreeze,
+            duration: Duration::from_secs(5),
+        };
+
+        publish_channel
+            .send(PublishEventRequest::StreamAlert(event.clone()))
+            .expect("Failed to publish stream alert event");
+
+        let response = test_utils::expect_mpsc_response(&mut subscriber_receiver).await;
+        assert_eq!(response, event, "Unexpected event received");
+    }
+
+    #[tokio::test]
+    async fn can_receive_stream_change_events() {
+        let (publish_channel, subscribe_channel) = start_event_hub(CancellationToken::new());
+        let (subscriber_sender, mut subscriber_receiver) = unbounded_channel();
+
+        subscribe_channel
+            .send(SubscriptionRequest::StreamChanges {
+                channel: subscriber_sender,
+            })
+            .expect("Failed to subscribe to stream change events");
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let event = StreamChangeEvent::BitrateChanged {
+            stream_id: StreamId(Arc::new("abc".to_string())),
+            stream_name: Arc::new("def".to_string()),
+            previous_kbps: 2500,
+            current_kbps: 800,
+        };
+
+        publish_channel
+            .send(PublishEventRequest::StreamChange(event.clone()))
+            .expect("Failed to publish stream change event");
+
+        let response = test_utils::expect_mpsc_response(&mut subscriber_receiver).await;
+        assert_eq!(response, event, "Unexpected event received");
+    }
+
+    #[tokio::test]
+    async fn can_receive_stream_parameters_events() {
+        let (publish_channel, subscribe_channel) = start_event_hub(CancellationToken::new());
+        let (subscriber_sender, mut subscriber_receiver) = unbounded_channel();
+
+        subscribe_channel
+            .send(SubscriptionRequest::StreamParameters {
+                channel: subscriber_sender,
+            })
+            .expect("Failed to subscribe to stream parameters events");
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let event = StreamParametersEvent {
+            stream_id: StreamId(Arc::new("abc".to_string())),
+            stream_name: Arc::new("def".to_string()),
+            video_codec: Some(Arc::new("h264-avc".to_string())),
+            resolution: Some(VideoResolution {
+                width: 1920,
+                height: 1080,
+            }),
+            frame_rate: FrameRate::new(30, 1),
+            audio_codec: None,
+            audio_sample_rate: None,
+            audio_channels: None,
+            bitrate_kbps: Some(2500),
+        };
+
+        publish_channel
+            .send(PublishEventRequest::StreamParameters(event.clone()))
+            .expect("Failed to publish stream parameters event");
+
+        let response = test_utils::expect_mpsc_response(&mut subscriber_receiver).await;
+        assert_eq!(response, event, "Unexpected event received");
+    }
+
+    #[tokio::test]
+    async fn can_receive_stream_properties_events() {
+        let (publish_channel, subscribe_channel) = start_event_hub(CancellationToken::new());
+        let (subscriber_sender, mut subscriber_receiver) = unbounded_channel();
+
+        subscribe_channel
+            .send(SubscriptionRequest::StreamProperties {
+                channel: subscriber_sender,
+            })
+            .expect("Failed to subscribe to stream properties events");
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let event = StreamPropertiesEvent {
+            stream_id: StreamId(Arc::new("abc".to_string())),
+            stream_name: Arc::new("def".to_string()),
+            properties: StreamProperties {
+                video_bitrate_kbps: Some(2500),
+                ..Default::default()
+            },
+        };
+
+        publish_channel
+            .send(PublishEventRequest::StreamProperties(event.clone()))
+            .expect("Failed to publish stream properties event");
+
+        let response = test_utils::expect_mpsc_response(&mut subscriber_receiver).await;
+        assert_eq!(response, event, "Unexpected event received");
+    }
+
+    #[tokio::test]
+    async fn can_receive_caption_presence_events() {
+        let (publish_channel, subscribe_channel) = start_event_hub(CancellationToken::new());
+        let (subscriber_sender, mut subscriber_receiver) = unbounded_channel();
+
+        subscribe_channel
+            .send(SubscriptionRequest::CaptionPresence {
+                channel: subscriber_sender,
+            })
+            .expect("Failed to subscribe to caption presence events");
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let event = CaptionPresenceEvent {
+            stream_id: StreamId(Arc::new("abc".to_string())),
+            stream_name: Arc::new("def".to_string()),
+            captions_present: true,
+            caption_count: 1,
+        };
+
+        publish_channel
+            .send(PublishEventRequest::CaptionPresence(event.clone()))
+            .expect("Failed to publish caption presence event");
+
+        let response = test_utils::expect_mpsc_response(&mut subscriber_receiver).await;
+        assert_eq!(response, event, "Unexpected event received");
+    }
 }
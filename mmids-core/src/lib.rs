@@ -12,13 +12,19 @@ use std::time::Duration;
 use tracing::error;
 
 pub mod actor_utils;
+pub mod captions;
+pub mod channel_metrics;
 pub mod codecs;
 pub mod config;
 pub mod event_hub;
 pub mod net;
+pub mod object_storage;
 pub mod reactors;
+pub mod server;
 #[cfg(feature = "test-utils")]
 pub mod test_utils;
+pub mod timed_metadata;
+pub mod url_signing;
 pub mod workflows;
 
 /// Unique identifier that identifies the flow of video end-to-end.  Normally when media data enters
@@ -33,7 +33,7 @@ impl Hash for ConnectionId {
 }
 
 /// Enumeration to make handling ip addresses vs subnets easier
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum IpAddress {
     Exact(Ipv4Addr),
     Cidr(Ipv4Cidr),
@@ -1,5 +1,5 @@
 use super::TcpSocketResponse;
-use crate::net::tcp::TlsOptions;
+use crate::net::tcp::{SocketOptions, TlsOptions};
 use crate::net::ConnectionId;
 use bytes::{Bytes, BytesMut};
 use futures::future::FutureExt;
@@ -34,6 +34,9 @@ pub struct ListenerParams {
     /// Options for TLS. Required if use_tls is true
     pub tls_options: Arc<Option<TlsOptions>>,
 
+    /// Options to apply to each accepted connection
+    pub socket_options: SocketOptions,
+
     /// The channel in which to send notifications of port activity to
     pub response_channel: UnboundedSender<TcpSocketResponse>,
 }
@@ -66,6 +69,7 @@ async fn listen(params: ListenerParams, _self_disconnection_signal: UnboundedRec
         response_channel,
         use_tls,
         tls_options,
+        socket_options,
     } = params;
 
     let tls = if let Some(tls) = tls_options.as_ref() {
@@ -107,6 +111,10 @@ async fn listen(params: ListenerParams, _self_disconnection_signal: UnboundedRec
                     }
                 };
 
+                if let Err(e) = socket_options.apply(&socket) {
+                    warn!("Failed to apply socket options to connection from {}: {:?}", client_info, e);
+                }
+
                 let connection_id = ConnectionId(Arc::new(Uuid::new_v4().to_string()));
                 tokio::spawn(handle_new_connection(socket, client_info, response_channel.clone(), port, connection_id, tls.clone()));
             },
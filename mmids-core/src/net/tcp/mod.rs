@@ -3,6 +3,7 @@
 //! requested), and pass networked data to requesters.
 mod listener;
 mod socket_manager;
+mod socket_options;
 
 use super::ConnectionId;
 use bytes::Bytes;
@@ -12,6 +13,10 @@ use tokio::sync::mpsc;
 
 pub use listener::OutboundPacket;
 pub use socket_manager::start as start_socket_manager;
+pub use socket_options::{
+    SocketOptions, SocketOptionsError, RECV_BUFFER_SIZE, SEND_BUFFER_SIZE, TCP_KEEPALIVE,
+    TCP_NODELAY,
+};
 
 /// Reasons why the request to listen for TCP connections can fail
 #[derive(Debug)]
@@ -47,6 +52,9 @@ pub enum TcpSocketRequest {
         /// If the port should be accepting TLS connections or not
         use_tls: bool,
 
+        /// Options to apply to each connection accepted on the port
+        socket_options: SocketOptions,
+
         /// The channel in which responses should be sent.  If the port is successfully opened
         /// then all state changes for the port (such as new connections) will use this channel
         /// for notifications
@@ -96,6 +96,7 @@ impl SocketManager {
                 port,
                 response_channel,
                 use_tls,
+                socket_options,
             } => {
                 if use_tls && tls_options.as_ref().is_none() {
                     error!(
@@ -122,6 +123,7 @@ impl SocketManager {
                         response_channel: response_channel.clone(),
                         use_tls,
                         tls_options,
+                        socket_options,
                     });
 
                     notify_on_unbounded_closed(
@@ -0,0 +1,249 @@
+//! Socket level options for tuning TCP connections, such as contribution links over high latency
+//! or high bandwidth WAN connections. Any option that isn't specified is left at the operating
+//! system's default.
+//!
+//! `SocketOptions` can be read from a workflow step's parameters:
+//! * `tcp_nodelay` - `true` to send data as soon as it's written instead of coalescing small
+//!   writes (Nagle's algorithm), or `false` to coalesce them. Steps may use their own default when
+//!   not specified.
+//! * `send_buffer_size` - The size of the socket's send buffer, in bytes.
+//! * `recv_buffer_size` - The size of the socket's receive buffer, in bytes.
+//! * `tcp_keepalive` - How many seconds a connection is idle before keepalive probes are sent.
+//!   Keepalive is disabled when not specified.
+//!
+//! The operating system may adjust buffer sizes (e.g. Linux doubles them and caps them by
+//! `net.core.wmem_max` and `net.core.rmem_max`).
+
+use std::collections::HashMap;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::net::TcpStream;
+
+pub const TCP_NODELAY: &str = "tcp_nodelay";
+pub const SEND_BUFFER_SIZE: &str = "send_buffer_size";
+pub const RECV_BUFFER_SIZE: &str = "recv_buffer_size";
+pub const TCP_KEEPALIVE: &str = "tcp_keepalive";
+
+/// Options applied to TCP sockets as connections are established
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    pub nodelay: Option<bool>,
+    pub send_buffer_size: Option<usize>,
+    pub recv_buffer_size: Option<usize>,
+
+    /// How long a connection is idle before keepalive probes are sent, or `None` to not enable
+    /// keepalive
+    pub keepalive: Option<Duration>,
+}
+
+#[derive(Error, Debug)]
+pub enum SocketOptionsError {
+    #[error("Invalid {parameter} value of '{value}'.  It must be {expected}")]
+    InvalidValue {
+        parameter: &'static str,
+        value: String,
+        expected: &'static str,
+    },
+}
+
+impl SocketOptions {
+    /// Reads socket options from a step's parameters, leaving any that aren't specified unset.
+    pub fn from_parameters(
+        parameters: &HashMap<String, Option<String>>,
+    ) -> Result<SocketOptions, SocketOptionsError> {
+        let get_parameter = |name: &str| match parameters.get(name) {
+            Some(Some(value)) if !value.trim().is_empty() => Some(value.trim().to_string()),
+            _ => None,
+        };
+
+        let invalid = |parameter: &'static str, value: String, expected: &'static str| {
+            SocketOptionsError::InvalidValue {
+                parameter,
+                value,
+                expected,
+            }
+        };
+
+        let get_buffer_size = |name: &'static str| match get_parameter(name) {
+            Some(value) => match value.parse::<usize>() {
+                Ok(size) if size > 0 => Ok(Some(size)),
+                _ => Err(invalid(name, value, "a number of bytes greater than zero")),
+            },
+
+            None => Ok(None),
+        };
+
+        let nodelay = match get_parameter(TCP_NODELAY) {
+            Some(value) => match value.to_lowercase().as_str() {
+                "true" => Some(true),
+                "false" => Some(false),
+                _ => return Err(invalid(TCP_NODELAY, value, "either 'true' or 'false'")),
+            },
+
+            None => None,
+        };
+
+        let keepalive = match get_parameter(TCP_KEEPALIVE) {
+            Some(value) => match value.parse::<u64>() {
+                Ok(seconds) if seconds > 0 => Some(Duration::from_secs(seconds)),
+                _ => {
+                    return Err(invalid(
+                        TCP_KEEPALIVE,
+                        value,
+                        "a number of seconds greater than zero",
+                    ))
+                }
+            },
+
+            None => None,
+        };
+
+        Ok(SocketOptions {
+            nodelay,
+            send_buffer_size: get_buffer_size(SEND_BUFFER_SIZE)?,
+            recv_buffer_size: get_buffer_size(RECV_BUFFER_SIZE)?,
+            keepalive,
+        })
+    }
+
+    /// Applies the options that have been set to the socket
+    pub fn apply(&self, stream: &TcpStream) -> std::io::Result<()> {
+        let socket = socket2::SockRef::from(stream);
+        if let Some(nodelay) = self.nodelay {
+            socket.set_nodelay(nodelay)?;
+        }
+
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+
+        if let Some(time) = self.keepalive {
+            socket.set_tcp_keepalive(&socket2::TcpKeepalive::new().with_time(time))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn parameters(parameters: &[(&str, &str)]) -> HashMap<String, Option<String>> {
+        parameters
+            .iter()
+            .map(|(key, value)| (key.to_string(), Some(value.to_string())))
+            .collect()
+    }
+
+    async fn connected_stream() -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (stream, _) = tokio::join!(TcpStream::connect(address), listener.accept());
+
+        stream.unwrap()
+    }
+
+    #[test]
+    fn nothing_set_when_no_parameters_specified() {
+        let options = SocketOptions::from_parameters(&parameters(&[])).unwrap();
+
+        assert_eq!(options, SocketOptions::default(), "Unexpected options");
+    }
+
+    #[test]
+    fn options_read_from_parameters() {
+        let options = SocketOptions::from_parameters(&parameters(&[
+            (TCP_NODELAY, "false"),
+            (SEND_BUFFER_SIZE, "1048576"),
+            (RECV_BUFFER_SIZE, "2097152"),
+            (TCP_KEEPALIVE, "30"),
+        ]))
+        .unwrap();
+
+        let expected = SocketOptions {
+            nodelay: Some(false),
+            send_buffer_size: Some(1048576),
+            recv_buffer_size: Some(2097152),
+            keepalive: Some(Duration::from_secs(30)),
+        };
+
+        assert_eq!(options, expected, "Unexpected options");
+    }
+
+    #[test]
+    fn invalid_parameters_return_error() {
+        let cases = [
+            (TCP_NODELAY, "yes"),
+            (SEND_BUFFER_SIZE, "0"),
+            (RECV_BUFFER_SIZE, "abc"),
+            (TCP_KEEPALIVE, "-1"),
+        ];
+
+        for (parameter, value) in cases {
+            let result = SocketOptions::from_parameters(&parameters(&[(parameter, value)]));
+
+            assert!(
+                result.is_err(),
+                "Expected error for {} of '{}'",
+                parameter,
+                value
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn options_applied_to_socket() {
+        let stream = connected_stream().await;
+        let options = SocketOptions {
+            nodelay: Some(true),
+            send_buffer_size: Some(65536),
+            recv_buffer_size: Some(65536),
+            keepalive: Some(Duration::from_secs(30)),
+        };
+
+        options.apply(&stream).unwrap();
+
+        // Operating systems may round buffer sizes up (Linux doubles them), but never below what
+        // was requested unless capped by system limits
+        let socket = socket2::SockRef::from(&stream);
+        assert!(socket.nodelay().unwrap(), "Expected nodelay to be set");
+        assert!(socket.keepalive().unwrap(), "Expected keepalive to be set");
+        assert!(
+            socket.send_buffer_size().unwrap() >= 65536,
+            "Unexpected send buffer size of {}",
+            socket.send_buffer_size().unwrap()
+        );
+
+        assert!(
+            socket.recv_buffer_size().unwrap() >= 65536,
+            "Unexpected receive buffer size of {}",
+            socket.recv_buffer_size().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn unset_options_leave_socket_untouched() {
+        let stream = connected_stream().await;
+        let socket = socket2::SockRef::from(&stream);
+        let send_buffer_size = socket.send_buffer_size().unwrap();
+
+        SocketOptions::default().apply(&stream).unwrap();
+
+        assert!(!socket.nodelay().unwrap(), "Expected nodelay to be unset");
+        assert!(
+            !socket.keepalive().unwrap(),
+            "Expected keepalive to be unset"
+        );
+        assert_eq!(
+            socket.send_buffer_size().unwrap(),
+            send_buffer_size,
+            "Unexpected send buffer size"
+        );
+    }
+}
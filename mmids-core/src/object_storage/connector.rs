@@ -0,0 +1,134 @@
+//! A hyper connector that supports both plain HTTP and HTTPS endpoints, since object stores may be
+//! hosted locally without TLS or by a cloud provider that requires it.
+
+use hyper::client::connect::{Connected, Connection, HttpConnector};
+use hyper::service::Service;
+use hyper::Uri;
+use std::future::Future;
+use std::io::IoSlice;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_native_tls::TlsStream;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Clone)]
+pub(crate) struct HttpsConnector {
+    http: HttpConnector,
+    tls: tokio_native_tls::TlsConnector,
+}
+
+pub(crate) enum MaybeHttpsStream {
+    Http(TcpStream),
+    Https(Box<TlsStream<TcpStream>>),
+}
+
+impl HttpsConnector {
+    pub fn new() -> Result<Self, native_tls::Error> {
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+
+        let tls = native_tls::TlsConnector::new()?;
+
+        Ok(HttpsConnector {
+            http,
+            tls: tls.into(),
+        })
+    }
+}
+
+impl Service<Uri> for HttpsConnector {
+    type Response = MaybeHttpsStream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let is_https = uri.scheme_str() == Some("https");
+        let host = uri.host().unwrap_or_default().to_string();
+        let connecting = self.http.call(uri);
+        let tls = self.tls.clone();
+
+        Box::pin(async move {
+            let stream = connecting.await?;
+            if !is_https {
+                return Ok(MaybeHttpsStream::Http(stream));
+            }
+
+            let stream = tls.connect(&host, stream).await?;
+            Ok(MaybeHttpsStream::Https(Box::new(stream)))
+        })
+    }
+}
+
+impl Connection for MaybeHttpsStream {
+    fn connected(&self) -> Connected {
+        match self {
+            MaybeHttpsStream::Http(stream) => stream.connected(),
+            MaybeHttpsStream::Https(_) => Connected::new(),
+        }
+    }
+}
+
+impl AsyncRead for MaybeHttpsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            MaybeHttpsStream::Http(stream) => Pin::new(stream).poll_read(cx, buf),
+            MaybeHttpsStream::Https(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for MaybeHttpsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            MaybeHttpsStream::Http(stream) => Pin::new(stream).poll_write(cx, buf),
+            MaybeHttpsStream::Https(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            MaybeHttpsStream::Http(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            MaybeHttpsStream::Https(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            MaybeHttpsStream::Http(stream) => stream.is_write_vectored(),
+            MaybeHttpsStream::Https(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            MaybeHttpsStream::Http(stream) => Pin::new(stream).poll_flush(cx),
+            MaybeHttpsStream::Https(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            MaybeHttpsStream::Http(stream) => Pin::new(stream).poll_shutdown(cx),
+            MaybeHttpsStream::Https(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
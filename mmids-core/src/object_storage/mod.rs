@@ -0,0 +1,216 @@
+//! Support for storing output, such as segment files, in S3 compatible object storage instead of
+//! (or in addition to) local disk. This allows content to be served directly out of object storage
+//! in cloud deployments.
+//!
+//! Requests use path style addressing (`<endpoint>/<bucket>/<key>`), which is supported by S3
+//! as well as most S3 compatible stores, and are authenticated with AWS signature version 4.
+
+mod connector;
+pub(crate) mod signing;
+
+#[cfg(test)]
+pub(crate) mod test_server;
+
+#[cfg(test)]
+mod tests;
+
+use crate::object_storage::connector::HttpsConnector;
+use crate::object_storage::signing::{
+    authorization_header, encode_key, format_amz_date, hex, sha256, CanonicalRequest,
+    SigningParams, EMPTY_PAYLOAD_HASH,
+};
+use bytes::Bytes;
+use hyper::{Body, Client, Method, Request, StatusCode, Uri};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+use tracing::{instrument, warn};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The location of, and credentials for, an S3 compatible bucket
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct S3Settings {
+    /// The base url of the object store without a path, such as
+    /// `https://s3.us-east-1.amazonaws.com` or `http://localhost:9000`
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+/// Errors that can occur when interacting with an object store
+#[derive(Error, Debug)]
+pub enum ObjectStorageError {
+    #[error("The endpoint '{0}' is not a valid http or https url")]
+    InvalidEndpoint(String),
+
+    #[error("Failed to initialize TLS: {0}")]
+    TlsInitializationFailed(#[from] native_tls::Error),
+
+    #[error("The request to the object store failed: {0}")]
+    RequestFailed(#[from] hyper::Error),
+
+    #[error("The object store responded with status code {0}")]
+    UnexpectedStatusCode(StatusCode),
+
+    #[error("The object store did not respond within {:?}", REQUEST_TIMEOUT)]
+    TimedOut,
+}
+
+/// Uploads and deletes objects in an S3 compatible bucket
+#[derive(Clone)]
+pub struct S3Client {
+    settings: Arc<S3Settings>,
+    authority: Arc<String>,
+    http_client: Client<HttpsConnector>,
+}
+
+impl S3Client {
+    pub fn new(settings: S3Settings) -> Result<Self, ObjectStorageError> {
+        let endpoint = settings.endpoint.trim_end_matches('/');
+        let authority = match endpoint.parse::<Uri>() {
+            // Endpoints with a path aren't supported, as the path wouldn't be signed
+            Ok(uri)
+                if matches!(uri.scheme_str(), Some("http") | Some("https"))
+                    && uri.path() == "/" =>
+            {
+                match uri.authority() {
+                    Some(authority) => authority.to_string(),
+                    None => return Err(ObjectStorageError::InvalidEndpoint(endpoint.to_string())),
+                }
+            }
+
+            _ => return Err(ObjectStorageError::InvalidEndpoint(endpoint.to_string())),
+        };
+
+        let settings = S3Settings {
+            endpoint: endpoint.to_string(),
+            ..settings
+        };
+
+        Ok(S3Client {
+            settings: Arc::new(settings),
+            authority: Arc::new(authority),
+            http_client: Client::builder().build(HttpsConnector::new()?),
+        })
+    }
+
+    /// Uploads the data as an object with the specified key, replacing any existing object
+    pub async fn put_object(
+        &self,
+        key: &str,
+        data: Bytes,
+        content_type: &str,
+    ) -> Result<(), ObjectStorageError> {
+        self.send(Method::PUT, key, data, Some(content_type)).await
+    }
+
+    /// Deletes the object with the specified key
+    pub async fn delete_object(&self, key: &str) -> Result<(), ObjectStorageError> {
+        self.send(Method::DELETE, key, Bytes::new(), None).await
+    }
+
+    /// Uploads the data as an object with the specified key. If the upload fails, the key is
+    /// deleted to make sure a partially written object isn't left behind, since not every S3
+    /// compatible store guarantees failed uploads are discarded.
+    #[instrument(skip(self, data, content_type))]
+    pub async fn put_object_or_clean_up(
+        &self,
+        key: &str,
+        data: Bytes,
+        content_type: &str,
+    ) -> Result<(), ObjectStorageError> {
+        let error = match self.put_object(key, data, content_type).await {
+            Ok(()) => return Ok(()),
+            Err(error) => error,
+        };
+
+        if let Err(delete_error) = self.delete_object(key).await {
+            warn!(
+                "Failed to clean up object after a failed upload: {}",
+                delete_error
+            );
+        }
+
+        Err(error)
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        body: Bytes,
+        content_type: Option<&str>,
+    ) -> Result<(), ObjectStorageError> {
+        let path = format!(
+            "/{}/{}",
+            encode_key(&self.settings.bucket),
+            encode_key(key.trim_start_matches('/'))
+        );
+
+        let payload_hash = if body.is_empty() {
+            EMPTY_PAYLOAD_HASH.to_string()
+        } else {
+            hex(&sha256(&body))
+        };
+
+        let amz_date = format_amz_date(SystemTime::now());
+        let mut headers = vec![
+            ("host", self.authority.as_str()),
+            ("x-amz-content-sha256", payload_hash.as_str()),
+            ("x-amz-date", amz_date.as_str()),
+        ];
+
+        if let Some(content_type) = content_type {
+            headers.push(("content-type", content_type));
+        }
+
+        let authorization = authorization_header(
+            &CanonicalRequest {
+                method: method.as_str(),
+                path: &path,
+                headers: &headers,
+                payload_hash: &payload_hash,
+            },
+            &SigningParams {
+                access_key_id: &self.settings.access_key_id,
+                secret_access_key: &self.settings.secret_access_key,
+                region: &self.settings.region,
+                service: "s3",
+                amz_date: &amz_date,
+            },
+        );
+
+        let mut request = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", self.settings.endpoint, path))
+            .header("authorization", authorization);
+
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+
+        let request = match request.body(Body::from(body)) {
+            Ok(request) => request,
+            Err(_) => {
+                return Err(ObjectStorageError::InvalidEndpoint(
+                    self.settings.endpoint.clone(),
+                ))
+            }
+        };
+
+        let response =
+            match tokio::time::timeout(REQUEST_TIMEOUT, self.http_client.request(request)).await {
+                Ok(response) => response?,
+                Err(_) => return Err(ObjectStorageError::TimedOut),
+            };
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(ObjectStorageError::UnexpectedStatusCode(response.status()))
+        }
+    }
+}
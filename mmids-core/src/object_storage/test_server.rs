@@ -0,0 +1,146 @@
+//! A minimal S3 compatible endpoint for tests, which records every request it receives and
+//! responds to them with a configurable status code.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+#[derive(Clone, Debug)]
+pub(crate) struct ReceivedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+pub(crate) struct MockS3Server {
+    pub endpoint: String,
+    requests: Arc<Mutex<Vec<ReceivedRequest>>>,
+    put_status: Arc<AtomicU16>,
+}
+
+impl MockS3Server {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind mock S3 server");
+
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let put_status = Arc::new(AtomicU16::new(200));
+
+        let server_requests = requests.clone();
+        let server_put_status = put_status.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let requests = server_requests.clone();
+                let put_status = server_put_status.clone();
+                tokio::spawn(handle_connection(stream, requests, put_status));
+            }
+        });
+
+        MockS3Server {
+            endpoint: format!("http://{}", address),
+            requests,
+            put_status,
+        }
+    }
+
+    /// Sets the status code to respond to `PUT` requests with
+    pub fn set_put_status(&self, status: u16) {
+        self.put_status.store(status, Ordering::Relaxed);
+    }
+
+    /// Waits until at least the specified number of requests have been received, and returns
+    /// all received requests. Panics if they aren't received in time.
+    pub async fn wait_for_requests(&self, count: usize) -> Vec<ReceivedRequest> {
+        for _ in 0..100 {
+            {
+                let requests = self.requests.lock().unwrap();
+                if requests.len() >= count {
+                    return requests.clone();
+                }
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        panic!(
+            "Expected {} requests but only received {:?}",
+            count,
+            self.requests.lock().unwrap()
+        );
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    requests: Arc<Mutex<Vec<ReceivedRequest>>>,
+    put_status: Arc<AtomicU16>,
+) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut request_line = String::new();
+        match reader.read_line(&mut request_line).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) => (),
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or_default().to_string();
+
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await.unwrap_or(0) == 0 {
+                return;
+            }
+
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+
+            if let Some((name, value)) = line.split_once(':') {
+                headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+            }
+        }
+
+        let length = headers
+            .get("content-length")
+            .and_then(|x| x.parse::<usize>().ok())
+            .unwrap_or_default();
+
+        let mut body = vec![0; length];
+        if reader.read_exact(&mut body).await.is_err() {
+            return;
+        }
+
+        let status = if method == "PUT" {
+            put_status.load(Ordering::Relaxed)
+        } else {
+            204
+        };
+
+        requests.lock().unwrap().push(ReceivedRequest {
+            method,
+            path,
+            headers,
+            body,
+        });
+
+        let response = format!("HTTP/1.1 {} Mock\r\ncontent-length: 0\r\n\r\n", status);
+        if reader
+            .get_mut()
+            .write_all(response.as_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
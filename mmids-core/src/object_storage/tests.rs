@@ -0,0 +1,90 @@
+use super::*;
+use crate::object_storage::test_server::MockS3Server;
+
+fn settings(endpoint: &str) -> S3Settings {
+    S3Settings {
+        endpoint: endpoint.to_string(),
+        region: "us-west-2".to_string(),
+        bucket: "videos".to_string(),
+        access_key_id: "access".to_string(),
+        secret_access_key: "secret".to_string(),
+    }
+}
+
+#[test]
+fn invalid_endpoint_returns_error() {
+    assert!(S3Client::new(settings("ftp://localhost")).is_err());
+    assert!(S3Client::new(settings("http://localhost/abc")).is_err());
+    assert!(S3Client::new(settings("localhost")).is_err());
+}
+
+#[tokio::test]
+async fn object_is_uploaded_with_signed_request() {
+    let server = MockS3Server::start().await;
+    let client = S3Client::new(settings(&server.endpoint)).unwrap();
+
+    client
+        .put_object(
+            "abc/segment 1.ts",
+            Bytes::from_static(b"data"),
+            "video/mp2t",
+        )
+        .await
+        .expect("Upload failed");
+
+    let requests = server.wait_for_requests(1).await;
+    let request = &requests[0];
+    assert_eq!(request.method, "PUT", "Unexpected method");
+    assert_eq!(
+        request.path, "/videos/abc/segment%201.ts",
+        "Unexpected path"
+    );
+    assert_eq!(request.body, b"data", "Unexpected body");
+    assert_eq!(
+        request.headers.get("content-type"),
+        Some(&"video/mp2t".to_string()),
+        "Unexpected content type"
+    );
+
+    assert_eq!(
+        request.headers.get("x-amz-content-sha256"),
+        Some(&hex(&sha256(b"data"))),
+        "Unexpected payload hash"
+    );
+
+    let authorization = request
+        .headers
+        .get("authorization")
+        .expect("No authorization header");
+
+    let date = &request.headers.get("x-amz-date").unwrap()[..8];
+    assert!(
+        authorization.starts_with(&format!(
+            "AWS4-HMAC-SHA256 Credential=access/{}/us-west-2/s3/aws4_request, \
+            SignedHeaders=content-type;host;x-amz-content-sha256;x-amz-date, Signature=",
+            date
+        )),
+        "Unexpected authorization header: {}",
+        authorization
+    );
+}
+
+#[tokio::test]
+async fn failed_upload_deletes_partial_object() {
+    let server = MockS3Server::start().await;
+    server.set_put_status(500);
+
+    let client = S3Client::new(settings(&server.endpoint)).unwrap();
+    let result = client
+        .put_object_or_clean_up("abc/1.ts", Bytes::from_static(b"data"), "video/mp2t")
+        .await;
+
+    match result {
+        Err(ObjectStorageError::UnexpectedStatusCode(StatusCode::INTERNAL_SERVER_ERROR)) => (),
+        result => panic!("Unexpected result: {:?}", result),
+    }
+
+    let requests = server.wait_for_requests(2).await;
+    assert_eq!(requests[1].method, "DELETE", "Expected a delete request");
+    assert_eq!(requests[1].path, "/videos/abc/1.ts", "Unexpected path");
+}
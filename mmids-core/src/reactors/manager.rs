@@ -1,15 +1,16 @@
 //! The reactor manager creates new reactors and allows relaying requests to the correct reactor
 //! based on names.
 
-use crate::actor_utils::notify_on_unbounded_recv;
+use crate::actor_utils::{notify_on_cancellation, notify_on_unbounded_recv};
 use crate::event_hub::SubscriptionRequest;
 use crate::reactors::executors::{GenerationError, ReactorExecutorFactory};
 use crate::reactors::reactor::ReactorWorkflowUpdate;
-use crate::reactors::{start_reactor, ReactorDefinition, ReactorRequest};
+use crate::reactors::{start_reactor, PendingReactorRequest, ReactorDefinition, ReactorRequest};
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
-use tokio::sync::oneshot::Sender;
+use tokio::sync::oneshot::{channel, Sender};
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info, instrument, warn};
 
 /// Requests that can be made to the reactor manager
@@ -34,6 +35,22 @@ pub enum ReactorManagerRequest {
         /// workflow.
         response_channel: UnboundedSender<ReactorWorkflowUpdate>,
     },
+
+    /// Requests the streams the specified reactor is still waiting on its executor for. `None`
+    /// is returned if no reactor exists with the specified name.
+    GetPendingReactorRequests {
+        reactor_name: Arc<String>,
+        response_channel: Sender<Option<Vec<PendingReactorRequest>>>,
+    },
+
+    /// Requests the specified reactor stop waiting on its executor for the specified stream.
+    /// Responds with `false` if no reactor exists with the specified name, or it had no executor
+    /// request pending for the stream.
+    CancelPendingReactorRequest {
+        reactor_name: Arc<String>,
+        stream_name: Arc<String>,
+        response_channel: Sender<bool>,
+    },
 }
 
 #[derive(Debug)]
@@ -44,9 +61,12 @@ pub enum CreateReactorResult {
     ExecutorReturnedError(Box<dyn std::error::Error + Sync + Send>),
 }
 
+/// Starts the reactor manager. Cancelling the shutdown token stops the manager along with all
+/// the reactors it created.
 pub fn start_reactor_manager(
     executor_factory: ReactorExecutorFactory,
     event_hub_subscriber: UnboundedSender<SubscriptionRequest>,
+    shutdown_token: CancellationToken,
 ) -> UnboundedSender<ReactorManagerRequest> {
     let (sender, receiver) = unbounded_channel();
     let (actor_sender, actor_receiver) = unbounded_channel();
@@ -54,6 +74,7 @@ pub fn start_reactor_manager(
         executor_factory,
         receiver,
         event_hub_subscriber,
+        shutdown_token,
         actor_sender,
     );
     tokio::spawn(actor.run(actor_receiver));
@@ -62,6 +83,7 @@ pub fn start_reactor_manager(
 }
 
 enum FutureResult {
+    ShutdownRequested,
     AllConsumersGone,
     RequestReceived(ReactorManagerRequest),
 }
@@ -69,6 +91,7 @@ enum FutureResult {
 struct Actor {
     executor_factory: ReactorExecutorFactory,
     event_hub_subscriber: UnboundedSender<SubscriptionRequest>,
+    shutdown_token: CancellationToken,
     reactors: HashMap<Arc<String>, UnboundedSender<ReactorRequest>>,
 }
 
@@ -77,18 +100,24 @@ impl Actor {
         executor_factory: ReactorExecutorFactory,
         receiver: UnboundedReceiver<ReactorManagerRequest>,
         event_hub_subscriber: UnboundedSender<SubscriptionRequest>,
+        shutdown_token: CancellationToken,
         actor_sender: UnboundedSender<FutureResult>,
     ) -> Self {
         notify_on_unbounded_recv(
             receiver,
-            actor_sender,
+            actor_sender.clone(),
             FutureResult::RequestReceived,
             || FutureResult::AllConsumersGone,
         );
 
+        notify_on_cancellation(shutdown_token.clone(), actor_sender, || {
+            FutureResult::ShutdownRequested
+        });
+
         Actor {
             executor_factory,
             event_hub_subscriber,
+            shutdown_token,
             reactors: HashMap::new(),
         }
     }
@@ -99,6 +128,11 @@ impl Actor {
 
         while let Some(result) = receiver.recv().await {
             match result {
+                FutureResult::ShutdownRequested => {
+                    info!("Shutdown requested");
+                    break;
+                }
+
                 FutureResult::AllConsumersGone => {
                     info!("All consumers gone");
                     break;
@@ -161,6 +195,9 @@ impl Actor {
                     executor,
                     self.event_hub_subscriber.clone(),
                     definition.update_interval,
+                    definition.executor_timeout,
+                    definition.executor_retries,
+                    self.shutdown_token.child_token(),
                 );
 
                 self.reactors.insert(definition.name, reactor);
@@ -196,6 +233,53 @@ impl Actor {
                     response_channel,
                 });
             }
+
+            ReactorManagerRequest::GetPendingReactorRequests {
+                reactor_name,
+                response_channel,
+            } => {
+                let reactor = match self.reactors.get(&reactor_name) {
+                    Some(reactor) => reactor.clone(),
+                    None => {
+                        let _ = response_channel.send(None);
+                        return;
+                    }
+                };
+
+                // Relayed through a task so the manager isn't blocked waiting on the reactor
+                tokio::spawn(async move {
+                    let (sender, receiver) = channel();
+                    let _ = reactor.send(ReactorRequest::GetPendingRequests {
+                        response_channel: sender,
+                    });
+
+                    let _ = response_channel.send(receiver.await.ok());
+                });
+            }
+
+            ReactorManagerRequest::CancelPendingReactorRequest {
+                reactor_name,
+                stream_name,
+                response_channel,
+            } => {
+                let reactor = match self.reactors.get(&reactor_name) {
+                    Some(reactor) => reactor.clone(),
+                    None => {
+                        let _ = response_channel.send(false);
+                        return;
+                    }
+                };
+
+                tokio::spawn(async move {
+                    let (sender, receiver) = channel();
+                    let _ = reactor.send(ReactorRequest::CancelPendingRequest {
+                        stream_name,
+                        response_channel: sender,
+                    });
+
+                    let _ = response_channel.send(receiver.await.unwrap_or_default());
+                });
+            }
         }
     }
 }
@@ -228,6 +312,8 @@ mod tests {
                 definition: ReactorDefinition {
                     name: Arc::new("reactor".to_string()),
                     update_interval: Duration::new(0, 0),
+                    executor_timeout: None,
+                    executor_retries: 0,
                     parameters,
                     executor: "exe".to_string(),
                 },
@@ -256,6 +342,8 @@ mod tests {
                 definition: ReactorDefinition {
                     name: Arc::new("reactor".to_string()),
                     update_interval: Duration::new(0, 0),
+                    executor_timeout: None,
+                    executor_retries: 0,
                     parameters: parameters.clone(),
                     executor: "exe".to_string(),
                 },
@@ -272,6 +360,8 @@ mod tests {
                 definition: ReactorDefinition {
                     name: Arc::new("reactor".to_string()),
                     update_interval: Duration::new(0, 0),
+                    executor_timeout: None,
+                    executor_retries: 0,
                     parameters: parameters.clone(),
                     executor: "exe".to_string(),
                 },
@@ -300,6 +390,8 @@ mod tests {
                 definition: ReactorDefinition {
                     name: Arc::new("reactor".to_string()),
                     update_interval: Duration::new(0, 0),
+                    executor_timeout: None,
+                    executor_retries: 0,
                     parameters,
                     executor: "exe".to_string(),
                 },
@@ -328,6 +420,8 @@ mod tests {
                 definition: ReactorDefinition {
                     name: Arc::new("reactor".to_string()),
                     update_interval: Duration::new(0, 0),
+                    executor_timeout: None,
+                    executor_retries: 0,
                     parameters,
                     executor: "exe2".to_string(),
                 },
@@ -360,6 +454,8 @@ mod tests {
                 definition: ReactorDefinition {
                     name: Arc::new("reactor".to_string()),
                     update_interval: Duration::new(0, 0),
+                    executor_timeout: None,
+                    executor_retries: 0,
                     parameters,
                     executor: "exe".to_string(),
                 },
@@ -404,6 +500,8 @@ mod tests {
                 definition: ReactorDefinition {
                     name: Arc::new("reactor".to_string()),
                     update_interval: Duration::new(0, 0),
+                    executor_timeout: None,
+                    executor_retries: 0,
                     parameters,
                     executor: "exe".to_string(),
                 },
@@ -450,7 +548,7 @@ mod tests {
                 .expect("Registration failed");
 
             let (event_sender, event_receiver) = unbounded_channel();
-            let manager = start_reactor_manager(factory, event_sender);
+            let manager = start_reactor_manager(factory, event_sender, CancellationToken::new());
 
             TestContext {
                 manager,
@@ -468,6 +566,9 @@ mod tests {
                 ReactorExecutionResult::valid(vec![WorkflowDefinition {
                     name: Arc::new("test".to_string()),
                     routed_by_reactor: false,
+                    max_stream_lifetime: None,
+                    max_cached_media_bytes: None,
+                    watcher_limit: None,
                     steps: Vec::new(),
                 }])
             }
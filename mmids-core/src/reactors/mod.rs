@@ -14,7 +14,7 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
-pub use reactor::{start_reactor, ReactorRequest, ReactorWorkflowUpdate};
+pub use reactor::{start_reactor, PendingReactorRequest, ReactorRequest, ReactorWorkflowUpdate};
 
 /// How reactors are defined
 #[derive(Clone, Debug)]
@@ -31,6 +31,14 @@ pub struct ReactorDefinition {
     /// specified) means it will never update.
     pub update_interval: Duration,
 
+    /// How long the reactor should wait for its executor to respond before giving up on the
+    /// request. A value of `None` means the reactor will wait indefinitely.
+    pub executor_timeout: Option<Duration>,
+
+    /// How many times the reactor should retry an executor request that timed out before giving
+    /// up on it.
+    pub executor_retries: u32,
+
     /// Key value pairs used to instruct the reactor's executor. Valid values here are specific
     /// to the executor that was picked.
     pub parameters: HashMap<String, Option<String>>,
@@ -1,5 +1,6 @@
 use crate::actor_utils::{
-    notify_on_future_completion, notify_on_unbounded_closed, notify_on_unbounded_recv,
+    notify_on_cancellation, notify_on_future_completion, notify_on_unbounded_closed,
+    notify_on_unbounded_recv,
 };
 use crate::event_hub::{SubscriptionRequest, WorkflowManagerEvent};
 use crate::reactors::executors::{ReactorExecutionResult, ReactorExecutor};
@@ -7,8 +8,10 @@ use crate::workflows::definitions::WorkflowDefinition;
 use crate::workflows::manager::{WorkflowManagerRequest, WorkflowManagerRequestOperation};
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot::Sender;
+use tokio_util::sync::CancellationToken;
 use tracing::{info, instrument, warn};
 
 /// Requests that can be made to a reactor
@@ -23,6 +26,32 @@ pub enum ReactorRequest {
         /// initial response, but updates will be sent any time the reactor detects changes.
         response_channel: UnboundedSender<ReactorWorkflowUpdate>,
     },
+
+    /// Requests details about every stream the reactor is still waiting on its executor for
+    GetPendingRequests {
+        response_channel: Sender<Vec<PendingReactorRequest>>,
+    },
+
+    /// Requests the reactor stop waiting on its executor for the specified stream. Anything
+    /// waiting on workflows for the stream is told the stream is invalid, unless the stream
+    /// already had workflows from a previous request, in which case those are kept. Responds
+    /// with `false` if no executor request was pending for the stream.
+    CancelPendingRequest {
+        stream_name: Arc<String>,
+        response_channel: Sender<bool>,
+    },
+}
+
+/// Details about a stream the reactor is waiting on its executor for
+#[derive(Clone, Debug)]
+pub struct PendingReactorRequest {
+    pub stream_name: Arc<String>,
+
+    /// How long the reactor has been waiting on the executor's current attempt
+    pub age: Duration,
+
+    /// How many times the executor request has been retried after timing out
+    pub retry_count: u32,
 }
 
 /// Contains information about a workflow from a reactor
@@ -35,21 +64,35 @@ pub struct ReactorWorkflowUpdate {
     pub routable_workflow_names: HashSet<Arc<String>>,
 }
 
+/// Starts a new reactor. If an `executor_timeout` is provided, then any executor request that
+/// does not complete within that time will be retried up to `executor_retries` times. Once all
+/// retries have been exhausted the stream is considered invalid, unless the request was to refresh
+/// an already valid stream, in which case the previously returned workflows are kept.
 pub fn start_reactor(
     name: Arc<String>,
     executor: Box<dyn ReactorExecutor + Send>,
     event_hub_subscriber: UnboundedSender<SubscriptionRequest>,
     update_interval: Duration,
+    executor_timeout: Option<Duration>,
+    executor_retries: u32,
+    shutdown_token: CancellationToken,
 ) -> UnboundedSender<ReactorRequest> {
     let (sender, receiver) = unbounded_channel();
     let (actor_sender, actor_receiver) = unbounded_channel();
+    notify_on_cancellation(shutdown_token, actor_sender.clone(), || {
+        FutureResult::ShutdownRequested
+    });
 
     let actor = Actor::new(
         name,
         receiver,
         executor,
         event_hub_subscriber,
-        update_interval,
+        ExecutionSettings {
+            update_interval,
+            executor_timeout,
+            executor_retries,
+        },
         actor_sender,
     );
     tokio::spawn(actor.run(actor_receiver));
@@ -58,6 +101,7 @@ pub fn start_reactor(
 }
 
 enum FutureResult {
+    ShutdownRequested,
     AllRequestConsumersGone,
     EventHubGone,
     WorkflowManagerGone,
@@ -67,6 +111,15 @@ enum FutureResult {
         result: ReactorExecutionResult,
     },
 
+    ExecutorTimedOut {
+        stream_name: Arc<String>,
+        attempt: u32,
+    },
+
+    ExecutorRequestCancelled {
+        stream_name: Arc<String>,
+    },
+
     WorkflowManagerEventReceived(WorkflowManagerEvent),
 
     ClientResponseChannelClosed {
@@ -82,6 +135,24 @@ struct CachedWorkflows {
     definitions: Vec<WorkflowDefinition>,
 }
 
+struct PendingExecutorRequest {
+    cancellation_token: CancellationToken,
+    started_at: Instant,
+    attempt: u32,
+}
+
+enum ExecutorOutcome {
+    Completed(ReactorExecutionResult),
+    TimedOut,
+    Cancelled,
+}
+
+struct ExecutionSettings {
+    update_interval: Duration,
+    executor_timeout: Option<Duration>,
+    executor_retries: u32,
+}
+
 struct Actor {
     internal_sender: UnboundedSender<FutureResult>,
     name: Arc<String>,
@@ -89,7 +160,10 @@ struct Actor {
     workflow_manager: Option<UnboundedSender<WorkflowManagerRequest>>,
     cached_workflows_for_stream_name: HashMap<Arc<String>, CachedWorkflows>,
     update_interval: Duration,
+    executor_timeout: Option<Duration>,
+    executor_retries: u32,
     stream_response_channels: HashMap<Arc<String>, Vec<UnboundedSender<ReactorWorkflowUpdate>>>,
+    pending_executor_requests: HashMap<Arc<String>, PendingExecutorRequest>,
 }
 
 impl Actor {
@@ -98,7 +172,7 @@ impl Actor {
         receiver: UnboundedReceiver<ReactorRequest>,
         executor: Box<dyn ReactorExecutor + Send>,
         event_hub_subscriber: UnboundedSender<SubscriptionRequest>,
-        update_interval: Duration,
+        settings: ExecutionSettings,
         actor_sender: UnboundedSender<FutureResult>,
     ) -> Self {
         notify_on_unbounded_recv(
@@ -126,8 +200,11 @@ impl Actor {
             executor,
             workflow_manager: None,
             cached_workflows_for_stream_name: HashMap::new(),
-            update_interval,
+            update_interval: settings.update_interval,
+            executor_timeout: settings.executor_timeout,
+            executor_retries: settings.executor_retries,
             stream_response_channels: HashMap::new(),
+            pending_executor_requests: HashMap::new(),
         }
     }
 
@@ -137,6 +214,11 @@ impl Actor {
 
         while let Some(result) = receiver.recv().await {
             match result {
+                FutureResult::ShutdownRequested => {
+                    info!("Shutdown requested");
+                    break;
+                }
+
                 FutureResult::AllRequestConsumersGone => {
                     info!("All consumers gone");
                     break;
@@ -167,20 +249,26 @@ impl Actor {
                     self.handle_executor_response(stream_name, workflow);
                 }
 
+                FutureResult::ExecutorTimedOut {
+                    stream_name,
+                    attempt,
+                } => {
+                    self.handle_executor_timeout(stream_name, attempt);
+                }
+
+                FutureResult::ExecutorRequestCancelled { stream_name } => {
+                    info!(
+                        stream_name = %stream_name,
+                        "Executor request for stream '{}' cancelled", stream_name
+                    );
+                }
+
                 FutureResult::UpdateStreamNameRequested { stream_name } => {
                     if self
                         .cached_workflows_for_stream_name
                         .contains_key(&stream_name)
                     {
-                        let future = self.executor.get_workflow(stream_name.clone());
-                        notify_on_future_completion(
-                            future,
-                            self.internal_sender.clone(),
-                            move |result| FutureResult::ExecutorResponseReceived {
-                                stream_name,
-                                result,
-                            },
-                        );
+                        self.request_workflow_from_executor(stream_name, 0);
                     }
                 }
 
@@ -222,16 +310,7 @@ impl Actor {
                             .collect::<HashSet<_>>(),
                     });
                 } else {
-                    let future = self.executor.get_workflow(stream_name.clone());
-                    let stream_name = stream_name.clone();
-                    notify_on_future_completion(
-                        future,
-                        self.internal_sender.clone(),
-                        move |result| FutureResult::ExecutorResponseReceived {
-                            stream_name,
-                            result,
-                        },
-                    );
+                    self.request_workflow_from_executor(stream_name.clone(), 0);
                 }
 
                 notify_on_unbounded_closed(
@@ -240,6 +319,166 @@ impl Actor {
                     move || FutureResult::ClientResponseChannelClosed { stream_name },
                 );
             }
+
+            ReactorRequest::GetPendingRequests { response_channel } => {
+                let requests = self
+                    .pending_executor_requests
+                    .iter()
+                    .map(|(stream_name, request)| PendingReactorRequest {
+                        stream_name: stream_name.clone(),
+                        age: request.started_at.elapsed(),
+                        retry_count: request.attempt,
+                    })
+                    .collect::<Vec<_>>();
+
+                let _ = response_channel.send(requests);
+            }
+
+            ReactorRequest::CancelPendingRequest {
+                stream_name,
+                response_channel,
+            } => {
+                let request = match self.pending_executor_requests.remove(&stream_name) {
+                    Some(request) => request,
+                    None => {
+                        let _ = response_channel.send(false);
+                        return;
+                    }
+                };
+
+                warn!(
+                    stream_name = %stream_name,
+                    "Executor request for stream '{}' cancelled by an external caller", stream_name
+                );
+
+                request.cancellation_token.cancel();
+                self.give_up_on_executor_request(stream_name);
+
+                let _ = response_channel.send(true);
+            }
+        }
+    }
+
+    fn request_workflow_from_executor(&mut self, stream_name: Arc<String>, attempt: u32) {
+        let future = self.executor.get_workflow(stream_name.clone());
+        let executor_timeout = self.executor_timeout;
+        let cancellation_token = CancellationToken::new();
+        let cancelled = cancellation_token.clone();
+        let future = async move {
+            let execution = async move {
+                match executor_timeout {
+                    Some(duration) => match tokio::time::timeout(duration, future).await {
+                        Ok(result) => ExecutorOutcome::Completed(result),
+                        Err(_) => ExecutorOutcome::TimedOut,
+                    },
+
+                    None => ExecutorOutcome::Completed(future.await),
+                }
+            };
+
+            // Cancelling drops the executor's future, aborting any work it still had to do
+            tokio::select! {
+                outcome = execution => outcome,
+                _ = cancelled.cancelled() => ExecutorOutcome::Cancelled,
+            }
+        };
+
+        let request = PendingExecutorRequest {
+            cancellation_token,
+            started_at: Instant::now(),
+            attempt,
+        };
+
+        if let Some(previous) = self
+            .pending_executor_requests
+            .insert(stream_name.clone(), request)
+        {
+            previous.cancellation_token.cancel();
+        }
+
+        notify_on_future_completion(future, self.internal_sender.clone(), move |outcome| {
+            match outcome {
+                ExecutorOutcome::Completed(result) => FutureResult::ExecutorResponseReceived {
+                    stream_name,
+                    result,
+                },
+
+                ExecutorOutcome::TimedOut => FutureResult::ExecutorTimedOut {
+                    stream_name,
+                    attempt,
+                },
+
+                ExecutorOutcome::Cancelled => {
+                    FutureResult::ExecutorRequestCancelled { stream_name }
+                }
+            }
+        });
+    }
+
+    /// Checks if anyone is still waiting on workflow updates for the stream. Response channels
+    /// can be closed before we've been notified of their closure, so they are checked directly.
+    fn has_active_requesters(&self, stream_name: &Arc<String>) -> bool {
+        match self.stream_response_channels.get(stream_name) {
+            Some(channels) => channels.iter().any(|channel| !channel.is_closed()),
+            None => false,
+        }
+    }
+
+    fn handle_executor_timeout(&mut self, stream_name: Arc<String>, attempt: u32) {
+        self.pending_executor_requests.remove(&stream_name);
+        if !self.has_active_requesters(&stream_name) {
+            // Nothing is waiting on this stream anymore
+            return;
+        }
+
+        if attempt < self.executor_retries {
+            warn!(
+                stream_name = %stream_name,
+                attempt = %(attempt + 1),
+                "Executor request for stream '{}' timed out, retrying", stream_name
+            );
+
+            self.request_workflow_from_executor(stream_name, attempt + 1);
+            return;
+        }
+
+        warn!(
+            stream_name = %stream_name,
+            "Executor request for stream '{}' timed out", stream_name
+        );
+
+        self.give_up_on_executor_request(stream_name);
+    }
+
+    /// Responds to an executor request that will never complete
+    fn give_up_on_executor_request(&mut self, stream_name: Arc<String>) {
+        if self
+            .cached_workflows_for_stream_name
+            .contains_key(&stream_name)
+        {
+            // This was a refresh of a stream that was already valid, so keep using the workflows
+            // we already have instead of tearing down an active stream.
+            warn!(
+                stream_name = %stream_name,
+                "No executor response for stream '{}', keeping the existing workflows",
+                stream_name
+            );
+
+            if !self.update_interval.is_zero() {
+                notify_after_update_interval(
+                    stream_name,
+                    self.update_interval,
+                    self.internal_sender.clone(),
+                );
+            }
+        } else {
+            warn!(
+                stream_name = %stream_name,
+                "No executor response for stream '{}', treating the stream as invalid",
+                stream_name
+            );
+
+            self.handle_executor_response(stream_name, ReactorExecutionResult::invalid());
         }
     }
 
@@ -248,6 +487,19 @@ impl Actor {
         stream_name: Arc<String>,
         result: ReactorExecutionResult,
     ) {
+        self.pending_executor_requests.remove(&stream_name);
+        if !self.has_active_requesters(&stream_name) {
+            // Everyone who wanted this stream has gone away, so don't create workflows that
+            // no one will use.
+            info!(
+                stream_name = %stream_name,
+                "Executor responded for stream '{}' but no requesters remain, ignoring response",
+                stream_name
+            );
+
+            return;
+        }
+
         if let Some(channels) = self.stream_response_channels.get(&stream_name) {
             let routed_workflow_names = result
                 .workflows_returned
@@ -301,6 +553,7 @@ impl Actor {
                             ),
                             operation: WorkflowManagerRequestOperation::UpsertWorkflow {
                                 definition: workflow.clone(),
+                                response_channel: None,
                             },
                         });
                     }
@@ -372,6 +625,7 @@ impl Actor {
                             request_id: format!("reactor_{}_cache_catchup", self.name),
                             operation: WorkflowManagerRequestOperation::UpsertWorkflow {
                                 definition: workflow.clone(),
+                                response_channel: None,
                             },
                         });
                     }
@@ -398,6 +652,10 @@ impl Actor {
 
                 self.stream_response_channels.remove(&stream_name);
 
+                if let Some(request) = self.pending_executor_requests.remove(&stream_name) {
+                    request.cancellation_token.cancel();
+                }
+
                 if let Some(channel) = &self.workflow_manager {
                     if let Some(cache) = self.cached_workflows_for_stream_name.remove(&stream_name)
                     {
@@ -448,6 +706,8 @@ mod tests {
     use crate::workflows::definitions::{WorkflowStepDefinition, WorkflowStepType};
     use futures::future::BoxFuture;
     use futures::FutureExt;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use tokio::sync::oneshot::channel;
     use tokio::time::timeout;
 
     struct TestContext {
@@ -462,10 +722,46 @@ mod tests {
         workflows: Vec<WorkflowDefinition>,
     }
 
+    struct HangingExecutor {
+        call_count: Arc<AtomicUsize>,
+    }
+
+    struct DelayedExecutor {
+        delay: Duration,
+        workflows: Vec<WorkflowDefinition>,
+        future_dropped: Arc<AtomicBool>,
+    }
+
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
     impl TestContext {
         async fn new(name: Arc<String>, duration: Duration, executor: TestExecutor) -> Self {
+            TestContext::new_with_executor(name, duration, Box::new(executor), None, 0).await
+        }
+
+        async fn new_with_executor(
+            name: Arc<String>,
+            duration: Duration,
+            executor: Box<dyn ReactorExecutor + Send>,
+            executor_timeout: Option<Duration>,
+            executor_retries: u32,
+        ) -> Self {
             let (sender, mut sub_receiver) = unbounded_channel();
-            let reactor = start_reactor(name, Box::new(executor), sender, duration);
+            let reactor = start_reactor(
+                name,
+                executor,
+                sender,
+                duration,
+                executor_timeout,
+                executor_retries,
+                CancellationToken::new(),
+            );
 
             let response = test_utils::expect_mpsc_response(&mut sub_receiver).await;
             let response_channel = match response {
@@ -503,6 +799,33 @@ mod tests {
         }
     }
 
+    impl ReactorExecutor for HangingExecutor {
+        fn get_workflow(
+            &self,
+            _stream_name: Arc<String>,
+        ) -> BoxFuture<'static, ReactorExecutionResult> {
+            self.call_count.fetch_add(1, Ordering::SeqCst);
+            futures::future::pending().boxed()
+        }
+    }
+
+    impl ReactorExecutor for DelayedExecutor {
+        fn get_workflow(
+            &self,
+            _stream_name: Arc<String>,
+        ) -> BoxFuture<'static, ReactorExecutionResult> {
+            let delay = self.delay;
+            let workflows = self.workflows.clone();
+            let drop_flag = DropFlag(self.future_dropped.clone());
+            async move {
+                let _drop_flag = drop_flag;
+                tokio::time::sleep(delay).await;
+                ReactorExecutionResult::valid(workflows)
+            }
+            .boxed()
+        }
+    }
+
     #[tokio::test]
     async fn can_get_routable_workflows_from_executor() {
         let executor = TestExecutor {
@@ -579,6 +902,217 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn executor_timeout_responds_with_invalid_stream() {
+        let call_count = Arc::new(AtomicUsize::new(0));
+        let executor = HangingExecutor {
+            call_count: call_count.clone(),
+        };
+
+        let context = TestContext::new_with_executor(
+            Arc::new("reactor".to_string()),
+            Duration::from_millis(0),
+            Box::new(executor),
+            Some(Duration::from_millis(20)),
+            0,
+        )
+        .await;
+
+        let (sender, mut receiver) = unbounded_channel();
+        context
+            .reactor
+            .send(ReactorRequest::CreateWorkflowNameForStream {
+                stream_name: Arc::new("stream".to_string()),
+                response_channel: sender,
+            })
+            .expect("Channel closed");
+
+        let update = timeout(Duration::from_millis(500), receiver.recv())
+            .await
+            .expect("Timed out waiting for a response")
+            .expect("Response channel closed");
+
+        assert!(!update.is_valid, "Expected is valid to be false");
+        assert_eq!(
+            call_count.load(Ordering::SeqCst),
+            1,
+            "Unexpected call count"
+        );
+    }
+
+    #[tokio::test]
+    async fn executor_timeout_retries_before_responding() {
+        let call_count = Arc::new(AtomicUsize::new(0));
+        let executor = HangingExecutor {
+            call_count: call_count.clone(),
+        };
+
+        let context = TestContext::new_with_executor(
+            Arc::new("reactor".to_string()),
+            Duration::from_millis(0),
+            Box::new(executor),
+            Some(Duration::from_millis(20)),
+            2,
+        )
+        .await;
+
+        let (sender, mut receiver) = unbounded_channel();
+        context
+            .reactor
+            .send(ReactorRequest::CreateWorkflowNameForStream {
+                stream_name: Arc::new("stream".to_string()),
+                response_channel: sender,
+            })
+            .expect("Channel closed");
+
+        let update = timeout(Duration::from_millis(500), receiver.recv())
+            .await
+            .expect("Timed out waiting for a response")
+            .expect("Response channel closed");
+
+        assert!(!update.is_valid, "Expected is valid to be false");
+        assert_eq!(
+            call_count.load(Ordering::SeqCst),
+            3,
+            "Unexpected call count"
+        );
+    }
+
+    #[tokio::test]
+    async fn executor_request_aborted_when_requester_goes_away() {
+        let future_dropped = Arc::new(AtomicBool::new(false));
+        let executor = DelayedExecutor {
+            delay: Duration::from_millis(100),
+            workflows: get_test_workflows(),
+            future_dropped: future_dropped.clone(),
+        };
+
+        let mut context = TestContext::new_with_executor(
+            Arc::new("reactor".to_string()),
+            Duration::from_millis(0),
+            Box::new(executor),
+            None,
+            0,
+        )
+        .await;
+
+        let (sender, receiver) = unbounded_channel();
+        context
+            .reactor
+            .send(ReactorRequest::CreateWorkflowNameForStream {
+                stream_name: Arc::new("stream".to_string()),
+                response_channel: sender,
+            })
+            .expect("Channel closed");
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(receiver);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        assert!(
+            future_dropped.load(Ordering::SeqCst),
+            "Expected executor future to be dropped"
+        );
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        test_utils::expect_mpsc_timeout(&mut context.workflow_manager).await;
+    }
+
+    #[tokio::test]
+    async fn pending_request_can_be_listed_and_cancelled() {
+        let call_count = Arc::new(AtomicUsize::new(0));
+        let executor = HangingExecutor {
+            call_count: call_count.clone(),
+        };
+
+        let context = TestContext::new_with_executor(
+            Arc::new("reactor".to_string()),
+            Duration::from_millis(0),
+            Box::new(executor),
+            None,
+            0,
+        )
+        .await;
+
+        let (sender, mut receiver) = unbounded_channel();
+        context
+            .reactor
+            .send(ReactorRequest::CreateWorkflowNameForStream {
+                stream_name: Arc::new("stream".to_string()),
+                response_channel: sender,
+            })
+            .expect("Channel closed");
+
+        let (sender, pending_receiver) = channel();
+        context
+            .reactor
+            .send(ReactorRequest::GetPendingRequests {
+                response_channel: sender,
+            })
+            .expect("Channel closed");
+
+        let pending = test_utils::expect_oneshot_response(pending_receiver).await;
+        assert_eq!(pending.len(), 1, "Unexpected number of pending requests");
+        assert_eq!(
+            pending[0].stream_name.as_str(),
+            "stream",
+            "Unexpected pending stream name"
+        );
+
+        let (sender, cancel_receiver) = channel();
+        context
+            .reactor
+            .send(ReactorRequest::CancelPendingRequest {
+                stream_name: Arc::new("stream".to_string()),
+                response_channel: sender,
+            })
+            .expect("Channel closed");
+
+        let cancelled = test_utils::expect_oneshot_response(cancel_receiver).await;
+        assert!(cancelled, "Expected the request to be cancelled");
+
+        let update = test_utils::expect_mpsc_response(&mut receiver).await;
+        assert!(!update.is_valid, "Expected is valid to be false");
+
+        let (sender, pending_receiver) = channel();
+        context
+            .reactor
+            .send(ReactorRequest::GetPendingRequests {
+                response_channel: sender,
+            })
+            .expect("Channel closed");
+
+        let pending = test_utils::expect_oneshot_response(pending_receiver).await;
+        assert!(pending.is_empty(), "Expected no pending requests");
+    }
+
+    #[tokio::test]
+    async fn executor_response_ignored_when_requester_already_gone() {
+        let executor = TestExecutor {
+            expected_name: Arc::new("stream".to_string()),
+            workflows: get_test_workflows(),
+        };
+
+        let mut context = TestContext::new(
+            Arc::new("reactor".to_string()),
+            Duration::from_millis(0),
+            executor,
+        )
+        .await;
+
+        let (sender, receiver) = unbounded_channel();
+        drop(receiver);
+        context
+            .reactor
+            .send(ReactorRequest::CreateWorkflowNameForStream {
+                stream_name: Arc::new("stream".to_string()),
+                response_channel: sender,
+            })
+            .expect("Channel closed");
+
+        test_utils::expect_mpsc_timeout(&mut context.workflow_manager).await;
+    }
+
     #[tokio::test]
     async fn all_workflows_upserted_to_workflow_manager() {
         let executor = TestExecutor {
@@ -605,7 +1139,7 @@ mod tests {
         loop {
             let request = test_utils::expect_mpsc_response(&mut context.workflow_manager).await;
             match request.operation {
-                WorkflowManagerRequestOperation::UpsertWorkflow { definition } => {
+                WorkflowManagerRequestOperation::UpsertWorkflow { definition, .. } => {
                     if definition.name.as_str() == "first" {
                         if workflows_found[0] {
                             panic!("Received duplicate upsert request for workflow 'first'");
@@ -757,7 +1291,7 @@ mod tests {
         loop {
             let request = test_utils::expect_mpsc_response(&mut context.workflow_manager).await;
             match request.operation {
-                WorkflowManagerRequestOperation::UpsertWorkflow { definition } => {
+                WorkflowManagerRequestOperation::UpsertWorkflow { definition, .. } => {
                     if definition.name.as_str() == "first" {
                         if workflows_found[0] {
                             panic!("Received duplicate upsert request for workflow 'first'");
@@ -834,6 +1368,9 @@ mod tests {
             WorkflowDefinition {
                 name: Arc::new("first".to_string()),
                 routed_by_reactor: true,
+                max_stream_lifetime: None,
+                max_cached_media_bytes: None,
+                watcher_limit: None,
                 steps: vec![WorkflowStepDefinition {
                     step_type: WorkflowStepType("a".to_string()),
                     parameters: HashMap::new(),
@@ -842,6 +1379,9 @@ mod tests {
             WorkflowDefinition {
                 name: Arc::new("second".to_string()),
                 routed_by_reactor: false,
+                max_stream_lifetime: None,
+                max_cached_media_bytes: None,
+                watcher_limit: None,
                 steps: vec![
                     WorkflowStepDefinition {
                         step_type: WorkflowStepType("b".to_string()),
@@ -856,6 +1396,9 @@ mod tests {
             WorkflowDefinition {
                 name: Arc::new("third".to_string()),
                 routed_by_reactor: true,
+                max_stream_lifetime: None,
+                max_cached_media_bytes: None,
+                watcher_limit: None,
                 steps: vec![
                     WorkflowStepDefinition {
                         step_type: WorkflowStepType("d".to_string()),
@@ -0,0 +1,437 @@
+//! Provides a single entry point for starting all of mmids' core systems from code, for
+//! applications that embed mmids instead of wiring up the event hub, reactor manager, and
+//! workflow manager themselves.
+//!
+//! Endpoints are not owned by the server, as they are not part of the core. Applications start
+//! the endpoints they need themselves, and hand them to the workflow steps that use them when
+//! registering those steps with the builder.
+
+use crate::config::MmidsConfig;
+use crate::event_hub::{start_event_hub, PublishEventRequest, SubscriptionRequest};
+use crate::reactors::executors::{
+    ReactorExecutorFactory, ReactorExecutorGenerator, RegistrationError,
+};
+use crate::reactors::manager::{start_reactor_manager, CreateReactorResult, ReactorManagerRequest};
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::log_levels::WorkflowLogLevels;
+use crate::workflows::manager::{
+    start_workflow_manager, StreamReaperSettings, WorkflowManagerRequest,
+    WorkflowManagerRequestOperation,
+};
+use crate::workflows::steps::factory::{
+    FactoryRegistrationError, StepGenerator, WorkflowStepFactory,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot::channel;
+use tokio::time::{sleep, timeout, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// How long shutting down waits for running workflows to be stopped
+const WORKFLOW_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+const WORKFLOW_DRAIN_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long shutting down waits for each actor to exit once it's been told to stop
+const ACTOR_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Creates a step generator once the server's channels are known
+type StepGeneratorFn =
+    Box<dyn FnOnce(&ServerChannels) -> Box<dyn StepGenerator + Sync + Send> + Send>;
+
+/// Channels to the core actors that workflow step generators may need when they're created
+#[derive(Clone)]
+pub struct ServerChannels {
+    pub event_hub_publisher: UnboundedSender<PublishEventRequest>,
+    pub event_hub_subscriber: UnboundedSender<SubscriptionRequest>,
+    pub reactor_manager: UnboundedSender<ReactorManagerRequest>,
+}
+
+/// Errors that can occur when starting a mmids server
+#[derive(Error, Debug)]
+pub enum ServerStartError {
+    #[error("Failed to register reactor executor")]
+    ReactorExecutorRegistrationFailed(#[from] RegistrationError),
+
+    #[error("Failed to register workflow step")]
+    StepRegistrationFailed(#[from] FactoryRegistrationError),
+
+    #[error("Failed to create reactor '{name}': {result:?}")]
+    ReactorCreationFailed {
+        name: Arc<String>,
+        result: CreateReactorResult,
+    },
+
+    #[error("The reactor manager stopped unexpectedly")]
+    ReactorManagerStopped,
+}
+
+/// Builds a mmids server from a configuration, along with the reactor executors and workflow
+/// steps the configuration's reactors and workflows rely on.
+pub struct MmidsServerBuilder {
+    config: MmidsConfig,
+    reactor_executors: Vec<(String, Box<dyn ReactorExecutorGenerator + Send>)>,
+    step_generators: Vec<(WorkflowStepType, StepGeneratorFn)>,
+    reaper_settings: Option<StreamReaperSettings>,
+    log_levels: WorkflowLogLevels,
+}
+
+/// Handles to a running mmids server
+pub struct MmidsServer {
+    pub channels: ServerChannels,
+    pub workflow_manager: UnboundedSender<WorkflowManagerRequest>,
+    shutdown_token: CancellationToken,
+
+    // Each actor has its own token so they can be stopped in dependency order
+    event_hub_token: CancellationToken,
+    reactor_manager_token: CancellationToken,
+    workflow_manager_token: CancellationToken,
+}
+
+impl MmidsServerBuilder {
+    /// Creates a builder for a server that will run the reactors and workflows defined in the
+    /// specified configuration
+    pub fn new(config: MmidsConfig) -> Self {
+        MmidsServerBuilder {
+            config,
+            reactor_executors: Vec::new(),
+            step_generators: Vec::new(),
+            reaper_settings: None,
+            log_levels: WorkflowLogLevels::default(),
+        }
+    }
+
+    /// Registers a reactor executor that reactors can use by name
+    pub fn reactor_executor(
+        mut self,
+        name: &str,
+        generator: Box<dyn ReactorExecutorGenerator + Send>,
+    ) -> Self {
+        self.reactor_executors.push((name.to_string(), generator));
+        self
+    }
+
+    /// Registers a workflow step type. The step's generator is created once the core actors have
+    /// been started, so it can be given channels to them.
+    pub fn step<F>(mut self, step_type: &str, create_generator: F) -> Self
+    where
+        F: FnOnce(&ServerChannels) -> Box<dyn StepGenerator + Sync + Send> + Send + 'static,
+    {
+        self.step_generators.push((
+            WorkflowStepType(step_type.to_string()),
+            Box::new(create_generator),
+        ));
+
+        self
+    }
+
+    /// Enables the dead stream reaper for the server's workflows
+    pub fn stream_reaper(mut self, settings: StreamReaperSettings) -> Self {
+        self.reaper_settings = Some(settings);
+        self
+    }
+
+    /// Sets the workflow log level overrides the workflow manager applies its log level requests
+    /// to. These should be the same overrides the application's `WorkflowLogFilter` was created
+    /// with.
+    pub fn workflow_log_levels(mut self, levels: WorkflowLogLevels) -> Self {
+        self.log_levels = levels;
+        self
+    }
+
+    /// Starts all the core actors, then creates the configured reactors and starts the
+    /// configured workflows.
+    pub async fn start(self) -> Result<MmidsServer, ServerStartError> {
+        let mut executor_factory = ReactorExecutorFactory::new();
+        for (name, generator) in self.reactor_executors {
+            executor_factory.register(name, generator)?;
+        }
+
+        let shutdown_token = CancellationToken::new();
+        let event_hub_token = shutdown_token.child_token();
+        let reactor_manager_token = shutdown_token.child_token();
+        let workflow_manager_token = shutdown_token.child_token();
+
+        info!("Starting event hub");
+        let (publisher, subscriber) = start_event_hub(event_hub_token.clone());
+
+        info!("Starting reactor manager");
+        let reactor_manager = start_reactor_manager(
+            executor_factory,
+            subscriber.clone(),
+            reactor_manager_token.clone(),
+        );
+        for (name, definition) in self.config.reactors {
+            let (sender, receiver) = channel();
+            let _ = reactor_manager.send(ReactorManagerRequest::CreateReactor {
+                definition,
+                response_channel: sender,
+            });
+
+            match receiver.await {
+                Ok(CreateReactorResult::Success) => (),
+                Ok(result) => return Err(ServerStartError::ReactorCreationFailed { name, result }),
+                Err(_) => return Err(ServerStartError::ReactorManagerStopped),
+            }
+        }
+
+        let channels = ServerChannels {
+            event_hub_publisher: publisher,
+            event_hub_subscriber: subscriber,
+            reactor_manager,
+        };
+
+        let mut step_factory = WorkflowStepFactory::new();
+        for (step_type, create_generator) in self.step_generators {
+            step_factory.register(step_type, create_generator(&channels))?;
+        }
+
+        info!("Starting workflow manager");
+        let workflow_manager = start_workflow_manager(
+            Arc::new(step_factory),
+            channels.event_hub_publisher.clone(),
+            self.reaper_settings,
+            self.log_levels,
+            workflow_manager_token.clone(),
+        );
+
+        for definition in self.config.workflows.into_values() {
+            let _ = workflow_manager.send(WorkflowManagerRequest {
+                request_id: "mmids-server-startup".to_string(),
+                operation: WorkflowManagerRequestOperation::UpsertWorkflow {
+                    definition,
+                    response_channel: None,
+                },
+            });
+        }
+
+        Ok(MmidsServer {
+            channels,
+            workflow_manager,
+            shutdown_token,
+            event_hub_token,
+            reactor_manager_token,
+            workflow_manager_token,
+        })
+    }
+}
+
+impl MmidsServer {
+    /// Token that gets cancelled once the server has shut down. Cancelling this token directly
+    /// stops all of the server's actors at once, without waiting for workflows to drain.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown_token.clone()
+    }
+
+    /// Gracefully shuts the server down. All running workflows are stopped (giving their steps a
+    /// chance to close out their streams), then the workflow manager, reactor manager, and event
+    /// hub are stopped in that order, so no actor is stopped while another still depends on it.
+    pub async fn shutdown(self) {
+        info!("Shutting down mmids server");
+
+        let workflows = self.get_running_workflows().await.unwrap_or_default();
+        for name in workflows {
+            let _ = self.workflow_manager.send(WorkflowManagerRequest {
+                request_id: "mmids-server-shutdown".to_string(),
+                operation: WorkflowManagerRequestOperation::StopWorkflow { name },
+            });
+        }
+
+        let deadline = Instant::now() + WORKFLOW_DRAIN_TIMEOUT;
+        loop {
+            match self.get_running_workflows().await {
+                Some(workflows) if !workflows.is_empty() => {
+                    if Instant::now() >= deadline {
+                        warn!(
+                            "{} workflows were still running after {:?}, shutting down anyway",
+                            workflows.len(),
+                            WORKFLOW_DRAIN_TIMEOUT
+                        );
+
+                        break;
+                    }
+
+                    sleep(WORKFLOW_DRAIN_CHECK_INTERVAL).await;
+                }
+
+                _ => break,
+            }
+        }
+
+        stop_actor(
+            "workflow manager",
+            &self.workflow_manager_token,
+            &self.workflow_manager,
+        )
+        .await;
+
+        stop_actor(
+            "reactor manager",
+            &self.reactor_manager_token,
+            &self.channels.reactor_manager,
+        )
+        .await;
+
+        stop_actor(
+            "event hub",
+            &self.event_hub_token,
+            &self.channels.event_hub_publisher,
+        )
+        .await;
+
+        self.shutdown_token.cancel();
+        info!("Mmids server shut down");
+    }
+
+    /// Gets the names of all running workflows, or `None` if the workflow manager is gone
+    async fn get_running_workflows(&self) -> Option<Vec<Arc<String>>> {
+        let (sender, receiver) = channel();
+        let _ = self.workflow_manager.send(WorkflowManagerRequest {
+            request_id: "mmids-server-shutdown".to_string(),
+            operation: WorkflowManagerRequestOperation::GetRunningWorkflows {
+                response_channel: sender,
+            },
+        });
+
+        let workflows = receiver.await.ok()?;
+
+        Some(
+            workflows
+                .into_iter()
+                .map(|workflow| workflow.name)
+                .collect(),
+        )
+    }
+}
+
+/// Cancels an actor's token and waits for the actor to exit, which is signified by its request
+/// channel closing
+async fn stop_actor<T>(name: &str, token: &CancellationToken, channel: &UnboundedSender<T>) {
+    token.cancel();
+    if timeout(ACTOR_STOP_TIMEOUT, channel.closed()).await.is_err() {
+        warn!("The {} did not stop within {:?}", name, ACTOR_STOP_TIMEOUT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse;
+    use crate::test_utils;
+    use crate::workflows::definitions::{WorkflowDefinition, WorkflowStepDefinition};
+    use crate::workflows::steps::stream_label::StreamLabelStepGenerator;
+    use crate::workflows::steps::timestamp_sanitize::TimestampSanitizeStepGenerator;
+    use std::collections::HashMap;
+
+    async fn get_running_workflows(
+        manager: &UnboundedSender<WorkflowManagerRequest>,
+    ) -> Vec<Arc<String>> {
+        let (sender, receiver) = channel();
+        manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::GetRunningWorkflows {
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send request to workflow manager");
+
+        let mut names = test_utils::expect_oneshot_response(receiver)
+            .await
+            .into_iter()
+            .map(|workflow| workflow.name)
+            .collect::<Vec<_>>();
+
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn server_runs_workflows_and_shuts_down_cleanly() {
+        let config = parse("workflow configured {\n    timestamp_sanitize\n}\n").unwrap();
+        let server = MmidsServerBuilder::new(config)
+            .step("timestamp_sanitize", |_| {
+                Box::new(TimestampSanitizeStepGenerator::new())
+            })
+            .step(
+                "stream_label",
+                |_| Box::new(StreamLabelStepGenerator::new()),
+            )
+            .start()
+            .await
+            .expect("Failed to start server");
+
+        let manager = server.workflow_manager.clone();
+        let reactor_manager = server.channels.reactor_manager.clone();
+        let event_hub = server.channels.event_hub_publisher.clone();
+        let shutdown_token = server.shutdown_token();
+
+        manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::UpsertWorkflow {
+                    definition: WorkflowDefinition {
+                        name: Arc::new("pushed".to_string()),
+                        routed_by_reactor: false,
+                        max_stream_lifetime: None,
+                        max_cached_media_bytes: None,
+                        watcher_limit: None,
+                        steps: vec![WorkflowStepDefinition {
+                            step_type: WorkflowStepType("stream_label".to_string()),
+                            parameters: HashMap::new(),
+                        }],
+                    },
+                    response_channel: None,
+                },
+            })
+            .expect("Failed to send workflow to the workflow manager");
+
+        let workflows = get_running_workflows(&manager).await;
+        assert_eq!(
+            workflows,
+            vec![
+                Arc::new("configured".to_string()),
+                Arc::new("pushed".to_string())
+            ],
+            "Unexpected running workflows"
+        );
+
+        server.shutdown().await;
+
+        assert!(
+            shutdown_token.is_cancelled(),
+            "Expected shutdown token to be cancelled"
+        );
+
+        assert!(manager.is_closed(), "Expected workflow manager to exit");
+        assert!(
+            reactor_manager.is_closed(),
+            "Expected reactor manager to exit"
+        );
+        assert!(event_hub.is_closed(), "Expected event hub to exit");
+    }
+
+    #[tokio::test]
+    async fn duplicate_step_types_fail_to_start() {
+        let config = parse("").unwrap();
+        let result = MmidsServerBuilder::new(config)
+            .step(
+                "stream_label",
+                |_| Box::new(StreamLabelStepGenerator::new()),
+            )
+            .step(
+                "stream_label",
+                |_| Box::new(StreamLabelStepGenerator::new()),
+            )
+            .start()
+            .await;
+
+        match result {
+            Err(ServerStartError::StepRegistrationFailed(_)) => (),
+            Err(error) => panic!("Unexpected error: {:?}", error),
+            Ok(_) => panic!("Expected server to fail to start"),
+        }
+    }
+}
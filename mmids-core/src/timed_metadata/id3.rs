@@ -0,0 +1,153 @@
+//! Minimal ID3v2.4 tag encoding, supporting the text frames used for timed metadata.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+const TAG_HEADER_SIZE: usize = 10;
+const FRAME_HEADER_SIZE: usize = 10;
+const UTF8_ENCODING: u8 = 0x03;
+
+/// A single frame within an ID3 tag
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Id3Frame {
+    /// A text information frame, such as `TIT2` (title) or `TPE1` (artist)
+    Text { id: [u8; 4], value: String },
+
+    /// A user defined text information frame (`TXXX`)
+    UserText { description: String, value: String },
+}
+
+/// Encodes the frames into a complete ID3v2.4 tag. All text is encoded as UTF-8.
+pub fn encode_tag(frames: &[Id3Frame]) -> Bytes {
+    let mut body = BytesMut::new();
+    for frame in frames {
+        let (id, content) = match frame {
+            Id3Frame::Text { id, value } => {
+                let mut content = Vec::with_capacity(value.len() + 1);
+                content.push(UTF8_ENCODING);
+                content.extend_from_slice(value.as_bytes());
+                (*id, content)
+            }
+
+            Id3Frame::UserText { description, value } => {
+                let mut content = Vec::with_capacity(description.len() + value.len() + 2);
+                content.push(UTF8_ENCODING);
+                content.extend_from_slice(description.as_bytes());
+                content.push(0);
+                content.extend_from_slice(value.as_bytes());
+                (*b"TXXX", content)
+            }
+        };
+
+        body.put_slice(&id);
+        body.put_u32(to_syncsafe(content.len() as u32));
+        body.put_u16(0); // no frame flags
+        body.put_slice(&content);
+    }
+
+    let mut tag = BytesMut::with_capacity(TAG_HEADER_SIZE + body.len());
+    tag.put_slice(b"ID3");
+    tag.put_u8(4); // major version
+    tag.put_u8(0); // revision
+    tag.put_u8(0); // no tag flags
+    tag.put_u32(to_syncsafe(body.len() as u32));
+    tag.put_slice(&body);
+
+    tag.freeze()
+}
+
+/// Reads the frames out of an ID3v2.4 tag that was encoded with `encode_tag()`. Returns `None`
+/// if the tag is not valid or contains frames that are not supported.
+pub fn decode_tag(tag: &[u8]) -> Option<Vec<Id3Frame>> {
+    if tag.len() < TAG_HEADER_SIZE || &tag[..3] != b"ID3" || tag[3] != 4 {
+        return None;
+    }
+
+    let size = from_syncsafe(&tag[6..10]) as usize;
+    let mut body = tag.get(TAG_HEADER_SIZE..TAG_HEADER_SIZE + size)?;
+    let mut frames = Vec::new();
+    while body.len() >= FRAME_HEADER_SIZE {
+        let id = [body[0], body[1], body[2], body[3]];
+        let size = from_syncsafe(&body[4..8]) as usize;
+        let content = body.get(FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + size)?;
+        body = &body[FRAME_HEADER_SIZE + size..];
+
+        let (&encoding, text) = content.split_first()?;
+        if encoding != UTF8_ENCODING {
+            return None;
+        }
+
+        let frame = if &id == b"TXXX" {
+            let separator = text.iter().position(|x| *x == 0)?;
+            Id3Frame::UserText {
+                description: String::from_utf8(text[..separator].to_vec()).ok()?,
+                value: String::from_utf8(text[separator + 1..].to_vec()).ok()?,
+            }
+        } else {
+            Id3Frame::Text {
+                id,
+                value: String::from_utf8(text.to_vec()).ok()?,
+            }
+        };
+
+        frames.push(frame);
+    }
+
+    Some(frames)
+}
+
+fn to_syncsafe(value: u32) -> u32 {
+    (value & 0x7F)
+        | ((value & 0x3F80) << 1)
+        | ((value & 0x1F_C000) << 2)
+        | ((value & 0x0FE0_0000) << 3)
+}
+
+fn from_syncsafe(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .take(4)
+        .fold(0, |result, byte| (result << 7) | (*byte as u32 & 0x7F))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_encode_and_decode_tag() {
+        let frames = vec![
+            Id3Frame::Text {
+                id: *b"TIT2",
+                value: "Some Song".to_string(),
+            },
+            Id3Frame::UserText {
+                description: "station".to_string(),
+                value: "KMID".to_string(),
+            },
+        ];
+
+        let tag = encode_tag(&frames);
+        assert_eq!(&tag[..5], b"ID3\x04\x00", "Unexpected tag header");
+
+        let decoded = decode_tag(&tag).expect("Failed to decode tag");
+        assert_eq!(decoded, frames, "Unexpected decoded frames");
+    }
+
+    #[test]
+    fn sizes_are_encoded_as_syncsafe_integers() {
+        let frames = vec![Id3Frame::Text {
+            id: *b"TIT2",
+            value: "a".repeat(200),
+        }];
+
+        let tag = encode_tag(&frames);
+
+        // Frame size of 201 bytes must not have the high bit of any byte set
+        assert_eq!(&tag[14..18], &[0, 0, 1, 73], "Unexpected frame size bytes");
+        assert_eq!(
+            from_syncsafe(&tag[6..10]) as usize,
+            tag.len() - TAG_HEADER_SIZE,
+            "Unexpected tag size"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! Support for carrying timed metadata (such as now-playing information) in band with media
+//! streams. Timed metadata is sourced from `Metadata` media notifications, converted into ID3
+//! tags based on a configurable mapping of metadata keys to ID3 frames, and can then be placed
+//! into an MPEG-TS stream as timed ID3 PES packets that players read alongside the audio and
+//! video.
+
+pub mod id3;
+pub mod mpegts;
+
+use crate::timed_metadata::id3::Id3Frame;
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Maps metadata keys to the ID3 frames their values should be written to.
+///
+/// A mapping is defined as a comma separated list of `<metadata key>:<frame id>` entries, such as
+/// `title:TIT2,artist:TPE1`. The frame id must be an ID3 text information frame. If the frame id
+/// is left off (e.g. just `station`) then the value is written to a user defined text (`TXXX`)
+/// frame, with the metadata key used as the frame's description.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Id3KeyMap {
+    entries: Vec<(String, Option<[u8; 4]>)>,
+}
+
+/// Errors that can occur when parsing an ID3 key mapping
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum Id3KeyMapError {
+    #[error("No metadata keys were specified")]
+    NoKeysSpecified,
+
+    #[error("Mapping entry '{0}' does not have a metadata key")]
+    EmptyMetadataKey(String),
+
+    #[error("'{0}' is not a valid ID3 text information frame id")]
+    InvalidFrameId(String),
+}
+
+impl Id3KeyMap {
+    /// Parses a key mapping from its textual form
+    pub fn parse(mapping: &str) -> Result<Self, Id3KeyMapError> {
+        let mut entries = Vec::new();
+        for entry in mapping
+            .split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+        {
+            let (key, frame_id) = match entry.split_once(':') {
+                Some((key, frame_id)) => (key.trim(), Some(frame_id.trim())),
+                None => (entry, None),
+            };
+
+            if key.is_empty() {
+                return Err(Id3KeyMapError::EmptyMetadataKey(entry.to_string()));
+            }
+
+            let frame_id = match frame_id {
+                Some(frame_id) => Some(parse_text_frame_id(frame_id)?),
+                None => None,
+            };
+
+            entries.push((key.to_string(), frame_id));
+        }
+
+        if entries.is_empty() {
+            return Err(Id3KeyMapError::NoKeysSpecified);
+        }
+
+        Ok(Id3KeyMap { entries })
+    }
+
+    /// Creates the ID3 frames for any mapped keys contained within the metadata. An empty list is
+    /// returned if the metadata does not contain any of the mapped keys.
+    pub fn frames_for(&self, metadata: &HashMap<String, String>) -> Vec<Id3Frame> {
+        self.entries
+            .iter()
+            .filter_map(|(key, frame_id)| {
+                let value = metadata.get(key)?;
+                let frame = match frame_id {
+                    Some(id) => Id3Frame::Text {
+                        id: *id,
+                        value: value.clone(),
+                    },
+
+                    None => Id3Frame::UserText {
+                        description: key.clone(),
+                        value: value.clone(),
+                    },
+                };
+
+                Some(frame)
+            })
+            .collect()
+    }
+}
+
+fn parse_text_frame_id(frame_id: &str) -> Result<[u8; 4], Id3KeyMapError> {
+    let bytes = frame_id.as_bytes();
+    let is_valid = bytes.len() == 4
+        && bytes[0] == b'T'
+        && bytes
+            .iter()
+            .all(|x| x.is_ascii_uppercase() || x.is_ascii_digit())
+        && frame_id != "TXXX";
+
+    if !is_valid {
+        return Err(Id3KeyMapError::InvalidFrameId(frame_id.to_string()));
+    }
+
+    Ok([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_mapping_with_frame_ids_and_user_text_entries() {
+        let map = Id3KeyMap::parse("title:TIT2, artist:TPE1,station").unwrap();
+        let mut metadata = HashMap::new();
+        metadata.insert("title".to_string(), "Song".to_string());
+        metadata.insert("station".to_string(), "KMID".to_string());
+        metadata.insert("other".to_string(), "ignored".to_string());
+
+        let frames = map.frames_for(&metadata);
+        assert_eq!(
+            frames,
+            vec![
+                Id3Frame::Text {
+                    id: *b"TIT2",
+                    value: "Song".to_string(),
+                },
+                Id3Frame::UserText {
+                    description: "station".to_string(),
+                    value: "KMID".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_frame_ids_are_rejected() {
+        for frame_id in ["APIC", "TIT", "tit2", "TXXX"] {
+            let result = Id3KeyMap::parse(&format!("title:{}", frame_id));
+            assert_eq!(
+                result,
+                Err(Id3KeyMapError::InvalidFrameId(frame_id.to_string())),
+                "Unexpected result for {}",
+                frame_id
+            );
+        }
+    }
+
+    #[test]
+    fn empty_mapping_is_rejected() {
+        assert_eq!(
+            Id3KeyMap::parse(" , "),
+            Err(Id3KeyMapError::NoKeysSpecified)
+        );
+    }
+}
@@ -0,0 +1,374 @@
+//! Writes timed ID3 metadata as a standalone MPEG-TS stream, following the timed metadata format
+//! used for HTTP live streaming. The stream contains a single program with one metadata
+//! elementary stream (stream type 0x15), and each ID3 tag is written as its own PES packet whose
+//! PTS places it on the media timeline.
+//!
+//! The resulting stream is meant to be muxed alongside the audio and video of the same stream
+//! (e.g. as an additional ffmpeg input), so PTS values are expected to be on the same timeline as
+//! the media's timestamps.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use std::time::Duration;
+
+/// The PID the program map table is written to
+pub const PMT_PID: u16 = 0x1000;
+
+/// The PID timed ID3 PES packets are written to
+pub const METADATA_PID: u16 = 0x0100;
+
+/// The MPEG-TS stream type of timed ID3 metadata carried in PES packets
+pub const METADATA_STREAM_TYPE: u8 = 0x15;
+
+const PACKET_SIZE: usize = 188;
+const PACKET_HEADER_SIZE: usize = 4;
+const PAYLOAD_SIZE: usize = PACKET_SIZE - PACKET_HEADER_SIZE;
+const SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0x0000;
+const NO_PCR_PID: u16 = 0x1FFF;
+const PROGRAM_NUMBER: u16 = 1;
+const PRIVATE_STREAM_1: u8 = 0xBD;
+const PTS_MASK: u64 = (1 << 33) - 1;
+
+/// Produces MPEG-TS packets for a stream of timed ID3 tags
+pub struct TimedMetadataTsWriter {
+    pat_continuity_counter: u8,
+    pmt_continuity_counter: u8,
+    metadata_continuity_counter: u8,
+}
+
+impl Default for TimedMetadataTsWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TimedMetadataTsWriter {
+    pub fn new() -> Self {
+        TimedMetadataTsWriter {
+            pat_continuity_counter: 0,
+            pmt_continuity_counter: 0,
+            metadata_continuity_counter: 0,
+        }
+    }
+
+    /// Creates the program association and program map tables, which must be sent before any
+    /// ID3 tags so readers know how to interpret the metadata PID.
+    pub fn write_tables(&mut self) -> Bytes {
+        let mut output = BytesMut::with_capacity(PACKET_SIZE * 2);
+        write_section(
+            &mut output,
+            PAT_PID,
+            &mut self.pat_continuity_counter,
+            &program_association_section(),
+        );
+
+        write_section(
+            &mut output,
+            PMT_PID,
+            &mut self.pmt_continuity_counter,
+            &program_map_section(),
+        );
+
+        output.freeze()
+    }
+
+    /// Wraps an ID3 tag in a PES packet with the specified presentation timestamp, and splits it
+    /// into MPEG-TS packets.
+    pub fn write_id3(&mut self, pts: Duration, tag: &[u8]) -> Bytes {
+        let pes = pes_packet(pts, tag);
+        let chunks = pes.chunks(PAYLOAD_SIZE);
+        let mut output = BytesMut::with_capacity(chunks.len() * PACKET_SIZE);
+        for (index, chunk) in chunks.enumerate() {
+            write_packet(
+                &mut output,
+                METADATA_PID,
+                index == 0,
+                &mut self.metadata_continuity_counter,
+                chunk,
+            );
+        }
+
+        output.freeze()
+    }
+}
+
+/// Converts a timestamp into a 33 bit, 90khz MPEG timestamp
+pub fn to_mpeg_timestamp(timestamp: Duration) -> u64 {
+    (timestamp.as_micros() as u64 * 9 / 100) & PTS_MASK
+}
+
+fn pes_packet(pts: Duration, payload: &[u8]) -> Vec<u8> {
+    const HEADER_DATA_LENGTH: u8 = 5;
+
+    // Bytes after the packet length field
+    let remaining_length = 3 + HEADER_DATA_LENGTH as usize + payload.len();
+    let packet_length = if remaining_length > u16::MAX as usize {
+        0 // unbounded
+    } else {
+        remaining_length as u16
+    };
+
+    let pts = to_mpeg_timestamp(pts);
+    let mut pes = Vec::with_capacity(6 + remaining_length);
+    pes.extend_from_slice(&[0x00, 0x00, 0x01, PRIVATE_STREAM_1]);
+    pes.put_u16(packet_length);
+    pes.put_u8(0x84); // marker bits and data alignment indicator
+    pes.put_u8(0x80); // PTS only
+    pes.put_u8(HEADER_DATA_LENGTH);
+    pes.put_u8(0x21 | (((pts >> 30) & 0x07) as u8) << 1);
+    pes.put_u16(0x0001 | (((pts >> 15) & 0x7FFF) as u16) << 1);
+    pes.put_u16(0x0001 | ((pts & 0x7FFF) as u16) << 1);
+    pes.extend_from_slice(payload);
+
+    pes
+}
+
+fn program_association_section() -> Vec<u8> {
+    let mut section = Vec::new();
+    section.put_u16(PROGRAM_NUMBER); // transport stream id
+    section.put_u8(0xC1); // version 0, current
+    section.put_u8(0); // section number
+    section.put_u8(0); // last section number
+    section.put_u16(PROGRAM_NUMBER);
+    section.put_u16(0xE000 | PMT_PID);
+
+    finish_section(0x00, section)
+}
+
+fn program_map_section() -> Vec<u8> {
+    // Metadata descriptor signaling that the stream contains ID3 tags
+    let mut descriptor = vec![0x26, 13];
+    descriptor.put_u16(0xFFFF); // metadata application format, defined by the identifier
+    descriptor.extend_from_slice(b"ID3 ");
+    descriptor.put_u8(0xFF); // metadata format, defined by the identifier
+    descriptor.extend_from_slice(b"ID3 ");
+    descriptor.put_u8(0); // metadata service id
+    descriptor.put_u8(0x0F); // no decoder config or DSM-CC
+
+    let mut section = Vec::new();
+    section.put_u16(PROGRAM_NUMBER);
+    section.put_u8(0xC1); // version 0, current
+    section.put_u8(0); // section number
+    section.put_u8(0); // last section number
+    section.put_u16(0xE000 | NO_PCR_PID);
+    section.put_u16(0xF000); // no program info
+    section.put_u8(METADATA_STREAM_TYPE);
+    section.put_u16(0xE000 | METADATA_PID);
+    section.put_u16(0xF000 | descriptor.len() as u16);
+    section.extend_from_slice(&descriptor);
+
+    finish_section(0x02, section)
+}
+
+/// Adds the table header and CRC around the body of a PSI section
+fn finish_section(table_id: u8, body: Vec<u8>) -> Vec<u8> {
+    let section_length = body.len() + 4; // includes the crc
+    let mut section = Vec::with_capacity(3 + section_length);
+    section.put_u8(table_id);
+    section.put_u16(0xB000 | section_length as u16);
+    section.extend_from_slice(&body);
+
+    let crc = crc32_mpeg2(&section);
+    section.put_u32(crc);
+
+    section
+}
+
+fn write_section(output: &mut BytesMut, pid: u16, continuity_counter: &mut u8, section: &[u8]) {
+    let mut payload = Vec::with_capacity(PAYLOAD_SIZE);
+    payload.put_u8(0); // pointer field
+    payload.extend_from_slice(section);
+    payload.resize(PAYLOAD_SIZE, 0xFF);
+
+    write_packet(output, pid, true, continuity_counter, &payload);
+}
+
+fn write_packet(
+    output: &mut BytesMut,
+    pid: u16,
+    payload_unit_start: bool,
+    continuity_counter: &mut u8,
+    payload: &[u8],
+) {
+    let start_flag = if payload_unit_start { 0x4000 } else { 0 };
+    output.put_u8(SYNC_BYTE);
+    output.put_u16(start_flag | (pid & 0x1FFF));
+
+    if payload.len() < PAYLOAD_SIZE {
+        // Pad out the packet with an adaptation field of stuffing bytes
+        let adaptation_length = PAYLOAD_SIZE - payload.len() - 1;
+        output.put_u8(0x30 | *continuity_counter);
+        output.put_u8(adaptation_length as u8);
+        if adaptation_length > 0 {
+            output.put_u8(0); // no adaptation field flags
+            output.put_bytes(0xFF, adaptation_length - 1);
+        }
+    } else {
+        output.put_u8(0x10 | *continuity_counter);
+    }
+
+    output.put_slice(payload);
+    *continuity_counter = (*continuity_counter + 1) & 0x0F;
+}
+
+fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timed_metadata::id3::{decode_tag, encode_tag, Id3Frame};
+
+    struct Packet {
+        pid: u16,
+        payload_unit_start: bool,
+        continuity_counter: u8,
+        payload: Vec<u8>,
+    }
+
+    fn parse_packets(data: &[u8]) -> Vec<Packet> {
+        assert_eq!(data.len() % PACKET_SIZE, 0, "Output was not whole packets");
+
+        data.chunks(PACKET_SIZE)
+            .map(|packet| {
+                assert_eq!(packet[0], SYNC_BYTE, "Packet did not start with sync byte");
+
+                let adaptation_control = (packet[3] >> 4) & 0x03;
+                let payload_start = match adaptation_control {
+                    0x01 => PACKET_HEADER_SIZE,
+                    0x03 => PACKET_HEADER_SIZE + 1 + packet[4] as usize,
+                    x => panic!("Unexpected adaptation field control of {}", x),
+                };
+
+                Packet {
+                    pid: (((packet[1] & 0x1F) as u16) << 8) | packet[2] as u16,
+                    payload_unit_start: packet[1] & 0x40 != 0,
+                    continuity_counter: packet[3] & 0x0F,
+                    payload: packet[payload_start..].to_vec(),
+                }
+            })
+            .collect()
+    }
+
+    fn section_from(packet: &Packet) -> &[u8] {
+        assert!(
+            packet.payload_unit_start,
+            "Section packet did not start unit"
+        );
+        let pointer = packet.payload[0] as usize;
+        let section = &packet.payload[1 + pointer..];
+        let length = ((((section[1] & 0x0F) as usize) << 8) | section[2] as usize) + 3;
+        let section = &section[..length];
+        assert_eq!(crc32_mpeg2(section), 0, "Section had an invalid CRC");
+
+        section
+    }
+
+    fn parse_pts(pes: &[u8]) -> u64 {
+        assert_eq!(pes[7] & 0x80, 0x80, "PES did not have a PTS");
+        let bytes = &pes[9..14];
+        (((bytes[0] >> 1) & 0x07) as u64) << 30
+            | (((bytes[1] as u64) << 7) | (bytes[2] >> 1) as u64) << 15
+            | (((bytes[3] as u64) << 7) | (bytes[4] >> 1) as u64)
+    }
+
+    #[test]
+    fn tables_declare_timed_id3_stream() {
+        let mut writer = TimedMetadataTsWriter::new();
+        let packets = parse_packets(&writer.write_tables());
+        assert_eq!(packets.len(), 2, "Unexpected number of packets");
+
+        let pat = section_from(&packets[0]);
+        assert_eq!(packets[0].pid, PAT_PID, "Unexpected PAT pid");
+        assert_eq!(pat[0], 0x00, "Unexpected PAT table id");
+        let pmt_pid = (((pat[10] & 0x1F) as u16) << 8) | pat[11] as u16;
+        assert_eq!(pmt_pid, PMT_PID, "Unexpected PMT pid in PAT");
+
+        let pmt = section_from(&packets[1]);
+        assert_eq!(packets[1].pid, PMT_PID, "Unexpected PMT pid");
+        assert_eq!(pmt[0], 0x02, "Unexpected PMT table id");
+        assert_eq!(pmt[12], METADATA_STREAM_TYPE, "Unexpected stream type");
+
+        let stream_pid = (((pmt[13] & 0x1F) as u16) << 8) | pmt[14] as u16;
+        assert_eq!(stream_pid, METADATA_PID, "Unexpected metadata pid");
+        assert_eq!(&pmt[21..25], b"ID3 ", "Missing ID3 metadata descriptor");
+    }
+
+    #[test]
+    fn id3_tags_are_written_at_requested_pts() {
+        let mut writer = TimedMetadataTsWriter::new();
+        let mut output = BytesMut::new();
+        output.put_slice(&writer.write_tables());
+
+        let first_tag = encode_tag(&[Id3Frame::Text {
+            id: *b"TIT2",
+            value: "First Song".to_string(),
+        }]);
+
+        // Large enough to need multiple packets
+        let second_tag = encode_tag(&[Id3Frame::UserText {
+            description: "notes".to_string(),
+            value: "b".repeat(500),
+        }]);
+
+        output.put_slice(&writer.write_id3(Duration::from_millis(1500), &first_tag));
+        output.put_slice(&writer.write_id3(Duration::from_secs(60), &second_tag));
+
+        let packets = parse_packets(&output);
+        let mut pes_packets = Vec::new();
+        let mut expected_counter = 0;
+        for packet in packets.iter().filter(|p| p.pid == METADATA_PID) {
+            assert_eq!(
+                packet.continuity_counter, expected_counter,
+                "Unexpected continuity counter"
+            );
+
+            expected_counter = (expected_counter + 1) & 0x0F;
+            if packet.payload_unit_start {
+                pes_packets.push(Vec::new());
+            }
+
+            pes_packets
+                .last_mut()
+                .expect("Metadata packet before start of PES")
+                .extend_from_slice(&packet.payload);
+        }
+
+        assert_eq!(pes_packets.len(), 2, "Unexpected number of PES packets");
+
+        let expected = [
+            (1500 * 90, first_tag.clone()),
+            (60_000 * 90, second_tag.clone()),
+        ];
+
+        for (pes, (expected_pts, expected_tag)) in pes_packets.iter().zip(expected.iter()) {
+            assert_eq!(&pes[..4], &[0, 0, 1, PRIVATE_STREAM_1], "Bad PES start");
+            assert_eq!(parse_pts(pes), *expected_pts, "Unexpected PTS");
+
+            let length = ((pes[4] as usize) << 8) | pes[5] as usize;
+            let tag = &pes[14..6 + length];
+            assert_eq!(tag, &expected_tag[..], "Unexpected ID3 tag in PES");
+            assert!(decode_tag(tag).is_some(), "ID3 tag could not be decoded");
+        }
+    }
+
+    #[test]
+    fn pts_wraps_at_33_bits() {
+        // 95,444 seconds is 8,589,960,000 ticks, which is 25,408 ticks past 2^33
+        let timestamp = Duration::from_secs(95_444);
+        assert_eq!(to_mpeg_timestamp(timestamp), 25_408);
+    }
+}
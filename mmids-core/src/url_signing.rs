@@ -0,0 +1,319 @@
+//! Signs URLs with expiring tokens, so content (such as HLS segments) can only be retrieved
+//! through URLs that were handed out recently. This prevents deep linking and hot linking of
+//! content from other sites, since copied URLs stop working once their token expires.
+//!
+//! A signed URL carries an `expires` query parameter with the unix timestamp (in seconds) the URL
+//! is valid until, and a `token` query parameter with a hex encoded HMAC-SHA256 of the resource
+//! and expiry time. The resource is the URL's path as it was signed, without any query string.
+//! Playlists reference segments with relative URIs, so for playlists the resource is the URI
+//! relative to the playlist, and the component serving the content must validate requests against
+//! the same relative path.
+
+use crate::object_storage::signing::{hex, hmac_sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+pub const EXPIRES_PARAMETER: &str = "expires";
+pub const TOKEN_PARAMETER: &str = "token";
+
+/// Creates and validates expiring tokens for URLs
+#[derive(Clone)]
+pub struct UrlSigner {
+    key: Vec<u8>,
+    ttl: Duration,
+}
+
+/// Reasons a URL's token may not be valid
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum UrlValidationError {
+    #[error(
+        "The url does not have an {} and {} parameter",
+        EXPIRES_PARAMETER,
+        TOKEN_PARAMETER
+    )]
+    MissingToken,
+
+    #[error(
+        "The url's {} parameter of '{0}' is not a valid timestamp",
+        EXPIRES_PARAMETER
+    )]
+    InvalidExpiry(String),
+
+    #[error("The url expired")]
+    Expired,
+
+    #[error("The url's token does not match")]
+    InvalidToken,
+}
+
+impl UrlSigner {
+    /// Creates a signer that signs URLs with the specified key, which are valid for the `ttl`
+    /// from when they were signed.
+    pub fn new(key: &[u8], ttl: Duration) -> Self {
+        UrlSigner {
+            key: key.to_vec(),
+            ttl,
+        }
+    }
+
+    /// How long signed URLs are valid for
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Returns the query string (without a leading `?`) that needs to be added to the URL of the
+    /// specified resource for it to be valid until `ttl` after `now`.
+    pub fn sign(&self, resource: &str, now: SystemTime) -> String {
+        let expires = unix_seconds(now + self.ttl);
+        format!(
+            "{}={}&{}={}",
+            EXPIRES_PARAMETER,
+            expires,
+            TOKEN_PARAMETER,
+            self.token(resource, expires)
+        )
+    }
+
+    /// Adds a token to the uri, keeping any query string it already has. The uri's path is what's
+    /// signed.
+    pub fn sign_uri(&self, uri: &str, now: SystemTime) -> String {
+        let (resource, separator) = match uri.split_once('?') {
+            Some((resource, _)) => (resource, '&'),
+            None => (uri, '?'),
+        };
+
+        format!("{}{}{}", uri, separator, self.sign(resource, now))
+    }
+
+    /// Rewrites an HLS playlist so every URI it references (segments, initialization sections,
+    /// variant playlists, etc...) carries a token. URIs are otherwise left as is, so relative
+    /// URIs remain relative to the playlist.
+    pub fn sign_playlist(&self, playlist: &str, now: SystemTime) -> String {
+        let mut signed = playlist
+            .lines()
+            .map(|line| {
+                let trimmed = line.trim();
+                if trimmed.is_empty() {
+                    line.to_string()
+                } else if trimmed.starts_with('#') {
+                    self.sign_uri_attributes(line, now)
+                } else {
+                    self.sign_uri(trimmed, now)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        if playlist.ends_with('\n') {
+            signed.push('\n');
+        }
+
+        signed
+    }
+
+    /// Checks that the query string of a request for the specified resource has a token that's
+    /// valid at `now`.
+    pub fn validate(
+        &self,
+        resource: &str,
+        query: Option<&str>,
+        now: SystemTime,
+    ) -> Result<(), UrlValidationError> {
+        let mut expires = None;
+        let mut token = None;
+        for pair in query.unwrap_or_default().split('&') {
+            match pair.split_once('=') {
+                Some((EXPIRES_PARAMETER, value)) => expires = Some(value),
+                Some((TOKEN_PARAMETER, value)) => token = Some(value),
+                _ => (),
+            }
+        }
+
+        let (expires, token) = match (expires, token) {
+            (Some(expires), Some(token)) => (expires, token),
+            _ => return Err(UrlValidationError::MissingToken),
+        };
+
+        let expires = expires
+            .parse::<u64>()
+            .map_err(|_| UrlValidationError::InvalidExpiry(expires.to_string()))?;
+
+        if !constant_time_eq(self.token(resource, expires).as_bytes(), token.as_bytes()) {
+            return Err(UrlValidationError::InvalidToken);
+        }
+
+        if unix_seconds(now) >= expires {
+            return Err(UrlValidationError::Expired);
+        }
+
+        Ok(())
+    }
+
+    fn token(&self, resource: &str, expires: u64) -> String {
+        let message = format!("{}\n{}", resource, expires);
+        hex(&hmac_sha256(&self.key, message.as_bytes()))
+    }
+
+    fn sign_uri_attributes(&self, line: &str, now: SystemTime) -> String {
+        const URI_ATTRIBUTE: &str = "URI=\"";
+
+        let mut result = String::with_capacity(line.len());
+        let mut remaining = line;
+        while let Some(start) = remaining.find(URI_ATTRIBUTE) {
+            let (before, after) = remaining.split_at(start + URI_ATTRIBUTE.len());
+            let end = match after.find('"') {
+                Some(end) => end,
+                None => break,
+            };
+
+            result.push_str(before);
+            result.push_str(&self.sign_uri(&after[..end], now));
+            remaining = &after[end..];
+        }
+
+        result.push_str(remaining);
+        result
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn constant_time_eq(first: &[u8], second: &[u8]) -> bool {
+    first.len() == second.len()
+        && first
+            .iter()
+            .zip(second)
+            .fold(0, |result, (x, y)| result | (x ^ y))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer() -> UrlSigner {
+        UrlSigner::new(b"secret", Duration::from_secs(60))
+    }
+
+    fn query_of(uri: &str) -> Option<&str> {
+        uri.split_once('?').map(|(_, query)| query)
+    }
+
+    #[test]
+    fn signed_uri_valid_until_it_expires() {
+        let signer = signer();
+        let now = SystemTime::now();
+        let uri = signer.sign_uri("abc0.ts", now);
+
+        assert_eq!(
+            signer.validate("abc0.ts", query_of(&uri), now),
+            Ok(()),
+            "Expected uri to be valid when signed"
+        );
+
+        assert_eq!(
+            signer.validate("abc0.ts", query_of(&uri), now + Duration::from_secs(61)),
+            Err(UrlValidationError::Expired),
+            "Expected uri to be expired after the ttl"
+        );
+    }
+
+    #[test]
+    fn token_not_valid_for_other_resources() {
+        let signer = signer();
+        let now = SystemTime::now();
+        let uri = signer.sign_uri("abc0.ts", now);
+
+        assert_eq!(
+            signer.validate("abc1.ts", query_of(&uri), now),
+            Err(UrlValidationError::InvalidToken),
+            "Expected token to be rejected for a different resource"
+        );
+    }
+
+    #[test]
+    fn token_not_valid_with_different_key() {
+        let now = SystemTime::now();
+        let uri = signer().sign_uri("abc0.ts", now);
+        let other = UrlSigner::new(b"other", Duration::from_secs(60));
+
+        assert_eq!(
+            other.validate("abc0.ts", query_of(&uri), now),
+            Err(UrlValidationError::InvalidToken),
+            "Expected token to be rejected with a different key"
+        );
+    }
+
+    #[test]
+    fn extended_expiry_is_rejected() {
+        let signer = signer();
+        let now = SystemTime::now();
+        let signed = signer.sign("abc0.ts", now);
+        let (_, token) = signed.split_once('&').unwrap();
+        let query = format!("{}={}&{}", EXPIRES_PARAMETER, u64::MAX, token);
+
+        assert_eq!(
+            signer.validate("abc0.ts", Some(&query), now),
+            Err(UrlValidationError::InvalidToken),
+            "Expected modified expiry to be rejected"
+        );
+    }
+
+    #[test]
+    fn missing_token_is_rejected() {
+        assert_eq!(
+            signer().validate("abc0.ts", None, SystemTime::now()),
+            Err(UrlValidationError::MissingToken),
+            "Expected missing token to be rejected"
+        );
+    }
+
+    #[test]
+    fn playlist_uris_signed_while_remaining_relative() {
+        let signer = signer();
+        let now = SystemTime::now();
+        let playlist = "#EXTM3U\n\
+            #EXT-X-TARGETDURATION:2\n\
+            #EXT-X-MAP:URI=\"init.mp4\"\n\
+            #EXTINF:2.000000,\n\
+            abc0.ts\n\
+            #EXTINF:2.000000,\n\
+            abc1.ts?x=1\n";
+
+        let signed = signer.sign_playlist(playlist, now);
+        let lines = signed.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 7, "Unexpected number of lines");
+        assert_eq!(lines[0], "#EXTM3U", "Unexpected header");
+        assert_eq!(lines[1], "#EXT-X-TARGETDURATION:2", "Unexpected tag");
+        assert_eq!(
+            lines[2],
+            format!(
+                "#EXT-X-MAP:URI=\"init.mp4?{}\"",
+                signer.sign("init.mp4", now)
+            ),
+            "Unexpected map tag"
+        );
+
+        assert_eq!(
+            lines[4],
+            format!("abc0.ts?{}", signer.sign("abc0.ts", now)),
+            "Unexpected first segment"
+        );
+
+        assert_eq!(
+            lines[6],
+            format!("abc1.ts?x=1&{}", signer.sign("abc1.ts", now)),
+            "Unexpected second segment"
+        );
+
+        assert!(
+            signed.ends_with('\n'),
+            "Expected trailing newline to remain"
+        );
+    }
+}
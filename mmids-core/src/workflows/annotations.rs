@@ -0,0 +1,134 @@
+//! Pipeline annotations are transient values attached to individual media notifications as they
+//! flow through a workflow, allowing steps to coordinate with each other (e.g. to measure latency
+//! from ingest to output) without each feature needing its own side channel.
+//!
+//! Annotations are keyed by their type, so each annotation is its own type defined by the module
+//! that owns it. They are internal to the mmids instance the media is flowing through: they are
+//! never serialized (so they don't cross to other mmids instances), and are ignored when media
+//! notifications are compared for equality.
+//!
+//! Ownership rules:
+//! * Only the step (or component) that defines an annotation type sets it. Any step can read it.
+//! * Ingest steps set `ArrivalTime` on media they raise into a workflow, and sinks read it.
+//! * Steps that pass media through do so with the same notification, so its annotations carry on
+//!   to later steps. Media created by a step (e.g. transcoded media) starts without annotations,
+//!   unless the step copies over the ones that still apply.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Type keyed map of transient values attached to a media notification. Most notifications carry
+/// no annotations, so the map is only allocated once the first annotation is inserted, and it's
+/// shared between clones of the notification until one of them modifies it.
+#[derive(Clone, Default)]
+pub struct PipelineAnnotations {
+    values: Option<Arc<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>>,
+}
+
+/// When the media first arrived into the mmids instance. Set by ingest steps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArrivalTime(pub Instant);
+
+impl PipelineAnnotations {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the annotation of the value's type, replacing any existing annotation of that type
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) {
+        let values = self.values.get_or_insert_with(Default::default);
+        Arc::make_mut(values).insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// Gets the annotation of the specified type, if one has been set
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.values
+            .as_ref()
+            .and_then(|values| values.get(&TypeId::of::<T>()))
+            .and_then(|value| value.downcast_ref::<T>())
+    }
+
+    /// Removes the annotation of the specified type
+    pub fn remove<T: Any + Send + Sync>(&mut self) {
+        if let Some(values) = self.values.as_mut() {
+            Arc::make_mut(values).remove(&TypeId::of::<T>());
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.as_ref().map_or(0, |values| values.len())
+    }
+}
+
+impl Debug for PipelineAnnotations {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PipelineAnnotations({} values)", self.len())
+    }
+}
+
+/// Annotations are transient, so they never make two notifications different
+impl PartialEq for PipelineAnnotations {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for PipelineAnnotations {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct TestAnnotation(u32);
+
+    #[test]
+    fn annotation_retrieved_by_type() {
+        let mut annotations = PipelineAnnotations::new();
+        annotations.insert(TestAnnotation(5));
+
+        assert_eq!(
+            annotations.get::<TestAnnotation>(),
+            Some(&TestAnnotation(5)),
+            "Unexpected annotation"
+        );
+        assert_eq!(
+            annotations.get::<ArrivalTime>(),
+            None,
+            "Expected no arrival time"
+        );
+    }
+
+    #[test]
+    fn inserting_same_type_replaces_annotation() {
+        let mut annotations = PipelineAnnotations::new();
+        annotations.insert(TestAnnotation(5));
+        annotations.insert(TestAnnotation(6));
+
+        assert_eq!(annotations.len(), 1, "Unexpected number of annotations");
+        assert_eq!(
+            annotations.get::<TestAnnotation>(),
+            Some(&TestAnnotation(6)),
+            "Unexpected annotation"
+        );
+    }
+
+    #[test]
+    fn annotations_ignored_for_equality() {
+        let mut annotations = PipelineAnnotations::new();
+        annotations.insert(TestAnnotation(5));
+
+        assert_eq!(
+            annotations,
+            PipelineAnnotations::new(),
+            "Expected annotations to be equal"
+        );
+    }
+}
@@ -0,0 +1,325 @@
+//! Logic for determining the media a watcher needs when it joins a stream that's already in
+//! progress. A watcher that only receives media from the point it joined can't decode any video
+//! until the next keyframe arrives, and without sequence headers it can't decode anything at all.
+//!
+//! The bootstrap sequence for a new watcher is always the latest video sequence header, the latest
+//! audio sequence header, and then the most recent video keyframe (if it was encoded with the
+//! latest video sequence header). All media received after the keyframe can then be sent as
+//! normal. Output steps and endpoints should use `WatcherBootstrap` to track this instead of
+//! managing it themselves.
+
+use crate::workflows::metadata::{MetadataKey, MetadataValue};
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+
+/// How a piece of media relates to bootstrapping a new watcher
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootstrapMediaKind {
+    VideoSequenceHeader,
+    AudioSequenceHeader,
+    VideoKeyframe,
+
+    /// Any media that isn't needed to bootstrap a watcher
+    Other,
+}
+
+/// Tracks the media needed to bootstrap new watchers of a single stream. This is generic over
+/// the type of media so it can be used with both media notifications and endpoint specific media
+/// types.
+#[derive(Clone, Debug)]
+pub struct WatcherBootstrap<T> {
+    video_sequence_header: Option<T>,
+    audio_sequence_header: Option<T>,
+    last_keyframe: Option<T>,
+}
+
+impl<T> Default for WatcherBootstrap<T> {
+    fn default() -> Self {
+        WatcherBootstrap {
+            video_sequence_header: None,
+            audio_sequence_header: None,
+            last_keyframe: None,
+        }
+    }
+}
+
+impl<T: Clone> WatcherBootstrap<T> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Updates the bootstrap state with media that has been sent to the stream's watchers
+    pub fn track(&mut self, media: &T, kind: BootstrapMediaKind) {
+        match kind {
+            BootstrapMediaKind::VideoSequenceHeader => {
+                self.video_sequence_header = Some(media.clone());
+
+                // Keyframes encoded before a new sequence header may not be decodable with it
+                self.last_keyframe = None;
+            }
+
+            BootstrapMediaKind::AudioSequenceHeader => {
+                self.audio_sequence_header = Some(media.clone());
+            }
+
+            BootstrapMediaKind::VideoKeyframe => {
+                self.last_keyframe = Some(media.clone());
+            }
+
+            BootstrapMediaKind::Other => (),
+        }
+    }
+
+    /// Forgets all tracked media, such as when the stream's publisher goes away
+    pub fn clear(&mut self) {
+        self.video_sequence_header = None;
+        self.audio_sequence_header = None;
+        self.last_keyframe = None;
+    }
+
+    /// Returns the media that should be sent to a newly joined watcher, in the order it should
+    /// be sent.
+    pub fn bootstrap_sequence(&self) -> Vec<T> {
+        let mut sequence = Vec::with_capacity(3);
+        if let Some(header) = &self.video_sequence_header {
+            sequence.push(header.clone());
+        }
+
+        if let Some(header) = &self.audio_sequence_header {
+            sequence.push(header.clone());
+        }
+
+        if let Some(keyframe) = &self.last_keyframe {
+            sequence.push(keyframe.clone());
+        }
+
+        sequence
+    }
+}
+
+impl WatcherBootstrap<MediaNotification> {
+    /// Creates a bootstrap state from media cached by the workflow runner for a stream, along
+    /// with the last keyframe of the stream, if one has been buffered.
+    pub fn from_cache(
+        cached_media: &[MediaNotification],
+        last_keyframe: Option<&MediaNotification>,
+        is_keyframe_metadata_key: MetadataKey,
+    ) -> Self {
+        let mut bootstrap = WatcherBootstrap::new();
+        for media in cached_media.iter().chain(last_keyframe) {
+            let kind = classify_media(&media.content, is_keyframe_metadata_key);
+            bootstrap.track(media, kind);
+        }
+
+        bootstrap
+    }
+}
+
+/// Determines how a media notification relates to bootstrapping new watchers
+pub fn classify_media(
+    content: &MediaNotificationContent,
+    is_keyframe_metadata_key: MetadataKey,
+) -> BootstrapMediaKind {
+    match content {
+        MediaNotificationContent::MediaPayload {
+            media_type,
+            is_required_for_decoding,
+            metadata,
+            ..
+        } => match (media_type, is_required_for_decoding) {
+            (MediaType::Video, true) => BootstrapMediaKind::VideoSequenceHeader,
+            (MediaType::Audio, true) => BootstrapMediaKind::AudioSequenceHeader,
+            (MediaType::Video, false) => {
+                let is_keyframe = metadata
+                    .iter()
+                    .filter(|m| m.key() == is_keyframe_metadata_key)
+                    .filter_map(|m| match m.value() {
+                        MetadataValue::Bool(val) => Some(val),
+                        _ => None,
+                    })
+                    .next()
+                    .unwrap_or_default();
+
+                if is_keyframe {
+                    BootstrapMediaKind::VideoKeyframe
+                } else {
+                    BootstrapMediaKind::Other
+                }
+            }
+
+            _ => BootstrapMediaKind::Other,
+        },
+
+        _ => BootstrapMediaKind::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::{AUDIO_CODEC_AAC_RAW, VIDEO_CODEC_H264_AVC};
+    use crate::workflows::metadata::common_metadata::get_is_keyframe_metadata_key;
+    use crate::workflows::metadata::{
+        MediaPayloadMetadataCollection, MetadataEntry, MetadataKeyMap,
+    };
+    use crate::StreamId;
+    use bytes::{Bytes, BytesMut};
+    use std::iter;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    struct TestContext {
+        is_keyframe_metadata_key: MetadataKey,
+    }
+
+    impl TestContext {
+        fn new() -> Self {
+            let mut metadata_map = MetadataKeyMap::new();
+            TestContext {
+                is_keyframe_metadata_key: get_is_keyframe_metadata_key(&mut metadata_map),
+            }
+        }
+
+        fn video(
+            &self,
+            timestamp: u64,
+            is_keyframe: bool,
+            is_sequence_header: bool,
+        ) -> MediaNotification {
+            let mut buffer = BytesMut::new();
+            let entry = MetadataEntry::new(
+                self.is_keyframe_metadata_key,
+                MetadataValue::Bool(is_keyframe),
+                &mut buffer,
+            )
+            .unwrap();
+
+            MediaNotification {
+                stream_id: StreamId(Arc::new("abc".to_string())),
+                content: MediaNotificationContent::MediaPayload {
+                    media_type: MediaType::Video,
+                    payload_type: VIDEO_CODEC_H264_AVC.clone(),
+                    timestamp: Duration::from_millis(timestamp),
+                    metadata: MediaPayloadMetadataCollection::new(iter::once(entry), &mut buffer),
+                    data: Bytes::from(vec![timestamp as u8]),
+                    is_required_for_decoding: is_sequence_header,
+                },
+                annotations: Default::default(),
+            }
+        }
+
+        fn audio(&self, timestamp: u64, is_sequence_header: bool) -> MediaNotification {
+            MediaNotification {
+                stream_id: StreamId(Arc::new("abc".to_string())),
+                content: MediaNotificationContent::MediaPayload {
+                    media_type: MediaType::Audio,
+                    payload_type: AUDIO_CODEC_AAC_RAW.clone(),
+                    timestamp: Duration::from_millis(timestamp),
+                    metadata: MediaPayloadMetadataCollection::new(
+                        iter::empty(),
+                        &mut BytesMut::new(),
+                    ),
+                    data: Bytes::from(vec![timestamp as u8]),
+                    is_required_for_decoding: is_sequence_header,
+                },
+                annotations: Default::default(),
+            }
+        }
+
+        /// Simulates an output tracking media, and a watcher joining after `join_after` media
+        /// notifications. Returns all media the watcher receives in order.
+        fn watcher_media(
+            &self,
+            media: &[MediaNotification],
+            join_after: usize,
+        ) -> Vec<MediaNotification> {
+            let mut bootstrap = WatcherBootstrap::new();
+            for item in &media[..join_after] {
+                bootstrap.track(
+                    item,
+                    classify_media(&item.content, self.is_keyframe_metadata_key),
+                );
+            }
+
+            let mut received = bootstrap.bootstrap_sequence();
+            received.extend_from_slice(&media[join_after..]);
+
+            received
+        }
+    }
+
+    #[test]
+    fn late_joiner_receives_headers_and_keyframe_before_subsequent_frames() {
+        let context = TestContext::new();
+        let media = vec![
+            context.video(0, true, true),
+            context.audio(0, true),
+            context.video(10, true, false),
+            context.audio(15, false),
+            context.video(20, false, false),
+            context.video(30, true, false),
+            context.video(40, false, false),
+            context.audio(45, false),
+            context.video(50, false, false),
+        ];
+
+        let received = context.watcher_media(&media, 7);
+        let expected = vec![
+            context.video(0, true, true),
+            context.audio(0, true),
+            context.video(30, true, false),
+            context.audio(45, false),
+            context.video(50, false, false),
+        ];
+
+        assert_eq!(received, expected, "Unexpected media received by watcher");
+    }
+
+    #[test]
+    fn keyframes_before_a_new_video_sequence_header_are_not_replayed() {
+        let context = TestContext::new();
+        let media = vec![
+            context.video(0, true, true),
+            context.video(10, true, false),
+            context.video(20, true, true),
+            context.video(30, false, false),
+        ];
+
+        let received = context.watcher_media(&media, 3);
+        let expected = vec![
+            context.video(20, true, true),
+            context.video(30, false, false),
+        ];
+
+        assert_eq!(received, expected, "Unexpected media received by watcher");
+    }
+
+    #[test]
+    fn can_create_bootstrap_from_runner_cache() {
+        let context = TestContext::new();
+        let new_stream = MediaNotification {
+            stream_id: StreamId(Arc::new("abc".to_string())),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        };
+
+        let cache = vec![
+            new_stream,
+            context.audio(0, true),
+            context.video(0, true, true),
+        ];
+
+        let keyframe = context.video(100, true, false);
+        let bootstrap =
+            WatcherBootstrap::from_cache(&cache, Some(&keyframe), context.is_keyframe_metadata_key);
+
+        let expected = vec![
+            context.video(0, true, true),
+            context.audio(0, true),
+            context.video(100, true, false),
+        ];
+
+        assert_eq!(bootstrap.bootstrap_sequence(), expected);
+    }
+}
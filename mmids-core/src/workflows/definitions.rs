@@ -1,8 +1,10 @@
+use crate::workflows::watchers::WatcherLimit;
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::hash::{Hash, Hasher};
 use std::sync::Arc;
+use std::time::Duration;
 
 /// Identifier representing the type of the workflow step being defined
 #[derive(Clone, Hash, Debug, Eq, PartialEq)]
@@ -26,6 +28,21 @@ pub struct WorkflowStepDefinition {
 pub struct WorkflowDefinition {
     pub name: Arc<String>,
     pub routed_by_reactor: bool,
+
+    /// How long a stream may flow through the workflow before it's forcefully disconnected,
+    /// regardless of whether it's still producing media. Streams have no lifetime limit when
+    /// not specified.
+    pub max_stream_lifetime: Option<Duration>,
+
+    /// The maximum number of bytes of required-for-decoding media the workflow will cache for
+    /// each stream, for replaying to steps added while the stream is active. A default limit is
+    /// used when not specified.
+    pub max_cached_media_bytes: Option<usize>,
+
+    /// The maximum number of watchers allowed across all of the workflow's output steps, and what
+    /// happens to watchers beyond it. Watchers are not limited when not specified.
+    pub watcher_limit: Option<WatcherLimit>,
+
     pub steps: Vec<WorkflowStepDefinition>,
 }
 
@@ -0,0 +1,25 @@
+//! Steps that switch a stream to different media (e.g. failing over to a backup source, or
+//! switching between passing through and transcoding) tell later steps that what follows doesn't
+//! continue from the media they last saw. This is done with a metadata notification containing
+//! only the `DISCONTINUITY_METADATA_KEY` key, which later steps can use to reset any state built
+//! up from the earlier media.
+
+use crate::workflows::{MediaNotification, MediaNotificationContent};
+use crate::StreamId;
+use std::collections::HashMap;
+
+/// The metadata key a discontinuity is announced with. The value describes what the stream
+/// switched to, and is specific to whatever raised the discontinuity.
+pub const DISCONTINUITY_METADATA_KEY: &str = "mmids_discontinuity";
+
+/// Creates the notification announcing a discontinuity in the stream
+pub fn discontinuity_notification(stream_id: StreamId, value: &str) -> MediaNotification {
+    let mut data = HashMap::new();
+    data.insert(DISCONTINUITY_METADATA_KEY.to_string(), value.to_string());
+
+    MediaNotification {
+        stream_id,
+        content: MediaNotificationContent::Metadata { data },
+        annotations: Default::default(),
+    }
+}
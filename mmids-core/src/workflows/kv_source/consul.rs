@@ -0,0 +1,225 @@
+use crate::workflows::kv_source::{KeyValueWatchError, KeyValueWatcher};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use hyper::{Body, Client, Request, StatusCode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::info;
+
+const INDEX_HEADER: &str = "X-Consul-Index";
+
+/// How long consul should hold a blocking query open waiting for changes
+const BLOCKING_WAIT_SECONDS: u64 = 300;
+
+/// How long past the blocking wait time before the request is considered lost
+const REQUEST_TIMEOUT_MARGIN_SECONDS: u64 = 30;
+
+/// Watches keys in consul's key-value store using blocking queries
+pub struct ConsulWatcher {
+    address: String,
+    prefix: String,
+    index: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ConsulKeyValue {
+    #[serde(rename = "Key")]
+    key: String,
+
+    #[serde(rename = "Value")]
+    value: Option<String>,
+}
+
+impl ConsulWatcher {
+    /// Creates a watcher for the keys under the prefix, using the consul agent at the specified
+    /// address (e.g. `http://localhost:8500`)
+    pub fn new(address: &str, prefix: &str) -> Self {
+        ConsulWatcher {
+            address: address.trim().trim_end_matches('/').to_string(),
+            prefix: prefix.trim().trim_matches('/').to_string(),
+            index: None,
+        }
+    }
+
+    async fn query(&mut self) -> Result<HashMap<String, String>, KeyValueWatchError> {
+        loop {
+            let mut url = format!(
+                "{}/v1/kv/{}/?recurse=true&wait={}s",
+                self.address, self.prefix, BLOCKING_WAIT_SECONDS
+            );
+
+            if let Some(index) = self.index {
+                url.push_str(&format!("&index={}", index));
+            }
+
+            let request = Request::get(url)
+                .body(Body::empty())
+                .map_err(|error| KeyValueWatchError::ConnectionFailed(error.to_string()))?;
+
+            let timeout =
+                Duration::from_secs(BLOCKING_WAIT_SECONDS + REQUEST_TIMEOUT_MARGIN_SECONDS);
+
+            let response = match tokio::time::timeout(timeout, Client::new().request(request)).await
+            {
+                Ok(Ok(response)) => response,
+                Ok(Err(error)) => {
+                    return Err(KeyValueWatchError::ConnectionFailed(error.to_string()));
+                }
+
+                Err(_) => {
+                    return Err(KeyValueWatchError::ConnectionFailed(
+                        "request timed out".to_string(),
+                    ));
+                }
+            };
+
+            let new_index = response
+                .headers()
+                .get(INDEX_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok());
+
+            let status = response.status();
+            let snapshot = match status {
+                // Consul returns not found when no keys exist under the prefix
+                StatusCode::NOT_FOUND => HashMap::new(),
+
+                StatusCode::OK => {
+                    let bytes = hyper::body::to_bytes(response.into_body())
+                        .await
+                        .map_err(|error| KeyValueWatchError::ConnectionFailed(error.to_string()))?;
+
+                    parse_response(&bytes)?
+                }
+
+                status => {
+                    return Err(KeyValueWatchError::InvalidResponse(format!(
+                        "status code {}",
+                        status
+                    )));
+                }
+            };
+
+            // Consul says to restart blocking queries from scratch if the index ever goes
+            // backwards, and an unchanged index means the wait timed out without any changes.
+            let previous_index = self.index;
+            self.index = match (previous_index, new_index) {
+                (Some(previous), Some(new)) if new < previous => None,
+                (_, new) => new,
+            };
+
+            if previous_index.is_some() && previous_index == new_index {
+                continue;
+            }
+
+            info!("Retrieved {} keys from consul", snapshot.len());
+            return Ok(snapshot);
+        }
+    }
+}
+
+impl KeyValueWatcher for ConsulWatcher {
+    fn next_snapshot(
+        &mut self,
+    ) -> BoxFuture<'_, Result<HashMap<String, String>, KeyValueWatchError>> {
+        async move {
+            let result = self.query().await;
+            if result.is_err() {
+                // Start over with a full read once consul is reachable again
+                self.index = None;
+            }
+
+            result
+        }
+        .boxed()
+    }
+}
+
+fn parse_response(bytes: &[u8]) -> Result<HashMap<String, String>, KeyValueWatchError> {
+    let entries: Vec<ConsulKeyValue> = serde_json::from_slice(bytes)
+        .map_err(|error| KeyValueWatchError::InvalidResponse(error.to_string()))?;
+
+    let mut snapshot = HashMap::new();
+    for ConsulKeyValue { key, value } in entries {
+        // Folders show up as keys ending in a slash without any value
+        if key.ends_with('/') {
+            continue;
+        }
+
+        let value = match value {
+            Some(value) => value,
+            None => continue,
+        };
+
+        let value = decode_base64(&value).ok_or_else(|| {
+            KeyValueWatchError::InvalidResponse(format!(
+                "value for key {} is not valid base64",
+                key
+            ))
+        })?;
+
+        let value = String::from_utf8(value).map_err(|_| {
+            KeyValueWatchError::InvalidResponse(format!("value for key {} is not valid utf8", key))
+        })?;
+
+        snapshot.insert(key, value);
+    }
+
+    Ok(snapshot)
+}
+
+fn decode_base64(value: &str) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(value.len() * 3 / 4);
+    let mut buffer = 0_u32;
+    let mut bit_count = 0;
+    for byte in value.bytes().filter(|byte| *byte != b'=') {
+        let bits = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+
+        buffer = (buffer << 6) | bits as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            output.push((buffer >> bit_count) as u8);
+        }
+    }
+
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_consul_response() {
+        let response = r#"[
+            {"Key": "mmids/workflows/", "Value": null},
+            {"Key": "mmids/workflows/abc", "Value": "eyJzdGVwcyI6IFtdfQ=="}
+        ]"#;
+
+        let snapshot = parse_response(response.as_bytes()).expect("Failed to parse response");
+
+        assert_eq!(snapshot.len(), 1, "Unexpected number of keys");
+        assert_eq!(
+            snapshot.get("mmids/workflows/abc"),
+            Some(&"{\"steps\": []}".to_string()),
+            "Unexpected value"
+        );
+    }
+
+    #[test]
+    fn invalid_base64_value_returns_error() {
+        let response = r#"[{"Key": "mmids/workflows/abc", "Value": "not*base64"}]"#;
+        let result = parse_response(response.as_bytes());
+
+        assert!(result.is_err(), "Expected an error");
+    }
+}
@@ -0,0 +1,321 @@
+//! Workflow definitions can be managed centrally in a key-value store (such as consul) instead of
+//! in each mmids instance's configuration file. The key-value workflow source watches every key
+//! under a prefix, where each key holds the json definition of a single workflow, and applies
+//! changes to the workflow manager as they occur. New and changed workflows are upserted, while
+//! workflows whose keys were removed are stopped.
+//!
+//! When the connection to the key-value store is lost the last known workflows keep running as
+//! they are, and the source keeps reconnecting until the store is reachable again.
+//!
+//! Workflows are stored as json in the form of:
+//!
+//! ```json
+//! {
+//!     "name": "basic",
+//!     "routed_by_reactor": false,
+//!     "max_stream_lifetime": 3600,
+//!     "steps": [
+//!         { "type": "rtmp_receive", "parameters": { "port": "1935", "rtmp_app": "live", "stream_key": "*" } },
+//!         { "type": "rtmp_watch", "parameters": { "port": "1935", "rtmp_app": "watch", "stream_key": "*" } }
+//!     ]
+//! }
+//! ```
+//!
+//! Only `steps` is required. When no name is given the last segment of the key is used as the
+//! workflow's name, and `max_stream_lifetime` is in seconds like in the configuration file.
+
+mod consul;
+#[cfg(test)]
+mod tests;
+
+pub use consul::ConsulWatcher;
+
+use crate::workflows::definitions::{WorkflowDefinition, WorkflowStepDefinition, WorkflowStepType};
+use crate::workflows::manager::{WorkflowManagerRequest, WorkflowManagerRequestOperation};
+use crate::workflows::watchers::{WatcherLimit, WatcherLimitBehavior};
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, instrument, warn};
+
+const REQUEST_ID: &str = "kv-workflow-source";
+
+/// Watches a key-value store for changes to the keys under a single prefix
+pub trait KeyValueWatcher {
+    /// Waits until the keys under the watched prefix have changed (or, for the first call, until
+    /// they can be retrieved) and returns every key under the prefix with its value.
+    fn next_snapshot(
+        &mut self,
+    ) -> BoxFuture<'_, Result<HashMap<String, String>, KeyValueWatchError>>;
+}
+
+#[derive(Error, Debug)]
+pub enum KeyValueWatchError {
+    #[error("Failed to connect to the key-value store: {0}")]
+    ConnectionFailed(String),
+
+    #[error("The key-value store returned an unexpected response: {0}")]
+    InvalidResponse(String),
+}
+
+#[derive(Error, Debug)]
+pub enum JsonWorkflowParseError {
+    #[error("The workflow is not valid json: {0}")]
+    InvalidJson(#[from] serde_json::Error),
+
+    #[error("The workflow does not have a name")]
+    NoName,
+}
+
+#[derive(Deserialize)]
+struct JsonWorkflowDefinition {
+    name: Option<String>,
+
+    #[serde(default)]
+    routed_by_reactor: bool,
+
+    #[serde(default)]
+    max_stream_lifetime: u64,
+
+    #[serde(default)]
+    max_cached_media_bytes: Option<usize>,
+
+    #[serde(default)]
+    max_watchers: Option<usize>,
+
+    #[serde(default)]
+    watcher_limit_behavior: JsonWatcherLimitBehavior,
+
+    steps: Vec<JsonWorkflowStepDefinition>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum JsonWatcherLimitBehavior {
+    #[default]
+    Reject,
+    Slate,
+}
+
+#[derive(Deserialize)]
+struct JsonWorkflowStepDefinition {
+    #[serde(rename = "type")]
+    step_type: String,
+
+    #[serde(default)]
+    parameters: HashMap<String, Option<String>>,
+}
+
+/// Parses the json representation of a single workflow. The default name is used if the json
+/// does not specify a name for the workflow.
+pub fn parse_json_workflow(
+    content: &str,
+    default_name: &str,
+) -> Result<WorkflowDefinition, JsonWorkflowParseError> {
+    let workflow: JsonWorkflowDefinition = serde_json::from_str(content)?;
+    let name = workflow
+        .name
+        .unwrap_or_else(|| default_name.to_string())
+        .trim()
+        .to_string();
+
+    if name.is_empty() {
+        return Err(JsonWorkflowParseError::NoName);
+    }
+
+    let behavior = match workflow.watcher_limit_behavior {
+        JsonWatcherLimitBehavior::Reject => WatcherLimitBehavior::Reject,
+        JsonWatcherLimitBehavior::Slate => WatcherLimitBehavior::Slate,
+    };
+
+    Ok(WorkflowDefinition {
+        name: Arc::new(name),
+        routed_by_reactor: workflow.routed_by_reactor,
+        max_stream_lifetime: if workflow.max_stream_lifetime > 0 {
+            Some(Duration::from_secs(workflow.max_stream_lifetime))
+        } else {
+            None
+        },
+        max_cached_media_bytes: workflow.max_cached_media_bytes.filter(|bytes| *bytes > 0),
+        watcher_limit: workflow.max_watchers.map(|max_watchers| WatcherLimit {
+            max_watchers,
+            behavior,
+        }),
+        steps: workflow
+            .steps
+            .into_iter()
+            .map(|step| WorkflowStepDefinition {
+                step_type: WorkflowStepType(step.step_type),
+                parameters: step.parameters,
+            })
+            .collect(),
+    })
+}
+
+/// Starts watching the key-value store for workflow definitions, applying them to the workflow
+/// manager. The reconnect delay is how long to wait before trying again after the connection to
+/// the key-value store failed.
+pub fn start_kv_workflow_source(
+    watcher: Box<dyn KeyValueWatcher + Send>,
+    workflow_manager: UnboundedSender<WorkflowManagerRequest>,
+    reconnect_delay: Duration,
+    shutdown_token: CancellationToken,
+) {
+    let source = KvWorkflowSource {
+        watcher,
+        workflow_manager,
+        reconnect_delay,
+        applied_workflows: HashMap::new(),
+    };
+
+    tokio::spawn(source.run(shutdown_token));
+}
+
+struct AppliedWorkflow {
+    raw_definition: String,
+    name: Arc<String>,
+}
+
+struct KvWorkflowSource {
+    watcher: Box<dyn KeyValueWatcher + Send>,
+    workflow_manager: UnboundedSender<WorkflowManagerRequest>,
+    reconnect_delay: Duration,
+    applied_workflows: HashMap<String, AppliedWorkflow>,
+}
+
+impl KvWorkflowSource {
+    #[instrument(name = "KV Workflow Source Execution", skip_all)]
+    async fn run(mut self, shutdown_token: CancellationToken) {
+        info!("Starting key-value workflow source");
+
+        loop {
+            let result = tokio::select! {
+                result = self.watcher.next_snapshot() => result,
+                _ = shutdown_token.cancelled() => break,
+            };
+
+            match result {
+                Ok(snapshot) => {
+                    if !self.apply_snapshot(snapshot) {
+                        info!("Workflow manager is gone");
+                        break;
+                    }
+                }
+
+                Err(error) => {
+                    warn!(
+                        "Failed to watch the key-value store, keeping the last known workflows: {}",
+                        error
+                    );
+
+                    tokio::select! {
+                        _ = tokio::time::sleep(self.reconnect_delay) => (),
+                        _ = shutdown_token.cancelled() => break,
+                    }
+                }
+            }
+        }
+
+        info!("Key-value workflow source closing");
+    }
+
+    /// Applies the differences between the snapshot and the previously applied workflows. Returns
+    /// false if the workflow manager is no longer available.
+    fn apply_snapshot(&mut self, mut snapshot: HashMap<String, String>) -> bool {
+        let removed_keys = self
+            .applied_workflows
+            .keys()
+            .filter(|key| !snapshot.contains_key(*key))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for key in removed_keys {
+            if let Some(workflow) = self.applied_workflows.remove(&key) {
+                info!(
+                    key = %key,
+                    workflow_name = %workflow.name,
+                    "Key for workflow {} was removed, stopping it", workflow.name
+                );
+
+                if !self.stop_workflow(workflow.name) {
+                    return false;
+                }
+            }
+        }
+
+        for (key, raw_definition) in snapshot.drain() {
+            let previous = self.applied_workflows.get(&key);
+            if let Some(previous) = previous {
+                if previous.raw_definition == raw_definition {
+                    continue;
+                }
+            }
+
+            let default_name = key.rsplit('/').next().unwrap_or(&key);
+            let definition = match parse_json_workflow(&raw_definition, default_name) {
+                Ok(definition) => definition,
+                Err(error) => {
+                    error!(
+                        key = %key,
+                        "The workflow stored in key {} is invalid and will not be applied: {}",
+                        key, error
+                    );
+
+                    continue;
+                }
+            };
+
+            if let Some(previous) = previous {
+                if previous.name != definition.name {
+                    let name = previous.name.clone();
+                    if !self.stop_workflow(name) {
+                        return false;
+                    }
+                }
+            }
+
+            info!(
+                key = %key,
+                workflow_name = %definition.name,
+                "Applying workflow {} from key {}", definition.name, key
+            );
+
+            let name = definition.name.clone();
+            let result = self.workflow_manager.send(WorkflowManagerRequest {
+                request_id: REQUEST_ID.to_string(),
+                operation: WorkflowManagerRequestOperation::UpsertWorkflow {
+                    definition,
+                    response_channel: None,
+                },
+            });
+
+            if result.is_err() {
+                return false;
+            }
+
+            self.applied_workflows.insert(
+                key,
+                AppliedWorkflow {
+                    raw_definition,
+                    name,
+                },
+            );
+        }
+
+        true
+    }
+
+    fn stop_workflow(&self, name: Arc<String>) -> bool {
+        self.workflow_manager
+            .send(WorkflowManagerRequest {
+                request_id: REQUEST_ID.to_string(),
+                operation: WorkflowManagerRequestOperation::StopWorkflow { name },
+            })
+            .is_ok()
+    }
+}
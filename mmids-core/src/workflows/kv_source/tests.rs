@@ -0,0 +1,258 @@
+use super::*;
+use crate::test_utils;
+use futures::FutureExt;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+type SnapshotResult = Result<HashMap<String, String>, KeyValueWatchError>;
+
+struct MockWatcher {
+    receiver: UnboundedReceiver<SnapshotResult>,
+}
+
+impl KeyValueWatcher for MockWatcher {
+    fn next_snapshot(&mut self) -> BoxFuture<'_, SnapshotResult> {
+        async move {
+            match self.receiver.recv().await {
+                Some(result) => result,
+                None => futures::future::pending().await,
+            }
+        }
+        .boxed()
+    }
+}
+
+struct TestContext {
+    snapshots: UnboundedSender<SnapshotResult>,
+    manager: UnboundedReceiver<WorkflowManagerRequest>,
+    _shutdown_token: tokio_util::sync::DropGuard,
+}
+
+impl TestContext {
+    fn new() -> Self {
+        let (snapshot_sender, snapshot_receiver) = unbounded_channel();
+        let (manager_sender, manager_receiver) = unbounded_channel();
+        let shutdown_token = CancellationToken::new();
+
+        start_kv_workflow_source(
+            Box::new(MockWatcher {
+                receiver: snapshot_receiver,
+            }),
+            manager_sender,
+            Duration::from_millis(10),
+            shutdown_token.clone(),
+        );
+
+        TestContext {
+            snapshots: snapshot_sender,
+            manager: manager_receiver,
+            _shutdown_token: shutdown_token.drop_guard(),
+        }
+    }
+
+    fn send_snapshot(&self, values: &[(&str, &str)]) {
+        let snapshot = values
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        self.snapshots
+            .send(Ok(snapshot))
+            .expect("Failed to send snapshot");
+    }
+
+    async fn expect_upsert(&mut self) -> WorkflowDefinition {
+        let request = test_utils::expect_mpsc_response(&mut self.manager).await;
+        match request.operation {
+            WorkflowManagerRequestOperation::UpsertWorkflow { definition, .. } => definition,
+            x => panic!("Expected upsert request, instead got {:?}", x),
+        }
+    }
+
+    async fn expect_stop(&mut self) -> Arc<String> {
+        let request = test_utils::expect_mpsc_response(&mut self.manager).await;
+        match request.operation {
+            WorkflowManagerRequestOperation::StopWorkflow { name } => name,
+            x => panic!("Expected stop request, instead got {:?}", x),
+        }
+    }
+}
+
+const WORKFLOW_V1: &str =
+    r#"{"steps": [{"type": "rtmp_receive", "parameters": {"port": "1935"}}]}"#;
+const WORKFLOW_V2: &str =
+    r#"{"steps": [{"type": "rtmp_receive", "parameters": {"port": "1936"}}]}"#;
+
+#[test]
+fn can_parse_json_workflow() {
+    let content = r#"{
+        "name": "abc",
+        "routed_by_reactor": true,
+        "max_stream_lifetime": 60,
+        "steps": [
+            {"type": "rtmp_receive", "parameters": {"port": "1935", "allow_ips": null}},
+            {"type": "rtmp_watch"}
+        ]
+    }"#;
+
+    let workflow = parse_json_workflow(content, "default").expect("Failed to parse workflow");
+
+    assert_eq!(workflow.name.as_str(), "abc", "Unexpected name");
+    assert!(workflow.routed_by_reactor, "Expected routed by reactor");
+    assert_eq!(
+        workflow.max_stream_lifetime,
+        Some(Duration::from_secs(60)),
+        "Unexpected max stream lifetime"
+    );
+    assert_eq!(workflow.steps.len(), 2, "Unexpected number of steps");
+    assert_eq!(
+        workflow.steps[0].step_type.0, "rtmp_receive",
+        "Unexpected first step type"
+    );
+    assert_eq!(
+        workflow.steps[0].parameters.get("port"),
+        Some(&Some("1935".to_string())),
+        "Unexpected port parameter"
+    );
+    assert_eq!(
+        workflow.steps[0].parameters.get("allow_ips"),
+        Some(&None),
+        "Unexpected allow_ips parameter"
+    );
+    assert!(
+        workflow.steps[1].parameters.is_empty(),
+        "Expected no parameters on second step"
+    );
+}
+
+#[test]
+fn json_workflow_without_name_uses_default_name() {
+    let workflow = parse_json_workflow(WORKFLOW_V1, "default").expect("Failed to parse workflow");
+
+    assert_eq!(workflow.name.as_str(), "default", "Unexpected name");
+    assert_eq!(
+        workflow.max_stream_lifetime, None,
+        "Unexpected max stream lifetime"
+    );
+}
+
+#[test]
+fn can_parse_json_workflow_watcher_limit() {
+    let content = r#"{
+        "max_watchers": 10,
+        "watcher_limit_behavior": "slate",
+        "steps": [{"type": "rtmp_watch"}]
+    }"#;
+
+    let workflow = parse_json_workflow(content, "default").expect("Failed to parse workflow");
+
+    assert_eq!(
+        workflow.watcher_limit,
+        Some(WatcherLimit {
+            max_watchers: 10,
+            behavior: WatcherLimitBehavior::Slate,
+        }),
+        "Unexpected watcher limit"
+    );
+}
+
+#[tokio::test]
+async fn new_key_upserts_workflow_named_after_key() {
+    let mut context = TestContext::new();
+    context.send_snapshot(&[("mmids/workflows/abc", WORKFLOW_V1)]);
+
+    let workflow = context.expect_upsert().await;
+    assert_eq!(workflow.name.as_str(), "abc", "Unexpected workflow name");
+    assert_eq!(workflow.steps.len(), 1, "Unexpected number of steps");
+}
+
+#[tokio::test]
+async fn updated_definition_triggers_reload() {
+    let mut context = TestContext::new();
+    context.send_snapshot(&[("mmids/workflows/abc", WORKFLOW_V1)]);
+    let _ = context.expect_upsert().await;
+
+    context.send_snapshot(&[("mmids/workflows/abc", WORKFLOW_V2)]);
+
+    let workflow = context.expect_upsert().await;
+    assert_eq!(workflow.name.as_str(), "abc", "Unexpected workflow name");
+    assert_eq!(
+        workflow.steps[0].parameters.get("port"),
+        Some(&Some("1936".to_string())),
+        "Expected updated port parameter"
+    );
+}
+
+#[tokio::test]
+async fn unchanged_definition_is_not_reapplied() {
+    let mut context = TestContext::new();
+    context.send_snapshot(&[("mmids/workflows/abc", WORKFLOW_V1)]);
+    let _ = context.expect_upsert().await;
+
+    context.send_snapshot(&[("mmids/workflows/abc", WORKFLOW_V1)]);
+
+    test_utils::expect_mpsc_timeout(&mut context.manager).await;
+}
+
+#[tokio::test]
+async fn removed_key_stops_workflow() {
+    let mut context = TestContext::new();
+    context.send_snapshot(&[("mmids/workflows/abc", WORKFLOW_V1)]);
+    let _ = context.expect_upsert().await;
+
+    context.send_snapshot(&[]);
+
+    let name = context.expect_stop().await;
+    assert_eq!(name.as_str(), "abc", "Unexpected stopped workflow");
+}
+
+#[tokio::test]
+async fn renamed_workflow_stops_workflow_with_old_name() {
+    let mut context = TestContext::new();
+    context.send_snapshot(&[("mmids/workflows/abc", WORKFLOW_V1)]);
+    let _ = context.expect_upsert().await;
+
+    let renamed = r#"{"name": "def", "steps": []}"#;
+    context.send_snapshot(&[("mmids/workflows/abc", renamed)]);
+
+    let name = context.expect_stop().await;
+    assert_eq!(name.as_str(), "abc", "Unexpected stopped workflow");
+
+    let workflow = context.expect_upsert().await;
+    assert_eq!(workflow.name.as_str(), "def", "Unexpected workflow name");
+}
+
+#[tokio::test]
+async fn connection_loss_keeps_last_known_workflows() {
+    let mut context = TestContext::new();
+    context.send_snapshot(&[("mmids/workflows/abc", WORKFLOW_V1)]);
+    let _ = context.expect_upsert().await;
+
+    context
+        .snapshots
+        .send(Err(KeyValueWatchError::ConnectionFailed(
+            "test".to_string(),
+        )))
+        .expect("Failed to send error");
+
+    test_utils::expect_mpsc_timeout(&mut context.manager).await;
+
+    // Once reconnected, changes should be picked up again
+    context.send_snapshot(&[("mmids/workflows/abc", WORKFLOW_V2)]);
+    let workflow = context.expect_upsert().await;
+    assert_eq!(
+        workflow.steps[0].parameters.get("port"),
+        Some(&Some("1936".to_string())),
+        "Expected updated port parameter"
+    );
+}
+
+#[tokio::test]
+async fn invalid_definition_is_not_applied() {
+    let mut context = TestContext::new();
+    context.send_snapshot(&[("mmids/workflows/abc", WORKFLOW_V1)]);
+    let _ = context.expect_upsert().await;
+
+    context.send_snapshot(&[("mmids/workflows/abc", "{not json")]);
+
+    test_utils::expect_mpsc_timeout(&mut context.manager).await;
+}
@@ -0,0 +1,337 @@
+//! Allows the log level of individual workflows to be raised above the application's log level,
+//! so a single workflow can be debugged without flooding the logs with every other workflow's
+//! debug output.
+//!
+//! Workflow runners and their steps log within spans carrying a `workflow_name` field. The
+//! `WorkflowLogFilter` is a `tracing_subscriber` per-layer filter that lets through anything at or
+//! below the application's log level, and anything within a span for a workflow that has an
+//! override at or below that override's level. Overrides are stored in a shared
+//! `WorkflowLogLevels` set, which is given to both the log filter and the workflow manager that
+//! updates it at runtime.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Filter};
+use tracing_subscriber::registry::LookupSpan;
+
+/// The span field workflow spans identify their workflow with
+pub const WORKFLOW_NAME_FIELD: &str = "workflow_name";
+
+/// A shared set of log level overrides, keyed by workflow name
+#[derive(Clone, Default)]
+pub struct WorkflowLogLevels {
+    levels: Arc<RwLock<HashMap<String, LevelFilter>>>,
+}
+
+impl WorkflowLogLevels {
+    /// Sets the log level for the specified workflow, or removes its override if `None` is passed
+    /// in.
+    pub fn set(&self, workflow_name: &str, level: Option<LevelFilter>) {
+        let previous = {
+            let mut levels = self.levels.write().unwrap();
+            match level {
+                Some(level) => levels.insert(workflow_name.to_string(), level),
+                None => levels.remove(workflow_name),
+            }
+        };
+
+        if previous != level {
+            // Filters decide which callsites they are interested in, and the most verbose level
+            // they may enable, based on the current overrides. Tracing caches both, so they have
+            // to be re-evaluated for the change to take effect.
+            tracing::callsite::rebuild_interest_cache();
+        }
+    }
+
+    /// Gets the log level override for the specified workflow, if one exists
+    pub fn get(&self, workflow_name: &str) -> Option<LevelFilter> {
+        self.levels.read().unwrap().get(workflow_name).copied()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.levels.read().unwrap().is_empty()
+    }
+
+    /// The most verbose level any workflow has been overridden to
+    fn max_level(&self) -> Option<LevelFilter> {
+        self.levels.read().unwrap().values().copied().max()
+    }
+}
+
+/// The name of the workflow a span belongs to, stored in the span's extensions
+struct WorkflowName(String);
+
+#[derive(Default)]
+struct WorkflowNameVisitor(Option<String>);
+
+impl Visit for WorkflowNameVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == WORKFLOW_NAME_FIELD {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == WORKFLOW_NAME_FIELD {
+            // Display values (e.g. `workflow_name = %name`) are recorded through their debug
+            // implementation, which writes them out as-is.
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+/// Per-layer filter enabling spans and events at or below the default level, or at or below the
+/// level override of the workflow they are logged within.
+#[derive(Clone)]
+pub struct WorkflowLogFilter {
+    default_level: LevelFilter,
+    levels: WorkflowLogLevels,
+}
+
+impl WorkflowLogFilter {
+    /// Creates a filter using the specified set of workflow log level overrides. The same set
+    /// should be given to the workflow manager, so overrides it applies are picked up by the
+    /// filter.
+    pub fn new(default_level: LevelFilter, levels: WorkflowLogLevels) -> Self {
+        WorkflowLogFilter {
+            default_level,
+            levels,
+        }
+    }
+}
+
+impl<S> Filter<S> for WorkflowLogFilter
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn enabled(&self, metadata: &Metadata<'_>, context: &Context<'_, S>) -> bool {
+        if self.default_level >= *metadata.level() {
+            return true;
+        }
+
+        if self.levels.is_empty() {
+            return false;
+        }
+
+        let current = match context.lookup_current() {
+            Some(span) => span,
+            None => return false,
+        };
+
+        for span in current.scope() {
+            if let Some(WorkflowName(name)) = span.extensions().get::<WorkflowName>() {
+                return match self.levels.get(name) {
+                    Some(level) => level >= *metadata.level(),
+                    None => false,
+                };
+            }
+        }
+
+        false
+    }
+
+    fn callsite_enabled(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if self.default_level >= *metadata.level() {
+            return Interest::always();
+        }
+
+        // The interest cache is rebuilt whenever an override changes, so callsites that no
+        // override enables can be skipped entirely until one does
+        match self.levels.max_level() {
+            Some(level) if level >= *metadata.level() => {
+                // Whether these are enabled depends on the workflow they are logged within
+                Interest::sometimes()
+            }
+
+            _ => Interest::never(),
+        }
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        let level = match self.levels.max_level() {
+            Some(level) => level.max(self.default_level),
+            None => self.default_level,
+        };
+
+        Some(level)
+    }
+
+    fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, context: Context<'_, S>) {
+        let mut visitor = WorkflowNameVisitor::default();
+        attributes.record(&mut visitor);
+        if let Some(name) = visitor.0 {
+            if let Some(span) = context.span(id) {
+                span.extensions_mut().replace(WorkflowName(name));
+            }
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, context: Context<'_, S>) {
+        // Renamed workflows update the field on their existing spans
+        let mut visitor = WorkflowNameVisitor::default();
+        values.record(&mut visitor);
+        if let Some(name) = visitor.0 {
+            if let Some(span) = context.span(id) {
+                span.extensions_mut().replace(WorkflowName(name));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tracing::{debug, info, info_span, Event, Level};
+    use tracing_subscriber::layer::{Layer, SubscriberExt};
+    use tracing_subscriber::registry::Registry;
+
+    /// Records the level and message of every event it receives
+    #[derive(Clone, Default)]
+    struct CapturingLayer {
+        events: Arc<Mutex<Vec<(Level, String)>>>,
+    }
+
+    #[derive(Default)]
+    struct MessageVisitor(String);
+
+    impl Visit for MessageVisitor {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                self.0 = format!("{:?}", value);
+            }
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for CapturingLayer {
+        fn on_event(&self, event: &Event<'_>, _context: tracing_subscriber::layer::Context<'_, S>) {
+            let mut visitor = MessageVisitor::default();
+            event.record(&mut visitor);
+            self.events
+                .lock()
+                .unwrap()
+                .push((*event.metadata().level(), visitor.0));
+        }
+    }
+
+    #[test]
+    fn only_overridden_workflow_logs_at_elevated_level() {
+        let levels = WorkflowLogLevels::default();
+        levels.set("noisy", Some(LevelFilter::DEBUG));
+
+        let layer = CapturingLayer::default();
+        let filter = WorkflowLogFilter::new(LevelFilter::INFO, levels);
+        let subscriber = Registry::default().with(layer.clone().with_filter(filter));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let name = "noisy".to_string();
+            info_span!("Workflow Execution", workflow_name = %name).in_scope(|| {
+                info_span!("Step Execution").in_scope(|| {
+                    debug!("noisy debug");
+                    info!("noisy info");
+                });
+            });
+
+            info_span!("Workflow Execution", workflow_name = "quiet").in_scope(|| {
+                debug!("quiet debug");
+                info!("quiet info");
+            });
+
+            debug!("outside debug");
+        });
+
+        let events = layer.events.lock().unwrap().clone();
+        assert_eq!(
+            events,
+            vec![
+                (Level::DEBUG, "noisy debug".to_string()),
+                (Level::INFO, "noisy info".to_string()),
+                (Level::INFO, "quiet info".to_string()),
+            ],
+            "Unexpected events logged"
+        );
+    }
+
+    #[test]
+    fn removed_override_returns_workflow_to_default_level() {
+        let levels = WorkflowLogLevels::default();
+        levels.set("noisy", Some(LevelFilter::DEBUG));
+        levels.set("noisy", None);
+
+        let layer = CapturingLayer::default();
+        let filter = WorkflowLogFilter::new(LevelFilter::INFO, levels);
+        let subscriber = Registry::default().with(layer.clone().with_filter(filter));
+
+        tracing::subscriber::with_default(subscriber, || {
+            info_span!("Workflow Execution", workflow_name = "noisy").in_scope(|| {
+                debug!("noisy debug");
+            });
+        });
+
+        assert!(
+            layer.events.lock().unwrap().is_empty(),
+            "Expected no events to be logged"
+        );
+    }
+
+    #[test]
+    fn max_level_hint_follows_overrides() {
+        let levels = WorkflowLogLevels::default();
+        let filter = WorkflowLogFilter::new(LevelFilter::INFO, levels.clone());
+        let hint = || <WorkflowLogFilter as Filter<Registry>>::max_level_hint(&filter);
+
+        assert_eq!(
+            hint(),
+            Some(LevelFilter::INFO),
+            "Unexpected hint without overrides"
+        );
+
+        levels.set("noisy", Some(LevelFilter::DEBUG));
+        levels.set("quiet", Some(LevelFilter::WARN));
+        assert_eq!(
+            hint(),
+            Some(LevelFilter::DEBUG),
+            "Unexpected hint with overrides"
+        );
+
+        levels.set("noisy", None);
+        assert_eq!(
+            hint(),
+            Some(LevelFilter::INFO),
+            "Unexpected hint after removal"
+        );
+    }
+
+    #[test]
+    fn override_applies_to_callsites_already_hit() {
+        let levels = WorkflowLogLevels::default();
+        let layer = CapturingLayer::default();
+        let filter = WorkflowLogFilter::new(LevelFilter::INFO, levels.clone());
+        let subscriber = Registry::default().with(layer.clone().with_filter(filter));
+
+        // Logs from the same callsite each time, so its cached interest is reused
+        let log = || {
+            info_span!("Workflow Execution", workflow_name = "noisy").in_scope(|| {
+                debug!("noisy debug");
+            });
+        };
+
+        tracing::subscriber::with_default(subscriber, || {
+            log();
+            levels.set("noisy", Some(LevelFilter::DEBUG));
+            log();
+        });
+
+        let events = layer.events.lock().unwrap().clone();
+        assert_eq!(
+            events,
+            vec![(Level::DEBUG, "noisy debug".to_string())],
+            "Unexpected events logged"
+        );
+    }
+}
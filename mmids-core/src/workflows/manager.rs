@@ -2,16 +2,28 @@
 //! used to start new workflows, change the steps of a managed workflow, get status the of managed
 //! workflows, and stop a managed workflow.
 
-use crate::actor_utils::{notify_on_unbounded_closed, notify_on_unbounded_recv};
+use crate::actor_utils::{
+    notify_on_cancellation, notify_on_future_completion, notify_on_unbounded_closed,
+    notify_on_unbounded_recv,
+};
+use crate::channel_metrics::ChannelDepthGauge;
 use crate::event_hub::{PublishEventRequest, WorkflowManagerEvent, WorkflowStartedOrStoppedEvent};
 use crate::workflows::definitions::WorkflowDefinition;
-use crate::workflows::runner::{WorkflowRequestOperation, WorkflowState};
+use crate::workflows::log_levels::WorkflowLogLevels;
+use crate::workflows::runner::{
+    ActiveStreamDetails, FlushCachesResult, WorkflowRequestOperation, WorkflowState,
+};
 use crate::workflows::steps::factory::WorkflowStepFactory;
-use crate::workflows::{start_workflow, WorkflowRequest};
+use crate::workflows::validation::{validate_workflow, WorkflowValidationResult};
+use crate::workflows::{start_workflow, WorkflowRequest, WorkflowResourceUsage};
+use crate::StreamId;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
-use tokio::sync::oneshot::Sender;
+use tokio::sync::oneshot::{channel, Sender};
+use tokio_util::sync::CancellationToken;
+use tracing::level_filters::LevelFilter;
 use tracing::{info, instrument, warn};
 
 /// Requests an action be taken by the workflow manager
@@ -27,8 +39,12 @@ pub struct WorkflowManagerRequest {
 /// Operations consumers can request the workflow manager to perform
 #[derive(Debug)]
 pub enum WorkflowManagerRequestOperation {
-    /// Starts or updates a specified workflow based on the passed in definition
-    UpsertWorkflow { definition: WorkflowDefinition },
+    /// Starts or updates a specified workflow based on the passed in definition. If a response
+    /// channel is provided, it's told whether the upsert was accepted.
+    UpsertWorkflow {
+        definition: WorkflowDefinition,
+        response_channel: Option<Sender<UpsertWorkflowResult>>,
+    },
 
     /// Stops the specified workflow, if it is running
     StopWorkflow { name: Arc<String> },
@@ -43,6 +59,99 @@ pub enum WorkflowManagerRequestOperation {
         name: Arc<String>,
         response_channel: Sender<Option<WorkflowState>>,
     },
+
+    /// Requests the total number of streams that have been disconnected by the dead stream reaper
+    GetReapedStreamCount { response_channel: Sender<u64> },
+
+    /// Requests estimates of the resources each running workflow is currently using
+    GetResourceUsage {
+        response_channel: Sender<Vec<WorkflowResourceUsage>>,
+    },
+
+    /// Renames a running workflow without interrupting its steps or active streams
+    RenameWorkflow {
+        old_name: Arc<String>,
+        new_name: Arc<String>,
+        response_channel: Sender<RenameWorkflowResult>,
+    },
+
+    /// Atomically swaps the definitions of two running workflows. Each workflow applies the other's
+    /// definition as an update, so steps the two definitions have in common (and the streams
+    /// flowing through them) are kept running.
+    SwapWorkflows {
+        name_a: Arc<String>,
+        name_b: Arc<String>,
+        response_channel: Sender<SwapWorkflowsResult>,
+    },
+
+    /// Checks that every step of the workflow can be constructed, without starting the workflow
+    /// or affecting any running workflow with the same name
+    ValidateWorkflow {
+        definition: WorkflowDefinition,
+        response_channel: Sender<WorkflowValidationResult>,
+    },
+
+    /// Discards the cached sequence headers of a stream in the specified workflow (or all of the
+    /// workflow's streams if no stream is specified), and asks the workflow's steps to produce
+    /// fresh ones. Responds with `None` if the workflow is not running.
+    FlushStreamCaches {
+        workflow_name: Arc<String>,
+        stream_id: Option<StreamId>,
+        response_channel: Sender<Option<FlushCachesResult>>,
+    },
+
+    /// Overrides the log level of the specified workflow's runner and steps, or removes the
+    /// override if no level is specified. Only takes effect for log output filtered through a
+    /// `WorkflowLogFilter` sharing the manager's `WorkflowLogLevels`. The override is kept if the
+    /// workflow is restarted.
+    SetWorkflowLogLevel {
+        workflow_name: Arc<String>,
+        level: Option<LevelFilter>,
+    },
+
+    /// Enters or exits maintenance mode. While in maintenance mode, upserts are rejected so
+    /// workflows can't be started or changed, but already running workflows keep running.
+    SetMaintenanceMode { enabled: bool },
+
+    /// Requests whether the workflow manager is in maintenance mode
+    GetMaintenanceMode { response_channel: Sender<bool> },
+}
+
+/// The outcome of a request to upsert a workflow
+#[derive(Debug, PartialEq, Eq)]
+pub enum UpsertWorkflowResult {
+    Upserted,
+
+    /// The workflow manager is in maintenance mode, so the workflow was not started or updated.
+    /// The upsert should be retried once maintenance is over.
+    InMaintenance,
+}
+
+/// The outcome of a request to rename a workflow
+#[derive(Debug, PartialEq, Eq)]
+pub enum RenameWorkflowResult {
+    Renamed,
+    WorkflowNotFound,
+
+    /// Another workflow is already running with the requested name
+    NameAlreadyInUse,
+
+    /// The new name belongs to a different workflow manager than the one running the workflow.
+    /// Only returned by a `WorkflowManagerRouter`, as workflows cannot be moved between managers.
+    NameOwnedByOtherManager,
+}
+
+/// The outcome of a request to swap the definitions of two workflows
+#[derive(Debug, PartialEq, Eq)]
+pub enum SwapWorkflowsResult {
+    Swapped,
+
+    /// At least one of the workflows is not running
+    WorkflowNotFound,
+
+    /// The two workflows belong to different workflow managers. Only returned by a
+    /// `WorkflowManagerRouter`, as a manager cannot swap definitions with another manager.
+    NameOwnedByOtherManager,
 }
 
 #[derive(Debug)]
@@ -50,51 +159,149 @@ pub struct GetWorkflowResponse {
     pub name: Arc<String>,
 }
 
+/// Settings for the dead stream reaper. When enabled, the workflow manager will periodically scan
+/// all workflows for streams that have not produced any media within the configured threshold,
+/// and force those streams to be disconnected. This acts as a safety net for streams that were
+/// leaked and are holding onto cached media.
+#[derive(Clone, Debug)]
+pub struct StreamReaperSettings {
+    /// How often all workflows are scanned for dead streams
+    pub scan_interval: Duration,
+
+    /// How long a stream can go without producing media before it is considered dead
+    pub inactivity_threshold: Duration,
+}
+
+/// Starts a workflow manager. Cancelling the shutdown token stops the manager along with all the
+/// workflows it started. Workflow log level overrides are applied to the specified log levels,
+/// which the application's `WorkflowLogFilter` should be created with.
 pub fn start_workflow_manager(
     step_factory: Arc<WorkflowStepFactory>,
     event_hub_publisher: UnboundedSender<PublishEventRequest>,
+    reaper_settings: Option<StreamReaperSettings>,
+    log_levels: WorkflowLogLevels,
+    shutdown_token: CancellationToken,
+) -> UnboundedSender<WorkflowManagerRequest> {
+    start(
+        step_factory,
+        event_hub_publisher,
+        reaper_settings,
+        log_levels,
+        true,
+        shutdown_token,
+    )
+}
+
+/// Starts a workflow manager that does not register itself with the event hub. This should be
+/// used for managers that sit behind a `WorkflowManagerRouter`, as the router is what reactors
+/// and other consumers should be sending requests to.
+pub fn start_routed_workflow_manager(
+    step_factory: Arc<WorkflowStepFactory>,
+    event_hub_publisher: UnboundedSender<PublishEventRequest>,
+    reaper_settings: Option<StreamReaperSettings>,
+    log_levels: WorkflowLogLevels,
+    shutdown_token: CancellationToken,
+) -> UnboundedSender<WorkflowManagerRequest> {
+    start(
+        step_factory,
+        event_hub_publisher,
+        reaper_settings,
+        log_levels,
+        false,
+        shutdown_token,
+    )
+}
+
+fn start(
+    step_factory: Arc<WorkflowStepFactory>,
+    event_hub_publisher: UnboundedSender<PublishEventRequest>,
+    reaper_settings: Option<StreamReaperSettings>,
+    log_levels: WorkflowLogLevels,
+    register_with_event_hub: bool,
+    shutdown_token: CancellationToken,
 ) -> UnboundedSender<WorkflowManagerRequest> {
     let (sender, receiver) = unbounded_channel();
     let (actor_sender, actor_receiver) = unbounded_channel();
-    let actor = Actor::new(step_factory, event_hub_publisher, receiver, actor_sender);
+    notify_on_unbounded_recv(
+        receiver,
+        actor_sender.clone(),
+        FutureResult::WorkflowManagerRequestReceived,
+        || FutureResult::AllConsumersGone,
+    );
+
+    let actor = Actor::new(
+        step_factory,
+        event_hub_publisher,
+        reaper_settings,
+        log_levels,
+        register_with_event_hub,
+        shutdown_token,
+        actor_sender,
+    );
+
     tokio::spawn(actor.run(sender.clone(), actor_receiver));
 
     sender
 }
 
 enum FutureResult {
+    ShutdownRequested,
     AllConsumersGone,
     EventHubGone,
     WorkflowManagerRequestReceived(WorkflowManagerRequest),
-    WorkflowGone(Arc<String>),
+    WorkflowGone(UnboundedSender<WorkflowRequest>),
+    ReaperScanTimerElapsed,
+    ActiveStreamsReceived {
+        workflow_name: Arc<String>,
+        streams: Vec<ActiveStreamDetails>,
+    },
+
+    ResourceUsageReceived {
+        usages: Vec<WorkflowResourceUsage>,
+        response_channel: Sender<Vec<WorkflowResourceUsage>>,
+    },
 }
 
 struct Actor {
     internal_sender: UnboundedSender<FutureResult>,
     workflows: HashMap<Arc<String>, UnboundedSender<WorkflowRequest>>,
+    definitions: HashMap<Arc<String>, WorkflowDefinition>,
     step_factory: Arc<WorkflowStepFactory>,
     event_hub_publisher: UnboundedSender<PublishEventRequest>,
+    reaper_settings: Option<StreamReaperSettings>,
+    register_with_event_hub: bool,
+    shutdown_token: CancellationToken,
+    reaped_stream_count: u64,
+    log_levels: WorkflowLogLevels,
+    in_maintenance: bool,
 }
 
 impl Actor {
     fn new(
         step_factory: Arc<WorkflowStepFactory>,
         event_hub_publisher: UnboundedSender<PublishEventRequest>,
-        request_receiver: UnboundedReceiver<WorkflowManagerRequest>,
+        reaper_settings: Option<StreamReaperSettings>,
+        log_levels: WorkflowLogLevels,
+        register_with_event_hub: bool,
+        shutdown_token: CancellationToken,
         actor_sender: UnboundedSender<FutureResult>,
     ) -> Self {
-        notify_on_unbounded_recv(
-            request_receiver,
-            actor_sender.clone(),
-            FutureResult::WorkflowManagerRequestReceived,
-            || FutureResult::AllConsumersGone,
-        );
+        notify_on_cancellation(shutdown_token.clone(), actor_sender.clone(), || {
+            FutureResult::ShutdownRequested
+        });
 
         Actor {
             internal_sender: actor_sender,
             workflows: HashMap::new(),
+            definitions: HashMap::new(),
             step_factory,
             event_hub_publisher,
+            reaper_settings,
+            register_with_event_hub,
+            shutdown_token,
+            reaped_stream_count: 0,
+            log_levels,
+            in_maintenance: false,
         }
     }
 
@@ -111,16 +318,34 @@ impl Actor {
         );
 
         info!("Starting workflow manager");
-        let _ = self
-            .event_hub_publisher
-            .send(PublishEventRequest::WorkflowManagerEvent(
-                WorkflowManagerEvent::WorkflowManagerRegistered {
-                    channel: request_sender,
-                },
-            ));
+        if let Some(settings) = &self.reaper_settings {
+            info!(
+                "Dead stream reaper enabled with a scan interval of {:?} and a threshold of {:?}",
+                settings.scan_interval, settings.inactivity_threshold
+            );
+
+            self.schedule_reaper_scan();
+        }
 
+        if self.register_with_event_hub {
+            let _ = self
+                .event_hub_publisher
+                .send(PublishEventRequest::WorkflowManagerEvent(
+                    WorkflowManagerEvent::WorkflowManagerRegistered {
+                        channel: request_sender,
+                    },
+                ));
+        }
+
+        let channel_depth = ChannelDepthGauge::register("workflow_manager");
         while let Some(result) = actor_receiver.recv().await {
+            channel_depth.record(actor_receiver.len());
             match result {
+                FutureResult::ShutdownRequested => {
+                    info!("Shutdown requested");
+                    break;
+                }
+
                 FutureResult::AllConsumersGone => {
                     info!("All consumers gone");
                     break;
@@ -135,8 +360,18 @@ impl Actor {
                     self.handle_request(request);
                 }
 
-                FutureResult::WorkflowGone(name) => {
-                    if self.workflows.remove(&name).is_some() {
+                FutureResult::WorkflowGone(channel) => {
+                    // Workflows can be renamed, so look them up by their channel instead of the
+                    // name they were started with
+                    let name = self
+                        .workflows
+                        .iter()
+                        .find(|(_, sender)| sender.same_channel(&channel))
+                        .map(|(name, _)| name.clone());
+
+                    if let Some(name) = name {
+                        self.workflows.remove(&name);
+                        self.definitions.remove(&name);
                         let event =
                             WorkflowStartedOrStoppedEvent::WorkflowEnded { name: name.clone() };
                         let _ = self
@@ -149,16 +384,124 @@ impl Actor {
                         );
                     }
                 }
+
+                FutureResult::ReaperScanTimerElapsed => {
+                    self.start_reaper_scan();
+                    self.schedule_reaper_scan();
+                }
+
+                FutureResult::ActiveStreamsReceived {
+                    workflow_name,
+                    streams,
+                } => {
+                    self.reap_dead_streams(workflow_name, streams);
+                }
+
+                FutureResult::ResourceUsageReceived {
+                    mut usages,
+                    response_channel,
+                } => {
+                    usages.sort_by(|a, b| a.workflow_name.cmp(&b.workflow_name));
+                    let _ = response_channel.send(usages);
+                }
             }
         }
 
         info!("Workflow manager closing")
     }
 
+    fn schedule_reaper_scan(&self) {
+        if let Some(settings) = &self.reaper_settings {
+            notify_on_future_completion(
+                tokio::time::sleep(settings.scan_interval),
+                self.internal_sender.clone(),
+                |_| FutureResult::ReaperScanTimerElapsed,
+            );
+        }
+    }
+
+    fn start_reaper_scan(&self) {
+        for (name, sender) in &self.workflows {
+            let (response_sender, response_receiver) = channel();
+            let _ = sender.send(WorkflowRequest {
+                request_id: "dead-stream-reaper".to_string(),
+                operation: WorkflowRequestOperation::GetActiveStreams {
+                    response_channel: response_sender,
+                },
+            });
+
+            let workflow_name = name.clone();
+            notify_on_future_completion(
+                response_receiver,
+                self.internal_sender.clone(),
+                move |result| FutureResult::ActiveStreamsReceived {
+                    workflow_name,
+                    streams: result.unwrap_or_default(),
+                },
+            );
+        }
+    }
+
+    fn reap_dead_streams(&mut self, workflow_name: Arc<String>, streams: Vec<ActiveStreamDetails>) {
+        let threshold = match &self.reaper_settings {
+            Some(settings) => settings.inactivity_threshold,
+            None => return,
+        };
+
+        let sender = match self.workflows.get(&workflow_name) {
+            Some(sender) => sender,
+            None => return, // workflow was stopped since the scan started
+        };
+
+        for stream in streams {
+            if stream.time_since_last_media >= threshold {
+                warn!(
+                    workflow_name = %workflow_name,
+                    stream_id = %stream.stream_id.0,
+                    "Reaping stream {} in workflow '{}', as it has not produced media in {:?}",
+                    stream.stream_id.0, workflow_name, stream.time_since_last_media,
+                );
+
+                let _ = sender.send(WorkflowRequest {
+                    request_id: "dead-stream-reaper".to_string(),
+                    operation: WorkflowRequestOperation::DisconnectStream {
+                        stream_id: stream.stream_id,
+                    },
+                });
+
+                self.reaped_stream_count += 1;
+            }
+        }
+    }
+
     #[instrument(skip(self, request), fields(request_id = %request.request_id))]
     fn handle_request(&mut self, request: WorkflowManagerRequest) {
         match request.operation {
-            WorkflowManagerRequestOperation::UpsertWorkflow { definition } => {
+            WorkflowManagerRequestOperation::UpsertWorkflow {
+                definition,
+                response_channel,
+            } => {
+                if self.in_maintenance {
+                    warn!(
+                        workflow_name = %definition.name,
+                        "Rejecting upsert of workflow '{}' as the manager is in maintenance mode",
+                        definition.name,
+                    );
+
+                    if let Some(channel) = response_channel {
+                        let _ = channel.send(UpsertWorkflowResult::InMaintenance);
+                    }
+
+                    return;
+                }
+
+                if let Some(channel) = response_channel {
+                    let _ = channel.send(UpsertWorkflowResult::Upserted);
+                }
+
+                self.definitions
+                    .insert(definition.name.clone(), definition.clone());
+
                 if let Some(sender) = self.workflows.get_mut(&definition.name) {
                     info!(
                         workflow_name = %definition.name,
@@ -178,13 +521,17 @@ impl Actor {
                     );
 
                     let name = definition.name.clone();
-                    let sender = start_workflow(definition, self.step_factory.clone());
+                    let sender = start_workflow(
+                        definition,
+                        self.step_factory.clone(),
+                        self.shutdown_token.child_token(),
+                    );
 
-                    let on_closed_name = name.clone();
+                    let on_closed_sender = sender.clone();
                     notify_on_unbounded_closed(
                         sender.clone(),
                         self.internal_sender.clone(),
-                        || FutureResult::WorkflowGone(on_closed_name),
+                        || FutureResult::WorkflowGone(on_closed_sender),
                     );
 
                     self.workflows.insert(name.clone(), sender.clone());
@@ -206,6 +553,7 @@ impl Actor {
                     "Stopping workflow '{}'", name,
                 );
 
+                self.definitions.remove(&name);
                 if let Some(sender) = self.workflows.remove(&name) {
                     let _ = sender.send(WorkflowRequest {
                         request_id: request.request_id,
@@ -247,30 +595,272 @@ impl Actor {
                     });
                 }
             },
+
+            WorkflowManagerRequestOperation::GetReapedStreamCount { response_channel } => {
+                let _ = response_channel.send(self.reaped_stream_count);
+            }
+
+            WorkflowManagerRequestOperation::GetResourceUsage { response_channel } => {
+                let request_id = request.request_id;
+                let receivers = self
+                    .workflows
+                    .values()
+                    .map(|sender| {
+                        let (usage_sender, usage_receiver) = channel();
+                        let _ = sender.send(WorkflowRequest {
+                            request_id: request_id.clone(),
+                            operation: WorkflowRequestOperation::GetResourceUsage {
+                                response_channel: usage_sender,
+                            },
+                        });
+
+                        usage_receiver
+                    })
+                    .collect::<Vec<_>>();
+
+                notify_on_future_completion(
+                    futures::future::join_all(receivers),
+                    self.internal_sender.clone(),
+                    move |results| FutureResult::ResourceUsageReceived {
+                        usages: results.into_iter().filter_map(|x| x.ok()).collect(),
+                        response_channel,
+                    },
+                );
+            }
+
+            WorkflowManagerRequestOperation::RenameWorkflow {
+                old_name,
+                new_name,
+                response_channel,
+            } => {
+                let result = self.rename_workflow(request.request_id, old_name, new_name);
+                let _ = response_channel.send(result);
+            }
+
+            WorkflowManagerRequestOperation::SwapWorkflows {
+                name_a,
+                name_b,
+                response_channel,
+            } => {
+                let result = self.swap_workflows(request.request_id, name_a, name_b);
+                let _ = response_channel.send(result);
+            }
+
+            WorkflowManagerRequestOperation::ValidateWorkflow {
+                definition,
+                response_channel,
+            } => {
+                let result = validate_workflow(&definition, &self.step_factory);
+                info!(
+                    workflow_name = %definition.name,
+                    "Validated workflow '{}' with {} errors and {} warnings",
+                    definition.name, result.errors.len(), result.warnings.len(),
+                );
+
+                let _ = response_channel.send(result);
+            }
+
+            WorkflowManagerRequestOperation::SetWorkflowLogLevel {
+                workflow_name,
+                level,
+            } => {
+                match level {
+                    Some(level) => info!(
+                        workflow_name = %workflow_name,
+                        "Overriding log level of workflow '{}' to {}", workflow_name, level,
+                    ),
+
+                    None => info!(
+                        workflow_name = %workflow_name,
+                        "Removing log level override of workflow '{}'", workflow_name,
+                    ),
+                }
+
+                self.log_levels.set(&workflow_name, level);
+            }
+
+            WorkflowManagerRequestOperation::SetMaintenanceMode { enabled } => {
+                if enabled != self.in_maintenance {
+                    if enabled {
+                        info!("Entering maintenance mode, upserts will be rejected");
+                    } else {
+                        info!("Exiting maintenance mode, upserts will be accepted");
+                    }
+                }
+
+                self.in_maintenance = enabled;
+            }
+
+            WorkflowManagerRequestOperation::GetMaintenanceMode { response_channel } => {
+                let _ = response_channel.send(self.in_maintenance);
+            }
+
+            WorkflowManagerRequestOperation::FlushStreamCaches {
+                workflow_name,
+                stream_id,
+                response_channel,
+            } => match self.workflows.get(&workflow_name) {
+                None => {
+                    warn!(
+                        workflow_name = %workflow_name,
+                        "Request to flush caches for workflow '{}' but it is not running",
+                        workflow_name,
+                    );
+
+                    let _ = response_channel.send(None);
+                }
+
+                Some(sender) => {
+                    let _ = sender.send(WorkflowRequest {
+                        request_id: request.request_id,
+                        operation: WorkflowRequestOperation::FlushCaches {
+                            stream_id,
+                            response_channel,
+                        },
+                    });
+                }
+            },
+        }
+    }
+
+    fn rename_workflow(
+        &mut self,
+        request_id: String,
+        old_name: Arc<String>,
+        new_name: Arc<String>,
+    ) -> RenameWorkflowResult {
+        if !self.workflows.contains_key(&old_name) {
+            return RenameWorkflowResult::WorkflowNotFound;
+        }
+
+        if self.workflows.contains_key(&new_name) {
+            warn!(
+                workflow_name = %old_name,
+                "Workflow '{}' cannot be renamed to '{}', as a workflow with that name is already running",
+                old_name, new_name,
+            );
+
+            return RenameWorkflowResult::NameAlreadyInUse;
+        }
+
+        info!(
+            workflow_name = %old_name,
+            "Renaming workflow '{}' to '{}'", old_name, new_name,
+        );
+
+        let sender = self
+            .workflows
+            .remove(&old_name)
+            .expect("Workflow existence was checked above");
+
+        let _ = sender.send(WorkflowRequest {
+            request_id,
+            operation: WorkflowRequestOperation::Rename {
+                new_name: new_name.clone(),
+            },
+        });
+
+        self.workflows.insert(new_name.clone(), sender.clone());
+        if let Some(mut definition) = self.definitions.remove(&old_name) {
+            definition.name = new_name.clone();
+            self.definitions.insert(new_name.clone(), definition);
+        }
+
+        let event = WorkflowStartedOrStoppedEvent::WorkflowRenamed {
+            old_name,
+            new_name,
+            channel: sender,
+        };
+
+        let _ = self
+            .event_hub_publisher
+            .send(PublishEventRequest::WorkflowStartedOrStopped(event));
+
+        RenameWorkflowResult::Renamed
+    }
+
+    fn swap_workflows(
+        &mut self,
+        request_id: String,
+        name_a: Arc<String>,
+        name_b: Arc<String>,
+    ) -> SwapWorkflowsResult {
+        let (mut definition_a, mut definition_b) =
+            match (self.definitions.get(&name_a), self.definitions.get(&name_b)) {
+                (Some(a), Some(b)) => (a.clone(), b.clone()),
+                _ => return SwapWorkflowsResult::WorkflowNotFound,
+            };
+
+        if name_a == name_b {
+            return SwapWorkflowsResult::Swapped;
         }
+
+        info!(
+            workflow_name = %name_a,
+            "Swapping the definitions of workflows '{}' and '{}'", name_a, name_b,
+        );
+
+        // Both updates are queued before any other request is handled, so no request can observe
+        // only one of the workflows having been swapped
+        definition_a.name = name_b.clone();
+        definition_b.name = name_a.clone();
+        for definition in [definition_a, definition_b] {
+            if let Some(sender) = self.workflows.get(&definition.name) {
+                let _ = sender.send(WorkflowRequest {
+                    request_id: request_id.clone(),
+                    operation: WorkflowRequestOperation::UpdateDefinition {
+                        new_definition: definition.clone(),
+                    },
+                });
+            }
+
+            self.definitions.insert(definition.name.clone(), definition);
+        }
+
+        SwapWorkflowsResult::Swapped
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::codecs::VIDEO_CODEC_H264_AVC;
     use crate::test_utils;
+    use crate::workflows::definitions::{WorkflowStepDefinition, WorkflowStepType};
+    use crate::workflows::metadata::MediaPayloadMetadataCollection;
+    use crate::workflows::runner::test_steps::{
+        TestInputStepGenerator, TestPassThroughStepGenerator,
+    };
+    use crate::workflows::steps::StepStatus;
+    use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+    use bytes::{Bytes, BytesMut};
+    use std::sync::atomic::AtomicU16;
     use tokio::sync::oneshot::channel;
+    use tokio::sync::watch;
 
     struct TestContext {
         event_hub: UnboundedReceiver<PublishEventRequest>,
         manager: UnboundedSender<WorkflowManagerRequest>,
+        log_levels: WorkflowLogLevels,
     }
 
     impl TestContext {
         fn new() -> Self {
             let (sender, receiver) = unbounded_channel();
             let factory = Arc::new(WorkflowStepFactory::new());
-            let manager = start_workflow_manager(factory, sender);
+            let log_levels = WorkflowLogLevels::default();
+            let manager = start_workflow_manager(
+                factory,
+                sender,
+                None,
+                log_levels.clone(),
+                CancellationToken::new(),
+            );
 
             TestContext {
                 event_hub: receiver,
                 manager,
+                log_levels,
             }
         }
     }
@@ -302,8 +892,12 @@ mod tests {
                     definition: WorkflowDefinition {
                         name: Arc::new("workflow".to_string()),
                         routed_by_reactor: false,
+                        max_stream_lifetime: None,
+                        max_cached_media_bytes: None,
+                        watcher_limit: None,
                         steps: Vec::new(),
                     },
+                    response_channel: None,
                 },
             })
             .expect("Failed to send upsert request");
@@ -335,8 +929,12 @@ mod tests {
                     definition: WorkflowDefinition {
                         name: Arc::new("workflow".to_string()),
                         routed_by_reactor: false,
+                        max_stream_lifetime: None,
+                        max_cached_media_bytes: None,
+                        watcher_limit: None,
                         steps: Vec::new(),
                     },
+                    response_channel: None,
                 },
             })
             .expect("Failed to send upsert request");
@@ -372,8 +970,12 @@ mod tests {
                     definition: WorkflowDefinition {
                         name: Arc::new("workflow".to_string()),
                         routed_by_reactor: false,
+                        max_stream_lifetime: None,
+                        max_cached_media_bytes: None,
+                        watcher_limit: None,
                         steps: Vec::new(),
                     },
+                    response_channel: None,
                 },
             })
             .expect("Failed to send upsert request");
@@ -410,8 +1012,12 @@ mod tests {
                     definition: WorkflowDefinition {
                         name: Arc::new("workflow".to_string()),
                         routed_by_reactor: false,
+                        max_stream_lifetime: None,
+                        max_cached_media_bytes: None,
+                        watcher_limit: None,
                         steps: Vec::new(),
                     },
+                    response_channel: None,
                 },
             })
             .expect("Failed to send upsert request");
@@ -426,8 +1032,12 @@ mod tests {
                     definition: WorkflowDefinition {
                         name: Arc::new("workflow".to_string()),
                         routed_by_reactor: false,
+                        max_stream_lifetime: None,
+                        max_cached_media_bytes: None,
+                        watcher_limit: None,
                         steps: Vec::new(),
                     },
+                    response_channel: None,
                 },
             })
             .expect("Failed to send upsert request");
@@ -446,8 +1056,12 @@ mod tests {
                     definition: WorkflowDefinition {
                         name: Arc::new("workflow".to_string()),
                         routed_by_reactor: false,
+                        max_stream_lifetime: None,
+                        max_cached_media_bytes: None,
+                        watcher_limit: None,
                         steps: Vec::new(),
                     },
+                    response_channel: None,
                 },
             })
             .expect("Failed to send upsert request");
@@ -460,8 +1074,12 @@ mod tests {
                     definition: WorkflowDefinition {
                         name: Arc::new("workflow".to_string()),
                         routed_by_reactor: false,
+                        max_stream_lifetime: None,
+                        max_cached_media_bytes: None,
+                        watcher_limit: None,
                         steps: Vec::new(),
                     },
+                    response_channel: None,
                 },
             })
             .expect("Failed to send upsert request");
@@ -499,8 +1117,12 @@ mod tests {
                     definition: WorkflowDefinition {
                         name: Arc::new("workflow".to_string()),
                         routed_by_reactor: false,
+                        max_stream_lifetime: None,
+                        max_cached_media_bytes: None,
+                        watcher_limit: None,
                         steps: Vec::new(),
                     },
+                    response_channel: None,
                 },
             })
             .expect("Failed to send upsert request");
@@ -545,8 +1167,12 @@ mod tests {
                     definition: WorkflowDefinition {
                         name: Arc::new("workflow".to_string()),
                         routed_by_reactor: false,
+                        max_stream_lifetime: None,
+                        max_cached_media_bytes: None,
+                        watcher_limit: None,
                         steps: Vec::new(),
                     },
+                    response_channel: None,
                 },
             })
             .expect("Failed to send upsert request");
@@ -592,8 +1218,12 @@ mod tests {
                     definition: WorkflowDefinition {
                         name: Arc::new("workflow".to_string()),
                         routed_by_reactor: false,
+                        max_stream_lifetime: None,
+                        max_cached_media_bytes: None,
+                        watcher_limit: None,
                         steps: Vec::new(),
                     },
+                    response_channel: None,
                 },
             })
             .expect("Failed to send upsert request");
@@ -626,4 +1256,816 @@ mod tests {
         let response = test_utils::expect_oneshot_response(receiver).await;
         assert!(response.is_none(), "Expected no workflow details returned");
     }
+
+    #[tokio::test]
+    async fn stalled_stream_is_reaped() {
+        let (event_hub_sender, _event_hub_receiver) = unbounded_channel();
+        let (media_sender, media_receiver) = watch::channel(MediaNotification {
+            stream_id: StreamId(Arc::new("invalid".to_string())),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        });
+
+        let (status_sender, status_receiver) = watch::channel(StepStatus::Created);
+        let (_future_media_sender, future_media_receiver) = watch::channel(MediaNotification {
+            stream_id: StreamId(Arc::new("invalid".to_string())),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        });
+
+        let mut factory = WorkflowStepFactory::new();
+        factory
+            .register(
+                WorkflowStepType("input".to_string()),
+                Box::new(TestInputStepGenerator {
+                    media_receiver,
+                    status_change: status_receiver,
+                    future_result_media_receiver: future_media_receiver,
+                    media_received_count: Arc::new(AtomicU16::new(0)),
+                }),
+            )
+            .expect("Failed to register input step");
+
+        let manager = start_workflow_manager(
+            Arc::new(factory),
+            event_hub_sender,
+            Some(StreamReaperSettings {
+                scan_interval: Duration::from_millis(10),
+                inactivity_threshold: Duration::from_millis(20),
+            }),
+            WorkflowLogLevels::default(),
+            CancellationToken::new(),
+        );
+
+        manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::UpsertWorkflow {
+                    definition: WorkflowDefinition {
+                        name: Arc::new("workflow".to_string()),
+                        routed_by_reactor: false,
+                        max_stream_lifetime: None,
+                        max_cached_media_bytes: None,
+                        watcher_limit: None,
+                        steps: vec![WorkflowStepDefinition {
+                            step_type: WorkflowStepType("input".to_string()),
+                            parameters: HashMap::new(),
+                        }],
+                    },
+                    response_channel: None,
+                },
+            })
+            .expect("Failed to send upsert request");
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        status_sender
+            .send(StepStatus::Active)
+            .expect("Failed to set step status");
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        media_sender
+            .send(MediaNotification {
+                stream_id: StreamId(Arc::new("abc".to_string())),
+                content: MediaNotificationContent::NewIncomingStream {
+                    stream_name: Arc::new("def".to_string()),
+                },
+                annotations: Default::default(),
+            })
+            .expect("Failed to send new stream notification");
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let (sender, receiver) = channel();
+        manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::GetReapedStreamCount {
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send reaped stream count request");
+
+        let response = test_utils::expect_oneshot_response(receiver).await;
+        assert_eq!(response, 1, "Unexpected number of reaped streams");
+    }
+
+    #[tokio::test]
+    async fn renamed_workflow_keeps_streams_flowing() {
+        let (event_hub_sender, mut event_hub_receiver) = unbounded_channel();
+        let (media_sender, media_receiver) = watch::channel(MediaNotification {
+            stream_id: StreamId(Arc::new("invalid".to_string())),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        });
+
+        let (status_sender, status_receiver) = watch::channel(StepStatus::Created);
+        let (_future_media_sender, future_media_receiver) = watch::channel(MediaNotification {
+            stream_id: StreamId(Arc::new("invalid".to_string())),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        });
+
+        let (output_sender, mut output_receiver) = unbounded_channel();
+
+        let mut factory = WorkflowStepFactory::new();
+        factory
+            .register(
+                WorkflowStepType("input".to_string()),
+                Box::new(TestInputStepGenerator {
+                    media_receiver,
+                    status_change: status_receiver,
+                    future_result_media_receiver: future_media_receiver,
+                    media_received_count: Arc::new(AtomicU16::new(0)),
+                }),
+            )
+            .expect("Failed to register input step");
+
+        factory
+            .register(
+                WorkflowStepType("output".to_string()),
+                Box::new(TestPassThroughStepGenerator {
+                    created_count: Arc::new(AtomicU16::new(0)),
+                    media_sender: Some(output_sender),
+                }),
+            )
+            .expect("Failed to register output step");
+
+        let manager = start_workflow_manager(
+            Arc::new(factory),
+            event_hub_sender,
+            None,
+            WorkflowLogLevels::default(),
+            CancellationToken::new(),
+        );
+        test_utils::expect_mpsc_response(&mut event_hub_receiver).await; // manager registered
+
+        manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::UpsertWorkflow {
+                    definition: WorkflowDefinition {
+                        name: Arc::new("workflow".to_string()),
+                        routed_by_reactor: false,
+                        max_stream_lifetime: None,
+                        max_cached_media_bytes: None,
+                        watcher_limit: None,
+                        steps: vec![
+                            WorkflowStepDefinition {
+                                step_type: WorkflowStepType("input".to_string()),
+                                parameters: HashMap::new(),
+                            },
+                            WorkflowStepDefinition {
+                                step_type: WorkflowStepType("output".to_string()),
+                                parameters: HashMap::new(),
+                            },
+                        ],
+                    },
+                    response_channel: None,
+                },
+            })
+            .expect("Failed to send upsert request");
+
+        test_utils::expect_mpsc_response(&mut event_hub_receiver).await; // workflow started
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        status_sender
+            .send(StepStatus::Active)
+            .expect("Failed to set step status");
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        media_sender
+            .send(MediaNotification {
+                stream_id: StreamId(Arc::new("abc".to_string())),
+                content: MediaNotificationContent::NewIncomingStream {
+                    stream_name: Arc::new("def".to_string()),
+                },
+                annotations: Default::default(),
+            })
+            .expect("Failed to send new stream notification");
+
+        let media = test_utils::expect_mpsc_response(&mut output_receiver).await;
+        match media.content {
+            MediaNotificationContent::NewIncomingStream { .. } => (),
+            content => panic!("Unexpected media content: {:?}", content),
+        }
+
+        let (sender, receiver) = channel();
+        manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::RenameWorkflow {
+                    old_name: Arc::new("workflow".to_string()),
+                    new_name: Arc::new("renamed".to_string()),
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send rename request");
+
+        let response = test_utils::expect_oneshot_response(receiver).await;
+        assert_eq!(response, RenameWorkflowResult::Renamed, "Unexpected result");
+
+        let event = test_utils::expect_mpsc_response(&mut event_hub_receiver).await;
+        match event {
+            PublishEventRequest::WorkflowStartedOrStopped(
+                WorkflowStartedOrStoppedEvent::WorkflowRenamed {
+                    old_name, new_name, ..
+                },
+            ) => {
+                assert_eq!(old_name.as_str(), "workflow", "Unexpected old name");
+                assert_eq!(new_name.as_str(), "renamed", "Unexpected new name");
+            }
+
+            event => panic!("Unexpected publish event received: {:?}", event),
+        }
+
+        media_sender
+            .send(MediaNotification {
+                stream_id: StreamId(Arc::new("abc".to_string())),
+                content: MediaNotificationContent::MediaPayload {
+                    media_type: MediaType::Video,
+                    payload_type: VIDEO_CODEC_H264_AVC.clone(),
+                    timestamp: Duration::from_millis(10),
+                    metadata: MediaPayloadMetadataCollection::new(
+                        std::iter::empty(),
+                        &mut BytesMut::new(),
+                    ),
+                    data: Bytes::from_static(&[1, 2, 3]),
+                    is_required_for_decoding: false,
+                },
+                annotations: Default::default(),
+            })
+            .expect("Failed to send media payload");
+
+        let media = test_utils::expect_mpsc_response(&mut output_receiver).await;
+        match media.content {
+            MediaNotificationContent::MediaPayload { data, .. } => {
+                assert_eq!(data, Bytes::from_static(&[1, 2, 3]), "Unexpected data");
+            }
+
+            content => panic!("Unexpected media content: {:?}", content),
+        }
+
+        let (sender, receiver) = channel();
+        manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::GetRunningWorkflows {
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send list workflow request");
+
+        let response = test_utils::expect_oneshot_response(receiver).await;
+        assert_eq!(response.len(), 1, "Unexpected number of workflows");
+        assert_eq!(
+            response[0].name.as_str(),
+            "renamed",
+            "Unexpected workflow name"
+        );
+    }
+
+    #[tokio::test]
+    async fn renaming_workflow_to_existing_name_returns_conflict() {
+        let context = TestContext::new();
+        for name in ["first", "second"] {
+            context
+                .manager
+                .send(WorkflowManagerRequest {
+                    request_id: "".to_string(),
+                    operation: WorkflowManagerRequestOperation::UpsertWorkflow {
+                        definition: WorkflowDefinition {
+                            name: Arc::new(name.to_string()),
+                            routed_by_reactor: false,
+                            max_stream_lifetime: None,
+                            max_cached_media_bytes: None,
+                            watcher_limit: None,
+                            steps: Vec::new(),
+                        },
+                        response_channel: None,
+                    },
+                })
+                .expect("Failed to send upsert request");
+        }
+
+        let (sender, receiver) = channel();
+        context
+            .manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::RenameWorkflow {
+                    old_name: Arc::new("first".to_string()),
+                    new_name: Arc::new("second".to_string()),
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send rename request");
+
+        let response = test_utils::expect_oneshot_response(receiver).await;
+        assert_eq!(
+            response,
+            RenameWorkflowResult::NameAlreadyInUse,
+            "Unexpected result"
+        );
+    }
+
+    #[tokio::test]
+    async fn swapped_workflows_keep_streams_flowing_through_swapped_steps() {
+        let (event_hub_sender, mut event_hub_receiver) = unbounded_channel();
+        let (media_sender, media_receiver) = watch::channel(MediaNotification {
+            stream_id: StreamId(Arc::new("invalid".to_string())),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        });
+
+        let (status_sender, status_receiver) = watch::channel(StepStatus::Created);
+        let (_future_media_sender, future_media_receiver) = watch::channel(MediaNotification {
+            stream_id: StreamId(Arc::new("invalid".to_string())),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        });
+
+        let (first_output_sender, mut first_output_receiver) = unbounded_channel();
+        let (second_output_sender, mut second_output_receiver) = unbounded_channel();
+
+        let mut factory = WorkflowStepFactory::new();
+        factory
+            .register(
+                WorkflowStepType("input".to_string()),
+                Box::new(TestInputStepGenerator {
+                    media_receiver,
+                    status_change: status_receiver,
+                    future_result_media_receiver: future_media_receiver,
+                    media_received_count: Arc::new(AtomicU16::new(0)),
+                }),
+            )
+            .expect("Failed to register input step");
+
+        for (step_type, sender) in [
+            ("first_output", first_output_sender),
+            ("second_output", second_output_sender),
+        ] {
+            factory
+                .register(
+                    WorkflowStepType(step_type.to_string()),
+                    Box::new(TestPassThroughStepGenerator {
+                        created_count: Arc::new(AtomicU16::new(0)),
+                        media_sender: Some(sender),
+                    }),
+                )
+                .expect("Failed to register output step");
+        }
+
+        let manager = start_workflow_manager(
+            Arc::new(factory),
+            event_hub_sender,
+            None,
+            WorkflowLogLevels::default(),
+            CancellationToken::new(),
+        );
+        test_utils::expect_mpsc_response(&mut event_hub_receiver).await; // manager registered
+
+        // Both workflows share an identical input step, so it is kept running through the swap
+        for name in ["first", "second"] {
+            manager
+                .send(WorkflowManagerRequest {
+                    request_id: "".to_string(),
+                    operation: WorkflowManagerRequestOperation::UpsertWorkflow {
+                        definition: WorkflowDefinition {
+                            name: Arc::new(name.to_string()),
+                            routed_by_reactor: false,
+                            max_stream_lifetime: None,
+                            max_cached_media_bytes: None,
+                            watcher_limit: None,
+                            steps: vec![
+                                WorkflowStepDefinition {
+                                    step_type: WorkflowStepType("input".to_string()),
+                                    parameters: HashMap::new(),
+                                },
+                                WorkflowStepDefinition {
+                                    step_type: WorkflowStepType(format!("{}_output", name)),
+                                    parameters: HashMap::new(),
+                                },
+                            ],
+                        },
+                        response_channel: None,
+                    },
+                })
+                .expect("Failed to send upsert request");
+
+            test_utils::expect_mpsc_response(&mut event_hub_receiver).await; // workflow started
+        }
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        status_sender
+            .send(StepStatus::Active)
+            .expect("Failed to set step status");
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        media_sender
+            .send(MediaNotification {
+                stream_id: StreamId(Arc::new("abc".to_string())),
+                content: MediaNotificationContent::NewIncomingStream {
+                    stream_name: Arc::new("def".to_string()),
+                },
+                annotations: Default::default(),
+            })
+            .expect("Failed to send new stream notification");
+
+        for receiver in [&mut first_output_receiver, &mut second_output_receiver] {
+            let media = test_utils::expect_mpsc_response(receiver).await;
+            match media.content {
+                MediaNotificationContent::NewIncomingStream { .. } => (),
+                content => panic!("Unexpected media content: {:?}", content),
+            }
+        }
+
+        let (sender, receiver) = channel();
+        manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::SwapWorkflows {
+                    name_a: Arc::new("first".to_string()),
+                    name_b: Arc::new("second".to_string()),
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send swap request");
+
+        let response = test_utils::expect_oneshot_response(receiver).await;
+        assert_eq!(response, SwapWorkflowsResult::Swapped, "Unexpected result");
+
+        for (name, expected_output) in [("first", "second_output"), ("second", "first_output")] {
+            let (sender, receiver) = channel();
+            manager
+                .send(WorkflowManagerRequest {
+                    request_id: "".to_string(),
+                    operation: WorkflowManagerRequestOperation::GetWorkflowDetails {
+                        name: Arc::new(name.to_string()),
+                        response_channel: sender,
+                    },
+                })
+                .expect("Failed to send details request");
+
+            let state = test_utils::expect_oneshot_response(receiver)
+                .await
+                .expect("Expected workflow details");
+
+            let step_types = state
+                .active_steps
+                .iter()
+                .map(|step| step.definition.step_type.0.as_str())
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                step_types,
+                vec!["input", expected_output],
+                "Unexpected steps in workflow '{}'",
+                name
+            );
+        }
+
+        // The stream is announced to each newly created output step, then keeps flowing without
+        // the input having to announce it again
+        for receiver in [&mut first_output_receiver, &mut second_output_receiver] {
+            let media = test_utils::expect_mpsc_response(receiver).await;
+            match media.content {
+                MediaNotificationContent::NewIncomingStream { .. } => (),
+                content => panic!("Unexpected media content: {:?}", content),
+            }
+        }
+
+        media_sender
+            .send(MediaNotification {
+                stream_id: StreamId(Arc::new("abc".to_string())),
+                content: MediaNotificationContent::MediaPayload {
+                    media_type: MediaType::Video,
+                    payload_type: VIDEO_CODEC_H264_AVC.clone(),
+                    timestamp: Duration::from_millis(10),
+                    metadata: MediaPayloadMetadataCollection::new(
+                        std::iter::empty(),
+                        &mut BytesMut::new(),
+                    ),
+                    data: Bytes::from_static(&[1, 2, 3]),
+                    is_required_for_decoding: false,
+                },
+                annotations: Default::default(),
+            })
+            .expect("Failed to send media payload");
+
+        for receiver in [&mut first_output_receiver, &mut second_output_receiver] {
+            let media = test_utils::expect_mpsc_response(receiver).await;
+            match media.content {
+                MediaNotificationContent::MediaPayload { data, .. } => {
+                    assert_eq!(data, Bytes::from_static(&[1, 2, 3]), "Unexpected data");
+                }
+
+                content => panic!("Unexpected media content: {:?}", content),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn swapping_with_unknown_workflow_returns_not_found() {
+        let context = TestContext::new();
+        context
+            .manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::UpsertWorkflow {
+                    definition: WorkflowDefinition {
+                        name: Arc::new("first".to_string()),
+                        routed_by_reactor: false,
+                        max_stream_lifetime: None,
+                        max_cached_media_bytes: None,
+                        watcher_limit: None,
+                        steps: Vec::new(),
+                    },
+                    response_channel: None,
+                },
+            })
+            .expect("Failed to send upsert request");
+
+        let (sender, receiver) = channel();
+        context
+            .manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::SwapWorkflows {
+                    name_a: Arc::new("first".to_string()),
+                    name_b: Arc::new("second".to_string()),
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send swap request");
+
+        let response = test_utils::expect_oneshot_response(receiver).await;
+        assert_eq!(
+            response,
+            SwapWorkflowsResult::WorkflowNotFound,
+            "Unexpected result"
+        );
+    }
+
+    #[tokio::test]
+    async fn validating_workflow_with_missing_step_returns_error_without_starting_it() {
+        let mut context = TestContext::new();
+        test_utils::expect_mpsc_response(&mut context.event_hub).await; // manager registered event
+
+        let (sender, receiver) = channel();
+        context
+            .manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::ValidateWorkflow {
+                    definition: WorkflowDefinition {
+                        name: Arc::new("workflow".to_string()),
+                        routed_by_reactor: false,
+                        max_stream_lifetime: None,
+                        max_cached_media_bytes: None,
+                        watcher_limit: None,
+                        steps: vec![WorkflowStepDefinition {
+                            step_type: WorkflowStepType("missing".to_string()),
+                            parameters: HashMap::new(),
+                        }],
+                    },
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send validate request");
+
+        let response = test_utils::expect_oneshot_response(receiver).await;
+        assert_eq!(response.errors.len(), 1, "Unexpected number of errors");
+        assert_eq!(
+            response.errors[0].step_type,
+            Some(WorkflowStepType("missing".to_string())),
+            "Unexpected step type in error"
+        );
+        assert!(
+            response.errors[0].message.contains("missing"),
+            "Unexpected error message: {}",
+            response.errors[0].message
+        );
+
+        let (sender, receiver) = channel();
+        context
+            .manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::GetRunningWorkflows {
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send list workflow request");
+
+        let response = test_utils::expect_oneshot_response(receiver).await;
+        assert!(response.is_empty(), "Expected no running workflows");
+        test_utils::expect_mpsc_timeout(&mut context.event_hub).await;
+    }
+
+    #[tokio::test]
+    async fn flushing_caches_of_unknown_workflow_returns_none() {
+        let mut context = TestContext::new();
+        test_utils::expect_mpsc_response(&mut context.event_hub).await; // manager registered event
+
+        let (sender, receiver) = channel();
+        context
+            .manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::FlushStreamCaches {
+                    workflow_name: Arc::new("workflow".to_string()),
+                    stream_id: None,
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send flush request");
+
+        let response = test_utils::expect_oneshot_response(receiver).await;
+        assert_eq!(response, None, "Expected no result");
+    }
+
+    #[tokio::test]
+    async fn flushing_caches_is_forwarded_to_running_workflow() {
+        let mut context = TestContext::new();
+        test_utils::expect_mpsc_response(&mut context.event_hub).await; // manager registered event
+
+        context
+            .manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::UpsertWorkflow {
+                    definition: WorkflowDefinition {
+                        name: Arc::new("workflow".to_string()),
+                        routed_by_reactor: false,
+                        max_stream_lifetime: None,
+                        max_cached_media_bytes: None,
+                        watcher_limit: None,
+                        steps: Vec::new(),
+                    },
+                    response_channel: None,
+                },
+            })
+            .expect("Failed to send upsert request");
+
+        let (sender, receiver) = channel();
+        context
+            .manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::FlushStreamCaches {
+                    workflow_name: Arc::new("workflow".to_string()),
+                    stream_id: None,
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send flush request");
+
+        let response = test_utils::expect_oneshot_response(receiver).await;
+        assert_eq!(
+            response,
+            Some(FlushCachesResult {
+                flushed_stream_count: 0,
+                refresh_requested: false,
+            }),
+            "Unexpected result"
+        );
+    }
+
+    #[tokio::test]
+    async fn setting_workflow_log_level_updates_manager_overrides() {
+        let mut context = TestContext::new();
+        test_utils::expect_mpsc_response(&mut context.event_hub).await; // manager registered event
+
+        let workflow_name = Arc::new("log_level_test_workflow".to_string());
+        context
+            .manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::SetWorkflowLogLevel {
+                    workflow_name: workflow_name.clone(),
+                    level: Some(LevelFilter::DEBUG),
+                },
+            })
+            .expect("Failed to send set log level request");
+
+        // Requests are handled in order, so once this responds the log level has been set
+        let (sender, receiver) = channel();
+        context
+            .manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::GetRunningWorkflows {
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send list workflow request");
+
+        test_utils::expect_oneshot_response(receiver).await;
+        let level = context.log_levels.get(&workflow_name);
+        assert_eq!(level, Some(LevelFilter::DEBUG), "Unexpected log level");
+    }
+
+    #[tokio::test]
+    async fn upserts_rejected_in_maintenance_mode_and_accepted_after_exiting() {
+        let context = TestContext::new();
+        let upsert = |name: &str| {
+            let (sender, receiver) = channel();
+            context
+                .manager
+                .send(WorkflowManagerRequest {
+                    request_id: "".to_string(),
+                    operation: WorkflowManagerRequestOperation::UpsertWorkflow {
+                        definition: WorkflowDefinition {
+                            name: Arc::new(name.to_string()),
+                            routed_by_reactor: false,
+                            max_stream_lifetime: None,
+                            max_cached_media_bytes: None,
+                            watcher_limit: None,
+                            steps: Vec::new(),
+                        },
+                        response_channel: Some(sender),
+                    },
+                })
+                .expect("Failed to send upsert request");
+
+            receiver
+        };
+
+        let set_maintenance_mode = |enabled| {
+            context
+                .manager
+                .send(WorkflowManagerRequest {
+                    request_id: "".to_string(),
+                    operation: WorkflowManagerRequestOperation::SetMaintenanceMode { enabled },
+                })
+                .expect("Failed to send maintenance mode request");
+        };
+
+        let get_workflow_names = || {
+            let (sender, receiver) = channel();
+            context
+                .manager
+                .send(WorkflowManagerRequest {
+                    request_id: "".to_string(),
+                    operation: WorkflowManagerRequestOperation::GetRunningWorkflows {
+                        response_channel: sender,
+                    },
+                })
+                .expect("Failed to send get workflows request");
+
+            receiver
+        };
+
+        let result = test_utils::expect_oneshot_response(upsert("existing")).await;
+        assert_eq!(result, UpsertWorkflowResult::Upserted, "Unexpected result");
+
+        set_maintenance_mode(true);
+
+        let (sender, receiver) = channel();
+        context
+            .manager
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::GetMaintenanceMode {
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send get maintenance mode request");
+
+        let in_maintenance = test_utils::expect_oneshot_response(receiver).await;
+        assert!(in_maintenance, "Expected manager to be in maintenance mode");
+
+        let result = test_utils::expect_oneshot_response(upsert("new")).await;
+        assert_eq!(
+            result,
+            UpsertWorkflowResult::InMaintenance,
+            "Unexpected result"
+        );
+
+        // The workflow running before maintenance started keeps running
+        let workflows = test_utils::expect_oneshot_response(get_workflow_names()).await;
+        let names = workflows
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, vec!["existing"], "Unexpected running workflows");
+
+        set_maintenance_mode(false);
+
+        let result = test_utils::expect_oneshot_response(upsert("new")).await;
+        assert_eq!(result, UpsertWorkflowResult::Upserted, "Unexpected result");
+
+        let workflows = test_utils::expect_oneshot_response(get_workflow_names()).await;
+        let names = workflows
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            names,
+            vec!["new", "existing"],
+            "Unexpected running workflows"
+        );
+    }
 }
@@ -0,0 +1,676 @@
+//! The workflow manager router allows multiple workflow managers to run side by side, with each
+//! manager owning a subset of workflows. Requests are routed to a manager based on the longest
+//! configured prefix that matches the workflow's name, with requests for workflows that don't
+//! match any prefix going to a default manager. Since reactor managed workflows are usually named
+//! after the stream they are for, this allows streams to be sharded across managers by stream
+//! name.
+//!
+//! The router accepts the same requests as a workflow manager, so anything that can talk to a
+//! workflow manager can talk to the router instead. Requests that aren't for a single workflow
+//! (such as listing running workflows) are sent to every manager and the responses are combined.
+//! Managers behind the router should be started with `start_routed_workflow_manager()` so only
+//! the router is registered with the event hub.
+
+use crate::actor_utils::{notify_on_future_completion, notify_on_unbounded_recv};
+use crate::event_hub::{PublishEventRequest, WorkflowManagerEvent};
+use crate::workflows::manager::{
+    GetWorkflowResponse, RenameWorkflowResult, SwapWorkflowsResult, WorkflowManagerRequest,
+    WorkflowManagerRequestOperation,
+};
+use crate::workflows::WorkflowResourceUsage;
+use std::sync::Arc;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot::{channel, Sender};
+use tracing::{info, instrument, warn};
+
+/// Routes workflow manager requests to one of multiple workflow managers based on the prefix of
+/// the workflow's name
+pub struct WorkflowManagerRouter {
+    default_manager: UnboundedSender<WorkflowManagerRequest>,
+    routes: Vec<(String, UnboundedSender<WorkflowManagerRequest>)>,
+}
+
+enum FutureResult {
+    AllConsumersGone,
+    RequestReceived(WorkflowManagerRequest),
+    RunningWorkflowsReceived {
+        responses: Vec<Vec<GetWorkflowResponse>>,
+        response_channel: Sender<Vec<GetWorkflowResponse>>,
+    },
+
+    ReapedStreamCountsReceived {
+        counts: Vec<u64>,
+        response_channel: Sender<u64>,
+    },
+
+    ResourceUsageReceived {
+        responses: Vec<Vec<WorkflowResourceUsage>>,
+        response_channel: Sender<Vec<WorkflowResourceUsage>>,
+    },
+
+    MaintenanceModesReceived {
+        modes: Vec<bool>,
+        response_channel: Sender<bool>,
+    },
+}
+
+struct Actor {
+    router: WorkflowManagerRouter,
+    internal_sender: UnboundedSender<FutureResult>,
+}
+
+impl WorkflowManagerRouter {
+    /// Creates a new router, where any workflow that doesn't match a route's prefix is managed by
+    /// the specified default manager
+    pub fn new(default_manager: UnboundedSender<WorkflowManagerRequest>) -> Self {
+        WorkflowManagerRouter {
+            default_manager,
+            routes: Vec::new(),
+        }
+    }
+
+    /// Routes all workflows with names starting with the specified prefix to the specified
+    /// manager. When multiple prefixes match a workflow's name, the longest prefix wins.
+    pub fn add_route(
+        &mut self,
+        prefix: String,
+        manager: UnboundedSender<WorkflowManagerRequest>,
+    ) -> &mut Self {
+        self.routes.retain(|(existing, _)| *existing != prefix);
+        self.routes.push((prefix, manager));
+        self.routes
+            .sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+
+        self
+    }
+
+    /// Gets the workflow manager that owns the workflow with the specified name
+    pub fn manager_for(&self, workflow_name: &str) -> &UnboundedSender<WorkflowManagerRequest> {
+        self.routes
+            .iter()
+            .find(|(prefix, _)| workflow_name.starts_with(prefix.as_str()))
+            .map(|(_, manager)| manager)
+            .unwrap_or(&self.default_manager)
+    }
+
+    /// Starts the router, returning the channel workflow manager requests should be sent to. The
+    /// router registers itself with the event hub as the active workflow manager.
+    pub fn start(
+        self,
+        event_hub_publisher: UnboundedSender<PublishEventRequest>,
+    ) -> UnboundedSender<WorkflowManagerRequest> {
+        let (sender, receiver) = unbounded_channel();
+        let (actor_sender, actor_receiver) = unbounded_channel();
+        let actor = Actor::new(self, receiver, actor_sender);
+
+        let _ = event_hub_publisher.send(PublishEventRequest::WorkflowManagerEvent(
+            WorkflowManagerEvent::WorkflowManagerRegistered {
+                channel: sender.clone(),
+            },
+        ));
+
+        tokio::spawn(actor.run(actor_receiver));
+
+        sender
+    }
+
+    fn all_managers(&self) -> impl Iterator<Item = &UnboundedSender<WorkflowManagerRequest>> {
+        std::iter::once(&self.default_manager).chain(self.routes.iter().map(|(_, x)| x))
+    }
+}
+
+impl Actor {
+    fn new(
+        router: WorkflowManagerRouter,
+        request_receiver: UnboundedReceiver<WorkflowManagerRequest>,
+        actor_sender: UnboundedSender<FutureResult>,
+    ) -> Self {
+        notify_on_unbounded_recv(
+            request_receiver,
+            actor_sender.clone(),
+            FutureResult::RequestReceived,
+            || FutureResult::AllConsumersGone,
+        );
+
+        Actor {
+            router,
+            internal_sender: actor_sender,
+        }
+    }
+
+    #[instrument(name = "Workflow Manager Router Execution", skip_all)]
+    async fn run(mut self, mut actor_receiver: UnboundedReceiver<FutureResult>) {
+        info!(
+            "Starting workflow manager router with {} routes",
+            self.router.routes.len()
+        );
+
+        while let Some(result) = actor_receiver.recv().await {
+            match result {
+                FutureResult::AllConsumersGone => {
+                    info!("All consumers gone");
+                    break;
+                }
+
+                FutureResult::RequestReceived(request) => {
+                    self.handle_request(request);
+                }
+
+                FutureResult::RunningWorkflowsReceived {
+                    responses,
+                    response_channel,
+                } => {
+                    let mut workflows = responses.into_iter().flatten().collect::<Vec<_>>();
+
+                    // Keep the same ordering as a single workflow manager
+                    workflows.sort_by(|a, b| b.name.cmp(&a.name));
+
+                    let _ = response_channel.send(workflows);
+                }
+
+                FutureResult::ReapedStreamCountsReceived {
+                    counts,
+                    response_channel,
+                } => {
+                    let _ = response_channel.send(counts.into_iter().sum());
+                }
+
+                FutureResult::ResourceUsageReceived {
+                    responses,
+                    response_channel,
+                } => {
+                    let mut usages = responses.into_iter().flatten().collect::<Vec<_>>();
+
+                    // Keep the same ordering as a single workflow manager
+                    usages.sort_by(|a, b| a.workflow_name.cmp(&b.workflow_name));
+
+                    let _ = response_channel.send(usages);
+                }
+
+                FutureResult::MaintenanceModesReceived {
+                    modes,
+                    response_channel,
+                } => {
+                    // Upserts may be rejected if any manager is in maintenance mode
+                    let _ = response_channel.send(modes.into_iter().any(|x| x));
+                }
+            }
+        }
+
+        info!("Workflow manager router closing");
+    }
+
+    #[instrument(skip(self, request), fields(request_id = %request.request_id))]
+    fn handle_request(&mut self, request: WorkflowManagerRequest) {
+        let workflow_name = match &request.operation {
+            WorkflowManagerRequestOperation::UpsertWorkflow { definition, .. } => {
+                Some(definition.name.clone())
+            }
+
+            WorkflowManagerRequestOperation::StopWorkflow { name } => Some(name.clone()),
+            WorkflowManagerRequestOperation::ValidateWorkflow { definition, .. } => {
+                Some(definition.name.clone())
+            }
+
+            WorkflowManagerRequestOperation::GetWorkflowDetails { name, .. } => Some(name.clone()),
+            WorkflowManagerRequestOperation::FlushStreamCaches { workflow_name, .. } => {
+                Some(workflow_name.clone())
+            }
+
+            WorkflowManagerRequestOperation::SetWorkflowLogLevel { workflow_name, .. } => {
+                Some(workflow_name.clone())
+            }
+
+            WorkflowManagerRequestOperation::GetRunningWorkflows { .. } => None,
+            WorkflowManagerRequestOperation::GetReapedStreamCount { .. } => None,
+            WorkflowManagerRequestOperation::GetResourceUsage { .. } => None,
+            WorkflowManagerRequestOperation::SetMaintenanceMode { .. } => None,
+            WorkflowManagerRequestOperation::GetMaintenanceMode { .. } => None,
+
+            // Need to be checked against both names before being forwarded
+            WorkflowManagerRequestOperation::RenameWorkflow { .. } => None,
+            WorkflowManagerRequestOperation::SwapWorkflows { .. } => None,
+        };
+
+        if let Some(name) = workflow_name {
+            self.forward(name, request);
+            return;
+        }
+
+        let request_id = request.request_id;
+        match request.operation {
+            WorkflowManagerRequestOperation::GetRunningWorkflows { response_channel } => {
+                let receivers = self
+                    .router
+                    .all_managers()
+                    .map(|manager| {
+                        let (sender, receiver) = channel();
+                        let _ = manager.send(WorkflowManagerRequest {
+                            request_id: request_id.clone(),
+                            operation: WorkflowManagerRequestOperation::GetRunningWorkflows {
+                                response_channel: sender,
+                            },
+                        });
+
+                        receiver
+                    })
+                    .collect::<Vec<_>>();
+
+                notify_on_future_completion(
+                    futures::future::join_all(receivers),
+                    self.internal_sender.clone(),
+                    move |results| FutureResult::RunningWorkflowsReceived {
+                        responses: results.into_iter().filter_map(|x| x.ok()).collect(),
+                        response_channel,
+                    },
+                );
+            }
+
+            WorkflowManagerRequestOperation::GetReapedStreamCount { response_channel } => {
+                let receivers = self
+                    .router
+                    .all_managers()
+                    .map(|manager| {
+                        let (sender, receiver) = channel();
+                        let _ = manager.send(WorkflowManagerRequest {
+                            request_id: request_id.clone(),
+                            operation: WorkflowManagerRequestOperation::GetReapedStreamCount {
+                                response_channel: sender,
+                            },
+                        });
+
+                        receiver
+                    })
+                    .collect::<Vec<_>>();
+
+                notify_on_future_completion(
+                    futures::future::join_all(receivers),
+                    self.internal_sender.clone(),
+                    move |results| FutureResult::ReapedStreamCountsReceived {
+                        counts: results.into_iter().filter_map(|x| x.ok()).collect(),
+                        response_channel,
+                    },
+                );
+            }
+
+            WorkflowManagerRequestOperation::GetResourceUsage { response_channel } => {
+                let receivers = self
+                    .router
+                    .all_managers()
+                    .map(|manager| {
+                        let (sender, receiver) = channel();
+                        let _ = manager.send(WorkflowManagerRequest {
+                            request_id: request_id.clone(),
+                            operation: WorkflowManagerRequestOperation::GetResourceUsage {
+                                response_channel: sender,
+                            },
+                        });
+
+                        receiver
+                    })
+                    .collect::<Vec<_>>();
+
+                notify_on_future_completion(
+                    futures::future::join_all(receivers),
+                    self.internal_sender.clone(),
+                    move |results| FutureResult::ResourceUsageReceived {
+                        responses: results.into_iter().filter_map(|x| x.ok()).collect(),
+                        response_channel,
+                    },
+                );
+            }
+
+            WorkflowManagerRequestOperation::SetMaintenanceMode { enabled } => {
+                for manager in self.router.all_managers() {
+                    let _ = manager.send(WorkflowManagerRequest {
+                        request_id: request_id.clone(),
+                        operation: WorkflowManagerRequestOperation::SetMaintenanceMode { enabled },
+                    });
+                }
+            }
+
+            WorkflowManagerRequestOperation::GetMaintenanceMode { response_channel } => {
+                let receivers = self
+                    .router
+                    .all_managers()
+                    .map(|manager| {
+                        let (sender, receiver) = channel();
+                        let _ = manager.send(WorkflowManagerRequest {
+                            request_id: request_id.clone(),
+                            operation: WorkflowManagerRequestOperation::GetMaintenanceMode {
+                                response_channel: sender,
+                            },
+                        });
+
+                        receiver
+                    })
+                    .collect::<Vec<_>>();
+
+                notify_on_future_completion(
+                    futures::future::join_all(receivers),
+                    self.internal_sender.clone(),
+                    move |results| FutureResult::MaintenanceModesReceived {
+                        modes: results.into_iter().filter_map(|x| x.ok()).collect(),
+                        response_channel,
+                    },
+                );
+            }
+
+            WorkflowManagerRequestOperation::RenameWorkflow {
+                old_name,
+                new_name,
+                response_channel,
+            } => {
+                let old_manager = self.router.manager_for(&old_name);
+                if !old_manager.same_channel(self.router.manager_for(&new_name)) {
+                    warn!(
+                        workflow_name = %old_name,
+                        "Workflow '{}' cannot be renamed to '{}', as that name is routed to a \
+                        different workflow manager", old_name, new_name,
+                    );
+
+                    let _ = response_channel.send(RenameWorkflowResult::NameOwnedByOtherManager);
+                    return;
+                }
+
+                let request = WorkflowManagerRequest {
+                    request_id,
+                    operation: WorkflowManagerRequestOperation::RenameWorkflow {
+                        old_name: old_name.clone(),
+                        new_name,
+                        response_channel,
+                    },
+                };
+
+                self.forward(old_name, request);
+            }
+
+            WorkflowManagerRequestOperation::SwapWorkflows {
+                name_a,
+                name_b,
+                response_channel,
+            } => {
+                let manager_a = self.router.manager_for(&name_a);
+                if !manager_a.same_channel(self.router.manager_for(&name_b)) {
+                    warn!(
+                        workflow_name = %name_a,
+                        "Workflow '{}' cannot be swapped with '{}', as they are routed to \
+                        different workflow managers", name_a, name_b,
+                    );
+
+                    let _ = response_channel.send(SwapWorkflowsResult::NameOwnedByOtherManager);
+                    return;
+                }
+
+                let request = WorkflowManagerRequest {
+                    request_id,
+                    operation: WorkflowManagerRequestOperation::SwapWorkflows {
+                        name_a: name_a.clone(),
+                        name_b,
+                        response_channel,
+                    },
+                };
+
+                self.forward(name_a, request);
+            }
+
+            _ => unreachable!("Workflow specific requests are forwarded above"),
+        }
+    }
+
+    fn forward(&self, workflow_name: Arc<String>, request: WorkflowManagerRequest) {
+        let manager = self.router.manager_for(&workflow_name);
+        if manager.send(request).is_err() {
+            warn!(
+                workflow_name = %workflow_name,
+                "Workflow manager for workflow '{}' is gone, request dropped", workflow_name
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+    use crate::workflows::definitions::WorkflowDefinition;
+
+    struct TestContext {
+        router: UnboundedSender<WorkflowManagerRequest>,
+        event_hub: UnboundedReceiver<PublishEventRequest>,
+        default_manager: UnboundedReceiver<WorkflowManagerRequest>,
+        live_manager: UnboundedReceiver<WorkflowManagerRequest>,
+        live_east_manager: UnboundedReceiver<WorkflowManagerRequest>,
+    }
+
+    impl TestContext {
+        fn new() -> Self {
+            let (event_hub_sender, event_hub) = unbounded_channel();
+            let (default_sender, default_manager) = unbounded_channel();
+            let (live_sender, live_manager) = unbounded_channel();
+            let (live_east_sender, live_east_manager) = unbounded_channel();
+
+            let mut router = WorkflowManagerRouter::new(default_sender);
+            router
+                .add_route("live".to_string(), live_sender)
+                .add_route("live_east".to_string(), live_east_sender);
+
+            TestContext {
+                router: router.start(event_hub_sender),
+                event_hub,
+                default_manager,
+                live_manager,
+                live_east_manager,
+            }
+        }
+
+        fn upsert(&self, name: &str) {
+            self.router
+                .send(WorkflowManagerRequest {
+                    request_id: "".to_string(),
+                    operation: WorkflowManagerRequestOperation::UpsertWorkflow {
+                        definition: WorkflowDefinition {
+                            name: Arc::new(name.to_string()),
+                            routed_by_reactor: false,
+                            max_stream_lifetime: None,
+                            max_cached_media_bytes: None,
+                            watcher_limit: None,
+                            steps: Vec::new(),
+                        },
+                        response_channel: None,
+                    },
+                })
+                .expect("Failed to send upsert request");
+        }
+    }
+
+    fn expect_upsert_for(request: WorkflowManagerRequest, expected_name: &str) {
+        match request.operation {
+            WorkflowManagerRequestOperation::UpsertWorkflow { definition, .. } => {
+                assert_eq!(
+                    definition.name.as_str(),
+                    expected_name,
+                    "Unexpected workflow name"
+                );
+            }
+
+            operation => panic!("Expected upsert request, instead got {:?}", operation),
+        }
+    }
+
+    #[tokio::test]
+    async fn router_registers_with_event_hub() {
+        let mut context = TestContext::new();
+
+        let event = test_utils::expect_mpsc_response(&mut context.event_hub).await;
+        match event {
+            PublishEventRequest::WorkflowManagerEvent(
+                WorkflowManagerEvent::WorkflowManagerRegistered { .. },
+            ) => (),
+
+            event => panic!("Expected workflow manager event, instead got {:?}", event),
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_routed_to_manager_with_matching_prefix() {
+        let mut context = TestContext::new();
+        context.upsert("live_abc");
+
+        let request = test_utils::expect_mpsc_response(&mut context.live_manager).await;
+        expect_upsert_for(request, "live_abc");
+
+        test_utils::expect_mpsc_timeout(&mut context.default_manager).await;
+        test_utils::expect_mpsc_timeout(&mut context.live_east_manager).await;
+    }
+
+    #[tokio::test]
+    async fn longest_matching_prefix_wins() {
+        let mut context = TestContext::new();
+        context.upsert("live_east_abc");
+
+        let request = test_utils::expect_mpsc_response(&mut context.live_east_manager).await;
+        expect_upsert_for(request, "live_east_abc");
+
+        test_utils::expect_mpsc_timeout(&mut context.live_manager).await;
+    }
+
+    #[tokio::test]
+    async fn unmatched_requests_routed_to_default_manager() {
+        let mut context = TestContext::new();
+        context.upsert("vod_abc");
+
+        let request = test_utils::expect_mpsc_response(&mut context.default_manager).await;
+        expect_upsert_for(request, "vod_abc");
+
+        test_utils::expect_mpsc_timeout(&mut context.live_manager).await;
+        test_utils::expect_mpsc_timeout(&mut context.live_east_manager).await;
+    }
+
+    #[tokio::test]
+    async fn stop_requests_routed_by_workflow_name() {
+        let mut context = TestContext::new();
+        context
+            .router
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::StopWorkflow {
+                    name: Arc::new("live_abc".to_string()),
+                },
+            })
+            .expect("Failed to send stop request");
+
+        let request = test_utils::expect_mpsc_response(&mut context.live_manager).await;
+        match request.operation {
+            WorkflowManagerRequestOperation::StopWorkflow { name } => {
+                assert_eq!(name.as_str(), "live_abc", "Unexpected workflow name");
+            }
+
+            operation => panic!("Expected stop request, instead got {:?}", operation),
+        }
+    }
+
+    #[tokio::test]
+    async fn running_workflows_aggregated_across_managers() {
+        let mut context = TestContext::new();
+        let (sender, receiver) = channel();
+        context
+            .router
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::GetRunningWorkflows {
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send list request");
+
+        for (manager, name) in [
+            (&mut context.default_manager, "vod_abc"),
+            (&mut context.live_manager, "live_abc"),
+            (&mut context.live_east_manager, "live_east_abc"),
+        ] {
+            let request = test_utils::expect_mpsc_response(manager).await;
+            match request.operation {
+                WorkflowManagerRequestOperation::GetRunningWorkflows { response_channel } => {
+                    let _ = response_channel.send(vec![GetWorkflowResponse {
+                        name: Arc::new(name.to_string()),
+                    }]);
+                }
+
+                operation => panic!("Expected list request, instead got {:?}", operation),
+            }
+        }
+
+        let response = test_utils::expect_oneshot_response(receiver).await;
+        let names = response.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+
+        assert_eq!(
+            names,
+            vec!["vod_abc", "live_east_abc", "live_abc"],
+            "Unexpected workflows"
+        );
+    }
+
+    #[tokio::test]
+    async fn reaped_stream_counts_summed_across_managers() {
+        let mut context = TestContext::new();
+        let (sender, receiver) = channel();
+        context
+            .router
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::GetReapedStreamCount {
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send reaped count request");
+
+        for (manager, count) in [
+            (&mut context.default_manager, 1),
+            (&mut context.live_manager, 2),
+            (&mut context.live_east_manager, 3),
+        ] {
+            let request = test_utils::expect_mpsc_response(manager).await;
+            match request.operation {
+                WorkflowManagerRequestOperation::GetReapedStreamCount { response_channel } => {
+                    let _ = response_channel.send(count);
+                }
+
+                operation => panic!("Expected reaped count request, instead got {:?}", operation),
+            }
+        }
+
+        let response = test_utils::expect_oneshot_response(receiver).await;
+        assert_eq!(response, 6, "Unexpected reaped stream count");
+    }
+
+    #[tokio::test]
+    async fn maintenance_mode_sent_to_every_manager() {
+        let mut context = TestContext::new();
+        context
+            .router
+            .send(WorkflowManagerRequest {
+                request_id: "".to_string(),
+                operation: WorkflowManagerRequestOperation::SetMaintenanceMode { enabled: true },
+            })
+            .expect("Failed to send maintenance mode request");
+
+        for manager in [
+            &mut context.default_manager,
+            &mut context.live_manager,
+            &mut context.live_east_manager,
+        ] {
+            let request = test_utils::expect_mpsc_response(manager).await;
+            match request.operation {
+                WorkflowManagerRequestOperation::SetMaintenanceMode { enabled } => {
+                    assert!(enabled, "Expected maintenance mode to be enabled");
+                }
+
+                operation => panic!(
+                    "Expected maintenance mode request, instead got {:?}",
+                    operation
+                ),
+            }
+        }
+    }
+}
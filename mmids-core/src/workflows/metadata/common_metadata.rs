@@ -13,3 +13,9 @@ pub fn get_is_keyframe_metadata_key(metadata_map: &mut MetadataKeyMap) -> Metada
 pub fn get_pts_offset_metadata_key(metadata_map: &mut MetadataKeyMap) -> MetadataKey {
     metadata_map.register("pts_offset", MetadataValueType::I32)
 }
+
+/// Returns the metadata key for a metadata entry containing a checksum of a media payload, used
+/// to verify payloads are not modified or misrouted as they flow through a workflow.
+pub fn get_payload_checksum_metadata_key(metadata_map: &mut MetadataKeyMap) -> MetadataKey {
+    metadata_map.register("payload_checksum", MetadataValueType::U32)
+}
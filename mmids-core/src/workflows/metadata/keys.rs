@@ -36,6 +36,15 @@ impl MetadataKey {
 
         MetadataKey { klv_id, value_type }
     }
+
+    /// Attempts to create a key from a klv id that came from an untrusted source. Returns `None`
+    /// if the klv id does not have a known value type encoded in it.
+    pub(super) fn try_from_klv_id(klv_id: u16) -> Option<Self> {
+        match klv_id >> VALUE_TYPE_SHIFT {
+            1..=10 => Some(MetadataKey::from_klv_id(klv_id)),
+            _ => None,
+        }
+    }
 }
 
 impl MetadataKeyMap {
@@ -61,6 +61,32 @@ impl KlvStore {
             data: self.data.clone(),
         }
     }
+
+    /// Returns the raw encoded KLV data
+    pub fn raw_data(&self) -> Bytes {
+        self.data.clone()
+    }
+
+    /// Creates a KLV store from data that was previously retrieved via `raw_data()`. The data is
+    /// validated to ensure every item's length fits within the data provided.
+    pub fn from_raw_data(data: Bytes) -> Result<Self> {
+        let mut remaining = data.clone();
+        while !remaining.is_empty() {
+            if remaining.len() < 4 {
+                return Err(anyhow!("Klv data had a truncated item header"));
+            }
+
+            let _key = remaining.get_u16();
+            let length = remaining.get_u16() as usize;
+            if remaining.len() < length {
+                return Err(anyhow!("Klv item length exceeds the remaining data"));
+            }
+
+            remaining.advance(length);
+        }
+
+        Ok(KlvStore { data })
+    }
 }
 
 impl Iterator for KlvIterator {
@@ -83,6 +83,22 @@ pub enum MetadataEntryError {
     ValueTooLarge,
 }
 
+/// Errors that can occur when reading a metadata collection from raw bytes
+#[derive(thiserror::Error, Debug)]
+pub enum MetadataDecodingError {
+    #[error("The metadata's raw data was malformed")]
+    MalformedData,
+
+    #[error("A metadata entry had a key id of {0}, which does not contain a known value type")]
+    UnknownValueType(u16),
+
+    #[error("A metadata entry of type {value_type:?} had an invalid length of {length}")]
+    InvalidValueLength {
+        value_type: MetadataValueType,
+        length: usize,
+    },
+}
+
 impl MediaPayloadMetadataCollection {
     /// Creates a new collection of metadata based on the provided entries. A buffer is passed in
     /// which can allow the creators of the collection to maintain an arena to reduce allocations
@@ -106,6 +122,51 @@ impl MediaPayloadMetadataCollection {
             raw_value: item.value,
         })
     }
+
+    /// Returns the raw bytes backing this collection, which is useful for sending the metadata
+    /// outside of the current process.
+    ///
+    /// Since metadata keys are only consistent with other keys created by the same
+    /// `MetadataKeyMap`, the raw bytes will only be meaningful to a process that registers the
+    /// same metadata keys in the same order.
+    pub fn raw_bytes(&self) -> Bytes {
+        self.data.raw_data()
+    }
+
+    /// Creates a metadata collection from raw bytes previously retrieved from `raw_bytes()`. As
+    /// the bytes may have come from an untrusted source, every entry is validated to ensure it
+    /// can be read safely.
+    pub fn from_raw_bytes(bytes: Bytes) -> Result<Self, MetadataDecodingError> {
+        let data =
+            KlvStore::from_raw_data(bytes).map_err(|_| MetadataDecodingError::MalformedData)?;
+        for item in data.iter() {
+            let key = MetadataKey::try_from_klv_id(item.key)
+                .ok_or(MetadataDecodingError::UnknownValueType(item.key))?;
+
+            let expected_length = match key.value_type {
+                MetadataValueType::U8 | MetadataValueType::I8 | MetadataValueType::Bool => Some(1),
+                MetadataValueType::U16 | MetadataValueType::I16 => Some(2),
+                MetadataValueType::U32 | MetadataValueType::I32 => Some(4),
+                MetadataValueType::U64 | MetadataValueType::I64 => Some(8),
+                MetadataValueType::Bytes => None,
+            };
+
+            if let Some(expected_length) = expected_length {
+                if item.value.len() != expected_length {
+                    return Err(MetadataDecodingError::InvalidValueLength {
+                        value_type: key.value_type,
+                        length: item.value.len(),
+                    });
+                }
+            }
+
+            if key.value_type == MetadataValueType::Bool && item.value[0] > 1 {
+                return Err(MetadataDecodingError::MalformedData);
+            }
+        }
+
+        Ok(MediaPayloadMetadataCollection { data })
+    }
 }
 
 impl MetadataEntry {
@@ -3,14 +3,31 @@
 //! transitions from one step to the next in a linear fashion based on the order in which they
 //! were defined.
 
+pub mod annotations;
+pub mod bootstrap;
 pub mod definitions;
+pub mod discontinuity;
+pub mod kv_source;
+pub mod log_levels;
 pub mod manager;
+pub mod manager_router;
 pub mod metadata;
+pub mod pacing;
 mod runner;
+pub mod serialization;
 pub mod steps;
-
-pub use runner::{start_workflow, WorkflowRequest, WorkflowRequestOperation, WorkflowStatus};
-
+pub mod stream_labels;
+pub mod stream_properties;
+pub mod stream_tags;
+pub mod tracks;
+pub mod validation;
+pub mod watchers;
+
+pub use runner::{
+    start_workflow, ActiveStreamDetails, WorkflowRequest, WorkflowRequestOperation, WorkflowStatus,
+};
+
+use crate::workflows::annotations::PipelineAnnotations;
 use crate::StreamId;
 use bytes::Bytes;
 use std::collections::HashMap;
@@ -18,10 +35,10 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use crate::workflows::metadata::MediaPayloadMetadataCollection;
-pub use runner::{WorkflowState, WorkflowStepState};
+pub use runner::{FlushCachesResult, WorkflowResourceUsage, WorkflowState, WorkflowStepState};
 
 /// Identifies the category of media contained within a payload
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum MediaType {
     Audio,
     Video,
@@ -36,6 +53,10 @@ pub struct MediaNotification {
 
     /// The content of the notification message
     pub content: MediaNotificationContent,
+
+    /// Transient values steps attach to this notification for other steps in the same mmids
+    /// instance to read. These are never serialized and are ignored for equality.
+    pub annotations: PipelineAnnotations,
 }
 
 /// The detailed information contained within a media notification
@@ -0,0 +1,135 @@
+//! Utilities for releasing media at the rate it was originally produced. Sources that read media
+//! from files (or any other source that can provide media faster than real time) would otherwise
+//! raise all of their media at once, overwhelming any steps that come after them.
+//!
+//! A `MediaPacer` maps media timestamps onto the wall clock. The first timestamp seen (or the one
+//! explicitly provided to `start()`) is anchored to the current time, and every later timestamp is
+//! released once the same amount of time has passed, divided by the speed multiplier. A speed of
+//! `2.0` releases media twice as fast as real time, while `0.5` releases it at half speed.
+
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Releases media based on its timestamp relative to the wall clock
+#[derive(Clone, Debug)]
+pub struct MediaPacer {
+    speed: f64,
+    anchor: Option<Anchor>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Anchor {
+    started_at: Instant,
+    base_timestamp: Duration,
+}
+
+impl MediaPacer {
+    /// Creates a new pacer with the specified speed multiplier. Panics if the speed is not a
+    /// finite number greater than zero.
+    pub fn new(speed: f64) -> Self {
+        assert!(
+            speed.is_finite() && speed > 0.0,
+            "Pacing speed must be a number greater than zero"
+        );
+
+        MediaPacer {
+            speed,
+            anchor: None,
+        }
+    }
+
+    /// The speed multiplier media is released at
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Anchors the specified timestamp to the current time. This should be called whenever the
+    /// source's timestamps restart, such as when a file is looped.
+    pub fn start(&mut self, base_timestamp: Duration) {
+        self.anchor = Some(Anchor {
+            started_at: Instant::now(),
+            base_timestamp,
+        });
+    }
+
+    /// Returns the point in time that media with the specified timestamp should be released at.
+    /// If the pacer has not been started yet, it is started with this timestamp. Timestamps
+    /// earlier than the anchored timestamp are due immediately.
+    pub fn release_time(&mut self, timestamp: Duration) -> Instant {
+        let anchor = match self.anchor {
+            Some(anchor) => anchor,
+            None => {
+                self.start(timestamp);
+                self.anchor.unwrap()
+            }
+        };
+
+        let offset = timestamp.saturating_sub(anchor.base_timestamp);
+        anchor.started_at + offset.div_f64(self.speed)
+    }
+
+    /// Waits until media with the specified timestamp is due to be released
+    pub async fn wait_until_due(&mut self, timestamp: Duration) {
+        let release_at = self.release_time(timestamp);
+        tokio::time::sleep_until(release_at).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn real_time_pacing_tracks_media_timestamps() {
+        let tolerance = Duration::from_millis(25);
+        let timestamps = [1000, 1000, 1040, 1080, 1120, 1200, 1250];
+        let mut pacer = MediaPacer::new(1.0);
+
+        let started_at = std::time::Instant::now();
+        for timestamp in timestamps {
+            pacer.wait_until_due(Duration::from_millis(timestamp)).await;
+
+            let elapsed = started_at.elapsed();
+            let expected = Duration::from_millis(timestamp - timestamps[0]);
+            assert!(
+                elapsed >= expected,
+                "Media with timestamp {}ms was released early ({:?} elapsed)",
+                timestamp,
+                elapsed
+            );
+
+            assert!(
+                elapsed - expected <= tolerance,
+                "Media with timestamp {}ms was released {:?} late",
+                timestamp,
+                elapsed - expected
+            );
+        }
+    }
+
+    #[test]
+    fn speed_multiplier_scales_release_times() {
+        let mut pacer = MediaPacer::new(2.0);
+        pacer.start(Duration::from_secs(10));
+
+        let start = pacer.release_time(Duration::from_secs(10));
+        let later = pacer.release_time(Duration::from_secs(12));
+
+        assert_eq!(
+            later - start,
+            Duration::from_secs(1),
+            "Unexpected release time"
+        );
+    }
+
+    #[test]
+    fn timestamps_before_the_anchor_are_due_immediately() {
+        let mut pacer = MediaPacer::new(1.0);
+        pacer.start(Duration::from_secs(10));
+
+        let start = pacer.release_time(Duration::from_secs(10));
+        let earlier = pacer.release_time(Duration::from_secs(5));
+
+        assert_eq!(earlier, start, "Unexpected release time");
+    }
+}
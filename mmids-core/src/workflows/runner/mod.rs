@@ -1,12 +1,17 @@
 #[cfg(test)]
 mod test_context;
 #[cfg(test)]
-mod test_steps;
+pub(crate) mod test_steps;
 #[cfg(test)]
 mod tests;
+mod watchdog;
 
-use crate::actor_utils::notify_on_unbounded_recv;
+use crate::actor_utils::{notify_on_cancellation, notify_on_unbounded_recv};
+use crate::channel_metrics::ChannelDepthGauge;
 use crate::workflows::definitions::{WorkflowDefinition, WorkflowStepDefinition, WorkflowStepId};
+use crate::workflows::discontinuity::discontinuity_notification;
+use crate::workflows::runner::watchdog::{StepWatchdog, WatchdogCheck};
+use crate::workflows::steps::buffer_stats::BufferStats;
 use crate::workflows::steps::factory::WorkflowStepFactory;
 use crate::workflows::steps::futures_channel::{
     FuturesChannelInnerResult, FuturesChannelResult, WorkflowStepFuturesChannel,
@@ -14,14 +19,35 @@ use crate::workflows::steps::futures_channel::{
 use crate::workflows::steps::{
     StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
 };
-use crate::workflows::{MediaNotification, MediaNotificationContent};
+use crate::workflows::stream_labels::get_announced_label;
+use crate::workflows::stream_tags::{get_announced_tags, Tags};
+use crate::workflows::tracks::track_added_notification;
+use crate::workflows::watchers::WorkflowWatchers;
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
 use crate::StreamId;
+use serde::Serialize;
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::sync::oneshot::Sender;
-use tracing::{error, info, instrument, span, warn, Level};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, info_span, instrument, span, warn, Instrument, Level, Span};
+
+/// The number of bytes of required-for-decoding media cached per stream when the workflow
+/// definition doesn't specify a limit. Sequence headers are normally tiny, so this is only reached
+/// when a source is flagging far more media as required than it should.
+const DEFAULT_MAX_CACHED_MEDIA_BYTES: usize = 1024 * 1024;
+
+/// The value of the discontinuity metadata sent to later steps when a step is restarted by its
+/// watchdog
+const WATCHDOG_RESTART_DISCONTINUITY: &str = "watchdog_restart";
+
+/// How far into a stream's media a track has to first appear for it to be announced as a late
+/// track. Encoders commonly start their tracks a few frames apart, which isn't worth disrupting
+/// later steps over.
+const LATE_TRACK_THRESHOLD: Duration = Duration::from_secs(1);
 
 /// A request to the workflow to perform an action
 #[derive(Debug)]
@@ -37,7 +63,8 @@ pub enum WorkflowRequestOperation {
     /// Requests the workflow update with a new definition. The workflow will take shape to look
     /// exactly as the specified definition has.  Any existing steps that aren't specified will
     /// be removed, any new steps will be created, and any steps that stay will reflect the order
-    /// specified.
+    /// specified. Steps that stay keep their existing instance even if they are moved, unless
+    /// they have seen a stream that will no longer flow through them in their new position.
     UpdateDefinition { new_definition: WorkflowDefinition },
 
     /// Requests the workflow to return a snapshot of its current state
@@ -50,6 +77,82 @@ pub enum WorkflowRequestOperation {
 
     /// Sends a media notification to this stream
     MediaNotification { media: MediaNotification },
+
+    /// Requests details about all streams currently flowing through the workflow
+    GetActiveStreams {
+        response_channel: Sender<Vec<ActiveStreamDetails>>,
+    },
+
+    /// Requests the workflow to treat the specified stream as disconnected. Steps after the step
+    /// the stream originated from will receive a stream disconnection notification, and any
+    /// media cached for the stream will be dropped.
+    DisconnectStream { stream_id: StreamId },
+
+    /// Requests estimates of the resources the workflow is currently holding onto
+    GetResourceUsage {
+        response_channel: Sender<WorkflowResourceUsage>,
+    },
+
+    /// Requests the workflow identify itself by a new name. The workflow's steps and active
+    /// streams are not affected.
+    Rename { new_name: Arc<String> },
+
+    /// Requests the workflow discard the sequence headers it has cached for the specified stream
+    /// (or all active streams if none is specified), and ask its steps to produce fresh ones. This
+    /// allows streams stuck with bad sequence headers to recover without being restarted.
+    FlushCaches {
+        stream_id: Option<StreamId>,
+        response_channel: Sender<Option<FlushCachesResult>>,
+    },
+}
+
+/// The outcome of a request to flush a workflow's caches
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlushCachesResult {
+    /// The number of active streams whose cached sequence headers were discarded
+    pub flushed_stream_count: usize,
+
+    /// If any step was able to request fresh sequence headers for the flushed streams
+    pub refresh_requested: bool,
+}
+
+/// Information about a stream that is currently flowing through a workflow
+#[derive(Debug)]
+pub struct ActiveStreamDetails {
+    pub stream_id: StreamId,
+
+    /// The step that first raised the new incoming stream notification for this stream
+    pub originating_step_id: WorkflowStepId,
+
+    /// How long it has been since the workflow last saw a media payload for this stream. If no
+    /// payloads have been seen yet, then this is the time since the stream was first announced.
+    pub time_since_last_media: Duration,
+
+    /// The purpose label most recently announced for this stream, if any
+    pub label: Option<Arc<String>>,
+
+    /// The custom tags most recently announced for this stream
+    pub tags: Tags,
+}
+
+/// Estimates of the resources a workflow is currently using, for capacity planning
+#[derive(Clone, Debug, Serialize)]
+pub struct WorkflowResourceUsage {
+    pub workflow_name: String,
+    pub active_streams: usize,
+
+    /// The number of media pipelines (such as transcodes) running across all active steps
+    pub active_pipelines: usize,
+
+    /// The number of media notifications cached for replaying to new steps
+    pub cached_media_count: usize,
+
+    /// The approximate number of bytes of media payloads held in the workflow's caches
+    pub cached_media_bytes: usize,
+
+    /// The number of required-for-decoding payloads that were not cached, because caching them
+    /// would have exceeded their stream's cache limit
+    pub cache_limit_exceeded_count: u64,
 }
 
 #[derive(Debug)]
@@ -57,6 +160,12 @@ pub struct WorkflowState {
     pub status: WorkflowStatus,
     pub active_steps: Vec<WorkflowStepState>,
     pub pending_steps: Vec<WorkflowStepState>,
+
+    /// The number of watchers currently counted across all of the workflow's output steps
+    pub watcher_count: usize,
+
+    /// The maximum number of watchers the workflow allows, if it has a limit
+    pub max_watchers: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -64,6 +173,16 @@ pub struct WorkflowStepState {
     pub step_id: WorkflowStepId,
     pub definition: WorkflowStepDefinition,
     pub status: StepStatus,
+
+    /// Step specific details about the step's current state
+    pub details: HashMap<String, String>,
+
+    /// The number of times the step was restarted by its watchdog, due to it not producing any
+    /// output for the media it was given
+    pub restart_count: u64,
+
+    /// Details about the media the step is holding onto, if it's a step that buffers media
+    pub buffer_stats: Option<BufferStats>,
 }
 
 #[derive(PartialEq, Eq, Clone, Debug)]
@@ -75,44 +194,94 @@ pub enum WorkflowStatus {
     },
 }
 
-/// Starts the execution of a workflow with the specified definition
+/// Starts the execution of a workflow with the specified definition. The workflow runs until all
+/// of its request senders are gone, it's told to stop, or the shutdown token is cancelled.
 pub fn start_workflow(
     definition: WorkflowDefinition,
     step_factory: Arc<WorkflowStepFactory>,
+    shutdown_token: CancellationToken,
 ) -> UnboundedSender<WorkflowRequest> {
     let (sender, receiver) = unbounded_channel();
     let (actor_sender, actor_receiver) = unbounded_channel();
+    notify_on_cancellation(shutdown_token, actor_sender.clone(), || {
+        FutureResult::ShutdownRequested
+    });
+
     let actor = Actor::new(&definition, step_factory, receiver, actor_sender);
-    tokio::spawn(actor.run(definition, actor_receiver));
+    let span = actor.span.clone();
+    tokio::spawn(actor.run(definition, actor_receiver).instrument(span));
 
     sender
 }
 
 enum FutureResult {
+    ShutdownRequested,
     AllConsumersGone,
     WorkflowRequestReceived(WorkflowRequest),
     StepFutureSendersGone,
     StepFutureResolved(FuturesChannelResult),
+    StreamLifetimeElapsed(StreamId),
+    WatchdogCheckDue(WorkflowStepId),
 }
 
 struct StreamDetails {
     /// The step that first sent a new stream media notification.  We know that if this step is
     /// removed, the stream no longer has a source of video and should be considered disconnected
     originating_step_id: WorkflowStepId,
+
+    /// When the last media payload for this stream was seen
+    last_media_received_at: Instant,
+
+    /// When the stream was first seen by the workflow
+    started_at: Instant,
+
+    /// The label most recently announced for the stream by any step
+    label: Option<Arc<String>>,
+
+    /// The tags most recently announced for the stream by any step
+    tags: Tags,
+
+    /// Span containing the stream's identifiers, which is the parent of all step execution spans
+    /// for the stream's media
+    span: Span,
+
+    /// Step execution spans for this stream, which are created the first time a step processes
+    /// media for the stream and reused for the lifetime of the stream.
+    step_spans: HashMap<WorkflowStepId, Span>,
+
+    /// Whether a warning has been logged about the stream exceeding its cache limit, so a source
+    /// that keeps exceeding it doesn't flood the logs
+    cache_limit_warning_logged: bool,
+
+    /// Timestamp of the stream's first payload that's not required for decoding, which tracks
+    /// appearing later are compared against
+    first_payload_timestamp: Option<Duration>,
+
+    /// The tracks that have had payloads not required for decoding flow for the stream
+    started_tracks: HashSet<MediaType>,
 }
 
 struct TrackedWorkflowStep {
     instance: Option<Box<dyn WorkflowStep + Send>>,
     status: StepStatus,
+
+    /// Span used when the step executes outside the context of a single stream
+    span: Span,
+
+    watchdog: Option<StepWatchdog>,
+    restart_count: u64,
 }
 
 struct Actor {
     name: Arc<String>,
+    span: Span,
+    current_stream: Option<StreamId>,
     steps_by_definition_id: HashMap<WorkflowStepId, TrackedWorkflowStep>,
     active_steps: Vec<WorkflowStepId>,
     pending_steps: Vec<WorkflowStepId>,
     step_inputs: StepInputs,
     step_outputs: StepOutputs,
+
     cached_step_media: HashMap<WorkflowStepId, HashMap<StreamId, Vec<MediaNotification>>>,
     cached_inbound_media: HashMap<StreamId, Vec<MediaNotification>>,
     active_streams: HashMap<StreamId, StreamDetails>,
@@ -120,6 +289,15 @@ struct Actor {
     step_definitions: HashMap<WorkflowStepId, WorkflowStepDefinition>,
     status: WorkflowStatus,
     step_futures_sender: UnboundedSender<FuturesChannelResult>,
+    actor_sender: UnboundedSender<FutureResult>,
+    max_stream_lifetime: Option<Duration>,
+    max_cached_media_bytes: usize,
+    cache_limit_exceeded_count: u64,
+    watchers: WorkflowWatchers,
+
+    /// Records how many media notifications, requests, and step future results are waiting for
+    /// the workflow to process them
+    channel_depth: ChannelDepthGauge,
 }
 
 impl Actor {
@@ -140,13 +318,15 @@ impl Actor {
         let (futures_sender, futures_receiver) = unbounded_channel();
         notify_on_unbounded_recv(
             futures_receiver,
-            actor_sender,
+            actor_sender.clone(),
             FutureResult::StepFutureResolved,
             || FutureResult::StepFutureSendersGone,
         );
 
         Actor {
             name: definition.name.clone(),
+            span: info_span!("Workflow Execution", workflow_name = %definition.name),
+            current_stream: None,
             steps_by_definition_id: HashMap::new(),
             active_steps: Vec::new(),
             pending_steps: Vec::new(),
@@ -159,10 +339,15 @@ impl Actor {
             step_definitions: HashMap::new(),
             status: WorkflowStatus::Running,
             step_futures_sender: futures_sender,
+            actor_sender,
+            max_stream_lifetime: None,
+            max_cached_media_bytes: DEFAULT_MAX_CACHED_MEDIA_BYTES,
+            cache_limit_exceeded_count: 0,
+            watchers: WorkflowWatchers::new(),
+            channel_depth: register_channel_depth_gauge(&definition.name),
         }
     }
 
-    #[instrument(name = "Workflow Execution", skip_all, fields(workflow_name = %self.name))]
     async fn run(
         mut self,
         initial_definition: WorkflowDefinition,
@@ -173,7 +358,13 @@ impl Actor {
         self.apply_new_definition(initial_definition);
 
         while let Some(future) = receiver.recv().await {
+            self.channel_depth.record(receiver.len());
             match future {
+                FutureResult::ShutdownRequested => {
+                    info!("Shutdown requested");
+                    break;
+                }
+
                 FutureResult::AllConsumersGone => {
                     warn!("All channel owners gone");
                     break;
@@ -186,6 +377,14 @@ impl Actor {
                     );
                 }
 
+                FutureResult::StreamLifetimeElapsed(stream_id) => {
+                    self.check_stream_lifetime(stream_id);
+                }
+
+                FutureResult::WatchdogCheckDue(step_id) => {
+                    self.check_step_watchdog(step_id);
+                }
+
                 FutureResult::WorkflowRequestReceived(request) => {
                     let mut stop_workflow = false;
                     self.handle_workflow_request(request, &mut stop_workflow);
@@ -203,6 +402,8 @@ impl Actor {
                         }
 
                         FuturesChannelInnerResult::Media(media) => {
+                            self.current_stream = Some(media.stream_id.clone());
+
                             // Handle the media as if it came as an output of a normal step execution
                             self.step_outputs.clear();
                             self.step_outputs.media.push(media);
@@ -219,6 +420,8 @@ impl Actor {
                                     self.execute_steps(*next_step_id, None, true, false);
                                 }
                             }
+
+                            self.current_stream = None;
                         }
                     }
                 }
@@ -241,6 +444,8 @@ impl Actor {
                     status: self.status.clone(),
                     pending_steps: Vec::new(),
                     active_steps: Vec::new(),
+                    watcher_count: self.watchers.current_count(),
+                    max_watchers: self.watchers.limit().map(|limit| limit.max_watchers),
                 };
 
                 for id in &self.pending_steps {
@@ -250,6 +455,16 @@ impl Actor {
                                 step_id: *id,
                                 definition: definition.clone(),
                                 status: step.status.clone(),
+                                details: step
+                                    .instance
+                                    .as_ref()
+                                    .map(|instance| instance.get_state_details())
+                                    .unwrap_or_default(),
+                                restart_count: step.restart_count,
+                                buffer_stats: step
+                                    .instance
+                                    .as_ref()
+                                    .and_then(|instance| instance.get_buffer_stats()),
                             });
                         } else {
                             state.pending_steps.push(WorkflowStepState {
@@ -258,6 +473,9 @@ impl Actor {
                                 status: StepStatus::Error {
                                     message: "Step not instantiated".to_string(),
                                 },
+                                details: HashMap::new(),
+                                restart_count: 0,
+                                buffer_stats: None,
                             });
                         }
                     } else {
@@ -272,6 +490,16 @@ impl Actor {
                                 step_id: *id,
                                 definition: definition.clone(),
                                 status: step.status.clone(),
+                                details: step
+                                    .instance
+                                    .as_ref()
+                                    .map(|instance| instance.get_state_details())
+                                    .unwrap_or_default(),
+                                restart_count: step.restart_count,
+                                buffer_stats: step
+                                    .instance
+                                    .as_ref()
+                                    .and_then(|instance| instance.get_buffer_stats()),
                             });
                         } else {
                             state.active_steps.push(WorkflowStepState {
@@ -280,6 +508,9 @@ impl Actor {
                                 status: StepStatus::Error {
                                     message: "Step not instantiated".to_string(),
                                 },
+                                details: HashMap::new(),
+                                restart_count: 0,
+                                buffer_stats: None,
                             });
                         }
                     } else {
@@ -290,6 +521,46 @@ impl Actor {
                 let _ = response_channel.send(Some(state));
             }
 
+            WorkflowRequestOperation::GetActiveStreams { response_channel } => {
+                let streams = self
+                    .active_streams
+                    .iter()
+                    .map(|(stream_id, details)| ActiveStreamDetails {
+                        stream_id: stream_id.clone(),
+                        originating_step_id: details.originating_step_id,
+                        time_since_last_media: details.last_media_received_at.elapsed(),
+                        label: details.label.clone(),
+                        tags: details.tags.clone(),
+                    })
+                    .collect::<Vec<_>>();
+
+                let _ = response_channel.send(streams);
+            }
+
+            WorkflowRequestOperation::DisconnectStream { stream_id } => {
+                self.disconnect_stream(stream_id, "requested by an external caller");
+            }
+
+            WorkflowRequestOperation::GetResourceUsage { response_channel } => {
+                let _ = response_channel.send(self.get_resource_usage());
+            }
+
+            WorkflowRequestOperation::FlushCaches {
+                stream_id,
+                response_channel,
+            } => {
+                let _ = response_channel.send(Some(self.flush_caches(stream_id)));
+            }
+
+            WorkflowRequestOperation::Rename { new_name } => {
+                info!("Workflow '{}' renamed to '{}'", self.name, new_name);
+                self.span
+                    .record("workflow_name", tracing::field::display(&new_name));
+
+                self.channel_depth = register_channel_depth_gauge(&new_name);
+                self.name = new_name;
+            }
+
             WorkflowRequestOperation::StopWorkflow => {
                 info!("Closing workflow as requested");
                 *stop_workflow = true;
@@ -317,27 +588,48 @@ impl Actor {
 
             WorkflowRequestOperation::MediaNotification { media } => {
                 self.update_inbound_media_cache(&media);
+                self.current_stream = Some(media.stream_id.clone());
                 self.step_inputs.clear();
                 self.step_inputs.media.push(media);
                 if let Some(id) = self.active_steps.first() {
                     let id = *id;
                     self.execute_steps(id, None, true, true);
                 }
+
+                self.current_stream = None;
             }
         }
     }
 
     fn apply_new_definition(&mut self, definition: WorkflowDefinition) {
-        let new_step_ids = definition
+        self.max_cached_media_bytes = definition
+            .max_cached_media_bytes
+            .unwrap_or(DEFAULT_MAX_CACHED_MEDIA_BYTES);
+
+        self.watchers.set_limit(definition.watcher_limit);
+
+        if self.max_stream_lifetime != definition.max_stream_lifetime {
+            self.max_stream_lifetime = definition.max_stream_lifetime;
+            if let Some(lifetime) = self.max_stream_lifetime {
+                info!("Streams now have a maximum lifetime of {:?}", lifetime);
+
+                // Streams that were already active need to be checked against the new lifetime
+                let active_streams = self.active_streams.keys().cloned().collect::<Vec<_>>();
+                for stream_id in active_streams {
+                    self.check_stream_lifetime(stream_id);
+                }
+            }
+        }
+
+        let new_step_order = definition
             .steps
             .iter()
             .map(|x| x.get_id())
-            .collect::<HashSet<_>>();
+            .collect::<Vec<_>>();
 
         if self.status == WorkflowStatus::Running
             && self.pending_steps.is_empty()
-            && self.active_steps.len() == new_step_ids.len()
-            && self.active_steps.iter().all(|x| new_step_ids.contains(x))
+            && self.active_steps == new_step_order
         {
             // No actual changes to this workflow
             return;
@@ -360,6 +652,8 @@ impl Actor {
             self.status = WorkflowStatus::Running;
         }
 
+        self.remove_steps_with_stale_streams(&new_step_order);
+
         self.pending_steps.clear();
         for step_definition in definition.steps {
             let id = step_definition.get_id();
@@ -383,9 +677,21 @@ impl Actor {
 
                 info!("Creating step {}", details);
 
-                let step_result = self
-                    .step_factory
-                    .create_step(step_definition, &self.step_futures_sender);
+                let watchdog = match StepWatchdog::from_step_definition(&step_definition) {
+                    Ok(watchdog) => watchdog,
+                    Err(error) => {
+                        error!("Step has an invalid watchdog: {}", error);
+                        self.set_status_to_error(id, format!("Invalid watchdog: {}", error));
+
+                        return;
+                    }
+                };
+
+                let step_result = self.step_factory.create_step(
+                    step_definition,
+                    &self.step_futures_sender,
+                    Some(self.watchers.clone()),
+                );
 
                 let step_result = match step_result {
                     Ok(step_result) => step_result,
@@ -413,6 +719,14 @@ impl Actor {
                 let tracked_step = TrackedWorkflowStep {
                     instance: Some(step),
                     status,
+                    span: info_span!(
+                        parent: &self.span,
+                        "Step Execution",
+                        step_id = %id,
+                        step_type = %step_type.0,
+                    ),
+                    watchdog,
+                    restart_count: 0,
                 };
 
                 entry.insert(tracked_step);
@@ -423,6 +737,50 @@ impl Actor {
         self.check_if_all_pending_steps_are_active(true);
     }
 
+    /// Existing steps are moved into their new position when the workflow is reordered, so they
+    /// keep their state. This isn't safe for a moved step that has seen a stream originating from
+    /// a step that will now come after it, since it would keep state for a stream that no longer
+    /// flows through it. These steps are removed so they are recreated in their new position.
+    fn remove_steps_with_stale_streams(&mut self, new_step_order: &[WorkflowStepId]) {
+        let moved_steps = find_moved_steps(&self.active_steps, new_step_order);
+        for (new_index, step_id) in new_step_order.iter().enumerate() {
+            if !moved_steps.contains(step_id) {
+                continue;
+            }
+
+            let old_index = self.get_active_step_index(*step_id).unwrap_or_default();
+            let stale_stream = self.active_streams.iter().find(|(_, details)| {
+                let origin = details.originating_step_id;
+                let was_before = self
+                    .get_active_step_index(origin)
+                    .is_some_and(|index| index < old_index);
+
+                let will_be_after = new_step_order
+                    .iter()
+                    .position(|x| *x == origin)
+                    .is_some_and(|index| index > new_index);
+
+                was_before && will_be_after
+            });
+
+            if let Some((stream_id, _)) = stale_stream {
+                info!(
+                    step_id = %step_id,
+                    "Recreating step id {} instead of moving it, as it has state for stream {} \
+                    which will no longer flow through it", step_id.0, stream_id.0
+                );
+
+                if let Some(mut step) = self.steps_by_definition_id.remove(step_id) {
+                    let span = span!(Level::INFO, "Step Shutdown", step_id = %step_id);
+                    let _enter = span.enter();
+                    step.instance.take();
+                }
+
+                self.cached_step_media.remove(step_id);
+            }
+        }
+    }
+
     fn execute_steps(
         &mut self,
         initial_step_id: WorkflowStepId,
@@ -466,9 +824,7 @@ impl Actor {
             return;
         }
 
-        let span = span!(Level::INFO, "Step Execution", step_id = %step_id);
-        let _enter = span.enter();
-
+        let stream_span = self.get_stream_step_span(step_id);
         let step = match self.steps_by_definition_id.get_mut(&step_id) {
             Some(x) => x,
             None => {
@@ -482,24 +838,141 @@ impl Actor {
             }
         };
 
-        let step_instance = match step.instance.as_mut() {
+        let span = stream_span.unwrap_or_else(|| step.span.clone());
+        let _enter = span.enter();
+
+        let step_instance = match step.instance.as_ref() {
             Some(instance) => instance,
             None => return, // We have no step instance to run. Might need to check status here?
         };
 
+        let wanted = self
+            .step_inputs
+            .media
+            .iter()
+            .map(|media| step_instance.wants_media(&media.content))
+            .collect::<Vec<_>>();
+
+        if wanted.iter().all(|is_wanted| *is_wanted) {
+            if self.run_step_instance(step_id) {
+                self.forward_step_outputs(step_id);
+            }
+
+            return;
+        }
+
+        // Media the step doesn't want is passed on in the same position it arrived in, so each
+        // stream's media stays in order. The step is executed separately for each run of media it
+        // does want, so its outputs land between the skipped media around that run. The step is
+        // not woken up at all if there's nothing left for it to do.
+        let mut remaining = std::mem::take(&mut self.step_inputs.media)
+            .into_iter()
+            .zip(wanted)
+            .peekable();
+
+        loop {
+            while let Some((media, _)) = remaining.next_if(|(_, is_wanted)| !*is_wanted) {
+                self.step_outputs.media.push(media);
+            }
+
+            while let Some((media, _)) = remaining.next_if(|(_, is_wanted)| *is_wanted) {
+                self.step_inputs.media.push(media);
+            }
+
+            if self.step_inputs.media.is_empty() && self.step_inputs.notifications.is_empty() {
+                break;
+            }
+
+            if !self.run_step_instance(step_id) {
+                return;
+            }
+
+            self.step_inputs.clear();
+        }
+
+        self.forward_step_outputs(step_id);
+    }
+
+    /// Executes the step with its current inputs, adding its outputs to any already gathered.
+    /// Returns `false` if the step errored, in which case the workflow is in an error state.
+    fn run_step_instance(&mut self, step_id: WorkflowStepId) -> bool {
+        let step = match self.steps_by_definition_id.get_mut(&step_id) {
+            Some(step) => step,
+            None => return false,
+        };
+
+        let step_instance = match step.instance.as_mut() {
+            Some(instance) => instance,
+            None => return false,
+        };
+
+        let mut watchdog_check_due_in = None;
+        if let Some(watchdog) = step.watchdog.as_mut() {
+            let has_input = self.step_inputs.media.iter().any(|media| {
+                matches!(
+                    media.content,
+                    MediaNotificationContent::MediaPayload {
+                        is_required_for_decoding: false,
+                        ..
+                    }
+                )
+            });
+
+            if has_input && watchdog.input_received() {
+                watchdog_check_due_in = Some(watchdog.timeout());
+            }
+        }
+
+        // Skipped media already in the outputs doesn't count as output for the watchdog
+        let produced_from = self.step_outputs.media.len();
         let channel = WorkflowStepFuturesChannel::new(step_id, self.step_futures_sender.clone());
         let new_status =
             step_instance.execute(&mut self.step_inputs, &mut self.step_outputs, channel);
         step.status = new_status;
 
-        if let StepStatus::Error { message } = &step.status {
-            let message = message.clone();
+        let error_message = match &step.status {
+            StepStatus::Error { message } => Some(message.clone()),
+            _ => None,
+        };
+
+        if let Some(timeout) = watchdog_check_due_in {
+            self.notify_when_watchdog_check_due(step_id, timeout);
+        }
+
+        if let Some(message) = error_message {
             self.set_status_to_error(step_id, message);
 
-            return;
+            return false;
         }
 
-        self.handle_executed_step_outputs(step_id);
+        self.record_step_output(step_id, produced_from);
+
+        true
+    }
+
+    /// Gets the span for executing the step within the context of the stream currently being
+    /// processed, if any. Spans are cached for the lifetime of the stream, so that entering them
+    /// does not require an allocation for every media packet.
+    fn get_stream_step_span(&mut self, step_id: WorkflowStepId) -> Option<Span> {
+        let stream_id = self.current_stream.as_ref()?;
+        let stream = self.active_streams.get_mut(stream_id)?;
+        let step_type = self
+            .step_definitions
+            .get(&step_id)
+            .map(|definition| definition.step_type.0.as_str())
+            .unwrap_or_default();
+
+        let stream_span = &stream.span;
+        let span = stream.step_spans.entry(step_id).or_insert_with(|| {
+            info_span!(
+                parent: stream_span,
+                "Step Execution",
+                step_id = %step_id,
+                step_type = %step_type,
+            )
+        });
+
+        Some(span.clone())
     }
 
     fn check_if_all_pending_steps_are_active(&mut self, swap_if_pending_is_empty: bool) {
@@ -583,6 +1056,7 @@ impl Actor {
                                         self.step_inputs.media.push(MediaNotification {
                                             stream_id: key.clone(),
                                             content: MediaNotificationContent::StreamDisconnected,
+                                            annotations: Default::default(),
                                         });
 
                                         self.execute_step(self.active_steps[x]);
@@ -598,11 +1072,27 @@ impl Actor {
 
             // Since some pending steps may not have been around previously, they would not have
             // gotten stream started notifications and missing sequence headers.  So we need to
-            // find its parent step's cache and replay any required media notifications
+            // find its parent step's cache and replay any required media notifications. The same
+            // is true for existing steps that have been moved to a new position, as they may now
+            // come after the steps some streams originate from.
+            let moved_steps = find_moved_steps(&self.active_steps, &self.pending_steps);
             for index in 0..self.pending_steps.len() {
                 let current_step_id = self.pending_steps[index];
-                if !self.active_steps.contains(&current_step_id) {
-                    // This is a new step
+                let is_new_step = !self.active_steps.contains(&current_step_id);
+                if is_new_step || moved_steps.contains(&current_step_id) {
+                    if !is_new_step {
+                        // Media cached for streams the moved step didn't originate came from its
+                        // old parents, so it's rebuilt from what its new parent replays to it
+                        let active_streams = &self.active_streams;
+                        if let Some(cache) = self.cached_step_media.get_mut(&current_step_id) {
+                            cache.retain(|stream_id, _| {
+                                active_streams.get(stream_id).is_some_and(|stream| {
+                                    stream.originating_step_id == current_step_id
+                                })
+                            });
+                        }
+                    }
+
                     let notifications = if index == 0 {
                         // The first step uses the inbound cache, not step based cache
                         self.cached_inbound_media
@@ -619,19 +1109,18 @@ impl Actor {
                         }
                     };
 
+                    // Only the step itself is executed, as moved steps are still in the active
+                    // list and the steps after them in the old order shouldn't see the replay
+                    self.step_outputs.clear();
                     self.step_inputs.clear();
                     self.step_inputs.media.extend(notifications);
-                    self.execute_steps(current_step_id, None, true, false);
+                    self.execute_step(current_step_id);
 
                     // TODO: This is probably going to cause duplicate stream started notifications.
                     // Not sure a way around that and we probably need to remove those warnings.
 
-                    // TODO: The current code only handles notifications raised by parents of
-                    // new steps.  There's the possibility that a change of order of existing
-                    // steps could cause steps to be tracking streams that come in after the step,
-                    // or not know about steps that were created in steps that used to be after but
-                    // is now before.  It also means it may have outdated sequence headers if
-                    // a transcoding step was removed.
+                    // TODO: Steps that weren't moved may have outdated sequence headers if a
+                    // transcoding step before them was removed.
                 }
             }
 
@@ -642,12 +1131,393 @@ impl Actor {
         }
     }
 
+    fn get_resource_usage(&self) -> WorkflowResourceUsage {
+        // Pending steps are already running, so any pipelines they started count as well
+        let active_pipelines = self
+            .steps_by_definition_id
+            .values()
+            .filter_map(|step| step.instance.as_ref())
+            .map(|instance| instance.get_active_pipeline_count())
+            .sum();
+
+        let cached_media = self
+            .cached_step_media
+            .values()
+            .flat_map(|cache| cache.values())
+            .chain(self.cached_inbound_media.values())
+            .flatten();
+
+        let mut cached_media_count = 0;
+        let mut cached_media_bytes = 0;
+        for media in cached_media {
+            cached_media_count += 1;
+            if let MediaNotificationContent::MediaPayload { data, .. } = &media.content {
+                cached_media_bytes += data.len();
+            }
+        }
+
+        WorkflowResourceUsage {
+            workflow_name: self.name.to_string(),
+            active_streams: self.active_streams.len(),
+            active_pipelines,
+            cached_media_count,
+            cached_media_bytes,
+            cache_limit_exceeded_count: self.cache_limit_exceeded_count,
+        }
+    }
+
+    fn flush_caches(&mut self, stream_id: Option<StreamId>) -> FlushCachesResult {
+        let stream_ids = match stream_id {
+            Some(stream_id) if self.active_streams.contains_key(&stream_id) => vec![stream_id],
+            Some(stream_id) => {
+                warn!(
+                    stream_id = %stream_id.0,
+                    "Request to flush caches for stream {} but it is not active", stream_id.0
+                );
+
+                Vec::new()
+            }
+
+            None => self.active_streams.keys().cloned().collect(),
+        };
+
+        let mut refresh_requested = false;
+        for stream_id in &stream_ids {
+            // Only media payloads are discarded, as new steps still need to know about the stream
+            // and its label and tags
+            let caches = self
+                .cached_step_media
+                .values_mut()
+                .filter_map(|cache| cache.get_mut(stream_id))
+                .chain(self.cached_inbound_media.get_mut(stream_id));
+
+            for collection in caches {
+                collection.retain(|media| {
+                    !matches!(media.content, MediaNotificationContent::MediaPayload { .. })
+                });
+            }
+
+            let mut stream_refresh_requested = false;
+            for step_id in &self.active_steps {
+                let instance = self
+                    .steps_by_definition_id
+                    .get_mut(step_id)
+                    .and_then(|step| step.instance.as_mut());
+
+                if let Some(instance) = instance {
+                    stream_refresh_requested |= instance.request_sequence_headers(stream_id);
+                }
+            }
+
+            refresh_requested |= stream_refresh_requested;
+            info!(
+                stream_id = %stream_id.0,
+                refresh_requested = %stream_refresh_requested,
+                "Flushed cached sequence headers for stream {}", stream_id.0
+            );
+        }
+
+        FlushCachesResult {
+            flushed_stream_count: stream_ids.len(),
+            refresh_requested,
+        }
+    }
+
+    fn disconnect_stream(&mut self, stream_id: StreamId, reason: &str) {
+        let details = match self.active_streams.remove(&stream_id) {
+            Some(details) => details,
+            None => {
+                warn!(
+                    stream_id = %stream_id.0,
+                    "Request to disconnect stream {} but it is not active", stream_id.0
+                );
+
+                return;
+            }
+        };
+
+        info!(
+            stream_id = %stream_id.0,
+            reason = %reason,
+            "Disconnecting stream {}: {}", stream_id.0, reason
+        );
+
+        self.cached_inbound_media.remove(&stream_id);
+        for cache in self.cached_step_media.values_mut() {
+            cache.remove(&stream_id);
+        }
+
+        // Only steps after the originating step should be told about the disconnection, since
+        // the originating step is the source of the stream.
+        if let Some(index) = self.get_active_step_index(details.originating_step_id) {
+            if let Some(next_step_id) = self.active_steps.get(index + 1) {
+                let next_step_id = *next_step_id;
+                self.step_inputs.clear();
+                self.step_inputs.media.push(MediaNotification {
+                    stream_id,
+                    content: MediaNotificationContent::StreamDisconnected,
+                    annotations: Default::default(),
+                });
+
+                self.execute_steps(next_step_id, None, true, true);
+            }
+        }
+    }
+
+    /// Disconnects the stream if it has been active for longer than the workflow's maximum stream
+    /// lifetime. Otherwise the stream is checked again once the rest of its lifetime elapses.
+    fn check_stream_lifetime(&mut self, stream_id: StreamId) {
+        let lifetime = match self.max_stream_lifetime {
+            Some(lifetime) => lifetime,
+            None => return, // lifetime limit was removed since the check was scheduled
+        };
+
+        // The stream may have disconnected, or even reconnected with the same id, since the
+        // check was scheduled, so only the stream's current start time is trusted.
+        let age = match self.active_streams.get(&stream_id) {
+            Some(details) => details.started_at.elapsed(),
+            None => return,
+        };
+
+        if age >= lifetime {
+            self.disconnect_stream(
+                stream_id,
+                &format!("it exceeded the maximum stream lifetime of {:?}", lifetime),
+            );
+        } else {
+            self.notify_when_lifetime_elapses(stream_id, lifetime - age);
+        }
+    }
+
+    fn notify_when_lifetime_elapses(&self, stream_id: StreamId, remaining: Duration) {
+        let sender = self.actor_sender.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(remaining).await;
+            let _ = sender.send(FutureResult::StreamLifetimeElapsed(stream_id));
+        });
+    }
+
+    fn notify_when_watchdog_check_due(&self, step_id: WorkflowStepId, remaining: Duration) {
+        let sender = self.actor_sender.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(remaining).await;
+            let _ = sender.send(FutureResult::WatchdogCheckDue(step_id));
+        });
+    }
+
+    /// Restarts the step if it has gone without producing output for longer than its watchdog
+    /// allows. Otherwise the watchdog is checked again once the rest of its timeout passes.
+    fn check_step_watchdog(&mut self, step_id: WorkflowStepId) {
+        if self.status != WorkflowStatus::Running {
+            return;
+        }
+
+        // The step may have been removed, or recreated without a watchdog, since the check was
+        // scheduled
+        let watchdog = match self.steps_by_definition_id.get_mut(&step_id) {
+            Some(step) => match step.watchdog.as_mut() {
+                Some(watchdog) => watchdog,
+                None => return,
+            },
+
+            None => return,
+        };
+
+        match watchdog.check() {
+            WatchdogCheck::Healthy => (),
+            WatchdogCheck::CheckAgainIn(remaining) => {
+                self.notify_when_watchdog_check_due(step_id, remaining);
+            }
+
+            WatchdogCheck::Tripped => {
+                if self.active_steps.contains(&step_id) {
+                    self.restart_step(step_id);
+                } else {
+                    // Pending steps only see media replayed to them, so there's nothing to
+                    // restart them for yet
+                    watchdog.reset();
+                }
+            }
+        }
+    }
+
+    /// Replaces an active step's instance with a new one, without affecting any other steps in
+    /// the workflow. The new instance is given the media cached for its active streams so it can
+    /// pick them back up, and later steps are told about the discontinuity in the stream.
+    fn restart_step(&mut self, step_id: WorkflowStepId) {
+        let definition = match self.step_definitions.get(&step_id) {
+            Some(definition) => definition.clone(),
+            None => return,
+        };
+
+        if let Some(step) = self.steps_by_definition_id.get_mut(&step_id) {
+            let span = span!(Level::INFO, "Step Shutdown", step_id = %step_id);
+            let _enter = span.enter();
+            step.instance.take();
+        }
+
+        {
+            let span = span!(Level::INFO, "Step Creation", step_id = %step_id);
+            let _enter = span.enter();
+
+            let step_result = self.step_factory.create_step(
+                definition,
+                &self.step_futures_sender,
+                Some(self.watchers.clone()),
+            );
+
+            let step_result = match step_result {
+                Ok(step_result) => step_result,
+                Err(error) => {
+                    error!("Step factory failed to generate step instance: {:?}", error);
+                    self.set_status_to_error(
+                        step_id,
+                        format!("Failed to generate step instance: {:?}", error),
+                    );
+
+                    return;
+                }
+            };
+
+            let (instance, status) = match step_result {
+                Ok(step) => step,
+                Err(error) => {
+                    error!("Step could not be generated: {}", error);
+                    self.set_status_to_error(
+                        step_id,
+                        format!("Failed to generate step: {}", error),
+                    );
+
+                    return;
+                }
+            };
+
+            if let Some(step) = self.steps_by_definition_id.get_mut(&step_id) {
+                step.instance = Some(instance);
+                step.status = status;
+                step.restart_count += 1;
+                if let Some(watchdog) = step.watchdog.as_mut() {
+                    watchdog.reset();
+                }
+
+                warn!(
+                    step_id = %step_id,
+                    restart_count = %step.restart_count,
+                    "Step id {} produced no output within its watchdog timeout and was restarted",
+                    step_id.0
+                );
+            }
+        }
+
+        // Streams originating from the step ended along with its old instance
+        let originated_streams = self
+            .active_streams
+            .iter()
+            .filter(|(_, details)| details.originating_step_id == step_id)
+            .map(|(stream_id, _)| stream_id.clone())
+            .collect::<Vec<_>>();
+
+        for stream_id in &originated_streams {
+            self.active_streams.remove(stream_id);
+        }
+
+        // The step's cache is rebuilt from what the new instance outputs for the replayed media
+        self.cached_step_media.remove(&step_id);
+
+        // The new instance has never seen the workflow's streams, so they are replayed to it the
+        // same way they are for newly added steps
+        let step_index = self.get_active_step_index(step_id).unwrap_or_default();
+        let notifications = if step_index == 0 {
+            self.cached_inbound_media
+                .values()
+                .flatten()
+                .cloned()
+                .collect::<Vec<_>>()
+        } else {
+            let previous_step_id = self.active_steps[step_index - 1];
+            match self.cached_step_media.get(&previous_step_id) {
+                Some(cache) => cache.values().flatten().cloned().collect::<Vec<_>>(),
+                None => Vec::new(),
+            }
+        };
+
+        let replayed_streams = notifications
+            .iter()
+            .filter(|media| {
+                matches!(
+                    media.content,
+                    MediaNotificationContent::NewIncomingStream { .. }
+                )
+            })
+            .map(|media| media.stream_id.clone())
+            .collect::<Vec<_>>();
+
+        self.step_outputs.clear();
+        self.step_inputs.clear();
+        self.step_inputs.media.extend(notifications);
+        self.execute_step(step_id);
+
+        if self.status != WorkflowStatus::Running {
+            return;
+        }
+
+        // Later steps already know about the replayed streams, but may have been in the middle of
+        // them when the step wedged. So they are told that whatever comes next doesn't continue
+        // from the media they last saw, followed by anything else the new instance output.
+        let replay_outputs = std::mem::take(&mut self.step_inputs.media);
+        self.step_inputs.clear();
+        for stream_id in replayed_streams {
+            self.step_inputs.media.push(discontinuity_notification(
+                stream_id,
+                WATCHDOG_RESTART_DISCONTINUITY,
+            ));
+        }
+
+        self.step_inputs
+            .media
+            .extend(replay_outputs.into_iter().filter(|media| {
+                !matches!(
+                    media.content,
+                    MediaNotificationContent::NewIncomingStream { .. }
+                )
+            }));
+
+        for stream_id in originated_streams {
+            self.step_inputs.media.push(MediaNotification {
+                stream_id,
+                content: MediaNotificationContent::StreamDisconnected,
+                annotations: Default::default(),
+            });
+        }
+
+        if let Some(next_step_id) = self.active_steps.get(step_index + 1) {
+            let next_step_id = *next_step_id;
+            self.execute_steps(next_step_id, None, true, false);
+        }
+    }
+
     fn update_stream_details(&mut self, current_step_id: WorkflowStepId) {
         for media in &self.step_outputs.media {
             match &media.content {
-                MediaNotificationContent::Metadata { .. } => (),
-                MediaNotificationContent::MediaPayload { .. } => (),
-                MediaNotificationContent::NewIncomingStream { .. } => {
+                MediaNotificationContent::Metadata { .. } => {
+                    if let Some(details) = self.active_streams.get_mut(&media.stream_id) {
+                        if let Some(label) = get_announced_label(&media.content) {
+                            details.label = Some(Arc::new(label.to_string()));
+                        }
+
+                        if let Some(tags) = get_announced_tags(&media.content) {
+                            details.tags = tags;
+                        }
+                    }
+                }
+
+                MediaNotificationContent::MediaPayload { .. } => {
+                    if let Some(details) = self.active_streams.get_mut(&media.stream_id) {
+                        details.last_media_received_at = Instant::now();
+                    }
+                }
+
+                MediaNotificationContent::NewIncomingStream { stream_name } => {
                     if !self.active_streams.contains_key(&media.stream_id) {
                         // Since this is the first time we've gotten a new incoming stream
                         // notification for this stream, assume this this stream originates from
@@ -656,8 +1526,26 @@ impl Actor {
                             media.stream_id.clone(),
                             StreamDetails {
                                 originating_step_id: current_step_id,
+                                last_media_received_at: Instant::now(),
+                                started_at: Instant::now(),
+                                label: None,
+                                tags: Tags::new(),
+                                span: info_span!(
+                                    parent: &self.span,
+                                    "Stream",
+                                    stream_id = %media.stream_id.0,
+                                    stream_name = %stream_name,
+                                ),
+                                step_spans: HashMap::new(),
+                                cache_limit_warning_logged: false,
+                                first_payload_timestamp: None,
+                                started_tracks: HashSet::new(),
                             },
                         );
+
+                        if let Some(lifetime) = self.max_stream_lifetime {
+                            self.notify_when_lifetime_elapses(media.stream_id.clone(), lifetime);
+                        }
                     }
                 }
 
@@ -672,6 +1560,102 @@ impl Actor {
         }
     }
 
+    /// Raises a track added notification before the first payload of any track that starts well
+    /// after the stream's media started flowing, followed by the track's sequence header so later
+    /// steps can set themselves up for the new track. Only outputs from the step the stream
+    /// originates from are checked, so each late track is only announced once.
+    fn announce_late_tracks(&mut self, step_id: WorkflowStepId) {
+        let is_new_track = |details: &StreamDetails, media_type: &MediaType| {
+            details.originating_step_id == step_id && !details.started_tracks.contains(media_type)
+        };
+
+        let has_new_track = self
+            .step_outputs
+            .media
+            .iter()
+            .any(|media| match &media.content {
+                MediaNotificationContent::MediaPayload {
+                    media_type,
+                    is_required_for_decoding: false,
+                    ..
+                } => self
+                    .active_streams
+                    .get(&media.stream_id)
+                    .is_some_and(|details| is_new_track(details, media_type)),
+
+                _ => false,
+            });
+
+        if !has_new_track {
+            return;
+        }
+
+        let mut outputs = Vec::with_capacity(self.step_outputs.media.len());
+        for media in self.step_outputs.media.drain(..) {
+            let (media_type, timestamp) = match &media.content {
+                MediaNotificationContent::MediaPayload {
+                    media_type,
+                    timestamp,
+                    is_required_for_decoding: false,
+                    ..
+                } => (*media_type, *timestamp),
+
+                _ => {
+                    outputs.push(media);
+                    continue;
+                }
+            };
+
+            let details = match self.active_streams.get_mut(&media.stream_id) {
+                Some(details) if is_new_track(details, &media_type) => details,
+                _ => {
+                    outputs.push(media);
+                    continue;
+                }
+            };
+
+            details.started_tracks.insert(media_type);
+            let first_timestamp = *details.first_payload_timestamp.get_or_insert(timestamp);
+            if timestamp.saturating_sub(first_timestamp) < LATE_TRACK_THRESHOLD {
+                outputs.push(media);
+                continue;
+            }
+
+            info!(
+                stream_id = %media.stream_id.0,
+                "{:?} track started {}ms into stream {}", media_type,
+                timestamp.saturating_sub(first_timestamp).as_millis(), media.stream_id.0,
+            );
+
+            // The sequence header may have come through in the same outputs, otherwise it will
+            // have been cached from an earlier execution
+            let cached_media = self
+                .cached_step_media
+                .get(&step_id)
+                .and_then(|cache| cache.get(&media.stream_id));
+
+            let sequence_header = outputs
+                .iter()
+                .rev()
+                .chain(cached_media.into_iter().flatten())
+                .find(|cached| is_sequence_header_for(cached, &media.stream_id, media_type))
+                .cloned();
+
+            outputs.push(track_added_notification(
+                media.stream_id.clone(),
+                media_type,
+            ));
+
+            outputs.extend(sequence_header);
+            outputs.push(media);
+        }
+
+        self.step_outputs.media = outputs;
+    }
+
+    /// Updates the cache of media that's replayed to steps added while a stream is active. A
+    /// sequence header identical to the one already cached for the stream leaves the cache as is,
+    /// but is still passed through the workflow, as steps may rely on receiving it again.
     fn update_inbound_media_cache(&mut self, media: &MediaNotification) {
         match media.content {
             MediaNotificationContent::NewIncomingStream { .. } => {
@@ -689,6 +1673,24 @@ impl Actor {
                 ..
             } => {
                 if let Some(collection) = self.cached_inbound_media.get_mut(&media.stream_id) {
+                    match cache_sequence_header(collection, media, self.max_cached_media_bytes) {
+                        SequenceHeaderCacheResult::Cached => (),
+                        SequenceHeaderCacheResult::Identical => {
+                            debug!(
+                                stream_id = ?media.stream_id,
+                                "Sequence header identical to the one already cached"
+                            );
+                        }
+                        SequenceHeaderCacheResult::LimitExceeded => {
+                            self.record_cache_limit_exceeded(&media.stream_id);
+                        }
+                    }
+                }
+            }
+
+            MediaNotificationContent::Metadata { .. } if is_stream_announcement(&media.content) => {
+                if let Some(collection) = self.cached_inbound_media.get_mut(&media.stream_id) {
+                    collection.retain(|x| !replaces_announcement(&media.content, &x.content));
                     collection.push(media.clone());
                 }
             }
@@ -699,10 +1701,13 @@ impl Actor {
 
     fn update_media_cache_from_outputs(&mut self, step_id: WorkflowStepId) {
         let step_cache = self.cached_step_media.entry(step_id).or_default();
+        let mut limit_exceeded_streams = Vec::new();
 
         for media in &self.step_outputs.media {
             enum Operation {
                 Add,
+                AddSequenceHeader,
+                ReplaceAnnouncement,
                 Remove,
                 Ignore,
             }
@@ -716,8 +1721,14 @@ impl Actor {
 
                 MediaNotificationContent::Metadata { .. } => {
                     // I *think* we can ignore these, since the sequence headers are really
-                    // what's important to replay
-                    Operation::Ignore
+                    // what's important to replay. Labels and tags are the exception, since new
+                    // steps need to know the purpose and provenance of streams that are already
+                    // flowing.
+                    if is_stream_announcement(&media.content) {
+                        Operation::ReplaceAnnouncement
+                    } else {
+                        Operation::Ignore
+                    }
                 }
 
                 MediaNotificationContent::MediaPayload {
@@ -725,7 +1736,7 @@ impl Actor {
                     ..
                 } => {
                     if *is_required_for_decoding {
-                        Operation::Add
+                        Operation::AddSequenceHeader
                     } else {
                         Operation::Ignore
                     }
@@ -743,8 +1754,47 @@ impl Actor {
 
                     collection.push(media.clone());
                 }
+
+                Operation::AddSequenceHeader => {
+                    let collection = step_cache.entry(media.stream_id.clone()).or_default();
+
+                    let result =
+                        cache_sequence_header(collection, media, self.max_cached_media_bytes);
+                    if let SequenceHeaderCacheResult::LimitExceeded = result {
+                        limit_exceeded_streams.push(media.stream_id.clone());
+                    }
+                }
+
+                Operation::ReplaceAnnouncement => {
+                    if let Some(collection) = step_cache.get_mut(&media.stream_id) {
+                        collection.retain(|x| !replaces_announcement(&media.content, &x.content));
+                        collection.push(media.clone());
+                    }
+                }
             }
         }
+
+        for stream_id in limit_exceeded_streams {
+            self.record_cache_limit_exceeded(&stream_id);
+        }
+    }
+
+    fn record_cache_limit_exceeded(&mut self, stream_id: &StreamId) {
+        self.cache_limit_exceeded_count += 1;
+
+        let already_logged = match self.active_streams.get_mut(stream_id) {
+            Some(details) => std::mem::replace(&mut details.cache_limit_warning_logged, true),
+            None => false,
+        };
+
+        if !already_logged {
+            warn!(
+                stream_id = %stream_id.0,
+                "Stream {} exceeded the workflow's limit of {} cached bytes. Required-for-decoding \
+                media that would exceed the limit will not be replayed to new steps",
+                stream_id.0, self.max_cached_media_bytes,
+            );
+        }
     }
 
     fn set_status_to_error(&mut self, step_id: WorkflowStepId, message: String) {
@@ -772,10 +1822,198 @@ impl Actor {
     }
 
     fn handle_executed_step_outputs(&mut self, step_id: WorkflowStepId) {
+        self.record_step_output(step_id, 0);
+        self.forward_step_outputs(step_id);
+    }
+
+    /// Lets the step's watchdog know if the step produced any media payloads
+    fn record_step_output(&mut self, step_id: WorkflowStepId, produced_from: usize) {
+        let produced_media = self.step_outputs.media[produced_from..]
+            .iter()
+            .any(|media| matches!(media.content, MediaNotificationContent::MediaPayload { .. }));
+
+        if produced_media {
+            if let Some(watchdog) = self
+                .steps_by_definition_id
+                .get_mut(&step_id)
+                .and_then(|step| step.watchdog.as_mut())
+            {
+                watchdog.output_produced();
+            }
+        }
+    }
+
+    /// Moves the outputs of a step into the inputs for the next step, tracking any changes to
+    /// the streams they contain
+    fn forward_step_outputs(&mut self, step_id: WorkflowStepId) {
         self.update_stream_details(step_id);
+        self.announce_late_tracks(step_id);
         self.update_media_cache_from_outputs(step_id);
         self.step_inputs.clear();
         self.step_inputs.media.append(&mut self.step_outputs.media);
         self.step_outputs.clear();
     }
 }
+
+enum SequenceHeaderCacheResult {
+    Cached,
+
+    /// The sequence header is identical to the one already cached, so the cache was not modified
+    Identical,
+
+    /// Caching the sequence header would have exceeded the stream's cache limit, so it was not
+    /// cached. Any sequence header previously cached for the same media type is evicted, since
+    /// it's no longer valid for the stream.
+    LimitExceeded,
+}
+
+/// Returns true if the content announces something about the stream itself (its label or tags),
+/// which new steps need to know even though it was announced before they were added
+fn is_stream_announcement(content: &MediaNotificationContent) -> bool {
+    get_announced_label(content).is_some() || get_announced_tags(content).is_some()
+}
+
+/// Returns true if the announcement supersedes a previously cached announcement
+fn replaces_announcement(
+    announcement: &MediaNotificationContent,
+    cached: &MediaNotificationContent,
+) -> bool {
+    (get_announced_label(announcement).is_some() && get_announced_label(cached).is_some())
+        || (get_announced_tags(announcement).is_some() && get_announced_tags(cached).is_some())
+}
+
+/// Registers the gauge for a workflow's channel, which is reported as `workflow:<name>`
+fn register_channel_depth_gauge(workflow_name: &str) -> ChannelDepthGauge {
+    ChannelDepthGauge::register(format!("workflow:{}", workflow_name))
+}
+
+/// Adds a sequence header to a stream's cached media, replacing any sequence header previously
+/// cached for the same media type, as long as the stream's cached payloads stay within the
+/// maximum number of bytes.
+fn cache_sequence_header(
+    collection: &mut Vec<MediaNotification>,
+    media: &MediaNotification,
+    max_bytes: usize,
+) -> SequenceHeaderCacheResult {
+    let (media_type, payload_type, data) = match &media.content {
+        MediaNotificationContent::MediaPayload {
+            media_type,
+            payload_type,
+            data,
+            ..
+        } => (media_type, payload_type, data),
+
+        _ => return SequenceHeaderCacheResult::Cached,
+    };
+
+    let is_same_sequence_header = |cached: &MediaNotification| {
+        matches!(
+            &cached.content,
+            MediaNotificationContent::MediaPayload {
+                media_type: cached_media_type,
+                is_required_for_decoding: true,
+                ..
+            } if cached_media_type == media_type
+        )
+    };
+
+    let other_cached_bytes: usize = collection
+        .iter()
+        .filter(|cached| !is_same_sequence_header(cached))
+        .map(|cached| match &cached.content {
+            MediaNotificationContent::MediaPayload { data, .. } => data.len(),
+            _ => 0,
+        })
+        .sum();
+
+    let existing = collection
+        .iter_mut()
+        .find(|cached| is_same_sequence_header(cached));
+
+    match existing {
+        Some(cached) => {
+            let is_identical = matches!(
+                &cached.content,
+                MediaNotificationContent::MediaPayload {
+                    payload_type: cached_payload_type,
+                    data: cached_data,
+                    ..
+                } if cached_payload_type == payload_type && cached_data == data
+            );
+
+            if is_identical {
+                return SequenceHeaderCacheResult::Identical;
+            }
+
+            if other_cached_bytes + data.len() > max_bytes {
+                collection.retain(|cached| !is_same_sequence_header(cached));
+                return SequenceHeaderCacheResult::LimitExceeded;
+            }
+
+            info!(
+                stream_id = ?media.stream_id,
+                "{:?} sequence header changed ({})", media_type, payload_type
+            );
+
+            *cached = media.clone();
+        }
+
+        None if other_cached_bytes + data.len() > max_bytes => {
+            return SequenceHeaderCacheResult::LimitExceeded;
+        }
+
+        None => collection.push(media.clone()),
+    }
+
+    SequenceHeaderCacheResult::Cached
+}
+
+fn is_sequence_header_for(
+    media: &MediaNotification,
+    stream_id: &StreamId,
+    media_type: MediaType,
+) -> bool {
+    media.stream_id == *stream_id
+        && matches!(
+            &media.content,
+            MediaNotificationContent::MediaPayload {
+                media_type: header_media_type,
+                is_required_for_decoding: true,
+                ..
+            } if *header_media_type == media_type
+        )
+}
+
+/// Returns the ids of steps that exist in both step orders, but which the new order places after a
+/// different set of the existing steps. Steps that only shift position because other steps were
+/// added or removed are not considered moved.
+fn find_moved_steps(
+    current_order: &[WorkflowStepId],
+    new_order: &[WorkflowStepId],
+) -> HashSet<WorkflowStepId> {
+    let kept_current = current_order
+        .iter()
+        .filter(|id| new_order.contains(id))
+        .collect::<Vec<_>>();
+
+    let kept_new = new_order
+        .iter()
+        .filter(|id| current_order.contains(id))
+        .collect::<Vec<_>>();
+
+    let mut moved_steps = HashSet::new();
+    for (new_index, step_id) in kept_new.iter().enumerate() {
+        let current_index = kept_current
+            .iter()
+            .position(|id| id == step_id)
+            .unwrap_or_default();
+
+        let current_parents = kept_current[..current_index].iter().collect::<HashSet<_>>();
+        let new_parents = kept_new[..new_index].iter().collect::<HashSet<_>>();
+        if current_parents != new_parents {
+            moved_steps.insert(**step_id);
+        }
+    }
+
+    moved_steps
+}
@@ -13,6 +13,7 @@ use std::sync::atomic::AtomicU16;
 use std::sync::Arc;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::sync::watch::{channel, Sender};
+use tokio_util::sync::CancellationToken;
 
 pub struct TestContext {
     pub workflow: UnboundedSender<WorkflowRequest>,
@@ -31,6 +32,7 @@ impl TestContext {
         let (input_media_sender, input_media_receiver) = channel(MediaNotification {
             stream_id: StreamId(Arc::new("invalid".to_string())),
             content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
         });
 
         let (output_media_sender, output_media_receiver) = unbounded_channel();
@@ -40,6 +42,7 @@ impl TestContext {
         let (future_media_sender, future_media_receiver) = channel(MediaNotification {
             stream_id: StreamId(Arc::new("bad".to_string())),
             content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
         });
 
         let input_received_counter = Arc::new(AtomicU16::new(0));
@@ -71,6 +74,9 @@ impl TestContext {
         let definition = WorkflowDefinition {
             name: Arc::new("abc".to_string()),
             routed_by_reactor: false,
+            max_stream_lifetime: None,
+            max_cached_media_bytes: None,
+            watcher_limit: None,
             steps: vec![
                 WorkflowStepDefinition {
                     step_type: WorkflowStepType("input".to_string()),
@@ -86,7 +92,7 @@ impl TestContext {
         let input_step_id = definition.steps[0].get_id();
         let output_step_id = definition.steps[1].get_id();
 
-        let workflow = start_workflow(definition, Arc::new(factory));
+        let workflow = start_workflow(definition, Arc::new(factory), CancellationToken::new());
 
         TestContext {
             workflow,
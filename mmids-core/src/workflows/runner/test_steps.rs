@@ -6,7 +6,10 @@ use crate::workflows::steps::futures_channel::{
 use crate::workflows::steps::{
     StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
 };
-use crate::workflows::MediaNotification;
+use crate::workflows::watchers::WorkflowWatchers;
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use crate::StreamId;
+use std::collections::HashSet;
 use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::Arc;
 use tokio::sync::mpsc::UnboundedSender;
@@ -24,6 +27,41 @@ pub struct TestOutputStepGenerator {
     pub status_change: Receiver<StepStatus>,
 }
 
+/// Generates steps that are immediately active and pass all media through, counting how many
+/// instances have been created and optionally reporting the media they receive.
+pub struct TestPassThroughStepGenerator {
+    pub created_count: Arc<AtomicU16>,
+    pub media_sender: Option<UnboundedSender<MediaNotification>>,
+}
+
+/// Generates steps that only want video payloads (along with any other notifications), reporting
+/// all the media they receive and passing it through.
+pub struct TestVideoOnlyStepGenerator {
+    pub media_sender: UnboundedSender<MediaNotification>,
+}
+
+/// Generates steps that hold onto media payloads that aren't required for decoding, and pass them
+/// all on in a single batch when any other media arrives.
+pub struct TestBatchingStepGenerator;
+
+/// Generates steps that pass all media through and act as if they start a transcoding pipeline
+/// for each stream they see, counting how many times they're asked for fresh sequence headers.
+pub struct TestTranscodeStepGenerator {
+    pub sequence_header_request_count: Arc<AtomicU16>,
+}
+
+/// Generates steps that act as if they've wedged, passing through everything except the media
+/// payloads that aren't required for decoding. The number of instances created is counted.
+pub struct TestStuckStepGenerator {
+    pub created_count: Arc<AtomicU16>,
+}
+
+/// Generates output steps that pass all media through, and hand the workflow watchers they were
+/// created with to the test so it can act as the steps' watchers.
+pub struct TestWatcherStepGenerator {
+    pub watchers_sender: UnboundedSender<Option<WorkflowWatchers>>,
+}
+
 struct TestInputStep {
     status: StepStatus,
     media_receiver: Receiver<MediaNotification>,
@@ -37,6 +75,25 @@ struct TestOutputStep {
     status_receiver: Receiver<StepStatus>,
 }
 
+struct TestPassThroughStep {
+    media_sender: Option<UnboundedSender<MediaNotification>>,
+}
+
+struct TestVideoOnlyStep {
+    media_sender: UnboundedSender<MediaNotification>,
+}
+
+struct TestBatchingStep {
+    held_media: Vec<MediaNotification>,
+}
+
+struct TestStuckStep;
+
+struct TestTranscodeStep {
+    transcoded_streams: HashSet<StreamId>,
+    sequence_header_request_count: Arc<AtomicU16>,
+}
+
 impl StepFutureResult for InputFutureResult {}
 
 enum InputFutureResult {
@@ -99,6 +156,92 @@ impl StepGenerator for TestOutputStepGenerator {
     }
 }
 
+impl StepGenerator for TestPassThroughStepGenerator {
+    fn generate(
+        &self,
+        _definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        self.created_count.fetch_add(1, Ordering::SeqCst);
+        let step = TestPassThroughStep {
+            media_sender: self.media_sender.clone(),
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl StepGenerator for TestVideoOnlyStepGenerator {
+    fn generate(
+        &self,
+        _definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let step = TestVideoOnlyStep {
+            media_sender: self.media_sender.clone(),
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl StepGenerator for TestBatchingStepGenerator {
+    fn generate(
+        &self,
+        _definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let step = TestBatchingStep {
+            held_media: Vec::new(),
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl StepGenerator for TestTranscodeStepGenerator {
+    fn generate(
+        &self,
+        _definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let step = TestTranscodeStep {
+            transcoded_streams: HashSet::new(),
+            sequence_header_request_count: self.sequence_header_request_count.clone(),
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl StepGenerator for TestStuckStepGenerator {
+    fn generate(
+        &self,
+        _definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        self.created_count.fetch_add(1, Ordering::SeqCst);
+
+        Ok((Box::new(TestStuckStep), StepStatus::Active))
+    }
+}
+
+impl StepGenerator for TestWatcherStepGenerator {
+    fn generate(
+        &self,
+        _definition: WorkflowStepDefinition,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let _ = self
+            .watchers_sender
+            .send(futures_channel.workflow_watchers().cloned());
+
+        let step = TestPassThroughStep { media_sender: None };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
 impl WorkflowStep for TestInputStep {
     fn execute(
         &mut self,
@@ -191,6 +334,142 @@ impl WorkflowStep for TestOutputStep {
     }
 }
 
+impl WorkflowStep for TestPassThroughStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            if let Some(sender) = &self.media_sender {
+                let _ = sender.send(media.clone());
+            }
+
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+}
+
+impl WorkflowStep for TestVideoOnlyStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            let _ = self.media_sender.send(media.clone());
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn wants_media(&self, content: &MediaNotificationContent) -> bool {
+        !matches!(
+            content,
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Audio,
+                ..
+            }
+        )
+    }
+}
+
+impl WorkflowStep for TestBatchingStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            if let MediaNotificationContent::MediaPayload {
+                is_required_for_decoding: false,
+                ..
+            } = &media.content
+            {
+                self.held_media.push(media);
+            } else {
+                outputs.media.append(&mut self.held_media);
+                outputs.media.push(media);
+            }
+        }
+
+        StepStatus::Active
+    }
+}
+
+impl WorkflowStep for TestStuckStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            let is_stuck_on = matches!(
+                media.content,
+                MediaNotificationContent::MediaPayload {
+                    is_required_for_decoding: false,
+                    ..
+                }
+            );
+
+            if !is_stuck_on {
+                outputs.media.push(media);
+            }
+        }
+
+        StepStatus::Active
+    }
+}
+
+impl WorkflowStep for TestTranscodeStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            match &media.content {
+                MediaNotificationContent::NewIncomingStream { .. } => {
+                    self.transcoded_streams.insert(media.stream_id.clone());
+                }
+
+                MediaNotificationContent::StreamDisconnected => {
+                    self.transcoded_streams.remove(&media.stream_id);
+                }
+
+                _ => (),
+            }
+
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_active_pipeline_count(&self) -> usize {
+        self.transcoded_streams.len()
+    }
+
+    fn request_sequence_headers(&mut self, stream_id: &StreamId) -> bool {
+        if !self.transcoded_streams.contains(stream_id) {
+            return false;
+        }
+
+        self.sequence_header_request_count
+            .fetch_add(1, Ordering::SeqCst);
+
+        true
+    }
+}
+
 fn input_media_received(
     receiver: Receiver<MediaNotification>,
     futures_channel: &WorkflowStepFuturesChannel,
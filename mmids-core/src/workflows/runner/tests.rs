@@ -1,18 +1,36 @@
+use crate::channel_metrics::channel_depths;
+use crate::codecs::av1::Av1CodecConfigurationRecord;
+use crate::codecs::{VIDEO_CODEC_AV1, VIDEO_CODEC_H265_HVCC};
+use crate::workflows::annotations::ArrivalTime;
 use crate::workflows::definitions::{WorkflowDefinition, WorkflowStepDefinition, WorkflowStepType};
+use crate::workflows::discontinuity::DISCONTINUITY_METADATA_KEY;
+use crate::workflows::metadata::MediaPayloadMetadataCollection;
 use crate::workflows::runner::test_context::TestContext;
+use crate::workflows::runner::test_steps::{
+    TestBatchingStepGenerator, TestPassThroughStepGenerator, TestStuckStepGenerator,
+    TestTranscodeStepGenerator, TestVideoOnlyStepGenerator, TestWatcherStepGenerator,
+};
+use crate::workflows::runner::watchdog::WATCHDOG_TIMEOUT;
 use crate::workflows::steps::factory::WorkflowStepFactory;
 use crate::workflows::steps::StepStatus;
+use crate::workflows::stream_labels::label_notification;
+use crate::workflows::tracks::get_added_track;
+use crate::workflows::watchers::{WatcherAdmission, WatcherLimit, WatcherLimitBehavior};
 use crate::workflows::{
-    start_workflow, MediaNotification, MediaNotificationContent, WorkflowRequest,
-    WorkflowRequestOperation, WorkflowStatus,
+    start_workflow, FlushCachesResult, MediaNotification, MediaNotificationContent, MediaType,
+    WorkflowRequest, WorkflowRequestOperation, WorkflowResourceUsage, WorkflowStatus,
 };
 use crate::{test_utils, StreamId};
+use bytes::{Bytes, BytesMut};
 use std::collections::HashMap;
-use std::sync::atomic::Ordering;
+use std::iter;
+use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::sync::oneshot::channel;
 use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
 
 #[tokio::test]
 async fn workflow_created_with_steps_in_pending_state() {
@@ -218,6 +236,7 @@ async fn workflow_passes_media_from_one_step_to_the_next() {
         .send(MediaNotification {
             stream_id: StreamId(Arc::new("abc".to_string())),
             content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
         })
         .expect("Failed to send media notification to step");
 
@@ -234,6 +253,40 @@ async fn workflow_passes_media_from_one_step_to_the_next() {
     }
 }
 
+#[tokio::test]
+async fn annotations_carried_from_one_step_to_the_next() {
+    let mut context = TestContext::new();
+    context
+        .output_status
+        .send(StepStatus::Active)
+        .expect("Failed to set output state");
+    context
+        .input_status
+        .send(StepStatus::Active)
+        .expect("Failed to set input state");
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let arrival_time = ArrivalTime(Instant::now());
+    let mut media = MediaNotification {
+        stream_id: StreamId(Arc::new("abc".to_string())),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    };
+
+    media.annotations.insert(arrival_time);
+    context
+        .input_media_sender
+        .send(media)
+        .expect("Failed to send media notification to step");
+
+    let response = test_utils::expect_mpsc_response(&mut context.output_step_media_receiver).await;
+    assert_eq!(
+        response.annotations.get::<ArrivalTime>(),
+        Some(&arrival_time),
+        "Unexpected arrival time annotation"
+    );
+}
+
 #[tokio::test]
 async fn media_sent_to_workflow_flows_through_steps() {
     let mut context = TestContext::new();
@@ -255,6 +308,7 @@ async fn media_sent_to_workflow_flows_through_steps() {
                 media: MediaNotification {
                     stream_id: StreamId(Arc::new("abc".to_string())),
                     content: MediaNotificationContent::StreamDisconnected,
+                    annotations: Default::default(),
                 },
             },
         })
@@ -297,6 +351,9 @@ async fn steps_in_active_workflow_are_pending() {
     let definition = WorkflowDefinition {
         name: Arc::new("abc".to_string()),
         routed_by_reactor: false,
+        max_stream_lifetime: None,
+        max_cached_media_bytes: None,
+        watcher_limit: None,
         steps: vec![WorkflowStepDefinition {
             step_type: WorkflowStepType("output".to_string()),
             parameters: params,
@@ -377,6 +434,9 @@ async fn new_pending_steps_replace_active_steps_when_pending_steps_get_active_st
     let definition = WorkflowDefinition {
         name: Arc::new("abc".to_string()),
         routed_by_reactor: false,
+        max_stream_lifetime: None,
+        max_cached_media_bytes: None,
+        watcher_limit: None,
         steps: vec![
             WorkflowStepDefinition {
                 step_type: WorkflowStepType("output".to_string()),
@@ -472,6 +532,9 @@ async fn workflow_in_error_state_if_factory_cant_find_step() {
     let definition = WorkflowDefinition {
         name: Arc::new("abc".to_string()),
         routed_by_reactor: false,
+        max_stream_lifetime: None,
+        max_cached_media_bytes: None,
+        watcher_limit: None,
         steps: vec![WorkflowStepDefinition {
             step_type: WorkflowStepType("input".to_string()),
             parameters: HashMap::new(),
@@ -479,7 +542,7 @@ async fn workflow_in_error_state_if_factory_cant_find_step() {
     };
 
     let step_id = definition.steps[0].get_id();
-    let workflow = start_workflow(definition, factory);
+    let workflow = start_workflow(definition, factory, CancellationToken::new());
     tokio::time::sleep(Duration::from_millis(10)).await;
 
     let (sender, receiver) = channel();
@@ -524,6 +587,9 @@ async fn workflow_in_error_state_if_updated_steps_arent_registered_with_factory(
     let definition = WorkflowDefinition {
         name: Arc::new("abc".to_string()),
         routed_by_reactor: false,
+        max_stream_lifetime: None,
+        max_cached_media_bytes: None,
+        watcher_limit: None,
         steps: vec![WorkflowStepDefinition {
             step_type: WorkflowStepType("output2".to_string()),
             parameters: HashMap::new(),
@@ -590,6 +656,7 @@ async fn media_future_result_from_active_step_immediately_goes_to_next_step() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     };
 
     context
@@ -626,6 +693,7 @@ async fn media_future_result_from_pending_step_does_not_go_to_next_step() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     };
 
     context
@@ -635,3 +703,1304 @@ async fn media_future_result_from_pending_step_does_not_go_to_next_step() {
 
     test_utils::expect_mpsc_timeout(&mut context.output_step_media_receiver).await;
 }
+
+#[tokio::test]
+async fn new_stream_is_returned_as_active_stream() {
+    let context = TestContext::new();
+    context
+        .output_status
+        .send(StepStatus::Active)
+        .expect("Failed to set output state");
+    context
+        .input_status
+        .send(StepStatus::Active)
+        .expect("Failed to set input state");
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    context
+        .input_media_sender
+        .send(MediaNotification {
+            stream_id: StreamId(Arc::new("abc".to_string())),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        })
+        .expect("Failed to send media notification to step");
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let (sender, receiver) = channel();
+    context
+        .workflow
+        .send(WorkflowRequest {
+            request_id: "".to_string(),
+            operation: WorkflowRequestOperation::GetActiveStreams {
+                response_channel: sender,
+            },
+        })
+        .expect("Failed to send get active streams request");
+
+    let response = test_utils::expect_oneshot_response(receiver).await;
+    assert_eq!(response.len(), 1, "Unexpected number of active streams");
+    assert_eq!(
+        response[0].stream_id,
+        StreamId(Arc::new("abc".to_string())),
+        "Unexpected stream id"
+    );
+
+    assert_eq!(
+        response[0].originating_step_id, context.input_step_id,
+        "Unexpected originating step"
+    );
+}
+
+#[tokio::test]
+async fn announced_label_is_returned_with_active_stream() {
+    let context = TestContext::new();
+    context
+        .output_status
+        .send(StepStatus::Active)
+        .expect("Failed to set output state");
+    context
+        .input_status
+        .send(StepStatus::Active)
+        .expect("Failed to set input state");
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    context
+        .input_media_sender
+        .send(MediaNotification {
+            stream_id: stream_id.clone(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        })
+        .expect("Failed to send media notification to step");
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    context
+        .input_media_sender
+        .send(label_notification(
+            stream_id,
+            Arc::new("backup".to_string()),
+        ))
+        .expect("Failed to send label notification to step");
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let (sender, receiver) = channel();
+    context
+        .workflow
+        .send(WorkflowRequest {
+            request_id: "".to_string(),
+            operation: WorkflowRequestOperation::GetActiveStreams {
+                response_channel: sender,
+            },
+        })
+        .expect("Failed to send get active streams request");
+
+    let response = test_utils::expect_oneshot_response(receiver).await;
+    assert_eq!(response.len(), 1, "Unexpected number of active streams");
+    assert_eq!(
+        response[0].label,
+        Some(Arc::new("backup".to_string())),
+        "Unexpected stream label"
+    );
+}
+
+#[tokio::test]
+async fn disconnect_stream_request_sends_disconnection_to_later_steps() {
+    let mut context = TestContext::new();
+    context
+        .output_status
+        .send(StepStatus::Active)
+        .expect("Failed to set output state");
+    context
+        .input_status
+        .send(StepStatus::Active)
+        .expect("Failed to set input state");
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    context
+        .input_media_sender
+        .send(MediaNotification {
+            stream_id: StreamId(Arc::new("abc".to_string())),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        })
+        .expect("Failed to send media notification to step");
+
+    let _ = test_utils::expect_mpsc_response(&mut context.output_step_media_receiver).await;
+
+    context
+        .workflow
+        .send(WorkflowRequest {
+            request_id: "".to_string(),
+            operation: WorkflowRequestOperation::DisconnectStream {
+                stream_id: StreamId(Arc::new("abc".to_string())),
+            },
+        })
+        .expect("Failed to send disconnect stream request");
+
+    let response = test_utils::expect_mpsc_response(&mut context.output_step_media_receiver).await;
+    assert_eq!(
+        response.stream_id,
+        StreamId(Arc::new("abc".to_string())),
+        "Unexpected stream id"
+    );
+
+    match response.content {
+        MediaNotificationContent::StreamDisconnected => (),
+        x => panic!("Unexpected media notification: {:?}", x),
+    }
+
+    let (sender, receiver) = channel();
+    context
+        .workflow
+        .send(WorkflowRequest {
+            request_id: "".to_string(),
+            operation: WorkflowRequestOperation::GetActiveStreams {
+                response_channel: sender,
+            },
+        })
+        .expect("Failed to send get active streams request");
+
+    let response = test_utils::expect_oneshot_response(receiver).await;
+    assert!(response.is_empty(), "Expected no active streams");
+}
+
+#[tokio::test]
+async fn h265_sequence_header_is_cached_and_replayed_to_new_steps() {
+    let mut context = TestContext::new();
+    context
+        .output_status
+        .send(StepStatus::Active)
+        .expect("Failed to set output state");
+    context
+        .input_status
+        .send(StepStatus::Active)
+        .expect("Failed to set input state");
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    let sequence_header = MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Video,
+            payload_type: VIDEO_CODEC_H265_HVCC.clone(),
+            timestamp: Duration::from_millis(0),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data: Bytes::from_static(&[1, 2, 3, 4]),
+            is_required_for_decoding: true,
+        },
+        annotations: Default::default(),
+    };
+
+    context
+        .input_media_sender
+        .send(MediaNotification {
+            stream_id: stream_id.clone(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        })
+        .expect("Failed to send media notification to step");
+
+    let _ = test_utils::expect_mpsc_response(&mut context.output_step_media_receiver).await;
+
+    context
+        .input_media_sender
+        .send(sequence_header.clone())
+        .expect("Failed to send media notification to step");
+
+    let response = test_utils::expect_mpsc_response(&mut context.output_step_media_receiver).await;
+    assert_eq!(
+        response, sequence_header,
+        "Sequence header not passed through"
+    );
+
+    // Replace the output step with a new one, which should get the cached sequence header
+    context
+        .output_status
+        .send(StepStatus::Created)
+        .expect("Failed to set output state");
+
+    let mut params = HashMap::new(); // parameters will give it a new id
+    params.insert("a".to_string(), Some("b".to_string()));
+    let definition = WorkflowDefinition {
+        name: Arc::new("abc".to_string()),
+        routed_by_reactor: false,
+        max_stream_lifetime: None,
+        max_cached_media_bytes: None,
+        watcher_limit: None,
+        steps: vec![
+            WorkflowStepDefinition {
+                step_type: WorkflowStepType("input".to_string()),
+                parameters: HashMap::new(),
+            },
+            WorkflowStepDefinition {
+                step_type: WorkflowStepType("output".to_string()),
+                parameters: params,
+            },
+        ],
+    };
+
+    context
+        .workflow
+        .send(WorkflowRequest {
+            request_id: "".to_string(),
+            operation: WorkflowRequestOperation::UpdateDefinition {
+                new_definition: definition,
+            },
+        })
+        .expect("Failed to send update request");
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    context
+        .output_status
+        .send(StepStatus::Active)
+        .expect("Failed to set output state");
+
+    let response = test_utils::expect_mpsc_response(&mut context.output_step_media_receiver).await;
+    match response.content {
+        MediaNotificationContent::NewIncomingStream { .. } => (),
+        x => panic!("Expected new incoming stream, instead got {:?}", x),
+    }
+
+    let response = test_utils::expect_mpsc_response(&mut context.output_step_media_receiver).await;
+    assert_eq!(
+        response, sequence_header,
+        "Cached sequence header was not replayed"
+    );
+}
+
+struct PassThroughWorkflow {
+    workflow: UnboundedSender<WorkflowRequest>,
+    created_counts: HashMap<&'static str, Arc<AtomicU16>>,
+    output_receiver: UnboundedReceiver<MediaNotification>,
+    video_only_receiver: UnboundedReceiver<MediaNotification>,
+    sequence_header_request_count: Arc<AtomicU16>,
+}
+
+impl PassThroughWorkflow {
+    /// Starts a workflow of pass through steps with the specified types. Media that reaches the
+    /// `output` step type is reported to the output receiver, and media given to the `video_only`
+    /// step type is reported to the video only receiver. The `batch` step type holds onto media
+    /// payloads not required for decoding until any other media arrives.
+    fn start(step_types: &[&'static str]) -> Self {
+        Self::start_with_definition(pass_through_definition(step_types))
+    }
+
+    fn start_with_definition(definition: WorkflowDefinition) -> Self {
+        let (output_sender, output_receiver) = unbounded_channel();
+        let sequence_header_request_count = Arc::new(AtomicU16::new(0));
+        let mut factory = WorkflowStepFactory::new();
+        let mut created_counts = HashMap::new();
+        for step_type in ["ingest", "first", "second", "output"] {
+            let created_count = Arc::new(AtomicU16::new(0));
+            let media_sender = if step_type == "output" {
+                Some(output_sender.clone())
+            } else {
+                None
+            };
+
+            factory
+                .register(
+                    WorkflowStepType(step_type.to_string()),
+                    Box::new(TestPassThroughStepGenerator {
+                        created_count: created_count.clone(),
+                        media_sender,
+                    }),
+                )
+                .expect("Failed to register step");
+
+            created_counts.insert(step_type, created_count);
+        }
+
+        factory
+            .register(
+                WorkflowStepType("transcode".to_string()),
+                Box::new(TestTranscodeStepGenerator {
+                    sequence_header_request_count: sequence_header_request_count.clone(),
+                }),
+            )
+            .expect("Failed to register transcode step");
+
+        let (video_only_sender, video_only_receiver) = unbounded_channel();
+        factory
+            .register(
+                WorkflowStepType("video_only".to_string()),
+                Box::new(TestVideoOnlyStepGenerator {
+                    media_sender: video_only_sender,
+                }),
+            )
+            .expect("Failed to register video only step");
+
+        factory
+            .register(
+                WorkflowStepType("batch".to_string()),
+                Box::new(TestBatchingStepGenerator),
+            )
+            .expect("Failed to register batch step");
+
+        let stuck_created_count = Arc::new(AtomicU16::new(0));
+        factory
+            .register(
+                WorkflowStepType("stuck".to_string()),
+                Box::new(TestStuckStepGenerator {
+                    created_count: stuck_created_count.clone(),
+                }),
+            )
+            .expect("Failed to register stuck step");
+
+        created_counts.insert("stuck", stuck_created_count);
+
+        let workflow = start_workflow(definition, Arc::new(factory), CancellationToken::new());
+
+        PassThroughWorkflow {
+            workflow,
+            created_counts,
+            output_receiver,
+            video_only_receiver,
+            sequence_header_request_count,
+        }
+    }
+
+    fn created_count(&self, step_type: &str) -> u16 {
+        self.created_counts[step_type].load(Ordering::SeqCst)
+    }
+
+    fn send_media(&self, media: MediaNotification) {
+        self.workflow
+            .send(WorkflowRequest {
+                request_id: "".to_string(),
+                operation: WorkflowRequestOperation::MediaNotification { media },
+            })
+            .expect("Failed to send media to workflow");
+    }
+
+    fn update_definition(&self, step_types: &[&'static str]) {
+        self.workflow
+            .send(WorkflowRequest {
+                request_id: "".to_string(),
+                operation: WorkflowRequestOperation::UpdateDefinition {
+                    new_definition: pass_through_definition(step_types),
+                },
+            })
+            .expect("Failed to send update request");
+    }
+
+    async fn flush_caches(&self, stream_id: Option<StreamId>) -> FlushCachesResult {
+        let (sender, receiver) = channel();
+        self.workflow
+            .send(WorkflowRequest {
+                request_id: "".to_string(),
+                operation: WorkflowRequestOperation::FlushCaches {
+                    stream_id,
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send flush caches request");
+
+        test_utils::expect_oneshot_response(receiver)
+            .await
+            .expect("Expected flush caches result")
+    }
+
+    async fn get_resource_usage(&self) -> WorkflowResourceUsage {
+        let (sender, receiver) = channel();
+        self.workflow
+            .send(WorkflowRequest {
+                request_id: "".to_string(),
+                operation: WorkflowRequestOperation::GetResourceUsage {
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send resource usage request");
+
+        test_utils::expect_oneshot_response(receiver).await
+    }
+}
+
+fn pass_through_definition(step_types: &[&'static str]) -> WorkflowDefinition {
+    WorkflowDefinition {
+        name: Arc::new("abc".to_string()),
+        routed_by_reactor: false,
+        max_stream_lifetime: None,
+        max_cached_media_bytes: None,
+        watcher_limit: None,
+        steps: step_types
+            .iter()
+            .map(|step_type| WorkflowStepDefinition {
+                step_type: WorkflowStepType(step_type.to_string()),
+                parameters: HashMap::new(),
+            })
+            .collect(),
+    }
+}
+
+fn video_payload(stream_id: &StreamId, is_sequence_header: bool) -> MediaNotification {
+    MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Video,
+            payload_type: VIDEO_CODEC_H265_HVCC.clone(),
+            timestamp: Duration::from_millis(0),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data: Bytes::from_static(&[1, 2, 3, 4]),
+            is_required_for_decoding: is_sequence_header,
+        },
+        annotations: Default::default(),
+    }
+}
+
+async fn start_stream(context: &mut PassThroughWorkflow, stream_id: &StreamId) {
+    context.send_media(MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("def".to_string()),
+        },
+        annotations: Default::default(),
+    });
+
+    context.send_media(video_payload(stream_id, true));
+
+    let _ = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    let _ = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+}
+
+#[tokio::test]
+async fn reordering_middle_steps_keeps_existing_step_instances() {
+    let mut context = PassThroughWorkflow::start(&["ingest", "first", "second", "output"]);
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    start_stream(&mut context, &stream_id).await;
+
+    context.update_definition(&["ingest", "second", "first", "output"]);
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let (sender, receiver) = channel();
+    context
+        .workflow
+        .send(WorkflowRequest {
+            request_id: "".to_string(),
+            operation: WorkflowRequestOperation::GetState {
+                response_channel: sender,
+            },
+        })
+        .expect("Failed to send get state request to workflow");
+
+    let state = test_utils::expect_oneshot_response(receiver)
+        .await
+        .expect("Expected workflow state returned");
+
+    let active_step_types = state
+        .active_steps
+        .iter()
+        .map(|step| step.definition.step_type.0.as_str())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        active_step_types,
+        vec!["ingest", "second", "first", "output"],
+        "Unexpected active step order"
+    );
+
+    assert!(state.pending_steps.is_empty(), "Expected no pending steps");
+    for step_type in ["ingest", "first", "second", "output"] {
+        assert_eq!(
+            context.created_count(step_type),
+            1,
+            "Expected {} step to not be recreated",
+            step_type
+        );
+    }
+
+    let payload = video_payload(&stream_id, false);
+    context.send_media(payload.clone());
+
+    let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    assert_eq!(
+        response, payload,
+        "Unexpected media received by output step"
+    );
+}
+
+#[tokio::test]
+async fn step_moved_before_stream_origin_is_recreated() {
+    let mut context = PassThroughWorkflow::start(&["ingest", "first", "output"]);
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    start_stream(&mut context, &stream_id).await;
+
+    // The first step has seen the stream originating from the ingest step, which will now come
+    // after it
+    context.update_definition(&["first", "ingest", "output"]);
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    assert_eq!(
+        context.created_count("first"),
+        2,
+        "Expected first step to be recreated"
+    );
+    assert_eq!(
+        context.created_count("ingest"),
+        1,
+        "Expected ingest step to be kept"
+    );
+    assert_eq!(
+        context.created_count("output"),
+        1,
+        "Expected output step to be kept"
+    );
+}
+
+#[tokio::test]
+async fn resource_usage_reports_transcodes_and_cached_media() {
+    let mut context = PassThroughWorkflow::start(&["ingest", "transcode", "output"]);
+    for stream_id in ["abc", "def"] {
+        let stream_id = StreamId(Arc::new(stream_id.to_string()));
+        start_stream(&mut context, &stream_id).await;
+    }
+
+    let (sender, receiver) = channel();
+    context
+        .workflow
+        .send(WorkflowRequest {
+            request_id: "".to_string(),
+            operation: WorkflowRequestOperation::GetResourceUsage {
+                response_channel: sender,
+            },
+        })
+        .expect("Failed to send resource usage request");
+
+    let usage = test_utils::expect_oneshot_response(receiver).await;
+    assert_eq!(usage.workflow_name, "abc", "Unexpected workflow name");
+    assert_eq!(usage.active_streams, 2, "Unexpected active stream count");
+    assert_eq!(usage.active_pipelines, 2, "Expected a transcode per stream");
+    assert!(
+        usage.cached_media_bytes > 0,
+        "Expected cached sequence headers to be counted"
+    );
+}
+
+#[tokio::test]
+async fn av1_sequence_header_passed_through_and_cached() {
+    let mut context = PassThroughWorkflow::start(&["ingest", "output"]);
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    let new_stream = MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("def".to_string()),
+        },
+        annotations: Default::default(),
+    };
+
+    let record = Av1CodecConfigurationRecord {
+        seq_profile: 0,
+        seq_level_idx_0: 8,
+        seq_tier_0: false,
+        high_bitdepth: false,
+        twelve_bit: false,
+        monochrome: false,
+        chroma_subsampling_x: true,
+        chroma_subsampling_y: true,
+        chroma_sample_position: 0,
+        initial_presentation_delay_minus_one: None,
+        config_obus: Bytes::from_static(&[0x0a, 0x0b, 0x00, 0x00, 0x00, 0x24, 0xc4, 0xff]),
+    };
+
+    let av1_payload = |data: Bytes, is_required_for_decoding: bool| MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Video,
+            payload_type: VIDEO_CODEC_AV1.clone(),
+            timestamp: Duration::from_millis(0),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data,
+            is_required_for_decoding,
+        },
+        annotations: Default::default(),
+    };
+
+    let sequence_header = av1_payload(record.to_bytes(), true);
+    let frame = av1_payload(Bytes::from_static(&[0x12, 0x00, 0x32, 0x01]), false);
+
+    context.send_media(new_stream.clone());
+    let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    assert_eq!(response, new_stream, "Unexpected media passed through");
+
+    let initial_usage = context.get_resource_usage().await;
+
+    context.send_media(sequence_header.clone());
+    context.send_media(frame.clone());
+
+    for expected in [&sequence_header, &frame] {
+        let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+        assert_eq!(&response, expected, "Unexpected media passed through");
+    }
+
+    let usage = context.get_resource_usage().await;
+    assert!(
+        usage.cached_media_count > initial_usage.cached_media_count,
+        "Expected the configuration record to be cached"
+    );
+
+    // Only the cached record is considered a duplicate, so this shows it was cached as a
+    // sequence header
+    context.send_media(sequence_header.clone());
+    let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    assert_eq!(response, sequence_header, "Unexpected media passed through");
+
+    let duplicate_usage = context.get_resource_usage().await;
+    assert_eq!(
+        duplicate_usage.cached_media_count, usage.cached_media_count,
+        "Expected the duplicate configuration record to not be cached"
+    );
+}
+
+#[tokio::test]
+async fn duplicate_sequence_header_is_passed_through_but_not_cached() {
+    let mut context = PassThroughWorkflow::start(&["ingest", "output"]);
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    start_stream(&mut context, &stream_id).await;
+
+    let initial_usage = context.get_resource_usage().await;
+
+    let sequence_header = video_payload(&stream_id, true);
+    context.send_media(sequence_header.clone());
+
+    let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    assert_eq!(
+        response, sequence_header,
+        "Duplicate sequence header not passed through"
+    );
+
+    let usage = context.get_resource_usage().await;
+    assert_eq!(
+        usage.cached_media_count, initial_usage.cached_media_count,
+        "Expected duplicate sequence header to not be cached"
+    );
+}
+
+#[tokio::test]
+async fn changed_sequence_header_replaces_cached_sequence_header() {
+    let mut context = PassThroughWorkflow::start(&["ingest", "output"]);
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    start_stream(&mut context, &stream_id).await;
+
+    let initial_usage = context.get_resource_usage().await;
+
+    let mut sequence_header = video_payload(&stream_id, true);
+    if let MediaNotificationContent::MediaPayload { data, .. } = &mut sequence_header.content {
+        *data = Bytes::from_static(&[5, 6, 7, 8, 9]);
+    }
+
+    context.send_media(sequence_header.clone());
+
+    let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    assert_eq!(
+        response, sequence_header,
+        "Changed sequence header not passed through"
+    );
+
+    let usage = context.get_resource_usage().await;
+    assert_eq!(
+        usage.cached_media_count, initial_usage.cached_media_count,
+        "Expected changed sequence header to replace the cached one"
+    );
+    assert!(
+        usage.cached_media_bytes > initial_usage.cached_media_bytes,
+        "Expected the changed sequence header to be cached"
+    );
+}
+
+#[tokio::test]
+async fn cached_media_stays_within_limit_when_source_flags_excessive_required_packets() {
+    let mut definition = pass_through_definition(&["ingest", "output"]);
+    definition.max_cached_media_bytes = Some(100);
+
+    let mut context = PassThroughWorkflow::start_with_definition(definition);
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    start_stream(&mut context, &stream_id).await;
+
+    for index in 0..50u8 {
+        let media_type = match index % 3 {
+            0 => MediaType::Video,
+            1 => MediaType::Audio,
+            _ => MediaType::Other,
+        };
+
+        let mut media = video_payload(&stream_id, true);
+        if let MediaNotificationContent::MediaPayload {
+            media_type: payload_media_type,
+            data,
+            ..
+        } = &mut media.content
+        {
+            *payload_media_type = media_type;
+            *data = Bytes::from(vec![index; 40 + index as usize]);
+        }
+
+        context.send_media(media.clone());
+
+        let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+        assert_eq!(
+            response, media,
+            "Expected required packet to be passed through"
+        );
+    }
+
+    // The inbound cache and each step's cache are limited separately
+    let usage = context.get_resource_usage().await;
+    assert!(
+        usage.cached_media_bytes <= 300,
+        "Expected cached bytes to stay within the limit, but {} bytes were cached",
+        usage.cached_media_bytes
+    );
+    assert!(
+        usage.cache_limit_exceeded_count > 0,
+        "Expected packets exceeding the limit to be counted"
+    );
+}
+
+#[tokio::test]
+async fn flushing_caches_clears_sequence_headers_and_requests_keyframe_from_transcode() {
+    let mut context = PassThroughWorkflow::start(&["ingest", "transcode", "output"]);
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    start_stream(&mut context, &stream_id).await;
+
+    let usage = context.get_resource_usage().await;
+    assert!(
+        usage.cached_media_bytes > 0,
+        "Expected sequence headers to be cached"
+    );
+
+    let result = context.flush_caches(Some(stream_id.clone())).await;
+    assert_eq!(
+        result,
+        FlushCachesResult {
+            flushed_stream_count: 1,
+            refresh_requested: true,
+        },
+        "Unexpected flush result"
+    );
+
+    assert_eq!(
+        context.sequence_header_request_count.load(Ordering::SeqCst),
+        1,
+        "Expected the transcode step to be asked for fresh sequence headers"
+    );
+
+    let usage = context.get_resource_usage().await;
+    assert_eq!(
+        usage.cached_media_bytes, 0,
+        "Expected sequence headers to be flushed"
+    );
+    assert!(
+        usage.cached_media_count > 0,
+        "Expected the stream's announcement to remain cached"
+    );
+
+    // The same sequence header is no longer a duplicate, so it's passed through again
+    context.send_media(video_payload(&stream_id, true));
+    let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    assert_eq!(
+        response,
+        video_payload(&stream_id, true),
+        "Expected sequence header to be passed through after flush"
+    );
+}
+
+#[tokio::test]
+async fn flushing_caches_without_transcode_does_not_request_refresh() {
+    let mut context = PassThroughWorkflow::start(&["ingest", "output"]);
+    for stream_id in ["abc", "def"] {
+        let stream_id = StreamId(Arc::new(stream_id.to_string()));
+        start_stream(&mut context, &stream_id).await;
+    }
+
+    let result = context.flush_caches(None).await;
+    assert_eq!(
+        result,
+        FlushCachesResult {
+            flushed_stream_count: 2,
+            refresh_requested: false,
+        },
+        "Unexpected flush result"
+    );
+}
+
+#[tokio::test]
+async fn workflow_stops_when_shutdown_token_cancelled() {
+    let shutdown_token = CancellationToken::new();
+    let workflow = start_workflow(
+        pass_through_definition(&["ingest", "output"]),
+        Arc::new(WorkflowStepFactory::new()),
+        shutdown_token.clone(),
+    );
+
+    shutdown_token.cancel();
+
+    timeout(Duration::from_millis(100), workflow.closed())
+        .await
+        .expect("Workflow did not stop after shutdown was requested");
+}
+
+#[tokio::test]
+async fn stream_disconnected_once_max_lifetime_elapses_despite_continuous_media() {
+    let mut definition = pass_through_definition(&["ingest", "output"]);
+    definition.max_stream_lifetime = Some(Duration::from_millis(200));
+
+    let mut context = PassThroughWorkflow::start_with_definition(definition);
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    start_stream(&mut context, &stream_id).await;
+
+    let mut payload_count = 0;
+    let disconnected = timeout(Duration::from_secs(2), async {
+        loop {
+            context.send_media(video_payload(&stream_id, false));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+
+            while let Ok(media) = context.output_receiver.try_recv() {
+                match media.content {
+                    MediaNotificationContent::MediaPayload { .. } => payload_count += 1,
+                    MediaNotificationContent::StreamDisconnected => return,
+                    _ => (),
+                }
+            }
+        }
+    })
+    .await;
+
+    assert!(disconnected.is_ok(), "Stream was never disconnected");
+    assert!(
+        payload_count > 5,
+        "Expected media to keep flowing until the lifetime elapsed, but only {} payloads did",
+        payload_count
+    );
+
+    let (sender, receiver) = channel();
+    context
+        .workflow
+        .send(WorkflowRequest {
+            request_id: "".to_string(),
+            operation: WorkflowRequestOperation::GetActiveStreams {
+                response_channel: sender,
+            },
+        })
+        .expect("Failed to send get active streams request");
+
+    let response = test_utils::expect_oneshot_response(receiver).await;
+    assert!(response.is_empty(), "Expected no active streams");
+}
+
+#[tokio::test]
+async fn stream_not_disconnected_without_max_lifetime() {
+    let mut context = PassThroughWorkflow::start(&["ingest", "output"]);
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    start_stream(&mut context, &stream_id).await;
+
+    for _ in 0..20 {
+        context.send_media(video_payload(&stream_id, false));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    while let Ok(media) = context.output_receiver.try_recv() {
+        if let MediaNotificationContent::StreamDisconnected = media.content {
+            panic!("Stream was unexpectedly disconnected");
+        }
+    }
+}
+
+#[tokio::test]
+async fn step_producing_no_output_restarted_after_watchdog_timeout() {
+    let mut definition = pass_through_definition(&["ingest", "stuck", "output"]);
+    definition.steps[1]
+        .parameters
+        .insert(WATCHDOG_TIMEOUT.to_string(), Some("0.1".to_string()));
+
+    let mut context = PassThroughWorkflow::start_with_definition(definition);
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    start_stream(&mut context, &stream_id).await;
+
+    context.send_media(video_payload(&stream_id, false));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    assert_eq!(
+        context.created_count("stuck"),
+        2,
+        "Expected the stuck step to be recreated"
+    );
+
+    assert_eq!(
+        context.created_count("output"),
+        1,
+        "Expected later steps to not be recreated"
+    );
+
+    let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    match response.content {
+        MediaNotificationContent::Metadata { data } => {
+            assert!(
+                data.contains_key(DISCONTINUITY_METADATA_KEY),
+                "Expected discontinuity metadata"
+            );
+        }
+
+        content => panic!("Expected metadata, instead got {:?}", content),
+    }
+
+    let (sender, receiver) = channel();
+    context
+        .workflow
+        .send(WorkflowRequest {
+            request_id: "".to_string(),
+            operation: WorkflowRequestOperation::GetState {
+                response_channel: sender,
+            },
+        })
+        .expect("Failed to send get state request");
+
+    let state = test_utils::expect_oneshot_response(receiver)
+        .await
+        .expect("Expected workflow state");
+
+    let stuck_step = state
+        .active_steps
+        .iter()
+        .find(|step| step.definition.step_type.0 == "stuck")
+        .expect("No stuck step in the workflow state");
+
+    assert_eq!(stuck_step.restart_count, 1, "Unexpected restart count");
+}
+
+#[tokio::test]
+async fn watcher_limit_enforced_across_all_output_steps() {
+    let (watchers_sender, mut watchers_receiver) = unbounded_channel();
+    let mut factory = WorkflowStepFactory::new();
+    factory
+        .register(
+            WorkflowStepType("watch".to_string()),
+            Box::new(TestWatcherStepGenerator { watchers_sender }),
+        )
+        .expect("Failed to register watcher step");
+
+    // Each step needs different parameters, otherwise they'd be treated as the same step
+    let mut definition = pass_through_definition(&["watch", "watch"]);
+    for (index, step) in definition.steps.iter_mut().enumerate() {
+        step.parameters
+            .insert("index".to_string(), Some(index.to_string()));
+    }
+
+    definition.watcher_limit = Some(WatcherLimit {
+        max_watchers: 3,
+        behavior: WatcherLimitBehavior::Reject,
+    });
+
+    let workflow = start_workflow(definition, Arc::new(factory), CancellationToken::new());
+
+    let first_step = test_utils::expect_mpsc_response(&mut watchers_receiver)
+        .await
+        .expect("First step was not given the workflow watchers");
+
+    let second_step = test_utils::expect_mpsc_response(&mut watchers_receiver)
+        .await
+        .expect("Second step was not given the workflow watchers");
+
+    let _first = first_step.try_add_watcher();
+    let _second = first_step.try_add_watcher();
+    let third = second_step.try_add_watcher();
+    assert!(
+        matches!(third, WatcherAdmission::Admitted(_)),
+        "Expected third watcher to be admitted"
+    );
+
+    assert!(
+        matches!(second_step.try_add_watcher(), WatcherAdmission::Rejected),
+        "Expected fourth watcher to be rejected by the second step"
+    );
+
+    assert!(
+        matches!(first_step.try_add_watcher(), WatcherAdmission::Rejected),
+        "Expected fourth watcher to be rejected by the first step"
+    );
+
+    let (sender, receiver) = channel();
+    workflow
+        .send(WorkflowRequest {
+            request_id: "".to_string(),
+            operation: WorkflowRequestOperation::GetState {
+                response_channel: sender,
+            },
+        })
+        .expect("Failed to send get state request");
+
+    let state = test_utils::expect_oneshot_response(receiver)
+        .await
+        .expect("Expected workflow state");
+
+    assert_eq!(state.watcher_count, 3, "Unexpected watcher count");
+    assert_eq!(state.max_watchers, Some(3), "Unexpected max watchers");
+
+    drop(third);
+    assert!(
+        matches!(second_step.try_add_watcher(), WatcherAdmission::Admitted(_)),
+        "Expected watcher to be admitted after another left"
+    );
+}
+
+#[tokio::test]
+async fn late_audio_track_announced_with_replayed_sequence_header() {
+    let mut context = PassThroughWorkflow::start(&["ingest", "first", "output"]);
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    start_stream(&mut context, &stream_id).await;
+
+    let payload = |media_type, millis, is_sequence_header| MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type,
+            payload_type: Arc::new("codec".to_string()),
+            timestamp: Duration::from_millis(millis),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data: Bytes::from_static(&[1, 2, 3]),
+            is_required_for_decoding: is_sequence_header,
+        },
+        annotations: Default::default(),
+    };
+
+    for millis in [0, 500, 1000, 1500] {
+        let video = payload(MediaType::Video, millis, false);
+        context.send_media(video.clone());
+
+        let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+        assert_eq!(response, video, "Expected video to pass through untouched");
+    }
+
+    let audio_sequence_header = payload(MediaType::Audio, 1800, true);
+    context.send_media(audio_sequence_header.clone());
+    let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    assert_eq!(
+        response, audio_sequence_header,
+        "Expected audio sequence header"
+    );
+
+    let audio = payload(MediaType::Audio, 2000, false);
+    context.send_media(audio.clone());
+
+    let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    assert_eq!(
+        get_added_track(&response.content),
+        Some(MediaType::Audio),
+        "Expected audio track added notification, instead got {:?}",
+        response.content
+    );
+
+    let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    assert_eq!(
+        response, audio_sequence_header,
+        "Expected audio sequence header to be replayed"
+    );
+
+    let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    assert_eq!(response, audio, "Expected the late audio payload");
+
+    // Both tracks continue on without any more announcements
+    let later_audio = payload(MediaType::Audio, 2100, false);
+    let later_video = payload(MediaType::Video, 2100, false);
+    context.send_media(later_audio.clone());
+    context.send_media(later_video.clone());
+
+    let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    assert_eq!(response, later_audio, "Expected audio to keep flowing");
+
+    let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    assert_eq!(response, later_video, "Expected video to keep flowing");
+    test_utils::expect_mpsc_timeout(&mut context.output_receiver).await;
+}
+
+#[tokio::test]
+async fn tracks_starting_together_are_not_announced() {
+    let mut context = PassThroughWorkflow::start(&["ingest", "output"]);
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    start_stream(&mut context, &stream_id).await;
+
+    let mut audio = video_payload(&stream_id, false);
+    if let MediaNotificationContent::MediaPayload { media_type, .. } = &mut audio.content {
+        *media_type = MediaType::Audio;
+    }
+
+    context.send_media(video_payload(&stream_id, false));
+    context.send_media(audio.clone());
+
+    let _ = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    assert_eq!(response, audio, "Expected audio without an announcement");
+    test_utils::expect_mpsc_timeout(&mut context.output_receiver).await;
+}
+
+#[tokio::test]
+async fn channel_depth_reflects_backed_up_workflow_channel() {
+    let definition = WorkflowDefinition {
+        name: Arc::new("backed_up_channel".to_string()),
+        routed_by_reactor: false,
+        max_stream_lifetime: None,
+        max_cached_media_bytes: None,
+        watcher_limit: None,
+        steps: Vec::new(),
+    };
+
+    let factory = Arc::new(WorkflowStepFactory::new());
+    let workflow = start_workflow(definition, factory, CancellationToken::new());
+
+    // Queue up requests faster than the workflow gets a chance to process them
+    for _ in 0..100 {
+        let (sender, _receiver) = channel();
+        workflow
+            .send(WorkflowRequest {
+                request_id: "".to_string(),
+                operation: WorkflowRequestOperation::GetResourceUsage {
+                    response_channel: sender,
+                },
+            })
+            .expect("Failed to send resource usage request");
+    }
+
+    let (sender, receiver) = channel();
+    workflow
+        .send(WorkflowRequest {
+            request_id: "".to_string(),
+            operation: WorkflowRequestOperation::GetState {
+                response_channel: sender,
+            },
+        })
+        .expect("Failed to send get state request");
+
+    let _ = test_utils::expect_oneshot_response(receiver).await;
+
+    let depth = channel_depths()
+        .into_iter()
+        .find(|depth| depth.name == "workflow:backed_up_channel")
+        .expect("No channel depth for the workflow");
+
+    assert!(
+        depth.peak_depth >= 50,
+        "Expected a peak depth of at least 50, but it was {}",
+        depth.peak_depth
+    );
+
+    assert_eq!(depth.depth, 0, "Expected the backlog to be processed");
+}
+
+#[tokio::test]
+async fn audio_bypasses_step_not_wanting_it_but_reaches_later_steps() {
+    let mut context = PassThroughWorkflow::start(&["ingest", "video_only", "output"]);
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    start_stream(&mut context, &stream_id).await;
+
+    let response = test_utils::expect_mpsc_response(&mut context.video_only_receiver).await;
+    match response.content {
+        MediaNotificationContent::NewIncomingStream { .. } => (),
+        x => panic!("Expected new incoming stream, instead got {:?}", x),
+    }
+
+    let _ = test_utils::expect_mpsc_response(&mut context.video_only_receiver).await;
+
+    let audio = MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Audio,
+            payload_type: Arc::new("codec".to_string()),
+            timestamp: Duration::from_millis(0),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data: Bytes::from_static(&[1, 2, 3]),
+            is_required_for_decoding: true,
+        },
+        annotations: Default::default(),
+    };
+
+    let video = video_payload(&stream_id, false);
+    context.send_media(audio.clone());
+    context.send_media(video.clone());
+
+    let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    assert_eq!(response, audio, "Expected audio to reach the output step");
+
+    let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+    assert_eq!(response, video, "Expected video to reach the output step");
+
+    let response = test_utils::expect_mpsc_response(&mut context.video_only_receiver).await;
+    assert_eq!(response, video, "Expected video only step to receive video");
+    test_utils::expect_mpsc_timeout(&mut context.video_only_receiver).await;
+}
+
+#[tokio::test]
+async fn bypassed_media_keeps_its_position_within_a_batch() {
+    let mut context = PassThroughWorkflow::start(&["ingest", "batch", "video_only", "output"]);
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    start_stream(&mut context, &stream_id).await;
+
+    let _ = test_utils::expect_mpsc_response(&mut context.video_only_receiver).await;
+    let _ = test_utils::expect_mpsc_response(&mut context.video_only_receiver).await;
+
+    let audio = |data: &'static [u8]| MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Audio,
+            payload_type: Arc::new("codec".to_string()),
+            timestamp: Duration::from_millis(0),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data: Bytes::from_static(data),
+            is_required_for_decoding: false,
+        },
+        annotations: Default::default(),
+    };
+
+    let metadata = MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::Metadata {
+            data: HashMap::new(),
+        },
+        annotations: Default::default(),
+    };
+
+    // The batch step hands all of these to the video only step in a single batch
+    let batch = vec![
+        audio(&[1]),
+        video_payload(&stream_id, false),
+        audio(&[2]),
+        audio(&[3]),
+        video_payload(&stream_id, false),
+        metadata,
+    ];
+
+    for media in &batch {
+        context.send_media(media.clone());
+    }
+
+    for expected in &batch {
+        let response = test_utils::expect_mpsc_response(&mut context.output_receiver).await;
+        assert_eq!(&response, expected, "Unexpected media order at the output");
+    }
+
+    test_utils::expect_mpsc_timeout(&mut context.output_receiver).await;
+
+    for expected in [&batch[1], &batch[4], &batch[5]] {
+        let response = test_utils::expect_mpsc_response(&mut context.video_only_receiver).await;
+        assert_eq!(
+            &response, expected,
+            "Unexpected media given to video only step"
+        );
+    }
+
+    test_utils::expect_mpsc_timeout(&mut context.video_only_receiver).await;
+}
@@ -0,0 +1,205 @@
+//! Watchdogs detect workflow steps that have wedged, such as a transcode whose pipeline got stuck,
+//! so the workflow runner can restart them.
+//!
+//! A watchdog is enabled for a step by giving it a `watchdog_timeout` parameter, with the number
+//! of seconds (fractions are allowed) the step may go without producing any media payloads while
+//! it's being given media payloads. Once that time has passed the watchdog trips. Not specifying
+//! the parameter (or specifying `0`) disables the watchdog. Sequence headers and other media
+//! required for decoding don't count as input, since steps aren't expected to produce output from
+//! them alone.
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+pub const WATCHDOG_TIMEOUT: &str = "watchdog_timeout";
+
+/// Tracks whether a step is producing output for the input it's given
+#[derive(Debug)]
+pub struct StepWatchdog {
+    timeout: Duration,
+
+    /// When the step was first given input without producing any output since
+    awaiting_output_since: Option<Instant>,
+
+    /// If the runner has been asked to check the watchdog, so only one check is pending at a time
+    check_scheduled: bool,
+}
+
+/// The outcome of checking a step's watchdog
+#[derive(Debug, PartialEq, Eq)]
+pub enum WatchdogCheck {
+    /// The step has produced output for all of its input
+    Healthy,
+
+    /// The step is waiting on output, but the timeout hasn't passed yet. The watchdog should be
+    /// checked again after the specified duration.
+    CheckAgainIn(Duration),
+
+    /// The step has gone without output for longer than the timeout
+    Tripped,
+}
+
+#[derive(Error, Debug)]
+pub enum WatchdogError {
+    #[error(
+        "Invalid {} value of '{0}'.  It must be a number of seconds that's zero or greater",
+        WATCHDOG_TIMEOUT
+    )]
+    InvalidTimeout(String),
+}
+
+impl StepWatchdog {
+    /// Creates a watchdog based on the step definition's parameters, or `None` if the step
+    /// doesn't have one enabled
+    pub fn from_step_definition(
+        definition: &WorkflowStepDefinition,
+    ) -> Result<Option<StepWatchdog>, WatchdogError> {
+        let value = match definition.parameters.get(WATCHDOG_TIMEOUT) {
+            Some(Some(value)) if !value.trim().is_empty() => value.trim(),
+            _ => return Ok(None),
+        };
+
+        match value.parse::<f64>() {
+            Ok(0.0) => Ok(None),
+            Ok(seconds) if seconds.is_finite() && seconds > 0.0 => Ok(Some(StepWatchdog {
+                timeout: Duration::from_secs_f64(seconds),
+                awaiting_output_since: None,
+                check_scheduled: false,
+            })),
+
+            _ => Err(WatchdogError::InvalidTimeout(value.to_string())),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Records that the step was given input. Returns `true` if the runner should schedule a
+    /// check of the watchdog once the timeout has passed.
+    pub fn input_received(&mut self) -> bool {
+        if self.awaiting_output_since.is_none() {
+            self.awaiting_output_since = Some(Instant::now());
+        }
+
+        !std::mem::replace(&mut self.check_scheduled, true)
+    }
+
+    /// Records that the step produced output
+    pub fn output_produced(&mut self) {
+        self.awaiting_output_since = None;
+    }
+
+    /// Checks if the watchdog has tripped. This should only be called when a scheduled check is
+    /// due, and a `CheckAgainIn` result must be followed by scheduling another check.
+    pub fn check(&mut self) -> WatchdogCheck {
+        let waiting_for = match self.awaiting_output_since {
+            Some(since) => since.elapsed(),
+            None => {
+                // The next input schedules a new check
+                self.check_scheduled = false;
+                return WatchdogCheck::Healthy;
+            }
+        };
+
+        if waiting_for < self.timeout {
+            return WatchdogCheck::CheckAgainIn(self.timeout - waiting_for);
+        }
+
+        self.check_scheduled = false;
+        WatchdogCheck::Tripped
+    }
+
+    /// Forgets any input the step was waiting to produce output for, such as after it was
+    /// restarted
+    pub fn reset(&mut self) {
+        self.awaiting_output_since = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflows::definitions::WorkflowStepType;
+    use std::collections::HashMap;
+
+    fn definition(timeout: Option<&str>) -> WorkflowStepDefinition {
+        let mut definition = WorkflowStepDefinition {
+            step_type: WorkflowStepType("test".to_string()),
+            parameters: HashMap::new(),
+        };
+
+        if let Some(timeout) = timeout {
+            definition
+                .parameters
+                .insert(WATCHDOG_TIMEOUT.to_string(), Some(timeout.to_string()));
+        }
+
+        definition
+    }
+
+    #[test]
+    fn watchdog_disabled_when_timeout_not_specified_or_zero() {
+        for timeout in [None, Some("0")] {
+            let watchdog = StepWatchdog::from_step_definition(&definition(timeout))
+                .expect("Expected a valid definition");
+
+            assert!(watchdog.is_none(), "Expected no watchdog for {:?}", timeout);
+        }
+    }
+
+    #[test]
+    fn invalid_timeout_returns_error() {
+        for timeout in ["abc", "-1"] {
+            let result = StepWatchdog::from_step_definition(&definition(Some(timeout)));
+            assert!(result.is_err(), "Expected an error for {}", timeout);
+        }
+    }
+
+    #[test]
+    fn only_first_input_schedules_a_check() {
+        let mut watchdog = StepWatchdog::from_step_definition(&definition(Some("5")))
+            .unwrap()
+            .unwrap();
+
+        assert!(
+            watchdog.input_received(),
+            "Expected first input to schedule"
+        );
+        watchdog.output_produced();
+        assert!(!watchdog.input_received(), "Expected no second schedule");
+    }
+
+    #[test]
+    fn healthy_when_output_produced() {
+        let mut watchdog = StepWatchdog::from_step_definition(&definition(Some("5")))
+            .unwrap()
+            .unwrap();
+
+        watchdog.input_received();
+        watchdog.output_produced();
+
+        assert_eq!(watchdog.check(), WatchdogCheck::Healthy, "Unexpected check");
+        assert!(
+            watchdog.input_received(),
+            "Expected input to schedule again"
+        );
+    }
+
+    #[test]
+    fn check_again_when_timeout_not_yet_passed() {
+        let mut watchdog = StepWatchdog::from_step_definition(&definition(Some("5")))
+            .unwrap()
+            .unwrap();
+
+        watchdog.input_received();
+        match watchdog.check() {
+            WatchdogCheck::CheckAgainIn(remaining) => {
+                assert!(remaining <= Duration::from_secs(5), "Unexpected remaining")
+            }
+
+            check => panic!("Expected check again, instead got {:?}", check),
+        }
+    }
+}
@@ -0,0 +1,547 @@
+//! Compact binary serialization of media notifications, allowing them to be sent over the network
+//! or persisted to disk.
+//!
+//! Every serialized notification starts with a version byte, followed by a single byte that packs
+//! the type of notification, the media type, and the required for decoding flag together. Lengths
+//! and timestamps are encoded as LEB128 variable length integers, and well known codecs are
+//! encoded as a single byte instead of their full name. Media payload bytes are placed at the end
+//! of the serialized notification without a length prefix, as they take up the remainder of it.
+//!
+//! Serialization does not copy the payload either. The serialized notification is returned as the
+//! encoded fields chained with the original payload `Bytes`, which can be written out as is.
+//! Deserialization does not copy the payload or its metadata, as both are returned as slices of
+//! the original `Bytes` value.
+
+use crate::codecs::{
+    AUDIO_CODEC_AAC_RAW, VIDEO_CODEC_AV1, VIDEO_CODEC_H264_AVC, VIDEO_CODEC_H265_HVCC,
+};
+use crate::workflows::metadata::MediaPayloadMetadataCollection;
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use crate::StreamId;
+use bytes::buf::Chain;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+/// The current version of the serialization format
+pub const SERIALIZATION_VERSION: u8 = 1;
+
+const NEW_INCOMING_STREAM: u8 = 0;
+const STREAM_DISCONNECTED: u8 = 1;
+const METADATA: u8 = 2;
+const MEDIA_PAYLOAD: u8 = 3;
+const NOTIFICATION_TYPE_MASK: u8 = 0b0000_0011;
+
+const REQUIRED_FOR_DECODING_FLAG: u8 = 0b0000_0100;
+
+const MEDIA_TYPE_SHIFT: u8 = 3;
+const MEDIA_TYPE_MASK: u8 = 0b0001_1000;
+const MEDIA_TYPE_AUDIO: u8 = 0;
+const MEDIA_TYPE_VIDEO: u8 = 1;
+const MEDIA_TYPE_OTHER: u8 = 2;
+
+const CODEC_CUSTOM: u8 = 0;
+const CODEC_H264_AVC: u8 = 1;
+const CODEC_AAC_RAW: u8 = 2;
+const CODEC_H265_HVCC: u8 = 3;
+const CODEC_AV1: u8 = 4;
+
+/// Errors that can occur when deserializing a media notification
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum DeserializationError {
+    #[error("Serialized notification had a version of {0}, which is not supported")]
+    UnsupportedVersion(u8),
+
+    #[error("Serialized notification ended before it could be fully read")]
+    UnexpectedEnd,
+
+    #[error("Unknown notification type of {0}")]
+    UnknownNotificationType(u8),
+
+    #[error("Unknown media type of {0}")]
+    UnknownMediaType(u8),
+
+    #[error("Unknown codec id of {0}")]
+    UnknownCodec(u8),
+
+    #[error("A variable length integer was malformed")]
+    InvalidVarInt,
+
+    #[error("A string value was not valid utf-8")]
+    InvalidString,
+
+    #[error("Payload metadata could not be decoded")]
+    InvalidMetadata,
+}
+
+/// Serializes the media notification into its compact binary form. The encoded fields are chained
+/// with the media payload's bytes (which are empty for other notifications), so the payload
+/// doesn't need to be copied.
+pub fn to_bytes(media: &MediaNotification) -> Chain<Bytes, Bytes> {
+    let mut buffer = BytesMut::with_capacity(estimate_size(media));
+    let mut payload = Bytes::new();
+    buffer.put_u8(SERIALIZATION_VERSION);
+
+    match &media.content {
+        MediaNotificationContent::NewIncomingStream { stream_name } => {
+            buffer.put_u8(NEW_INCOMING_STREAM);
+            put_string(&media.stream_id.0, &mut buffer);
+            put_string(stream_name, &mut buffer);
+        }
+
+        MediaNotificationContent::StreamDisconnected => {
+            buffer.put_u8(STREAM_DISCONNECTED);
+            put_string(&media.stream_id.0, &mut buffer);
+        }
+
+        MediaNotificationContent::Metadata { data } => {
+            buffer.put_u8(METADATA);
+            put_string(&media.stream_id.0, &mut buffer);
+            put_varint(data.len() as u64, &mut buffer);
+            for (key, value) in data {
+                put_string(key, &mut buffer);
+                put_string(value, &mut buffer);
+            }
+        }
+
+        MediaNotificationContent::MediaPayload {
+            media_type,
+            payload_type,
+            timestamp,
+            metadata,
+            data,
+            is_required_for_decoding,
+        } => {
+            let media_type = match media_type {
+                MediaType::Audio => MEDIA_TYPE_AUDIO,
+                MediaType::Video => MEDIA_TYPE_VIDEO,
+                MediaType::Other => MEDIA_TYPE_OTHER,
+            };
+
+            let mut tag = MEDIA_PAYLOAD | (media_type << MEDIA_TYPE_SHIFT);
+            if *is_required_for_decoding {
+                tag |= REQUIRED_FOR_DECODING_FLAG;
+            }
+
+            buffer.put_u8(tag);
+            put_string(&media.stream_id.0, &mut buffer);
+
+            if payload_type == &*VIDEO_CODEC_H264_AVC {
+                buffer.put_u8(CODEC_H264_AVC);
+            } else if payload_type == &*AUDIO_CODEC_AAC_RAW {
+                buffer.put_u8(CODEC_AAC_RAW);
+            } else if payload_type == &*VIDEO_CODEC_H265_HVCC {
+                buffer.put_u8(CODEC_H265_HVCC);
+            } else if payload_type == &*VIDEO_CODEC_AV1 {
+                buffer.put_u8(CODEC_AV1);
+            } else {
+                buffer.put_u8(CODEC_CUSTOM);
+                put_string(payload_type, &mut buffer);
+            }
+
+            put_varint(timestamp.as_micros() as u64, &mut buffer);
+
+            let metadata = metadata.raw_bytes();
+            put_varint(metadata.len() as u64, &mut buffer);
+            buffer.put(metadata);
+
+            payload = data.clone();
+        }
+    }
+
+    buffer.freeze().chain(payload)
+}
+
+/// Deserializes a media notification that was serialized with `to_bytes()`
+pub fn from_bytes(mut bytes: Bytes) -> Result<MediaNotification, DeserializationError> {
+    let version = get_u8(&mut bytes)?;
+    if version != SERIALIZATION_VERSION {
+        return Err(DeserializationError::UnsupportedVersion(version));
+    }
+
+    let tag = get_u8(&mut bytes)?;
+    let stream_id = StreamId(Arc::new(get_string(&mut bytes)?));
+    let content = match tag & NOTIFICATION_TYPE_MASK {
+        NEW_INCOMING_STREAM => MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new(get_string(&mut bytes)?),
+        },
+
+        STREAM_DISCONNECTED => MediaNotificationContent::StreamDisconnected,
+
+        METADATA => {
+            let count = get_varint(&mut bytes)?;
+            let mut data = HashMap::new();
+            for _ in 0..count {
+                let key = get_string(&mut bytes)?;
+                let value = get_string(&mut bytes)?;
+                data.insert(key, value);
+            }
+
+            MediaNotificationContent::Metadata { data }
+        }
+
+        MEDIA_PAYLOAD => {
+            let media_type = match (tag & MEDIA_TYPE_MASK) >> MEDIA_TYPE_SHIFT {
+                MEDIA_TYPE_AUDIO => MediaType::Audio,
+                MEDIA_TYPE_VIDEO => MediaType::Video,
+                MEDIA_TYPE_OTHER => MediaType::Other,
+                x => return Err(DeserializationError::UnknownMediaType(x)),
+            };
+
+            let payload_type = match get_u8(&mut bytes)? {
+                CODEC_CUSTOM => Arc::new(get_string(&mut bytes)?),
+                CODEC_H264_AVC => VIDEO_CODEC_H264_AVC.clone(),
+                CODEC_AAC_RAW => AUDIO_CODEC_AAC_RAW.clone(),
+                CODEC_H265_HVCC => VIDEO_CODEC_H265_HVCC.clone(),
+                CODEC_AV1 => VIDEO_CODEC_AV1.clone(),
+                x => return Err(DeserializationError::UnknownCodec(x)),
+            };
+
+            let timestamp = Duration::from_micros(get_varint(&mut bytes)?);
+
+            let metadata_length = get_varint(&mut bytes)? as usize;
+            if bytes.remaining() < metadata_length {
+                return Err(DeserializationError::UnexpectedEnd);
+            }
+
+            let metadata = bytes.split_to(metadata_length);
+            let metadata = MediaPayloadMetadataCollection::from_raw_bytes(metadata)
+                .map_err(|_| DeserializationError::InvalidMetadata)?;
+
+            MediaNotificationContent::MediaPayload {
+                media_type,
+                payload_type,
+                timestamp,
+                metadata,
+                data: bytes,
+                is_required_for_decoding: tag & REQUIRED_FOR_DECODING_FLAG != 0,
+            }
+        }
+
+        x => return Err(DeserializationError::UnknownNotificationType(x)),
+    };
+
+    Ok(MediaNotification {
+        stream_id,
+        content,
+        annotations: Default::default(),
+    })
+}
+
+fn estimate_size(media: &MediaNotification) -> usize {
+    // version, tag, and room for a few varints
+    let mut size = 16 + media.stream_id.0.len();
+    match &media.content {
+        MediaNotificationContent::NewIncomingStream { stream_name } => size += stream_name.len(),
+        MediaNotificationContent::StreamDisconnected => (),
+        MediaNotificationContent::Metadata { data } => {
+            size += data
+                .iter()
+                .map(|(k, v)| k.len() + v.len() + 4)
+                .sum::<usize>();
+        }
+
+        MediaNotificationContent::MediaPayload {
+            payload_type,
+            metadata,
+            ..
+        } => {
+            size += payload_type.len() + metadata.raw_bytes().len() + 32;
+        }
+    }
+
+    size
+}
+
+fn put_varint(mut value: u64, buffer: &mut BytesMut) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buffer.put_u8(byte);
+            break;
+        }
+
+        buffer.put_u8(byte | 0x80);
+    }
+}
+
+fn get_varint(bytes: &mut Bytes) -> Result<u64, DeserializationError> {
+    let mut value = 0_u64;
+    for index in 0..10 {
+        let byte = get_u8(bytes)?;
+        value |= ((byte & 0x7f) as u64) << (index * 7);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(DeserializationError::InvalidVarInt)
+}
+
+fn put_string(value: &str, buffer: &mut BytesMut) {
+    put_varint(value.len() as u64, buffer);
+    buffer.put(value.as_bytes());
+}
+
+fn get_string(bytes: &mut Bytes) -> Result<String, DeserializationError> {
+    let length = get_varint(bytes)? as usize;
+    if bytes.remaining() < length {
+        return Err(DeserializationError::UnexpectedEnd);
+    }
+
+    let value = bytes.split_to(length);
+    String::from_utf8(value.to_vec()).map_err(|_| DeserializationError::InvalidString)
+}
+
+fn get_u8(bytes: &mut Bytes) -> Result<u8, DeserializationError> {
+    if !bytes.has_remaining() {
+        return Err(DeserializationError::UnexpectedEnd);
+    }
+
+    Ok(bytes.get_u8())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflows::annotations::ArrivalTime;
+    use crate::workflows::metadata::{
+        MetadataEntry, MetadataKeyMap, MetadataValue, MetadataValueType,
+    };
+    use std::iter;
+    use std::time::Instant;
+
+    fn stream_id() -> StreamId {
+        StreamId(Arc::new("abc".to_string()))
+    }
+
+    fn serialize(media: &MediaNotification) -> Bytes {
+        let mut serialized = to_bytes(media);
+
+        serialized.copy_to_bytes(serialized.remaining())
+    }
+
+    fn assert_round_trip(media: MediaNotification) {
+        let bytes = serialize(&media);
+        let result = from_bytes(bytes).expect("Failed to deserialize notification");
+
+        assert_eq!(result, media, "Unexpected deserialized notification");
+    }
+
+    #[test]
+    fn can_round_trip_new_incoming_stream() {
+        assert_round_trip(MediaNotification {
+            stream_id: stream_id(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        });
+    }
+
+    #[test]
+    fn can_round_trip_stream_disconnected() {
+        assert_round_trip(MediaNotification {
+            stream_id: stream_id(),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        });
+    }
+
+    #[test]
+    fn annotations_are_not_serialized() {
+        let mut media = MediaNotification {
+            stream_id: stream_id(),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        };
+
+        media.annotations.insert(ArrivalTime(Instant::now()));
+
+        let result = from_bytes(serialize(&media)).expect("Failed to deserialize notification");
+        assert!(
+            result.annotations.is_empty(),
+            "Expected deserialized notification to have no annotations"
+        );
+    }
+
+    #[test]
+    fn can_round_trip_metadata() {
+        let mut data = HashMap::new();
+        data.insert("width".to_string(), "1920".to_string());
+        data.insert("height".to_string(), "1080".to_string());
+
+        assert_round_trip(MediaNotification {
+            stream_id: stream_id(),
+            content: MediaNotificationContent::Metadata { data },
+            annotations: Default::default(),
+        });
+    }
+
+    #[test]
+    fn can_round_trip_media_payload_with_metadata() {
+        let mut key_map = MetadataKeyMap::new();
+        let keyframe_key = key_map.register("keyframe", MetadataValueType::Bool);
+        let offset_key = key_map.register("offset", MetadataValueType::I32);
+
+        let mut buffer = BytesMut::new();
+        let entries = vec![
+            MetadataEntry::new(keyframe_key, MetadataValue::Bool(true), &mut buffer).unwrap(),
+            MetadataEntry::new(offset_key, MetadataValue::I32(-25), &mut buffer).unwrap(),
+        ];
+
+        let metadata = MediaPayloadMetadataCollection::new(entries.into_iter(), &mut buffer);
+
+        assert_round_trip(MediaNotification {
+            stream_id: stream_id(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type: VIDEO_CODEC_H264_AVC.clone(),
+                timestamp: Duration::from_millis(123456),
+                metadata,
+                data: Bytes::from_static(&[1, 2, 3, 4, 5]),
+                is_required_for_decoding: true,
+            },
+            annotations: Default::default(),
+        });
+    }
+
+    #[test]
+    fn can_round_trip_media_payload_with_custom_codec() {
+        assert_round_trip(MediaNotification {
+            stream_id: stream_id(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Other,
+                payload_type: Arc::new("custom".to_string()),
+                timestamp: Duration::from_micros(u32::MAX as u64 * 5),
+                metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+                data: Bytes::new(),
+                is_required_for_decoding: false,
+            },
+            annotations: Default::default(),
+        });
+    }
+
+    #[test]
+    fn can_round_trip_h265_media_payload() {
+        let notification = MediaNotification {
+            stream_id: stream_id(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type: VIDEO_CODEC_H265_HVCC.clone(),
+                timestamp: Duration::from_millis(33),
+                metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+                data: Bytes::from_static(&[0x40, 0x01, 0x0c]),
+                is_required_for_decoding: true,
+            },
+            annotations: Default::default(),
+        };
+
+        // Well known codecs should not have their name serialized
+        assert!(
+            to_bytes(&notification).remaining() < 20,
+            "Expected codec to be serialized as a single byte"
+        );
+
+        assert_round_trip(notification);
+    }
+
+    #[test]
+    fn can_round_trip_av1_media_payload() {
+        let notification = MediaNotification {
+            stream_id: stream_id(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type: VIDEO_CODEC_AV1.clone(),
+                timestamp: Duration::from_millis(33),
+                metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+                data: Bytes::from_static(&[0x81, 0x08, 0x0c, 0x00]),
+                is_required_for_decoding: true,
+            },
+            annotations: Default::default(),
+        };
+
+        assert!(
+            to_bytes(&notification).remaining() < 20,
+            "Expected codec to be serialized as a single byte"
+        );
+
+        assert_round_trip(notification);
+    }
+
+    #[test]
+    fn can_round_trip_audio_media_payload() {
+        assert_round_trip(MediaNotification {
+            stream_id: stream_id(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Audio,
+                payload_type: AUDIO_CODEC_AAC_RAW.clone(),
+                timestamp: Duration::from_millis(0),
+                metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+                data: Bytes::from_static(&[9, 8, 7]),
+                is_required_for_decoding: false,
+            },
+            annotations: Default::default(),
+        });
+    }
+
+    #[test]
+    fn payload_is_not_copied() {
+        let data = Bytes::from(vec![1, 2, 3, 4]);
+        let serialized = to_bytes(&MediaNotification {
+            stream_id: stream_id(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type: VIDEO_CODEC_H264_AVC.clone(),
+                timestamp: Duration::from_millis(0),
+                metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+                data: data.clone(),
+                is_required_for_decoding: false,
+            },
+            annotations: Default::default(),
+        });
+
+        assert_eq!(
+            serialized.last_ref().as_ptr(),
+            data.as_ptr(),
+            "Expected the serialized payload to share the original payload's memory"
+        );
+    }
+
+    #[test]
+    fn unsupported_version_returns_error() {
+        let bytes = serialize(&MediaNotification {
+            stream_id: stream_id(),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        });
+
+        let mut bytes = BytesMut::from(&bytes[..]);
+        bytes[0] = SERIALIZATION_VERSION + 1;
+
+        let result = from_bytes(bytes.freeze());
+        assert_eq!(
+            result,
+            Err(DeserializationError::UnsupportedVersion(
+                SERIALIZATION_VERSION + 1
+            ))
+        );
+    }
+
+    #[test]
+    fn truncated_notification_returns_error() {
+        let bytes = serialize(&MediaNotification {
+            stream_id: stream_id(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        });
+
+        let result = from_bytes(bytes.slice(0..bytes.len() - 1));
+        assert_eq!(result, Err(DeserializationError::UnexpectedEnd));
+    }
+}
@@ -0,0 +1,214 @@
+//! The admission control step limits how quickly new streams are allowed into the rest of the
+//! workflow, protecting later (and usually more expensive) steps from being overwhelmed when a
+//! large number of streams connect at once.
+//!
+//! Admission is governed by a token bucket. Each new stream consumes one token, tokens are
+//! refilled at the configured rate per second, and up to `burst` tokens can accumulate. When no
+//! token is available the new stream is rejected, and all media for it is dropped until it
+//! disconnects. Media for admitted streams is passed through untouched, and an admitted stream
+//! that re-announces itself stays admitted without consuming another token.
+
+#[cfg(test)]
+mod tests;
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::MediaNotificationContent;
+use crate::StreamId;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+use thiserror::Error;
+use tracing::{info, warn};
+
+pub const RATE: &str = "rate";
+pub const BURST: &str = "burst";
+
+const ADMITTED_COUNT_DETAIL: &str = "admitted_count";
+const REJECTED_COUNT_DETAIL: &str = "rejected_count";
+
+/// Generates new instances of the admission control workflow step
+#[derive(Default)]
+pub struct AdmissionControlStepGenerator {}
+
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_second: f64,
+    last_refill: Instant,
+}
+
+struct AdmissionControlStep {
+    bucket: TokenBucket,
+    admitted_streams: HashSet<StreamId>,
+    rejected_streams: HashSet<StreamId>,
+    admitted_count: u64,
+    rejected_count: u64,
+}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error("No {} parameter specified", RATE)]
+    NoRateSpecified,
+
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a number greater than zero",
+        RATE
+    )]
+    InvalidRate(String),
+
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a number of at least 1",
+        BURST
+    )]
+    InvalidBurst(String),
+}
+
+impl AdmissionControlStepGenerator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl StepGenerator for AdmissionControlStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let rate = match definition.parameters.get(RATE) {
+            Some(Some(value)) => match value.parse::<f64>() {
+                Ok(rate) if rate.is_finite() && rate > 0.0 => rate,
+                _ => return Err(Box::new(StepStartupError::InvalidRate(value.clone()))),
+            },
+
+            _ => return Err(Box::new(StepStartupError::NoRateSpecified)),
+        };
+
+        let burst = match definition.parameters.get(BURST) {
+            Some(Some(value)) => match value.parse::<u32>() {
+                Ok(burst) if burst >= 1 => burst as f64,
+                _ => return Err(Box::new(StepStartupError::InvalidBurst(value.clone()))),
+            },
+
+            _ => rate.ceil(),
+        };
+
+        let step = AdmissionControlStep {
+            bucket: TokenBucket::new(rate, burst),
+            admitted_streams: HashSet::new(),
+            rejected_streams: HashSet::new(),
+            admitted_count: 0,
+            rejected_count: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl TokenBucket {
+    fn new(refill_per_second: f64, capacity: f64) -> Self {
+        TokenBucket {
+            capacity,
+            tokens: capacity,
+            refill_per_second,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl WorkflowStep for AdmissionControlStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            match &media.content {
+                MediaNotificationContent::NewIncomingStream { stream_name } => {
+                    if self.admitted_streams.contains(&media.stream_id) {
+                        // Already admitted, so re-announcing doesn't cost another token
+                        outputs.media.push(media);
+                        continue;
+                    }
+
+                    // A re-announced stream gets re-evaluated, even if it was previously rejected
+                    self.rejected_streams.remove(&media.stream_id);
+
+                    if self.bucket.try_take() {
+                        info!(
+                            stream_id = %media.stream_id.0,
+                            stream_name = %stream_name,
+                            "Admitted new stream '{}'", stream_name
+                        );
+
+                        self.admitted_count += 1;
+                        self.admitted_streams.insert(media.stream_id.clone());
+                    } else {
+                        warn!(
+                            stream_id = %media.stream_id.0,
+                            stream_name = %stream_name,
+                            "Rejected new stream '{}': new stream rate limit exceeded", stream_name
+                        );
+
+                        self.rejected_count += 1;
+                        self.rejected_streams.insert(media.stream_id.clone());
+                        continue;
+                    }
+                }
+
+                MediaNotificationContent::StreamDisconnected => {
+                    self.admitted_streams.remove(&media.stream_id);
+                    if self.rejected_streams.remove(&media.stream_id) {
+                        continue;
+                    }
+                }
+
+                _ => {
+                    if self.rejected_streams.contains(&media.stream_id) {
+                        continue;
+                    }
+                }
+            }
+
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            ADMITTED_COUNT_DETAIL.to_string(),
+            self.admitted_count.to_string(),
+        );
+
+        details.insert(
+            REJECTED_COUNT_DETAIL.to_string(),
+            self.rejected_count.to_string(),
+        );
+
+        details
+    }
+}
@@ -0,0 +1,155 @@
+use super::*;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::steps::test_utils::StepTestContext;
+use crate::workflows::MediaNotification;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn create_context(rate: &str, burst: Option<&str>) -> StepTestContext {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("admission_control".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    definition
+        .parameters
+        .insert(RATE.to_string(), Some(rate.to_string()));
+
+    if let Some(burst) = burst {
+        definition
+            .parameters
+            .insert(BURST.to_string(), Some(burst.to_string()));
+    }
+
+    StepTestContext::new(Box::new(AdmissionControlStepGenerator::new()), definition)
+        .expect("Failed to create step")
+}
+
+fn new_stream(id: &str) -> MediaNotification {
+    MediaNotification {
+        stream_id: StreamId(Arc::new(id.to_string())),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new(id.to_string()),
+        },
+        annotations: Default::default(),
+    }
+}
+
+fn metadata(id: &str) -> MediaNotification {
+    MediaNotification {
+        stream_id: StreamId(Arc::new(id.to_string())),
+        content: MediaNotificationContent::Metadata {
+            data: HashMap::new(),
+        },
+        annotations: Default::default(),
+    }
+}
+
+fn disconnected(id: &str) -> MediaNotification {
+    MediaNotification {
+        stream_id: StreamId(Arc::new(id.to_string())),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    }
+}
+
+#[test]
+fn missing_rate_returns_error() {
+    let definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("admission_control".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    let result = StepTestContext::new(Box::new(AdmissionControlStepGenerator::new()), definition);
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn invalid_burst_returns_error() {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("admission_control".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    definition
+        .parameters
+        .insert(RATE.to_string(), Some("1".to_string()));
+
+    definition
+        .parameters
+        .insert(BURST.to_string(), Some("0".to_string()));
+
+    let result = StepTestContext::new(Box::new(AdmissionControlStepGenerator::new()), definition);
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn streams_beyond_burst_are_rejected() {
+    let mut context = create_context("1", Some("2"));
+
+    context.assert_media_passed_through(new_stream("a"));
+    context.assert_media_passed_through(new_stream("b"));
+    context.assert_media_not_passed_through(new_stream("c"));
+    context.assert_media_not_passed_through(new_stream("d"));
+
+    let details = context.step.get_state_details();
+    assert_eq!(
+        details.get(ADMITTED_COUNT_DETAIL),
+        Some(&"2".to_string()),
+        "Unexpected admitted count"
+    );
+
+    assert_eq!(
+        details.get(REJECTED_COUNT_DETAIL),
+        Some(&"2".to_string()),
+        "Unexpected rejected count"
+    );
+}
+
+#[test]
+fn media_for_rejected_streams_is_dropped() {
+    let mut context = create_context("1", Some("1"));
+
+    context.assert_media_passed_through(new_stream("a"));
+    context.assert_media_not_passed_through(new_stream("b"));
+
+    context.assert_media_passed_through(metadata("a"));
+    context.assert_media_not_passed_through(metadata("b"));
+    context.assert_media_not_passed_through(disconnected("b"));
+    context.assert_media_passed_through(disconnected("a"));
+}
+
+#[tokio::test]
+async fn streams_are_admitted_again_after_tokens_refill() {
+    let mut context = create_context("20", Some("1"));
+
+    context.assert_media_passed_through(new_stream("a"));
+    context.assert_media_not_passed_through(new_stream("b"));
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    context.assert_media_passed_through(new_stream("c"));
+}
+
+#[test]
+fn admitted_stream_reannouncing_does_not_consume_a_token() {
+    let mut context = create_context("1", Some("1"));
+
+    context.assert_media_passed_through(new_stream("a"));
+    context.assert_media_passed_through(new_stream("a"));
+    context.assert_media_passed_through(metadata("a"));
+    context.assert_media_passed_through(disconnected("a"));
+
+    let details = context.step.get_state_details();
+    assert_eq!(
+        details.get(ADMITTED_COUNT_DETAIL),
+        Some(&"1".to_string()),
+        "Unexpected admitted count"
+    );
+
+    assert_eq!(
+        details.get(REJECTED_COUNT_DETAIL),
+        Some(&"0".to_string()),
+        "Unexpected rejected count"
+    );
+}
@@ -0,0 +1,65 @@
+//! Steps that hold onto media (e.g. to delay, reorder, or gate it) report how much they are
+//! holding with `BufferStats`, which is surfaced as part of the step's state. This gives operators
+//! one consistent view of buffer health across all buffering steps, so growing buffers can be
+//! caught before they become memory issues.
+
+use crate::workflows::{MediaNotification, MediaNotificationContent};
+use std::time::Duration;
+
+/// The configured limit of a step's buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferLimit {
+    Bytes(u64),
+    Duration(Duration),
+    Packets(usize),
+}
+
+/// Details about the media a step is currently holding onto
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BufferStats {
+    /// The number of payload bytes currently held
+    pub current_bytes: u64,
+
+    /// The number of media payloads currently held
+    pub current_packets: usize,
+
+    /// The longest span of media timestamps currently held for a single stream
+    pub current_duration: Duration,
+
+    /// The limit the buffer was configured with, if it has one
+    pub max_configured: Option<BufferLimit>,
+
+    /// The number of media payloads dropped because the buffer was full
+    pub drop_count: u64,
+}
+
+impl BufferStats {
+    /// Creates stats for an empty buffer with the specified limit
+    pub fn new(max_configured: Option<BufferLimit>) -> Self {
+        BufferStats {
+            max_configured,
+            ..Default::default()
+        }
+    }
+
+    /// Adds the media payloads held for a single stream to the stats
+    pub fn add_stream_media<'a>(&mut self, media: impl IntoIterator<Item = &'a MediaNotification>) {
+        let mut earliest = None;
+        let mut latest = None;
+        for media in media {
+            if let MediaNotificationContent::MediaPayload {
+                timestamp, data, ..
+            } = &media.content
+            {
+                self.current_bytes += data.len() as u64;
+                self.current_packets += 1;
+                earliest = Some(earliest.map_or(*timestamp, |x: Duration| x.min(*timestamp)));
+                latest = Some(latest.map_or(*timestamp, |x: Duration| x.max(*timestamp)));
+            }
+        }
+
+        if let (Some(earliest), Some(latest)) = (earliest, latest) {
+            self.current_duration = self.current_duration.max(latest - earliest);
+        }
+    }
+}
@@ -0,0 +1,359 @@
+//! The caption detect step checks the H264 video of every stream flowing through it for closed
+//! captions (CEA-608/708 carried in ATSC A/53 `cc_data` SEI messages), so operators can verify
+//! incoming streams meet accessibility requirements. All media is passed through untouched.
+//!
+//! To limit the cost of parsing every frame, only one out of every `sample_every` video frames
+//! (default of 5) is inspected. A sampled frame counts as a caption when it carries caption data
+//! that isn't CEA-608 padding. Captions are considered present from the first caption found, and
+//! absent again once no sampled frame has carried a caption for `absence_duration` seconds of the
+//! stream's timeline (default of 10).
+//!
+//! Whether each stream has captions, how many captions have been found, and the cadence captions
+//! are arriving at (estimated from the sampled frames) are reported through the step's state
+//! details. A caption presence event is published to the event hub whenever captions are first
+//! found in a stream, and when they go absent. Video that isn't H264 is never inspected.
+
+#[cfg(test)]
+mod tests;
+
+use crate::captions::{parse_cc_data_sei_payload, SEI_PAYLOAD_TYPE_USER_DATA_REGISTERED};
+use crate::codecs::h264::{self, AvcDecoderConfigurationRecord};
+use crate::codecs::nal::{split_annexb, split_length_prefixed, DEFAULT_NAL_LENGTH_SIZE};
+use crate::codecs::{VIDEO_CODEC_H264_ANNEXB, VIDEO_CODEC_H264_AVC};
+use crate::event_hub::{CaptionPresenceEvent, PublishEventRequest};
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use crate::StreamId;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{info, warn};
+
+pub const SAMPLE_EVERY: &str = "sample_every";
+pub const ABSENCE_DURATION: &str = "absence_duration";
+
+const DEFAULT_SAMPLE_EVERY: u64 = 5;
+const DEFAULT_ABSENCE_DURATION: Duration = Duration::from_secs(10);
+
+const CAPTIONS_PRESENT_DETAIL: &str = "captions_present";
+const CAPTION_COUNT_DETAIL: &str = "caption_count";
+const CAPTION_CADENCE_DETAIL: &str = "caption_cadence";
+
+/// Generates new instances of the caption detect workflow step
+pub struct CaptionDetectStepGenerator {
+    event_publisher: UnboundedSender<PublishEventRequest>,
+}
+
+struct StreamState {
+    stream_name: Arc<String>,
+    nal_length_size: u8,
+    frames_seen: u64,
+    caption_count: u64,
+    presence: Option<CaptionPresence>,
+}
+
+/// The span of a stream's timeline that captions have been continuously present for
+struct CaptionPresence {
+    first_caption_at: Duration,
+    last_caption_at: Duration,
+    caption_count: u64,
+}
+
+struct CaptionDetectStep {
+    event_publisher: UnboundedSender<PublishEventRequest>,
+    sample_every: u64,
+    absence_duration: Duration,
+    streams: HashMap<StreamId, StreamState>,
+}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a number greater than zero",
+        SAMPLE_EVERY
+    )]
+    InvalidSampleEvery(String),
+
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a number of seconds greater than zero",
+        ABSENCE_DURATION
+    )]
+    InvalidAbsenceDuration(String),
+}
+
+impl CaptionDetectStepGenerator {
+    pub fn new(event_publisher: UnboundedSender<PublishEventRequest>) -> Self {
+        CaptionDetectStepGenerator { event_publisher }
+    }
+}
+
+impl StepGenerator for CaptionDetectStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let sample_every = match definition.parameters.get(SAMPLE_EVERY) {
+            Some(Some(value)) => match value.trim().parse::<u64>() {
+                Ok(count) if count > 0 => count,
+                _ => {
+                    return Err(Box::new(StepStartupError::InvalidSampleEvery(
+                        value.clone(),
+                    )))
+                }
+            },
+
+            _ => DEFAULT_SAMPLE_EVERY,
+        };
+
+        let absence_duration = match definition.parameters.get(ABSENCE_DURATION) {
+            Some(Some(value)) => match value.trim().parse::<f64>() {
+                Ok(seconds) if seconds.is_finite() && seconds > 0.0 => {
+                    Duration::from_secs_f64(seconds)
+                }
+
+                _ => {
+                    return Err(Box::new(StepStartupError::InvalidAbsenceDuration(
+                        value.clone(),
+                    )))
+                }
+            },
+
+            _ => DEFAULT_ABSENCE_DURATION,
+        };
+
+        let step = CaptionDetectStep {
+            event_publisher: self.event_publisher.clone(),
+            sample_every,
+            absence_duration,
+            streams: HashMap::new(),
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl CaptionDetectStep {
+    fn handle_media(&mut self, media: &MediaNotification) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                self.streams.insert(
+                    media.stream_id.clone(),
+                    StreamState {
+                        stream_name: stream_name.clone(),
+                        nal_length_size: DEFAULT_NAL_LENGTH_SIZE,
+                        frames_seen: 0,
+                        caption_count: 0,
+                        presence: None,
+                    },
+                );
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.streams.remove(&media.stream_id);
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type,
+                timestamp,
+                data,
+                is_required_for_decoding,
+                ..
+            } => {
+                let stream = match self.streams.get_mut(&media.stream_id) {
+                    Some(stream) => stream,
+                    None => return,
+                };
+
+                let is_avc = *payload_type == *VIDEO_CODEC_H264_AVC;
+                if !is_avc && *payload_type != *VIDEO_CODEC_H264_ANNEXB {
+                    return;
+                }
+
+                if *is_required_for_decoding {
+                    if is_avc {
+                        match AvcDecoderConfigurationRecord::parse(data) {
+                            Ok(record) => stream.nal_length_size = record.nal_length_size,
+                            Err(error) => warn!(
+                                stream_id = %media.stream_id.0,
+                                "Failed to parse AVC sequence header: {:?}", error
+                            ),
+                        }
+                    }
+
+                    return;
+                }
+
+                let absence_duration = self.absence_duration;
+                let is_sampled = stream.frames_seen % self.sample_every == 0;
+                stream.frames_seen += 1;
+
+                let has_caption = is_sampled
+                    && contains_caption(&media.stream_id, data, is_avc, stream.nal_length_size);
+
+                let presence_changed = if has_caption {
+                    stream.caption_count += 1;
+                    match &mut stream.presence {
+                        Some(presence) => {
+                            presence.last_caption_at = *timestamp;
+                            presence.caption_count += 1;
+                            false
+                        }
+
+                        None => {
+                            info!(
+                                stream_id = %media.stream_id.0,
+                                "Closed captions found in stream {}", stream.stream_name
+                            );
+
+                            stream.presence = Some(CaptionPresence {
+                                first_caption_at: *timestamp,
+                                last_caption_at: *timestamp,
+                                caption_count: 1,
+                            });
+
+                            true
+                        }
+                    }
+                } else {
+                    let is_absent = stream.presence.as_ref().is_some_and(|presence| {
+                        timestamp.saturating_sub(presence.last_caption_at) >= absence_duration
+                    });
+
+                    if is_absent {
+                        info!(
+                            stream_id = %media.stream_id.0,
+                            "Closed captions are no longer present in stream {}", stream.stream_name
+                        );
+
+                        stream.presence = None;
+                    }
+
+                    is_absent
+                };
+
+                if presence_changed {
+                    let _ = self
+                        .event_publisher
+                        .send(PublishEventRequest::CaptionPresence(CaptionPresenceEvent {
+                            stream_id: media.stream_id.clone(),
+                            stream_name: stream.stream_name.clone(),
+                            captions_present: stream.presence.is_some(),
+                            caption_count: stream.caption_count,
+                        }));
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    /// Formats a detail containing a value for each stream, ordered by stream name
+    fn per_stream_detail(&self, value: impl Fn(&StreamState) -> String) -> String {
+        let mut entries = self
+            .streams
+            .values()
+            .map(|stream| format!("{}: {}", stream.stream_name, value(stream)))
+            .collect::<Vec<_>>();
+
+        entries.sort();
+        entries.join("; ")
+    }
+}
+
+impl CaptionPresence {
+    /// Estimates how many captions per second are arriving, accounting for the frames that
+    /// weren't sampled. `None` is returned until the span is long enough to measure.
+    fn cadence(&self, sample_every: u64) -> Option<f64> {
+        let span = self.last_caption_at.saturating_sub(self.first_caption_at);
+        if span.is_zero() {
+            return None;
+        }
+
+        // The first caption starts the span, so it isn't part of the rate
+        let captions = (self.caption_count - 1) * sample_every;
+        Some(captions as f64 / span.as_secs_f64())
+    }
+}
+
+/// Returns true if the video frame carries caption data that isn't padding
+fn contains_caption(stream_id: &StreamId, data: &Bytes, is_avc: bool, nal_length_size: u8) -> bool {
+    let nal_units = if is_avc {
+        match split_length_prefixed(data, nal_length_size) {
+            Ok(nal_units) => nal_units,
+            Err(error) => {
+                warn!(
+                    stream_id = %stream_id.0,
+                    "Failed to split video frame into NAL units: {:?}", error
+                );
+
+                return false;
+            }
+        }
+    } else {
+        split_annexb(data)
+    };
+
+    nal_units
+        .iter()
+        .filter(|nal_unit| h264::nal_unit_type(nal_unit) == Some(h264::NAL_UNIT_TYPE_SEI))
+        .flat_map(|nal_unit| h264::parse_sei_nal_unit(nal_unit))
+        .filter(|message| message.payload_type == SEI_PAYLOAD_TYPE_USER_DATA_REGISTERED)
+        .filter_map(|message| parse_cc_data_sei_payload(&message.payload))
+        .flatten()
+        .any(|cc_data| !cc_data.is_padding())
+}
+
+impl WorkflowStep for CaptionDetectStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            self.handle_media(&media);
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            CAPTIONS_PRESENT_DETAIL.to_string(),
+            self.per_stream_detail(|stream| stream.presence.is_some().to_string()),
+        );
+
+        details.insert(
+            CAPTION_COUNT_DETAIL.to_string(),
+            self.per_stream_detail(|stream| stream.caption_count.to_string()),
+        );
+
+        details.insert(
+            CAPTION_CADENCE_DETAIL.to_string(),
+            self.per_stream_detail(|stream| {
+                match stream
+                    .presence
+                    .as_ref()
+                    .and_then(|presence| presence.cadence(self.sample_every))
+                {
+                    Some(cadence) => format!("{:.1}/s", cadence),
+                    None => "unknown".to_string(),
+                }
+            }),
+        );
+
+        details
+    }
+}
@@ -0,0 +1,206 @@
+use super::*;
+use crate::captions::create_cc_data_sei_payload;
+use crate::codecs::h264::SeiMessage;
+use crate::codecs::nal::write_length_prefixed;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::MediaPayloadMetadataCollection;
+use crate::workflows::steps::test_utils::StepTestContext;
+use bytes::BytesMut;
+use std::iter;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+const RESUME_CAPTION_LOADING: [u8; 2] = [0x94, 0x20];
+const PADDING: [u8; 2] = [0x80, 0x80];
+
+struct TestContext {
+    step_context: StepTestContext,
+    events: UnboundedReceiver<PublishEventRequest>,
+}
+
+impl TestContext {
+    fn new(parameters: &[(&str, &str)]) -> Self {
+        let (sender, events) = unbounded_channel();
+        let definition = WorkflowStepDefinition {
+            step_type: WorkflowStepType("caption_detect".to_string()),
+            parameters: parameters
+                .iter()
+                .map(|(key, value)| (key.to_string(), Some(value.to_string())))
+                .collect(),
+        };
+
+        let generator = CaptionDetectStepGenerator::new(sender);
+        let mut step_context =
+            StepTestContext::new(Box::new(generator), definition).expect("Failed to create step");
+
+        step_context.execute_with_media(MediaNotification {
+            stream_id: stream_id(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        });
+
+        TestContext {
+            step_context,
+            events,
+        }
+    }
+
+    /// Sends a video frame for each entry, with entries being the caption byte pair attached to
+    /// the frame (if any)
+    fn send_frames(&mut self, frames: &[Option<[u8; 2]>]) {
+        for (index, pair) in frames.iter().enumerate() {
+            let timestamp = FRAME_INTERVAL * index as u32;
+            self.step_context
+                .execute_with_media(video_frame(timestamp, *pair));
+        }
+    }
+
+    fn events(&mut self) -> Vec<CaptionPresenceEvent> {
+        let mut events = Vec::new();
+        while let Ok(request) = self.events.try_recv() {
+            match request {
+                PublishEventRequest::CaptionPresence(event) => events.push(event),
+                request => panic!("Unexpected publish request: {:?}", request),
+            }
+        }
+
+        events
+    }
+
+    fn detail(&self, name: &str) -> String {
+        self.step_context.step.get_state_details()[name].clone()
+    }
+}
+
+fn stream_id() -> StreamId {
+    StreamId(Arc::new("abc".to_string()))
+}
+
+fn video_frame(timestamp: Duration, caption: Option<[u8; 2]>) -> MediaNotification {
+    let mut nal_units = Vec::new();
+    if let Some(pair) = caption {
+        nal_units.push(h264::create_sei_nal_unit(&[SeiMessage {
+            payload_type: SEI_PAYLOAD_TYPE_USER_DATA_REGISTERED,
+            payload: create_cc_data_sei_payload(&[pair]),
+        }]));
+    }
+
+    nal_units.push(Bytes::from_static(&[0x65, 0x88, 0x84]));
+
+    MediaNotification {
+        stream_id: stream_id(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Video,
+            payload_type: VIDEO_CODEC_H264_AVC.clone(),
+            timestamp,
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data: write_length_prefixed(&nal_units, DEFAULT_NAL_LENGTH_SIZE)
+                .expect("Failed to write frame"),
+            is_required_for_decoding: false,
+        },
+        annotations: Default::default(),
+    }
+}
+
+#[test]
+fn invalid_sample_every_returns_error() {
+    let (sender, _events) = unbounded_channel();
+    let definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("caption_detect".to_string()),
+        parameters: vec![(SAMPLE_EVERY.to_string(), Some("0".to_string()))]
+            .into_iter()
+            .collect(),
+    };
+
+    let result = StepTestContext::new(
+        Box::new(CaptionDetectStepGenerator::new(sender)),
+        definition,
+    );
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn video_passed_through() {
+    let mut context = TestContext::new(&[]);
+
+    context
+        .step_context
+        .assert_media_passed_through(video_frame(Duration::ZERO, Some(RESUME_CAPTION_LOADING)));
+}
+
+#[test]
+fn injected_captions_detected() {
+    let mut context = TestContext::new(&[(SAMPLE_EVERY, "1")]);
+    context.send_frames(&[
+        None,
+        Some(RESUME_CAPTION_LOADING),
+        Some(RESUME_CAPTION_LOADING),
+    ]);
+
+    assert_eq!(context.detail(CAPTIONS_PRESENT_DETAIL), "def: true");
+    assert_eq!(context.detail(CAPTION_COUNT_DETAIL), "def: 2");
+    assert_eq!(context.detail(CAPTION_CADENCE_DETAIL), "def: 10.0/s");
+
+    let events = context.events();
+    assert_eq!(events.len(), 1, "Unexpected number of events");
+    assert_eq!(
+        events[0],
+        CaptionPresenceEvent {
+            stream_id: stream_id(),
+            stream_name: Arc::new("def".to_string()),
+            captions_present: true,
+            caption_count: 1,
+        },
+        "Unexpected event"
+    );
+}
+
+#[test]
+fn padding_is_not_a_caption() {
+    let mut context = TestContext::new(&[(SAMPLE_EVERY, "1")]);
+    context.send_frames(&[None, Some(PADDING), Some(PADDING)]);
+
+    assert_eq!(context.detail(CAPTIONS_PRESENT_DETAIL), "def: false");
+    assert_eq!(context.detail(CAPTION_COUNT_DETAIL), "def: 0");
+    assert!(context.events().is_empty(), "Expected no events");
+}
+
+#[test]
+fn only_sampled_frames_inspected() {
+    let mut context = TestContext::new(&[(SAMPLE_EVERY, "2")]);
+    context.send_frames(&[
+        None,
+        Some(RESUME_CAPTION_LOADING),
+        None,
+        Some(RESUME_CAPTION_LOADING),
+    ]);
+
+    assert_eq!(context.detail(CAPTIONS_PRESENT_DETAIL), "def: false");
+
+    context.send_frames(&[Some(RESUME_CAPTION_LOADING)]);
+    assert_eq!(context.detail(CAPTIONS_PRESENT_DETAIL), "def: true");
+}
+
+#[test]
+fn captions_absent_after_absence_duration() {
+    let mut context = TestContext::new(&[(SAMPLE_EVERY, "1"), (ABSENCE_DURATION, "0.5")]);
+    context.send_frames(&[Some(RESUME_CAPTION_LOADING), None, None, None, None]);
+
+    assert_eq!(context.detail(CAPTIONS_PRESENT_DETAIL), "def: true");
+
+    context
+        .step_context
+        .execute_with_media(video_frame(Duration::from_millis(500), None));
+
+    assert_eq!(context.detail(CAPTIONS_PRESENT_DETAIL), "def: false");
+
+    let events = context.events();
+    assert_eq!(events.len(), 2, "Unexpected number of events");
+    assert!(
+        !events[1].captions_present,
+        "Expected captions to be absent"
+    );
+}
@@ -0,0 +1,411 @@
+//! The caption inject step embeds closed captions from an external source into the H264 video of
+//! every stream flowing through it, without re-encoding the video. Captions are encoded as
+//! CEA-608 and carried in ATSC A/53 `cc_data` within SEI NAL units, which decoders and players
+//! read as CEA-608/708 captions. Since the captions are part of the video's bitstream, outputs
+//! that copy the video (such as HLS) carry them through.
+//!
+//! Captions are sourced from either a WebVTT file (the `webvtt_file` parameter) or a named caption
+//! feed that captions are published to through the event hub (the `feed` parameter). Cue times
+//! are on the stream's own timeline, and the optional `offset` parameter (in milliseconds, and
+//! which may be negative) is added to every cue to align them with the video.
+//!
+//! A caption is sent with the first video frame at or after its start time, with one CEA-608
+//! byte pair attached to each video frame until the whole caption has been sent, and is erased
+//! with the first frame at or after its end time. Video that isn't H264 is passed through
+//! untouched.
+
+#[cfg(test)]
+mod tests;
+
+use crate::captions::cea608::{encode_erase_displayed_caption, encode_pop_on_caption};
+use crate::captions::webvtt;
+use crate::captions::{
+    create_cc_data_sei_payload, CaptionCue, SEI_PAYLOAD_TYPE_USER_DATA_REGISTERED,
+};
+use crate::codecs::h264::{self, AvcDecoderConfigurationRecord, SeiMessage};
+use crate::codecs::nal::{
+    split_annexb, split_length_prefixed, write_annexb, write_length_prefixed, NalFramingError,
+    DEFAULT_NAL_LENGTH_SIZE,
+};
+use crate::codecs::{VIDEO_CODEC_H264_ANNEXB, VIDEO_CODEC_H264_AVC};
+use crate::event_hub::{CaptionEvent, SubscriptionRequest};
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use crate::StreamId;
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tracing::{error, info, warn};
+
+pub const WEBVTT_FILE: &str = "webvtt_file";
+pub const FEED: &str = "feed";
+pub const OFFSET: &str = "offset";
+
+const CUE_COUNT_DETAIL: &str = "cue_count";
+const INJECTED_CAPTION_COUNT_DETAIL: &str = "injected_caption_count";
+
+/// Generates new instances of the caption inject workflow step
+pub struct CaptionInjectStepGenerator {
+    event_hub_subscriber: UnboundedSender<SubscriptionRequest>,
+}
+
+struct StreamState {
+    nal_length_size: u8,
+    last_frame_micros: Option<i128>,
+    displayed_until_micros: Option<i128>,
+    pending_pairs: VecDeque<[u8; 2]>,
+}
+
+struct CaptionInjectStep {
+    feed: Option<Arc<String>>,
+    offset_micros: i128,
+    cues: Vec<CaptionCue>,
+    streams: HashMap<StreamId, StreamState>,
+    injected_caption_count: u64,
+    status: StepStatus,
+}
+
+enum FutureResult {
+    EventHubGone,
+    WebVttFileRead(Result<String, std::io::Error>),
+    CaptionReceived(CaptionEvent),
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error("A {} or {} value must be specified", WEBVTT_FILE, FEED)]
+    NoCaptionSource,
+
+    #[error("A WebVTT file and feed were specified. Only one can be used at a time")]
+    WebVttFileAndFeedBothSpecified,
+
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a number of milliseconds",
+        OFFSET
+    )]
+    InvalidOffset(String),
+}
+
+impl CaptionInjectStepGenerator {
+    pub fn new(event_hub_subscriber: UnboundedSender<SubscriptionRequest>) -> Self {
+        CaptionInjectStepGenerator {
+            event_hub_subscriber,
+        }
+    }
+}
+
+impl StepGenerator for CaptionInjectStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let webvtt_file = match definition.parameters.get(WEBVTT_FILE) {
+            Some(Some(path)) => Some(path.clone()),
+            _ => None,
+        };
+
+        let feed = match definition.parameters.get(FEED) {
+            Some(Some(feed)) => Some(Arc::new(feed.clone())),
+            _ => None,
+        };
+
+        let offset_millis = match definition.parameters.get(OFFSET) {
+            Some(Some(value)) => match value.trim().parse::<i64>() {
+                Ok(millis) => millis,
+                Err(_) => return Err(Box::new(StepStartupError::InvalidOffset(value.clone()))),
+            },
+
+            _ => 0,
+        };
+
+        match (webvtt_file, &feed) {
+            (None, None) => return Err(Box::new(StepStartupError::NoCaptionSource)),
+            (Some(_), Some(_)) => {
+                return Err(Box::new(StepStartupError::WebVttFileAndFeedBothSpecified))
+            }
+
+            (Some(path), None) => {
+                futures_channel.send_on_generic_future_completion(async move {
+                    FutureResult::WebVttFileRead(tokio::fs::read_to_string(path).await)
+                });
+            }
+
+            (None, Some(_)) => {
+                let (sender, receiver) = unbounded_channel();
+                let _ = self
+                    .event_hub_subscriber
+                    .send(SubscriptionRequest::Captions { channel: sender });
+
+                futures_channel.send_on_generic_unbounded_recv(
+                    receiver,
+                    FutureResult::CaptionReceived,
+                    || FutureResult::EventHubGone,
+                );
+            }
+        }
+
+        let step = CaptionInjectStep {
+            feed,
+            offset_micros: offset_millis as i128 * 1000,
+            cues: Vec::new(),
+            streams: HashMap::new(),
+            injected_caption_count: 0,
+            status: StepStatus::Active,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl CaptionInjectStep {
+    fn handle_future_result(&mut self, result: FutureResult) {
+        match result {
+            FutureResult::EventHubGone => {
+                error!("Received a notification that the event hub is gone");
+                self.status = StepStatus::Error {
+                    message: "Event hub gone".to_string(),
+                };
+            }
+
+            FutureResult::WebVttFileRead(Err(error)) => {
+                error!("Failed to read WebVTT file: {:?}", error);
+                self.status = StepStatus::Error {
+                    message: format!("Failed to read WebVTT file: {}", error),
+                };
+            }
+
+            FutureResult::WebVttFileRead(Ok(content)) => match webvtt::parse(&content) {
+                Ok(cues) => {
+                    info!("Loaded {} caption cues from WebVTT file", cues.len());
+                    self.cues = cues;
+                }
+
+                Err(error) => {
+                    error!("Failed to parse WebVTT file: {:?}", error);
+                    self.status = StepStatus::Error {
+                        message: format!("Failed to parse WebVTT file: {}", error),
+                    };
+                }
+            },
+
+            FutureResult::CaptionReceived(event) => {
+                if self.feed.as_ref() == Some(&event.feed) {
+                    let index = self
+                        .cues
+                        .partition_point(|cue| cue.start <= event.cue.start);
+
+                    self.cues.insert(index, event.cue);
+                }
+            }
+        }
+    }
+
+    fn handle_media(&mut self, mut media: MediaNotification) -> MediaNotification {
+        match &mut media.content {
+            MediaNotificationContent::NewIncomingStream { .. } => {
+                self.streams.insert(
+                    media.stream_id.clone(),
+                    StreamState {
+                        nal_length_size: DEFAULT_NAL_LENGTH_SIZE,
+                        last_frame_micros: None,
+                        displayed_until_micros: None,
+                        pending_pairs: VecDeque::new(),
+                    },
+                );
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.streams.remove(&media.stream_id);
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type,
+                timestamp,
+                data,
+                is_required_for_decoding,
+                ..
+            } => {
+                let stream = match self.streams.get_mut(&media.stream_id) {
+                    Some(stream) => stream,
+                    None => return media,
+                };
+
+                let is_avc = *payload_type == *VIDEO_CODEC_H264_AVC;
+                if !is_avc && *payload_type != *VIDEO_CODEC_H264_ANNEXB {
+                    return media;
+                }
+
+                if *is_required_for_decoding {
+                    if is_avc {
+                        match AvcDecoderConfigurationRecord::parse(data) {
+                            Ok(record) => stream.nal_length_size = record.nal_length_size,
+                            Err(error) => warn!(
+                                stream_id = %media.stream_id.0,
+                                "Failed to parse AVC sequence header: {:?}", error
+                            ),
+                        }
+                    }
+
+                    return media;
+                }
+
+                let frame_micros = timestamp.as_micros() as i128;
+                let (pair, caption_started) =
+                    stream.next_caption_pair(&self.cues, self.offset_micros, frame_micros);
+
+                if caption_started {
+                    self.injected_caption_count += 1;
+                }
+
+                if let Some(pair) = pair {
+                    match insert_caption_sei(data, is_avc, stream.nal_length_size, pair) {
+                        Ok(frame) => *data = frame,
+                        Err(error) => warn!(
+                            stream_id = %media.stream_id.0,
+                            "Failed to insert captions into video frame: {:?}", error
+                        ),
+                    }
+                }
+            }
+
+            MediaNotificationContent::MediaPayload { .. } => (),
+            MediaNotificationContent::Metadata { .. } => (),
+        }
+
+        media
+    }
+}
+
+impl StreamState {
+    /// Gets the caption byte pair to send with the video frame at the specified timestamp, and
+    /// whether a new caption started with this frame
+    fn next_caption_pair(
+        &mut self,
+        cues: &[CaptionCue],
+        offset_micros: i128,
+        frame_micros: i128,
+    ) -> (Option<[u8; 2]>, bool) {
+        let start_of = |cue: &CaptionCue| cue.start.as_micros() as i128 + offset_micros;
+        let end_of = |cue: &CaptionCue| cue.end.as_micros() as i128 + offset_micros;
+
+        if let Some(displayed_until) = self.displayed_until_micros {
+            if displayed_until <= frame_micros {
+                self.pending_pairs.extend(encode_erase_displayed_caption());
+                self.displayed_until_micros = None;
+            }
+        }
+
+        // Only cues starting since the last frame are new, and if multiple started since then
+        // only the most recent one is shown
+        let first_index = match self.last_frame_micros {
+            Some(last_frame) => cues.partition_point(|cue| start_of(cue) <= last_frame),
+            None => 0,
+        };
+
+        self.last_frame_micros = Some(frame_micros);
+        let cue = cues[first_index..]
+            .iter()
+            .take_while(|cue| start_of(cue) <= frame_micros)
+            .filter(|cue| end_of(cue) > frame_micros)
+            .last();
+
+        let caption_started = match cue {
+            Some(cue) => {
+                // A new caption replaces anything that hasn't been sent yet
+                self.pending_pairs.clear();
+                self.pending_pairs.extend(encode_pop_on_caption(&cue.text));
+                self.displayed_until_micros = Some(end_of(cue));
+
+                true
+            }
+
+            None => false,
+        };
+
+        (self.pending_pairs.pop_front(), caption_started)
+    }
+}
+
+impl WorkflowStep for CaptionInjectStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            match notification.downcast::<FutureResult>() {
+                Ok(result) => self.handle_future_result(*result),
+                Err(_) => {
+                    error!("Received future result that could not be casted to the internal future result type");
+                }
+            }
+        }
+
+        if self.status != StepStatus::Active {
+            return self.status.clone();
+        }
+
+        for media in inputs.media.drain(..) {
+            let media = self.handle_media(media);
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(CUE_COUNT_DETAIL.to_string(), self.cues.len().to_string());
+        details.insert(
+            INJECTED_CAPTION_COUNT_DETAIL.to_string(),
+            self.injected_caption_count.to_string(),
+        );
+
+        details
+    }
+}
+
+/// Adds an SEI NAL unit carrying the caption byte pair to the video frame, ahead of the frame's
+/// coded picture data
+fn insert_caption_sei(
+    data: &Bytes,
+    is_avc: bool,
+    nal_length_size: u8,
+    pair: [u8; 2],
+) -> Result<Bytes, NalFramingError> {
+    let sei = h264::create_sei_nal_unit(&[SeiMessage {
+        payload_type: SEI_PAYLOAD_TYPE_USER_DATA_REGISTERED,
+        payload: create_cc_data_sei_payload(&[pair]),
+    }]);
+
+    let mut nal_units = if is_avc {
+        split_length_prefixed(data, nal_length_size)?
+    } else {
+        split_annexb(data)
+    };
+
+    let index = nal_units
+        .iter()
+        .position(|nal_unit| h264::is_video_coding_layer(nal_unit))
+        .unwrap_or(nal_units.len());
+
+    nal_units.insert(index, sei);
+
+    if is_avc {
+        write_length_prefixed(&nal_units, nal_length_size)
+    } else {
+        Ok(write_annexb(&nal_units))
+    }
+}
@@ -0,0 +1,303 @@
+use super::*;
+use crate::captions::{cea608, parse_cc_data_sei_payload};
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::MediaPayloadMetadataCollection;
+use crate::workflows::steps::test_utils::StepTestContext;
+use bytes::BytesMut;
+use std::iter;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+const ERASE_DISPLAYED_MEMORY: [u8; 2] = [0x94, 0x2c];
+
+struct WebVttFile {
+    path: PathBuf,
+}
+
+impl WebVttFile {
+    fn new(content: &str) -> Self {
+        let file_name = format!("mmids-captions-{}.vtt", uuid::Uuid::new_v4());
+        let path = std::env::temp_dir().join(file_name);
+        std::fs::write(&path, content).expect("Failed to write WebVTT file");
+
+        WebVttFile { path }
+    }
+
+    fn path(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+}
+
+impl Drop for WebVttFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn create_definition(parameters: &[(&str, &str)]) -> WorkflowStepDefinition {
+    WorkflowStepDefinition {
+        step_type: WorkflowStepType("caption_inject".to_string()),
+        parameters: parameters
+            .iter()
+            .map(|(key, value)| (key.to_string(), Some(value.to_string())))
+            .collect(),
+    }
+}
+
+fn create_context(
+    parameters: &[(&str, &str)],
+) -> (StepTestContext, UnboundedReceiver<SubscriptionRequest>) {
+    let (sender, receiver) = unbounded_channel();
+    let generator = CaptionInjectStepGenerator::new(sender);
+    let mut context = StepTestContext::new(Box::new(generator), create_definition(parameters))
+        .expect("Failed to create step");
+
+    context.execute_with_media(MediaNotification {
+        stream_id: stream_id(),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("def".to_string()),
+        },
+        annotations: Default::default(),
+    });
+
+    (context, receiver)
+}
+
+fn stream_id() -> StreamId {
+    StreamId(Arc::new("abc".to_string()))
+}
+
+fn video_frame(timestamp: Duration) -> MediaNotification {
+    MediaNotification {
+        stream_id: stream_id(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Video,
+            payload_type: VIDEO_CODEC_H264_AVC.clone(),
+            timestamp,
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data: Bytes::from_static(&[0, 0, 0, 3, 0x65, 0x88, 0x84]),
+            is_required_for_decoding: false,
+        },
+        annotations: Default::default(),
+    }
+}
+
+/// Sends video frames from time zero up to the specified time through the step, returning the
+/// timestamp of each frame along with any caption byte pair attached to it
+fn send_frames(context: &mut StepTestContext, until: Duration) -> Vec<(Duration, Option<[u8; 2]>)> {
+    let mut results = Vec::new();
+    let mut timestamp = Duration::ZERO;
+    while timestamp <= until {
+        context.execute_with_media(video_frame(timestamp));
+        assert_eq!(
+            context.media_outputs.len(),
+            1,
+            "Unexpected number of outputs"
+        );
+
+        let data = match &context.media_outputs[0].content {
+            MediaNotificationContent::MediaPayload { data, .. } => data.clone(),
+            content => panic!("Unexpected media output: {:?}", content),
+        };
+
+        let nal_units = split_length_prefixed(&data, DEFAULT_NAL_LENGTH_SIZE)
+            .expect("Failed to split output frame");
+
+        let last = nal_units.last().expect("Output frame had no NAL units");
+        assert_eq!(last[0], 0x65, "Expected slice to remain the final NAL unit");
+
+        let pair = nal_units
+            .iter()
+            .filter(|nal_unit| h264::nal_unit_type(nal_unit) == Some(h264::NAL_UNIT_TYPE_SEI))
+            .flat_map(|nal_unit| h264::parse_sei_nal_unit(nal_unit))
+            .filter_map(|message| parse_cc_data_sei_payload(&message.payload))
+            .flatten()
+            .map(|cc_data| cc_data.data)
+            .next();
+
+        results.push((timestamp, pair));
+        timestamp += FRAME_INTERVAL;
+    }
+
+    results
+}
+
+fn caption_pairs(frames: &[(Duration, Option<[u8; 2]>)]) -> Vec<[u8; 2]> {
+    frames.iter().filter_map(|(_, pair)| *pair).collect()
+}
+
+fn first_captioned_frame(frames: &[(Duration, Option<[u8; 2]>)]) -> Duration {
+    frames
+        .iter()
+        .find(|(_, pair)| pair.is_some())
+        .map(|(timestamp, _)| *timestamp)
+        .expect("No frame had captions")
+}
+
+async fn subscribe_to_feed(
+    context: &mut StepTestContext,
+    receiver: &mut UnboundedReceiver<SubscriptionRequest>,
+) -> UnboundedSender<CaptionEvent> {
+    let channel = match receiver.try_recv() {
+        Ok(SubscriptionRequest::Captions { channel }) => channel,
+        Ok(request) => panic!("Unexpected subscription request: {:?}", request),
+        Err(error) => panic!("No subscription request received: {:?}", error),
+    };
+
+    context.execute_pending_futures().await;
+
+    channel
+}
+
+fn caption_event(feed: &str, start_millis: u64, end_millis: u64, text: &str) -> CaptionEvent {
+    CaptionEvent {
+        feed: Arc::new(feed.to_string()),
+        cue: CaptionCue {
+            start: Duration::from_millis(start_millis),
+            end: Duration::from_millis(end_millis),
+            text: text.to_string(),
+        },
+    }
+}
+
+#[test]
+fn error_when_no_caption_source_specified() {
+    let (sender, _receiver) = unbounded_channel();
+    let generator = CaptionInjectStepGenerator::new(sender);
+    let result = StepTestContext::new(Box::new(generator), create_definition(&[]));
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn error_when_both_caption_sources_specified() {
+    let (sender, _receiver) = unbounded_channel();
+    let generator = CaptionInjectStepGenerator::new(sender);
+    let definition = create_definition(&[(WEBVTT_FILE, "captions.vtt"), (FEED, "abc")]);
+    let result = StepTestContext::new(Box::new(generator), definition);
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn error_when_offset_not_a_number() {
+    let (sender, _receiver) = unbounded_channel();
+    let generator = CaptionInjectStepGenerator::new(sender);
+    let definition = create_definition(&[(FEED, "abc"), (OFFSET, "soon")]);
+    let result = StepTestContext::new(Box::new(generator), definition);
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[tokio::test]
+async fn webvtt_captions_injected_from_cue_start_and_erased_at_cue_end() {
+    let file = WebVttFile::new("WEBVTT\n\n00:00.100 --> 00:00.500\nHi\n");
+    let (mut context, _receiver) = create_context(&[(WEBVTT_FILE, &file.path())]);
+    context.execute_pending_futures().await;
+    assert_eq!(context.status, StepStatus::Active, "Unexpected step status");
+
+    let frames = send_frames(&mut context, Duration::from_secs(1));
+    assert_eq!(
+        first_captioned_frame(&frames),
+        Duration::from_millis(132),
+        "Expected captions to start on the first frame after the cue's start"
+    );
+
+    let pairs = caption_pairs(&frames);
+    assert_eq!(
+        cea608::extract_text(&pairs),
+        "Hi",
+        "Unexpected caption text"
+    );
+
+    let erase_frame = frames
+        .iter()
+        .find(|(_, pair)| *pair == Some(ERASE_DISPLAYED_MEMORY))
+        .map(|(timestamp, _)| *timestamp)
+        .expect("Expected caption to be erased");
+
+    assert_eq!(
+        erase_frame,
+        Duration::from_millis(528),
+        "Expected erase on the first frame after the cue's end"
+    );
+}
+
+#[tokio::test]
+async fn missing_webvtt_file_puts_step_in_error_state() {
+    let path = std::env::temp_dir().join(format!("mmids-missing-{}.vtt", uuid::Uuid::new_v4()));
+    let (mut context, _receiver) =
+        create_context(&[(WEBVTT_FILE, path.to_string_lossy().as_ref())]);
+
+    context.execute_pending_futures().await;
+
+    match context.status {
+        StepStatus::Error { .. } => (),
+        status => panic!("Expected error status, instead was {:?}", status),
+    }
+}
+
+#[tokio::test]
+async fn feed_captions_injected_with_offset_applied() {
+    let (mut context, mut receiver) = create_context(&[(FEED, "live"), (OFFSET, "-1000")]);
+    let channel = subscribe_to_feed(&mut context, &mut receiver).await;
+
+    channel
+        .send(caption_event("live", 1200, 1800, "Hello"))
+        .expect("Failed to send caption event");
+
+    context.execute_pending_futures().await;
+
+    let frames = send_frames(&mut context, Duration::from_secs(1));
+    assert_eq!(
+        first_captioned_frame(&frames),
+        Duration::from_millis(231),
+        "Expected captions to start on the first frame after the offset cue start"
+    );
+
+    let pairs = caption_pairs(&frames);
+    assert_eq!(
+        cea608::extract_text(&pairs),
+        "Hello",
+        "Unexpected caption text"
+    );
+}
+
+#[tokio::test]
+async fn captions_for_other_feeds_ignored() {
+    let (mut context, mut receiver) = create_context(&[(FEED, "live")]);
+    let channel = subscribe_to_feed(&mut context, &mut receiver).await;
+
+    channel
+        .send(caption_event("other", 100, 800, "Hello"))
+        .expect("Failed to send caption event");
+
+    context.execute_pending_futures().await;
+
+    let frames = send_frames(&mut context, Duration::from_secs(1));
+    assert!(
+        caption_pairs(&frames).is_empty(),
+        "Expected no captions to be injected"
+    );
+}
+
+#[tokio::test]
+async fn non_h264_video_passed_through_unchanged() {
+    let (mut context, mut receiver) = create_context(&[(FEED, "live")]);
+    let channel = subscribe_to_feed(&mut context, &mut receiver).await;
+
+    channel
+        .send(caption_event("live", 0, 800, "Hello"))
+        .expect("Failed to send caption event");
+
+    context.execute_pending_futures().await;
+
+    let mut media = video_frame(Duration::from_millis(100));
+    if let MediaNotificationContent::MediaPayload { payload_type, .. } = &mut media.content {
+        *payload_type = Arc::new("vp8".to_string());
+    }
+
+    context.assert_media_passed_through(media);
+}
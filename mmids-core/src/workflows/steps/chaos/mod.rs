@@ -0,0 +1,434 @@
+//! The chaos step impairs the media flowing through it, so operators can validate how players and
+//! later steps (such as jitter buffers) cope with unreliable sources before they meet one in
+//! production. It does nothing unless at least one impairment is explicitly configured:
+//!
+//! * `drop_percent` - The percentage (0 to 100) of media payloads that are dropped.
+//! * `latency_ms` - A fixed delay added to all media.
+//! * `jitter_ms` - A random extra delay of up to this many milliseconds added to each media
+//!   payload. Jitter never changes the order media leaves the step in.
+//! * `reorder_percent` - The percentage (0 to 100) of media payloads that are held back and sent
+//!   after the next payload of the same stream.
+//!
+//! All random decisions come from a generator seeded with the `seed` parameter (0 by default), so
+//! the same media passing through a step with the same seed is always impaired the same way.
+//!
+//! Sequence headers are never dropped or reordered, as losing them makes the rest of the stream
+//! undecodable, unless the `drop_sequence_headers` flag is specified. Stream announcements,
+//! metadata and disconnections are never dropped or reordered, but are delayed by `latency_ms` to
+//! stay in order with the stream's media.
+//!
+//! How many payloads were dropped, reordered and delayed is surfaced in the step's state details.
+
+#[cfg(test)]
+mod tests;
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::buffer_stats::BufferStats;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent};
+use crate::StreamId;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time::Instant;
+use tracing::{error, info};
+
+pub const DROP_PERCENT: &str = "drop_percent";
+pub const LATENCY_MS: &str = "latency_ms";
+pub const JITTER_MS: &str = "jitter_ms";
+pub const REORDER_PERCENT: &str = "reorder_percent";
+pub const SEED: &str = "seed";
+pub const DROP_SEQUENCE_HEADERS_FLAG: &str = "drop_sequence_headers";
+
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+const DROPPED_COUNT_DETAIL: &str = "dropped_count";
+const REORDERED_COUNT_DETAIL: &str = "reordered_count";
+const DELAYED_COUNT_DETAIL: &str = "delayed_count";
+const PAYLOAD_COUNT_DETAIL: &str = "payload_count";
+
+/// Generates new instances of the chaos workflow step
+pub struct ChaosStepGenerator;
+
+/// A small deterministic random number generator (SplitMix64). Impairments only need to be
+/// repeatable, not unpredictable.
+struct SeededRandom {
+    state: u64,
+}
+
+struct DelayedMedia {
+    release_at: Instant,
+    media: MediaNotification,
+}
+
+struct ChaosStep {
+    drop_percent: f64,
+    reorder_percent: f64,
+    latency: Duration,
+    jitter_ms: u64,
+    drop_sequence_headers: bool,
+    random: SeededRandom,
+
+    /// Payloads held back to be sent after the next payload of their stream
+    reordered: HashMap<StreamId, MediaNotification>,
+
+    /// Media waiting for its delay to pass, in the order it will be released
+    delayed: VecDeque<DelayedMedia>,
+    release_scheduled: bool,
+
+    payload_count: u64,
+    dropped_count: u64,
+    reordered_count: u64,
+    delayed_count: u64,
+}
+
+enum FutureResult {
+    ReleaseDue,
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "No impairments were configured. At least one of {}, {}, {} or {} must be specified",
+        DROP_PERCENT,
+        LATENCY_MS,
+        JITTER_MS,
+        REORDER_PERCENT
+    )]
+    NoImpairments,
+
+    #[error("Invalid {0} value of '{1}' specified, must be a percentage from 0 to 100")]
+    InvalidPercent(&'static str, String),
+
+    #[error(
+        "Invalid {0} value of '{1}' specified, must be a number of milliseconds no more than {}",
+        MAX_DELAY.as_millis()
+    )]
+    InvalidDelay(&'static str, String),
+
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a positive whole number",
+        SEED
+    )]
+    InvalidSeed(String),
+}
+
+impl StepGenerator for ChaosStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let drop_percent = get_percent(&definition, DROP_PERCENT)?;
+        let reorder_percent = get_percent(&definition, REORDER_PERCENT)?;
+        let latency = get_delay(&definition, LATENCY_MS)?;
+        let jitter = get_delay(&definition, JITTER_MS)?;
+        if drop_percent.is_none()
+            && reorder_percent.is_none()
+            && latency.is_none()
+            && jitter.is_none()
+        {
+            return Err(Box::new(StepStartupError::NoImpairments));
+        }
+
+        let seed = match definition.parameters.get(SEED) {
+            Some(Some(value)) => match value.trim().parse::<u64>() {
+                Ok(seed) => seed,
+                Err(_) => return Err(Box::new(StepStartupError::InvalidSeed(value.clone()))),
+            },
+
+            _ => 0,
+        };
+
+        let step = ChaosStep {
+            drop_percent: drop_percent.unwrap_or_default(),
+            reorder_percent: reorder_percent.unwrap_or_default(),
+            latency: latency.unwrap_or_default(),
+            jitter_ms: jitter.unwrap_or_default().as_millis() as u64,
+            drop_sequence_headers: definition
+                .parameters
+                .contains_key(DROP_SEQUENCE_HEADERS_FLAG),
+            random: SeededRandom { state: seed },
+            reordered: HashMap::new(),
+            delayed: VecDeque::new(),
+            release_scheduled: false,
+            payload_count: 0,
+            dropped_count: 0,
+            reordered_count: 0,
+            delayed_count: 0,
+        };
+
+        info!(
+            "Chaos step impairing media with a {}% drop rate, {}% reorder rate, {:?} latency and \
+            {}ms of jitter",
+            step.drop_percent, step.reorder_percent, step.latency, step.jitter_ms
+        );
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+fn get_percent(
+    definition: &WorkflowStepDefinition,
+    parameter: &'static str,
+) -> Result<Option<f64>, StepStartupError> {
+    match definition.parameters.get(parameter) {
+        Some(Some(value)) => match value.trim().parse::<f64>() {
+            Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(Some(percent)),
+            _ => Err(StepStartupError::InvalidPercent(parameter, value.clone())),
+        },
+
+        _ => Ok(None),
+    }
+}
+
+fn get_delay(
+    definition: &WorkflowStepDefinition,
+    parameter: &'static str,
+) -> Result<Option<Duration>, StepStartupError> {
+    match definition.parameters.get(parameter) {
+        Some(Some(value)) => match value.trim().parse::<u64>() {
+            Ok(millis) if Duration::from_millis(millis) <= MAX_DELAY => {
+                Ok(Some(Duration::from_millis(millis)))
+            }
+
+            _ => Err(StepStartupError::InvalidDelay(parameter, value.clone())),
+        },
+
+        _ => Ok(None),
+    }
+}
+
+impl SeededRandom {
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut value = self.state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        value ^ (value >> 31)
+    }
+
+    /// Randomly returns true the specified percentage of the time
+    fn chance(&mut self, percent: f64) -> bool {
+        if percent <= 0.0 {
+            return false;
+        }
+
+        // The top 53 bits give a uniform value in [0, 1) at full f64 precision
+        let value = (self.next() >> 11) as f64 / (1_u64 << 53) as f64;
+        value * 100.0 < percent
+    }
+
+    /// A random number from 0 up to and including the maximum
+    fn up_to(&mut self, max: u64) -> u64 {
+        if max == 0 {
+            return 0;
+        }
+
+        self.next() % (max + 1)
+    }
+}
+
+impl ChaosStep {
+    fn handle_media(
+        &mut self,
+        media: MediaNotification,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let is_sequence_header = match &media.content {
+            MediaNotificationContent::MediaPayload {
+                is_required_for_decoding,
+                ..
+            } => *is_required_for_decoding,
+
+            _ => {
+                // Anything still held back for the stream has to go out before the stream ends
+                if let Some(held) = self.reordered.remove(&media.stream_id) {
+                    self.delay(held, false, outputs, futures_channel);
+                }
+
+                self.delay(media, false, outputs, futures_channel);
+                return;
+            }
+        };
+
+        self.payload_count += 1;
+        let is_protected = is_sequence_header && !self.drop_sequence_headers;
+        if !is_protected && self.random.chance(self.drop_percent) {
+            self.dropped_count += 1;
+            return;
+        }
+
+        if let Some(held) = self.reordered.remove(&media.stream_id) {
+            // Media from before a sequence header may not be decodable with it, so a held payload
+            // is never moved after one
+            if is_sequence_header {
+                self.delay(held, true, outputs, futures_channel);
+                self.delay(media, true, outputs, futures_channel);
+            } else {
+                self.reordered_count += 1;
+                self.delay(media, true, outputs, futures_channel);
+                self.delay(held, true, outputs, futures_channel);
+            }
+
+            return;
+        }
+
+        if !is_sequence_header && self.random.chance(self.reorder_percent) {
+            self.reordered.insert(media.stream_id.clone(), media);
+            return;
+        }
+
+        self.delay(media, true, outputs, futures_channel);
+    }
+
+    /// Sends the media out once its delay has passed. Jitter is only added to media payloads, and
+    /// media is never released ahead of media that came before it.
+    fn delay(
+        &mut self,
+        media: MediaNotification,
+        is_payload: bool,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let jitter = if is_payload {
+            Duration::from_millis(self.random.up_to(self.jitter_ms))
+        } else {
+            Duration::ZERO
+        };
+
+        let delay = self.latency + jitter;
+        if delay.is_zero() && self.delayed.is_empty() {
+            outputs.media.push(media);
+            return;
+        }
+
+        if is_payload && !delay.is_zero() {
+            self.delayed_count += 1;
+        }
+
+        let mut release_at = Instant::now() + delay;
+        if let Some(last) = self.delayed.back() {
+            release_at = release_at.max(last.release_at);
+        }
+
+        self.delayed.push_back(DelayedMedia { release_at, media });
+        self.schedule_release(futures_channel);
+    }
+
+    fn schedule_release(&mut self, futures_channel: &WorkflowStepFuturesChannel) {
+        if self.release_scheduled {
+            return;
+        }
+
+        if let Some(next) = self.delayed.front() {
+            let release_at = next.release_at;
+            self.release_scheduled = true;
+            futures_channel.send_on_generic_future_completion(async move {
+                tokio::time::sleep_until(release_at).await;
+                FutureResult::ReleaseDue
+            });
+        }
+    }
+
+    fn release_due_media(
+        &mut self,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        self.release_scheduled = false;
+
+        let now = Instant::now();
+        while let Some(next) = self.delayed.front() {
+            if next.release_at > now {
+                break;
+            }
+
+            if let Some(delayed) = self.delayed.pop_front() {
+                outputs.media.push(delayed.media);
+            }
+        }
+
+        self.schedule_release(futures_channel);
+    }
+}
+
+impl WorkflowStep for ChaosStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            match notification.downcast::<FutureResult>() {
+                Ok(result) => match *result {
+                    FutureResult::ReleaseDue => self.release_due_media(outputs, &futures_channel),
+                },
+
+                Err(_) => {
+                    error!("Chaos step received a notification that is not a known type");
+                    return StepStatus::Error {
+                        message: "Received future result of unknown type".to_string(),
+                    };
+                }
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            self.handle_media(media, outputs, &futures_channel);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            PAYLOAD_COUNT_DETAIL.to_string(),
+            self.payload_count.to_string(),
+        );
+
+        details.insert(
+            DROPPED_COUNT_DETAIL.to_string(),
+            self.dropped_count.to_string(),
+        );
+
+        details.insert(
+            REORDERED_COUNT_DETAIL.to_string(),
+            self.reordered_count.to_string(),
+        );
+
+        details.insert(
+            DELAYED_COUNT_DETAIL.to_string(),
+            self.delayed_count.to_string(),
+        );
+
+        details
+    }
+
+    fn get_buffer_stats(&self) -> Option<BufferStats> {
+        let mut held_by_stream: HashMap<&StreamId, Vec<&MediaNotification>> = HashMap::new();
+        let delayed = self.delayed.iter().map(|delayed| &delayed.media);
+        for media in delayed.chain(self.reordered.values()) {
+            held_by_stream
+                .entry(&media.stream_id)
+                .or_default()
+                .push(media);
+        }
+
+        let mut stats = BufferStats::new(None);
+        for media in held_by_stream.into_values() {
+            stats.add_stream_media(media);
+        }
+
+        Some(stats)
+    }
+}
@@ -0,0 +1,278 @@
+use super::*;
+use crate::codecs::VIDEO_CODEC_H264_AVC;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::MediaPayloadMetadataCollection;
+use crate::workflows::steps::test_utils::StepTestContext;
+use crate::workflows::MediaType;
+use bytes::{Bytes, BytesMut};
+use std::iter;
+use std::sync::Arc;
+
+struct TestContext {
+    step_context: StepTestContext,
+    stream_id: StreamId,
+}
+
+impl TestContext {
+    fn new(parameters: &[(&str, Option<&str>)]) -> Self {
+        let mut step_context =
+            StepTestContext::new(Box::new(ChaosStepGenerator), definition(parameters))
+                .expect("Failed to create step");
+
+        let stream_id = StreamId(Arc::new("abc".to_string()));
+        step_context.execute_with_media(MediaNotification {
+            stream_id: stream_id.clone(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        });
+
+        TestContext {
+            step_context,
+            stream_id,
+        }
+    }
+
+    fn video(&self, timestamp: u64, is_sequence_header: bool) -> MediaNotification {
+        MediaNotification {
+            stream_id: self.stream_id.clone(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type: VIDEO_CODEC_H264_AVC.clone(),
+                timestamp: Duration::from_millis(timestamp),
+                metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+                data: Bytes::from(vec![1, 2, 3]),
+                is_required_for_decoding: is_sequence_header,
+            },
+            annotations: Default::default(),
+        }
+    }
+
+    /// Sends the frames through the step, returning the timestamps of the payloads output
+    fn send_frames(&mut self, timestamps: impl IntoIterator<Item = u64>) -> Vec<u64> {
+        let mut output_timestamps = Vec::new();
+        for timestamp in timestamps {
+            let media = self.video(timestamp, false);
+            self.step_context.execute_with_media(media);
+            output_timestamps.extend(payload_timestamps(&self.step_context.media_outputs));
+        }
+
+        output_timestamps
+    }
+
+    fn detail(&self, name: &str) -> String {
+        self.step_context.step.get_state_details()[name].clone()
+    }
+}
+
+fn definition(parameters: &[(&str, Option<&str>)]) -> WorkflowStepDefinition {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("chaos".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    for (key, value) in parameters {
+        definition
+            .parameters
+            .insert(key.to_string(), value.map(|value| value.to_string()));
+    }
+
+    definition
+}
+
+fn payload_timestamps(media: &[MediaNotification]) -> Vec<u64> {
+    media
+        .iter()
+        .filter_map(|media| match &media.content {
+            MediaNotificationContent::MediaPayload { timestamp, .. } => {
+                Some(timestamp.as_millis() as u64)
+            }
+
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn error_when_no_impairments_configured() {
+    let result = StepTestContext::new(
+        Box::new(ChaosStepGenerator),
+        definition(&[(SEED, Some("5"))]),
+    );
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn error_when_drop_percent_above_100() {
+    let result = StepTestContext::new(
+        Box::new(ChaosStepGenerator),
+        definition(&[(DROP_PERCENT, Some("101"))]),
+    );
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn configured_percentage_of_payloads_dropped() {
+    let mut context = TestContext::new(&[(DROP_PERCENT, Some("25"))]);
+
+    let outputs = context.send_frames(0..10_000);
+
+    let dropped = 10_000 - outputs.len();
+    assert!(
+        (2300..=2700).contains(&dropped),
+        "Expected around 2500 payloads to be dropped, but {} were",
+        dropped
+    );
+
+    assert_eq!(
+        context.detail(DROPPED_COUNT_DETAIL),
+        dropped.to_string(),
+        "Unexpected dropped count detail"
+    );
+
+    assert_eq!(
+        context.detail(PAYLOAD_COUNT_DETAIL),
+        "10000",
+        "Unexpected payload count detail"
+    );
+}
+
+#[test]
+fn sequence_headers_never_dropped() {
+    let mut context = TestContext::new(&[(DROP_PERCENT, Some("100"))]);
+
+    for timestamp in 0..100 {
+        let header = context.video(timestamp, true);
+        context.step_context.assert_media_passed_through(header);
+
+        let frame = context.video(timestamp, false);
+        context.step_context.assert_media_not_passed_through(frame);
+    }
+}
+
+#[test]
+fn sequence_headers_dropped_when_flag_specified() {
+    let mut context = TestContext::new(&[
+        (DROP_PERCENT, Some("100")),
+        (DROP_SEQUENCE_HEADERS_FLAG, None),
+    ]);
+
+    let header = context.video(0, true);
+    context.step_context.assert_media_not_passed_through(header);
+}
+
+#[test]
+fn same_seed_impairs_media_the_same_way() {
+    let parameters = [(DROP_PERCENT, Some("50")), (SEED, Some("1234"))];
+    let mut first = TestContext::new(&parameters);
+    let mut second = TestContext::new(&parameters);
+    let mut different_seed = TestContext::new(&[(DROP_PERCENT, Some("50")), (SEED, Some("1"))]);
+
+    let first_outputs = first.send_frames(0..100);
+    let second_outputs = second.send_frames(0..100);
+    let different_outputs = different_seed.send_frames(0..100);
+
+    assert_eq!(first_outputs, second_outputs, "Expected identical outputs");
+    assert_ne!(
+        first_outputs, different_outputs,
+        "Expected a different seed to drop different payloads"
+    );
+}
+
+#[test]
+fn reordered_payloads_sent_after_next_payload() {
+    let mut context = TestContext::new(&[(REORDER_PERCENT, Some("100"))]);
+
+    let outputs = context.send_frames([1, 2, 3, 4]);
+
+    assert_eq!(outputs, vec![2, 1, 4, 3], "Unexpected payload order");
+    assert_eq!(
+        context.detail(REORDERED_COUNT_DETAIL),
+        "2",
+        "Unexpected reordered count detail"
+    );
+}
+
+#[test]
+fn held_payload_not_moved_after_sequence_header() {
+    let mut context = TestContext::new(&[(REORDER_PERCENT, Some("100"))]);
+    context.send_frames([1]);
+
+    let header = context.video(2, true);
+    context.step_context.execute_with_media(header);
+
+    let outputs = payload_timestamps(&context.step_context.media_outputs);
+    assert_eq!(
+        outputs,
+        vec![1, 2],
+        "Expected held payload before the header"
+    );
+}
+
+#[test]
+fn held_payload_released_before_stream_disconnects() {
+    let mut context = TestContext::new(&[(REORDER_PERCENT, Some("100"))]);
+    context.send_frames([1]);
+
+    context.step_context.execute_with_media(MediaNotification {
+        stream_id: context.stream_id.clone(),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    });
+
+    let outputs = &context.step_context.media_outputs;
+    assert_eq!(outputs.len(), 2, "Unexpected number of outputs");
+    assert_eq!(
+        payload_timestamps(outputs),
+        vec![1],
+        "Expected held payload"
+    );
+    assert!(
+        matches!(
+            outputs[1].content,
+            MediaNotificationContent::StreamDisconnected
+        ),
+        "Expected disconnection last"
+    );
+}
+
+#[tokio::test]
+async fn media_released_after_latency() {
+    let mut context = TestContext::new(&[(LATENCY_MS, Some("50"))]);
+
+    let outputs = context.send_frames([1, 2]);
+    assert!(outputs.is_empty(), "Expected media to be delayed");
+    assert_eq!(
+        context.detail(DELAYED_COUNT_DETAIL),
+        "2",
+        "Unexpected delayed count detail"
+    );
+
+    let stats = context.step_context.step.get_buffer_stats().unwrap();
+    assert_eq!(stats.current_packets, 2, "Unexpected buffered packet count");
+
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    context.step_context.execute_pending_futures().await;
+
+    let outputs = payload_timestamps(&context.step_context.media_outputs);
+    assert_eq!(outputs, vec![1, 2], "Expected delayed media in order");
+}
+
+#[tokio::test]
+async fn jitter_does_not_reorder_media() {
+    let mut context = TestContext::new(&[(JITTER_MS, Some("20"))]);
+
+    let mut outputs = context.send_frames(0..50);
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    context.step_context.execute_pending_futures().await;
+    outputs.extend(payload_timestamps(&context.step_context.media_outputs));
+
+    assert_eq!(
+        outputs,
+        (0..50).collect::<Vec<_>>(),
+        "Unexpected media order"
+    );
+}
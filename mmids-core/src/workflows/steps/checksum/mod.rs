@@ -0,0 +1,281 @@
+//! The checksum step helps catch steps that corrupt or misroute media payloads. It's meant for
+//! integration tests and debugging, and works in pairs: a step in `mode=tag` placed early in a
+//! workflow attaches a checksum of each media payload to the payload's metadata, and a step in
+//! `mode=verify` placed later in the workflow recomputes the checksum and reports every payload
+//! whose checksum no longer matches.
+//!
+//! Checksums cover the payload's data and the id of the stream it belongs to, so payloads that
+//! are modified or end up on the wrong stream are both flagged. Steps between the two that
+//! legitimately re-create payloads (such as transcodes) drop the checksum, and those payloads are
+//! counted as untagged rather than as mismatches.
+//!
+//! The verify step always strips the checksum from payloads before passing them on, so it never
+//! reaches real outputs. Checksumming can be turned off without changing the workflow's steps by
+//! setting `enabled=false`, in which case tag steps don't attach checksums and verify steps only
+//! strip them.
+//!
+//! Mismatches are logged, and the number of verified, mismatched, and untagged payloads are
+//! reported through the step's state details.
+
+#[cfg(test)]
+mod tests;
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::metadata::{
+    MediaPayloadMetadataCollection, MetadataEntry, MetadataKey, MetadataValue,
+};
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent};
+use crate::StreamId;
+use bytes::BytesMut;
+use std::collections::HashMap;
+use thiserror::Error;
+use tracing::warn;
+
+pub const MODE: &str = "mode";
+pub const ENABLED: &str = "enabled";
+
+const VERIFIED_COUNT_DETAIL: &str = "verified_count";
+const MISMATCH_COUNT_DETAIL: &str = "mismatch_count";
+const UNTAGGED_COUNT_DETAIL: &str = "untagged_count";
+const TAGGED_COUNT_DETAIL: &str = "tagged_count";
+
+const FNV_OFFSET_BASIS: u32 = 0x811c_9dc5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+/// Generates new instances of the checksum workflow step
+pub struct ChecksumStepGenerator {
+    checksum_metadata_key: MetadataKey,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Mode {
+    Tag,
+    Verify,
+}
+
+struct ChecksumStep {
+    mode: Mode,
+    is_enabled: bool,
+    checksum_metadata_key: MetadataKey,
+    metadata_buffer: BytesMut,
+    tagged_count: u64,
+    verified_count: u64,
+    mismatch_count: u64,
+    untagged_count: u64,
+}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "No {} parameter specified.  It must be either 'tag' or 'verify'",
+        MODE
+    )]
+    NoMode,
+
+    #[error(
+        "Invalid {} value of '{0}'.  It must be either 'tag' or 'verify'",
+        MODE
+    )]
+    InvalidMode(String),
+
+    #[error(
+        "Invalid {} value of '{0}'.  It must be either 'true' or 'false'",
+        ENABLED
+    )]
+    InvalidEnabled(String),
+}
+
+impl ChecksumStepGenerator {
+    /// Creates a generator for checksum steps. The metadata key is expected to be the one
+    /// returned by `get_payload_checksum_metadata_key()`.
+    pub fn new(checksum_metadata_key: MetadataKey) -> Self {
+        ChecksumStepGenerator {
+            checksum_metadata_key,
+        }
+    }
+}
+
+impl StepGenerator for ChecksumStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let mode = match definition.parameters.get(MODE) {
+            Some(Some(value)) => match value.trim().to_lowercase().as_str() {
+                "tag" => Mode::Tag,
+                "verify" => Mode::Verify,
+                _ => return Err(Box::new(StepStartupError::InvalidMode(value.clone()))),
+            },
+
+            _ => return Err(Box::new(StepStartupError::NoMode)),
+        };
+
+        let is_enabled = match definition.parameters.get(ENABLED) {
+            Some(Some(value)) => match value.trim().parse::<bool>() {
+                Ok(value) => value,
+                Err(_) => return Err(Box::new(StepStartupError::InvalidEnabled(value.clone()))),
+            },
+
+            _ => true,
+        };
+
+        let step = ChecksumStep {
+            mode,
+            is_enabled,
+            checksum_metadata_key: self.checksum_metadata_key,
+            metadata_buffer: BytesMut::new(),
+            tagged_count: 0,
+            verified_count: 0,
+            mismatch_count: 0,
+            untagged_count: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl ChecksumStep {
+    fn handle_media(&mut self, mut media: MediaNotification) -> MediaNotification {
+        let (data, metadata) = match &mut media.content {
+            MediaNotificationContent::MediaPayload { data, metadata, .. } => (data, metadata),
+            _ => return media,
+        };
+
+        let checksum_metadata_key = self.checksum_metadata_key;
+        let existing_checksum = metadata
+            .iter()
+            .filter(|entry| entry.key() == checksum_metadata_key)
+            .filter_map(|entry| match entry.value() {
+                MetadataValue::U32(checksum) => Some(checksum),
+                _ => None,
+            })
+            .next();
+
+        match self.mode {
+            Mode::Tag => {
+                if !self.is_enabled {
+                    return media;
+                }
+
+                let entry = MetadataEntry::new(
+                    checksum_metadata_key,
+                    MetadataValue::U32(calculate_checksum(&media.stream_id, data)),
+                    &mut self.metadata_buffer,
+                )
+                .unwrap(); // Can only panic if the key is not for a u32
+
+                // Any checksum from an earlier tag step is replaced
+                let entries = metadata
+                    .iter()
+                    .filter(|entry| entry.key() != checksum_metadata_key)
+                    .chain(std::iter::once(entry));
+
+                *metadata = MediaPayloadMetadataCollection::new(entries, &mut self.metadata_buffer);
+                self.tagged_count += 1;
+            }
+
+            Mode::Verify => {
+                let expected_checksum = match existing_checksum {
+                    Some(checksum) => checksum,
+                    None => {
+                        if self.is_enabled {
+                            self.untagged_count += 1;
+                        }
+
+                        return media;
+                    }
+                };
+
+                if self.is_enabled {
+                    let actual_checksum = calculate_checksum(&media.stream_id, data);
+                    if actual_checksum == expected_checksum {
+                        self.verified_count += 1;
+                    } else {
+                        self.mismatch_count += 1;
+                        warn!(
+                            stream_id = %media.stream_id.0,
+                            expected_checksum = expected_checksum,
+                            actual_checksum = actual_checksum,
+                            "Media payload's checksum does not match the checksum it was tagged \
+                            with, so it was modified or misrouted since it was tagged"
+                        );
+                    }
+                }
+
+                let entries = metadata
+                    .iter()
+                    .filter(|entry| entry.key() != checksum_metadata_key);
+
+                *metadata = MediaPayloadMetadataCollection::new(entries, &mut self.metadata_buffer);
+            }
+        }
+
+        media
+    }
+}
+
+impl WorkflowStep for ChecksumStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            let media = self.handle_media(media);
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        match self.mode {
+            Mode::Tag => {
+                details.insert(
+                    TAGGED_COUNT_DETAIL.to_string(),
+                    self.tagged_count.to_string(),
+                );
+            }
+
+            Mode::Verify => {
+                details.insert(
+                    VERIFIED_COUNT_DETAIL.to_string(),
+                    self.verified_count.to_string(),
+                );
+
+                details.insert(
+                    MISMATCH_COUNT_DETAIL.to_string(),
+                    self.mismatch_count.to_string(),
+                );
+
+                details.insert(
+                    UNTAGGED_COUNT_DETAIL.to_string(),
+                    self.untagged_count.to_string(),
+                );
+            }
+        }
+
+        details
+    }
+}
+
+/// 32 bit FNV-1a hash of the stream id and payload data. This only needs to catch accidental
+/// changes, not deliberate ones, so a cryptographic hash isn't needed.
+fn calculate_checksum(stream_id: &StreamId, data: &[u8]) -> u32 {
+    stream_id
+        .0
+        .as_bytes()
+        .iter()
+        .chain(data.iter())
+        .fold(FNV_OFFSET_BASIS, |hash, byte| {
+            (hash ^ *byte as u32).wrapping_mul(FNV_PRIME)
+        })
+}
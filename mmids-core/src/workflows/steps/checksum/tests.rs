@@ -0,0 +1,266 @@
+use super::*;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::common_metadata::get_payload_checksum_metadata_key;
+use crate::workflows::metadata::MetadataKeyMap;
+use crate::workflows::steps::test_utils::StepTestContext;
+use crate::workflows::MediaType;
+use crate::{codecs::VIDEO_CODEC_H264_AVC, StreamId};
+use bytes::Bytes;
+use std::iter;
+use std::sync::Arc;
+use std::time::Duration;
+
+struct TestContext {
+    checksum_metadata_key: MetadataKey,
+    tag_step: StepTestContext,
+    verify_step: StepTestContext,
+}
+
+/// Test step that flips a bit in every media payload passing through it
+struct CorruptPayloadStepGenerator;
+struct CorruptPayloadStep;
+
+impl StepGenerator for CorruptPayloadStepGenerator {
+    fn generate(
+        &self,
+        _definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        Ok((Box::new(CorruptPayloadStep), StepStatus::Active))
+    }
+}
+
+impl WorkflowStep for CorruptPayloadStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for mut media in inputs.media.drain(..) {
+            if let MediaNotificationContent::MediaPayload { data, .. } = &mut media.content {
+                let mut corrupted = data.to_vec();
+                corrupted[0] ^= 0x01;
+                *data = Bytes::from(corrupted);
+            }
+
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+}
+
+impl TestContext {
+    fn new(enabled: Option<&str>) -> Self {
+        let mut key_map = MetadataKeyMap::new();
+        let checksum_metadata_key = get_payload_checksum_metadata_key(&mut key_map);
+
+        TestContext {
+            checksum_metadata_key,
+            tag_step: create_step(checksum_metadata_key, "tag", enabled),
+            verify_step: create_step(checksum_metadata_key, "verify", enabled),
+        }
+    }
+
+    fn tag(&mut self, media: MediaNotification) -> MediaNotification {
+        self.tag_step.media_outputs.clear();
+        self.tag_step.execute_with_media(media);
+
+        assert_eq!(
+            self.tag_step.media_outputs.len(),
+            1,
+            "Unexpected number of tag outputs"
+        );
+
+        self.tag_step.media_outputs.remove(0)
+    }
+
+    fn verify(&mut self, media: MediaNotification) -> MediaNotification {
+        self.verify_step.media_outputs.clear();
+        self.verify_step.execute_with_media(media);
+
+        assert_eq!(
+            self.verify_step.media_outputs.len(),
+            1,
+            "Unexpected number of verify outputs"
+        );
+
+        self.verify_step.media_outputs.remove(0)
+    }
+
+    fn get_checksum(&self, media: &MediaNotification) -> Option<u32> {
+        match &media.content {
+            MediaNotificationContent::MediaPayload { metadata, .. } => metadata
+                .iter()
+                .filter(|entry| entry.key() == self.checksum_metadata_key)
+                .filter_map(|entry| match entry.value() {
+                    MetadataValue::U32(checksum) => Some(checksum),
+                    _ => None,
+                })
+                .next(),
+
+            content => panic!("Unexpected media content: {:?}", content),
+        }
+    }
+
+    fn verify_detail(&self, name: &str) -> String {
+        self.verify_step
+            .step
+            .get_state_details()
+            .get(name)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+fn create_step(key: MetadataKey, mode: &str, enabled: Option<&str>) -> StepTestContext {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("checksum".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    definition
+        .parameters
+        .insert(MODE.to_string(), Some(mode.to_string()));
+
+    if let Some(enabled) = enabled {
+        definition
+            .parameters
+            .insert(ENABLED.to_string(), Some(enabled.to_string()));
+    }
+
+    StepTestContext::new(Box::new(ChecksumStepGenerator::new(key)), definition)
+        .expect("Failed to create step")
+}
+
+fn video(stream_id: &str, data: &'static [u8]) -> MediaNotification {
+    MediaNotification {
+        stream_id: StreamId(Arc::new(stream_id.to_string())),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Video,
+            payload_type: VIDEO_CODEC_H264_AVC.clone(),
+            timestamp: Duration::from_millis(10),
+            is_required_for_decoding: false,
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data: Bytes::from_static(data),
+        },
+        annotations: Default::default(),
+    }
+}
+
+#[test]
+fn error_if_no_mode_specified() {
+    let mut key_map = MetadataKeyMap::new();
+    let definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("checksum".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    let generator = ChecksumStepGenerator::new(get_payload_checksum_metadata_key(&mut key_map));
+    let result = StepTestContext::new(Box::new(generator), definition);
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn tag_step_attaches_checksum_to_payloads() {
+    let mut context = TestContext::new(None);
+    let media = context.tag(video("abc", &[1, 2, 3]));
+
+    assert!(
+        context.get_checksum(&media).is_some(),
+        "Expected a checksum to be attached"
+    );
+}
+
+#[test]
+fn unmodified_payload_is_verified_and_checksum_stripped() {
+    let mut context = TestContext::new(None);
+    let media = context.tag(video("abc", &[1, 2, 3]));
+    let media = context.verify(media);
+
+    assert_eq!(
+        context.get_checksum(&media),
+        None,
+        "Expected checksum to be stripped"
+    );
+
+    assert_eq!(context.verify_detail(VERIFIED_COUNT_DETAIL), "1");
+    assert_eq!(context.verify_detail(MISMATCH_COUNT_DETAIL), "0");
+}
+
+#[test]
+fn payload_corrupted_by_step_is_flagged() {
+    let mut context = TestContext::new(None);
+    let mut corrupt_step = StepTestContext::new(
+        Box::new(CorruptPayloadStepGenerator),
+        WorkflowStepDefinition {
+            step_type: WorkflowStepType("corrupt".to_string()),
+            parameters: HashMap::new(),
+        },
+    )
+    .expect("Failed to create corrupting step");
+
+    let media = context.tag(video("abc", &[1, 2, 3]));
+    corrupt_step.execute_with_media(media);
+    let media = corrupt_step.media_outputs.remove(0);
+    let media = context.verify(media);
+
+    assert_eq!(context.verify_detail(MISMATCH_COUNT_DETAIL), "1");
+    assert_eq!(context.verify_detail(VERIFIED_COUNT_DETAIL), "0");
+    assert_eq!(
+        context.get_checksum(&media),
+        None,
+        "Expected checksum to be stripped"
+    );
+}
+
+#[test]
+fn misrouted_payload_is_flagged() {
+    let mut context = TestContext::new(None);
+    let mut media = context.tag(video("abc", &[1, 2, 3]));
+    media.stream_id = StreamId(Arc::new("def".to_string()));
+    context.verify(media);
+
+    assert_eq!(context.verify_detail(MISMATCH_COUNT_DETAIL), "1");
+}
+
+#[test]
+fn untagged_payload_is_counted() {
+    let mut context = TestContext::new(None);
+    context.verify(video("abc", &[1, 2, 3]));
+
+    assert_eq!(context.verify_detail(UNTAGGED_COUNT_DETAIL), "1");
+    assert_eq!(context.verify_detail(MISMATCH_COUNT_DETAIL), "0");
+}
+
+#[test]
+fn disabled_tag_step_does_not_attach_checksum() {
+    let mut context = TestContext::new(Some("false"));
+    let media = context.tag(video("abc", &[1, 2, 3]));
+
+    assert_eq!(
+        context.get_checksum(&media),
+        None,
+        "Expected no checksum to be attached"
+    );
+}
+
+#[test]
+fn disabled_verify_step_strips_checksum_without_verifying() {
+    let mut enabled_context = TestContext::new(None);
+    let mut disabled_context = TestContext::new(Some("false"));
+
+    // Misrouted so it would be flagged if it was verified
+    let mut media = enabled_context.tag(video("abc", &[1, 2, 3]));
+    media.stream_id = StreamId(Arc::new("def".to_string()));
+    let media = disabled_context.verify(media);
+
+    assert_eq!(
+        disabled_context.get_checksum(&media),
+        None,
+        "Expected checksum to be stripped"
+    );
+
+    assert_eq!(disabled_context.verify_detail(MISMATCH_COUNT_DETAIL), "0");
+}
@@ -1,6 +1,8 @@
 use crate::workflows::definitions::{WorkflowStepDefinition, WorkflowStepType};
 use crate::workflows::steps::futures_channel::{FuturesChannelResult, WorkflowStepFuturesChannel};
+use crate::workflows::steps::side_channel::SideChannelMessage;
 use crate::workflows::steps::StepCreationResult;
+use crate::workflows::watchers::WorkflowWatchers;
 use std::collections::HashMap;
 use thiserror::Error;
 use tokio::sync::mpsc::UnboundedSender;
@@ -23,6 +25,7 @@ pub trait StepGenerator {
 #[derive(Default)]
 pub struct WorkflowStepFactory {
     generators: HashMap<WorkflowStepType, Box<dyn StepGenerator + Sync + Send>>,
+    side_channel: Option<UnboundedSender<SideChannelMessage>>,
 }
 
 /// Errors that can occur when an attempting to register a generator fails
@@ -61,20 +64,35 @@ impl WorkflowStepFactory {
         Ok(())
     }
 
-    /// Attempts to create a new instance of a workflow step based on a specified definition
+    /// Sets the channel that all steps created by this factory will send their out-of-band data to
+    pub fn set_side_channel(&mut self, side_channel: UnboundedSender<SideChannelMessage>) {
+        self.side_channel = Some(side_channel);
+    }
+
+    /// Attempts to create a new instance of a workflow step based on a specified definition. The
+    /// workflow watchers are shared with the step, if provided.
     pub(crate) fn create_step(
         &self,
         definition: WorkflowStepDefinition,
         futures_channel: &UnboundedSender<FuturesChannelResult>,
+        workflow_watchers: Option<WorkflowWatchers>,
     ) -> Result<StepCreationResult, FactoryCreateError> {
         let generator = match self.generators.get(&definition.step_type) {
             Some(generator) => generator,
             None => return Err(FactoryCreateError::NoRegisteredStep(definition.step_type)),
         };
 
-        let futures_channel =
+        let mut futures_channel =
             WorkflowStepFuturesChannel::new(definition.get_id(), futures_channel.clone());
 
+        if let Some(side_channel) = &self.side_channel {
+            futures_channel = futures_channel.with_side_channel(side_channel.clone());
+        }
+
+        if let Some(workflow_watchers) = workflow_watchers {
+            futures_channel = futures_channel.with_workflow_watchers(workflow_watchers);
+        }
+
         Ok(generator.generate(definition, futures_channel))
     }
 }
@@ -0,0 +1,593 @@
+//! The failover step combines a primary and a backup source into a single output stream, allowing
+//! redundant feeds of the same content to provide resilient ingest.
+//!
+//! Sources are identified either by stream name (`primary_stream` and `backup_stream`) or by
+//! stream label (`primary_label` and `backup_label`). The primary source's media is forwarded as
+//! long as it's healthy. If the primary disconnects or no media has been received from it for
+//! `timeout` milliseconds, the step switches to the backup source. Once the primary has been
+//! continuously healthy again for `restore_delay` milliseconds it's switched back to, which
+//! prevents a flapping primary from causing constant switches.
+//!
+//! The output stream is announced with the `output_stream_name` name. Timestamps are rebased on
+//! each switch so the output stream's timeline continues where the previous source left off. Each
+//! switch is signaled with a discontinuity notification (whose value is the name of the now active
+//! source), followed by the new source's sequence headers.
+//!
+//! Media from source streams is never passed through as is, while all other streams are passed
+//! through untouched. Since a stream's label is only known after its new incoming stream
+//! notification has been passed through, streams adopted by their label are announced as
+//! disconnected to later steps when they are adopted.
+
+#[cfg(test)]
+mod tests;
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::discontinuity::discontinuity_notification;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::stream_labels::get_announced_label;
+use crate::workflows::{MediaNotification, MediaNotificationContent};
+use crate::StreamId;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+pub const PRIMARY_STREAM: &str = "primary_stream";
+pub const BACKUP_STREAM: &str = "backup_stream";
+pub const PRIMARY_LABEL: &str = "primary_label";
+pub const BACKUP_LABEL: &str = "backup_label";
+pub const OUTPUT_STREAM_NAME: &str = "output_stream_name";
+pub const TIMEOUT: &str = "timeout";
+pub const RESTORE_DELAY: &str = "restore_delay";
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(2000);
+const DEFAULT_RESTORE_DELAY: Duration = Duration::from_millis(5000);
+
+const ACTIVE_SOURCE_DETAIL: &str = "active_source";
+const SWITCH_COUNT_DETAIL: &str = "switch_count";
+
+/// Generates new instances of the failover workflow step
+#[derive(Default)]
+pub struct FailoverStepGenerator {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Role {
+    Primary,
+    Backup,
+}
+
+enum SourceMatcher {
+    StreamName(String),
+    Label(String),
+}
+
+struct Source {
+    matcher: SourceMatcher,
+    stream_id: Option<StreamId>,
+    last_media_at: Option<Instant>,
+    healthy_since: Option<Instant>,
+    last_timestamp: Option<Duration>,
+    sequence_headers: Vec<MediaNotificationContent>,
+}
+
+struct OutputStream {
+    stream_id: StreamId,
+    last_timestamp: Option<Duration>,
+
+    /// Microseconds added to the active source's timestamps to get the output timestamps
+    timestamp_offset: i128,
+}
+
+struct FailoverStep {
+    primary: Source,
+    backup: Source,
+    output_stream_name: Arc<String>,
+    timeout: Duration,
+    restore_delay: Duration,
+    active_source: Option<Role>,
+    output: Option<OutputStream>,
+    switch_count: u64,
+}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error("Exactly one of the {0} or {1} parameters must be specified")]
+    InvalidSource(&'static str, &'static str),
+
+    #[error("No {} parameter specified", OUTPUT_STREAM_NAME)]
+    NoOutputStreamName,
+
+    #[error(
+        "Invalid {0} value of '{1}' specified, must be a number of milliseconds greater than zero"
+    )]
+    InvalidDuration(&'static str, String),
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Role::Primary => write!(f, "primary"),
+            Role::Backup => write!(f, "backup"),
+        }
+    }
+}
+
+impl FailoverStepGenerator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl StepGenerator for FailoverStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let primary = get_source_matcher(&definition, PRIMARY_STREAM, PRIMARY_LABEL)?;
+        let backup = get_source_matcher(&definition, BACKUP_STREAM, BACKUP_LABEL)?;
+        let output_stream_name = match get_parameter(&definition, OUTPUT_STREAM_NAME) {
+            Some(name) => Arc::new(name),
+            None => return Err(Box::new(StepStartupError::NoOutputStreamName)),
+        };
+
+        let step = FailoverStep {
+            primary: Source::new(primary),
+            backup: Source::new(backup),
+            output_stream_name,
+            timeout: get_duration(&definition, TIMEOUT)?.unwrap_or(DEFAULT_TIMEOUT),
+            restore_delay: get_duration(&definition, RESTORE_DELAY)?
+                .unwrap_or(DEFAULT_RESTORE_DELAY),
+            active_source: None,
+            output: None,
+            switch_count: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+fn get_parameter(definition: &WorkflowStepDefinition, parameter: &str) -> Option<String> {
+    match definition.parameters.get(parameter) {
+        Some(Some(value)) if !value.trim().is_empty() => Some(value.trim().to_string()),
+        _ => None,
+    }
+}
+
+fn get_source_matcher(
+    definition: &WorkflowStepDefinition,
+    stream_parameter: &'static str,
+    label_parameter: &'static str,
+) -> Result<SourceMatcher, StepStartupError> {
+    let stream_name = get_parameter(definition, stream_parameter);
+    let label = get_parameter(definition, label_parameter);
+    match (stream_name, label) {
+        (Some(stream_name), None) => Ok(SourceMatcher::StreamName(stream_name)),
+        (None, Some(label)) => Ok(SourceMatcher::Label(label)),
+        _ => Err(StepStartupError::InvalidSource(
+            stream_parameter,
+            label_parameter,
+        )),
+    }
+}
+
+fn get_duration(
+    definition: &WorkflowStepDefinition,
+    parameter: &'static str,
+) -> Result<Option<Duration>, StepStartupError> {
+    match get_parameter(definition, parameter) {
+        Some(value) => match value.parse::<u64>() {
+            Ok(millis) if millis > 0 => Ok(Some(Duration::from_millis(millis))),
+            _ => Err(StepStartupError::InvalidDuration(parameter, value)),
+        },
+
+        None => Ok(None),
+    }
+}
+
+impl Source {
+    fn new(matcher: SourceMatcher) -> Self {
+        Source {
+            matcher,
+            stream_id: None,
+            last_media_at: None,
+            healthy_since: None,
+            last_timestamp: None,
+            sequence_headers: Vec::new(),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.stream_id = None;
+        self.last_media_at = None;
+        self.healthy_since = None;
+        self.last_timestamp = None;
+        self.sequence_headers.clear();
+    }
+
+    fn media_received(&mut self, content: &MediaNotificationContent, timeout: Duration) {
+        let now = Instant::now();
+        if !self.is_up(now, timeout) {
+            self.healthy_since = Some(now);
+        }
+
+        self.last_media_at = Some(now);
+
+        if let MediaNotificationContent::MediaPayload {
+            media_type,
+            timestamp,
+            is_required_for_decoding,
+            ..
+        } = content
+        {
+            self.last_timestamp = Some(*timestamp);
+
+            if *is_required_for_decoding {
+                // Only the latest sequence header of each media type is needed for decoding
+                self.sequence_headers.retain(|header| match header {
+                    MediaNotificationContent::MediaPayload {
+                        media_type: header_type,
+                        ..
+                    } => header_type != media_type,
+                    _ => true,
+                });
+
+                self.sequence_headers.push(content.clone());
+            }
+        }
+    }
+
+    fn is_up(&self, now: Instant, timeout: Duration) -> bool {
+        self.stream_id.is_some()
+            && self
+                .last_media_at
+                .is_some_and(|last| now.duration_since(last) <= timeout)
+    }
+
+    fn is_stable(&self, now: Instant, timeout: Duration, restore_delay: Duration) -> bool {
+        self.is_up(now, timeout)
+            && self
+                .healthy_since
+                .is_some_and(|since| now.duration_since(since) >= restore_delay)
+    }
+}
+
+impl FailoverStep {
+    fn source(&self, role: Role) -> &Source {
+        match role {
+            Role::Primary => &self.primary,
+            Role::Backup => &self.backup,
+        }
+    }
+
+    fn source_mut(&mut self, role: Role) -> &mut Source {
+        match role {
+            Role::Primary => &mut self.primary,
+            Role::Backup => &mut self.backup,
+        }
+    }
+
+    fn role_of(&self, stream_id: &StreamId) -> Option<Role> {
+        if self.primary.stream_id.as_ref() == Some(stream_id) {
+            Some(Role::Primary)
+        } else if self.backup.stream_id.as_ref() == Some(stream_id) {
+            Some(Role::Backup)
+        } else {
+            None
+        }
+    }
+
+    /// Finds the unassigned source that the new stream should be adopted as
+    fn find_unassigned_source(
+        &self,
+        stream_name: Option<&str>,
+        label: Option<&str>,
+    ) -> Option<Role> {
+        [Role::Primary, Role::Backup].iter().copied().find(|role| {
+            let source = self.source(*role);
+            let is_match = match &source.matcher {
+                SourceMatcher::StreamName(name) => stream_name == Some(name.as_str()),
+                SourceMatcher::Label(source_label) => label == Some(source_label.as_str()),
+            };
+
+            is_match && source.stream_id.is_none()
+        })
+    }
+
+    fn handle_media(&mut self, media: MediaNotification, outputs: &mut StepOutputs) {
+        let role = match self.role_of(&media.stream_id) {
+            Some(role) => role,
+            None => {
+                self.handle_unassigned_media(media, outputs);
+                return;
+            }
+        };
+
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { .. } => {
+                // The stream was re-announced, so anything we knew about it is stale
+                let source = self.source_mut(role);
+                let stream_id = source.stream_id.clone();
+                source.reset();
+                source.stream_id = stream_id;
+                self.select_source(outputs);
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                info!(
+                    stream_id = %media.stream_id.0,
+                    "The {} source stream disconnected", role
+                );
+
+                self.source_mut(role).reset();
+                self.select_source(outputs);
+            }
+
+            MediaNotificationContent::Metadata { .. } => {
+                if get_announced_label(&media.content).is_some() {
+                    // Source labels are consumed by this step
+                    return;
+                }
+
+                if self.active_source == Some(role) {
+                    self.forward(media.content, outputs);
+                }
+            }
+
+            MediaNotificationContent::MediaPayload {
+                is_required_for_decoding,
+                ..
+            } => {
+                let is_required_for_decoding = *is_required_for_decoding;
+                let timeout = self.timeout;
+                self.source_mut(role)
+                    .media_received(&media.content, timeout);
+
+                let switched = self.select_source(outputs);
+                if self.active_source != Some(role) {
+                    return;
+                }
+
+                // Sequence headers were already sent as part of the switch
+                if switched && is_required_for_decoding {
+                    return;
+                }
+
+                self.forward(media.content, outputs);
+            }
+        }
+    }
+
+    fn handle_unassigned_media(&mut self, media: MediaNotification, outputs: &mut StepOutputs) {
+        let adopted_role = match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                self.find_unassigned_source(Some(stream_name.as_str()), None)
+            }
+
+            content => match get_announced_label(content) {
+                Some(label) => self.find_unassigned_source(None, Some(label)),
+                None => None,
+            },
+        };
+
+        let role = match adopted_role {
+            Some(role) => role,
+            None => {
+                outputs.media.push(media);
+                return;
+            }
+        };
+
+        info!(
+            stream_id = %media.stream_id.0,
+            "Stream adopted as the {} source", role
+        );
+
+        if get_announced_label(&media.content).is_some() {
+            // Later steps were told about this stream before we knew its label
+            outputs.media.push(MediaNotification {
+                stream_id: media.stream_id.clone(),
+                content: MediaNotificationContent::StreamDisconnected,
+                annotations: Default::default(),
+            });
+        }
+
+        let source = self.source_mut(role);
+        source.reset();
+        source.stream_id = Some(media.stream_id);
+    }
+
+    /// Picks which source should be active, switching to it if it's not already active. Returns
+    /// true if a switch occurred.
+    fn select_source(&mut self, outputs: &mut StepOutputs) -> bool {
+        let now = Instant::now();
+        let primary_up = self.primary.is_up(now, self.timeout);
+        let backup_up = self.backup.is_up(now, self.timeout);
+
+        let desired = match self.active_source {
+            None if primary_up => Some(Role::Primary),
+            None if backup_up => Some(Role::Backup),
+            Some(Role::Primary) if !primary_up && backup_up => Some(Role::Backup),
+            Some(Role::Backup)
+                if self
+                    .primary
+                    .is_stable(now, self.timeout, self.restore_delay) =>
+            {
+                Some(Role::Primary)
+            }
+
+            Some(Role::Backup) if !backup_up && primary_up => Some(Role::Primary),
+            current => current,
+        };
+
+        // Once both sources are gone there's nothing left to output
+        let desired = if self.primary.stream_id.is_none() && self.backup.stream_id.is_none() {
+            None
+        } else {
+            desired
+        };
+
+        if desired == self.active_source {
+            return false;
+        }
+
+        match desired {
+            Some(role) => self.switch_to(role, outputs),
+            None => self.end_output(outputs),
+        }
+
+        true
+    }
+
+    fn switch_to(&mut self, role: Role, outputs: &mut StepOutputs) {
+        let previous = self.active_source.replace(role);
+        let source_timestamp = self.source(role).last_timestamp;
+        let sequence_headers = self.source(role).sequence_headers.clone();
+
+        match self.output.as_mut() {
+            None => {
+                info!("Starting output stream from the {} source", role);
+
+                let stream_id = StreamId(Arc::new(Uuid::new_v4().to_string()));
+                outputs.media.push(MediaNotification {
+                    stream_id: stream_id.clone(),
+                    content: MediaNotificationContent::NewIncomingStream {
+                        stream_name: self.output_stream_name.clone(),
+                    },
+                    annotations: Default::default(),
+                });
+
+                self.output = Some(OutputStream {
+                    stream_id,
+                    last_timestamp: None,
+                    timestamp_offset: 0,
+                });
+            }
+
+            Some(output) => {
+                warn!(
+                    "Switching from the {} source to the {} source",
+                    previous.map_or("no".to_string(), |x| x.to_string()),
+                    role
+                );
+
+                // Continue the output timeline from where the previous source left off
+                output.timestamp_offset = match (output.last_timestamp, source_timestamp) {
+                    (Some(output), Some(source)) => {
+                        output.as_micros() as i128 - source.as_micros() as i128
+                    }
+                    _ => 0,
+                };
+
+                self.switch_count += 1;
+
+                outputs.media.push(discontinuity_notification(
+                    output.stream_id.clone(),
+                    &role.to_string(),
+                ));
+            }
+        }
+
+        for header in sequence_headers {
+            self.forward(header, outputs);
+        }
+    }
+
+    fn end_output(&mut self, outputs: &mut StepOutputs) {
+        self.active_source = None;
+        if let Some(output) = self.output.take() {
+            info!("Both sources are gone, ending the output stream");
+            outputs.media.push(MediaNotification {
+                stream_id: output.stream_id,
+                content: MediaNotificationContent::StreamDisconnected,
+                annotations: Default::default(),
+            });
+        }
+    }
+
+    /// Sends the active source's media out on the output stream
+    fn forward(&mut self, content: MediaNotificationContent, outputs: &mut StepOutputs) {
+        let output = match self.output.as_mut() {
+            Some(output) => output,
+            None => return,
+        };
+
+        let content = match content {
+            MediaNotificationContent::MediaPayload {
+                media_type,
+                payload_type,
+                timestamp,
+                metadata,
+                data,
+                is_required_for_decoding,
+            } => {
+                let timestamp = apply_offset(timestamp, output.timestamp_offset);
+                output.last_timestamp = Some(
+                    output
+                        .last_timestamp
+                        .map_or(timestamp, |last| last.max(timestamp)),
+                );
+
+                MediaNotificationContent::MediaPayload {
+                    media_type,
+                    payload_type,
+                    timestamp,
+                    metadata,
+                    data,
+                    is_required_for_decoding,
+                }
+            }
+
+            content => content,
+        };
+
+        outputs.media.push(MediaNotification {
+            stream_id: output.stream_id.clone(),
+            content,
+            annotations: Default::default(),
+        });
+    }
+}
+
+fn apply_offset(timestamp: Duration, offset_micros: i128) -> Duration {
+    let micros = (timestamp.as_micros() as i128 + offset_micros).max(0);
+    Duration::from_micros(micros as u64)
+}
+
+impl WorkflowStep for FailoverStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            self.handle_media(media, outputs);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            ACTIVE_SOURCE_DETAIL.to_string(),
+            self.active_source
+                .map_or("none".to_string(), |role| role.to_string()),
+        );
+
+        details.insert(
+            SWITCH_COUNT_DETAIL.to_string(),
+            self.switch_count.to_string(),
+        );
+
+        details
+    }
+}
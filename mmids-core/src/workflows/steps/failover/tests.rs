@@ -0,0 +1,374 @@
+use super::*;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::discontinuity::DISCONTINUITY_METADATA_KEY;
+use crate::workflows::metadata::MediaPayloadMetadataCollection;
+use crate::workflows::steps::test_utils::StepTestContext;
+use crate::workflows::stream_labels::label_notification;
+use crate::workflows::MediaType;
+use bytes::{Bytes, BytesMut};
+use std::iter;
+
+const PRIMARY_NAME: &str = "primary";
+const BACKUP_NAME: &str = "backup";
+const OUTPUT_NAME: &str = "output";
+
+fn definition(parameters: &[(&str, &str)]) -> WorkflowStepDefinition {
+    WorkflowStepDefinition {
+        step_type: WorkflowStepType("failover".to_string()),
+        parameters: parameters
+            .iter()
+            .map(|(key, value)| (key.to_string(), Some(value.to_string())))
+            .collect(),
+    }
+}
+
+fn create_context(parameters: &[(&str, &str)]) -> StepTestContext {
+    StepTestContext::new(
+        Box::new(FailoverStepGenerator::new()),
+        definition(parameters),
+    )
+    .expect("Failed to create step")
+}
+
+fn create_named_context() -> StepTestContext {
+    create_context(&[
+        (PRIMARY_STREAM, PRIMARY_NAME),
+        (BACKUP_STREAM, BACKUP_NAME),
+        (OUTPUT_STREAM_NAME, OUTPUT_NAME),
+        (TIMEOUT, "100"),
+        (RESTORE_DELAY, "100"),
+    ])
+}
+
+fn stream_id(name: &str) -> StreamId {
+    StreamId(Arc::new(format!("{}-id", name)))
+}
+
+fn new_stream(name: &str) -> MediaNotification {
+    MediaNotification {
+        stream_id: stream_id(name),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new(name.to_string()),
+        },
+        annotations: Default::default(),
+    }
+}
+
+fn video(name: &str, timestamp: u64, is_sequence_header: bool) -> MediaNotification {
+    MediaNotification {
+        stream_id: stream_id(name),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Video,
+            payload_type: Arc::new("test".to_string()),
+            timestamp: Duration::from_millis(timestamp),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data: Bytes::from(name.to_string()),
+            is_required_for_decoding: is_sequence_header,
+        },
+        annotations: Default::default(),
+    }
+}
+
+fn payload_of(media: &MediaNotification) -> (Duration, Bytes, bool) {
+    match &media.content {
+        MediaNotificationContent::MediaPayload {
+            timestamp,
+            data,
+            is_required_for_decoding,
+            ..
+        } => (*timestamp, data.clone(), *is_required_for_decoding),
+
+        content => panic!("Expected media payload, instead got {:?}", content),
+    }
+}
+
+fn assert_discontinuity(media: &MediaNotification, source: &str) {
+    match &media.content {
+        MediaNotificationContent::Metadata { data } => {
+            assert_eq!(
+                data.get(DISCONTINUITY_METADATA_KEY).map(|x| x.as_str()),
+                Some(source),
+                "Unexpected discontinuity source"
+            );
+        }
+
+        content => panic!("Expected discontinuity, instead got {:?}", content),
+    }
+}
+
+fn active_source(context: &StepTestContext) -> String {
+    context
+        .step
+        .get_state_details()
+        .get(ACTIVE_SOURCE_DETAIL)
+        .cloned()
+        .unwrap_or_default()
+}
+
+async fn sleep(millis: u64) {
+    tokio::time::sleep(Duration::from_millis(millis)).await;
+}
+
+#[test]
+fn missing_sources_returns_error() {
+    let definition = definition(&[(PRIMARY_STREAM, "a"), (OUTPUT_STREAM_NAME, "b")]);
+    let result = StepTestContext::new(Box::new(FailoverStepGenerator::new()), definition);
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn source_with_name_and_label_returns_error() {
+    let definition = definition(&[
+        (PRIMARY_STREAM, "a"),
+        (PRIMARY_LABEL, "a"),
+        (BACKUP_STREAM, "b"),
+        (OUTPUT_STREAM_NAME, "c"),
+    ]);
+
+    let result = StepTestContext::new(Box::new(FailoverStepGenerator::new()), definition);
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn unrelated_streams_passed_through() {
+    let mut context = create_named_context();
+
+    context.assert_media_passed_through(new_stream("other"));
+    context.assert_media_passed_through(video("other", 5, false));
+}
+
+#[test]
+fn primary_media_forwarded_as_output_stream() {
+    let mut context = create_named_context();
+    context.assert_media_not_passed_through(new_stream(PRIMARY_NAME));
+    context.assert_media_not_passed_through(new_stream(BACKUP_NAME));
+
+    context.execute_with_media(video(PRIMARY_NAME, 0, true));
+    assert_eq!(
+        context.media_outputs.len(),
+        2,
+        "Unexpected number of outputs"
+    );
+
+    let output_id = context.media_outputs[0].stream_id.clone();
+    assert_eq!(
+        context.media_outputs[0].content,
+        MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new(OUTPUT_NAME.to_string())
+        },
+        "Expected output stream to be announced"
+    );
+
+    assert_eq!(context.media_outputs[1].stream_id, output_id);
+    assert!(
+        payload_of(&context.media_outputs[1]).2,
+        "Expected sequence header"
+    );
+
+    context.assert_media_not_passed_through(video(BACKUP_NAME, 0, true));
+    context.assert_media_not_passed_through(video(BACKUP_NAME, 10, false));
+
+    context.execute_with_media(video(PRIMARY_NAME, 10, false));
+    assert_eq!(
+        context.media_outputs.len(),
+        1,
+        "Unexpected number of outputs"
+    );
+    assert_eq!(context.media_outputs[0].stream_id, output_id);
+    assert_eq!(
+        payload_of(&context.media_outputs[0]),
+        (Duration::from_millis(10), Bytes::from(PRIMARY_NAME), false)
+    );
+
+    assert_eq!(active_source(&context), "primary");
+}
+
+#[tokio::test]
+async fn switches_to_backup_when_primary_stops_and_back_once_primary_is_stable() {
+    let mut context = create_named_context();
+    context.execute_with_media(new_stream(PRIMARY_NAME));
+    context.execute_with_media(new_stream(BACKUP_NAME));
+    context.execute_with_media(video(PRIMARY_NAME, 1000, true));
+    let output_id = context.media_outputs[0].stream_id.clone();
+
+    context.execute_with_media(video(BACKUP_NAME, 5000, true));
+    context.execute_with_media(video(PRIMARY_NAME, 1100, false));
+
+    // Primary stops sending media
+    sleep(150).await;
+    context.execute_with_media(video(BACKUP_NAME, 5200, false));
+
+    assert_eq!(
+        context.media_outputs.len(),
+        3,
+        "Unexpected number of outputs"
+    );
+    assert!(
+        context
+            .media_outputs
+            .iter()
+            .all(|media| media.stream_id == output_id),
+        "Expected all media on the output stream"
+    );
+
+    assert_discontinuity(&context.media_outputs[0], "backup");
+
+    let (_, data, is_sequence_header) = payload_of(&context.media_outputs[1]);
+    assert_eq!(
+        data,
+        Bytes::from(BACKUP_NAME),
+        "Expected backup sequence header"
+    );
+    assert!(is_sequence_header, "Expected sequence header");
+
+    // The backup's timeline continues from the last primary timestamp
+    let (timestamp, data, _) = payload_of(&context.media_outputs[2]);
+    assert_eq!(data, Bytes::from(BACKUP_NAME), "Expected backup media");
+    assert_eq!(
+        timestamp,
+        Duration::from_millis(1100),
+        "Unexpected timestamp"
+    );
+    assert_eq!(active_source(&context), "backup");
+
+    // Primary comes back, but isn't switched to until it's been stable
+    context.assert_media_not_passed_through(video(PRIMARY_NAME, 1300, false));
+
+    sleep(40).await;
+    context.execute_with_media(video(BACKUP_NAME, 5240, false));
+    assert_eq!(
+        context.media_outputs.len(),
+        1,
+        "Expected backup to be forwarded"
+    );
+    context.assert_media_not_passed_through(video(PRIMARY_NAME, 1340, false));
+
+    sleep(40).await;
+    context.execute_with_media(video(BACKUP_NAME, 5280, false));
+    assert_eq!(
+        context.media_outputs.len(),
+        1,
+        "Expected backup to be forwarded"
+    );
+    let (last_backup_timestamp, _, _) = payload_of(&context.media_outputs[0]);
+    context.assert_media_not_passed_through(video(PRIMARY_NAME, 1380, false));
+
+    sleep(40).await;
+    context.execute_with_media(video(PRIMARY_NAME, 1420, false));
+
+    assert_eq!(
+        context.media_outputs.len(),
+        3,
+        "Unexpected number of outputs"
+    );
+    assert_discontinuity(&context.media_outputs[0], "primary");
+
+    let (_, data, is_sequence_header) = payload_of(&context.media_outputs[1]);
+    assert_eq!(
+        data,
+        Bytes::from(PRIMARY_NAME),
+        "Expected primary sequence header"
+    );
+    assert!(is_sequence_header, "Expected sequence header");
+
+    let (timestamp, data, _) = payload_of(&context.media_outputs[2]);
+    assert_eq!(data, Bytes::from(PRIMARY_NAME), "Expected primary media");
+    assert_eq!(
+        timestamp, last_backup_timestamp,
+        "Expected timeline to continue from the backup"
+    );
+
+    assert_eq!(active_source(&context), "primary");
+    context.assert_media_not_passed_through(video(BACKUP_NAME, 5320, false));
+}
+
+#[test]
+fn primary_disconnection_switches_to_backup_immediately() {
+    let mut context = create_named_context();
+    context.execute_with_media(new_stream(PRIMARY_NAME));
+    context.execute_with_media(new_stream(BACKUP_NAME));
+    context.execute_with_media(video(PRIMARY_NAME, 0, true));
+    context.execute_with_media(video(BACKUP_NAME, 0, true));
+
+    context.execute_with_media(MediaNotification {
+        stream_id: stream_id(PRIMARY_NAME),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    });
+
+    assert_eq!(
+        context.media_outputs.len(),
+        2,
+        "Unexpected number of outputs"
+    );
+    assert_discontinuity(&context.media_outputs[0], "backup");
+    assert_eq!(active_source(&context), "backup");
+}
+
+#[test]
+fn output_stream_ends_when_both_sources_disconnect() {
+    let mut context = create_named_context();
+    context.execute_with_media(new_stream(PRIMARY_NAME));
+    context.execute_with_media(video(PRIMARY_NAME, 0, true));
+    let output_id = context.media_outputs[0].stream_id.clone();
+
+    context.execute_with_media(MediaNotification {
+        stream_id: stream_id(PRIMARY_NAME),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    });
+
+    assert_eq!(
+        context.media_outputs.len(),
+        1,
+        "Unexpected number of outputs"
+    );
+    assert_eq!(
+        context.media_outputs[0],
+        MediaNotification {
+            stream_id: output_id,
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        }
+    );
+
+    assert_eq!(active_source(&context), "none");
+}
+
+#[test]
+fn sources_can_be_matched_by_label() {
+    let mut context = create_context(&[
+        (PRIMARY_LABEL, "main"),
+        (BACKUP_LABEL, "spare"),
+        (OUTPUT_STREAM_NAME, OUTPUT_NAME),
+    ]);
+
+    // Stream's purpose isn't known until it's labeled
+    context.assert_media_passed_through(new_stream("abc"));
+
+    context.execute_with_media(label_notification(
+        stream_id("abc"),
+        Arc::new("main".to_string()),
+    ));
+
+    assert_eq!(
+        context.media_outputs,
+        vec![MediaNotification {
+            stream_id: stream_id("abc"),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        }],
+        "Expected adopted stream to be disconnected for later steps"
+    );
+
+    context.execute_with_media(video("abc", 0, true));
+    assert_eq!(
+        context.media_outputs.len(),
+        2,
+        "Unexpected number of outputs"
+    );
+    assert_ne!(context.media_outputs[1].stream_id, stream_id("abc"));
+    assert_eq!(active_source(&context), "primary");
+}
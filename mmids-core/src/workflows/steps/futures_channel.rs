@@ -3,9 +3,15 @@
 //! with minimal allocations.
 
 use crate::workflows::definitions::WorkflowStepId;
+use crate::workflows::steps::side_channel::SideChannelMessage;
 use crate::workflows::steps::StepFutureResult;
-use crate::workflows::MediaNotification;
+use crate::workflows::watchers::WorkflowWatchers;
+use crate::workflows::{MediaNotification, MediaNotificationContent};
+use crate::StreamId;
+use std::any::Any;
+use std::collections::HashSet;
 use std::future::Future;
+use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio_util::sync::CancellationToken;
 
@@ -15,6 +21,8 @@ use tokio_util::sync::CancellationToken;
 pub struct WorkflowStepFuturesChannel {
     step_id: WorkflowStepId,
     sender: UnboundedSender<FuturesChannelResult>,
+    side_channel: Option<UnboundedSender<SideChannelMessage>>,
+    workflow_watchers: Option<WorkflowWatchers>,
 }
 
 /// The type of information that's returned to the workflow upon a future's completion
@@ -35,9 +43,101 @@ pub enum FuturesChannelInnerResult {
     Media(MediaNotification),
 }
 
+/// Handle that a task spawned via `WorkflowStepFuturesChannel::spawn_media_source()` uses to send
+/// media into the workflow. Media emitted through the source goes to the next step in the workflow.
+///
+/// The source keeps track of which streams it has announced with a `NewIncomingStream`
+/// notification, so any that haven't been disconnected when the task ends can be disconnected
+/// automatically.
+#[derive(Clone)]
+pub struct MediaSource {
+    channel: WorkflowStepFuturesChannel,
+    active_streams: Arc<Mutex<HashSet<StreamId>>>,
+}
+
+impl MediaSource {
+    /// Sends the media notification to the next step in the workflow. If the workflow has gone
+    /// away the media is dropped, and the task emitting it will be stopped shortly after.
+    pub fn emit(&self, media: MediaNotification) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { .. } => {
+                self.active_streams
+                    .lock()
+                    .unwrap()
+                    .insert(media.stream_id.clone());
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.active_streams.lock().unwrap().remove(&media.stream_id);
+            }
+
+            _ => (),
+        }
+
+        let _ = self.channel.send(FuturesChannelInnerResult::Media(media));
+    }
+
+    /// Sends a `StreamDisconnected` notification for every stream that was announced but not yet
+    /// disconnected
+    fn disconnect_active_streams(&self) {
+        let stream_ids = self
+            .active_streams
+            .lock()
+            .unwrap()
+            .drain()
+            .collect::<Vec<_>>();
+
+        for stream_id in stream_ids {
+            let _ = self
+                .channel
+                .send(FuturesChannelInnerResult::Media(MediaNotification {
+                    stream_id,
+                    content: MediaNotificationContent::StreamDisconnected,
+                    annotations: Default::default(),
+                }));
+        }
+    }
+}
+
 impl WorkflowStepFuturesChannel {
     pub fn new(step_id: WorkflowStepId, sender: UnboundedSender<FuturesChannelResult>) -> Self {
-        WorkflowStepFuturesChannel { step_id, sender }
+        WorkflowStepFuturesChannel {
+            step_id,
+            sender,
+            side_channel: None,
+            workflow_watchers: None,
+        }
+    }
+
+    /// Allows the workflow step to send out-of-band data to the specified side channel
+    pub fn with_side_channel(mut self, side_channel: UnboundedSender<SideChannelMessage>) -> Self {
+        self.side_channel = Some(side_channel);
+        self
+    }
+
+    /// Shares the watchers of the workflow the step is part of with the step
+    pub fn with_workflow_watchers(mut self, workflow_watchers: WorkflowWatchers) -> Self {
+        self.workflow_watchers = Some(workflow_watchers);
+        self
+    }
+
+    /// The watchers of the workflow the step is part of. Output steps use these to count their
+    /// watchers against the workflow's watcher limit. This is `None` when the step was not created
+    /// by a workflow runner (e.g. during validation).
+    pub fn workflow_watchers(&self) -> Option<&WorkflowWatchers> {
+        self.workflow_watchers.as_ref()
+    }
+
+    /// Sends out-of-band data to the side channel consumer, tagged with the workflow step's id.
+    /// The data is dropped if no side channel has been provided or its receiver has been closed,
+    /// as side channel data is never required for the step to function.
+    pub fn send_side_channel_data(&self, data: impl Any + Send) {
+        if let Some(side_channel) = &self.side_channel {
+            let _ = side_channel.send(SideChannelMessage {
+                step_id: self.step_id,
+                data: Box::new(data),
+            });
+        }
     }
 
     /// Sends the workflow step's future result over the channel. Returns an error if the channel
@@ -250,6 +350,52 @@ impl WorkflowStepFuturesChannel {
         });
     }
 
+    /// Spawns a task that produces media for the workflow, such as a source step reading from an
+    /// external connection. The task is given a `MediaSource` it can emit media notifications
+    /// through.
+    ///
+    /// When the task completes its result is sent back to the workflow step, and a
+    /// `StreamDisconnected` notification is sent for each stream the task announced but did not
+    /// disconnect itself. The same disconnections are sent if the task is cancelled through the
+    /// returned token. If the workflow goes away the task is stopped with nothing sent.
+    pub fn spawn_media_source<Task, TaskFuture, FutureResult>(
+        &self,
+        task: Task,
+    ) -> CancellationToken
+    where
+        Task: FnOnce(MediaSource) -> TaskFuture,
+        TaskFuture: Future<Output = FutureResult> + Send + 'static,
+        FutureResult: StepFutureResult + Send + 'static,
+    {
+        let source = MediaSource {
+            channel: self.clone(),
+            active_streams: Arc::new(Mutex::new(HashSet::new())),
+        };
+
+        let cancellation_token = CancellationToken::new();
+        let future = task(source.clone());
+        let channel = self.clone();
+        let token = cancellation_token.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                result = future => {
+                    source.disconnect_active_streams();
+                    let _ = channel.send(FuturesChannelInnerResult::Generic(Box::new(result)));
+                }
+
+                _ = token.cancelled() => {
+                    source.disconnect_active_streams();
+                }
+
+                _ = channel.closed() => {
+                    // Nothing to send to, so just stop the task
+                }
+            }
+        });
+
+        cancellation_token
+    }
+
     /// Helper function for workflow steps to easily send a message upon future completion.
     ///
     /// This only sends a generic `StepFutureResult` value.
@@ -271,3 +417,177 @@ impl WorkflowStepFuturesChannel {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::mpsc::unbounded_channel;
+    use tokio::sync::oneshot;
+    use tokio::time::timeout;
+
+    struct SourceEnded;
+    impl StepFutureResult for SourceEnded {}
+
+    fn stream_id() -> StreamId {
+        StreamId(Arc::new("abc".to_string()))
+    }
+
+    fn new_stream() -> MediaNotification {
+        MediaNotification {
+            stream_id: stream_id(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        }
+    }
+
+    async fn next_result(
+        receiver: &mut UnboundedReceiver<FuturesChannelResult>,
+    ) -> FuturesChannelInnerResult {
+        match timeout(Duration::from_millis(100), receiver.recv()).await {
+            Ok(Some(result)) => result.result,
+            Ok(None) => panic!("Futures channel closed"),
+            Err(_) => panic!("No result received within timeout period"),
+        }
+    }
+
+    async fn expect_media(
+        receiver: &mut UnboundedReceiver<FuturesChannelResult>,
+    ) -> MediaNotification {
+        match next_result(receiver).await {
+            FuturesChannelInnerResult::Media(media) => media,
+            FuturesChannelInnerResult::Generic(_) => panic!("Expected media, got generic result"),
+        }
+    }
+
+    #[tokio::test]
+    async fn media_source_emits_media_then_disconnects_streams_when_task_ends() {
+        let (sender, mut receiver) = unbounded_channel();
+        let channel = WorkflowStepFuturesChannel::new(WorkflowStepId(1), sender);
+
+        channel.spawn_media_source(|source| async move {
+            source.emit(new_stream());
+            source.emit(MediaNotification {
+                stream_id: stream_id(),
+                content: MediaNotificationContent::Metadata {
+                    data: Default::default(),
+                },
+                annotations: Default::default(),
+            });
+
+            SourceEnded
+        });
+
+        let media = expect_media(&mut receiver).await;
+        assert_eq!(
+            media,
+            new_stream(),
+            "Expected new stream notification first"
+        );
+
+        let media = expect_media(&mut receiver).await;
+        assert!(
+            matches!(media.content, MediaNotificationContent::Metadata { .. }),
+            "Expected metadata notification, got {:?}",
+            media.content
+        );
+
+        let media = expect_media(&mut receiver).await;
+        assert_eq!(media.stream_id, stream_id(), "Unexpected stream id");
+        assert_eq!(
+            media.content,
+            MediaNotificationContent::StreamDisconnected,
+            "Expected stream to be disconnected"
+        );
+
+        match next_result(&mut receiver).await {
+            FuturesChannelInnerResult::Generic(result) => {
+                assert!(
+                    result.downcast::<SourceEnded>().is_ok(),
+                    "Unexpected generic result type"
+                );
+            }
+
+            FuturesChannelInnerResult::Media(media) => panic!("Unexpected media: {:?}", media),
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_disconnected_by_task_are_not_disconnected_again() {
+        let (sender, mut receiver) = unbounded_channel();
+        let channel = WorkflowStepFuturesChannel::new(WorkflowStepId(1), sender);
+
+        channel.spawn_media_source(|source| async move {
+            source.emit(new_stream());
+            source.emit(MediaNotification {
+                stream_id: stream_id(),
+                content: MediaNotificationContent::StreamDisconnected,
+                annotations: Default::default(),
+            });
+
+            SourceEnded
+        });
+
+        expect_media(&mut receiver).await;
+        expect_media(&mut receiver).await;
+
+        match next_result(&mut receiver).await {
+            FuturesChannelInnerResult::Generic(_) => (),
+            FuturesChannelInnerResult::Media(media) => panic!("Unexpected media: {:?}", media),
+        }
+    }
+
+    #[tokio::test]
+    async fn cancelling_media_source_stops_task_and_disconnects_streams() {
+        let (sender, mut receiver) = unbounded_channel();
+        let channel = WorkflowStepFuturesChannel::new(WorkflowStepId(1), sender);
+        let (task_sender, task_receiver) = oneshot::channel::<()>();
+
+        let token = channel.spawn_media_source(|source| async move {
+            let _task_alive = task_sender;
+            source.emit(new_stream());
+            futures::future::pending::<()>().await;
+
+            SourceEnded
+        });
+
+        expect_media(&mut receiver).await;
+        token.cancel();
+
+        let media = expect_media(&mut receiver).await;
+        assert_eq!(
+            media.content,
+            MediaNotificationContent::StreamDisconnected,
+            "Expected stream to be disconnected"
+        );
+
+        let result = timeout(Duration::from_millis(100), task_receiver).await;
+        assert!(matches!(result, Ok(Err(_))), "Expected task to be dropped");
+
+        let result = timeout(Duration::from_millis(10), receiver.recv()).await;
+        assert!(result.is_err(), "Expected no result after cancellation");
+    }
+
+    #[tokio::test]
+    async fn media_source_stopped_when_channel_closed() {
+        let (sender, receiver) = unbounded_channel();
+        let channel = WorkflowStepFuturesChannel::new(WorkflowStepId(1), sender);
+        let (task_sender, task_receiver) = oneshot::channel::<()>();
+
+        channel.spawn_media_source(|_source| async move {
+            let _task_alive = task_sender;
+            futures::future::pending::<()>().await;
+
+            SourceEnded
+        });
+
+        drop(channel);
+        drop(receiver);
+
+        let result = timeout(Duration::from_millis(100), task_receiver).await;
+        assert!(matches!(result, Ok(Err(_))), "Expected task to be dropped");
+    }
+}
@@ -0,0 +1,482 @@
+//! The GOP segmenter step writes each group of pictures (a video keyframe and every media payload
+//! up until the next keyframe) of a stream to its own file. Every file starts with the media
+//! required to decode the stream (e.g. sequence headers), followed by the keyframe that started
+//! the GOP, so each file can be decoded independently of all others.
+//!
+//! Segments are written in the session file format used by the `session_record` step, and are
+//! named sequentially as `<stream name>-<segment number>.mmsr` in the configured directory. Any
+//! media received for a stream before its first keyframe is not written. All media notifications
+//! are passed to subsequent steps untouched.
+//!
+//! Segment files are written asynchronously, so slow storage never blocks media from flowing
+//! through the workflow. Instead, writes queue up to the configured `max_queued_writes` (1000 by
+//! default) across all segments. Once the queue is full, the rest of the current GOP of a stream
+//! is dropped from its segment, which keeps the segment decodable up to that point. If a segment
+//! can't be written at all (e.g. the storage is unavailable), the failure is logged and the next
+//! segment tries again with a new file.
+//!
+//! When an `s3_bucket` is configured, each segment is also uploaded to that bucket once its file
+//! has been written, under the key `<s3_key_prefix><file name>`. The key prefix may contain a
+//! `{stream_name}` placeholder and defaults to `{stream_name}/`. The `s3_endpoint` (for S3
+//! compatible stores), `s3_region`, `s3_access_key_id`, and `s3_secret_access_key` parameters
+//! configure how the bucket is reached, with credentials falling back to the standard AWS
+//! environment variables. Uploads happen in the background, and at most `max_queued_uploads` (10
+//! by default) are in progress at once. Segments completed while that many uploads are in
+//! progress are not uploaded. A failed upload is logged, and the object is deleted so no partial
+//! segment is left behind.
+//!
+//! Once a segment's file has been fully written, a `CompletedGopSegment` is sent over the step's
+//! side channel, so external consumers can pick up segments as they become available.
+
+#[cfg(test)]
+mod tests;
+mod upload;
+
+pub use upload::{
+    MAX_QUEUED_UPLOADS, S3_ACCESS_KEY_ID, S3_BUCKET, S3_ENDPOINT, S3_KEY_PREFIX, S3_REGION,
+    S3_SECRET_ACCESS_KEY,
+};
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::metadata::{MetadataKey, MetadataValue};
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::gop_segmenter::upload::{SegmentUploader, UploadSetupError};
+use crate::workflows::steps::session_record::{format, run_tracked_writer};
+use crate::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use crate::StreamId;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tracing::{error, info, warn};
+
+pub const PATH: &str = "path";
+pub const MAX_QUEUED_WRITES: &str = "max_queued_writes";
+
+const DEFAULT_MAX_QUEUED_WRITES: usize = 1000;
+
+const SEGMENT_COUNT_DETAIL: &str = "segment_count";
+const CURRENT_SEGMENT_DETAIL: &str = "current_segment";
+const WRITE_QUEUE_DEPTH_DETAIL: &str = "write_queue_depth";
+const DROPPED_WRITES_DETAIL: &str = "dropped_writes";
+const FAILED_SEGMENTS_DETAIL: &str = "failed_segments";
+const PENDING_UPLOADS_DETAIL: &str = "pending_uploads";
+const UPLOADED_SEGMENTS_DETAIL: &str = "uploaded_segments";
+const FAILED_UPLOADS_DETAIL: &str = "failed_uploads";
+const DROPPED_UPLOADS_DETAIL: &str = "dropped_uploads";
+
+/// Generates new instances of the GOP segmenter workflow step
+pub struct GopSegmenterStepGenerator {
+    is_keyframe_metadata_key: MetadataKey,
+}
+
+/// Sent over the step's side channel when a segment's file has been fully written
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompletedGopSegment {
+    pub stream_id: StreamId,
+    pub stream_name: Arc<String>,
+    pub path: PathBuf,
+}
+
+struct Segment {
+    path: PathBuf,
+    started_at: Duration,
+    last_offset: Duration,
+    writer: UnboundedSender<format::Record>,
+    is_truncated: bool,
+}
+
+/// Bounds how many records can be waiting to be written across all segments
+struct WriteQueue {
+    depth: Arc<AtomicUsize>,
+    max_depth: usize,
+    dropped_writes: u64,
+}
+
+struct StreamState {
+    stream_name: Arc<String>,
+    required_media: Vec<MediaNotification>,
+    current_segment: Option<Segment>,
+}
+
+struct GopSegmenterStep {
+    directory: PathBuf,
+    is_keyframe_metadata_key: MetadataKey,
+    streams: HashMap<StreamId, StreamState>,
+    write_queue: WriteQueue,
+    uploader: Option<SegmentUploader>,
+    segment_count: u64,
+    failed_segments: u64,
+    current_segment_path: Option<PathBuf>,
+}
+
+enum FutureResult {
+    WriterFinished(CompletedGopSegment),
+    WriterFailed(PathBuf, String),
+    UploadFinished,
+    UploadFailed(String, String),
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error("No {} parameter specified", PATH)]
+    NoPathSpecified,
+
+    #[error("Failed to create the segment directory '{0}': {1}")]
+    DirectoryCreationFailed(String, std::io::Error),
+
+    #[error(
+        "Invalid {} value of '{0}', must be a number greater than zero",
+        MAX_QUEUED_WRITES
+    )]
+    InvalidMaxQueuedWrites(String),
+
+    #[error("Invalid object storage configuration: {0}")]
+    InvalidUploadConfiguration(#[from] UploadSetupError),
+}
+
+impl GopSegmenterStepGenerator {
+    pub fn new(is_keyframe_metadata_key: MetadataKey) -> Self {
+        GopSegmenterStepGenerator {
+            is_keyframe_metadata_key,
+        }
+    }
+}
+
+impl StepGenerator for GopSegmenterStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let directory = match definition.parameters.get(PATH) {
+            Some(Some(path)) => path.clone(),
+            _ => return Err(Box::new(StepStartupError::NoPathSpecified)),
+        };
+
+        let max_queued_writes = match definition.parameters.get(MAX_QUEUED_WRITES) {
+            Some(Some(value)) => match value.parse::<usize>() {
+                Ok(max) if max > 0 => max,
+                _ => {
+                    return Err(Box::new(StepStartupError::InvalidMaxQueuedWrites(
+                        value.clone(),
+                    )))
+                }
+            },
+
+            _ => DEFAULT_MAX_QUEUED_WRITES,
+        };
+
+        let uploader = match SegmentUploader::from_parameters(&definition.parameters) {
+            Ok(uploader) => uploader,
+            Err(error) => return Err(Box::new(StepStartupError::from(error))),
+        };
+
+        if let Err(error) = std::fs::create_dir_all(&directory) {
+            return Err(Box::new(StepStartupError::DirectoryCreationFailed(
+                directory, error,
+            )));
+        }
+
+        let step = GopSegmenterStep {
+            directory: PathBuf::from(directory),
+            is_keyframe_metadata_key: self.is_keyframe_metadata_key,
+            streams: HashMap::new(),
+            write_queue: WriteQueue {
+                depth: Arc::new(AtomicUsize::new(0)),
+                max_depth: max_queued_writes,
+                dropped_writes: 0,
+            },
+            uploader,
+            segment_count: 0,
+            failed_segments: 0,
+            current_segment_path: None,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl GopSegmenterStep {
+    fn handle_media(
+        &mut self,
+        media: &MediaNotification,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                self.streams.insert(
+                    media.stream_id.clone(),
+                    StreamState {
+                        stream_name: stream_name.clone(),
+                        required_media: vec![media.clone()],
+                        current_segment: None,
+                    },
+                );
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                // Dropping the stream's state closes any open segment
+                self.streams.remove(&media.stream_id);
+            }
+
+            MediaNotificationContent::Metadata { .. } => {
+                if let Some(stream) = self.streams.get_mut(&media.stream_id) {
+                    stream.required_media.retain(|existing| {
+                        !matches!(existing.content, MediaNotificationContent::Metadata { .. })
+                    });
+
+                    stream.required_media.push(media.clone());
+
+                    if let Some(segment) = &mut stream.current_segment {
+                        let record = format::encode_record(segment.last_offset, media);
+                        self.write_queue.write(segment, record);
+                    }
+                }
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type,
+                timestamp,
+                metadata,
+                is_required_for_decoding,
+                ..
+            } => {
+                let is_keyframe_metadata_key = self.is_keyframe_metadata_key;
+                let stream = match self.streams.get_mut(&media.stream_id) {
+                    Some(stream) => stream,
+                    None => return,
+                };
+
+                if *is_required_for_decoding {
+                    // Only the latest sequence header for each type of media is relevant
+                    stream
+                        .required_media
+                        .retain(|existing| match &existing.content {
+                            MediaNotificationContent::MediaPayload {
+                                media_type: existing_type,
+                                ..
+                            } => existing_type != media_type,
+                            _ => true,
+                        });
+
+                    stream.required_media.push(media.clone());
+                }
+
+                let is_keyframe = *media_type == MediaType::Video
+                    && metadata
+                        .iter()
+                        .filter(|m| m.key() == is_keyframe_metadata_key)
+                        .filter_map(|m| match m.value() {
+                            MetadataValue::Bool(val) => Some(val),
+                            _ => None,
+                        })
+                        .next()
+                        .unwrap_or_default();
+
+                if is_keyframe && !*is_required_for_decoding {
+                    let segment_path = self.directory.join(format!(
+                        "{}-{:06}.mmsr",
+                        stream.stream_name, self.segment_count
+                    ));
+
+                    info!(
+                        stream_id = %media.stream_id.0,
+                        "Starting GOP segment {}", segment_path.display()
+                    );
+
+                    let (sender, receiver) = unbounded_channel();
+                    let writer_path = Arc::new(segment_path.to_string_lossy().to_string());
+                    let queue_depth = self.write_queue.depth.clone();
+                    let completed_segment = CompletedGopSegment {
+                        stream_id: media.stream_id.clone(),
+                        stream_name: stream.stream_name.clone(),
+                        path: segment_path.clone(),
+                    };
+
+                    futures_channel.send_on_generic_future_completion(async move {
+                        match run_tracked_writer(writer_path, receiver, queue_depth).await {
+                            Ok(()) => FutureResult::WriterFinished(completed_segment),
+                            Err(error) => FutureResult::WriterFailed(completed_segment.path, error),
+                        }
+                    });
+
+                    let mut segment = Segment {
+                        path: segment_path.clone(),
+                        started_at: *timestamp,
+                        last_offset: Duration::new(0, 0),
+                        writer: sender,
+                        is_truncated: false,
+                    };
+
+                    for required_media in &stream.required_media {
+                        let record = format::encode_record(Duration::new(0, 0), required_media);
+                        self.write_queue.write(&mut segment, record);
+                    }
+
+                    // Replacing the previous segment drops its writer, which finishes its file
+                    stream.current_segment = Some(segment);
+
+                    self.segment_count += 1;
+                    self.current_segment_path = Some(segment_path);
+                }
+
+                if let Some(segment) = &mut stream.current_segment {
+                    let offset = timestamp.saturating_sub(segment.started_at);
+                    self.write_queue
+                        .write(segment, format::encode_record(offset, media));
+
+                    segment.last_offset = offset;
+                }
+            }
+        }
+    }
+}
+
+impl WriteQueue {
+    /// Queues the record to be written to the segment's file. If too many records are already
+    /// waiting to be written, the record is dropped along with the rest of the segment, as a
+    /// segment with a gap in it can't be decoded past that gap.
+    fn write(&mut self, segment: &mut Segment, record: format::Record) {
+        if segment.is_truncated {
+            self.dropped_writes += 1;
+            return;
+        }
+
+        if self.depth.load(Ordering::Relaxed) >= self.max_depth {
+            warn!(
+                "Segment write queue is full, dropping the rest of segment {}",
+                segment.path.display()
+            );
+
+            segment.is_truncated = true;
+            self.dropped_writes += 1;
+            return;
+        }
+
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        if segment.writer.send(record).is_err() {
+            // The writer already stopped due to an error, which gets reported separately
+            self.depth.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl WorkflowStep for GopSegmenterStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            let future_result = match notification.downcast::<FutureResult>() {
+                Ok(x) => *x,
+                Err(_) => {
+                    error!("GOP segmenter step received a notification that is not a known type");
+                    return StepStatus::Error {
+                        message: "Received future result of unknown type".to_string(),
+                    };
+                }
+            };
+
+            match future_result {
+                FutureResult::WriterFinished(segment) => {
+                    if let Some(uploader) = &mut self.uploader {
+                        uploader.upload(&segment, &futures_channel);
+                    }
+
+                    futures_channel.send_side_channel_data(segment);
+                }
+
+                FutureResult::UploadFinished => {
+                    if let Some(uploader) = &mut self.uploader {
+                        uploader.pending_uploads -= 1;
+                        uploader.uploaded_segments += 1;
+                    }
+                }
+
+                FutureResult::UploadFailed(key, message) => {
+                    error!("Failed to upload GOP segment to '{}': {}", key, message);
+                    if let Some(uploader) = &mut self.uploader {
+                        uploader.pending_uploads -= 1;
+                        uploader.failed_uploads += 1;
+                    }
+                }
+
+                FutureResult::WriterFailed(path, message) => {
+                    // Storage may only be temporarily unavailable, so the next segment will try
+                    // again with a new file.
+                    error!(
+                        "Failed to write GOP segment {}: {}",
+                        path.display(),
+                        message
+                    );
+
+                    self.failed_segments += 1;
+                }
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            self.handle_media(&media, &futures_channel);
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            SEGMENT_COUNT_DETAIL.to_string(),
+            self.segment_count.to_string(),
+        );
+
+        details.insert(
+            WRITE_QUEUE_DEPTH_DETAIL.to_string(),
+            self.write_queue.depth.load(Ordering::Relaxed).to_string(),
+        );
+
+        details.insert(
+            DROPPED_WRITES_DETAIL.to_string(),
+            self.write_queue.dropped_writes.to_string(),
+        );
+
+        details.insert(
+            FAILED_SEGMENTS_DETAIL.to_string(),
+            self.failed_segments.to_string(),
+        );
+
+        if let Some(uploader) = &self.uploader {
+            let counts = [
+                (PENDING_UPLOADS_DETAIL, uploader.pending_uploads as u64),
+                (UPLOADED_SEGMENTS_DETAIL, uploader.uploaded_segments),
+                (FAILED_UPLOADS_DETAIL, uploader.failed_uploads),
+                (DROPPED_UPLOADS_DETAIL, uploader.dropped_uploads),
+            ];
+
+            for (name, count) in counts {
+                details.insert(name.to_string(), count.to_string());
+            }
+        }
+
+        if let Some(path) = &self.current_segment_path {
+            details.insert(
+                CURRENT_SEGMENT_DETAIL.to_string(),
+                path.to_string_lossy().to_string(),
+            );
+        }
+
+        details
+    }
+}
@@ -0,0 +1,562 @@
+use super::*;
+use crate::codecs::{AUDIO_CODEC_AAC_RAW, VIDEO_CODEC_H264_AVC};
+use crate::object_storage::test_server::MockS3Server;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::common_metadata::get_is_keyframe_metadata_key;
+use crate::workflows::metadata::{MediaPayloadMetadataCollection, MetadataEntry, MetadataKeyMap};
+use crate::workflows::steps::session_record::format::SessionRecord;
+use crate::workflows::steps::test_utils::StepTestContext;
+use bytes::{Bytes, BytesMut};
+use std::iter;
+use tokio::fs::File;
+use tokio::io::BufReader;
+
+struct TestContext {
+    step_context: StepTestContext,
+    directory: PathBuf,
+    is_keyframe_metadata_key: MetadataKey,
+    stream_id: StreamId,
+}
+
+impl TestContext {
+    fn new() -> Self {
+        Self::with_parameters(&[])
+    }
+
+    fn with_parameters(parameters: &[(&str, &str)]) -> Self {
+        let directory = std::env::temp_dir().join(format!("mmids-gop-{}", uuid::Uuid::new_v4()));
+        let mut metadata_map = MetadataKeyMap::new();
+        let is_keyframe_metadata_key = get_is_keyframe_metadata_key(&mut metadata_map);
+
+        let mut definition = WorkflowStepDefinition {
+            step_type: WorkflowStepType("gop_segmenter".to_string()),
+            parameters: HashMap::new(),
+        };
+
+        definition.parameters.insert(
+            PATH.to_string(),
+            Some(directory.to_string_lossy().to_string()),
+        );
+
+        for (name, value) in parameters {
+            definition
+                .parameters
+                .insert(name.to_string(), Some(value.to_string()));
+        }
+
+        let generator = GopSegmenterStepGenerator::new(is_keyframe_metadata_key);
+        let step_context =
+            StepTestContext::new(Box::new(generator), definition).expect("Failed to create step");
+
+        TestContext {
+            step_context,
+            directory,
+            is_keyframe_metadata_key,
+            stream_id: StreamId(Arc::new("abc".to_string())),
+        }
+    }
+
+    fn video(
+        &self,
+        timestamp: u64,
+        is_keyframe: bool,
+        is_sequence_header: bool,
+    ) -> MediaNotification {
+        let mut buffer = BytesMut::new();
+        let entry = MetadataEntry::new(
+            self.is_keyframe_metadata_key,
+            MetadataValue::Bool(is_keyframe),
+            &mut buffer,
+        )
+        .unwrap();
+
+        MediaNotification {
+            stream_id: self.stream_id.clone(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type: VIDEO_CODEC_H264_AVC.clone(),
+                timestamp: Duration::from_millis(timestamp),
+                metadata: MediaPayloadMetadataCollection::new(iter::once(entry), &mut buffer),
+                data: Bytes::from(vec![timestamp as u8]),
+                is_required_for_decoding: is_sequence_header,
+            },
+            annotations: Default::default(),
+        }
+    }
+
+    fn audio(&self, timestamp: u64) -> MediaNotification {
+        MediaNotification {
+            stream_id: self.stream_id.clone(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Audio,
+                payload_type: AUDIO_CODEC_AAC_RAW.clone(),
+                timestamp: Duration::from_millis(timestamp),
+                metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+                data: Bytes::from(vec![timestamp as u8]),
+                is_required_for_decoding: false,
+            },
+            annotations: Default::default(),
+        }
+    }
+
+    fn start_stream(&mut self) {
+        self.step_context.execute_with_media(MediaNotification {
+            stream_id: self.stream_id.clone(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        });
+
+        let sequence_header = self.video(0, true, true);
+        self.step_context.execute_with_media(sequence_header);
+    }
+
+    fn segment_path(&self, index: u64) -> PathBuf {
+        self.directory.join(format!("def-{:06}.mmsr", index))
+    }
+
+    async fn read_segment(&self, index: u64) -> Vec<SessionRecord> {
+        let file = File::open(self.segment_path(index))
+            .await
+            .expect("Failed to open segment");
+
+        let mut reader = BufReader::new(file);
+        format::read_header(&mut reader)
+            .await
+            .expect("Failed to read header");
+
+        let mut records = Vec::new();
+        while let Some(record) = format::read_record(&mut reader)
+            .await
+            .expect("Failed to read record")
+        {
+            records.push(record);
+        }
+
+        records
+    }
+}
+
+impl Drop for TestContext {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.directory);
+    }
+}
+
+#[tokio::test]
+async fn missing_path_returns_error() {
+    let definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("gop_segmenter".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    let mut metadata_map = MetadataKeyMap::new();
+    let is_keyframe_metadata_key = get_is_keyframe_metadata_key(&mut metadata_map);
+    let generator = GopSegmenterStepGenerator::new(is_keyframe_metadata_key);
+
+    let result = StepTestContext::new(Box::new(generator), definition);
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[tokio::test]
+async fn invalid_max_queued_writes_returns_error() {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("gop_segmenter".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    let directory = std::env::temp_dir().join(format!("mmids-gop-{}", uuid::Uuid::new_v4()));
+    definition.parameters.insert(
+        PATH.to_string(),
+        Some(directory.to_string_lossy().to_string()),
+    );
+
+    definition
+        .parameters
+        .insert(MAX_QUEUED_WRITES.to_string(), Some("0".to_string()));
+
+    let mut metadata_map = MetadataKeyMap::new();
+    let is_keyframe_metadata_key = get_is_keyframe_metadata_key(&mut metadata_map);
+    let generator = GopSegmenterStepGenerator::new(is_keyframe_metadata_key);
+
+    let result = StepTestContext::new(Box::new(generator), definition);
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[tokio::test]
+async fn media_is_passed_through() {
+    let mut context = TestContext::new();
+    context.start_stream();
+
+    let media = context.video(10, true, false);
+    context.step_context.assert_media_passed_through(media);
+}
+
+#[tokio::test]
+async fn each_gop_is_written_to_its_own_segment() {
+    let mut context = TestContext::new();
+    context.start_stream();
+
+    let media = vec![
+        context.video(5, false, false), // before the first keyframe, so not written
+        context.video(10, true, false),
+        context.audio(15),
+        context.video(20, false, false),
+        context.video(30, true, false),
+        context.video(40, false, false),
+        context.video(50, true, false),
+    ];
+
+    for media in media {
+        context.step_context.execute_with_media(media);
+    }
+
+    // Disconnect the stream so the last segment gets closed
+    context.step_context.execute_with_media(MediaNotification {
+        stream_id: context.stream_id.clone(),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    });
+
+    context.step_context.execute_pending_futures().await;
+
+    assert!(
+        !context.segment_path(3).exists(),
+        "Expected only three segments to be written"
+    );
+
+    for index in 0..3 {
+        let records = context.read_segment(index).await;
+        assert!(records.len() >= 3, "Segment {} had too few records", index);
+
+        match &records[0].media.content {
+            MediaNotificationContent::NewIncomingStream { .. } => (),
+            content => panic!("Segment {} started with {:?}", index, content),
+        }
+
+        assert_eq!(
+            records[1].media,
+            context.video(0, true, true),
+            "Segment {} did not contain the sequence header",
+            index
+        );
+
+        match &records[2].media.content {
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                is_required_for_decoding: false,
+                metadata,
+                ..
+            } => {
+                let is_keyframe = metadata
+                    .iter()
+                    .any(|entry| entry.value() == MetadataValue::Bool(true));
+
+                assert!(
+                    is_keyframe,
+                    "Segment {} did not start with a keyframe",
+                    index
+                );
+            }
+
+            content => panic!(
+                "Segment {} did not start with a keyframe: {:?}",
+                index, content
+            ),
+        }
+
+        assert_eq!(
+            records[2].offset,
+            Duration::new(0, 0),
+            "Unexpected keyframe offset"
+        );
+    }
+
+    let first_segment = context.read_segment(0).await;
+    let expected = vec![
+        context.video(10, true, false),
+        context.audio(15),
+        context.video(20, false, false),
+    ];
+
+    let actual = first_segment
+        .into_iter()
+        .skip(2)
+        .map(|record| record.media)
+        .collect::<Vec<_>>();
+
+    assert_eq!(actual, expected, "Unexpected media in the first segment");
+}
+
+#[tokio::test]
+async fn state_details_contain_segment_count_and_current_segment() {
+    let mut context = TestContext::new();
+    context.start_stream();
+
+    let keyframe = context.video(10, true, false);
+    context.step_context.execute_with_media(keyframe);
+    let keyframe = context.video(20, true, false);
+    context.step_context.execute_with_media(keyframe);
+
+    let details = context.step_context.step.get_state_details();
+    assert_eq!(
+        details.get(SEGMENT_COUNT_DETAIL),
+        Some(&"2".to_string()),
+        "Unexpected segment count"
+    );
+
+    assert_eq!(
+        details.get(CURRENT_SEGMENT_DETAIL),
+        Some(&context.segment_path(1).to_string_lossy().to_string()),
+        "Unexpected current segment"
+    );
+}
+
+#[tokio::test]
+async fn completed_segments_are_sent_over_side_channel() {
+    let mut context = TestContext::new();
+    context.start_stream();
+
+    let keyframe = context.video(10, true, false);
+    context.step_context.execute_with_media(keyframe);
+    let keyframe = context.video(20, true, false);
+    context.step_context.execute_with_media(keyframe);
+
+    // Only the first segment has been closed
+    context.step_context.execute_pending_futures().await;
+    let messages = context.step_context.side_channel_messages();
+    assert_eq!(
+        messages.len(),
+        1,
+        "Unexpected number of side channel messages"
+    );
+
+    let segment = messages[0]
+        .data_as::<CompletedGopSegment>()
+        .expect("Side channel data was not a completed segment");
+
+    assert_eq!(
+        segment,
+        &CompletedGopSegment {
+            stream_id: context.stream_id.clone(),
+            stream_name: Arc::new("def".to_string()),
+            path: context.segment_path(0),
+        },
+        "Unexpected completed segment"
+    );
+
+    assert!(
+        segment.path.exists(),
+        "Completed segment file does not exist"
+    );
+}
+
+#[tokio::test]
+async fn slow_writer_does_not_stall_media_and_drops_rest_of_segment() {
+    // Segment writers only get to run when the test yields, so until then they act like storage
+    // that has stalled.
+    let mut context = TestContext::with_parameters(&[(MAX_QUEUED_WRITES, "3")]);
+    context.start_stream();
+
+    // The stream announcement, sequence header, and keyframe fill the queue
+    for media in [
+        context.video(10, true, false),
+        context.video(20, false, false),
+        context.audio(25),
+        context.video(30, false, false),
+    ] {
+        context.step_context.assert_media_passed_through(media);
+    }
+
+    let details = context.step_context.step.get_state_details();
+    assert_eq!(
+        details.get(WRITE_QUEUE_DEPTH_DETAIL),
+        Some(&"3".to_string()),
+        "Unexpected write queue depth"
+    );
+
+    assert_eq!(
+        details.get(DROPPED_WRITES_DETAIL),
+        Some(&"3".to_string()),
+        "Unexpected dropped write count"
+    );
+
+    // Once storage catches up, the next segment is written in full as long as it keeps up
+    context.step_context.execute_pending_futures().await;
+    let keyframe = context.video(40, true, false);
+    context.step_context.assert_media_passed_through(keyframe);
+
+    context.step_context.execute_pending_futures().await;
+    let frame = context.video(50, false, false);
+    context.step_context.assert_media_passed_through(frame);
+
+    context.step_context.execute_with_media(MediaNotification {
+        stream_id: context.stream_id.clone(),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    });
+
+    context.step_context.execute_pending_futures().await;
+
+    let details = context.step_context.step.get_state_details();
+    assert_eq!(
+        details.get(WRITE_QUEUE_DEPTH_DETAIL),
+        Some(&"0".to_string()),
+        "Unexpected write queue depth"
+    );
+
+    let first_segment = context.read_segment(0).await;
+    assert_eq!(first_segment.len(), 3, "Unexpected first segment length");
+    assert_eq!(
+        first_segment[2].media,
+        context.video(10, true, false),
+        "Unexpected last record in first segment"
+    );
+
+    let second_segment = context.read_segment(1).await;
+    assert_eq!(second_segment.len(), 4, "Unexpected second segment length");
+}
+
+#[tokio::test]
+async fn completed_segments_are_uploaded_to_object_storage() {
+    let server = MockS3Server::start().await;
+    let mut context = TestContext::with_parameters(&[
+        (S3_ENDPOINT, &server.endpoint),
+        (S3_BUCKET, "videos"),
+        (S3_KEY_PREFIX, "live/{stream_name}/"),
+        (S3_ACCESS_KEY_ID, "access"),
+        (S3_SECRET_ACCESS_KEY, "secret"),
+    ]);
+
+    context.start_stream();
+
+    for media in [
+        context.video(10, true, false),
+        context.video(20, false, false),
+        context.video(30, true, false),
+    ] {
+        context.step_context.execute_with_media(media);
+    }
+
+    context.step_context.execute_with_media(MediaNotification {
+        stream_id: context.stream_id.clone(),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    });
+
+    context.step_context.execute_pending_futures().await;
+    let mut requests = server.wait_for_requests(2).await;
+    requests.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let paths = requests.iter().map(|r| r.path.as_str()).collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        vec![
+            "/videos/live/def/def-000000.mmsr",
+            "/videos/live/def/def-000001.mmsr"
+        ],
+        "Unexpected uploaded keys"
+    );
+
+    for (index, request) in requests.iter().enumerate() {
+        assert_eq!(request.method, "PUT", "Unexpected method");
+
+        let file = std::fs::read(context.segment_path(index as u64)).unwrap();
+        assert_eq!(request.body, file, "Upload did not match segment {}", index);
+    }
+
+    context.step_context.execute_pending_futures().await;
+    let details = context.step_context.step.get_state_details();
+    assert_eq!(
+        details.get(UPLOADED_SEGMENTS_DETAIL),
+        Some(&"2".to_string()),
+        "Unexpected uploaded segment count"
+    );
+
+    assert_eq!(
+        details.get(PENDING_UPLOADS_DETAIL),
+        Some(&"0".to_string()),
+        "Unexpected pending upload count"
+    );
+}
+
+#[tokio::test]
+async fn failed_uploads_are_counted_and_cleaned_up() {
+    let server = MockS3Server::start().await;
+    server.set_put_status(503);
+
+    let mut context = TestContext::with_parameters(&[
+        (S3_ENDPOINT, &server.endpoint),
+        (S3_BUCKET, "videos"),
+        (S3_ACCESS_KEY_ID, "access"),
+        (S3_SECRET_ACCESS_KEY, "secret"),
+    ]);
+
+    context.start_stream();
+    let keyframe = context.video(10, true, false);
+    context.step_context.execute_with_media(keyframe);
+    context.step_context.execute_with_media(MediaNotification {
+        stream_id: context.stream_id.clone(),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    });
+
+    context.step_context.execute_pending_futures().await;
+    let requests = server.wait_for_requests(2).await;
+    assert_eq!(
+        requests[1].method, "DELETE",
+        "Expected partial upload to be deleted"
+    );
+    assert_eq!(
+        requests[1].path, "/videos/def/def-000000.mmsr",
+        "Unexpected deleted key"
+    );
+
+    context.step_context.execute_pending_futures().await;
+    assert_eq!(
+        context
+            .step_context
+            .step
+            .get_state_details()
+            .get(FAILED_UPLOADS_DETAIL),
+        Some(&"1".to_string()),
+        "Unexpected failed upload count"
+    );
+
+    assert_eq!(
+        context.step_context.status,
+        StepStatus::Active,
+        "Step should stay active"
+    );
+}
+
+#[tokio::test]
+async fn s3_bucket_without_credentials_returns_error() {
+    if std::env::var("AWS_ACCESS_KEY_ID").is_ok() {
+        // Credentials from the environment would be used
+        return;
+    }
+
+    let directory = std::env::temp_dir().join(format!("mmids-gop-{}", uuid::Uuid::new_v4()));
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("gop_segmenter".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    definition.parameters.insert(
+        PATH.to_string(),
+        Some(directory.to_string_lossy().to_string()),
+    );
+
+    definition
+        .parameters
+        .insert(S3_BUCKET.to_string(), Some("videos".to_string()));
+
+    let mut metadata_map = MetadataKeyMap::new();
+    let is_keyframe_metadata_key = get_is_keyframe_metadata_key(&mut metadata_map);
+    let generator = GopSegmenterStepGenerator::new(is_keyframe_metadata_key);
+
+    let result = StepTestContext::new(Box::new(generator), definition);
+    assert!(result.is_err(), "Expected an error");
+}
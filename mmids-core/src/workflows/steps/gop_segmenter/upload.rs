@@ -0,0 +1,166 @@
+//! Uploads completed segment files to S3 compatible object storage.
+
+use super::{CompletedGopSegment, FutureResult};
+use crate::object_storage::{ObjectStorageError, S3Client, S3Settings};
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use bytes::Bytes;
+use std::collections::HashMap;
+use thiserror::Error;
+use tracing::warn;
+
+pub const S3_BUCKET: &str = "s3_bucket";
+pub const S3_ENDPOINT: &str = "s3_endpoint";
+pub const S3_REGION: &str = "s3_region";
+pub const S3_ACCESS_KEY_ID: &str = "s3_access_key_id";
+pub const S3_SECRET_ACCESS_KEY: &str = "s3_secret_access_key";
+pub const S3_KEY_PREFIX: &str = "s3_key_prefix";
+pub const MAX_QUEUED_UPLOADS: &str = "max_queued_uploads";
+
+const DEFAULT_REGION: &str = "us-east-1";
+const DEFAULT_KEY_PREFIX: &str = "{stream_name}/";
+const DEFAULT_MAX_QUEUED_UPLOADS: usize = 10;
+const SEGMENT_CONTENT_TYPE: &str = "application/octet-stream";
+
+#[derive(Error, Debug)]
+pub enum UploadSetupError {
+    #[error(
+        "No S3 credentials specified. Either the {} and {} parameters or the AWS_ACCESS_KEY_ID \
+        and AWS_SECRET_ACCESS_KEY environment variables are required",
+        S3_ACCESS_KEY_ID,
+        S3_SECRET_ACCESS_KEY
+    )]
+    NoCredentials,
+
+    #[error(
+        "Invalid {} value of '{0}', must be a number greater than zero",
+        MAX_QUEUED_UPLOADS
+    )]
+    InvalidMaxQueuedUploads(String),
+
+    #[error("Invalid S3 settings: {0}")]
+    InvalidSettings(#[from] ObjectStorageError),
+}
+
+pub(super) struct SegmentUploader {
+    client: S3Client,
+    key_prefix: String,
+    max_pending_uploads: usize,
+    pub pending_uploads: usize,
+    pub uploaded_segments: u64,
+    pub failed_uploads: u64,
+    pub dropped_uploads: u64,
+}
+
+impl SegmentUploader {
+    /// Creates an uploader if the step has been configured with a bucket to upload to
+    pub fn from_parameters(
+        parameters: &HashMap<String, Option<String>>,
+    ) -> Result<Option<Self>, UploadSetupError> {
+        let get = |name: &str| match parameters.get(name) {
+            Some(Some(value)) => Some(value.clone()),
+            _ => None,
+        };
+
+        let bucket = match get(S3_BUCKET) {
+            Some(bucket) => bucket,
+            None => return Ok(None),
+        };
+
+        let region = get(S3_REGION).unwrap_or_else(|| DEFAULT_REGION.to_string());
+        let endpoint =
+            get(S3_ENDPOINT).unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region));
+
+        let access_key_id =
+            get(S3_ACCESS_KEY_ID).or_else(|| std::env::var("AWS_ACCESS_KEY_ID").ok());
+        let secret_access_key =
+            get(S3_SECRET_ACCESS_KEY).or_else(|| std::env::var("AWS_SECRET_ACCESS_KEY").ok());
+
+        let (access_key_id, secret_access_key) = match (access_key_id, secret_access_key) {
+            (Some(id), Some(secret)) => (id, secret),
+            _ => return Err(UploadSetupError::NoCredentials),
+        };
+
+        let max_pending_uploads = match get(MAX_QUEUED_UPLOADS) {
+            Some(value) => match value.parse::<usize>() {
+                Ok(max) if max > 0 => max,
+                _ => return Err(UploadSetupError::InvalidMaxQueuedUploads(value)),
+            },
+
+            None => DEFAULT_MAX_QUEUED_UPLOADS,
+        };
+
+        let client = S3Client::new(S3Settings {
+            endpoint,
+            region,
+            bucket,
+            access_key_id,
+            secret_access_key,
+        })?;
+
+        Ok(Some(SegmentUploader {
+            client,
+            key_prefix: get(S3_KEY_PREFIX).unwrap_or_else(|| DEFAULT_KEY_PREFIX.to_string()),
+            max_pending_uploads,
+            pending_uploads: 0,
+            uploaded_segments: 0,
+            failed_uploads: 0,
+            dropped_uploads: 0,
+        }))
+    }
+
+    /// Starts uploading the segment's file in the background. If too many uploads are already in
+    /// progress the segment is not uploaded, so an unreachable object store can't cause an
+    /// unbounded amount of uploads to pile up.
+    pub fn upload(
+        &mut self,
+        segment: &CompletedGopSegment,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        if self.pending_uploads >= self.max_pending_uploads {
+            warn!(
+                "Too many segment uploads are in progress, not uploading {}",
+                segment.path.display()
+            );
+
+            self.dropped_uploads += 1;
+            return;
+        }
+
+        let file_name = segment
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let key = format!(
+            "{}{}",
+            self.key_prefix
+                .replace("{stream_name}", segment.stream_name.as_str()),
+            file_name
+        );
+
+        let client = self.client.clone();
+        let path = segment.path.clone();
+        self.pending_uploads += 1;
+
+        futures_channel.send_on_generic_future_completion(async move {
+            let data = match tokio::fs::read(&path).await {
+                Ok(data) => data,
+                Err(error) => {
+                    return FutureResult::UploadFailed(
+                        key,
+                        format!("Failed to read {}: {}", path.display(), error),
+                    );
+                }
+            };
+
+            match client
+                .put_object_or_clean_up(&key, Bytes::from(data), SEGMENT_CONTENT_TYPE)
+                .await
+            {
+                Ok(()) => FutureResult::UploadFinished,
+                Err(error) => FutureResult::UploadFailed(key, error.to_string()),
+            }
+        });
+    }
+}
@@ -0,0 +1,311 @@
+//! The H264 framing step converts H264 video between AVCC framing (length prefixed NAL units with
+//! an `avcC` sequence header, as used by RTMP) and Annex-B framing (start code separated NAL units
+//! with the SPS and PPS as the sequence header, as used by MPEG-TS), without re-encoding it.
+//!
+//! The `format` parameter specifies the framing video should be converted to, and must be either
+//! `annexb` or `avcc`. Video with the `h264-avc` payload type is converted to the `h264-annexb`
+//! payload type and vice versa. All other media is passed through untouched.
+//!
+//! Annex-B streams commonly only carry their SPS and PPS in-band. When converting these to AVCC, a
+//! sequence header is generated from the first payload containing both, so downstream consumers
+//! always receive an `avcC` before any frames.
+
+#[cfg(test)]
+mod tests;
+
+use crate::codecs::h264::{
+    nal_unit_type, AvcDecoderConfigurationRecord, H264Error, NAL_UNIT_TYPE_PPS, NAL_UNIT_TYPE_SPS,
+};
+use crate::codecs::nal::{
+    split_annexb, split_length_prefixed, write_annexb, write_length_prefixed,
+    DEFAULT_NAL_LENGTH_SIZE,
+};
+use crate::codecs::{VIDEO_CODEC_H264_ANNEXB, VIDEO_CODEC_H264_AVC};
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::metadata::MediaPayloadMetadataCollection;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use crate::StreamId;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::warn;
+
+pub const FORMAT: &str = "format";
+pub const ANNEXB_FORMAT: &str = "annexb";
+pub const AVCC_FORMAT: &str = "avcc";
+
+const FAILED_CONVERSION_COUNT_DETAIL: &str = "failed_conversion_count";
+
+/// Generates new instances of the H264 framing workflow step
+#[derive(Default)]
+pub struct H264FramingStepGenerator {}
+
+#[derive(Clone, Copy)]
+enum Framing {
+    AnnexB,
+    Avcc,
+}
+
+#[derive(Default)]
+struct StreamState {
+    /// Length prefix size of the stream's AVCC NAL units
+    nal_length_size: Option<u8>,
+
+    /// If an AVCC sequence header has been sent for the stream
+    has_sent_avcc_sequence_header: bool,
+}
+
+struct H264FramingStep {
+    target: Framing,
+    streams: HashMap<StreamId, StreamState>,
+    failed_conversion_count: u64,
+}
+
+struct VideoPayload<'a> {
+    timestamp: Duration,
+    metadata: &'a MediaPayloadMetadataCollection,
+    data: &'a Bytes,
+    is_required_for_decoding: bool,
+}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "No {} parameter specified, must be '{}' or '{}'",
+        FORMAT,
+        ANNEXB_FORMAT,
+        AVCC_FORMAT
+    )]
+    NoFormatSpecified,
+
+    #[error(
+        "Invalid {} value of '{0}', must be '{}' or '{}'",
+        FORMAT,
+        ANNEXB_FORMAT,
+        AVCC_FORMAT
+    )]
+    InvalidFormat(String),
+}
+
+impl H264FramingStepGenerator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl StepGenerator for H264FramingStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let target = match definition.parameters.get(FORMAT) {
+            Some(Some(format)) => match format.to_lowercase().as_str() {
+                ANNEXB_FORMAT => Framing::AnnexB,
+                AVCC_FORMAT => Framing::Avcc,
+                _ => return Err(Box::new(StepStartupError::InvalidFormat(format.clone()))),
+            },
+
+            _ => return Err(Box::new(StepStartupError::NoFormatSpecified)),
+        };
+
+        let step = H264FramingStep {
+            target,
+            streams: HashMap::new(),
+            failed_conversion_count: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl H264FramingStep {
+    fn handle_media(&mut self, media: MediaNotification, outputs: &mut StepOutputs) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { .. } => {
+                self.streams
+                    .insert(media.stream_id.clone(), StreamState::default());
+
+                outputs.media.push(media);
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.streams.remove(&media.stream_id);
+                outputs.media.push(media);
+            }
+
+            MediaNotificationContent::Metadata { .. } => outputs.media.push(media),
+
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type,
+                timestamp,
+                metadata,
+                data,
+                is_required_for_decoding,
+            } => {
+                let payload = VideoPayload {
+                    timestamp: *timestamp,
+                    metadata,
+                    data,
+                    is_required_for_decoding: *is_required_for_decoding,
+                };
+
+                let stream = self.streams.entry(media.stream_id.clone()).or_default();
+                let result = match self.target {
+                    Framing::AnnexB if *payload_type == *VIDEO_CODEC_H264_AVC => {
+                        to_annexb(stream, &payload)
+                    }
+
+                    Framing::Avcc if *payload_type == *VIDEO_CODEC_H264_ANNEXB => {
+                        to_avcc(stream, &payload)
+                    }
+
+                    _ => {
+                        // Not something we need to convert
+                        outputs.media.push(media);
+                        return;
+                    }
+                };
+
+                match result {
+                    Ok(converted) => {
+                        let payload_type = match self.target {
+                            Framing::AnnexB => VIDEO_CODEC_H264_ANNEXB.clone(),
+                            Framing::Avcc => VIDEO_CODEC_H264_AVC.clone(),
+                        };
+
+                        for (data, is_required_for_decoding) in converted {
+                            outputs.media.push(MediaNotification {
+                                stream_id: media.stream_id.clone(),
+                                content: MediaNotificationContent::MediaPayload {
+                                    media_type: MediaType::Video,
+                                    payload_type: payload_type.clone(),
+                                    timestamp: payload.timestamp,
+                                    metadata: payload.metadata.clone(),
+                                    data,
+                                    is_required_for_decoding,
+                                },
+                                annotations: Default::default(),
+                            });
+                        }
+                    }
+
+                    Err(error) => {
+                        warn!(
+                            stream_id = %media.stream_id.0,
+                            "Failed to convert H264 video payload: {}", error
+                        );
+
+                        self.failed_conversion_count += 1;
+                    }
+                }
+            }
+
+            MediaNotificationContent::MediaPayload { .. } => outputs.media.push(media),
+        }
+    }
+}
+
+/// Converts an AVCC payload to Annex-B. The converted payloads are returned along with if each
+/// one is required for decoding.
+fn to_annexb(
+    stream: &mut StreamState,
+    payload: &VideoPayload,
+) -> Result<Vec<(Bytes, bool)>, H264Error> {
+    if payload.is_required_for_decoding {
+        let record = AvcDecoderConfigurationRecord::parse(payload.data)?;
+        stream.nal_length_size = Some(record.nal_length_size);
+
+        return Ok(vec![(write_annexb(&record.parameter_sets()), true)]);
+    }
+
+    let nal_length_size = stream.nal_length_size.unwrap_or(DEFAULT_NAL_LENGTH_SIZE);
+    let nal_units = split_length_prefixed(payload.data, nal_length_size)?;
+
+    Ok(vec![(write_annexb(&nal_units), false)])
+}
+
+/// Converts an Annex-B payload to AVCC. The converted payloads are returned along with if each
+/// one is required for decoding, as a sequence header may be generated from in-band parameter sets.
+fn to_avcc(
+    stream: &mut StreamState,
+    payload: &VideoPayload,
+) -> Result<Vec<(Bytes, bool)>, H264Error> {
+    let nal_units = split_annexb(payload.data);
+    let sequence_parameter_sets = parameter_sets_of_type(&nal_units, NAL_UNIT_TYPE_SPS);
+    let picture_parameter_sets = parameter_sets_of_type(&nal_units, NAL_UNIT_TYPE_PPS);
+
+    if payload.is_required_for_decoding {
+        let record = AvcDecoderConfigurationRecord::from_parameter_sets(
+            sequence_parameter_sets,
+            picture_parameter_sets,
+            DEFAULT_NAL_LENGTH_SIZE,
+        )?;
+
+        stream.has_sent_avcc_sequence_header = true;
+
+        return Ok(vec![(record.to_bytes(), true)]);
+    }
+
+    let mut converted = Vec::new();
+    if !stream.has_sent_avcc_sequence_header
+        && !sequence_parameter_sets.is_empty()
+        && !picture_parameter_sets.is_empty()
+    {
+        let record = AvcDecoderConfigurationRecord::from_parameter_sets(
+            sequence_parameter_sets,
+            picture_parameter_sets,
+            DEFAULT_NAL_LENGTH_SIZE,
+        )?;
+
+        converted.push((record.to_bytes(), true));
+        stream.has_sent_avcc_sequence_header = true;
+    }
+
+    converted.push((
+        write_length_prefixed(&nal_units, DEFAULT_NAL_LENGTH_SIZE)?,
+        false,
+    ));
+
+    Ok(converted)
+}
+
+fn parameter_sets_of_type(nal_units: &[Bytes], nal_type: u8) -> Vec<Bytes> {
+    nal_units
+        .iter()
+        .filter(|nal_unit| nal_unit_type(nal_unit) == Some(nal_type))
+        .cloned()
+        .collect()
+}
+
+impl WorkflowStep for H264FramingStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            self.handle_media(media, outputs);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            FAILED_CONVERSION_COUNT_DETAIL.to_string(),
+            self.failed_conversion_count.to_string(),
+        );
+
+        details
+    }
+}
@@ -0,0 +1,244 @@
+use super::*;
+use crate::codecs::AUDIO_CODEC_AAC_RAW;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::steps::test_utils::StepTestContext;
+use bytes::BytesMut;
+use std::iter;
+use std::sync::Arc;
+
+fn sps() -> Bytes {
+    Bytes::from_static(&[0x67, 0x64, 0x00, 0x1f, 0xac, 0xd9, 0x40, 0x50])
+}
+
+fn pps() -> Bytes {
+    Bytes::from_static(&[0x68, 0xeb, 0xe3, 0xcb, 0x22, 0xc0])
+}
+
+fn frame_nal_units() -> Vec<Bytes> {
+    vec![
+        Bytes::from_static(&[0x06, 0x05, 0x01, 0x80]),
+        Bytes::from_static(&[
+            0x65, 0x88, 0x84, 0x00, 0x00, 0x03, 0x00, 0x21, 0x00, 0x00, 0x03, 0x01,
+        ]),
+    ]
+}
+
+fn stream_id() -> StreamId {
+    StreamId(Arc::new("abc".to_string()))
+}
+
+fn create_step(format: Option<&str>) -> anyhow::Result<StepTestContext> {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("h264_framing".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    if let Some(format) = format {
+        definition
+            .parameters
+            .insert(FORMAT.to_string(), Some(format.to_string()));
+    }
+
+    StepTestContext::new(Box::new(H264FramingStepGenerator::new()), definition)
+}
+
+fn start_stream(context: &mut StepTestContext) {
+    context.execute_with_media(MediaNotification {
+        stream_id: stream_id(),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("def".to_string()),
+        },
+        annotations: Default::default(),
+    });
+}
+
+fn video(payload_type: &Arc<String>, data: Bytes, is_sequence_header: bool) -> MediaNotification {
+    MediaNotification {
+        stream_id: stream_id(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Video,
+            payload_type: payload_type.clone(),
+            timestamp: Duration::from_millis(10),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data,
+            is_required_for_decoding: is_sequence_header,
+        },
+        annotations: Default::default(),
+    }
+}
+
+fn payload_of(media: &MediaNotification) -> (Arc<String>, Bytes, bool) {
+    match &media.content {
+        MediaNotificationContent::MediaPayload {
+            payload_type,
+            data,
+            is_required_for_decoding,
+            ..
+        } => (
+            payload_type.clone(),
+            data.clone(),
+            *is_required_for_decoding,
+        ),
+
+        content => panic!("Expected media payload, instead got {:?}", content),
+    }
+}
+
+fn avcc_sequence_header() -> Bytes {
+    AvcDecoderConfigurationRecord::from_parameter_sets(vec![sps()], vec![pps()], 4)
+        .unwrap()
+        .to_bytes()
+}
+
+#[test]
+fn missing_format_returns_error() {
+    assert!(create_step(None).is_err(), "Expected an error");
+}
+
+#[test]
+fn invalid_format_returns_error() {
+    assert!(create_step(Some("mp4")).is_err(), "Expected an error");
+}
+
+#[test]
+fn avcc_round_trips_through_annexb() {
+    let mut to_annexb = create_step(Some(ANNEXB_FORMAT)).unwrap();
+    let mut to_avcc = create_step(Some(AVCC_FORMAT)).unwrap();
+    start_stream(&mut to_annexb);
+    start_stream(&mut to_avcc);
+
+    let original = vec![
+        video(&VIDEO_CODEC_H264_AVC, avcc_sequence_header(), true),
+        video(
+            &VIDEO_CODEC_H264_AVC,
+            write_length_prefixed(&frame_nal_units(), 4).unwrap(),
+            false,
+        ),
+    ];
+
+    for media in &original {
+        to_annexb.execute_with_media(media.clone());
+        assert_eq!(
+            to_annexb.media_outputs.len(),
+            1,
+            "Unexpected number of Annex-B outputs"
+        );
+
+        let annexb = to_annexb.media_outputs.remove(0);
+        let (payload_type, _, _) = payload_of(&annexb);
+        assert_eq!(
+            payload_type, *VIDEO_CODEC_H264_ANNEXB,
+            "Unexpected Annex-B payload type"
+        );
+
+        to_avcc.execute_with_media(annexb);
+        assert_eq!(
+            to_avcc.media_outputs,
+            vec![media.clone()],
+            "AVCC media was not restored"
+        );
+    }
+
+    let (_, restored_frame, _) = payload_of(&to_avcc.media_outputs[0]);
+    assert_eq!(
+        split_length_prefixed(&restored_frame, 4).unwrap(),
+        frame_nal_units(),
+        "NAL unit payloads were not restored"
+    );
+}
+
+#[test]
+fn sequence_header_converted_to_annexb_parameter_sets() {
+    let mut context = create_step(Some(ANNEXB_FORMAT)).unwrap();
+    start_stream(&mut context);
+
+    context.execute_with_media(video(&VIDEO_CODEC_H264_AVC, avcc_sequence_header(), true));
+
+    assert_eq!(
+        context.media_outputs.len(),
+        1,
+        "Unexpected number of outputs"
+    );
+    let (_, data, is_sequence_header) = payload_of(&context.media_outputs[0]);
+    assert!(is_sequence_header, "Expected a sequence header");
+    assert_eq!(split_annexb(&data), vec![sps(), pps()]);
+}
+
+#[test]
+fn sequence_header_generated_from_in_band_parameter_sets() {
+    let mut context = create_step(Some(AVCC_FORMAT)).unwrap();
+    start_stream(&mut context);
+
+    let mut nal_units = vec![sps(), pps()];
+    nal_units.extend(frame_nal_units());
+    let keyframe = write_annexb(&nal_units);
+
+    context.execute_with_media(video(&VIDEO_CODEC_H264_ANNEXB, keyframe.clone(), false));
+    assert_eq!(
+        context.media_outputs.len(),
+        2,
+        "Unexpected number of outputs"
+    );
+    assert_eq!(
+        payload_of(&context.media_outputs[0]),
+        (VIDEO_CODEC_H264_AVC.clone(), avcc_sequence_header(), true),
+        "Unexpected sequence header"
+    );
+
+    let (_, frame, is_sequence_header) = payload_of(&context.media_outputs[1]);
+    assert!(!is_sequence_header, "Frame marked as sequence header");
+    assert_eq!(split_length_prefixed(&frame, 4).unwrap(), nal_units);
+
+    // Only the first keyframe should cause a sequence header to be generated
+    context.execute_with_media(video(&VIDEO_CODEC_H264_ANNEXB, keyframe, false));
+    assert_eq!(
+        context.media_outputs.len(),
+        1,
+        "Unexpected number of outputs"
+    );
+}
+
+#[test]
+fn non_matching_media_is_passed_through() {
+    let mut context = create_step(Some(ANNEXB_FORMAT)).unwrap();
+    start_stream(&mut context);
+
+    let audio = MediaNotification {
+        stream_id: stream_id(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Audio,
+            payload_type: AUDIO_CODEC_AAC_RAW.clone(),
+            timestamp: Duration::from_millis(10),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data: Bytes::from(vec![1, 2, 3]),
+            is_required_for_decoding: false,
+        },
+        annotations: Default::default(),
+    };
+
+    context.assert_media_passed_through(audio);
+
+    // Already Annex-B, so nothing to convert
+    let annexb = video(&VIDEO_CODEC_H264_ANNEXB, write_annexb(&[sps()]), false);
+    context.assert_media_passed_through(annexb);
+}
+
+#[test]
+fn invalid_payload_is_dropped_and_counted() {
+    let mut context = create_step(Some(ANNEXB_FORMAT)).unwrap();
+    start_stream(&mut context);
+
+    let truncated = Bytes::from_static(&[0x00, 0x00, 0x00, 0x10, 0x65]);
+    context.execute_with_media(video(&VIDEO_CODEC_H264_AVC, truncated, false));
+
+    assert!(context.media_outputs.is_empty(), "Expected no outputs");
+    assert_eq!(context.status, StepStatus::Active, "Unexpected step status");
+    assert_eq!(
+        context
+            .step
+            .get_state_details()
+            .get(FAILED_CONVERSION_COUNT_DETAIL),
+        Some(&"1".to_string()),
+        "Unexpected failed conversion count"
+    );
+}
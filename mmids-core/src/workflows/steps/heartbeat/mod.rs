@@ -0,0 +1,234 @@
+//! The heartbeat step emits a metadata notification every `interval` milliseconds for each active
+//! stream, so downstream systems that can't easily inspect media can tell a stream that's alive but
+//! quiet apart from a stream that's gone.
+//!
+//! Each heartbeat contains a `heartbeat_counter` value, which starts at 1 for a new stream and
+//! increases by one with each heartbeat, and a `heartbeat_timestamp` value with the number of
+//! milliseconds since the unix epoch when the heartbeat was raised. Heartbeats for a stream stop
+//! once it disconnects.
+//!
+//! All media is passed through untouched.
+
+#[cfg(test)]
+mod tests;
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent};
+use crate::StreamId;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tokio::time::Instant;
+use tracing::error;
+
+pub const INTERVAL: &str = "interval";
+pub const HEARTBEAT_COUNTER: &str = "heartbeat_counter";
+pub const HEARTBEAT_TIMESTAMP: &str = "heartbeat_timestamp";
+
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(5000);
+
+const ACTIVE_STREAMS_DETAIL: &str = "active_streams";
+const HEARTBEATS_SENT_DETAIL: &str = "heartbeats_sent";
+
+/// Generates new instances of the heartbeat workflow step
+#[derive(Default)]
+pub struct HeartbeatStepGenerator {}
+
+struct StreamState {
+    /// Identifies this instance of the stream, so heartbeats scheduled for a previous stream with
+    /// the same stream id can be ignored
+    heartbeat_id: u64,
+    counter: u64,
+    next_heartbeat_at: Instant,
+}
+
+struct HeartbeatStep {
+    interval: Duration,
+    streams: HashMap<StreamId, StreamState>,
+    next_heartbeat_id: u64,
+    heartbeats_sent: u64,
+}
+
+enum FutureResult {
+    HeartbeatDue {
+        stream_id: StreamId,
+        heartbeat_id: u64,
+    },
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a number of milliseconds greater than zero",
+        INTERVAL
+    )]
+    InvalidInterval(String),
+}
+
+impl HeartbeatStepGenerator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl StepGenerator for HeartbeatStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let interval = match definition.parameters.get(INTERVAL) {
+            Some(Some(value)) => match value.trim().parse::<u64>() {
+                Ok(millis) if millis > 0 => Duration::from_millis(millis),
+                _ => return Err(Box::new(StepStartupError::InvalidInterval(value.clone()))),
+            },
+
+            _ => DEFAULT_INTERVAL,
+        };
+
+        let step = HeartbeatStep {
+            interval,
+            streams: HashMap::new(),
+            next_heartbeat_id: 0,
+            heartbeats_sent: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl HeartbeatStep {
+    fn handle_media(
+        &mut self,
+        media: &MediaNotification,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { .. } => {
+                self.next_heartbeat_id += 1;
+                let state = StreamState {
+                    heartbeat_id: self.next_heartbeat_id,
+                    counter: 0,
+                    next_heartbeat_at: Instant::now() + self.interval,
+                };
+
+                schedule_heartbeat(media.stream_id.clone(), &state, futures_channel);
+                self.streams.insert(media.stream_id.clone(), state);
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.streams.remove(&media.stream_id);
+            }
+
+            _ => (),
+        }
+    }
+
+    fn handle_heartbeat_due(
+        &mut self,
+        stream_id: StreamId,
+        heartbeat_id: u64,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let state = match self.streams.get_mut(&stream_id) {
+            Some(state) if state.heartbeat_id == heartbeat_id => state,
+            _ => return, // stream has since disconnected
+        };
+
+        state.counter += 1;
+        self.heartbeats_sent += 1;
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+
+        let mut data = HashMap::new();
+        data.insert(HEARTBEAT_COUNTER.to_string(), state.counter.to_string());
+        data.insert(HEARTBEAT_TIMESTAMP.to_string(), timestamp.to_string());
+
+        outputs.media.push(MediaNotification {
+            stream_id: stream_id.clone(),
+            content: MediaNotificationContent::Metadata { data },
+            annotations: Default::default(),
+        });
+
+        // Scheduled from when the heartbeat was due, so delays in processing don't add up
+        state.next_heartbeat_at += self.interval;
+        schedule_heartbeat(stream_id, state, futures_channel);
+    }
+}
+
+fn schedule_heartbeat(
+    stream_id: StreamId,
+    state: &StreamState,
+    futures_channel: &WorkflowStepFuturesChannel,
+) {
+    let heartbeat_id = state.heartbeat_id;
+    let due_at = state.next_heartbeat_at;
+    futures_channel.send_on_generic_future_completion(async move {
+        tokio::time::sleep_until(due_at).await;
+        FutureResult::HeartbeatDue {
+            stream_id,
+            heartbeat_id,
+        }
+    });
+}
+
+impl WorkflowStep for HeartbeatStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            let future_result = match notification.downcast::<FutureResult>() {
+                Ok(x) => *x,
+                Err(_) => {
+                    error!("Heartbeat step received a notification that is not a known type");
+                    return StepStatus::Error {
+                        message: "Received future result of unknown type".to_string(),
+                    };
+                }
+            };
+
+            match future_result {
+                FutureResult::HeartbeatDue {
+                    stream_id,
+                    heartbeat_id,
+                } => self.handle_heartbeat_due(stream_id, heartbeat_id, outputs, &futures_channel),
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            self.handle_media(&media, &futures_channel);
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            ACTIVE_STREAMS_DETAIL.to_string(),
+            self.streams.len().to_string(),
+        );
+
+        details.insert(
+            HEARTBEATS_SENT_DETAIL.to_string(),
+            self.heartbeats_sent.to_string(),
+        );
+
+        details
+    }
+}
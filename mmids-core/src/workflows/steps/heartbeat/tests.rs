@@ -0,0 +1,175 @@
+use super::*;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::steps::test_utils::StepTestContext;
+use std::sync::Arc;
+
+struct TestContext {
+    step_context: StepTestContext,
+}
+
+impl TestContext {
+    fn new(interval: &str) -> Self {
+        let mut definition = WorkflowStepDefinition {
+            step_type: WorkflowStepType("heartbeat".to_string()),
+            parameters: HashMap::new(),
+        };
+
+        definition
+            .parameters
+            .insert(INTERVAL.to_string(), Some(interval.to_string()));
+
+        let generator = HeartbeatStepGenerator::new();
+        let step_context =
+            StepTestContext::new(Box::new(generator), definition).expect("Failed to create step");
+
+        TestContext { step_context }
+    }
+
+    /// Runs the step for the specified duration, returning all heartbeats raised during it
+    async fn collect_heartbeats(&mut self, duration: Duration) -> Vec<HashMap<String, String>> {
+        let mut heartbeats = Vec::new();
+        let end = Instant::now() + duration;
+        while Instant::now() < end {
+            self.step_context.execute_pending_futures().await;
+            for media in self.step_context.media_outputs.drain(..) {
+                match media.content {
+                    MediaNotificationContent::Metadata { data } => heartbeats.push(data),
+                    content => panic!("Unexpected media content: {:?}", content),
+                }
+            }
+        }
+
+        heartbeats
+    }
+}
+
+fn new_stream() -> MediaNotification {
+    MediaNotification {
+        stream_id: StreamId(Arc::new("abc".to_string())),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("def".to_string()),
+        },
+        annotations: Default::default(),
+    }
+}
+
+fn disconnection() -> MediaNotification {
+    MediaNotification {
+        stream_id: StreamId(Arc::new("abc".to_string())),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    }
+}
+
+#[test]
+fn invalid_interval_returns_error() {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("heartbeat".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    definition
+        .parameters
+        .insert(INTERVAL.to_string(), Some("0".to_string()));
+
+    let result = StepTestContext::new(Box::new(HeartbeatStepGenerator::new()), definition);
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[tokio::test]
+async fn media_passed_through() {
+    let mut context = TestContext::new("100");
+
+    context
+        .step_context
+        .assert_media_passed_through(new_stream());
+    context
+        .step_context
+        .assert_media_passed_through(disconnection());
+}
+
+#[tokio::test]
+async fn heartbeats_raised_at_configured_interval() {
+    let mut context = TestContext::new("100");
+    context
+        .step_context
+        .assert_media_passed_through(new_stream());
+
+    let heartbeats = context.collect_heartbeats(Duration::from_millis(50)).await;
+
+    assert!(heartbeats.is_empty(), "Expected no heartbeats yet");
+
+    let heartbeats = context.collect_heartbeats(Duration::from_millis(200)).await;
+
+    assert_eq!(heartbeats.len(), 2, "Unexpected number of heartbeats");
+    assert_eq!(
+        heartbeats[0].get(HEARTBEAT_COUNTER),
+        Some(&"1".to_string()),
+        "Unexpected first heartbeat counter"
+    );
+    assert_eq!(
+        heartbeats[1].get(HEARTBEAT_COUNTER),
+        Some(&"2".to_string()),
+        "Unexpected second heartbeat counter"
+    );
+
+    let first_timestamp = heartbeats[0]
+        .get(HEARTBEAT_TIMESTAMP)
+        .and_then(|value| value.parse::<u64>().ok())
+        .expect("Expected first heartbeat to have a timestamp");
+
+    let second_timestamp = heartbeats[1]
+        .get(HEARTBEAT_TIMESTAMP)
+        .and_then(|value| value.parse::<u64>().ok())
+        .expect("Expected second heartbeat to have a timestamp");
+
+    assert!(
+        second_timestamp > first_timestamp,
+        "Expected heartbeat timestamps to increase"
+    );
+}
+
+#[tokio::test]
+async fn heartbeats_stop_on_disconnection() {
+    let mut context = TestContext::new("50");
+    context
+        .step_context
+        .assert_media_passed_through(new_stream());
+
+    let heartbeats = context.collect_heartbeats(Duration::from_millis(125)).await;
+
+    assert!(!heartbeats.is_empty(), "Expected heartbeats");
+
+    context
+        .step_context
+        .assert_media_passed_through(disconnection());
+
+    let heartbeats = context.collect_heartbeats(Duration::from_millis(150)).await;
+
+    assert!(heartbeats.is_empty(), "Expected no heartbeats");
+}
+
+#[tokio::test]
+async fn reconnected_stream_restarts_counter() {
+    let mut context = TestContext::new("50");
+    context
+        .step_context
+        .assert_media_passed_through(new_stream());
+    context.collect_heartbeats(Duration::from_millis(125)).await;
+
+    context
+        .step_context
+        .assert_media_passed_through(disconnection());
+    context
+        .step_context
+        .assert_media_passed_through(new_stream());
+
+    let heartbeats = context.collect_heartbeats(Duration::from_millis(75)).await;
+
+    assert_eq!(heartbeats.len(), 1, "Unexpected number of heartbeats");
+    assert_eq!(
+        heartbeats[0].get(HEARTBEAT_COUNTER),
+        Some(&"1".to_string()),
+        "Unexpected heartbeat counter"
+    );
+}
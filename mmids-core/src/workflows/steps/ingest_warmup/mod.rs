@@ -0,0 +1,369 @@
+//! The ingest warmup step delays the announcement of new streams until they are producing
+//! decodable video. Placing it directly after an ingest step prevents later steps from
+//! initializing for a stream that exists but has no video yet, and watchers from seeing such a
+//! stream.
+//!
+//! A stream's `NewIncomingStream` notification is held back until both a video sequence header
+//! and a video keyframe have been received. Once they have, the stream is announced followed by
+//! its label and latest metadata, its latest sequence headers and the keyframe. Any other media received
+//! during the warmup can't be decoded without the keyframe and is dropped.
+//!
+//! If the stream does not warm up within the `timeout` (in milliseconds), the `on_timeout`
+//! parameter decides what happens to it. With `announce` the stream is announced anyway with
+//! whatever has been received so far, while with `drop` all of the stream's media is dropped
+//! until it disconnects.
+
+#[cfg(test)]
+mod tests;
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::metadata::{MetadataKey, MetadataValue};
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::stream_labels::get_announced_label;
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use crate::StreamId;
+use std::collections::HashMap;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+pub const TIMEOUT: &str = "timeout";
+pub const ON_TIMEOUT: &str = "on_timeout";
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(5000);
+
+const WARMING_STREAMS_DETAIL: &str = "warming_streams";
+const TIMED_OUT_COUNT_DETAIL: &str = "timed_out_count";
+
+/// Generates new instances of the ingest warmup workflow step
+pub struct IngestWarmupStepGenerator {
+    is_keyframe_metadata_key: MetadataKey,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TimeoutAction {
+    Announce,
+    Drop,
+}
+
+struct WarmingStream {
+    /// Identifies this warmup, so timeouts for previous announcements of the same stream id
+    /// can be ignored
+    warmup_id: u64,
+    new_stream: MediaNotification,
+    metadata: Option<MediaNotification>,
+    label: Option<MediaNotification>,
+    sequence_headers: Vec<MediaNotification>,
+    has_video_sequence_header: bool,
+}
+
+enum StreamState {
+    Warming(Box<WarmingStream>),
+    Announced,
+    Dropped,
+}
+
+struct IngestWarmupStep {
+    is_keyframe_metadata_key: MetadataKey,
+    timeout: Duration,
+    timeout_action: TimeoutAction,
+    streams: HashMap<StreamId, StreamState>,
+    next_warmup_id: u64,
+    timed_out_count: u64,
+}
+
+enum FutureResult {
+    WarmupTimedOut { stream_id: StreamId, warmup_id: u64 },
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a number of milliseconds greater than zero",
+        TIMEOUT
+    )]
+    InvalidTimeout(String),
+
+    #[error(
+        "Invalid {} value of '{0}' specified, must be either 'announce' or 'drop'",
+        ON_TIMEOUT
+    )]
+    InvalidTimeoutAction(String),
+}
+
+impl IngestWarmupStepGenerator {
+    pub fn new(is_keyframe_metadata_key: MetadataKey) -> Self {
+        IngestWarmupStepGenerator {
+            is_keyframe_metadata_key,
+        }
+    }
+}
+
+impl StepGenerator for IngestWarmupStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let timeout = match definition.parameters.get(TIMEOUT) {
+            Some(Some(value)) => match value.trim().parse::<u64>() {
+                Ok(millis) if millis > 0 => Duration::from_millis(millis),
+                _ => return Err(Box::new(StepStartupError::InvalidTimeout(value.clone()))),
+            },
+
+            _ => DEFAULT_TIMEOUT,
+        };
+
+        let timeout_action = match definition.parameters.get(ON_TIMEOUT) {
+            Some(Some(value)) => match value.trim().to_lowercase().as_str() {
+                "announce" => TimeoutAction::Announce,
+                "drop" => TimeoutAction::Drop,
+                _ => {
+                    return Err(Box::new(StepStartupError::InvalidTimeoutAction(
+                        value.clone(),
+                    )))
+                }
+            },
+
+            _ => TimeoutAction::Announce,
+        };
+
+        let step = IngestWarmupStep {
+            is_keyframe_metadata_key: self.is_keyframe_metadata_key,
+            timeout,
+            timeout_action,
+            streams: HashMap::new(),
+            next_warmup_id: 0,
+            timed_out_count: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl IngestWarmupStep {
+    fn handle_media(
+        &mut self,
+        media: MediaNotification,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { .. } => {
+                if let Some(StreamState::Announced) = self.streams.get(&media.stream_id) {
+                    // Later steps were told about the previous stream, and need to know it's gone
+                    outputs.media.push(MediaNotification {
+                        stream_id: media.stream_id.clone(),
+                        content: MediaNotificationContent::StreamDisconnected,
+                        annotations: Default::default(),
+                    });
+                }
+
+                self.next_warmup_id += 1;
+                let warmup_id = self.next_warmup_id;
+                let stream_id = media.stream_id.clone();
+                let timeout = self.timeout;
+                futures_channel.send_on_generic_future_completion(async move {
+                    tokio::time::sleep(timeout).await;
+                    FutureResult::WarmupTimedOut {
+                        stream_id,
+                        warmup_id,
+                    }
+                });
+
+                self.streams.insert(
+                    media.stream_id.clone(),
+                    StreamState::Warming(Box::new(WarmingStream {
+                        warmup_id,
+                        new_stream: media,
+                        metadata: None,
+                        label: None,
+                        sequence_headers: Vec::new(),
+                        has_video_sequence_header: false,
+                    })),
+                );
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                match self.streams.remove(&media.stream_id) {
+                    Some(StreamState::Announced) | None => outputs.media.push(media),
+                    Some(StreamState::Warming(_)) | Some(StreamState::Dropped) => (),
+                }
+            }
+
+            MediaNotificationContent::Metadata { .. } => {
+                match self.streams.get_mut(&media.stream_id) {
+                    Some(StreamState::Warming(stream)) => {
+                        if get_announced_label(&media.content).is_some() {
+                            stream.label = Some(media);
+                        } else {
+                            stream.metadata = Some(media);
+                        }
+                    }
+
+                    Some(StreamState::Dropped) => (),
+                    Some(StreamState::Announced) | None => outputs.media.push(media),
+                }
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type,
+                metadata,
+                is_required_for_decoding,
+                ..
+            } => {
+                let is_keyframe_metadata_key = self.is_keyframe_metadata_key;
+                let stream = match self.streams.get_mut(&media.stream_id) {
+                    Some(StreamState::Warming(stream)) => stream,
+                    Some(StreamState::Dropped) => return,
+                    Some(StreamState::Announced) | None => {
+                        outputs.media.push(media);
+                        return;
+                    }
+                };
+
+                let is_video = *media_type == MediaType::Video;
+                if *is_required_for_decoding {
+                    let media_type = *media_type;
+                    stream
+                        .sequence_headers
+                        .retain(|existing| match &existing.content {
+                            MediaNotificationContent::MediaPayload {
+                                media_type: existing_type,
+                                ..
+                            } => *existing_type != media_type,
+                            _ => true,
+                        });
+
+                    stream.sequence_headers.push(media);
+                    stream.has_video_sequence_header |= is_video;
+                    return;
+                }
+
+                let is_keyframe = is_video
+                    && metadata
+                        .iter()
+                        .filter(|m| m.key() == is_keyframe_metadata_key)
+                        .filter_map(|m| match m.value() {
+                            MetadataValue::Bool(val) => Some(val),
+                            _ => None,
+                        })
+                        .next()
+                        .unwrap_or_default();
+
+                if is_keyframe && stream.has_video_sequence_header {
+                    let stream_id = media.stream_id.clone();
+                    self.announce(stream_id, outputs);
+                    outputs.media.push(media);
+                }
+            }
+        }
+    }
+
+    /// Sends out the held announcement and cached media for a warming stream
+    fn announce(&mut self, stream_id: StreamId, outputs: &mut StepOutputs) {
+        let stream = match self
+            .streams
+            .insert(stream_id.clone(), StreamState::Announced)
+        {
+            Some(StreamState::Warming(stream)) => *stream,
+            _ => return,
+        };
+
+        info!(stream_id = %stream_id.0, "Announcing stream after warmup");
+
+        outputs.media.push(stream.new_stream);
+        outputs.media.extend(stream.label);
+        outputs.media.extend(stream.metadata);
+        outputs.media.extend(stream.sequence_headers);
+    }
+
+    fn handle_timeout(&mut self, stream_id: StreamId, warmup_id: u64, outputs: &mut StepOutputs) {
+        match self.streams.get(&stream_id) {
+            Some(StreamState::Warming(stream)) if stream.warmup_id == warmup_id => (),
+            _ => return,
+        }
+
+        self.timed_out_count += 1;
+        match self.timeout_action {
+            TimeoutAction::Announce => {
+                warn!(
+                    stream_id = %stream_id.0,
+                    "Stream did not warm up within {:?}, announcing it anyway", self.timeout
+                );
+
+                self.announce(stream_id, outputs);
+            }
+
+            TimeoutAction::Drop => {
+                warn!(
+                    stream_id = %stream_id.0,
+                    "Stream did not warm up within {:?}, dropping it", self.timeout
+                );
+
+                self.streams.insert(stream_id, StreamState::Dropped);
+            }
+        }
+    }
+}
+
+impl WorkflowStep for IngestWarmupStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            let future_result = match notification.downcast::<FutureResult>() {
+                Ok(x) => *x,
+                Err(_) => {
+                    error!("Ingest warmup step received a notification that is not a known type");
+                    return StepStatus::Error {
+                        message: "Received future result of unknown type".to_string(),
+                    };
+                }
+            };
+
+            match future_result {
+                FutureResult::WarmupTimedOut {
+                    stream_id,
+                    warmup_id,
+                } => self.handle_timeout(stream_id, warmup_id, outputs),
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            self.handle_media(media, outputs, &futures_channel);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let warming_count = self
+            .streams
+            .values()
+            .filter(|state| matches!(state, StreamState::Warming(_)))
+            .count();
+
+        let mut details = HashMap::new();
+        details.insert(
+            WARMING_STREAMS_DETAIL.to_string(),
+            warming_count.to_string(),
+        );
+
+        details.insert(
+            TIMED_OUT_COUNT_DETAIL.to_string(),
+            self.timed_out_count.to_string(),
+        );
+
+        details
+    }
+}
@@ -0,0 +1,279 @@
+use super::*;
+use crate::codecs::{AUDIO_CODEC_AAC_RAW, VIDEO_CODEC_H264_AVC};
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::common_metadata::get_is_keyframe_metadata_key;
+use crate::workflows::metadata::{MediaPayloadMetadataCollection, MetadataEntry, MetadataKeyMap};
+use crate::workflows::steps::futures_channel::FuturesChannelInnerResult;
+use crate::workflows::steps::test_utils::StepTestContext;
+use bytes::{Bytes, BytesMut};
+use std::iter;
+use std::sync::Arc;
+
+struct TestContext {
+    step_context: StepTestContext,
+    is_keyframe_metadata_key: MetadataKey,
+    stream_id: StreamId,
+}
+
+impl TestContext {
+    fn new(parameters: &[(&str, &str)]) -> Self {
+        let mut metadata_map = MetadataKeyMap::new();
+        let is_keyframe_metadata_key = get_is_keyframe_metadata_key(&mut metadata_map);
+
+        let mut definition = WorkflowStepDefinition {
+            step_type: WorkflowStepType("ingest_warmup".to_string()),
+            parameters: HashMap::new(),
+        };
+
+        for (name, value) in parameters {
+            definition
+                .parameters
+                .insert(name.to_string(), Some(value.to_string()));
+        }
+
+        let generator = IngestWarmupStepGenerator::new(is_keyframe_metadata_key);
+        let step_context =
+            StepTestContext::new(Box::new(generator), definition).expect("Failed to create step");
+
+        TestContext {
+            step_context,
+            is_keyframe_metadata_key,
+            stream_id: StreamId(Arc::new("abc".to_string())),
+        }
+    }
+
+    fn new_stream(&self) -> MediaNotification {
+        MediaNotification {
+            stream_id: self.stream_id.clone(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        }
+    }
+
+    fn video(
+        &self,
+        timestamp: u64,
+        is_keyframe: bool,
+        is_sequence_header: bool,
+    ) -> MediaNotification {
+        let mut buffer = BytesMut::new();
+        let entry = MetadataEntry::new(
+            self.is_keyframe_metadata_key,
+            MetadataValue::Bool(is_keyframe),
+            &mut buffer,
+        )
+        .unwrap();
+
+        MediaNotification {
+            stream_id: self.stream_id.clone(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type: VIDEO_CODEC_H264_AVC.clone(),
+                timestamp: Duration::from_millis(timestamp),
+                metadata: MediaPayloadMetadataCollection::new(iter::once(entry), &mut buffer),
+                data: Bytes::from(vec![timestamp as u8]),
+                is_required_for_decoding: is_sequence_header,
+            },
+            annotations: Default::default(),
+        }
+    }
+
+    fn audio(&self, timestamp: u64, is_sequence_header: bool) -> MediaNotification {
+        MediaNotification {
+            stream_id: self.stream_id.clone(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Audio,
+                payload_type: AUDIO_CODEC_AAC_RAW.clone(),
+                timestamp: Duration::from_millis(timestamp),
+                metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+                data: Bytes::from(vec![timestamp as u8]),
+                is_required_for_decoding: is_sequence_header,
+            },
+            annotations: Default::default(),
+        }
+    }
+
+    fn disconnection(&self) -> MediaNotification {
+        MediaNotification {
+            stream_id: self.stream_id.clone(),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        }
+    }
+
+    async fn execute_timeout(&mut self) {
+        match self.step_context.expect_future_resolved().await {
+            FuturesChannelInnerResult::Generic(notification) => {
+                let mut inputs = StepInputs::new();
+                let mut outputs = StepOutputs::new();
+                inputs.notifications.push(notification);
+
+                let channel = self.step_context.futures_channel_sender.clone();
+                self.step_context
+                    .step
+                    .execute(&mut inputs, &mut outputs, channel);
+
+                self.step_context.media_outputs = outputs.media;
+            }
+
+            FuturesChannelInnerResult::Media(_) => panic!("Expected generic future result"),
+        }
+    }
+}
+
+#[test]
+fn invalid_timeout_action_returns_error() {
+    let mut metadata_map = MetadataKeyMap::new();
+    let generator = IngestWarmupStepGenerator::new(get_is_keyframe_metadata_key(&mut metadata_map));
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("ingest_warmup".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    definition
+        .parameters
+        .insert(ON_TIMEOUT.to_string(), Some("wait".to_string()));
+
+    let result = StepTestContext::new(Box::new(generator), definition);
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[tokio::test]
+async fn announcement_delayed_until_first_keyframe() {
+    let mut context = TestContext::new(&[]);
+    let new_stream = context.new_stream();
+    let audio_header = context.audio(0, true);
+    let video_header = context.video(0, true, true);
+    let keyframe = context.video(20, true, false);
+
+    context
+        .step_context
+        .assert_media_not_passed_through(new_stream.clone());
+    context
+        .step_context
+        .assert_media_not_passed_through(audio_header.clone());
+
+    context
+        .step_context
+        .assert_media_not_passed_through(video_header.clone());
+
+    // Media before the first keyframe can't be decoded
+    let audio = context.audio(5, false);
+    context.step_context.assert_media_not_passed_through(audio);
+
+    let non_keyframe = context.video(10, false, false);
+    context
+        .step_context
+        .assert_media_not_passed_through(non_keyframe);
+
+    context.step_context.execute_with_media(keyframe.clone());
+    assert_eq!(
+        context.step_context.media_outputs,
+        vec![new_stream, audio_header, video_header, keyframe],
+        "Unexpected media after keyframe"
+    );
+
+    let audio = context.audio(25, false);
+    context.step_context.assert_media_passed_through(audio);
+
+    let non_keyframe = context.video(30, false, false);
+    context
+        .step_context
+        .assert_media_passed_through(non_keyframe);
+
+    let disconnection = context.disconnection();
+    context
+        .step_context
+        .assert_media_passed_through(disconnection);
+}
+
+#[tokio::test]
+async fn keyframe_without_sequence_header_does_not_announce_stream() {
+    let mut context = TestContext::new(&[]);
+    let new_stream = context.new_stream();
+    let keyframe = context.video(20, true, false);
+
+    context.step_context.execute_with_media(new_stream);
+    context
+        .step_context
+        .assert_media_not_passed_through(keyframe);
+}
+
+#[tokio::test]
+async fn stream_announced_on_timeout_when_configured() {
+    let mut context = TestContext::new(&[(TIMEOUT, "1"), (ON_TIMEOUT, "announce")]);
+    let new_stream = context.new_stream();
+    let audio_header = context.audio(0, true);
+
+    context.step_context.execute_with_media(new_stream.clone());
+    context
+        .step_context
+        .execute_with_media(audio_header.clone());
+    context.execute_timeout().await;
+
+    assert_eq!(
+        context.step_context.media_outputs,
+        vec![new_stream, audio_header],
+        "Unexpected media after timeout"
+    );
+
+    let audio = context.audio(5, false);
+    context.step_context.assert_media_passed_through(audio);
+}
+
+#[tokio::test]
+async fn stream_dropped_on_timeout_when_configured() {
+    let mut context = TestContext::new(&[(TIMEOUT, "1"), (ON_TIMEOUT, "drop")]);
+    let new_stream = context.new_stream();
+    let audio_header = context.audio(0, true);
+
+    context.step_context.execute_with_media(new_stream);
+    context.step_context.execute_with_media(audio_header);
+    context.execute_timeout().await;
+
+    assert!(
+        context.step_context.media_outputs.is_empty(),
+        "Expected no media after timeout"
+    );
+
+    let video_header = context.video(10, true, true);
+    let keyframe = context.video(20, true, false);
+    let disconnection = context.disconnection();
+    context
+        .step_context
+        .assert_media_not_passed_through(video_header);
+    context
+        .step_context
+        .assert_media_not_passed_through(keyframe);
+    context
+        .step_context
+        .assert_media_not_passed_through(disconnection);
+}
+
+#[tokio::test]
+async fn timeout_ignored_once_stream_is_announced() {
+    let mut context = TestContext::new(&[(TIMEOUT, "1")]);
+    let new_stream = context.new_stream();
+    let video_header = context.video(0, true, true);
+    let keyframe = context.video(20, true, false);
+
+    context.step_context.execute_with_media(new_stream);
+    context.step_context.execute_with_media(video_header);
+    context.step_context.execute_with_media(keyframe);
+    context.execute_timeout().await;
+
+    assert!(
+        context.step_context.media_outputs.is_empty(),
+        "Expected no media after timeout"
+    );
+}
+
+#[test]
+fn media_for_unknown_streams_passed_through() {
+    let mut context = TestContext::new(&[]);
+    let audio = context.audio(0, false);
+
+    context.step_context.assert_media_passed_through(audio);
+}
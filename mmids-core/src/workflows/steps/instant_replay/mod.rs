@@ -0,0 +1,646 @@
+//! The instant replay step keeps a rolling buffer of the most recent media of each stream, and on
+//! command plays part of that buffer back out as a separate replay stream while the live stream
+//! continues untouched. The buffer holds up to `window` seconds (60 by default) of media per
+//! stream, measured by the media's timestamps.
+//!
+//! A replay is triggered by a metadata notification on the live stream containing only the
+//! `INSTANT_REPLAY_METADATA_KEY` key, with the number of seconds to go back as its value. An
+//! empty value goes back the `replay_length` (10 seconds by default). These notifications are
+//! consumed by the step.
+//!
+//! The replay starts at the last video keyframe at or before the requested point, so it's
+//! decodable from its first frame, and plays through to the newest media that was buffered when
+//! the command was received. It's announced as a new stream named `<stream name>_replay`, starts
+//! with the live stream's latest sequence headers, and has its timestamps rebased to start at
+//! zero. Replay media is released at the pace its timestamps dictate, multiplied by the `speed`
+//! parameter (1.0 by default, e.g. 0.5 for a slow motion replay). The replay stream is
+//! disconnected once all of its media has been played. Triggering another replay of the same
+//! stream ends the replay already in progress.
+
+#[cfg(test)]
+mod tests;
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::metadata::{MediaPayloadMetadataCollection, MetadataKey, MetadataValue};
+use crate::workflows::pacing::MediaPacer;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use crate::StreamId;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+pub const WINDOW: &str = "window";
+pub const REPLAY_LENGTH: &str = "replay_length";
+pub const SPEED: &str = "speed";
+
+/// The metadata key a replay is requested with. The value is how many seconds back the replay
+/// should start, or empty to use the step's configured replay length.
+pub const INSTANT_REPLAY_METADATA_KEY: &str = "mmids_instant_replay";
+
+const BUFFER_DEPTH_DETAIL: &str = "buffer_depth";
+const ACTIVE_REPLAYS_DETAIL: &str = "active_replays";
+const REPLAYS_STARTED_DETAIL: &str = "replays_started";
+
+/// Generates new instances of the instant replay workflow step
+pub struct InstantReplayStepGenerator {
+    is_keyframe_metadata_key: MetadataKey,
+}
+
+struct BufferedPayload {
+    timestamp: Duration,
+    media_type: MediaType,
+    is_keyframe: bool,
+    content: MediaNotificationContent,
+}
+
+struct BufferedStream {
+    stream_name: Arc<String>,
+    video_sequence_header: Option<MediaNotificationContent>,
+    audio_sequence_header: Option<MediaNotificationContent>,
+    payloads: VecDeque<BufferedPayload>,
+}
+
+struct ActiveReplay {
+    replay_id: u64,
+    stream_id: StreamId,
+    stream_name: Arc<String>,
+    cancellation_token: CancellationToken,
+}
+
+struct InstantReplayStep {
+    is_keyframe_metadata_key: MetadataKey,
+    window: Duration,
+    replay_length: Duration,
+    speed: f64,
+    streams: HashMap<StreamId, BufferedStream>,
+
+    /// Replays in progress, keyed by the live stream they are replaying
+    replays: HashMap<StreamId, ActiveReplay>,
+    next_replay_id: u64,
+    cancellation_token: CancellationToken,
+}
+
+enum FutureResult {
+    ReplayMediaReceived {
+        source_stream_id: StreamId,
+        replay_id: u64,
+        content: MediaNotificationContent,
+    },
+
+    ReplayFinished {
+        source_stream_id: StreamId,
+        replay_id: u64,
+    },
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "Invalid {parameter} value of '{value}' specified, must be a number of seconds greater \
+        than zero"
+    )]
+    InvalidDuration {
+        parameter: &'static str,
+        value: String,
+    },
+
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a number greater than zero",
+        SPEED
+    )]
+    InvalidSpeed(String),
+}
+
+impl InstantReplayStepGenerator {
+    pub fn new(is_keyframe_metadata_key: MetadataKey) -> Self {
+        InstantReplayStepGenerator {
+            is_keyframe_metadata_key,
+        }
+    }
+}
+
+impl StepGenerator for InstantReplayStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let window = match definition.parameters.get(WINDOW) {
+            Some(Some(value)) => match parse_seconds(value) {
+                Some(window) => window,
+                None => {
+                    return Err(Box::new(StepStartupError::InvalidDuration {
+                        parameter: WINDOW,
+                        value: value.clone(),
+                    }))
+                }
+            },
+
+            _ => Duration::from_secs(60),
+        };
+
+        let replay_length = match definition.parameters.get(REPLAY_LENGTH) {
+            Some(Some(value)) => match parse_seconds(value) {
+                Some(length) => length,
+                None => {
+                    return Err(Box::new(StepStartupError::InvalidDuration {
+                        parameter: REPLAY_LENGTH,
+                        value: value.clone(),
+                    }))
+                }
+            },
+
+            _ => Duration::from_secs(10),
+        };
+
+        let speed = match definition.parameters.get(SPEED) {
+            Some(Some(value)) => match value.trim().parse::<f64>() {
+                Ok(speed) if speed.is_finite() && speed > 0.0 => speed,
+                _ => return Err(Box::new(StepStartupError::InvalidSpeed(value.clone()))),
+            },
+
+            _ => 1.0,
+        };
+
+        let step = InstantReplayStep {
+            is_keyframe_metadata_key: self.is_keyframe_metadata_key,
+            window,
+            replay_length,
+            speed,
+            streams: HashMap::new(),
+            replays: HashMap::new(),
+            next_replay_id: 0,
+            cancellation_token: CancellationToken::new(),
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+fn parse_seconds(value: &str) -> Option<Duration> {
+    match value.trim().parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds > 0.0 => {
+            Some(Duration::from_secs_f64(seconds))
+        }
+
+        _ => None,
+    }
+}
+
+/// Returns the value of the replay request in the notification content, if it's a replay request
+fn get_replay_request(content: &MediaNotificationContent) -> Option<&str> {
+    match content {
+        MediaNotificationContent::Metadata { data } if data.len() == 1 => data
+            .get(INSTANT_REPLAY_METADATA_KEY)
+            .map(|value| value.as_str()),
+
+        _ => None,
+    }
+}
+
+impl BufferedStream {
+    fn new(stream_name: Arc<String>) -> Self {
+        BufferedStream {
+            stream_name,
+            video_sequence_header: None,
+            audio_sequence_header: None,
+            payloads: VecDeque::new(),
+        }
+    }
+
+    fn depth(&self) -> Duration {
+        match (self.payloads.front(), self.payloads.back()) {
+            (Some(oldest), Some(newest)) => newest.timestamp.saturating_sub(oldest.timestamp),
+            _ => Duration::new(0, 0),
+        }
+    }
+
+    fn add_payload(&mut self, payload: BufferedPayload, window: Duration) {
+        let newest = self.payloads.back().map(|payload| payload.timestamp);
+        if newest.is_some_and(|newest| payload.timestamp + window < newest) {
+            // Timestamps went back further than the window can cover, so they were most likely
+            // reset and the buffered media no longer shares a timeline with new media
+            self.payloads.clear();
+        }
+
+        let newest = newest.map_or(payload.timestamp, |newest| newest.max(payload.timestamp));
+        self.payloads.push_back(payload);
+
+        while let Some(oldest) = self.payloads.front() {
+            if oldest.timestamp + window >= newest {
+                break;
+            }
+
+            self.payloads.pop_front();
+        }
+    }
+
+    /// Finds where in the buffer a replay going back the specified duration should start
+    fn replay_start(&self, go_back: Duration) -> Option<usize> {
+        let newest = self.payloads.back()?.timestamp;
+        let target = newest.saturating_sub(go_back);
+
+        let has_video = self
+            .payloads
+            .iter()
+            .any(|payload| payload.media_type == MediaType::Video);
+
+        if !has_video {
+            return self
+                .payloads
+                .iter()
+                .position(|payload| payload.timestamp >= target);
+        }
+
+        // Video can only be decoded from a keyframe, so start at the keyframe closest to the
+        // requested point that still covers it, or the first keyframe available if none do
+        let keyframes = self
+            .payloads
+            .iter()
+            .enumerate()
+            .filter(|(_, payload)| payload.media_type == MediaType::Video && payload.is_keyframe)
+            .map(|(index, payload)| (index, payload.timestamp))
+            .collect::<Vec<_>>();
+
+        keyframes
+            .iter()
+            .rev()
+            .find(|(_, timestamp)| *timestamp <= target)
+            .or_else(|| keyframes.first())
+            .map(|(index, _)| *index)
+    }
+}
+
+impl InstantReplayStep {
+    fn handle_media(
+        &mut self,
+        media: MediaNotification,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        if let Some(value) = get_replay_request(&media.content) {
+            let go_back = if value.trim().is_empty() {
+                Some(self.replay_length)
+            } else {
+                parse_seconds(value)
+            };
+
+            match go_back {
+                Some(go_back) => {
+                    self.start_replay(&media.stream_id, go_back, outputs, futures_channel)
+                }
+
+                None => warn!(
+                    stream_id = %media.stream_id.0,
+                    "Ignoring instant replay request with invalid length of '{}'", value
+                ),
+            }
+
+            return;
+        }
+
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                self.streams.insert(
+                    media.stream_id.clone(),
+                    BufferedStream::new(stream_name.clone()),
+                );
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                // Replays already in progress keep playing, since they have all their media
+                self.streams.remove(&media.stream_id);
+            }
+
+            MediaNotificationContent::Metadata { .. } => (),
+
+            MediaNotificationContent::MediaPayload {
+                media_type,
+                timestamp,
+                metadata,
+                is_required_for_decoding,
+                ..
+            } => {
+                let is_keyframe = self.is_keyframe(metadata);
+                if let Some(stream) = self.streams.get_mut(&media.stream_id) {
+                    if *is_required_for_decoding {
+                        match media_type {
+                            MediaType::Video => {
+                                stream.video_sequence_header = Some(media.content.clone())
+                            }
+
+                            MediaType::Audio => {
+                                stream.audio_sequence_header = Some(media.content.clone())
+                            }
+
+                            MediaType::Other => (),
+                        }
+                    } else {
+                        let payload = BufferedPayload {
+                            timestamp: *timestamp,
+                            media_type: *media_type,
+                            is_keyframe,
+                            content: media.content.clone(),
+                        };
+
+                        stream.add_payload(payload, self.window);
+                    }
+                }
+            }
+        }
+
+        outputs.media.push(media);
+    }
+
+    fn is_keyframe(&self, metadata: &MediaPayloadMetadataCollection) -> bool {
+        metadata
+            .iter()
+            .filter(|m| m.key() == self.is_keyframe_metadata_key)
+            .filter_map(|m| match m.value() {
+                MetadataValue::Bool(val) => Some(val),
+                _ => None,
+            })
+            .next()
+            .unwrap_or_default()
+    }
+
+    fn start_replay(
+        &mut self,
+        source_stream_id: &StreamId,
+        go_back: Duration,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let stream = match self.streams.get(source_stream_id) {
+            Some(stream) => stream,
+            None => {
+                warn!(
+                    stream_id = %source_stream_id.0,
+                    "Instant replay requested for a stream that's not connected"
+                );
+
+                return;
+            }
+        };
+
+        let start = match stream.replay_start(go_back) {
+            Some(start) => start,
+            None => {
+                warn!(
+                    stream_id = %source_stream_id.0,
+                    "Instant replay requested but no replayable media is buffered"
+                );
+
+                return;
+            }
+        };
+
+        let start_timestamp = stream.payloads[start].timestamp;
+        let mut media = stream
+            .video_sequence_header
+            .iter()
+            .chain(stream.audio_sequence_header.iter())
+            .map(|header| rebase(header.clone(), Duration::new(0, 0)))
+            .collect::<Vec<_>>();
+
+        media.extend(stream.payloads.iter().skip(start).map(|payload| {
+            rebase(
+                payload.content.clone(),
+                payload.timestamp.saturating_sub(start_timestamp),
+            )
+        }));
+
+        let replay_duration = stream
+            .payloads
+            .back()
+            .map_or(Duration::new(0, 0), |newest| {
+                newest.timestamp.saturating_sub(start_timestamp)
+            });
+
+        let stream_name = Arc::new(format!("{}_replay", stream.stream_name));
+        self.end_replay(source_stream_id, outputs);
+
+        info!(
+            stream_id = %source_stream_id.0,
+            "Starting instant replay '{}' of the last {:.1}s",
+            stream_name,
+            replay_duration.as_secs_f64(),
+        );
+
+        let stream_id = StreamId(Arc::new(Uuid::new_v4().to_string()));
+        outputs.media.push(MediaNotification {
+            stream_id: stream_id.clone(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: stream_name.clone(),
+            },
+            annotations: Default::default(),
+        });
+
+        self.next_replay_id += 1;
+        let replay_id = self.next_replay_id;
+        let cancellation_token = self.cancellation_token.child_token();
+
+        let (sender, receiver) = unbounded_channel();
+        tokio::spawn(play_replay(
+            media,
+            self.speed,
+            sender,
+            cancellation_token.clone(),
+        ));
+
+        let media_stream_id = source_stream_id.clone();
+        let finished_stream_id = source_stream_id.clone();
+        futures_channel.send_on_generic_unbounded_recv(
+            receiver,
+            move |content| FutureResult::ReplayMediaReceived {
+                source_stream_id: media_stream_id.clone(),
+                replay_id,
+                content,
+            },
+            move || FutureResult::ReplayFinished {
+                source_stream_id: finished_stream_id,
+                replay_id,
+            },
+        );
+
+        self.replays.insert(
+            source_stream_id.clone(),
+            ActiveReplay {
+                replay_id,
+                stream_id,
+                stream_name,
+                cancellation_token,
+            },
+        );
+    }
+
+    /// Stops the replay of the specified live stream, if one is in progress
+    fn end_replay(&mut self, source_stream_id: &StreamId, outputs: &mut StepOutputs) {
+        if let Some(replay) = self.replays.remove(source_stream_id) {
+            replay.cancellation_token.cancel();
+            outputs.media.push(MediaNotification {
+                stream_id: replay.stream_id,
+                content: MediaNotificationContent::StreamDisconnected,
+                annotations: Default::default(),
+            });
+        }
+    }
+
+    fn handle_future_result(&mut self, result: FutureResult, outputs: &mut StepOutputs) {
+        match result {
+            FutureResult::ReplayMediaReceived {
+                source_stream_id,
+                replay_id,
+                content,
+            } => {
+                if let Some(replay) = self.replays.get(&source_stream_id) {
+                    if replay.replay_id == replay_id {
+                        outputs.media.push(MediaNotification {
+                            stream_id: replay.stream_id.clone(),
+                            content,
+                            annotations: Default::default(),
+                        });
+                    }
+                }
+            }
+
+            FutureResult::ReplayFinished {
+                source_stream_id,
+                replay_id,
+            } => {
+                let is_current = self
+                    .replays
+                    .get(&source_stream_id)
+                    .is_some_and(|replay| replay.replay_id == replay_id);
+
+                // Replays that were replaced have already been disconnected
+                if is_current {
+                    info!(stream_id = %source_stream_id.0, "Instant replay finished");
+                    self.end_replay(&source_stream_id, outputs);
+                }
+            }
+        }
+    }
+}
+
+/// Returns the content with its timestamp replaced
+fn rebase(content: MediaNotificationContent, new_timestamp: Duration) -> MediaNotificationContent {
+    match content {
+        MediaNotificationContent::MediaPayload {
+            media_type,
+            payload_type,
+            metadata,
+            data,
+            is_required_for_decoding,
+            ..
+        } => MediaNotificationContent::MediaPayload {
+            media_type,
+            payload_type,
+            timestamp: new_timestamp,
+            metadata,
+            data,
+            is_required_for_decoding,
+        },
+
+        content => content,
+    }
+}
+
+impl WorkflowStep for InstantReplayStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            match notification.downcast::<FutureResult>() {
+                Ok(result) => self.handle_future_result(*result, outputs),
+                Err(_) => {
+                    error!("Instant replay step received a notification that is not a known type");
+                }
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            self.handle_media(media, outputs, &futures_channel);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut depths = self
+            .streams
+            .values()
+            .map(|stream| {
+                format!(
+                    "{}: {:.1}s",
+                    stream.stream_name,
+                    stream.depth().as_secs_f64()
+                )
+            })
+            .collect::<Vec<_>>();
+
+        depths.sort();
+
+        let mut replays = self
+            .replays
+            .values()
+            .map(|replay| replay.stream_name.to_string())
+            .collect::<Vec<_>>();
+
+        replays.sort();
+
+        let mut details = HashMap::new();
+        details.insert(BUFFER_DEPTH_DETAIL.to_string(), depths.join(", "));
+        details.insert(ACTIVE_REPLAYS_DETAIL.to_string(), replays.join(", "));
+        details.insert(
+            REPLAYS_STARTED_DETAIL.to_string(),
+            self.next_replay_id.to_string(),
+        );
+
+        details
+    }
+}
+
+impl Drop for InstantReplayStep {
+    fn drop(&mut self) {
+        self.cancellation_token.cancel();
+    }
+}
+
+/// Sends the replay's media out at the pace its timestamps dictate
+async fn play_replay(
+    media: Vec<MediaNotificationContent>,
+    speed: f64,
+    sender: UnboundedSender<MediaNotificationContent>,
+    cancellation_token: CancellationToken,
+) {
+    let mut pacer = MediaPacer::new(speed);
+    pacer.start(Duration::new(0, 0));
+    for content in media {
+        if let MediaNotificationContent::MediaPayload { timestamp, .. } = &content {
+            tokio::select! {
+                _ = pacer.wait_until_due(*timestamp) => (),
+                _ = cancellation_token.cancelled() => return,
+            }
+        }
+
+        if sender.send(content).is_err() {
+            break; // step is gone
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use super::*;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::common_metadata::get_is_keyframe_metadata_key;
+use crate::workflows::metadata::{MetadataEntry, MetadataKeyMap};
+use crate::workflows::steps::test_utils::StepTestContext;
+use bytes::{Bytes, BytesMut};
+use std::iter;
+
+const STREAM_NAME: &str = "live";
+
+struct TestContext {
+    step_context: StepTestContext,
+    is_keyframe_metadata_key: MetadataKey,
+}
+
+fn definition(parameters: &[(&str, &str)]) -> WorkflowStepDefinition {
+    WorkflowStepDefinition {
+        step_type: WorkflowStepType("instant_replay".to_string()),
+        parameters: parameters
+            .iter()
+            .map(|(key, value)| (key.to_string(), Some(value.to_string())))
+            .collect(),
+    }
+}
+
+fn live_stream_id() -> StreamId {
+    StreamId(Arc::new("live-id".to_string()))
+}
+
+fn replay_request(seconds: &str) -> MediaNotification {
+    let mut data = HashMap::new();
+    data.insert(INSTANT_REPLAY_METADATA_KEY.to_string(), seconds.to_string());
+
+    MediaNotification {
+        stream_id: live_stream_id(),
+        content: MediaNotificationContent::Metadata { data },
+        annotations: Default::default(),
+    }
+}
+
+impl TestContext {
+    fn new(parameters: &[(&str, &str)]) -> Self {
+        let mut metadata_map = MetadataKeyMap::new();
+        let is_keyframe_metadata_key = get_is_keyframe_metadata_key(&mut metadata_map);
+        let generator = InstantReplayStepGenerator::new(is_keyframe_metadata_key);
+        let step_context = StepTestContext::new(Box::new(generator), definition(parameters))
+            .expect("Failed to create step");
+
+        let mut context = TestContext {
+            step_context,
+            is_keyframe_metadata_key,
+        };
+
+        context
+            .step_context
+            .assert_media_passed_through(MediaNotification {
+                stream_id: live_stream_id(),
+                content: MediaNotificationContent::NewIncomingStream {
+                    stream_name: Arc::new(STREAM_NAME.to_string()),
+                },
+                annotations: Default::default(),
+            });
+
+        context
+    }
+
+    fn payload(
+        &self,
+        media_type: MediaType,
+        timestamp: u64,
+        is_keyframe: bool,
+        is_sequence_header: bool,
+    ) -> MediaNotification {
+        let mut buffer = BytesMut::new();
+        let entry = MetadataEntry::new(
+            self.is_keyframe_metadata_key,
+            MetadataValue::Bool(is_keyframe),
+            &mut buffer,
+        )
+        .unwrap();
+
+        MediaNotification {
+            stream_id: live_stream_id(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type,
+                payload_type: Arc::new("test".to_string()),
+                timestamp: Duration::from_millis(timestamp),
+                metadata: MediaPayloadMetadataCollection::new(iter::once(entry), &mut buffer),
+                data: Bytes::from(format!("{:?}-{}", media_type, timestamp)),
+                is_required_for_decoding: is_sequence_header,
+            },
+            annotations: Default::default(),
+        }
+    }
+
+    fn video(&self, timestamp: u64, is_keyframe: bool) -> MediaNotification {
+        self.payload(MediaType::Video, timestamp, is_keyframe, false)
+    }
+
+    fn detail(&self, name: &str) -> String {
+        self.step_context
+            .step
+            .get_state_details()
+            .get(name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Gathers all media raised by the step over the specified number of future polls
+    async fn collect_replay_media(&mut self, iterations: usize) -> Vec<MediaNotification> {
+        let mut received = Vec::new();
+        for _ in 0..iterations {
+            self.step_context.execute_pending_futures().await;
+            received.append(&mut self.step_context.media_outputs);
+        }
+
+        received
+    }
+}
+
+fn payload_of(media: &MediaNotification) -> (Duration, Bytes) {
+    match &media.content {
+        MediaNotificationContent::MediaPayload {
+            timestamp, data, ..
+        } => (*timestamp, data.clone()),
+
+        content => panic!("Expected media payload, instead got {:?}", content),
+    }
+}
+
+#[test]
+fn invalid_parameters_return_error() {
+    let cases: &[&[(&str, &str)]] = &[
+        &[(WINDOW, "0")],
+        &[(WINDOW, "abc")],
+        &[(REPLAY_LENGTH, "-5")],
+        &[(SPEED, "0")],
+    ];
+
+    for parameters in cases {
+        let mut metadata_map = MetadataKeyMap::new();
+        let generator =
+            InstantReplayStepGenerator::new(get_is_keyframe_metadata_key(&mut metadata_map));
+        let result = StepTestContext::new(Box::new(generator), definition(parameters));
+
+        assert!(result.is_err(), "Expected error for {:?}", parameters);
+    }
+}
+
+#[test]
+fn live_media_passed_through() {
+    let mut context = TestContext::new(&[]);
+
+    let media = context.payload(MediaType::Video, 0, false, true);
+    context.step_context.assert_media_passed_through(media);
+
+    let media = context.video(10, true);
+    context.step_context.assert_media_passed_through(media);
+
+    let media = context.payload(MediaType::Audio, 15, false, false);
+    context.step_context.assert_media_passed_through(media);
+}
+
+#[test]
+fn buffer_bounded_by_window() {
+    let mut context = TestContext::new(&[(WINDOW, "1")]);
+
+    for timestamp in (0..=3000).step_by(500) {
+        let media = context.video(timestamp, timestamp % 1000 == 0);
+        context.step_context.execute_with_media(media);
+    }
+
+    assert_eq!(
+        context.detail(BUFFER_DEPTH_DETAIL),
+        format!("{}: 1.0s", STREAM_NAME),
+        "Unexpected buffer depth"
+    );
+}
+
+#[tokio::test]
+async fn replay_request_plays_buffered_media_as_new_stream() {
+    let mut context = TestContext::new(&[(WINDOW, "10")]);
+
+    let header = context.payload(MediaType::Video, 0, false, true);
+    context.step_context.execute_with_media(header);
+
+    let live_media = vec![
+        context.video(0, true),
+        context.video(100, false),
+        context.video(200, true),
+        context.video(300, false),
+        context.payload(MediaType::Audio, 350, false, false),
+        context.video(400, false),
+    ];
+
+    for media in live_media {
+        context.step_context.execute_with_media(media);
+    }
+
+    // Going back 150ms lands at 250ms, so the replay must start at the keyframe before it
+    context
+        .step_context
+        .execute_with_media(replay_request("0.15"));
+
+    let outputs = context.step_context.media_outputs.clone();
+    assert_eq!(outputs.len(), 1, "Expected only the replay announcement");
+    assert_ne!(
+        outputs[0].stream_id,
+        live_stream_id(),
+        "Replay should not use the live stream's id"
+    );
+
+    let replay_stream_id = outputs[0].stream_id.clone();
+    match &outputs[0].content {
+        MediaNotificationContent::NewIncomingStream { stream_name } => assert_eq!(
+            stream_name.as_str(),
+            "live_replay",
+            "Unexpected replay stream name"
+        ),
+
+        content => panic!("Expected new stream, instead got {:?}", content),
+    }
+
+    assert_eq!(
+        context.detail(ACTIVE_REPLAYS_DETAIL),
+        "live_replay",
+        "Unexpected active replays"
+    );
+
+    // The live stream keeps flowing while the replay plays
+    let media = context.video(500, false);
+    context.step_context.execute_with_media(media.clone());
+    assert_eq!(
+        context.step_context.media_outputs,
+        vec![media],
+        "Live media should be passed through during the replay"
+    );
+
+    let replayed = context.collect_replay_media(30).await;
+    assert!(
+        replayed
+            .iter()
+            .all(|media| media.stream_id == replay_stream_id),
+        "All replayed media should be on the replay stream"
+    );
+
+    let payloads = replayed
+        .iter()
+        .filter(|media| !matches!(media.content, MediaNotificationContent::StreamDisconnected))
+        .map(payload_of)
+        .collect::<Vec<_>>();
+
+    let expected = vec![
+        (Duration::from_millis(0), Bytes::from("Video-0")),
+        (Duration::from_millis(0), Bytes::from("Video-200")),
+        (Duration::from_millis(100), Bytes::from("Video-300")),
+        (Duration::from_millis(150), Bytes::from("Audio-350")),
+        (Duration::from_millis(200), Bytes::from("Video-400")),
+    ];
+
+    assert_eq!(payloads, expected, "Unexpected replayed media");
+    assert_eq!(
+        replayed.last().map(|media| &media.content),
+        Some(&MediaNotificationContent::StreamDisconnected),
+        "Expected replay stream to be disconnected after its media"
+    );
+
+    assert_eq!(
+        context.detail(ACTIVE_REPLAYS_DETAIL),
+        "",
+        "Expected no active replays"
+    );
+
+    assert_eq!(
+        context.detail(REPLAYS_STARTED_DETAIL),
+        "1",
+        "Unexpected replay count"
+    );
+}
+
+#[tokio::test]
+async fn new_replay_request_ends_replay_in_progress() {
+    let mut context = TestContext::new(&[(SPEED, "0.01")]);
+
+    for timestamp in (0..=1000).step_by(100) {
+        let media = context.video(timestamp, timestamp % 500 == 0);
+        context.step_context.execute_with_media(media);
+    }
+
+    context.step_context.execute_with_media(replay_request(""));
+    let first_replay = context.step_context.media_outputs[0].stream_id.clone();
+
+    context
+        .step_context
+        .execute_with_media(replay_request("0.5"));
+    let outputs = context.step_context.media_outputs.clone();
+    assert_eq!(outputs.len(), 2, "Unexpected number of outputs");
+    assert_eq!(
+        outputs[0],
+        MediaNotification {
+            stream_id: first_replay.clone(),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        },
+        "Expected the first replay to be disconnected"
+    );
+
+    assert!(
+        matches!(
+            outputs[1].content,
+            MediaNotificationContent::NewIncomingStream { .. }
+        ),
+        "Expected the second replay to be announced"
+    );
+
+    let replayed = context.collect_replay_media(3).await;
+    assert!(
+        replayed.iter().all(|media| media.stream_id != first_replay),
+        "No media should be raised for the replaced replay"
+    );
+}
+
+#[test]
+fn replay_request_without_buffered_media_is_ignored() {
+    let mut context = TestContext::new(&[]);
+
+    context.step_context.execute_with_media(replay_request("5"));
+
+    assert!(
+        context.step_context.media_outputs.is_empty(),
+        "Expected the request to be consumed without starting a replay"
+    );
+
+    assert_eq!(
+        context.detail(REPLAYS_STARTED_DETAIL),
+        "0",
+        "Unexpected replay count"
+    );
+}
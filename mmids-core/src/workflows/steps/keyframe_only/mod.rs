@@ -0,0 +1,231 @@
+//! The keyframe only step creates a low bandwidth monitoring feed for each stream passing through
+//! it. Alongside the original stream, which is passed through untouched, a derived stream is
+//! announced containing only the original stream's sequence headers and video keyframes. This
+//! produces a very low frame rate stream that's still decodable, which is useful for monitoring
+//! streams over constrained links.
+//!
+//! The derived stream is announced with the original stream's name followed by the `name_suffix`
+//! (`_keyframes` by default), and is disconnected when the original stream disconnects. Audio is
+//! dropped from the derived stream unless `audio` is set to `keep`.
+
+#[cfg(test)]
+mod tests;
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::metadata::{MediaPayloadMetadataCollection, MetadataKey, MetadataValue};
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use crate::StreamId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::info;
+use uuid::Uuid;
+
+pub const NAME_SUFFIX: &str = "name_suffix";
+pub const AUDIO: &str = "audio";
+
+const DEFAULT_NAME_SUFFIX: &str = "_keyframes";
+
+const DERIVED_STREAMS_DETAIL: &str = "derived_streams";
+const DROPPED_FRAME_COUNT_DETAIL: &str = "dropped_frame_count";
+
+/// Generates new instances of the keyframe only workflow step
+pub struct KeyframeOnlyStepGenerator {
+    is_keyframe_metadata_key: MetadataKey,
+}
+
+struct KeyframeOnlyStep {
+    is_keyframe_metadata_key: MetadataKey,
+    name_suffix: String,
+    keep_audio: bool,
+
+    /// The derived stream id for each original stream id
+    derived_streams: HashMap<StreamId, StreamId>,
+    dropped_frame_count: u64,
+}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "Invalid {} value of '{0}' specified, must be either 'keep' or 'drop'",
+        AUDIO
+    )]
+    InvalidAudioOption(String),
+
+    #[error("The {} parameter can not be empty", NAME_SUFFIX)]
+    EmptyNameSuffix,
+}
+
+impl KeyframeOnlyStepGenerator {
+    pub fn new(is_keyframe_metadata_key: MetadataKey) -> Self {
+        KeyframeOnlyStepGenerator {
+            is_keyframe_metadata_key,
+        }
+    }
+}
+
+impl StepGenerator for KeyframeOnlyStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let name_suffix = match definition.parameters.get(NAME_SUFFIX) {
+            Some(Some(value)) if value.trim().is_empty() => {
+                return Err(Box::new(StepStartupError::EmptyNameSuffix));
+            }
+
+            Some(Some(value)) => value.trim().to_string(),
+            Some(None) => return Err(Box::new(StepStartupError::EmptyNameSuffix)),
+            None => DEFAULT_NAME_SUFFIX.to_string(),
+        };
+
+        let keep_audio = match definition.parameters.get(AUDIO) {
+            Some(Some(value)) => match value.trim().to_lowercase().as_str() {
+                "keep" => true,
+                "drop" => false,
+                _ => {
+                    return Err(Box::new(StepStartupError::InvalidAudioOption(
+                        value.clone(),
+                    )))
+                }
+            },
+
+            _ => false,
+        };
+
+        let step = KeyframeOnlyStep {
+            is_keyframe_metadata_key: self.is_keyframe_metadata_key,
+            name_suffix,
+            keep_audio,
+            derived_streams: HashMap::new(),
+            dropped_frame_count: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl KeyframeOnlyStep {
+    fn handle_media(&mut self, media: &MediaNotification, outputs: &mut StepOutputs) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                self.end_derived_stream(&media.stream_id, outputs);
+
+                let derived_id = StreamId(Arc::new(Uuid::new_v4().to_string()));
+                let derived_name = Arc::new(format!("{}{}", stream_name, self.name_suffix));
+                info!(
+                    stream_id = %media.stream_id.0,
+                    derived_stream_id = %derived_id.0,
+                    "Starting keyframe only stream '{}'", derived_name
+                );
+
+                outputs.media.push(MediaNotification {
+                    stream_id: derived_id.clone(),
+                    content: MediaNotificationContent::NewIncomingStream {
+                        stream_name: derived_name,
+                    },
+                    annotations: Default::default(),
+                });
+
+                self.derived_streams
+                    .insert(media.stream_id.clone(), derived_id);
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.end_derived_stream(&media.stream_id, outputs);
+            }
+
+            MediaNotificationContent::Metadata { .. } => {
+                self.forward_to_derived_stream(media, outputs);
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type,
+                metadata,
+                is_required_for_decoding,
+                ..
+            } => {
+                let should_forward = *is_required_for_decoding
+                    || match media_type {
+                        MediaType::Video => self.is_keyframe(metadata),
+                        MediaType::Audio => self.keep_audio,
+                        MediaType::Other => false,
+                    };
+
+                if should_forward {
+                    self.forward_to_derived_stream(media, outputs);
+                } else if self.derived_streams.contains_key(&media.stream_id) {
+                    self.dropped_frame_count += 1;
+                }
+            }
+        }
+    }
+
+    fn is_keyframe(&self, metadata: &MediaPayloadMetadataCollection) -> bool {
+        metadata
+            .iter()
+            .filter(|m| m.key() == self.is_keyframe_metadata_key)
+            .filter_map(|m| match m.value() {
+                MetadataValue::Bool(val) => Some(val),
+                _ => None,
+            })
+            .next()
+            .unwrap_or_default()
+    }
+
+    fn forward_to_derived_stream(&self, media: &MediaNotification, outputs: &mut StepOutputs) {
+        if let Some(derived_id) = self.derived_streams.get(&media.stream_id) {
+            let mut derived = media.clone();
+            derived.stream_id = derived_id.clone();
+            outputs.media.push(derived);
+        }
+    }
+
+    fn end_derived_stream(&mut self, stream_id: &StreamId, outputs: &mut StepOutputs) {
+        if let Some(derived_id) = self.derived_streams.remove(stream_id) {
+            outputs.media.push(MediaNotification {
+                stream_id: derived_id,
+                content: MediaNotificationContent::StreamDisconnected,
+                annotations: Default::default(),
+            });
+        }
+    }
+}
+
+impl WorkflowStep for KeyframeOnlyStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            // The original stream goes first, so it's announced before its derived stream
+            outputs.media.push(media.clone());
+            self.handle_media(&media, outputs);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            DERIVED_STREAMS_DETAIL.to_string(),
+            self.derived_streams.len().to_string(),
+        );
+
+        details.insert(
+            DROPPED_FRAME_COUNT_DETAIL.to_string(),
+            self.dropped_frame_count.to_string(),
+        );
+
+        details
+    }
+}
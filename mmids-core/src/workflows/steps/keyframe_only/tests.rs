@@ -0,0 +1,248 @@
+use super::*;
+use crate::codecs::{AUDIO_CODEC_AAC_RAW, VIDEO_CODEC_H264_AVC};
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::common_metadata::get_is_keyframe_metadata_key;
+use crate::workflows::metadata::{MetadataEntry, MetadataKeyMap};
+use crate::workflows::steps::test_utils::StepTestContext;
+use bytes::{Bytes, BytesMut};
+use std::iter;
+use std::time::Duration;
+
+struct TestContext {
+    step_context: StepTestContext,
+    is_keyframe_metadata_key: MetadataKey,
+    stream_id: StreamId,
+    derived_id: StreamId,
+}
+
+impl TestContext {
+    fn new(parameters: &[(&str, &str)]) -> Self {
+        let mut metadata_map = MetadataKeyMap::new();
+        let is_keyframe_metadata_key = get_is_keyframe_metadata_key(&mut metadata_map);
+
+        let mut definition = WorkflowStepDefinition {
+            step_type: WorkflowStepType("keyframe_only".to_string()),
+            parameters: HashMap::new(),
+        };
+
+        for (name, value) in parameters {
+            definition
+                .parameters
+                .insert(name.to_string(), Some(value.to_string()));
+        }
+
+        let generator = KeyframeOnlyStepGenerator::new(is_keyframe_metadata_key);
+        let mut step_context =
+            StepTestContext::new(Box::new(generator), definition).expect("Failed to create step");
+
+        let stream_id = StreamId(Arc::new("abc".to_string()));
+        step_context.execute_with_media(MediaNotification {
+            stream_id: stream_id.clone(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        });
+
+        assert_eq!(
+            step_context.media_outputs.len(),
+            2,
+            "Expected original and derived new stream notifications"
+        );
+
+        let derived_id = step_context.media_outputs[1].stream_id.clone();
+        assert_ne!(
+            derived_id, stream_id,
+            "Expected a different derived stream id"
+        );
+
+        TestContext {
+            step_context,
+            is_keyframe_metadata_key,
+            stream_id,
+            derived_id,
+        }
+    }
+
+    fn video(
+        &self,
+        timestamp: u64,
+        is_keyframe: bool,
+        is_sequence_header: bool,
+    ) -> MediaNotification {
+        let mut buffer = BytesMut::new();
+        let entry = MetadataEntry::new(
+            self.is_keyframe_metadata_key,
+            MetadataValue::Bool(is_keyframe),
+            &mut buffer,
+        )
+        .unwrap();
+
+        MediaNotification {
+            stream_id: self.stream_id.clone(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type: VIDEO_CODEC_H264_AVC.clone(),
+                timestamp: Duration::from_millis(timestamp),
+                metadata: MediaPayloadMetadataCollection::new(iter::once(entry), &mut buffer),
+                data: Bytes::from(vec![timestamp as u8]),
+                is_required_for_decoding: is_sequence_header,
+            },
+            annotations: Default::default(),
+        }
+    }
+
+    fn audio(&self, timestamp: u64, is_sequence_header: bool) -> MediaNotification {
+        MediaNotification {
+            stream_id: self.stream_id.clone(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Audio,
+                payload_type: AUDIO_CODEC_AAC_RAW.clone(),
+                timestamp: Duration::from_millis(timestamp),
+                metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+                data: Bytes::from(vec![timestamp as u8]),
+                is_required_for_decoding: is_sequence_header,
+            },
+            annotations: Default::default(),
+        }
+    }
+
+    /// Sends the media through the step, asserting the original was passed through and returning
+    /// any media raised for the derived stream
+    fn send(&mut self, media: MediaNotification) -> Vec<MediaNotification> {
+        self.step_context.execute_with_media(media.clone());
+        assert_eq!(
+            self.step_context.media_outputs.first(),
+            Some(&media),
+            "Expected original media to be passed through first"
+        );
+
+        let derived = self.step_context.media_outputs.split_off(1);
+        for media in &derived {
+            assert_eq!(media.stream_id, self.derived_id, "Unexpected stream id");
+        }
+
+        derived
+    }
+}
+
+fn as_derived(media: MediaNotification, derived_id: &StreamId) -> MediaNotification {
+    MediaNotification {
+        stream_id: derived_id.clone(),
+        ..media
+    }
+}
+
+#[test]
+fn invalid_audio_option_returns_error() {
+    let mut metadata_map = MetadataKeyMap::new();
+    let generator = KeyframeOnlyStepGenerator::new(get_is_keyframe_metadata_key(&mut metadata_map));
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("keyframe_only".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    definition
+        .parameters
+        .insert(AUDIO.to_string(), Some("abc".to_string()));
+
+    let result = StepTestContext::new(Box::new(generator), definition);
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn derived_stream_announced_with_suffixed_name() {
+    let context = TestContext::new(&[(NAME_SUFFIX, "_preview")]);
+
+    match &context.step_context.media_outputs[1].content {
+        MediaNotificationContent::NewIncomingStream { stream_name } => {
+            assert_eq!(
+                stream_name.as_str(),
+                "def_preview",
+                "Unexpected stream name"
+            );
+        }
+
+        content => panic!("Unexpected derived content: {:?}", content),
+    }
+}
+
+#[test]
+fn only_sequence_headers_and_keyframes_sent_on_derived_stream() {
+    let mut context = TestContext::new(&[]);
+    let derived_id = context.derived_id.clone();
+
+    let sequence_header = context.video(0, false, true);
+    let derived = context.send(sequence_header.clone());
+    assert_eq!(
+        derived,
+        vec![as_derived(sequence_header, &derived_id)],
+        "Expected sequence header on derived stream"
+    );
+
+    let keyframe = context.video(10, true, false);
+    let derived = context.send(keyframe.clone());
+    assert_eq!(
+        derived,
+        vec![as_derived(keyframe, &derived_id)],
+        "Expected keyframe on derived stream"
+    );
+
+    let inter_frame = context.video(20, false, false);
+    let derived = context.send(inter_frame);
+    assert!(derived.is_empty(), "Expected inter frame to be dropped");
+
+    let audio = context.audio(20, false);
+    let derived = context.send(audio);
+    assert!(derived.is_empty(), "Expected audio to be dropped");
+
+    let audio_header = context.audio(0, true);
+    let derived = context.send(audio_header.clone());
+    assert_eq!(
+        derived,
+        vec![as_derived(audio_header, &derived_id)],
+        "Expected audio sequence header on derived stream"
+    );
+}
+
+#[test]
+fn audio_sent_on_derived_stream_when_kept() {
+    let mut context = TestContext::new(&[(AUDIO, "keep")]);
+    let derived_id = context.derived_id.clone();
+
+    let audio = context.audio(20, false);
+    let derived = context.send(audio.clone());
+    assert_eq!(
+        derived,
+        vec![as_derived(audio, &derived_id)],
+        "Expected audio on derived stream"
+    );
+}
+
+#[test]
+fn derived_stream_disconnected_with_original() {
+    let mut context = TestContext::new(&[]);
+    let disconnection = MediaNotification {
+        stream_id: context.stream_id.clone(),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    };
+
+    let derived = context.send(disconnection);
+    assert_eq!(
+        derived.len(),
+        1,
+        "Unexpected number of derived notifications"
+    );
+    assert_eq!(
+        derived[0].content,
+        MediaNotificationContent::StreamDisconnected,
+        "Expected derived stream to be disconnected"
+    );
+
+    let derived = context.send(context.video(10, true, false));
+    assert!(
+        derived.is_empty(),
+        "Expected no derived media after disconnection"
+    );
+}
@@ -0,0 +1,165 @@
+//! The metadata strip step removes keys from `Metadata` notifications before they reach output
+//! steps, so source metadata (such as encoder identifiers or device names) isn't exposed to
+//! public outputs. Since in-band timed metadata (such as ID3 tags in HLS output) is sourced from
+//! `Metadata` notifications, stripped keys are kept out of those as well.
+//!
+//! Which keys are removed depends on the parameters specified:
+//! * `strip_keys` - A comma separated list of keys to remove, keeping all others.
+//! * `allowed_keys` - A comma separated list of keys to keep, removing all others.
+//! * When neither is specified, all keys are removed.
+//!
+//! Keys are matched case insensitively. Keys starting with `mmids_` are used by mmids itself to
+//! signal information between steps (such as stream labels), and are only removed when listed
+//! in `strip_keys`. Metadata notifications left without any keys are not passed on at all. All
+//! other media is passed through untouched.
+
+#[cfg(test)]
+mod tests;
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::MediaNotificationContent;
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+pub const STRIP_KEYS: &str = "strip_keys";
+pub const ALLOWED_KEYS: &str = "allowed_keys";
+
+const INTERNAL_KEY_PREFIX: &str = "mmids_";
+
+const STRIPPED_KEY_COUNT_DETAIL: &str = "stripped_key_count";
+
+/// Generates new instances of the metadata strip workflow step
+#[derive(Default)]
+pub struct MetadataStripStepGenerator {}
+
+enum StripMode {
+    All,
+    Keys(HashSet<String>),
+    AllExcept(HashSet<String>),
+}
+
+struct MetadataStripStep {
+    mode: StripMode,
+    stripped_key_count: u64,
+}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "Only one of the {} and {} parameters may be specified",
+        STRIP_KEYS,
+        ALLOWED_KEYS
+    )]
+    ConflictingKeyLists,
+
+    #[error("No keys were specified for the {0} parameter")]
+    NoKeysSpecified(&'static str),
+}
+
+impl MetadataStripStepGenerator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl StepGenerator for MetadataStripStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let strip_keys = parse_keys(&definition, STRIP_KEYS)?;
+        let allowed_keys = parse_keys(&definition, ALLOWED_KEYS)?;
+
+        let mode = match (strip_keys, allowed_keys) {
+            (Some(_), Some(_)) => return Err(Box::new(StepStartupError::ConflictingKeyLists)),
+            (Some(keys), None) => StripMode::Keys(keys),
+            (None, Some(keys)) => StripMode::AllExcept(keys),
+            (None, None) => StripMode::All,
+        };
+
+        let step = MetadataStripStep {
+            mode,
+            stripped_key_count: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl MetadataStripStep {
+    fn should_strip(&self, key: &str) -> bool {
+        let key = key.to_lowercase();
+        match &self.mode {
+            StripMode::Keys(keys) => keys.contains(&key),
+            StripMode::AllExcept(keys) => {
+                !keys.contains(&key) && !key.starts_with(INTERNAL_KEY_PREFIX)
+            }
+
+            StripMode::All => !key.starts_with(INTERNAL_KEY_PREFIX),
+        }
+    }
+}
+
+impl WorkflowStep for MetadataStripStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for mut media in inputs.media.drain(..) {
+            if let MediaNotificationContent::Metadata { data } = &mut media.content {
+                let original_count = data.len();
+                data.retain(|key, _| !self.should_strip(key));
+                self.stripped_key_count += (original_count - data.len()) as u64;
+
+                if data.is_empty() {
+                    continue;
+                }
+            }
+
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            STRIPPED_KEY_COUNT_DETAIL.to_string(),
+            self.stripped_key_count.to_string(),
+        );
+
+        details
+    }
+}
+
+fn parse_keys(
+    definition: &WorkflowStepDefinition,
+    parameter: &'static str,
+) -> Result<Option<HashSet<String>>, StepStartupError> {
+    let value = match definition.parameters.get(parameter) {
+        Some(Some(value)) => value,
+        Some(None) => return Err(StepStartupError::NoKeysSpecified(parameter)),
+        None => return Ok(None),
+    };
+
+    let keys = value
+        .split(',')
+        .map(|key| key.trim().to_lowercase())
+        .filter(|key| !key.is_empty())
+        .collect::<HashSet<_>>();
+
+    if keys.is_empty() {
+        return Err(StepStartupError::NoKeysSpecified(parameter));
+    }
+
+    Ok(Some(keys))
+}
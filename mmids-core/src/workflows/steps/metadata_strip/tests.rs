@@ -0,0 +1,136 @@
+use super::*;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::steps::test_utils::StepTestContext;
+use crate::workflows::stream_labels::STREAM_LABEL_METADATA_KEY;
+use crate::workflows::MediaNotification;
+use crate::StreamId;
+use std::sync::Arc;
+
+fn create_context(parameters: &[(&str, &str)]) -> StepTestContext {
+    StepTestContext::new(
+        Box::new(MetadataStripStepGenerator::new()),
+        definition(parameters),
+    )
+    .expect("Failed to create step")
+}
+
+fn definition(parameters: &[(&str, &str)]) -> WorkflowStepDefinition {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("metadata_strip".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    for (name, value) in parameters {
+        definition
+            .parameters
+            .insert(name.to_string(), Some(value.to_string()));
+    }
+
+    definition
+}
+
+fn metadata(entries: &[(&str, &str)]) -> MediaNotification {
+    MediaNotification {
+        stream_id: StreamId(Arc::new("abc".to_string())),
+        content: MediaNotificationContent::Metadata {
+            data: entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        },
+        annotations: Default::default(),
+    }
+}
+
+fn forwarded_keys(context: &StepTestContext) -> Vec<String> {
+    assert_eq!(
+        context.media_outputs.len(),
+        1,
+        "Unexpected number of outputs"
+    );
+
+    match &context.media_outputs[0].content {
+        MediaNotificationContent::Metadata { data } => {
+            let mut keys = data.keys().cloned().collect::<Vec<_>>();
+            keys.sort();
+            keys
+        }
+
+        content => panic!("Expected metadata, instead got {:?}", content),
+    }
+}
+
+#[test]
+fn both_key_lists_returns_error() {
+    let result = StepTestContext::new(
+        Box::new(MetadataStripStepGenerator::new()),
+        definition(&[(STRIP_KEYS, "encoder"), (ALLOWED_KEYS, "width")]),
+    );
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn configured_keys_are_stripped() {
+    let mut context = create_context(&[(STRIP_KEYS, "encoder, Device_Name")]);
+    context.execute_with_media(metadata(&[
+        ("encoder", "obs 29"),
+        ("device_name", "studio laptop"),
+        ("width", "1920"),
+    ]));
+
+    assert_eq!(
+        forwarded_keys(&context),
+        vec!["width".to_string()],
+        "Unexpected forwarded keys"
+    );
+
+    let details = context.step.get_state_details();
+    assert_eq!(
+        details.get(STRIPPED_KEY_COUNT_DETAIL),
+        Some(&"2".to_string()),
+        "Unexpected stripped key count"
+    );
+}
+
+#[test]
+fn only_allowed_and_internal_keys_are_kept() {
+    let mut context = create_context(&[(ALLOWED_KEYS, "width,height")]);
+    context.execute_with_media(metadata(&[
+        ("encoder", "obs 29"),
+        ("width", "1920"),
+        ("height", "1080"),
+        (STREAM_LABEL_METADATA_KEY, "primary"),
+    ]));
+
+    assert_eq!(
+        forwarded_keys(&context),
+        vec![
+            "height".to_string(),
+            STREAM_LABEL_METADATA_KEY.to_string(),
+            "width".to_string()
+        ],
+        "Unexpected forwarded keys"
+    );
+}
+
+#[test]
+fn metadata_without_remaining_keys_is_not_forwarded() {
+    let mut context = create_context(&[]);
+    context.assert_media_not_passed_through(metadata(&[
+        ("encoder", "obs 29"),
+        ("device_name", "studio laptop"),
+    ]));
+}
+
+#[test]
+fn non_metadata_media_passed_through() {
+    let mut context = create_context(&[]);
+    context.assert_media_passed_through(MediaNotification {
+        stream_id: StreamId(Arc::new("abc".to_string())),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("def".to_string()),
+        },
+        annotations: Default::default(),
+    });
+}
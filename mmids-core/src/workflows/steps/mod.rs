@@ -1,16 +1,52 @@
 //! Workflow steps are individual actions that can be taken on media as part of a media pipeline.
 
+pub mod admission_control;
+pub mod buffer_stats;
+pub mod caption_detect;
+pub mod caption_inject;
+pub mod chaos;
+pub mod checksum;
 pub mod factory;
+pub mod failover;
 pub mod futures_channel;
+pub mod gop_segmenter;
+pub mod h264_framing;
+pub mod heartbeat;
+pub mod ingest_warmup;
+pub mod instant_replay;
+pub mod keyframe_only;
+pub mod metadata_strip;
+pub mod mpegts_demux;
+pub mod rate_shape;
+pub mod remote_forward;
+pub mod remote_ingest;
+pub mod resolution_guard;
+pub mod retry;
+pub mod session_record;
+pub mod session_replay;
+pub mod side_channel;
+pub mod stall_detection;
+pub mod startup_delay;
+pub mod startup_order;
+pub mod stream_change_monitor;
+pub mod stream_label;
+pub mod stream_properties;
+pub mod stream_tags;
+pub mod timestamp_sanitize;
+pub mod watch_authorization;
+pub mod whep_output;
 pub mod workflow_forwarder;
 
 #[cfg(feature = "test-utils")]
 pub mod test_utils;
 
-use super::MediaNotification;
+use super::{MediaNotification, MediaNotificationContent};
 use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::buffer_stats::BufferStats;
 use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::StreamId;
 use downcast_rs::{impl_downcast, Downcast};
+use std::collections::HashMap;
 
 /// Represents the result of a future for a workflow step.  It is expected that the workflow step
 /// will downcast this result into a struct that it owns.
@@ -97,4 +133,39 @@ pub trait WorkflowStep {
         outputs: &mut StepOutputs,
         futures_channel: WorkflowStepFuturesChannel,
     ) -> StepStatus;
+
+    /// Returns `true` if the step wants to receive media with the specified content. Media the
+    /// step doesn't want is never given to it, and is instead passed on to the next step as if the
+    /// step had output it untouched. Steps that only act on some media (such as only video
+    /// payloads) can use this to avoid being executed for media they'd just pass through.
+    ///
+    /// This must give the same answer for the same content for the lifetime of the step.
+    fn wants_media(&self, _content: &MediaNotificationContent) -> bool {
+        true
+    }
+
+    /// Returns step specific details about the current state of the step, such as counters, that
+    /// will be surfaced as part of the workflow's state.
+    fn get_state_details(&self) -> HashMap<String, String> {
+        HashMap::new()
+    }
+
+    /// Returns details about the media the step is holding onto, for steps that buffer media.
+    /// These are surfaced as part of the workflow's state.
+    fn get_buffer_stats(&self) -> Option<BufferStats> {
+        None
+    }
+
+    /// Returns the number of media pipelines (such as transcodes) the step currently has running,
+    /// which is reported as part of the workflow's resource usage.
+    fn get_active_pipeline_count(&self) -> usize {
+        0
+    }
+
+    /// Asks the step to have fresh sequence headers and a keyframe produced for the specified
+    /// stream, such as by forcing a transcode's encoder to emit a key unit. Returns `true` if the
+    /// step was able to make the request.
+    fn request_sequence_headers(&mut self, _stream_id: &StreamId) -> bool {
+        false
+    }
 }
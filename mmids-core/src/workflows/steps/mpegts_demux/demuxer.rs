@@ -0,0 +1,303 @@
+//! A minimal MPEG-TS demuxer, which follows the program association and program map tables to
+//! find each program's elementary streams, and reassembles the PES packets of H264 and ADTS AAC
+//! elementary streams into frames.
+//!
+//! Program specific information sections are expected to fit within a single TS packet, which is
+//! the case for all but the largest program map tables. Elementary streams of other types are
+//! ignored.
+
+use bytes::{Bytes, BytesMut};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0x0000;
+const PAT_TABLE_ID: u8 = 0x00;
+const PMT_TABLE_ID: u8 = 0x02;
+const STREAM_TYPE_AAC_ADTS: u8 = 0x0F;
+const STREAM_TYPE_H264: u8 = 0x1B;
+const PES_HEADER_SIZE: usize = 6;
+
+/// The types of elementary streams the demuxer can extract frames from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ElementaryStreamType {
+    /// H264 video in Annex-B framing
+    H264,
+
+    /// AAC audio with ADTS headers
+    AacAdts,
+}
+
+/// A complete PES packet's payload from one of a program's elementary streams
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DemuxedFrame {
+    pub program_number: u16,
+    pub stream_type: ElementaryStreamType,
+    pub pts: Duration,
+    pub dts: Duration,
+    pub data: Bytes,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DemuxEvent {
+    /// A program with at least one supported elementary stream was found for the first time
+    ProgramFound {
+        program_number: u16,
+    },
+
+    Frame(DemuxedFrame),
+}
+
+struct ElementaryStream {
+    program_number: u16,
+    stream_type: ElementaryStreamType,
+    pes_buffer: Option<BytesMut>,
+}
+
+/// Demuxes a single transport stream, which may be provided in arbitrarily sized chunks
+#[derive(Default)]
+pub struct TsDemuxer {
+    /// Bytes of a TS packet that was split across chunks
+    partial_packet: BytesMut,
+
+    /// The program number for each PID the PAT specified a program map table is on
+    pmt_pids: HashMap<u16, u16>,
+
+    elementary_streams: HashMap<u16, ElementaryStream>,
+    found_programs: HashSet<u16>,
+    skipped_byte_count: u64,
+}
+
+impl TsDemuxer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The number of bytes that were skipped while looking for the start of a TS packet
+    pub fn skipped_byte_count(&self) -> u64 {
+        self.skipped_byte_count
+    }
+
+    /// Reads the next chunk of the transport stream, returning any programs and frames that were
+    /// completed by it.
+    pub fn push(&mut self, data: &[u8]) -> Vec<DemuxEvent> {
+        self.partial_packet.extend_from_slice(data);
+        let buffer = self.partial_packet.split();
+
+        let mut events = Vec::new();
+        let mut offset = 0;
+        while offset + PACKET_SIZE <= buffer.len() {
+            if buffer[offset] != SYNC_BYTE {
+                // Lost sync, so move forward until the next possible packet start
+                offset += 1;
+                self.skipped_byte_count += 1;
+                continue;
+            }
+
+            self.read_packet(&buffer[offset..offset + PACKET_SIZE], &mut events);
+            offset += PACKET_SIZE;
+        }
+
+        self.partial_packet.extend_from_slice(&buffer[offset..]);
+
+        events
+    }
+
+    fn read_packet(&mut self, packet: &[u8], events: &mut Vec<DemuxEvent>) {
+        let payload_unit_start = packet[1] & 0x40 != 0;
+        let pid = (u16::from(packet[1] & 0x1F) << 8) | u16::from(packet[2]);
+        let adaptation_field_control = (packet[3] >> 4) & 0x03;
+
+        let mut payload_start = 4;
+        if adaptation_field_control & 0x02 != 0 {
+            payload_start += 1 + packet[4] as usize;
+        }
+
+        if adaptation_field_control & 0x01 == 0 || payload_start >= PACKET_SIZE {
+            return; // no payload
+        }
+
+        let payload = &packet[payload_start..];
+        if pid == PAT_PID {
+            if payload_unit_start {
+                self.read_pat(payload);
+            }
+        } else if let Some(program_number) = self.pmt_pids.get(&pid).copied() {
+            if payload_unit_start {
+                self.read_pmt(program_number, payload, events);
+            }
+        } else if let Some(stream) = self.elementary_streams.get_mut(&pid) {
+            if payload_unit_start {
+                if let Some(frame) = stream.take_frame() {
+                    events.push(DemuxEvent::Frame(frame));
+                }
+
+                stream.pes_buffer = Some(BytesMut::from(payload));
+            } else if let Some(buffer) = &mut stream.pes_buffer {
+                buffer.extend_from_slice(payload);
+            }
+
+            // PES packets that declare their length can be completed without waiting for the
+            // next one to start
+            let is_complete = match &stream.pes_buffer {
+                Some(buffer) => declared_pes_length(buffer)
+                    .map(|length| buffer.len() >= PES_HEADER_SIZE + length)
+                    .unwrap_or_default(),
+
+                None => false,
+            };
+
+            if is_complete {
+                if let Some(frame) = stream.take_frame() {
+                    events.push(DemuxEvent::Frame(frame));
+                }
+            }
+        }
+    }
+
+    fn read_pat(&mut self, payload: &[u8]) {
+        let entries = match read_section(payload, PAT_TABLE_ID) {
+            Some((_, entries)) => entries,
+            None => return,
+        };
+
+        for entry in entries.chunks_exact(4) {
+            let program_number = u16::from_be_bytes([entry[0], entry[1]]);
+            let pid = (u16::from(entry[2] & 0x1F) << 8) | u16::from(entry[3]);
+
+            // Program 0 points to the network information table instead of a program map table
+            if program_number != 0 {
+                self.pmt_pids.insert(pid, program_number);
+            }
+        }
+    }
+
+    fn read_pmt(&mut self, program_number: u16, payload: &[u8], events: &mut Vec<DemuxEvent>) {
+        let body = match read_section(payload, PMT_TABLE_ID) {
+            Some((table_id_extension, body)) if table_id_extension == program_number => body,
+            _ => return,
+        };
+
+        if body.len() < 4 {
+            return;
+        }
+
+        let program_info_length = (usize::from(body[2] & 0x0F) << 8) | usize::from(body[3]);
+        let mut streams = match body.get(4 + program_info_length..) {
+            Some(streams) => streams,
+            None => return,
+        };
+
+        let mut has_supported_stream = false;
+        while streams.len() >= 5 {
+            let pid = (u16::from(streams[1] & 0x1F) << 8) | u16::from(streams[2]);
+            let info_length = (usize::from(streams[3] & 0x0F) << 8) | usize::from(streams[4]);
+            let stream_type = match streams[0] {
+                STREAM_TYPE_H264 => Some(ElementaryStreamType::H264),
+                STREAM_TYPE_AAC_ADTS => Some(ElementaryStreamType::AacAdts),
+                _ => None,
+            };
+
+            if let Some(stream_type) = stream_type {
+                has_supported_stream = true;
+                self.elementary_streams
+                    .entry(pid)
+                    .or_insert(ElementaryStream {
+                        program_number,
+                        stream_type,
+                        pes_buffer: None,
+                    });
+            }
+
+            streams = streams.get(5 + info_length..).unwrap_or_default();
+        }
+
+        if has_supported_stream && self.found_programs.insert(program_number) {
+            events.push(DemuxEvent::ProgramFound { program_number });
+        }
+    }
+}
+
+impl ElementaryStream {
+    /// Takes the buffered PES packet, returning its payload as a frame if it's valid
+    fn take_frame(&mut self) -> Option<DemuxedFrame> {
+        let buffer = self.pes_buffer.take()?.freeze();
+        if buffer.len() < PES_HEADER_SIZE + 3 || buffer[0..3] != [0, 0, 1] {
+            return None;
+        }
+
+        let pts_dts_flags = buffer[7] >> 6;
+        let header_data_length = buffer[8] as usize;
+        let payload_start = PES_HEADER_SIZE + 3 + header_data_length;
+        let payload_end = match declared_pes_length(&buffer) {
+            Some(length) => PES_HEADER_SIZE + length,
+            None => buffer.len(),
+        };
+
+        if payload_start > payload_end || payload_end > buffer.len() {
+            return None;
+        }
+
+        let pts = match pts_dts_flags {
+            0b10 | 0b11 => read_timestamp(buffer.get(9..14)?),
+            _ => return None, // frames without timestamps can't be placed on the timeline
+        };
+
+        let dts = match pts_dts_flags {
+            0b11 => read_timestamp(buffer.get(14..19)?),
+            _ => pts,
+        };
+
+        Some(DemuxedFrame {
+            program_number: self.program_number,
+            stream_type: self.stream_type,
+            pts,
+            dts,
+            data: buffer.slice(payload_start..payload_end),
+        })
+    }
+}
+
+/// Reads the PES packet length from the start of a PES packet. Video PES packets commonly leave
+/// this as zero, as their length is not bounded.
+fn declared_pes_length(buffer: &[u8]) -> Option<usize> {
+    match buffer.get(4..6) {
+        Some(&[high, low]) if high != 0 || low != 0 => {
+            Some(usize::from(u16::from_be_bytes([high, low])))
+        }
+        _ => None,
+    }
+}
+
+/// Reads a program specific information section starting at the beginning of a packet's payload,
+/// returning the section's table id extension and the data between the section header and CRC.
+fn read_section(payload: &[u8], expected_table_id: u8) -> Option<(u16, &[u8])> {
+    let pointer = *payload.first()? as usize;
+    let section = payload.get(1 + pointer..)?;
+    if *section.first()? != expected_table_id || section.len() < 3 {
+        return None;
+    }
+
+    let section_length = (usize::from(section[1] & 0x0F) << 8) | usize::from(section[2]);
+    let section = section.get(3..3 + section_length)?;
+    if section.len() < 9 {
+        return None;
+    }
+
+    let table_id_extension = u16::from_be_bytes([section[0], section[1]]);
+
+    // Skip the rest of the header, and leave off the CRC
+    Some((table_id_extension, &section[5..section.len() - 4]))
+}
+
+/// Reads a 33 bit, 90kHz PES timestamp
+fn read_timestamp(bytes: &[u8]) -> Duration {
+    let ticks = (u64::from(bytes[0] >> 1) & 0x07) << 30
+        | u64::from(bytes[1]) << 22
+        | u64::from(bytes[2] >> 1) << 15
+        | u64::from(bytes[3]) << 7
+        | u64::from(bytes[4] >> 1);
+
+    Duration::from_micros(ticks * 100 / 9)
+}
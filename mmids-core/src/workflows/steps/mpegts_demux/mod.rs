@@ -0,0 +1,438 @@
+//! The MPEG-TS demux step splits streams carrying MPEG-TS packets (`Other` media payloads with the
+//! `mpegts` payload type) into a separate stream for each program contained within the transport
+//! stream. This allows a single multi-program contribution feed to carry several channels, with
+//! each channel flowing through the rest of the workflow as its own stream.
+//!
+//! Each program is announced as a new stream once its program map table has been read, and is
+//! disconnected when the stream carrying it disconnects. H264 video is raised with the
+//! `h264-annexb` payload type, and AAC audio is converted from ADTS to raw AAC with a sequence
+//! header raised before the first frame and whenever the audio configuration changes. Other
+//! elementary streams are ignored.
+//!
+//! The `programs` parameter maps program numbers to the names their streams are announced with,
+//! as a comma separated list of `<program number>:<stream name>` entries (e.g. `1:news,2:sports`).
+//! Programs without a mapping are named after the stream carrying them followed by
+//! `_program<number>`.
+//!
+//! Streams carrying the transport stream are consumed by this step. Metadata for them is passed
+//! along to each of their programs' streams, and any other media is dropped.
+
+mod demuxer;
+#[cfg(test)]
+mod tests;
+
+use crate::codecs::aac::split_adts;
+use crate::codecs::h264::{nal_unit_type, NAL_UNIT_TYPE_IDR};
+use crate::codecs::nal::split_annexb;
+use crate::codecs::{AUDIO_CODEC_AAC_RAW, CONTAINER_MPEG_TS, VIDEO_CODEC_H264_ANNEXB};
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::metadata::{
+    MediaPayloadMetadataCollection, MetadataEntry, MetadataKey, MetadataValue,
+};
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::mpegts_demux::demuxer::{
+    DemuxEvent, DemuxedFrame, ElementaryStreamType, TsDemuxer,
+};
+use crate::workflows::steps::{
+    StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use crate::StreamId;
+use bytes::{Bytes, BytesMut};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+pub const PROGRAMS: &str = "programs";
+
+const AAC_SAMPLES_PER_FRAME: u64 = 1024;
+
+const PROGRAM_STREAMS_DETAIL: &str = "program_streams";
+const INVALID_FRAME_COUNT_DETAIL: &str = "invalid_frame_count";
+const SKIPPED_BYTE_COUNT_DETAIL: &str = "skipped_byte_count";
+
+/// Generates new instances of the MPEG-TS demux workflow step
+pub struct MpegTsDemuxStepGenerator {
+    is_keyframe_metadata_key: MetadataKey,
+    pts_offset_metadata_key: MetadataKey,
+}
+
+struct ProgramStream {
+    stream_id: StreamId,
+
+    /// The most recent AAC sequence header raised for the program
+    audio_sequence_header: Option<Bytes>,
+}
+
+struct SourceStream {
+    name: Arc<String>,
+    demuxer: TsDemuxer,
+    programs: HashMap<u16, ProgramStream>,
+}
+
+struct MpegTsDemuxStep {
+    is_keyframe_metadata_key: MetadataKey,
+    pts_offset_metadata_key: MetadataKey,
+    program_names: HashMap<u16, Arc<String>>,
+    sources: HashMap<StreamId, SourceStream>,
+    metadata_buffer: BytesMut,
+    invalid_frame_count: u64,
+}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "Invalid {} entry '{0}', entries must be in the form of <program number>:<stream name>",
+        PROGRAMS
+    )]
+    InvalidProgramMapping(String),
+
+    #[error("Program {0} was mapped to more than one stream name")]
+    DuplicateProgram(u16),
+}
+
+impl MpegTsDemuxStepGenerator {
+    pub fn new(
+        is_keyframe_metadata_key: MetadataKey,
+        pts_offset_metadata_key: MetadataKey,
+    ) -> Self {
+        MpegTsDemuxStepGenerator {
+            is_keyframe_metadata_key,
+            pts_offset_metadata_key,
+        }
+    }
+}
+
+impl StepGenerator for MpegTsDemuxStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let mut program_names = HashMap::new();
+        if let Some(Some(mapping)) = definition.parameters.get(PROGRAMS) {
+            for entry in mapping
+                .split(',')
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty())
+            {
+                let (program, name) = match entry.split_once(':') {
+                    Some((program, name)) => (program.trim(), name.trim()),
+                    None => {
+                        return Err(Box::new(StepStartupError::InvalidProgramMapping(
+                            entry.to_string(),
+                        )))
+                    }
+                };
+
+                let program = match program.parse::<u16>() {
+                    Ok(program) if !name.is_empty() => program,
+                    _ => {
+                        return Err(Box::new(StepStartupError::InvalidProgramMapping(
+                            entry.to_string(),
+                        )))
+                    }
+                };
+
+                if program_names
+                    .insert(program, Arc::new(name.to_string()))
+                    .is_some()
+                {
+                    return Err(Box::new(StepStartupError::DuplicateProgram(program)));
+                }
+            }
+        }
+
+        let step = MpegTsDemuxStep {
+            is_keyframe_metadata_key: self.is_keyframe_metadata_key,
+            pts_offset_metadata_key: self.pts_offset_metadata_key,
+            program_names,
+            sources: HashMap::new(),
+            metadata_buffer: BytesMut::new(),
+            invalid_frame_count: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl MpegTsDemuxStep {
+    fn handle_media(&mut self, media: MediaNotification, outputs: &mut StepOutputs) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                self.end_programs(&media.stream_id, outputs);
+                self.sources.insert(
+                    media.stream_id.clone(),
+                    SourceStream {
+                        name: stream_name.clone(),
+                        demuxer: TsDemuxer::new(),
+                        programs: HashMap::new(),
+                    },
+                );
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.end_programs(&media.stream_id, outputs);
+            }
+
+            MediaNotificationContent::Metadata { .. } => {
+                if let Some(source) = self.sources.get(&media.stream_id) {
+                    for program in source.programs.values() {
+                        outputs.media.push(MediaNotification {
+                            stream_id: program.stream_id.clone(),
+                            ..media.clone()
+                        });
+                    }
+                }
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Other,
+                payload_type,
+                data,
+                ..
+            } if payload_type == &*CONTAINER_MPEG_TS => {
+                let events = match self.sources.get_mut(&media.stream_id) {
+                    Some(source) => source.demuxer.push(data),
+                    None => return,
+                };
+
+                for event in events {
+                    match event {
+                        DemuxEvent::ProgramFound { program_number } => {
+                            self.start_program(&media.stream_id, program_number, outputs);
+                        }
+
+                        DemuxEvent::Frame(frame) => {
+                            self.handle_frame(&media.stream_id, frame, outputs);
+                        }
+                    }
+                }
+            }
+
+            MediaNotificationContent::MediaPayload { .. } => (),
+        }
+    }
+
+    fn start_program(
+        &mut self,
+        source_id: &StreamId,
+        program_number: u16,
+        outputs: &mut StepOutputs,
+    ) {
+        let source = match self.sources.get_mut(source_id) {
+            Some(source) => source,
+            None => return,
+        };
+
+        let stream_id = StreamId(Arc::new(Uuid::new_v4().to_string()));
+        let stream_name = match self.program_names.get(&program_number) {
+            Some(name) => name.clone(),
+            None => Arc::new(format!("{}_program{}", source.name, program_number)),
+        };
+
+        info!(
+            stream_id = %source_id.0,
+            program_stream_id = %stream_id.0,
+            "Found program {} in transport stream, starting stream '{}'",
+            program_number, stream_name
+        );
+
+        outputs.media.push(MediaNotification {
+            stream_id: stream_id.clone(),
+            content: MediaNotificationContent::NewIncomingStream { stream_name },
+            annotations: Default::default(),
+        });
+
+        source.programs.insert(
+            program_number,
+            ProgramStream {
+                stream_id,
+                audio_sequence_header: None,
+            },
+        );
+    }
+
+    fn handle_frame(
+        &mut self,
+        source_id: &StreamId,
+        frame: DemuxedFrame,
+        outputs: &mut StepOutputs,
+    ) {
+        let program = match self
+            .sources
+            .get_mut(source_id)
+            .and_then(|source| source.programs.get_mut(&frame.program_number))
+        {
+            Some(program) => program,
+            None => return,
+        };
+
+        match frame.stream_type {
+            ElementaryStreamType::H264 => {
+                let is_keyframe = split_annexb(&frame.data)
+                    .iter()
+                    .any(|nal_unit| nal_unit_type(nal_unit) == Some(NAL_UNIT_TYPE_IDR));
+
+                let pts_offset = frame.pts.as_millis() as i64 - frame.dts.as_millis() as i64;
+                let is_keyframe_metadata = MetadataEntry::new(
+                    self.is_keyframe_metadata_key,
+                    MetadataValue::Bool(is_keyframe),
+                    &mut self.metadata_buffer,
+                )
+                .unwrap(); // Should only happen if type mismatch occurs
+
+                let pts_offset_metadata = MetadataEntry::new(
+                    self.pts_offset_metadata_key,
+                    MetadataValue::I32(pts_offset as i32),
+                    &mut self.metadata_buffer,
+                )
+                .unwrap(); // Should only happen if type mismatch occurs
+
+                let metadata = MediaPayloadMetadataCollection::new(
+                    vec![is_keyframe_metadata, pts_offset_metadata].into_iter(),
+                    &mut self.metadata_buffer,
+                );
+
+                outputs.media.push(MediaNotification {
+                    stream_id: program.stream_id.clone(),
+                    content: MediaNotificationContent::MediaPayload {
+                        media_type: MediaType::Video,
+                        payload_type: VIDEO_CODEC_H264_ANNEXB.clone(),
+                        timestamp: frame.dts,
+                        metadata,
+                        data: frame.data,
+                        is_required_for_decoding: false,
+                    },
+                    annotations: Default::default(),
+                });
+            }
+
+            ElementaryStreamType::AacAdts => {
+                let adts_frames = match split_adts(&frame.data) {
+                    Ok(frames) => frames,
+                    Err(error) => {
+                        warn!(
+                            program_stream_id = %program.stream_id.0,
+                            "Failed to read AAC frames from program {}: {}",
+                            frame.program_number, error
+                        );
+
+                        self.invalid_frame_count += 1;
+                        return;
+                    }
+                };
+
+                for (index, adts_frame) in adts_frames.into_iter().enumerate() {
+                    let sequence_header = adts_frame.config.to_bytes();
+                    if program.audio_sequence_header.as_ref() != Some(&sequence_header) {
+                        program.audio_sequence_header = Some(sequence_header.clone());
+                        outputs.media.push(aac_payload(
+                            &program.stream_id,
+                            frame.pts,
+                            sequence_header,
+                            true,
+                        ));
+                    }
+
+                    // Each ADTS frame in a PES packet follows the previous one, but only the
+                    // first frame's timestamp is specified.
+                    let offset = Duration::from_micros(
+                        index as u64 * AAC_SAMPLES_PER_FRAME * 1_000_000
+                            / u64::from(adts_frame.config.sample_rate),
+                    );
+
+                    outputs.media.push(aac_payload(
+                        &program.stream_id,
+                        frame.pts + offset,
+                        adts_frame.data,
+                        false,
+                    ));
+                }
+            }
+        }
+    }
+
+    fn end_programs(&mut self, source_id: &StreamId, outputs: &mut StepOutputs) {
+        if let Some(source) = self.sources.remove(source_id) {
+            for program in source.programs.into_values() {
+                outputs.media.push(MediaNotification {
+                    stream_id: program.stream_id,
+                    content: MediaNotificationContent::StreamDisconnected,
+                    annotations: Default::default(),
+                });
+            }
+        }
+    }
+}
+
+fn aac_payload(
+    stream_id: &StreamId,
+    timestamp: Duration,
+    data: Bytes,
+    is_required_for_decoding: bool,
+) -> MediaNotification {
+    MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Audio,
+            payload_type: AUDIO_CODEC_AAC_RAW.clone(),
+            timestamp,
+            metadata: MediaPayloadMetadataCollection::new(std::iter::empty(), &mut BytesMut::new()),
+            data,
+            is_required_for_decoding,
+        },
+        annotations: Default::default(),
+    }
+}
+
+impl WorkflowStep for MpegTsDemuxStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            self.handle_media(media, outputs);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let program_streams: usize = self
+            .sources
+            .values()
+            .map(|source| source.programs.len())
+            .sum();
+
+        let skipped_byte_count: u64 = self
+            .sources
+            .values()
+            .map(|source| source.demuxer.skipped_byte_count())
+            .sum();
+
+        let mut details = HashMap::new();
+        details.insert(
+            PROGRAM_STREAMS_DETAIL.to_string(),
+            program_streams.to_string(),
+        );
+
+        details.insert(
+            INVALID_FRAME_COUNT_DETAIL.to_string(),
+            self.invalid_frame_count.to_string(),
+        );
+
+        details.insert(
+            SKIPPED_BYTE_COUNT_DETAIL.to_string(),
+            skipped_byte_count.to_string(),
+        );
+
+        details
+    }
+}
@@ -0,0 +1,446 @@
+use super::*;
+use crate::codecs::aac::AudioSpecificConfig;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::common_metadata::{
+    get_is_keyframe_metadata_key, get_pts_offset_metadata_key,
+};
+use crate::workflows::metadata::MetadataKeyMap;
+use crate::workflows::steps::test_utils::StepTestContext;
+use std::iter;
+
+const PMT_PID_1: u16 = 0x1000;
+const PMT_PID_2: u16 = 0x1001;
+const VIDEO_PID_1: u16 = 0x0100;
+const AUDIO_PID_2: u16 = 0x0101;
+const VIDEO_PID_2: u16 = 0x0102;
+
+struct TestContext {
+    step_context: StepTestContext,
+    is_keyframe_metadata_key: MetadataKey,
+    source_id: StreamId,
+}
+
+impl TestContext {
+    fn new(programs: Option<&str>) -> Self {
+        let mut metadata_map = MetadataKeyMap::new();
+        let is_keyframe_metadata_key = get_is_keyframe_metadata_key(&mut metadata_map);
+        let pts_offset_metadata_key = get_pts_offset_metadata_key(&mut metadata_map);
+
+        let mut definition = WorkflowStepDefinition {
+            step_type: WorkflowStepType("mpegts_demux".to_string()),
+            parameters: HashMap::new(),
+        };
+
+        if let Some(programs) = programs {
+            definition
+                .parameters
+                .insert(PROGRAMS.to_string(), Some(programs.to_string()));
+        }
+
+        let generator =
+            MpegTsDemuxStepGenerator::new(is_keyframe_metadata_key, pts_offset_metadata_key);
+        let mut step_context =
+            StepTestContext::new(Box::new(generator), definition).expect("Failed to create step");
+
+        let source_id = StreamId(Arc::new("abc".to_string()));
+        step_context.assert_media_not_passed_through(MediaNotification {
+            stream_id: source_id.clone(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("source".to_string()),
+            },
+            annotations: Default::default(),
+        });
+
+        TestContext {
+            step_context,
+            is_keyframe_metadata_key,
+            source_id,
+        }
+    }
+
+    /// Sends the transport stream through the step in small chunks that don't line up with TS
+    /// packet boundaries, returning all media raised by the step
+    fn send_transport_stream(&mut self, data: &[u8]) -> Vec<MediaNotification> {
+        let mut outputs = Vec::new();
+        for chunk in data.chunks(100) {
+            self.step_context.execute_with_media(MediaNotification {
+                stream_id: self.source_id.clone(),
+                content: MediaNotificationContent::MediaPayload {
+                    media_type: MediaType::Other,
+                    payload_type: CONTAINER_MPEG_TS.clone(),
+                    timestamp: Duration::from_millis(0),
+                    metadata: MediaPayloadMetadataCollection::new(
+                        iter::empty(),
+                        &mut BytesMut::new(),
+                    ),
+                    data: Bytes::copy_from_slice(chunk),
+                    is_required_for_decoding: false,
+                },
+                annotations: Default::default(),
+            });
+
+            outputs.append(&mut self.step_context.media_outputs);
+        }
+
+        outputs
+    }
+
+    fn is_keyframe(&self, metadata: &MediaPayloadMetadataCollection) -> bool {
+        metadata
+            .iter()
+            .filter(|m| m.key() == self.is_keyframe_metadata_key)
+            .filter_map(|m| match m.value() {
+                MetadataValue::Bool(val) => Some(val),
+                _ => None,
+            })
+            .next()
+            .unwrap_or_default()
+    }
+}
+
+/// Writes a TS packet containing the payload, padding it with adaptation field stuffing if it
+/// doesn't fill the packet
+fn ts_packet(pid: u16, payload_unit_start: bool, payload: &[u8]) -> Vec<u8> {
+    assert!(
+        payload.len() <= 184,
+        "Payload too large for a single packet"
+    );
+
+    let mut packet = vec![
+        0x47,
+        ((payload_unit_start as u8) << 6) | (pid >> 8) as u8,
+        pid as u8,
+    ];
+
+    if payload.len() == 184 {
+        packet.push(0x10);
+    } else {
+        let adaptation_field_length = 184 - payload.len() - 1;
+        packet.push(0x30);
+        packet.push(adaptation_field_length as u8);
+        if adaptation_field_length > 0 {
+            packet.push(0x00);
+            packet.extend(iter::repeat_n(0xFF, adaptation_field_length - 1));
+        }
+    }
+
+    packet.extend_from_slice(payload);
+    packet
+}
+
+fn psi_packet(pid: u16, table_id: u8, table_id_extension: u16, body: &[u8]) -> Vec<u8> {
+    let section_length = 5 + body.len() + 4;
+    let mut payload = vec![
+        0x00, // pointer field
+        table_id,
+        0xB0 | (section_length >> 8) as u8,
+        section_length as u8,
+        (table_id_extension >> 8) as u8,
+        table_id_extension as u8,
+        0xC1,
+        0x00,
+        0x00,
+    ];
+
+    payload.extend_from_slice(body);
+    payload.extend_from_slice(&[0, 0, 0, 0]); // CRC, which the demuxer doesn't verify
+
+    ts_packet(pid, true, &payload)
+}
+
+fn pat() -> Vec<u8> {
+    let body = [
+        0x00,
+        0x01,
+        0xE0 | (PMT_PID_1 >> 8) as u8,
+        PMT_PID_1 as u8,
+        0x00,
+        0x02,
+        0xE0 | (PMT_PID_2 >> 8) as u8,
+        PMT_PID_2 as u8,
+    ];
+
+    psi_packet(0x0000, 0x00, 1, &body)
+}
+
+fn pmt(pmt_pid: u16, program_number: u16, streams: &[(u8, u16)]) -> Vec<u8> {
+    let pcr_pid = streams[0].1;
+    let mut body = vec![0xE0 | (pcr_pid >> 8) as u8, pcr_pid as u8, 0xF0, 0x00];
+    for (stream_type, pid) in streams {
+        body.extend_from_slice(&[
+            *stream_type,
+            0xE0 | (pid >> 8) as u8,
+            *pid as u8,
+            0xF0,
+            0x00,
+        ]);
+    }
+
+    psi_packet(pmt_pid, 0x02, program_number, &body)
+}
+
+fn pes(pid: u16, stream_id: u8, pts_millis: u64, data: &[u8], declare_length: bool) -> Vec<u8> {
+    let pts = pts_millis * 90;
+    let mut pes = vec![0x00, 0x00, 0x01, stream_id];
+    let length = if declare_length {
+        3 + 5 + data.len()
+    } else {
+        0
+    };
+    pes.extend_from_slice(&(length as u16).to_be_bytes());
+    pes.extend_from_slice(&[
+        0x80,
+        0x80,
+        0x05,
+        0x21 | ((pts >> 29) & 0x0E) as u8,
+        (pts >> 22) as u8,
+        ((pts >> 14) & 0xFE) as u8 | 0x01,
+        (pts >> 7) as u8,
+        ((pts << 1) & 0xFE) as u8 | 0x01,
+    ]);
+
+    pes.extend_from_slice(data);
+
+    pes.chunks(184)
+        .enumerate()
+        .flat_map(|(index, chunk)| ts_packet(pid, index == 0, chunk))
+        .collect()
+}
+
+fn h264_frame(nal_unit_type: u8, size: usize) -> Vec<u8> {
+    let mut frame = vec![0x00, 0x00, 0x00, 0x01, 0x60 | nal_unit_type];
+    frame.extend(iter::repeat_n(0xAB, size));
+    frame
+}
+
+/// Two ADTS framed AAC-LC frames, at 48kHz stereo
+fn adts_frames() -> Vec<u8> {
+    vec![
+        0xFF, 0xF1, 0x4C, 0x80, 0x01, 0x3F, 0xFC, 0xAA, 0xBB, 0xFF, 0xF1, 0x4C, 0x80, 0x01, 0x5F,
+        0xFC, 0xCC, 0xDD, 0xEE,
+    ]
+}
+
+fn two_program_transport_stream() -> Vec<u8> {
+    let mut ts = Vec::new();
+    ts.extend(pat());
+    ts.extend(pmt(PMT_PID_1, 1, &[(0x1B, VIDEO_PID_1)]));
+    ts.extend(pmt(
+        PMT_PID_2,
+        2,
+        &[(0x1B, VIDEO_PID_2), (0x0F, AUDIO_PID_2)],
+    ));
+    ts.extend(pes(VIDEO_PID_1, 0xE0, 1000, &h264_frame(5, 400), false));
+    ts.extend(pes(AUDIO_PID_2, 0xC0, 1000, &adts_frames(), true));
+    ts.extend(pes(VIDEO_PID_1, 0xE0, 1040, &h264_frame(1, 50), false));
+    ts.extend(pes(VIDEO_PID_2, 0xE0, 1000, &h264_frame(5, 50), false));
+    ts.extend(pes(VIDEO_PID_2, 0xE0, 1040, &h264_frame(1, 50), false));
+
+    ts
+}
+
+fn new_stream_ids(outputs: &[MediaNotification]) -> HashMap<String, StreamId> {
+    outputs
+        .iter()
+        .filter_map(|media| match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                Some((stream_name.to_string(), media.stream_id.clone()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn invalid_program_mapping_returns_error() {
+    let mut metadata_map = MetadataKeyMap::new();
+    let generator = MpegTsDemuxStepGenerator::new(
+        get_is_keyframe_metadata_key(&mut metadata_map),
+        get_pts_offset_metadata_key(&mut metadata_map),
+    );
+
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("mpegts_demux".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    definition
+        .parameters
+        .insert(PROGRAMS.to_string(), Some("abc:news".to_string()));
+
+    let result = StepTestContext::new(Box::new(generator), definition);
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn two_program_transport_stream_produces_two_streams() {
+    let mut context = TestContext::new(Some("1:news"));
+    let outputs = context.send_transport_stream(&two_program_transport_stream());
+
+    let stream_ids = new_stream_ids(&outputs);
+    assert_eq!(stream_ids.len(), 2, "Unexpected number of new streams");
+
+    let news_id = stream_ids.get("news").expect("No stream for program 1");
+    let program2_id = stream_ids
+        .get("source_program2")
+        .expect("No stream for program 2");
+
+    assert_ne!(news_id, program2_id, "Expected distinct stream ids");
+
+    let payloads_for = |stream_id: &StreamId| {
+        outputs
+            .iter()
+            .filter(|media| &media.stream_id == stream_id)
+            .filter_map(|media| match &media.content {
+                MediaNotificationContent::MediaPayload {
+                    media_type,
+                    payload_type,
+                    timestamp,
+                    metadata,
+                    data,
+                    is_required_for_decoding,
+                } => Some((
+                    *media_type,
+                    payload_type.clone(),
+                    *timestamp,
+                    context.is_keyframe(metadata),
+                    data.clone(),
+                    *is_required_for_decoding,
+                )),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Program 1's second video frame is still waiting for the next PES packet to start
+    let news_payloads = payloads_for(news_id);
+    assert_eq!(
+        news_payloads.len(),
+        1,
+        "Unexpected number of program 1 payloads"
+    );
+    assert_eq!(
+        news_payloads[0].0,
+        MediaType::Video,
+        "Unexpected media type"
+    );
+    assert_eq!(
+        news_payloads[0].1, *VIDEO_CODEC_H264_ANNEXB,
+        "Unexpected payload type"
+    );
+    assert_eq!(
+        news_payloads[0].2,
+        Duration::from_millis(1000),
+        "Unexpected timestamp"
+    );
+    assert!(news_payloads[0].3, "Expected IDR frame to be a keyframe");
+    assert_eq!(
+        news_payloads[0].4.as_ref(),
+        h264_frame(5, 400).as_slice(),
+        "Unexpected video data"
+    );
+
+    let program2_payloads = payloads_for(program2_id);
+    let audio = program2_payloads
+        .iter()
+        .filter(|payload| payload.0 == MediaType::Audio)
+        .collect::<Vec<_>>();
+
+    assert_eq!(audio.len(), 3, "Expected a sequence header and two frames");
+    assert!(audio
+        .iter()
+        .all(|payload| payload.1 == *AUDIO_CODEC_AAC_RAW));
+    assert!(audio[0].5, "Expected sequence header first");
+    assert_eq!(
+        AudioSpecificConfig::parse(&audio[0].4).unwrap(),
+        AudioSpecificConfig {
+            audio_object_type: 2,
+            sample_rate: 48000,
+            channel_configuration: 2,
+        },
+        "Unexpected audio sequence header"
+    );
+
+    assert_eq!(audio[1].4.as_ref(), &[0xAA, 0xBB], "Unexpected first frame");
+    assert_eq!(
+        audio[2].4.as_ref(),
+        &[0xCC, 0xDD, 0xEE],
+        "Unexpected second frame"
+    );
+    assert!(
+        audio[2].2 > audio[1].2,
+        "Expected second audio frame to be after the first"
+    );
+
+    let video = program2_payloads
+        .iter()
+        .filter(|payload| payload.0 == MediaType::Video)
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        video.len(),
+        1,
+        "Unexpected number of program 2 video frames"
+    );
+    assert_eq!(
+        video[0].1, *VIDEO_CODEC_H264_ANNEXB,
+        "Unexpected payload type"
+    );
+    assert!(video[0].3, "Expected IDR frame to be a keyframe");
+}
+
+#[test]
+fn non_keyframes_are_not_marked_as_keyframes() {
+    let mut context = TestContext::new(None);
+    let mut ts = two_program_transport_stream();
+    ts.extend(pes(VIDEO_PID_1, 0xE0, 1080, &h264_frame(1, 50), false));
+
+    let outputs = context.send_transport_stream(&ts);
+    let stream_ids = new_stream_ids(&outputs);
+    let program1_id = stream_ids
+        .get("source_program1")
+        .expect("No stream for program 1");
+
+    let keyframes = outputs
+        .iter()
+        .filter(|media| &media.stream_id == program1_id)
+        .filter_map(|media| match &media.content {
+            MediaNotificationContent::MediaPayload { metadata, .. } => {
+                Some(context.is_keyframe(metadata))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(keyframes, vec![true, false], "Unexpected keyframe flags");
+}
+
+#[test]
+fn program_streams_disconnected_when_source_disconnects() {
+    let mut context = TestContext::new(None);
+    let outputs = context.send_transport_stream(&two_program_transport_stream());
+    let stream_ids = new_stream_ids(&outputs);
+
+    context.step_context.execute_with_media(MediaNotification {
+        stream_id: context.source_id.clone(),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    });
+
+    let mut disconnected = context
+        .step_context
+        .media_outputs
+        .iter()
+        .filter(|media| media.content == MediaNotificationContent::StreamDisconnected)
+        .map(|media| media.stream_id.clone())
+        .collect::<Vec<_>>();
+
+    let mut expected = stream_ids.into_values().collect::<Vec<_>>();
+    disconnected.sort_by(|a, b| a.0.cmp(&b.0));
+    expected.sort_by(|a, b| a.0.cmp(&b.0));
+
+    assert_eq!(
+        disconnected, expected,
+        "Expected both programs to disconnect"
+    );
+}
@@ -0,0 +1,498 @@
+//! The rate shape step picks one rendition out of a group of renditions of the same content (e.g.
+//! the outputs of several transcodes) to forward as a single output stream, based on a target
+//! bitrate. This provides server side adaptive bitrate for destinations that can only receive a
+//! single rendition, such as an RTMP push to a bandwidth limited destination.
+//!
+//! Renditions are specified with the `renditions` parameter as a comma separated list of
+//! `<label>:<bitrate>` entries, where the label is the stream label the rendition is announced
+//! with and the bitrate is the rendition's bitrate in kbps (e.g. `renditions=high:3000,low:800`).
+//! The highest bitrate rendition that fits within the `target_bitrate` (in kbps) is selected, or
+//! the lowest bitrate rendition if none fit. Without a `target_bitrate`, the highest bitrate
+//! rendition is selected.
+//!
+//! The target bitrate can be changed while streams are flowing (e.g. from downstream bandwidth
+//! estimates) by a metadata notification containing only the `TARGET_BITRATE_METADATA_KEY` key,
+//! with the new target in kbps as its value. These notifications are consumed by the step.
+//!
+//! Switches to a different rendition happen on the new rendition's next video keyframe, so the
+//! output stays decodable. Each switch is signaled with a metadata notification containing only
+//! the `DISCONTINUITY_METADATA_KEY` key, followed by the new rendition's sequence headers. Since
+//! renditions of the same content share a timeline, timestamps are not rebased on switches.
+//!
+//! The output stream is announced with the `output_stream_name` name. Media from renditions is
+//! never passed through as is, while all other streams are passed through untouched. Since a
+//! stream's label is only known after its new incoming stream notification has been passed
+//! through, renditions are announced as disconnected to later steps when they are adopted.
+
+#[cfg(test)]
+mod tests;
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::discontinuity::discontinuity_notification;
+use crate::workflows::metadata::{MediaPayloadMetadataCollection, MetadataKey, MetadataValue};
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::stream_labels::get_announced_label;
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use crate::StreamId;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+pub const RENDITIONS: &str = "renditions";
+pub const TARGET_BITRATE: &str = "target_bitrate";
+pub const OUTPUT_STREAM_NAME: &str = "output_stream_name";
+
+/// The metadata key a new target bitrate is announced with. The value is the target in kbps.
+pub const TARGET_BITRATE_METADATA_KEY: &str = "mmids_target_bitrate";
+
+const SELECTED_RENDITION_DETAIL: &str = "selected_rendition";
+const TARGET_BITRATE_DETAIL: &str = "target_bitrate";
+const SWITCH_COUNT_DETAIL: &str = "switch_count";
+
+/// Generates new instances of the rate shape workflow step
+pub struct RateShapeStepGenerator {
+    is_keyframe_metadata_key: MetadataKey,
+}
+
+struct Rendition {
+    label: String,
+    bitrate: u32,
+    stream_id: Option<StreamId>,
+    sequence_headers: Vec<MediaNotificationContent>,
+}
+
+struct RateShapeStep {
+    is_keyframe_metadata_key: MetadataKey,
+
+    /// Renditions ordered from the highest bitrate to the lowest
+    renditions: Vec<Rendition>,
+    output_stream_name: Arc<String>,
+    target_bitrate: Option<u32>,
+    active_rendition: Option<usize>,
+
+    /// The rendition that will be switched to on its next keyframe
+    pending_rendition: Option<usize>,
+    output_stream_id: Option<StreamId>,
+    switch_count: u64,
+}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error("No {} parameter specified", RENDITIONS)]
+    NoRenditions,
+
+    #[error(
+        "Invalid rendition of '{0}' specified, must be in the format of <label>:<bitrate in kbps>"
+    )]
+    InvalidRendition(String),
+
+    #[error("The rendition label '{0}' was specified more than once")]
+    DuplicateRendition(String),
+
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a number of kbps greater than zero",
+        TARGET_BITRATE
+    )]
+    InvalidTargetBitrate(String),
+
+    #[error("No {} parameter specified", OUTPUT_STREAM_NAME)]
+    NoOutputStreamName,
+}
+
+impl RateShapeStepGenerator {
+    pub fn new(is_keyframe_metadata_key: MetadataKey) -> Self {
+        RateShapeStepGenerator {
+            is_keyframe_metadata_key,
+        }
+    }
+}
+
+impl StepGenerator for RateShapeStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let renditions = match get_parameter(&definition, RENDITIONS) {
+            Some(value) => parse_renditions(&value)?,
+            None => return Err(Box::new(StepStartupError::NoRenditions)),
+        };
+
+        let target_bitrate = match get_parameter(&definition, TARGET_BITRATE) {
+            Some(value) => match parse_bitrate(&value) {
+                Some(bitrate) => Some(bitrate),
+                None => return Err(Box::new(StepStartupError::InvalidTargetBitrate(value))),
+            },
+
+            None => None,
+        };
+
+        let output_stream_name = match get_parameter(&definition, OUTPUT_STREAM_NAME) {
+            Some(name) => Arc::new(name),
+            None => return Err(Box::new(StepStartupError::NoOutputStreamName)),
+        };
+
+        let step = RateShapeStep {
+            is_keyframe_metadata_key: self.is_keyframe_metadata_key,
+            renditions,
+            output_stream_name,
+            target_bitrate,
+            active_rendition: None,
+            pending_rendition: None,
+            output_stream_id: None,
+            switch_count: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+fn get_parameter(definition: &WorkflowStepDefinition, parameter: &str) -> Option<String> {
+    match definition.parameters.get(parameter) {
+        Some(Some(value)) if !value.trim().is_empty() => Some(value.trim().to_string()),
+        _ => None,
+    }
+}
+
+fn parse_bitrate(value: &str) -> Option<u32> {
+    match value.trim().parse::<u32>() {
+        Ok(bitrate) if bitrate > 0 => Some(bitrate),
+        _ => None,
+    }
+}
+
+fn parse_renditions(value: &str) -> Result<Vec<Rendition>, StepStartupError> {
+    let mut labels = HashSet::new();
+    let mut renditions = Vec::new();
+    for entry in value.split(',').map(|entry| entry.trim()) {
+        let (label, bitrate) = match entry.split_once(':') {
+            Some((label, bitrate)) if !label.trim().is_empty() => (label.trim(), bitrate),
+            _ => return Err(StepStartupError::InvalidRendition(entry.to_string())),
+        };
+
+        let bitrate = match parse_bitrate(bitrate) {
+            Some(bitrate) => bitrate,
+            None => return Err(StepStartupError::InvalidRendition(entry.to_string())),
+        };
+
+        if !labels.insert(label.to_string()) {
+            return Err(StepStartupError::DuplicateRendition(label.to_string()));
+        }
+
+        renditions.push(Rendition {
+            label: label.to_string(),
+            bitrate,
+            stream_id: None,
+            sequence_headers: Vec::new(),
+        });
+    }
+
+    renditions.sort_by_key(|rendition| Reverse(rendition.bitrate));
+
+    Ok(renditions)
+}
+
+/// Returns the target bitrate announced by the notification content, if it's a target bitrate
+/// announcement
+fn get_announced_target_bitrate(content: &MediaNotificationContent) -> Option<&str> {
+    match content {
+        MediaNotificationContent::Metadata { data } if data.len() == 1 => data
+            .get(TARGET_BITRATE_METADATA_KEY)
+            .map(|bitrate| bitrate.as_str()),
+
+        _ => None,
+    }
+}
+
+impl Rendition {
+    fn reset(&mut self) {
+        self.stream_id = None;
+        self.sequence_headers.clear();
+    }
+
+    fn store_sequence_header(&mut self, content: &MediaNotificationContent) {
+        if let MediaNotificationContent::MediaPayload { media_type, .. } = content {
+            // Only the latest sequence header of each media type is needed for decoding
+            self.sequence_headers.retain(|header| match header {
+                MediaNotificationContent::MediaPayload {
+                    media_type: header_type,
+                    ..
+                } => header_type != media_type,
+                _ => true,
+            });
+
+            self.sequence_headers.push(content.clone());
+        }
+    }
+}
+
+impl RateShapeStep {
+    fn rendition_of(&self, stream_id: &StreamId) -> Option<usize> {
+        self.renditions
+            .iter()
+            .position(|rendition| rendition.stream_id.as_ref() == Some(stream_id))
+    }
+
+    fn handle_media(&mut self, media: MediaNotification, outputs: &mut StepOutputs) {
+        if let Some(bitrate) = get_announced_target_bitrate(&media.content) {
+            match parse_bitrate(bitrate) {
+                Some(bitrate) => {
+                    info!("Target bitrate changed to {}kbps", bitrate);
+                    self.target_bitrate = Some(bitrate);
+                    self.select_rendition(outputs);
+                }
+
+                None => warn!("Ignoring invalid target bitrate of '{}'", bitrate),
+            }
+
+            return;
+        }
+
+        let index = match self.rendition_of(&media.stream_id) {
+            Some(index) => index,
+            None => {
+                self.handle_unassigned_media(media, outputs);
+                return;
+            }
+        };
+
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { .. } => {
+                // The stream was re-announced, so its sequence headers are stale
+                self.renditions[index].sequence_headers.clear();
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                info!(
+                    stream_id = %media.stream_id.0,
+                    "The {} rendition disconnected", self.renditions[index].label
+                );
+
+                self.renditions[index].reset();
+                if self.active_rendition == Some(index) {
+                    self.active_rendition = None;
+                }
+
+                self.select_rendition(outputs);
+            }
+
+            MediaNotificationContent::Metadata { .. } => {
+                if get_announced_label(&media.content).is_some() {
+                    // Rendition labels are consumed by this step
+                    return;
+                }
+
+                if self.active_rendition == Some(index) {
+                    self.forward(media.content, outputs);
+                }
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type,
+                metadata,
+                is_required_for_decoding,
+                ..
+            } => {
+                if *is_required_for_decoding {
+                    self.renditions[index].store_sequence_header(&media.content);
+                } else if self.pending_rendition == Some(index)
+                    && *media_type == MediaType::Video
+                    && self.is_keyframe(metadata)
+                {
+                    self.switch_to(index, outputs);
+                }
+
+                if self.active_rendition == Some(index) {
+                    self.forward(media.content, outputs);
+                }
+            }
+        }
+    }
+
+    fn handle_unassigned_media(&mut self, media: MediaNotification, outputs: &mut StepOutputs) {
+        let index = get_announced_label(&media.content).and_then(|label| {
+            self.renditions
+                .iter()
+                .position(|rendition| rendition.label == label && rendition.stream_id.is_none())
+        });
+
+        let index = match index {
+            Some(index) => index,
+            None => {
+                outputs.media.push(media);
+                return;
+            }
+        };
+
+        info!(
+            stream_id = %media.stream_id.0,
+            "Stream adopted as the {} rendition", self.renditions[index].label
+        );
+
+        // Later steps were told about this stream before we knew its label
+        outputs.media.push(MediaNotification {
+            stream_id: media.stream_id.clone(),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        });
+
+        let rendition = &mut self.renditions[index];
+        rendition.reset();
+        rendition.stream_id = Some(media.stream_id);
+
+        self.select_rendition(outputs);
+    }
+
+    fn is_keyframe(&self, metadata: &MediaPayloadMetadataCollection) -> bool {
+        metadata
+            .iter()
+            .filter(|m| m.key() == self.is_keyframe_metadata_key)
+            .filter_map(|m| match m.value() {
+                MetadataValue::Bool(val) => Some(val),
+                _ => None,
+            })
+            .next()
+            .unwrap_or_default()
+    }
+
+    /// Picks which of the connected renditions should be forwarded based on the target bitrate,
+    /// and waits for its next keyframe if it's not the one already being forwarded.
+    fn select_rendition(&mut self, outputs: &mut StepOutputs) {
+        let connected = self
+            .renditions
+            .iter()
+            .enumerate()
+            .filter(|(_, rendition)| rendition.stream_id.is_some())
+            .collect::<Vec<_>>();
+
+        let desired = match self.target_bitrate {
+            Some(target) => connected
+                .iter()
+                .find(|(_, rendition)| rendition.bitrate <= target)
+                .or_else(|| connected.last())
+                .map(|(index, _)| *index),
+
+            None => connected.first().map(|(index, _)| *index),
+        };
+
+        if desired.is_none() {
+            self.pending_rendition = None;
+            self.end_output(outputs);
+        } else if desired == self.active_rendition {
+            self.pending_rendition = None;
+        } else if desired != self.pending_rendition {
+            if let Some(index) = desired {
+                info!(
+                    "Switching to the {} rendition on its next keyframe",
+                    self.renditions[index].label
+                );
+            }
+
+            self.pending_rendition = desired;
+        }
+    }
+
+    fn switch_to(&mut self, index: usize, outputs: &mut StepOutputs) {
+        self.pending_rendition = None;
+        self.active_rendition = Some(index);
+
+        let label = self.renditions[index].label.clone();
+        match &self.output_stream_id {
+            None => {
+                info!("Starting output stream from the {} rendition", label);
+
+                let stream_id = StreamId(Arc::new(Uuid::new_v4().to_string()));
+                outputs.media.push(MediaNotification {
+                    stream_id: stream_id.clone(),
+                    content: MediaNotificationContent::NewIncomingStream {
+                        stream_name: self.output_stream_name.clone(),
+                    },
+                    annotations: Default::default(),
+                });
+
+                self.output_stream_id = Some(stream_id);
+            }
+
+            Some(stream_id) => {
+                info!("Switched to the {} rendition", label);
+                self.switch_count += 1;
+
+                outputs
+                    .media
+                    .push(discontinuity_notification(stream_id.clone(), &label));
+            }
+        }
+
+        for header in self.renditions[index].sequence_headers.clone() {
+            self.forward(header, outputs);
+        }
+    }
+
+    fn end_output(&mut self, outputs: &mut StepOutputs) {
+        self.active_rendition = None;
+        if let Some(stream_id) = self.output_stream_id.take() {
+            info!("All renditions are gone, ending the output stream");
+            outputs.media.push(MediaNotification {
+                stream_id,
+                content: MediaNotificationContent::StreamDisconnected,
+                annotations: Default::default(),
+            });
+        }
+    }
+
+    /// Sends the active rendition's media out on the output stream
+    fn forward(&self, content: MediaNotificationContent, outputs: &mut StepOutputs) {
+        if let Some(stream_id) = &self.output_stream_id {
+            outputs.media.push(MediaNotification {
+                stream_id: stream_id.clone(),
+                content,
+                annotations: Default::default(),
+            });
+        }
+    }
+}
+
+impl WorkflowStep for RateShapeStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            self.handle_media(media, outputs);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            SELECTED_RENDITION_DETAIL.to_string(),
+            self.active_rendition.map_or("none".to_string(), |index| {
+                self.renditions[index].label.clone()
+            }),
+        );
+
+        details.insert(
+            TARGET_BITRATE_DETAIL.to_string(),
+            self.target_bitrate
+                .map_or("none".to_string(), |bitrate| bitrate.to_string()),
+        );
+
+        details.insert(
+            SWITCH_COUNT_DETAIL.to_string(),
+            self.switch_count.to_string(),
+        );
+
+        details
+    }
+}
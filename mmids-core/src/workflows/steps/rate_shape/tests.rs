@@ -0,0 +1,345 @@
+use super::*;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::discontinuity::DISCONTINUITY_METADATA_KEY;
+use crate::workflows::metadata::common_metadata::get_is_keyframe_metadata_key;
+use crate::workflows::metadata::{MetadataEntry, MetadataKeyMap};
+use crate::workflows::steps::test_utils::StepTestContext;
+use crate::workflows::stream_labels::label_notification;
+use bytes::{Bytes, BytesMut};
+use std::iter;
+use std::time::Duration;
+
+const OUTPUT_NAME: &str = "output";
+
+struct TestContext {
+    step_context: StepTestContext,
+    is_keyframe_metadata_key: MetadataKey,
+}
+
+fn definition(parameters: &[(&str, &str)]) -> WorkflowStepDefinition {
+    WorkflowStepDefinition {
+        step_type: WorkflowStepType("rate_shape".to_string()),
+        parameters: parameters
+            .iter()
+            .map(|(key, value)| (key.to_string(), Some(value.to_string())))
+            .collect(),
+    }
+}
+
+fn stream_id(label: &str) -> StreamId {
+    StreamId(Arc::new(format!("{}-id", label)))
+}
+
+fn target_bitrate(stream_label: &str, bitrate: &str) -> MediaNotification {
+    let mut data = HashMap::new();
+    data.insert(TARGET_BITRATE_METADATA_KEY.to_string(), bitrate.to_string());
+
+    MediaNotification {
+        stream_id: stream_id(stream_label),
+        content: MediaNotificationContent::Metadata { data },
+        annotations: Default::default(),
+    }
+}
+
+impl TestContext {
+    fn new(parameters: &[(&str, &str)]) -> Self {
+        let mut metadata_map = MetadataKeyMap::new();
+        let is_keyframe_metadata_key = get_is_keyframe_metadata_key(&mut metadata_map);
+        let generator = RateShapeStepGenerator::new(is_keyframe_metadata_key);
+        let step_context = StepTestContext::new(Box::new(generator), definition(parameters))
+            .expect("Failed to create step");
+
+        TestContext {
+            step_context,
+            is_keyframe_metadata_key,
+        }
+    }
+
+    /// Announces a rendition stream and labels it
+    fn connect(&mut self, label: &str) {
+        self.step_context.execute_with_media(MediaNotification {
+            stream_id: stream_id(label),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new(label.to_string()),
+            },
+            annotations: Default::default(),
+        });
+
+        self.step_context.execute_with_media(label_notification(
+            stream_id(label),
+            Arc::new(label.to_string()),
+        ));
+
+        self.step_context
+            .execute_with_media(self.video(label, 0, false, true));
+    }
+
+    fn video(
+        &self,
+        label: &str,
+        timestamp: u64,
+        is_keyframe: bool,
+        is_sequence_header: bool,
+    ) -> MediaNotification {
+        let mut buffer = BytesMut::new();
+        let entry = MetadataEntry::new(
+            self.is_keyframe_metadata_key,
+            MetadataValue::Bool(is_keyframe),
+            &mut buffer,
+        )
+        .unwrap();
+
+        MediaNotification {
+            stream_id: stream_id(label),
+            content: MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type: Arc::new("test".to_string()),
+                timestamp: Duration::from_millis(timestamp),
+                metadata: MediaPayloadMetadataCollection::new(iter::once(entry), &mut buffer),
+                data: Bytes::from(format!("{}-{}", label, timestamp)),
+                is_required_for_decoding: is_sequence_header,
+            },
+            annotations: Default::default(),
+        }
+    }
+
+    fn selected_rendition(&self) -> String {
+        self.step_context
+            .step
+            .get_state_details()
+            .get(SELECTED_RENDITION_DETAIL)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+fn data_of(media: &MediaNotification) -> Bytes {
+    match &media.content {
+        MediaNotificationContent::MediaPayload { data, .. } => data.clone(),
+        content => panic!("Expected media payload, instead got {:?}", content),
+    }
+}
+
+fn assert_discontinuity(media: &MediaNotification, label: &str) {
+    match &media.content {
+        MediaNotificationContent::Metadata { data } => {
+            assert_eq!(
+                data.get(DISCONTINUITY_METADATA_KEY).map(|x| x.as_str()),
+                Some(label),
+                "Unexpected discontinuity rendition"
+            );
+        }
+
+        content => panic!("Expected discontinuity, instead got {:?}", content),
+    }
+}
+
+#[test]
+fn invalid_parameters_return_error() {
+    let cases: &[&[(&str, &str)]] = &[
+        &[(OUTPUT_STREAM_NAME, OUTPUT_NAME)],
+        &[(RENDITIONS, "high"), (OUTPUT_STREAM_NAME, OUTPUT_NAME)],
+        &[(RENDITIONS, "high:abc"), (OUTPUT_STREAM_NAME, OUTPUT_NAME)],
+        &[
+            (RENDITIONS, "high:3000,high:800"),
+            (OUTPUT_STREAM_NAME, OUTPUT_NAME),
+        ],
+        &[
+            (RENDITIONS, "high:3000"),
+            (TARGET_BITRATE, "0"),
+            (OUTPUT_STREAM_NAME, OUTPUT_NAME),
+        ],
+        &[(RENDITIONS, "high:3000")],
+    ];
+
+    for parameters in cases {
+        let mut metadata_map = MetadataKeyMap::new();
+        let generator =
+            RateShapeStepGenerator::new(get_is_keyframe_metadata_key(&mut metadata_map));
+        let result = StepTestContext::new(Box::new(generator), definition(parameters));
+
+        assert!(result.is_err(), "Expected error for {:?}", parameters);
+    }
+}
+
+#[test]
+fn unrelated_streams_passed_through() {
+    let mut context = TestContext::new(&[
+        (RENDITIONS, "high:3000,low:800"),
+        (OUTPUT_STREAM_NAME, OUTPUT_NAME),
+    ]);
+
+    let media = context.video("other", 0, true, false);
+    context.step_context.assert_media_passed_through(media);
+}
+
+#[test]
+fn highest_rendition_within_target_starts_output_on_keyframe() {
+    let mut context = TestContext::new(&[
+        (RENDITIONS, "high:3000,mid:1500,low:800"),
+        (TARGET_BITRATE, "2000"),
+        (OUTPUT_STREAM_NAME, OUTPUT_NAME),
+    ]);
+
+    context.connect("high");
+    context.connect("mid");
+    context.connect("low");
+
+    let media = context.video("mid", 10, false, false);
+    context.step_context.assert_media_not_passed_through(media);
+
+    let media = context.video("mid", 20, true, false);
+    context.step_context.execute_with_media(media);
+
+    let outputs = &context.step_context.media_outputs;
+    assert_eq!(outputs.len(), 3, "Unexpected number of outputs");
+    match &outputs[0].content {
+        MediaNotificationContent::NewIncomingStream { stream_name } => {
+            assert_eq!(stream_name.as_str(), OUTPUT_NAME, "Unexpected stream name")
+        }
+
+        content => panic!("Expected new stream, instead got {:?}", content),
+    }
+
+    assert_eq!(
+        data_of(&outputs[1]),
+        Bytes::from("mid-0"),
+        "Expected header"
+    );
+    assert_eq!(data_of(&outputs[2]), Bytes::from("mid-20"), "Expected key");
+    assert_eq!(context.selected_rendition(), "mid");
+}
+
+#[test]
+fn switches_renditions_on_keyframe_as_target_bitrate_changes() {
+    let mut context = TestContext::new(&[
+        (RENDITIONS, "high:3000,low:800"),
+        (TARGET_BITRATE, "1000"),
+        (OUTPUT_STREAM_NAME, OUTPUT_NAME),
+    ]);
+
+    context.connect("high");
+    context.connect("low");
+
+    let media = context.video("low", 10, true, false);
+    context.step_context.execute_with_media(media);
+    assert_eq!(context.selected_rendition(), "low");
+
+    let output_id = context.step_context.media_outputs[0].stream_id.clone();
+
+    // Bandwidth improved, but high keeps waiting until its next keyframe
+    context
+        .step_context
+        .assert_media_not_passed_through(target_bitrate("high", "5000"));
+
+    let media = context.video("high", 20, false, false);
+    context.step_context.assert_media_not_passed_through(media);
+
+    let media = context.video("low", 20, false, false);
+    context.step_context.execute_with_media(media);
+    assert_eq!(
+        data_of(&context.step_context.media_outputs[0]),
+        Bytes::from("low-20"),
+        "Expected low rendition to still be forwarded"
+    );
+
+    let media = context.video("high", 30, true, false);
+    context.step_context.execute_with_media(media);
+
+    let outputs = &context.step_context.media_outputs;
+    assert_eq!(outputs.len(), 3, "Unexpected number of outputs");
+    assert!(
+        outputs.iter().all(|media| media.stream_id == output_id),
+        "Expected all media on the output stream"
+    );
+
+    assert_discontinuity(&outputs[0], "high");
+    assert_eq!(
+        data_of(&outputs[1]),
+        Bytes::from("high-0"),
+        "Expected header"
+    );
+    assert_eq!(data_of(&outputs[2]), Bytes::from("high-30"), "Expected key");
+    assert_eq!(context.selected_rendition(), "high");
+
+    let media = context.video("low", 40, false, false);
+    context.step_context.assert_media_not_passed_through(media);
+
+    // Bandwidth dropped again
+    context
+        .step_context
+        .assert_media_not_passed_through(target_bitrate("low", "500"));
+
+    let media = context.video("low", 50, true, false);
+    context.step_context.execute_with_media(media);
+
+    let outputs = &context.step_context.media_outputs;
+    assert_eq!(outputs.len(), 3, "Unexpected number of outputs");
+    assert_discontinuity(&outputs[0], "low");
+    assert_eq!(context.selected_rendition(), "low");
+
+    let details = context.step_context.step.get_state_details();
+    assert_eq!(
+        details.get(SWITCH_COUNT_DETAIL),
+        Some(&"2".to_string()),
+        "Unexpected switch count"
+    );
+}
+
+#[test]
+fn active_rendition_disconnecting_switches_to_remaining_rendition() {
+    let mut context = TestContext::new(&[
+        (RENDITIONS, "high:3000,low:800"),
+        (OUTPUT_STREAM_NAME, OUTPUT_NAME),
+    ]);
+
+    context.connect("high");
+    context.connect("low");
+
+    let media = context.video("high", 10, true, false);
+    context.step_context.execute_with_media(media);
+    assert_eq!(context.selected_rendition(), "high");
+
+    context
+        .step_context
+        .assert_media_not_passed_through(MediaNotification {
+            stream_id: stream_id("high"),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        });
+
+    assert_eq!(context.selected_rendition(), "none");
+
+    let media = context.video("low", 20, true, false);
+    context.step_context.execute_with_media(media);
+    assert_discontinuity(&context.step_context.media_outputs[0], "low");
+    assert_eq!(context.selected_rendition(), "low");
+}
+
+#[test]
+fn output_stream_ends_when_all_renditions_disconnect() {
+    let mut context =
+        TestContext::new(&[(RENDITIONS, "high:3000"), (OUTPUT_STREAM_NAME, OUTPUT_NAME)]);
+
+    context.connect("high");
+
+    let media = context.video("high", 10, true, false);
+    context.step_context.execute_with_media(media);
+    let output_id = context.step_context.media_outputs[0].stream_id.clone();
+
+    context.step_context.execute_with_media(MediaNotification {
+        stream_id: stream_id("high"),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    });
+
+    assert_eq!(
+        context.step_context.media_outputs,
+        vec![MediaNotification {
+            stream_id: output_id,
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        }],
+        "Expected output stream to end"
+    );
+}
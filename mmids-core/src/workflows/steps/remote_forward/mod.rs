@@ -0,0 +1,490 @@
+//! The remote forward step sends all media notifications it receives to another mmids instance
+//! over a TCP connection, where a `remote_ingest` step will pass them into its own workflow. This
+//! allows the work for a single stream to be split across multiple machines (e.g. ingest on one
+//! machine and transcoding on another). All media notifications are also passed to subsequent
+//! steps.
+//!
+//! If the connection to the remote instance is lost or can't be made, the step retries based on
+//! the retry policy parameters described in the `retry` module, and reports its connection state
+//! through its state details. Media notifications received while disconnected are not sent, but
+//! once the connection has been re-established any media required for decoding each active stream
+//! (e.g. sequence headers) is re-sent, along with the stream's latest tags.
+//!
+//! Stream tags are carried across to the remote instance. The `tags` parameter can be given a
+//! comma separated list of tag names to only forward those tags, otherwise all tags are forwarded.
+//!
+//! The TCP options of the connection can be tuned with the parameters described in the `net::tcp`
+//! module's socket options (`tcp_nodelay`, `send_buffer_size`, `recv_buffer_size`, and
+//! `tcp_keepalive`). Nagle's algorithm is disabled unless `tcp_nodelay` is set to `false`, so
+//! frames are sent as soon as they are available, while the other options are left at the
+//! operating system's defaults.
+//!
+//! Frames waiting to be written to a slow connection are held in memory. Once `max_queued_frames`
+//! frames (1000 by default) are waiting, media payloads that aren't required for decoding are
+//! dropped instead of being queued, and counted in the step's state details. Everything else
+//! (such as sequence headers and stream announcements) is always queued, since the remote side
+//! can't make sense of the stream without it.
+//!
+//! If all connection attempts fail and the policy is set to disconnect, every active stream is
+//! disconnected from later steps and no more media is passed through.
+
+#[cfg(test)]
+mod tests;
+pub mod wire;
+
+use crate::net::tcp::{SocketOptions, SocketOptionsError};
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::retry::{
+    Backoff, ConnectionState, RetryFailureAction, RetryPolicy, RetryPolicyError,
+    CONNECTION_STATE_DETAIL,
+};
+use crate::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::stream_tags::{filter_tags_notification, get_announced_tags};
+use crate::workflows::{MediaNotification, MediaNotificationContent};
+use crate::StreamId;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::{error, info, instrument, warn};
+
+pub const TARGET: &str = "target";
+pub const TAGS: &str = "tags";
+pub const MAX_QUEUED_FRAMES: &str = "max_queued_frames";
+
+const DEFAULT_MAX_QUEUED_FRAMES: usize = 1000;
+
+const DROPPED_FRAMES_DETAIL: &str = "dropped_frames";
+
+/// Generates new instances of the remote forward workflow step
+#[derive(Default)]
+pub struct RemoteForwardStepGenerator {}
+
+struct RemoteForwardStep {
+    target: Arc<String>,
+    connection: FrameQueue,
+    connection_state: ConnectionState,
+    on_retry_failure: RetryFailureAction,
+    required_media_by_stream: HashMap<StreamId, Vec<MediaNotification>>,
+
+    /// The names of the tags to forward, or `None` if all tags are forwarded
+    tags: Option<HashSet<String>>,
+}
+
+/// Bounds how many frames can be waiting to be written to the remote target
+struct FrameQueue {
+    sender: UnboundedSender<wire::Frame>,
+    depth: Arc<AtomicUsize>,
+    max_depth: usize,
+    dropped_frames: u64,
+}
+
+enum ConnectionEvent {
+    Connected,
+    Disconnected,
+    Retrying { attempt: u32 },
+    Failed,
+}
+
+enum FutureResult {
+    ConnectionTaskGone,
+    ConnectionEventReceived(ConnectionEvent),
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error("No {} parameter specified", TARGET)]
+    NoTargetSpecified,
+
+    #[error("Invalid retry policy: {0}")]
+    InvalidRetryPolicy(#[from] RetryPolicyError),
+
+    #[error(transparent)]
+    InvalidSocketOptions(#[from] SocketOptionsError),
+
+    #[error(
+        "Invalid {} value of '{0}', must be a number greater than zero",
+        MAX_QUEUED_FRAMES
+    )]
+    InvalidMaxQueuedFrames(String),
+}
+
+impl RemoteForwardStepGenerator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl StepGenerator for RemoteForwardStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let target = match definition.parameters.get(TARGET) {
+            Some(Some(target)) => Arc::new(target.clone()),
+            _ => return Err(Box::new(StepStartupError::NoTargetSpecified)),
+        };
+
+        let retry_policy = RetryPolicy::from_step_definition(&definition)
+            .map_err(StepStartupError::InvalidRetryPolicy)?;
+
+        let mut socket_options = SocketOptions::from_parameters(&definition.parameters)
+            .map_err(StepStartupError::InvalidSocketOptions)?;
+
+        socket_options.nodelay = socket_options.nodelay.or(Some(true));
+
+        let tags = match definition.parameters.get(TAGS) {
+            Some(Some(tags)) => Some(
+                tags.split(',')
+                    .map(|x| x.trim())
+                    .filter(|x| !x.is_empty())
+                    .map(|x| x.to_string())
+                    .collect(),
+            ),
+            _ => None,
+        };
+
+        let max_queued_frames = match definition.parameters.get(MAX_QUEUED_FRAMES) {
+            Some(Some(value)) => match value.parse::<usize>() {
+                Ok(max) if max > 0 => max,
+                _ => {
+                    return Err(Box::new(StepStartupError::InvalidMaxQueuedFrames(
+                        value.clone(),
+                    )))
+                }
+            },
+
+            _ => DEFAULT_MAX_QUEUED_FRAMES,
+        };
+
+        let queue_depth = Arc::new(AtomicUsize::new(0));
+        let (frame_sender, frame_receiver) = unbounded_channel();
+        let (event_sender, event_receiver) = unbounded_channel();
+        tokio::spawn(run_connection(
+            target.clone(),
+            retry_policy,
+            socket_options,
+            frame_receiver,
+            queue_depth.clone(),
+            event_sender,
+        ));
+
+        futures_channel.send_on_generic_unbounded_recv(
+            event_receiver,
+            FutureResult::ConnectionEventReceived,
+            || FutureResult::ConnectionTaskGone,
+        );
+
+        let step = RemoteForwardStep {
+            target,
+            connection: FrameQueue {
+                sender: frame_sender,
+                depth: queue_depth,
+                max_depth: max_queued_frames,
+                dropped_frames: 0,
+            },
+            connection_state: ConnectionState::Connecting,
+            on_retry_failure: retry_policy.on_failure,
+            required_media_by_stream: HashMap::new(),
+            tags,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl RemoteForwardStep {
+    fn has_disconnected_streams(&self) -> bool {
+        self.connection_state == ConnectionState::Failed
+            && self.on_retry_failure == RetryFailureAction::DisconnectStream
+    }
+
+    fn handle_media(&mut self, media: &MediaNotification) {
+        // Tag announcements only carry the tags that are allowed to be forwarded
+        let filtered_tags;
+        let media = if get_announced_tags(&media.content).is_some() {
+            filtered_tags = match filter_tags_notification(media, self.tags.as_ref()) {
+                Some(notification) => notification,
+                None => return,
+            };
+
+            &filtered_tags
+        } else {
+            media
+        };
+
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { .. } => {
+                self.required_media_by_stream
+                    .insert(media.stream_id.clone(), vec![media.clone()]);
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.required_media_by_stream.remove(&media.stream_id);
+            }
+
+            MediaNotificationContent::MediaPayload {
+                is_required_for_decoding: true,
+                ..
+            } => {
+                if let Some(required_media) =
+                    self.required_media_by_stream.get_mut(&media.stream_id)
+                {
+                    required_media.push(media.clone());
+                }
+            }
+
+            MediaNotificationContent::Metadata { .. }
+                if get_announced_tags(&media.content).is_some() =>
+            {
+                if let Some(required_media) =
+                    self.required_media_by_stream.get_mut(&media.stream_id)
+                {
+                    required_media.retain(|x| get_announced_tags(&x.content).is_none());
+                    required_media.push(media.clone());
+                }
+            }
+
+            _ => (),
+        }
+
+        if self.connection_state == ConnectionState::Connected {
+            self.connection.send(media);
+        }
+    }
+
+    fn handle_connection_event(&mut self, event: ConnectionEvent, outputs: &mut StepOutputs) {
+        match event {
+            ConnectionEvent::Connected => {
+                info!(remote_target = %self.target, "Connected to remote target {}", self.target);
+                self.connection_state = ConnectionState::Connected;
+
+                // The remote side won't know about any streams that were started before this
+                // connection was established, so replay everything it needs to decode them.
+                for required_media in self.required_media_by_stream.values() {
+                    for media in required_media {
+                        self.connection.send(media);
+                    }
+                }
+            }
+
+            ConnectionEvent::Disconnected => {
+                warn!(
+                    remote_target = %self.target,
+                    "Connection to remote target {} lost", self.target
+                );
+
+                self.connection_state = ConnectionState::Connecting;
+            }
+
+            ConnectionEvent::Retrying { attempt } => {
+                self.connection_state = ConnectionState::Retrying { attempt };
+            }
+
+            ConnectionEvent::Failed => {
+                error!(
+                    remote_target = %self.target,
+                    "Giving up on connecting to remote target {}", self.target
+                );
+
+                self.connection_state = ConnectionState::Failed;
+                if self.on_retry_failure == RetryFailureAction::DisconnectStream {
+                    for (stream_id, _) in self.required_media_by_stream.drain() {
+                        outputs.media.push(MediaNotification {
+                            stream_id,
+                            content: MediaNotificationContent::StreamDisconnected,
+                            annotations: Default::default(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl FrameQueue {
+    /// Queues the media to be sent to the remote target. If too many frames are already waiting
+    /// to be written, media payloads that aren't required for decoding are dropped.
+    fn send(&mut self, media: &MediaNotification) {
+        let is_droppable = matches!(
+            media.content,
+            MediaNotificationContent::MediaPayload {
+                is_required_for_decoding: false,
+                ..
+            }
+        );
+
+        if is_droppable && self.depth.load(Ordering::Relaxed) >= self.max_depth {
+            self.dropped_frames += 1;
+            return;
+        }
+
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        if self.sender.send(wire::encode_frame(media)).is_err() {
+            // The connection task is gone, which gets reported separately
+            self.depth.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl WorkflowStep for RemoteForwardStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            let future_result = match notification.downcast::<FutureResult>() {
+                Ok(x) => *x,
+                Err(_) => {
+                    error!("Remote forward step received a notification that is not a known type");
+                    return StepStatus::Error {
+                        message: "Received future result of unknown type".to_string(),
+                    };
+                }
+            };
+
+            match future_result {
+                // The connection task ends once it gives up
+                FutureResult::ConnectionTaskGone
+                    if self.connection_state == ConnectionState::Failed => {}
+
+                FutureResult::ConnectionTaskGone => {
+                    error!("Remote connection task is gone");
+                    return StepStatus::Error {
+                        message: "Remote connection task gone".to_string(),
+                    };
+                }
+
+                FutureResult::ConnectionEventReceived(event) => {
+                    self.handle_connection_event(event, outputs);
+                }
+            }
+        }
+
+        if self.has_disconnected_streams() {
+            inputs.media.clear();
+        }
+
+        for media in inputs.media.drain(..) {
+            self.handle_media(&media);
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            CONNECTION_STATE_DETAIL.to_string(),
+            self.connection_state.to_string(),
+        );
+
+        details.insert(
+            DROPPED_FRAMES_DETAIL.to_string(),
+            self.connection.dropped_frames.to_string(),
+        );
+
+        details
+    }
+}
+
+#[instrument(skip(
+    retry_policy,
+    socket_options,
+    frame_receiver,
+    queue_depth,
+    event_sender
+))]
+async fn run_connection(
+    address: Arc<String>,
+    retry_policy: RetryPolicy,
+    socket_options: SocketOptions,
+    mut frame_receiver: UnboundedReceiver<wire::Frame>,
+    queue_depth: Arc<AtomicUsize>,
+    event_sender: UnboundedSender<ConnectionEvent>,
+) {
+    let mut backoff = Backoff::new(retry_policy);
+    loop {
+        let mut stream = match TcpStream::connect(address.as_str()).await {
+            Ok(stream) => stream,
+            Err(error) => {
+                warn!(
+                    "Failed to connect to remote target {}: {:?}",
+                    address, error
+                );
+
+                let delay = match backoff.next_delay() {
+                    Some(delay) => delay,
+                    None => {
+                        let _ = event_sender.send(ConnectionEvent::Failed);
+                        break;
+                    }
+                };
+
+                let attempt = backoff.next_attempt();
+                if event_sender
+                    .send(ConnectionEvent::Retrying { attempt })
+                    .is_err()
+                {
+                    break;
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => continue,
+                    _ = event_sender.closed() => break,
+                }
+            }
+        };
+
+        backoff.reset();
+        if let Err(error) = socket_options.apply(&stream) {
+            warn!(
+                "Failed to apply socket options to remote target {}: {:?}",
+                address, error
+            );
+        }
+
+        // Any frames still queued were meant for a previous connection, and the step will re-send
+        // what the remote side needs once it knows we are connected.
+        while frame_receiver.try_recv().is_ok() {
+            queue_depth.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        if event_sender.send(ConnectionEvent::Connected).is_err() {
+            break;
+        }
+
+        loop {
+            match frame_receiver.recv().await {
+                Some(mut frame) => {
+                    queue_depth.fetch_sub(1, Ordering::Relaxed);
+                    if let Err(error) = stream.write_all_buf(&mut frame).await {
+                        warn!("Failed to write to remote target {}: {:?}", address, error);
+                        break;
+                    }
+                }
+
+                None => return, // step is gone
+            }
+        }
+
+        if event_sender.send(ConnectionEvent::Disconnected).is_err() {
+            break;
+        }
+    }
+
+    info!("Remote connection to {} closing", address);
+}
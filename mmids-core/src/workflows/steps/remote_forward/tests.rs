@@ -0,0 +1,263 @@
+use super::*;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::MediaPayloadMetadataCollection;
+use crate::workflows::steps::retry::{ON_RETRY_FAILURE, RETRY_DELAY, RETRY_MAX_ATTEMPTS};
+use crate::workflows::steps::test_utils::StepTestContext;
+use crate::workflows::MediaType;
+use bytes::{Bytes, BytesMut};
+use std::iter;
+use std::net::TcpListener;
+use std::time::Duration;
+
+/// Gets a local address that nothing is listening on
+fn unused_target() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    address.to_string()
+}
+
+fn create_context(parameters: &[(&str, &str)]) -> StepTestContext {
+    create_context_with_target(unused_target(), parameters)
+}
+
+fn create_context_with_target(target: String, parameters: &[(&str, &str)]) -> StepTestContext {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("remote_forward".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    definition
+        .parameters
+        .insert(TARGET.to_string(), Some(target));
+
+    for (key, value) in parameters {
+        definition
+            .parameters
+            .insert(key.to_string(), Some(value.to_string()));
+    }
+
+    StepTestContext::new(Box::new(RemoteForwardStepGenerator::new()), definition)
+        .expect("Failed to create forward step")
+}
+
+fn payload(stream_id: &StreamId, is_required_for_decoding: bool) -> MediaNotification {
+    MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Video,
+            payload_type: Arc::new("codec".to_string()),
+            timestamp: Duration::from_millis(0),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data: Bytes::from_static(&[1, 2, 3, 4]),
+            is_required_for_decoding,
+        },
+        annotations: Default::default(),
+    }
+}
+
+fn dropped_frames(context: &StepTestContext) -> String {
+    context
+        .step
+        .get_state_details()
+        .get(DROPPED_FRAMES_DETAIL)
+        .cloned()
+        .expect("No dropped frames detail")
+}
+
+fn connection_state(context: &StepTestContext) -> String {
+    context
+        .step
+        .get_state_details()
+        .get(CONNECTION_STATE_DETAIL)
+        .cloned()
+        .expect("No connection state detail")
+}
+
+/// Executes the step until its connection state is failed, returning every state seen
+async fn run_until_failed(context: &mut StepTestContext) -> Vec<String> {
+    let mut states = vec![connection_state(context)];
+    for _ in 0..100 {
+        context.execute_pending_futures().await;
+
+        let state = connection_state(context);
+        if states.last() != Some(&state) {
+            states.push(state.clone());
+        }
+
+        if state == ConnectionState::Failed.to_string() {
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    states
+}
+
+#[tokio::test]
+async fn invalid_retry_policy_returns_error() {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("remote_forward".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    definition
+        .parameters
+        .insert(TARGET.to_string(), Some(unused_target()));
+
+    definition
+        .parameters
+        .insert(RETRY_DELAY.to_string(), Some("abc".to_string()));
+
+    let result = StepTestContext::new(Box::new(RemoteForwardStepGenerator::new()), definition);
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[tokio::test]
+async fn retries_per_policy_then_reports_failed_after_max_attempts() {
+    let mut context = create_context(&[(RETRY_MAX_ATTEMPTS, "3"), (RETRY_DELAY, "100")]);
+
+    let states = run_until_failed(&mut context).await;
+
+    assert_eq!(
+        states,
+        vec![
+            ConnectionState::Connecting.to_string(),
+            ConnectionState::Retrying { attempt: 2 }.to_string(),
+            ConnectionState::Retrying { attempt: 3 }.to_string(),
+            ConnectionState::Failed.to_string(),
+        ],
+        "Unexpected connection states"
+    );
+
+    assert_eq!(context.status, StepStatus::Active, "Unexpected step status");
+}
+
+#[tokio::test]
+async fn media_passed_through_after_failing_when_not_set_to_disconnect() {
+    let mut context = create_context(&[(RETRY_MAX_ATTEMPTS, "1")]);
+    run_until_failed(&mut context).await;
+
+    context.assert_media_passed_through(MediaNotification {
+        stream_id: StreamId(Arc::new("abc".to_string())),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("name".to_string()),
+        },
+        annotations: Default::default(),
+    });
+}
+
+#[tokio::test]
+async fn active_streams_disconnected_after_failing_when_set_to_disconnect() {
+    let mut context = create_context(&[
+        (RETRY_MAX_ATTEMPTS, "2"),
+        (RETRY_DELAY, "100"),
+        (ON_RETRY_FAILURE, "disconnect"),
+    ]);
+
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    context.execute_with_media(MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("name".to_string()),
+        },
+        annotations: Default::default(),
+    });
+
+    let mut outputs = Vec::new();
+    for _ in 0..100 {
+        context.execute_pending_futures().await;
+        outputs.append(&mut context.media_outputs);
+        if connection_state(&context) == ConnectionState::Failed.to_string() {
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    assert_eq!(outputs.len(), 1, "Unexpected number of outputs");
+    assert_eq!(outputs[0].stream_id, stream_id, "Unexpected stream id");
+    match &outputs[0].content {
+        MediaNotificationContent::StreamDisconnected => (),
+        content => panic!("Expected stream disconnected, instead got {:?}", content),
+    }
+
+    context.assert_media_not_passed_through(MediaNotification {
+        stream_id,
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    });
+}
+
+#[tokio::test]
+async fn invalid_max_queued_frames_returns_error() {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("remote_forward".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    definition
+        .parameters
+        .insert(TARGET.to_string(), Some(unused_target()));
+
+    definition
+        .parameters
+        .insert(MAX_QUEUED_FRAMES.to_string(), Some("0".to_string()));
+
+    let result = StepTestContext::new(Box::new(RemoteForwardStepGenerator::new()), definition);
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[tokio::test]
+async fn non_required_frames_dropped_once_queue_is_full() {
+    // Nothing reads from the connection, and the connection task can't run while the step is
+    // executing, so every frame sent in one execution stays queued
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = listener.local_addr().unwrap().to_string();
+    let mut context = create_context_with_target(target, &[(MAX_QUEUED_FRAMES, "2")]);
+
+    for _ in 0..100 {
+        context.execute_pending_futures().await;
+        if connection_state(&context) == ConnectionState::Connected.to_string() {
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    assert_eq!(
+        connection_state(&context),
+        ConnectionState::Connected.to_string(),
+        "Expected the step to connect"
+    );
+
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    context.execute_with_media(MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("name".to_string()),
+        },
+        annotations: Default::default(),
+    });
+
+    context.execute_with_media(payload(&stream_id, false));
+    context.execute_with_media(payload(&stream_id, false));
+    context.execute_with_media(payload(&stream_id, false));
+    assert_eq!(dropped_frames(&context), "2", "Unexpected dropped frames");
+
+    context.execute_with_media(payload(&stream_id, true));
+    assert_eq!(
+        dropped_frames(&context),
+        "2",
+        "Expected frames required for decoding to still be queued"
+    );
+
+    assert_eq!(
+        context.media_outputs.len(),
+        1,
+        "Expected media to still be passed through"
+    );
+}
@@ -0,0 +1,86 @@
+//! Defines the format used to send media notifications between mmids instances over the network.
+//!
+//! Each media notification is sent as a single frame, consisting of a 4 byte big endian length
+//! followed by that many bytes of the notification, serialized by the
+//! [`serialization`](crate::workflows::serialization) module.
+
+use crate::workflows::serialization::{self, DeserializationError};
+use crate::workflows::MediaNotification;
+use bytes::buf::Chain;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use thiserror::Error;
+
+/// The largest frame that will be accepted. Anything larger than this is assumed to be a
+/// corrupted stream of data.
+pub const MAX_FRAME_SIZE: usize = 50 * 1024 * 1024;
+
+/// An encoded frame, made up of the encoded fields chained with the media payload's bytes
+pub type Frame = Chain<Bytes, Bytes>;
+
+/// Errors that can occur when decoding frames
+#[derive(Error, Debug)]
+pub enum WireDecodingError {
+    #[error("Frame size of {0} exceeds the maximum allowed frame size")]
+    FrameTooLarge(usize),
+
+    #[error("Frame did not contain a valid notification: {0}")]
+    InvalidNotification(#[from] DeserializationError),
+}
+
+/// Decodes frames from a stream of bytes, where the bytes may arrive split at arbitrary points
+#[derive(Default)]
+pub struct FrameDecoder {
+    buffer: BytesMut,
+}
+
+/// Encodes the media notification into a full frame that can be sent over the network. The media
+/// payload is chained onto the end of the frame instead of being copied into it.
+pub fn encode_frame(media: &MediaNotification) -> Frame {
+    let (notification, payload) = serialization::to_bytes(media).into_inner();
+    let mut buffer = BytesMut::with_capacity(notification.len() + 4);
+    buffer.put_u32((notification.len() + payload.len()) as u32);
+    buffer.put(notification);
+
+    buffer.freeze().chain(payload)
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds newly received bytes to the decoder
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Attempts to decode the next media notification from the bytes that have been received so
+    /// far. `None` is returned if a full frame has not been received yet. Once an error has been
+    /// returned, the decoder is in an unknown state and should not be used further.
+    pub fn next_notification(&mut self) -> Result<Option<MediaNotification>, WireDecodingError> {
+        if self.buffer.len() < 4 {
+            return Ok(None);
+        }
+
+        let length = u32::from_be_bytes([
+            self.buffer[0],
+            self.buffer[1],
+            self.buffer[2],
+            self.buffer[3],
+        ]) as usize;
+
+        if length > MAX_FRAME_SIZE {
+            return Err(WireDecodingError::FrameTooLarge(length));
+        }
+
+        if self.buffer.len() < length + 4 {
+            return Ok(None);
+        }
+
+        self.buffer.advance(4);
+        let frame = self.buffer.split_to(length).freeze();
+        let notification = serialization::from_bytes(frame)?;
+
+        Ok(Some(notification))
+    }
+}
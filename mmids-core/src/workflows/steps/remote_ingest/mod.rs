@@ -0,0 +1,398 @@
+//! The remote ingest step listens on a TCP port for connections from `remote_forward` steps running
+//! on other mmids instances. Any media notifications received over these connections are passed
+//! on to subsequent steps in the workflow, as if the streams originated from this step.
+//!
+//! When a remote connection is lost, all streams that were received over that connection are
+//! considered disconnected. Media notifications that come from prior steps are passed through
+//! untouched.
+//!
+//! The TCP options of accepted connections can be tuned with the parameters described in the
+//! `net::tcp` module's socket options (`tcp_nodelay`, `send_buffer_size`, `recv_buffer_size`, and
+//! `tcp_keepalive`), otherwise the operating system's defaults are used.
+//!
+//! When a `stall_timeout` is specified, a stream that receives no media for that many
+//! milliseconds while its remote connection stays open raises an ingest stall alert on the event
+//! hub, which is cleared once its media resumes. The stream is not disconnected while stalled.
+
+#[cfg(test)]
+mod tests;
+
+use crate::event_hub::PublishEventRequest;
+use crate::net::tcp::{
+    OutboundPacket, SocketOptions, SocketOptionsError, TcpSocketRequest, TcpSocketResponse,
+};
+use crate::net::ConnectionId;
+use crate::workflows::annotations::ArrivalTime;
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::remote_forward::wire::FrameDecoder;
+use crate::workflows::steps::stall_detection::{
+    read_stall_timeout, StallCheckDue, StallDetector, StallTimeoutError, STALLED_STREAMS_DETAIL,
+};
+use crate::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent};
+use crate::StreamId;
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+use thiserror::Error;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+pub const PORT: &str = "port";
+
+/// Generates new instances of the remote ingest workflow step
+pub struct RemoteIngestStepGenerator {
+    socket_manager: UnboundedSender<TcpSocketRequest>,
+    event_publisher: UnboundedSender<PublishEventRequest>,
+}
+
+struct RemoteConnection {
+    decoder: FrameDecoder,
+    stream_ids: HashSet<StreamId>,
+
+    // Held so the connection stays open. Dropping it closes the connection.
+    _outgoing_bytes: UnboundedSender<OutboundPacket>,
+}
+
+struct RemoteIngestStep {
+    port: u16,
+    status: StepStatus,
+    connections: HashMap<ConnectionId, RemoteConnection>,
+    cancellation_token: CancellationToken,
+    stall_detector: Option<StallDetector>,
+}
+
+enum FutureResult {
+    SocketManagerGone,
+    SocketListenerCancelled,
+    SocketResponseReceived(TcpSocketResponse),
+    BytesReceived {
+        connection_id: ConnectionId,
+        bytes: Bytes,
+    },
+    ConnectionClosed(ConnectionId),
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error("No {} parameter specified", PORT)]
+    NoPortSpecified,
+
+    #[error("Invalid {} value of '{0}' specified", PORT)]
+    InvalidPort(String),
+
+    #[error(transparent)]
+    InvalidStallTimeout(#[from] StallTimeoutError),
+
+    #[error(transparent)]
+    InvalidSocketOptions(#[from] SocketOptionsError),
+}
+
+impl RemoteIngestStepGenerator {
+    pub fn new(
+        socket_manager: UnboundedSender<TcpSocketRequest>,
+        event_publisher: UnboundedSender<PublishEventRequest>,
+    ) -> Self {
+        RemoteIngestStepGenerator {
+            socket_manager,
+            event_publisher,
+        }
+    }
+}
+
+impl StepGenerator for RemoteIngestStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let port = match definition.parameters.get(PORT) {
+            Some(Some(port)) => match port.parse::<u16>() {
+                Ok(port) => port,
+                Err(_) => return Err(Box::new(StepStartupError::InvalidPort(port.clone()))),
+            },
+
+            _ => return Err(Box::new(StepStartupError::NoPortSpecified)),
+        };
+
+        let stall_detector = read_stall_timeout(&definition)
+            .map_err(StepStartupError::from)?
+            .map(|timeout| StallDetector::new(timeout, self.event_publisher.clone()));
+
+        let socket_options = SocketOptions::from_parameters(&definition.parameters)
+            .map_err(StepStartupError::from)?;
+
+        let (response_sender, response_receiver) = unbounded_channel();
+        let _ = self.socket_manager.send(TcpSocketRequest::OpenPort {
+            port,
+            use_tls: false,
+            socket_options,
+            response_channel: response_sender,
+        });
+
+        // The port stays open as long as the response channel is alive, so we need to make sure
+        // the receiver is dropped when the step is dropped.
+        let cancellation_token = CancellationToken::new();
+        futures_channel.send_on_generic_unbounded_recv_cancellable(
+            response_receiver,
+            cancellation_token.child_token(),
+            FutureResult::SocketResponseReceived,
+            || FutureResult::SocketManagerGone,
+            || FutureResult::SocketListenerCancelled,
+        );
+
+        let step = RemoteIngestStep {
+            port,
+            status: StepStatus::Created,
+            connections: HashMap::new(),
+            cancellation_token,
+            stall_detector,
+        };
+
+        Ok((Box::new(step), StepStatus::Created))
+    }
+}
+
+impl RemoteIngestStep {
+    fn handle_socket_response(
+        &mut self,
+        response: TcpSocketResponse,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        match response {
+            TcpSocketResponse::RequestAccepted {} => {
+                info!("Listening for remote connections on port {}", self.port);
+                self.status = StepStatus::Active;
+            }
+
+            TcpSocketResponse::RequestDenied { reason } => {
+                error!(
+                    "Request to listen on port {} was denied: {:?}",
+                    self.port, reason
+                );
+
+                self.status = StepStatus::Error {
+                    message: format!("Could not listen on port {}: {:?}", self.port, reason),
+                };
+            }
+
+            TcpSocketResponse::PortForciblyClosed { port } => {
+                error!("Port {} was forcibly closed", port);
+                self.status = StepStatus::Error {
+                    message: format!("Port {} was forcibly closed", port),
+                };
+            }
+
+            TcpSocketResponse::NewConnection {
+                connection_id,
+                incoming_bytes,
+                outgoing_bytes,
+                socket_address,
+                ..
+            } => {
+                info!(
+                    connection_id = %connection_id,
+                    "New remote connection from {}", socket_address
+                );
+
+                let recv_id = connection_id.clone();
+                let closed_id = connection_id.clone();
+                futures_channel.send_on_generic_unbounded_recv(
+                    incoming_bytes,
+                    move |bytes| FutureResult::BytesReceived {
+                        connection_id: recv_id.clone(),
+                        bytes,
+                    },
+                    move || FutureResult::ConnectionClosed(closed_id),
+                );
+
+                self.connections.insert(
+                    connection_id,
+                    RemoteConnection {
+                        decoder: FrameDecoder::new(),
+                        stream_ids: HashSet::new(),
+                        _outgoing_bytes: outgoing_bytes,
+                    },
+                );
+            }
+
+            TcpSocketResponse::Disconnection { connection_id } => {
+                self.remove_connection(&connection_id, outputs);
+            }
+        }
+    }
+
+    fn handle_bytes(
+        &mut self,
+        connection_id: ConnectionId,
+        bytes: Bytes,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let connection = match self.connections.get_mut(&connection_id) {
+            Some(connection) => connection,
+            None => return, // connection was already closed
+        };
+
+        connection.decoder.push(&bytes);
+        loop {
+            match connection.decoder.next_notification() {
+                Ok(Some(mut media)) => {
+                    media.annotations.insert(ArrivalTime(Instant::now()));
+                    let stall_detector = self.stall_detector.as_mut();
+                    match &media.content {
+                        MediaNotificationContent::NewIncomingStream { stream_name } => {
+                            connection.stream_ids.insert(media.stream_id.clone());
+                            if let Some(detector) = stall_detector {
+                                detector.stream_started(
+                                    media.stream_id.clone(),
+                                    stream_name.clone(),
+                                    futures_channel,
+                                );
+                            }
+                        }
+
+                        MediaNotificationContent::StreamDisconnected => {
+                            connection.stream_ids.remove(&media.stream_id);
+                            if let Some(detector) = stall_detector {
+                                detector.stream_stopped(&media.stream_id);
+                            }
+                        }
+
+                        _ => {
+                            if let Some(detector) = stall_detector {
+                                detector.media_received(&media.stream_id, futures_channel);
+                            }
+                        }
+                    }
+
+                    outputs.media.push(media);
+                }
+
+                Ok(None) => break,
+
+                Err(error) => {
+                    warn!(
+                        connection_id = %connection_id,
+                        "Failed to decode data from remote connection, closing it: {}", error
+                    );
+
+                    self.remove_connection(&connection_id, outputs);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn remove_connection(&mut self, connection_id: &ConnectionId, outputs: &mut StepOutputs) {
+        if let Some(connection) = self.connections.remove(connection_id) {
+            info!(
+                connection_id = %connection_id,
+                "Remote connection disconnected"
+            );
+
+            for stream_id in connection.stream_ids {
+                if let Some(detector) = &mut self.stall_detector {
+                    detector.stream_stopped(&stream_id);
+                }
+
+                outputs.media.push(MediaNotification {
+                    stream_id,
+                    content: MediaNotificationContent::StreamDisconnected,
+                    annotations: Default::default(),
+                });
+            }
+        }
+    }
+}
+
+impl WorkflowStep for RemoteIngestStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            let notification = match notification.downcast::<StallCheckDue>() {
+                Ok(check) => {
+                    if let Some(detector) = &mut self.stall_detector {
+                        detector.handle_check_due(*check, &futures_channel);
+                    }
+
+                    continue;
+                }
+
+                Err(notification) => notification,
+            };
+
+            let future_result = match notification.downcast::<FutureResult>() {
+                Ok(x) => *x,
+                Err(_) => {
+                    error!("Remote ingest step received a notification that is not a known type");
+                    return StepStatus::Error {
+                        message: "Received future result of unknown type".to_string(),
+                    };
+                }
+            };
+
+            match future_result {
+                FutureResult::SocketManagerGone => {
+                    error!("Socket manager is gone");
+                    return StepStatus::Error {
+                        message: "Socket manager gone".to_string(),
+                    };
+                }
+
+                FutureResult::SocketListenerCancelled => (),
+
+                FutureResult::SocketResponseReceived(response) => {
+                    self.handle_socket_response(response, outputs, &futures_channel);
+                }
+
+                FutureResult::BytesReceived {
+                    connection_id,
+                    bytes,
+                } => {
+                    self.handle_bytes(connection_id, bytes, outputs, &futures_channel);
+                }
+
+                FutureResult::ConnectionClosed(connection_id) => {
+                    self.remove_connection(&connection_id, outputs);
+                }
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            outputs.media.push(media);
+        }
+
+        self.status.clone()
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        if let Some(detector) = &self.stall_detector {
+            details.insert(
+                STALLED_STREAMS_DETAIL.to_string(),
+                detector.stalled_stream_count().to_string(),
+            );
+        }
+
+        details
+    }
+}
+
+impl Drop for RemoteIngestStep {
+    fn drop(&mut self) {
+        self.cancellation_token.cancel();
+    }
+}
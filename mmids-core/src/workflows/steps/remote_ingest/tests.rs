@@ -0,0 +1,327 @@
+use super::*;
+use crate::event_hub::{StreamAlert, StreamAlertEvent};
+use crate::net::tcp::start_socket_manager;
+use crate::test_utils;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::MediaPayloadMetadataCollection;
+use crate::workflows::steps::remote_forward::{RemoteForwardStepGenerator, TARGET};
+use crate::workflows::steps::stall_detection::STALL_TIMEOUT;
+use crate::workflows::steps::test_utils::StepTestContext;
+use crate::workflows::MediaType;
+use bytes::BytesMut;
+use std::iter;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+/// Gets a local port that the operating system has assigned and nothing is listening on
+fn unused_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+    listener.local_addr().unwrap().port()
+}
+
+fn create_ingest_context(port: u16) -> StepTestContext {
+    create_ingest_context_with_parameters(port, &[]).0
+}
+
+fn create_ingest_context_with_parameters(
+    port: u16,
+    parameters: &[(&str, &str)],
+) -> (StepTestContext, UnboundedReceiver<PublishEventRequest>) {
+    let socket_manager = start_socket_manager(None);
+    let (event_sender, event_receiver) = unbounded_channel();
+    let generator = RemoteIngestStepGenerator::new(socket_manager, event_sender);
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("remote_ingest".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    definition
+        .parameters
+        .insert(PORT.to_string(), Some(port.to_string()));
+
+    for (key, value) in parameters {
+        definition
+            .parameters
+            .insert(key.to_string(), Some(value.to_string()));
+    }
+
+    let context = StepTestContext::new(Box::new(generator), definition)
+        .expect("Failed to create ingest step");
+
+    (context, event_receiver)
+}
+
+fn create_forward_context(port: u16) -> StepTestContext {
+    let generator = RemoteForwardStepGenerator::new();
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("remote_forward".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    definition
+        .parameters
+        .insert(TARGET.to_string(), Some(format!("127.0.0.1:{}", port)));
+
+    StepTestContext::new(Box::new(generator), definition).expect("Failed to create forward step")
+}
+
+/// Gives the network and both steps time to process everything pending
+async fn settle(
+    ingest: &mut StepTestContext,
+    forward: &mut StepTestContext,
+) -> Vec<MediaNotification> {
+    let mut received = Vec::new();
+    for _ in 0..10 {
+        forward.execute_pending_futures().await;
+        ingest.execute_pending_futures().await;
+        received.append(&mut ingest.media_outputs);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    received
+}
+
+#[tokio::test]
+async fn missing_port_returns_error() {
+    let socket_manager = start_socket_manager(None);
+    let (event_sender, _event_receiver) = unbounded_channel();
+    let generator = RemoteIngestStepGenerator::new(socket_manager, event_sender);
+    let definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("remote_ingest".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    let result = StepTestContext::new(Box::new(generator), definition);
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[tokio::test]
+async fn ingest_step_passes_through_media_from_previous_steps() {
+    let mut ingest = create_ingest_context(unused_port());
+    ingest.execute_pending_futures().await;
+
+    ingest.assert_media_passed_through(MediaNotification {
+        stream_id: StreamId(Arc::new("abc".to_string())),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    });
+}
+
+#[tokio::test]
+async fn media_forwarded_over_loopback_is_received_by_ingest_step() {
+    let port = unused_port();
+    let mut ingest = create_ingest_context(port);
+    ingest.execute_pending_futures().await;
+    assert_eq!(
+        ingest.status,
+        StepStatus::Active,
+        "Unexpected ingest status"
+    );
+
+    let mut forward = create_forward_context(port);
+    settle(&mut ingest, &mut forward).await;
+
+    let new_stream = MediaNotification {
+        stream_id: StreamId(Arc::new("abc".to_string())),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("def".to_string()),
+        },
+        annotations: Default::default(),
+    };
+
+    let payload = MediaNotification {
+        stream_id: StreamId(Arc::new("abc".to_string())),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Video,
+            payload_type: Arc::new("test".to_string()),
+            timestamp: Duration::from_millis(500),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data: Bytes::from_static(&[1, 2, 3, 4]),
+            is_required_for_decoding: true,
+        },
+        annotations: Default::default(),
+    };
+
+    forward.assert_media_passed_through(new_stream.clone());
+    forward.assert_media_passed_through(payload.clone());
+
+    let received = settle(&mut ingest, &mut forward).await;
+    assert_eq!(
+        received,
+        vec![new_stream, payload],
+        "Unexpected received media"
+    );
+
+    for media in received {
+        assert!(
+            media.annotations.get::<ArrivalTime>().is_some(),
+            "Expected received media to have an arrival time"
+        );
+    }
+}
+
+#[tokio::test]
+async fn streams_sent_before_connection_are_replayed_once_connected() {
+    let port = unused_port();
+    let mut forward = create_forward_context(port);
+    let new_stream = MediaNotification {
+        stream_id: StreamId(Arc::new("abc".to_string())),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("def".to_string()),
+        },
+        annotations: Default::default(),
+    };
+
+    forward.assert_media_passed_through(new_stream.clone());
+
+    let mut ingest = create_ingest_context(port);
+    let mut received = Vec::new();
+    for _ in 0..70 {
+        // The forward step waits several seconds between connection attempts
+        received.extend(settle(&mut ingest, &mut forward).await);
+        if !received.is_empty() {
+            break;
+        }
+    }
+
+    assert_eq!(received, vec![new_stream], "Unexpected received media");
+}
+
+#[tokio::test]
+async fn remote_disconnection_disconnects_streams() {
+    let port = unused_port();
+    let mut ingest = create_ingest_context(port);
+    ingest.execute_pending_futures().await;
+
+    let mut forward = create_forward_context(port);
+    settle(&mut ingest, &mut forward).await;
+
+    forward.assert_media_passed_through(MediaNotification {
+        stream_id: StreamId(Arc::new("abc".to_string())),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("def".to_string()),
+        },
+        annotations: Default::default(),
+    });
+
+    let received = settle(&mut ingest, &mut forward).await;
+    assert_eq!(received.len(), 1, "Expected new stream to be received");
+
+    drop(forward);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    ingest.execute_pending_futures().await;
+
+    let expected = MediaNotification {
+        stream_id: StreamId(Arc::new("abc".to_string())),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    };
+
+    assert_eq!(
+        ingest.media_outputs,
+        vec![expected],
+        "Expected stream disconnection"
+    );
+}
+
+#[tokio::test]
+async fn paused_media_on_open_connection_raises_and_clears_stall_alert() {
+    let port = unused_port();
+    let (mut ingest, mut events) =
+        create_ingest_context_with_parameters(port, &[(STALL_TIMEOUT, "600")]);
+    ingest.execute_pending_futures().await;
+
+    let mut forward = create_forward_context(port);
+    settle(&mut ingest, &mut forward).await;
+
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    let payload = MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Video,
+            payload_type: Arc::new("test".to_string()),
+            timestamp: Duration::from_millis(500),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data: Bytes::from_static(&[1, 2, 3, 4]),
+            is_required_for_decoding: false,
+        },
+        annotations: Default::default(),
+    };
+
+    forward.assert_media_passed_through(MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("def".to_string()),
+        },
+        annotations: Default::default(),
+    });
+
+    forward.assert_media_passed_through(payload.clone());
+    let received = settle(&mut ingest, &mut forward).await;
+    assert_eq!(received.len(), 2, "Expected new stream and payload");
+    assert!(
+        events.try_recv().is_err(),
+        "Expected no stall before timeout"
+    );
+
+    // Media stops while the connection stays open
+    tokio::time::sleep(Duration::from_millis(650)).await;
+    ingest.execute_pending_futures().await;
+
+    let event = test_utils::expect_mpsc_response(&mut events).await;
+    match event {
+        PublishEventRequest::StreamAlert(StreamAlertEvent::AlertRaised {
+            stream_id: alert_stream_id,
+            stream_name,
+            alert,
+            ..
+        }) => {
+            assert_eq!(alert_stream_id, stream_id, "Unexpected stream id");
+            assert_eq!(stream_name.as_str(), "def", "Unexpected stream name");
+            assert_eq!(alert, StreamAlert::IngestStalled, "Unexpected alert");
+        }
+
+        event => panic!("Unexpected event: {:?}", event),
+    }
+
+    assert_eq!(
+        ingest.step.get_state_details()[STALLED_STREAMS_DETAIL],
+        "1",
+        "Unexpected stalled stream count"
+    );
+    assert!(
+        ingest.media_outputs.is_empty(),
+        "Expected stream to not be disconnected"
+    );
+
+    forward.assert_media_passed_through(payload);
+    let received = settle(&mut ingest, &mut forward).await;
+    assert_eq!(received.len(), 1, "Expected resumed payload");
+
+    let event = test_utils::expect_mpsc_response(&mut events).await;
+    match event {
+        PublishEventRequest::StreamAlert(StreamAlertEvent::AlertCleared {
+            alert,
+            duration,
+            ..
+        }) => {
+            assert_eq!(alert, StreamAlert::IngestStalled, "Unexpected alert");
+            assert!(
+                duration >= Duration::from_millis(600),
+                "Unexpected stall duration of {:?}",
+                duration
+            );
+        }
+
+        event => panic!("Unexpected event: {:?}", event),
+    }
+
+    assert_eq!(
+        ingest.step.get_state_details()[STALLED_STREAMS_DETAIL],
+        "0",
+        "Unexpected stalled stream count"
+    );
+}
@@ -0,0 +1,222 @@
+//! The resolution guard step protects later (and usually resource intensive) steps, such as
+//! transcoders, from streams whose video resolution is larger than the system was provisioned for.
+//!
+//! The resolution of each stream is read from the sequence parameter set of its video as soon as it
+//! is seen, which is usually the stream's sequence header. If the resolution is larger than the
+//! configured `max_width` or `max_height`, the stream is rejected. Later steps are told the stream
+//! has disconnected, and all further media for it is dropped until it disconnects or is announced
+//! again. Streams whose resolution can't be determined (e.g. an unknown codec) are passed through.
+
+#[cfg(test)]
+mod tests;
+
+use crate::codecs::{read_video_resolution, VideoResolution};
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use crate::StreamId;
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+use tracing::{info, warn};
+
+pub const MAX_WIDTH: &str = "max_width";
+pub const MAX_HEIGHT: &str = "max_height";
+
+const REJECTED_COUNT_DETAIL: &str = "rejected_count";
+const LAST_REJECTION_DETAIL: &str = "last_rejection";
+
+/// Generates new instances of the resolution guard workflow step
+#[derive(Default)]
+pub struct ResolutionGuardStepGenerator {}
+
+struct ResolutionGuardStep {
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+    checked_streams: HashSet<StreamId>,
+    rejected_streams: HashSet<StreamId>,
+    rejected_count: u64,
+    last_rejection: Option<String>,
+}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "At least one of the {} or {} parameters must be specified",
+        MAX_WIDTH,
+        MAX_HEIGHT
+    )]
+    NoMaximumSpecified,
+
+    #[error("Invalid {0} value of '{1}' specified, must be a number greater than zero")]
+    InvalidMaximum(&'static str, String),
+}
+
+impl ResolutionGuardStepGenerator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl StepGenerator for ResolutionGuardStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let max_width = get_maximum(&definition, MAX_WIDTH)?;
+        let max_height = get_maximum(&definition, MAX_HEIGHT)?;
+        if max_width.is_none() && max_height.is_none() {
+            return Err(Box::new(StepStartupError::NoMaximumSpecified));
+        }
+
+        let step = ResolutionGuardStep {
+            max_width,
+            max_height,
+            checked_streams: HashSet::new(),
+            rejected_streams: HashSet::new(),
+            rejected_count: 0,
+            last_rejection: None,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+fn get_maximum(
+    definition: &WorkflowStepDefinition,
+    parameter: &'static str,
+) -> Result<Option<u32>, StepStartupError> {
+    match definition.parameters.get(parameter) {
+        Some(Some(value)) => match value.parse::<u32>() {
+            Ok(maximum) if maximum > 0 => Ok(Some(maximum)),
+            _ => Err(StepStartupError::InvalidMaximum(parameter, value.clone())),
+        },
+
+        _ => Ok(None),
+    }
+}
+
+impl ResolutionGuardStep {
+    fn handle_media(&mut self, media: MediaNotification, outputs: &mut StepOutputs) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { .. } => {
+                // A re-announced stream gets re-evaluated, even if it was previously rejected
+                self.rejected_streams.remove(&media.stream_id);
+                self.checked_streams.remove(&media.stream_id);
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.checked_streams.remove(&media.stream_id);
+                if self.rejected_streams.remove(&media.stream_id) {
+                    // Later steps were already told the stream disconnected
+                    return;
+                }
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type,
+                data,
+                is_required_for_decoding,
+                ..
+            } => {
+                if self.rejected_streams.contains(&media.stream_id) {
+                    return;
+                }
+
+                if !self.checked_streams.contains(&media.stream_id) {
+                    if let Some(resolution) =
+                        read_video_resolution(payload_type, data, *is_required_for_decoding)
+                    {
+                        self.checked_streams.insert(media.stream_id.clone());
+                        if self.exceeds_maximum(resolution) {
+                            self.reject(media.stream_id, resolution, outputs);
+                            return;
+                        }
+
+                        info!(
+                            stream_id = %media.stream_id.0,
+                            "Stream resolution of {} is allowed", resolution
+                        );
+                    }
+                }
+            }
+
+            MediaNotificationContent::MediaPayload { .. }
+            | MediaNotificationContent::Metadata { .. } => {
+                if self.rejected_streams.contains(&media.stream_id) {
+                    return;
+                }
+            }
+        }
+
+        outputs.media.push(media);
+    }
+
+    fn exceeds_maximum(&self, resolution: VideoResolution) -> bool {
+        let too_wide = self.max_width.is_some_and(|max| resolution.width > max);
+        let too_high = self.max_height.is_some_and(|max| resolution.height > max);
+
+        too_wide || too_high
+    }
+
+    fn reject(
+        &mut self,
+        stream_id: StreamId,
+        resolution: VideoResolution,
+        outputs: &mut StepOutputs,
+    ) {
+        let format_max = |max: Option<u32>| max.map_or("any".to_string(), |x| x.to_string());
+        let reason = format!(
+            "Stream resolution of {} exceeds the maximum of {}x{}",
+            resolution,
+            format_max(self.max_width),
+            format_max(self.max_height),
+        );
+
+        warn!(stream_id = %stream_id.0, "Rejecting stream: {}", reason);
+
+        self.rejected_count += 1;
+        self.last_rejection = Some(reason);
+        self.rejected_streams.insert(stream_id.clone());
+
+        outputs.media.push(MediaNotification {
+            stream_id,
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        });
+    }
+}
+
+impl WorkflowStep for ResolutionGuardStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            self.handle_media(media, outputs);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            REJECTED_COUNT_DETAIL.to_string(),
+            self.rejected_count.to_string(),
+        );
+
+        if let Some(reason) = &self.last_rejection {
+            details.insert(LAST_REJECTION_DETAIL.to_string(), reason.clone());
+        }
+
+        details
+    }
+}
@@ -0,0 +1,223 @@
+use super::*;
+use crate::codecs::h264::{test_sps, AvcDecoderConfigurationRecord};
+use crate::codecs::h265::HevcDecoderConfigurationRecord;
+use crate::codecs::nal::write_annexb;
+use crate::codecs::{h265, VIDEO_CODEC_H264_ANNEXB, VIDEO_CODEC_H264_AVC, VIDEO_CODEC_H265_HVCC};
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::MediaPayloadMetadataCollection;
+use crate::workflows::steps::test_utils::StepTestContext;
+use bytes::{Bytes, BytesMut};
+use std::iter;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn create_context(
+    max_width: Option<&str>,
+    max_height: Option<&str>,
+) -> Result<StepTestContext, anyhow::Error> {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("resolution_guard".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    if let Some(max_width) = max_width {
+        definition
+            .parameters
+            .insert(MAX_WIDTH.to_string(), Some(max_width.to_string()));
+    }
+
+    if let Some(max_height) = max_height {
+        definition
+            .parameters
+            .insert(MAX_HEIGHT.to_string(), Some(max_height.to_string()));
+    }
+
+    StepTestContext::new(Box::new(ResolutionGuardStepGenerator::new()), definition)
+}
+
+fn stream_id() -> StreamId {
+    StreamId(Arc::new("abc".to_string()))
+}
+
+fn new_stream() -> MediaNotification {
+    MediaNotification {
+        stream_id: stream_id(),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("def".to_string()),
+        },
+        annotations: Default::default(),
+    }
+}
+
+fn video(payload_type: &Arc<String>, data: Bytes, is_sequence_header: bool) -> MediaNotification {
+    MediaNotification {
+        stream_id: stream_id(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Video,
+            payload_type: payload_type.clone(),
+            timestamp: Duration::from_millis(0),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data,
+            is_required_for_decoding: is_sequence_header,
+        },
+        annotations: Default::default(),
+    }
+}
+
+fn avc_sequence_header(width: u32, height: u32) -> MediaNotification {
+    let pps = Bytes::from_static(&[0x68, 0xeb, 0xe3, 0xcb, 0x22, 0xc0]);
+    let record = AvcDecoderConfigurationRecord::from_parameter_sets(
+        vec![test_sps(width, height)],
+        vec![pps],
+        4,
+    )
+    .unwrap();
+
+    video(&VIDEO_CODEC_H264_AVC, record.to_bytes(), true)
+}
+
+fn frame() -> MediaNotification {
+    video(
+        &VIDEO_CODEC_H264_AVC,
+        Bytes::from_static(&[0, 0, 0, 2, 0x65, 0x88]),
+        false,
+    )
+}
+
+fn assert_stream_rejected(context: &mut StepTestContext, media: MediaNotification) {
+    context.execute_with_media(media);
+
+    assert_eq!(
+        context.media_outputs,
+        vec![MediaNotification {
+            stream_id: stream_id(),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        }],
+        "Expected only a disconnection notification"
+    );
+}
+
+#[test]
+fn missing_maximums_returns_error() {
+    assert!(create_context(None, None).is_err(), "Expected an error");
+}
+
+#[test]
+fn invalid_maximum_returns_error() {
+    assert!(
+        create_context(Some("abc"), Some("1080")).is_err(),
+        "Expected an error"
+    );
+
+    assert!(
+        create_context(Some("0"), None).is_err(),
+        "Expected an error"
+    );
+}
+
+#[test]
+fn stream_within_maximum_is_passed_through() {
+    let mut context = create_context(Some("1920"), Some("1080")).unwrap();
+
+    context.assert_media_passed_through(new_stream());
+    context.assert_media_passed_through(avc_sequence_header(1920, 1080));
+    context.assert_media_passed_through(frame());
+
+    assert_eq!(
+        context.step.get_state_details().get(REJECTED_COUNT_DETAIL),
+        Some(&"0".to_string()),
+        "Unexpected rejected count"
+    );
+}
+
+#[test]
+fn stream_exceeding_maximum_is_rejected() {
+    let mut context = create_context(Some("1920"), Some("1080")).unwrap();
+
+    context.assert_media_passed_through(new_stream());
+    assert_stream_rejected(&mut context, avc_sequence_header(7680, 4320));
+
+    context.assert_media_not_passed_through(frame());
+    context.assert_media_not_passed_through(MediaNotification {
+        stream_id: stream_id(),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    });
+
+    let details = context.step.get_state_details();
+    assert_eq!(
+        details.get(REJECTED_COUNT_DETAIL),
+        Some(&"1".to_string()),
+        "Unexpected rejected count"
+    );
+
+    assert_eq!(
+        details.get(LAST_REJECTION_DETAIL),
+        Some(&"Stream resolution of 7680x4320 exceeds the maximum of 1920x1080".to_string()),
+        "Unexpected rejection reason"
+    );
+}
+
+#[test]
+fn only_configured_dimension_is_enforced() {
+    let mut context = create_context(None, Some("720")).unwrap();
+
+    context.assert_media_passed_through(new_stream());
+    assert_stream_rejected(&mut context, avc_sequence_header(1280, 1080));
+
+    let mut context = create_context(None, Some("720")).unwrap();
+    context.assert_media_passed_through(new_stream());
+    context.assert_media_passed_through(avc_sequence_header(3840, 720));
+}
+
+#[test]
+fn in_band_annexb_sps_is_checked() {
+    let mut context = create_context(Some("1920"), Some("1080")).unwrap();
+
+    context.assert_media_passed_through(new_stream());
+
+    let keyframe = write_annexb(&[
+        test_sps(3840, 2160),
+        Bytes::from_static(&[0x65, 0x88, 0x84]),
+    ]);
+
+    assert_stream_rejected(
+        &mut context,
+        video(&VIDEO_CODEC_H264_ANNEXB, keyframe, false),
+    );
+}
+
+#[test]
+fn hevc_sequence_header_is_checked() {
+    let mut context = create_context(Some("1920"), Some("1080")).unwrap();
+
+    context.assert_media_passed_through(new_stream());
+
+    let record = HevcDecoderConfigurationRecord {
+        general_configuration: Bytes::from_static(&[0; 21]),
+        nal_length_size: 4,
+        arrays: vec![h265::NalUnitArray {
+            array_completeness: true,
+            nal_unit_type: h265::NAL_UNIT_TYPE_SPS,
+            nal_units: vec![h265::test_sps(3840, 2160)],
+        }],
+    };
+
+    assert_stream_rejected(
+        &mut context,
+        video(&VIDEO_CODEC_H265_HVCC, record.to_bytes(), true),
+    );
+}
+
+#[test]
+fn rejected_stream_is_checked_again_when_announced_again() {
+    let mut context = create_context(Some("1920"), Some("1080")).unwrap();
+
+    context.assert_media_passed_through(new_stream());
+    assert_stream_rejected(&mut context, avc_sequence_header(7680, 4320));
+
+    context.assert_media_passed_through(new_stream());
+    context.assert_media_passed_through(avc_sequence_header(1280, 720));
+    context.assert_media_passed_through(frame());
+}
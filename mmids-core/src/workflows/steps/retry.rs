@@ -0,0 +1,326 @@
+//! Shared connection retry handling for workflow steps that send media to external systems over
+//! the network. Giving every output step the same retry parameters means connection failures are
+//! handled (and configured) consistently, no matter which output a workflow uses.
+//!
+//! A `RetryPolicy` is read from the step definition's parameters:
+//! * `retry_max_attempts` - How many connection attempts are made in a row before giving up. When
+//!   not specified (or zero) attempts are retried forever.
+//! * `retry_delay` - How many milliseconds to wait after the first failed attempt. Defaults to
+//!   5000.
+//! * `retry_max_delay` - The delay doubles after each failed attempt, up to this many
+//!   milliseconds. Defaults to 60000.
+//! * `on_retry_failure` - What to do once all attempts have failed. `stop` (the default) stops
+//!   retrying while media keeps flowing to later steps, while `disconnect` disconnects the affected
+//!   streams.
+//!
+//! Steps track the delays between attempts with a `Backoff`, and report their `ConnectionState`
+//! through their state details.
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use thiserror::Error;
+
+pub const RETRY_MAX_ATTEMPTS: &str = "retry_max_attempts";
+pub const RETRY_DELAY: &str = "retry_delay";
+pub const RETRY_MAX_DELAY: &str = "retry_max_delay";
+pub const ON_RETRY_FAILURE: &str = "on_retry_failure";
+
+/// The state details key steps report their connection state with
+pub const CONNECTION_STATE_DETAIL: &str = "connection_state";
+
+const DEFAULT_DELAY: Duration = Duration::from_millis(5000);
+const DEFAULT_MAX_DELAY: Duration = Duration::from_millis(60000);
+
+/// What a step does once all of its connection attempts have failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryFailureAction {
+    /// Stop trying to connect, while still passing media to later steps
+    StopRetrying,
+
+    /// Disconnect the streams that were being sent over the connection
+    DisconnectStream,
+}
+
+/// How a step retries failed connections
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The number of connection attempts in a row before giving up, or `None` to never give up
+    pub max_attempts: Option<u32>,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub on_failure: RetryFailureAction,
+}
+
+/// Tracks the delays between connection attempts, based on a retry policy
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    policy: RetryPolicy,
+    failed_attempts: u32,
+}
+
+/// The state of a step's connection to an external system
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+
+    /// The previous attempt failed, and the specified attempt will be made once the retry delay
+    /// has passed
+    Retrying {
+        attempt: u32,
+    },
+
+    /// All attempts failed, and no more will be made
+    Failed,
+}
+
+#[derive(Error, Debug)]
+pub enum RetryPolicyError {
+    #[error("Invalid {0} value of '{1}'.  It must be a whole number")]
+    InvalidNumber(&'static str, String),
+
+    #[error("Invalid {0} value of '{1}'.  It must be a number of milliseconds greater than zero")]
+    InvalidDelay(&'static str, String),
+
+    #[error(
+        "Invalid {} value of '{0}'.  It must be either 'stop' or 'disconnect'",
+        ON_RETRY_FAILURE
+    )]
+    InvalidFailureAction(String),
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: None,
+            initial_delay: DEFAULT_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+            on_failure: RetryFailureAction::StopRetrying,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Reads the retry policy from the step definition's parameters, using the default for any
+    /// that aren't specified.
+    pub fn from_step_definition(
+        definition: &WorkflowStepDefinition,
+    ) -> Result<RetryPolicy, RetryPolicyError> {
+        let get_parameter = |name: &str| match definition.parameters.get(name) {
+            Some(Some(value)) if !value.trim().is_empty() => Some(value.trim().to_string()),
+            _ => None,
+        };
+
+        let get_delay = |name: &'static str, default: Duration| match get_parameter(name) {
+            Some(value) => match value.parse::<u64>() {
+                Ok(millis) if millis > 0 => Ok(Duration::from_millis(millis)),
+                _ => Err(RetryPolicyError::InvalidDelay(name, value)),
+            },
+
+            None => Ok(default),
+        };
+
+        let max_attempts = match get_parameter(RETRY_MAX_ATTEMPTS) {
+            Some(value) => match value.parse::<u32>() {
+                Ok(0) => None,
+                Ok(attempts) => Some(attempts),
+                Err(_) => return Err(RetryPolicyError::InvalidNumber(RETRY_MAX_ATTEMPTS, value)),
+            },
+
+            None => None,
+        };
+
+        let on_failure = match get_parameter(ON_RETRY_FAILURE) {
+            Some(value) => match value.to_lowercase().as_str() {
+                "stop" => RetryFailureAction::StopRetrying,
+                "disconnect" => RetryFailureAction::DisconnectStream,
+                _ => return Err(RetryPolicyError::InvalidFailureAction(value)),
+            },
+
+            None => RetryFailureAction::StopRetrying,
+        };
+
+        let initial_delay = get_delay(RETRY_DELAY, DEFAULT_DELAY)?;
+        let max_delay = get_delay(RETRY_MAX_DELAY, DEFAULT_MAX_DELAY)?;
+
+        // A max delay shorter than the initial delay would shrink the delay after the first retry
+        Ok(RetryPolicy {
+            max_attempts,
+            initial_delay,
+            max_delay: max_delay.max(initial_delay),
+            on_failure,
+        })
+    }
+}
+
+impl Backoff {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Backoff {
+            policy,
+            failed_attempts: 0,
+        }
+    }
+
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    /// Records a failed connection attempt. Returns how long to wait before the next attempt, or
+    /// `None` if the policy's maximum number of attempts has been reached.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        self.failed_attempts = self.failed_attempts.saturating_add(1);
+        if let Some(max_attempts) = self.policy.max_attempts {
+            if self.failed_attempts >= max_attempts {
+                return None;
+            }
+        }
+
+        let multiplier = 2_u32.saturating_pow(self.failed_attempts - 1);
+        let delay = self
+            .policy
+            .initial_delay
+            .checked_mul(multiplier)
+            .unwrap_or(self.policy.max_delay);
+
+        Some(delay.min(self.policy.max_delay))
+    }
+
+    /// The number of the next connection attempt, starting at 1
+    pub fn next_attempt(&self) -> u32 {
+        self.failed_attempts.saturating_add(1)
+    }
+
+    /// Resets the backoff after a successful connection, so the next failure starts over with the
+    /// initial delay.
+    pub fn reset(&mut self) {
+        self.failed_attempts = 0;
+    }
+}
+
+impl Display for ConnectionState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionState::Connecting => write!(f, "connecting"),
+            ConnectionState::Connected => write!(f, "connected"),
+            ConnectionState::Retrying { attempt } => write!(f, "retrying (attempt {})", attempt),
+            ConnectionState::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflows::definitions::WorkflowStepType;
+    use std::collections::HashMap;
+
+    fn definition(parameters: &[(&str, &str)]) -> WorkflowStepDefinition {
+        WorkflowStepDefinition {
+            step_type: WorkflowStepType("test".to_string()),
+            parameters: parameters
+                .iter()
+                .map(|(key, value)| (key.to_string(), Some(value.to_string())))
+                .collect::<HashMap<_, _>>(),
+        }
+    }
+
+    #[test]
+    fn default_policy_used_when_no_parameters_specified() {
+        let policy = RetryPolicy::from_step_definition(&definition(&[])).unwrap();
+
+        assert_eq!(policy, RetryPolicy::default(), "Unexpected policy");
+    }
+
+    #[test]
+    fn policy_read_from_parameters() {
+        let policy = RetryPolicy::from_step_definition(&definition(&[
+            (RETRY_MAX_ATTEMPTS, "3"),
+            (RETRY_DELAY, "100"),
+            (RETRY_MAX_DELAY, "1000"),
+            (ON_RETRY_FAILURE, "disconnect"),
+        ]))
+        .unwrap();
+
+        assert_eq!(policy.max_attempts, Some(3), "Unexpected max attempts");
+        assert_eq!(
+            policy.initial_delay,
+            Duration::from_millis(100),
+            "Unexpected initial delay"
+        );
+        assert_eq!(
+            policy.max_delay,
+            Duration::from_millis(1000),
+            "Unexpected max delay"
+        );
+        assert_eq!(
+            policy.on_failure,
+            RetryFailureAction::DisconnectStream,
+            "Unexpected failure action"
+        );
+    }
+
+    #[test]
+    fn invalid_failure_action_returns_error() {
+        let result =
+            RetryPolicy::from_step_definition(&definition(&[(ON_RETRY_FAILURE, "explode")]));
+
+        assert!(result.is_err(), "Expected an error");
+    }
+
+    #[test]
+    fn backoff_doubles_delay_up_to_max_delay() {
+        let mut backoff = Backoff::new(RetryPolicy {
+            max_attempts: None,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(350),
+            on_failure: RetryFailureAction::StopRetrying,
+        });
+
+        let delays = (0..4)
+            .map(|_| backoff.next_delay().unwrap().as_millis())
+            .collect::<Vec<_>>();
+
+        assert_eq!(delays, vec![100, 200, 350, 350], "Unexpected delays");
+    }
+
+    #[test]
+    fn backoff_gives_up_after_max_attempts() {
+        let mut backoff = Backoff::new(RetryPolicy {
+            max_attempts: Some(3),
+            ..Default::default()
+        });
+
+        assert!(
+            backoff.next_delay().is_some(),
+            "Expected a delay after attempt 1"
+        );
+        assert!(
+            backoff.next_delay().is_some(),
+            "Expected a delay after attempt 2"
+        );
+        assert!(
+            backoff.next_delay().is_none(),
+            "Expected no delay after attempt 3"
+        );
+    }
+
+    #[test]
+    fn reset_starts_backoff_over() {
+        let mut backoff = Backoff::new(RetryPolicy {
+            max_attempts: Some(2),
+            initial_delay: Duration::from_millis(100),
+            ..Default::default()
+        });
+
+        backoff.next_delay();
+        backoff.reset();
+
+        assert_eq!(backoff.next_attempt(), 1, "Unexpected next attempt");
+        assert_eq!(
+            backoff.next_delay(),
+            Some(Duration::from_millis(100)),
+            "Unexpected delay"
+        );
+    }
+}
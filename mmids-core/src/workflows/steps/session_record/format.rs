@@ -0,0 +1,126 @@
+//! Defines the file format used for recorded sessions.
+//!
+//! A session file starts with a 4 byte signature and a version byte. It is followed by one record
+//! per media notification, each consisting of an 8 byte big endian offset (in microseconds) from
+//! the start of the session, a 4 byte big endian length, and then the notification serialized by
+//! the [`serialization`](crate::workflows::serialization) module.
+
+use crate::workflows::serialization::{self, DeserializationError};
+use crate::workflows::MediaNotification;
+use bytes::buf::Chain;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::io::ErrorKind;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub const FILE_SIGNATURE: &[u8; 4] = b"MMSR";
+pub const FILE_VERSION: u8 = 1;
+
+/// An encoded record, made up of the encoded fields chained with the media payload's bytes
+pub type Record = Chain<Bytes, Bytes>;
+
+/// The largest record that will be read. Anything larger is assumed to be a corrupted file.
+const MAX_RECORD_SIZE: usize = 50 * 1024 * 1024;
+
+/// A single media notification read from a session file
+#[derive(Debug, PartialEq)]
+pub struct SessionRecord {
+    /// How long after the start of the session the notification was recorded
+    pub offset: Duration,
+    pub media: MediaNotification,
+}
+
+/// Errors that can occur when reading a session file
+#[derive(Error, Debug)]
+pub enum SessionFileError {
+    #[error("The file is not a recorded session")]
+    InvalidSignature,
+
+    #[error("Session file version {0} is not supported")]
+    UnsupportedVersion(u8),
+
+    #[error("Record size of {0} exceeds the maximum allowed size")]
+    RecordTooLarge(usize),
+
+    #[error("Session file ended in the middle of a record")]
+    TruncatedRecord,
+
+    #[error("Record did not contain a valid notification: {0}")]
+    InvalidNotification(#[from] DeserializationError),
+
+    #[error("Failed to read the session file: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Creates the header that must be written at the beginning of every session file
+pub fn encode_header() -> Bytes {
+    let mut buffer = BytesMut::with_capacity(FILE_SIGNATURE.len() + 1);
+    buffer.put_slice(FILE_SIGNATURE);
+    buffer.put_u8(FILE_VERSION);
+
+    buffer.freeze()
+}
+
+/// Encodes a single media notification as a record for a session file. The media payload is
+/// chained onto the end of the record instead of being copied into it.
+pub fn encode_record(offset: Duration, media: &MediaNotification) -> Record {
+    let (notification, payload) = serialization::to_bytes(media).into_inner();
+    let mut buffer = BytesMut::with_capacity(notification.len() + 12);
+    buffer.put_u64(offset.as_micros() as u64);
+    buffer.put_u32((notification.len() + payload.len()) as u32);
+    buffer.put(notification);
+
+    buffer.freeze().chain(payload)
+}
+
+/// Reads and verifies the header from the start of a session file
+pub async fn read_header<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(), SessionFileError> {
+    let mut signature = [0_u8; 4];
+    reader.read_exact(&mut signature).await?;
+    if &signature != FILE_SIGNATURE {
+        return Err(SessionFileError::InvalidSignature);
+    }
+
+    let version = reader.read_u8().await?;
+    if version != FILE_VERSION {
+        return Err(SessionFileError::UnsupportedVersion(version));
+    }
+
+    Ok(())
+}
+
+/// Reads the next record from the session file. `None` is returned when the end of the file has
+/// been reached.
+pub async fn read_record<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<Option<SessionRecord>, SessionFileError> {
+    let offset = match reader.read_u64().await {
+        Ok(offset) => Duration::from_micros(offset),
+        Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+
+    let length = reader.read_u32().await.map_err(truncation_error)? as usize;
+    if length > MAX_RECORD_SIZE {
+        return Err(SessionFileError::RecordTooLarge(length));
+    }
+
+    let mut buffer = vec![0_u8; length];
+    reader
+        .read_exact(&mut buffer)
+        .await
+        .map_err(truncation_error)?;
+
+    let media = serialization::from_bytes(Bytes::from(buffer))?;
+
+    Ok(Some(SessionRecord { offset, media }))
+}
+
+fn truncation_error(error: std::io::Error) -> SessionFileError {
+    if error.kind() == ErrorKind::UnexpectedEof {
+        SessionFileError::TruncatedRecord
+    } else {
+        SessionFileError::Io(error)
+    }
+}
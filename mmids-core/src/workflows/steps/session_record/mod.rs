@@ -0,0 +1,206 @@
+//! The session record step writes every media notification it receives to a file, along with the
+//! time each notification was received relative to the first one. This allows a problematic
+//! stream to be captured and later replayed into a workflow with the `session_replay` step. All
+//! media notifications are passed to subsequent steps untouched.
+
+pub mod format;
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use thiserror::Error;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::{error, info, instrument};
+
+pub const PATH: &str = "path";
+
+/// Generates new instances of the session record workflow step
+#[derive(Default)]
+pub struct SessionRecordStepGenerator {}
+
+struct SessionRecordStep {
+    path: Arc<String>,
+    status: StepStatus,
+    session_started_at: Option<Instant>,
+    writer: UnboundedSender<format::Record>,
+}
+
+enum FutureResult {
+    WriterStopped { error: Option<String> },
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error("No {} parameter specified", PATH)]
+    NoPathSpecified,
+}
+
+impl SessionRecordStepGenerator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl StepGenerator for SessionRecordStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let path = match definition.parameters.get(PATH) {
+            Some(Some(path)) => Arc::new(path.clone()),
+            _ => return Err(Box::new(StepStartupError::NoPathSpecified)),
+        };
+
+        let (sender, receiver) = unbounded_channel();
+        let writer_path = path.clone();
+        futures_channel.send_on_generic_future_completion(async move {
+            let error = run_writer(writer_path, receiver).await.err();
+            FutureResult::WriterStopped { error }
+        });
+
+        let step = SessionRecordStep {
+            path,
+            status: StepStatus::Active,
+            session_started_at: None,
+            writer: sender,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl WorkflowStep for SessionRecordStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            let future_result = match notification.downcast::<FutureResult>() {
+                Ok(x) => *x,
+                Err(_) => {
+                    error!("Session record step received a notification that is not a known type");
+                    return StepStatus::Error {
+                        message: "Received future result of unknown type".to_string(),
+                    };
+                }
+            };
+
+            match future_result {
+                FutureResult::WriterStopped { error } => {
+                    let message = match error {
+                        Some(error) => error,
+                        None => "Session writer stopped unexpectedly".to_string(),
+                    };
+
+                    error!(path = %self.path, "Session recording stopped: {}", message);
+                    self.status = StepStatus::Error { message };
+                }
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            if self.status == StepStatus::Active {
+                let started_at = *self.session_started_at.get_or_insert_with(Instant::now);
+                let record = format::encode_record(started_at.elapsed(), &media);
+                let _ = self.writer.send(record);
+            }
+
+            outputs.media.push(media);
+        }
+
+        self.status.clone()
+    }
+}
+
+/// Writes a session file to the specified path, containing each record received over the channel.
+/// Records are expected to have been encoded with `format::encode_record()`. The session file is
+/// complete once the channel is closed.
+#[instrument(skip(receiver))]
+pub(crate) async fn run_writer(
+    path: Arc<String>,
+    mut receiver: UnboundedReceiver<format::Record>,
+) -> Result<(), String> {
+    write_records(&path, &mut receiver, None).await
+}
+
+/// Writes a session file the same way as `run_writer()`, but decrements the specified counter for
+/// every record taken off the channel. This allows the sender to track how many records are
+/// waiting to be written, by incrementing the counter for every record it sends.
+#[instrument(skip(receiver, queue_depth))]
+pub(crate) async fn run_tracked_writer(
+    path: Arc<String>,
+    mut receiver: UnboundedReceiver<format::Record>,
+    queue_depth: Arc<AtomicUsize>,
+) -> Result<(), String> {
+    let result = write_records(&path, &mut receiver, Some(&queue_depth)).await;
+
+    // Anything still queued up will never be written
+    receiver.close();
+    while receiver.try_recv().is_ok() {
+        queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    result
+}
+
+async fn write_records(
+    path: &Arc<String>,
+    receiver: &mut UnboundedReceiver<format::Record>,
+    queue_depth: Option<&AtomicUsize>,
+) -> Result<(), String> {
+    let record_taken = || {
+        if let Some(queue_depth) = queue_depth {
+            queue_depth.fetch_sub(1, Ordering::Relaxed);
+        }
+    };
+
+    let file = File::create(path.as_str())
+        .await
+        .map_err(|error| format!("Failed to create session file '{}': {}", path, error))?;
+
+    info!("Recording session to {}", path);
+
+    let mut writer = BufWriter::new(file);
+    let write_error = |error: std::io::Error| format!("Failed to write to '{}': {}", path, error);
+
+    writer
+        .write_all(&format::encode_header())
+        .await
+        .map_err(write_error)?;
+
+    while let Some(mut record) = receiver.recv().await {
+        record_taken();
+        writer
+            .write_all_buf(&mut record)
+            .await
+            .map_err(write_error)?;
+
+        // Only flush once everything queued up has been written, to avoid a flush per record
+        while let Ok(mut record) = receiver.try_recv() {
+            record_taken();
+            writer
+                .write_all_buf(&mut record)
+                .await
+                .map_err(write_error)?;
+        }
+
+        writer.flush().await.map_err(write_error)?;
+    }
+
+    info!("Session recording to {} finished", path);
+
+    Ok(())
+}
@@ -0,0 +1,244 @@
+//! The session replay step reads a session file created by the `session_record` step and raises
+//! each recorded media notification with the same timing it was originally recorded with. This
+//! allows a captured stream to be deterministically replayed into a workflow.
+//!
+//! The replay can optionally be looped, in which case any streams still active at the end of the
+//! file are disconnected before the file is replayed again. A speed multiplier can also be
+//! provided to replay faster (or slower) than the original cadence. Media notifications that come
+//! from prior steps are passed through untouched.
+
+#[cfg(test)]
+mod tests;
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::pacing::MediaPacer;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::session_record::format;
+use crate::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent};
+use crate::StreamId;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::fs::File;
+use tokio::io::BufReader;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tracing::{error, info, instrument};
+
+pub const PATH: &str = "path";
+pub const LOOP_FLAG: &str = "loop";
+pub const SPEED: &str = "speed";
+
+/// Generates new instances of the session replay workflow step
+#[derive(Default)]
+pub struct SessionReplayStepGenerator {}
+
+struct SessionReplayStep {
+    path: Arc<String>,
+    status: StepStatus,
+    active_streams: HashSet<StreamId>,
+}
+
+enum ReplayEvent {
+    Media(MediaNotification),
+    PassCompleted,
+    Failed(String),
+}
+
+enum FutureResult {
+    ReplayFinished,
+    ReplayEventReceived(ReplayEvent),
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error("No {} parameter specified", PATH)]
+    NoPathSpecified,
+
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a number greater than zero",
+        SPEED
+    )]
+    InvalidSpeed(String),
+}
+
+impl SessionReplayStepGenerator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl StepGenerator for SessionReplayStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let path = match definition.parameters.get(PATH) {
+            Some(Some(path)) => Arc::new(path.clone()),
+            _ => return Err(Box::new(StepStartupError::NoPathSpecified)),
+        };
+
+        let speed = match definition.parameters.get(SPEED) {
+            Some(Some(value)) => match value.parse::<f64>() {
+                Ok(speed) if speed.is_finite() && speed > 0.0 => speed,
+                _ => return Err(Box::new(StepStartupError::InvalidSpeed(value.clone()))),
+            },
+
+            _ => 1.0,
+        };
+
+        let should_loop = definition.parameters.contains_key(LOOP_FLAG);
+
+        let (sender, receiver) = unbounded_channel();
+        tokio::spawn(run_replay(path.clone(), speed, should_loop, sender));
+
+        futures_channel.send_on_generic_unbounded_recv(
+            receiver,
+            FutureResult::ReplayEventReceived,
+            || FutureResult::ReplayFinished,
+        );
+
+        let step = SessionReplayStep {
+            path,
+            status: StepStatus::Active,
+            active_streams: HashSet::new(),
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl SessionReplayStep {
+    fn handle_replay_event(&mut self, event: ReplayEvent, outputs: &mut StepOutputs) {
+        match event {
+            ReplayEvent::Media(media) => {
+                match &media.content {
+                    MediaNotificationContent::NewIncomingStream { .. } => {
+                        self.active_streams.insert(media.stream_id.clone());
+                    }
+
+                    MediaNotificationContent::StreamDisconnected => {
+                        self.active_streams.remove(&media.stream_id);
+                    }
+
+                    _ => (),
+                }
+
+                outputs.media.push(media);
+            }
+
+            ReplayEvent::PassCompleted => {
+                info!(path = %self.path, "Finished replaying session");
+                self.disconnect_active_streams(outputs);
+            }
+
+            ReplayEvent::Failed(message) => {
+                error!(path = %self.path, "Session replay failed: {}", message);
+                self.disconnect_active_streams(outputs);
+                self.status = StepStatus::Error { message };
+            }
+        }
+    }
+
+    fn disconnect_active_streams(&mut self, outputs: &mut StepOutputs) {
+        for stream_id in self.active_streams.drain() {
+            outputs.media.push(MediaNotification {
+                stream_id,
+                content: MediaNotificationContent::StreamDisconnected,
+                annotations: Default::default(),
+            });
+        }
+    }
+}
+
+impl WorkflowStep for SessionReplayStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            let future_result = match notification.downcast::<FutureResult>() {
+                Ok(x) => *x,
+                Err(_) => {
+                    error!("Session replay step received a notification that is not a known type");
+                    return StepStatus::Error {
+                        message: "Received future result of unknown type".to_string(),
+                    };
+                }
+            };
+
+            match future_result {
+                FutureResult::ReplayFinished => (),
+                FutureResult::ReplayEventReceived(event) => {
+                    self.handle_replay_event(event, outputs);
+                }
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            outputs.media.push(media);
+        }
+
+        self.status.clone()
+    }
+}
+
+#[instrument(skip(sender))]
+async fn run_replay(
+    path: Arc<String>,
+    speed: f64,
+    should_loop: bool,
+    sender: UnboundedSender<ReplayEvent>,
+) {
+    loop {
+        if let Err(message) = replay_file(&path, speed, &sender).await {
+            let _ = sender.send(ReplayEvent::Failed(message));
+            break;
+        }
+
+        if sender.send(ReplayEvent::PassCompleted).is_err() || !should_loop {
+            break;
+        }
+    }
+}
+
+async fn replay_file(
+    path: &str,
+    speed: f64,
+    sender: &UnboundedSender<ReplayEvent>,
+) -> Result<(), String> {
+    let file = File::open(path)
+        .await
+        .map_err(|error| format!("Failed to open session file '{}': {}", path, error))?;
+
+    let mut reader = BufReader::new(file);
+    let read_error = |error: format::SessionFileError| {
+        format!("Failed to read session file '{}': {}", path, error)
+    };
+
+    format::read_header(&mut reader).await.map_err(read_error)?;
+
+    let mut pacer = MediaPacer::new(speed);
+    pacer.start(Duration::new(0, 0));
+    while let Some(record) = format::read_record(&mut reader).await.map_err(read_error)? {
+        tokio::select! {
+            _ = pacer.wait_until_due(record.offset) => (),
+            _ = sender.closed() => return Ok(()),
+        }
+
+        if sender.send(ReplayEvent::Media(record.media)).is_err() {
+            break; // step is gone
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,233 @@
+use super::*;
+use crate::codecs::VIDEO_CODEC_H264_AVC;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::MediaPayloadMetadataCollection;
+use crate::workflows::steps::session_record::SessionRecordStepGenerator;
+use crate::workflows::steps::test_utils::StepTestContext;
+use crate::workflows::MediaType;
+use bytes::{Bytes, BytesMut};
+use std::collections::HashMap;
+use std::iter;
+use std::path::PathBuf;
+use std::time::Duration;
+
+struct SessionFile {
+    path: PathBuf,
+}
+
+impl SessionFile {
+    fn new() -> Self {
+        let file_name = format!("mmids-session-{}.bin", uuid::Uuid::new_v4());
+        let path = std::env::temp_dir().join(file_name);
+        SessionFile { path }
+    }
+
+    fn path(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+}
+
+impl Drop for SessionFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn create_definition(
+    step_type: &str,
+    parameters: &[(&str, Option<&str>)],
+) -> WorkflowStepDefinition {
+    WorkflowStepDefinition {
+        step_type: WorkflowStepType(step_type.to_string()),
+        parameters: parameters
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.map(|x| x.to_string())))
+            .collect(),
+    }
+}
+
+fn session_media() -> Vec<MediaNotification> {
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    let mut metadata = HashMap::new();
+    metadata.insert("width".to_string(), "1920".to_string());
+
+    vec![
+        MediaNotification {
+            stream_id: stream_id.clone(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        },
+        MediaNotification {
+            stream_id: stream_id.clone(),
+            content: MediaNotificationContent::Metadata { data: metadata },
+            annotations: Default::default(),
+        },
+        MediaNotification {
+            stream_id: stream_id.clone(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type: VIDEO_CODEC_H264_AVC.clone(),
+                timestamp: Duration::from_millis(5),
+                metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+                data: Bytes::from_static(&[1, 2, 3]),
+                is_required_for_decoding: true,
+            },
+            annotations: Default::default(),
+        },
+        MediaNotification {
+            stream_id,
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        },
+    ]
+}
+
+async fn record_session(file: &SessionFile) {
+    let definition = create_definition("session_record", &[(PATH, Some(&file.path()))]);
+    let mut context = StepTestContext::new(Box::new(SessionRecordStepGenerator::new()), definition)
+        .expect("Failed to create record step");
+
+    context.execute_pending_futures().await;
+    for media in session_media() {
+        context.assert_media_passed_through(media);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    // Give the writer time to flush everything to disk
+    context.execute_pending_futures().await;
+    assert_eq!(
+        context.status,
+        StepStatus::Active,
+        "Unexpected record step status"
+    );
+}
+
+async fn collect_replayed_media(
+    context: &mut StepTestContext,
+    iterations: usize,
+) -> Vec<MediaNotification> {
+    let mut received = Vec::new();
+    for _ in 0..iterations {
+        context.execute_pending_futures().await;
+        received.append(&mut context.media_outputs);
+    }
+
+    received
+}
+
+#[tokio::test]
+async fn missing_path_returns_error() {
+    let definition = create_definition("session_replay", &[]);
+    let result = StepTestContext::new(Box::new(SessionReplayStepGenerator::new()), definition);
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[tokio::test]
+async fn invalid_speed_returns_error() {
+    let definition = create_definition(
+        "session_replay",
+        &[(PATH, Some("session.bin")), (SPEED, Some("0"))],
+    );
+
+    let result = StepTestContext::new(Box::new(SessionReplayStepGenerator::new()), definition);
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[tokio::test]
+async fn replay_step_passes_through_media_from_previous_steps() {
+    let file = SessionFile::new();
+    record_session(&file).await;
+
+    let definition = create_definition("session_replay", &[(PATH, Some(&file.path()))]);
+    let mut context = StepTestContext::new(Box::new(SessionReplayStepGenerator::new()), definition)
+        .expect("Failed to create replay step");
+
+    context.assert_media_passed_through(MediaNotification {
+        stream_id: StreamId(Arc::new("xyz".to_string())),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    });
+}
+
+#[tokio::test]
+async fn replayed_session_matches_recorded_session() {
+    let file = SessionFile::new();
+    record_session(&file).await;
+
+    let definition = create_definition("session_replay", &[(PATH, Some(&file.path()))]);
+    let mut context = StepTestContext::new(Box::new(SessionReplayStepGenerator::new()), definition)
+        .expect("Failed to create replay step");
+
+    let received = collect_replayed_media(&mut context, 10).await;
+
+    assert_eq!(received, session_media(), "Unexpected replayed media");
+    assert_eq!(
+        context.status,
+        StepStatus::Active,
+        "Unexpected replay step status"
+    );
+}
+
+#[tokio::test]
+async fn looped_replay_replays_session_again() {
+    let file = SessionFile::new();
+    record_session(&file).await;
+
+    let definition = create_definition(
+        "session_replay",
+        &[
+            (PATH, Some(&file.path())),
+            (LOOP_FLAG, None),
+            (SPEED, Some("2")),
+        ],
+    );
+
+    let mut context = StepTestContext::new(Box::new(SessionReplayStepGenerator::new()), definition)
+        .expect("Failed to create replay step");
+
+    // A looped session never goes idle, so stop collecting media after a fixed amount of time
+    let _ = tokio::time::timeout(
+        Duration::from_millis(150),
+        context.execute_pending_futures(),
+    )
+    .await;
+
+    let received = std::mem::take(&mut context.media_outputs);
+    let expected = session_media();
+
+    assert!(
+        received.len() >= expected.len() * 2,
+        "Expected at least two passes of the session, but got {} notifications",
+        received.len()
+    );
+
+    assert_eq!(
+        &received[..expected.len()],
+        &expected[..],
+        "Unexpected first pass"
+    );
+    assert_eq!(
+        &received[expected.len()..expected.len() * 2],
+        &expected[..],
+        "Unexpected second pass"
+    );
+}
+
+#[tokio::test]
+async fn missing_file_sets_error_status() {
+    let file = SessionFile::new();
+    let definition = create_definition("session_replay", &[(PATH, Some(&file.path()))]);
+    let mut context = StepTestContext::new(Box::new(SessionReplayStepGenerator::new()), definition)
+        .expect("Failed to create replay step");
+
+    context.execute_pending_futures().await;
+
+    match &context.status {
+        StepStatus::Error { .. } => (),
+        status => panic!("Expected error status, instead got {:?}", status),
+    }
+}
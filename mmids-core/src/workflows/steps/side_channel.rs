@@ -0,0 +1,103 @@
+//! Side channels allow workflow steps to hand out-of-band data (e.g. images, metrics, or completed
+//! files) to consumers outside of the workflow, without that data flowing through the media path.
+//!
+//! A single side channel receiver is registered with the workflow step factory, and every step
+//! created by that factory is able to send data to it through its futures channel. Each message is
+//! tagged with the id of the step that produced it. The data itself is type erased, so steps
+//! should expose public types for the data they send, which consumers can then downcast to.
+
+use crate::workflows::definitions::WorkflowStepId;
+use std::any::Any;
+
+/// Out-of-band data sent by a workflow step
+pub struct SideChannelMessage {
+    /// The identifier of the step that sent the data
+    pub step_id: WorkflowStepId,
+
+    /// The data the step sent. Consumers are expected to downcast this to the type published
+    /// by the step.
+    pub data: Box<dyn Any + Send>,
+}
+
+impl SideChannelMessage {
+    /// Attempts to get a reference to the message's data as the specified type
+    pub fn data_as<T: Any>(&self) -> Option<&T> {
+        self.data.downcast_ref::<T>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::workflows::definitions::{WorkflowStepDefinition, WorkflowStepType};
+    use crate::workflows::steps::factory::{StepGenerator, WorkflowStepFactory};
+    use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+    use crate::workflows::steps::{
+        StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+    };
+    use std::collections::HashMap;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    struct TestData(&'static str);
+
+    struct TestStepGenerator;
+
+    struct TestStep;
+
+    impl StepGenerator for TestStepGenerator {
+        fn generate(
+            &self,
+            _definition: WorkflowStepDefinition,
+            futures_channel: WorkflowStepFuturesChannel,
+        ) -> StepCreationResult {
+            futures_channel.send_side_channel_data(TestData("created"));
+
+            Ok((Box::new(TestStep), StepStatus::Active))
+        }
+    }
+
+    impl WorkflowStep for TestStep {
+        fn execute(
+            &mut self,
+            _inputs: &mut StepInputs,
+            _outputs: &mut StepOutputs,
+            _futures_channel: WorkflowStepFuturesChannel,
+        ) -> StepStatus {
+            StepStatus::Active
+        }
+    }
+
+    #[test]
+    fn steps_created_by_factory_send_to_its_side_channel() {
+        let step_type = WorkflowStepType("test".to_string());
+        let mut factory = WorkflowStepFactory::new();
+        factory
+            .register(step_type.clone(), Box::new(TestStepGenerator))
+            .unwrap();
+
+        let (side_channel_sender, mut side_channel_receiver) = unbounded_channel();
+        factory.set_side_channel(side_channel_sender);
+
+        let definition = WorkflowStepDefinition {
+            step_type,
+            parameters: HashMap::new(),
+        };
+
+        let step_id = definition.get_id();
+        let (futures_sender, _futures_receiver) = unbounded_channel();
+        let result = factory
+            .create_step(definition, &futures_sender, None)
+            .unwrap();
+        assert!(result.is_ok(), "Expected step to be created");
+
+        let message = side_channel_receiver
+            .try_recv()
+            .expect("No side channel message received");
+
+        assert_eq!(message.step_id, step_id, "Unexpected step id");
+        assert_eq!(
+            message.data_as::<TestData>().map(|data| data.0),
+            Some("created"),
+            "Unexpected side channel data"
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! Shared stall detection for workflow steps that ingest streams from publishers. A publisher can
+//! stop sending media while keeping its connection open, which downstream steps would otherwise
+//! only notice once the connection finally times out (if ever). Detecting the stall separately from
+//! a disconnect lets operators see flaky contributions, without the stream being torn down.
+//!
+//! Stall detection is enabled with the `stall_timeout` parameter, the number of milliseconds a
+//! connected stream can go without media before it is considered stalled. When a stream stalls an
+//! `IngestStalled` stream alert is raised on the event hub, and the alert is cleared as soon as the
+//! stream's media resumes. Steps report how many of their streams are currently stalled through
+//! their state details.
+
+use crate::event_hub::{PublishEventRequest, StreamAlert, StreamAlertEvent};
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::StepFutureResult;
+use crate::StreamId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+pub const STALL_TIMEOUT: &str = "stall_timeout";
+
+/// The state details key steps report the number of currently stalled streams with
+pub const STALLED_STREAMS_DETAIL: &str = "stalled_streams";
+
+#[derive(Error, Debug)]
+#[error(
+    "Invalid {} value of '{0}'.  It must be a number of milliseconds greater than zero",
+    STALL_TIMEOUT
+)]
+pub struct StallTimeoutError(String);
+
+/// Future result raised when a stream should be checked for a stall. Steps using a
+/// `StallDetector` must pass these to `StallDetector::handle_check_due()`.
+pub struct StallCheckDue {
+    stream_id: StreamId,
+}
+
+impl StepFutureResult for StallCheckDue {}
+
+struct StreamActivity {
+    stream_name: Arc<String>,
+    last_media_at: Instant,
+    is_stalled: bool,
+}
+
+/// Tracks when each ingested stream last received media, and raises an event hub alert when a
+/// stream goes longer than the stall timeout without any.
+pub struct StallDetector {
+    timeout: Duration,
+    event_publisher: UnboundedSender<PublishEventRequest>,
+    streams: HashMap<StreamId, StreamActivity>,
+}
+
+/// Reads the stall timeout from the step definition's parameters. Returns `None` if stall
+/// detection was not requested.
+pub fn read_stall_timeout(
+    definition: &WorkflowStepDefinition,
+) -> Result<Option<Duration>, StallTimeoutError> {
+    match definition.parameters.get(STALL_TIMEOUT) {
+        Some(Some(value)) => match value.trim().parse::<u64>() {
+            Ok(millis) if millis > 0 => Ok(Some(Duration::from_millis(millis))),
+            _ => Err(StallTimeoutError(value.clone())),
+        },
+
+        _ => Ok(None),
+    }
+}
+
+impl StallDetector {
+    pub fn new(timeout: Duration, event_publisher: UnboundedSender<PublishEventRequest>) -> Self {
+        StallDetector {
+            timeout,
+            event_publisher,
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Starts watching a newly connected stream for stalls
+    pub fn stream_started(
+        &mut self,
+        stream_id: StreamId,
+        stream_name: Arc<String>,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let last_media_at = Instant::now();
+        self.streams.insert(
+            stream_id.clone(),
+            StreamActivity {
+                stream_name,
+                last_media_at,
+                is_stalled: false,
+            },
+        );
+
+        self.schedule_check(stream_id, last_media_at + self.timeout, futures_channel);
+    }
+
+    /// Records that media was received for the stream, clearing its stall if it had stalled
+    pub fn media_received(
+        &mut self,
+        stream_id: &StreamId,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let stream = match self.streams.get_mut(stream_id) {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        let now = Instant::now();
+        let previous_media_at = std::mem::replace(&mut stream.last_media_at, now);
+        if stream.is_stalled {
+            stream.is_stalled = false;
+            let duration = now - previous_media_at;
+            info!(
+                stream_id = %stream_id.0,
+                "Stream {} resumed after being stalled for {:?}", stream_id.0, duration,
+            );
+
+            let _ = self.event_publisher.send(PublishEventRequest::StreamAlert(
+                StreamAlertEvent::AlertCleared {
+                    stream_id: stream_id.clone(),
+                    stream_name: stream.stream_name.clone(),
+                    alert: StreamAlert::IngestStalled,
+                    duration,
+                },
+            ));
+
+            // Checks stop while a stream is stalled, so they need to start again
+            self.schedule_check(stream_id.clone(), now + self.timeout, futures_channel);
+        }
+    }
+
+    /// Stops watching a stream that disconnected. A disconnection is not a stall, so no events are
+    /// raised for it.
+    pub fn stream_stopped(&mut self, stream_id: &StreamId) {
+        self.streams.remove(stream_id);
+    }
+
+    /// Checks if the stream has stalled, raising an alert if it has
+    pub fn handle_check_due(
+        &mut self,
+        check: StallCheckDue,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let stream = match self.streams.get_mut(&check.stream_id) {
+            Some(stream) => stream,
+            None => return, // stream disconnected since the check was scheduled
+        };
+
+        if stream.is_stalled {
+            return;
+        }
+
+        let check_at = stream.last_media_at + self.timeout;
+        if Instant::now() < check_at {
+            // Media was received since the check was scheduled
+            self.schedule_check(check.stream_id, check_at, futures_channel);
+            return;
+        }
+
+        warn!(
+            stream_id = %check.stream_id.0,
+            "Stream {} has stalled, as no media has been received for {:?}",
+            check.stream_id.0, self.timeout,
+        );
+
+        stream.is_stalled = true;
+        let _ = self.event_publisher.send(PublishEventRequest::StreamAlert(
+            StreamAlertEvent::AlertRaised {
+                stream_id: check.stream_id,
+                stream_name: stream.stream_name.clone(),
+                alert: StreamAlert::IngestStalled,
+                duration: self.timeout,
+            },
+        ));
+    }
+
+    /// The number of streams that are currently stalled
+    pub fn stalled_stream_count(&self) -> usize {
+        self.streams
+            .values()
+            .filter(|stream| stream.is_stalled)
+            .count()
+    }
+
+    fn schedule_check(
+        &self,
+        stream_id: StreamId,
+        check_at: Instant,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        futures_channel.send_on_generic_future_completion(async move {
+            tokio::time::sleep_until(check_at).await;
+            StallCheckDue { stream_id }
+        });
+    }
+}
@@ -0,0 +1,286 @@
+//! The startup delay step helps players start new streams smoothly by building up a small buffer
+//! for them. Players that begin playback exactly at the point media starts flowing have nothing
+//! buffered, and tend to stutter until they've built up a buffer of their own.
+//!
+//! When a stream is announced, its media payloads are held back until they span `buffer_ms`
+//! milliseconds of media (1000 by default). The held media is then released all at once, giving
+//! downstream players that much media up front, and from then on the stream's media passes through
+//! in real time. Since media is only held once per stream, the delay never accumulates.
+//!
+//! Streams that don't produce enough media to fill the buffer (such as streams that stall right
+//! after being announced) have their held media released anyway once twice the buffer target has
+//! passed, so a stream is never delayed by more than that.
+//!
+//! Stream announcements, metadata and disconnections are never held back.
+
+#[cfg(test)]
+mod tests;
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::buffer_stats::{BufferLimit, BufferStats};
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent};
+use crate::StreamId;
+use std::collections::HashMap;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+pub const BUFFER_MS: &str = "buffer_ms";
+
+const DEFAULT_BUFFER: Duration = Duration::from_millis(1000);
+const MAX_BUFFER: Duration = Duration::from_secs(10);
+
+const BUFFERING_STREAMS_DETAIL: &str = "buffering_streams";
+const TIMED_OUT_COUNT_DETAIL: &str = "timed_out_count";
+
+/// Generates new instances of the startup delay workflow step
+pub struct StartupDelayStepGenerator;
+
+struct BufferingStream {
+    /// Identifies this buffering period, so timeouts for previous announcements of the same
+    /// stream id can be ignored
+    buffering_id: u64,
+
+    /// The earliest timestamp of the held media
+    earliest_timestamp: Option<Duration>,
+
+    held_media: Vec<MediaNotification>,
+}
+
+enum StreamState {
+    Buffering(BufferingStream),
+    Released,
+}
+
+struct StartupDelayStep {
+    buffer: Duration,
+    streams: HashMap<StreamId, StreamState>,
+    next_buffering_id: u64,
+    timed_out_count: u64,
+}
+
+enum FutureResult {
+    BufferingTimedOut {
+        stream_id: StreamId,
+        buffering_id: u64,
+    },
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a number of milliseconds greater than zero \
+        and no more than {}",
+        BUFFER_MS,
+        MAX_BUFFER.as_millis()
+    )]
+    InvalidBuffer(String),
+}
+
+impl StepGenerator for StartupDelayStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let buffer = match definition.parameters.get(BUFFER_MS) {
+            Some(Some(value)) => match value.trim().parse::<u64>() {
+                Ok(millis) if millis > 0 && Duration::from_millis(millis) <= MAX_BUFFER => {
+                    Duration::from_millis(millis)
+                }
+
+                _ => return Err(Box::new(StepStartupError::InvalidBuffer(value.clone()))),
+            },
+
+            _ => DEFAULT_BUFFER,
+        };
+
+        let step = StartupDelayStep {
+            buffer,
+            streams: HashMap::new(),
+            next_buffering_id: 0,
+            timed_out_count: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl StartupDelayStep {
+    fn handle_media(
+        &mut self,
+        media: MediaNotification,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { .. } => {
+                self.next_buffering_id += 1;
+                let buffering_id = self.next_buffering_id;
+                let stream_id = media.stream_id.clone();
+                let max_wait = self.buffer * 2;
+                futures_channel.send_on_generic_future_completion(async move {
+                    tokio::time::sleep(max_wait).await;
+                    FutureResult::BufferingTimedOut {
+                        stream_id,
+                        buffering_id,
+                    }
+                });
+
+                self.streams.insert(
+                    media.stream_id.clone(),
+                    StreamState::Buffering(BufferingStream {
+                        buffering_id,
+                        earliest_timestamp: None,
+                        held_media: Vec::new(),
+                    }),
+                );
+
+                outputs.media.push(media);
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.streams.remove(&media.stream_id);
+                outputs.media.push(media);
+            }
+
+            MediaNotificationContent::Metadata { .. } => outputs.media.push(media),
+
+            MediaNotificationContent::MediaPayload { timestamp, .. } => {
+                let timestamp = *timestamp;
+                let stream_id = media.stream_id.clone();
+                let stream = match self.streams.get_mut(&stream_id) {
+                    Some(StreamState::Buffering(stream)) => stream,
+                    Some(StreamState::Released) | None => {
+                        outputs.media.push(media);
+                        return;
+                    }
+                };
+
+                let earliest = match stream.earliest_timestamp {
+                    Some(earliest) if earliest <= timestamp => earliest,
+                    _ => timestamp,
+                };
+
+                stream.earliest_timestamp = Some(earliest);
+                stream.held_media.push(media);
+
+                if timestamp - earliest >= self.buffer {
+                    self.release(stream_id, outputs);
+                }
+            }
+        }
+    }
+
+    /// Sends out all media held for a buffering stream, after which its media passes through
+    fn release(&mut self, stream_id: StreamId, outputs: &mut StepOutputs) {
+        let stream = match self
+            .streams
+            .insert(stream_id.clone(), StreamState::Released)
+        {
+            Some(StreamState::Buffering(stream)) => stream,
+            _ => return,
+        };
+
+        info!(
+            stream_id = %stream_id.0,
+            "Releasing {} packets held to build the startup buffer",
+            stream.held_media.len()
+        );
+
+        outputs.media.extend(stream.held_media);
+    }
+
+    fn handle_timeout(
+        &mut self,
+        stream_id: StreamId,
+        buffering_id: u64,
+        outputs: &mut StepOutputs,
+    ) {
+        match self.streams.get(&stream_id) {
+            Some(StreamState::Buffering(stream)) if stream.buffering_id == buffering_id => (),
+            _ => return,
+        }
+
+        warn!(
+            stream_id = %stream_id.0,
+            "Stream did not fill its {:?} startup buffer in time, releasing held media", self.buffer
+        );
+
+        self.timed_out_count += 1;
+        self.release(stream_id, outputs);
+    }
+}
+
+impl WorkflowStep for StartupDelayStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            let future_result = match notification.downcast::<FutureResult>() {
+                Ok(x) => *x,
+                Err(_) => {
+                    error!("Startup delay step received a notification that is not a known type");
+                    return StepStatus::Error {
+                        message: "Received future result of unknown type".to_string(),
+                    };
+                }
+            };
+
+            match future_result {
+                FutureResult::BufferingTimedOut {
+                    stream_id,
+                    buffering_id,
+                } => self.handle_timeout(stream_id, buffering_id, outputs),
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            self.handle_media(media, outputs, &futures_channel);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let buffering_count = self
+            .streams
+            .values()
+            .filter(|state| matches!(state, StreamState::Buffering(_)))
+            .count();
+
+        let mut details = HashMap::new();
+        details.insert(
+            BUFFERING_STREAMS_DETAIL.to_string(),
+            buffering_count.to_string(),
+        );
+
+        details.insert(
+            TIMED_OUT_COUNT_DETAIL.to_string(),
+            self.timed_out_count.to_string(),
+        );
+
+        details
+    }
+
+    fn get_buffer_stats(&self) -> Option<BufferStats> {
+        let mut stats = BufferStats::new(Some(BufferLimit::Duration(self.buffer)));
+        for state in self.streams.values() {
+            if let StreamState::Buffering(stream) = state {
+                stats.add_stream_media(&stream.held_media);
+            }
+        }
+
+        Some(stats)
+    }
+}
@@ -0,0 +1,189 @@
+use super::*;
+use crate::codecs::VIDEO_CODEC_H264_AVC;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::MediaPayloadMetadataCollection;
+use crate::workflows::steps::futures_channel::FuturesChannelInnerResult;
+use crate::workflows::steps::test_utils::StepTestContext;
+use crate::workflows::MediaType;
+use bytes::{Bytes, BytesMut};
+use std::iter;
+use std::sync::Arc;
+
+struct TestContext {
+    step_context: StepTestContext,
+    stream_id: StreamId,
+}
+
+impl TestContext {
+    fn new(parameters: &[(&str, &str)]) -> Self {
+        let mut step_context =
+            StepTestContext::new(Box::new(StartupDelayStepGenerator), definition(parameters))
+                .expect("Failed to create step");
+
+        let stream_id = StreamId(Arc::new("abc".to_string()));
+        step_context.assert_media_passed_through(MediaNotification {
+            stream_id: stream_id.clone(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        });
+
+        TestContext {
+            step_context,
+            stream_id,
+        }
+    }
+
+    fn video(&self, timestamp: u64) -> MediaNotification {
+        MediaNotification {
+            stream_id: self.stream_id.clone(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type: VIDEO_CODEC_H264_AVC.clone(),
+                timestamp: Duration::from_millis(timestamp),
+                metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+                data: Bytes::from(vec![timestamp as u8]),
+                is_required_for_decoding: false,
+            },
+            annotations: Default::default(),
+        }
+    }
+
+    async fn execute_timeout(&mut self) {
+        match self.step_context.expect_future_resolved().await {
+            FuturesChannelInnerResult::Generic(notification) => {
+                let mut inputs = StepInputs::new();
+                let mut outputs = StepOutputs::new();
+                inputs.notifications.push(notification);
+
+                let channel = self.step_context.futures_channel_sender.clone();
+                self.step_context
+                    .step
+                    .execute(&mut inputs, &mut outputs, channel);
+
+                self.step_context.media_outputs = outputs.media;
+            }
+
+            FuturesChannelInnerResult::Media(_) => panic!("Expected generic future result"),
+        }
+    }
+}
+
+fn definition(parameters: &[(&str, &str)]) -> WorkflowStepDefinition {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("startup_delay".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    for (name, value) in parameters {
+        definition
+            .parameters
+            .insert(name.to_string(), Some(value.to_string()));
+    }
+
+    definition
+}
+
+#[test]
+fn buffer_larger_than_max_returns_error() {
+    let result = StepTestContext::new(
+        Box::new(StartupDelayStepGenerator),
+        definition(&[(BUFFER_MS, "60000")]),
+    );
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[tokio::test]
+async fn startup_media_held_until_buffer_filled_then_passed_through() {
+    let mut context = TestContext::new(&[(BUFFER_MS, "100")]);
+    let held = (0..4)
+        .map(|index| context.video(1000 + index * 33))
+        .collect::<Vec<_>>();
+
+    for media in &held {
+        context
+            .step_context
+            .assert_media_not_passed_through(media.clone());
+    }
+
+    let filling_media = context.video(1100);
+    context
+        .step_context
+        .execute_with_media(filling_media.clone());
+
+    let mut expected = held;
+    expected.push(filling_media);
+    assert_eq!(
+        context.step_context.media_outputs, expected,
+        "Expected all held media to be released at once"
+    );
+
+    let next = context.video(1133);
+    context.step_context.assert_media_passed_through(next);
+}
+
+#[tokio::test]
+async fn held_media_released_when_buffer_not_filled_in_time() {
+    let mut context = TestContext::new(&[(BUFFER_MS, "1")]);
+    let media = context.video(0);
+    context
+        .step_context
+        .assert_media_not_passed_through(media.clone());
+
+    context.execute_timeout().await;
+    assert_eq!(
+        context.step_context.media_outputs,
+        vec![media],
+        "Expected held media to be released"
+    );
+
+    let details = context.step_context.step.get_state_details();
+    assert_eq!(
+        details.get(TIMED_OUT_COUNT_DETAIL),
+        Some(&"1".to_string()),
+        "Unexpected timed out count"
+    );
+
+    let next = context.video(5);
+    context.step_context.assert_media_passed_through(next);
+}
+
+#[tokio::test]
+async fn buffer_stats_updated_as_buffer_fills_and_drains() {
+    let mut context = TestContext::new(&[(BUFFER_MS, "100")]);
+    assert_eq!(
+        context.step_context.step.get_buffer_stats(),
+        Some(BufferStats::new(Some(BufferLimit::Duration(
+            Duration::from_millis(100)
+        )))),
+        "Expected empty buffer stats"
+    );
+
+    for timestamp in [1000, 1033, 1066] {
+        let media = context.video(timestamp);
+        context.step_context.execute_with_media(media);
+    }
+
+    let stats = context.step_context.step.get_buffer_stats().unwrap();
+    assert_eq!(stats.current_packets, 3, "Unexpected packet count");
+    assert_eq!(stats.current_bytes, 3, "Unexpected byte count");
+    assert_eq!(
+        stats.current_duration,
+        Duration::from_millis(66),
+        "Unexpected buffered duration"
+    );
+
+    let media = context.video(1100);
+    context.step_context.execute_with_media(media);
+
+    let stats = context.step_context.step.get_buffer_stats().unwrap();
+    assert_eq!(stats.current_packets, 0, "Expected drained packet count");
+    assert_eq!(stats.current_bytes, 0, "Expected drained byte count");
+    assert_eq!(
+        stats.current_duration,
+        Duration::ZERO,
+        "Expected drained duration"
+    );
+}
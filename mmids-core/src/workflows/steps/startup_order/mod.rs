@@ -0,0 +1,283 @@
+//! The startup order step makes sure a new stream's sequence headers reach later steps in a
+//! specific order before any other media does. Some muxers fail to initialize when audio arrives
+//! before the video configuration, and placing this step in front of them guarantees the order
+//! they expect.
+//!
+//! The `order` parameter is a comma separated list of the media types (`video` and `audio`) whose
+//! sequence headers are required, in the order they should be raised (e.g. `video,audio`, which
+//! is the default). Media payloads for a new stream are held back until a sequence header for
+//! every required media type has been received. The sequence headers are then raised in the
+//! configured order, followed by all held media in the order it was received. Once a stream has
+//! been released its media passes through untouched.
+//!
+//! Streams that never provide one of the required sequence headers (such as video only streams
+//! when audio is required) are released once `max_buffered_packets` media payloads have been
+//! held, with whatever sequence headers have been received still raised in the configured order.
+//!
+//! Stream announcements, metadata and disconnections are never held back.
+
+#[cfg(test)]
+mod tests;
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::buffer_stats::{BufferLimit, BufferStats};
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use crate::StreamId;
+use std::collections::HashMap;
+use thiserror::Error;
+use tracing::{info, warn};
+
+pub const ORDER: &str = "order";
+pub const MAX_BUFFERED_PACKETS: &str = "max_buffered_packets";
+
+const DEFAULT_MAX_BUFFERED_PACKETS: usize = 300;
+
+const PENDING_STREAMS_DETAIL: &str = "pending_streams";
+const INCOMPLETE_RELEASE_COUNT_DETAIL: &str = "incomplete_release_count";
+
+/// Generates new instances of the startup order workflow step
+pub struct StartupOrderStepGenerator;
+
+struct PendingStream {
+    /// The latest sequence header received for each required media type
+    sequence_headers: HashMap<MediaType, MediaNotification>,
+
+    /// Media payloads received before the stream was released, in the order they arrived
+    held_media: Vec<MediaNotification>,
+}
+
+enum StreamState {
+    Pending(PendingStream),
+    Released,
+}
+
+struct StartupOrderStep {
+    order: Vec<MediaType>,
+    max_buffered_packets: usize,
+    streams: HashMap<StreamId, StreamState>,
+    incomplete_release_count: u64,
+}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a comma separated list of 'video' and 'audio'",
+        ORDER
+    )]
+    InvalidOrder(String),
+
+    #[error("The {} parameter lists '{0}' more than once", ORDER)]
+    DuplicateMediaType(String),
+
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a number greater than zero",
+        MAX_BUFFERED_PACKETS
+    )]
+    InvalidMaxBufferedPackets(String),
+}
+
+impl StepGenerator for StartupOrderStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let order = match definition.parameters.get(ORDER) {
+            Some(Some(value)) => {
+                let mut order = Vec::new();
+                for entry in value.split(',').map(|x| x.trim()) {
+                    let media_type = match entry.to_lowercase().as_str() {
+                        "video" => MediaType::Video,
+                        "audio" => MediaType::Audio,
+                        _ => return Err(Box::new(StepStartupError::InvalidOrder(value.clone()))),
+                    };
+
+                    if order.contains(&media_type) {
+                        return Err(Box::new(StepStartupError::DuplicateMediaType(
+                            entry.to_string(),
+                        )));
+                    }
+
+                    order.push(media_type);
+                }
+
+                order
+            }
+
+            _ => vec![MediaType::Video, MediaType::Audio],
+        };
+
+        let max_buffered_packets = match definition.parameters.get(MAX_BUFFERED_PACKETS) {
+            Some(Some(value)) => match value.trim().parse::<usize>() {
+                Ok(count) if count > 0 => count,
+                _ => {
+                    return Err(Box::new(StepStartupError::InvalidMaxBufferedPackets(
+                        value.clone(),
+                    )))
+                }
+            },
+
+            _ => DEFAULT_MAX_BUFFERED_PACKETS,
+        };
+
+        let step = StartupOrderStep {
+            order,
+            max_buffered_packets,
+            streams: HashMap::new(),
+            incomplete_release_count: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl StartupOrderStep {
+    fn handle_media(&mut self, media: MediaNotification, outputs: &mut StepOutputs) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { .. } => {
+                self.streams.insert(
+                    media.stream_id.clone(),
+                    StreamState::Pending(PendingStream {
+                        sequence_headers: HashMap::new(),
+                        held_media: Vec::new(),
+                    }),
+                );
+
+                outputs.media.push(media);
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.streams.remove(&media.stream_id);
+                outputs.media.push(media);
+            }
+
+            MediaNotificationContent::Metadata { .. } => outputs.media.push(media),
+
+            MediaNotificationContent::MediaPayload {
+                media_type,
+                is_required_for_decoding,
+                ..
+            } => {
+                let media_type = *media_type;
+                let is_sequence_header = *is_required_for_decoding;
+                let stream_id = media.stream_id.clone();
+                let stream = match self.streams.get_mut(&stream_id) {
+                    Some(StreamState::Pending(stream)) => stream,
+                    Some(StreamState::Released) | None => {
+                        outputs.media.push(media);
+                        return;
+                    }
+                };
+
+                if is_sequence_header && self.order.contains(&media_type) {
+                    stream.sequence_headers.insert(media_type, media);
+                } else {
+                    stream.held_media.push(media);
+                }
+
+                let has_all_headers = self
+                    .order
+                    .iter()
+                    .all(|media_type| stream.sequence_headers.contains_key(media_type));
+
+                let held_count = stream.held_media.len() + stream.sequence_headers.len();
+                if has_all_headers {
+                    self.release(stream_id, outputs);
+                } else if held_count >= self.max_buffered_packets {
+                    warn!(
+                        stream_id = %stream_id.0,
+                        "Stream did not provide all required sequence headers within {} packets, \
+                        releasing it anyway",
+                        self.max_buffered_packets
+                    );
+
+                    self.incomplete_release_count += 1;
+                    self.release(stream_id, outputs);
+                }
+            }
+        }
+    }
+
+    /// Raises a pending stream's sequence headers in the configured order, followed by all of its
+    /// held media
+    fn release(&mut self, stream_id: StreamId, outputs: &mut StepOutputs) {
+        let mut stream = match self
+            .streams
+            .insert(stream_id.clone(), StreamState::Released)
+        {
+            Some(StreamState::Pending(stream)) => stream,
+            _ => return,
+        };
+
+        info!(
+            stream_id = %stream_id.0,
+            "Releasing stream with {} sequence headers and {} held packets",
+            stream.sequence_headers.len(), stream.held_media.len()
+        );
+
+        for media_type in &self.order {
+            outputs
+                .media
+                .extend(stream.sequence_headers.remove(media_type));
+        }
+
+        outputs.media.append(&mut stream.held_media);
+    }
+}
+
+impl WorkflowStep for StartupOrderStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            self.handle_media(media, outputs);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let pending_count = self
+            .streams
+            .values()
+            .filter(|state| matches!(state, StreamState::Pending(_)))
+            .count();
+
+        let mut details = HashMap::new();
+        details.insert(
+            PENDING_STREAMS_DETAIL.to_string(),
+            pending_count.to_string(),
+        );
+
+        details.insert(
+            INCOMPLETE_RELEASE_COUNT_DETAIL.to_string(),
+            self.incomplete_release_count.to_string(),
+        );
+
+        details
+    }
+
+    fn get_buffer_stats(&self) -> Option<BufferStats> {
+        let mut stats = BufferStats::new(Some(BufferLimit::Packets(self.max_buffered_packets)));
+        for state in self.streams.values() {
+            if let StreamState::Pending(stream) = state {
+                stats.add_stream_media(
+                    stream
+                        .held_media
+                        .iter()
+                        .chain(stream.sequence_headers.values()),
+                );
+            }
+        }
+
+        Some(stats)
+    }
+}
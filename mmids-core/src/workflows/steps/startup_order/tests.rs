@@ -0,0 +1,238 @@
+use super::*;
+use crate::codecs::{AUDIO_CODEC_AAC_RAW, VIDEO_CODEC_H264_AVC};
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::MediaPayloadMetadataCollection;
+use crate::workflows::steps::test_utils::StepTestContext;
+use bytes::{Bytes, BytesMut};
+use std::iter;
+use std::sync::Arc;
+use std::time::Duration;
+
+struct TestContext {
+    step_context: StepTestContext,
+    stream_id: StreamId,
+}
+
+impl TestContext {
+    fn new(parameters: &[(&str, &str)]) -> Self {
+        let mut step_context =
+            StepTestContext::new(Box::new(StartupOrderStepGenerator), definition(parameters))
+                .expect("Failed to create step");
+
+        let stream_id = StreamId(Arc::new("abc".to_string()));
+        step_context.assert_media_passed_through(MediaNotification {
+            stream_id: stream_id.clone(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        });
+
+        TestContext {
+            step_context,
+            stream_id,
+        }
+    }
+
+    fn payload(
+        &self,
+        media_type: MediaType,
+        timestamp: u64,
+        is_sequence_header: bool,
+    ) -> MediaNotification {
+        let payload_type = match media_type {
+            MediaType::Audio => AUDIO_CODEC_AAC_RAW.clone(),
+            _ => VIDEO_CODEC_H264_AVC.clone(),
+        };
+
+        MediaNotification {
+            stream_id: self.stream_id.clone(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type,
+                payload_type,
+                timestamp: Duration::from_millis(timestamp),
+                metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+                data: Bytes::from(vec![timestamp as u8]),
+                is_required_for_decoding: is_sequence_header,
+            },
+            annotations: Default::default(),
+        }
+    }
+}
+
+fn definition(parameters: &[(&str, &str)]) -> WorkflowStepDefinition {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("startup_order".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    for (name, value) in parameters {
+        definition
+            .parameters
+            .insert(name.to_string(), Some(value.to_string()));
+    }
+
+    definition
+}
+
+#[test]
+fn invalid_order_returns_error() {
+    let result = StepTestContext::new(
+        Box::new(StartupOrderStepGenerator),
+        definition(&[(ORDER, "video,subtitles")]),
+    );
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn duplicate_media_type_in_order_returns_error() {
+    let result = StepTestContext::new(
+        Box::new(StartupOrderStepGenerator),
+        definition(&[(ORDER, "video,audio,video")]),
+    );
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn sequence_headers_raised_in_default_order_before_held_media() {
+    let mut context = TestContext::new(&[]);
+    let audio_header = context.payload(MediaType::Audio, 0, true);
+    let audio_frame = context.payload(MediaType::Audio, 10, false);
+    let video_header = context.payload(MediaType::Video, 0, true);
+    let video_frame = context.payload(MediaType::Video, 20, false);
+
+    context
+        .step_context
+        .assert_media_not_passed_through(audio_header.clone());
+    context
+        .step_context
+        .assert_media_not_passed_through(audio_frame.clone());
+
+    context
+        .step_context
+        .execute_with_media(video_header.clone());
+    assert_eq!(
+        context.step_context.media_outputs,
+        vec![video_header, audio_header, audio_frame],
+        "Unexpected startup media"
+    );
+
+    context
+        .step_context
+        .assert_media_passed_through(video_frame);
+}
+
+#[test]
+fn sequence_headers_raised_in_configured_order() {
+    let mut context = TestContext::new(&[(ORDER, "audio,video")]);
+    let video_header = context.payload(MediaType::Video, 0, true);
+    let audio_header = context.payload(MediaType::Audio, 0, true);
+
+    context
+        .step_context
+        .assert_media_not_passed_through(video_header.clone());
+
+    context
+        .step_context
+        .execute_with_media(audio_header.clone());
+    assert_eq!(
+        context.step_context.media_outputs,
+        vec![audio_header, video_header],
+        "Unexpected startup media"
+    );
+}
+
+#[test]
+fn only_configured_media_types_are_required() {
+    let mut context = TestContext::new(&[(ORDER, "video")]);
+    let video_header = context.payload(MediaType::Video, 0, true);
+
+    context
+        .step_context
+        .assert_media_passed_through(video_header);
+}
+
+#[test]
+fn stream_released_once_buffer_limit_reached() {
+    let mut context = TestContext::new(&[(MAX_BUFFERED_PACKETS, "3")]);
+    let video_header = context.payload(MediaType::Video, 0, true);
+    let first_frame = context.payload(MediaType::Video, 10, false);
+    let second_frame = context.payload(MediaType::Video, 20, false);
+
+    context
+        .step_context
+        .assert_media_not_passed_through(first_frame.clone());
+    context
+        .step_context
+        .assert_media_not_passed_through(video_header.clone());
+
+    context
+        .step_context
+        .execute_with_media(second_frame.clone());
+    assert_eq!(
+        context.step_context.media_outputs,
+        vec![video_header, first_frame, second_frame],
+        "Unexpected released media"
+    );
+
+    let details = context.step_context.step.get_state_details();
+    assert_eq!(
+        details.get(INCOMPLETE_RELEASE_COUNT_DETAIL),
+        Some(&"1".to_string()),
+        "Unexpected incomplete release count"
+    );
+}
+
+#[test]
+fn held_media_dropped_when_stream_disconnects() {
+    let mut context = TestContext::new(&[]);
+    let video_header = context.payload(MediaType::Video, 0, true);
+    context
+        .step_context
+        .assert_media_not_passed_through(video_header);
+
+    context
+        .step_context
+        .assert_media_passed_through(MediaNotification {
+            stream_id: context.stream_id.clone(),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        });
+
+    let details = context.step_context.step.get_state_details();
+    assert_eq!(
+        details.get(PENDING_STREAMS_DETAIL),
+        Some(&"0".to_string()),
+        "Expected no pending streams"
+    );
+}
+
+#[test]
+fn buffer_stats_reflect_held_media() {
+    let mut context = TestContext::new(&[(MAX_BUFFERED_PACKETS, "10")]);
+    let audio_frame = context.payload(MediaType::Audio, 10, false);
+    let video_header = context.payload(MediaType::Video, 30, true);
+    context.step_context.execute_with_media(audio_frame);
+    context.step_context.execute_with_media(video_header);
+
+    let stats = context.step_context.step.get_buffer_stats().unwrap();
+    assert_eq!(
+        stats.max_configured,
+        Some(BufferLimit::Packets(10)),
+        "Unexpected configured limit"
+    );
+    assert_eq!(stats.current_packets, 2, "Unexpected packet count");
+    assert_eq!(
+        stats.current_duration,
+        Duration::from_millis(20),
+        "Unexpected buffered duration"
+    );
+
+    let audio_header = context.payload(MediaType::Audio, 40, true);
+    context.step_context.execute_with_media(audio_header);
+
+    let stats = context.step_context.step.get_buffer_stats().unwrap();
+    assert_eq!(stats.current_packets, 0, "Expected released packets");
+}
@@ -0,0 +1,397 @@
+//! The stream change monitor step watches the streams flowing through it and publishes an event
+//! hub notification when a stream's bitrate or video resolution changes significantly, allowing
+//! external systems to react to it (e.g. re-planning adaptive bitrate ladders or alerting).
+//!
+//! A stream's bitrate is measured over windows of `bitrate_window` milliseconds of the stream's
+//! media timeline. A bitrate change is raised when a window's bitrate differs from the last
+//! reported bitrate by at least `bitrate_change_percent` percent. So normal fluctuations don't
+//! cause a flood of events, bitrate changes are raised at most once every `min_event_interval`
+//! milliseconds of the stream's timeline. A resolution change is raised any time a new sequence
+//! parameter set contains a different resolution than the previous one.
+//!
+//! The step also publishes the parameters of each stream (codecs, resolution, frame rate, audio
+//! channel info, and bitrate) when they are first determined and whenever they change. The
+//! parameters are read from the stream's sequence headers, while the bitrate follows the same
+//! change threshold and rate limit as bitrate changes, so a fluctuating bitrate doesn't flood
+//! subscribers with events.
+//!
+//! All media is passed through untouched.
+
+#[cfg(test)]
+mod tests;
+
+use crate::codecs::aac::AudioSpecificConfig;
+use crate::codecs::{
+    read_video_frame_rate, read_video_resolution, FrameRate, VideoResolution, AUDIO_CODEC_AAC_RAW,
+};
+use crate::event_hub::{PublishEventRequest, StreamChangeEvent, StreamParametersEvent};
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use crate::StreamId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::info;
+
+pub const BITRATE_CHANGE_PERCENT: &str = "bitrate_change_percent";
+pub const BITRATE_WINDOW: &str = "bitrate_window";
+pub const MIN_EVENT_INTERVAL: &str = "min_event_interval";
+
+const DEFAULT_BITRATE_CHANGE_PERCENT: u64 = 50;
+const DEFAULT_BITRATE_WINDOW: Duration = Duration::from_millis(5000);
+const DEFAULT_MIN_EVENT_INTERVAL: Duration = Duration::from_millis(30000);
+
+const BITRATE_EVENT_COUNT_DETAIL: &str = "bitrate_change_count";
+const RESOLUTION_EVENT_COUNT_DETAIL: &str = "resolution_change_count";
+const PARAMETERS_EVENT_COUNT_DETAIL: &str = "parameters_event_count";
+
+/// Generates new instances of the stream change monitor workflow step
+pub struct StreamChangeMonitorStepGenerator {
+    event_publisher: UnboundedSender<PublishEventRequest>,
+}
+
+/// The parameters of a stream that are published when they change
+#[derive(Clone, Default, PartialEq, Eq)]
+struct StreamParameters {
+    video_codec: Option<Arc<String>>,
+    resolution: Option<VideoResolution>,
+    frame_rate: Option<FrameRate>,
+    audio_codec: Option<Arc<String>>,
+    audio_sample_rate: Option<u32>,
+    audio_channels: Option<u8>,
+    bitrate_kbps: Option<u64>,
+}
+
+struct StreamState {
+    stream_name: Arc<String>,
+    parameters: StreamParameters,
+    resolution: Option<VideoResolution>,
+    window_start: Option<Duration>,
+    window_bytes: u64,
+    reported_kbps: Option<u64>,
+    last_bitrate_event_at: Option<Duration>,
+}
+
+struct StreamChangeMonitorStep {
+    event_publisher: UnboundedSender<PublishEventRequest>,
+    bitrate_change_percent: u64,
+    bitrate_window: Duration,
+    min_event_interval: Duration,
+    streams: HashMap<StreamId, StreamState>,
+    bitrate_event_count: u64,
+    resolution_event_count: u64,
+    parameters_event_count: u64,
+}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error("Invalid {0} value of '{1}' specified, must be a number greater than zero")]
+    InvalidValue(&'static str, String),
+}
+
+impl StreamChangeMonitorStepGenerator {
+    pub fn new(event_publisher: UnboundedSender<PublishEventRequest>) -> Self {
+        StreamChangeMonitorStepGenerator { event_publisher }
+    }
+}
+
+impl StepGenerator for StreamChangeMonitorStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let bitrate_change_percent = get_number(&definition, BITRATE_CHANGE_PERCENT)?
+            .unwrap_or(DEFAULT_BITRATE_CHANGE_PERCENT);
+
+        let bitrate_window = get_number(&definition, BITRATE_WINDOW)?
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_BITRATE_WINDOW);
+
+        let min_event_interval = get_number(&definition, MIN_EVENT_INTERVAL)?
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_MIN_EVENT_INTERVAL);
+
+        let step = StreamChangeMonitorStep {
+            event_publisher: self.event_publisher.clone(),
+            bitrate_change_percent,
+            bitrate_window,
+            min_event_interval,
+            streams: HashMap::new(),
+            bitrate_event_count: 0,
+            resolution_event_count: 0,
+            parameters_event_count: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+fn get_number(
+    definition: &WorkflowStepDefinition,
+    parameter: &'static str,
+) -> Result<Option<u64>, StepStartupError> {
+    match definition.parameters.get(parameter) {
+        Some(Some(value)) => match value.trim().parse::<u64>() {
+            Ok(number) if number > 0 => Ok(Some(number)),
+            _ => Err(StepStartupError::InvalidValue(parameter, value.clone())),
+        },
+
+        _ => Ok(None),
+    }
+}
+
+impl StreamChangeMonitorStep {
+    fn handle_media(&mut self, media: &MediaNotification) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                self.streams.insert(
+                    media.stream_id.clone(),
+                    StreamState {
+                        stream_name: stream_name.clone(),
+                        parameters: StreamParameters::default(),
+                        resolution: None,
+                        window_start: None,
+                        window_bytes: 0,
+                        reported_kbps: None,
+                        last_bitrate_event_at: None,
+                    },
+                );
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.streams.remove(&media.stream_id);
+            }
+
+            MediaNotificationContent::Metadata { .. } => (),
+
+            MediaNotificationContent::MediaPayload {
+                media_type,
+                payload_type,
+                timestamp,
+                data,
+                is_required_for_decoding,
+                ..
+            } => {
+                if *media_type == MediaType::Video {
+                    let resolution =
+                        read_video_resolution(payload_type, data, *is_required_for_decoding);
+
+                    if let Some(resolution) = resolution {
+                        self.check_resolution(&media.stream_id, resolution);
+                    }
+
+                    // Annex-B streams carry their sequence parameter sets in-band
+                    if *is_required_for_decoding || resolution.is_some() {
+                        let frame_rate =
+                            read_video_frame_rate(payload_type, data, *is_required_for_decoding);
+
+                        self.update_parameters(&media.stream_id, |parameters| {
+                            parameters.video_codec = Some(payload_type.clone());
+                            parameters.resolution = resolution.or(parameters.resolution);
+                            parameters.frame_rate = frame_rate;
+                        });
+                    }
+                }
+
+                if *media_type == MediaType::Audio && *is_required_for_decoding {
+                    let config = if *payload_type == *AUDIO_CODEC_AAC_RAW {
+                        AudioSpecificConfig::parse(data).ok()
+                    } else {
+                        None
+                    };
+
+                    self.update_parameters(&media.stream_id, |parameters| {
+                        parameters.audio_codec = Some(payload_type.clone());
+                        parameters.audio_sample_rate = config.map(|config| config.sample_rate);
+                        parameters.audio_channels =
+                            config.map(|config| config.channel_configuration);
+                    });
+                }
+
+                if !*is_required_for_decoding {
+                    let bitrate =
+                        self.measure_bitrate(&media.stream_id, *timestamp, data.len() as u64);
+
+                    if let Some(kbps) = bitrate {
+                        self.update_parameters(&media.stream_id, |parameters| {
+                            parameters.bitrate_kbps = Some(kbps);
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_resolution(&mut self, stream_id: &StreamId, resolution: VideoResolution) {
+        let stream = match self.streams.get_mut(stream_id) {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        let previous = match stream.resolution.replace(resolution) {
+            Some(previous) if previous != resolution => previous,
+            _ => return,
+        };
+
+        info!(
+            stream_id = %stream_id.0,
+            "Stream resolution changed from {} to {}", previous, resolution
+        );
+
+        self.resolution_event_count += 1;
+        let _ = self.event_publisher.send(PublishEventRequest::StreamChange(
+            StreamChangeEvent::ResolutionChanged {
+                stream_id: stream_id.clone(),
+                stream_name: stream.stream_name.clone(),
+                previous,
+                current: resolution,
+            },
+        ));
+    }
+
+    /// Publishes the stream's parameters if the update changes them
+    fn update_parameters(
+        &mut self,
+        stream_id: &StreamId,
+        update: impl FnOnce(&mut StreamParameters),
+    ) {
+        let stream = match self.streams.get_mut(stream_id) {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        let mut parameters = stream.parameters.clone();
+        update(&mut parameters);
+        if parameters == stream.parameters {
+            return;
+        }
+
+        stream.parameters = parameters.clone();
+        self.parameters_event_count += 1;
+
+        let _ = self
+            .event_publisher
+            .send(PublishEventRequest::StreamParameters(
+                StreamParametersEvent {
+                    stream_id: stream_id.clone(),
+                    stream_name: stream.stream_name.clone(),
+                    video_codec: parameters.video_codec,
+                    resolution: parameters.resolution,
+                    frame_rate: parameters.frame_rate,
+                    audio_codec: parameters.audio_codec,
+                    audio_sample_rate: parameters.audio_sample_rate,
+                    audio_channels: parameters.audio_channels,
+                    bitrate_kbps: parameters.bitrate_kbps,
+                },
+            ));
+    }
+
+    /// Measures the stream's bitrate, returning the new bitrate when it's first measured or when
+    /// a bitrate change was raised
+    fn measure_bitrate(
+        &mut self,
+        stream_id: &StreamId,
+        timestamp: Duration,
+        bytes: u64,
+    ) -> Option<u64> {
+        let min_event_interval = self.min_event_interval;
+        let stream = self.streams.get_mut(stream_id)?;
+
+        let window_start = *stream.window_start.get_or_insert(timestamp);
+        let elapsed = timestamp.saturating_sub(window_start);
+        if elapsed < self.bitrate_window {
+            stream.window_bytes += bytes;
+            return None;
+        }
+
+        // This payload belongs to the next window
+        let current_kbps = stream.window_bytes * 8 / elapsed.as_millis().max(1) as u64;
+        stream.window_start = Some(timestamp);
+        stream.window_bytes = bytes;
+
+        let previous_kbps = match stream.reported_kbps {
+            Some(previous) => previous,
+            None => {
+                stream.reported_kbps = Some(current_kbps);
+                return Some(current_kbps);
+            }
+        };
+
+        let difference = previous_kbps.abs_diff(current_kbps);
+        if difference * 100 < previous_kbps.max(1) * self.bitrate_change_percent {
+            return None;
+        }
+
+        let is_rate_limited = stream
+            .last_bitrate_event_at
+            .is_some_and(|last| timestamp.saturating_sub(last) < min_event_interval);
+
+        if is_rate_limited {
+            return None;
+        }
+
+        info!(
+            stream_id = %stream_id.0,
+            "Stream bitrate changed from {}kbps to {}kbps", previous_kbps, current_kbps
+        );
+
+        stream.reported_kbps = Some(current_kbps);
+        stream.last_bitrate_event_at = Some(timestamp);
+        self.bitrate_event_count += 1;
+
+        let _ = self.event_publisher.send(PublishEventRequest::StreamChange(
+            StreamChangeEvent::BitrateChanged {
+                stream_id: stream_id.clone(),
+                stream_name: stream.stream_name.clone(),
+                previous_kbps,
+                current_kbps,
+            },
+        ));
+
+        Some(current_kbps)
+    }
+}
+
+impl WorkflowStep for StreamChangeMonitorStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            self.handle_media(&media);
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            BITRATE_EVENT_COUNT_DETAIL.to_string(),
+            self.bitrate_event_count.to_string(),
+        );
+
+        details.insert(
+            RESOLUTION_EVENT_COUNT_DETAIL.to_string(),
+            self.resolution_event_count.to_string(),
+        );
+
+        details.insert(
+            PARAMETERS_EVENT_COUNT_DETAIL.to_string(),
+            self.parameters_event_count.to_string(),
+        );
+
+        details
+    }
+}
@@ -0,0 +1,329 @@
+use super::*;
+use crate::codecs::h264::{test_sps, test_sps_with_frame_rate, AvcDecoderConfigurationRecord};
+use crate::codecs::VIDEO_CODEC_H264_AVC;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::MediaPayloadMetadataCollection;
+use crate::workflows::steps::test_utils::StepTestContext;
+use bytes::{Bytes, BytesMut};
+use std::iter;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+struct TestContext {
+    step_context: StepTestContext,
+    events: UnboundedReceiver<PublishEventRequest>,
+    change_events: Vec<StreamChangeEvent>,
+    parameters_events: Vec<StreamParametersEvent>,
+}
+
+impl TestContext {
+    fn new(parameters: &[(&str, &str)]) -> Self {
+        let (sender, events) = unbounded_channel();
+        let mut definition = WorkflowStepDefinition {
+            step_type: WorkflowStepType("stream_change_monitor".to_string()),
+            parameters: HashMap::new(),
+        };
+
+        for (name, value) in parameters {
+            definition
+                .parameters
+                .insert(name.to_string(), Some(value.to_string()));
+        }
+
+        let generator = StreamChangeMonitorStepGenerator::new(sender);
+        let mut step_context =
+            StepTestContext::new(Box::new(generator), definition).expect("Failed to create step");
+
+        step_context.execute_with_media(MediaNotification {
+            stream_id: stream_id(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        });
+
+        TestContext {
+            step_context,
+            events,
+            change_events: Vec::new(),
+            parameters_events: Vec::new(),
+        }
+    }
+
+    fn receive_events(&mut self) {
+        while let Ok(request) = self.events.try_recv() {
+            match request {
+                PublishEventRequest::StreamChange(event) => self.change_events.push(event),
+                PublishEventRequest::StreamParameters(event) => self.parameters_events.push(event),
+                request => panic!("Unexpected publish request: {:?}", request),
+            }
+        }
+    }
+
+    fn events(&mut self) -> Vec<StreamChangeEvent> {
+        self.receive_events();
+        self.change_events.drain(..).collect()
+    }
+
+    fn parameters_events(&mut self) -> Vec<StreamParametersEvent> {
+        self.receive_events();
+        self.parameters_events.drain(..).collect()
+    }
+
+    /// Sends 100ms spaced frames of the specified size for the duration
+    fn send_frames(&mut self, start_millis: u64, end_millis: u64, frame_size: usize) {
+        for timestamp in (start_millis..end_millis).step_by(100) {
+            let media = video(
+                Duration::from_millis(timestamp),
+                Bytes::from(vec![0; frame_size]),
+                false,
+            );
+
+            self.step_context.assert_media_passed_through(media);
+        }
+    }
+}
+
+fn stream_id() -> StreamId {
+    StreamId(Arc::new("abc".to_string()))
+}
+
+fn video(timestamp: Duration, data: Bytes, is_sequence_header: bool) -> MediaNotification {
+    MediaNotification {
+        stream_id: stream_id(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Video,
+            payload_type: VIDEO_CODEC_H264_AVC.clone(),
+            timestamp,
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data,
+            is_required_for_decoding: is_sequence_header,
+        },
+        annotations: Default::default(),
+    }
+}
+
+fn sequence_header(width: u32, height: u32) -> MediaNotification {
+    sequence_header_with_sps(test_sps(width, height))
+}
+
+fn sequence_header_with_sps(sps: Bytes) -> MediaNotification {
+    let pps = Bytes::from_static(&[0x68, 0xeb, 0xe3, 0xcb, 0x22, 0xc0]);
+    let record =
+        AvcDecoderConfigurationRecord::from_parameter_sets(vec![sps], vec![pps], 4).unwrap();
+
+    video(Duration::from_millis(0), record.to_bytes(), true)
+}
+
+fn audio_sequence_header(sample_rate: u32, channels: u8) -> MediaNotification {
+    let config = AudioSpecificConfig {
+        audio_object_type: 2,
+        sample_rate,
+        channel_configuration: channels,
+    };
+
+    MediaNotification {
+        stream_id: stream_id(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Audio,
+            payload_type: AUDIO_CODEC_AAC_RAW.clone(),
+            timestamp: Duration::from_millis(0),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data: config.to_bytes(),
+            is_required_for_decoding: true,
+        },
+        annotations: Default::default(),
+    }
+}
+
+#[test]
+fn invalid_bitrate_change_percent_returns_error() {
+    let (sender, _receiver) = unbounded_channel();
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("stream_change_monitor".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    definition
+        .parameters
+        .insert(BITRATE_CHANGE_PERCENT.to_string(), Some("abc".to_string()));
+
+    let generator = StreamChangeMonitorStepGenerator::new(sender);
+    let result = StepTestContext::new(Box::new(generator), definition);
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn resolution_change_raises_event() {
+    let mut context = TestContext::new(&[]);
+
+    context
+        .step_context
+        .assert_media_passed_through(sequence_header(1280, 720));
+
+    context
+        .step_context
+        .assert_media_passed_through(sequence_header(1280, 720));
+
+    assert!(context.events().is_empty(), "Expected no events");
+
+    context
+        .step_context
+        .assert_media_passed_through(sequence_header(1920, 1080));
+
+    assert_eq!(
+        context.events(),
+        vec![StreamChangeEvent::ResolutionChanged {
+            stream_id: stream_id(),
+            stream_name: Arc::new("def".to_string()),
+            previous: VideoResolution {
+                width: 1280,
+                height: 720
+            },
+            current: VideoResolution {
+                width: 1920,
+                height: 1080
+            },
+        }]
+    );
+}
+
+#[test]
+fn large_bitrate_swing_raises_event() {
+    let mut context = TestContext::new(&[(BITRATE_WINDOW, "1000"), (MIN_EVENT_INTERVAL, "1000")]);
+
+    // 1000 bytes every 100ms is 80kbps
+    context.send_frames(0, 2000, 1000);
+    assert!(context.events().is_empty(), "Expected no events");
+
+    context.send_frames(2000, 3100, 5000);
+    assert_eq!(
+        context.events(),
+        vec![StreamChangeEvent::BitrateChanged {
+            stream_id: stream_id(),
+            stream_name: Arc::new("def".to_string()),
+            previous_kbps: 80,
+            current_kbps: 400,
+        }]
+    );
+
+    // Small fluctuations are ignored
+    context.send_frames(3100, 4100, 5500);
+    assert!(context.events().is_empty(), "Expected no events");
+}
+
+#[test]
+fn bitrate_events_are_rate_limited() {
+    let mut context = TestContext::new(&[(BITRATE_WINDOW, "1000"), (MIN_EVENT_INTERVAL, "5000")]);
+
+    context.send_frames(0, 1000, 1000);
+    context.send_frames(1000, 2100, 5000);
+    assert_eq!(context.events().len(), 1, "Expected one event");
+
+    context.send_frames(2100, 3100, 1000);
+    assert!(
+        context.events().is_empty(),
+        "Expected event to be rate limited"
+    );
+
+    context.send_frames(3100, 7100, 1000);
+    assert_eq!(
+        context.events(),
+        vec![StreamChangeEvent::BitrateChanged {
+            stream_id: stream_id(),
+            stream_name: Arc::new("def".to_string()),
+            previous_kbps: 400,
+            current_kbps: 80,
+        }]
+    );
+}
+
+#[test]
+fn parameters_event_raised_once_sequence_headers_arrive() {
+    let mut context = TestContext::new(&[]);
+    let frame_rate = FrameRate::new(30000, 1001).unwrap();
+
+    context
+        .step_context
+        .assert_media_passed_through(sequence_header_with_sps(test_sps_with_frame_rate(
+            1280,
+            720,
+            Some(frame_rate),
+        )));
+
+    context
+        .step_context
+        .assert_media_passed_through(audio_sequence_header(48000, 2));
+
+    let events = context.parameters_events();
+    assert_eq!(
+        events.len(),
+        2,
+        "Expected an event for each sequence header"
+    );
+    assert_eq!(
+        events[1],
+        StreamParametersEvent {
+            stream_id: stream_id(),
+            stream_name: Arc::new("def".to_string()),
+            video_codec: Some(VIDEO_CODEC_H264_AVC.clone()),
+            resolution: Some(VideoResolution {
+                width: 1280,
+                height: 720
+            }),
+            frame_rate: Some(frame_rate),
+            audio_codec: Some(AUDIO_CODEC_AAC_RAW.clone()),
+            audio_sample_rate: Some(48000),
+            audio_channels: Some(2),
+            bitrate_kbps: None,
+        }
+    );
+}
+
+#[test]
+fn parameters_event_not_raised_for_repeated_sequence_header() {
+    let mut context = TestContext::new(&[]);
+    context
+        .step_context
+        .assert_media_passed_through(sequence_header(1280, 720));
+
+    assert_eq!(context.parameters_events().len(), 1, "Expected one event");
+
+    context
+        .step_context
+        .assert_media_passed_through(sequence_header(1280, 720));
+
+    assert!(
+        context.parameters_events().is_empty(),
+        "Expected no event for unchanged parameters"
+    );
+}
+
+#[test]
+fn parameters_event_raised_only_for_reported_bitrate_changes() {
+    let mut context = TestContext::new(&[(BITRATE_WINDOW, "1000"), (MIN_EVENT_INTERVAL, "1000")]);
+
+    // 1000 bytes every 100ms is 80kbps
+    context.send_frames(0, 1100, 1000);
+    let events = context.parameters_events();
+    assert_eq!(events.len(), 1, "Expected an event for the first bitrate");
+    assert_eq!(events[0].bitrate_kbps, Some(80), "Unexpected bitrate");
+
+    context.send_frames(1100, 2000, 1000);
+    assert!(
+        context.parameters_events().is_empty(),
+        "Expected no events for an unchanged bitrate"
+    );
+
+    context.send_frames(2000, 3100, 5000);
+    let events = context.parameters_events();
+    assert_eq!(events.len(), 1, "Expected an event for the bitrate change");
+    assert_eq!(events[0].bitrate_kbps, Some(400), "Unexpected bitrate");
+
+    // Small fluctuations are ignored
+    context.send_frames(3100, 4100, 5500);
+    assert!(
+        context.parameters_events().is_empty(),
+        "Expected no events for small fluctuations"
+    );
+}
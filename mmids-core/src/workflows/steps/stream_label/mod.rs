@@ -0,0 +1,116 @@
+//! The stream label step assigns a purpose label (e.g. `primary`, `backup` or `preview`) to
+//! streams flowing through it, so later steps can route streams based on their label. The label
+//! is announced right after each stream's new incoming stream notification.
+//!
+//! All streams are labeled unless the `stream_name` parameter is specified, in which case only
+//! streams with that name are labeled. All media is passed through untouched.
+
+#[cfg(test)]
+mod tests;
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::stream_labels::label_notification;
+use crate::workflows::MediaNotificationContent;
+use std::collections::HashMap;
+use std::sync::Arc;
+use thiserror::Error;
+
+pub const LABEL: &str = "label";
+pub const STREAM_NAME: &str = "stream_name";
+
+const LABELED_COUNT_DETAIL: &str = "labeled_count";
+
+/// Generates new instances of the stream label workflow step
+#[derive(Default)]
+pub struct StreamLabelStepGenerator {}
+
+struct StreamLabelStep {
+    label: Arc<String>,
+    stream_name: Option<String>,
+    labeled_count: u64,
+}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error("No {} parameter specified", LABEL)]
+    NoLabelSpecified,
+}
+
+impl StreamLabelStepGenerator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl StepGenerator for StreamLabelStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let label = match definition.parameters.get(LABEL) {
+            Some(Some(label)) if !label.trim().is_empty() => Arc::new(label.trim().to_string()),
+            _ => return Err(Box::new(StepStartupError::NoLabelSpecified)),
+        };
+
+        let stream_name = match definition.parameters.get(STREAM_NAME) {
+            Some(Some(name)) => Some(name.clone()),
+            _ => None,
+        };
+
+        let step = StreamLabelStep {
+            label,
+            stream_name,
+            labeled_count: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl WorkflowStep for StreamLabelStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            let label_stream = match &media.content {
+                MediaNotificationContent::NewIncomingStream { stream_name } => self
+                    .stream_name
+                    .as_ref()
+                    .is_none_or(|name| name == stream_name.as_str()),
+
+                _ => false,
+            };
+
+            let stream_id = media.stream_id.clone();
+            outputs.media.push(media);
+
+            if label_stream {
+                self.labeled_count += 1;
+                outputs
+                    .media
+                    .push(label_notification(stream_id, self.label.clone()));
+            }
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            LABELED_COUNT_DETAIL.to_string(),
+            self.labeled_count.to_string(),
+        );
+
+        details
+    }
+}
@@ -0,0 +1,93 @@
+use super::*;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::steps::test_utils::StepTestContext;
+use crate::workflows::stream_labels::get_announced_label;
+use crate::workflows::MediaNotification;
+use crate::StreamId;
+
+fn create_context(label: Option<&str>, stream_name: Option<&str>) -> StepTestContext {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("stream_label".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    if let Some(label) = label {
+        definition
+            .parameters
+            .insert(LABEL.to_string(), Some(label.to_string()));
+    }
+
+    if let Some(stream_name) = stream_name {
+        definition
+            .parameters
+            .insert(STREAM_NAME.to_string(), Some(stream_name.to_string()));
+    }
+
+    StepTestContext::new(Box::new(StreamLabelStepGenerator::new()), definition)
+        .expect("Failed to create step")
+}
+
+fn new_stream(name: &str) -> MediaNotification {
+    MediaNotification {
+        stream_id: StreamId(Arc::new(format!("{}-id", name))),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new(name.to_string()),
+        },
+        annotations: Default::default(),
+    }
+}
+
+#[test]
+fn missing_label_returns_error() {
+    let definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("stream_label".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    let result = StepTestContext::new(Box::new(StreamLabelStepGenerator::new()), definition);
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn label_announced_after_new_stream() {
+    let mut context = create_context(Some("backup"), None);
+    let media = new_stream("abc");
+    context.execute_with_media(media.clone());
+
+    assert_eq!(
+        context.media_outputs.len(),
+        2,
+        "Unexpected number of outputs"
+    );
+    assert_eq!(context.media_outputs[0], media, "Expected new stream first");
+    assert_eq!(
+        context.media_outputs[1].stream_id, media.stream_id,
+        "Unexpected stream id for label"
+    );
+
+    assert_eq!(
+        get_announced_label(&context.media_outputs[1].content),
+        Some("backup"),
+        "Unexpected label announced"
+    );
+}
+
+#[test]
+fn only_streams_with_matching_name_labeled_when_stream_name_specified() {
+    let mut context = create_context(Some("backup"), Some("abc"));
+    context.execute_with_media(new_stream("def"));
+    assert_eq!(context.media_outputs.len(), 1, "Expected no label for def");
+
+    context.execute_with_media(new_stream("abc"));
+    assert_eq!(context.media_outputs.len(), 2, "Expected label for abc");
+}
+
+#[test]
+fn other_media_passed_through_without_label() {
+    let mut context = create_context(Some("backup"), None);
+    context.assert_media_passed_through(MediaNotification {
+        stream_id: StreamId(Arc::new("abc".to_string())),
+        content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
+    });
+}
@@ -0,0 +1,182 @@
+//! The stream properties step reads the properties encoders report in their stream metadata (such
+//! as RTMP's `onMetaData`), like the stream's resolution, frame rate and bitrates, into typed
+//! `StreamProperties`. The latest properties of each stream are reported through the step's state
+//! details.
+//!
+//! If the `publish_events` flag is specified, a stream properties event is published to the event
+//! hub whenever a stream's properties are first reported and any time they change, so other
+//! systems can make decisions based on them (e.g. routing high resolution streams differently).
+//!
+//! Metadata values that can't be read are ignored, and the number of values that couldn't be read
+//! is reported through the step's state details. All media is passed through untouched.
+
+#[cfg(test)]
+mod tests;
+
+use crate::event_hub::{PublishEventRequest, StreamPropertiesEvent};
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::stream_properties::StreamProperties;
+use crate::workflows::{MediaNotification, MediaNotificationContent};
+use crate::StreamId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{info, warn};
+
+pub const PUBLISH_EVENTS_FLAG: &str = "publish_events";
+
+const STREAM_PROPERTIES_DETAIL: &str = "stream_properties";
+const MALFORMED_VALUE_COUNT_DETAIL: &str = "malformed_value_count";
+
+/// Generates new instances of the stream properties workflow step
+pub struct StreamPropertiesStepGenerator {
+    event_publisher: UnboundedSender<PublishEventRequest>,
+}
+
+struct StreamState {
+    stream_name: Arc<String>,
+    properties: StreamProperties,
+}
+
+struct StreamPropertiesStep {
+    event_publisher: Option<UnboundedSender<PublishEventRequest>>,
+    streams: HashMap<StreamId, StreamState>,
+    malformed_value_count: u64,
+}
+
+impl StreamPropertiesStepGenerator {
+    pub fn new(event_publisher: UnboundedSender<PublishEventRequest>) -> Self {
+        StreamPropertiesStepGenerator { event_publisher }
+    }
+}
+
+impl StepGenerator for StreamPropertiesStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let event_publisher = if definition.parameters.contains_key(PUBLISH_EVENTS_FLAG) {
+            Some(self.event_publisher.clone())
+        } else {
+            None
+        };
+
+        let step = StreamPropertiesStep {
+            event_publisher,
+            streams: HashMap::new(),
+            malformed_value_count: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl StreamPropertiesStep {
+    fn handle_media(&mut self, media: &MediaNotification) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                self.streams.insert(
+                    media.stream_id.clone(),
+                    StreamState {
+                        stream_name: stream_name.clone(),
+                        properties: StreamProperties::default(),
+                    },
+                );
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.streams.remove(&media.stream_id);
+            }
+
+            MediaNotificationContent::Metadata { data } => {
+                let stream = match self.streams.get_mut(&media.stream_id) {
+                    Some(stream) => stream,
+                    None => return,
+                };
+
+                let mut properties = stream.properties.clone();
+                properties.apply_metadata(data);
+                if properties == stream.properties {
+                    return;
+                }
+
+                for key in &properties.malformed_keys {
+                    if !stream.properties.malformed_keys.contains(key) {
+                        warn!(
+                            stream_id = %media.stream_id.0,
+                            "Stream {} has a malformed {} metadata value of '{}'",
+                            stream.stream_name,
+                            key,
+                            data.get(key).map(|x| x.as_str()).unwrap_or_default(),
+                        );
+
+                        self.malformed_value_count += 1;
+                    }
+                }
+
+                info!(
+                    stream_id = %media.stream_id.0,
+                    "Stream {} reported properties: {}", stream.stream_name, properties,
+                );
+
+                stream.properties = properties;
+                if let Some(publisher) = &self.event_publisher {
+                    let _ = publisher.send(PublishEventRequest::StreamProperties(
+                        StreamPropertiesEvent {
+                            stream_id: media.stream_id.clone(),
+                            stream_name: stream.stream_name.clone(),
+                            properties: stream.properties.clone(),
+                        },
+                    ));
+                }
+            }
+
+            MediaNotificationContent::MediaPayload { .. } => (),
+        }
+    }
+}
+
+impl WorkflowStep for StreamPropertiesStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            self.handle_media(&media);
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut stream_properties = self
+            .streams
+            .values()
+            .map(|stream| format!("{}: {}", stream.stream_name, stream.properties))
+            .collect::<Vec<_>>();
+
+        stream_properties.sort();
+
+        let mut details = HashMap::new();
+        details.insert(
+            STREAM_PROPERTIES_DETAIL.to_string(),
+            stream_properties.join("; "),
+        );
+
+        details.insert(
+            MALFORMED_VALUE_COUNT_DETAIL.to_string(),
+            self.malformed_value_count.to_string(),
+        );
+
+        details
+    }
+}
@@ -0,0 +1,152 @@
+use super::*;
+use crate::codecs::VideoResolution;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::steps::test_utils::StepTestContext;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+struct TestContext {
+    step_context: StepTestContext,
+    events: UnboundedReceiver<PublishEventRequest>,
+}
+
+impl TestContext {
+    fn new(publish_events: bool) -> Self {
+        let (sender, events) = unbounded_channel();
+        let mut definition = WorkflowStepDefinition {
+            step_type: WorkflowStepType("stream_properties".to_string()),
+            parameters: HashMap::new(),
+        };
+
+        if publish_events {
+            definition
+                .parameters
+                .insert(PUBLISH_EVENTS_FLAG.to_string(), None);
+        }
+
+        let generator = StreamPropertiesStepGenerator::new(sender);
+        let mut step_context =
+            StepTestContext::new(Box::new(generator), definition).expect("Failed to create step");
+
+        step_context.execute_with_media(MediaNotification {
+            stream_id: stream_id(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        });
+
+        TestContext {
+            step_context,
+            events,
+        }
+    }
+
+    fn events(&mut self) -> Vec<StreamPropertiesEvent> {
+        let mut events = Vec::new();
+        while let Ok(request) = self.events.try_recv() {
+            match request {
+                PublishEventRequest::StreamProperties(event) => events.push(event),
+                request => panic!("Unexpected publish request: {:?}", request),
+            }
+        }
+
+        events
+    }
+
+    fn detail(&self, name: &str) -> String {
+        self.step_context.step.get_state_details()[name].clone()
+    }
+}
+
+fn stream_id() -> StreamId {
+    StreamId(Arc::new("abc".to_string()))
+}
+
+fn metadata(entries: &[(&str, &str)]) -> MediaNotification {
+    MediaNotification {
+        stream_id: stream_id(),
+        content: MediaNotificationContent::Metadata {
+            data: entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        },
+        annotations: Default::default(),
+    }
+}
+
+#[test]
+fn metadata_passed_through() {
+    let mut context = TestContext::new(false);
+
+    context
+        .step_context
+        .assert_media_passed_through(metadata(&[("width", "1280"), ("height", "720")]));
+}
+
+#[test]
+fn reported_properties_exposed_in_state_details() {
+    let mut context = TestContext::new(false);
+    context.step_context.execute_with_media(metadata(&[
+        ("width", "1280"),
+        ("height", "720"),
+        ("videodatarate", "3000"),
+    ]));
+
+    assert_eq!(
+        context.detail(STREAM_PROPERTIES_DETAIL),
+        "def: resolution=1280x720, video_bitrate=3000kbps",
+        "Unexpected stream properties detail"
+    );
+}
+
+#[test]
+fn no_events_published_without_flag() {
+    let mut context = TestContext::new(false);
+    context
+        .step_context
+        .execute_with_media(metadata(&[("width", "1280"), ("height", "720")]));
+
+    assert!(context.events().is_empty(), "Expected no events");
+}
+
+#[test]
+fn event_published_when_properties_change() {
+    let mut context = TestContext::new(true);
+    context
+        .step_context
+        .execute_with_media(metadata(&[("width", "1280"), ("height", "720")]));
+
+    let events = context.events();
+    assert_eq!(events.len(), 1, "Unexpected number of events");
+    assert_eq!(events[0].stream_id, stream_id(), "Unexpected stream id");
+    assert_eq!(
+        events[0].properties.resolution,
+        Some(VideoResolution {
+            width: 1280,
+            height: 720
+        }),
+        "Unexpected resolution"
+    );
+
+    // Metadata without any property changes, such as internal announcements, raises no event
+    context
+        .step_context
+        .execute_with_media(metadata(&[("width", "1280"), ("mmids_label", "backup")]));
+
+    assert!(context.events().is_empty(), "Expected no events");
+}
+
+#[test]
+fn malformed_values_counted() {
+    let mut context = TestContext::new(false);
+    context
+        .step_context
+        .execute_with_media(metadata(&[("framerate", "fast"), ("width", "-1")]));
+
+    assert_eq!(
+        context.detail(MALFORMED_VALUE_COUNT_DETAIL),
+        "2",
+        "Unexpected malformed value count"
+    );
+}
@@ -0,0 +1,123 @@
+//! The stream tags step adds custom tags to every stream flowing through it, so the stream's
+//! provenance (e.g. which region or venue it came from) is known by later steps, including steps
+//! in other workflows the stream is forwarded to. Every parameter given to the step is a tag, with
+//! the parameter's name as the tag's name (e.g. `stream_tags region=eu venue=main-stage`).
+//!
+//! The tags are announced right after each stream's new incoming stream notification. If a stream
+//! already has tags (e.g. from a step in the workflow the stream was forwarded from), the step's
+//! tags are added to them, replacing the values of any tags with the same name. All other media is
+//! passed through untouched.
+
+#[cfg(test)]
+mod tests;
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::stream_tags::{get_announced_tags, tags_notification, Tags};
+use crate::workflows::MediaNotificationContent;
+use std::collections::HashMap;
+use thiserror::Error;
+
+const TAGGED_COUNT_DETAIL: &str = "tagged_count";
+
+/// Generates new instances of the stream tags workflow step
+#[derive(Default)]
+pub struct StreamTagsStepGenerator {}
+
+struct StreamTagsStep {
+    tags: Tags,
+    tagged_count: u64,
+}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error("No tags were specified")]
+    NoTagsSpecified,
+
+    #[error("No value was specified for the '{0}' tag")]
+    NoTagValue(String),
+}
+
+impl StreamTagsStepGenerator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl StepGenerator for StreamTagsStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let mut tags = Tags::new();
+        for (name, value) in &definition.parameters {
+            match value {
+                Some(value) if !value.trim().is_empty() => {
+                    tags.insert(name.clone(), value.trim().to_string());
+                }
+
+                _ => return Err(Box::new(StepStartupError::NoTagValue(name.clone()))),
+            }
+        }
+
+        if tags.is_empty() {
+            return Err(Box::new(StepStartupError::NoTagsSpecified));
+        }
+
+        let step = StreamTagsStep {
+            tags,
+            tagged_count: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl WorkflowStep for StreamTagsStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            match &media.content {
+                MediaNotificationContent::NewIncomingStream { .. } => {
+                    let stream_id = media.stream_id.clone();
+                    outputs.media.push(media);
+                    outputs.media.push(tags_notification(stream_id, &self.tags));
+
+                    self.tagged_count += 1;
+                }
+
+                content => match get_announced_tags(content) {
+                    Some(mut tags) => {
+                        tags.extend(self.tags.clone());
+                        let mut notification = tags_notification(media.stream_id, &tags);
+                        notification.annotations = media.annotations;
+                        outputs.media.push(notification);
+                    }
+
+                    None => outputs.media.push(media),
+                },
+            }
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            TAGGED_COUNT_DETAIL.to_string(),
+            self.tagged_count.to_string(),
+        );
+
+        details
+    }
+}
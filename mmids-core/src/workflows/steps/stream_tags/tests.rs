@@ -0,0 +1,112 @@
+use super::*;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::steps::test_utils::StepTestContext;
+use crate::workflows::MediaNotification;
+use crate::StreamId;
+use std::sync::Arc;
+
+fn definition(parameters: &[(&str, Option<&str>)]) -> WorkflowStepDefinition {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("stream_tags".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    for (name, value) in parameters {
+        definition
+            .parameters
+            .insert(name.to_string(), value.map(|x| x.to_string()));
+    }
+
+    definition
+}
+
+fn tags(entries: &[(&str, &str)]) -> Tags {
+    entries
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+fn stream_id() -> StreamId {
+    StreamId(Arc::new("abc".to_string()))
+}
+
+fn new_stream() -> MediaNotification {
+    MediaNotification {
+        stream_id: stream_id(),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("def".to_string()),
+        },
+        annotations: Default::default(),
+    }
+}
+
+#[test]
+fn error_when_no_tags_specified() {
+    let result = StepTestContext::new(Box::new(StreamTagsStepGenerator::new()), definition(&[]));
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn error_when_tag_has_no_value() {
+    let result = StepTestContext::new(
+        Box::new(StreamTagsStepGenerator::new()),
+        definition(&[("region", None)]),
+    );
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn tags_announced_after_new_stream() {
+    let mut context = StepTestContext::new(
+        Box::new(StreamTagsStepGenerator::new()),
+        definition(&[("region", Some("eu")), ("venue", Some("main"))]),
+    )
+    .expect("Failed to create step");
+
+    context.execute_with_media(new_stream());
+
+    assert_eq!(
+        context.media_outputs.len(),
+        2,
+        "Unexpected number of outputs"
+    );
+    assert_eq!(
+        context.media_outputs[0],
+        new_stream(),
+        "Expected new stream"
+    );
+    assert_eq!(
+        get_announced_tags(&context.media_outputs[1].content),
+        Some(tags(&[("region", "eu"), ("venue", "main")])),
+        "Unexpected tags announced"
+    );
+}
+
+#[test]
+fn existing_tags_merged_with_step_tags() {
+    let mut context = StepTestContext::new(
+        Box::new(StreamTagsStepGenerator::new()),
+        definition(&[("region", Some("eu"))]),
+    )
+    .expect("Failed to create step");
+
+    context.execute_with_media(new_stream());
+    context.execute_with_media(tags_notification(
+        stream_id(),
+        &tags(&[("region", "us"), ("source", "venue-a")]),
+    ));
+
+    assert_eq!(
+        context.media_outputs.len(),
+        1,
+        "Unexpected number of outputs"
+    );
+    assert_eq!(
+        get_announced_tags(&context.media_outputs[0].content),
+        Some(tags(&[("region", "eu"), ("source", "venue-a")])),
+        "Unexpected tags announced"
+    );
+}
@@ -5,9 +5,11 @@ use crate::workflows::steps::futures_channel::{
 };
 
 use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::side_channel::SideChannelMessage;
 use crate::workflows::steps::{
     StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
 };
+use crate::workflows::watchers::WorkflowWatchers;
 use crate::workflows::MediaNotification;
 use anyhow::{anyhow, Result};
 use std::time::Duration;
@@ -20,15 +22,40 @@ pub struct StepTestContext {
     pub media_outputs: Vec<MediaNotification>,
     pub futures_channel_sender: WorkflowStepFuturesChannel,
     futures_channel_receiver: UnboundedReceiver<FuturesChannelResult>,
+    side_channel_receiver: UnboundedReceiver<SideChannelMessage>,
 }
 
 impl StepTestContext {
     pub fn new(
         generator: Box<dyn StepGenerator>,
         definition: WorkflowStepDefinition,
+    ) -> Result<Self> {
+        Self::create(generator, definition, None)
+    }
+
+    /// Creates the step as if it was part of a workflow with the specified watchers. Multiple
+    /// contexts sharing the same watchers act as output steps of the same workflow.
+    pub fn with_workflow_watchers(
+        generator: Box<dyn StepGenerator>,
+        definition: WorkflowStepDefinition,
+        workflow_watchers: WorkflowWatchers,
+    ) -> Result<Self> {
+        Self::create(generator, definition, Some(workflow_watchers))
+    }
+
+    fn create(
+        generator: Box<dyn StepGenerator>,
+        definition: WorkflowStepDefinition,
+        workflow_watchers: Option<WorkflowWatchers>,
     ) -> Result<Self> {
         let (sender, receiver) = unbounded_channel();
-        let channel = WorkflowStepFuturesChannel::new(definition.get_id(), sender);
+        let (side_channel_sender, side_channel_receiver) = unbounded_channel();
+        let mut channel = WorkflowStepFuturesChannel::new(definition.get_id(), sender)
+            .with_side_channel(side_channel_sender);
+
+        if let Some(workflow_watchers) = workflow_watchers {
+            channel = channel.with_workflow_watchers(workflow_watchers);
+        }
 
         let (step, status) = generator
             .generate(definition, channel.clone())
@@ -40,6 +67,7 @@ impl StepTestContext {
             media_outputs: Vec::new(),
             futures_channel_sender: channel,
             futures_channel_receiver: receiver,
+            side_channel_receiver,
         })
     }
 
@@ -144,4 +172,14 @@ impl StepTestContext {
             _ => panic!("No future resolved within timeout period"),
         }
     }
+
+    /// Gets all data the step has sent over its side channel so far
+    pub fn side_channel_messages(&mut self) -> Vec<SideChannelMessage> {
+        let mut messages = Vec::new();
+        while let Ok(message) = self.side_channel_receiver.try_recv() {
+            messages.push(message);
+        }
+
+        messages
+    }
 }
@@ -0,0 +1,244 @@
+//! The timestamp sanitize step repairs the timestamps of streams coming from misbehaving encoders,
+//! so later steps (especially muxers) always see a continuous and monotonic timeline.
+//!
+//! Each track (audio, video, etc...) of a stream is sanitized independently. The difference
+//! between a payload's timestamp and the previous payload's timestamp on the same track is
+//! considered plausible as long as it is within `max_jump` milliseconds (default 2000), and
+//! plausible timestamps are passed along with their original inter-frame gaps intact. Only clear
+//! anomalies are corrected:
+//!
+//! * A large backwards jump that matches the 33 bit wraparound of a 90kHz MPEG-TS clock is
+//!   treated as a wraparound, and the track keeps counting up from where it was.
+//! * Any other jump larger than `max_jump` in either direction rebases the track, so the payload
+//!   is placed one inter-frame gap after the previous payload.
+//!
+//! Small backwards steps within a track are clamped so the track's timestamps never go
+//! backwards. Sequence headers do not contribute to anomaly detection, since encoders commonly
+//! send them with arbitrary timestamps, but still have the track's current correction applied.
+
+#[cfg(test)]
+mod tests;
+
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::{
+    StepCreationResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use crate::StreamId;
+use std::collections::HashMap;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{info, warn};
+
+pub const MAX_JUMP: &str = "max_jump";
+
+const DEFAULT_MAX_JUMP: Duration = Duration::from_millis(2000);
+
+/// The duration of a full cycle of a 33 bit 90kHz clock, in microseconds
+const WRAPAROUND_PERIOD_MICROS: i128 = (1_i128 << 33) * 1_000_000 / 90_000;
+
+const WRAPAROUND_COUNT_DETAIL: &str = "wraparound_count";
+const JUMP_CORRECTION_COUNT_DETAIL: &str = "jump_correction_count";
+
+/// Generates new instances of the timestamp sanitize workflow step
+#[derive(Default)]
+pub struct TimestampSanitizeStepGenerator {}
+
+#[derive(Default)]
+struct TrackState {
+    last_input_micros: Option<i128>,
+    last_output_micros: Option<i128>,
+    last_gap_micros: i128,
+
+    /// Microseconds added to the track's incoming timestamps to get the outgoing timestamps
+    offset_micros: i128,
+}
+
+enum Correction {
+    Wraparound,
+    Jump,
+}
+
+struct TimestampSanitizeStep {
+    max_jump_micros: i128,
+    streams: HashMap<StreamId, HashMap<MediaType, TrackState>>,
+    wraparound_count: u64,
+    jump_correction_count: u64,
+}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a number of milliseconds greater than zero",
+        MAX_JUMP
+    )]
+    InvalidMaxJump(String),
+}
+
+impl TimestampSanitizeStepGenerator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl StepGenerator for TimestampSanitizeStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let max_jump = match definition.parameters.get(MAX_JUMP) {
+            Some(Some(value)) => match value.trim().parse::<u64>() {
+                Ok(millis) if millis > 0 => Duration::from_millis(millis),
+                _ => return Err(Box::new(StepStartupError::InvalidMaxJump(value.clone()))),
+            },
+
+            _ => DEFAULT_MAX_JUMP,
+        };
+
+        let step = TimestampSanitizeStep {
+            max_jump_micros: max_jump.as_micros() as i128,
+            streams: HashMap::new(),
+            wraparound_count: 0,
+            jump_correction_count: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl TimestampSanitizeStep {
+    fn handle_media(&mut self, mut media: MediaNotification) -> MediaNotification {
+        match &mut media.content {
+            MediaNotificationContent::NewIncomingStream { .. } => {
+                self.streams.insert(media.stream_id.clone(), HashMap::new());
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.streams.remove(&media.stream_id);
+            }
+
+            MediaNotificationContent::Metadata { .. } => (),
+
+            MediaNotificationContent::MediaPayload {
+                media_type,
+                timestamp,
+                is_required_for_decoding,
+                ..
+            } => {
+                let max_jump_micros = self.max_jump_micros;
+                let track = self
+                    .streams
+                    .entry(media.stream_id.clone())
+                    .or_default()
+                    .entry(*media_type)
+                    .or_default();
+
+                let input_micros = timestamp.as_micros() as i128;
+                if *is_required_for_decoding {
+                    let output_micros = (input_micros + track.offset_micros).max(0);
+                    *timestamp = Duration::from_micros(output_micros as u64);
+                    return media;
+                }
+
+                let correction =
+                    track.detect_anomaly(input_micros, max_jump_micros, &media.stream_id);
+
+                match correction {
+                    Some(Correction::Wraparound) => self.wraparound_count += 1,
+                    Some(Correction::Jump) => self.jump_correction_count += 1,
+                    None => (),
+                }
+
+                *timestamp = Duration::from_micros(track.output(input_micros) as u64);
+            }
+        }
+
+        media
+    }
+}
+
+impl TrackState {
+    /// Compares the incoming timestamp to the previous one, adjusting the track's offset if the
+    /// difference between them is implausible
+    fn detect_anomaly(
+        &mut self,
+        input_micros: i128,
+        max_jump_micros: i128,
+        stream_id: &StreamId,
+    ) -> Option<Correction> {
+        let last_input_micros = self.last_input_micros.replace(input_micros)?;
+        let delta = input_micros - last_input_micros;
+        if delta.abs() <= max_jump_micros {
+            if delta > 0 {
+                self.last_gap_micros = delta;
+            }
+
+            return None;
+        }
+
+        if delta < 0 && (delta + WRAPAROUND_PERIOD_MICROS).abs() <= max_jump_micros {
+            info!(
+                stream_id = %stream_id.0,
+                "Timestamp wraparound detected, continuing timeline from {}us", last_input_micros
+            );
+
+            self.offset_micros += WRAPAROUND_PERIOD_MICROS;
+            return Some(Correction::Wraparound);
+        }
+
+        let last_output_micros = self.last_output_micros.unwrap_or_default();
+        warn!(
+            stream_id = %stream_id.0,
+            "Timestamp jumped by {}us, rebasing timeline to continue from {}us",
+            delta, last_output_micros
+        );
+
+        self.offset_micros = last_output_micros + self.last_gap_micros - input_micros;
+        Some(Correction::Jump)
+    }
+
+    /// Gets the outgoing timestamp for the payload, ensuring the track never goes backwards
+    fn output(&mut self, input_micros: i128) -> i128 {
+        let mut output_micros = (input_micros + self.offset_micros).max(0);
+        if let Some(last_output_micros) = self.last_output_micros {
+            output_micros = output_micros.max(last_output_micros);
+        }
+
+        self.last_output_micros = Some(output_micros);
+        output_micros
+    }
+}
+
+impl WorkflowStep for TimestampSanitizeStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            let media = self.handle_media(media);
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            WRAPAROUND_COUNT_DETAIL.to_string(),
+            self.wraparound_count.to_string(),
+        );
+
+        details.insert(
+            JUMP_CORRECTION_COUNT_DETAIL.to_string(),
+            self.jump_correction_count.to_string(),
+        );
+
+        details
+    }
+}
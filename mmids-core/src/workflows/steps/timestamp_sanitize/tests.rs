@@ -0,0 +1,197 @@
+use super::*;
+use crate::codecs::{AUDIO_CODEC_AAC_RAW, VIDEO_CODEC_H264_AVC};
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::MediaPayloadMetadataCollection;
+use crate::workflows::steps::test_utils::StepTestContext;
+use bytes::{Bytes, BytesMut};
+use std::iter;
+use std::sync::Arc;
+
+/// Timestamp of the last 90kHz tick before a 33 bit clock wraps around
+const LAST_TICK_BEFORE_WRAP: Duration = Duration::from_micros(95_443_717_677);
+
+fn create_context(parameters: &[(&str, &str)]) -> StepTestContext {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("timestamp_sanitize".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    for (name, value) in parameters {
+        definition
+            .parameters
+            .insert(name.to_string(), Some(value.to_string()));
+    }
+
+    let mut context =
+        StepTestContext::new(Box::new(TimestampSanitizeStepGenerator::new()), definition)
+            .expect("Failed to create step");
+
+    context.execute_with_media(MediaNotification {
+        stream_id: stream_id(),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("def".to_string()),
+        },
+        annotations: Default::default(),
+    });
+
+    context
+}
+
+fn stream_id() -> StreamId {
+    StreamId(Arc::new("abc".to_string()))
+}
+
+fn media(
+    media_type: MediaType,
+    timestamp: Duration,
+    is_sequence_header: bool,
+) -> MediaNotification {
+    let payload_type = match media_type {
+        MediaType::Audio => AUDIO_CODEC_AAC_RAW.clone(),
+        _ => VIDEO_CODEC_H264_AVC.clone(),
+    };
+
+    MediaNotification {
+        stream_id: stream_id(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type,
+            payload_type,
+            timestamp,
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data: Bytes::from_static(&[1, 2, 3]),
+            is_required_for_decoding: is_sequence_header,
+        },
+        annotations: Default::default(),
+    }
+}
+
+fn video(timestamp: Duration) -> MediaNotification {
+    media(MediaType::Video, timestamp, false)
+}
+
+fn output_timestamp(context: &mut StepTestContext, media: MediaNotification) -> Duration {
+    context.execute_with_media(media);
+    assert_eq!(
+        context.media_outputs.len(),
+        1,
+        "Unexpected number of outputs"
+    );
+
+    match &context.media_outputs[0].content {
+        MediaNotificationContent::MediaPayload { timestamp, .. } => *timestamp,
+        content => panic!("Unexpected media output: {:?}", content),
+    }
+}
+
+#[test]
+fn invalid_max_jump_returns_error() {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("timestamp_sanitize".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    definition
+        .parameters
+        .insert(MAX_JUMP.to_string(), Some("0".to_string()));
+
+    let result = StepTestContext::new(Box::new(TimestampSanitizeStepGenerator::new()), definition);
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn plausible_timestamps_passed_through_unchanged() {
+    let mut context = create_context(&[]);
+    for millis in [0, 33, 66, 100, 500, 533] {
+        let timestamp = Duration::from_millis(millis);
+        context.assert_media_passed_through(video(timestamp));
+    }
+
+    // Audio and video are interleaved independently of each other
+    let audio = media(MediaType::Audio, Duration::from_millis(520), false);
+    context.assert_media_passed_through(audio);
+}
+
+#[test]
+fn wraparound_continues_timeline() {
+    let mut context = create_context(&[]);
+    let before_wrap = LAST_TICK_BEFORE_WRAP - Duration::from_millis(33);
+
+    context.assert_media_passed_through(video(before_wrap));
+    context.assert_media_passed_through(video(LAST_TICK_BEFORE_WRAP));
+
+    let timestamp = output_timestamp(&mut context, video(Duration::from_millis(22)));
+    let expected =
+        Duration::from_micros(WRAPAROUND_PERIOD_MICROS as u64) + Duration::from_millis(22);
+    assert_eq!(timestamp, expected, "Unexpected timestamp after wraparound");
+
+    let timestamp = output_timestamp(&mut context, video(Duration::from_millis(55)));
+    let expected =
+        Duration::from_micros(WRAPAROUND_PERIOD_MICROS as u64) + Duration::from_millis(55);
+    assert_eq!(timestamp, expected, "Unexpected timestamp after wraparound");
+
+    let details = context.step.get_state_details();
+    assert_eq!(
+        details.get(WRAPAROUND_COUNT_DETAIL),
+        Some(&"1".to_string()),
+        "Unexpected wraparound count"
+    );
+}
+
+#[test]
+fn large_forward_jump_rebased_to_continue_timeline() {
+    let mut context = create_context(&[(MAX_JUMP, "1000")]);
+    context.assert_media_passed_through(video(Duration::from_millis(1000)));
+    context.assert_media_passed_through(video(Duration::from_millis(1033)));
+
+    let timestamp = output_timestamp(&mut context, video(Duration::from_secs(5000)));
+    assert_eq!(
+        timestamp,
+        Duration::from_millis(1066),
+        "Expected jump to continue one frame after the previous timestamp"
+    );
+
+    // Later frames keep their spacing relative to the jumped timestamp
+    let timestamp = output_timestamp(&mut context, video(Duration::from_millis(5_000_040)));
+    assert_eq!(
+        timestamp,
+        Duration::from_millis(1106),
+        "Unexpected timestamp"
+    );
+
+    let details = context.step.get_state_details();
+    assert_eq!(
+        details.get(JUMP_CORRECTION_COUNT_DETAIL),
+        Some(&"1".to_string()),
+        "Unexpected jump correction count"
+    );
+}
+
+#[test]
+fn small_backwards_step_clamped_to_previous_timestamp() {
+    let mut context = create_context(&[]);
+    context.assert_media_passed_through(video(Duration::from_millis(100)));
+
+    let timestamp = output_timestamp(&mut context, video(Duration::from_millis(90)));
+    assert_eq!(
+        timestamp,
+        Duration::from_millis(100),
+        "Unexpected timestamp"
+    );
+}
+
+#[test]
+fn sequence_headers_do_not_trigger_corrections() {
+    let mut context = create_context(&[]);
+    context.assert_media_passed_through(video(Duration::from_secs(100)));
+
+    let header = media(MediaType::Video, Duration::from_millis(0), true);
+    context.assert_media_passed_through(header);
+    context.assert_media_passed_through(video(Duration::from_millis(100_033)));
+
+    let details = context.step.get_state_details();
+    assert_eq!(
+        details.get(JUMP_CORRECTION_COUNT_DETAIL),
+        Some(&"0".to_string()),
+        "Unexpected jump correction count"
+    );
+}
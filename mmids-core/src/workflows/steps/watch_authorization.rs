@@ -0,0 +1,46 @@
+//! Shared authorization handling for workflow steps that let clients consume streams, such as
+//! RTMP playback. Applications provide a `WatchAuthorizer` to the generators of these steps, which
+//! is asked whether each client requesting to watch a stream should be allowed to. This allows
+//! playback to be gated behind tokens or any other external checks, without each output step
+//! needing its own mechanism.
+//!
+//! Clients are only allowed to watch once the authorizer has allowed them, and steps reject the
+//! request of any client that was denied.
+
+use crate::net::ConnectionId;
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// Details about a client requesting to watch a stream
+#[derive(Clone, Debug)]
+pub struct WatchAuthorizationRequest {
+    /// The name of the stream the client is requesting to watch
+    pub stream_name: Arc<String>,
+
+    /// Unique identifier for the client's connection
+    pub connection_id: ConnectionId,
+
+    /// The ip address the client connected from
+    pub client_ip: IpAddr,
+
+    /// Protocol specific details about the request, such as the RTMP application the client
+    /// connected to
+    pub metadata: HashMap<String, String>,
+}
+
+/// Whether a client is allowed to watch the stream it requested
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchAuthorization {
+    Allow,
+    Deny,
+}
+
+/// Decides if clients are allowed to watch the streams they request
+pub trait WatchAuthorizer {
+    fn authorize(
+        &self,
+        request: WatchAuthorizationRequest,
+    ) -> BoxFuture<'static, WatchAuthorization>;
+}
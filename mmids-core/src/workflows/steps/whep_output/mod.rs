@@ -0,0 +1,773 @@
+//! The WHEP output step lets WebRTC players subscribe to the streams passing through it, using the
+//! WebRTC-HTTP Egress Protocol (WHEP) for signaling. Subscribers post their SDP offer to
+//! `<path>/<stream name>` on the HTTP port specified by the required `port` parameter, with `path`
+//! defaulting to `/whep`. The answer's `Location` header contains the subscriber's resource, which
+//! is deleted to end the session.
+//!
+//! Each stream's H264 or VP8 video and AAC or Opus audio is packetized into RTP and sent to the
+//! address offered for each media section, from a UDP socket bound to the `rtp_ip` address
+//! (`127.0.0.1` by default) for each subscriber track. Video is held back from a subscriber until
+//! the next keyframe, and H264 keyframes are preceded by the stream's parameter sets so
+//! subscribers can join at any time. Subscribers are removed when they end their session, when
+//! their stream disconnects, or when the subscriber's address is reported unreachable.
+//!
+//! The `max_subscribers` parameter limits the total number of subscribers across all streams.
+//!
+//! Media is sent as plain RTP. ICE connectivity checks and DTLS-SRTP are not performed, so
+//! subscribers must be able to receive unencrypted RTP at the addresses they offer (e.g. a media
+//! gateway on a trusted network).
+//!
+//! All media is passed through to the next step untouched.
+
+mod rtp;
+mod sdp;
+mod server;
+#[cfg(test)]
+mod tests;
+
+use crate::codecs::aac::AudioSpecificConfig;
+use crate::codecs::h264::AvcDecoderConfigurationRecord;
+use crate::codecs::nal::split_length_prefixed;
+use crate::codecs::{AUDIO_CODEC_AAC_RAW, AUDIO_CODEC_OPUS, VIDEO_CODEC_H264_AVC, VIDEO_CODEC_VP8};
+use crate::workflows::definitions::WorkflowStepDefinition;
+use crate::workflows::metadata::{MediaPayloadMetadataCollection, MetadataKey, MetadataValue};
+use crate::workflows::steps::factory::StepGenerator;
+use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use crate::workflows::steps::whep_output::rtp::{RtpCodec, RtpPacketizer};
+use crate::workflows::steps::whep_output::sdp::{
+    build_answer, parse_offer, MediaAnswer, MediaOffer,
+};
+use crate::workflows::steps::whep_output::server::{SubscribeResponse, WhepRequest, WhepServer};
+use crate::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use crate::StreamId;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc::unbounded_channel;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+pub const PORT: &str = "port";
+pub const PATH: &str = "path";
+pub const MAX_SUBSCRIBERS: &str = "max_subscribers";
+pub const RTP_IP: &str = "rtp_ip";
+
+const DEFAULT_PATH: &str = "/whep";
+const DEFAULT_RTP_IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+const PORT_DETAIL: &str = "port";
+const SUBSCRIBER_COUNT_DETAIL: &str = "subscriber_count";
+const DROPPED_PACKET_COUNT_DETAIL: &str = "dropped_packet_count";
+
+/// Generates new instances of the WHEP output workflow step
+pub struct WhepOutputStepGenerator {
+    is_keyframe_metadata_key: MetadataKey,
+    pts_offset_metadata_key: MetadataKey,
+}
+
+#[derive(Clone)]
+enum VideoFormat {
+    H264 {
+        nal_length_size: u8,
+        parameter_sets: Vec<Bytes>,
+        profile_level_id: [u8; 3],
+    },
+
+    Vp8,
+}
+
+#[derive(Clone)]
+enum AudioFormat {
+    Aac { config: Bytes, codec: RtpCodec },
+    Opus,
+}
+
+struct SubscriberTrack {
+    socket: UdpSocket,
+    packetizer: RtpPacketizer,
+}
+
+struct Subscriber {
+    video: Option<SubscriberTrack>,
+    audio: Option<SubscriberTrack>,
+    waiting_for_keyframe: bool,
+}
+
+struct WhepStream {
+    name: Arc<String>,
+    video: Option<VideoFormat>,
+    audio: Option<AudioFormat>,
+    subscribers: HashMap<String, Subscriber>,
+}
+
+struct WhepOutputStep {
+    server: WhepServer,
+    rtp_ip: IpAddr,
+    max_subscribers: Option<usize>,
+    is_keyframe_metadata_key: MetadataKey,
+    pts_offset_metadata_key: MetadataKey,
+    streams: HashMap<StreamId, WhepStream>,
+    dropped_packet_count: u64,
+}
+
+enum FutureResult {
+    RequestReceived(WhepRequest),
+    ServerGone,
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error("No {} parameter specified", PORT)]
+    NoPort,
+
+    #[error(
+        "Invalid {} value of '{0}'.  A number from 0 to 65535 should be specified",
+        PORT
+    )]
+    InvalidPort(String),
+
+    #[error("Invalid {} value of '{0}'.  It must start with a '/'", PATH)]
+    InvalidPath(String),
+
+    #[error(
+        "Invalid {} value of '{0}'.  It must be a number greater than zero",
+        MAX_SUBSCRIBERS
+    )]
+    InvalidMaxSubscribers(String),
+
+    #[error("Invalid {} value of '{0}'.  It must be an IP address", RTP_IP)]
+    InvalidRtpIp(String),
+
+    #[error("Failed to start serving WHEP requests on port {0}: {1}")]
+    ServerStartFailed(u16, hyper::Error),
+}
+
+/// Media being sent to subscribers, in the form the media's RTP packetization needs
+enum OutgoingMedia {
+    H264 { nal_units: Vec<Bytes> },
+    Vp8 { frame: Bytes },
+    Aac { frame: Bytes },
+    Opus { packet: Bytes },
+}
+
+impl WhepOutputStepGenerator {
+    pub fn new(
+        is_keyframe_metadata_key: MetadataKey,
+        pts_offset_metadata_key: MetadataKey,
+    ) -> Self {
+        WhepOutputStepGenerator {
+            is_keyframe_metadata_key,
+            pts_offset_metadata_key,
+        }
+    }
+}
+
+impl StepGenerator for WhepOutputStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let port = match definition.parameters.get(PORT) {
+            Some(Some(value)) => match value.trim().parse::<u16>() {
+                Ok(port) => port,
+                Err(_) => return Err(Box::new(StepStartupError::InvalidPort(value.clone()))),
+            },
+
+            _ => return Err(Box::new(StepStartupError::NoPort)),
+        };
+
+        let path = match definition.parameters.get(PATH) {
+            Some(Some(value)) if !value.trim().starts_with('/') => {
+                return Err(Box::new(StepStartupError::InvalidPath(value.clone())));
+            }
+
+            Some(Some(value)) => value.trim().trim_end_matches('/').to_string(),
+            _ => DEFAULT_PATH.to_string(),
+        };
+
+        let max_subscribers = match definition.parameters.get(MAX_SUBSCRIBERS) {
+            Some(Some(value)) => match value.trim().parse::<usize>() {
+                Ok(max) if max > 0 => Some(max),
+                _ => {
+                    return Err(Box::new(StepStartupError::InvalidMaxSubscribers(
+                        value.clone(),
+                    )))
+                }
+            },
+
+            _ => None,
+        };
+
+        let rtp_ip = match definition.parameters.get(RTP_IP) {
+            Some(Some(value)) => match value.trim().parse::<IpAddr>() {
+                Ok(ip) => ip,
+                Err(_) => return Err(Box::new(StepStartupError::InvalidRtpIp(value.clone()))),
+            },
+
+            _ => DEFAULT_RTP_IP,
+        };
+
+        let (sender, receiver) = unbounded_channel();
+        let server = match WhepServer::start(port, Arc::new(path), sender) {
+            Ok(server) => server,
+            Err(error) => return Err(Box::new(StepStartupError::ServerStartFailed(port, error))),
+        };
+
+        futures_channel.send_on_generic_unbounded_recv(
+            receiver,
+            FutureResult::RequestReceived,
+            || FutureResult::ServerGone,
+        );
+
+        let step = WhepOutputStep {
+            server,
+            rtp_ip,
+            max_subscribers,
+            is_keyframe_metadata_key: self.is_keyframe_metadata_key,
+            pts_offset_metadata_key: self.pts_offset_metadata_key,
+            streams: HashMap::new(),
+            dropped_packet_count: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl WhepOutputStep {
+    fn subscriber_count(&self) -> usize {
+        self.streams
+            .values()
+            .map(|stream| stream.subscribers.len())
+            .sum()
+    }
+
+    fn handle_request(&mut self, request: WhepRequest) {
+        match request {
+            WhepRequest::Subscribe {
+                stream_name,
+                offer,
+                response_channel,
+            } => {
+                let response = self.subscribe(&stream_name, &offer);
+                let _ = response_channel.send(response);
+            }
+
+            WhepRequest::Unsubscribe {
+                stream_name,
+                subscriber_id,
+                response_channel,
+            } => {
+                let removed = self
+                    .streams
+                    .values_mut()
+                    .filter(|stream| stream.name.as_str() == stream_name)
+                    .any(|stream| stream.subscribers.remove(&subscriber_id).is_some());
+
+                if removed {
+                    info!(
+                        "WHEP subscriber {} of stream {} ended its session",
+                        subscriber_id, stream_name
+                    );
+                }
+
+                let _ = response_channel.send(removed);
+            }
+        }
+    }
+
+    fn subscribe(&mut self, stream_name: &str, offer: &str) -> SubscribeResponse {
+        if let Some(max) = self.max_subscribers {
+            if self.subscriber_count() >= max {
+                warn!(
+                    "Rejecting WHEP subscriber of stream {} as the limit of {} subscribers has \
+                    been reached",
+                    stream_name, max
+                );
+
+                return SubscribeResponse::TooManySubscribers;
+            }
+        }
+
+        let rtp_ip = self.rtp_ip;
+        let stream = match self
+            .streams
+            .values_mut()
+            .find(|stream| stream.name.as_str() == stream_name)
+        {
+            Some(stream) => stream,
+            None => return SubscribeResponse::StreamNotFound,
+        };
+
+        let offers = match parse_offer(offer) {
+            Ok(offers) => offers,
+            Err(error) => return SubscribeResponse::InvalidOffer(error.to_string()),
+        };
+
+        let mut subscriber = Subscriber {
+            video: None,
+            audio: None,
+            waiting_for_keyframe: true,
+        };
+
+        let mut answers = Vec::new();
+        for media_offer in &offers {
+            let format = match media_offer.kind.as_str() {
+                "video" if subscriber.video.is_none() => stream.video.as_ref().map(video_codec),
+                "audio" if subscriber.audio.is_none() => stream.audio.as_ref().map(audio_codec),
+                _ => None,
+            };
+
+            let track =
+                format.and_then(|(codec, fmtp)| create_track(rtp_ip, media_offer, codec, fmtp));
+
+            let (track, answer) = match track {
+                Some(track) => track,
+                None => {
+                    answers.push(MediaAnswer::Rejected);
+                    continue;
+                }
+            };
+
+            if media_offer.kind == "video" {
+                subscriber.video = Some(track);
+            } else {
+                subscriber.audio = Some(track);
+            }
+
+            answers.push(answer);
+        }
+
+        if subscriber.video.is_none() && subscriber.audio.is_none() {
+            return SubscribeResponse::NoCompatibleMedia;
+        }
+
+        let subscriber_id = Uuid::new_v4().to_string();
+        let answer = build_answer(random_u32() as u64, rtp_ip, &offers, &answers);
+
+        info!(
+            "WHEP subscriber {} started watching stream {} (video: {}, audio: {})",
+            subscriber_id,
+            stream_name,
+            subscriber.video.is_some(),
+            subscriber.audio.is_some(),
+        );
+
+        stream.subscribers.insert(subscriber_id.clone(), subscriber);
+        SubscribeResponse::Accepted {
+            subscriber_id,
+            answer,
+        }
+    }
+
+    fn handle_media(&mut self, media: &MediaNotification) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                info!(
+                    stream_id = %media.stream_id.0,
+                    "Stream {} is available to WHEP subscribers", stream_name
+                );
+
+                self.streams.insert(
+                    media.stream_id.clone(),
+                    WhepStream {
+                        name: stream_name.clone(),
+                        video: None,
+                        audio: None,
+                        subscribers: HashMap::new(),
+                    },
+                );
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                if let Some(stream) = self.streams.remove(&media.stream_id) {
+                    if !stream.subscribers.is_empty() {
+                        info!(
+                            stream_id = %media.stream_id.0,
+                            "Stream {} disconnected, ending {} WHEP subscriber sessions",
+                            stream.name, stream.subscribers.len(),
+                        );
+                    }
+                }
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type,
+                payload_type,
+                timestamp,
+                metadata,
+                data,
+                is_required_for_decoding,
+            } => {
+                let (is_keyframe, pts_offset) = self.read_metadata(metadata);
+                let stream = match self.streams.get_mut(&media.stream_id) {
+                    Some(stream) => stream,
+                    None => return,
+                };
+
+                if *is_required_for_decoding {
+                    update_format(stream, *media_type, payload_type, data);
+                    return;
+                }
+
+                let has_h264 = matches!(stream.video, Some(VideoFormat::H264 { .. }));
+                let has_aac = matches!(stream.audio, Some(AudioFormat::Aac { .. }));
+                let payload_type = payload_type.as_str();
+                let (outgoing, is_keyframe) = match media_type {
+                    MediaType::Video
+                        if has_h264 && payload_type == VIDEO_CODEC_H264_AVC.as_str() =>
+                    {
+                        match h264_access_unit(stream, data, is_keyframe) {
+                            Some(nal_units) => (OutgoingMedia::H264 { nal_units }, is_keyframe),
+                            None => return,
+                        }
+                    }
+
+                    MediaType::Video if payload_type == VIDEO_CODEC_VP8.as_str() => {
+                        // VP8 frame tags have their lowest bit cleared for keyframes
+                        let is_keyframe =
+                            is_keyframe || data.first().is_some_and(|tag| tag & 1 == 0);
+                        stream.video = Some(VideoFormat::Vp8);
+
+                        (
+                            OutgoingMedia::Vp8 {
+                                frame: data.clone(),
+                            },
+                            is_keyframe,
+                        )
+                    }
+
+                    MediaType::Audio if has_aac && payload_type == AUDIO_CODEC_AAC_RAW.as_str() => {
+                        (
+                            OutgoingMedia::Aac {
+                                frame: data.clone(),
+                            },
+                            false,
+                        )
+                    }
+
+                    MediaType::Audio if payload_type == AUDIO_CODEC_OPUS.as_str() => {
+                        stream.audio = Some(AudioFormat::Opus);
+                        (
+                            OutgoingMedia::Opus {
+                                packet: data.clone(),
+                            },
+                            false,
+                        )
+                    }
+
+                    _ => return,
+                };
+
+                let time = *timestamp + Duration::from_millis(pts_offset.max(0) as u64);
+                let dropped = send_to_subscribers(stream, &outgoing, time, is_keyframe);
+                self.dropped_packet_count += dropped;
+            }
+
+            MediaNotificationContent::Metadata { .. } => (),
+        }
+    }
+
+    fn read_metadata(&self, metadata: &MediaPayloadMetadataCollection) -> (bool, i32) {
+        let mut is_keyframe = false;
+        let mut pts_offset = 0;
+        for item in metadata.iter() {
+            if item.key() == self.is_keyframe_metadata_key {
+                is_keyframe = matches!(item.value(), MetadataValue::Bool(true));
+            } else if item.key() == self.pts_offset_metadata_key {
+                if let MetadataValue::I32(offset) = item.value() {
+                    pts_offset = offset;
+                }
+            }
+        }
+
+        (is_keyframe, pts_offset)
+    }
+}
+
+impl WorkflowStep for WhepOutputStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            let future_result = match notification.downcast::<FutureResult>() {
+                Ok(result) => *result,
+                Err(_) => continue,
+            };
+
+            match future_result {
+                FutureResult::RequestReceived(request) => self.handle_request(request),
+                FutureResult::ServerGone => {
+                    return StepStatus::Error {
+                        message: "WHEP server stopped unexpectedly".to_string(),
+                    };
+                }
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            self.handle_media(&media);
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            PORT_DETAIL.to_string(),
+            self.server.local_address().port().to_string(),
+        );
+
+        details.insert(
+            SUBSCRIBER_COUNT_DETAIL.to_string(),
+            self.subscriber_count().to_string(),
+        );
+
+        details.insert(
+            DROPPED_PACKET_COUNT_DETAIL.to_string(),
+            self.dropped_packet_count.to_string(),
+        );
+
+        details
+    }
+}
+
+fn update_format(stream: &mut WhepStream, media_type: MediaType, payload_type: &str, data: &Bytes) {
+    match media_type {
+        MediaType::Video if payload_type == VIDEO_CODEC_H264_AVC.as_str() => {
+            match AvcDecoderConfigurationRecord::parse(data) {
+                Ok(record) => {
+                    stream.video = Some(VideoFormat::H264 {
+                        nal_length_size: record.nal_length_size,
+                        parameter_sets: record.parameter_sets(),
+                        profile_level_id: [
+                            record.profile_indication,
+                            record.profile_compatibility,
+                            record.level_indication,
+                        ],
+                    });
+                }
+
+                Err(error) => warn!(
+                    "Stream {} has an invalid H264 sequence header: {}",
+                    stream.name, error
+                ),
+            }
+        }
+
+        MediaType::Audio if payload_type == AUDIO_CODEC_AAC_RAW.as_str() => {
+            match AudioSpecificConfig::parse(data) {
+                Ok(config) => {
+                    stream.audio = Some(AudioFormat::Aac {
+                        config: data.clone(),
+                        codec: RtpCodec::Aac {
+                            sample_rate: config.sample_rate,
+                            channels: config.channel_configuration,
+                        },
+                    });
+                }
+
+                Err(error) => warn!(
+                    "Stream {} has an invalid AAC sequence header: {}",
+                    stream.name, error
+                ),
+            }
+        }
+
+        _ => (),
+    }
+}
+
+/// Gets the NAL units to send for an H264 video frame. Keyframes are sent with the stream's
+/// parameter sets, so subscribers can start decoding from any keyframe.
+fn h264_access_unit(stream: &WhepStream, data: &Bytes, is_keyframe: bool) -> Option<Vec<Bytes>> {
+    let (nal_length_size, parameter_sets) = match &stream.video {
+        Some(VideoFormat::H264 {
+            nal_length_size,
+            parameter_sets,
+            ..
+        }) => (*nal_length_size, parameter_sets),
+
+        _ => return None,
+    };
+
+    let nal_units = match split_length_prefixed(data, nal_length_size) {
+        Ok(nal_units) => nal_units,
+        Err(error) => {
+            warn!("Stream {} has invalid H264 framing: {}", stream.name, error);
+            return None;
+        }
+    };
+
+    if !is_keyframe {
+        return Some(nal_units);
+    }
+
+    Some(parameter_sets.iter().cloned().chain(nal_units).collect())
+}
+
+/// Sends the media to every subscriber of the stream, returning the number of packets that had
+/// to be dropped. Subscribers whose address is unreachable are removed.
+fn send_to_subscribers(
+    stream: &mut WhepStream,
+    outgoing: &OutgoingMedia,
+    time: Duration,
+    is_keyframe: bool,
+) -> u64 {
+    let mut dropped = 0;
+    let stream_name = stream.name.clone();
+    stream.subscribers.retain(|subscriber_id, subscriber| {
+        let track = match outgoing {
+            OutgoingMedia::H264 { .. } | OutgoingMedia::Vp8 { .. } => {
+                if subscriber.waiting_for_keyframe && !is_keyframe {
+                    return true;
+                }
+
+                subscriber.waiting_for_keyframe = false;
+                subscriber.video.as_mut()
+            }
+
+            OutgoingMedia::Aac { .. } | OutgoingMedia::Opus { .. } => subscriber.audio.as_mut(),
+        };
+
+        let track = match track {
+            Some(track) => track,
+            None => return true,
+        };
+
+        let packets = match outgoing {
+            OutgoingMedia::H264 { nal_units } => track.packetizer.packetize_h264(time, nal_units),
+            OutgoingMedia::Vp8 { frame } => track.packetizer.packetize_vp8(time, frame),
+            OutgoingMedia::Aac { frame } => track.packetizer.packetize_aac(time, frame),
+            OutgoingMedia::Opus { packet } => track.packetizer.packetize_opus(time, packet),
+        };
+
+        for packet in packets {
+            match track.socket.send(&packet) {
+                Ok(_) => (),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => dropped += 1,
+                Err(error) => {
+                    info!(
+                        "Removing WHEP subscriber {} of stream {} as sending to it failed: {}",
+                        subscriber_id, stream_name, error
+                    );
+
+                    return false;
+                }
+            }
+        }
+
+        true
+    });
+
+    dropped
+}
+
+fn video_codec(format: &VideoFormat) -> (RtpCodec, Option<String>) {
+    match format {
+        VideoFormat::H264 {
+            profile_level_id, ..
+        } => {
+            let fmtp = format!(
+                "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id={:02x}{:02x}{:02x}",
+                profile_level_id[0], profile_level_id[1], profile_level_id[2],
+            );
+
+            (RtpCodec::H264, Some(fmtp))
+        }
+
+        VideoFormat::Vp8 => (RtpCodec::Vp8, None),
+    }
+}
+
+fn audio_codec(format: &AudioFormat) -> (RtpCodec, Option<String>) {
+    match format {
+        AudioFormat::Aac { config, codec } => {
+            let config = config
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>();
+
+            let fmtp = format!(
+                "streamtype=5;profile-level-id=1;mode=AAC-hbr;sizelength=13;indexlength=3;\
+                indexdeltalength=3;config={}",
+                config
+            );
+
+            (*codec, Some(fmtp))
+        }
+
+        AudioFormat::Opus => (
+            RtpCodec::Opus,
+            Some("minptime=10;useinbandfec=1".to_string()),
+        ),
+    }
+}
+
+/// Creates the track for an offered media section, if the section offered the codec and a socket
+/// could be set up to send RTP to the subscriber
+fn create_track(
+    rtp_ip: IpAddr,
+    offer: &MediaOffer,
+    codec: RtpCodec,
+    fmtp: Option<String>,
+) -> Option<(SubscriberTrack, MediaAnswer)> {
+    let payload_type = offer.find_payload_type(codec)?;
+    let destination = offer.destination()?;
+    let socket = match bind_rtp_socket(rtp_ip, destination) {
+        Ok(socket) => socket,
+        Err(error) => {
+            warn!(
+                "Failed to create an RTP socket to send {} to {}: {}",
+                offer.kind, destination, error
+            );
+
+            return None;
+        }
+    };
+
+    let port = socket.local_addr().ok()?.port();
+    let ssrc = random_u32();
+    let track = SubscriberTrack {
+        socket,
+        packetizer: RtpPacketizer::new(payload_type, codec.clock_rate(), ssrc, random_u32() as u16),
+    };
+
+    let answer = MediaAnswer::Accepted {
+        port,
+        payload_type,
+        codec,
+        fmtp,
+        ssrc,
+    };
+
+    Some((track, answer))
+}
+
+/// Binds a UDP socket connected to the subscriber, so errors reported for the subscriber's
+/// address (such as ICMP port unreachable messages) surface when sending to it
+fn bind_rtp_socket(rtp_ip: IpAddr, destination: SocketAddr) -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(SocketAddr::new(rtp_ip, 0))?;
+    socket.connect(destination)?;
+    socket.set_nonblocking(true)?;
+
+    Ok(socket)
+}
+
+fn random_u32() -> u32 {
+    Uuid::new_v4().as_u128() as u32
+}
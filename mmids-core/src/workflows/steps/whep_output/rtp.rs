@@ -0,0 +1,264 @@
+//! Packetizes media payloads into RTP packets, following the payload formats for each supported
+//! codec (RFC 6184 for H264, RFC 3640 for AAC, RFC 7741 for VP8 and RFC 7587 for Opus).
+
+use bytes::{BufMut, Bytes, BytesMut};
+use std::time::Duration;
+
+/// The largest RTP payload sent, which keeps packets under the MTU of most networks once the RTP,
+/// UDP and IP headers are added
+pub const MAX_PAYLOAD_SIZE: usize = 1200;
+
+const RTP_VERSION: u8 = 2;
+const RTP_HEADER_SIZE: usize = 12;
+const H264_FU_A_TYPE: u8 = 28;
+
+/// The codecs media can be packetized for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtpCodec {
+    H264,
+    Aac { sample_rate: u32, channels: u8 },
+    Vp8,
+    Opus,
+}
+
+impl RtpCodec {
+    /// The encoding name the codec is identified by in SDP `rtpmap` attributes
+    pub fn encoding_name(&self) -> &'static str {
+        match self {
+            RtpCodec::H264 => "H264",
+            RtpCodec::Aac { .. } => "MPEG4-GENERIC",
+            RtpCodec::Vp8 => "VP8",
+            RtpCodec::Opus => "opus",
+        }
+    }
+
+    pub fn clock_rate(&self) -> u32 {
+        match self {
+            RtpCodec::H264 | RtpCodec::Vp8 => 90_000,
+            RtpCodec::Aac { sample_rate, .. } => *sample_rate,
+            RtpCodec::Opus => 48_000,
+        }
+    }
+
+    /// The value of the SDP `rtpmap` attribute for the codec, minus the payload type
+    pub fn rtpmap(&self) -> String {
+        match self.channels() {
+            1 => format!("{}/{}", self.encoding_name(), self.clock_rate()),
+            channels => format!(
+                "{}/{}/{}",
+                self.encoding_name(),
+                self.clock_rate(),
+                channels
+            ),
+        }
+    }
+
+    fn channels(&self) -> u8 {
+        match self {
+            RtpCodec::Aac { channels, .. } => *channels,
+            RtpCodec::Opus => 2, // Opus is always signaled as stereo
+            _ => 1,
+        }
+    }
+}
+
+/// Creates RTP packets for a single track, keeping track of the packets' sequence numbers
+pub struct RtpPacketizer {
+    payload_type: u8,
+    clock_rate: u32,
+    ssrc: u32,
+    sequence_number: u16,
+}
+
+impl RtpPacketizer {
+    pub fn new(payload_type: u8, clock_rate: u32, ssrc: u32, initial_sequence_number: u16) -> Self {
+        RtpPacketizer {
+            payload_type,
+            clock_rate,
+            ssrc,
+            sequence_number: initial_sequence_number,
+        }
+    }
+
+    /// Packetizes the NAL units of a single H264 access unit. NAL units that don't fit in a single
+    /// packet are split into FU-A fragments.
+    pub fn packetize_h264(&mut self, time: Duration, nal_units: &[Bytes]) -> Vec<Bytes> {
+        let timestamp = self.timestamp(time);
+        let mut packets = Vec::new();
+        for (index, nal_unit) in nal_units.iter().enumerate() {
+            let is_last_nal_unit = index == nal_units.len() - 1;
+            if nal_unit.is_empty() {
+                continue;
+            }
+
+            if nal_unit.len() <= MAX_PAYLOAD_SIZE {
+                packets.push(self.packet(timestamp, is_last_nal_unit, &[], nal_unit));
+                continue;
+            }
+
+            let indicator = (nal_unit[0] & 0xe0) | H264_FU_A_TYPE;
+            let nal_type = nal_unit[0] & 0x1f;
+            let fragments = nal_unit[1..].chunks(MAX_PAYLOAD_SIZE - 2);
+            let fragment_count = fragments.len();
+            for (fragment_index, fragment) in fragments.enumerate() {
+                let is_first = fragment_index == 0;
+                let is_last = fragment_index == fragment_count - 1;
+                let header =
+                    nal_type | if is_first { 0x80 } else { 0 } | if is_last { 0x40 } else { 0 };
+
+                let marker = is_last_nal_unit && is_last;
+                packets.push(self.packet(timestamp, marker, &[indicator, header], fragment));
+            }
+        }
+
+        packets
+    }
+
+    /// Packetizes a single raw AAC frame, using the `AAC-hbr` mode's AU header
+    pub fn packetize_aac(&mut self, time: Duration, frame: &[u8]) -> Vec<Bytes> {
+        let timestamp = self.timestamp(time);
+
+        // One 16 bit AU header, containing a 13 bit size and 3 bit index
+        let au_size = (frame.len() as u16) << 3;
+        let header = [0x00, 0x10, (au_size >> 8) as u8, au_size as u8];
+
+        vec![self.packet(timestamp, true, &header, frame)]
+    }
+
+    /// Packetizes a single VP8 frame, splitting it across as many packets as needed
+    pub fn packetize_vp8(&mut self, time: Duration, frame: &[u8]) -> Vec<Bytes> {
+        let timestamp = self.timestamp(time);
+        let chunks = frame.chunks(MAX_PAYLOAD_SIZE - 1);
+        let chunk_count = chunks.len();
+
+        chunks
+            .enumerate()
+            .map(|(index, chunk)| {
+                // The payload descriptor's S bit marks the start of the frame's first partition
+                let descriptor = if index == 0 { 0x10 } else { 0x00 };
+                self.packet(timestamp, index == chunk_count - 1, &[descriptor], chunk)
+            })
+            .collect()
+    }
+
+    /// Packetizes a single Opus packet
+    pub fn packetize_opus(&mut self, time: Duration, packet: &[u8]) -> Vec<Bytes> {
+        let timestamp = self.timestamp(time);
+
+        vec![self.packet(timestamp, false, &[], packet)]
+    }
+
+    fn timestamp(&self, time: Duration) -> u32 {
+        (time.as_millis() as u64 * self.clock_rate as u64 / 1000) as u32
+    }
+
+    fn packet(&mut self, timestamp: u32, marker: bool, header: &[u8], payload: &[u8]) -> Bytes {
+        let mut packet = BytesMut::with_capacity(RTP_HEADER_SIZE + header.len() + payload.len());
+        packet.put_u8(RTP_VERSION << 6);
+        packet.put_u8(if marker { 0x80 } else { 0 } | self.payload_type);
+        packet.put_u16(self.sequence_number);
+        packet.put_u32(timestamp);
+        packet.put_u32(self.ssrc);
+        packet.put_slice(header);
+        packet.put_slice(payload);
+
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        packet.freeze()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_nal_units_sent_as_single_packets() {
+        let mut packetizer = RtpPacketizer::new(96, 90_000, 1234, 65535);
+        let nal_units = [
+            Bytes::from_static(&[0x67, 1, 2]),
+            Bytes::from_static(&[0x65, 3, 4]),
+        ];
+
+        let packets = packetizer.packetize_h264(Duration::from_millis(1000), &nal_units);
+
+        assert_eq!(packets.len(), 2, "Unexpected number of packets");
+        assert_eq!(
+            &packets[0][..],
+            &[0x80, 96, 0xff, 0xff, 0x00, 0x01, 0x5f, 0x90, 0, 0, 0x04, 0xd2, 0x67, 1, 2]
+        );
+        assert_eq!(
+            &packets[1][..],
+            &[
+                0x80,
+                0x80 | 96,
+                0x00,
+                0x00,
+                0x00,
+                0x01,
+                0x5f,
+                0x90,
+                0,
+                0,
+                0x04,
+                0xd2,
+                0x65,
+                3,
+                4
+            ],
+            "Expected marker on the last NAL unit and a wrapped sequence number"
+        );
+    }
+
+    #[test]
+    fn large_nal_units_split_into_fragments() {
+        let mut packetizer = RtpPacketizer::new(96, 90_000, 1, 0);
+        let mut nal_unit = vec![0x65];
+        nal_unit.extend(vec![7; MAX_PAYLOAD_SIZE * 2]);
+
+        let packets = packetizer.packetize_h264(Duration::ZERO, &[Bytes::from(nal_unit)]);
+
+        assert_eq!(packets.len(), 3, "Unexpected number of fragments");
+        assert_eq!(
+            packets[0][12],
+            0x60 | H264_FU_A_TYPE,
+            "Unexpected FU indicator"
+        );
+        assert_eq!(
+            packets[0][13],
+            0x80 | 0x05,
+            "Expected start bit on first fragment"
+        );
+        assert_eq!(packets[1][13], 0x05, "Expected no start or end bits");
+        assert_eq!(
+            packets[2][13],
+            0x40 | 0x05,
+            "Expected end bit on last fragment"
+        );
+        assert_eq!(
+            packets[2][1] & 0x80,
+            0x80,
+            "Expected marker on last fragment"
+        );
+
+        let payload_size: usize = packets.iter().map(|packet| packet.len() - 14).sum();
+        assert_eq!(
+            payload_size,
+            MAX_PAYLOAD_SIZE * 2,
+            "Unexpected payload size"
+        );
+    }
+
+    #[test]
+    fn aac_frame_prefixed_with_au_header() {
+        let mut packetizer = RtpPacketizer::new(97, 48_000, 1, 0);
+        let packets = packetizer.packetize_aac(Duration::from_millis(10), &[1, 2, 3]);
+
+        assert_eq!(packets.len(), 1, "Unexpected number of packets");
+        assert_eq!(
+            &packets[0][4..8],
+            &[0, 0, 0x01, 0xe0],
+            "Unexpected timestamp"
+        );
+        assert_eq!(&packets[0][12..], &[0x00, 0x10, 0x00, 0x18, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,362 @@
+//! Just enough SDP handling to read a WHEP subscriber's offer and produce the answer for it
+
+use crate::workflows::steps::whep_output::rtp::RtpCodec;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::{IpAddr, SocketAddr};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SdpError {
+    #[error("The offer has no media sections")]
+    NoMediaSections,
+
+    #[error("Invalid SDP line: '{0}'")]
+    InvalidLine(String),
+}
+
+/// A single media section (`m=` line and its attributes) from an offer
+#[derive(Debug, Default)]
+pub struct MediaOffer {
+    /// The type of media, such as `audio` or `video`
+    pub kind: String,
+    pub port: u16,
+    pub protocol: String,
+    pub formats: Vec<String>,
+    pub mid: Option<String>,
+
+    /// The connection address for the section, falling back to the session's connection address
+    pub address: Option<IpAddr>,
+
+    /// The first UDP candidate offered for the section, if any
+    pub candidate: Option<SocketAddr>,
+
+    rtpmaps: HashMap<u8, String>,
+    fmtps: HashMap<u8, String>,
+}
+
+impl MediaOffer {
+    /// The address RTP for this media section should be sent to. Candidates are preferred, as
+    /// WebRTC clients commonly put a placeholder in the section's connection address.
+    pub fn destination(&self) -> Option<SocketAddr> {
+        if let Some(candidate) = self.candidate {
+            return Some(candidate);
+        }
+
+        match self.address {
+            Some(address) if self.port != 0 && !address.is_unspecified() => {
+                Some(SocketAddr::new(address, self.port))
+            }
+
+            _ => None,
+        }
+    }
+
+    /// Finds the payload type the offer uses for the codec, if the codec was offered
+    pub fn find_payload_type(&self, codec: RtpCodec) -> Option<u8> {
+        self.formats
+            .iter()
+            .filter_map(|format| format.parse::<u8>().ok())
+            .find(|payload_type| {
+                let rtpmap = match self.rtpmaps.get(payload_type) {
+                    Some(rtpmap) => rtpmap,
+                    None => return false,
+                };
+
+                let mut parts = rtpmap.split('/');
+                let name = parts.next().unwrap_or_default();
+                let clock_rate = parts.next().and_then(|rate| rate.parse::<u32>().ok());
+                if !name.eq_ignore_ascii_case(codec.encoding_name())
+                    || clock_rate != Some(codec.clock_rate())
+                {
+                    return false;
+                }
+
+                // Only non-interleaved mode supports the fragmentation used for large NAL units
+                codec != RtpCodec::H264
+                    || self
+                        .fmtps
+                        .get(payload_type)
+                        .is_some_and(|fmtp| fmtp.contains("packetization-mode=1"))
+            })
+    }
+}
+
+/// Parses the media sections out of an SDP offer
+pub fn parse_offer(sdp: &str) -> Result<Vec<MediaOffer>, SdpError> {
+    let mut session_address = None;
+    let mut sections: Vec<MediaOffer> = Vec::new();
+    for line in sdp.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) if key.len() == 1 => (key, value),
+            _ => return Err(SdpError::InvalidLine(line.to_string())),
+        };
+
+        let invalid_line = || SdpError::InvalidLine(line.to_string());
+        match key {
+            "m" => {
+                let mut parts = value.split_whitespace();
+                let kind = parts.next().ok_or_else(invalid_line)?;
+                let port = parts.next().ok_or_else(invalid_line)?;
+                let protocol = parts.next().ok_or_else(invalid_line)?;
+
+                // Ports may have a `/<count>` suffix
+                let port = port.split('/').next().unwrap_or_default();
+                sections.push(MediaOffer {
+                    kind: kind.to_string(),
+                    port: port.parse().map_err(|_| invalid_line())?,
+                    protocol: protocol.to_string(),
+                    formats: parts.map(str::to_string).collect(),
+                    address: session_address,
+                    ..Default::default()
+                });
+            }
+
+            "c" => {
+                let address = value
+                    .split_whitespace()
+                    .nth(2)
+                    .and_then(|address| address.split('/').next())
+                    .and_then(|address| address.parse::<IpAddr>().ok())
+                    .ok_or_else(invalid_line)?;
+
+                match sections.last_mut() {
+                    Some(section) => section.address = Some(address),
+                    None => session_address = Some(address),
+                }
+            }
+
+            "a" => {
+                if let Some(section) = sections.last_mut() {
+                    read_attribute(section, value);
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    if sections.is_empty() {
+        return Err(SdpError::NoMediaSections);
+    }
+
+    Ok(sections)
+}
+
+fn read_attribute(section: &mut MediaOffer, attribute: &str) {
+    let (name, value) = attribute.split_once(':').unwrap_or((attribute, ""));
+    match name {
+        "mid" => section.mid = Some(value.to_string()),
+        "rtpmap" | "fmtp" => {
+            let (payload_type, value) = match value.split_once(' ') {
+                Some((payload_type, value)) => (payload_type, value),
+                None => return,
+            };
+
+            if let Ok(payload_type) = payload_type.parse::<u8>() {
+                let map = if name == "rtpmap" {
+                    &mut section.rtpmaps
+                } else {
+                    &mut section.fmtps
+                };
+
+                map.insert(payload_type, value.trim().to_string());
+            }
+        }
+
+        "candidate" if section.candidate.is_none() => {
+            // foundation component transport priority address port typ type ...
+            let parts = value.split_whitespace().collect::<Vec<_>>();
+            if parts.len() >= 6 && parts[2].eq_ignore_ascii_case("udp") {
+                let address = parts[4].parse::<IpAddr>();
+                let port = parts[5].parse::<u16>();
+                if let (Ok(address), Ok(port)) = (address, port) {
+                    section.candidate = Some(SocketAddr::new(address, port));
+                }
+            }
+        }
+
+        _ => (),
+    }
+}
+
+/// How a media section of the offer is answered
+pub enum MediaAnswer {
+    /// The media section is accepted, and RTP for it will be sent from the specified port
+    Accepted {
+        port: u16,
+        payload_type: u8,
+        codec: RtpCodec,
+        fmtp: Option<String>,
+        ssrc: u32,
+    },
+
+    Rejected,
+}
+
+/// Builds the answer to an offer, with an answer for each of the offer's media sections
+pub fn build_answer(
+    session_id: u64,
+    address: IpAddr,
+    offers: &[MediaOffer],
+    answers: &[MediaAnswer],
+) -> String {
+    let address_type = if address.is_ipv6() { "IP6" } else { "IP4" };
+    let mut sdp = String::new();
+
+    // Writing to a string can't fail
+    let _ = write!(
+        sdp,
+        "v=0\r\no=mmids {} 1 IN {} {}\r\ns=-\r\nt=0 0\r\n",
+        session_id, address_type, address
+    );
+
+    for (offer, answer) in offers.iter().zip(answers) {
+        match answer {
+            MediaAnswer::Accepted {
+                port,
+                payload_type,
+                codec,
+                fmtp,
+                ssrc,
+            } => {
+                let _ = write!(
+                    sdp,
+                    "m={} {} {} {}\r\nc=IN {} {}\r\n",
+                    offer.kind, port, offer.protocol, payload_type, address_type, address
+                );
+
+                if let Some(mid) = &offer.mid {
+                    let _ = write!(sdp, "a=mid:{}\r\n", mid);
+                }
+
+                let _ = write!(
+                    sdp,
+                    "a=sendonly\r\na=rtcp-mux\r\na=rtpmap:{} {}\r\n",
+                    payload_type,
+                    codec.rtpmap()
+                );
+
+                if let Some(fmtp) = fmtp {
+                    let _ = write!(sdp, "a=fmtp:{} {}\r\n", payload_type, fmtp);
+                }
+
+                let _ = write!(sdp, "a=ssrc:{} cname:mmids\r\n", ssrc);
+            }
+
+            MediaAnswer::Rejected => {
+                let format = offer.formats.first().map(String::as_str).unwrap_or("0");
+                let _ = write!(sdp, "m={} 0 {} {}\r\n", offer.kind, offer.protocol, format);
+
+                if let Some(mid) = &offer.mid {
+                    let _ = write!(sdp, "a=mid:{}\r\n", mid);
+                }
+
+                sdp.push_str("a=inactive\r\n");
+            }
+        }
+    }
+
+    sdp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OFFER: &str = "v=0\r\n\
+        o=- 1 2 IN IP4 127.0.0.1\r\n\
+        s=-\r\n\
+        c=IN IP4 0.0.0.0\r\n\
+        t=0 0\r\n\
+        m=audio 9 RTP/AVPF 111\r\n\
+        a=mid:0\r\n\
+        a=recvonly\r\n\
+        a=rtpmap:111 opus/48000/2\r\n\
+        a=candidate:1 1 udp 2130706431 192.168.1.5 50000 typ host\r\n\
+        m=video 50002 RTP/AVPF 96 98\r\n\
+        c=IN IP4 192.168.1.5\r\n\
+        a=mid:1\r\n\
+        a=rtpmap:96 VP8/90000\r\n\
+        a=rtpmap:98 H264/90000\r\n\
+        a=fmtp:98 level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f\r\n";
+
+    #[test]
+    fn media_sections_read_from_offer() {
+        let offers = parse_offer(OFFER).unwrap();
+
+        assert_eq!(offers.len(), 2, "Unexpected number of media sections");
+        assert_eq!(offers[0].kind, "audio");
+        assert_eq!(offers[0].mid.as_deref(), Some("0"));
+        assert_eq!(
+            offers[0].destination(),
+            Some("192.168.1.5:50000".parse().unwrap()),
+            "Expected the candidate to be used"
+        );
+
+        assert_eq!(offers[1].kind, "video");
+        assert_eq!(
+            offers[1].destination(),
+            Some("192.168.1.5:50002".parse().unwrap()),
+            "Expected the media section's connection address to be used"
+        );
+    }
+
+    #[test]
+    fn payload_types_found_for_offered_codecs() {
+        let offers = parse_offer(OFFER).unwrap();
+
+        assert_eq!(offers[0].find_payload_type(RtpCodec::Opus), Some(111));
+        assert_eq!(offers[1].find_payload_type(RtpCodec::Vp8), Some(96));
+        assert_eq!(offers[1].find_payload_type(RtpCodec::H264), Some(98));
+        assert_eq!(
+            offers[0].find_payload_type(RtpCodec::Aac {
+                sample_rate: 48000,
+                channels: 2
+            }),
+            None
+        );
+    }
+
+    #[test]
+    fn offer_without_media_sections_is_rejected() {
+        let result = parse_offer("v=0\r\ns=-\r\nt=0 0\r\n");
+
+        assert_eq!(result.err(), Some(SdpError::NoMediaSections));
+    }
+
+    #[test]
+    fn answer_contains_accepted_and_rejected_sections() {
+        let offers = parse_offer(OFFER).unwrap();
+        let answers = [
+            MediaAnswer::Rejected,
+            MediaAnswer::Accepted {
+                port: 40000,
+                payload_type: 98,
+                codec: RtpCodec::H264,
+                fmtp: Some("packetization-mode=1".to_string()),
+                ssrc: 5,
+            },
+        ];
+
+        let answer = build_answer(1, "10.0.0.1".parse().unwrap(), &offers, &answers);
+
+        assert!(
+            answer.contains("m=audio 0 RTP/AVPF 111\r\na=mid:0\r\na=inactive\r\n"),
+            "Expected the audio section to be rejected: {}",
+            answer
+        );
+        assert!(
+            answer.contains(
+                "m=video 40000 RTP/AVPF 98\r\nc=IN IP4 10.0.0.1\r\na=mid:1\r\na=sendonly\r\n"
+            ),
+            "Expected the video section to be accepted: {}",
+            answer
+        );
+        assert!(
+            answer.contains("a=rtpmap:98 H264/90000\r\na=fmtp:98 packetization-mode=1\r\n"),
+            "Expected the video format: {}",
+            answer
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! HTTP server handling WHEP signaling. Offers are posted to `<path>/<stream name>`, and a
+//! successful response contains the SDP answer along with the location of the subscriber's
+//! resource, which the subscriber deletes to end the session.
+
+use hyper::header::{CONTENT_TYPE, LOCATION};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+use tracing::{error, info};
+
+const SDP_CONTENT_TYPE: &str = "application/sdp";
+
+/// Requests from WHEP clients that the step needs to handle
+#[derive(Debug)]
+pub enum WhepRequest {
+    Subscribe {
+        stream_name: String,
+        offer: String,
+        response_channel: oneshot::Sender<SubscribeResponse>,
+    },
+
+    Unsubscribe {
+        stream_name: String,
+        subscriber_id: String,
+        response_channel: oneshot::Sender<bool>,
+    },
+}
+
+#[derive(Debug)]
+pub enum SubscribeResponse {
+    Accepted {
+        subscriber_id: String,
+        answer: String,
+    },
+
+    StreamNotFound,
+    TooManySubscribers,
+    InvalidOffer(String),
+    NoCompatibleMedia,
+}
+
+/// Serves WHEP requests, handing each one to the step over the request channel
+pub struct WhepServer {
+    local_address: SocketAddr,
+    shutdown_sender: Option<oneshot::Sender<()>>,
+}
+
+impl WhepServer {
+    /// Starts serving WHEP requests on the specified port, for paths under the path prefix
+    pub fn start(
+        port: u16,
+        path: Arc<String>,
+        request_sender: UnboundedSender<WhepRequest>,
+    ) -> Result<WhepServer, hyper::Error> {
+        let service = make_service_fn(move |_socket: &AddrStream| {
+            let path = path.clone();
+            let request_sender = request_sender.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |request: Request<Body>| {
+                    handle_request(request, path.clone(), request_sender.clone())
+                }))
+            }
+        });
+
+        let bind_address = SocketAddr::from(([0, 0, 0, 0], port));
+        let server = Server::try_bind(&bind_address)?.serve(service);
+        let local_address = server.local_addr();
+
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+        let server = server.with_graceful_shutdown(async {
+            let _ = shutdown_receiver.await;
+        });
+
+        info!("Serving WHEP requests on {}", local_address);
+        tokio::spawn(async move {
+            if let Err(error) = server.await {
+                error!("WHEP server stopped with an error: {:?}", error);
+            }
+        });
+
+        Ok(WhepServer {
+            local_address,
+            shutdown_sender: Some(shutdown_sender),
+        })
+    }
+
+    /// The address the server is listening on
+    pub fn local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+}
+
+impl Drop for WhepServer {
+    fn drop(&mut self) {
+        if let Some(sender) = self.shutdown_sender.take() {
+            let _ = sender.send(());
+        }
+    }
+}
+
+async fn handle_request(
+    request: Request<Body>,
+    path: Arc<String>,
+    request_sender: UnboundedSender<WhepRequest>,
+) -> Result<Response<Body>, hyper::Error> {
+    let resource = request
+        .uri()
+        .path()
+        .strip_prefix(path.as_str())
+        .and_then(|resource| resource.strip_prefix('/'))
+        .map(|resource| resource.split('/').map(str::to_string).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let response = match (request.method(), resource.as_slice()) {
+        (&Method::POST, [stream_name]) if !stream_name.is_empty() => {
+            let stream_name = stream_name.clone();
+            let body = hyper::body::to_bytes(request.into_body()).await?;
+            let offer = match String::from_utf8(body.to_vec()) {
+                Ok(offer) => offer,
+                Err(_) => return Ok(status(StatusCode::BAD_REQUEST)),
+            };
+
+            subscribe(stream_name, offer, path, request_sender).await
+        }
+
+        (&Method::DELETE, [stream_name, subscriber_id]) => {
+            let (sender, receiver) = oneshot::channel();
+            let request = WhepRequest::Unsubscribe {
+                stream_name: stream_name.clone(),
+                subscriber_id: subscriber_id.clone(),
+                response_channel: sender,
+            };
+
+            let _ = request_sender.send(request);
+            match receiver.await {
+                Ok(true) => status(StatusCode::OK),
+                Ok(false) => status(StatusCode::NOT_FOUND),
+                Err(_) => status(StatusCode::SERVICE_UNAVAILABLE),
+            }
+        }
+
+        (_, [_]) | (_, [_, _]) => status(StatusCode::METHOD_NOT_ALLOWED),
+        _ => status(StatusCode::NOT_FOUND),
+    };
+
+    Ok(response)
+}
+
+async fn subscribe(
+    stream_name: String,
+    offer: String,
+    path: Arc<String>,
+    request_sender: UnboundedSender<WhepRequest>,
+) -> Response<Body> {
+    let (sender, receiver) = oneshot::channel();
+    let request = WhepRequest::Subscribe {
+        stream_name: stream_name.clone(),
+        offer,
+        response_channel: sender,
+    };
+
+    let _ = request_sender.send(request);
+    let (subscriber_id, answer) = match receiver.await {
+        Ok(SubscribeResponse::Accepted {
+            subscriber_id,
+            answer,
+        }) => (subscriber_id, answer),
+
+        Ok(SubscribeResponse::StreamNotFound) => return status(StatusCode::NOT_FOUND),
+        Ok(SubscribeResponse::NoCompatibleMedia) => return status(StatusCode::NOT_ACCEPTABLE),
+        Ok(SubscribeResponse::InvalidOffer(reason)) => {
+            let mut response = Response::new(Body::from(reason));
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+
+            return response;
+        }
+
+        Ok(SubscribeResponse::TooManySubscribers) | Err(_) => {
+            return status(StatusCode::SERVICE_UNAVAILABLE)
+        }
+    };
+
+    let location = format!("{}/{}/{}", path, stream_name, subscriber_id);
+    let mut response = Response::new(Body::from(answer));
+    *response.status_mut() = StatusCode::CREATED;
+
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, SDP_CONTENT_TYPE.parse().unwrap());
+    if let Ok(location) = location.parse() {
+        headers.insert(LOCATION, location);
+    }
+
+    response
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+
+    response
+}
@@ -0,0 +1,398 @@
+use super::*;
+use crate::codecs::h264::test_sps;
+use crate::workflows::definitions::WorkflowStepType;
+use crate::workflows::metadata::common_metadata::{
+    get_is_keyframe_metadata_key, get_pts_offset_metadata_key,
+};
+use crate::workflows::metadata::{MetadataEntry, MetadataKeyMap};
+use crate::workflows::steps::test_utils::StepTestContext;
+use bytes::BytesMut;
+use hyper::header::{CONTENT_TYPE, LOCATION};
+use hyper::{Body, Client, Method, Request, Response, StatusCode};
+use std::iter;
+use std::net::TcpListener;
+use tokio::time::timeout;
+
+struct TestContext {
+    step_context: StepTestContext,
+    is_keyframe_metadata_key: MetadataKey,
+    port: u16,
+    stream_id: StreamId,
+}
+
+impl TestContext {
+    fn new(parameters: &[(&str, &str)]) -> Self {
+        let mut metadata_map = MetadataKeyMap::new();
+        let is_keyframe_metadata_key = get_is_keyframe_metadata_key(&mut metadata_map);
+        let pts_offset_metadata_key = get_pts_offset_metadata_key(&mut metadata_map);
+
+        let port = available_port();
+        let mut definition = create_definition(parameters);
+        definition
+            .parameters
+            .insert(PORT.to_string(), Some(port.to_string()));
+
+        let generator =
+            WhepOutputStepGenerator::new(is_keyframe_metadata_key, pts_offset_metadata_key);
+
+        let mut step_context =
+            StepTestContext::new(Box::new(generator), definition).expect("Failed to create step");
+
+        let stream_id = StreamId(Arc::new("abc".to_string()));
+        step_context.assert_media_passed_through(MediaNotification {
+            stream_id: stream_id.clone(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("def".to_string()),
+            },
+            annotations: Default::default(),
+        });
+
+        let pps = Bytes::from_static(&[0x68, 0xeb, 0xe3, 0xcb, 0x22, 0xc0]);
+        let record = AvcDecoderConfigurationRecord::from_parameter_sets(
+            vec![test_sps(1280, 720)],
+            vec![pps],
+            4,
+        )
+        .unwrap();
+
+        let mut context = TestContext {
+            step_context,
+            is_keyframe_metadata_key,
+            port,
+            stream_id,
+        };
+
+        let sequence_header = context.video(0, record.to_bytes(), false, true);
+        context
+            .step_context
+            .assert_media_passed_through(sequence_header);
+
+        context
+    }
+
+    fn video(
+        &self,
+        timestamp: u64,
+        data: Bytes,
+        is_keyframe: bool,
+        is_sequence_header: bool,
+    ) -> MediaNotification {
+        let mut buffer = BytesMut::new();
+        let entry = MetadataEntry::new(
+            self.is_keyframe_metadata_key,
+            MetadataValue::Bool(is_keyframe),
+            &mut buffer,
+        )
+        .unwrap();
+
+        MediaNotification {
+            stream_id: self.stream_id.clone(),
+            content: MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type: VIDEO_CODEC_H264_AVC.clone(),
+                timestamp: Duration::from_millis(timestamp),
+                metadata: MediaPayloadMetadataCollection::new(iter::once(entry), &mut buffer),
+                data,
+                is_required_for_decoding: is_sequence_header,
+            },
+            annotations: Default::default(),
+        }
+    }
+
+    /// Sends a length prefixed frame consisting of a single NAL unit through the step
+    fn send_frame(&mut self, timestamp: u64, is_keyframe: bool) {
+        let nal_type = if is_keyframe { 0x65 } else { 0x41 };
+        let data = Bytes::from(vec![0, 0, 0, 3, nal_type, 1, 2]);
+        let media = self.video(timestamp, data, is_keyframe, false);
+
+        self.step_context.assert_media_passed_through(media);
+    }
+
+    /// Sends the request to the step's WHEP server, executing the step until it's been answered
+    async fn request(&mut self, method: Method, path: &str, body: String) -> Response<Body> {
+        let request = Request::builder()
+            .method(method)
+            .uri(format!("http://127.0.0.1:{}{}", self.port, path))
+            .header(CONTENT_TYPE, "application/sdp")
+            .body(Body::from(body))
+            .unwrap();
+
+        let response = tokio::spawn(Client::new().request(request));
+        timeout(Duration::from_secs(5), async {
+            while !response.is_finished() {
+                self.step_context.execute_pending_futures().await;
+            }
+        })
+        .await
+        .expect("Timed out waiting for the WHEP response");
+
+        response
+            .await
+            .unwrap()
+            .expect("Failed to send WHEP request")
+    }
+
+    async fn subscribe(&mut self, client: &UdpSocket) -> Response<Body> {
+        let port = client.local_addr().unwrap().port();
+        self.request(Method::POST, "/whep/def", offer(port)).await
+    }
+
+    fn subscriber_count(&self) -> String {
+        self.step_context.step.get_state_details()[SUBSCRIBER_COUNT_DETAIL].clone()
+    }
+}
+
+fn create_definition(parameters: &[(&str, &str)]) -> WorkflowStepDefinition {
+    let mut definition = WorkflowStepDefinition {
+        step_type: WorkflowStepType("whep_output".to_string()),
+        parameters: HashMap::new(),
+    };
+
+    for (name, value) in parameters {
+        definition
+            .parameters
+            .insert(name.to_string(), Some(value.to_string()));
+    }
+
+    definition
+}
+
+fn available_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind to a port");
+    listener.local_addr().unwrap().port()
+}
+
+fn client_socket() -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind client socket");
+    socket
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+
+    socket
+}
+
+/// An offer for opus audio and H264 video, both received on the specified port
+fn offer(port: u16) -> String {
+    format!(
+        "v=0\r\n\
+        o=- 1 1 IN IP4 127.0.0.1\r\n\
+        s=-\r\n\
+        t=0 0\r\n\
+        m=audio {port} RTP/AVPF 111\r\n\
+        c=IN IP4 127.0.0.1\r\n\
+        a=mid:0\r\n\
+        a=recvonly\r\n\
+        a=rtpmap:111 opus/48000/2\r\n\
+        m=video {port} RTP/AVPF 102\r\n\
+        c=IN IP4 127.0.0.1\r\n\
+        a=mid:1\r\n\
+        a=recvonly\r\n\
+        a=rtpmap:102 H264/90000\r\n\
+        a=fmtp:102 packetization-mode=1;profile-level-id=42e01f\r\n",
+        port = port
+    )
+}
+
+fn receive_packet(client: &UdpSocket) -> Option<Vec<u8>> {
+    let mut buffer = [0_u8; 2048];
+    match client.recv(&mut buffer) {
+        Ok(size) => Some(buffer[..size].to_vec()),
+        Err(_) => None,
+    }
+}
+
+#[test]
+fn error_when_no_port_specified() {
+    let mut metadata_map = MetadataKeyMap::new();
+    let generator = WhepOutputStepGenerator::new(
+        get_is_keyframe_metadata_key(&mut metadata_map),
+        get_pts_offset_metadata_key(&mut metadata_map),
+    );
+
+    let result = StepTestContext::new(Box::new(generator), create_definition(&[]));
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[test]
+fn error_when_path_does_not_start_with_slash() {
+    let mut metadata_map = MetadataKeyMap::new();
+    let generator = WhepOutputStepGenerator::new(
+        get_is_keyframe_metadata_key(&mut metadata_map),
+        get_pts_offset_metadata_key(&mut metadata_map),
+    );
+
+    let definition = create_definition(&[(PORT, "0"), (PATH, "whep")]);
+    let result = StepTestContext::new(Box::new(generator), definition);
+
+    assert!(result.is_err(), "Expected an error");
+}
+
+#[tokio::test]
+async fn whep_session_negotiated_and_rtp_received() {
+    let mut context = TestContext::new(&[]);
+    let client = client_socket();
+
+    let response = context.subscribe(&client).await;
+    assert_eq!(response.status(), StatusCode::CREATED, "Unexpected status");
+    assert_eq!(
+        response.headers()[CONTENT_TYPE],
+        "application/sdp",
+        "Unexpected content type"
+    );
+
+    let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+    assert!(
+        location.starts_with("/whep/def/"),
+        "Unexpected location: {}",
+        location
+    );
+
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let answer = String::from_utf8(body.to_vec()).unwrap();
+    assert!(
+        answer.contains("m=audio 0 RTP/AVPF 111\r\n"),
+        "Expected audio to be rejected as the stream has none: {}",
+        answer
+    );
+    assert!(
+        answer.contains("a=rtpmap:102 H264/90000\r\n"),
+        "Expected H264 video to be accepted: {}",
+        answer
+    );
+
+    // Video is only sent once a keyframe arrives
+    context.send_frame(0, false);
+    assert_eq!(receive_packet(&client), None, "Expected no packets");
+
+    context.send_frame(33, true);
+    let packet = receive_packet(&client).expect("Expected an RTP packet");
+    assert_eq!(packet[0] >> 6, 2, "Unexpected RTP version");
+    assert_eq!(packet[1] & 0x7f, 102, "Unexpected payload type");
+    assert_eq!(packet[12] & 0x1f, 7, "Expected the SPS to be sent first");
+
+    let video_port = answer
+        .lines()
+        .find_map(|line| line.strip_prefix("m=video "))
+        .and_then(|line| line.split(' ').next())
+        .and_then(|port| port.parse::<u16>().ok())
+        .expect("No video port in the answer");
+
+    let mut buffer = [0_u8; 2048];
+    let (_, source) = client.recv_from(&mut buffer).expect("Expected the PPS");
+    assert_eq!(
+        source.port(),
+        video_port,
+        "Expected RTP from the answered port"
+    );
+}
+
+#[tokio::test]
+async fn offer_for_unknown_stream_returns_not_found() {
+    let mut context = TestContext::new(&[]);
+    let client = client_socket();
+    let port = client.local_addr().unwrap().port();
+
+    let response = context
+        .request(Method::POST, "/whep/unknown", offer(port))
+        .await;
+
+    assert_eq!(
+        response.status(),
+        StatusCode::NOT_FOUND,
+        "Unexpected status"
+    );
+}
+
+#[tokio::test]
+async fn offer_without_compatible_media_is_not_acceptable() {
+    let mut context = TestContext::new(&[]);
+    let offer = "v=0\r\ns=-\r\nt=0 0\r\nm=video 5000 RTP/AVPF 96\r\nc=IN IP4 127.0.0.1\r\n\
+        a=rtpmap:96 VP8/90000\r\n";
+
+    let response = context
+        .request(Method::POST, "/whep/def", offer.to_string())
+        .await;
+
+    assert_eq!(
+        response.status(),
+        StatusCode::NOT_ACCEPTABLE,
+        "Unexpected status"
+    );
+}
+
+#[tokio::test]
+async fn subscribers_beyond_max_are_rejected() {
+    let mut context = TestContext::new(&[(MAX_SUBSCRIBERS, "1")]);
+    let first = client_socket();
+    let second = client_socket();
+
+    let response = context.subscribe(&first).await;
+    assert_eq!(response.status(), StatusCode::CREATED, "Unexpected status");
+
+    let response = context.subscribe(&second).await;
+    assert_eq!(
+        response.status(),
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Unexpected status"
+    );
+}
+
+#[tokio::test]
+async fn deleting_session_stops_media_to_subscriber() {
+    let mut context = TestContext::new(&[]);
+    let client = client_socket();
+
+    let response = context.subscribe(&client).await;
+    let location = response.headers()[LOCATION].to_str().unwrap().to_string();
+    assert_eq!(
+        context.subscriber_count(),
+        "1",
+        "Unexpected subscriber count"
+    );
+
+    let response = context
+        .request(Method::DELETE, &location, String::new())
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK, "Unexpected status");
+    assert_eq!(
+        context.subscriber_count(),
+        "0",
+        "Unexpected subscriber count"
+    );
+
+    context.send_frame(0, true);
+    assert_eq!(receive_packet(&client), None, "Expected no packets");
+
+    let response = context
+        .request(Method::DELETE, &location, String::new())
+        .await;
+
+    assert_eq!(
+        response.status(),
+        StatusCode::NOT_FOUND,
+        "Expected deleted session to be gone"
+    );
+}
+
+#[tokio::test]
+async fn subscribers_removed_when_stream_disconnects() {
+    let mut context = TestContext::new(&[]);
+    let client = client_socket();
+    context.subscribe(&client).await;
+
+    context
+        .step_context
+        .assert_media_passed_through(MediaNotification {
+            stream_id: context.stream_id.clone(),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        });
+
+    assert_eq!(
+        context.subscriber_count(),
+        "0",
+        "Unexpected subscriber count"
+    );
+}
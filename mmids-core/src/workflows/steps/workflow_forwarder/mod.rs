@@ -1,6 +1,12 @@
 //! The workflow forwarder step takes all media notifications it receives and sends them to the
 //! specified workflow, using the workflow media relay. All media notifications are also passed
 //! to subsequent steps.
+//!
+//! When the `label` parameter is specified, only streams that have been labeled with that purpose
+//! (e.g. `primary` or `backup`) are forwarded.
+//!
+//! Stream tags are carried across to the target workflow. The `tags` parameter can be given a
+//! comma separated list of tag names to only forward those tags, otherwise all tags are forwarded.
 
 #[cfg(test)]
 mod tests;
@@ -14,6 +20,8 @@ use crate::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
 use crate::workflows::steps::{
     StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
 };
+use crate::workflows::stream_labels::{get_announced_label, StreamLabels};
+use crate::workflows::stream_tags::{filter_tags_notification, get_announced_tags};
 use crate::workflows::{
     MediaNotification, MediaNotificationContent, WorkflowRequest, WorkflowRequestOperation,
 };
@@ -27,6 +35,8 @@ use tracing::{error, info, span, Level};
 
 pub const TARGET_WORKFLOW: &str = "target_workflow";
 pub const REACTOR_NAME: &str = "reactor";
+pub const LABEL: &str = "label";
+pub const TAGS: &str = "tags";
 
 /// Generates a new workflow forwarder step
 pub struct WorkflowForwarderStepGenerator {
@@ -60,6 +70,15 @@ struct WorkflowForwarderStep {
     active_streams: HashMap<StreamId, StreamDetails>,
     stream_for_workflow_name: HashMap<Arc<String>, HashSet<StreamId>>,
     known_workflows: HashMap<Arc<String>, UnboundedSender<WorkflowRequest>>,
+    label: Option<Arc<String>>,
+    stream_labels: StreamLabels,
+
+    /// The names of the tags to forward, or `None` if all tags are forwarded
+    tags: Option<HashSet<String>>,
+
+    /// New stream notifications for streams that aren't forwarded yet, since they do not have
+    /// the label required for forwarding
+    unlabeled_streams: HashMap<StreamId, MediaNotification>,
 }
 
 enum FutureResult {
@@ -139,6 +158,22 @@ impl StepGenerator for WorkflowForwarderStepGenerator {
             ));
         }
 
+        let label = match definition.parameters.get(LABEL) {
+            Some(Some(label)) => Some(Arc::new(label.clone())),
+            _ => None,
+        };
+
+        let tags = match definition.parameters.get(TAGS) {
+            Some(Some(tags)) => Some(
+                tags.split(',')
+                    .map(|x| x.trim())
+                    .filter(|x| !x.is_empty())
+                    .map(|x| x.to_string())
+                    .collect(),
+            ),
+            _ => None,
+        };
+
         let (event_sender, event_receiver) = unbounded_channel();
         let _ = self
             .event_hub_subscriber
@@ -153,6 +188,10 @@ impl StepGenerator for WorkflowForwarderStepGenerator {
             active_streams: HashMap::new(),
             reactor_manager: self.reactor_manager.clone(),
             known_workflows: HashMap::new(),
+            label,
+            stream_labels: StreamLabels::new(),
+            tags,
+            unlabeled_streams: HashMap::new(),
         };
 
         notify_on_workflow_event(event_receiver, &futures_channel);
@@ -216,6 +255,42 @@ impl WorkflowForwarderStep {
                     );
                 }
             }
+
+            WorkflowStartedOrStoppedEvent::WorkflowRenamed {
+                old_name,
+                new_name,
+                channel,
+            } => {
+                // The workflow is still running, so streams being forwarded to it should keep
+                // flowing to it under its new name
+                self.known_workflows.remove(&old_name);
+                self.known_workflows.insert(new_name.clone(), channel);
+
+                if self.global_workflow_name.as_ref() == Some(&old_name) {
+                    self.global_workflow_name = Some(new_name.clone());
+                }
+
+                if let Some(stream_ids) = self.stream_for_workflow_name.remove(&old_name) {
+                    info!(
+                        workflow_name = %new_name,
+                        "Received notification that workflow {} was renamed to {}",
+                        old_name, new_name
+                    );
+
+                    for stream_id in &stream_ids {
+                        if let Some(stream) = self.active_streams.get_mut(stream_id) {
+                            if stream.target_workflow_names.remove(&old_name) {
+                                stream.target_workflow_names.insert(new_name.clone());
+                            }
+                        }
+                    }
+
+                    self.stream_for_workflow_name
+                        .entry(new_name)
+                        .or_default()
+                        .extend(stream_ids);
+                }
+            }
         }
     }
 
@@ -225,6 +300,99 @@ impl WorkflowForwarderStep {
         outputs: &mut StepOutputs,
         futures_channel: &WorkflowStepFuturesChannel,
     ) {
+        if self.label.is_some() {
+            self.handle_media_for_label(&media, futures_channel);
+        } else {
+            self.forward_media(&media, futures_channel);
+        }
+
+        outputs.media.push(media);
+    }
+
+    /// Only forwards streams with the step's label. Since a stream's label is announced after
+    /// the stream itself, new streams are held back until their label is known.
+    fn handle_media_for_label(
+        &mut self,
+        media: &MediaNotification,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let label = match &self.label {
+            Some(label) => label.clone(),
+            None => return,
+        };
+
+        self.stream_labels.handle_media(media);
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { .. } => {
+                if !self.active_streams.contains_key(&media.stream_id) {
+                    self.unlabeled_streams
+                        .insert(media.stream_id.clone(), media.clone());
+                }
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.unlabeled_streams.remove(&media.stream_id);
+                self.forward_media(media, futures_channel);
+            }
+
+            content if get_announced_label(content).is_some() => {
+                if self.stream_labels.has_label(&media.stream_id, &label) {
+                    if let Some(new_stream) = self.unlabeled_streams.remove(&media.stream_id) {
+                        info!(
+                            stream_id = ?media.stream_id,
+                            "Stream {:?} has the label {}, so it will be forwarded",
+                            media.stream_id, label
+                        );
+
+                        self.forward_media(&new_stream, futures_channel);
+                    }
+
+                    self.forward_media(media, futures_channel);
+                } else if let Some(stream) = self.active_streams.get(&media.stream_id) {
+                    // The stream was relabeled, so stop forwarding it until it gets our label back
+                    info!(
+                        stream_id = ?media.stream_id,
+                        "Stream {:?} no longer has the label {}, so it will no longer be forwarded",
+                        media.stream_id, label
+                    );
+
+                    if let Some(new_stream) = stream.required_media.first().cloned() {
+                        self.unlabeled_streams
+                            .insert(media.stream_id.clone(), new_stream);
+                    }
+
+                    let disconnection = MediaNotification {
+                        stream_id: media.stream_id.clone(),
+                        content: MediaNotificationContent::StreamDisconnected,
+                        annotations: Default::default(),
+                    };
+
+                    self.forward_media(&disconnection, futures_channel);
+                }
+            }
+
+            _ => self.forward_media(media, futures_channel),
+        }
+    }
+
+    fn forward_media(
+        &mut self,
+        media: &MediaNotification,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        // Tag announcements only carry the tags that are allowed to be forwarded
+        let filtered_tags;
+        let media = if get_announced_tags(&media.content).is_some() {
+            filtered_tags = match filter_tags_notification(media, self.tags.as_ref()) {
+                Some(notification) => notification,
+                None => return,
+            };
+
+            &filtered_tags
+        } else {
+            media
+        };
+
         match &media.content {
             MediaNotificationContent::NewIncomingStream { stream_name } => {
                 if !self.active_streams.contains_key(&media.stream_id) {
@@ -304,9 +472,32 @@ impl WorkflowForwarderStep {
                 }
             }
 
-            MediaNotificationContent::Metadata { .. } => {
-                // I don't think this can be considered required, as I think closed captions and
-                // other data will come down as metadata that we don't want to permanently store.
+            // Other metadata can't be considered required, as I think closed captions and other
+            // data will come down as metadata that we don't want to permanently store. Label and
+            // tag announcements are the exception, so workflows started later know what purpose
+            // the stream serves and where it came from.
+            MediaNotificationContent::Metadata { .. }
+                if get_announced_label(&media.content).is_some() =>
+            {
+                if let Some(stream) = self.active_streams.get_mut(&media.stream_id) {
+                    stream
+                        .required_media
+                        .retain(|x| get_announced_label(&x.content).is_none());
+
+                    stream.required_media.push(media.clone());
+                }
+            }
+
+            MediaNotificationContent::Metadata { .. }
+                if get_announced_tags(&media.content).is_some() =>
+            {
+                if let Some(stream) = self.active_streams.get_mut(&media.stream_id) {
+                    stream
+                        .required_media
+                        .retain(|x| get_announced_tags(&x.content).is_none());
+
+                    stream.required_media.push(media.clone());
+                }
             }
 
             MediaNotificationContent::MediaPayload {
@@ -333,8 +524,6 @@ impl WorkflowForwarderStep {
                 }
             }
         }
-
-        outputs.media.push(media);
     }
 
     fn handle_reactor_update(&mut self, stream_id: StreamId, update: ReactorWorkflowUpdate) {
@@ -375,6 +564,7 @@ impl WorkflowForwarderStep {
                                 media: MediaNotification {
                                     stream_id: stream_id.clone(),
                                     content: MediaNotificationContent::StreamDisconnected,
+                                    annotations: Default::default(),
                                 },
                             },
                         });
@@ -423,6 +613,7 @@ impl WorkflowForwarderStep {
                                 media: MediaNotification {
                                     stream_id: stream_id.clone(),
                                     content: MediaNotificationContent::StreamDisconnected,
+                                    annotations: Default::default(),
                                 },
                             },
                         });
@@ -540,6 +731,7 @@ impl WorkflowStep for WorkflowForwarderStep {
                                         media: MediaNotification {
                                             stream_id: stream_id.clone(),
                                             content: MediaNotificationContent::StreamDisconnected,
+                                            annotations: Default::default(),
                                         },
                                     },
                                 });
@@ -586,6 +778,7 @@ impl Drop for WorkflowForwarderStep {
                             media: MediaNotification {
                                 stream_id: stream_id.clone(),
                                 content: MediaNotificationContent::StreamDisconnected,
+                                annotations: Default::default(),
                             },
                         },
                     });
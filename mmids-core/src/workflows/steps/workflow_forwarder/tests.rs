@@ -4,6 +4,8 @@ use crate::workflows::definitions::WorkflowStepType;
 use crate::workflows::metadata::MediaPayloadMetadataCollection;
 use crate::workflows::steps::futures_channel::FuturesChannelInnerResult;
 use crate::workflows::steps::test_utils::StepTestContext;
+use crate::workflows::stream_labels::label_notification;
+use crate::workflows::stream_tags::{tags_notification, Tags};
 use crate::workflows::MediaType;
 use anyhow::{anyhow, Result};
 use bytes::{Bytes, BytesMut};
@@ -22,6 +24,23 @@ struct TestContext {
 
 impl TestContext {
     async fn new(specific_workflow: Option<&str>, reactor: Option<&str>) -> Result<Self> {
+        TestContext::new_with_label(specific_workflow, reactor, None).await
+    }
+
+    async fn new_with_label(
+        specific_workflow: Option<&str>,
+        reactor: Option<&str>,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        TestContext::new_with_options(specific_workflow, reactor, label, None).await
+    }
+
+    async fn new_with_options(
+        specific_workflow: Option<&str>,
+        reactor: Option<&str>,
+        label: Option<&str>,
+        tags: Option<&str>,
+    ) -> Result<Self> {
         if specific_workflow.is_some() && reactor.is_some() {
             return Err(anyhow!(
                 "Both workflow and reactor names specified. Only one should be"
@@ -56,6 +75,18 @@ impl TestContext {
                 .insert(TARGET_WORKFLOW.to_string(), Some(workflow.to_string()));
         }
 
+        if let Some(label) = label {
+            definition
+                .parameters
+                .insert(LABEL.to_string(), Some(label.to_string()));
+        }
+
+        if let Some(tags) = tags {
+            definition
+                .parameters
+                .insert(TAGS.to_string(), Some(tags.to_string()));
+        }
+
         let step_context = StepTestContext::new(Box::new(generator), definition)?;
 
         // It must send a subscription event on startup
@@ -133,6 +164,7 @@ async fn new_stream_message_sent_to_global_workflow() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let response = test_utils::expect_mpsc_response(&mut context.workflow_receiver).await;
@@ -160,6 +192,7 @@ async fn new_stream_message_sent_if_workflow_started_after_message_comes_in() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     test_utils::expect_mpsc_timeout(&mut context.workflow_receiver).await;
@@ -192,6 +225,7 @@ async fn no_message_passed_if_workflow_has_different_name_than_global_name() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     test_utils::expect_mpsc_timeout(&mut context.workflow_receiver).await;
@@ -208,6 +242,7 @@ async fn no_message_passed_if_workflow_stopped_before_media_sent() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     test_utils::expect_mpsc_timeout(&mut context.workflow_receiver).await;
@@ -222,11 +257,13 @@ async fn no_message_passed_if_stream_disconnected_before_workflow_started() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     context.step_context.execute_with_media(MediaNotification {
         stream_id: StreamId(Arc::new("abc".to_string())),
         content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
     });
 
     context.send_workflow_started_event("test", None).await;
@@ -242,6 +279,7 @@ async fn new_stream_media_passed_as_output_immediately() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     assert_eq!(
@@ -269,6 +307,7 @@ async fn stream_disconnected_media_passed_as_output_immediately() {
     context.step_context.execute_with_media(MediaNotification {
         stream_id: StreamId(Arc::new("abc".to_string())),
         content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
     });
 
     assert_eq!(
@@ -303,6 +342,7 @@ async fn media_passed_as_output_immediately() {
     context.step_context.execute_with_media(MediaNotification {
         stream_id: StreamId(Arc::new("abc".to_string())),
         content: expected_content.clone(),
+        annotations: Default::default(),
     });
 
     assert_eq!(
@@ -328,6 +368,7 @@ async fn metadata_media_passed_as_output_immediately() {
         content: MediaNotificationContent::Metadata {
             data: metadata.clone(),
         },
+        annotations: Default::default(),
     });
 
     assert_eq!(
@@ -356,6 +397,7 @@ async fn required_media_payload_sent_to_workflow_when_received_before_workflow_s
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let expected_content = MediaNotificationContent::MediaPayload {
@@ -370,6 +412,7 @@ async fn required_media_payload_sent_to_workflow_when_received_before_workflow_s
     context.step_context.execute_with_media(MediaNotification {
         stream_id: StreamId(Arc::new("abc".to_string())),
         content: expected_content.clone(),
+        annotations: Default::default(),
     });
 
     test_utils::expect_mpsc_timeout(&mut context.workflow_receiver).await;
@@ -403,6 +446,7 @@ async fn non_required_payload_not_sent_to_workflow_when_received_before_workflow
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let expected_content = MediaNotificationContent::MediaPayload {
@@ -417,6 +461,7 @@ async fn non_required_payload_not_sent_to_workflow_when_received_before_workflow
     context.step_context.execute_with_media(MediaNotification {
         stream_id: StreamId(Arc::new("abc".to_string())),
         content: expected_content.clone(),
+        annotations: Default::default(),
     });
 
     test_utils::expect_mpsc_timeout(&mut context.workflow_receiver).await;
@@ -443,6 +488,7 @@ async fn metadata_not_sent_when_received_before_workflow_starts() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     context.step_context.execute_with_media(MediaNotification {
@@ -450,6 +496,7 @@ async fn metadata_not_sent_when_received_before_workflow_starts() {
         content: MediaNotificationContent::Metadata {
             data: HashMap::new(),
         },
+        annotations: Default::default(),
     });
 
     test_utils::expect_mpsc_timeout(&mut context.workflow_receiver).await;
@@ -476,6 +523,7 @@ async fn new_stream_triggers_reactor_query() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let response = test_utils::expect_mpsc_response(&mut context.reactor_manager).await;
@@ -501,6 +549,7 @@ async fn new_stream_passed_to_all_specified_routable_workflow() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let response = test_utils::expect_mpsc_response(&mut context.reactor_manager).await;
@@ -566,3 +615,280 @@ async fn new_stream_passed_to_all_specified_routable_workflow() {
         operation => panic!("Unexpected operation: {:?}", operation),
     }
 }
+
+fn new_stream(stream_id: &str) -> MediaNotification {
+    MediaNotification {
+        stream_id: StreamId(Arc::new(stream_id.to_string())),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("name".to_string()),
+        },
+        annotations: Default::default(),
+    }
+}
+
+fn label(stream_id: &str, label: &str) -> MediaNotification {
+    label_notification(
+        StreamId(Arc::new(stream_id.to_string())),
+        Arc::new(label.to_string()),
+    )
+}
+
+fn tags(stream_id: &str, entries: &[(&str, &str)]) -> MediaNotification {
+    let tags = entries
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect::<Tags>();
+
+    tags_notification(StreamId(Arc::new(stream_id.to_string())), &tags)
+}
+
+fn video(stream_id: &str) -> MediaNotification {
+    MediaNotification {
+        stream_id: StreamId(Arc::new(stream_id.to_string())),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Video,
+            payload_type: Arc::new("test".to_string()),
+            timestamp: Duration::from_millis(0),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data: Bytes::from(vec![1, 2, 3]),
+            is_required_for_decoding: false,
+        },
+        annotations: Default::default(),
+    }
+}
+
+async fn expect_forwarded_media(context: &mut TestContext) -> MediaNotification {
+    let response = test_utils::expect_mpsc_response(&mut context.workflow_receiver).await;
+    match response.operation {
+        WorkflowRequestOperation::MediaNotification { media } => media,
+        operation => panic!("Unexpected workflow operation: {:?}", operation),
+    }
+}
+
+#[tokio::test]
+async fn only_streams_with_matching_label_forwarded() {
+    let mut context = TestContext::new_with_label(Some("test"), None, Some("primary"))
+        .await
+        .unwrap();
+
+    context.send_workflow_started_event("test", None).await;
+
+    context
+        .step_context
+        .execute_with_media(new_stream("backup"));
+    context
+        .step_context
+        .execute_with_media(new_stream("primary"));
+    test_utils::expect_mpsc_timeout(&mut context.workflow_receiver).await;
+
+    context
+        .step_context
+        .execute_with_media(label("backup", "backup"));
+
+    test_utils::expect_mpsc_timeout(&mut context.workflow_receiver).await;
+
+    context
+        .step_context
+        .execute_with_media(label("primary", "primary"));
+
+    let media = expect_forwarded_media(&mut context).await;
+    assert_eq!(media, new_stream("primary"), "Expected new stream first");
+
+    let media = expect_forwarded_media(&mut context).await;
+    assert_eq!(media, label("primary", "primary"), "Expected label second");
+
+    context.step_context.execute_with_media(video("backup"));
+    test_utils::expect_mpsc_timeout(&mut context.workflow_receiver).await;
+
+    context.step_context.execute_with_media(video("primary"));
+    let media = expect_forwarded_media(&mut context).await;
+    assert_eq!(media, video("primary"), "Expected primary video forwarded");
+}
+
+#[tokio::test]
+async fn all_media_passed_to_next_step_regardless_of_label() {
+    let mut context = TestContext::new_with_label(Some("test"), None, Some("primary"))
+        .await
+        .unwrap();
+
+    context.send_workflow_started_event("test", None).await;
+    context
+        .step_context
+        .assert_media_passed_through(new_stream("backup"));
+
+    context
+        .step_context
+        .assert_media_passed_through(label("backup", "backup"));
+
+    context
+        .step_context
+        .assert_media_passed_through(video("backup"));
+}
+
+#[tokio::test]
+async fn relabeled_stream_disconnected_from_target_workflow() {
+    let mut context = TestContext::new_with_label(Some("test"), None, Some("primary"))
+        .await
+        .unwrap();
+
+    context.send_workflow_started_event("test", None).await;
+    context.step_context.execute_with_media(new_stream("abc"));
+    context
+        .step_context
+        .execute_with_media(label("abc", "primary"));
+
+    let _ = expect_forwarded_media(&mut context).await;
+    let _ = expect_forwarded_media(&mut context).await;
+
+    context
+        .step_context
+        .execute_with_media(label("abc", "backup"));
+    let media = expect_forwarded_media(&mut context).await;
+    assert_eq!(
+        media.content,
+        MediaNotificationContent::StreamDisconnected,
+        "Expected stream to be disconnected"
+    );
+
+    context.step_context.execute_with_media(video("abc"));
+    test_utils::expect_mpsc_timeout(&mut context.workflow_receiver).await;
+
+    // Getting the label back resumes forwarding
+    context
+        .step_context
+        .execute_with_media(label("abc", "primary"));
+
+    let media = expect_forwarded_media(&mut context).await;
+    assert_eq!(
+        media,
+        new_stream("abc"),
+        "Expected new stream to be re-sent"
+    );
+}
+
+#[tokio::test]
+async fn renamed_target_workflow_keeps_receiving_media() {
+    let mut context = TestContext::new(Some("test"), None).await.unwrap();
+    context.send_workflow_started_event("test", None).await;
+
+    context
+        .workflow_event_channel
+        .send(WorkflowStartedOrStoppedEvent::WorkflowRenamed {
+            old_name: Arc::new("test".to_string()),
+            new_name: Arc::new("renamed".to_string()),
+            channel: context.workflow_sender.clone(),
+        })
+        .expect("Failed to send workflow renamed event");
+
+    match context.step_context.expect_future_resolved().await {
+        FuturesChannelInnerResult::Generic(result) => {
+            context.step_context.execute_notification(result).await;
+        }
+
+        FuturesChannelInnerResult::Media(_) => {
+            panic!("Expected a generic step future result but instead got media packet");
+        }
+    }
+
+    // A new workflow taking over the old name should not receive the stream
+    let (other_sender, mut other_receiver) = unbounded_channel();
+    context
+        .send_workflow_started_event("test", Some(other_sender))
+        .await;
+
+    context.step_context.execute_with_media(MediaNotification {
+        stream_id: StreamId(Arc::new("abc".to_string())),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("def".to_string()),
+        },
+        annotations: Default::default(),
+    });
+
+    let response = test_utils::expect_mpsc_response(&mut context.workflow_receiver).await;
+    match response.operation {
+        WorkflowRequestOperation::MediaNotification { media } => {
+            assert_eq!(media.stream_id.0.as_str(), "abc", "Unexpected stream id");
+        }
+
+        operation => panic!("Unexpected workflow operation: {:?}", operation),
+    }
+
+    test_utils::expect_mpsc_timeout(&mut other_receiver).await;
+}
+
+#[tokio::test]
+async fn tagged_stream_forwarded_with_its_tags() {
+    let mut context = TestContext::new(Some("test"), None).await.unwrap();
+    context.send_workflow_started_event("test", None).await;
+
+    context.step_context.execute_with_media(new_stream("abc"));
+    context
+        .step_context
+        .execute_with_media(tags("abc", &[("region", "eu"), ("venue", "main")]));
+
+    let media = expect_forwarded_media(&mut context).await;
+    assert_eq!(media, new_stream("abc"), "Expected new stream first");
+
+    let media = expect_forwarded_media(&mut context).await;
+    assert_eq!(
+        media,
+        tags("abc", &[("region", "eu"), ("venue", "main")]),
+        "Expected tags to arrive in target workflow"
+    );
+}
+
+#[tokio::test]
+async fn only_specified_tags_forwarded() {
+    let mut context = TestContext::new_with_options(Some("test"), None, None, Some("region"))
+        .await
+        .unwrap();
+
+    context.send_workflow_started_event("test", None).await;
+    context.step_context.execute_with_media(new_stream("abc"));
+    let _ = expect_forwarded_media(&mut context).await;
+
+    context
+        .step_context
+        .execute_with_media(tags("abc", &[("region", "eu"), ("venue", "main")]));
+
+    let media = expect_forwarded_media(&mut context).await;
+    assert_eq!(
+        media,
+        tags("abc", &[("region", "eu")]),
+        "Expected only the region tag to be forwarded"
+    );
+
+    // Announcements without any forwarded tags aren't sent at all
+    context
+        .step_context
+        .execute_with_media(tags("abc", &[("venue", "main")]));
+
+    test_utils::expect_mpsc_timeout(&mut context.workflow_receiver).await;
+}
+
+#[tokio::test]
+async fn tags_sent_to_workflow_started_after_they_were_announced() {
+    let mut context = TestContext::new(Some("test"), None).await.unwrap();
+
+    context.step_context.execute_with_media(new_stream("abc"));
+    context
+        .step_context
+        .execute_with_media(tags("abc", &[("region", "us")]));
+    context
+        .step_context
+        .execute_with_media(tags("abc", &[("region", "eu")]));
+
+    context.send_workflow_started_event("test", None).await;
+
+    let media = expect_forwarded_media(&mut context).await;
+    assert_eq!(media, new_stream("abc"), "Expected new stream first");
+
+    let media = expect_forwarded_media(&mut context).await;
+    assert_eq!(
+        media,
+        tags("abc", &[("region", "eu")]),
+        "Expected latest tags to be sent"
+    );
+
+    test_utils::expect_mpsc_timeout(&mut context.workflow_receiver).await;
+}
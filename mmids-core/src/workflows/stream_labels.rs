@@ -0,0 +1,158 @@
+//! Streams can be labeled with their purpose (e.g. `primary`, `backup` or `preview`), which allows
+//! steps to route streams based on what they are used for instead of their name. This enables
+//! workflows to handle primary and backup feeds of the same content differently.
+//!
+//! A stream's label is announced through a metadata notification containing only the
+//! `STREAM_LABEL_METADATA_KEY` key, which is raised right after the stream's `NewIncomingStream`
+//! notification by the step assigning the label. A stream has no label until one is announced,
+//! and a later label announcement replaces the stream's previous label.
+
+use crate::workflows::{MediaNotification, MediaNotificationContent};
+use crate::StreamId;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The metadata key a stream's label is announced with
+pub const STREAM_LABEL_METADATA_KEY: &str = "mmids_stream_label";
+
+/// Creates the notification announcing the label of a stream
+pub fn label_notification(stream_id: StreamId, label: Arc<String>) -> MediaNotification {
+    let mut data = HashMap::new();
+    data.insert(STREAM_LABEL_METADATA_KEY.to_string(), label.to_string());
+
+    MediaNotification {
+        stream_id,
+        content: MediaNotificationContent::Metadata { data },
+        annotations: Default::default(),
+    }
+}
+
+/// Returns the label announced by the notification content, if it's a label announcement
+pub fn get_announced_label(content: &MediaNotificationContent) -> Option<&str> {
+    match content {
+        MediaNotificationContent::Metadata { data } if data.len() == 1 => data
+            .get(STREAM_LABEL_METADATA_KEY)
+            .map(|label| label.as_str()),
+
+        _ => None,
+    }
+}
+
+/// Tracks the labels of streams based on the media notifications that flow through a step
+#[derive(Default)]
+pub struct StreamLabels {
+    labels: HashMap<StreamId, Arc<String>>,
+}
+
+impl StreamLabels {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Updates the tracked labels based on the media notification
+    pub fn handle_media(&mut self, media: &MediaNotification) {
+        match &media.content {
+            // A new stream starts out unlabeled, even if a previous stream with the same id had
+            // a label
+            MediaNotificationContent::NewIncomingStream { .. }
+            | MediaNotificationContent::StreamDisconnected => {
+                self.labels.remove(&media.stream_id);
+            }
+
+            content => {
+                if let Some(label) = get_announced_label(content) {
+                    self.labels
+                        .insert(media.stream_id.clone(), Arc::new(label.to_string()));
+                }
+            }
+        }
+    }
+
+    /// Returns the label of the stream, if it has one
+    pub fn label_of(&self, stream_id: &StreamId) -> Option<&Arc<String>> {
+        self.labels.get(stream_id)
+    }
+
+    /// Returns true if the stream has been labeled with the specified label
+    pub fn has_label(&self, stream_id: &StreamId, label: &str) -> bool {
+        self.labels
+            .get(stream_id)
+            .is_some_and(|stream_label| stream_label.as_str() == label)
+    }
+
+    /// Returns the ids of all streams with the specified label
+    pub fn streams_with_label(&self, label: &str) -> Vec<StreamId> {
+        self.labels
+            .iter()
+            .filter(|(_, stream_label)| stream_label.as_str() == label)
+            .map(|(stream_id, _)| stream_id.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_stream(stream_id: &StreamId) -> MediaNotification {
+        MediaNotification {
+            stream_id: stream_id.clone(),
+            content: MediaNotificationContent::NewIncomingStream {
+                stream_name: Arc::new("name".to_string()),
+            },
+            annotations: Default::default(),
+        }
+    }
+
+    #[test]
+    fn labels_tracked_from_announcements() {
+        let primary = StreamId(Arc::new("a".to_string()));
+        let backup = StreamId(Arc::new("b".to_string()));
+        let mut labels = StreamLabels::new();
+
+        labels.handle_media(&new_stream(&primary));
+        labels.handle_media(&new_stream(&backup));
+        assert_eq!(labels.label_of(&primary), None, "Expected no label yet");
+
+        labels.handle_media(&label_notification(
+            primary.clone(),
+            Arc::new("primary".to_string()),
+        ));
+
+        labels.handle_media(&label_notification(
+            backup.clone(),
+            Arc::new("backup".to_string()),
+        ));
+
+        assert!(
+            labels.has_label(&primary, "primary"),
+            "Expected primary label"
+        );
+        assert!(
+            !labels.has_label(&primary, "backup"),
+            "Unexpected backup label"
+        );
+        assert_eq!(labels.streams_with_label("backup"), vec![backup.clone()]);
+
+        labels.handle_media(&MediaNotification {
+            stream_id: backup.clone(),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        });
+
+        assert!(
+            labels.streams_with_label("backup").is_empty(),
+            "Expected disconnected stream to lose its label"
+        );
+    }
+
+    #[test]
+    fn other_metadata_is_not_a_label_announcement() {
+        let mut data = HashMap::new();
+        data.insert(STREAM_LABEL_METADATA_KEY.to_string(), "primary".to_string());
+        data.insert("width".to_string(), "1920".to_string());
+
+        let content = MediaNotificationContent::Metadata { data };
+        assert_eq!(get_announced_label(&content), None);
+    }
+}
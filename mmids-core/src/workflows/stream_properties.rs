@@ -0,0 +1,357 @@
+//! Encoders describe the streams they publish with loosely typed metadata (e.g. RTMP's
+//! `onMetaData` message), which reaches workflow steps as `Metadata` notifications. This module
+//! reads the common keys of that metadata into typed `StreamProperties`, so the encoder reported
+//! properties of a stream can be used for routing and display without every consumer re-parsing
+//! strings.
+//!
+//! Encoders are inconsistent in how they format values (e.g. frame rates of `30` or `29.97`, and
+//! bitrates with fractional parts), so numbers are read leniently. Values that can't be read are
+//! left unset and their keys recorded as malformed, rather than failing the whole notification.
+
+use crate::codecs::{FrameRate, VideoResolution};
+use std::collections::HashMap;
+use std::fmt;
+
+pub const WIDTH_KEY: &str = "width";
+pub const HEIGHT_KEY: &str = "height";
+pub const FRAME_RATE_KEY: &str = "framerate";
+pub const VIDEO_CODEC_KEY: &str = "videocodecid";
+pub const VIDEO_BITRATE_KEY: &str = "videodatarate";
+pub const AUDIO_CODEC_KEY: &str = "audiocodecid";
+pub const AUDIO_BITRATE_KEY: &str = "audiodatarate";
+pub const AUDIO_SAMPLE_RATE_KEY: &str = "audiosamplerate";
+pub const AUDIO_CHANNELS_KEY: &str = "audiochannels";
+pub const STEREO_KEY: &str = "stereo";
+pub const ENCODER_KEY: &str = "encoder";
+
+/// Frame rates are read as decimals, and kept to this many fractions of a frame
+const FRAME_RATE_PRECISION: u32 = 1000;
+
+/// The properties of a stream, as reported by its encoder. Properties the encoder did not report
+/// (or reported in a form that couldn't be read) are `None`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StreamProperties {
+    pub resolution: Option<VideoResolution>,
+    pub frame_rate: Option<FrameRate>,
+    pub video_codec: Option<String>,
+    pub video_bitrate_kbps: Option<u32>,
+    pub audio_codec: Option<String>,
+    pub audio_bitrate_kbps: Option<u32>,
+    pub audio_sample_rate: Option<u32>,
+    pub audio_channels: Option<u32>,
+    pub stereo: Option<bool>,
+    pub encoder: Option<String>,
+
+    /// Keys of known properties whose values could not be read, sorted by name
+    pub malformed_keys: Vec<String>,
+}
+
+impl StreamProperties {
+    /// Reads the stream properties contained in a metadata notification's data. Keys are matched
+    /// case insensitively, and keys that aren't known properties are ignored.
+    pub fn from_metadata(data: &HashMap<String, String>) -> Self {
+        let mut properties = StreamProperties::default();
+        properties.apply_metadata(data);
+        properties
+    }
+
+    /// Updates the properties with the values contained in a metadata notification's data.
+    /// Properties that the data doesn't contain keep their current value, as metadata may only
+    /// contain the properties that changed.
+    pub fn apply_metadata(&mut self, data: &HashMap<String, String>) {
+        let values = data
+            .iter()
+            .map(|(key, value)| (key.to_lowercase(), value.trim()))
+            .collect::<HashMap<_, _>>();
+
+        let width = self.read(&values, WIDTH_KEY, parse_whole_number);
+        let height = self.read(&values, HEIGHT_KEY, parse_whole_number);
+        match (width, height) {
+            (Some(width), Some(height)) => {
+                self.resolution = Some(VideoResolution { width, height });
+            }
+
+            // Updating only one dimension would pair it with a stale value of the other
+            (Some(width), None) => {
+                if let Some(resolution) = &mut self.resolution {
+                    resolution.width = width;
+                }
+            }
+
+            (None, Some(height)) => {
+                if let Some(resolution) = &mut self.resolution {
+                    resolution.height = height;
+                }
+            }
+
+            (None, None) => (),
+        }
+
+        if let Some(frame_rate) = self.read(&values, FRAME_RATE_KEY, parse_frame_rate) {
+            self.frame_rate = Some(frame_rate);
+        }
+
+        if let Some(codec) = self.read(&values, VIDEO_CODEC_KEY, parse_text) {
+            self.video_codec = Some(codec);
+        }
+
+        if let Some(bitrate) = self.read(&values, VIDEO_BITRATE_KEY, parse_whole_number) {
+            self.video_bitrate_kbps = Some(bitrate);
+        }
+
+        if let Some(codec) = self.read(&values, AUDIO_CODEC_KEY, parse_text) {
+            self.audio_codec = Some(codec);
+        }
+
+        if let Some(bitrate) = self.read(&values, AUDIO_BITRATE_KEY, parse_whole_number) {
+            self.audio_bitrate_kbps = Some(bitrate);
+        }
+
+        if let Some(sample_rate) = self.read(&values, AUDIO_SAMPLE_RATE_KEY, parse_whole_number) {
+            self.audio_sample_rate = Some(sample_rate);
+        }
+
+        if let Some(channels) = self.read(&values, AUDIO_CHANNELS_KEY, parse_whole_number) {
+            self.audio_channels = Some(channels);
+        }
+
+        if let Some(stereo) = self.read(&values, STEREO_KEY, parse_bool) {
+            self.stereo = Some(stereo);
+        }
+
+        if let Some(encoder) = self.read(&values, ENCODER_KEY, parse_text) {
+            self.encoder = Some(encoder);
+        }
+    }
+
+    /// Reads a single property's value, tracking whether its key is malformed
+    fn read<T>(
+        &mut self,
+        values: &HashMap<String, &str>,
+        key: &str,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> Option<T> {
+        let value = values.get(key)?;
+        let parsed = parse(value);
+        let malformed_index = self
+            .malformed_keys
+            .binary_search_by(|x| x.as_str().cmp(key));
+        match (&parsed, malformed_index) {
+            (Some(_), Ok(index)) => {
+                self.malformed_keys.remove(index);
+            }
+
+            (None, Err(index)) => self.malformed_keys.insert(index, key.to_string()),
+            _ => (),
+        }
+
+        parsed
+    }
+}
+
+impl fmt::Display for StreamProperties {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(resolution) = &self.resolution {
+            parts.push(format!("resolution={}", resolution));
+        }
+
+        if let Some(frame_rate) = &self.frame_rate {
+            parts.push(format!("frame_rate={}", frame_rate));
+        }
+
+        if let Some(codec) = &self.video_codec {
+            parts.push(format!("video_codec={}", codec));
+        }
+
+        if let Some(bitrate) = self.video_bitrate_kbps {
+            parts.push(format!("video_bitrate={}kbps", bitrate));
+        }
+
+        if let Some(codec) = &self.audio_codec {
+            parts.push(format!("audio_codec={}", codec));
+        }
+
+        if let Some(bitrate) = self.audio_bitrate_kbps {
+            parts.push(format!("audio_bitrate={}kbps", bitrate));
+        }
+
+        if let Some(sample_rate) = self.audio_sample_rate {
+            parts.push(format!("audio_sample_rate={}Hz", sample_rate));
+        }
+
+        if let Some(channels) = self.audio_channels {
+            parts.push(format!("audio_channels={}", channels));
+        }
+
+        if let Some(stereo) = self.stereo {
+            parts.push(format!("stereo={}", stereo));
+        }
+
+        if let Some(encoder) = &self.encoder {
+            parts.push(format!("encoder={}", encoder));
+        }
+
+        if parts.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", parts.join(", "))
+        }
+    }
+}
+
+/// Reads a non-negative number, rounding any fractional part (e.g. a bitrate of `2500.5`)
+fn parse_whole_number(value: &str) -> Option<u32> {
+    let number = value.parse::<f64>().ok()?;
+    if !number.is_finite() || number < 0.0 || number > u32::MAX as f64 {
+        return None;
+    }
+
+    Some(number.round() as u32)
+}
+
+fn parse_frame_rate(value: &str) -> Option<FrameRate> {
+    let fps = value.parse::<f64>().ok()?;
+    if !fps.is_finite() || fps <= 0.0 || fps > 1000.0 {
+        return None;
+    }
+
+    let numerator = (fps * FRAME_RATE_PRECISION as f64).round() as u32;
+    FrameRate::new(numerator, FRAME_RATE_PRECISION)
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_text(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn representative_on_metadata_parsed_into_properties() {
+        let data = metadata(&[
+            ("width", "1920"),
+            ("height", "1080"),
+            ("framerate", "29.97"),
+            ("videocodecid", "7"),
+            ("videodatarate", "2500.5"),
+            ("audiocodecid", "10"),
+            ("audiodatarate", "160"),
+            ("audiosamplerate", "48000"),
+            ("audiochannels", "2"),
+            ("stereo", "true"),
+            ("encoder", "obs-output module (libobs version 29.1.3)"),
+            ("duration", "0"),
+        ]);
+
+        let properties = StreamProperties::from_metadata(&data);
+
+        assert_eq!(
+            properties,
+            StreamProperties {
+                resolution: Some(VideoResolution {
+                    width: 1920,
+                    height: 1080
+                }),
+                frame_rate: FrameRate::new(2997, 100),
+                video_codec: Some("7".to_string()),
+                video_bitrate_kbps: Some(2501),
+                audio_codec: Some("10".to_string()),
+                audio_bitrate_kbps: Some(160),
+                audio_sample_rate: Some(48000),
+                audio_channels: Some(2),
+                stereo: Some(true),
+                encoder: Some("obs-output module (libobs version 29.1.3)".to_string()),
+                malformed_keys: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn malformed_values_left_unset_and_recorded() {
+        let data = metadata(&[
+            ("width", "wide"),
+            ("height", "720"),
+            ("framerate", "-30"),
+            ("audiosamplerate", "44100"),
+        ]);
+
+        let properties = StreamProperties::from_metadata(&data);
+
+        assert_eq!(properties.resolution, None, "Expected no resolution");
+        assert_eq!(properties.frame_rate, None, "Expected no frame rate");
+        assert_eq!(properties.audio_sample_rate, Some(44100));
+        assert_eq!(
+            properties.malformed_keys,
+            vec!["framerate".to_string(), "width".to_string()],
+            "Unexpected malformed keys"
+        );
+    }
+
+    #[test]
+    fn keys_matched_case_insensitively() {
+        let data = metadata(&[("Width", "640"), ("HEIGHT", "360")]);
+
+        let properties = StreamProperties::from_metadata(&data);
+
+        assert_eq!(
+            properties.resolution,
+            Some(VideoResolution {
+                width: 640,
+                height: 360
+            })
+        );
+    }
+
+    #[test]
+    fn applied_metadata_only_updates_properties_it_contains() {
+        let mut properties =
+            StreamProperties::from_metadata(&metadata(&[("width", "1280"), ("height", "720")]));
+
+        properties.apply_metadata(&metadata(&[("videodatarate", "3000"), ("framerate", "x")]));
+        properties.apply_metadata(&metadata(&[("framerate", "60")]));
+
+        assert_eq!(
+            properties.resolution,
+            Some(VideoResolution {
+                width: 1280,
+                height: 720
+            })
+        );
+        assert_eq!(properties.video_bitrate_kbps, Some(3000));
+        assert_eq!(properties.frame_rate, FrameRate::new(60, 1));
+        assert!(
+            properties.malformed_keys.is_empty(),
+            "Expected malformed frame rate to be cleared"
+        );
+    }
+
+    #[test]
+    fn properties_displayed_as_key_value_pairs() {
+        let properties =
+            StreamProperties::from_metadata(&metadata(&[("width", "640"), ("height", "360")]));
+
+        assert_eq!(properties.to_string(), "resolution=640x360");
+        assert_eq!(StreamProperties::default().to_string(), "none");
+    }
+}
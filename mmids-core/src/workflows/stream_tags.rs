@@ -0,0 +1,190 @@
+//! Streams can carry custom tags, which are name/value pairs describing where a stream came from
+//! or what it's for (e.g. `region=eu` or `venue=main-stage`). Unlike a stream's name, tags survive
+//! a stream being forwarded to other workflows (or other mmids instances), so steps and stats in
+//! the receiving workflow can still tell the stream's provenance.
+//!
+//! A stream's tags are announced through a metadata notification containing only keys starting
+//! with `STREAM_TAG_METADATA_PREFIX`, followed by the tag's name. Each announcement contains the
+//! full set of the stream's tags, replacing any previously announced tags. A stream has no tags
+//! until they are announced.
+
+use crate::workflows::{MediaNotification, MediaNotificationContent};
+use crate::StreamId;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// The prefix of the metadata keys a stream's tags are announced with
+pub const STREAM_TAG_METADATA_PREFIX: &str = "mmids_tag_";
+
+/// The tags of a single stream, ordered by tag name
+pub type Tags = BTreeMap<String, String>;
+
+/// Creates the notification announcing the tags of a stream
+pub fn tags_notification(stream_id: StreamId, tags: &Tags) -> MediaNotification {
+    let data = tags
+        .iter()
+        .map(|(name, value)| {
+            (
+                format!("{}{}", STREAM_TAG_METADATA_PREFIX, name),
+                value.clone(),
+            )
+        })
+        .collect();
+
+    MediaNotification {
+        stream_id,
+        content: MediaNotificationContent::Metadata { data },
+        annotations: Default::default(),
+    }
+}
+
+/// Returns the tags announced by the notification content, if it's a tag announcement
+pub fn get_announced_tags(content: &MediaNotificationContent) -> Option<Tags> {
+    let data = match content {
+        MediaNotificationContent::Metadata { data } if !data.is_empty() => data,
+        _ => return None,
+    };
+
+    data.iter()
+        .map(|(key, value)| {
+            key.strip_prefix(STREAM_TAG_METADATA_PREFIX)
+                .map(|name| (name.to_string(), value.clone()))
+        })
+        .collect()
+}
+
+/// Creates the tag announcement to forward for the media, containing only the tags allowed to be
+/// forwarded. Tags are all forwarded if no allowed tags are specified. `None` is returned if the
+/// media isn't a tag announcement, or if none of its tags are allowed.
+pub fn filter_tags_notification(
+    media: &MediaNotification,
+    allowed_tags: Option<&HashSet<String>>,
+) -> Option<MediaNotification> {
+    let mut tags = get_announced_tags(&media.content)?;
+    if let Some(allowed_tags) = allowed_tags {
+        tags.retain(|name, _| allowed_tags.contains(name));
+    }
+
+    if tags.is_empty() {
+        return None;
+    }
+
+    let mut notification = tags_notification(media.stream_id.clone(), &tags);
+    notification.annotations = media.annotations.clone();
+
+    Some(notification)
+}
+
+/// Tracks the tags of streams based on the media notifications that flow through a step
+#[derive(Default)]
+pub struct StreamTags {
+    tags: HashMap<StreamId, Tags>,
+}
+
+impl StreamTags {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Updates the tracked tags based on the media notification
+    pub fn handle_media(&mut self, media: &MediaNotification) {
+        match &media.content {
+            // A new stream starts out untagged, even if a previous stream with the same id had
+            // tags
+            MediaNotificationContent::NewIncomingStream { .. }
+            | MediaNotificationContent::StreamDisconnected => {
+                self.tags.remove(&media.stream_id);
+            }
+
+            content => {
+                if let Some(tags) = get_announced_tags(content) {
+                    self.tags.insert(media.stream_id.clone(), tags);
+                }
+            }
+        }
+    }
+
+    /// Returns the tags of the stream, if any have been announced
+    pub fn tags_of(&self, stream_id: &StreamId) -> Option<&Tags> {
+        self.tags.get(stream_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn tags(entries: &[(&str, &str)]) -> Tags {
+        entries
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn announced_tags_can_be_read_back() {
+        let stream_id = StreamId(Arc::new("a".to_string()));
+        let announced = tags(&[("region", "eu"), ("venue", "main-stage")]);
+
+        let notification = tags_notification(stream_id, &announced);
+
+        assert_eq!(get_announced_tags(&notification.content), Some(announced));
+    }
+
+    #[test]
+    fn other_metadata_is_not_a_tag_announcement() {
+        let mut data = HashMap::new();
+        data.insert("mmids_tag_region".to_string(), "eu".to_string());
+        data.insert("width".to_string(), "1920".to_string());
+
+        let content = MediaNotificationContent::Metadata { data };
+        assert_eq!(get_announced_tags(&content), None);
+
+        let content = MediaNotificationContent::Metadata {
+            data: HashMap::new(),
+        };
+
+        assert_eq!(get_announced_tags(&content), None);
+    }
+
+    #[test]
+    fn filtered_notification_only_contains_allowed_tags() {
+        let stream_id = StreamId(Arc::new("a".to_string()));
+        let notification = tags_notification(stream_id, &tags(&[("region", "eu"), ("x", "y")]));
+        let allowed = vec!["region".to_string()]
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        let filtered = filter_tags_notification(&notification, Some(&allowed))
+            .expect("Expected a tag announcement");
+
+        assert_eq!(
+            get_announced_tags(&filtered.content),
+            Some(tags(&[("region", "eu")]))
+        );
+    }
+
+    #[test]
+    fn tags_tracked_from_announcements() {
+        let stream_id = StreamId(Arc::new("a".to_string()));
+        let mut stream_tags = StreamTags::new();
+
+        stream_tags.handle_media(&tags_notification(
+            stream_id.clone(),
+            &tags(&[("region", "eu")]),
+        ));
+
+        assert_eq!(
+            stream_tags.tags_of(&stream_id),
+            Some(&tags(&[("region", "eu")]))
+        );
+
+        stream_tags.handle_media(&MediaNotification {
+            stream_id: stream_id.clone(),
+            content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
+        });
+
+        assert_eq!(stream_tags.tags_of(&stream_id), None, "Expected no tags");
+    }
+}
@@ -0,0 +1,83 @@
+//! Streams don't always start with all of their tracks. An encoder may send video for several
+//! seconds before its audio joins (or vice versa), and steps that set themselves up based on the
+//! tracks they first saw may not be prepared for another track to appear.
+//!
+//! When the workflow runner sees the first payload of a track arrive well after the stream's
+//! media started flowing, it announces the late track through a metadata notification containing
+//! only the `TRACK_ADDED_METADATA_KEY` key. The announcement is raised right before the late
+//! track's first payload, and is followed by a replay of the track's sequence header so steps that
+//! re-initialize in response have everything they need to decode the new track.
+
+use crate::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use crate::StreamId;
+use std::collections::HashMap;
+
+/// The metadata key a late track is announced with. The value is the type of media in the track.
+pub const TRACK_ADDED_METADATA_KEY: &str = "mmids_track_added";
+
+/// Creates the notification announcing that a track of the specified media type has been added to
+/// the stream
+pub fn track_added_notification(stream_id: StreamId, media_type: MediaType) -> MediaNotification {
+    let mut data = HashMap::new();
+    data.insert(
+        TRACK_ADDED_METADATA_KEY.to_string(),
+        media_type_name(media_type).to_string(),
+    );
+
+    MediaNotification {
+        stream_id,
+        content: MediaNotificationContent::Metadata { data },
+        annotations: Default::default(),
+    }
+}
+
+/// Returns the media type of the track announced by the notification content, if it's a track
+/// added announcement
+pub fn get_added_track(content: &MediaNotificationContent) -> Option<MediaType> {
+    match content {
+        MediaNotificationContent::Metadata { data } if data.len() == 1 => {
+            match data.get(TRACK_ADDED_METADATA_KEY)?.as_str() {
+                "audio" => Some(MediaType::Audio),
+                "video" => Some(MediaType::Video),
+                "other" => Some(MediaType::Other),
+                _ => None,
+            }
+        }
+
+        _ => None,
+    }
+}
+
+fn media_type_name(media_type: MediaType) -> &'static str {
+    match media_type {
+        MediaType::Audio => "audio",
+        MediaType::Video => "video",
+        MediaType::Other => "other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn added_track_read_from_announcement() {
+        let stream_id = StreamId(Arc::new("abc".to_string()));
+        let notification = track_added_notification(stream_id, MediaType::Audio);
+
+        assert_eq!(
+            get_added_track(&notification.content),
+            Some(MediaType::Audio)
+        );
+    }
+
+    #[test]
+    fn no_added_track_for_other_metadata() {
+        let mut data = HashMap::new();
+        data.insert("key".to_string(), "audio".to_string());
+
+        let content = MediaNotificationContent::Metadata { data };
+        assert_eq!(get_added_track(&content), None);
+    }
+}
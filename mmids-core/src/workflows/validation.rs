@@ -0,0 +1,171 @@
+//! Validates workflow definitions without running them, so tooling can check that a workflow will
+//! start successfully before it's applied.
+//!
+//! Each step is constructed the same way it would be when the workflow starts, which catches
+//! unknown step types, invalid parameters and missing plugins. Constructed steps are dropped
+//! immediately and never receive media, and any futures they spawned are cancelled since nothing
+//! is listening for their results.
+
+use crate::workflows::definitions::{WorkflowDefinition, WorkflowStepId, WorkflowStepType};
+use crate::workflows::steps::factory::WorkflowStepFactory;
+use crate::workflows::steps::StepStatus;
+use std::collections::HashMap;
+use tokio::sync::mpsc::unbounded_channel;
+
+/// A single problem found while validating a workflow
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WorkflowValidationIssue {
+    /// The index of the step the issue was found on, if it's for a specific step
+    pub step_index: Option<usize>,
+    pub step_type: Option<WorkflowStepType>,
+    pub message: String,
+}
+
+/// The outcome of validating a workflow. Errors prevent the workflow from becoming active, while
+/// warnings are problems that won't prevent it from starting but may not be intended.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WorkflowValidationResult {
+    pub errors: Vec<WorkflowValidationIssue>,
+    pub warnings: Vec<WorkflowValidationIssue>,
+}
+
+impl WorkflowValidationResult {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Validates the workflow definition by constructing each of its steps with the specified factory
+pub fn validate_workflow(
+    definition: &WorkflowDefinition,
+    step_factory: &WorkflowStepFactory,
+) -> WorkflowValidationResult {
+    let mut result = WorkflowValidationResult::default();
+    if definition.name.trim().is_empty() {
+        result.errors.push(WorkflowValidationIssue {
+            step_index: None,
+            step_type: None,
+            message: "The workflow does not have a name".to_string(),
+        });
+    }
+
+    if definition.steps.is_empty() {
+        result.warnings.push(WorkflowValidationIssue {
+            step_index: None,
+            step_type: None,
+            message: "The workflow does not have any steps".to_string(),
+        });
+    }
+
+    // Steps that are never executed still need a futures channel, but nothing will ever read
+    // from it, so any futures they start are cancelled as soon as they try to send a result.
+    let (futures_sender, futures_receiver) = unbounded_channel();
+    drop(futures_receiver);
+
+    let mut seen_steps: HashMap<WorkflowStepId, usize> = HashMap::new();
+    for (index, step) in definition.steps.iter().enumerate() {
+        let issue = |message: String| WorkflowValidationIssue {
+            step_index: Some(index),
+            step_type: Some(step.step_type.clone()),
+            message,
+        };
+
+        if let Some(first_index) = seen_steps.get(&step.get_id()) {
+            result.warnings.push(issue(format!(
+                "Step is identical to step {}, and identical steps share a single instance",
+                first_index
+            )));
+
+            continue;
+        }
+
+        seen_steps.insert(step.get_id(), index);
+        match step_factory.create_step(step.clone(), &futures_sender, None) {
+            Err(error) => result.errors.push(issue(error.to_string())),
+            Ok(Err(error)) => result.errors.push(issue(error.to_string())),
+            Ok(Ok((_step, StepStatus::Error { message }))) => result.errors.push(issue(message)),
+            Ok(Ok(_)) => (),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflows::definitions::WorkflowStepDefinition;
+    use crate::workflows::runner::test_steps::TestPassThroughStepGenerator;
+    use std::sync::atomic::AtomicU16;
+    use std::sync::Arc;
+
+    fn definition(step_types: &[&str]) -> WorkflowDefinition {
+        WorkflowDefinition {
+            name: Arc::new("workflow".to_string()),
+            routed_by_reactor: false,
+            max_stream_lifetime: None,
+            max_cached_media_bytes: None,
+            watcher_limit: None,
+            steps: step_types
+                .iter()
+                .map(|step_type| WorkflowStepDefinition {
+                    step_type: WorkflowStepType(step_type.to_string()),
+                    parameters: HashMap::new(),
+                })
+                .collect(),
+        }
+    }
+
+    fn factory() -> WorkflowStepFactory {
+        let mut factory = WorkflowStepFactory::new();
+        factory
+            .register(
+                WorkflowStepType("pass".to_string()),
+                Box::new(TestPassThroughStepGenerator {
+                    created_count: Arc::new(AtomicU16::new(0)),
+                    media_sender: None,
+                }),
+            )
+            .expect("Failed to register step");
+
+        factory
+    }
+
+    #[test]
+    fn workflow_with_registered_steps_is_valid() {
+        let result = validate_workflow(&definition(&["pass"]), &factory());
+
+        assert!(result.is_valid(), "Expected valid workflow: {:?}", result);
+        assert!(result.warnings.is_empty(), "Expected no warnings");
+    }
+
+    #[test]
+    fn unregistered_step_type_is_an_error() {
+        let result = validate_workflow(&definition(&["pass", "missing"]), &factory());
+
+        assert_eq!(result.errors.len(), 1, "Unexpected number of errors");
+        assert_eq!(
+            result.errors[0].step_index,
+            Some(1),
+            "Unexpected step index"
+        );
+        assert_eq!(
+            result.errors[0].step_type,
+            Some(WorkflowStepType("missing".to_string())),
+            "Unexpected step type"
+        );
+    }
+
+    #[test]
+    fn identical_steps_are_a_warning() {
+        let result = validate_workflow(&definition(&["pass", "pass"]), &factory());
+
+        assert!(result.is_valid(), "Expected valid workflow: {:?}", result);
+        assert_eq!(result.warnings.len(), 1, "Unexpected number of warnings");
+        assert_eq!(
+            result.warnings[0].step_index,
+            Some(1),
+            "Unexpected step index"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! Tracks the watchers of a workflow across all of its output steps. Each workflow has a single
+//! `WorkflowWatchers` instance that's shared with every step it creates, allowing a cap on the
+//! total number of watchers to be enforced no matter how many steps serve them.
+//!
+//! Output steps ask for a slot before letting a watcher in. The slot is held for as long as the
+//! watcher is connected, and the watcher is no longer counted once the slot is dropped.
+
+use std::sync::{Arc, Mutex};
+
+/// What happens to watchers that show up after a workflow reached its watcher limit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatcherLimitBehavior {
+    /// The watcher is turned away
+    Reject,
+
+    /// The watcher is redirected to a slate announcing the workflow is full, if the output step
+    /// serving them has one. Otherwise they are rejected.
+    Slate,
+}
+
+/// The maximum number of watchers a workflow allows across all of its output steps
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatcherLimit {
+    pub max_watchers: usize,
+    pub behavior: WatcherLimitBehavior,
+}
+
+/// The result of attempting to add a watcher to a workflow
+#[derive(Debug)]
+pub enum WatcherAdmission {
+    /// The watcher was admitted, and is counted until the slot is dropped
+    Admitted(WatcherSlot),
+
+    /// The workflow is full, and the watcher should be turned away
+    Rejected,
+
+    /// The workflow is full, and the watcher should be redirected to a slate
+    Slate,
+}
+
+#[derive(Default, Debug)]
+struct WatcherCounts {
+    current: usize,
+    limit: Option<WatcherLimit>,
+}
+
+/// The watchers of a single workflow. Clones share the same counts.
+#[derive(Clone, Default)]
+pub struct WorkflowWatchers {
+    counts: Arc<Mutex<WatcherCounts>>,
+}
+
+/// A watcher that's counted against its workflow's watcher limit
+#[derive(Debug)]
+pub struct WatcherSlot {
+    counts: Arc<Mutex<WatcherCounts>>,
+}
+
+impl WorkflowWatchers {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Changes the workflow's watcher limit. Watchers that are already counted are not affected,
+    /// even if there are now more of them than the new limit allows.
+    pub fn set_limit(&self, limit: Option<WatcherLimit>) {
+        self.counts.lock().unwrap().limit = limit;
+    }
+
+    /// The workflow's current watcher limit, if it has one
+    pub fn limit(&self) -> Option<WatcherLimit> {
+        self.counts.lock().unwrap().limit
+    }
+
+    /// The number of watchers currently counted across the workflow
+    pub fn current_count(&self) -> usize {
+        self.counts.lock().unwrap().current
+    }
+
+    /// Attempts to add a watcher to the workflow
+    pub fn try_add_watcher(&self) -> WatcherAdmission {
+        let mut counts = self.counts.lock().unwrap();
+        match counts.limit {
+            Some(limit) if counts.current >= limit.max_watchers => match limit.behavior {
+                WatcherLimitBehavior::Reject => WatcherAdmission::Rejected,
+                WatcherLimitBehavior::Slate => WatcherAdmission::Slate,
+            },
+
+            _ => {
+                counts.current += 1;
+                WatcherAdmission::Admitted(WatcherSlot {
+                    counts: self.counts.clone(),
+                })
+            }
+        }
+    }
+}
+
+impl Drop for WatcherSlot {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.current = counts.current.saturating_sub(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limited(max_watchers: usize, behavior: WatcherLimitBehavior) -> WorkflowWatchers {
+        let watchers = WorkflowWatchers::new();
+        watchers.set_limit(Some(WatcherLimit {
+            max_watchers,
+            behavior,
+        }));
+
+        watchers
+    }
+
+    #[test]
+    fn watchers_are_admitted_without_a_limit() {
+        let watchers = WorkflowWatchers::new();
+        let slots = (0..100)
+            .map(|_| watchers.try_add_watcher())
+            .collect::<Vec<_>>();
+
+        assert!(
+            slots
+                .iter()
+                .all(|slot| matches!(slot, WatcherAdmission::Admitted(_))),
+            "Expected all watchers to be admitted"
+        );
+
+        assert_eq!(watchers.current_count(), 100, "Unexpected watcher count");
+    }
+
+    #[test]
+    fn limit_is_shared_by_all_clones() {
+        let first_step = limited(2, WatcherLimitBehavior::Reject);
+        let second_step = first_step.clone();
+
+        let _first = first_step.try_add_watcher();
+        let _second = second_step.try_add_watcher();
+
+        assert!(
+            matches!(first_step.try_add_watcher(), WatcherAdmission::Rejected),
+            "Expected watcher to be rejected"
+        );
+
+        assert_eq!(second_step.current_count(), 2, "Unexpected watcher count");
+    }
+
+    #[test]
+    fn dropping_slot_frees_room_for_another_watcher() {
+        let watchers = limited(1, WatcherLimitBehavior::Reject);
+        let slot = watchers.try_add_watcher();
+        assert!(matches!(
+            watchers.try_add_watcher(),
+            WatcherAdmission::Rejected
+        ));
+
+        drop(slot);
+        assert_eq!(watchers.current_count(), 0, "Unexpected watcher count");
+        assert!(
+            matches!(watchers.try_add_watcher(), WatcherAdmission::Admitted(_)),
+            "Expected watcher to be admitted"
+        );
+    }
+
+    #[test]
+    fn full_workflow_with_slate_behavior_returns_slate() {
+        let watchers = limited(1, WatcherLimitBehavior::Slate);
+        let _slot = watchers.try_add_watcher();
+
+        assert!(matches!(
+            watchers.try_add_watcher(),
+            WatcherAdmission::Slate
+        ));
+        assert_eq!(watchers.current_count(), 1, "Slates should not be counted");
+    }
+}
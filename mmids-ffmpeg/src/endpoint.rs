@@ -119,6 +119,10 @@ pub enum TargetParams {
         /// The maximum number of segments that should be in the playlist.  If none is specified
         /// than ffmpeg's default will be used
         max_entries: Option<u16>,
+
+        /// The path template (e.g. `/hls/abc%d.ts`) segment files should be written to.  If none
+        /// is specified then ffmpeg names segments after the playlist.
+        segment_filename: Option<String>,
     },
 }
 
@@ -134,6 +138,13 @@ pub struct VideoScale {
 pub struct FfmpegParams {
     pub read_in_real_time: bool,
     pub input: String,
+
+    /// An optional secondary input containing an MPEG-TS stream of timed ID3 metadata. When
+    /// specified, the metadata stream is muxed into the output alongside the primary input's
+    /// audio and video, and original timestamps are preserved so the metadata's PTS values line
+    /// up with the primary input's timeline.
+    pub timed_metadata_input: Option<String>,
+
     pub video_transcode: VideoTranscodeParams,
     pub scale: Option<VideoScale>,
     pub audio_transcode: AudioTranscodeParams,
@@ -397,6 +408,21 @@ impl Actor {
         args.push("-i".to_string());
         args.push(params.input.to_string());
 
+        if let Some(metadata_input) = &params.timed_metadata_input {
+            args.push("-copyts".to_string());
+            args.push("-i".to_string());
+            args.push(metadata_input.to_string());
+
+            args.push("-map".to_string());
+            args.push("0:v?".to_string());
+            args.push("-map".to_string());
+            args.push("0:a?".to_string());
+            args.push("-map".to_string());
+            args.push("1:d".to_string());
+            args.push("-c:d".to_string());
+            args.push("copy".to_string());
+        }
+
         args.push("-vcodec".to_string());
         match &params.video_transcode {
             VideoTranscodeParams::Copy => args.push("copy".to_string()),
@@ -452,6 +478,7 @@ impl Actor {
                 path,
                 max_entries,
                 segment_length,
+                segment_filename,
             } => {
                 args.push("hls".to_string());
 
@@ -463,6 +490,11 @@ impl Actor {
                     args.push(entries.to_string());
                 }
 
+                if let Some(segment_filename) = segment_filename {
+                    args.push("-hls_segment_filename".to_string());
+                    args.push(segment_filename.clone());
+                }
+
                 args.push(path.clone());
             }
         }
@@ -1,26 +1,37 @@
 use crate::endpoint::{FfmpegEndpointNotification, FfmpegEndpointRequest, FfmpegParams};
 use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::retry::{
+    Backoff, ConnectionState, RetryFailureAction, RetryPolicy,
+};
 use mmids_core::StreamId;
 use mmids_rtmp::workflow_steps::external_stream_handler::{
     ExternalStreamHandler, ExternalStreamHandlerGenerator, ResolvedFutureStatus,
     StreamHandlerFutureResult, StreamHandlerFutureWrapper,
 };
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tracing::{error, info, instrument, warn};
 use uuid::Uuid;
 
+/// An ffmpeg process that has been running for this long is assumed to have successfully
+/// connected to its target, so a failure after that starts the retry policy over.
+const HEALTHY_RUN_DURATION: Duration = Duration::from_secs(30);
+
 pub struct FfmpegHandler {
     ffmpeg_endpoint: UnboundedSender<FfmpegEndpointRequest>,
     status: FfmpegHandlerStatus,
     param_generator: Arc<Box<dyn FfmpegParameterGenerator + Sync + Send>>,
     stream_id: StreamId,
     ffmpeg_id: Uuid,
+    backoff: Option<Backoff>,
+    futures_channel: Option<WorkflowStepFuturesChannel>,
 }
 
 pub struct FfmpegHandlerGenerator {
     ffmpeg_endpoint: UnboundedSender<FfmpegEndpointRequest>,
     param_generator: Arc<Box<dyn FfmpegParameterGenerator + Sync + Send>>,
+    retry_policy: Option<RetryPolicy>,
 }
 
 pub trait FfmpegParameterGenerator {
@@ -31,12 +42,15 @@ pub trait FfmpegParameterGenerator {
 enum FfmpegHandlerStatus {
     Inactive,
     Pending,
-    Active,
+    Active { started_at: Instant },
+    WaitingToRetry { attempt: u32 },
+    Failed,
 }
 
 enum FutureResult {
     FfmpegChannelGone,
     NotificationReceived(FfmpegEndpointNotification),
+    RetryDelayElapsed,
 }
 
 impl StreamHandlerFutureResult for FutureResult {}
@@ -49,8 +63,16 @@ impl FfmpegHandlerGenerator {
         FfmpegHandlerGenerator {
             ffmpeg_endpoint,
             param_generator: Arc::new(param_generator),
+            retry_policy: None,
         }
     }
+
+    /// Restarts ffmpeg based on the specified retry policy when it stops or fails to start.
+    /// Without a retry policy ffmpeg is restarted right away, indefinitely.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
 }
 
 impl ExternalStreamHandlerGenerator for FfmpegHandlerGenerator {
@@ -61,6 +83,8 @@ impl ExternalStreamHandlerGenerator for FfmpegHandlerGenerator {
             stream_id,
             status: FfmpegHandlerStatus::Inactive,
             ffmpeg_id: Uuid::new_v4(),
+            backoff: self.retry_policy.map(Backoff::new),
+            futures_channel: None,
         })
     }
 }
@@ -76,7 +100,9 @@ impl FfmpegHandler {
                         self.stream_id, self.ffmpeg_id
                     );
 
-                    self.status = FfmpegHandlerStatus::Active;
+                    self.status = FfmpegHandlerStatus::Active {
+                        started_at: Instant::now(),
+                    };
                 }
 
                 status => {
@@ -94,7 +120,15 @@ impl FfmpegHandler {
                     self.stream_id
                 );
 
-                self.status = FfmpegHandlerStatus::Inactive;
+                if let FfmpegHandlerStatus::Active { started_at } = &self.status {
+                    if started_at.elapsed() >= HEALTHY_RUN_DURATION {
+                        if let Some(backoff) = &mut self.backoff {
+                            backoff.reset();
+                        }
+                    }
+                }
+
+                self.schedule_restart();
             }
 
             FfmpegEndpointNotification::FfmpegFailedToStart { cause } => {
@@ -103,14 +137,53 @@ impl FfmpegHandler {
                     self.stream_id, cause
                 );
 
-                self.status = FfmpegHandlerStatus::Inactive;
+                self.schedule_restart();
             }
         }
     }
+
+    /// Marks ffmpeg as inactive so it's restarted, waiting for the retry policy's delay first if
+    /// there is one.
+    fn schedule_restart(&mut self) {
+        let (backoff, futures_channel) = match (&mut self.backoff, &self.futures_channel) {
+            (Some(backoff), Some(futures_channel)) => (backoff, futures_channel),
+            _ => {
+                self.status = FfmpegHandlerStatus::Inactive;
+                return;
+            }
+        };
+
+        let delay = match backoff.next_delay() {
+            Some(delay) => delay,
+            None => {
+                error!(
+                    "Ffmpeg for stream {:?} failed too many times, giving up",
+                    self.stream_id
+                );
+
+                self.status = FfmpegHandlerStatus::Failed;
+                return;
+            }
+        };
+
+        let stream_id = self.stream_id.clone();
+        futures_channel.send_on_generic_future_completion(async move {
+            tokio::time::sleep(delay).await;
+            StreamHandlerFutureWrapper {
+                stream_id,
+                future: Box::new(FutureResult::RetryDelayElapsed),
+            }
+        });
+
+        self.status = FfmpegHandlerStatus::WaitingToRetry {
+            attempt: backoff.next_attempt(),
+        };
+    }
 }
 
 impl ExternalStreamHandler for FfmpegHandler {
     fn prepare_stream(&mut self, stream_name: &str, futures_channel: &WorkflowStepFuturesChannel) {
+        self.futures_channel = Some(futures_channel.clone());
         if let FfmpegHandlerStatus::Inactive = &self.status {
             let parameters = self
                 .param_generator
@@ -150,13 +223,15 @@ impl ExternalStreamHandler for FfmpegHandler {
                     .send(FfmpegEndpointRequest::StopFfmpeg { id: self.ffmpeg_id });
             }
 
-            FfmpegHandlerStatus::Active => {
+            FfmpegHandlerStatus::Active { .. } => {
                 let _ = self
                     .ffmpeg_endpoint
                     .send(FfmpegEndpointRequest::StopFfmpeg { id: self.ffmpeg_id });
             }
 
-            FfmpegHandlerStatus::Inactive => (),
+            FfmpegHandlerStatus::Inactive
+            | FfmpegHandlerStatus::WaitingToRetry { .. }
+            | FfmpegHandlerStatus::Failed => (),
         }
     }
 
@@ -174,16 +249,55 @@ impl ExternalStreamHandler for FfmpegHandler {
             FutureResult::NotificationReceived(notification) => {
                 self.handle_ffmpeg_notification(notification);
 
+                let should_disconnect = self
+                    .backoff
+                    .as_ref()
+                    .map(|backoff| backoff.policy().on_failure)
+                    == Some(RetryFailureAction::DisconnectStream);
+
+                match &self.status {
+                    FfmpegHandlerStatus::Failed if should_disconnect => {
+                        ResolvedFutureStatus::StreamShouldBeStopped
+                    }
+
+                    _ => ResolvedFutureStatus::Success,
+                }
+            }
+
+            FutureResult::RetryDelayElapsed => {
+                if let FfmpegHandlerStatus::WaitingToRetry { .. } = &self.status {
+                    self.status = FfmpegHandlerStatus::Inactive;
+                }
+
                 ResolvedFutureStatus::Success
             }
         }
     }
+
+    fn connection_state(&self) -> Option<ConnectionState> {
+        let state = match &self.status {
+            FfmpegHandlerStatus::Inactive | FfmpegHandlerStatus::Pending => {
+                ConnectionState::Connecting
+            }
+
+            FfmpegHandlerStatus::Active { .. } => ConnectionState::Connected,
+            FfmpegHandlerStatus::WaitingToRetry { attempt } => {
+                ConnectionState::Retrying { attempt: *attempt }
+            }
+
+            FfmpegHandlerStatus::Failed => ConnectionState::Failed,
+        };
+
+        Some(state)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::endpoint::{AudioTranscodeParams, TargetParams, VideoTranscodeParams};
+    use crate::endpoint::{
+        AudioTranscodeParams, FfmpegFailureCause, TargetParams, VideoTranscodeParams,
+    };
     use mmids_core::workflows::definitions::WorkflowStepId;
     use mmids_core::workflows::steps::futures_channel::FuturesChannelResult;
     use tokio::sync::mpsc::UnboundedReceiver;
@@ -198,6 +312,7 @@ mod tests {
                 scale: None,
                 read_in_real_time: true,
                 input: stream_name.to_string(),
+                timed_metadata_input: None,
                 target: TargetParams::Rtmp {
                     url: stream_id.0.to_string(),
                 },
@@ -214,10 +329,15 @@ mod tests {
 
     impl TestContext {
         fn new() -> Self {
+            TestContext::with_retry_policy(None)
+        }
+
+        fn with_retry_policy(retry_policy: Option<RetryPolicy>) -> Self {
             let (request_sender, request_receiver) = unbounded_channel();
             let generator = FfmpegHandlerGenerator {
                 ffmpeg_endpoint: request_sender,
                 param_generator: Arc::new(Box::new(TestParamGenerator)),
+                retry_policy,
             };
 
             let (futures_sender, futures_receiver) = unbounded_channel();
@@ -270,4 +390,37 @@ mod tests {
             other => panic!("Expected Ok(StopFfmpeg) instead got {:?}", other),
         }
     }
+
+    #[tokio::test]
+    async fn stream_stopped_once_retry_policy_exhausted_when_set_to_disconnect() {
+        let mut context = TestContext::with_retry_policy(Some(RetryPolicy {
+            max_attempts: Some(1),
+            on_failure: RetryFailureAction::DisconnectStream,
+            ..Default::default()
+        }));
+
+        context
+            .handler
+            .prepare_stream("name", &context.step_futures_channel);
+
+        let result =
+            context
+                .handler
+                .handle_resolved_future(Box::new(FutureResult::NotificationReceived(
+                    FfmpegEndpointNotification::FfmpegFailedToStart {
+                        cause: FfmpegFailureCause::FfmpegFailedToStart,
+                    },
+                )));
+
+        match result {
+            ResolvedFutureStatus::StreamShouldBeStopped => (),
+            ResolvedFutureStatus::Success => panic!("Expected the stream to be stopped"),
+        }
+
+        assert_eq!(
+            context.handler.connection_state(),
+            Some(ConnectionState::Failed),
+            "Unexpected connection state"
+        );
+    }
 }
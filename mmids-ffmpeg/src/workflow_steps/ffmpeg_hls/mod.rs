@@ -2,11 +2,30 @@
 //!
 //! Media packets that are received from previous steps are passed to the RTMP endpoint for ffmpeg
 //! consumption, and then passed on to the next step as-is.
+//!
+//! When an `id3` mapping of metadata keys to ID3 frames is specified (e.g. `id3=title:TIT2`),
+//! values from `Metadata` notifications with mapped keys are inserted into the HLS output as
+//! timed ID3 metadata. Each tag is presented at the timestamp of the last media payload the step
+//! received for the stream before the metadata notification.
+//!
+//! When a `signing_key` is specified, every URI in the playlist is signed with a token that expires
+//! after `signed_url_ttl` seconds (see `mmids_core::url_signing`). Ffmpeg writes an unsigned
+//! playlist next to the public playlist (e.g. `abc.unsigned.m3u8`), which the step keeps re-signing
+//! into the public playlist as ffmpeg updates it. Whatever serves the HLS files must validate
+//! segment requests with the same key, and should not serve the unsigned playlists.
+
+mod signed_playlists;
+mod timed_metadata;
 
 use crate::endpoint::{
     AudioTranscodeParams, FfmpegEndpointRequest, FfmpegParams, TargetParams, VideoTranscodeParams,
 };
 use crate::workflow_steps::ffmpeg_handler::{FfmpegHandlerGenerator, FfmpegParameterGenerator};
+use crate::workflow_steps::ffmpeg_hls::signed_playlists::SignedPlaylists;
+use crate::workflow_steps::ffmpeg_hls::timed_metadata::TimedMetadataFeeds;
+use mmids_core::timed_metadata::id3::encode_tag;
+use mmids_core::timed_metadata::{Id3KeyMap, Id3KeyMapError};
+use mmids_core::url_signing::UrlSigner;
 use mmids_core::workflows::definitions::WorkflowStepDefinition;
 use mmids_core::workflows::metadata::MetadataKey;
 use mmids_core::workflows::steps::factory::StepGenerator;
@@ -14,10 +33,14 @@ use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
 use mmids_core::workflows::steps::{
     StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
 };
+use mmids_core::workflows::{MediaNotification, MediaNotificationContent};
 use mmids_core::StreamId;
 use mmids_rtmp::rtmp_server::RtmpEndpointRequest;
 use mmids_rtmp::workflow_steps::external_stream_reader::ExternalStreamReader;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 use tokio::sync::mpsc::UnboundedSender;
 use tracing::error;
@@ -26,6 +49,11 @@ const PATH: &str = "path";
 const SEGMENT_DURATION: &str = "duration";
 const SEGMENT_COUNT: &str = "count";
 const STREAM_NAME: &str = "stream_name";
+const ID3_KEYS: &str = "id3";
+const SIGNING_KEY: &str = "signing_key";
+const SIGNED_URL_TTL: &str = "signed_url_ttl";
+
+const DEFAULT_SIGNED_URL_TTL: Duration = Duration::from_secs(300);
 
 /// Generates new instances of the ffmpeg HLS workflow step based on specified step definitions.
 pub struct FfmpegHlsStepGenerator {
@@ -39,6 +67,14 @@ struct FfmpegHlsStep {
     status: StepStatus,
     stream_reader: ExternalStreamReader,
     path: String,
+    timed_metadata: Option<TimedMetadataOutput>,
+    signed_playlists: Option<Arc<SignedPlaylists>>,
+}
+
+struct TimedMetadataOutput {
+    key_map: Id3KeyMap,
+    feeds: Arc<TimedMetadataFeeds>,
+    latest_timestamps: HashMap<StreamId, Duration>,
 }
 
 enum FutureResult {
@@ -61,6 +97,21 @@ enum StepStartupError {
         SEGMENT_COUNT
     )]
     InvalidSegmentCount(String),
+
+    #[error("Invalid {} mapping: {0}", ID3_KEYS)]
+    InvalidId3Mapping(#[from] Id3KeyMapError),
+
+    #[error("No value specified for {}", SIGNING_KEY)]
+    NoSigningKeyProvided,
+
+    #[error(
+        "Invalid signed url ttl of '{0}'.  {} should be a positive number of seconds",
+        SIGNED_URL_TTL
+    )]
+    InvalidSignedUrlTtl(String),
+
+    #[error("{} can only be specified along with {}", SIGNED_URL_TTL, SIGNING_KEY)]
+    SignedUrlTtlWithoutSigningKey,
 }
 
 struct ParamGenerator {
@@ -69,6 +120,8 @@ struct ParamGenerator {
     segment_duration: u16,
     segment_count: u16,
     stream_name: Option<String>,
+    timed_metadata_feeds: Option<Arc<TimedMetadataFeeds>>,
+    signed_playlists: Option<Arc<SignedPlaylists>>,
 }
 
 impl FfmpegHlsStepGenerator {
@@ -127,12 +180,33 @@ impl StepGenerator for FfmpegHlsStepGenerator {
         let stream_name = definition.parameters.get(STREAM_NAME).cloned().flatten();
         let rtmp_app = Arc::new(get_rtmp_app(definition.get_id().to_string()));
 
+        let timed_metadata = match definition.parameters.get(ID3_KEYS) {
+            Some(Some(value)) => match Id3KeyMap::parse(value) {
+                Ok(key_map) => Some(TimedMetadataOutput {
+                    key_map,
+                    feeds: Arc::new(TimedMetadataFeeds::default()),
+                    latest_timestamps: HashMap::new(),
+                }),
+
+                Err(error) => return Err(Box::new(StepStartupError::from(error))),
+            },
+
+            _ => None,
+        };
+
+        let signed_playlists = match get_url_signer(&definition) {
+            Ok(signer) => signer.map(|signer| Arc::new(SignedPlaylists::new(signer))),
+            Err(error) => return Err(Box::new(error)),
+        };
+
         let param_generator = ParamGenerator {
             rtmp_app: rtmp_app.clone(),
             path: path.clone(),
             segment_duration: duration,
             segment_count: count,
             stream_name,
+            timed_metadata_feeds: timed_metadata.as_ref().map(|x| x.feeds.clone()),
+            signed_playlists: signed_playlists.clone(),
         };
 
         let handler_generator =
@@ -152,6 +226,8 @@ impl StepGenerator for FfmpegHlsStepGenerator {
             status: StepStatus::Created,
             stream_reader: reader,
             path: path.clone(),
+            timed_metadata,
+            signed_playlists,
         };
 
         let ffmpeg_endpoint = self.ffmpeg_endpoint.clone();
@@ -222,6 +298,16 @@ impl WorkflowStep for FfmpegHlsStep {
         }
 
         for media in inputs.media.drain(..) {
+            if let Some(timed_metadata) = &mut self.timed_metadata {
+                timed_metadata.handle_media(&media);
+            }
+
+            if let Some(signed_playlists) = &self.signed_playlists {
+                if let MediaNotificationContent::StreamDisconnected = &media.content {
+                    signed_playlists.stop(&media.stream_id);
+                }
+            }
+
             self.stream_reader
                 .handle_media(media, outputs, &futures_channel);
         }
@@ -230,6 +316,39 @@ impl WorkflowStep for FfmpegHlsStep {
     }
 }
 
+impl TimedMetadataOutput {
+    fn handle_media(&mut self, media: &MediaNotification) {
+        match &media.content {
+            MediaNotificationContent::MediaPayload { timestamp, .. } => {
+                self.latest_timestamps
+                    .insert(media.stream_id.clone(), *timestamp);
+            }
+
+            MediaNotificationContent::Metadata { data } => {
+                let frames = self.key_map.frames_for(data);
+                if frames.is_empty() {
+                    return;
+                }
+
+                let pts = self
+                    .latest_timestamps
+                    .get(&media.stream_id)
+                    .copied()
+                    .unwrap_or_default();
+
+                self.feeds.send(&media.stream_id, pts, encode_tag(&frames));
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.latest_timestamps.remove(&media.stream_id);
+                self.feeds.stop_feed(&media.stream_id);
+            }
+
+            MediaNotificationContent::NewIncomingStream { .. } => (),
+        }
+    }
+}
+
 impl Drop for FfmpegHlsStep {
     fn drop(&mut self) {
         self.stream_reader.stop_all_streams();
@@ -238,26 +357,70 @@ impl Drop for FfmpegHlsStep {
 
 impl FfmpegParameterGenerator for ParamGenerator {
     fn form_parameters(&self, stream_id: &StreamId, stream_name: &str) -> FfmpegParams {
+        let playlist_name = self.stream_name.as_deref().unwrap_or(stream_name);
+        let (path, segment_filename) = match &self.signed_playlists {
+            Some(signed_playlists) => {
+                let unsigned_path = format!("{}/{}.unsigned.m3u8", self.path, playlist_name);
+                signed_playlists.start(
+                    stream_id,
+                    PathBuf::from(&unsigned_path),
+                    PathBuf::from(format!("{}/{}.m3u8", self.path, playlist_name)),
+                );
+
+                // Keep segments named after the public playlist instead of the unsigned one
+                let segment_filename = format!("{}/{}%d.ts", self.path, playlist_name);
+                (unsigned_path, Some(segment_filename))
+            }
+
+            None => (format!("{}/{}.m3u8", self.path, playlist_name), None),
+        };
+
         FfmpegParams {
             read_in_real_time: true,
             input: format!("rtmp://localhost/{}/{}", self.rtmp_app, stream_id.0),
+            timed_metadata_input: self
+                .timed_metadata_feeds
+                .as_ref()
+                .and_then(|feeds| feeds.start_feed(stream_id)),
             video_transcode: VideoTranscodeParams::Copy,
             audio_transcode: AudioTranscodeParams::Copy,
             scale: None,
             bitrate_in_kbps: None,
             target: TargetParams::Hls {
-                path: format!(
-                    "{}/{}.m3u8",
-                    self.path,
-                    self.stream_name.as_deref().unwrap_or(stream_name)
-                ),
+                path,
                 max_entries: Some(self.segment_count),
                 segment_length: self.segment_duration,
+                segment_filename,
             },
         }
     }
 }
 
+fn get_url_signer(
+    definition: &WorkflowStepDefinition,
+) -> Result<Option<UrlSigner>, StepStartupError> {
+    let ttl = match definition.parameters.get(SIGNED_URL_TTL) {
+        Some(Some(value)) => match value.parse::<u64>() {
+            Ok(seconds) if seconds > 0 => Some(Duration::from_secs(seconds)),
+            _ => return Err(StepStartupError::InvalidSignedUrlTtl(value.clone())),
+        },
+
+        Some(None) => return Err(StepStartupError::InvalidSignedUrlTtl(String::new())),
+        None => None,
+    };
+
+    match definition.parameters.get(SIGNING_KEY) {
+        Some(Some(key)) if !key.is_empty() => Ok(Some(UrlSigner::new(
+            key.as_bytes(),
+            ttl.unwrap_or(DEFAULT_SIGNED_URL_TTL),
+        ))),
+
+        Some(_) => Err(StepStartupError::NoSigningKeyProvided),
+        None if ttl.is_some() => Err(StepStartupError::SignedUrlTtlWithoutSigningKey),
+        None => Ok(None),
+    }
+}
+
 fn get_rtmp_app(id: String) -> String {
     format!("ffmpeg-hls-{}", id)
 }
@@ -0,0 +1,102 @@
+//! Keeps signed copies of the playlists ffmpeg writes. Ffmpeg writes each stream's playlist to an
+//! unsigned file, which is periodically read and written back out to the public playlist path
+//! with tokens added to every URI. Playlists are re-signed whenever ffmpeg updates them, as well
+//! as before the tokens in them get close to expiring.
+
+use mmids_core::url_signing::UrlSigner;
+use mmids_core::StreamId;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::oneshot::{channel, Receiver, Sender};
+use tracing::warn;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tracks the playlist signing of each active stream
+pub struct SignedPlaylists {
+    signer: UrlSigner,
+    active: Mutex<HashMap<StreamId, Sender<()>>>,
+}
+
+impl SignedPlaylists {
+    pub fn new(signer: UrlSigner) -> Self {
+        SignedPlaylists {
+            signer,
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Starts keeping the signed playlist up to date with the unsigned playlist. Any existing
+    /// signing for the stream is replaced, as this is called each time ffmpeg is (re)started.
+    pub fn start(&self, stream_id: &StreamId, unsigned_path: PathBuf, signed_path: PathBuf) {
+        let (sender, receiver) = channel();
+        tokio::spawn(keep_playlist_signed(
+            stream_id.clone(),
+            self.signer.clone(),
+            unsigned_path,
+            signed_path,
+            receiver,
+        ));
+
+        self.active
+            .lock()
+            .unwrap()
+            .insert(stream_id.clone(), sender);
+    }
+
+    /// Stops signing the stream's playlist
+    pub fn stop(&self, stream_id: &StreamId) {
+        self.active.lock().unwrap().remove(stream_id);
+    }
+}
+
+async fn keep_playlist_signed(
+    stream_id: StreamId,
+    signer: UrlSigner,
+    unsigned_path: PathBuf,
+    signed_path: PathBuf,
+    mut stop_receiver: Receiver<()>,
+) {
+    let resign_after = signer.ttl() / 2;
+    let mut temp_path = signed_path.clone().into_os_string();
+    temp_path.push(".tmp");
+
+    let mut last_signed: Option<(String, Instant)> = None;
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = &mut stop_receiver => break,
+            _ = interval.tick() => (),
+        }
+
+        let playlist = match tokio::fs::read_to_string(&unsigned_path).await {
+            Ok(playlist) => playlist,
+            Err(_) => continue, // ffmpeg hasn't written the playlist yet
+        };
+
+        if let Some((contents, signed_at)) = &last_signed {
+            if *contents == playlist && signed_at.elapsed() < resign_after {
+                continue;
+            }
+        }
+
+        let signed = signer.sign_playlist(&playlist, SystemTime::now());
+        let result = match tokio::fs::write(&temp_path, signed).await {
+            Ok(()) => tokio::fs::rename(&temp_path, &signed_path).await,
+            Err(error) => Err(error),
+        };
+
+        if let Err(error) = result {
+            warn!(
+                stream_id = %stream_id.0,
+                "Failed to write signed playlist '{}': {:?}", signed_path.display(), error
+            );
+
+            continue;
+        }
+
+        last_signed = Some((playlist, Instant::now()));
+    }
+}
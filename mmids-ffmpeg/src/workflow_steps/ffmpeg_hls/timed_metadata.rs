@@ -0,0 +1,124 @@
+//! Serves timed ID3 metadata to ffmpeg processes. Each stream gets its own local TCP listener
+//! that ffmpeg connects to as a secondary input, and any ID3 tags raised for the stream are
+//! written to that connection as an MPEG-TS stream.
+
+use bytes::Bytes;
+use mmids_core::timed_metadata::mpegts::TimedMetadataTsWriter;
+use mmids_core::StreamId;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::{info, warn};
+
+struct TimedId3Tag {
+    pts: Duration,
+    tag: Bytes,
+}
+
+/// Tracks the timed metadata feed of each active stream
+#[derive(Default)]
+pub struct TimedMetadataFeeds {
+    feeds: Mutex<HashMap<StreamId, UnboundedSender<TimedId3Tag>>>,
+}
+
+impl TimedMetadataFeeds {
+    /// Starts listening for ffmpeg to connect for the stream's timed metadata, and returns the
+    /// url ffmpeg should use as an input. Any existing feed for the stream is replaced, as this
+    /// is called each time ffmpeg is (re)started.
+    pub fn start_feed(&self, stream_id: &StreamId) -> Option<String> {
+        let listener = match std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+            .and_then(TcpListener::from_std)
+        {
+            Ok(listener) => listener,
+            Err(error) => {
+                warn!(
+                    stream_id = %stream_id.0,
+                    "Failed to create timed metadata listener: {:?}", error
+                );
+
+                return None;
+            }
+        };
+
+        let url = match listener.local_addr() {
+            Ok(address) => format!("tcp://{}", address),
+            Err(error) => {
+                warn!(
+                    stream_id = %stream_id.0,
+                    "Failed to get timed metadata listener address: {:?}", error
+                );
+
+                return None;
+            }
+        };
+
+        let (sender, receiver) = unbounded_channel();
+        tokio::spawn(serve_feed(stream_id.clone(), listener, receiver));
+
+        self.feeds.lock().unwrap().insert(stream_id.clone(), sender);
+
+        Some(url)
+    }
+
+    /// Sends an ID3 tag to the stream's feed, to be presented at the specified timestamp
+    pub fn send(&self, stream_id: &StreamId, pts: Duration, tag: Bytes) {
+        if let Some(sender) = self.feeds.lock().unwrap().get(stream_id) {
+            let _ = sender.send(TimedId3Tag { pts, tag });
+        }
+    }
+
+    /// Stops the stream's feed, closing any connection ffmpeg has to it
+    pub fn stop_feed(&self, stream_id: &StreamId) {
+        self.feeds.lock().unwrap().remove(stream_id);
+    }
+}
+
+async fn serve_feed(
+    stream_id: StreamId,
+    listener: TcpListener,
+    mut receiver: UnboundedReceiver<TimedId3Tag>,
+) {
+    // Tags raised before ffmpeg connects have nowhere to go, so they are dropped
+    let mut socket = loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((socket, _)) => break socket,
+                Err(error) => {
+                    warn!(
+                        stream_id = %stream_id.0,
+                        "Failed to accept timed metadata connection: {:?}", error
+                    );
+
+                    return;
+                }
+            },
+
+            tag = receiver.recv() => if tag.is_none() {
+                return;
+            },
+        }
+    };
+
+    info!(stream_id = %stream_id.0, "Ffmpeg connected for timed metadata");
+
+    let mut writer = TimedMetadataTsWriter::new();
+    if socket.write_all(&writer.write_tables()).await.is_err() {
+        return;
+    }
+
+    while let Some(tag) = receiver.recv().await {
+        let packets = writer.write_id3(tag.pts, &tag.tag);
+        if let Err(error) = socket.write_all(&packets).await {
+            warn!(
+                stream_id = %stream_id.0,
+                "Failed to write timed metadata to ffmpeg: {:?}", error
+            );
+
+            return;
+        }
+    }
+}
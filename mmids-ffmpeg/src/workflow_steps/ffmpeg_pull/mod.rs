@@ -10,7 +10,7 @@ use crate::endpoint::{
     TargetParams, VideoTranscodeParams,
 };
 use bytes::BytesMut;
-use mmids_core::codecs::{AUDIO_CODEC_AAC_RAW, VIDEO_CODEC_H264_AVC};
+use mmids_core::codecs::AUDIO_CODEC_AAC_RAW;
 use mmids_core::workflows::definitions::WorkflowStepDefinition;
 use mmids_core::workflows::metadata::{
     MediaPayloadMetadataCollection, MetadataEntry, MetadataKey, MetadataValue,
@@ -263,6 +263,7 @@ impl FfmpegPullStep {
                     content: MediaNotificationContent::NewIncomingStream {
                         stream_name: self.stream_name.clone(),
                     },
+                    annotations: Default::default(),
                 });
             }
 
@@ -272,6 +273,7 @@ impl FfmpegPullStep {
                     outputs.media.push(MediaNotification {
                         stream_id: stream_id.clone(),
                         content: MediaNotificationContent::StreamDisconnected,
+                        annotations: Default::default(),
                     });
                 }
             }
@@ -286,6 +288,7 @@ impl FfmpegPullStep {
                         content: MediaNotificationContent::Metadata {
                             data: mmids_rtmp::utils::stream_metadata_to_hash_map(metadata),
                         },
+                        annotations: Default::default(),
                     });
                 } else {
                     error!("Received stream metadata without an active stream id");
@@ -298,6 +301,7 @@ impl FfmpegPullStep {
 
             RtmpEndpointPublisherMessage::NewVideoData {
                 publisher: _,
+                codec,
                 data,
                 is_keyframe,
                 is_sequence_header,
@@ -328,12 +332,13 @@ impl FfmpegPullStep {
                         stream_id: stream_id.clone(),
                         content: MediaNotificationContent::MediaPayload {
                             media_type: MediaType::Video,
-                            payload_type: VIDEO_CODEC_H264_AVC.clone(),
+                            payload_type: codec,
                             is_required_for_decoding: is_sequence_header,
                             timestamp: Duration::from_millis(timestamp.value.into()),
                             metadata,
                             data,
                         },
+                        annotations: Default::default(),
                     });
                 } else {
                     error!("Received video data without an active stream id");
@@ -364,6 +369,7 @@ impl FfmpegPullStep {
                                 &mut self.metadata_buffer,
                             ),
                         },
+                        annotations: Default::default(),
                     });
                 } else {
                     error!("Received audio data without an active stream id");
@@ -397,6 +403,7 @@ impl FfmpegPullStep {
                     params: FfmpegParams {
                         read_in_real_time: true,
                         input: self.pull_location.clone(),
+                        timed_metadata_input: None,
                         video_transcode: VideoTranscodeParams::Copy,
                         audio_transcode: AudioTranscodeParams::Copy,
                         scale: None,
@@ -0,0 +1,331 @@
+//! This step utilizes the ffmpeg endpoint to push the same media to multiple external RTMP
+//! destinations at once, such as when multistreaming to several streaming platforms.
+//!
+//! Each destination is configured with a `target_<name>` parameter, whose value is the url to push
+//! to (e.g. `target_twitch=rtmp://live.twitch.tv/app/<key>`). Every destination gets its own ffmpeg
+//! process and its own connection retry handling, based on the retry policy parameters described
+//! in mmids-core's `retry` module. A destination that can't be reached retries (or gives up) on its
+//! own, and never affects media being sent to the other destinations.
+//!
+//! Any incoming media packets are passed on as is to the next workflow step. The connection state
+//! of each stream is reported per destination through the step's state details, keyed by the
+//! destination's name so the urls (which usually contain stream keys) are not exposed.
+
+#[cfg(test)]
+mod tests;
+
+use crate::endpoint::{
+    AudioTranscodeParams, FfmpegEndpointRequest, FfmpegParams, TargetParams, VideoTranscodeParams,
+};
+use crate::workflow_steps::ffmpeg_handler::{FfmpegHandlerGenerator, FfmpegParameterGenerator};
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::metadata::MetadataKey;
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::{
+    FuturesChannelInnerResult, FuturesChannelResult, WorkflowStepFuturesChannel,
+};
+use mmids_core::workflows::steps::retry::{RetryPolicy, RetryPolicyError, CONNECTION_STATE_DETAIL};
+use mmids_core::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use mmids_core::StreamId;
+use mmids_rtmp::rtmp_server::RtmpEndpointRequest;
+use mmids_rtmp::workflow_steps::external_stream_reader::ExternalStreamReader;
+use std::collections::HashMap;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tracing::{error, warn};
+
+pub const TARGET_PARAM_PREFIX: &str = "target_";
+
+/// Generates new instances of the ffmpeg rtmp multi output workflow step based on specified step
+/// definitions.
+pub struct FfmpegRtmpMultiOutputStepGenerator {
+    rtmp_endpoint: UnboundedSender<RtmpEndpointRequest>,
+    ffmpeg_endpoint: UnboundedSender<FfmpegEndpointRequest>,
+    is_keyframe_metadata_key: MetadataKey,
+    pts_offset_metadata_key: MetadataKey,
+}
+
+struct Destination {
+    name: String,
+    stream_reader: ExternalStreamReader,
+
+    /// The futures channel the destination's stream reader raises its futures on. Each destination
+    /// has its own channel, so resolved futures can be routed back to the reader that raised them.
+    futures_channel: WorkflowStepFuturesChannel,
+}
+
+struct FfmpegRtmpMultiOutputStep {
+    destinations: Vec<Destination>,
+}
+
+enum FutureResult {
+    FfmpegEndpointGone,
+    DestinationFutureResolved {
+        index: usize,
+        result: FuturesChannelInnerResult,
+    },
+    DestinationChannelClosed,
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "No rtmp targets specified.  At least one '{}<name>' parameter is required",
+        TARGET_PARAM_PREFIX
+    )]
+    NoTargetsProvided,
+
+    #[error("No url specified for the '{0}' target")]
+    NoTargetUrl(String),
+
+    #[error("Invalid retry policy: {0}")]
+    InvalidRetryPolicy(#[from] RetryPolicyError),
+}
+
+struct ParamGenerator {
+    rtmp_app: String,
+    target: String,
+}
+
+impl FfmpegRtmpMultiOutputStepGenerator {
+    pub fn new(
+        rtmp_endpoint: UnboundedSender<RtmpEndpointRequest>,
+        ffmpeg_endpoint: UnboundedSender<FfmpegEndpointRequest>,
+        is_keyframe_metadata_key: MetadataKey,
+        pts_offset_metadata_key: MetadataKey,
+    ) -> Self {
+        FfmpegRtmpMultiOutputStepGenerator {
+            rtmp_endpoint,
+            ffmpeg_endpoint,
+            is_keyframe_metadata_key,
+            pts_offset_metadata_key,
+        }
+    }
+}
+
+impl StepGenerator for FfmpegRtmpMultiOutputStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let mut targets = Vec::new();
+        for (key, value) in &definition.parameters {
+            if key.starts_with(TARGET_PARAM_PREFIX) && key.len() > TARGET_PARAM_PREFIX.len() {
+                let name = key[TARGET_PARAM_PREFIX.len()..].to_string();
+                match value {
+                    Some(url) if !url.trim().is_empty() => targets.push((name, url.clone())),
+                    _ => return Err(Box::new(StepStartupError::NoTargetUrl(name))),
+                }
+            }
+        }
+
+        if targets.is_empty() {
+            return Err(Box::new(StepStartupError::NoTargetsProvided));
+        }
+
+        // Parameters are unordered, so sort them to always set destinations up in the same order
+        targets.sort();
+
+        let retry_policy = RetryPolicy::from_step_definition(&definition)
+            .map_err(StepStartupError::InvalidRetryPolicy)?;
+
+        let mut destinations = Vec::new();
+        for (index, (name, target)) in targets.into_iter().enumerate() {
+            let rtmp_app = get_rtmp_app(definition.get_id().to_string(), index);
+            let param_generator = ParamGenerator {
+                rtmp_app: rtmp_app.clone(),
+                target,
+            };
+
+            let handler_generator = FfmpegHandlerGenerator::new(
+                self.ffmpeg_endpoint.clone(),
+                Box::new(param_generator),
+            )
+            .with_retry_policy(retry_policy);
+
+            let (sender, receiver) = unbounded_channel::<FuturesChannelResult>();
+            let destination_channel = WorkflowStepFuturesChannel::new(definition.get_id(), sender);
+            futures_channel.send_on_generic_unbounded_recv(
+                receiver,
+                move |future| FutureResult::DestinationFutureResolved {
+                    index,
+                    result: future.result,
+                },
+                || FutureResult::DestinationChannelClosed,
+            );
+
+            let reader = ExternalStreamReader::new(
+                Arc::new(rtmp_app),
+                self.rtmp_endpoint.clone(),
+                Box::new(handler_generator),
+                self.is_keyframe_metadata_key,
+                self.pts_offset_metadata_key,
+                &destination_channel,
+            );
+
+            destinations.push(Destination {
+                name,
+                stream_reader: reader,
+                futures_channel: destination_channel,
+            });
+        }
+
+        let step = FfmpegRtmpMultiOutputStep { destinations };
+
+        let ffmpeg_endpoint = self.ffmpeg_endpoint.clone();
+        futures_channel.send_on_generic_future_completion(async move {
+            ffmpeg_endpoint.closed().await;
+            FutureResult::FfmpegEndpointGone
+        });
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl FfmpegRtmpMultiOutputStep {
+    /// The step is in error if any destination's stream reader is, since stream readers only
+    /// error when the rtmp endpoint shared by all destinations can't be used.
+    fn status(&self) -> StepStatus {
+        self.destinations
+            .iter()
+            .map(|destination| &destination.stream_reader.status)
+            .find(|status| matches!(status, StepStatus::Error { .. }))
+            .cloned()
+            .unwrap_or(StepStatus::Active)
+    }
+}
+
+impl WorkflowStep for FfmpegRtmpMultiOutputStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        if let StepStatus::Error { message } = self.status() {
+            error!("External stream reader is in error status, so putting the step in in error status as well.");
+
+            return StepStatus::Error { message };
+        }
+
+        for future_result in inputs.notifications.drain(..) {
+            let future_result = match future_result.downcast::<FutureResult>() {
+                Ok(x) => *x,
+                Err(_) => {
+                    error!(
+                        "Rtmp multi output step received a notification that is not a known type"
+                    );
+                    return StepStatus::Error {
+                        message: "Received future result of unknown type".to_string(),
+                    };
+                }
+            };
+
+            match future_result {
+                FutureResult::FfmpegEndpointGone => {
+                    error!("Ffmpeg endpoint has disappeared.  Closing all streams");
+                    for destination in &mut self.destinations {
+                        destination.stream_reader.stop_all_streams();
+                    }
+                }
+
+                FutureResult::DestinationFutureResolved { index, result } => {
+                    let destination = match self.destinations.get_mut(index) {
+                        Some(destination) => destination,
+                        None => continue,
+                    };
+
+                    match result {
+                        FuturesChannelInnerResult::Generic(result) => destination
+                            .stream_reader
+                            .handle_resolved_future(result, &destination.futures_channel),
+
+                        FuturesChannelInnerResult::Media(_) => {
+                            warn!(
+                                destination = %destination.name,
+                                "Destination unexpectedly raised media, ignoring it"
+                            );
+                        }
+                    }
+                }
+
+                // Only happens once the step itself is being shut down
+                FutureResult::DestinationChannelClosed => (),
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            // Each stream reader passes the media it's given through as an output, so only the
+            // outputs of a single reader are kept to not duplicate media for later steps.
+            let (last, others) = match self.destinations.split_last_mut() {
+                Some(destinations) => destinations,
+                None => {
+                    outputs.media.push(media);
+                    continue;
+                }
+            };
+
+            for destination in others {
+                let mut discarded_outputs = StepOutputs::new();
+                destination.stream_reader.handle_media(
+                    media.clone(),
+                    &mut discarded_outputs,
+                    &destination.futures_channel,
+                );
+            }
+
+            last.stream_reader
+                .handle_media(media, outputs, &last.futures_channel);
+        }
+
+        self.status()
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        for destination in &self.destinations {
+            let mut states = destination
+                .stream_reader
+                .connection_states()
+                .into_iter()
+                .map(|(stream_name, state)| format!("{}: {}", stream_name, state))
+                .collect::<Vec<_>>();
+
+            states.sort();
+
+            details.insert(
+                format!("{}_{}", CONNECTION_STATE_DETAIL, destination.name),
+                states.join(", "),
+            );
+        }
+
+        details
+    }
+}
+
+impl FfmpegParameterGenerator for ParamGenerator {
+    fn form_parameters(&self, stream_id: &StreamId, _stream_name: &str) -> FfmpegParams {
+        FfmpegParams {
+            read_in_real_time: true,
+            input: format!("rtmp://localhost/{}/{}", self.rtmp_app, stream_id.0),
+            timed_metadata_input: None,
+            video_transcode: VideoTranscodeParams::Copy,
+            audio_transcode: AudioTranscodeParams::Copy,
+            scale: None,
+            bitrate_in_kbps: None,
+            target: TargetParams::Rtmp {
+                url: self.target.clone(),
+            },
+        }
+    }
+}
+
+fn get_rtmp_app(id: String, destination_index: usize) -> String {
+    format!("ffmpeg-rtmp-multi-output-{}-{}", id, destination_index)
+}
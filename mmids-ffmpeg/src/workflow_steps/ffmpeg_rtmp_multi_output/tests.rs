@@ -0,0 +1,221 @@
+use super::*;
+use crate::endpoint::{FfmpegEndpointNotification, FfmpegFailureCause};
+use bytes::{Bytes, BytesMut};
+use mmids_core::codecs::VIDEO_CODEC_H264_AVC;
+use mmids_core::test_utils;
+use mmids_core::workflows::definitions::WorkflowStepType;
+use mmids_core::workflows::metadata::common_metadata::{
+    get_is_keyframe_metadata_key, get_pts_offset_metadata_key,
+};
+use mmids_core::workflows::metadata::{MediaPayloadMetadataCollection, MetadataKeyMap};
+use mmids_core::workflows::steps::retry::RETRY_MAX_ATTEMPTS;
+use mmids_core::workflows::steps::test_utils::StepTestContext;
+use mmids_core::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use mmids_rtmp::rtmp_server::{
+    RtmpEndpointMediaData, RtmpEndpointMediaMessage, RtmpEndpointWatcherNotification,
+};
+use std::iter;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+struct TestContext {
+    step_context: StepTestContext,
+    rtmp_endpoint: UnboundedReceiver<RtmpEndpointRequest>,
+    ffmpeg_endpoint: UnboundedReceiver<FfmpegEndpointRequest>,
+
+    /// Kept open so watch registrations stay active
+    watch_notification_channels: Vec<UnboundedSender<RtmpEndpointWatcherNotification>>,
+}
+
+impl TestContext {
+    fn new(parameters: &[(&str, &str)]) -> anyhow::Result<Self> {
+        let (rtmp_sender, rtmp_receiver) = unbounded_channel();
+        let (ffmpeg_sender, ffmpeg_receiver) = unbounded_channel();
+
+        let mut metadata_map = MetadataKeyMap::new();
+        let generator = FfmpegRtmpMultiOutputStepGenerator::new(
+            rtmp_sender,
+            ffmpeg_sender,
+            get_is_keyframe_metadata_key(&mut metadata_map),
+            get_pts_offset_metadata_key(&mut metadata_map),
+        );
+
+        let mut definition = WorkflowStepDefinition {
+            step_type: WorkflowStepType("rtmp_multi_output".to_string()),
+            parameters: HashMap::new(),
+        };
+
+        for (name, value) in parameters {
+            definition
+                .parameters
+                .insert(name.to_string(), Some(value.to_string()));
+        }
+
+        let step_context = StepTestContext::new(Box::new(generator), definition)?;
+
+        Ok(TestContext {
+            step_context,
+            rtmp_endpoint: rtmp_receiver,
+            ffmpeg_endpoint: ffmpeg_receiver,
+            watch_notification_channels: Vec::new(),
+        })
+    }
+
+    /// Accepts the watch registration of each destination, returning the media channels keyed by
+    /// the rtmp app they were registered for
+    async fn accept_watch_registrations(
+        &mut self,
+        count: usize,
+    ) -> HashMap<String, UnboundedReceiver<RtmpEndpointMediaMessage>> {
+        let mut media_channels = HashMap::new();
+        for _ in 0..count {
+            let request = test_utils::expect_mpsc_response(&mut self.rtmp_endpoint).await;
+            match request {
+                RtmpEndpointRequest::ListenForWatchers {
+                    rtmp_app,
+                    media_channel,
+                    notification_channel,
+                    ..
+                } => {
+                    notification_channel
+                        .send(RtmpEndpointWatcherNotification::WatcherRegistrationSuccessful)
+                        .expect("Failed to send registration response");
+
+                    self.watch_notification_channels.push(notification_channel);
+                    media_channels.insert(rtmp_app.to_string(), media_channel);
+                }
+
+                request => panic!("Unexpected rtmp request seen: {:?}", request),
+            }
+        }
+
+        self.step_context.execute_pending_futures().await;
+
+        media_channels
+    }
+
+    /// Responds to each ffmpeg start request, failing the ones pushing to the specified url
+    async fn respond_to_ffmpeg_starts(
+        &mut self,
+        count: usize,
+        failing_url: &str,
+    ) -> Vec<UnboundedSender<FfmpegEndpointNotification>> {
+        let mut channels = Vec::new();
+        for _ in 0..count {
+            let request = test_utils::expect_mpsc_response(&mut self.ffmpeg_endpoint).await;
+            match request {
+                FfmpegEndpointRequest::StartFfmpeg {
+                    params,
+                    notification_channel,
+                    ..
+                } => {
+                    let notification = match params.target {
+                        TargetParams::Rtmp { url } if url == failing_url => {
+                            FfmpegEndpointNotification::FfmpegFailedToStart {
+                                cause: FfmpegFailureCause::FfmpegFailedToStart,
+                            }
+                        }
+
+                        _ => FfmpegEndpointNotification::FfmpegStarted,
+                    };
+
+                    notification_channel
+                        .send(notification)
+                        .expect("Failed to send ffmpeg notification");
+
+                    channels.push(notification_channel);
+                }
+
+                request => panic!("Unexpected ffmpeg request seen: {:?}", request),
+            }
+        }
+
+        self.step_context.execute_pending_futures().await;
+
+        channels
+    }
+}
+
+fn video(stream_id: &StreamId) -> MediaNotification {
+    MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Video,
+            payload_type: VIDEO_CODEC_H264_AVC.clone(),
+            timestamp: Duration::from_millis(10),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
+            data: Bytes::from(vec![1, 2, 3]),
+            is_required_for_decoding: false,
+        },
+        annotations: Default::default(),
+    }
+}
+
+#[test]
+fn step_fails_to_build_when_no_targets_specified() {
+    match TestContext::new(&[]) {
+        Err(_) => (),
+        Ok(_) => panic!("Expected failure"),
+    }
+}
+
+#[tokio::test]
+async fn failing_destination_does_not_stop_media_to_other_destination() {
+    let mut context = TestContext::new(&[
+        ("target_broken", "rtmp://broken/live/key"),
+        ("target_working", "rtmp://working/live/key"),
+        (RETRY_MAX_ATTEMPTS, "1"),
+    ])
+    .unwrap();
+
+    let stream_id = StreamId(Arc::new("abc".to_string()));
+    context.step_context.execute_with_media(MediaNotification {
+        stream_id: stream_id.clone(),
+        content: MediaNotificationContent::NewIncomingStream {
+            stream_name: Arc::new("def".to_string()),
+        },
+        annotations: Default::default(),
+    });
+
+    let mut media_channels = context.accept_watch_registrations(2).await;
+    let _ffmpeg_channels = context
+        .respond_to_ffmpeg_starts(2, "rtmp://broken/live/key")
+        .await;
+
+    let details = context.step_context.step.get_state_details();
+    assert_eq!(
+        details.get("connection_state_broken"),
+        Some(&"def: failed".to_string()),
+        "Unexpected state for the broken destination"
+    );
+
+    assert_eq!(
+        details.get("connection_state_working"),
+        Some(&"def: connected".to_string()),
+        "Unexpected state for the working destination"
+    );
+
+    let media = video(&stream_id);
+    context.step_context.execute_with_media(media.clone());
+    assert_eq!(
+        context.step_context.media_outputs,
+        vec![media],
+        "Expected media to be passed through once"
+    );
+
+    let working_app = media_channels
+        .keys()
+        .find(|app| app.ends_with("-1"))
+        .cloned()
+        .expect("No registration for the working destination");
+
+    let working_channel = media_channels.get_mut(&working_app).unwrap();
+    let message = test_utils::expect_mpsc_response(working_channel).await;
+    match message.data {
+        RtmpEndpointMediaData::NewVideoData { data, .. } => {
+            assert_eq!(data, Bytes::from(vec![1, 2, 3]), "Unexpected video data");
+        }
+
+        data => panic!("Unexpected media data: {:?}", data),
+    }
+}
@@ -3,6 +3,11 @@
 //!
 //! Any incoming media packets are passed to the rtmp endpoint for sending to ffmpeg, and then
 //! passed along as is for the next workflow step.
+//!
+//! When ffmpeg stops or fails to start (e.g. because the target can't be reached) it's restarted
+//! based on the retry policy parameters described in mmids-core's `retry` module. Once the policy
+//! gives up, a policy set to disconnect stops pushing the stream entirely. The connection state of
+//! each stream is reported through the step's state details.
 
 use crate::endpoint::{
     AudioTranscodeParams, FfmpegEndpointRequest, FfmpegParams, TargetParams, VideoTranscodeParams,
@@ -12,12 +17,14 @@ use mmids_core::workflows::definitions::WorkflowStepDefinition;
 use mmids_core::workflows::metadata::MetadataKey;
 use mmids_core::workflows::steps::factory::StepGenerator;
 use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::retry::{RetryPolicy, RetryPolicyError, CONNECTION_STATE_DETAIL};
 use mmids_core::workflows::steps::{
     StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
 };
 use mmids_core::StreamId;
 use mmids_rtmp::rtmp_server::RtmpEndpointRequest;
 use mmids_rtmp::workflow_steps::external_stream_reader::ExternalStreamReader;
+use std::collections::HashMap;
 use std::sync::Arc;
 use thiserror::Error;
 use tokio::sync::mpsc::UnboundedSender;
@@ -47,6 +54,9 @@ impl StepFutureResult for FutureResult {}
 enum StepStartupError {
     #[error("No rtmp target specified.  A 'target' parameter is required")]
     NoTargetProvided,
+
+    #[error("Invalid retry policy: {0}")]
+    InvalidRetryPolicy(#[from] RetryPolicyError),
 }
 
 struct ParamGenerator {
@@ -81,13 +91,17 @@ impl StepGenerator for FfmpegRtmpPushStepGenerator {
             _ => return Err(Box::new(StepStartupError::NoTargetProvided)),
         };
 
+        let retry_policy = RetryPolicy::from_step_definition(&definition)
+            .map_err(StepStartupError::InvalidRetryPolicy)?;
+
         let param_generator = ParamGenerator {
             rtmp_app: get_rtmp_app(definition.get_id().to_string()),
             target: target.to_string(),
         };
 
         let handler_generator =
-            FfmpegHandlerGenerator::new(self.ffmpeg_endpoint.clone(), Box::new(param_generator));
+            FfmpegHandlerGenerator::new(self.ffmpeg_endpoint.clone(), Box::new(param_generator))
+                .with_retry_policy(retry_policy);
 
         let reader = ExternalStreamReader::new(
             Arc::new(format!("ffmpeg-rtmp-push-{}", definition.get_id())),
@@ -151,6 +165,22 @@ impl WorkflowStep for FfmpegRtmpPushStep {
 
         self.stream_reader.status.clone()
     }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut states = self
+            .stream_reader
+            .connection_states()
+            .into_iter()
+            .map(|(stream_name, state)| format!("{}: {}", stream_name, state))
+            .collect::<Vec<_>>();
+
+        states.sort();
+
+        let mut details = HashMap::new();
+        details.insert(CONNECTION_STATE_DETAIL.to_string(), states.join(", "));
+
+        details
+    }
 }
 
 impl FfmpegParameterGenerator for ParamGenerator {
@@ -158,6 +188,7 @@ impl FfmpegParameterGenerator for ParamGenerator {
         FfmpegParams {
             read_in_real_time: true,
             input: format!("rtmp://localhost/{}/{}", self.rtmp_app, stream_id.0),
+            timed_metadata_input: None,
             video_transcode: VideoTranscodeParams::Copy,
             audio_transcode: AudioTranscodeParams::Copy,
             scale: None,
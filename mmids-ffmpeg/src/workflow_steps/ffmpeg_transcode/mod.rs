@@ -19,7 +19,7 @@ use crate::endpoint::{
     H264Preset, TargetParams, VideoScale, VideoTranscodeParams,
 };
 use bytes::BytesMut;
-use mmids_core::codecs::{AUDIO_CODEC_AAC_RAW, VIDEO_CODEC_H264_AVC};
+use mmids_core::codecs::AUDIO_CODEC_AAC_RAW;
 use mmids_core::workflows::definitions::WorkflowStepDefinition;
 use mmids_core::workflows::metadata::{
     MediaPayloadMetadataCollection, MetadataEntry, MetadataKey, MetadataValue,
@@ -600,6 +600,7 @@ impl FfmpegTranscoder {
                         read_in_real_time: true,
                         bitrate_in_kbps: self.bitrate,
                         input: format!("rtmp://localhost/{}/{}", source_rtmp_app, stream.id.0),
+                        timed_metadata_input: None,
                         video_transcode: self.video_codec_params.clone(),
                         audio_transcode: self.audio_codec_params.clone(),
                         scale: self.video_scale_params.clone(),
@@ -732,6 +733,7 @@ impl FfmpegTranscoder {
                 } => (),
 
                 RtmpEndpointWatcherNotification::StreamKeyBecameInactive { stream_key: _ } => (),
+                RtmpEndpointWatcherNotification::WatcherStopped { .. } => (),
 
                 RtmpEndpointWatcherNotification::WatcherRequiringApproval { .. } => {
                     error!("Watcher requires approval but all watchers should be auto-approved");
@@ -791,11 +793,13 @@ impl FfmpegTranscoder {
                     outputs.media.push(MediaNotification {
                         stream_id: stream_id.clone(),
                         content: MediaNotificationContent::Metadata { data: metadata },
+                        annotations: Default::default(),
                     });
                 }
 
                 RtmpEndpointPublisherMessage::NewVideoData {
                     publisher: _,
+                    codec,
                     data,
                     is_sequence_header,
                     is_keyframe,
@@ -825,12 +829,13 @@ impl FfmpegTranscoder {
                         stream_id: stream_id.clone(),
                         content: MediaNotificationContent::MediaPayload {
                             media_type: MediaType::Video,
-                            payload_type: VIDEO_CODEC_H264_AVC.clone(),
+                            payload_type: codec,
                             timestamp: Duration::from_millis(timestamp.value as u64),
                             is_required_for_decoding: is_sequence_header,
                             data,
                             metadata,
                         },
+                        annotations: Default::default(),
                     })
                 }
 
@@ -852,6 +857,7 @@ impl FfmpegTranscoder {
                             &mut self.metadata_buffer,
                         ),
                     },
+                    annotations: Default::default(),
                 }),
 
                 RtmpEndpointPublisherMessage::PublisherRequiringApproval { .. } => {
@@ -946,6 +952,10 @@ impl WorkflowStep for FfmpegTranscoder {
 
         self.status.clone()
     }
+
+    fn get_active_pipeline_count(&self) -> usize {
+        self.active_streams.len()
+    }
 }
 
 impl Drop for FfmpegTranscoder {
@@ -327,6 +327,7 @@ async fn rtmp_watch_registration_raised_on_new_stream() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let request = test_utils::expect_mpsc_response(&mut context.rtmp_endpoint).await;
@@ -355,6 +356,7 @@ async fn rtmp_publish_registration_raised_after_watch_accepted() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let _watch_channels = context.accept_watch_registration().await;
@@ -385,6 +387,7 @@ async fn ffmpeg_request_raised_after_publish_accepted() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let _watch_channels = context.accept_watch_registration().await;
@@ -411,6 +414,7 @@ async fn h264_with_preset_passed_to_ffmpeg() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let _watch_channels = context.accept_watch_registration().await;
@@ -436,6 +440,7 @@ async fn video_copy_passed_to_ffmpeg() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let _watch_channels = context.accept_watch_registration().await;
@@ -459,6 +464,7 @@ async fn aac_acodec_passed_to_ffmpeg() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let _watch_channels = context.accept_watch_registration().await;
@@ -482,6 +488,7 @@ async fn copy_acodec_passed_to_ffmpeg() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let _watch_channels = context.accept_watch_registration().await;
@@ -509,6 +516,7 @@ async fn size_passed_to_ffmpeg() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let _watch_channels = context.accept_watch_registration().await;
@@ -535,6 +543,7 @@ async fn bitrate_passed_to_ffmpeg() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let _watch_channels = context.accept_watch_registration().await;
@@ -555,6 +564,7 @@ async fn ffmpeg_always_told_to_read_in_real_time() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let _watch_channels = context.accept_watch_registration().await;
@@ -577,6 +587,7 @@ async fn ffmpeg_instructed_to_read_from_rtmp() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let _watch_channels = context.accept_watch_registration().await;
@@ -607,6 +618,7 @@ async fn if_ffmpeg_process_stops_unexpectedly_it_starts_again_with_same_id_and_p
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let _watch_channels = context.accept_watch_registration().await;
@@ -637,6 +649,7 @@ async fn stream_started_notification_passed_through_immediately() {
             content: MediaNotificationContent::NewIncomingStream {
                 stream_name: Arc::new("abc".to_string()),
             },
+            annotations: Default::default(),
         });
 }
 
@@ -650,6 +663,7 @@ async fn disconnection_notification_passed_through_immediately() {
         .assert_media_passed_through(MediaNotification {
             stream_id: StreamId(Arc::new("abc".to_string())),
             content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
         });
 }
 #[tokio::test]
@@ -664,6 +678,7 @@ async fn metadata_notification_passed_as_input_does_not_get_passed_as_output() {
             content: MediaNotificationContent::Metadata {
                 data: HashMap::new(),
             },
+            annotations: Default::default(),
         });
 }
 
@@ -684,6 +699,7 @@ async fn video_notification_passed_as_input_does_not_get_passed_as_output() {
                 metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
                 data: Bytes::from(vec![1, 2]),
             },
+            annotations: Default::default(),
         });
 }
 
@@ -704,6 +720,7 @@ async fn audio_notification_passed_as_input_does_not_get_passed_as_output() {
                 payload_type: AUDIO_CODEC_AAC_RAW.clone(),
                 metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
             },
+            annotations: Default::default(),
         });
 }
 
@@ -717,6 +734,7 @@ async fn video_packet_sent_to_watcher_media_channel() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let (_notification, mut media_channel) = context.accept_watch_registration().await;
@@ -733,6 +751,7 @@ async fn video_packet_sent_to_watcher_media_channel() {
             metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
             data: Bytes::from_static(&[1, 2]),
         },
+        annotations: Default::default(),
     };
 
     context.step_context.execute_with_media(media.clone());
@@ -763,6 +782,7 @@ async fn audio_packet_sent_to_watcher_media_channel() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let (_notification, mut media_channel) = context.accept_watch_registration().await;
@@ -779,6 +799,7 @@ async fn audio_packet_sent_to_watcher_media_channel() {
             payload_type: AUDIO_CODEC_AAC_RAW.clone(),
             metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
         },
+        annotations: Default::default(),
     };
 
     context.step_context.execute_with_media(media.clone());
@@ -809,6 +830,7 @@ async fn metadata_packet_sent_to_watcher_media_channel() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let (_notification, mut media_channel) = context.accept_watch_registration().await;
@@ -820,6 +842,7 @@ async fn metadata_packet_sent_to_watcher_media_channel() {
         content: MediaNotificationContent::Metadata {
             data: HashMap::new(),
         },
+        annotations: Default::default(),
     };
 
     context.step_context.execute_with_media(media.clone());
@@ -851,6 +874,7 @@ async fn video_packet_with_other_stream_id_not_sent_to_watcher_media_channel() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let (_notification, mut media_channel) = context.accept_watch_registration().await;
@@ -867,6 +891,7 @@ async fn video_packet_with_other_stream_id_not_sent_to_watcher_media_channel() {
             is_required_for_decoding: true,
             metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
         },
+        annotations: Default::default(),
     };
 
     context.step_context.execute_with_media(media.clone());
@@ -884,6 +909,7 @@ async fn video_packet_from_publisher_passed_as_media_output() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let _watch_channels = context.accept_watch_registration().await;
@@ -893,6 +919,7 @@ async fn video_packet_from_publisher_passed_as_media_output() {
     publish_channel
         .send(RtmpEndpointPublisherMessage::NewVideoData {
             publisher: ConnectionId(Arc::new("connection".to_string())),
+            codec: VIDEO_CODEC_H264_AVC.clone(),
             data: Bytes::from(vec![1, 2, 3]),
             timestamp: RtmpTimestamp::new(5),
             is_keyframe: true,
@@ -974,6 +1001,7 @@ async fn audio_packet_from_publisher_passed_as_media_output() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let _watch_channels = context.accept_watch_registration().await;
@@ -1026,6 +1054,7 @@ async fn metadata_packet_from_publisher_passed_as_media_output() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let _watch_channels = context.accept_watch_registration().await;
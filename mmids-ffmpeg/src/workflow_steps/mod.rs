@@ -3,5 +3,6 @@
 pub mod ffmpeg_handler;
 pub mod ffmpeg_hls;
 pub mod ffmpeg_pull;
+pub mod ffmpeg_rtmp_multi_output;
 pub mod ffmpeg_rtmp_push;
 pub mod ffmpeg_transcode;
@@ -1,12 +1,12 @@
 use crate::encoders::{AudioEncoder, AudioEncoderGenerator, SampleResult};
 use crate::utils::{
-    create_gst_element, get_codec_data_from_element, set_gst_buffer,
-    set_source_audio_sequence_header,
+    configure_latency, create_gst_element, get_codec_data_from_element, get_latency,
+    push_raw_sample_to_source, set_gst_buffer, set_source_audio_sequence_header,
 };
 use anyhow::{anyhow, Context, Result};
 use bytes::{Bytes, BytesMut};
 use gstreamer::prelude::*;
-use gstreamer::{Element, FlowError, FlowSuccess, Pipeline};
+use gstreamer::{Element, FlowError, FlowSuccess, Pipeline, Sample};
 use gstreamer_app::{AppSink, AppSinkCallbacks, AppSrc};
 use mmids_core::codecs::AUDIO_CODEC_AAC_RAW;
 use mmids_core::workflows::metadata::MediaPayloadMetadataCollection;
@@ -22,9 +22,22 @@ use tracing::{error, warn};
 ///
 /// This encoder supports the following optional parameters:
 /// * `bitrate` - The average **bytes** per second to target.
+/// * `latency_ms` - How many milliseconds of media the pipeline's queues may hold.
 pub struct AvencAacEncoderGenerator {}
 
 impl AudioEncoderGenerator for AvencAacEncoderGenerator {
+    fn required_elements(&self) -> &[&'static str] {
+        &[
+            "appsrc",
+            "queue",
+            "decodebin",
+            "audioconvert",
+            "avenc_aac",
+            "aacparse",
+            "appsink",
+        ]
+    }
+
     fn create(
         &self,
         pipeline: &Pipeline,
@@ -37,6 +50,10 @@ impl AudioEncoderGenerator for AvencAacEncoderGenerator {
             pipeline,
         )?))
     }
+
+    fn accepts_raw_audio(&self) -> bool {
+        true
+    }
 }
 
 struct AvencAacEncoder {
@@ -50,6 +67,7 @@ impl AvencAacEncoder {
         pipeline: &Pipeline,
     ) -> Result<AvencAacEncoder> {
         let bitrate = get_number(parameters, "bitrate");
+        let latency = get_latency(parameters)?;
 
         let appsrc = create_gst_element("appsrc")?;
         let queue = create_gst_element("queue")?;
@@ -77,6 +95,10 @@ impl AvencAacEncoder {
         Element::link_many(&[&convert, &encoder, &output_parser, &appsink])
             .with_context(|| "Failed to link avenc_aac -> aacparse -> appsink")?;
 
+        if let Some(latency) = latency {
+            configure_latency(pipeline, &[&queue, &decodebin], latency);
+        }
+
         // decodebin's pad is added dynamically
         let link_destination = convert;
         decodebin.connect_pad_added(move |src, src_pad| {
@@ -149,6 +171,11 @@ impl AudioEncoder for AvencAacEncoder {
 
         Ok(())
     }
+
+    fn push_raw_sample(&self, sample: Sample) -> Result<()> {
+        // `decodebin` passes raw audio straight through to the encoder
+        push_raw_sample_to_source(&self.source, sample)
+    }
 }
 
 fn get_number(parameters: &HashMap<String, Option<String>>, key: &str) -> Option<i32> {
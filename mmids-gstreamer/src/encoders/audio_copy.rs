@@ -1,5 +1,5 @@
 use crate::encoders::{AudioEncoder, AudioEncoderGenerator, SampleResult};
-use crate::utils::{create_gst_element, set_gst_buffer};
+use crate::utils::{configure_latency, create_gst_element, get_latency, set_gst_buffer};
 use anyhow::{anyhow, Context, Result};
 use bytes::{Bytes, BytesMut};
 use gstreamer::prelude::*;
@@ -15,16 +15,27 @@ use tokio::sync::mpsc::UnboundedSender;
 use tracing::error;
 
 /// Generates an audio encoder that passes audio packets to the output channel without modification.
+///
+/// The optional `latency_ms` parameter sets how many milliseconds of audio the pipeline's queue
+/// may hold.
 pub struct AudioCopyEncoderGenerator {}
 
 impl AudioEncoderGenerator for AudioCopyEncoderGenerator {
+    fn required_elements(&self) -> &[&'static str] {
+        &["appsrc", "queue", "appsink"]
+    }
+
     fn create(
         &self,
         pipeline: &Pipeline,
-        _parameters: &HashMap<String, Option<String>>,
+        parameters: &HashMap<String, Option<String>>,
         media_sender: UnboundedSender<MediaNotificationContent>,
     ) -> Result<Box<dyn AudioEncoder + Send>> {
-        Ok(Box::new(AudioCopyEncoder::new(media_sender, pipeline)?))
+        Ok(Box::new(AudioCopyEncoder::new(
+            media_sender,
+            parameters,
+            pipeline,
+        )?))
     }
 }
 
@@ -41,8 +52,11 @@ struct AudioCopyEncoder {
 impl AudioCopyEncoder {
     fn new(
         media_sender: UnboundedSender<MediaNotificationContent>,
+        parameters: &HashMap<String, Option<String>>,
         pipeline: &Pipeline,
     ) -> Result<AudioCopyEncoder> {
+        let latency = get_latency(parameters)?;
+
         // While we won't be mutating the stream, we want to pass it through a gstreamer pipeline
         // so the packets will be synchronized with possibly transcoded video delay.
 
@@ -57,6 +71,10 @@ impl AudioCopyEncoder {
         Element::link_many(&[&appsrc, &queue, &appsink])
             .with_context(|| "Failed to link audio copy encoder's elements together")?;
 
+        if let Some(latency) = latency {
+            configure_latency(pipeline, &[&queue], latency);
+        }
+
         let appsink = appsink
             .dynamic_cast::<AppSink>()
             .map_err(|_| anyhow!("Audio copy encoder's appsink could not be casted"))?;
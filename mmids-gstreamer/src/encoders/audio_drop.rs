@@ -1,7 +1,7 @@
 use crate::encoders::{AudioEncoder, AudioEncoderGenerator};
 use anyhow::Result;
 use bytes::Bytes;
-use gstreamer::Pipeline;
+use gstreamer::{Pipeline, Sample};
 use mmids_core::workflows::MediaNotificationContent;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -20,6 +20,10 @@ impl AudioEncoderGenerator for AudioDropEncoderGenerator {
     ) -> Result<Box<dyn AudioEncoder + Send>> {
         Ok(Box::new(AudioDropEncoder {}))
     }
+
+    fn accepts_raw_audio(&self) -> bool {
+        true
+    }
 }
 
 struct AudioDropEncoder {}
@@ -35,4 +39,8 @@ impl AudioEncoder for AudioDropEncoder {
         // Do nothing with the data since we are dropping the audio stream
         Ok(())
     }
+
+    fn push_raw_sample(&self, _sample: Sample) -> Result<()> {
+        Ok(())
+    }
 }
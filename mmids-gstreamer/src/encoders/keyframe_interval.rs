@@ -0,0 +1,218 @@
+//! Support for forcing encoders to produce keyframes at a minimum interval. Sources with very
+//! long GOPs make it take a long time for new viewers to start playback, so encoders can be told to
+//! force a keyframe whenever too much time has passed since the last forced keyframe.
+//!
+//! Keyframes are requested by sending a `GstForceKeyUnit` event into the encoder's sink pad, which
+//! all encoders based on `GstVideoEncoder` (such as `x264enc` and `x265enc`) respond to by encoding
+//! the next frame as an IDR frame.
+
+use anyhow::{anyhow, Context, Result};
+use gstreamer::event::CustomDownstream;
+use gstreamer::prelude::*;
+use gstreamer::{Element, PadProbeData, PadProbeReturn, PadProbeType, Structure};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::warn;
+
+/// Decides which frames need a keyframe forced based on their presentation timestamps
+pub(crate) struct KeyframeScheduler {
+    interval: Duration,
+    last_keyframe_pts: Option<Duration>,
+}
+
+impl KeyframeScheduler {
+    pub fn new(interval: Duration) -> Self {
+        KeyframeScheduler {
+            interval,
+            last_keyframe_pts: None,
+        }
+    }
+
+    /// Returns true if the frame with the specified presentation timestamp should be encoded as a
+    /// keyframe. The first frame is never forced, as encoders always start with a keyframe.
+    pub fn should_force_keyframe(&mut self, pts: Duration) -> bool {
+        let last = match self.last_keyframe_pts {
+            Some(last) => last,
+            None => {
+                self.last_keyframe_pts = Some(pts);
+                return false;
+            }
+        };
+
+        if pts < last {
+            // Timestamps went backwards (e.g. the source restarted), so start the interval over
+            self.last_keyframe_pts = Some(pts);
+            return false;
+        }
+
+        if pts - last >= self.interval {
+            self.last_keyframe_pts = Some(pts);
+            return true;
+        }
+
+        false
+    }
+}
+
+/// Reads the optional `min_keyframe_interval` encoder parameter, which is the maximum number of
+/// seconds allowed between keyframes.
+pub(crate) fn get_min_keyframe_interval(
+    parameters: &HashMap<String, Option<String>>,
+) -> Result<Option<Duration>> {
+    match parameters.get("min_keyframe_interval") {
+        Some(Some(value)) => match value.parse::<f64>() {
+            Ok(seconds) if seconds.is_finite() && seconds > 0.0 => {
+                Ok(Some(Duration::from_secs_f64(seconds)))
+            }
+
+            _ => Err(anyhow!(
+                "min_keyframe_interval had a value of '{value}', which is not a number \
+                greater than zero"
+            )),
+        },
+
+        _ => Ok(None),
+    }
+}
+
+/// Reads the optional `fixed_gop` encoder parameter. When enabled, the encoder may only produce
+/// keyframes where they are forced, so every GOP is exactly `min_keyframe_interval` long. Specifying
+/// the parameter without a value enables it.
+pub(crate) fn get_fixed_gop(
+    parameters: &HashMap<String, Option<String>>,
+    min_keyframe_interval: Option<Duration>,
+) -> Result<bool> {
+    let fixed_gop = match parameters.get("fixed_gop") {
+        Some(None) => true,
+        Some(Some(value)) => match value.trim().to_lowercase().as_str() {
+            "true" => true,
+            "false" => false,
+            _ => {
+                return Err(anyhow!(
+                    "fixed_gop had a value of '{value}', which is not 'true' or 'false'"
+                ))
+            }
+        },
+
+        None => false,
+    };
+
+    if fixed_gop && min_keyframe_interval.is_none() {
+        return Err(anyhow!(
+            "fixed_gop requires min_keyframe_interval to be specified"
+        ));
+    }
+
+    Ok(fixed_gop)
+}
+
+/// Makes the specified encoder element produce a keyframe at least as often as the specified
+/// interval, based on the presentation timestamps of the raw frames going into it.
+pub(crate) fn force_keyframes_at_interval(encoder: &Element, interval: Duration) -> Result<()> {
+    let sink_pad = encoder
+        .static_pad("sink")
+        .with_context(|| format!("Encoder '{}' has no sink pad", encoder.name()))?;
+
+    let scheduler = Mutex::new(KeyframeScheduler::new(interval));
+    sink_pad
+        .add_probe(PadProbeType::BUFFER, move |pad, info| {
+            let pts = match &info.data {
+                Some(PadProbeData::Buffer(buffer)) => buffer.pts(),
+                _ => None,
+            };
+
+            if let Some(pts) = pts {
+                let pts = Duration::from_nanos(pts.nseconds());
+                if scheduler.lock().unwrap().should_force_keyframe(pts) {
+                    let structure = Structure::builder("GstForceKeyUnit")
+                        .field("all-headers", true)
+                        .build();
+
+                    if !pad.send_event(CustomDownstream::builder(structure).build()) {
+                        warn!("Encoder did not accept the force key unit event");
+                    }
+                }
+            }
+
+            PadProbeReturn::Ok
+        })
+        .with_context(|| "Failed to add keyframe probe to the encoder's sink pad")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gstreamer::{BufferFlags, Pipeline, State};
+    use gstreamer_app::AppSink;
+
+    #[test]
+    fn scheduler_forces_keyframe_once_interval_elapses() {
+        let mut scheduler = KeyframeScheduler::new(Duration::from_secs(2));
+        let forced = (0..=150)
+            .map(|frame| Duration::from_millis(frame * 1000 / 30))
+            .filter(|pts| scheduler.should_force_keyframe(*pts))
+            .map(|pts| pts.as_millis())
+            .collect::<Vec<_>>();
+
+        assert_eq!(forced, vec![2000, 4000], "Unexpected forced keyframes");
+    }
+
+    #[test]
+    fn scheduler_restarts_interval_when_timestamps_go_backwards() {
+        let mut scheduler = KeyframeScheduler::new(Duration::from_secs(2));
+        assert!(!scheduler.should_force_keyframe(Duration::from_secs(10)));
+        assert!(!scheduler.should_force_keyframe(Duration::from_secs(1)));
+        assert!(!scheduler.should_force_keyframe(Duration::from_secs(2)));
+        assert!(scheduler.should_force_keyframe(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn encoder_produces_keyframes_at_configured_interval() {
+        gstreamer::init().unwrap();
+
+        // A high key-int-max with no scene changes means the encoder would only produce a single
+        // keyframe on its own.
+        let pipeline = gstreamer::parse_launch(
+            "videotestsrc num-buffers=90 ! video/x-raw,width=160,height=120,framerate=30/1 \
+                ! x264enc name=encoder key-int-max=1000 tune=zerolatency \
+                ! appsink name=sink sync=false",
+        )
+        .unwrap()
+        .dynamic_cast::<Pipeline>()
+        .unwrap();
+
+        let encoder = pipeline.by_name("encoder").unwrap();
+        force_keyframes_at_interval(&encoder, Duration::from_millis(500)).unwrap();
+
+        let sink = pipeline
+            .by_name("sink")
+            .unwrap()
+            .dynamic_cast::<AppSink>()
+            .unwrap();
+
+        pipeline.set_state(State::Playing).unwrap();
+
+        let mut keyframe_times = Vec::new();
+        while let Ok(sample) = sink.pull_sample() {
+            let buffer = sample.buffer().unwrap();
+            if !buffer.flags().contains(BufferFlags::DELTA_UNIT) {
+                keyframe_times.push(buffer.pts().unwrap().mseconds());
+            }
+        }
+
+        pipeline.set_state(State::Null).unwrap();
+
+        // x264enc offsets its timestamps, so compare against the first keyframe
+        let first = keyframe_times[0];
+        let offsets = keyframe_times.iter().map(|x| x - first).collect::<Vec<_>>();
+
+        assert_eq!(
+            offsets,
+            vec![0, 500, 1000, 1500, 2000, 2500],
+            "Unexpected keyframe timestamps"
+        );
+    }
+}
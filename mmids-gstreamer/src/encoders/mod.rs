@@ -4,13 +4,16 @@
 mod audio_avenc_aac;
 mod audio_copy;
 mod audio_drop;
+mod keyframe_interval;
 mod video_copy;
 mod video_drop;
 mod video_x264;
+mod video_x265;
 
-use anyhow::{Context, Result};
+use crate::utils::{ensure_elements_available, GstElementError};
+use anyhow::{anyhow, Context, Result};
 use bytes::Bytes;
-use gstreamer::{Format, GenericFormattedValue, Pipeline};
+use gstreamer::{Format, GenericFormattedValue, Pipeline, Sample};
 use gstreamer_app::AppSink;
 use mmids_core::workflows::MediaNotificationContent;
 use mmids_core::VideoTimestamp;
@@ -27,6 +30,7 @@ pub use audio_drop::AudioDropEncoderGenerator;
 pub use video_copy::VideoCopyEncoderGenerator;
 pub use video_drop::VideoDropEncoderGenerator;
 pub use video_x264::X264EncoderGenerator;
+pub use video_x265::X265EncoderGenerator;
 
 /// An encoder that processes video in its pipeline.  It is expected that each instance of an
 /// encoder is used by one stream at a time, even if multiple media streams require the same
@@ -40,6 +44,18 @@ pub trait VideoEncoder {
         timestamp: VideoTimestamp,
         is_sequence_header: bool,
     ) -> Result<()>;
+
+    /// How much media is waiting in the encoder's input queue, for encoders that queue frames
+    /// before encoding them
+    fn queue_level(&self) -> Option<EncoderQueueLevel> {
+        None
+    }
+
+    /// Pushes a decoded video frame into the encoder's pipeline, with the sample's caps describing
+    /// the frame's format.  Only supported by encoders whose generator accepts raw video.
+    fn push_raw_sample(&self, _sample: Sample) -> Result<()> {
+        Err(anyhow!("The encoder does not accept raw video"))
+    }
 }
 
 /// An encoder that processes audio in its pipeline.  It is expected that each instance of an
@@ -54,8 +70,32 @@ pub trait AudioEncoder {
         timestamp: Duration,
         is_sequence_header: bool,
     ) -> Result<()>;
+
+    /// Pushes decoded audio into the encoder's pipeline, with the sample's caps describing the
+    /// audio's format.  Only supported by encoders whose generator accepts raw audio.
+    fn push_raw_sample(&self, _sample: Sample) -> Result<()> {
+        Err(anyhow!("The encoder does not accept raw audio"))
+    }
+}
+
+/// How much media is queued up in an encoder waiting to be encoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EncoderQueueLevel {
+    pub queued_bytes: u64,
+    pub max_bytes: u64,
 }
 
+/// Error returned when media is pushed into an encoder whose input queue is full, which happens
+/// when the encoder can't keep up with the incoming media. The media was not queued, and the
+/// caller should drop it rather than waiting on the encoder.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error(
+    "The encoder's input queue is full ({} of {} bytes queued)",
+    .0.queued_bytes,
+    .0.max_bytes
+)]
+pub struct EncoderQueueFullError(pub EncoderQueueLevel);
+
 /// Errors that can occur when registering an encoder with the encoder factory
 #[derive(thiserror::Error, Debug)]
 pub enum EncoderFactoryRegistrationError {
@@ -69,6 +109,15 @@ pub enum EncoderFactoryCreationError {
     #[error("No encoder exists with the name '{0}'")]
     NoEncoderWithName(String),
 
+    #[error("The encoder '{encoder}' cannot be used: {error}")]
+    MissingElement {
+        encoder: String,
+        error: GstElementError,
+    },
+
+    #[error("The encoder '{0}' cannot encode decoded media")]
+    RawMediaNotSupported(String),
+
     #[error("Creation of the encoder failed")]
     CreationFailed(#[from] anyhow::Error),
 }
@@ -81,6 +130,18 @@ pub trait VideoEncoderGenerator {
         parameters: &HashMap<String, Option<String>>,
         media_sender: UnboundedSender<MediaNotificationContent>,
     ) -> anyhow::Result<Box<dyn VideoEncoder + Send>>;
+
+    /// The gstreamer elements that instances of the encoder are built from.  These are checked
+    /// before the encoder is used, so a missing plugin is reported when a workflow is started
+    /// instead of when the first stream arrives.
+    fn required_elements(&self) -> &[&'static str] {
+        &[]
+    }
+
+    /// Whether instances of the encoder can encode decoded video pushed in as raw samples
+    fn accepts_raw_video(&self) -> bool {
+        false
+    }
 }
 
 /// A type that can generate a new instance for a specific audio encoder.
@@ -91,6 +152,18 @@ pub trait AudioEncoderGenerator {
         parameters: &HashMap<String, Option<String>>,
         media_sender: UnboundedSender<MediaNotificationContent>,
     ) -> anyhow::Result<Box<dyn AudioEncoder + Send>>;
+
+    /// The gstreamer elements that instances of the encoder are built from.  These are checked
+    /// before the encoder is used, so a missing plugin is reported when a workflow is started
+    /// instead of when the first stream arrives.
+    fn required_elements(&self) -> &[&'static str] {
+        &[]
+    }
+
+    /// Whether instances of the encoder can encode decoded audio pushed in as raw samples
+    fn accepts_raw_audio(&self) -> bool {
+        false
+    }
 }
 
 /// Allows encoder generators to be registered and be referred to via a name that given at
@@ -142,6 +215,58 @@ impl EncoderFactory {
         Ok(())
     }
 
+    /// Verifies that a video encoder is registered with the specified name, and that all the
+    /// gstreamer elements it requires are available.
+    pub fn validate_video_encoder(&self, name: &str) -> Result<(), EncoderFactoryCreationError> {
+        match self.video_encoders.get(name) {
+            Some(generator) => check_required_elements(name, generator.required_elements()),
+            None => Err(EncoderFactoryCreationError::NoEncoderWithName(
+                name.to_string(),
+            )),
+        }
+    }
+
+    /// Verifies that an audio encoder is registered with the specified name, and that all the
+    /// gstreamer elements it requires are available.
+    pub fn validate_audio_encoder(&self, name: &str) -> Result<(), EncoderFactoryCreationError> {
+        match self.audio_encoders.get(name) {
+            Some(generator) => check_required_elements(name, generator.required_elements()),
+            None => Err(EncoderFactoryCreationError::NoEncoderWithName(
+                name.to_string(),
+            )),
+        }
+    }
+
+    /// Verifies that a video encoder is registered with the specified name, that all the gstreamer
+    /// elements it requires are available, and that it can encode decoded video.
+    pub fn validate_raw_video_encoder(
+        &self,
+        name: &str,
+    ) -> Result<(), EncoderFactoryCreationError> {
+        self.validate_video_encoder(name)?;
+        match self.video_encoders.get(name) {
+            Some(generator) if generator.accepts_raw_video() => Ok(()),
+            _ => Err(EncoderFactoryCreationError::RawMediaNotSupported(
+                name.to_string(),
+            )),
+        }
+    }
+
+    /// Verifies that an audio encoder is registered with the specified name, that all the
+    /// gstreamer elements it requires are available, and that it can encode decoded audio.
+    pub fn validate_raw_audio_encoder(
+        &self,
+        name: &str,
+    ) -> Result<(), EncoderFactoryCreationError> {
+        self.validate_audio_encoder(name)?;
+        match self.audio_encoders.get(name) {
+            Some(generator) if generator.accepts_raw_audio() => Ok(()),
+            _ => Err(EncoderFactoryCreationError::RawMediaNotSupported(
+                name.to_string(),
+            )),
+        }
+    }
+
     /// Creates a new instance of a video encoder based on the name it was specified with at
     /// registration
     pub fn get_video_encoder(
@@ -156,6 +281,7 @@ impl EncoderFactory {
             None => return Err(EncoderFactoryCreationError::NoEncoderWithName(name)),
         };
 
+        check_required_elements(&name, generator.required_elements())?;
         let encoder = generator.create(pipeline, parameters, media_sender)?;
 
         Ok(encoder)
@@ -175,12 +301,25 @@ impl EncoderFactory {
             None => return Err(EncoderFactoryCreationError::NoEncoderWithName(name)),
         };
 
+        check_required_elements(&name, generator.required_elements())?;
         let encoder = generator.create(pipeline, parameters, media_sender)?;
 
         Ok(encoder)
     }
 }
 
+fn check_required_elements(
+    encoder: &str,
+    elements: &[&'static str],
+) -> Result<(), EncoderFactoryCreationError> {
+    ensure_elements_available(elements).map_err(|error| {
+        EncoderFactoryCreationError::MissingElement {
+            encoder: encoder.to_string(),
+            error,
+        }
+    })
+}
+
 /// Helper struct that contains the result after parsing a sample pulled from an `appsrc` gstreamer
 /// element.  Only used within encoder implementations.
 pub struct SampleResult {
@@ -193,6 +332,11 @@ impl SampleResult {
     /// Pulls a sample from the `appsink` element and attempts to parse the contents from it.
     pub fn from_sink(sink: &AppSink) -> Result<SampleResult> {
         let sample = sink.pull_sample().with_context(|| "Sink had no sample")?;
+        SampleResult::from_sample(&sample)
+    }
+
+    /// Attempts to parse the contents of a sample that has already been pulled from an `appsink`
+    pub fn from_sample(sample: &Sample) -> Result<SampleResult> {
         let buffer = sample.buffer().with_context(|| "Sample had no buffer")?;
 
         let map = buffer
@@ -240,6 +384,16 @@ impl SampleResult {
         })
     }
 
+    /// The contents of the sample
+    pub fn content(&self) -> &Bytes {
+        &self.content
+    }
+
+    /// The decoding time of the sample, if one was specified
+    pub fn dts(&self) -> Option<Duration> {
+        self.dts
+    }
+
     /// Converts the dts and pts from a sample into a video timestamp.
     pub fn to_video_timestamp(&self) -> VideoTimestamp {
         match (&self.dts, &self.pts) {
@@ -250,3 +404,92 @@ impl SampleResult {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GSTREAMER_INIT_RESULT;
+    use anyhow::anyhow;
+
+    struct BogusEncoderGenerator;
+
+    impl VideoEncoderGenerator for BogusEncoderGenerator {
+        fn create(
+            &self,
+            _pipeline: &Pipeline,
+            _parameters: &HashMap<String, Option<String>>,
+            _media_sender: UnboundedSender<MediaNotificationContent>,
+        ) -> Result<Box<dyn VideoEncoder + Send>> {
+            Err(anyhow!("Encoder should not be created"))
+        }
+
+        fn required_elements(&self) -> &[&'static str] {
+            &["appsrc", "mmidsbogusenc", "appsink"]
+        }
+    }
+
+    #[test]
+    fn encoder_with_missing_element_fails_validation() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let mut factory = EncoderFactory::new();
+        factory
+            .register_video_encoder("bogus", Box::new(BogusEncoderGenerator))
+            .unwrap();
+
+        match factory.validate_video_encoder("bogus") {
+            Err(EncoderFactoryCreationError::MissingElement { encoder, error }) => {
+                assert_eq!(encoder, "bogus", "Unexpected encoder name");
+                assert_eq!(
+                    error,
+                    GstElementError::MissingUnknownElement("mmidsbogusenc".to_string())
+                );
+            }
+
+            other => panic!("Unexpected validation result: {:?}", other),
+        }
+    }
+
+    struct EncodedOnlyEncoderGenerator;
+
+    impl AudioEncoderGenerator for EncodedOnlyEncoderGenerator {
+        fn create(
+            &self,
+            _pipeline: &Pipeline,
+            _parameters: &HashMap<String, Option<String>>,
+            _media_sender: UnboundedSender<MediaNotificationContent>,
+        ) -> Result<Box<dyn AudioEncoder + Send>> {
+            Err(anyhow!("Encoder should not be created"))
+        }
+    }
+
+    #[test]
+    fn encoder_without_raw_support_fails_raw_validation() {
+        let mut factory = EncoderFactory::new();
+        factory
+            .register_audio_encoder("encoded_only", Box::new(EncodedOnlyEncoderGenerator))
+            .unwrap();
+
+        factory
+            .validate_audio_encoder("encoded_only")
+            .expect("Encoder should be valid for encoded media");
+
+        match factory.validate_raw_audio_encoder("encoded_only") {
+            Err(EncoderFactoryCreationError::RawMediaNotSupported(name)) => {
+                assert_eq!(name, "encoded_only", "Unexpected encoder name")
+            }
+
+            other => panic!("Unexpected validation result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unregistered_encoder_fails_validation() {
+        let factory = EncoderFactory::new();
+
+        match factory.validate_audio_encoder("aac") {
+            Err(EncoderFactoryCreationError::NoEncoderWithName(name)) => assert_eq!(name, "aac"),
+            other => panic!("Unexpected validation result: {:?}", other),
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use crate::encoders::{SampleResult, VideoEncoder, VideoEncoderGenerator};
-use crate::utils::create_gst_element;
+use crate::utils::{configure_latency, create_gst_element, get_latency};
 use anyhow::{anyhow, Context, Result};
 use bytes::{Bytes, BytesMut};
 use gstreamer::prelude::*;
@@ -18,20 +18,29 @@ use std::time::Duration;
 use tokio::sync::mpsc::UnboundedSender;
 use tracing::error;
 
-/// Creates an encoder that passes video packets through to the output channel without modification
+/// Creates an encoder that passes video packets through to the output channel without modification.
+/// Since the video is not decoded, any codec (e.g. H264 or H265) can be passed through.
+///
+/// The optional `latency_ms` parameter sets how many milliseconds of video the pipeline's queue
+/// may hold.
 pub struct VideoCopyEncoderGenerator {
     pub pts_offset_metadata_key: MetadataKey,
 }
 
 impl VideoEncoderGenerator for VideoCopyEncoderGenerator {
+    fn required_elements(&self) -> &[&'static str] {
+        &["appsrc", "queue", "appsink"]
+    }
+
     fn create(
         &self,
         pipeline: &Pipeline,
-        _parameters: &HashMap<String, Option<String>>,
+        parameters: &HashMap<String, Option<String>>,
         media_sender: UnboundedSender<MediaNotificationContent>,
     ) -> Result<Box<dyn VideoEncoder + Send>> {
         Ok(Box::new(VideoCopyEncoder::new(
             media_sender,
+            parameters,
             pipeline,
             self.pts_offset_metadata_key,
         )?))
@@ -39,6 +48,7 @@ impl VideoEncoderGenerator for VideoCopyEncoderGenerator {
 }
 
 struct CodecInfo {
+    payload_type: Arc<String>,
     sequence_header: Bytes,
 }
 
@@ -50,9 +60,12 @@ struct VideoCopyEncoder {
 impl VideoCopyEncoder {
     fn new(
         media_sender: UnboundedSender<MediaNotificationContent>,
+        parameters: &HashMap<String, Option<String>>,
         pipeline: &Pipeline,
         pts_offset_metadata_key: MetadataKey,
     ) -> Result<VideoCopyEncoder> {
+        let latency = get_latency(parameters)?;
+
         // While we won't be mutating the stream, we want to pass it through a gstreamer pipeline
         // so the packets will be synchronized with audio in case of transcoding delay.
 
@@ -67,6 +80,10 @@ impl VideoCopyEncoder {
         Element::link_many(&[&appsrc, &queue, &appsink])
             .with_context(|| "Failed to link video copy encoder's elements together")?;
 
+        if let Some(latency) = latency {
+            configure_latency(pipeline, &[&queue], latency);
+        }
+
         let appsink = appsink
             .dynamic_cast::<AppSink>()
             .map_err(|_| anyhow!("Video copy encoder's appsink could not be casted"))?;
@@ -74,6 +91,7 @@ impl VideoCopyEncoder {
         let codec_data: Arc<Mutex<Option<CodecInfo>>> = Arc::new(Mutex::new(None));
         let copy_of_codec_data = codec_data.clone();
         let mut sent_codec_data = false;
+        let mut payload_type = VIDEO_CODEC_H264_AVC.clone();
         let mut codec_data_error_raised = false;
         let mut metadata_buffer = BytesMut::new();
         appsink.set_callbacks(
@@ -93,9 +111,10 @@ impl VideoCopyEncoder {
                         };
 
                         if let Some(info) = &*data {
+                            payload_type = info.payload_type.clone();
                             let _ = media_sender.send(MediaNotificationContent::MediaPayload {
                                 media_type: MediaType::Video,
-                                payload_type: VIDEO_CODEC_H264_AVC.clone(),
+                                payload_type: payload_type.clone(),
                                 timestamp: Duration::new(0, 0),
                                 is_required_for_decoding: true,
                                 data: info.sequence_header.clone(),
@@ -121,7 +140,7 @@ impl VideoCopyEncoder {
 
                     let _ = media_sender.send(MediaNotificationContent::MediaPayload {
                         media_type: MediaType::Video,
-                        payload_type: VIDEO_CODEC_H264_AVC.clone(),
+                        payload_type: payload_type.clone(),
                         timestamp: timestamp.dts(),
                         is_required_for_decoding: false,
                         data: sample.content,
@@ -150,7 +169,7 @@ impl VideoCopyEncoder {
 impl VideoEncoder for VideoCopyEncoder {
     fn push_data(
         &self,
-        _payload_type: Arc<String>,
+        payload_type: Arc<String>,
         data: Bytes,
         timestamp: VideoTimestamp,
         is_sequence_header: bool,
@@ -162,6 +181,7 @@ impl VideoEncoder for VideoCopyEncoder {
                 .map_err(|_| anyhow!("Video copy encoder's lock was poisoned"))?;
 
             *codec_data = Some(CodecInfo {
+                payload_type,
                 sequence_header: data,
             })
         } else {
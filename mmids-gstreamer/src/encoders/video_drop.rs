@@ -1,6 +1,6 @@
 use crate::encoders::{VideoEncoder, VideoEncoderGenerator};
 use bytes::Bytes;
-use gstreamer::Pipeline;
+use gstreamer::{Pipeline, Sample};
 use mmids_core::workflows::MediaNotificationContent;
 use mmids_core::VideoTimestamp;
 use std::collections::HashMap;
@@ -19,6 +19,10 @@ impl VideoEncoderGenerator for VideoDropEncoderGenerator {
     ) -> anyhow::Result<Box<dyn VideoEncoder + Send>> {
         Ok(Box::new(VideoDropEncoder {}))
     }
+
+    fn accepts_raw_video(&self) -> bool {
+        true
+    }
 }
 
 struct VideoDropEncoder {}
@@ -35,4 +39,8 @@ impl VideoEncoder for VideoDropEncoder {
 
         Ok(())
     }
+
+    fn push_raw_sample(&self, _sample: Sample) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
@@ -1,9 +1,15 @@
-use crate::encoders::{SampleResult, VideoEncoder, VideoEncoderGenerator};
-use crate::utils::{create_gst_element, get_codec_data_from_element};
+use crate::encoders::keyframe_interval::{
+    force_keyframes_at_interval, get_fixed_gop, get_min_keyframe_interval,
+};
+use crate::encoders::{EncoderQueueLevel, SampleResult, VideoEncoder, VideoEncoderGenerator};
+use crate::utils::{
+    configure_latency, configure_source_queue, create_gst_element, get_codec_data_from_element,
+    get_latency, push_buffer_to_source, push_raw_sample_to_source, source_queue_level,
+};
 use anyhow::{anyhow, Context, Result};
 use bytes::{Bytes, BytesMut};
 use gstreamer::prelude::*;
-use gstreamer::{Caps, Element, FlowError, FlowSuccess, Fraction, Pipeline};
+use gstreamer::{Caps, Element, FlowError, FlowSuccess, Fraction, Pipeline, Sample};
 use gstreamer_app::{AppSink, AppSinkCallbacks, AppSrc};
 use mmids_core::codecs::VIDEO_CODEC_H264_AVC;
 use mmids_core::workflows::metadata::{
@@ -18,6 +24,21 @@ use std::time::Duration;
 use tokio::sync::mpsc::UnboundedSender;
 use tracing::{error, warn};
 
+const PROFILES: [&str; 7] = [
+    "constrained-baseline",
+    "baseline",
+    "main",
+    "high",
+    "high-10",
+    "high-4:2:2",
+    "high-4:4:4",
+];
+
+const LEVELS: [&str; 17] = [
+    "1", "1b", "1.1", "1.2", "1.3", "2", "2.1", "2.2", "3", "3.1", "3.2", "4", "4.1", "4.2", "5",
+    "5.1", "5.2",
+];
+
 /// Creates a video encoder that uses the gstreamer `x264enc` encoder to encode video into h264
 /// video.
 ///
@@ -29,11 +50,41 @@ use tracing::{error, warn};
 /// * `preset` - The `speed-preset` value to use in the encoder.  Valid values are: `ultrafast`,
 /// `superfast`, `veryfast`, `faster`, `fast`, `medium`, `slow`, `slower`, `veryslow`.  The default
 /// is `medium`.
+/// * `min_keyframe_interval` - The maximum number of seconds allowed between keyframes.  When
+/// specified, a keyframe is forced whenever this much time has passed since the last one.
+/// * `fixed_gop` - When `true`, keyframes are only produced where `min_keyframe_interval` forces
+/// them, with scene change detection and B-frames disabled. Every GOP is then closed and exactly
+/// `min_keyframe_interval` long, which keeps segment boundaries aligned for packagers.  Requires
+/// `min_keyframe_interval`.
+/// * `profile` - The H264 profile the output is constrained to, for devices that only support
+/// some profiles.  Valid values are: `constrained-baseline`, `baseline`, `main`, `high`,
+/// `high-10`, `high-4:2:2`, `high-4:4:4`.
+/// * `level` - The H264 level the output is constrained to, such as `3.1`.  Valid values are `1`,
+/// `1b`, `1.1` through `1.3`, `2` through `2.2`, `3` through `3.2`, `4` through `4.2`, and `5`
+/// through `5.2`.
+/// * `latency_ms` - How many milliseconds of media the pipeline's queues may hold.  Lower values
+/// reduce latency, while higher values absorb more jitter from the incoming stream.
+///
+/// Invalid `profile` and `level` values are ignored with a warning.
 pub struct X264EncoderGenerator {
     pub pts_offset_metadata_key: MetadataKey,
 }
 
 impl VideoEncoderGenerator for X264EncoderGenerator {
+    fn required_elements(&self) -> &[&'static str] {
+        &[
+            "appsrc",
+            "queue",
+            "decodebin",
+            "videoscale",
+            "videorate",
+            "capsfilter",
+            "x264enc",
+            "h264parse",
+            "appsink",
+        ]
+    }
+
     fn create(
         &self,
         pipeline: &Pipeline,
@@ -47,6 +98,10 @@ impl VideoEncoderGenerator for X264EncoderGenerator {
             self.pts_offset_metadata_key,
         )?))
     }
+
+    fn accepts_raw_video(&self) -> bool {
+        true
+    }
 }
 
 struct X264Encoder {
@@ -65,6 +120,11 @@ impl X264Encoder {
         let preset = parameters.get("preset").unwrap_or(&None);
         let fps = get_number(parameters, "fps");
         let bitrate = get_number(parameters, "bitrate");
+        let min_keyframe_interval = get_min_keyframe_interval(parameters)?;
+        let fixed_gop = get_fixed_gop(parameters, min_keyframe_interval)?;
+        let latency = get_latency(parameters)?;
+        let profile = get_allowed_value(parameters, "profile", &PROFILES);
+        let level = get_allowed_value(parameters, "level", &LEVELS);
 
         let appsrc = create_gst_element("appsrc")?;
         let queue = create_gst_element("queue")?;
@@ -73,6 +133,7 @@ impl X264Encoder {
         let rate_changer = create_gst_element("videorate")?;
         let capsfilter = create_gst_element("capsfilter")?;
         let encoder = create_gst_element("x264enc")?;
+        let output_capsfilter = create_gst_element("capsfilter")?;
         let output_parser = create_gst_element("h264parse")?;
         let appsink = create_gst_element("appsink")?;
 
@@ -85,6 +146,7 @@ impl X264Encoder {
                 &rate_changer,
                 &capsfilter,
                 &encoder,
+                &output_capsfilter,
                 &output_parser,
                 &appsink,
             ])
@@ -98,11 +160,16 @@ impl X264Encoder {
             &rate_changer,
             &capsfilter,
             &encoder,
+            &output_capsfilter,
             &output_parser,
             &appsink,
         ])
         .with_context(|| "Failed to link scale to sink")?;
 
+        if let Some(latency) = latency {
+            configure_latency(pipeline, &[&queue, &decoder], latency);
+        }
+
         // decodebin's video pad is added dynamically
         let link_destination = scale;
         decoder.connect_pad_added(move |src, src_pad| {
@@ -137,6 +204,20 @@ impl X264Encoder {
         let caps = caps.build();
         capsfilter.set_property("caps", caps);
 
+        // x264enc picks its profile and level based on what downstream caps allow
+        if profile.is_some() || level.is_some() {
+            let mut output_caps = Caps::builder("video/x-h264");
+            if let Some(profile) = profile {
+                output_caps = output_caps.field("profile", profile);
+            }
+
+            if let Some(level) = level {
+                output_caps = output_caps.field("level", level);
+            }
+
+            output_capsfilter.set_property("caps", output_caps.build());
+        }
+
         encoder.set_property_from_str("tune", "zerolatency");
 
         if let Some(preset) = preset {
@@ -147,6 +228,19 @@ impl X264Encoder {
             encoder.set_property("bitrate", bitrate);
         }
 
+        if fixed_gop {
+            // Only forced keyframes may start a GOP, so the encoder's own keyframe limit is raised
+            // out of reach and scene changes no longer insert keyframes. Without B-frames, no frame
+            // is ever presented before the keyframe of its GOP.
+            encoder.set_property("key-int-max", i32::MAX as u32);
+            encoder.set_property("bframes", 0_u32);
+            encoder.set_property("option-string", "scenecut=0:open-gop=0");
+        }
+
+        if let Some(interval) = min_keyframe_interval {
+            force_keyframes_at_interval(&encoder, interval)?;
+        }
+
         let appsink = appsink
             .dynamic_cast::<AppSink>()
             .map_err(|_| anyhow!("appsink could not be cast to 'AppSink'"))?;
@@ -178,6 +272,8 @@ impl X264Encoder {
             .dynamic_cast::<AppSrc>()
             .map_err(|_| anyhow!("source element could not be cast to 'Appsrc'"))?;
 
+        configure_source_queue(&appsrc);
+
         Ok(X264Encoder { source: appsrc })
     }
 }
@@ -198,16 +294,23 @@ impl VideoEncoder for X264Encoder {
             crate::utils::set_source_video_sequence_header(&self.source, payload_type, buffer)
                 .with_context(|| "Failed to set sequence header for x264 encoder")?;
         } else {
-            self.source
-                .push_buffer(buffer)
-                .with_context(|| "Failed to push the buffer into video source")?;
+            push_buffer_to_source(&self.source, buffer)?;
         }
 
         Ok(())
     }
+
+    fn queue_level(&self) -> Option<EncoderQueueLevel> {
+        Some(source_queue_level(&self.source))
+    }
+
+    fn push_raw_sample(&self, sample: Sample) -> Result<()> {
+        // `decodebin` passes raw video straight through to the encoder
+        push_raw_sample_to_source(&self.source, sample)
+    }
 }
 
-fn get_number(parameters: &HashMap<String, Option<String>>, key: &str) -> Option<u32> {
+pub(super) fn get_number(parameters: &HashMap<String, Option<String>>, key: &str) -> Option<u32> {
     if let Some(Some(inner)) = parameters.get(key) {
         match inner.parse() {
             Ok(num) => return Some(num),
@@ -218,6 +321,28 @@ fn get_number(parameters: &HashMap<String, Option<String>>, key: &str) -> Option
     None
 }
 
+/// Gets the value of a parameter that only allows specific values, ignoring (with a warning) any
+/// value that's not allowed
+fn get_allowed_value(
+    parameters: &HashMap<String, Option<String>>,
+    key: &str,
+    allowed: &[&'static str],
+) -> Option<&'static str> {
+    if let Some(Some(inner)) = parameters.get(key) {
+        let value = inner.trim().to_lowercase();
+        match allowed.iter().find(|x| **x == value) {
+            Some(allowed_value) => return Some(*allowed_value),
+            None => warn!(
+                "Parameter {key} had a value of '{inner}', which is not one of the supported \
+                values: {}",
+                allowed.join(", ")
+            ),
+        }
+    }
+
+    None
+}
+
 fn sample_received(
     sink: &AppSink,
     codec_data_sent: &mut bool,
@@ -262,3 +387,345 @@ fn sample_received(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoders::EncoderQueueFullError;
+    use crate::utils::MAX_SOURCE_QUEUE_BYTES;
+    use crate::GSTREAMER_INIT_RESULT;
+    use gstreamer::{Format, State};
+    use mmids_core::codecs::h264::{
+        nal_unit_type, AvcDecoderConfigurationRecord, NAL_UNIT_TYPE_IDR,
+    };
+    use mmids_core::codecs::nal::{split_length_prefixed, DEFAULT_NAL_LENGTH_SIZE};
+    use mmids_core::workflows::metadata::common_metadata::get_pts_offset_metadata_key;
+    use mmids_core::workflows::metadata::MetadataKeyMap;
+    use std::time::Instant;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 64;
+
+    fn pts_offset_key() -> MetadataKey {
+        get_pts_offset_metadata_key(&mut MetadataKeyMap::new())
+    }
+
+    /// Feeds raw frames in, which `decodebin` passes straight through to the encoder
+    fn set_raw_video_caps(encoder: &X264Encoder) {
+        let caps = Caps::builder("video/x-raw")
+            .field("format", "I420")
+            .field("width", WIDTH as i32)
+            .field("height", HEIGHT as i32)
+            .field("framerate", Fraction::new(30, 1))
+            .build();
+
+        encoder.source.set_caps(Some(&caps));
+        encoder.source.set_format(Format::Time);
+    }
+
+    fn raw_frame() -> Bytes {
+        Bytes::from(vec![128_u8; WIDTH * HEIGHT * 3 / 2])
+    }
+
+    #[test]
+    fn full_source_queue_returns_queue_full_error() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        // A paused pipeline stops consuming frames once its sink has prerolled, so the `appsrc`'s
+        // queue fills up the same way it does when the encoder falls behind
+        let pipeline = Pipeline::new(None);
+        let (sender, _receiver) = unbounded_channel();
+        let encoder =
+            X264Encoder::new(sender, &HashMap::new(), &pipeline, pts_offset_key()).unwrap();
+
+        set_raw_video_caps(&encoder);
+        pipeline.set_state(State::Paused).unwrap();
+
+        let frame = raw_frame();
+        let max_pushes = MAX_SOURCE_QUEUE_BYTES as usize / frame.len() * 2;
+        let mut error = None;
+        for index in 0..max_pushes {
+            let time = Duration::from_millis(index as u64 * 33);
+            let result = encoder.push_data(
+                VIDEO_CODEC_H264_AVC.clone(),
+                frame.clone(),
+                VideoTimestamp::from_durations(time, time),
+                false,
+            );
+
+            if let Err(result) = result {
+                error = Some(result);
+                break;
+            }
+        }
+
+        let level = encoder.queue_level().expect("Expected a queue level");
+        pipeline.set_state(State::Null).unwrap();
+
+        assert!(
+            level.queued_bytes >= level.max_bytes,
+            "Expected queue level to report a full queue"
+        );
+
+        let error = error.expect("Expected pushing to eventually fail");
+        match error.downcast_ref::<EncoderQueueFullError>() {
+            Some(EncoderQueueFullError(level)) => {
+                assert_eq!(
+                    level.max_bytes, MAX_SOURCE_QUEUE_BYTES,
+                    "Unexpected max bytes"
+                );
+                assert!(
+                    level.queued_bytes >= MAX_SOURCE_QUEUE_BYTES,
+                    "Expected the queue to be full"
+                );
+            }
+
+            None => panic!("Expected a queue full error, instead got {:?}", error),
+        }
+    }
+
+    #[test]
+    fn output_caps_carry_requested_profile_and_level() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let mut parameters = HashMap::new();
+        parameters.insert("preset".to_string(), Some("ultrafast".to_string()));
+        parameters.insert("profile".to_string(), Some("main".to_string()));
+        parameters.insert("level".to_string(), Some("3.1".to_string()));
+
+        let pipeline = Pipeline::new(None);
+        let (sender, mut receiver) = unbounded_channel();
+        let encoder = X264Encoder::new(sender, &parameters, &pipeline, pts_offset_key()).unwrap();
+
+        set_raw_video_caps(&encoder);
+        pipeline.set_state(State::Playing).unwrap();
+
+        let frame = raw_frame();
+        for index in 0..10 {
+            let time = Duration::from_millis(index * 33);
+            encoder
+                .push_data(
+                    VIDEO_CODEC_H264_AVC.clone(),
+                    frame.clone(),
+                    VideoTimestamp::from_durations(time, time),
+                    false,
+                )
+                .unwrap();
+        }
+
+        let _ = encoder.source.end_of_stream();
+
+        let mut sequence_header = None;
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while sequence_header.is_none() && Instant::now() < deadline {
+            match receiver.try_recv() {
+                Ok(MediaNotificationContent::MediaPayload {
+                    is_required_for_decoding: true,
+                    data,
+                    ..
+                }) => sequence_header = Some(data),
+
+                Ok(_) => (),
+                Err(_) => std::thread::sleep(Duration::from_millis(10)),
+            }
+        }
+
+        let sink = pipeline
+            .iterate_sinks()
+            .next()
+            .unwrap()
+            .expect("Pipeline had no sink");
+
+        let caps = sink
+            .static_pad("sink")
+            .and_then(|pad| pad.current_caps())
+            .expect("Sink had no caps");
+
+        pipeline.set_state(State::Null).unwrap();
+
+        let structure = caps.structure(0).unwrap();
+        assert_eq!(
+            structure.get::<&str>("profile").unwrap(),
+            "main",
+            "Unexpected profile in output caps"
+        );
+        assert_eq!(
+            structure.get::<&str>("level").unwrap(),
+            "3.1",
+            "Unexpected level in output caps"
+        );
+
+        let sequence_header = sequence_header.expect("No sequence header received");
+        let record = AvcDecoderConfigurationRecord::parse(&sequence_header).unwrap();
+        assert_eq!(record.profile_indication, 77, "Expected main profile SPS");
+        assert_eq!(record.level_indication, 31, "Expected level 3.1 SPS");
+    }
+
+    #[test]
+    fn latency_parameter_bounds_queues_by_time() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let mut parameters = HashMap::new();
+        parameters.insert("latency_ms".to_string(), Some("150".to_string()));
+
+        let pipeline = Pipeline::new(None);
+        let (sender, _receiver) = unbounded_channel();
+        let _encoder = X264Encoder::new(sender, &parameters, &pipeline, pts_offset_key()).unwrap();
+
+        let mut buffering_elements = Vec::new();
+        let mut elements = pipeline.iterate_elements();
+        while let Ok(Some(element)) = elements.next() {
+            let factory_name = element.factory().map(|factory| factory.name().to_string());
+            if let Some("queue") | Some("decodebin") = factory_name.as_deref() {
+                buffering_elements.push(element);
+            }
+        }
+
+        assert_eq!(
+            buffering_elements.len(),
+            2,
+            "Expected a queue and a decodebin"
+        );
+
+        let expected_nanos = Duration::from_millis(150).as_nanos() as u64;
+        for element in buffering_elements {
+            assert_eq!(
+                element.property::<u64>("max-size-time"),
+                expected_nanos,
+                "Unexpected max-size-time for {}",
+                element.name()
+            );
+            assert_eq!(
+                element.property::<u32>("max-size-bytes"),
+                0,
+                "Expected no byte limit for {}",
+                element.name()
+            );
+            assert_eq!(
+                element.property::<u32>("max-size-buffers"),
+                0,
+                "Expected no buffer limit for {}",
+                element.name()
+            );
+        }
+
+        assert_eq!(
+            pipeline.property::<u64>("latency"),
+            expected_nanos,
+            "Unexpected pipeline latency"
+        );
+    }
+
+    #[test]
+    fn fixed_gop_without_min_keyframe_interval_returns_error() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let mut parameters = HashMap::new();
+        parameters.insert("fixed_gop".to_string(), Some("true".to_string()));
+
+        let pipeline = Pipeline::new(None);
+        let (sender, _receiver) = unbounded_channel();
+        let result = X264Encoder::new(sender, &parameters, &pipeline, pts_offset_key());
+
+        assert!(result.is_err(), "Expected an error");
+    }
+
+    #[test]
+    fn fixed_gop_output_has_closed_gops_at_exact_interval() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let mut parameters = HashMap::new();
+        parameters.insert("preset".to_string(), Some("ultrafast".to_string()));
+        parameters.insert("min_keyframe_interval".to_string(), Some("0.5".to_string()));
+        parameters.insert("fixed_gop".to_string(), Some("true".to_string()));
+
+        let pipeline = Pipeline::new(None);
+        let (sender, mut receiver) = unbounded_channel();
+        let encoder = X264Encoder::new(sender, &parameters, &pipeline, pts_offset_key()).unwrap();
+
+        set_raw_video_caps(&encoder);
+        pipeline.set_state(State::Playing).unwrap();
+
+        // 3 seconds of 30fps video
+        let frame = raw_frame();
+        let frame_count = 90;
+        for index in 0..frame_count {
+            let time = Duration::from_nanos(index * 1_000_000_000 / 30);
+            encoder
+                .push_data(
+                    VIDEO_CODEC_H264_AVC.clone(),
+                    frame.clone(),
+                    VideoTimestamp::from_durations(time, time),
+                    false,
+                )
+                .unwrap();
+        }
+
+        let _ = encoder.source.end_of_stream();
+
+        // Each encoded frame's presentation timestamp and whether it's a keyframe
+        let mut frames = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while frames.len() < frame_count as usize && Instant::now() < deadline {
+            match receiver.try_recv() {
+                Ok(MediaNotificationContent::MediaPayload {
+                    is_required_for_decoding: false,
+                    timestamp,
+                    metadata,
+                    data,
+                    ..
+                }) => {
+                    let pts_offset = metadata
+                        .iter()
+                        .filter(|m| m.key() == pts_offset_key())
+                        .filter_map(|m| match m.value() {
+                            MetadataValue::I32(offset) => Some(offset),
+                            _ => None,
+                        })
+                        .next()
+                        .unwrap_or_default();
+
+                    let is_keyframe = split_length_prefixed(&data, DEFAULT_NAL_LENGTH_SIZE)
+                        .unwrap()
+                        .iter()
+                        .any(|nal| nal_unit_type(nal) == Some(NAL_UNIT_TYPE_IDR));
+
+                    frames.push((timestamp, pts_offset, is_keyframe));
+                }
+
+                Ok(_) => (),
+                Err(_) => std::thread::sleep(Duration::from_millis(10)),
+            }
+        }
+
+        pipeline.set_state(State::Null).unwrap();
+
+        assert_eq!(frames.len(), frame_count as usize, "Not all frames encoded");
+        assert!(
+            frames.iter().all(|(_, pts_offset, _)| *pts_offset == 0),
+            "Expected no frames to be reordered"
+        );
+
+        // Without reordering, no frame can be presented before the keyframe of its GOP, so every
+        // GOP is closed. Timestamps are compared against the first keyframe, as x264enc offsets
+        // its timestamps.
+        let keyframe_times = frames
+            .iter()
+            .filter(|(_, _, is_keyframe)| *is_keyframe)
+            .map(|(timestamp, _, _)| *timestamp)
+            .collect::<Vec<_>>();
+
+        let first = keyframe_times[0];
+        let offsets = keyframe_times
+            .iter()
+            .map(|time| (*time - first).as_millis())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            offsets,
+            vec![0, 500, 1000, 1500, 2000, 2500],
+            "Unexpected keyframe timestamps"
+        );
+    }
+}
@@ -0,0 +1,452 @@
+use crate::encoders::keyframe_interval::{force_keyframes_at_interval, get_min_keyframe_interval};
+use crate::encoders::video_x264::get_number;
+use crate::encoders::{EncoderQueueLevel, SampleResult, VideoEncoder, VideoEncoderGenerator};
+use crate::utils::{
+    configure_latency, configure_source_queue, create_gst_element, get_codec_data_from_element,
+    get_latency, push_buffer_to_source, push_raw_sample_to_source, source_queue_level,
+};
+use anyhow::{anyhow, Context, Result};
+use bytes::{Bytes, BytesMut};
+use gstreamer::prelude::*;
+use gstreamer::{Caps, Element, FlowError, FlowSuccess, Fraction, Pipeline, Sample};
+use gstreamer_app::{AppSink, AppSinkCallbacks, AppSrc};
+use mmids_core::codecs::VIDEO_CODEC_H265_HVCC;
+use mmids_core::workflows::metadata::{
+    MediaPayloadMetadataCollection, MetadataEntry, MetadataKey, MetadataValue,
+};
+use mmids_core::workflows::{MediaNotificationContent, MediaType};
+use mmids_core::VideoTimestamp;
+use std::collections::HashMap;
+use std::iter;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::error;
+
+/// Creates a video encoder that uses the gstreamer `x265enc` encoder to encode video into h265
+/// (HEVC) video.  The output is HVCC framed, with the VPS, SPS, and PPS sent as the sequence header.
+///
+/// This encoder supports the following optional parameters:
+/// * `width` - How many pixels wide the resulting video should be
+/// * `height` - How many pixels high the resulting video should be
+/// * `fps` - The exact fps the resulting video should be
+/// * `bitrate` - the desired bitrate specified in **kbps**.
+/// * `preset` - The `speed-preset` value to use in the encoder.  Valid values are: `ultrafast`,
+/// `superfast`, `veryfast`, `faster`, `fast`, `medium`, `slow`, `slower`, `veryslow`, `placebo`.
+/// The default is `medium`.
+/// * `min_keyframe_interval` - The maximum number of seconds allowed between keyframes.  When
+/// specified, a keyframe is forced whenever this much time has passed since the last one.
+/// * `latency_ms` - How many milliseconds of media the pipeline's queues may hold.  Lower values
+/// reduce latency, while higher values absorb more jitter from the incoming stream.
+pub struct X265EncoderGenerator {
+    pub pts_offset_metadata_key: MetadataKey,
+}
+
+impl VideoEncoderGenerator for X265EncoderGenerator {
+    fn required_elements(&self) -> &[&'static str] {
+        &[
+            "appsrc",
+            "queue",
+            "decodebin",
+            "videoscale",
+            "videorate",
+            "capsfilter",
+            "x265enc",
+            "h265parse",
+            "appsink",
+        ]
+    }
+
+    fn create(
+        &self,
+        pipeline: &Pipeline,
+        parameters: &HashMap<String, Option<String>>,
+        media_sender: UnboundedSender<MediaNotificationContent>,
+    ) -> Result<Box<dyn VideoEncoder + Send>> {
+        Ok(Box::new(X265Encoder::new(
+            media_sender,
+            parameters,
+            pipeline,
+            self.pts_offset_metadata_key,
+        )?))
+    }
+
+    fn accepts_raw_video(&self) -> bool {
+        true
+    }
+}
+
+struct X265Encoder {
+    source: AppSrc,
+}
+
+impl X265Encoder {
+    fn new(
+        media_sender: UnboundedSender<MediaNotificationContent>,
+        parameters: &HashMap<String, Option<String>>,
+        pipeline: &Pipeline,
+        pts_offset_metadata_key: MetadataKey,
+    ) -> Result<X265Encoder> {
+        let height = get_number(parameters, "height");
+        let width = get_number(parameters, "width");
+        let preset = parameters.get("preset").unwrap_or(&None);
+        let fps = get_number(parameters, "fps");
+        let bitrate = get_number(parameters, "bitrate");
+        let min_keyframe_interval = get_min_keyframe_interval(parameters)?;
+        let latency = get_latency(parameters)?;
+
+        let appsrc = create_gst_element("appsrc")?;
+        let queue = create_gst_element("queue")?;
+        let decoder = create_gst_element("decodebin")?;
+        let scale = create_gst_element("videoscale")?;
+        let rate_changer = create_gst_element("videorate")?;
+        let capsfilter = create_gst_element("capsfilter")?;
+        let encoder = create_gst_element("x265enc")?;
+        let output_parser = create_gst_element("h265parse")?;
+        let output_capsfilter = create_gst_element("capsfilter")?;
+        let appsink = create_gst_element("appsink")?;
+
+        pipeline
+            .add_many(&[
+                &appsrc,
+                &queue,
+                &decoder,
+                &scale,
+                &rate_changer,
+                &capsfilter,
+                &encoder,
+                &output_parser,
+                &output_capsfilter,
+                &appsink,
+            ])
+            .with_context(|| "Failed to add x265 encoder's elements to pipeline")?;
+
+        Element::link_many(&[&appsrc, &queue, &decoder])
+            .with_context(|| "Failed to link appsrc -> queue -> decoder")?;
+
+        Element::link_many(&[
+            &scale,
+            &rate_changer,
+            &capsfilter,
+            &encoder,
+            &output_parser,
+            &output_capsfilter,
+            &appsink,
+        ])
+        .with_context(|| "Failed to link scale to sink")?;
+
+        if let Some(latency) = latency {
+            configure_latency(pipeline, &[&queue, &decoder], latency);
+        }
+
+        // decodebin's video pad is added dynamically
+        let link_destination = scale;
+        decoder.connect_pad_added(move |src, src_pad| {
+            match src.link_pads(
+                Some(&src_pad.name()),
+                &link_destination.clone(),
+                Some("sink"),
+            ) {
+                Ok(_) => (),
+                Err(_) => error!(
+                    src_caps = ?src_pad.caps(),
+                    dest_caps = ?link_destination.static_pad("sink").unwrap().caps(),
+                    "Failed to link `decodebin`'s {} pad to videoscale element",
+                    src_pad.name()
+                ),
+            }
+        });
+
+        let mut caps = Caps::builder("video/x-raw");
+        if let Some(height) = height {
+            caps = caps.field("height", height as i32);
+        }
+
+        if let Some(width) = width {
+            caps = caps.field("width", width as i32);
+        }
+
+        if let Some(fps) = fps {
+            caps = caps.field("framerate", Fraction::new(fps as i32, 1));
+        }
+
+        let caps = caps.build();
+        capsfilter.set_property("caps", caps);
+
+        // x265enc outputs Annex-B, so have the parser convert it to HVCC with the parameter sets
+        // in the `codec_data`.
+        let output_caps = Caps::builder("video/x-h265")
+            .field("stream-format", "hvc1")
+            .field("alignment", "au")
+            .build();
+
+        output_capsfilter.set_property("caps", output_caps);
+
+        encoder.set_property_from_str("tune", "zerolatency");
+
+        if let Some(preset) = preset {
+            encoder.set_property_from_str("speed-preset", preset.as_str());
+        }
+
+        if let Some(bitrate) = bitrate {
+            encoder.set_property("bitrate", bitrate);
+        }
+
+        if let Some(interval) = min_keyframe_interval {
+            force_keyframes_at_interval(&encoder, interval)?;
+        }
+
+        let appsink = appsink
+            .dynamic_cast::<AppSink>()
+            .map_err(|_| anyhow!("appsink could not be cast to 'AppSink'"))?;
+
+        let mut sent_codec_data = false;
+        let mut metadata_buffer = BytesMut::new();
+        appsink.set_callbacks(
+            AppSinkCallbacks::builder()
+                .new_sample(move |sink| {
+                    match sample_received(
+                        sink,
+                        &mut sent_codec_data,
+                        &output_parser,
+                        media_sender.clone(),
+                        pts_offset_metadata_key,
+                        &mut metadata_buffer,
+                    ) {
+                        Ok(_) => Ok(FlowSuccess::Ok),
+                        Err(error) => {
+                            error!("new_sample callback error received: {:?}", error);
+                            Err(FlowError::Error)
+                        }
+                    }
+                })
+                .build(),
+        );
+
+        let appsrc = appsrc
+            .dynamic_cast::<AppSrc>()
+            .map_err(|_| anyhow!("source element could not be cast to 'Appsrc'"))?;
+
+        configure_source_queue(&appsrc);
+
+        Ok(X265Encoder { source: appsrc })
+    }
+}
+
+impl VideoEncoder for X265Encoder {
+    fn push_data(
+        &self,
+        payload_type: Arc<String>,
+        data: Bytes,
+        timestamp: VideoTimestamp,
+        is_sequence_header: bool,
+    ) -> Result<()> {
+        let buffer =
+            crate::utils::set_gst_buffer(data, Some(timestamp.dts()), Some(timestamp.pts()))
+                .with_context(|| "Failed to set buffer")?;
+
+        if is_sequence_header {
+            crate::utils::set_source_video_sequence_header(&self.source, payload_type, buffer)
+                .with_context(|| "Failed to set sequence header for x265 encoder")?;
+        } else {
+            push_buffer_to_source(&self.source, buffer)?;
+        }
+
+        Ok(())
+    }
+
+    fn queue_level(&self) -> Option<EncoderQueueLevel> {
+        Some(source_queue_level(&self.source))
+    }
+
+    fn push_raw_sample(&self, sample: Sample) -> Result<()> {
+        // `decodebin` passes raw video straight through to the encoder
+        push_raw_sample_to_source(&self.source, sample)
+    }
+}
+
+fn sample_received(
+    sink: &AppSink,
+    codec_data_sent: &mut bool,
+    output_parser: &Element,
+    media_sender: UnboundedSender<MediaNotificationContent>,
+    pts_offset_metadata_key: MetadataKey,
+    metadata_buffer: &mut BytesMut,
+) -> Result<()> {
+    if !*codec_data_sent {
+        // Pull the hvcC sequence header out from the output parser
+        let codec_data = get_codec_data_from_element(output_parser)?;
+
+        let _ = media_sender.send(MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Video,
+            payload_type: VIDEO_CODEC_H265_HVCC.clone(),
+            timestamp: Duration::from_millis(0),
+            is_required_for_decoding: true,
+            data: codec_data,
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), metadata_buffer),
+        });
+
+        *codec_data_sent = true;
+    }
+
+    let sample = SampleResult::from_sink(sink).with_context(|| "Failed to get x265enc sample")?;
+    let timestamp = sample.to_video_timestamp();
+    let pts_offset = MetadataEntry::new(
+        pts_offset_metadata_key,
+        MetadataValue::I32(timestamp.pts_offset()),
+        metadata_buffer,
+    )
+    .unwrap(); // Can only panic if the key is not for an i32
+
+    let _ = media_sender.send(MediaNotificationContent::MediaPayload {
+        media_type: MediaType::Video,
+        payload_type: VIDEO_CODEC_H265_HVCC.clone(),
+        timestamp: timestamp.dts(),
+        is_required_for_decoding: false,
+        data: sample.content,
+        metadata: MediaPayloadMetadataCollection::new([pts_offset].into_iter(), metadata_buffer),
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GSTREAMER_INIT_RESULT;
+    use gstreamer::{Format, State};
+    use mmids_core::codecs::h265::HevcDecoderConfigurationRecord;
+    use mmids_core::workflows::metadata::common_metadata::get_pts_offset_metadata_key;
+    use mmids_core::workflows::metadata::MetadataKeyMap;
+    use std::time::Instant;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 64;
+
+    fn pts_offset_key() -> MetadataKey {
+        get_pts_offset_metadata_key(&mut MetadataKeyMap::new())
+    }
+
+    fn parameters() -> HashMap<String, Option<String>> {
+        let mut parameters = HashMap::new();
+        parameters.insert("preset".to_string(), Some("ultrafast".to_string()));
+        parameters.insert("bitrate".to_string(), Some("500".to_string()));
+        parameters.insert("min_keyframe_interval".to_string(), Some("1".to_string()));
+
+        parameters
+    }
+
+    #[test]
+    fn can_create_encoder() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let pipeline = Pipeline::new(None);
+        let (sender, _receiver) = unbounded_channel();
+        let generator = X265EncoderGenerator {
+            pts_offset_metadata_key: pts_offset_key(),
+        };
+
+        let result = generator.create(&pipeline, &parameters(), sender);
+
+        assert!(result.is_ok(), "Expected encoder to be created");
+    }
+
+    #[test]
+    fn invalid_min_keyframe_interval_returns_error() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let pipeline = Pipeline::new(None);
+        let (sender, _receiver) = unbounded_channel();
+        let generator = X265EncoderGenerator {
+            pts_offset_metadata_key: pts_offset_key(),
+        };
+
+        let mut parameters = parameters();
+        parameters.insert("min_keyframe_interval".to_string(), Some("-1".to_string()));
+
+        let result = generator.create(&pipeline, &parameters, sender);
+
+        assert!(result.is_err(), "Expected an error");
+    }
+
+    #[test]
+    fn sequence_header_is_sent_before_frames() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let pipeline = Pipeline::new(None);
+        let (sender, mut receiver) = unbounded_channel();
+        let encoder = X265Encoder::new(sender, &parameters(), &pipeline, pts_offset_key()).unwrap();
+
+        // Feed raw frames in, which `decodebin` passes straight through to the encoder
+        let caps = Caps::builder("video/x-raw")
+            .field("format", "I420")
+            .field("width", WIDTH as i32)
+            .field("height", HEIGHT as i32)
+            .field("framerate", Fraction::new(30, 1))
+            .build();
+
+        encoder.source.set_caps(Some(&caps));
+        encoder.source.set_format(Format::Time);
+        pipeline.set_state(State::Playing).unwrap();
+
+        let frame = Bytes::from(vec![128_u8; WIDTH * HEIGHT * 3 / 2]);
+        for index in 0..10 {
+            let time = Duration::from_millis(index * 33);
+            encoder
+                .push_data(
+                    VIDEO_CODEC_H265_HVCC.clone(),
+                    frame.clone(),
+                    VideoTimestamp::from_durations(time, time),
+                    false,
+                )
+                .unwrap();
+        }
+
+        let _ = encoder.source.end_of_stream();
+
+        let mut payloads = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while payloads.len() < 2 && Instant::now() < deadline {
+            match receiver.try_recv() {
+                Ok(content) => payloads.push(content),
+                Err(_) => std::thread::sleep(Duration::from_millis(10)),
+            }
+        }
+
+        pipeline.set_state(State::Null).unwrap();
+
+        assert_eq!(payloads.len(), 2, "Expected at least two payloads");
+        match &payloads[0] {
+            MediaNotificationContent::MediaPayload {
+                payload_type,
+                is_required_for_decoding,
+                data,
+                ..
+            } => {
+                assert_eq!(
+                    payload_type, &*VIDEO_CODEC_H265_HVCC,
+                    "Unexpected payload type"
+                );
+                assert!(*is_required_for_decoding, "Expected a sequence header");
+
+                let record = HevcDecoderConfigurationRecord::parse(data).unwrap();
+                assert!(
+                    record.has_required_parameter_sets(),
+                    "Expected VPS, SPS, and PPS in the sequence header"
+                );
+            }
+
+            other => panic!("Expected a media payload, instead got {:?}", other),
+        }
+
+        match &payloads[1] {
+            MediaNotificationContent::MediaPayload {
+                is_required_for_decoding,
+                ..
+            } => assert!(!is_required_for_decoding, "Expected a frame"),
+
+            other => panic!("Expected a media payload, instead got {:?}", other),
+        }
+    }
+}
@@ -49,6 +49,13 @@ pub enum GstTranscoderRequest {
         /// The identifier of the transcoding process to stop.
         id: Uuid,
     },
+
+    /// Requests that the video encoder of a transcoding process produce a keyframe as soon as
+    /// possible, with sequence headers attached to it.
+    ForceKeyUnit {
+        /// The identifier of the transcoding process that should produce a keyframe
+        id: Uuid,
+    },
 }
 
 /// Notifications the transcoding endpoint can raise
@@ -61,6 +68,21 @@ pub enum GstTranscoderNotification {
 
     /// Notification that transcoding stopped
     TranscodingStopped(GstTranscoderStoppedCause),
+
+    /// Notification that the amount of video waiting on the video encoder has changed, or that
+    /// video frames were dropped because the encoder could not keep up
+    VideoEncoderQueuePressure(EncoderQueuePressure),
+}
+
+/// How backed up a transcoding process' video encoder is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EncoderQueuePressure {
+    /// How full the video encoder's input queue is, as a percentage
+    pub queued_percent: u8,
+
+    /// How many video frames the transcoding process has dropped because the video encoder's
+    /// input queue was full
+    pub dropped_frames: u64,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -246,6 +268,13 @@ impl EndpointActor {
                         .send(TranscodeManagerRequest::StopTranscode);
                 }
             }
+
+            GstTranscoderRequest::ForceKeyUnit { id } => {
+                info!("Keyframe requested for transcoding process id {}", id);
+                if let Some(transcode) = self.active_transcodes.get(&id) {
+                    let _ = transcode.sender.send(TranscodeManagerRequest::ForceKeyUnit);
+                }
+            }
         }
     }
 
@@ -332,6 +361,7 @@ impl EndpointActor {
             audio_encoder,
             inbound_media: params.input_media,
             outbound_media: outbound_media_sender,
+            notification_channel: params.notification_channel.clone(),
             process_id: params.id,
         };
 
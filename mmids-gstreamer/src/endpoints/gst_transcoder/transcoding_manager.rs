@@ -1,19 +1,22 @@
-use crate::encoders::{AudioEncoder, VideoEncoder};
+use crate::encoders::{AudioEncoder, EncoderQueueFullError, VideoEncoder};
+use crate::endpoints::gst_transcoder::{EncoderQueuePressure, GstTranscoderNotification};
 use futures::StreamExt;
 use gstreamer::bus::BusStream;
+use gstreamer::event::CustomUpstream;
 use gstreamer::prelude::*;
-use gstreamer::{MessageView, Pipeline, State};
+use gstreamer::{MessageView, Pipeline, State, Structure};
 use mmids_core::actor_utils::{notify_on_unbounded_closed, notify_on_unbounded_recv};
 use mmids_core::workflows::metadata::{MetadataKey, MetadataValue};
 use mmids_core::workflows::{MediaNotificationContent, MediaType};
 use mmids_core::VideoTimestamp;
 use std::time::Duration;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
-use tracing::{error, info, instrument};
+use tracing::{error, info, instrument, warn};
 use uuid::Uuid;
 
 pub enum TranscodeManagerRequest {
     StopTranscode,
+    ForceKeyUnit,
 }
 
 pub struct TranscoderParams {
@@ -22,6 +25,7 @@ pub struct TranscoderParams {
     pub audio_encoder: Box<dyn AudioEncoder + Send>,
     pub inbound_media: UnboundedReceiver<MediaNotificationContent>,
     pub outbound_media: UnboundedSender<MediaNotificationContent>,
+    pub notification_channel: UnboundedSender<GstTranscoderNotification>,
     pub pipeline: Pipeline,
 }
 
@@ -63,6 +67,10 @@ struct TranscodeManager {
     audio_encoder: Box<dyn AudioEncoder + Send>,
     pipeline: Pipeline,
     pts_offset_metadata_key: MetadataKey,
+    notification_channel: UnboundedSender<GstTranscoderNotification>,
+    dropped_video_frames: u64,
+    is_dropping_video: bool,
+    last_reported_pressure: Option<EncoderQueuePressure>,
 }
 
 impl TranscodeManager {
@@ -98,6 +106,10 @@ impl TranscodeManager {
             audio_encoder: parameters.audio_encoder,
             pipeline: parameters.pipeline,
             pts_offset_metadata_key,
+            notification_channel: parameters.notification_channel,
+            dropped_video_frames: 0,
+            is_dropping_video: false,
+            last_reported_pressure: None,
         }
     }
 
@@ -233,10 +245,32 @@ impl TranscodeManager {
                         is_required_for_decoding,
                     );
 
-                    if let Err(error) = result {
-                        error!("Failed to push media to video encoder: {}", error);
-                        self.termination_requested = true;
+                    match result {
+                        Ok(_) => {
+                            if self.is_dropping_video && !is_required_for_decoding {
+                                info!("Video encoder caught up, no longer dropping frames");
+                                self.is_dropping_video = false;
+                            }
+                        }
+
+                        Err(error) if error.downcast_ref::<EncoderQueueFullError>().is_some() => {
+                            // Only log when frames start being dropped, as a slow encoder will
+                            // usually cause every frame to be dropped for a while
+                            if !self.is_dropping_video {
+                                warn!("Video encoder can't keep up, dropping frames: {}", error);
+                                self.is_dropping_video = true;
+                            }
+
+                            self.dropped_video_frames += 1;
+                        }
+
+                        Err(error) => {
+                            error!("Failed to push media to video encoder: {}", error);
+                            self.termination_requested = true;
+                        }
                     }
+
+                    self.report_video_queue_pressure();
                 }
 
                 MediaType::Other => (), // ignore non audio/video types
@@ -244,11 +278,62 @@ impl TranscodeManager {
         }
     }
 
+    /// Lets the transcode requester know how backed up the video encoder is. To avoid flooding
+    /// the requester, this is only reported when frames are dropped or the queue's fill level
+    /// moves into a different 10% band.
+    fn report_video_queue_pressure(&mut self) {
+        let level = match self.video_encoder.queue_level() {
+            Some(level) => level,
+            None => return,
+        };
+
+        let queued_percent = match level.max_bytes {
+            0 => 100,
+            max => (level.queued_bytes.min(max) * 100 / max) as u8,
+        };
+
+        let pressure = EncoderQueuePressure {
+            queued_percent,
+            dropped_frames: self.dropped_video_frames,
+        };
+
+        let should_report = match self.last_reported_pressure {
+            Some(last) => {
+                last.dropped_frames != pressure.dropped_frames
+                    || last.queued_percent / 10 != pressure.queued_percent / 10
+            }
+
+            None => true,
+        };
+
+        if should_report {
+            self.last_reported_pressure = Some(pressure);
+            let _ = self.notification_channel.send(
+                GstTranscoderNotification::VideoEncoderQueuePressure(pressure),
+            );
+        }
+    }
+
     fn handle_request(&mut self, request: TranscodeManagerRequest) {
         match request {
             TranscodeManagerRequest::StopTranscode => {
                 self.termination_requested = true;
             }
+
+            TranscodeManagerRequest::ForceKeyUnit => {
+                // Upstream force key unit events are sent to the pipeline's sinks and travel
+                // upstream until they reach the video encoder.
+                let structure = Structure::builder("GstForceKeyUnit")
+                    .field("all-headers", true)
+                    .build();
+
+                if !self
+                    .pipeline
+                    .send_event(CustomUpstream::builder(structure).build())
+                {
+                    warn!("Transcoding pipeline did not accept the force key unit event");
+                }
+            }
         }
     }
 }
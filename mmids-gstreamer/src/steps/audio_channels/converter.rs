@@ -0,0 +1,230 @@
+//! Gstreamer elements that mix decoded audio down (or up) to a specific channel layout.
+
+use crate::utils::create_gst_element;
+use anyhow::Result;
+use gstreamer::prelude::*;
+use gstreamer::{Bitmask, Caps, Element};
+use std::fmt::{Display, Formatter};
+
+/// Gstreamer elements channel conversion is built from
+pub const REQUIRED_ELEMENTS: &[&str] = &["audioconvert", "capsfilter"];
+
+/// Channel mask of gstreamer's default 5.1 layout (front left, front right, front center, LFE,
+/// rear left, rear right)
+const SURROUND_51_CHANNEL_MASK: u64 = 0x3f;
+
+/// Gain applied to channels folded into both sides of a downmix (-3dB)
+const FOLD_GAIN: f32 = 0.707;
+
+/// The channel layouts audio can be converted to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelLayout {
+    Mono,
+    Stereo,
+    Surround51,
+}
+
+impl ChannelLayout {
+    /// Parses a layout by name, returning `None` for unsupported layouts
+    pub fn from_name(name: &str) -> Option<ChannelLayout> {
+        match name.trim().to_lowercase().as_str() {
+            "mono" => Some(ChannelLayout::Mono),
+            "stereo" => Some(ChannelLayout::Stereo),
+            "5.1" => Some(ChannelLayout::Surround51),
+            _ => None,
+        }
+    }
+
+    /// Gets the layout with the specified number of channels, if it's a supported one
+    pub fn from_channel_count(channels: u32) -> Option<ChannelLayout> {
+        match channels {
+            1 => Some(ChannelLayout::Mono),
+            2 => Some(ChannelLayout::Stereo),
+            6 => Some(ChannelLayout::Surround51),
+            _ => None,
+        }
+    }
+
+    pub fn channels(&self) -> u32 {
+        match self {
+            ChannelLayout::Mono => 1,
+            ChannelLayout::Stereo => 2,
+            ChannelLayout::Surround51 => 6,
+        }
+    }
+}
+
+impl Display for ChannelLayout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChannelLayout::Mono => write!(f, "mono"),
+            ChannelLayout::Stereo => write!(f, "stereo"),
+            ChannelLayout::Surround51 => write!(f, "5.1"),
+        }
+    }
+}
+
+/// Creates the elements that convert audio to the target layout. When the source's layout is
+/// known, an explicit mix matrix is used to convert between them. Otherwise `audioconvert`'s
+/// default channel mixing is used.
+pub fn create_elements(
+    source_layout: Option<ChannelLayout>,
+    target_layout: ChannelLayout,
+) -> Result<Vec<Element>> {
+    let convert = create_gst_element("audioconvert")?;
+    let capsfilter = create_gst_element("capsfilter")?;
+
+    if let Some(source_layout) = source_layout {
+        let matrix = mix_matrix(source_layout, target_layout);
+        convert.set_property_from_str("mix-matrix", &format_mix_matrix(&matrix));
+    }
+
+    let mut caps = Caps::builder("audio/x-raw").field("channels", target_layout.channels() as i32);
+    if target_layout == ChannelLayout::Surround51 {
+        caps = caps.field("channel-mask", Bitmask::new(SURROUND_51_CHANNEL_MASK));
+    }
+
+    capsfilter.set_property("caps", caps.build());
+
+    Ok(vec![convert, capsfilter])
+}
+
+/// Builds the mix matrix converting between two layouts, with a row for each target channel and
+/// a column for each source channel. Channels are in gstreamer's default order, so 5.1 audio is
+/// front left, front right, front center, LFE, rear left, rear right. Downmixes are scaled so
+/// full scale source channels can't clip.
+fn mix_matrix(source: ChannelLayout, target: ChannelLayout) -> Vec<Vec<f32>> {
+    let stereo_from_51 = {
+        let scale = 1.0 / (1.0 + FOLD_GAIN * 2.0);
+        vec![
+            vec![scale, 0.0, FOLD_GAIN * scale, 0.0, FOLD_GAIN * scale, 0.0],
+            vec![0.0, scale, FOLD_GAIN * scale, 0.0, 0.0, FOLD_GAIN * scale],
+        ]
+    };
+
+    match (source, target) {
+        (ChannelLayout::Surround51, ChannelLayout::Stereo) => stereo_from_51,
+        (ChannelLayout::Surround51, ChannelLayout::Mono) => {
+            let mono = (0..6)
+                .map(|column| (stereo_from_51[0][column] + stereo_from_51[1][column]) / 2.0)
+                .collect();
+
+            vec![mono]
+        }
+
+        (ChannelLayout::Stereo, ChannelLayout::Mono) => vec![vec![0.5, 0.5]],
+        (ChannelLayout::Mono, ChannelLayout::Stereo) => vec![vec![1.0], vec![1.0]],
+
+        // Mono audio becomes the center channel, and stereo stays in the front channels
+        (ChannelLayout::Mono, ChannelLayout::Surround51) => {
+            let mut matrix = vec![vec![0.0]; 6];
+            matrix[2][0] = 1.0;
+            matrix
+        }
+
+        (ChannelLayout::Stereo, ChannelLayout::Surround51) => {
+            let mut matrix = vec![vec![0.0, 0.0]; 6];
+            matrix[0][0] = 1.0;
+            matrix[1][1] = 1.0;
+            matrix
+        }
+
+        // Same layout
+        _ => (0..target.channels())
+            .map(|row| {
+                (0..source.channels())
+                    .map(|column| if row == column { 1.0 } else { 0.0 })
+                    .collect()
+            })
+            .collect(),
+    }
+}
+
+/// Formats the mix matrix as a serialized `GstValueArray` of float arrays
+fn format_mix_matrix(matrix: &[Vec<f32>]) -> String {
+    let rows = matrix
+        .iter()
+        .map(|row| {
+            let values = row
+                .iter()
+                .map(|value| format!("(float){}", value))
+                .collect::<Vec<_>>();
+
+            format!("<{}>", values.join(", "))
+        })
+        .collect::<Vec<_>>();
+
+    format!("<{}>", rows.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::steps::filter_encode::test_utils::run_through_elements;
+    use crate::utils::set_gst_buffer;
+    use crate::GSTREAMER_INIT_RESULT;
+    use bytes::Bytes;
+    use std::time::Duration;
+
+    const SAMPLE_RATE: usize = 48000;
+    const SAMPLES_PER_BUFFER: usize = 1024;
+
+    #[test]
+    fn downmix_matrix_has_row_per_target_and_column_per_source_channel() {
+        let matrix = mix_matrix(ChannelLayout::Surround51, ChannelLayout::Stereo);
+
+        assert_eq!(matrix.len(), 2, "Unexpected number of rows");
+        assert!(
+            matrix.iter().all(|row| row.len() == 6),
+            "Expected a column for every source channel"
+        );
+        assert_eq!(
+            matrix[0][3], 0.0,
+            "Expected LFE to be dropped from the left"
+        );
+        assert!(
+            matrix.iter().all(|row| row.iter().sum::<f32>() <= 1.0),
+            "Expected no row to be able to clip"
+        );
+    }
+
+    #[test]
+    fn surround_audio_downmixed_to_stereo() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let caps = Caps::builder("audio/x-raw")
+            .field("format", "S16LE")
+            .field("layout", "interleaved")
+            .field("rate", SAMPLE_RATE as i32)
+            .field("channels", 6)
+            .field("channel-mask", Bitmask::new(SURROUND_51_CHANNEL_MASK))
+            .build();
+
+        let samples = Bytes::from(vec![0_u8; SAMPLES_PER_BUFFER * 6 * 2]);
+        let buffers = (0..20)
+            .map(|index| {
+                let micros = index * SAMPLES_PER_BUFFER * 1_000_000 / SAMPLE_RATE;
+                let time = Duration::from_micros(micros as u64);
+                set_gst_buffer(samples.clone(), Some(time), Some(time)).unwrap()
+            })
+            .collect();
+
+        let elements =
+            create_elements(Some(ChannelLayout::Surround51), ChannelLayout::Stereo).unwrap();
+
+        let output = run_through_elements(&elements, &caps, buffers);
+        let caps = output
+            .first()
+            .expect("No converted audio received")
+            .caps()
+            .expect("Converted audio had no caps")
+            .to_owned();
+
+        let structure = caps.structure(0).unwrap();
+        assert_eq!(
+            structure.get::<i32>("channels").unwrap(),
+            2,
+            "Unexpected channels"
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! The audio channels workflow step converts the audio of every stream flowing through it to a
+//! configured channel layout, for outputs and devices that can't play the source's layout (e.g.
+//! players that can't handle surround sound). The audio is decoded, mixed down (or up) with
+//! gstreamer's `audioconvert` element and re-encoded, while video is passed through untouched.
+//!
+//! The `layout` parameter is required and must be one of `mono`, `stereo` or `5.1`. When the
+//! source's layout is one of those, an explicit channel mix matrix is used, which folds the center
+//! and surround channels into the front channels when downmixing and drops the LFE channel.
+//! Other source layouts are mixed with `audioconvert`'s default channel mixing. The converted
+//! audio is encoded the same way as the basic transcode step, with the `audio` parameter naming
+//! the encoder to use and `audio_` prefixed parameters being passed to it.
+//!
+//! Each stream's source layout and the target layout are reported through the step's state
+//! details.
+
+mod converter;
+
+use crate::encoders::EncoderFactory;
+use crate::steps::audio_channels::converter::ChannelLayout;
+use crate::steps::filter_encode::{
+    aac_sequence_header_config, ensure_filter_elements_available, per_stream_detail,
+    EncoderSettings, EncoderSettingsError, FilterEncodeStep, MediaFilter, StreamSummary,
+};
+use crate::utils::GstElementError;
+use crate::GSTREAMER_INIT_RESULT;
+use anyhow::Result;
+use gstreamer::Element;
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{StepCreationResult, StepStatus};
+use mmids_core::workflows::{MediaNotificationContent, MediaType};
+use mmids_core::StreamId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{info, warn};
+
+pub const LAYOUT: &str = "layout";
+
+const SOURCE_LAYOUTS_DETAIL: &str = "source_layouts";
+const TARGET_LAYOUT_DETAIL: &str = "target_layout";
+
+/// Generates new instances of the audio channels workflow step
+pub struct AudioChannelsStepGenerator {
+    encoder_factory: Arc<EncoderFactory>,
+}
+
+/// Converts each stream's audio to the target layout, mixing it based on the source's layout
+struct ChannelsFilter {
+    target_layout: ChannelLayout,
+}
+
+struct ConvertedStream {
+    /// The number of channels in the source's audio, if known
+    source_channels: Option<u32>,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum StepStartupError {
+    #[error("No {} parameter specified", LAYOUT)]
+    NoLayout,
+
+    #[error(
+        "Invalid {} value of '{0}'.  It must be one of 'mono', 'stereo' or '5.1'",
+        LAYOUT
+    )]
+    InvalidLayout(String),
+
+    #[error(transparent)]
+    InvalidEncoderSettings(#[from] EncoderSettingsError),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
+
+    #[error("Audio channels cannot be converted: {0}")]
+    MissingElement(#[from] GstElementError),
+}
+
+impl AudioChannelsStepGenerator {
+    /// Creates the generator, with the encoder factory converted audio is encoded with
+    pub fn new(encoder_factory: Arc<EncoderFactory>) -> Self {
+        AudioChannelsStepGenerator { encoder_factory }
+    }
+}
+
+impl StepGenerator for AudioChannelsStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let target_layout = match definition.parameters.get(LAYOUT) {
+            Some(Some(value)) => match ChannelLayout::from_name(value) {
+                Some(layout) => layout,
+                None => return Err(Box::new(StepStartupError::InvalidLayout(value.clone()))),
+            },
+
+            _ => return Err(Box::new(StepStartupError::NoLayout)),
+        };
+
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
+        ensure_filter_elements_available(converter::REQUIRED_ELEMENTS)
+            .map_err(StepStartupError::from)?;
+
+        let settings = EncoderSettings::audio_from_definition(&definition, &self.encoder_factory)
+            .map_err(StepStartupError::from)?;
+
+        let filter = ChannelsFilter { target_layout };
+        let step = FilterEncodeStep::new(filter, settings, self.encoder_factory.clone(), None);
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl MediaFilter for ChannelsFilter {
+    type StreamState = ConvertedStream;
+    type Event = ();
+
+    const MEDIA_TYPE: MediaType = MediaType::Audio;
+    const NAME: &'static str = "channel converter";
+
+    fn new_stream(&self, _stream_name: &Arc<String>) -> Self::StreamState {
+        ConvertedStream {
+            source_channels: None,
+        }
+    }
+
+    fn create_elements(
+        &self,
+        stream: &Self::StreamState,
+        _events: &UnboundedSender<Self::Event>,
+    ) -> Result<Vec<Element>> {
+        let source_layout = stream
+            .source_channels
+            .and_then(ChannelLayout::from_channel_count);
+
+        converter::create_elements(source_layout, self.target_layout)
+    }
+
+    fn media_received(
+        &mut self,
+        stream_id: &StreamId,
+        stream: &mut Self::StreamState,
+        content: &MediaNotificationContent,
+    ) {
+        let is_sequence_header = matches!(
+            content,
+            MediaNotificationContent::MediaPayload {
+                is_required_for_decoding: true,
+                ..
+            }
+        );
+
+        if !is_sequence_header {
+            return;
+        }
+
+        // Each sequence header may describe a different layout, which the converter's mix
+        // matrix is built for
+        stream.source_channels = aac_sequence_header_config(content)
+            .and_then(|config| aac_channel_count(config.channel_configuration));
+
+        let source_layout = stream
+            .source_channels
+            .and_then(ChannelLayout::from_channel_count);
+
+        info!(
+            stream_id = %stream_id.0,
+            "Converting stream's audio from {} to {}",
+            source_layout.map(|layout| layout.to_string()).unwrap_or_else(|| "an unknown layout".to_string()),
+            self.target_layout
+        );
+    }
+
+    fn encoded_media_received(
+        &mut self,
+        stream_id: &StreamId,
+        _stream: &mut Self::StreamState,
+        content: &MediaNotificationContent,
+    ) {
+        let target_channels = self.target_layout.channels();
+        match aac_sequence_header_config(content) {
+            Some(config)
+                if aac_channel_count(config.channel_configuration) != Some(target_channels) =>
+            {
+                warn!(
+                    stream_id = %stream_id.0,
+                    "Converted audio has a channel configuration of {} instead of {} channels",
+                    config.channel_configuration, target_channels
+                )
+            }
+
+            _ => (),
+        }
+    }
+
+    fn add_state_details(
+        &self,
+        streams: &[StreamSummary<'_, Self::StreamState>],
+        details: &mut HashMap<String, String>,
+    ) {
+        let source_layouts = per_stream_detail(streams, |stream| {
+            stream.source_channels.map(|channels| {
+                match ChannelLayout::from_channel_count(channels) {
+                    Some(layout) => layout.to_string(),
+                    None => format!("{} channels", channels),
+                }
+            })
+        });
+
+        details.insert(SOURCE_LAYOUTS_DETAIL.to_string(), source_layouts);
+        details.insert(
+            TARGET_LAYOUT_DETAIL.to_string(),
+            self.target_layout.to_string(),
+        );
+    }
+}
+
+/// Gets the number of channels for an AAC channel configuration, if it's one that defines them
+fn aac_channel_count(channel_configuration: u8) -> Option<u32> {
+    match channel_configuration {
+        1..=6 => Some(channel_configuration as u32),
+        7 => Some(8),
+        _ => None,
+    }
+}
@@ -0,0 +1,193 @@
+//! Gstreamer pipeline that decodes an audio stream and measures its levels.
+
+use crate::steps::audio_levels::meter::{LevelMeter, LevelReport};
+use crate::utils::{create_gst_element, set_gst_buffer, set_source_audio_sequence_header};
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use gstreamer::prelude::*;
+use gstreamer::{Caps, Element, FlowError, FlowSuccess, Pipeline, State};
+use gstreamer_app::{AppSink, AppSinkCallbacks, AppSrc};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::error;
+
+/// Gstreamer elements the decoding pipeline is built from
+pub const REQUIRED_ELEMENTS: &[&str] = &[
+    "appsrc",
+    "queue",
+    "decodebin",
+    "audioconvert",
+    "capsfilter",
+    "appsink",
+];
+
+/// Decodes audio pushed into it, and sends out a level report for each window of decoded audio
+pub struct LevelDecoder {
+    pipeline: Pipeline,
+    source: AppSrc,
+}
+
+impl LevelDecoder {
+    pub fn new(
+        interval: Duration,
+        waveform_points: usize,
+        report_sender: UnboundedSender<LevelReport>,
+    ) -> Result<LevelDecoder> {
+        let pipeline = Pipeline::new(None);
+        let appsrc = create_gst_element("appsrc")?;
+        let queue = create_gst_element("queue")?;
+        let decoder = create_gst_element("decodebin")?;
+        let convert = create_gst_element("audioconvert")?;
+        let capsfilter = create_gst_element("capsfilter")?;
+        let appsink = create_gst_element("appsink")?;
+
+        pipeline
+            .add_many(&[&appsrc, &queue, &decoder, &convert, &capsfilter, &appsink])
+            .with_context(|| "Failed to add level decoder's elements to pipeline")?;
+
+        Element::link_many(&[&appsrc, &queue, &decoder])
+            .with_context(|| "Failed to link appsrc -> queue -> decoder")?;
+
+        Element::link_many(&[&convert, &capsfilter, &appsink])
+            .with_context(|| "Failed to link convert to sink")?;
+
+        // decodebin's audio pad is added dynamically
+        let link_destination = convert;
+        decoder.connect_pad_added(move |src, src_pad| {
+            if src
+                .link_pads(Some(&src_pad.name()), &link_destination, Some("sink"))
+                .is_err()
+            {
+                error!(
+                    src_caps = ?src_pad.caps(),
+                    "Failed to link `decodebin`'s {} pad to audioconvert element",
+                    src_pad.name()
+                );
+            }
+        });
+
+        let caps = Caps::builder("audio/x-raw")
+            .field("format", "S16LE")
+            .field("layout", "interleaved")
+            .build();
+
+        capsfilter.set_property("caps", caps);
+
+        let appsink = appsink
+            .dynamic_cast::<AppSink>()
+            .map_err(|_| anyhow!("appsink could not be cast to 'AppSink'"))?;
+
+        // Levels are measured as audio is decoded, so only the reports leave the pipeline
+        let mut meter = LevelMeter::new(interval, waveform_points);
+        appsink.set_callbacks(
+            AppSinkCallbacks::builder()
+                .new_sample(
+                    move |sink| match sample_received(sink, &mut meter, &report_sender) {
+                        Ok(_) => Ok(FlowSuccess::Ok),
+                        Err(error) => {
+                            error!("new_sample callback error received: {:?}", error);
+                            Err(FlowError::Error)
+                        }
+                    },
+                )
+                .build(),
+        );
+
+        let appsrc = appsrc
+            .dynamic_cast::<AppSrc>()
+            .map_err(|_| anyhow!("source element could not be cast to 'Appsrc'"))?;
+
+        pipeline
+            .set_state(State::Playing)
+            .with_context(|| "Failed to set level decoder pipeline to playing")?;
+
+        Ok(LevelDecoder {
+            pipeline,
+            source: appsrc,
+        })
+    }
+
+    /// Pushes an audio frame into the decoder
+    pub fn push_data(
+        &self,
+        payload_type: Arc<String>,
+        data: Bytes,
+        timestamp: Duration,
+        is_sequence_header: bool,
+    ) -> Result<()> {
+        let buffer = set_gst_buffer(data, Some(timestamp), Some(timestamp))
+            .with_context(|| "Failed to set buffer")?;
+
+        if is_sequence_header {
+            set_source_audio_sequence_header(&self.source, payload_type, buffer)
+                .with_context(|| "Failed to set sequence header for level decoder")?;
+        } else {
+            self.source
+                .push_buffer(buffer)
+                .with_context(|| "Failed to push the buffer into the level decoder")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for LevelDecoder {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(State::Null);
+    }
+}
+
+fn sample_received(
+    sink: &AppSink,
+    meter: &mut LevelMeter,
+    report_sender: &UnboundedSender<LevelReport>,
+) -> Result<()> {
+    let sample = sink
+        .pull_sample()
+        .with_context(|| "Sink had no sample available")?;
+
+    let structure = sample
+        .caps()
+        .and_then(|caps| caps.structure(0))
+        .with_context(|| "Decoded sample did not have caps")?;
+
+    let sample_rate = structure
+        .get::<i32>("rate")
+        .with_context(|| "Decoded sample's caps did not have a rate")?;
+
+    let channels = structure
+        .get::<i32>("channels")
+        .with_context(|| "Decoded sample's caps did not have a channel count")?;
+
+    let buffer = sample
+        .buffer()
+        .with_context(|| "Sample did not contain a buffer")?;
+
+    let pts = buffer
+        .pts()
+        .with_context(|| "Decoded buffer did not have a pts")?;
+
+    let map = buffer
+        .map_readable()
+        .with_context(|| "Decoded buffer could not be made readable")?;
+
+    let samples = map
+        .as_slice()
+        .chunks_exact(2)
+        .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+        .collect::<Vec<_>>();
+
+    let report = meter.add_samples(
+        Duration::from_nanos(pts.nseconds()),
+        &samples,
+        channels as usize,
+        sample_rate as u32,
+    );
+
+    if let Some(report) = report {
+        let _ = report_sender.send(report);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,258 @@
+//! Measures the levels of decoded audio over fixed length windows, for driving VU meters and
+//! waveform displays.
+
+use std::time::Duration;
+
+/// The measured levels of a window of audio
+#[derive(Clone, Debug, PartialEq)]
+pub struct LevelReport {
+    /// Presentation time of the start of the window
+    pub pts: Duration,
+
+    /// Root mean square level across all channels, in dBFS
+    pub rms_db: f64,
+
+    /// Peak level across all channels, in dBFS
+    pub peak_db: f64,
+
+    /// The peak of each evenly sized slice of the window, as a fraction of full scale from 0 to 1.
+    /// Empty if no waveform was requested.
+    pub waveform: Vec<f32>,
+}
+
+/// Accumulates decoded audio, producing a level report each time a full window has been seen
+pub struct LevelMeter {
+    interval: Duration,
+    waveform_points: usize,
+    window_start: Option<Duration>,
+    sum_of_squares: f64,
+    sample_count: u64,
+    peak: u16,
+    waveform: Vec<f32>,
+    point_peak: u16,
+    point_frames: u64,
+}
+
+impl LevelMeter {
+    /// Creates a meter that reports levels every `interval`, with a waveform of up to
+    /// `waveform_points` points per report
+    pub fn new(interval: Duration, waveform_points: usize) -> Self {
+        LevelMeter {
+            interval,
+            waveform_points,
+            window_start: None,
+            sum_of_squares: 0.0,
+            sample_count: 0,
+            peak: 0,
+            waveform: Vec::with_capacity(waveform_points),
+            point_peak: 0,
+            point_frames: 0,
+        }
+    }
+
+    /// Adds a buffer of interleaved signed 16 bit samples presented at the specified time. Returns
+    /// a report if the buffer completed a window.
+    pub fn add_samples(
+        &mut self,
+        pts: Duration,
+        samples: &[i16],
+        channels: usize,
+        sample_rate: u32,
+    ) -> Option<LevelReport> {
+        let channels = channels.max(1);
+        let sample_rate = sample_rate.max(1);
+        let window_start = match self.window_start {
+            // Audio going back in time can't belong to the current window
+            Some(start) if pts >= start => start,
+            _ => {
+                self.reset(pts);
+                pts
+            }
+        };
+
+        let frames_per_point = if self.waveform_points > 0 {
+            let window_frames = self.interval.as_secs_f64() * sample_rate as f64;
+            ((window_frames / self.waveform_points as f64) as u64).max(1)
+        } else {
+            u64::MAX
+        };
+
+        for frame in samples.chunks_exact(channels) {
+            let frame_peak = frame.iter().map(|x| x.unsigned_abs()).max().unwrap_or(0);
+            self.sum_of_squares += frame.iter().map(|x| *x as f64 * *x as f64).sum::<f64>();
+            self.sample_count += channels as u64;
+            self.peak = self.peak.max(frame_peak);
+
+            if self.waveform_points > 0 {
+                self.point_peak = self.point_peak.max(frame_peak);
+                self.point_frames += 1;
+                if self.point_frames >= frames_per_point {
+                    self.finish_waveform_point();
+                }
+            }
+        }
+
+        let buffer_end =
+            pts + Duration::from_secs_f64((samples.len() / channels) as f64 / sample_rate as f64);
+
+        if buffer_end.saturating_sub(window_start) < self.interval {
+            return None;
+        }
+
+        if self.point_frames > 0 {
+            self.finish_waveform_point();
+        }
+
+        let report = LevelReport {
+            pts: window_start,
+            rms_db: to_db((self.sum_of_squares / self.sample_count.max(1) as f64).sqrt()),
+            peak_db: to_db(self.peak as f64),
+            waveform: std::mem::take(&mut self.waveform),
+        };
+
+        self.reset(buffer_end);
+        Some(report)
+    }
+
+    fn finish_waveform_point(&mut self) {
+        if self.waveform.len() < self.waveform_points {
+            self.waveform.push(self.point_peak as f32 / 32768.0);
+        }
+
+        self.point_peak = 0;
+        self.point_frames = 0;
+    }
+
+    fn reset(&mut self, window_start: Duration) {
+        self.window_start = Some(window_start);
+        self.sum_of_squares = 0.0;
+        self.sample_count = 0;
+        self.peak = 0;
+        self.waveform.clear();
+        self.point_peak = 0;
+        self.point_frames = 0;
+    }
+}
+
+/// Converts a 16 bit sample magnitude into dBFS
+fn to_db(magnitude: f64) -> f64 {
+    if magnitude <= 0.0 {
+        return f64::NEG_INFINITY;
+    }
+
+    20.0 * (magnitude / 32768.0).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// Interleaved samples of a 1kHz sine wave at the specified fraction of full scale
+    fn tone(amplitude: f64, frames: usize, channels: usize) -> Vec<i16> {
+        (0..frames)
+            .flat_map(|index| {
+                let time = index as f64 / SAMPLE_RATE as f64;
+                let value = amplitude * 32767.0 * (2.0 * PI * 1000.0 * time).sin();
+                vec![value.round() as i16; channels]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tone_level_reported() {
+        let mut meter = LevelMeter::new(Duration::from_millis(100), 0);
+        let samples = tone(0.5, SAMPLE_RATE as usize / 10, 2);
+
+        let report = meter
+            .add_samples(Duration::from_secs(3), &samples, 2, SAMPLE_RATE)
+            .expect("Expected a report");
+
+        // A sine wave's RMS is 3dB below its peak
+        let peak_db = 20.0 * 0.5_f64.log10();
+        assert!(
+            (report.peak_db - peak_db).abs() < 0.1,
+            "Expected peak of {:.2}dB, got {:.2}dB",
+            peak_db,
+            report.peak_db
+        );
+
+        assert!(
+            (report.rms_db - (peak_db - 3.01)).abs() < 0.1,
+            "Expected rms of {:.2}dB, got {:.2}dB",
+            peak_db - 3.01,
+            report.rms_db
+        );
+
+        assert_eq!(report.pts, Duration::from_secs(3), "Unexpected pts");
+    }
+
+    #[test]
+    fn report_only_produced_once_window_is_complete() {
+        let mut meter = LevelMeter::new(Duration::from_millis(100), 0);
+        let buffer = tone(0.5, 1024, 1);
+        let buffer_length = Duration::from_secs_f64(1024.0 / SAMPLE_RATE as f64);
+
+        let mut reports = Vec::new();
+        let mut pts = Duration::from_secs(0);
+        for _ in 0..10 {
+            if let Some(report) = meter.add_samples(pts, &buffer, 1, SAMPLE_RATE) {
+                reports.push(report);
+            }
+
+            pts += buffer_length;
+        }
+
+        // 10 buffers of 1024 samples is ~213ms, so two full windows of 5 buffers each
+        assert_eq!(reports.len(), 2, "Unexpected number of reports");
+        assert_eq!(
+            reports[0].pts,
+            Duration::from_secs(0),
+            "Unexpected first pts"
+        );
+        assert!(
+            reports[1].pts > Duration::from_millis(100),
+            "Second report started at {:?}",
+            reports[1].pts
+        );
+    }
+
+    #[test]
+    fn silence_reported_as_negative_infinity() {
+        let mut meter = LevelMeter::new(Duration::from_millis(100), 0);
+        let samples = vec![0; SAMPLE_RATE as usize / 10];
+
+        let report = meter
+            .add_samples(Duration::from_secs(0), &samples, 1, SAMPLE_RATE)
+            .expect("Expected a report");
+
+        assert_eq!(report.rms_db, f64::NEG_INFINITY, "Unexpected rms");
+        assert_eq!(report.peak_db, f64::NEG_INFINITY, "Unexpected peak");
+    }
+
+    #[test]
+    fn waveform_follows_amplitude_changes() {
+        let mut meter = LevelMeter::new(Duration::from_millis(100), 10);
+        let frames = SAMPLE_RATE as usize / 20;
+        let mut samples = tone(0.25, frames, 1);
+        samples.extend(tone(0.75, frames, 1));
+
+        let report = meter
+            .add_samples(Duration::from_secs(0), &samples, 1, SAMPLE_RATE)
+            .expect("Expected a report");
+
+        assert_eq!(report.waveform.len(), 10, "Unexpected number of points");
+        for (index, point) in report.waveform.iter().enumerate() {
+            let expected = if index < 5 { 0.25 } else { 0.75 };
+            assert!(
+                (point - expected).abs() < 0.01,
+                "Point {} was {} instead of {}",
+                index,
+                point,
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,398 @@
+//! The audio levels workflow step measures the loudness of every stream's audio, for driving VU
+//! meters and waveform displays in operator dashboards. The audio is decoded and its RMS and peak
+//! levels (in dBFS, across all channels) are measured over windows of `interval` seconds (default
+//! of 0.1). All media is passed to the next step untouched.
+//!
+//! After each window a metadata notification is sent to the next step containing the
+//! `AUDIO_LEVELS_PTS_METADATA_KEY`, `AUDIO_RMS_METADATA_KEY` and `AUDIO_PEAK_METADATA_KEY` keys. When the `waveform_points`
+//! parameter is set above zero, the `AUDIO_WAVEFORM_METADATA_KEY` key is also included, holding
+//! a comma separated list of that many points. Each point is the peak of an even slice of the
+//! window, as a fraction of full scale between 0 and 1. Silent audio is reported as `-inf`.
+//!
+//! Levels are measured as the audio is decoded, so only the reports leave the decoding pipeline.
+//! The most recent levels of each stream are reported through the step's state details.
+
+mod decoder;
+mod meter;
+
+use crate::steps::audio_levels::decoder::LevelDecoder;
+use crate::steps::audio_levels::meter::LevelReport;
+use crate::utils::{ensure_elements_available, GstElementError};
+use crate::GSTREAMER_INIT_RESULT;
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use mmids_core::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use mmids_core::StreamId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::unbounded_channel;
+use tracing::{error, warn};
+
+pub const INTERVAL: &str = "interval";
+pub const WAVEFORM_POINTS: &str = "waveform_points";
+
+/// Metadata key holding the presentation time of the start of the window the levels were
+/// measured over, in milliseconds
+pub const AUDIO_LEVELS_PTS_METADATA_KEY: &str = "mmids_audio_levels_pts";
+
+/// Metadata key holding the RMS level of the last window of audio, in dBFS
+pub const AUDIO_RMS_METADATA_KEY: &str = "mmids_audio_rms_db";
+
+/// Metadata key holding the peak level of the last window of audio, in dBFS
+pub const AUDIO_PEAK_METADATA_KEY: &str = "mmids_audio_peak_db";
+
+/// Metadata key holding the downsampled waveform of the last window of audio
+pub const AUDIO_WAVEFORM_METADATA_KEY: &str = "mmids_audio_waveform";
+
+const LEVELS_DETAIL: &str = "levels";
+const INTERVAL_DETAIL: &str = "interval";
+
+/// Generates new instances of the audio levels workflow step
+#[derive(Default)]
+pub struct AudioLevelsStepGenerator {}
+
+struct MeasuredStream {
+    stream_name: Arc<String>,
+    decoder: Option<LevelDecoder>,
+
+    /// Identifies the current decoder, so reports from decoders that have been replaced can be
+    /// ignored
+    decoder_id: u64,
+    latest_report: Option<LevelReport>,
+}
+
+struct AudioLevelsStep {
+    interval: Duration,
+    waveform_points: usize,
+    streams: HashMap<StreamId, MeasuredStream>,
+    next_decoder_id: u64,
+}
+
+enum FutureResult {
+    LevelsMeasured {
+        stream_id: StreamId,
+        decoder_id: u64,
+        report: LevelReport,
+    },
+
+    // Decoders only stop when they are dropped, so there's nothing to do when this occurs
+    DecoderStopped,
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(thiserror::Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "Invalid {} value of '{0}'.  It must be a number of seconds greater than zero",
+        INTERVAL
+    )]
+    InvalidInterval(String),
+
+    #[error(
+        "Invalid {} value of '{0}'.  It must be a whole number",
+        WAVEFORM_POINTS
+    )]
+    InvalidWaveformPoints(String),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
+
+    #[error("Audio cannot be decoded: {0}")]
+    MissingElement(#[from] GstElementError),
+}
+
+impl AudioLevelsStepGenerator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl StepGenerator for AudioLevelsStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let interval = match definition.parameters.get(INTERVAL) {
+            Some(Some(value)) => match value.trim().parse::<f64>() {
+                Ok(seconds) if seconds.is_finite() && seconds > 0.0 => {
+                    Duration::from_secs_f64(seconds)
+                }
+
+                _ => return Err(Box::new(StepStartupError::InvalidInterval(value.clone()))),
+            },
+
+            _ => Duration::from_millis(100),
+        };
+
+        let waveform_points = match definition.parameters.get(WAVEFORM_POINTS) {
+            Some(Some(value)) => match value.trim().parse::<usize>() {
+                Ok(points) => points,
+                Err(_) => {
+                    return Err(Box::new(StepStartupError::InvalidWaveformPoints(
+                        value.clone(),
+                    )))
+                }
+            },
+
+            _ => 0,
+        };
+
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
+        if let Err(error) = ensure_elements_available(decoder::REQUIRED_ELEMENTS) {
+            return Err(Box::new(StepStartupError::MissingElement(error)));
+        }
+
+        let step = AudioLevelsStep {
+            interval,
+            waveform_points,
+            streams: HashMap::new(),
+            next_decoder_id: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl AudioLevelsStep {
+    fn handle_media(
+        &mut self,
+        media: &MediaNotification,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                self.streams.insert(
+                    media.stream_id.clone(),
+                    MeasuredStream {
+                        stream_name: stream_name.clone(),
+                        decoder: None,
+                        decoder_id: 0,
+                        latest_report: None,
+                    },
+                );
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.streams.remove(&media.stream_id);
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Audio,
+                payload_type,
+                timestamp,
+                data,
+                is_required_for_decoding,
+                ..
+            } => {
+                let stream = match self.streams.get_mut(&media.stream_id) {
+                    Some(stream) => stream,
+                    None => return,
+                };
+
+                if *is_required_for_decoding {
+                    // Each sequence header may describe a different format, so a new decoder is
+                    // needed to decode it
+                    self.next_decoder_id += 1;
+                    stream.decoder_id = self.next_decoder_id;
+                    stream.decoder = start_decoder(
+                        &media.stream_id,
+                        stream.decoder_id,
+                        self.interval,
+                        self.waveform_points,
+                        futures_channel,
+                    );
+                }
+
+                if let Some(decoder) = &stream.decoder {
+                    let result = decoder.push_data(
+                        payload_type.clone(),
+                        data.clone(),
+                        *timestamp,
+                        *is_required_for_decoding,
+                    );
+
+                    if let Err(error) = result {
+                        warn!(
+                            stream_id = %media.stream_id.0,
+                            "Failed to push audio into the level decoder, no longer measuring \
+                            the stream's levels: {:?}", error
+                        );
+
+                        stream.decoder = None;
+                    }
+                }
+            }
+
+            MediaNotificationContent::MediaPayload { .. } => (),
+            MediaNotificationContent::Metadata { .. } => (),
+        }
+    }
+
+    fn handle_report(
+        &mut self,
+        stream_id: StreamId,
+        decoder_id: u64,
+        report: LevelReport,
+        outputs: &mut StepOutputs,
+    ) {
+        let stream = match self.streams.get_mut(&stream_id) {
+            Some(stream) if stream.decoder_id == decoder_id => stream,
+            _ => return, // report from a decoder that's been replaced or a stream that's gone
+        };
+
+        let mut data = HashMap::new();
+        data.insert(
+            AUDIO_LEVELS_PTS_METADATA_KEY.to_string(),
+            report.pts.as_millis().to_string(),
+        );
+
+        data.insert(
+            AUDIO_RMS_METADATA_KEY.to_string(),
+            format_level(report.rms_db),
+        );
+
+        data.insert(
+            AUDIO_PEAK_METADATA_KEY.to_string(),
+            format_level(report.peak_db),
+        );
+
+        if !report.waveform.is_empty() {
+            let waveform = report
+                .waveform
+                .iter()
+                .map(|point| format!("{:.3}", point))
+                .collect::<Vec<_>>();
+
+            data.insert(AUDIO_WAVEFORM_METADATA_KEY.to_string(), waveform.join(","));
+        }
+
+        stream.latest_report = Some(report);
+        outputs.media.push(MediaNotification {
+            stream_id,
+            content: MediaNotificationContent::Metadata { data },
+            annotations: Default::default(),
+        });
+    }
+}
+
+impl WorkflowStep for AudioLevelsStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            match notification.downcast::<FutureResult>() {
+                Ok(result) => match *result {
+                    FutureResult::DecoderStopped => (),
+                    FutureResult::LevelsMeasured {
+                        stream_id,
+                        decoder_id,
+                        report,
+                    } => self.handle_report(stream_id, decoder_id, report, outputs),
+                },
+
+                Err(_) => {
+                    error!("Received future result that could not be casted to the internal future result type");
+                }
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            self.handle_media(&media, &futures_channel);
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut levels = self
+            .streams
+            .values()
+            .filter_map(|stream| {
+                stream.latest_report.as_ref().map(|report| {
+                    format!(
+                        "{}: rms {}dB peak {}dB",
+                        stream.stream_name,
+                        format_level(report.rms_db),
+                        format_level(report.peak_db)
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+
+        levels.sort();
+
+        let mut details = HashMap::new();
+        details.insert(LEVELS_DETAIL.to_string(), levels.join(", "));
+        details.insert(
+            INTERVAL_DETAIL.to_string(),
+            format!("{}ms", self.interval.as_millis()),
+        );
+
+        details
+    }
+
+    fn get_active_pipeline_count(&self) -> usize {
+        self.streams
+            .values()
+            .filter(|stream| stream.decoder.is_some())
+            .count()
+    }
+}
+
+fn format_level(level_db: f64) -> String {
+    if level_db.is_finite() {
+        format!("{:.1}", level_db)
+    } else {
+        "-inf".to_string()
+    }
+}
+
+fn start_decoder(
+    stream_id: &StreamId,
+    decoder_id: u64,
+    interval: Duration,
+    waveform_points: usize,
+    futures_channel: &WorkflowStepFuturesChannel,
+) -> Option<LevelDecoder> {
+    let (sender, receiver) = unbounded_channel();
+    let decoder = match LevelDecoder::new(interval, waveform_points, sender) {
+        Ok(decoder) => decoder,
+        Err(error) => {
+            error!(
+                stream_id = %stream_id.0,
+                "Failed to create audio level decoder: {:?}", error
+            );
+
+            return None;
+        }
+    };
+
+    let stream_id = stream_id.clone();
+    futures_channel.send_on_generic_unbounded_recv(
+        receiver,
+        move |report| FutureResult::LevelsMeasured {
+            stream_id: stream_id.clone(),
+            decoder_id,
+            report,
+        },
+        || FutureResult::DecoderStopped,
+    );
+
+    Some(decoder)
+}
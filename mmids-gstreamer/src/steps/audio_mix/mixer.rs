@@ -0,0 +1,391 @@
+//! Gstreamer pipeline that decodes two audio sources, resamples them to a common format, applies
+//! a gain to each, and mixes them together into a single audio track encoded by an encoder from
+//! the encoder factory.
+
+use crate::encoders::EncoderFactory;
+use crate::steps::filter_encode::{EncoderPipeline, EncoderSettings};
+use crate::steps::qc_monitor::detector::peak_level_db;
+use crate::utils::{create_gst_element, set_gst_buffer, set_source_audio_sequence_header};
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use gstreamer::prelude::*;
+use gstreamer::{
+    Caps, Element, Format, PadProbeData, PadProbeReturn, PadProbeType, Pipeline, State,
+};
+use gstreamer_app::AppSrc;
+use mmids_core::workflows::MediaNotificationContent;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::error;
+
+/// Gstreamer elements the mixing pipeline is built from
+pub const REQUIRED_ELEMENTS: &[&str] = &[
+    "appsrc",
+    "queue",
+    "decodebin",
+    "audioconvert",
+    "audioresample",
+    "capsfilter",
+    "volume",
+    "audiomixer",
+    "appsink",
+];
+
+/// The audio sources being mixed together
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MixSource {
+    Main,
+    Commentary,
+}
+
+/// How the sources are mixed and the format of the mixed audio
+#[derive(Clone, Copy, Debug)]
+pub struct MixSettings {
+    pub sample_rate: u32,
+    pub main_gain: f64,
+    pub commentary_gain: f64,
+}
+
+/// The peak level of a source's audio after its gain was applied
+pub struct SourceLevel {
+    pub source: MixSource,
+    pub peak_level_db: f64,
+}
+
+/// Mixes audio pushed into it for each source, sending the encoded mix out through the media
+/// sender. The encoder sends its sequence header before the first mixed frame.
+///
+/// Sources are mixed based on their timestamps, so both sources must be pushed on the same
+/// timeline. The mix only advances while both sources have audio pushed into them.
+pub struct AudioMixer {
+    pipeline: Pipeline,
+    main_source: AppSrc,
+    commentary_source: AppSrc,
+    _encoder: EncoderPipeline,
+}
+
+impl AudioMixer {
+    pub fn new(
+        settings: MixSettings,
+        encoder_settings: &EncoderSettings,
+        encoder_factory: &EncoderFactory,
+        media_sender: UnboundedSender<MediaNotificationContent>,
+        level_sender: UnboundedSender<SourceLevel>,
+    ) -> Result<AudioMixer> {
+        let pipeline = Pipeline::new(None);
+        let mixer = create_gst_element("audiomixer")?;
+
+        pipeline
+            .add(&mixer)
+            .with_context(|| "Failed to add audio mixer's elements to pipeline")?;
+
+        // Without this the mix starts at a timestamp of zero, and would be filled with silence
+        // up until the timestamp of the first source audio
+        mixer.set_property_from_str("start-time-selection", "first");
+
+        let main_source = add_source_branch(
+            &pipeline,
+            &mixer,
+            MixSource::Main,
+            settings.main_gain,
+            settings.sample_rate,
+            level_sender.clone(),
+        )?;
+
+        let commentary_source = add_source_branch(
+            &pipeline,
+            &mixer,
+            MixSource::Commentary,
+            settings.commentary_gain,
+            settings.sample_rate,
+            level_sender,
+        )?;
+
+        let encoder = EncoderPipeline::new(
+            encoder_settings,
+            encoder_factory,
+            &pipeline,
+            &mixer,
+            media_sender,
+        )?;
+
+        pipeline
+            .set_state(State::Playing)
+            .with_context(|| "Failed to set audio mixer pipeline to playing")?;
+
+        Ok(AudioMixer {
+            pipeline,
+            main_source,
+            commentary_source,
+            _encoder: encoder,
+        })
+    }
+
+    /// Pushes an audio frame from the specified source into the mixer
+    pub fn push_data(
+        &self,
+        source: MixSource,
+        payload_type: Arc<String>,
+        data: Bytes,
+        timestamp: Duration,
+        is_sequence_header: bool,
+    ) -> Result<()> {
+        let appsrc = match source {
+            MixSource::Main => &self.main_source,
+            MixSource::Commentary => &self.commentary_source,
+        };
+
+        let buffer = set_gst_buffer(data, Some(timestamp), Some(timestamp))
+            .with_context(|| "Failed to set buffer")?;
+
+        if is_sequence_header {
+            set_source_audio_sequence_header(appsrc, payload_type, buffer)
+                .with_context(|| "Failed to set sequence header for audio mixer")?;
+        } else {
+            appsrc
+                .push_buffer(buffer)
+                .with_context(|| "Failed to push the buffer into the audio mixer")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for AudioMixer {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(State::Null);
+    }
+}
+
+/// Adds `appsrc -> queue -> decodebin -> audioconvert -> audioresample -> capsfilter -> volume`
+/// to the pipeline for a source, with the volume element feeding into the mixer.
+fn add_source_branch(
+    pipeline: &Pipeline,
+    mixer: &Element,
+    source: MixSource,
+    gain: f64,
+    sample_rate: u32,
+    level_sender: UnboundedSender<SourceLevel>,
+) -> Result<AppSrc> {
+    let appsrc = create_gst_element("appsrc")?;
+    let queue = create_gst_element("queue")?;
+    let decoder = create_gst_element("decodebin")?;
+    let convert = create_gst_element("audioconvert")?;
+    let resample = create_gst_element("audioresample")?;
+    let capsfilter = create_gst_element("capsfilter")?;
+    let volume = create_gst_element("volume")?;
+
+    pipeline
+        .add_many(&[
+            &appsrc,
+            &queue,
+            &decoder,
+            &convert,
+            &resample,
+            &capsfilter,
+            &volume,
+        ])
+        .with_context(|| format!("Failed to add {:?} source's elements to pipeline", source))?;
+
+    Element::link_many(&[&appsrc, &queue, &decoder])
+        .with_context(|| "Failed to link appsrc -> queue -> decoder")?;
+
+    Element::link_many(&[&convert, &resample, &capsfilter, &volume, mixer])
+        .with_context(|| "Failed to link convert to mixer")?;
+
+    // decodebin's audio pad is added dynamically
+    let link_destination = convert;
+    decoder.connect_pad_added(move |src, src_pad| {
+        if src
+            .link_pads(Some(&src_pad.name()), &link_destination, Some("sink"))
+            .is_err()
+        {
+            error!(
+                src_caps = ?src_pad.caps(),
+                "Failed to link `decodebin`'s {} pad to audioconvert element",
+                src_pad.name()
+            );
+        }
+    });
+
+    // Both sources are converted to the same format, so the mixer never has to negotiate
+    // between different sample rates or channel layouts
+    let caps = Caps::builder("audio/x-raw")
+        .field("format", "S16LE")
+        .field("layout", "interleaved")
+        .field("rate", sample_rate as i32)
+        .field("channels", 2)
+        .build();
+
+    capsfilter.set_property("caps", caps);
+    volume.set_property("volume", gain);
+
+    let volume_src = volume
+        .static_pad("src")
+        .with_context(|| "volume element has no src pad")?;
+
+    volume_src.add_probe(PadProbeType::BUFFER, move |_pad, info| {
+        if let Some(PadProbeData::Buffer(buffer)) = &info.data {
+            if let Ok(map) = buffer.map_readable() {
+                let samples = map
+                    .as_slice()
+                    .chunks_exact(2)
+                    .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+                    .collect::<Vec<_>>();
+
+                let _ = level_sender.send(SourceLevel {
+                    source,
+                    peak_level_db: peak_level_db(&samples),
+                });
+            }
+        }
+
+        PadProbeReturn::Ok
+    });
+
+    let appsrc = appsrc
+        .dynamic_cast::<AppSrc>()
+        .map_err(|_| anyhow!("source element could not be cast to 'Appsrc'"))?;
+
+    appsrc.set_format(Format::Time);
+
+    Ok(appsrc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoders::AvencAacEncoderGenerator;
+    use crate::GSTREAMER_INIT_RESULT;
+    use mmids_core::codecs::aac::AudioSpecificConfig;
+    use mmids_core::codecs::AUDIO_CODEC_AAC_RAW;
+    use mmids_core::workflows::MediaType;
+    use std::collections::HashMap;
+    use std::time::Instant;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    const SAMPLES_PER_BUFFER: usize = 1024;
+
+    fn set_raw_caps(source: &AppSrc, sample_rate: usize) {
+        // Raw audio is passed straight through `decodebin`
+        let caps = Caps::builder("audio/x-raw")
+            .field("format", "S16LE")
+            .field("layout", "interleaved")
+            .field("rate", sample_rate as i32)
+            .field("channels", 2)
+            .build();
+
+        source.set_caps(Some(&caps));
+    }
+
+    /// Creates a buffer of interleaved stereo samples that all have the same value
+    fn constant_samples(value: i16) -> Bytes {
+        let sample = value.to_le_bytes();
+        let data = sample
+            .iter()
+            .cycle()
+            .take(SAMPLES_PER_BUFFER * 2 * 2)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        Bytes::from(data)
+    }
+
+    fn push_audio(mixer: &AudioMixer, source: MixSource, sample_rate: usize, value: i16) {
+        let samples = constant_samples(value);
+        for index in 0..20 {
+            let micros = index * SAMPLES_PER_BUFFER * 1_000_000 / sample_rate;
+            mixer
+                .push_data(
+                    source,
+                    AUDIO_CODEC_AAC_RAW.clone(),
+                    samples.clone(),
+                    Duration::from_micros(micros as u64),
+                    false,
+                )
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn sources_with_different_sample_rates_mixed_into_single_track() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let mut encoder_factory = EncoderFactory::new();
+        encoder_factory
+            .register_audio_encoder("aac", Box::new(AvencAacEncoderGenerator {}))
+            .unwrap();
+
+        let encoder_settings = EncoderSettings {
+            media_type: MediaType::Audio,
+            encoder_name: "aac".to_string(),
+            parameters: HashMap::new(),
+        };
+
+        let (media_sender, mut media_receiver) = unbounded_channel();
+        let (level_sender, mut level_receiver) = unbounded_channel();
+        let settings = MixSettings {
+            sample_rate: 48000,
+            main_gain: 1.0,
+            commentary_gain: 0.5,
+        };
+
+        let mixer = AudioMixer::new(
+            settings,
+            &encoder_settings,
+            &encoder_factory,
+            media_sender,
+            level_sender,
+        )
+        .unwrap();
+        set_raw_caps(&mixer.main_source, 44100);
+        set_raw_caps(&mixer.commentary_source, 48000);
+
+        push_audio(&mixer, MixSource::Main, 44100, 8000);
+        push_audio(&mixer, MixSource::Commentary, 48000, 8000);
+
+        let _ = mixer.main_source.end_of_stream();
+        let _ = mixer.commentary_source.end_of_stream();
+
+        let mut sequence_header = None;
+        let mut frame_count = 0;
+        let mut main_level = None;
+        let mut commentary_level = None;
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline && (sequence_header.is_none() || frame_count < 5) {
+            while let Ok(level) = level_receiver.try_recv() {
+                match level.source {
+                    MixSource::Main => main_level = Some(level.peak_level_db),
+                    MixSource::Commentary => commentary_level = Some(level.peak_level_db),
+                }
+            }
+
+            match media_receiver.try_recv() {
+                Ok(MediaNotificationContent::MediaPayload {
+                    data,
+                    is_required_for_decoding: true,
+                    ..
+                }) => sequence_header = Some(data),
+
+                Ok(_) => frame_count += 1,
+                Err(_) => std::thread::sleep(Duration::from_millis(10)),
+            }
+        }
+
+        let sequence_header = sequence_header.expect("No sequence header received");
+        let config = AudioSpecificConfig::parse(&sequence_header).unwrap();
+        assert_eq!(config.sample_rate, 48000, "Unexpected output sample rate");
+        assert!(frame_count >= 5, "Expected mixed frames to be produced");
+
+        // The commentary's gain halves its level, which is ~6dB quieter
+        let main_level = main_level.expect("No main level received");
+        let commentary_level = commentary_level.expect("No commentary level received");
+        assert!(
+            (main_level - commentary_level - 6.0).abs() < 0.5,
+            "Expected commentary to be 6dB quieter than main ({} vs {})",
+            commentary_level,
+            main_level
+        );
+    }
+}
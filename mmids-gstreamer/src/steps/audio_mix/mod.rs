@@ -0,0 +1,612 @@
+//! The audio mix workflow step mixes the audio of a commentary stream into the audio of a main
+//! stream, for overlaying commentary onto a feed. The main stream is identified by the required
+//! `main_stream` parameter and the commentary stream by the required `commentary_stream`
+//! parameter, both by stream name.
+//!
+//! Both sources' audio is decoded, resampled to the `sample_rate` parameter (defaulting to
+//! 48000Hz), scaled by the `main_gain` and `commentary_gain` parameters (defaulting to 1.0) and
+//! mixed together with gstreamer's `audiomixer` element. The mix is re-encoded and replaces the
+//! main stream's audio, while the main stream's video is passed through untouched. The mix is
+//! encoded the same way as the basic transcode step, with the `audio` parameter naming the encoder
+//! to use and `audio_` prefixed parameters being passed to it.
+//!
+//! The commentary's timestamps are shifted onto the main stream's timeline when mixing starts, so
+//! the mix keeps the main stream's timestamps and stays aligned with its video. Mixing only
+//! starts once both streams have sent audio. If the commentary disconnects or stops sending audio
+//! for more than a second, the main stream's own audio is passed through again until the
+//! commentary's audio resumes. A new sequence header is sent each time the stream switches
+//! between mixed and unmixed audio.
+//!
+//! The commentary stream's media is never passed through, while all other streams are passed
+//! through untouched. The peak level of each source (after its gain is applied) is reported
+//! through the step's state details.
+
+mod mixer;
+
+use crate::encoders::EncoderFactory;
+use crate::steps::audio_mix::mixer::{AudioMixer, MixSettings, MixSource, SourceLevel};
+use crate::steps::filter_encode::{EncoderSettings, EncoderSettingsError};
+use crate::utils::{ensure_elements_available, GstElementError};
+use crate::GSTREAMER_INIT_RESULT;
+use bytes::{Bytes, BytesMut};
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::metadata::MediaPayloadMetadataCollection;
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use mmids_core::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use mmids_core::StreamId;
+use std::collections::HashMap;
+use std::iter;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::unbounded_channel;
+use tracing::{error, info, warn};
+
+pub const MAIN_STREAM: &str = "main_stream";
+pub const COMMENTARY_STREAM: &str = "commentary_stream";
+pub const MAIN_GAIN: &str = "main_gain";
+pub const COMMENTARY_GAIN: &str = "commentary_gain";
+pub const SAMPLE_RATE: &str = "sample_rate";
+
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+/// How far behind the main stream's audio the commentary's audio can fall before the commentary
+/// is considered silent and mixing stops
+const COMMENTARY_TIMEOUT: Duration = Duration::from_secs(1);
+
+const IS_MIXING_DETAIL: &str = "is_mixing";
+const MAIN_LEVEL_DETAIL: &str = "main_level_db";
+const COMMENTARY_LEVEL_DETAIL: &str = "commentary_level_db";
+
+/// Generates new instances of the audio mix workflow step
+pub struct AudioMixStepGenerator {
+    encoder_factory: Arc<EncoderFactory>,
+}
+
+/// Audio state of one of the sources being mixed
+#[derive(Default)]
+struct SourceAudio {
+    stream_id: Option<StreamId>,
+    payload_type: Option<Arc<String>>,
+    sequence_header: Option<Bytes>,
+
+    /// Timestamp of the most recent audio frame, on the source's own timeline
+    last_timestamp: Option<Duration>,
+    peak_level_db: Option<f64>,
+}
+
+struct ActiveMix {
+    mixer: AudioMixer,
+
+    /// Identifies the mixer, so audio from mixers that have been replaced can be ignored
+    mixer_id: u64,
+
+    /// Amount added to the commentary's timestamps to shift them onto the main stream's timeline
+    commentary_offset_ms: i64,
+}
+
+struct AudioMixStep {
+    main_stream_name: Arc<String>,
+    commentary_stream_name: Arc<String>,
+    settings: MixSettings,
+    encoder_settings: EncoderSettings,
+    encoder_factory: Arc<EncoderFactory>,
+    main: SourceAudio,
+    commentary: SourceAudio,
+    mix: Option<ActiveMix>,
+    next_mixer_id: u64,
+    metadata_buffer: BytesMut,
+}
+
+enum FutureResult {
+    AudioMixed {
+        mixer_id: u64,
+        content: MediaNotificationContent,
+    },
+
+    SourceLevelMeasured {
+        mixer_id: u64,
+        level: SourceLevel,
+    },
+
+    // Mixers only stop when they are dropped, so there's nothing to do when this occurs
+    MixerStopped,
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(thiserror::Error, Debug)]
+enum StepStartupError {
+    #[error("No {0} parameter specified")]
+    NoStreamName(&'static str),
+
+    #[error(
+        "The {} and {} parameters must be different streams",
+        MAIN_STREAM,
+        COMMENTARY_STREAM
+    )]
+    SameStreams,
+
+    #[error("Invalid {0} value of '{1}'.  It must be a number zero or greater")]
+    InvalidGain(&'static str, String),
+
+    #[error("Invalid {0} value of '{1}'.  It must be a number greater than zero")]
+    InvalidNumber(&'static str, String),
+
+    #[error(transparent)]
+    InvalidEncoderSettings(#[from] EncoderSettingsError),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
+
+    #[error("Audio cannot be mixed: {0}")]
+    MissingElement(#[from] GstElementError),
+}
+
+impl AudioMixStepGenerator {
+    /// Creates the generator, with the encoder factory the mix is encoded with
+    pub fn new(encoder_factory: Arc<EncoderFactory>) -> Self {
+        AudioMixStepGenerator { encoder_factory }
+    }
+}
+
+impl StepGenerator for AudioMixStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let get_stream_name = |name: &'static str| match definition.parameters.get(name) {
+            Some(Some(value)) if !value.trim().is_empty() => Ok(Arc::new(value.trim().to_string())),
+
+            _ => Err(StepStartupError::NoStreamName(name)),
+        };
+
+        let main_stream_name = get_stream_name(MAIN_STREAM)?;
+        let commentary_stream_name = get_stream_name(COMMENTARY_STREAM)?;
+        if main_stream_name == commentary_stream_name {
+            return Err(Box::new(StepStartupError::SameStreams));
+        }
+
+        let get_gain = |name: &'static str| match definition.parameters.get(name) {
+            Some(Some(value)) => match value.trim().parse::<f64>() {
+                Ok(gain) if gain >= 0.0 => Ok(gain),
+                _ => Err(StepStartupError::InvalidGain(name, value.clone())),
+            },
+
+            _ => Ok(1.0),
+        };
+
+        let get_number = |name: &'static str| match definition.parameters.get(name) {
+            Some(Some(value)) => match value.trim().parse::<u32>() {
+                Ok(number) if number > 0 => Ok(Some(number)),
+                _ => Err(StepStartupError::InvalidNumber(name, value.clone())),
+            },
+
+            _ => Ok(None),
+        };
+
+        let settings = MixSettings {
+            sample_rate: get_number(SAMPLE_RATE)?.unwrap_or(DEFAULT_SAMPLE_RATE),
+            main_gain: get_gain(MAIN_GAIN)?,
+            commentary_gain: get_gain(COMMENTARY_GAIN)?,
+        };
+
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
+        if let Err(error) = ensure_elements_available(mixer::REQUIRED_ELEMENTS) {
+            return Err(Box::new(StepStartupError::MissingElement(error)));
+        }
+
+        let encoder_settings =
+            EncoderSettings::audio_from_definition(&definition, &self.encoder_factory)
+                .map_err(StepStartupError::from)?;
+
+        let step = AudioMixStep {
+            main_stream_name,
+            commentary_stream_name,
+            settings,
+            encoder_settings,
+            encoder_factory: self.encoder_factory.clone(),
+            main: SourceAudio::default(),
+            commentary: SourceAudio::default(),
+            mix: None,
+            next_mixer_id: 0,
+            metadata_buffer: BytesMut::new(),
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl AudioMixStep {
+    fn handle_media(
+        &mut self,
+        media: MediaNotification,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let is_main = self.main.stream_id.as_ref() == Some(&media.stream_id);
+        let is_commentary = self.commentary.stream_id.as_ref() == Some(&media.stream_id);
+
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                if *stream_name == self.main_stream_name {
+                    self.stop_mixing(outputs);
+                    self.main = SourceAudio {
+                        stream_id: Some(media.stream_id.clone()),
+                        ..Default::default()
+                    };
+
+                    outputs.media.push(media);
+                } else if *stream_name == self.commentary_stream_name {
+                    self.stop_mixing(outputs);
+                    self.commentary = SourceAudio {
+                        stream_id: Some(media.stream_id.clone()),
+                        ..Default::default()
+                    };
+                } else {
+                    outputs.media.push(media);
+                }
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                if is_main {
+                    self.mix = None;
+                    self.main = SourceAudio::default();
+                    outputs.media.push(media);
+                } else if is_commentary {
+                    info!(
+                        "Commentary stream {} disconnected",
+                        self.commentary_stream_name
+                    );
+
+                    self.stop_mixing(outputs);
+                    self.commentary = SourceAudio::default();
+                } else {
+                    outputs.media.push(media);
+                }
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Audio,
+                payload_type,
+                timestamp,
+                data,
+                is_required_for_decoding,
+                ..
+            } if is_main || is_commentary => {
+                let source = if is_main {
+                    MixSource::Main
+                } else {
+                    MixSource::Commentary
+                };
+
+                let audio = match source {
+                    MixSource::Main => &mut self.main,
+                    MixSource::Commentary => &mut self.commentary,
+                };
+
+                audio.payload_type = Some(payload_type.clone());
+                if *is_required_for_decoding {
+                    audio.sequence_header = Some(data.clone());
+
+                    // The mixer can't switch formats part way through, so the mix is restarted
+                    // with the new sequence header once the next frame comes in
+                    if source == MixSource::Main {
+                        self.mix = None;
+                        outputs.media.push(media);
+                    } else {
+                        self.stop_mixing(outputs);
+                    }
+
+                    return;
+                }
+
+                audio.last_timestamp = Some(*timestamp);
+                if self.mix.is_some() && source == MixSource::Main && self.is_commentary_stale() {
+                    warn!(
+                        "No audio received from commentary stream {} for over {:?}, passing \
+                        through the main stream's audio until it resumes",
+                        self.commentary_stream_name, COMMENTARY_TIMEOUT
+                    );
+
+                    self.stop_mixing(outputs);
+
+                    // Mixing shouldn't restart until new commentary audio arrives
+                    self.commentary.last_timestamp = None;
+                }
+
+                if self.mix.is_none() && !self.is_commentary_stale() {
+                    self.start_mixing(futures_channel);
+                }
+
+                match &self.mix {
+                    Some(mix) => {
+                        let timestamp = match source {
+                            MixSource::Main => *timestamp,
+                            MixSource::Commentary => {
+                                shift_timestamp(*timestamp, mix.commentary_offset_ms)
+                            }
+                        };
+
+                        let result = mix.mixer.push_data(
+                            source,
+                            payload_type.clone(),
+                            data.clone(),
+                            timestamp,
+                            false,
+                        );
+
+                        if let Err(error) = result {
+                            error!(
+                                "Failed to push {:?} audio into the mixer, passing through the \
+                                main stream's audio: {:?}",
+                                source, error
+                            );
+
+                            self.stop_mixing(outputs);
+                            if source == MixSource::Main {
+                                outputs.media.push(media);
+                            }
+                        }
+                    }
+
+                    None => {
+                        if source == MixSource::Main {
+                            outputs.media.push(media);
+                        }
+                    }
+                }
+            }
+
+            _ if is_commentary => (),
+            _ => outputs.media.push(media),
+        }
+    }
+
+    /// The commentary is stale when its latest audio (on the main stream's timeline) is too far
+    /// behind the main stream's latest audio, or when there's no commentary audio at all.
+    fn is_commentary_stale(&self) -> bool {
+        let (main, commentary) = match (self.main.last_timestamp, self.commentary.last_timestamp) {
+            (Some(main), Some(commentary)) => (main, commentary),
+            _ => return true,
+        };
+
+        let commentary = match &self.mix {
+            Some(mix) => shift_timestamp(commentary, mix.commentary_offset_ms),
+            None => return false, // a new mix aligns the commentary to the main stream
+        };
+
+        commentary + COMMENTARY_TIMEOUT < main
+    }
+
+    fn start_mixing(&mut self, futures_channel: &WorkflowStepFuturesChannel) {
+        let (main_header, main_payload_type, main_timestamp) = match &self.main {
+            SourceAudio {
+                sequence_header: Some(header),
+                payload_type: Some(payload_type),
+                last_timestamp: Some(timestamp),
+                ..
+            } => (header.clone(), payload_type.clone(), *timestamp),
+
+            _ => return,
+        };
+
+        let (commentary_header, commentary_payload_type, commentary_timestamp) =
+            match &self.commentary {
+                SourceAudio {
+                    sequence_header: Some(header),
+                    payload_type: Some(payload_type),
+                    last_timestamp: Some(timestamp),
+                    ..
+                } => (header.clone(), payload_type.clone(), *timestamp),
+
+                _ => return,
+            };
+
+        let (media_sender, media_receiver) = unbounded_channel();
+        let (level_sender, level_receiver) = unbounded_channel();
+        let mixer = AudioMixer::new(
+            self.settings,
+            &self.encoder_settings,
+            &self.encoder_factory,
+            media_sender,
+            level_sender,
+        );
+
+        let mixer = match mixer {
+            Ok(mixer) => mixer,
+            Err(error) => {
+                error!("Failed to create audio mixer: {:?}", error);
+                return;
+            }
+        };
+
+        let headers = [
+            (MixSource::Main, main_payload_type, main_header),
+            (
+                MixSource::Commentary,
+                commentary_payload_type,
+                commentary_header,
+            ),
+        ];
+
+        for (source, payload_type, header) in headers {
+            if let Err(error) =
+                mixer.push_data(source, payload_type, header, Duration::default(), true)
+            {
+                error!(
+                    "Failed to set {:?} sequence header on audio mixer: {:?}",
+                    source, error
+                );
+
+                return;
+            }
+        }
+
+        self.next_mixer_id += 1;
+        let mixer_id = self.next_mixer_id;
+        futures_channel.send_on_generic_unbounded_recv(
+            media_receiver,
+            move |content| FutureResult::AudioMixed { mixer_id, content },
+            || FutureResult::MixerStopped,
+        );
+
+        futures_channel.send_on_generic_unbounded_recv(
+            level_receiver,
+            move |level| FutureResult::SourceLevelMeasured { mixer_id, level },
+            || FutureResult::MixerStopped,
+        );
+
+        info!(
+            "Mixing commentary stream {} into main stream {}",
+            self.commentary_stream_name, self.main_stream_name
+        );
+
+        self.mix = Some(ActiveMix {
+            mixer,
+            mixer_id,
+            commentary_offset_ms: main_timestamp.as_millis() as i64
+                - commentary_timestamp.as_millis() as i64,
+        });
+    }
+
+    /// Stops any active mix, and switches the main stream back to its own audio
+    fn stop_mixing(&mut self, outputs: &mut StepOutputs) {
+        if self.mix.take().is_none() {
+            return;
+        }
+
+        if let (Some(stream_id), Some(payload_type), Some(header)) = (
+            &self.main.stream_id,
+            &self.main.payload_type,
+            &self.main.sequence_header,
+        ) {
+            outputs.media.push(MediaNotification {
+                stream_id: stream_id.clone(),
+                content: MediaNotificationContent::MediaPayload {
+                    media_type: MediaType::Audio,
+                    payload_type: payload_type.clone(),
+                    timestamp: Duration::default(),
+                    metadata: MediaPayloadMetadataCollection::new(
+                        iter::empty(),
+                        &mut self.metadata_buffer,
+                    ),
+                    data: header.clone(),
+                    is_required_for_decoding: true,
+                },
+                annotations: Default::default(),
+            });
+        }
+    }
+
+    fn handle_mixed_audio(
+        &mut self,
+        mixer_id: u64,
+        content: MediaNotificationContent,
+        outputs: &mut StepOutputs,
+    ) {
+        if !self.is_current_mixer(mixer_id) {
+            return; // audio from a mixer that's been replaced
+        }
+
+        if let Some(stream_id) = &self.main.stream_id {
+            outputs.media.push(MediaNotification {
+                stream_id: stream_id.clone(),
+                content,
+                annotations: Default::default(),
+            });
+        }
+    }
+
+    fn handle_source_level(&mut self, mixer_id: u64, level: SourceLevel) {
+        if !self.is_current_mixer(mixer_id) {
+            return;
+        }
+
+        match level.source {
+            MixSource::Main => self.main.peak_level_db = Some(level.peak_level_db),
+            MixSource::Commentary => self.commentary.peak_level_db = Some(level.peak_level_db),
+        }
+    }
+
+    fn is_current_mixer(&self, mixer_id: u64) -> bool {
+        matches!(&self.mix, Some(mix) if mix.mixer_id == mixer_id)
+    }
+}
+
+impl WorkflowStep for AudioMixStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            match notification.downcast::<FutureResult>() {
+                Ok(result) => match *result {
+                    FutureResult::MixerStopped => (),
+                    FutureResult::AudioMixed { mixer_id, content } => {
+                        self.handle_mixed_audio(mixer_id, content, outputs)
+                    }
+
+                    FutureResult::SourceLevelMeasured { mixer_id, level } => {
+                        self.handle_source_level(mixer_id, level)
+                    }
+                },
+
+                Err(_) => {
+                    error!("Received future result that could not be casted to the internal future result type");
+                }
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            self.handle_media(media, outputs, &futures_channel);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let format_level = |level: Option<f64>| match level {
+            Some(level) => format!("{:.1}", level),
+            None => "".to_string(),
+        };
+
+        let mut details = HashMap::new();
+        details.insert(IS_MIXING_DETAIL.to_string(), self.mix.is_some().to_string());
+        details.insert(
+            MAIN_LEVEL_DETAIL.to_string(),
+            format_level(self.main.peak_level_db),
+        );
+
+        details.insert(
+            COMMENTARY_LEVEL_DETAIL.to_string(),
+            format_level(self.commentary.peak_level_db),
+        );
+
+        details
+    }
+
+    fn get_active_pipeline_count(&self) -> usize {
+        if self.mix.is_some() {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+fn shift_timestamp(timestamp: Duration, offset_ms: i64) -> Duration {
+    let shifted = timestamp.as_millis() as i64 + offset_ms;
+    Duration::from_millis(shifted.max(0) as u64)
+}
@@ -0,0 +1,195 @@
+//! The audio resample workflow step converts the audio of every stream flowing through it to a
+//! configured sample rate, for targets that require a specific one (e.g. 48kHz). The audio is
+//! decoded, resampled with gstreamer's `audioresample` element and re-encoded, while video is
+//! passed through untouched.
+//!
+//! The `sample_rate` parameter (in Hz) is required. The optional `channels` parameter converts
+//! the audio to the specified number of channels, otherwise each stream keeps its own channel
+//! layout. The resampled audio is encoded the same way as the basic transcode step, with the
+//! `audio` parameter naming the encoder to use and `audio_` prefixed parameters being passed to
+//! it.
+//!
+//! Each stream's source sample rate and the output sample rate are reported through the step's
+//! state details.
+
+mod resampler;
+
+use crate::encoders::EncoderFactory;
+use crate::steps::audio_resample::resampler::OutputFormat;
+use crate::steps::filter_encode::{
+    aac_sequence_header_config, ensure_filter_elements_available, per_stream_detail,
+    EncoderSettings, EncoderSettingsError, FilterEncodeStep, MediaFilter, StreamSummary,
+};
+use crate::utils::GstElementError;
+use crate::GSTREAMER_INIT_RESULT;
+use anyhow::Result;
+use gstreamer::Element;
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{StepCreationResult, StepStatus};
+use mmids_core::workflows::{MediaNotificationContent, MediaType};
+use mmids_core::StreamId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::warn;
+
+pub const SAMPLE_RATE: &str = "sample_rate";
+pub const CHANNELS: &str = "channels";
+
+const INPUT_SAMPLE_RATES_DETAIL: &str = "input_sample_rates";
+const OUTPUT_SAMPLE_RATE_DETAIL: &str = "output_sample_rate";
+
+/// Generates new instances of the audio resample workflow step
+pub struct AudioResampleStepGenerator {
+    encoder_factory: Arc<EncoderFactory>,
+}
+
+/// Resamples each stream's audio, while tracking each stream's source sample rate
+struct ResampleFilter {
+    format: OutputFormat,
+}
+
+struct ResampledStream {
+    input_sample_rate: Option<u32>,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum StepStartupError {
+    #[error("No {} parameter specified", SAMPLE_RATE)]
+    NoSampleRate,
+
+    #[error("Invalid {0} value of '{1}'.  It must be a number greater than zero")]
+    InvalidNumber(&'static str, String),
+
+    #[error(transparent)]
+    InvalidEncoderSettings(#[from] EncoderSettingsError),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
+
+    #[error("Audio cannot be resampled: {0}")]
+    MissingElement(#[from] GstElementError),
+}
+
+impl AudioResampleStepGenerator {
+    /// Creates the generator, with the encoder factory resampled audio is encoded with
+    pub fn new(encoder_factory: Arc<EncoderFactory>) -> Self {
+        AudioResampleStepGenerator { encoder_factory }
+    }
+}
+
+impl StepGenerator for AudioResampleStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
+        ensure_filter_elements_available(resampler::REQUIRED_ELEMENTS)
+            .map_err(StepStartupError::from)?;
+
+        let get_number = |name: &'static str| match definition.parameters.get(name) {
+            Some(Some(value)) => match value.trim().parse::<u32>() {
+                Ok(number) if number > 0 => Ok(Some(number)),
+                _ => Err(StepStartupError::InvalidNumber(name, value.clone())),
+            },
+
+            _ => Ok(None),
+        };
+
+        let sample_rate = match get_number(SAMPLE_RATE)? {
+            Some(sample_rate) => sample_rate,
+            None => return Err(Box::new(StepStartupError::NoSampleRate)),
+        };
+
+        let channels = get_number(CHANNELS)?;
+        let settings = EncoderSettings::audio_from_definition(&definition, &self.encoder_factory)
+            .map_err(StepStartupError::from)?;
+
+        let filter = ResampleFilter {
+            format: OutputFormat {
+                sample_rate,
+                channels,
+            },
+        };
+
+        let step = FilterEncodeStep::new(filter, settings, self.encoder_factory.clone(), None);
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl MediaFilter for ResampleFilter {
+    type StreamState = ResampledStream;
+    type Event = ();
+
+    const MEDIA_TYPE: MediaType = MediaType::Audio;
+    const NAME: &'static str = "audio resampler";
+
+    fn new_stream(&self, _stream_name: &Arc<String>) -> Self::StreamState {
+        ResampledStream {
+            input_sample_rate: None,
+        }
+    }
+
+    fn create_elements(
+        &self,
+        _stream: &Self::StreamState,
+        _events: &UnboundedSender<Self::Event>,
+    ) -> Result<Vec<Element>> {
+        resampler::create_elements(self.format)
+    }
+
+    fn media_received(
+        &mut self,
+        _stream_id: &StreamId,
+        stream: &mut Self::StreamState,
+        content: &MediaNotificationContent,
+    ) {
+        if let Some(config) = aac_sequence_header_config(content) {
+            stream.input_sample_rate = Some(config.sample_rate);
+        }
+    }
+
+    fn encoded_media_received(
+        &mut self,
+        stream_id: &StreamId,
+        _stream: &mut Self::StreamState,
+        content: &MediaNotificationContent,
+    ) {
+        match aac_sequence_header_config(content) {
+            Some(config) if config.sample_rate != self.format.sample_rate => warn!(
+                stream_id = %stream_id.0,
+                "Resampled audio has a sample rate of {}Hz instead of {}Hz",
+                config.sample_rate, self.format.sample_rate
+            ),
+
+            _ => (),
+        }
+    }
+
+    fn add_state_details(
+        &self,
+        streams: &[StreamSummary<'_, Self::StreamState>],
+        details: &mut HashMap<String, String>,
+    ) {
+        details.insert(
+            INPUT_SAMPLE_RATES_DETAIL.to_string(),
+            per_stream_detail(streams, |stream| {
+                stream.input_sample_rate.map(|rate| rate.to_string())
+            }),
+        );
+
+        details.insert(
+            OUTPUT_SAMPLE_RATE_DETAIL.to_string(),
+            self.format.sample_rate.to_string(),
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Gstreamer elements that convert decoded audio to a specific sample rate and channel count.
+
+use crate::utils::create_gst_element;
+use anyhow::Result;
+use gstreamer::prelude::*;
+use gstreamer::{Caps, Element};
+
+/// Gstreamer elements resampling is built from
+pub const REQUIRED_ELEMENTS: &[&str] = &["audioconvert", "audioresample", "capsfilter"];
+
+/// The format audio is converted to
+#[derive(Clone, Copy, Debug)]
+pub struct OutputFormat {
+    pub sample_rate: u32,
+
+    /// The number of channels to convert to. When not specified the channel layout of the source
+    /// is kept.
+    pub channels: Option<u32>,
+}
+
+/// Creates the elements that convert audio to the specified format
+pub fn create_elements(format: OutputFormat) -> Result<Vec<Element>> {
+    let convert = create_gst_element("audioconvert")?;
+    let resample = create_gst_element("audioresample")?;
+    let capsfilter = create_gst_element("capsfilter")?;
+
+    let mut caps = Caps::builder("audio/x-raw").field("rate", format.sample_rate as i32);
+    if let Some(channels) = format.channels {
+        caps = caps.field("channels", channels as i32);
+    }
+
+    capsfilter.set_property("caps", caps.build());
+
+    Ok(vec![convert, resample, capsfilter])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::steps::filter_encode::test_utils::run_through_elements;
+    use crate::utils::set_gst_buffer;
+    use crate::GSTREAMER_INIT_RESULT;
+    use bytes::Bytes;
+    use std::time::Duration;
+
+    const SOURCE_SAMPLE_RATE: usize = 44100;
+    const SAMPLES_PER_BUFFER: usize = 1024;
+
+    #[test]
+    fn audio_resampled_from_44_1khz_to_48khz() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let caps = Caps::builder("audio/x-raw")
+            .field("format", "S16LE")
+            .field("layout", "interleaved")
+            .field("rate", SOURCE_SAMPLE_RATE as i32)
+            .field("channels", 1)
+            .build();
+
+        let samples = Bytes::from(vec![0_u8; SAMPLES_PER_BUFFER * 2]);
+        let buffers = (0..20)
+            .map(|index| {
+                let micros = index * SAMPLES_PER_BUFFER * 1_000_000 / SOURCE_SAMPLE_RATE;
+                let time = Duration::from_micros(micros as u64);
+                set_gst_buffer(samples.clone(), Some(time), Some(time)).unwrap()
+            })
+            .collect();
+
+        let format = OutputFormat {
+            sample_rate: 48000,
+            channels: Some(2),
+        };
+
+        let elements = create_elements(format).unwrap();
+        let output = run_through_elements(&elements, &caps, buffers);
+        let caps = output
+            .first()
+            .expect("No resampled audio received")
+            .caps()
+            .expect("Resampled audio had no caps")
+            .to_owned();
+
+        let structure = caps.structure(0).unwrap();
+        assert_eq!(
+            structure.get::<i32>("rate").unwrap(),
+            48000,
+            "Unexpected output sample rate"
+        );
+
+        assert_eq!(
+            structure.get::<i32>("channels").unwrap(),
+            2,
+            "Unexpected channels"
+        );
+    }
+}
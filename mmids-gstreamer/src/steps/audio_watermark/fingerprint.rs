@@ -0,0 +1,261 @@
+//! Embeds and detects a watermark ID carried by quiet high frequency tones. The ID is sent as a
+//! repeating frame of bits, with each bit being a short burst of one of two tones (frequency shift
+//! keying). A frame is an 8 bit preamble, the 32 bit ID, and an 8 bit CRC of the ID.
+//!
+//! The tones sit near the top of the audible band, at a fixed fraction of the sample rate, and
+//! fade in and out at each bit so no clicks are introduced. At low strengths they are inaudible to
+//! most listeners under program audio.
+
+use std::f64::consts::PI;
+
+/// How long each bit of the watermark lasts
+const SYMBOL_MILLIS: u64 = 40;
+
+/// Fractions of the sample rate the tones for `0` and `1` bits are at. At 48kHz these are 15.36kHz
+/// and 16.32kHz, which are low enough to survive AAC encoding at typical bitrates.
+const ZERO_TONE_RATIO: f64 = 0.32;
+const ONE_TONE_RATIO: f64 = 0.34;
+
+const PREAMBLE: [bool; 8] = [true, false, true, true, false, false, true, false];
+const ID_BITS: usize = 32;
+const CRC_BITS: usize = 8;
+const FRAME_BITS: usize = PREAMBLE.len() + ID_BITS + CRC_BITS;
+
+/// How many alignments within a symbol the detector tries
+const ALIGNMENT_STEPS: usize = 16;
+
+/// Adds the watermark to audio
+pub struct WatermarkEmbedder {
+    bits: Vec<bool>,
+    amplitude: f64,
+    sample_rate: u32,
+    symbol_length: u64,
+    position: u64,
+}
+
+impl WatermarkEmbedder {
+    /// Creates an embedder for audio with the specified sample rate. The strength is the peak
+    /// amplitude of the tones relative to full scale.
+    pub fn new(id: u32, strength: f32, sample_rate: u32) -> Self {
+        WatermarkEmbedder {
+            bits: frame_bits(id),
+            amplitude: strength as f64,
+            sample_rate,
+            symbol_length: symbol_length(sample_rate) as u64,
+            position: 0,
+        }
+    }
+
+    /// Adds the watermark to interleaved samples, with the same tone added to every channel
+    pub fn embed(&mut self, samples: &mut [f32], channels: usize) {
+        for frame in samples.chunks_mut(channels.max(1)) {
+            let value = self.next_value() as f32;
+            for sample in frame {
+                *sample = (*sample + value).clamp(-1.0, 1.0);
+            }
+        }
+    }
+
+    fn next_value(&mut self) -> f64 {
+        let symbol = self.position / self.symbol_length;
+        let offset = self.position % self.symbol_length;
+        let bit = self.bits[(symbol % self.bits.len() as u64) as usize];
+
+        let time = self.position as f64 / self.sample_rate as f64;
+        let frequency = tone_frequency(bit, self.sample_rate);
+        let envelope = envelope(offset, self.symbol_length);
+
+        self.position += 1;
+        self.amplitude * envelope * (2.0 * PI * frequency * time).sin()
+    }
+}
+
+/// Looks for a watermark in mono audio, returning the first ID that's found. At least two frames
+/// worth of audio (around 4 seconds) are needed to guarantee a complete frame is present.
+pub fn detect_watermark(samples: &[f32], sample_rate: u32) -> Option<u32> {
+    let symbol_length = symbol_length(sample_rate);
+    let step = (symbol_length / ALIGNMENT_STEPS).max(1);
+    let zero_frequency = tone_frequency(false, sample_rate);
+    let one_frequency = tone_frequency(true, sample_rate);
+
+    // Only the middle of each symbol is measured, so misalignment and fades have less effect
+    let margin = symbol_length / 4;
+
+    for alignment in (0..symbol_length).step_by(step) {
+        let bits = samples
+            .get(alignment..)
+            .unwrap_or_default()
+            .chunks_exact(symbol_length)
+            .map(|symbol| {
+                let middle = &symbol[margin..symbol_length - margin];
+                let zero = tone_power(middle, zero_frequency, sample_rate);
+                let one = tone_power(middle, one_frequency, sample_rate);
+                one > zero
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(id) = bits.windows(FRAME_BITS).find_map(parse_frame) {
+            return Some(id);
+        }
+    }
+
+    None
+}
+
+fn symbol_length(sample_rate: u32) -> usize {
+    (sample_rate as u64 * SYMBOL_MILLIS / 1000).max(1) as usize
+}
+
+fn tone_frequency(bit: bool, sample_rate: u32) -> f64 {
+    let ratio = if bit { ONE_TONE_RATIO } else { ZERO_TONE_RATIO };
+    sample_rate as f64 * ratio
+}
+
+/// Raised cosine fade over the first and last eighth of each symbol
+fn envelope(offset: u64, symbol_length: u64) -> f64 {
+    let ramp = (symbol_length / 8).max(1);
+    let distance = offset.min(symbol_length - 1 - offset);
+    if distance >= ramp {
+        return 1.0;
+    }
+
+    0.5 - 0.5 * (PI * distance as f64 / ramp as f64).cos()
+}
+
+fn frame_bits(id: u32) -> Vec<bool> {
+    let id_bytes = id.to_be_bytes();
+    let mut bits = PREAMBLE.to_vec();
+    bits.extend((0..ID_BITS).rev().map(|bit| (id >> bit) & 1 == 1));
+    bits.extend(
+        (0..CRC_BITS)
+            .rev()
+            .map(|bit| (crc8(&id_bytes) >> bit) & 1 == 1),
+    );
+
+    bits
+}
+
+fn parse_frame(bits: &[bool]) -> Option<u32> {
+    if bits[..PREAMBLE.len()] != PREAMBLE {
+        return None;
+    }
+
+    let to_number = |bits: &[bool]| {
+        bits.iter()
+            .fold(0_u32, |value, bit| (value << 1) | *bit as u32)
+    };
+
+    let id_start = PREAMBLE.len();
+    let id = to_number(&bits[id_start..id_start + ID_BITS]);
+    let crc = to_number(&bits[id_start + ID_BITS..]) as u8;
+
+    (crc == crc8(&id.to_be_bytes())).then_some(id)
+}
+
+/// CRC-8 with the 0x07 polynomial
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0_u8, |mut crc, byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+
+        crc
+    })
+}
+
+/// Measures the power of a single frequency within the samples (Goertzel algorithm)
+fn tone_power(samples: &[f32], frequency: f64, sample_rate: u32) -> f64 {
+    let coefficient = 2.0 * (2.0 * PI * frequency / sample_rate as f64).cos();
+    let (previous, before_previous) = samples.iter().fold((0.0, 0.0), |(s1, s2), sample| {
+        (*sample as f64 + coefficient * s1 - s2, s1)
+    });
+
+    previous * previous + before_previous * before_previous
+        - coefficient * previous * before_previous
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// Program audio of a 440Hz tone mixed with pseudo-random noise
+    fn program_audio(seconds: usize, channels: usize) -> Vec<f32> {
+        let mut seed = 12345_u32;
+        (0..SAMPLE_RATE as usize * seconds)
+            .flat_map(|index| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let noise = (seed >> 16) as f32 / 65536.0 - 0.5;
+                let time = index as f64 / SAMPLE_RATE as f64;
+                let tone = (2.0 * PI * 440.0 * time).sin() as f32 * 0.5;
+
+                vec![tone + noise * 0.04; channels]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn embedded_id_detected() {
+        let mut samples = program_audio(5, 2);
+        let mut embedder = WatermarkEmbedder::new(0xC0FFEE, 0.01, SAMPLE_RATE);
+        embedder.embed(&mut samples, 2);
+
+        // Detection starts partway into the audio, as a listener joining late would
+        let left = samples
+            .chunks(2)
+            .skip(12345)
+            .map(|frame| frame[0])
+            .collect::<Vec<_>>();
+
+        assert_eq!(detect_watermark(&left, SAMPLE_RATE), Some(0xC0FFEE));
+    }
+
+    #[test]
+    fn embedding_split_across_buffers_matches_single_buffer() {
+        let mut single = program_audio(1, 1);
+        let mut split = single.clone();
+
+        WatermarkEmbedder::new(7, 0.01, SAMPLE_RATE).embed(&mut single, 1);
+
+        let mut embedder = WatermarkEmbedder::new(7, 0.01, SAMPLE_RATE);
+        for chunk in split.chunks_mut(1024) {
+            embedder.embed(chunk, 1);
+        }
+
+        assert_eq!(single, split, "Expected the same watermarked audio");
+    }
+
+    #[test]
+    fn no_id_detected_in_unwatermarked_audio() {
+        let samples = program_audio(5, 1);
+
+        assert_eq!(detect_watermark(&samples, SAMPLE_RATE), None);
+    }
+
+    #[test]
+    fn too_little_audio_returns_no_id() {
+        let mut samples = program_audio(1, 1);
+        WatermarkEmbedder::new(42, 0.01, SAMPLE_RATE).embed(&mut samples, 1);
+
+        assert_eq!(detect_watermark(&samples, SAMPLE_RATE), None);
+    }
+
+    #[test]
+    fn watermark_is_quiet() {
+        let mut silence = vec![0.0; SAMPLE_RATE as usize];
+        WatermarkEmbedder::new(42, 0.01, SAMPLE_RATE).embed(&mut silence, 1);
+
+        let peak = silence.iter().fold(0.0_f32, |peak, x| peak.max(x.abs()));
+        assert!(
+            peak <= 0.01,
+            "Peak of {} was louder than the strength",
+            peak
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! The audio watermark workflow step embeds an inaudible ID into the audio of every stream flowing
+//! through it, so recordings or restreams of the audio can later be traced back to where they came
+//! from. The audio is decoded, has the watermark mixed into it, and is re-encoded, while video is
+//! passed through untouched.
+//!
+//! The `watermark_id` parameter is required and is the 32 bit ID to embed, either as a decimal
+//! number or as hex prefixed with `0x`. The optional `strength` parameter is the peak amplitude of
+//! the watermark relative to full scale, and must be greater than zero and no more than 1. It
+//! defaults to 0.01, which is inaudible under typical program audio while still surviving AAC
+//! encoding. The watermarked audio is encoded the same way as the basic transcode step, with the
+//! `audio` parameter naming the encoder to use and `audio_` prefixed parameters being passed to
+//! it.
+//!
+//! The watermark repeats roughly every 2 seconds, and can be read back out of decoded audio with
+//! [`fingerprint::detect_watermark`]. The embedded ID and the names of the streams being
+//! watermarked are reported through the step's state details.
+
+pub mod fingerprint;
+mod watermarker;
+
+use crate::encoders::EncoderFactory;
+use crate::steps::audio_watermark::watermarker::WatermarkSettings;
+use crate::steps::filter_encode::{
+    ensure_filter_elements_available, EncoderSettings, EncoderSettingsError, FilterEncodeStep,
+    MediaFilter, StreamSummary,
+};
+use crate::utils::GstElementError;
+use crate::GSTREAMER_INIT_RESULT;
+use anyhow::Result;
+use gstreamer::Element;
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{StepCreationResult, StepStatus};
+use mmids_core::workflows::{MediaNotificationContent, MediaType};
+use mmids_core::StreamId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+
+pub const WATERMARK_ID: &str = "watermark_id";
+pub const STRENGTH: &str = "strength";
+
+const DEFAULT_STRENGTH: f32 = 0.01;
+
+const WATERMARK_ID_DETAIL: &str = "watermark_id";
+const STRENGTH_DETAIL: &str = "strength";
+const WATERMARKED_STREAMS_DETAIL: &str = "watermarked_streams";
+
+/// Generates new instances of the audio watermark workflow step
+pub struct AudioWatermarkStepGenerator {
+    encoder_factory: Arc<EncoderFactory>,
+}
+
+/// Adds the watermark to each stream's audio
+struct WatermarkFilter {
+    settings: WatermarkSettings,
+}
+
+struct WatermarkedStream {
+    /// If the stream has had audio to watermark
+    has_audio: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum StepStartupError {
+    #[error("No {} parameter specified", WATERMARK_ID)]
+    NoWatermarkId,
+
+    #[error(
+        "Invalid {} value of '{0}'.  It must be a 32 bit number, either in decimal or as hex \
+        prefixed with '0x'",
+        WATERMARK_ID
+    )]
+    InvalidWatermarkId(String),
+
+    #[error(
+        "Invalid {} value of '{0}'.  It must be a number greater than zero and no more than 1",
+        STRENGTH
+    )]
+    InvalidStrength(String),
+
+    #[error(transparent)]
+    InvalidEncoderSettings(#[from] EncoderSettingsError),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
+
+    #[error("Audio cannot be watermarked: {0}")]
+    MissingElement(#[from] GstElementError),
+}
+
+impl AudioWatermarkStepGenerator {
+    /// Creates the generator, with the encoder factory watermarked audio is encoded with
+    pub fn new(encoder_factory: Arc<EncoderFactory>) -> Self {
+        AudioWatermarkStepGenerator { encoder_factory }
+    }
+}
+
+impl StepGenerator for AudioWatermarkStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let watermark_id = match definition.parameters.get(WATERMARK_ID) {
+            Some(Some(value)) => match parse_watermark_id(value) {
+                Some(id) => id,
+                None => {
+                    return Err(Box::new(StepStartupError::InvalidWatermarkId(
+                        value.clone(),
+                    )))
+                }
+            },
+
+            _ => return Err(Box::new(StepStartupError::NoWatermarkId)),
+        };
+
+        let strength = match definition.parameters.get(STRENGTH) {
+            Some(Some(value)) => match value.trim().parse::<f32>() {
+                Ok(strength) if strength > 0.0 && strength <= 1.0 => strength,
+                _ => return Err(Box::new(StepStartupError::InvalidStrength(value.clone()))),
+            },
+
+            _ => DEFAULT_STRENGTH,
+        };
+
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
+        ensure_filter_elements_available(watermarker::REQUIRED_ELEMENTS)
+            .map_err(StepStartupError::from)?;
+
+        let settings = EncoderSettings::audio_from_definition(&definition, &self.encoder_factory)
+            .map_err(StepStartupError::from)?;
+
+        let filter = WatermarkFilter {
+            settings: WatermarkSettings {
+                watermark_id,
+                strength,
+            },
+        };
+
+        let step = FilterEncodeStep::new(filter, settings, self.encoder_factory.clone(), None);
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl MediaFilter for WatermarkFilter {
+    type StreamState = WatermarkedStream;
+    type Event = ();
+
+    const MEDIA_TYPE: MediaType = MediaType::Audio;
+    const NAME: &'static str = "watermarker";
+
+    fn new_stream(&self, _stream_name: &Arc<String>) -> Self::StreamState {
+        WatermarkedStream { has_audio: false }
+    }
+
+    fn create_elements(
+        &self,
+        _stream: &Self::StreamState,
+        _events: &UnboundedSender<Self::Event>,
+    ) -> Result<Vec<Element>> {
+        watermarker::create_elements(self.settings)
+    }
+
+    fn media_received(
+        &mut self,
+        _stream_id: &StreamId,
+        stream: &mut Self::StreamState,
+        _content: &MediaNotificationContent,
+    ) {
+        stream.has_audio = true;
+    }
+
+    fn add_state_details(
+        &self,
+        streams: &[StreamSummary<'_, Self::StreamState>],
+        details: &mut HashMap<String, String>,
+    ) {
+        let mut stream_names = streams
+            .iter()
+            .filter(|stream| stream.state.has_audio)
+            .map(|stream| stream.name)
+            .collect::<Vec<_>>();
+
+        stream_names.sort();
+
+        details.insert(
+            WATERMARK_ID_DETAIL.to_string(),
+            format!("{:#010x}", self.settings.watermark_id),
+        );
+
+        details.insert(
+            STRENGTH_DETAIL.to_string(),
+            self.settings.strength.to_string(),
+        );
+
+        details.insert(
+            WATERMARKED_STREAMS_DETAIL.to_string(),
+            stream_names.join(", "),
+        );
+    }
+}
+
+fn parse_watermark_id(value: &str) -> Option<u32> {
+    let value = value.trim();
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
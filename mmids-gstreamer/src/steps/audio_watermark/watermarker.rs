@@ -0,0 +1,160 @@
+//! Gstreamer elements that add a watermark to decoded audio.
+
+use crate::steps::audio_watermark::fingerprint::WatermarkEmbedder;
+use crate::utils::create_gst_element;
+use anyhow::{Context, Result};
+use gstreamer::prelude::*;
+use gstreamer::{Caps, Element, PadProbeData, PadProbeReturn, PadProbeType};
+use std::sync::Mutex;
+
+/// Gstreamer elements watermarking is built from
+pub const REQUIRED_ELEMENTS: &[&str] = &["audioconvert", "capsfilter"];
+
+/// The watermark added to audio
+#[derive(Clone, Copy, Debug)]
+pub struct WatermarkSettings {
+    pub watermark_id: u32,
+
+    /// The peak amplitude of the watermark relative to full scale
+    pub strength: f32,
+}
+
+/// The embedder for the audio format currently flowing through the elements
+struct ActiveEmbedder {
+    sample_rate: u32,
+    channels: usize,
+    embedder: WatermarkEmbedder,
+}
+
+/// Creates the elements that add the watermark to decoded audio. The audio is converted to float
+/// samples for the watermark to be added to, and the embedder is restarted whenever the audio's
+/// sample rate or channel count changes.
+pub fn create_elements(settings: WatermarkSettings) -> Result<Vec<Element>> {
+    let convert = create_gst_element("audioconvert")?;
+    let capsfilter = create_gst_element("capsfilter")?;
+
+    // The watermark is added to float samples, so it can't overflow the sample format
+    let caps = Caps::builder("audio/x-raw")
+        .field("format", "F32LE")
+        .field("layout", "interleaved")
+        .build();
+
+    capsfilter.set_property("caps", caps);
+
+    let capsfilter_src = capsfilter
+        .static_pad("src")
+        .with_context(|| "capsfilter element has no src pad")?;
+
+    let embedder: Mutex<Option<ActiveEmbedder>> = Mutex::new(None);
+    capsfilter_src.add_probe(PadProbeType::BUFFER, move |pad, info| {
+        let format = pad.current_caps().and_then(|caps| {
+            let structure = caps.structure(0)?;
+            let rate = structure.get::<i32>("rate").ok()?;
+            let channels = structure.get::<i32>("channels").ok()?;
+            Some((rate as u32, channels as usize))
+        });
+
+        let (sample_rate, channels) = match format {
+            Some(format) => format,
+            None => return PadProbeReturn::Ok,
+        };
+
+        let mut embedder = embedder.lock().unwrap();
+        let is_same_format = embedder.as_ref().map_or(false, |active| {
+            active.sample_rate == sample_rate && active.channels == channels
+        });
+
+        if !is_same_format {
+            *embedder = Some(ActiveEmbedder {
+                sample_rate,
+                channels,
+                embedder: WatermarkEmbedder::new(
+                    settings.watermark_id,
+                    settings.strength,
+                    sample_rate,
+                ),
+            });
+        }
+
+        let embedder = &mut embedder.as_mut().unwrap().embedder;
+        if let Some(PadProbeData::Buffer(buffer)) = &mut info.data {
+            if let Ok(mut map) = buffer.make_mut().map_writable() {
+                let bytes = map.as_mut_slice();
+                let mut samples = bytes
+                    .chunks_exact(4)
+                    .map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+                    .collect::<Vec<_>>();
+
+                embedder.embed(&mut samples, channels);
+
+                for (bytes, sample) in bytes.chunks_exact_mut(4).zip(samples) {
+                    bytes.copy_from_slice(&sample.to_le_bytes());
+                }
+            }
+        }
+
+        PadProbeReturn::Ok
+    });
+
+    Ok(vec![convert, capsfilter])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::steps::audio_watermark::fingerprint::detect_watermark;
+    use crate::steps::filter_encode::test_utils::run_through_elements;
+    use crate::utils::set_gst_buffer;
+    use crate::GSTREAMER_INIT_RESULT;
+    use bytes::Bytes;
+    use std::time::Duration;
+
+    const SAMPLE_RATE: usize = 48000;
+    const SAMPLES_PER_BUFFER: usize = 1024;
+
+    #[test]
+    fn watermark_detected_in_filtered_audio() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let caps = Caps::builder("audio/x-raw")
+            .field("format", "F32LE")
+            .field("layout", "interleaved")
+            .field("rate", SAMPLE_RATE as i32)
+            .field("channels", 1)
+            .build();
+
+        let silence = Bytes::from(vec![0_u8; SAMPLES_PER_BUFFER * 4]);
+        let buffers = (0..SAMPLE_RATE * 5 / SAMPLES_PER_BUFFER)
+            .map(|index| {
+                let time = Duration::from_secs_f64(
+                    (index * SAMPLES_PER_BUFFER) as f64 / SAMPLE_RATE as f64,
+                );
+
+                set_gst_buffer(silence.clone(), Some(time), Some(time)).unwrap()
+            })
+            .collect();
+
+        let elements = create_elements(WatermarkSettings {
+            watermark_id: 0xC0FFEE,
+            strength: 0.01,
+        })
+        .unwrap();
+
+        let samples = run_through_elements(&elements, &caps, buffers)
+            .iter()
+            .flat_map(|sample| {
+                let map = sample.buffer().unwrap().map_readable().unwrap();
+                map.as_slice()
+                    .chunks_exact(4)
+                    .map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            detect_watermark(&samples, SAMPLE_RATE as u32),
+            Some(0xC0FFEE),
+            "Expected the watermark to be detected"
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+/// Measures the length of a stream's groups of pictures, based on the timestamps of the keyframes
+/// that start them.
+#[derive(Default)]
+pub struct GopTracker {
+    last_keyframe: Option<Duration>,
+    last_gop_length: Option<Duration>,
+}
+
+impl GopTracker {
+    /// Records a keyframe, returning the length of the GOP it completed
+    pub fn keyframe_received(&mut self, timestamp: Duration) -> Option<Duration> {
+        let gop_length = self
+            .last_keyframe
+            .filter(|last| *last <= timestamp)
+            .map(|last| timestamp - last);
+
+        self.last_keyframe = Some(timestamp);
+        if gop_length.is_some() {
+            self.last_gop_length = gop_length;
+        }
+
+        gop_length
+    }
+
+    /// The length of the most recently completed GOP
+    pub fn last_gop_length(&self) -> Option<Duration> {
+        self.last_gop_length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gop_length_is_time_between_keyframes() {
+        let mut tracker = GopTracker::default();
+
+        assert_eq!(tracker.keyframe_received(Duration::from_secs(1)), None);
+        assert_eq!(
+            tracker.keyframe_received(Duration::from_secs(3)),
+            Some(Duration::from_secs(2))
+        );
+
+        assert_eq!(
+            tracker.keyframe_received(Duration::from_secs(8)),
+            Some(Duration::from_secs(5))
+        );
+
+        assert_eq!(tracker.last_gop_length(), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn timestamps_going_backwards_does_not_complete_a_gop() {
+        let mut tracker = GopTracker::default();
+        tracker.keyframe_received(Duration::from_secs(10));
+        tracker.keyframe_received(Duration::from_secs(12));
+
+        assert_eq!(tracker.keyframe_received(Duration::from_secs(1)), None);
+        assert_eq!(tracker.last_gop_length(), Some(Duration::from_secs(2)));
+        assert_eq!(
+            tracker.keyframe_received(Duration::from_secs(2)),
+            Some(Duration::from_secs(1))
+        );
+    }
+}
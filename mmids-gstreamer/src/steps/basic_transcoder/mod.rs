@@ -6,11 +6,39 @@
 //! parameter with either `audio_` or `video_`.  These prefixes allow the workflow step to know
 //! which encoder to route the each parameter to.   The prefix is removed from the parameter before
 //! passing it to the encoder, so `video_bitrate` gets passed to the video encoder as `bitrate`.
+//!
+//! The optional `min_keyframe_interval` parameter specifies the maximum number of seconds allowed
+//! between keyframes. It is passed to the video encoder (unless a `video_min_keyframe_interval`
+//! parameter was also specified), so encoders that re-encode video can force keyframes at that
+//! interval. Encoders that pass video through can't insert keyframes, so the step also measures the
+//! GOP length of each incoming stream and counts any GOP longer than the interval as a long GOP.
+//! Both are surfaced in the step's state details.
+//!
+//! The optional `latency_ms` parameter sets how many milliseconds of media the queues within the
+//! transcoding pipeline may hold, and is passed to both encoders (unless an encoder specific
+//! `video_latency_ms` or `audio_latency_ms` parameter was also specified). Low values suit low
+//! latency restreaming, but give the pipeline less room to absorb jittery inputs or slow encodes.
+//! High values keep output stable on jittery inputs at the cost of added delay. When not
+//! specified, gstreamer's default buffering is used.
+//!
+//! When a video encoder can't keep up with a stream, video frames are dropped rather than letting
+//! media back up. How full the most backed up video encoder's queue is, and how many frames have
+//! been dropped, are also surfaced in the step's state details.
+//!
+//! The encoders are validated when the step is created, so a workflow referencing an unknown
+//! encoder, or an encoder whose gstreamer plugins are not installed, will fail to start.
 
+mod gop;
+
+use crate::encoders::{EncoderFactory, EncoderFactoryCreationError};
 use crate::endpoints::gst_transcoder::{
-    GstTranscoderNotification, GstTranscoderRequest, GstTranscoderStoppedCause,
+    EncoderQueuePressure, GstTranscoderNotification, GstTranscoderRequest,
+    GstTranscoderStoppedCause,
 };
+use crate::GSTREAMER_INIT_RESULT;
+use gop::GopTracker;
 use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::metadata::{MetadataKey, MetadataValue};
 use mmids_core::workflows::steps::factory::StepGenerator;
 use mmids_core::workflows::steps::futures_channel::{
     FuturesChannelInnerResult, WorkflowStepFuturesChannel,
@@ -18,10 +46,11 @@ use mmids_core::workflows::steps::futures_channel::{
 use mmids_core::workflows::steps::{
     StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
 };
-use mmids_core::workflows::{MediaNotification, MediaNotificationContent};
+use mmids_core::workflows::{MediaNotification, MediaNotificationContent, MediaType};
 use mmids_core::StreamId;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tracing::{error, info, instrument, warn};
 use uuid::Uuid;
@@ -30,16 +59,27 @@ pub const VIDEO_ENCODER: &str = "video";
 pub const AUDIO_ENCODER: &str = "audio";
 pub const VIDEO_PARAM_PREFIX: &str = "video_";
 pub const AUDIO_PARAM_PREFIX: &str = "audio_";
+pub const MIN_KEYFRAME_INTERVAL: &str = "min_keyframe_interval";
+pub const LATENCY_MS: &str = "latency_ms";
+
+const GOP_LENGTH_DETAIL: &str = "gop_length_ms";
+const LONG_GOP_COUNT_DETAIL: &str = "long_gop_count";
+const VIDEO_QUEUE_PERCENT_DETAIL: &str = "video_encoder_queue_percent";
+const DROPPED_VIDEO_FRAMES_DETAIL: &str = "dropped_video_frames";
 
 /// Creates a new instance of the basic transcode workflow step.
 pub struct BasicTranscodeStepGenerator {
     transcode_endpoint: UnboundedSender<GstTranscoderRequest>,
+    encoder_factory: Arc<EncoderFactory>,
+    is_keyframe_metadata_key: MetadataKey,
 }
 
 struct ActiveTranscode {
     media_sender: UnboundedSender<MediaNotificationContent>,
     transcode_process_id: Uuid,
     stream_name: Arc<String>,
+    gop_tracker: GopTracker,
+    queue_pressure: Option<EncoderQueuePressure>,
 }
 
 struct BasicTranscodeStep {
@@ -49,6 +89,10 @@ struct BasicTranscodeStep {
     audio_encoder_name: String,
     video_parameters: HashMap<String, Option<String>>,
     audio_parameters: HashMap<String, Option<String>>,
+    is_keyframe_metadata_key: MetadataKey,
+    min_keyframe_interval: Option<Duration>,
+    long_gop_count: u64,
+    dropped_video_frames: u64,
 }
 
 enum FutureResult {
@@ -71,13 +115,42 @@ enum StepStartupError {
 
     #[error("No audio encoder specified")]
     NoAudioEncoderSpecified,
+
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a number of seconds greater than zero",
+        MIN_KEYFRAME_INTERVAL
+    )]
+    InvalidMinKeyframeInterval(String),
+
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a number of milliseconds greater than zero",
+        LATENCY_MS
+    )]
+    InvalidLatency(String),
+
+    #[error("Invalid video encoder: {0}")]
+    InvalidVideoEncoder(#[source] EncoderFactoryCreationError),
+
+    #[error("Invalid audio encoder: {0}")]
+    InvalidAudioEncoder(#[source] EncoderFactoryCreationError),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
 }
 
 impl BasicTranscodeStepGenerator {
+    /// Creates the generator. The encoder factory must be the same one the transcode endpoint
+    /// uses, as it's used to validate the encoders each step is created with.
     pub fn new(
         transcode_endpoint: UnboundedSender<GstTranscoderRequest>,
+        encoder_factory: Arc<EncoderFactory>,
+        is_keyframe_metadata_key: MetadataKey,
     ) -> BasicTranscodeStepGenerator {
-        BasicTranscodeStepGenerator { transcode_endpoint }
+        BasicTranscodeStepGenerator {
+            transcode_endpoint,
+            encoder_factory,
+            is_keyframe_metadata_key,
+        }
     }
 }
 
@@ -87,6 +160,12 @@ impl StepGenerator for BasicTranscodeStepGenerator {
         definition: WorkflowStepDefinition,
         futures_channel: WorkflowStepFuturesChannel,
     ) -> StepCreationResult {
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
         let video_encoder_name = match definition.parameters.get(VIDEO_ENCODER) {
             Some(Some(encoder)) => encoder.clone(),
             _ => return Err(Box::new(StepStartupError::NoVideoEncoderSpecified)),
@@ -97,6 +176,20 @@ impl StepGenerator for BasicTranscodeStepGenerator {
             _ => return Err(Box::new(StepStartupError::NoAudioEncoderSpecified)),
         };
 
+        if let Err(error) = self
+            .encoder_factory
+            .validate_video_encoder(&video_encoder_name)
+        {
+            return Err(Box::new(StepStartupError::InvalidVideoEncoder(error)));
+        }
+
+        if let Err(error) = self
+            .encoder_factory
+            .validate_audio_encoder(&audio_encoder_name)
+        {
+            return Err(Box::new(StepStartupError::InvalidAudioEncoder(error)));
+        }
+
         // Split out audio and video specific parameters based on prefixes.
         let mut audio_params = HashMap::new();
         let mut video_params = HashMap::new();
@@ -110,6 +203,40 @@ impl StepGenerator for BasicTranscodeStepGenerator {
             }
         }
 
+        let min_keyframe_interval = match definition.parameters.get(MIN_KEYFRAME_INTERVAL) {
+            Some(Some(value)) => match value.parse::<f64>() {
+                Ok(seconds) if seconds.is_finite() && seconds > 0.0 => {
+                    video_params
+                        .entry(MIN_KEYFRAME_INTERVAL.to_string())
+                        .or_insert_with(|| Some(value.clone()));
+
+                    Some(Duration::from_secs_f64(seconds))
+                }
+
+                _ => {
+                    return Err(Box::new(StepStartupError::InvalidMinKeyframeInterval(
+                        value.clone(),
+                    )))
+                }
+            },
+
+            _ => None,
+        };
+
+        if let Some(Some(value)) = definition.parameters.get(LATENCY_MS) {
+            match value.trim().parse::<u64>() {
+                Ok(millis) if millis > 0 => {
+                    for params in [&mut video_params, &mut audio_params] {
+                        params
+                            .entry(LATENCY_MS.to_string())
+                            .or_insert_with(|| Some(value.clone()));
+                    }
+                }
+
+                _ => return Err(Box::new(StepStartupError::InvalidLatency(value.clone()))),
+            }
+        }
+
         let step = BasicTranscodeStep {
             transcoder_endpoint: self.transcode_endpoint.clone(),
             active_transcodes: HashMap::new(),
@@ -117,6 +244,10 @@ impl StepGenerator for BasicTranscodeStepGenerator {
             audio_encoder_name,
             video_parameters: video_params,
             audio_parameters: audio_params,
+            is_keyframe_metadata_key: self.is_keyframe_metadata_key,
+            min_keyframe_interval,
+            long_gop_count: 0,
+            dropped_video_frames: 0,
         };
 
         let transcode_endpoint = self.transcode_endpoint.clone();
@@ -175,6 +306,8 @@ impl BasicTranscodeStep {
                 transcode_process_id: process_id,
                 media_sender,
                 stream_name: stream_name.clone(),
+                gop_tracker: GopTracker::default(),
+                queue_pressure: None,
             },
         );
 
@@ -227,8 +360,46 @@ impl BasicTranscodeStep {
                 outputs.media.push(media);
             }
 
-            MediaNotificationContent::MediaPayload { .. } => {
-                if let Some(transcode) = self.active_transcodes.get(&media.stream_id) {
+            MediaNotificationContent::MediaPayload {
+                media_type,
+                timestamp,
+                metadata,
+                is_required_for_decoding,
+                ..
+            } => {
+                let is_keyframe_metadata_key = self.is_keyframe_metadata_key;
+                if let Some(transcode) = self.active_transcodes.get_mut(&media.stream_id) {
+                    let is_keyframe = *media_type == MediaType::Video
+                        && !*is_required_for_decoding
+                        && metadata
+                            .iter()
+                            .filter(|m| m.key() == is_keyframe_metadata_key)
+                            .filter_map(|m| match m.value() {
+                                MetadataValue::Bool(val) => Some(val),
+                                _ => None,
+                            })
+                            .next()
+                            .unwrap_or_default();
+
+                    if is_keyframe {
+                        let gop_length = transcode.gop_tracker.keyframe_received(*timestamp);
+                        if let (Some(gop_length), Some(interval)) =
+                            (gop_length, self.min_keyframe_interval)
+                        {
+                            if gop_length > interval {
+                                warn!(
+                                    stream_id = ?media.stream_id,
+                                    "Stream had a GOP of {:?}, which is longer than the minimum \
+                                    keyframe interval of {:?}",
+                                    gop_length,
+                                    interval,
+                                );
+
+                                self.long_gop_count += 1;
+                            }
+                        }
+                    }
+
                     let _ = transcode.media_sender.send(media.content.clone());
                 }
             }
@@ -262,6 +433,22 @@ impl BasicTranscodeStep {
                 }
             }
 
+            GstTranscoderNotification::VideoEncoderQueuePressure(pressure) => {
+                if let Some(transcode) = self.active_transcodes.get_mut(&stream_id) {
+                    // Dropped frame counts are per transcoding process, so only the frames
+                    // dropped since the last report are new
+                    let previously_dropped = transcode
+                        .queue_pressure
+                        .map(|pressure| pressure.dropped_frames)
+                        .unwrap_or_default();
+
+                    self.dropped_video_frames +=
+                        pressure.dropped_frames.saturating_sub(previously_dropped);
+
+                    transcode.queue_pressure = Some(pressure);
+                }
+            }
+
             GstTranscoderNotification::TranscodingStarted { output_media } => {
                 let closed_stream_id = stream_id.clone();
 
@@ -271,6 +458,7 @@ impl BasicTranscodeStep {
                         FuturesChannelInnerResult::Media(MediaNotification {
                             stream_id: stream_id.clone(),
                             content: media,
+                            annotations: Default::default(),
                         })
                     },
                     move || {
@@ -343,4 +531,76 @@ impl WorkflowStep for BasicTranscodeStep {
 
         StepStatus::Active
     }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+
+        // Report the longest recent GOP, as that's what determines the worst case join latency
+        let gop_length = self
+            .active_transcodes
+            .values()
+            .filter_map(|transcode| transcode.gop_tracker.last_gop_length())
+            .max();
+
+        if let Some(gop_length) = gop_length {
+            details.insert(
+                GOP_LENGTH_DETAIL.to_string(),
+                gop_length.as_millis().to_string(),
+            );
+        }
+
+        if self.min_keyframe_interval.is_some() {
+            details.insert(
+                LONG_GOP_COUNT_DETAIL.to_string(),
+                self.long_gop_count.to_string(),
+            );
+        }
+
+        let queue_percent = self
+            .active_transcodes
+            .values()
+            .filter_map(|transcode| transcode.queue_pressure)
+            .map(|pressure| pressure.queued_percent)
+            .max();
+
+        if let Some(queue_percent) = queue_percent {
+            details.insert(
+                VIDEO_QUEUE_PERCENT_DETAIL.to_string(),
+                queue_percent.to_string(),
+            );
+        }
+
+        details.insert(
+            DROPPED_VIDEO_FRAMES_DETAIL.to_string(),
+            self.dropped_video_frames.to_string(),
+        );
+
+        details
+    }
+
+    fn get_active_pipeline_count(&self) -> usize {
+        self.active_transcodes.len()
+    }
+
+    fn request_sequence_headers(&mut self, stream_id: &StreamId) -> bool {
+        match self.active_transcodes.get(stream_id) {
+            Some(transcode) => {
+                info!(
+                    stream_id = ?stream_id,
+                    "Requesting keyframe from transcode process {}",
+                    transcode.transcode_process_id
+                );
+
+                let _ = self
+                    .transcoder_endpoint
+                    .send(GstTranscoderRequest::ForceKeyUnit {
+                        id: transcode.transcode_process_id,
+                    });
+
+                true
+            }
+
+            None => false,
+        }
+    }
 }
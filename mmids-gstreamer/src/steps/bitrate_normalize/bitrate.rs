@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+/// Whether a stream's video can be passed through, or must be re-encoded at a target bitrate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Passthrough,
+    Reencode { target_kbps: u32 },
+}
+
+/// The range of video bitrates that are passed through. Either end may be unconstrained.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BitrateBand {
+    pub min_kbps: Option<u32>,
+    pub max_kbps: Option<u32>,
+}
+
+impl BitrateBand {
+    /// Decides if video measured at the specified bitrate must be re-encoded, and if so, which
+    /// end of the band it should be brought to.
+    pub fn decide(&self, measured_kbps: u32) -> Decision {
+        if let Some(max) = self.max_kbps {
+            if measured_kbps > max {
+                return Decision::Reencode { target_kbps: max };
+            }
+        }
+
+        if let Some(min) = self.min_kbps {
+            if measured_kbps < min {
+                return Decision::Reencode { target_kbps: min };
+            }
+        }
+
+        Decision::Passthrough
+    }
+}
+
+/// Measures bitrate over consecutive windows of a stream's media timeline
+pub struct BitrateWindow {
+    length: Duration,
+    started_at: Option<Duration>,
+    bytes: u64,
+}
+
+impl BitrateWindow {
+    pub fn new(length: Duration) -> Self {
+        BitrateWindow {
+            length,
+            started_at: None,
+            bytes: 0,
+        }
+    }
+
+    /// Records a payload, returning the bitrate in kbps of the window the payload completed
+    pub fn record(&mut self, timestamp: Duration, bytes: u64) -> Option<u32> {
+        let started_at = *self.started_at.get_or_insert(timestamp);
+        if timestamp < started_at {
+            // The timeline jumped backwards, so the current window can't be measured
+            self.started_at = Some(timestamp);
+            self.bytes = bytes;
+            return None;
+        }
+
+        let elapsed = timestamp - started_at;
+        if elapsed < self.length {
+            self.bytes += bytes;
+            return None;
+        }
+
+        // This payload belongs to the next window
+        let kbps = self.bytes * 8 / elapsed.as_millis().max(1) as u64;
+        self.started_at = Some(timestamp);
+        self.bytes = bytes;
+
+        Some(kbps.min(u32::MAX as u64) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn band() -> BitrateBand {
+        BitrateBand {
+            min_kbps: Some(1000),
+            max_kbps: Some(6000),
+        }
+    }
+
+    #[test]
+    fn bitrate_within_band_is_passed_through() {
+        assert_eq!(band().decide(1000), Decision::Passthrough);
+        assert_eq!(band().decide(4500), Decision::Passthrough);
+        assert_eq!(band().decide(6000), Decision::Passthrough);
+    }
+
+    #[test]
+    fn over_bitrate_reencoded_down_to_maximum() {
+        assert_eq!(
+            band().decide(9000),
+            Decision::Reencode { target_kbps: 6000 }
+        );
+    }
+
+    #[test]
+    fn under_bitrate_reencoded_up_to_minimum() {
+        assert_eq!(band().decide(400), Decision::Reencode { target_kbps: 1000 });
+    }
+
+    #[test]
+    fn only_constrained_end_checked() {
+        let band = BitrateBand {
+            min_kbps: None,
+            max_kbps: Some(6000),
+        };
+
+        assert_eq!(band.decide(1), Decision::Passthrough);
+    }
+
+    #[test]
+    fn bitrate_measured_once_window_completes() {
+        let mut window = BitrateWindow::new(Duration::from_millis(1000));
+
+        // 2 payloads of 500KB over one second is 8000kbps
+        assert_eq!(window.record(Duration::from_millis(0), 500_000), None);
+        assert_eq!(window.record(Duration::from_millis(500), 500_000), None);
+        assert_eq!(
+            window.record(Duration::from_millis(1000), 100_000),
+            Some(8000)
+        );
+
+        // The payload that completed the window starts the next one
+        assert_eq!(
+            window.record(Duration::from_millis(2000), 100_000),
+            Some(800)
+        );
+    }
+
+    #[test]
+    fn window_restarted_when_timeline_jumps_backwards() {
+        let mut window = BitrateWindow::new(Duration::from_millis(1000));
+
+        assert_eq!(window.record(Duration::from_millis(5000), 500_000), None);
+        assert_eq!(window.record(Duration::from_millis(0), 100_000), None);
+        assert_eq!(
+            window.record(Duration::from_millis(1000), 100_000),
+            Some(800)
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! The bitrate normalize workflow step keeps the video bitrate of each stream within a configured
+//! band, re-encoding any stream whose video bitrate is above `max_bitrate` or below `min_bitrate`
+//! while passing all other streams through untouched. This normalizes contributions from sources
+//! with mixed encoder settings, without paying to re-encode sources that are already in range.
+//! Re-encoding an under-bitrate stream can't add back quality the source never had, so
+//! `min_bitrate` is only useful when something downstream requires a minimum bitrate, while
+//! capping over-bitrate streams saves downstream bandwidth.
+//!
+//! Each stream's video bitrate is measured over windows of `bitrate_window` milliseconds of the
+//! stream's media timeline, and streams are passed through until their first window completes.
+//! The source's bitrate keeps being measured while it's re-encoded, and each completed window
+//! is re-evaluated, so a stream whose bitrate changes is switched between passing through and
+//! re-encoding as needed. Each switch is signaled with a metadata notification containing only the
+//! `DISCONTINUITY_METADATA_KEY` key, whose value is either `passthrough` or `reencode`.
+//!
+//! Re-encoding is done the same way as the basic transcode step, with the `video` and `audio`
+//! parameters naming the encoders to use, and `video_` and `audio_` prefixed parameters being
+//! passed to the respective encoders. The video encoder's `bitrate` parameter is set by the step
+//! to the end of the band each stream is brought to. Each stream's measured and target bitrates
+//! are surfaced in the step's state details.
+
+mod bitrate;
+
+use crate::encoders::EncoderFactory;
+use crate::endpoints::gst_transcoder::GstTranscoderRequest;
+use crate::steps::selective_transcode::{
+    per_stream_detail, Decision, SelectiveTranscodeStep, StreamStatus, StreamSummary,
+    TranscodeDecider, TranscodeSettings, TranscodeSettingsError, PASSTHROUGH_MODE,
+};
+use crate::GSTREAMER_INIT_RESULT;
+use bitrate::{BitrateBand, BitrateWindow};
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{StepCreationResult, StepStatus};
+use mmids_core::workflows::{MediaNotificationContent, MediaType};
+use mmids_core::StreamId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+
+pub const MIN_BITRATE: &str = "min_bitrate";
+pub const MAX_BITRATE: &str = "max_bitrate";
+pub const BITRATE_WINDOW: &str = "bitrate_window";
+
+const BITRATE_ENCODER_PARAM: &str = "bitrate";
+const DEFAULT_BITRATE_WINDOW: Duration = Duration::from_millis(5000);
+
+const REENCODE_MODE: &str = "reencode";
+
+const MEASURED_BITRATE_DETAIL: &str = "measured_bitrate";
+const TARGET_BITRATE_DETAIL: &str = "target_bitrate";
+const REENCODING_STREAMS_DETAIL: &str = "reencoding_streams";
+
+/// Creates new instances of the bitrate normalize workflow step.
+pub struct BitrateNormalizeStepGenerator {
+    transcode_endpoint: UnboundedSender<GstTranscoderRequest>,
+    encoder_factory: Arc<EncoderFactory>,
+}
+
+/// Re-encodes streams whose measured video bitrate is outside the band
+struct BitrateNormalizeDecider {
+    band: BitrateBand,
+    bitrate_window: Duration,
+}
+
+struct StreamBitrate {
+    window: BitrateWindow,
+    measured_kbps: Option<u32>,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "At least one of the {} or {} parameters must be specified",
+        MIN_BITRATE,
+        MAX_BITRATE
+    )]
+    NoBandSpecified,
+
+    #[error("Invalid {0} value of '{1}' specified, must be a number greater than zero")]
+    InvalidNumber(&'static str, String),
+
+    #[error(
+        "The {} of {}kbps is greater than the {} of {}kbps",
+        MIN_BITRATE,
+        .0,
+        MAX_BITRATE,
+        .1
+    )]
+    MinimumAboveMaximum(u32, u32),
+
+    #[error(transparent)]
+    InvalidTranscodeSettings(#[from] TranscodeSettingsError),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
+}
+
+impl BitrateNormalizeStepGenerator {
+    /// Creates the generator. The encoder factory must be the same one the transcode endpoint
+    /// uses, as it's used to validate the encoders each step is created with.
+    pub fn new(
+        transcode_endpoint: UnboundedSender<GstTranscoderRequest>,
+        encoder_factory: Arc<EncoderFactory>,
+    ) -> BitrateNormalizeStepGenerator {
+        BitrateNormalizeStepGenerator {
+            transcode_endpoint,
+            encoder_factory,
+        }
+    }
+}
+
+impl StepGenerator for BitrateNormalizeStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
+        let band = BitrateBand {
+            min_kbps: get_number(&definition, MIN_BITRATE)?,
+            max_kbps: get_number(&definition, MAX_BITRATE)?,
+        };
+
+        match (band.min_kbps, band.max_kbps) {
+            (None, None) => return Err(Box::new(StepStartupError::NoBandSpecified)),
+            (Some(min), Some(max)) if min > max => {
+                return Err(Box::new(StepStartupError::MinimumAboveMaximum(min, max)));
+            }
+
+            _ => (),
+        }
+
+        let bitrate_window = get_number(&definition, BITRATE_WINDOW)?
+            .map(|ms| Duration::from_millis(ms as u64))
+            .unwrap_or(DEFAULT_BITRATE_WINDOW);
+
+        let settings = TranscodeSettings::from_definition(&definition, &self.encoder_factory)
+            .map_err(StepStartupError::from)?;
+
+        let decider = BitrateNormalizeDecider {
+            band,
+            bitrate_window,
+        };
+
+        let step = SelectiveTranscodeStep::new(
+            decider,
+            self.transcode_endpoint.clone(),
+            settings,
+            &futures_channel,
+        );
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+fn get_number(
+    definition: &WorkflowStepDefinition,
+    parameter: &'static str,
+) -> Result<Option<u32>, StepStartupError> {
+    match definition.parameters.get(parameter) {
+        Some(Some(value)) => match value.trim().parse::<u32>() {
+            Ok(number) if number > 0 => Ok(Some(number)),
+            _ => Err(StepStartupError::InvalidNumber(parameter, value.clone())),
+        },
+
+        _ => Ok(None),
+    }
+}
+
+impl TranscodeDecider for BitrateNormalizeDecider {
+    type Target = u32;
+    type StreamState = StreamBitrate;
+
+    const TRANSCODE_MODE: &'static str = REENCODE_MODE;
+    const TRANSCODING_STREAMS_DETAIL: &'static str = REENCODING_STREAMS_DETAIL;
+
+    fn new_stream(&self) -> StreamBitrate {
+        StreamBitrate {
+            window: BitrateWindow::new(self.bitrate_window),
+            measured_kbps: None,
+        }
+    }
+
+    /// Streams are passed through until their first window completes
+    fn initial_decision(&self) -> Option<Decision<u32>> {
+        Some(Decision::Passthrough)
+    }
+
+    fn evaluate(
+        &mut self,
+        _stream_id: &StreamId,
+        stream: &mut StreamBitrate,
+        content: &MediaNotificationContent,
+        _is_pending: bool,
+    ) -> Option<Decision<u32>> {
+        let (timestamp, data) = match content {
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                timestamp,
+                data,
+                is_required_for_decoding: false,
+                ..
+            } => (timestamp, data),
+
+            _ => return None,
+        };
+
+        let measured_kbps = stream.window.record(*timestamp, data.len() as u64)?;
+        stream.measured_kbps = Some(measured_kbps);
+
+        Some(match self.band.decide(measured_kbps) {
+            bitrate::Decision::Passthrough => Decision::Passthrough,
+            bitrate::Decision::Reencode { target_kbps } => Decision::Transcode {
+                target: target_kbps,
+                reason: format!(
+                    "bitrate of {}kbps is outside the band, re-encoding at {}kbps",
+                    measured_kbps, target_kbps
+                ),
+            },
+        })
+    }
+
+    fn configure_video_encoder(
+        &self,
+        target_kbps: &u32,
+        parameters: &mut HashMap<String, Option<String>>,
+    ) {
+        parameters.insert(
+            BITRATE_ENCODER_PARAM.to_string(),
+            Some(target_kbps.to_string()),
+        );
+    }
+
+    fn add_state_details(
+        &self,
+        streams: &[StreamSummary<'_, StreamBitrate, u32>],
+        details: &mut HashMap<String, String>,
+    ) {
+        details.insert(
+            MEASURED_BITRATE_DETAIL.to_string(),
+            per_stream_detail(streams, |stream| match stream.state.measured_kbps {
+                Some(kbps) => format!("{}kbps", kbps),
+                None => "measuring".to_string(),
+            }),
+        );
+
+        details.insert(
+            TARGET_BITRATE_DETAIL.to_string(),
+            per_stream_detail(streams, |stream| match stream.status {
+                StreamStatus::Transcoding(target_kbps) => format!("{}kbps", target_kbps),
+                _ => PASSTHROUGH_MODE.to_string(),
+            }),
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Gstreamer elements that convert decoded video to a constant frame rate with gstreamer's
+//! `videorate` element.
+
+use crate::utils::create_gst_element;
+use anyhow::Result;
+use gstreamer::prelude::*;
+use gstreamer::{Caps, Element, Fraction};
+
+/// Gstreamer elements the frame rate conversion is built from
+pub const REQUIRED_ELEMENTS: &[&str] = &["videoconvert", "videorate", "capsfilter"];
+
+/// Creates the elements that convert video to the specified frame rate. Frames are duplicated or
+/// dropped as needed so every output frame is evenly spaced at the target frame rate.
+pub fn create_elements(fps: u32) -> Result<Vec<Element>> {
+    let convert = create_gst_element("videoconvert")?;
+    let rate_changer = create_gst_element("videorate")?;
+    let capsfilter = create_gst_element("capsfilter")?;
+
+    // Without this, `videorate` fills the time between the start of the segment and the
+    // stream's first frame with duplicates of that frame
+    rate_changer.set_property("skip-to-first", true);
+
+    let caps = Caps::builder("video/x-raw")
+        .field("framerate", Fraction::new(fps as i32, 1))
+        .build();
+
+    capsfilter.set_property("caps", caps);
+
+    Ok(vec![convert, rate_changer, capsfilter])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::steps::filter_encode::test_utils::run_through_elements;
+    use crate::utils::set_gst_buffer;
+    use crate::GSTREAMER_INIT_RESULT;
+    use bytes::Bytes;
+    use std::time::Duration;
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 64;
+
+    #[test]
+    fn uneven_frames_converted_to_evenly_spaced_frames() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        // A frame rate of 0/1 marks the source as having a variable frame rate
+        let caps = Caps::builder("video/x-raw")
+            .field("format", "I420")
+            .field("width", WIDTH as i32)
+            .field("height", HEIGHT as i32)
+            .field("framerate", Fraction::new(0, 1))
+            .build();
+
+        let frame = Bytes::from(vec![128_u8; WIDTH * HEIGHT * 3 / 2]);
+        let input_times = [0, 15, 70, 90, 160, 170, 180, 260, 300, 390, 400, 410, 500];
+        let buffers = input_times
+            .iter()
+            .map(|millis| {
+                let time = Duration::from_millis(*millis);
+                set_gst_buffer(frame.clone(), Some(time), Some(time)).unwrap()
+            })
+            .collect();
+
+        let elements = create_elements(25).unwrap();
+        let output_times = run_through_elements(&elements, &caps, buffers)
+            .iter()
+            .map(|sample| Duration::from_millis(sample.buffer().unwrap().pts().unwrap().mseconds()))
+            .collect::<Vec<_>>();
+
+        assert!(
+            output_times.len() >= 10,
+            "Expected at least 10 frames, only received {}",
+            output_times.len()
+        );
+
+        for pair in output_times.windows(2) {
+            assert_eq!(
+                pair[1] - pair[0],
+                Duration::from_millis(40),
+                "Expected frames evenly spaced at 25fps, got {:?}",
+                output_times
+            );
+        }
+    }
+}
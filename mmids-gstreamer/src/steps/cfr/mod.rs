@@ -0,0 +1,216 @@
+//! The constant frame rate (CFR) workflow step makes sure the video of every stream flowing
+//! through it has a constant frame rate, for downstream targets (such as HLS) that break when
+//! frames are unevenly spaced. The video is decoded, converted to the target frame rate with
+//! gstreamer's `videorate` element (duplicating or dropping frames as needed) and re-encoded,
+//! while audio is passed through untouched.
+//!
+//! The `fps` parameter is required and specifies the frame rate to convert to. The converted video
+//! is encoded the same way as the basic transcode step, with the `video` parameter naming the
+//! encoder to use and `video_` prefixed parameters being passed to it.
+//!
+//! When the `passthrough` parameter is specified video is not re-encoded, and no encoder is
+//! needed. Instead, each stream's video is passed through as is, and a warning is logged when a
+//! stream is detected as having a variable frame rate.
+//!
+//! Converted frames have timestamps that are evenly spaced at the target frame rate. Whether each
+//! stream's source video has a variable frame rate and the output frame rate are reported through
+//! the step's state details.
+
+mod converter;
+mod monitor;
+
+use crate::encoders::EncoderFactory;
+use crate::steps::cfr::monitor::FrameRateMonitor;
+use crate::steps::filter_encode::{
+    ensure_filter_elements_available, per_stream_detail, EncoderSettings, EncoderSettingsError,
+    FilterEncodeStep, MediaFilter, StreamSummary,
+};
+use crate::utils::GstElementError;
+use crate::GSTREAMER_INIT_RESULT;
+use anyhow::Result;
+use gstreamer::Element;
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::metadata::MetadataKey;
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{StepCreationResult, StepStatus};
+use mmids_core::workflows::{MediaNotificationContent, MediaType};
+use mmids_core::StreamId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::warn;
+
+pub const FPS: &str = "fps";
+pub const PASSTHROUGH: &str = "passthrough";
+
+const INPUT_VARIABLE_FRAME_RATE_DETAIL: &str = "input_variable_frame_rate";
+const OUTPUT_FRAME_RATE_DETAIL: &str = "output_frame_rate";
+
+/// Generates new instances of the constant frame rate workflow step
+pub struct CfrStepGenerator {
+    encoder_factory: Arc<EncoderFactory>,
+    pts_offset_metadata_key: MetadataKey,
+}
+
+/// Converts each stream's video to a constant frame rate, while watching the source video for a
+/// variable frame rate
+struct FrameRateFilter {
+    fps: u32,
+    is_passthrough: bool,
+}
+
+struct CfrStream {
+    stream_name: Arc<String>,
+    monitor: FrameRateMonitor,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum StepStartupError {
+    #[error("No {} parameter specified", FPS)]
+    NoFps,
+
+    #[error(
+        "Invalid {} value of '{0}'.  It must be a number greater than zero",
+        FPS
+    )]
+    InvalidFps(String),
+
+    #[error(transparent)]
+    InvalidEncoderSettings(#[from] EncoderSettingsError),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
+
+    #[error("Video cannot be converted: {0}")]
+    MissingElement(#[from] GstElementError),
+}
+
+impl CfrStepGenerator {
+    /// Creates the generator, with the encoder factory converted video is encoded with
+    pub fn new(encoder_factory: Arc<EncoderFactory>, pts_offset_metadata_key: MetadataKey) -> Self {
+        CfrStepGenerator {
+            encoder_factory,
+            pts_offset_metadata_key,
+        }
+    }
+}
+
+impl StepGenerator for CfrStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let fps = match definition.parameters.get(FPS) {
+            Some(Some(value)) => match value.trim().parse::<u32>() {
+                Ok(fps) if fps > 0 => fps,
+                _ => return Err(Box::new(StepStartupError::InvalidFps(value.clone()))),
+            },
+
+            _ => return Err(Box::new(StepStartupError::NoFps)),
+        };
+
+        let is_passthrough = definition.parameters.contains_key(PASSTHROUGH);
+        let filter = FrameRateFilter {
+            fps,
+            is_passthrough,
+        };
+
+        // Passthrough mode never creates a pipeline, so gstreamer and an encoder aren't needed
+        // for it
+        if is_passthrough {
+            return Ok((
+                Box::new(FilterEncodeStep::passthrough(filter)),
+                StepStatus::Active,
+            ));
+        }
+
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
+        ensure_filter_elements_available(converter::REQUIRED_ELEMENTS)
+            .map_err(StepStartupError::from)?;
+
+        let settings = EncoderSettings::video_from_definition(&definition, &self.encoder_factory)
+            .map_err(StepStartupError::from)?;
+
+        let step = FilterEncodeStep::new(
+            filter,
+            settings,
+            self.encoder_factory.clone(),
+            Some(self.pts_offset_metadata_key),
+        );
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl MediaFilter for FrameRateFilter {
+    type StreamState = CfrStream;
+    type Event = ();
+
+    const MEDIA_TYPE: MediaType = MediaType::Video;
+    const NAME: &'static str = "frame rate converter";
+
+    fn new_stream(&self, stream_name: &Arc<String>) -> Self::StreamState {
+        CfrStream {
+            stream_name: stream_name.clone(),
+            monitor: FrameRateMonitor::new(),
+        }
+    }
+
+    fn create_elements(
+        &self,
+        _stream: &Self::StreamState,
+        _events: &UnboundedSender<Self::Event>,
+    ) -> Result<Vec<Element>> {
+        converter::create_elements(self.fps)
+    }
+
+    fn media_received(
+        &mut self,
+        stream_id: &StreamId,
+        stream: &mut Self::StreamState,
+        content: &MediaNotificationContent,
+    ) {
+        if let MediaNotificationContent::MediaPayload {
+            timestamp,
+            is_required_for_decoding: false,
+            ..
+        } = content
+        {
+            if stream.monitor.add_frame(*timestamp) {
+                warn!(
+                    stream_id = %stream_id.0,
+                    stream_name = %stream.stream_name,
+                    "Stream's video has a variable frame rate"
+                );
+            }
+        }
+    }
+
+    fn add_state_details(
+        &self,
+        streams: &[StreamSummary<'_, Self::StreamState>],
+        details: &mut HashMap<String, String>,
+    ) {
+        let output_frame_rate = if self.is_passthrough {
+            "unchanged".to_string()
+        } else {
+            self.fps.to_string()
+        };
+
+        details.insert(
+            INPUT_VARIABLE_FRAME_RATE_DETAIL.to_string(),
+            per_stream_detail(streams, |stream| {
+                Some(stream.monitor.is_variable().to_string())
+            }),
+        );
+
+        details.insert(OUTPUT_FRAME_RATE_DETAIL.to_string(), output_frame_rate);
+    }
+}
@@ -0,0 +1,120 @@
+//! Detection of variable frame rate video, based on how evenly spaced a stream's frames are
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// How many of the most recent frame intervals are considered when deciding if the frame rate is
+/// variable
+const INTERVAL_WINDOW_SIZE: usize = 30;
+
+/// The smallest amount frame intervals can differ by before the frame rate is considered
+/// variable. Timestamps only have millisecond precision, so frame rates that aren't a whole
+/// number of milliseconds per frame (e.g. 30fps) naturally alternate between intervals.
+const MIN_INTERVAL_TOLERANCE: Duration = Duration::from_millis(2);
+
+/// Tracks the spacing between a stream's frames to detect when it has a variable frame rate.
+/// Frames are tracked by their decoding timestamps, as presentation timestamps are not in order
+/// when B-frames are used.
+pub struct FrameRateMonitor {
+    last_timestamp: Option<Duration>,
+    intervals: VecDeque<Duration>,
+    is_variable: bool,
+}
+
+impl FrameRateMonitor {
+    pub fn new() -> Self {
+        FrameRateMonitor {
+            last_timestamp: None,
+            intervals: VecDeque::with_capacity(INTERVAL_WINDOW_SIZE),
+            is_variable: false,
+        }
+    }
+
+    /// Whether the most recent frames were unevenly spaced
+    pub fn is_variable(&self) -> bool {
+        self.is_variable
+    }
+
+    /// Records the decoding timestamp of the next frame. Returns `true` if this frame caused the
+    /// stream to be detected as having a variable frame rate.
+    pub fn add_frame(&mut self, timestamp: Duration) -> bool {
+        let last_timestamp = self.last_timestamp.replace(timestamp);
+        match last_timestamp {
+            Some(last) if timestamp > last => {
+                if self.intervals.len() == INTERVAL_WINDOW_SIZE {
+                    self.intervals.pop_front();
+                }
+
+                self.intervals.push_back(timestamp - last);
+            }
+
+            // Non-increasing timestamps say nothing about the frame rate
+            _ => return false,
+        }
+
+        if self.intervals.len() < INTERVAL_WINDOW_SIZE {
+            return false;
+        }
+
+        let min = self.intervals.iter().min().copied().unwrap_or_default();
+        let max = self.intervals.iter().max().copied().unwrap_or_default();
+        let average = self.intervals.iter().sum::<Duration>() / self.intervals.len() as u32;
+        let tolerance = MIN_INTERVAL_TOLERANCE.max(average / 10);
+
+        let was_variable = self.is_variable;
+        self.is_variable = max - min > tolerance;
+
+        self.is_variable && !was_variable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_frames(monitor: &mut FrameRateMonitor, timestamps: impl Iterator<Item = u64>) -> bool {
+        timestamps
+            .map(|millis| monitor.add_frame(Duration::from_millis(millis)))
+            .fold(false, |detected, result| detected || result)
+    }
+
+    #[test]
+    fn evenly_spaced_frames_are_not_variable() {
+        let mut monitor = FrameRateMonitor::new();
+        let detected = add_frames(&mut monitor, (0..100).map(|index| index * 40));
+
+        assert!(!detected, "Expected variable frame rate to not be detected");
+        assert!(!monitor.is_variable(), "Expected constant frame rate");
+    }
+
+    #[test]
+    fn millisecond_rounding_is_not_variable() {
+        // 30fps with millisecond timestamps alternates between 33ms and 34ms intervals
+        let mut monitor = FrameRateMonitor::new();
+        let detected = add_frames(&mut monitor, (0..100).map(|index| index * 1000 / 30));
+
+        assert!(!detected, "Expected variable frame rate to not be detected");
+        assert!(!monitor.is_variable(), "Expected constant frame rate");
+    }
+
+    #[test]
+    fn unevenly_spaced_frames_are_variable() {
+        let mut monitor = FrameRateMonitor::new();
+        let timestamps = (0..100).map(|index| index * 40 + if index % 3 == 1 { 15 } else { 0 });
+        let detected = add_frames(&mut monitor, timestamps);
+
+        assert!(detected, "Expected variable frame rate to be detected");
+        assert!(monitor.is_variable(), "Expected variable frame rate");
+    }
+
+    #[test]
+    fn variable_frame_rate_cleared_once_frames_are_evenly_spaced_again() {
+        let mut monitor = FrameRateMonitor::new();
+        let timestamps = (0..40).map(|index| index * 40 + if index % 3 == 1 { 15 } else { 0 });
+        add_frames(&mut monitor, timestamps);
+        assert!(monitor.is_variable(), "Expected variable frame rate");
+
+        add_frames(&mut monitor, (40..100).map(|index| index * 40));
+        assert!(!monitor.is_variable(), "Expected constant frame rate");
+    }
+}
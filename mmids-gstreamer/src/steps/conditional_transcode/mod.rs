@@ -0,0 +1,253 @@
+//! The conditional transcode workflow step only transcodes streams whose video isn't already in
+//! the desired format, passing all other streams through untouched. This avoids spending CPU
+//! re-encoding sources that later steps could already use as is.
+//!
+//! The desired format is specified with the `codec` parameter (`h264`, `h265` or `av1`), and
+//! optionally a comma separated list of allowed `profiles` (either names like `main` or
+//! `profile_idc` numbers) and a `max_width` and/or `max_height`. The source parameters of each
+//! stream are read from its video sequence header, and media received before it is held until the
+//! decision can be made. Streams whose codec, or any constrained parameter, can't be read are
+//! transcoded. The resolution of AV1 streams can't be read, so they are always transcoded when a
+//! maximum resolution is set.
+//!
+//! Transcoding is done the same way as the basic transcode step, with the `video` and `audio`
+//! parameters naming the encoders to use, and `video_` and `audio_` prefixed parameters being
+//! passed to the respective encoders.
+//!
+//! Each new video sequence header is re-evaluated, so a stream whose codec changes mid-stream is
+//! switched between passing through and transcoding as needed. Each switch is signaled with a
+//! metadata notification containing only the `DISCONTINUITY_METADATA_KEY` key, whose value is
+//! either `passthrough` or `transcode`. How many streams are passing through and being transcoded
+//! is surfaced in the step's state details.
+
+mod source;
+
+use crate::encoders::EncoderFactory;
+use crate::endpoints::gst_transcoder::GstTranscoderRequest;
+use crate::steps::selective_transcode::{
+    Decision, SelectiveTranscodeStep, StreamSummary, TranscodeDecider, TranscodeSettings,
+    TranscodeSettingsError,
+};
+use crate::GSTREAMER_INIT_RESULT;
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{StepCreationResult, StepStatus};
+use mmids_core::workflows::{MediaNotificationContent, MediaType};
+use mmids_core::StreamId;
+use source::{SourceParameters, TargetFormat, VideoCodec};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+
+pub const CODEC: &str = "codec";
+pub const PROFILES: &str = "profiles";
+pub const MAX_WIDTH: &str = "max_width";
+pub const MAX_HEIGHT: &str = "max_height";
+
+const TRANSCODE_MODE: &str = "transcode";
+
+const TRANSCODING_STREAMS_DETAIL: &str = "transcoding_streams";
+const LAST_TRANSCODE_REASON_DETAIL: &str = "last_transcode_reason";
+
+/// Creates new instances of the conditional transcode workflow step.
+pub struct ConditionalTranscodeStepGenerator {
+    transcode_endpoint: UnboundedSender<GstTranscoderRequest>,
+    encoder_factory: Arc<EncoderFactory>,
+}
+
+/// Transcodes streams whose video source parameters don't match the target format
+struct ConditionalTranscodeDecider {
+    target: TargetFormat,
+    last_transcode_reason: Option<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum StepStartupError {
+    #[error("No {} parameter specified", CODEC)]
+    NoCodecSpecified,
+
+    #[error(
+        "Invalid {} value of '{0}' specified, must be h264, h265 or av1",
+        CODEC
+    )]
+    InvalidCodec(String),
+
+    #[error(
+        "Invalid {} value of '{0}' specified, unknown profile for the codec",
+        PROFILES
+    )]
+    InvalidProfile(String),
+
+    #[error("Invalid {0} value of '{1}' specified, must be a number greater than zero")]
+    InvalidMaximum(&'static str, String),
+
+    #[error(transparent)]
+    InvalidTranscodeSettings(#[from] TranscodeSettingsError),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
+}
+
+impl ConditionalTranscodeStepGenerator {
+    /// Creates the generator. The encoder factory must be the same one the transcode endpoint
+    /// uses, as it's used to validate the encoders each step is created with.
+    pub fn new(
+        transcode_endpoint: UnboundedSender<GstTranscoderRequest>,
+        encoder_factory: Arc<EncoderFactory>,
+    ) -> ConditionalTranscodeStepGenerator {
+        ConditionalTranscodeStepGenerator {
+            transcode_endpoint,
+            encoder_factory,
+        }
+    }
+}
+
+impl StepGenerator for ConditionalTranscodeStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
+        let target = get_target_format(&definition)?;
+        let settings = TranscodeSettings::from_definition(&definition, &self.encoder_factory)
+            .map_err(StepStartupError::from)?;
+
+        let decider = ConditionalTranscodeDecider {
+            target,
+            last_transcode_reason: None,
+        };
+
+        let step = SelectiveTranscodeStep::new(
+            decider,
+            self.transcode_endpoint.clone(),
+            settings,
+            &futures_channel,
+        );
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+fn get_target_format(
+    definition: &WorkflowStepDefinition,
+) -> Result<TargetFormat, StepStartupError> {
+    let codec = match definition.parameters.get(CODEC) {
+        Some(Some(value)) => VideoCodec::from_name(value)
+            .ok_or_else(|| StepStartupError::InvalidCodec(value.clone()))?,
+
+        _ => return Err(StepStartupError::NoCodecSpecified),
+    };
+
+    let profiles = match definition.parameters.get(PROFILES) {
+        Some(Some(value)) => value
+            .split(',')
+            .map(|name| {
+                codec
+                    .profile_from_name(name)
+                    .ok_or_else(|| StepStartupError::InvalidProfile(name.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+
+        _ => Vec::new(),
+    };
+
+    Ok(TargetFormat {
+        codec,
+        profiles,
+        max_width: get_maximum(definition, MAX_WIDTH)?,
+        max_height: get_maximum(definition, MAX_HEIGHT)?,
+    })
+}
+
+fn get_maximum(
+    definition: &WorkflowStepDefinition,
+    parameter: &'static str,
+) -> Result<Option<u32>, StepStartupError> {
+    match definition.parameters.get(parameter) {
+        Some(Some(value)) => match value.trim().parse::<u32>() {
+            Ok(maximum) if maximum > 0 => Ok(Some(maximum)),
+            _ => Err(StepStartupError::InvalidMaximum(parameter, value.clone())),
+        },
+
+        _ => Ok(None),
+    }
+}
+
+impl ConditionalTranscodeDecider {
+    fn transcode(&mut self, reason: String) -> Decision<()> {
+        self.last_transcode_reason = Some(reason.clone());
+        Decision::Transcode { target: (), reason }
+    }
+}
+
+impl TranscodeDecider for ConditionalTranscodeDecider {
+    type Target = ();
+    type StreamState = Option<SourceParameters>;
+
+    const TRANSCODE_MODE: &'static str = TRANSCODE_MODE;
+    const TRANSCODING_STREAMS_DETAIL: &'static str = TRANSCODING_STREAMS_DETAIL;
+
+    fn new_stream(&self) -> Option<SourceParameters> {
+        None
+    }
+
+    /// Every video payload the source parameters can be read from is checked, so a stream whose
+    /// codec changes mid-stream is re-evaluated.
+    fn evaluate(
+        &mut self,
+        _stream_id: &StreamId,
+        source: &mut Option<SourceParameters>,
+        content: &MediaNotificationContent,
+        _is_pending: bool,
+    ) -> Option<Decision<()>> {
+        let new_source = match content {
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type,
+                data,
+                is_required_for_decoding,
+                ..
+            } => SourceParameters::read(payload_type, data, *is_required_for_decoding)?,
+
+            _ => return None,
+        };
+
+        if source.as_ref() == Some(&new_source) {
+            return None;
+        }
+
+        let decision = self.target.decide(&new_source);
+        *source = Some(new_source);
+
+        match decision {
+            source::Decision::Passthrough => Some(Decision::Passthrough),
+            source::Decision::Transcode { reason } => Some(self.transcode(reason)),
+        }
+    }
+
+    /// Streams whose source couldn't be read at all are transcoded
+    fn undecided(
+        &mut self,
+        _stream_id: &StreamId,
+        _source: &Option<SourceParameters>,
+    ) -> Decision<()> {
+        self.transcode("no video sequence header was received".to_string())
+    }
+
+    fn add_state_details(
+        &self,
+        _streams: &[StreamSummary<'_, Option<SourceParameters>, ()>],
+        details: &mut HashMap<String, String>,
+    ) {
+        if let Some(reason) = &self.last_transcode_reason {
+            details.insert(LAST_TRANSCODE_REASON_DETAIL.to_string(), reason.clone());
+        }
+    }
+}
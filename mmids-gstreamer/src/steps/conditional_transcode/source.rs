@@ -0,0 +1,359 @@
+use bytes::Bytes;
+use mmids_core::codecs::av1::Av1CodecConfigurationRecord;
+use mmids_core::codecs::h264::{self, AvcDecoderConfigurationRecord};
+use mmids_core::codecs::h265::HevcDecoderConfigurationRecord;
+use mmids_core::codecs::nal::split_annexb;
+use mmids_core::codecs::{
+    read_video_resolution, VideoResolution, VIDEO_CODEC_AV1, VIDEO_CODEC_H264_ANNEXB,
+    VIDEO_CODEC_H264_AVC, VIDEO_CODEC_H265_HVCC,
+};
+use std::fmt;
+
+/// Video codecs the source of a stream can be compared against
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VideoCodec {
+    H264,
+    H265,
+    Av1,
+}
+
+impl VideoCodec {
+    /// Parses the codec name used in step parameters
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "h264" | "avc" => Some(VideoCodec::H264),
+            "h265" | "hevc" => Some(VideoCodec::H265),
+            "av1" => Some(VideoCodec::Av1),
+            _ => None,
+        }
+    }
+
+    /// Parses a profile name (or number) of this codec into its `profile_idc` value
+    pub fn profile_from_name(&self, name: &str) -> Option<u8> {
+        let name = name.trim().to_lowercase();
+        if let Ok(number) = name.parse() {
+            return Some(number);
+        }
+
+        match (self, name.as_str()) {
+            (VideoCodec::H264, "baseline") => Some(66),
+            (VideoCodec::H264, "main") => Some(77),
+            (VideoCodec::H264, "extended") => Some(88),
+            (VideoCodec::H264, "high") => Some(100),
+            (VideoCodec::H264, "high10") => Some(110),
+            (VideoCodec::H265, "main") => Some(1),
+            (VideoCodec::H265, "main10") => Some(2),
+            (VideoCodec::Av1, "main") => Some(0),
+            (VideoCodec::Av1, "high") => Some(1),
+            (VideoCodec::Av1, "professional") => Some(2),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for VideoCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VideoCodec::H264 => write!(f, "h264"),
+            VideoCodec::H265 => write!(f, "h265"),
+            VideoCodec::Av1 => write!(f, "av1"),
+        }
+    }
+}
+
+/// The parameters of a stream's video, as read from its sequence header. Parameters that could not
+/// be read (e.g. due to an unknown codec) are `None`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceParameters {
+    pub codec: Option<VideoCodec>,
+    pub profile: Option<u8>,
+    pub resolution: Option<VideoResolution>,
+}
+
+impl SourceParameters {
+    /// Reads the source parameters from a video payload. `None` is returned if the payload does
+    /// not describe the video, i.e. it isn't a sequence header or doesn't contain a parameter set.
+    pub fn read(payload_type: &str, data: &Bytes, is_required_for_decoding: bool) -> Option<Self> {
+        let resolution = read_video_resolution(payload_type, data, is_required_for_decoding);
+        if payload_type == VIDEO_CODEC_H264_AVC.as_str() {
+            if !is_required_for_decoding {
+                return None;
+            }
+
+            let profile = AvcDecoderConfigurationRecord::parse(data)
+                .ok()
+                .map(|record| record.profile_indication);
+
+            Some(SourceParameters {
+                codec: Some(VideoCodec::H264),
+                profile,
+                resolution,
+            })
+        } else if payload_type == VIDEO_CODEC_H264_ANNEXB.as_str() {
+            // Annex-B streams carry their SPS in-band, and the profile is the byte after its header
+            let profile = split_annexb(data)
+                .iter()
+                .find(|nal_unit| h264::nal_unit_type(nal_unit) == Some(h264::NAL_UNIT_TYPE_SPS))
+                .map(|sps| sps.get(1).copied())?;
+
+            Some(SourceParameters {
+                codec: Some(VideoCodec::H264),
+                profile,
+                resolution,
+            })
+        } else if payload_type == VIDEO_CODEC_H265_HVCC.as_str() {
+            if !is_required_for_decoding {
+                return None;
+            }
+
+            // The profile is the low 5 bits of the first general configuration byte
+            let profile = HevcDecoderConfigurationRecord::parse(data)
+                .ok()
+                .and_then(|record| record.general_configuration.first().copied())
+                .map(|byte| byte & 0x1f);
+
+            Some(SourceParameters {
+                codec: Some(VideoCodec::H265),
+                profile,
+                resolution,
+            })
+        } else if payload_type == VIDEO_CODEC_AV1.as_str() {
+            if !is_required_for_decoding {
+                return None;
+            }
+
+            // The resolution is only in the sequence header OBU, which isn't parsed
+            let profile = Av1CodecConfigurationRecord::parse(data)
+                .ok()
+                .map(|record| record.seq_profile);
+
+            Some(SourceParameters {
+                codec: Some(VideoCodec::Av1),
+                profile,
+                resolution,
+            })
+        } else if is_required_for_decoding {
+            Some(SourceParameters::default())
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether a stream's video can be passed through, or must be transcoded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Passthrough,
+    Transcode { reason: String },
+}
+
+/// The format the video of each stream must be in to be passed through untouched
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TargetFormat {
+    pub codec: VideoCodec,
+    pub profiles: Vec<u8>,
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+}
+
+impl TargetFormat {
+    /// Decides if video with the specified source parameters matches the target format. Any
+    /// parameter that's constrained but couldn't be read from the source is treated as a mismatch,
+    /// since there's no guarantee the source is compatible.
+    pub fn decide(&self, source: &SourceParameters) -> Decision {
+        let codec = match source.codec {
+            Some(codec) => codec,
+            None => {
+                return Decision::Transcode {
+                    reason: "unknown codec".to_string(),
+                }
+            }
+        };
+
+        if codec != self.codec {
+            return Decision::Transcode {
+                reason: format!("codec {} is not {}", codec, self.codec),
+            };
+        }
+
+        if !self.profiles.is_empty() {
+            match source.profile {
+                Some(profile) if self.profiles.contains(&profile) => (),
+                Some(profile) => {
+                    return Decision::Transcode {
+                        reason: format!("profile {} is not allowed", profile),
+                    }
+                }
+
+                None => {
+                    return Decision::Transcode {
+                        reason: "unknown profile".to_string(),
+                    }
+                }
+            }
+        }
+
+        if self.max_width.is_some() || self.max_height.is_some() {
+            let resolution = match source.resolution {
+                Some(resolution) => resolution,
+                None => {
+                    return Decision::Transcode {
+                        reason: "unknown resolution".to_string(),
+                    }
+                }
+            };
+
+            let too_wide = self.max_width.is_some_and(|max| resolution.width > max);
+            let too_tall = self.max_height.is_some_and(|max| resolution.height > max);
+            if too_wide || too_tall {
+                return Decision::Transcode {
+                    reason: format!("resolution {} is too large", resolution),
+                };
+            }
+        }
+
+        Decision::Passthrough
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target() -> TargetFormat {
+        TargetFormat {
+            codec: VideoCodec::H264,
+            profiles: vec![66, 77],
+            max_width: Some(1280),
+            max_height: Some(720),
+        }
+    }
+
+    fn source(codec: Option<VideoCodec>, profile: u8, width: u32, height: u32) -> SourceParameters {
+        SourceParameters {
+            codec,
+            profile: Some(profile),
+            resolution: Some(VideoResolution { width, height }),
+        }
+    }
+
+    fn is_transcode(decision: Decision) -> bool {
+        matches!(decision, Decision::Transcode { .. })
+    }
+
+    #[test]
+    fn matching_source_is_passed_through() {
+        let decision = target().decide(&source(Some(VideoCodec::H264), 77, 1280, 720));
+
+        assert_eq!(decision, Decision::Passthrough);
+    }
+
+    #[test]
+    fn unconstrained_parameters_are_not_checked() {
+        let target = TargetFormat {
+            codec: VideoCodec::H264,
+            profiles: Vec::new(),
+            max_width: None,
+            max_height: None,
+        };
+
+        let source = SourceParameters {
+            codec: Some(VideoCodec::H264),
+            profile: None,
+            resolution: None,
+        };
+
+        assert_eq!(target.decide(&source), Decision::Passthrough);
+    }
+
+    #[test]
+    fn different_codec_is_transcoded() {
+        let decision = target().decide(&source(Some(VideoCodec::H265), 1, 1280, 720));
+
+        assert!(is_transcode(decision));
+    }
+
+    #[test]
+    fn unknown_codec_is_transcoded() {
+        let decision = target().decide(&SourceParameters::default());
+
+        assert!(is_transcode(decision));
+    }
+
+    #[test]
+    fn disallowed_profile_is_transcoded() {
+        let decision = target().decide(&source(Some(VideoCodec::H264), 100, 1280, 720));
+
+        assert!(is_transcode(decision));
+    }
+
+    #[test]
+    fn resolution_over_either_maximum_is_transcoded() {
+        let too_wide = target().decide(&source(Some(VideoCodec::H264), 77, 1920, 720));
+        let too_tall = target().decide(&source(Some(VideoCodec::H264), 77, 1280, 1080));
+
+        assert!(
+            is_transcode(too_wide),
+            "Expected wide source to be transcoded"
+        );
+        assert!(
+            is_transcode(too_tall),
+            "Expected tall source to be transcoded"
+        );
+    }
+
+    #[test]
+    fn unknown_resolution_is_transcoded_when_constrained() {
+        let source = SourceParameters {
+            codec: Some(VideoCodec::H264),
+            profile: Some(77),
+            resolution: None,
+        };
+
+        assert!(is_transcode(target().decide(&source)));
+    }
+
+    #[test]
+    fn profile_names_are_parsed() {
+        assert_eq!(VideoCodec::H264.profile_from_name("High"), Some(100));
+        assert_eq!(VideoCodec::H265.profile_from_name("main10"), Some(2));
+        assert_eq!(VideoCodec::H264.profile_from_name("77"), Some(77));
+        assert_eq!(VideoCodec::H264.profile_from_name("main10"), None);
+        assert_eq!(VideoCodec::Av1.profile_from_name("high"), Some(1));
+    }
+
+    #[test]
+    fn avc_sequence_header_is_read() {
+        let sps = Bytes::from_static(&[0x67, 0x4d, 0x00, 0x1f]);
+        let pps = Bytes::from_static(&[0x68, 0xce, 0x3c, 0x80]);
+        let record = AvcDecoderConfigurationRecord::from_parameter_sets(vec![sps], vec![pps], 4)
+            .expect("Failed to create record");
+
+        let source = SourceParameters::read(&VIDEO_CODEC_H264_AVC, &record.to_bytes(), true)
+            .expect("Expected source parameters");
+
+        assert_eq!(source.codec, Some(VideoCodec::H264), "Unexpected codec");
+        assert_eq!(source.profile, Some(77), "Unexpected profile");
+    }
+
+    #[test]
+    fn av1_sequence_header_is_read() {
+        let data = Bytes::from_static(&[0x81, 0x28, 0x0c, 0x00, 0x0a, 0x0b, 0x00]);
+
+        let source = SourceParameters::read(&VIDEO_CODEC_AV1, &data, true)
+            .expect("Expected source parameters");
+
+        assert_eq!(source.codec, Some(VideoCodec::Av1), "Unexpected codec");
+        assert_eq!(source.profile, Some(1), "Unexpected profile");
+    }
+
+    #[test]
+    fn non_sequence_header_payloads_are_not_read() {
+        let data = Bytes::from_static(&[0, 0, 0, 1, 0x65]);
+
+        assert_eq!(
+            SourceParameters::read(&VIDEO_CODEC_H264_AVC, &data, false),
+            None
+        );
+    }
+}
@@ -0,0 +1,387 @@
+//! The custom gstreamer workflow step runs each stream's media through a user provided gstreamer
+//! pipeline, allowing custom elements and plugins to be used without writing a new encoder or
+//! step. The pipeline is specified via the `pipeline` parameter, using the same syntax as
+//! `gst-launch-1.0`.
+//!
+//! Media goes into the pipeline through `appsrc` elements and comes back out through `appsink`
+//! elements, which must be named:
+//!
+//! * `video_src` and `video_sink` - for video
+//! * `audio_src` and `audio_sink` - for audio
+//!
+//! At least one of the sources must exist, and every source must have its matching sink. Sources
+//! are given the same caps as the incoming codec (e.g. `video/x-h264` with its `codec_data`), and
+//! media coming out of a sink is assumed to be in the same codec. Media types without a source in
+//! the pipeline are passed through untouched.
+//!
+//! The pipeline description is validated when the step is created, and a new instance of the
+//! pipeline is created for each stream.
+
+mod pipeline;
+
+use crate::steps::custom_gst::pipeline::{CustomPipeline, OutputMetadataKeys};
+use crate::GSTREAMER_INIT_RESULT;
+use futures::StreamExt;
+use gstreamer::bus::BusStream;
+use gstreamer::prelude::*;
+use gstreamer::MessageView;
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::metadata::{MetadataKey, MetadataValue};
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::{
+    FuturesChannelInnerResult, WorkflowStepFuturesChannel,
+};
+use mmids_core::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use mmids_core::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use mmids_core::{StreamId, VideoTimestamp};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::oneshot;
+use tracing::{error, info, warn};
+
+pub use pipeline::{
+    PipelineDescriptionError, AUDIO_SINK_NAME, AUDIO_SRC_NAME, VIDEO_SINK_NAME, VIDEO_SRC_NAME,
+};
+
+pub const PIPELINE: &str = "pipeline";
+
+/// Generates new instances of the custom gstreamer workflow step
+pub struct CustomGstStepGenerator {
+    metadata_keys: OutputMetadataKeys,
+}
+
+struct ActivePipeline {
+    pipeline: CustomPipeline,
+    _stop_bus_watch: oneshot::Sender<()>,
+}
+
+struct CustomGstStep {
+    description: Arc<String>,
+    metadata_keys: OutputMetadataKeys,
+    pipelines: HashMap<StreamId, ActivePipeline>,
+    status: StepStatus,
+}
+
+enum FutureResult {
+    PipelineFailed {
+        stream_id: StreamId,
+        message: String,
+    },
+
+    BusWatchStopped,
+    OutputChannelClosed(StreamId),
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(thiserror::Error, Debug)]
+enum StepStartupError {
+    #[error("No {} parameter specified", PIPELINE)]
+    NoPipelineSpecified,
+
+    #[error("Invalid {} parameter: {0}", PIPELINE)]
+    InvalidPipeline(#[from] PipelineDescriptionError),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
+}
+
+impl CustomGstStepGenerator {
+    pub fn new(
+        is_keyframe_metadata_key: MetadataKey,
+        pts_offset_metadata_key: MetadataKey,
+    ) -> Self {
+        CustomGstStepGenerator {
+            metadata_keys: OutputMetadataKeys {
+                is_keyframe: is_keyframe_metadata_key,
+                pts_offset: pts_offset_metadata_key,
+            },
+        }
+    }
+}
+
+impl StepGenerator for CustomGstStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
+        let description = match definition.parameters.get(PIPELINE) {
+            Some(Some(value)) => Arc::new(value.clone()),
+            _ => return Err(Box::new(StepStartupError::NoPipelineSpecified)),
+        };
+
+        if let Err(error) = CustomPipeline::validate(&description) {
+            return Err(Box::new(StepStartupError::InvalidPipeline(error)));
+        }
+
+        let step = CustomGstStep {
+            description,
+            metadata_keys: self.metadata_keys,
+            pipelines: HashMap::new(),
+            status: StepStatus::Active,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl CustomGstStep {
+    fn start_pipeline(
+        &mut self,
+        stream_id: &StreamId,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let (media_sender, media_receiver) = unbounded_channel();
+        let pipeline =
+            match CustomPipeline::new(&self.description, media_sender, self.metadata_keys) {
+                Ok(pipeline) => pipeline,
+                Err(error) => {
+                    error!(
+                        stream_id = %stream_id.0,
+                        "Failed to create custom pipeline: {:?}", error
+                    );
+
+                    self.status = StepStatus::Error {
+                        message: format!("Failed to create custom pipeline: {:?}", error),
+                    };
+
+                    return;
+                }
+            };
+
+        info!(stream_id = %stream_id.0, "Starting custom pipeline");
+
+        let media_stream_id = stream_id.clone();
+        let closed_stream_id = stream_id.clone();
+        futures_channel.send_on_unbounded_recv(
+            media_receiver,
+            move |content| {
+                FuturesChannelInnerResult::Media(MediaNotification {
+                    stream_id: media_stream_id.clone(),
+                    content,
+                    annotations: Default::default(),
+                })
+            },
+            move || {
+                FuturesChannelInnerResult::Generic(Box::new(FutureResult::OutputChannelClosed(
+                    closed_stream_id,
+                )))
+            },
+        );
+
+        let (stop_sender, stop_receiver) = oneshot::channel();
+        if let Some(bus) = pipeline.pipeline().bus() {
+            let bus_stream_id = stream_id.clone();
+            let messages = bus.stream();
+            futures_channel.send_on_generic_future_completion(async move {
+                tokio::select! {
+                    message = wait_for_pipeline_end(messages) => FutureResult::PipelineFailed {
+                        stream_id: bus_stream_id,
+                        message,
+                    },
+
+                    _ = stop_receiver => FutureResult::BusWatchStopped,
+                }
+            });
+        }
+
+        self.pipelines.insert(
+            stream_id.clone(),
+            ActivePipeline {
+                pipeline,
+                _stop_bus_watch: stop_sender,
+            },
+        );
+    }
+
+    fn handle_media(
+        &mut self,
+        media: MediaNotification,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { .. } => {
+                // A re-announced stream needs a fresh pipeline
+                self.pipelines.remove(&media.stream_id);
+                self.start_pipeline(&media.stream_id, futures_channel);
+                outputs.media.push(media);
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                if self.pipelines.remove(&media.stream_id).is_some() {
+                    info!(stream_id = %media.stream_id.0, "Stopping custom pipeline");
+                }
+
+                outputs.media.push(media);
+            }
+
+            MediaNotificationContent::Metadata { .. } => outputs.media.push(media),
+
+            MediaNotificationContent::MediaPayload {
+                media_type,
+                payload_type,
+                timestamp,
+                metadata,
+                data,
+                is_required_for_decoding,
+            } => {
+                let active = match self.pipelines.get(&media.stream_id) {
+                    Some(active) => active,
+                    None => {
+                        outputs.media.push(media);
+                        return;
+                    }
+                };
+
+                let result = match media_type {
+                    MediaType::Video if active.pipeline.handles_video() => {
+                        let mut pts_offset = 0;
+                        let mut is_keyframe = false;
+                        for entry in metadata.iter() {
+                            match entry.value() {
+                                MetadataValue::I32(value)
+                                    if entry.key() == self.metadata_keys.pts_offset =>
+                                {
+                                    pts_offset = value;
+                                }
+
+                                MetadataValue::Bool(value)
+                                    if entry.key() == self.metadata_keys.is_keyframe =>
+                                {
+                                    is_keyframe = value;
+                                }
+
+                                _ => (),
+                            }
+                        }
+
+                        let pts =
+                            Duration::from_millis(timestamp.as_millis() as u64 + pts_offset as u64);
+
+                        active.pipeline.push_video(
+                            payload_type.clone(),
+                            data.clone(),
+                            VideoTimestamp::from_durations(*timestamp, pts),
+                            is_keyframe,
+                            *is_required_for_decoding,
+                        )
+                    }
+
+                    MediaType::Audio if active.pipeline.handles_audio() => {
+                        active.pipeline.push_audio(
+                            payload_type.clone(),
+                            data.clone(),
+                            *timestamp,
+                            *is_required_for_decoding,
+                        )
+                    }
+
+                    _ => {
+                        // Not handled by the pipeline, so just pass it through
+                        outputs.media.push(media);
+                        return;
+                    }
+                };
+
+                if let Err(error) = result {
+                    error!(
+                        stream_id = %media.stream_id.0,
+                        "Failed to push media into the custom pipeline: {:?}", error
+                    );
+
+                    self.status = StepStatus::Error {
+                        message: format!("Failed to push media into custom pipeline: {:?}", error),
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl WorkflowStep for CustomGstStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            let future_result = match notification.downcast::<FutureResult>() {
+                Ok(result) => *result,
+                Err(_) => {
+                    error!("Received future result that could not be casted to the internal future result type");
+                    continue;
+                }
+            };
+
+            match future_result {
+                FutureResult::BusWatchStopped => (),
+                FutureResult::PipelineFailed { stream_id, message } => {
+                    if self.pipelines.remove(&stream_id).is_some() {
+                        error!(stream_id = %stream_id.0, "Custom pipeline stopped: {}", message);
+                        self.status = StepStatus::Error { message };
+                    }
+                }
+
+                FutureResult::OutputChannelClosed(stream_id) => {
+                    if self.pipelines.contains_key(&stream_id) {
+                        warn!(
+                            stream_id = %stream_id.0,
+                            "Custom pipeline's output channel closed unexpectedly"
+                        );
+                    }
+                }
+            }
+        }
+
+        if self.status != StepStatus::Active {
+            self.pipelines.clear();
+            return self.status.clone();
+        }
+
+        for media in inputs.media.drain(..) {
+            self.handle_media(media, outputs, &futures_channel);
+        }
+
+        self.status.clone()
+    }
+
+    fn get_active_pipeline_count(&self) -> usize {
+        self.pipelines.len()
+    }
+}
+
+/// Waits for the pipeline to report an error or the end of its stream, and returns why it ended
+async fn wait_for_pipeline_end(mut messages: BusStream) -> String {
+    while let Some(message) = messages.next().await {
+        match message.view() {
+            MessageView::Error(error) => {
+                return format!(
+                    "Gstreamer error from element '{}': {}",
+                    error
+                        .src()
+                        .map(|s| s.path_string().to_string())
+                        .unwrap_or_else(|| "<none>".to_string()),
+                    error.error(),
+                );
+            }
+
+            MessageView::Eos(..) => return "Pipeline reached the end of the stream".to_string(),
+            _ => (),
+        }
+    }
+
+    "Pipeline's bus closed".to_string()
+}
@@ -0,0 +1,607 @@
+//! Wraps a user provided gstreamer pipeline, pushing media into its `appsrc` elements and sending
+//! everything that comes out of its `appsink` elements back out as media notifications.
+
+use crate::encoders::SampleResult;
+use crate::utils::{
+    set_gst_buffer, set_source_audio_sequence_header, set_source_video_sequence_header,
+    GstElementError,
+};
+use anyhow::{anyhow, Context, Result};
+use bytes::{Bytes, BytesMut};
+use gstreamer::prelude::*;
+use gstreamer::{
+    Buffer, BufferFlags, FlowError, FlowSuccess, ParseContext, ParseFlags, Pipeline, Sample, State,
+};
+use gstreamer_app::{AppSink, AppSinkCallbacks, AppSrc};
+use mmids_core::workflows::metadata::{
+    MediaPayloadMetadataCollection, MetadataEntry, MetadataKey, MetadataValue,
+};
+use mmids_core::workflows::{MediaNotificationContent, MediaType};
+use mmids_core::VideoTimestamp;
+use std::iter;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::error;
+
+/// Name of the `appsrc` element video is pushed into
+pub const VIDEO_SRC_NAME: &str = "video_src";
+
+/// Name of the `appsink` element processed video is read from
+pub const VIDEO_SINK_NAME: &str = "video_sink";
+
+/// Name of the `appsrc` element audio is pushed into
+pub const AUDIO_SRC_NAME: &str = "audio_src";
+
+/// Name of the `appsink` element processed audio is read from
+pub const AUDIO_SINK_NAME: &str = "audio_sink";
+
+/// Problems with a custom pipeline description that prevent it from being used
+#[derive(thiserror::Error, Debug)]
+pub enum PipelineDescriptionError {
+    #[error("The pipeline description could not be parsed: {0}")]
+    ParseFailed(String),
+
+    #[error("The pipeline uses an unavailable element: {0}")]
+    MissingElement(#[from] GstElementError),
+
+    #[error("The pipeline description must contain more than a single element")]
+    NotAPipeline,
+
+    #[error(
+        "The pipeline must contain an appsrc named '{}' or '{}'",
+        VIDEO_SRC_NAME,
+        AUDIO_SRC_NAME
+    )]
+    NoSources,
+
+    #[error("The element named '{0}' must be an {1} element")]
+    WrongElementType(&'static str, &'static str),
+
+    #[error("The pipeline contains '{0}' but no appsink named '{1}'")]
+    MissingSink(&'static str, &'static str),
+}
+
+/// The metadata keys attached to media coming out of the pipeline
+#[derive(Clone, Copy)]
+pub struct OutputMetadataKeys {
+    pub is_keyframe: MetadataKey,
+    pub pts_offset: MetadataKey,
+}
+
+struct ParsedPipeline {
+    pipeline: Pipeline,
+    video: Option<(AppSrc, AppSink)>,
+    audio: Option<(AppSrc, AppSink)>,
+}
+
+/// The codec of the media pushed into the pipeline, which is assumed to also be the codec of the
+/// media coming out of it.
+struct CodecInfo {
+    payload_type: Arc<String>,
+    sequence_header: Bytes,
+}
+
+/// A running instance of a custom pipeline for a single stream
+pub struct CustomPipeline {
+    pipeline: Pipeline,
+    video_source: Option<AppSrc>,
+    audio_source: Option<AppSrc>,
+    video_codec: Arc<Mutex<Option<CodecInfo>>>,
+    audio_codec: Arc<Mutex<Option<CodecInfo>>>,
+}
+
+impl CustomPipeline {
+    /// Verifies that the pipeline description can be parsed and contains the required elements
+    pub fn validate(description: &str) -> Result<(), PipelineDescriptionError> {
+        parse(description).map(|_| ())
+    }
+
+    /// Creates and starts a new instance of the described pipeline
+    pub fn new(
+        description: &str,
+        media_sender: UnboundedSender<MediaNotificationContent>,
+        metadata_keys: OutputMetadataKeys,
+    ) -> Result<CustomPipeline> {
+        let parsed = parse(description)?;
+        let video_codec = Arc::new(Mutex::new(None));
+        let audio_codec = Arc::new(Mutex::new(None));
+
+        let video_source = parsed.video.map(|(source, sink)| {
+            set_sink_callbacks(
+                &sink,
+                MediaType::Video,
+                video_codec.clone(),
+                media_sender.clone(),
+                metadata_keys,
+            );
+
+            source
+        });
+
+        let audio_source = parsed.audio.map(|(source, sink)| {
+            set_sink_callbacks(
+                &sink,
+                MediaType::Audio,
+                audio_codec.clone(),
+                media_sender.clone(),
+                metadata_keys,
+            );
+
+            source
+        });
+
+        parsed
+            .pipeline
+            .set_state(State::Playing)
+            .with_context(|| "Failed to set custom pipeline to playing")?;
+
+        Ok(CustomPipeline {
+            pipeline: parsed.pipeline,
+            video_source,
+            audio_source,
+            video_codec,
+            audio_codec,
+        })
+    }
+
+    /// The pipeline this wraps, mostly so its bus can be watched
+    pub fn pipeline(&self) -> &Pipeline {
+        &self.pipeline
+    }
+
+    /// True if the pipeline processes video
+    pub fn handles_video(&self) -> bool {
+        self.video_source.is_some()
+    }
+
+    /// True if the pipeline processes audio
+    pub fn handles_audio(&self) -> bool {
+        self.audio_source.is_some()
+    }
+
+    /// Pushes a video frame into the pipeline
+    pub fn push_video(
+        &self,
+        payload_type: Arc<String>,
+        data: Bytes,
+        timestamp: VideoTimestamp,
+        is_keyframe: bool,
+        is_sequence_header: bool,
+    ) -> Result<()> {
+        let source = self
+            .video_source
+            .as_ref()
+            .with_context(|| "Pipeline does not process video")?;
+
+        let mut buffer = set_gst_buffer(data.clone(), Some(timestamp.dts()), Some(timestamp.pts()))
+            .with_context(|| "Failed to set buffer")?;
+
+        if is_sequence_header {
+            *self.video_codec.lock().unwrap() = Some(CodecInfo {
+                payload_type: payload_type.clone(),
+                sequence_header: data,
+            });
+
+            set_source_video_sequence_header(source, payload_type, buffer)
+                .with_context(|| "Failed to set video sequence header for custom pipeline")?;
+        } else {
+            if !is_keyframe {
+                buffer.make_mut().set_flags(BufferFlags::DELTA_UNIT);
+            }
+
+            source
+                .push_buffer(buffer)
+                .with_context(|| "Failed to push the buffer into the video source")?;
+        }
+
+        Ok(())
+    }
+
+    /// Pushes an audio frame into the pipeline
+    pub fn push_audio(
+        &self,
+        payload_type: Arc<String>,
+        data: Bytes,
+        timestamp: Duration,
+        is_sequence_header: bool,
+    ) -> Result<()> {
+        let source = self
+            .audio_source
+            .as_ref()
+            .with_context(|| "Pipeline does not process audio")?;
+
+        let buffer = set_gst_buffer(data.clone(), Some(timestamp), None)
+            .with_context(|| "Failed to set buffer")?;
+
+        if is_sequence_header {
+            *self.audio_codec.lock().unwrap() = Some(CodecInfo {
+                payload_type: payload_type.clone(),
+                sequence_header: data,
+            });
+
+            set_source_audio_sequence_header(source, payload_type, buffer)
+                .with_context(|| "Failed to set audio sequence header for custom pipeline")?;
+        } else {
+            source
+                .push_buffer(buffer)
+                .with_context(|| "Failed to push the buffer into the audio source")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for CustomPipeline {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(State::Null);
+    }
+}
+
+fn parse(description: &str) -> Result<ParsedPipeline, PipelineDescriptionError> {
+    let mut context = ParseContext::new();
+    let pipeline =
+        gstreamer::parse_launch_full(description, Some(&mut context), ParseFlags::empty())
+            .map_err(|error| match context.missing_elements().first() {
+                Some(element) => GstElementError::missing(element).into(),
+                None => PipelineDescriptionError::ParseFailed(error.to_string()),
+            })?
+            .dynamic_cast::<Pipeline>()
+            .map_err(|_| PipelineDescriptionError::NotAPipeline)?;
+
+    let video = get_endpoints(&pipeline, VIDEO_SRC_NAME, VIDEO_SINK_NAME)?;
+    let audio = get_endpoints(&pipeline, AUDIO_SRC_NAME, AUDIO_SINK_NAME)?;
+    if video.is_none() && audio.is_none() {
+        return Err(PipelineDescriptionError::NoSources);
+    }
+
+    Ok(ParsedPipeline {
+        pipeline,
+        video,
+        audio,
+    })
+}
+
+fn get_endpoints(
+    pipeline: &Pipeline,
+    source_name: &'static str,
+    sink_name: &'static str,
+) -> Result<Option<(AppSrc, AppSink)>, PipelineDescriptionError> {
+    let source = match pipeline.by_name(source_name) {
+        Some(source) => source
+            .dynamic_cast::<AppSrc>()
+            .map_err(|_| PipelineDescriptionError::WrongElementType(source_name, "appsrc"))?,
+
+        None => return Ok(None),
+    };
+
+    let sink = pipeline
+        .by_name(sink_name)
+        .ok_or(PipelineDescriptionError::MissingSink(
+            source_name,
+            sink_name,
+        ))?
+        .dynamic_cast::<AppSink>()
+        .map_err(|_| PipelineDescriptionError::WrongElementType(sink_name, "appsink"))?;
+
+    Ok(Some((source, sink)))
+}
+
+fn set_sink_callbacks(
+    sink: &AppSink,
+    media_type: MediaType,
+    codec: Arc<Mutex<Option<CodecInfo>>>,
+    media_sender: UnboundedSender<MediaNotificationContent>,
+    metadata_keys: OutputMetadataKeys,
+) {
+    let mut sent_sequence_header: Option<Bytes> = None;
+    let mut metadata_buffer = BytesMut::new();
+    sink.set_callbacks(
+        AppSinkCallbacks::builder()
+            .new_sample(move |sink| {
+                let result = sample_received(
+                    sink,
+                    media_type,
+                    &codec,
+                    &mut sent_sequence_header,
+                    &media_sender,
+                    metadata_keys,
+                    &mut metadata_buffer,
+                );
+
+                match result {
+                    Ok(_) => Ok(FlowSuccess::Ok),
+                    Err(error) => {
+                        error!(
+                            "Custom pipeline {:?} sink failed to handle a sample: {:?}",
+                            media_type, error
+                        );
+
+                        Err(FlowError::Error)
+                    }
+                }
+            })
+            .build(),
+    );
+}
+
+fn sample_received(
+    sink: &AppSink,
+    media_type: MediaType,
+    codec: &Mutex<Option<CodecInfo>>,
+    sent_sequence_header: &mut Option<Bytes>,
+    media_sender: &UnboundedSender<MediaNotificationContent>,
+    metadata_keys: OutputMetadataKeys,
+    metadata_buffer: &mut BytesMut,
+) -> Result<()> {
+    let sample = sink.pull_sample().with_context(|| "Sink had no sample")?;
+    let (payload_type, sequence_header) = match &*codec.lock().unwrap() {
+        Some(info) => (
+            info.payload_type.clone(),
+            get_codec_data(&sample).unwrap_or_else(|| info.sequence_header.clone()),
+        ),
+
+        None => {
+            return Err(anyhow!(
+                "Received a sample before a sequence header was set"
+            ))
+        }
+    };
+
+    // Pipelines may change the codec configuration (e.g. by re-encoding), so a new sequence
+    // header needs to be sent out any time it changes.
+    if sent_sequence_header.as_ref() != Some(&sequence_header) {
+        let _ = media_sender.send(MediaNotificationContent::MediaPayload {
+            media_type,
+            payload_type: payload_type.clone(),
+            timestamp: Duration::new(0, 0),
+            is_required_for_decoding: true,
+            data: sequence_header.clone(),
+            metadata: MediaPayloadMetadataCollection::new(iter::empty(), metadata_buffer),
+        });
+
+        *sent_sequence_header = Some(sequence_header);
+    }
+
+    let is_keyframe = sample
+        .buffer()
+        .map(|buffer| !buffer.flags().contains(BufferFlags::DELTA_UNIT))
+        .unwrap_or_default();
+
+    let result = SampleResult::from_sample(&sample)?;
+    let (timestamp, metadata) = match media_type {
+        MediaType::Video => {
+            let timestamp = result.to_video_timestamp();
+            let pts_offset = MetadataEntry::new(
+                metadata_keys.pts_offset,
+                MetadataValue::I32(timestamp.pts_offset()),
+                metadata_buffer,
+            )
+            .unwrap(); // Can only panic if the key is not for an i32
+
+            let keyframe = MetadataEntry::new(
+                metadata_keys.is_keyframe,
+                MetadataValue::Bool(is_keyframe),
+                metadata_buffer,
+            )
+            .unwrap(); // Can only panic if the key is not for a bool
+
+            let metadata = MediaPayloadMetadataCollection::new(
+                [pts_offset, keyframe].into_iter(),
+                metadata_buffer,
+            );
+
+            (timestamp.dts(), metadata)
+        }
+
+        _ => (
+            result.dts().unwrap_or_default(),
+            MediaPayloadMetadataCollection::new(iter::empty(), metadata_buffer),
+        ),
+    };
+
+    let _ = media_sender.send(MediaNotificationContent::MediaPayload {
+        media_type,
+        payload_type,
+        timestamp,
+        is_required_for_decoding: false,
+        data: result.content().clone(),
+        metadata,
+    });
+
+    Ok(())
+}
+
+fn get_codec_data(sample: &Sample) -> Option<Bytes> {
+    let caps = sample.caps()?;
+    let structure = caps.structure(0)?;
+    let codec_data = structure.get::<Buffer>("codec_data").ok()?;
+    let map = codec_data.map_readable().ok()?;
+
+    Some(Bytes::copy_from_slice(map.as_slice()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GSTREAMER_INIT_RESULT;
+    use mmids_core::codecs::VIDEO_CODEC_H264_AVC;
+    use mmids_core::workflows::metadata::common_metadata::{
+        get_is_keyframe_metadata_key, get_pts_offset_metadata_key,
+    };
+    use mmids_core::workflows::metadata::MetadataKeyMap;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    fn metadata_keys() -> OutputMetadataKeys {
+        let mut map = MetadataKeyMap::new();
+        OutputMetadataKeys {
+            is_keyframe: get_is_keyframe_metadata_key(&mut map),
+            pts_offset: get_pts_offset_metadata_key(&mut map),
+        }
+    }
+
+    fn receive(
+        receiver: &mut UnboundedReceiver<MediaNotificationContent>,
+    ) -> MediaNotificationContent {
+        for _ in 0..500 {
+            if let Ok(media) = receiver.try_recv() {
+                return media;
+            }
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        panic!("No media received from the custom pipeline");
+    }
+
+    #[test]
+    fn invalid_descriptions_are_rejected() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        assert!(CustomPipeline::validate("appsrc name=video_src ! notarealelement").is_err());
+        assert!(CustomPipeline::validate("videotestsrc ! appsink name=video_sink").is_err());
+        assert!(CustomPipeline::validate("appsrc name=video_src ! identity ! fakesink").is_err());
+        assert!(CustomPipeline::validate(
+            "identity name=video_src ! identity ! appsink name=video_sink"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn missing_element_is_named_in_error() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let result = CustomPipeline::validate(
+            "appsrc name=video_src ! mmidsbogusenc ! appsink name=video_sink",
+        );
+
+        match result {
+            Err(PipelineDescriptionError::MissingElement(error)) => assert_eq!(
+                error,
+                GstElementError::MissingUnknownElement("mmidsbogusenc".to_string())
+            ),
+
+            other => panic!("Unexpected validation result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn identity_pipeline_passes_video_through_unchanged() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let keys = metadata_keys();
+        let (sender, mut receiver) = unbounded_channel();
+        let description = "appsrc name=video_src ! identity ! appsink name=video_sink";
+        let pipeline = CustomPipeline::new(description, sender, keys).unwrap();
+
+        assert!(
+            pipeline.handles_video(),
+            "Expected pipeline to handle video"
+        );
+        assert!(
+            !pipeline.handles_audio(),
+            "Expected pipeline to not handle audio"
+        );
+
+        let frames = [
+            (vec![4u8, 5, 6], 0, 0, true),
+            (vec![7, 8, 9], 33, 66, false),
+            (vec![10, 11], 66, 99, false),
+        ];
+
+        pipeline
+            .push_video(
+                VIDEO_CODEC_H264_AVC.clone(),
+                Bytes::from_static(&[1, 2, 3]),
+                VideoTimestamp::from_zero(),
+                false,
+                true,
+            )
+            .unwrap();
+
+        for (data, dts, pts, is_keyframe) in &frames {
+            let timestamp = VideoTimestamp::from_durations(
+                Duration::from_millis(*dts),
+                Duration::from_millis(*pts),
+            );
+
+            pipeline
+                .push_video(
+                    VIDEO_CODEC_H264_AVC.clone(),
+                    Bytes::from(data.clone()),
+                    timestamp,
+                    *is_keyframe,
+                    false,
+                )
+                .unwrap();
+        }
+
+        match receive(&mut receiver) {
+            MediaNotificationContent::MediaPayload {
+                data,
+                is_required_for_decoding,
+                ..
+            } => {
+                assert!(is_required_for_decoding, "Expected a sequence header");
+                assert_eq!(
+                    data,
+                    Bytes::from_static(&[1, 2, 3]),
+                    "Unexpected sequence header"
+                );
+            }
+
+            media => panic!("Unexpected media received: {:?}", media),
+        }
+
+        for (expected_data, dts, pts, expected_keyframe) in frames {
+            match receive(&mut receiver) {
+                MediaNotificationContent::MediaPayload {
+                    media_type,
+                    payload_type,
+                    data,
+                    timestamp,
+                    metadata,
+                    is_required_for_decoding,
+                } => {
+                    assert_eq!(media_type, MediaType::Video, "Unexpected media type");
+                    assert_eq!(
+                        payload_type, *VIDEO_CODEC_H264_AVC,
+                        "Unexpected payload type"
+                    );
+                    assert_eq!(data, Bytes::from(expected_data), "Unexpected data");
+                    assert_eq!(timestamp, Duration::from_millis(dts), "Unexpected dts");
+                    assert!(!is_required_for_decoding, "Unexpected sequence header");
+
+                    let mut pts_offset = None;
+                    let mut is_keyframe = None;
+                    for entry in metadata.iter() {
+                        match entry.value() {
+                            MetadataValue::I32(value) if entry.key() == keys.pts_offset => {
+                                pts_offset = Some(value)
+                            }
+
+                            MetadataValue::Bool(value) if entry.key() == keys.is_keyframe => {
+                                is_keyframe = Some(value)
+                            }
+
+                            _ => (),
+                        }
+                    }
+
+                    assert_eq!(
+                        pts_offset,
+                        Some((pts - dts) as i32),
+                        "Unexpected pts offset"
+                    );
+                    assert_eq!(
+                        is_keyframe,
+                        Some(expected_keyframe),
+                        "Unexpected keyframe flag"
+                    );
+                }
+
+                media => panic!("Unexpected media received: {:?}", media),
+            }
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Gstreamer elements that deinterlace decoded video with gstreamer's `deinterlace` element.
+
+use crate::utils::create_gst_element;
+use anyhow::{Context, Result};
+use gstreamer::prelude::*;
+use gstreamer::{CapsRef, Element, EventView, PadProbeData, PadProbeReturn, PadProbeType};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Gstreamer elements deinterlacing is built from
+pub const REQUIRED_ELEMENTS: &[&str] = &["videoconvert", "deinterlace"];
+
+/// Deinterlacing methods supported by the `deinterlace` element
+pub const METHODS: &[&str] = &[
+    "tomsmocomp",
+    "greedyh",
+    "greedyl",
+    "vfir",
+    "linear",
+    "linearblend",
+    "scalerbob",
+    "weave",
+    "weavetff",
+    "weavebff",
+    "yadif",
+];
+
+/// Which field of each frame is first in time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldOrder {
+    /// Use the field order the source video's caps specify
+    Auto,
+    TopFieldFirst,
+    BottomFieldFirst,
+}
+
+/// How video is deinterlaced
+#[derive(Clone, Debug)]
+pub struct DeinterlaceSettings {
+    /// The `deinterlace` element's method. The element's default is used when not specified.
+    pub method: Option<String>,
+    pub field_order: FieldOrder,
+}
+
+/// How the decoded video's frames are laid out, as described by its caps
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceFormat {
+    /// The caps' `interlace-mode`, which is `progressive` when the caps don't specify one
+    pub interlace_mode: String,
+
+    /// The caps' `field-order`, if specified
+    pub field_order: Option<String>,
+}
+
+/// Creates the elements that deinterlace decoded video. One frame is output for each input
+/// frame, so the video's timing is unchanged. Video whose caps mark it as progressive is passed
+/// through the `deinterlace` element untouched.
+///
+/// The format of the decoded video is sent out whenever it's detected.
+pub fn create_elements(
+    settings: &DeinterlaceSettings,
+    format_sender: &UnboundedSender<SourceFormat>,
+) -> Result<Vec<Element>> {
+    let convert = create_gst_element("videoconvert")?;
+    let deinterlace = create_gst_element("deinterlace")?;
+
+    // Only deinterlace video whose caps say it's interlaced, and only output one field per
+    // frame so the frame rate isn't doubled
+    deinterlace.set_property_from_str("mode", "auto");
+    deinterlace.set_property_from_str("fields", "top");
+    if let Some(method) = &settings.method {
+        deinterlace.set_property_from_str("method", method);
+    }
+
+    let field_order = match settings.field_order {
+        FieldOrder::Auto => "auto",
+        FieldOrder::TopFieldFirst => "tff",
+        FieldOrder::BottomFieldFirst => "bff",
+    };
+
+    deinterlace.set_property_from_str("tff", field_order);
+
+    // Report how the decoded video is laid out whenever its caps are set
+    let sink_pad = deinterlace
+        .static_pad("sink")
+        .with_context(|| "Failed to get the deinterlace element's sink pad")?;
+
+    let format_sender = format_sender.clone();
+    sink_pad.add_probe(PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+        if let Some(PadProbeData::Event(event)) = &info.data {
+            if let EventView::Caps(caps) = event.view() {
+                if let Some(format) = SourceFormat::from_caps(caps.caps()) {
+                    let _ = format_sender.send(format);
+                }
+            }
+        }
+
+        PadProbeReturn::Ok
+    });
+
+    Ok(vec![convert, deinterlace])
+}
+
+impl SourceFormat {
+    fn from_caps(caps: &CapsRef) -> Option<SourceFormat> {
+        let structure = caps.structure(0)?;
+        if !structure.name().starts_with("video/") {
+            return None;
+        }
+
+        let interlace_mode = structure
+            .get::<&str>("interlace-mode")
+            .unwrap_or("progressive")
+            .to_string();
+
+        let field_order = structure
+            .get::<&str>("field-order")
+            .ok()
+            .map(|order| order.to_string());
+
+        Some(SourceFormat {
+            interlace_mode,
+            field_order,
+        })
+    }
+
+    /// Whether the video's frames are made up of separate fields
+    pub fn is_interlaced(&self) -> bool {
+        self.interlace_mode != "progressive"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GSTREAMER_INIT_RESULT;
+    use gstreamer::glib;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    fn get_enum_property(element: &Element, name: &str) -> String {
+        let value = element.property_value(name);
+        let class = glib::EnumClass::new(value.type_()).expect("Property is not an enum");
+        let number = value
+            .transform::<i32>()
+            .expect("Enum could not be converted to an i32")
+            .get::<i32>()
+            .unwrap();
+
+        class
+            .value(number)
+            .expect("Unknown enum value")
+            .nick()
+            .to_string()
+    }
+
+    #[test]
+    fn deinterlace_element_configured_from_settings() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let (sender, _receiver) = unbounded_channel();
+        let settings = DeinterlaceSettings {
+            method: Some("yadif".to_string()),
+            field_order: FieldOrder::BottomFieldFirst,
+        };
+
+        let elements = create_elements(&settings, &sender).unwrap();
+        let element = elements
+            .into_iter()
+            .find(|element| {
+                element
+                    .factory()
+                    .map(|factory| factory.name() == "deinterlace")
+                    .unwrap_or_default()
+            })
+            .expect("No deinterlace element was created");
+
+        assert_eq!(
+            get_enum_property(&element, "method"),
+            "yadif",
+            "Unexpected method"
+        );
+        assert_eq!(
+            get_enum_property(&element, "tff"),
+            "bff",
+            "Unexpected field order"
+        );
+        assert_eq!(
+            get_enum_property(&element, "mode"),
+            "auto",
+            "Unexpected mode"
+        );
+        assert_eq!(
+            get_enum_property(&element, "fields"),
+            "top",
+            "Unexpected fields"
+        );
+    }
+}
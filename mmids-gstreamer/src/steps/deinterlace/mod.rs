@@ -0,0 +1,237 @@
+//! The deinterlace workflow step converts interlaced video into progressive video, for targets
+//! (such as browsers) that don't handle interlaced video well. The video is decoded, deinterlaced
+//! with gstreamer's `deinterlace` element and re-encoded, while audio is passed through untouched.
+//!
+//! Whether video is interlaced is detected from the decoded video's caps, and video that is
+//! already progressive is not deinterlaced (though it is still re-encoded). One frame is output
+//! for each input frame, so the video's frame rate and timestamps are unchanged.
+//!
+//! The optional `method` parameter specifies the `deinterlace` element's deinterlacing method
+//! (e.g. `linear`, `greedyh`, or `yadif`). The optional `field_order` parameter can be `auto`
+//! (the default), `tff` (top field first), or `bff` (bottom field first), and is used to override
+//! the field order specified by the video's caps. The deinterlaced video is encoded the same way
+//! as the basic transcode step, with the `video` parameter naming the encoder to use and `video_`
+//! prefixed parameters being passed to it.
+//!
+//! The field order detected for each stream's video is reported through the step's state details.
+
+mod deinterlacer;
+
+use crate::encoders::EncoderFactory;
+use crate::steps::deinterlace::deinterlacer::{
+    DeinterlaceSettings, FieldOrder, SourceFormat, METHODS,
+};
+use crate::steps::filter_encode::{
+    ensure_filter_elements_available, per_stream_detail, EncoderSettings, EncoderSettingsError,
+    FilterEncodeStep, MediaFilter, StreamSummary,
+};
+use crate::utils::GstElementError;
+use crate::GSTREAMER_INIT_RESULT;
+use anyhow::Result;
+use gstreamer::Element;
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::metadata::MetadataKey;
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{StepCreationResult, StepStatus};
+use mmids_core::workflows::{MediaNotificationContent, MediaType};
+use mmids_core::StreamId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::info;
+
+pub const METHOD: &str = "method";
+pub const FIELD_ORDER: &str = "field_order";
+
+const INPUT_FIELD_ORDER_DETAIL: &str = "input_field_order";
+const METHOD_DETAIL: &str = "method";
+
+/// Generates new instances of the deinterlace workflow step
+pub struct DeinterlaceStepGenerator {
+    encoder_factory: Arc<EncoderFactory>,
+    pts_offset_metadata_key: MetadataKey,
+}
+
+/// Deinterlaces each stream's video, while tracking how the decoded video is laid out
+struct DeinterlaceFilter {
+    settings: DeinterlaceSettings,
+}
+
+struct DeinterlaceStream {
+    stream_name: Arc<String>,
+    source_format: Option<SourceFormat>,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum StepStartupError {
+    #[error("Invalid {} value of '{0}'.  It must be one of: {}", METHOD, METHODS.join(", "))]
+    InvalidMethod(String),
+
+    #[error(
+        "Invalid {} value of '{0}'.  It must be 'auto', 'tff', or 'bff'",
+        FIELD_ORDER
+    )]
+    InvalidFieldOrder(String),
+
+    #[error(transparent)]
+    InvalidEncoderSettings(#[from] EncoderSettingsError),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
+
+    #[error("Video cannot be deinterlaced: {0}")]
+    MissingElement(#[from] GstElementError),
+}
+
+impl DeinterlaceStepGenerator {
+    /// Creates the generator, with the encoder factory deinterlaced video is encoded with
+    pub fn new(encoder_factory: Arc<EncoderFactory>, pts_offset_metadata_key: MetadataKey) -> Self {
+        DeinterlaceStepGenerator {
+            encoder_factory,
+            pts_offset_metadata_key,
+        }
+    }
+}
+
+impl StepGenerator for DeinterlaceStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let method = match definition.parameters.get(METHOD) {
+            Some(Some(value)) => {
+                let method = value.trim().to_lowercase();
+                if !METHODS.contains(&method.as_str()) {
+                    return Err(Box::new(StepStartupError::InvalidMethod(value.clone())));
+                }
+
+                Some(method)
+            }
+
+            _ => None,
+        };
+
+        let field_order = match definition.parameters.get(FIELD_ORDER) {
+            Some(Some(value)) => match value.trim().to_lowercase().as_str() {
+                "auto" => FieldOrder::Auto,
+                "tff" => FieldOrder::TopFieldFirst,
+                "bff" => FieldOrder::BottomFieldFirst,
+                _ => return Err(Box::new(StepStartupError::InvalidFieldOrder(value.clone()))),
+            },
+
+            _ => FieldOrder::Auto,
+        };
+
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
+        ensure_filter_elements_available(deinterlacer::REQUIRED_ELEMENTS)
+            .map_err(StepStartupError::from)?;
+
+        let settings = EncoderSettings::video_from_definition(&definition, &self.encoder_factory)
+            .map_err(StepStartupError::from)?;
+
+        let filter = DeinterlaceFilter {
+            settings: DeinterlaceSettings {
+                method,
+                field_order,
+            },
+        };
+
+        let step = FilterEncodeStep::new(
+            filter,
+            settings,
+            self.encoder_factory.clone(),
+            Some(self.pts_offset_metadata_key),
+        );
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl MediaFilter for DeinterlaceFilter {
+    type StreamState = DeinterlaceStream;
+    type Event = SourceFormat;
+
+    const MEDIA_TYPE: MediaType = MediaType::Video;
+    const NAME: &'static str = "deinterlacer";
+
+    fn new_stream(&self, stream_name: &Arc<String>) -> Self::StreamState {
+        DeinterlaceStream {
+            stream_name: stream_name.clone(),
+            source_format: None,
+        }
+    }
+
+    fn create_elements(
+        &self,
+        _stream: &Self::StreamState,
+        events: &UnboundedSender<Self::Event>,
+    ) -> Result<Vec<Element>> {
+        deinterlacer::create_elements(&self.settings, events)
+    }
+
+    fn media_received(
+        &mut self,
+        _stream_id: &StreamId,
+        stream: &mut Self::StreamState,
+        content: &MediaNotificationContent,
+    ) {
+        if let MediaNotificationContent::MediaPayload {
+            is_required_for_decoding: true,
+            ..
+        } = content
+        {
+            // A new sequence header may describe a different format, which isn't known until
+            // it's decoded
+            stream.source_format = None;
+        }
+    }
+
+    fn event_received(
+        &mut self,
+        stream_id: &StreamId,
+        stream: &mut Self::StreamState,
+        format: SourceFormat,
+    ) {
+        info!(
+            stream_id = %stream_id.0,
+            stream_name = %stream.stream_name,
+            interlace_mode = %format.interlace_mode,
+            field_order = ?format.field_order,
+            "Detected stream's video format"
+        );
+
+        stream.source_format = Some(format);
+    }
+
+    fn add_state_details(
+        &self,
+        streams: &[StreamSummary<'_, Self::StreamState>],
+        details: &mut HashMap<String, String>,
+    ) {
+        let input_field_orders = per_stream_detail(streams, |stream| {
+            let field_order = match &stream.source_format {
+                None => "unknown",
+                Some(format) if !format.is_interlaced() => "progressive",
+                Some(format) => format.field_order.as_deref().unwrap_or("unknown"),
+            };
+
+            Some(field_order.to_string())
+        });
+
+        let method = self
+            .settings
+            .method
+            .clone()
+            .unwrap_or_else(|| "default".to_string());
+
+        details.insert(INPUT_FIELD_ORDER_DETAIL.to_string(), input_field_orders);
+        details.insert(METHOD_DETAIL.to_string(), method);
+    }
+}
@@ -0,0 +1,894 @@
+//! Shared machinery for workflow steps that decode one type of media, run it through gstreamer
+//! filter elements, and re-encode it, while all other media is passed through untouched. Each
+//! stream's media goes through its own pipeline, and since every sequence header may describe a
+//! new format, a new pipeline is started for each one. Which elements the media is run through,
+//! and whatever needs to be tracked about each stream, is left to a `MediaFilter`.
+//!
+//! The filtered media is encoded by an encoder from the encoder factory, the same way the basic
+//! transcode step encodes media. The `video` (or `audio`) parameter names the encoder, and `video_`
+//! (or `audio_`) prefixed parameters are passed to it. The encoder is handed decoded media, so only
+//! encoders that can encode raw media can be used.
+//!
+//! The source's sequence headers for the filtered media are never passed on. Instead, the encoder
+//! sends its own sequence header before the first media it encodes.
+
+use crate::encoders::{EncoderFactory, EncoderFactoryCreationError, EncoderQueueFullError};
+use crate::steps::basic_transcoder::{
+    AUDIO_ENCODER, AUDIO_PARAM_PREFIX, VIDEO_ENCODER, VIDEO_PARAM_PREFIX,
+};
+use crate::utils::{
+    configure_source_queue, create_gst_element, ensure_elements_available, push_buffer_to_source,
+    set_gst_buffer, set_source_audio_sequence_header, set_source_video_sequence_header,
+    GstElementError,
+};
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use gstreamer::prelude::*;
+use gstreamer::{Element, FlowError, FlowSuccess, Format, Pipeline, Sample, State};
+use gstreamer_app::{AppSink, AppSinkCallbacks, AppSrc};
+use mmids_core::codecs::aac::AudioSpecificConfig;
+use mmids_core::codecs::AUDIO_CODEC_AAC_RAW;
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::metadata::{MetadataKey, MetadataValue};
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{
+    StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use mmids_core::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use mmids_core::StreamId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tracing::{error, info, warn};
+
+/// Gstreamer elements every filter pipeline is built from, in addition to the filter's elements
+const PIPELINE_ELEMENTS: &[&str] = &["appsrc", "queue", "decodebin", "appsink"];
+
+/// Decides which gstreamer elements a filter encode step runs media through, and tracks whatever
+/// it needs to about each stream
+pub(crate) trait MediaFilter: Send + Sync + 'static {
+    /// Whatever the filter needs to track for each stream
+    type StreamState: Send + Sync;
+
+    /// Something the filter's elements report about a stream's decoded media, such as its format
+    type Event: Send + 'static;
+
+    /// The type of media that's filtered. All other media is passed through untouched.
+    const MEDIA_TYPE: MediaType;
+
+    /// What the filter is called in logs
+    const NAME: &'static str;
+
+    fn new_stream(&self, stream_name: &Arc<String>) -> Self::StreamState;
+
+    /// Creates the elements the stream's decoded media is run through, in the order the media
+    /// flows through them. The elements can report events for the stream (e.g. from pad probes)
+    /// through the events channel.
+    fn create_elements(
+        &self,
+        stream: &Self::StreamState,
+        events: &UnboundedSender<Self::Event>,
+    ) -> Result<Vec<Element>>;
+
+    /// Looks at a media payload of the filtered media type before it's pushed into the pipeline.
+    /// Sequence headers are looked at before the pipeline for them is created.
+    fn media_received(
+        &mut self,
+        _stream_id: &StreamId,
+        _stream: &mut Self::StreamState,
+        _content: &MediaNotificationContent,
+    ) {
+    }
+
+    fn event_received(
+        &mut self,
+        _stream_id: &StreamId,
+        _stream: &mut Self::StreamState,
+        _event: Self::Event,
+    ) {
+    }
+
+    /// Looks at media that came out of the stream's encoder, before it's passed on
+    fn encoded_media_received(
+        &mut self,
+        _stream_id: &StreamId,
+        _stream: &mut Self::StreamState,
+        _content: &MediaNotificationContent,
+    ) {
+    }
+
+    /// Adds filter specific details to the step's state details
+    fn add_state_details(
+        &self,
+        _streams: &[StreamSummary<'_, Self::StreamState>],
+        _details: &mut HashMap<String, String>,
+    ) {
+    }
+}
+
+/// A snapshot of a stream, as reported to the filter for state details
+pub(crate) struct StreamSummary<'a, S> {
+    pub name: &'a str,
+    pub state: &'a S,
+}
+
+/// Formats a detail containing a value for each stream, ordered by stream name. Streams without
+/// a value are left out.
+pub(crate) fn per_stream_detail<S>(
+    streams: &[StreamSummary<'_, S>],
+    value: impl Fn(&S) -> Option<String>,
+) -> String {
+    let mut entries = streams
+        .iter()
+        .filter_map(|stream| value(stream.state).map(|value| format!("{}: {}", stream.name, value)))
+        .collect::<Vec<_>>();
+
+    entries.sort();
+    entries.join(", ")
+}
+
+/// Parses the AAC config out of a media payload, if it's an AAC sequence header
+pub(crate) fn aac_sequence_header_config(
+    content: &MediaNotificationContent,
+) -> Option<AudioSpecificConfig> {
+    match content {
+        MediaNotificationContent::MediaPayload {
+            payload_type,
+            data,
+            is_required_for_decoding: true,
+            ..
+        } if *payload_type == *AUDIO_CODEC_AAC_RAW => AudioSpecificConfig::parse(data).ok(),
+
+        _ => None,
+    }
+}
+
+/// Verifies that all the gstreamer elements a filter pipeline needs are available, including the
+/// filter's own elements
+pub(crate) fn ensure_filter_elements_available(
+    filter_elements: &[&str],
+) -> Result<(), GstElementError> {
+    ensure_elements_available(PIPELINE_ELEMENTS)?;
+    ensure_elements_available(filter_elements)
+}
+
+/// The encoder, and its parameters, that filtered media is encoded with
+pub(crate) struct EncoderSettings {
+    pub media_type: MediaType,
+    pub encoder_name: String,
+    pub parameters: HashMap<String, Option<String>>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum EncoderSettingsError {
+    #[error("No {0} encoder specified")]
+    NoEncoderSpecified(&'static str),
+
+    #[error("Invalid {0} encoder: {1}")]
+    InvalidEncoder(&'static str, #[source] EncoderFactoryCreationError),
+}
+
+impl EncoderSettings {
+    /// Reads the video encoder and its parameters from the step's definition, validating that the
+    /// encoder can encode decoded video.
+    pub fn video_from_definition(
+        definition: &WorkflowStepDefinition,
+        encoder_factory: &EncoderFactory,
+    ) -> Result<Self, EncoderSettingsError> {
+        let settings = EncoderSettings::from_definition(
+            MediaType::Video,
+            VIDEO_ENCODER,
+            VIDEO_PARAM_PREFIX,
+            definition,
+        )?;
+
+        encoder_factory
+            .validate_raw_video_encoder(&settings.encoder_name)
+            .map_err(|error| EncoderSettingsError::InvalidEncoder("video", error))?;
+
+        Ok(settings)
+    }
+
+    /// Reads the audio encoder and its parameters from the step's definition, validating that the
+    /// encoder can encode decoded audio.
+    pub fn audio_from_definition(
+        definition: &WorkflowStepDefinition,
+        encoder_factory: &EncoderFactory,
+    ) -> Result<Self, EncoderSettingsError> {
+        let settings = EncoderSettings::from_definition(
+            MediaType::Audio,
+            AUDIO_ENCODER,
+            AUDIO_PARAM_PREFIX,
+            definition,
+        )?;
+
+        encoder_factory
+            .validate_raw_audio_encoder(&settings.encoder_name)
+            .map_err(|error| EncoderSettingsError::InvalidEncoder("audio", error))?;
+
+        Ok(settings)
+    }
+
+    fn from_definition(
+        media_type: MediaType,
+        encoder_key: &'static str,
+        prefix: &str,
+        definition: &WorkflowStepDefinition,
+    ) -> Result<Self, EncoderSettingsError> {
+        let encoder_name = match definition.parameters.get(encoder_key) {
+            Some(Some(encoder)) => encoder.clone(),
+            _ => return Err(EncoderSettingsError::NoEncoderSpecified(encoder_key)),
+        };
+
+        let parameters = definition
+            .parameters
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix) && key.len() > prefix.len())
+            .map(|(key, value)| (key[prefix.len()..].to_string(), value.clone()))
+            .collect();
+
+        Ok(EncoderSettings {
+            media_type,
+            encoder_name,
+            parameters,
+        })
+    }
+}
+
+/// An encoder from the encoder factory, encoding the raw media coming out of another pipeline.
+/// The encoder runs in its own pipeline, so neither pipeline waits on the other to preroll. The
+/// encoder's pipeline is stopped when this is dropped.
+pub(crate) struct EncoderPipeline {
+    pipeline: Pipeline,
+}
+
+impl EncoderPipeline {
+    /// Creates the encoder, and feeds it the media coming out of the output element through an
+    /// `appsink` added to the output element's pipeline. The encoded media is sent out through
+    /// the media sender.
+    pub fn new(
+        settings: &EncoderSettings,
+        encoder_factory: &EncoderFactory,
+        output_pipeline: &Pipeline,
+        output: &Element,
+        media_sender: UnboundedSender<MediaNotificationContent>,
+    ) -> Result<EncoderPipeline> {
+        let pipeline = Pipeline::new(None);
+        let name = settings.encoder_name.clone();
+        let push_sample: Box<dyn Fn(Sample) -> Result<()> + Send> = match settings.media_type {
+            MediaType::Video => {
+                let encoder = encoder_factory.get_video_encoder(
+                    name,
+                    &pipeline,
+                    &settings.parameters,
+                    media_sender,
+                )?;
+
+                Box::new(move |sample| encoder.push_raw_sample(sample))
+            }
+
+            MediaType::Audio => {
+                let encoder = encoder_factory.get_audio_encoder(
+                    name,
+                    &pipeline,
+                    &settings.parameters,
+                    media_sender,
+                )?;
+
+                Box::new(move |sample| encoder.push_raw_sample(sample))
+            }
+
+            MediaType::Other => return Err(anyhow!("Only audio and video can be encoded")),
+        };
+
+        let appsink = create_gst_element("appsink")?;
+        output_pipeline
+            .add(&appsink)
+            .with_context(|| "Failed to add the encoder's appsink to the pipeline")?;
+
+        output
+            .link(&appsink)
+            .with_context(|| "Failed to link the output element to the encoder's appsink")?;
+
+        // The encoder's pipeline syncs its own output, so media is handed to it as soon as it's
+        // ready
+        appsink.set_property("sync", false);
+
+        let appsink = appsink
+            .dynamic_cast::<AppSink>()
+            .map_err(|_| anyhow!("appsink could not be cast to 'AppSink'"))?;
+
+        let mut is_dropping_media = false;
+        appsink.set_callbacks(
+            AppSinkCallbacks::builder()
+                .new_sample(move |sink| {
+                    let sample = sink.pull_sample().map_err(|_| FlowError::Eos)?;
+                    match push_sample(sample) {
+                        Ok(_) => is_dropping_media = false,
+                        Err(error) if error.downcast_ref::<EncoderQueueFullError>().is_some() => {
+                            if !is_dropping_media {
+                                warn!("Encoder can't keep up, dropping filtered media");
+                                is_dropping_media = true;
+                            }
+                        }
+
+                        Err(error) => {
+                            error!(
+                                "Failed to push filtered media into the encoder: {:?}",
+                                error
+                            );
+                            return Err(FlowError::Error);
+                        }
+                    }
+
+                    Ok(FlowSuccess::Ok)
+                })
+                .build(),
+        );
+
+        pipeline
+            .set_state(State::Playing)
+            .with_context(|| "Failed to set encoder pipeline to playing")?;
+
+        Ok(EncoderPipeline { pipeline })
+    }
+}
+
+impl Drop for EncoderPipeline {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(State::Null);
+    }
+}
+
+/// Decodes media pushed into it, runs the decoded media through a filter's elements, and encodes
+/// the result. The pipelines are stopped when this is dropped.
+struct FilterPipeline {
+    pipeline: Pipeline,
+    source: AppSrc,
+    media_type: MediaType,
+    _encoder: EncoderPipeline,
+}
+
+impl FilterPipeline {
+    fn new(
+        elements: Vec<Element>,
+        settings: &EncoderSettings,
+        encoder_factory: &EncoderFactory,
+        media_sender: UnboundedSender<MediaNotificationContent>,
+    ) -> Result<FilterPipeline> {
+        let (first, last) = match (elements.first(), elements.last()) {
+            (Some(first), Some(last)) => (first.clone(), last.clone()),
+            _ => return Err(anyhow!("The filter did not create any elements")),
+        };
+
+        let pipeline = Pipeline::new(None);
+        let appsrc = create_gst_element("appsrc")?;
+        let queue = create_gst_element("queue")?;
+        let decoder = create_gst_element("decodebin")?;
+
+        let elements = elements.iter().collect::<Vec<_>>();
+        pipeline
+            .add_many(&[&appsrc, &queue, &decoder])
+            .with_context(|| "Failed to add filter pipeline's elements to pipeline")?;
+
+        pipeline
+            .add_many(&elements)
+            .with_context(|| "Failed to add the filter's elements to pipeline")?;
+
+        Element::link_many(&[&appsrc, &queue, &decoder])
+            .with_context(|| "Failed to link appsrc -> queue -> decoder")?;
+
+        Element::link_many(&elements).with_context(|| "Failed to link the filter's elements")?;
+
+        // decodebin's pad is added dynamically
+        decoder.connect_pad_added(move |src, src_pad| {
+            if src
+                .link_pads(Some(&src_pad.name()), &first, Some("sink"))
+                .is_err()
+            {
+                error!(
+                    src_caps = ?src_pad.caps(),
+                    "Failed to link `decodebin`'s {} pad to the filter's first element",
+                    src_pad.name()
+                );
+            }
+        });
+
+        let encoder =
+            EncoderPipeline::new(settings, encoder_factory, &pipeline, &last, media_sender)?;
+
+        let appsrc = appsrc
+            .dynamic_cast::<AppSrc>()
+            .map_err(|_| anyhow!("source element could not be cast to 'Appsrc'"))?;
+
+        appsrc.set_format(Format::Time);
+        configure_source_queue(&appsrc);
+
+        pipeline
+            .set_state(State::Playing)
+            .with_context(|| "Failed to set filter pipeline to playing")?;
+
+        Ok(FilterPipeline {
+            pipeline,
+            source: appsrc,
+            media_type: settings.media_type,
+            _encoder: encoder,
+        })
+    }
+
+    /// Pushes media into the pipeline. If the pipeline has fallen too far behind, the media is
+    /// rejected with an `EncoderQueueFullError`.
+    fn push_data(
+        &self,
+        payload_type: Arc<String>,
+        data: Bytes,
+        dts: Duration,
+        pts: Duration,
+        is_sequence_header: bool,
+    ) -> Result<()> {
+        let buffer =
+            set_gst_buffer(data, Some(dts), Some(pts)).with_context(|| "Failed to set buffer")?;
+
+        if !is_sequence_header {
+            return push_buffer_to_source(&self.source, buffer);
+        }
+
+        let result = match self.media_type {
+            MediaType::Video => {
+                set_source_video_sequence_header(&self.source, payload_type, buffer)
+            }
+            _ => set_source_audio_sequence_header(&self.source, payload_type, buffer),
+        };
+
+        result.with_context(|| "Failed to set sequence header for filter pipeline")
+    }
+}
+
+impl Drop for FilterPipeline {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(State::Null);
+    }
+}
+
+struct Encoding {
+    settings: EncoderSettings,
+    encoder_factory: Arc<EncoderFactory>,
+}
+
+struct FilteredStream<S> {
+    name: Arc<String>,
+    state: S,
+    pipeline: Option<FilterPipeline>,
+
+    /// Identifies the current pipeline, so media from pipelines that have been replaced can be
+    /// ignored
+    pipeline_id: u64,
+
+    /// Tracks if media is being dropped because the pipeline can't keep up, so it's only logged
+    /// when dropping starts
+    is_dropping_media: bool,
+}
+
+/// A workflow step that filters and re-encodes one type of media, with a `MediaFilter` deciding
+/// how the media is filtered
+pub(crate) struct FilterEncodeStep<F: MediaFilter> {
+    filter: F,
+    encoding: Option<Encoding>,
+    pts_offset_metadata_key: Option<MetadataKey>,
+    streams: HashMap<StreamId, FilteredStream<F::StreamState>>,
+    next_pipeline_id: u64,
+}
+
+enum FutureResult<E> {
+    MediaEncoded {
+        stream_id: StreamId,
+        pipeline_id: u64,
+        content: MediaNotificationContent,
+    },
+
+    FilterEventRaised {
+        stream_id: StreamId,
+        pipeline_id: u64,
+        event: E,
+    },
+
+    // Pipelines only stop when they are dropped, so there's nothing to do when this occurs
+    PipelineStopped,
+}
+
+impl<E: Send + 'static> StepFutureResult for FutureResult<E> {}
+
+impl<F: MediaFilter> FilterEncodeStep<F> {
+    /// Creates a step that filters media and re-encodes it with the specified encoder. Video
+    /// frames' presentation timestamps are read from the pts offset metadata, so the key is
+    /// required when video is filtered.
+    pub fn new(
+        filter: F,
+        settings: EncoderSettings,
+        encoder_factory: Arc<EncoderFactory>,
+        pts_offset_metadata_key: Option<MetadataKey>,
+    ) -> Self {
+        FilterEncodeStep {
+            filter,
+            encoding: Some(Encoding {
+                settings,
+                encoder_factory,
+            }),
+            pts_offset_metadata_key,
+            streams: HashMap::new(),
+            next_pipeline_id: 0,
+        }
+    }
+
+    /// Creates a step that passes all media through untouched, while still letting the filter
+    /// look at the media it would have filtered
+    pub fn passthrough(filter: F) -> Self {
+        FilterEncodeStep {
+            filter,
+            encoding: None,
+            pts_offset_metadata_key: None,
+            streams: HashMap::new(),
+            next_pipeline_id: 0,
+        }
+    }
+
+    fn handle_media(
+        &mut self,
+        media: MediaNotification,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                self.streams.insert(
+                    media.stream_id.clone(),
+                    FilteredStream {
+                        name: stream_name.clone(),
+                        state: self.filter.new_stream(stream_name),
+                        pipeline: None,
+                        pipeline_id: 0,
+                        is_dropping_media: false,
+                    },
+                );
+
+                outputs.media.push(media);
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.streams.remove(&media.stream_id);
+                outputs.media.push(media);
+            }
+
+            MediaNotificationContent::MediaPayload { media_type, .. }
+                if *media_type == F::MEDIA_TYPE =>
+            {
+                self.handle_filtered_media(media, outputs, futures_channel);
+            }
+
+            MediaNotificationContent::MediaPayload { .. } => outputs.media.push(media),
+            MediaNotificationContent::Metadata { .. } => outputs.media.push(media),
+        }
+    }
+
+    fn handle_filtered_media(
+        &mut self,
+        media: MediaNotification,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let stream = match self.streams.get_mut(&media.stream_id) {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        self.filter
+            .media_received(&media.stream_id, &mut stream.state, &media.content);
+
+        let encoding = match &self.encoding {
+            Some(encoding) => encoding,
+            None => {
+                outputs.media.push(media);
+                return;
+            }
+        };
+
+        let (payload_type, timestamp, metadata, data, is_required_for_decoding) =
+            match &media.content {
+                MediaNotificationContent::MediaPayload {
+                    payload_type,
+                    timestamp,
+                    metadata,
+                    data,
+                    is_required_for_decoding,
+                    ..
+                } => (
+                    payload_type,
+                    timestamp,
+                    metadata,
+                    data,
+                    *is_required_for_decoding,
+                ),
+
+                _ => return,
+            };
+
+        if is_required_for_decoding {
+            // Each sequence header may describe a different format, so a new pipeline is needed
+            // to decode it
+            self.next_pipeline_id += 1;
+            stream.pipeline_id = self.next_pipeline_id;
+            stream.is_dropping_media = false;
+            stream.pipeline = start_pipeline(
+                &self.filter,
+                &media.stream_id,
+                stream,
+                encoding,
+                futures_channel,
+            );
+        }
+
+        let pipeline = match &stream.pipeline {
+            Some(pipeline) => pipeline,
+            None => return,
+        };
+
+        let pts_offset = match self.pts_offset_metadata_key {
+            Some(key) => metadata
+                .iter()
+                .filter(|m| m.key() == key)
+                .filter_map(|m| match m.value() {
+                    MetadataValue::I32(num) => Some(num),
+                    _ => None,
+                })
+                .next()
+                .unwrap_or_default(),
+
+            None => 0,
+        };
+
+        let pts = Duration::from_millis(timestamp.as_millis() as u64 + pts_offset as u64);
+        let result = pipeline.push_data(
+            payload_type.clone(),
+            data.clone(),
+            *timestamp,
+            pts,
+            is_required_for_decoding,
+        );
+
+        match result {
+            Ok(_) => stream.is_dropping_media = false,
+            Err(error) if error.downcast_ref::<EncoderQueueFullError>().is_some() => {
+                if !stream.is_dropping_media {
+                    warn!(
+                        stream_id = %media.stream_id.0,
+                        "The {} can't keep up, dropping {} frames",
+                        F::NAME, media_name(F::MEDIA_TYPE)
+                    );
+
+                    stream.is_dropping_media = true;
+                }
+            }
+
+            Err(error) => {
+                error!(
+                    stream_id = %media.stream_id.0,
+                    "Failed to push {} into the {}, dropping the stream's {}: {:?}",
+                    media_name(F::MEDIA_TYPE), F::NAME, media_name(F::MEDIA_TYPE), error
+                );
+
+                stream.pipeline = None;
+            }
+        }
+    }
+
+    fn handle_encoded_media(
+        &mut self,
+        stream_id: StreamId,
+        pipeline_id: u64,
+        content: MediaNotificationContent,
+        outputs: &mut StepOutputs,
+    ) {
+        let stream = match self.streams.get_mut(&stream_id) {
+            Some(stream) if stream.pipeline_id == pipeline_id => stream,
+            _ => return, // media from a pipeline that's been replaced or a stream that's gone
+        };
+
+        self.filter
+            .encoded_media_received(&stream_id, &mut stream.state, &content);
+
+        outputs.media.push(MediaNotification {
+            stream_id,
+            content,
+            annotations: Default::default(),
+        });
+    }
+
+    fn handle_filter_event(&mut self, stream_id: StreamId, pipeline_id: u64, event: F::Event) {
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            if stream.pipeline_id == pipeline_id {
+                self.filter
+                    .event_received(&stream_id, &mut stream.state, event);
+            }
+        }
+    }
+}
+
+impl<F: MediaFilter> WorkflowStep for FilterEncodeStep<F> {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            match notification.downcast::<FutureResult<F::Event>>() {
+                Ok(result) => match *result {
+                    FutureResult::PipelineStopped => (),
+                    FutureResult::MediaEncoded {
+                        stream_id,
+                        pipeline_id,
+                        content,
+                    } => self.handle_encoded_media(stream_id, pipeline_id, content, outputs),
+
+                    FutureResult::FilterEventRaised {
+                        stream_id,
+                        pipeline_id,
+                        event,
+                    } => self.handle_filter_event(stream_id, pipeline_id, event),
+                },
+
+                Err(_) => {
+                    error!("Received future result that could not be casted to the internal future result type");
+                }
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            self.handle_media(media, outputs, &futures_channel);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let streams = self
+            .streams
+            .values()
+            .map(|stream| StreamSummary {
+                name: stream.name.as_str(),
+                state: &stream.state,
+            })
+            .collect::<Vec<_>>();
+
+        let mut details = HashMap::new();
+        self.filter.add_state_details(&streams, &mut details);
+
+        details
+    }
+
+    fn get_active_pipeline_count(&self) -> usize {
+        self.streams
+            .values()
+            .filter(|stream| stream.pipeline.is_some())
+            .count()
+    }
+}
+
+fn start_pipeline<F: MediaFilter>(
+    filter: &F,
+    stream_id: &StreamId,
+    stream: &FilteredStream<F::StreamState>,
+    encoding: &Encoding,
+    futures_channel: &WorkflowStepFuturesChannel,
+) -> Option<FilterPipeline> {
+    let (media_sender, media_receiver) = unbounded_channel();
+    let (event_sender, event_receiver) = unbounded_channel();
+    let pipeline = filter
+        .create_elements(&stream.state, &event_sender)
+        .and_then(|elements| {
+            FilterPipeline::new(
+                elements,
+                &encoding.settings,
+                &encoding.encoder_factory,
+                media_sender,
+            )
+        });
+
+    let pipeline = match pipeline {
+        Ok(pipeline) => pipeline,
+        Err(error) => {
+            error!(
+                stream_id = %stream_id.0,
+                "Failed to create {}, dropping the stream's {}: {:?}",
+                F::NAME, media_name(F::MEDIA_TYPE), error
+            );
+
+            return None;
+        }
+    };
+
+    info!(
+        stream_id = %stream_id.0,
+        "Started {} for the stream's {}", F::NAME, media_name(F::MEDIA_TYPE)
+    );
+
+    let pipeline_id = stream.pipeline_id;
+    let media_stream_id = stream_id.clone();
+    futures_channel.send_on_generic_unbounded_recv(
+        media_receiver,
+        move |content| FutureResult::<F::Event>::MediaEncoded {
+            stream_id: media_stream_id.clone(),
+            pipeline_id,
+            content,
+        },
+        || FutureResult::PipelineStopped,
+    );
+
+    let event_stream_id = stream_id.clone();
+    futures_channel.send_on_generic_unbounded_recv(
+        event_receiver,
+        move |event| FutureResult::FilterEventRaised {
+            stream_id: event_stream_id.clone(),
+            pipeline_id,
+            event,
+        },
+        || FutureResult::PipelineStopped,
+    );
+
+    Some(pipeline)
+}
+
+fn media_name(media_type: MediaType) -> &'static str {
+    match media_type {
+        MediaType::Video => "video",
+        MediaType::Audio => "audio",
+        MediaType::Other => "media",
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use super::*;
+    use gstreamer::{Buffer, Caps};
+
+    /// Pushes raw buffers with the specified caps through a filter's elements, and collects the
+    /// samples that come out of them
+    pub fn run_through_elements(
+        elements: &[Element],
+        caps: &Caps,
+        buffers: Vec<Buffer>,
+    ) -> Vec<Sample> {
+        let pipeline = Pipeline::new(None);
+        let appsrc = create_gst_element("appsrc").unwrap();
+        let appsink = create_gst_element("appsink").unwrap();
+
+        let mut chain = vec![&appsrc];
+        chain.extend(elements.iter());
+        chain.push(&appsink);
+
+        pipeline.add_many(&chain).unwrap();
+        Element::link_many(&chain).unwrap();
+        appsink.set_property("sync", false);
+
+        let appsrc = appsrc.dynamic_cast::<AppSrc>().unwrap();
+        appsrc.set_caps(Some(caps));
+        appsrc.set_format(Format::Time);
+
+        pipeline.set_state(State::Playing).unwrap();
+        for buffer in buffers {
+            appsrc.push_buffer(buffer).unwrap();
+        }
+
+        let _ = appsrc.end_of_stream();
+
+        let sink = appsink.dynamic_cast::<AppSink>().unwrap();
+        let mut samples = Vec::new();
+        while let Ok(sample) = sink.pull_sample() {
+            samples.push(sample);
+        }
+
+        pipeline.set_state(State::Null).unwrap();
+        samples
+    }
+}
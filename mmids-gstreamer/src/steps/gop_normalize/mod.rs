@@ -0,0 +1,375 @@
+//! The GOP normalize workflow step re-encodes every stream to a fixed, closed GOP structure, so
+//! that packagers segmenting the output (such as for HLS or DASH) can cut segments on keyframes
+//! that all land at the same interval. Sources with irregular keyframe placement or open GOPs
+//! otherwise produce segments of varying lengths, or segments that can't be decoded on their own.
+//!
+//! The `keyframe_interval` parameter is required, and is the exact number of seconds between
+//! keyframes in the output. Re-encoding is done the same way as the basic transcode step, with the
+//! `video` and `audio` parameters naming the encoders to use, and `video_` and `audio_` prefixed
+//! parameters being passed to the respective encoders. The step sets the video encoder's
+//! `min_keyframe_interval` parameter to the keyframe interval and enables its `fixed_gop`
+//! parameter, so the video encoder must support fixed GOPs (such as the `x264` encoder).
+//!
+//! The GOP structure of each stream's source and output video is measured, and surfaced in the
+//! step's state details along with the target structure. Any output GOP that isn't closed, has
+//! reordered frames, or isn't exactly the keyframe interval long is counted as a non-conforming
+//! GOP. Output keyframes are found by looking for IDR frames, so only H264 output is measured.
+//!
+//! Unlike other transcoding steps, requests for fresh sequence headers do not force a keyframe,
+//! as an extra keyframe would break the fixed GOP structure.
+
+mod structure;
+
+use crate::encoders::EncoderFactory;
+use crate::endpoints::gst_transcoder::GstTranscoderRequest;
+use crate::steps::basic_transcoder::MIN_KEYFRAME_INTERVAL;
+use crate::steps::selective_transcode::{
+    per_stream_detail, Decision, SelectiveTranscodeStep, StreamSummary, TranscodeDecider,
+    TranscodeSettings, TranscodeSettingsError,
+};
+use crate::GSTREAMER_INIT_RESULT;
+use mmids_core::codecs::h264::{nal_unit_type, AvcDecoderConfigurationRecord, NAL_UNIT_TYPE_IDR};
+use mmids_core::codecs::nal::{split_length_prefixed, DEFAULT_NAL_LENGTH_SIZE};
+use mmids_core::codecs::VIDEO_CODEC_H264_AVC;
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::metadata::{MediaPayloadMetadataCollection, MetadataKey, MetadataValue};
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{StepCreationResult, StepStatus};
+use mmids_core::workflows::{MediaNotificationContent, MediaType};
+use mmids_core::StreamId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use structure::GopMonitor;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::warn;
+
+pub const KEYFRAME_INTERVAL: &str = "keyframe_interval";
+
+const FIXED_GOP_ENCODER_PARAM: &str = "fixed_gop";
+
+const TRANSCODE_MODE: &str = "transcode";
+
+const TARGET_GOP_DETAIL: &str = "target_gop";
+const SOURCE_GOP_DETAIL: &str = "source_gop";
+const OUTPUT_GOP_DETAIL: &str = "output_gop";
+const NONCONFORMING_GOP_COUNT_DETAIL: &str = "nonconforming_gop_count";
+const TRANSCODING_STREAMS_DETAIL: &str = "transcoding_streams";
+
+/// Creates new instances of the GOP normalize workflow step.
+pub struct GopNormalizeStepGenerator {
+    transcode_endpoint: UnboundedSender<GstTranscoderRequest>,
+    encoder_factory: Arc<EncoderFactory>,
+    is_keyframe_metadata_key: MetadataKey,
+    pts_offset_metadata_key: MetadataKey,
+}
+
+/// Transcodes every stream, measuring the GOP structure of each stream's source and output
+struct GopNormalizeDecider {
+    is_keyframe_metadata_key: MetadataKey,
+    pts_offset_metadata_key: MetadataKey,
+    keyframe_interval: Duration,
+    nonconforming_gop_count: u64,
+}
+
+struct StreamGops {
+    source_gop: GopMonitor,
+    output_gop: GopMonitor,
+    output_nal_length_size: u8,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum StepStartupError {
+    #[error("No {} specified", KEYFRAME_INTERVAL)]
+    NoKeyframeIntervalSpecified,
+
+    #[error(
+        "Invalid {} value of '{0}' specified, must be a number of seconds greater than zero",
+        KEYFRAME_INTERVAL
+    )]
+    InvalidKeyframeInterval(String),
+
+    #[error(transparent)]
+    InvalidTranscodeSettings(#[from] TranscodeSettingsError),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
+}
+
+impl GopNormalizeStepGenerator {
+    /// Creates the generator. The encoder factory must be the same one the transcode endpoint
+    /// uses, as it's used to validate the encoders each step is created with.
+    pub fn new(
+        transcode_endpoint: UnboundedSender<GstTranscoderRequest>,
+        encoder_factory: Arc<EncoderFactory>,
+        is_keyframe_metadata_key: MetadataKey,
+        pts_offset_metadata_key: MetadataKey,
+    ) -> GopNormalizeStepGenerator {
+        GopNormalizeStepGenerator {
+            transcode_endpoint,
+            encoder_factory,
+            is_keyframe_metadata_key,
+            pts_offset_metadata_key,
+        }
+    }
+}
+
+impl StepGenerator for GopNormalizeStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
+        let (keyframe_interval_value, keyframe_interval) =
+            match definition.parameters.get(KEYFRAME_INTERVAL) {
+                Some(Some(value)) => match value.trim().parse::<f64>() {
+                    Ok(seconds) if seconds.is_finite() && seconds > 0.0 => {
+                        (value.trim().to_string(), Duration::from_secs_f64(seconds))
+                    }
+
+                    _ => {
+                        return Err(Box::new(StepStartupError::InvalidKeyframeInterval(
+                            value.clone(),
+                        )))
+                    }
+                },
+
+                _ => return Err(Box::new(StepStartupError::NoKeyframeIntervalSpecified)),
+            };
+
+        let mut settings = TranscodeSettings::from_definition(&definition, &self.encoder_factory)
+            .map_err(StepStartupError::from)?;
+
+        // The GOP structure is what this step exists for, so it overrides any encoder specific
+        // keyframe settings
+        settings.video_parameters.insert(
+            MIN_KEYFRAME_INTERVAL.to_string(),
+            Some(keyframe_interval_value),
+        );
+
+        settings.video_parameters.insert(
+            FIXED_GOP_ENCODER_PARAM.to_string(),
+            Some("true".to_string()),
+        );
+
+        let decider = GopNormalizeDecider {
+            is_keyframe_metadata_key: self.is_keyframe_metadata_key,
+            pts_offset_metadata_key: self.pts_offset_metadata_key,
+            keyframe_interval,
+            nonconforming_gop_count: 0,
+        };
+
+        let step = SelectiveTranscodeStep::new(
+            decider,
+            self.transcode_endpoint.clone(),
+            settings,
+            &futures_channel,
+        );
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl TranscodeDecider for GopNormalizeDecider {
+    type Target = ();
+    type StreamState = StreamGops;
+
+    const TRANSCODE_MODE: &'static str = TRANSCODE_MODE;
+    const TRANSCODING_STREAMS_DETAIL: &'static str = TRANSCODING_STREAMS_DETAIL;
+
+    // Forcing a keyframe would start a GOP off the fixed interval
+    const CAN_FORCE_KEY_UNITS: bool = false;
+
+    fn new_stream(&self) -> StreamGops {
+        StreamGops {
+            source_gop: GopMonitor::default(),
+            output_gop: GopMonitor::default(),
+            output_nal_length_size: DEFAULT_NAL_LENGTH_SIZE,
+        }
+    }
+
+    fn initial_decision(&self) -> Option<Decision<()>> {
+        Some(Decision::Transcode {
+            target: (),
+            reason: "every stream is re-encoded to a fixed GOP".to_string(),
+        })
+    }
+
+    /// Only measures the source's GOP structure, as every stream stays transcoded
+    fn evaluate(
+        &mut self,
+        _stream_id: &StreamId,
+        stream: &mut StreamGops,
+        content: &MediaNotificationContent,
+        _is_pending: bool,
+    ) -> Option<Decision<()>> {
+        if let MediaNotificationContent::MediaPayload {
+            media_type: MediaType::Video,
+            timestamp,
+            metadata,
+            is_required_for_decoding: false,
+            ..
+        } = content
+        {
+            let pts = presentation_time(*timestamp, metadata, self.pts_offset_metadata_key);
+            let is_keyframe = metadata
+                .iter()
+                .filter(|m| m.key() == self.is_keyframe_metadata_key)
+                .filter_map(|m| match m.value() {
+                    MetadataValue::Bool(val) => Some(val),
+                    _ => None,
+                })
+                .next()
+                .unwrap_or_default();
+
+            stream
+                .source_gop
+                .frame_received(*timestamp, pts, is_keyframe);
+        }
+
+        None
+    }
+
+    /// A new transcode's output starts a new GOP structure
+    fn transcode_started(&mut self, stream: &mut StreamGops) {
+        stream.output_gop = GopMonitor::default();
+        stream.output_nal_length_size = DEFAULT_NAL_LENGTH_SIZE;
+    }
+
+    fn transcoded_media_received(
+        &mut self,
+        stream_id: &StreamId,
+        stream: &mut StreamGops,
+        content: &MediaNotificationContent,
+    ) {
+        let (payload_type, timestamp, metadata, data, is_required_for_decoding) = match content {
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type,
+                timestamp,
+                metadata,
+                data,
+                is_required_for_decoding,
+            } => (
+                payload_type,
+                timestamp,
+                metadata,
+                data,
+                is_required_for_decoding,
+            ),
+
+            _ => return,
+        };
+
+        if *payload_type != *VIDEO_CODEC_H264_AVC {
+            return;
+        }
+
+        if *is_required_for_decoding {
+            match AvcDecoderConfigurationRecord::parse(data) {
+                Ok(record) => stream.output_nal_length_size = record.nal_length_size,
+                Err(error) => warn!(
+                    stream_id = ?stream_id,
+                    "Failed to parse transcoded AVC sequence header: {:?}", error
+                ),
+            }
+
+            return;
+        }
+
+        let is_keyframe = match split_length_prefixed(data, stream.output_nal_length_size) {
+            Ok(nal_units) => nal_units
+                .iter()
+                .any(|nal| nal_unit_type(nal) == Some(NAL_UNIT_TYPE_IDR)),
+
+            Err(error) => {
+                warn!(
+                    stream_id = ?stream_id,
+                    "Failed to split transcoded video into NAL units: {:?}", error
+                );
+
+                false
+            }
+        };
+
+        let pts = presentation_time(*timestamp, metadata, self.pts_offset_metadata_key);
+        let gop = stream
+            .output_gop
+            .frame_received(*timestamp, pts, is_keyframe);
+        if let Some(gop) = gop {
+            if !gop.conforms_to(self.keyframe_interval) {
+                warn!(
+                    stream_id = ?stream_id,
+                    "Transcoded GOP of {} does not match the target of a closed {:?} GOP",
+                    gop,
+                    self.keyframe_interval,
+                );
+
+                self.nonconforming_gop_count += 1;
+            }
+        }
+    }
+
+    fn add_state_details(
+        &self,
+        streams: &[StreamSummary<'_, StreamGops, ()>],
+        details: &mut HashMap<String, String>,
+    ) {
+        details.insert(
+            TARGET_GOP_DETAIL.to_string(),
+            format!("{}ms closed", self.keyframe_interval.as_millis()),
+        );
+
+        details.insert(
+            SOURCE_GOP_DETAIL.to_string(),
+            per_stream_detail(streams, |stream| match stream.state.source_gop.last_gop() {
+                Some(gop) => gop.to_string(),
+                None => "measuring".to_string(),
+            }),
+        );
+
+        details.insert(
+            OUTPUT_GOP_DETAIL.to_string(),
+            per_stream_detail(streams, |stream| match stream.state.output_gop.last_gop() {
+                Some(gop) => gop.to_string(),
+                None => "measuring".to_string(),
+            }),
+        );
+
+        details.insert(
+            NONCONFORMING_GOP_COUNT_DETAIL.to_string(),
+            self.nonconforming_gop_count.to_string(),
+        );
+    }
+}
+
+/// Gets the presentation time of a video frame from its decode timestamp and pts offset metadata
+fn presentation_time(
+    dts: Duration,
+    metadata: &MediaPayloadMetadataCollection,
+    pts_offset_metadata_key: MetadataKey,
+) -> Duration {
+    let pts_offset = metadata
+        .iter()
+        .filter(|m| m.key() == pts_offset_metadata_key)
+        .filter_map(|m| match m.value() {
+            MetadataValue::I32(num) => Some(num),
+            _ => None,
+        })
+        .next()
+        .unwrap_or_default();
+
+    let offset = Duration::from_millis(pts_offset.unsigned_abs() as u64);
+    if pts_offset >= 0 {
+        dts + offset
+    } else {
+        dts.saturating_sub(offset)
+    }
+}
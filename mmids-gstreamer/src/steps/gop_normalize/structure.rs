@@ -0,0 +1,198 @@
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+/// How far a GOP's length may drift from the target interval and still be considered exact, to
+/// account for timestamps that are rounded to the millisecond.
+const LENGTH_TOLERANCE: Duration = Duration::from_millis(1);
+
+/// The structure of a single GOP, as observed from the frames within it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Gop {
+    pub length: Duration,
+
+    /// True if no frame in the GOP is presented before the keyframe that starts it, meaning the
+    /// GOP can be decoded without the GOP before it.
+    pub closed: bool,
+
+    /// True if any frame in the GOP is presented in a different order than it's decoded in, such
+    /// as when B-frames are used.
+    pub reordered: bool,
+}
+
+impl Gop {
+    /// Returns true if the GOP is closed, has no reordered frames, and is exactly as long as the
+    /// specified interval.
+    pub fn conforms_to(&self, interval: Duration) -> bool {
+        self.closed && !self.reordered && self.length.abs_diff(interval) < LENGTH_TOLERANCE
+    }
+}
+
+impl Display for Gop {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}ms", self.length.as_millis())?;
+        write!(f, " {}", if self.closed { "closed" } else { "open" })?;
+        if self.reordered {
+            write!(f, " with reordered frames")?;
+        }
+
+        Ok(())
+    }
+}
+
+struct CurrentGop {
+    keyframe_pts: Duration,
+    closed: bool,
+    reordered: bool,
+}
+
+/// Observes the frames of a stream's video to determine the structure of each GOP
+#[derive(Default)]
+pub struct GopMonitor {
+    current: Option<CurrentGop>,
+    last_gop: Option<Gop>,
+}
+
+impl GopMonitor {
+    /// Records a video frame in decode order, returning the GOP that the frame completed (if a
+    /// keyframe completed one). Frames before the first keyframe are ignored, as the GOP they
+    /// belong to was never fully seen.
+    pub fn frame_received(
+        &mut self,
+        dts: Duration,
+        pts: Duration,
+        is_keyframe: bool,
+    ) -> Option<Gop> {
+        if is_keyframe {
+            let completed = match self.current.take() {
+                // A timeline that jumped backwards can't be measured, so that GOP is discarded
+                Some(current) if pts >= current.keyframe_pts => Some(Gop {
+                    length: pts - current.keyframe_pts,
+                    closed: current.closed,
+                    reordered: current.reordered,
+                }),
+
+                _ => None,
+            };
+
+            self.current = Some(CurrentGop {
+                keyframe_pts: pts,
+                closed: true,
+                reordered: pts != dts,
+            });
+
+            if completed.is_some() {
+                self.last_gop = completed;
+            }
+
+            return completed;
+        }
+
+        if let Some(current) = &mut self.current {
+            if pts < current.keyframe_pts {
+                current.closed = false;
+            }
+
+            if pts != dts {
+                current.reordered = true;
+            }
+        }
+
+        None
+    }
+
+    pub fn last_gop(&self) -> Option<Gop> {
+        self.last_gop
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(value: u64) -> Duration {
+        Duration::from_millis(value)
+    }
+
+    /// Sends 30fps frames without reordering, with a keyframe at each of the specified frames
+    fn send_frames(monitor: &mut GopMonitor, count: u64, keyframes: &[u64]) -> Vec<Gop> {
+        (0..count)
+            .filter_map(|frame| {
+                let time = Duration::from_nanos(frame * 1_000_000_000 / 30);
+                monitor.frame_received(time, time, keyframes.contains(&frame))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keyframes_at_exact_interval_conform() {
+        let mut monitor = GopMonitor::default();
+        let gops = send_frames(&mut monitor, 121, &[0, 60, 120]);
+
+        assert_eq!(gops.len(), 2, "Unexpected number of GOPs");
+        for gop in gops {
+            assert_eq!(gop.length, millis(2000), "Unexpected GOP length");
+            assert!(gop.closed, "Expected a closed GOP");
+            assert!(gop.conforms_to(millis(2000)), "Expected GOP to conform");
+        }
+    }
+
+    #[test]
+    fn keyframe_off_interval_does_not_conform() {
+        let mut monitor = GopMonitor::default();
+        let gops = send_frames(&mut monitor, 121, &[0, 45, 120]);
+
+        assert_eq!(gops[0].length, millis(1500), "Unexpected GOP length");
+        assert!(!gops[0].conforms_to(millis(2000)), "Expected short GOP");
+        assert!(!gops[1].conforms_to(millis(2000)), "Expected long GOP");
+    }
+
+    #[test]
+    fn frame_presented_before_keyframe_opens_gop() {
+        let mut monitor = GopMonitor::default();
+        monitor.frame_received(millis(0), millis(0), true);
+
+        // A B-frame at the start of the second GOP that's presented before its keyframe
+        monitor.frame_received(millis(1000), millis(1066), true);
+        monitor.frame_received(millis(1033), millis(1033), false);
+        monitor.frame_received(millis(1066), millis(1100), false);
+
+        let gop = monitor
+            .frame_received(millis(2000), millis(2066), true)
+            .expect("Expected a completed GOP");
+
+        assert_eq!(gop.length, millis(1000), "Unexpected GOP length");
+        assert!(!gop.closed, "Expected an open GOP");
+        assert!(gop.reordered, "Expected reordered frames");
+        assert!(
+            !gop.conforms_to(millis(1000)),
+            "Expected GOP to not conform"
+        );
+        assert_eq!(gop.to_string(), "1000ms open with reordered frames");
+    }
+
+    #[test]
+    fn frames_before_first_keyframe_ignored() {
+        let mut monitor = GopMonitor::default();
+        let gops = send_frames(&mut monitor, 91, &[30, 90]);
+
+        assert_eq!(gops.len(), 1, "Unexpected number of GOPs");
+        assert_eq!(gops[0].length, millis(2000), "Unexpected GOP length");
+        assert_eq!(monitor.last_gop(), Some(gops[0]));
+    }
+
+    #[test]
+    fn gop_discarded_when_timeline_jumps_backwards() {
+        let mut monitor = GopMonitor::default();
+        monitor.frame_received(millis(5000), millis(5000), true);
+
+        assert_eq!(monitor.frame_received(millis(0), millis(0), true), None);
+        assert_eq!(
+            monitor.frame_received(millis(2000), millis(2000), true),
+            Some(Gop {
+                length: millis(2000),
+                closed: true,
+                reordered: false,
+            })
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! Determines the color space of video from the colorimetry its gstreamer caps specify, and the
+//! color spaces HDR video can be converted to.
+
+use std::fmt::{Display, Formatter};
+
+/// Color spaces video can be converted to, as named in gstreamer caps
+pub const TARGETS: &[&str] = &["bt709", "bt601"];
+
+/// Ways the `videoconvert` element can convert between color primaries
+pub const PRIMARIES_MODES: &[&str] = &["full", "fast"];
+
+// Values of gstreamer's `GstVideoTransferFunction` enum, which is how transfer functions are
+// specified by colorimetries without a well known name
+const TRANSFER_SMPTE_2084: &str = "14";
+const TRANSFER_ARIB_STD_B67: &str = "15";
+
+/// The range of brightness video's transfer function can represent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DynamicRange {
+    Sdr,
+
+    /// HDR10 style video, using the SMPTE ST 2084 perceptual quantizer transfer function
+    Pq,
+
+    /// Hybrid log-gamma HDR video
+    Hlg,
+}
+
+/// The color space of video, as described by its caps
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColorSpace {
+    /// The caps' colorimetry, which is `unknown` when the caps don't specify one
+    pub colorimetry: String,
+    pub dynamic_range: DynamicRange,
+}
+
+impl ColorSpace {
+    /// Reads the color space from a caps colorimetry value. Colorimetries are either a well known
+    /// name (e.g. `bt2100-pq`) or a `range:matrix:transfer:primaries` tuple of enum values.
+    pub fn from_colorimetry(colorimetry: Option<&str>) -> ColorSpace {
+        let colorimetry = colorimetry.unwrap_or("unknown");
+        let dynamic_range = match colorimetry {
+            "bt2100-pq" => DynamicRange::Pq,
+            "bt2100-hlg" => DynamicRange::Hlg,
+            value => match value.split(':').nth(2) {
+                Some(TRANSFER_SMPTE_2084) => DynamicRange::Pq,
+                Some(TRANSFER_ARIB_STD_B67) => DynamicRange::Hlg,
+                _ => DynamicRange::Sdr,
+            },
+        };
+
+        ColorSpace {
+            colorimetry: colorimetry.to_string(),
+            dynamic_range,
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        self.dynamic_range != DynamicRange::Sdr
+    }
+}
+
+impl Display for DynamicRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DynamicRange::Sdr => write!(f, "sdr"),
+            DynamicRange::Pq => write!(f, "hdr pq"),
+            DynamicRange::Hlg => write!(f, "hdr hlg"),
+        }
+    }
+}
+
+impl Display for ColorSpace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.colorimetry, self.dynamic_range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_hdr_colorimetries_detected() {
+        let pq = ColorSpace::from_colorimetry(Some("bt2100-pq"));
+        let hlg = ColorSpace::from_colorimetry(Some("bt2100-hlg"));
+
+        assert_eq!(pq.dynamic_range, DynamicRange::Pq, "Unexpected pq range");
+        assert_eq!(hlg.dynamic_range, DynamicRange::Hlg, "Unexpected hlg range");
+    }
+
+    #[test]
+    fn hdr_transfer_functions_detected_in_colorimetry_tuples() {
+        let pq = ColorSpace::from_colorimetry(Some("2:6:14:7"));
+        let hlg = ColorSpace::from_colorimetry(Some("2:6:15:7"));
+
+        assert_eq!(pq.dynamic_range, DynamicRange::Pq, "Unexpected pq range");
+        assert_eq!(hlg.dynamic_range, DynamicRange::Hlg, "Unexpected hlg range");
+    }
+
+    #[test]
+    fn sdr_and_unknown_colorimetries_are_not_hdr() {
+        for colorimetry in [Some("bt709"), Some("bt2020"), Some("2:3:5:1"), None] {
+            let color_space = ColorSpace::from_colorimetry(colorimetry);
+
+            assert!(
+                !color_space.is_hdr(),
+                "Expected {:?} to not be hdr",
+                colorimetry
+            );
+        }
+    }
+
+    #[test]
+    fn color_space_displays_colorimetry_and_range() {
+        let color_space = ColorSpace::from_colorimetry(Some("bt2100-pq"));
+
+        assert_eq!(color_space.to_string(), "bt2100-pq (hdr pq)");
+        assert_eq!(
+            ColorSpace::from_colorimetry(None).to_string(),
+            "unknown (sdr)"
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! The HDR tonemap workflow step converts HDR (PQ or HLG) video to SDR, so it can be played on
+//! devices that can't display HDR. The video is decoded, converted to the target color space with
+//! gstreamer's `videoconvert` element and re-encoded, while audio is passed through untouched. The
+//! conversion remaps the video's transfer function and color primaries, and the target colorimetry
+//! is set on the converted video, so encoders can write it into the re-encoded video's VUI for
+//! players to know how to interpret it. Brightness beyond the SDR range is clipped rather than
+//! compressed by a tone curve.
+//!
+//! Whether video is HDR is detected from the decoded video's caps, whose colorimetry the decoder
+//! sets from the SPS's VUI. Video that is already SDR passes through the conversion untouched
+//! (though it is still re-encoded, so every stream leaves the step with the same color metadata).
+//!
+//! The optional `target` parameter specifies the colorimetry video is converted to, which can be
+//! `bt709` (the default) or `bt601`. The optional `primaries_mode` parameter can be `full` (the
+//! default) for an accurate conversion of the color primaries, or `fast` for a cheaper
+//! approximation. The converted video is encoded the same way as the basic transcode step, with
+//! the `video` parameter naming the encoder to use and `video_` prefixed parameters being passed
+//! to it.
+//!
+//! The color space detected for each stream's video and the target color space are reported
+//! through the step's state details.
+
+mod colorimetry;
+mod tonemapper;
+
+use crate::encoders::EncoderFactory;
+use crate::steps::filter_encode::{
+    ensure_filter_elements_available, per_stream_detail, EncoderSettings, EncoderSettingsError,
+    FilterEncodeStep, MediaFilter, StreamSummary,
+};
+use crate::steps::hdr_tonemap::colorimetry::{ColorSpace, PRIMARIES_MODES, TARGETS};
+use crate::steps::hdr_tonemap::tonemapper::TonemapSettings;
+use crate::utils::GstElementError;
+use crate::GSTREAMER_INIT_RESULT;
+use anyhow::Result;
+use gstreamer::Element;
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::metadata::MetadataKey;
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{StepCreationResult, StepStatus};
+use mmids_core::workflows::{MediaNotificationContent, MediaType};
+use mmids_core::StreamId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::info;
+
+pub const TARGET: &str = "target";
+pub const PRIMARIES_MODE: &str = "primaries_mode";
+
+const DEFAULT_TARGET: &str = "bt709";
+const DEFAULT_PRIMARIES_MODE: &str = "full";
+
+const INPUT_COLOR_SPACE_DETAIL: &str = "input_color_space";
+const TARGET_COLOR_SPACE_DETAIL: &str = "target_color_space";
+
+/// Generates new instances of the HDR tonemap workflow step
+pub struct HdrTonemapStepGenerator {
+    encoder_factory: Arc<EncoderFactory>,
+    pts_offset_metadata_key: MetadataKey,
+}
+
+/// Converts each stream's video to SDR, while tracking the color space of the decoded video
+struct TonemapFilter {
+    settings: TonemapSettings,
+}
+
+struct TonemapStream {
+    stream_name: Arc<String>,
+    source_color_space: Option<ColorSpace>,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum StepStartupError {
+    #[error("Invalid {} value of '{0}'.  It must be one of: {}", TARGET, TARGETS.join(", "))]
+    InvalidTarget(String),
+
+    #[error(
+        "Invalid {} value of '{0}'.  It must be one of: {}",
+        PRIMARIES_MODE,
+        PRIMARIES_MODES.join(", ")
+    )]
+    InvalidPrimariesMode(String),
+
+    #[error(transparent)]
+    InvalidEncoderSettings(#[from] EncoderSettingsError),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
+
+    #[error("Video cannot be tone mapped: {0}")]
+    MissingElement(#[from] GstElementError),
+}
+
+impl HdrTonemapStepGenerator {
+    /// Creates the generator, with the encoder factory tone mapped video is encoded with
+    pub fn new(encoder_factory: Arc<EncoderFactory>, pts_offset_metadata_key: MetadataKey) -> Self {
+        HdrTonemapStepGenerator {
+            encoder_factory,
+            pts_offset_metadata_key,
+        }
+    }
+}
+
+impl StepGenerator for HdrTonemapStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let target = match definition.parameters.get(TARGET) {
+            Some(Some(value)) => {
+                let target = value.trim().to_lowercase();
+                if !TARGETS.contains(&target.as_str()) {
+                    return Err(Box::new(StepStartupError::InvalidTarget(value.clone())));
+                }
+
+                target
+            }
+
+            _ => DEFAULT_TARGET.to_string(),
+        };
+
+        let primaries_mode = match definition.parameters.get(PRIMARIES_MODE) {
+            Some(Some(value)) => {
+                let mode = value.trim().to_lowercase();
+                if !PRIMARIES_MODES.contains(&mode.as_str()) {
+                    return Err(Box::new(StepStartupError::InvalidPrimariesMode(
+                        value.clone(),
+                    )));
+                }
+
+                mode
+            }
+
+            _ => DEFAULT_PRIMARIES_MODE.to_string(),
+        };
+
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
+        ensure_filter_elements_available(tonemapper::REQUIRED_ELEMENTS)
+            .map_err(StepStartupError::from)?;
+
+        let settings = EncoderSettings::video_from_definition(&definition, &self.encoder_factory)
+            .map_err(StepStartupError::from)?;
+
+        let filter = TonemapFilter {
+            settings: TonemapSettings {
+                target,
+                primaries_mode,
+            },
+        };
+
+        let step = FilterEncodeStep::new(
+            filter,
+            settings,
+            self.encoder_factory.clone(),
+            Some(self.pts_offset_metadata_key),
+        );
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl MediaFilter for TonemapFilter {
+    type StreamState = TonemapStream;
+    type Event = ColorSpace;
+
+    const MEDIA_TYPE: MediaType = MediaType::Video;
+    const NAME: &'static str = "tonemapper";
+
+    fn new_stream(&self, stream_name: &Arc<String>) -> Self::StreamState {
+        TonemapStream {
+            stream_name: stream_name.clone(),
+            source_color_space: None,
+        }
+    }
+
+    fn create_elements(
+        &self,
+        _stream: &Self::StreamState,
+        events: &UnboundedSender<Self::Event>,
+    ) -> Result<Vec<Element>> {
+        tonemapper::create_elements(&self.settings, events)
+    }
+
+    fn media_received(
+        &mut self,
+        _stream_id: &StreamId,
+        stream: &mut Self::StreamState,
+        content: &MediaNotificationContent,
+    ) {
+        if let MediaNotificationContent::MediaPayload {
+            is_required_for_decoding: true,
+            ..
+        } = content
+        {
+            // A new sequence header may describe a different color space, which isn't known
+            // until it's decoded
+            stream.source_color_space = None;
+        }
+    }
+
+    fn event_received(
+        &mut self,
+        stream_id: &StreamId,
+        stream: &mut Self::StreamState,
+        color_space: ColorSpace,
+    ) {
+        info!(
+            stream_id = %stream_id.0,
+            stream_name = %stream.stream_name,
+            colorimetry = %color_space.colorimetry,
+            is_hdr = color_space.is_hdr(),
+            "Detected stream's video color space"
+        );
+
+        stream.source_color_space = Some(color_space);
+    }
+
+    fn add_state_details(
+        &self,
+        streams: &[StreamSummary<'_, Self::StreamState>],
+        details: &mut HashMap<String, String>,
+    ) {
+        let input_color_spaces = per_stream_detail(streams, |stream| {
+            let color_space = match &stream.source_color_space {
+                Some(color_space) => color_space.to_string(),
+                None => "unknown".to_string(),
+            };
+
+            Some(color_space)
+        });
+
+        details.insert(INPUT_COLOR_SPACE_DETAIL.to_string(), input_color_spaces);
+        details.insert(
+            TARGET_COLOR_SPACE_DETAIL.to_string(),
+            self.settings.target.clone(),
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Gstreamer elements that convert decoded video to an SDR color space with gstreamer's
+//! `videoconvert` element.
+
+use crate::steps::hdr_tonemap::colorimetry::ColorSpace;
+use crate::utils::create_gst_element;
+use anyhow::{Context, Result};
+use gstreamer::prelude::*;
+use gstreamer::{Caps, CapsRef, Element, EventView, PadProbeData, PadProbeReturn, PadProbeType};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Gstreamer elements the tone mapping is built from
+pub const REQUIRED_ELEMENTS: &[&str] = &["videoconvert", "capsfilter"];
+
+/// How video is converted to SDR
+#[derive(Clone, Debug)]
+pub struct TonemapSettings {
+    /// The colorimetry (as named in gstreamer caps) the video is converted to
+    pub target: String,
+
+    /// The `videoconvert` element's primaries mode
+    pub primaries_mode: String,
+}
+
+/// Creates the elements that convert the color space of decoded video. The transfer function,
+/// color primaries, and matrix of the decoded video are all converted to the target colorimetry,
+/// which is set on the converted video's caps so encoders can write it into the output's VUI.
+/// Video that's already in the target color space passes through the conversion untouched.
+///
+/// The color space of the decoded video is sent out whenever it's detected.
+pub fn create_elements(
+    settings: &TonemapSettings,
+    color_space_sender: &UnboundedSender<ColorSpace>,
+) -> Result<Vec<Element>> {
+    let convert = create_gst_element("videoconvert")?;
+    let capsfilter = create_gst_element("capsfilter")?;
+
+    // By default `videoconvert` only converts the matrix, which leaves HDR video's transfer
+    // function and wide gamut primaries in place under SDR colorimetry
+    convert.set_property_from_str("gamma-mode", "remap");
+    convert.set_property_from_str("primaries-mode", &settings.primaries_mode);
+    convert.set_property_from_str("matrix-mode", "full");
+
+    // HDR video is usually 10 bit, which encoder profiles players support often can't carry
+    let caps = Caps::builder("video/x-raw")
+        .field("format", "I420")
+        .field("colorimetry", settings.target.as_str())
+        .build();
+
+    capsfilter.set_property("caps", caps);
+
+    // Report the color space of the decoded video whenever its caps are set
+    let sink_pad = convert
+        .static_pad("sink")
+        .with_context(|| "Failed to get the videoconvert element's sink pad")?;
+
+    let color_space_sender = color_space_sender.clone();
+    sink_pad.add_probe(PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+        if let Some(PadProbeData::Event(event)) = &info.data {
+            if let EventView::Caps(caps) = event.view() {
+                if let Some(color_space) = color_space_from_caps(caps.caps()) {
+                    let _ = color_space_sender.send(color_space);
+                }
+            }
+        }
+
+        PadProbeReturn::Ok
+    });
+
+    Ok(vec![convert, capsfilter])
+}
+
+fn color_space_from_caps(caps: &CapsRef) -> Option<ColorSpace> {
+    let structure = caps.structure(0)?;
+    if !structure.name().starts_with("video/") {
+        return None;
+    }
+
+    let colorimetry = structure.get::<&str>("colorimetry").ok();
+
+    Some(ColorSpace::from_colorimetry(colorimetry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GSTREAMER_INIT_RESULT;
+    use gstreamer::glib;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    fn get_enum_property(element: &Element, name: &str) -> String {
+        let value = element.property_value(name);
+        let class = glib::EnumClass::new(value.type_()).expect("Property is not an enum");
+        let number = value
+            .transform::<i32>()
+            .expect("Enum could not be converted to an i32")
+            .get::<i32>()
+            .unwrap();
+
+        class
+            .value(number)
+            .expect("Unknown enum value")
+            .nick()
+            .to_string()
+    }
+
+    fn find_element(elements: &[Element], factory_name: &str) -> Element {
+        elements
+            .iter()
+            .find(|element| {
+                element
+                    .factory()
+                    .map(|factory| factory.name() == factory_name)
+                    .unwrap_or_default()
+            })
+            .cloned()
+            .unwrap_or_else(|| panic!("No {} element was created", factory_name))
+    }
+
+    #[test]
+    fn color_conversion_elements_configured_from_settings() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let (sender, _receiver) = unbounded_channel();
+        let settings = TonemapSettings {
+            target: "bt709".to_string(),
+            primaries_mode: "fast".to_string(),
+        };
+
+        let elements = create_elements(&settings, &sender).unwrap();
+
+        let convert = find_element(&elements, "videoconvert");
+        assert_eq!(
+            get_enum_property(&convert, "gamma-mode"),
+            "remap",
+            "Unexpected gamma mode"
+        );
+        assert_eq!(
+            get_enum_property(&convert, "primaries-mode"),
+            "fast",
+            "Unexpected primaries mode"
+        );
+        assert_eq!(
+            get_enum_property(&convert, "matrix-mode"),
+            "full",
+            "Unexpected matrix mode"
+        );
+
+        let capsfilter = find_element(&elements, "capsfilter");
+        let caps = capsfilter.property::<Caps>("caps");
+        let structure = caps.structure(0).expect("Caps had no structure");
+        assert_eq!(
+            structure.get::<&str>("colorimetry").ok(),
+            Some("bt709"),
+            "Unexpected output colorimetry"
+        );
+        assert_eq!(
+            structure.get::<&str>("format").ok(),
+            Some("I420"),
+            "Unexpected output format"
+        );
+    }
+}
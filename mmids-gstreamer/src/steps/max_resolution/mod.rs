@@ -0,0 +1,239 @@
+//! The max resolution workflow step caps the resolution of each stream's video, downscaling any
+//! stream larger than the configured `max_width` and/or `max_height` while passing all other
+//! streams through untouched. This normalizes output resolution when sources of mixed sizes are
+//! published to the same workflow, without paying to re-encode sources that already fit.
+//!
+//! The source resolution of each stream is read from its video sequence header, and media
+//! received before it is held until the decision can be made. Streams whose resolution can't be
+//! read are passed through, as there's no way to know what size to downscale them to. Downscaled
+//! video keeps the source's aspect ratio.
+//!
+//! Downscaling is done the same way as the basic transcode step, with the `video` and `audio`
+//! parameters naming the encoders to use, and `video_` and `audio_` prefixed parameters being
+//! passed to the respective encoders. The video encoder's `width` and `height` parameters are set
+//! by the step for each stream.
+//!
+//! Each new video sequence header is re-evaluated, so a stream whose resolution changes mid-stream
+//! is switched between passing through and downscaling as needed. Each switch is signaled with a
+//! metadata notification containing only the `DISCONTINUITY_METADATA_KEY` key, whose value is
+//! either `passthrough` or `downscale`. Whether any stream is being downscaled, and how many streams
+//! are passing through and being downscaled, is surfaced in the step's state details.
+
+mod scaling;
+
+use crate::encoders::EncoderFactory;
+use crate::endpoints::gst_transcoder::GstTranscoderRequest;
+use crate::steps::selective_transcode::{
+    Decision, SelectiveTranscodeStep, StreamStatus, StreamSummary, TranscodeDecider,
+    TranscodeSettings, TranscodeSettingsError,
+};
+use crate::GSTREAMER_INIT_RESULT;
+use mmids_core::codecs::{read_video_resolution, VideoResolution};
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{StepCreationResult, StepStatus};
+use mmids_core::workflows::{MediaNotificationContent, MediaType};
+use mmids_core::StreamId;
+use scaling::MaxResolution;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::warn;
+
+pub const MAX_WIDTH: &str = "max_width";
+pub const MAX_HEIGHT: &str = "max_height";
+
+const WIDTH_ENCODER_PARAM: &str = "width";
+const HEIGHT_ENCODER_PARAM: &str = "height";
+
+const DOWNSCALE_MODE: &str = "downscale";
+
+const DOWNSCALING_DETAIL: &str = "downscaling";
+const DOWNSCALING_STREAMS_DETAIL: &str = "downscaling_streams";
+
+/// Creates new instances of the max resolution workflow step.
+pub struct MaxResolutionStepGenerator {
+    transcode_endpoint: UnboundedSender<GstTranscoderRequest>,
+    encoder_factory: Arc<EncoderFactory>,
+}
+
+/// Downscales streams whose video is larger than the maximum resolution
+struct MaxResolutionDecider {
+    max_resolution: MaxResolution,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "At least one of the {} or {} parameters must be specified",
+        MAX_WIDTH,
+        MAX_HEIGHT
+    )]
+    NoMaximumSpecified,
+
+    #[error("Invalid {0} value of '{1}' specified, must be a number greater than one")]
+    InvalidMaximum(&'static str, String),
+
+    #[error(transparent)]
+    InvalidTranscodeSettings(#[from] TranscodeSettingsError),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
+}
+
+impl MaxResolutionStepGenerator {
+    /// Creates the generator. The encoder factory must be the same one the transcode endpoint
+    /// uses, as it's used to validate the encoders each step is created with.
+    pub fn new(
+        transcode_endpoint: UnboundedSender<GstTranscoderRequest>,
+        encoder_factory: Arc<EncoderFactory>,
+    ) -> MaxResolutionStepGenerator {
+        MaxResolutionStepGenerator {
+            transcode_endpoint,
+            encoder_factory,
+        }
+    }
+}
+
+impl StepGenerator for MaxResolutionStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
+        let max_resolution = MaxResolution {
+            width: get_maximum(&definition, MAX_WIDTH)?,
+            height: get_maximum(&definition, MAX_HEIGHT)?,
+        };
+
+        if max_resolution.width.is_none() && max_resolution.height.is_none() {
+            return Err(Box::new(StepStartupError::NoMaximumSpecified));
+        }
+
+        let settings = TranscodeSettings::from_definition(&definition, &self.encoder_factory)
+            .map_err(StepStartupError::from)?;
+
+        let step = SelectiveTranscodeStep::new(
+            MaxResolutionDecider { max_resolution },
+            self.transcode_endpoint.clone(),
+            settings,
+            &futures_channel,
+        );
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+fn get_maximum(
+    definition: &WorkflowStepDefinition,
+    parameter: &'static str,
+) -> Result<Option<u32>, StepStartupError> {
+    match definition.parameters.get(parameter) {
+        // Dimensions are rounded down to even numbers, so a maximum of 1 can't be satisfied
+        Some(Some(value)) => match value.trim().parse::<u32>() {
+            Ok(maximum) if maximum > 1 => Ok(Some(maximum)),
+            _ => Err(StepStartupError::InvalidMaximum(parameter, value.clone())),
+        },
+
+        _ => Ok(None),
+    }
+}
+
+impl TranscodeDecider for MaxResolutionDecider {
+    type Target = VideoResolution;
+    type StreamState = Option<VideoResolution>;
+
+    const TRANSCODE_MODE: &'static str = DOWNSCALE_MODE;
+    const TRANSCODING_STREAMS_DETAIL: &'static str = DOWNSCALING_STREAMS_DETAIL;
+
+    fn new_stream(&self) -> Option<VideoResolution> {
+        None
+    }
+
+    fn evaluate(
+        &mut self,
+        stream_id: &StreamId,
+        source_resolution: &mut Option<VideoResolution>,
+        content: &MediaNotificationContent,
+        is_pending: bool,
+    ) -> Option<Decision<VideoResolution>> {
+        let (payload_type, data, is_required_for_decoding) = match content {
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type,
+                data,
+                is_required_for_decoding,
+                ..
+            } => (payload_type, data, *is_required_for_decoding),
+
+            _ => return None,
+        };
+
+        let resolution = read_video_resolution(payload_type, data, is_required_for_decoding);
+        match resolution {
+            Some(resolution) if *source_resolution != Some(resolution) => {
+                *source_resolution = Some(resolution);
+                Some(match self.max_resolution.decide(resolution) {
+                    scaling::Decision::Passthrough => Decision::Passthrough,
+                    scaling::Decision::Downscale(target) => Decision::Transcode {
+                        target,
+                        reason: format!("downscaling from {} to {}", resolution, target),
+                    },
+                })
+            }
+
+            // Nothing will tell us the resolution, so don't hold the stream
+            None if is_pending && is_required_for_decoding => {
+                Some(self.undecided(stream_id, source_resolution))
+            }
+
+            _ => None,
+        }
+    }
+
+    /// Streams whose resolution can't be read are passed through, as there's no way to know what
+    /// size to downscale them to
+    fn undecided(
+        &mut self,
+        stream_id: &StreamId,
+        _source_resolution: &Option<VideoResolution>,
+    ) -> Decision<VideoResolution> {
+        warn!(stream_id = ?stream_id, "Stream's resolution is unknown, passing it through");
+        Decision::Passthrough
+    }
+
+    fn configure_video_encoder(
+        &self,
+        resolution: &VideoResolution,
+        parameters: &mut HashMap<String, Option<String>>,
+    ) {
+        parameters.insert(
+            WIDTH_ENCODER_PARAM.to_string(),
+            Some(resolution.width.to_string()),
+        );
+
+        parameters.insert(
+            HEIGHT_ENCODER_PARAM.to_string(),
+            Some(resolution.height.to_string()),
+        );
+    }
+
+    fn add_state_details(
+        &self,
+        streams: &[StreamSummary<'_, Option<VideoResolution>, VideoResolution>],
+        details: &mut HashMap<String, String>,
+    ) {
+        let downscaling = streams
+            .iter()
+            .any(|stream| matches!(stream.status, StreamStatus::Transcoding(_)));
+
+        details.insert(DOWNSCALING_DETAIL.to_string(), downscaling.to_string());
+    }
+}
@@ -0,0 +1,127 @@
+use mmids_core::codecs::VideoResolution;
+
+/// Whether a stream's video can be passed through, or must be downscaled to a smaller resolution
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Passthrough,
+    Downscale(VideoResolution),
+}
+
+/// The largest resolution video is allowed to have. Either dimension may be unconstrained.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaxResolution {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl MaxResolution {
+    /// Decides if video of the specified resolution must be downscaled. Downscaled video keeps the
+    /// source's aspect ratio, and has its dimensions rounded down to even numbers as required by
+    /// 4:2:0 chroma subsampling.
+    pub fn decide(&self, source: VideoResolution) -> Decision {
+        let too_wide = self.width.is_some_and(|max| source.width > max);
+        let too_tall = self.height.is_some_and(|max| source.height > max);
+        if (!too_wide && !too_tall) || source.width == 0 || source.height == 0 {
+            return Decision::Passthrough;
+        }
+
+        // Both dimensions are scaled from the source, so rounding can't compound
+        let (source_width, source_height) = (source.width as u64, source.height as u64);
+        let scale_to_width = |max: u32| (max as u64, source_height * max as u64 / source_width);
+        let scale_to_height = |max: u32| (source_width * max as u64 / source_height, max as u64);
+        let (width, height) = match (self.width, self.height) {
+            (Some(max_width), Some(max_height)) => match scale_to_width(max_width) {
+                (width, height) if height <= max_height as u64 => (width, height),
+                _ => scale_to_height(max_height),
+            },
+
+            (Some(max_width), None) => scale_to_width(max_width),
+            (None, Some(max_height)) => scale_to_height(max_height),
+            (None, None) => return Decision::Passthrough,
+        };
+
+        Decision::Downscale(VideoResolution {
+            width: round_to_even(width),
+            height: round_to_even(height),
+        })
+    }
+}
+
+fn round_to_even(value: u64) -> u32 {
+    (value as u32 & !1).max(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolution(width: u32, height: u32) -> VideoResolution {
+        VideoResolution { width, height }
+    }
+
+    fn max_1080p() -> MaxResolution {
+        MaxResolution {
+            width: Some(1920),
+            height: Some(1080),
+        }
+    }
+
+    #[test]
+    fn resolution_within_maximum_is_passed_through() {
+        assert_eq!(
+            max_1080p().decide(resolution(1920, 1080)),
+            Decision::Passthrough
+        );
+
+        assert_eq!(
+            max_1080p().decide(resolution(1280, 720)),
+            Decision::Passthrough
+        );
+    }
+
+    #[test]
+    fn larger_resolution_downscaled_to_maximum() {
+        let decision = max_1080p().decide(resolution(3840, 2160));
+
+        assert_eq!(decision, Decision::Downscale(resolution(1920, 1080)));
+    }
+
+    #[test]
+    fn aspect_ratio_kept_when_downscaling() {
+        let wide = max_1080p().decide(resolution(2560, 720));
+        let tall = max_1080p().decide(resolution(1080, 1920));
+
+        assert_eq!(
+            wide,
+            Decision::Downscale(resolution(1920, 540)),
+            "Unexpected downscale of wide video"
+        );
+
+        assert_eq!(
+            tall,
+            Decision::Downscale(resolution(606, 1080)),
+            "Unexpected downscale of tall video"
+        );
+    }
+
+    #[test]
+    fn only_constrained_dimension_checked() {
+        let max = MaxResolution {
+            width: None,
+            height: Some(720),
+        };
+
+        assert_eq!(max.decide(resolution(4000, 720)), Decision::Passthrough);
+        assert_eq!(
+            max.decide(resolution(1920, 1080)),
+            Decision::Downscale(resolution(1280, 720))
+        );
+    }
+
+    #[test]
+    fn downscaled_dimensions_are_even() {
+        let decision = max_1080p().decide(resolution(2001, 1333));
+
+        assert_eq!(decision, Decision::Downscale(resolution(1620, 1080)));
+    }
+}
@@ -0,0 +1,230 @@
+//! Gstreamer pipeline that decodes video frames and encodes each one as a scaled down JPEG image.
+
+use crate::encoders::SampleResult;
+use crate::utils::{create_gst_element, set_gst_buffer, set_source_video_sequence_header};
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use gstreamer::prelude::*;
+use gstreamer::{Caps, Element, FlowError, FlowSuccess, Format, Pipeline, State};
+use gstreamer_app::{AppSink, AppSinkCallbacks, AppSrc};
+use mmids_core::VideoTimestamp;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::error;
+
+/// Gstreamer elements the JPEG encoding pipeline is built from
+pub const REQUIRED_ELEMENTS: &[&str] = &[
+    "appsrc",
+    "queue",
+    "decodebin",
+    "videoconvert",
+    "videoscale",
+    "capsfilter",
+    "jpegenc",
+    "appsink",
+];
+
+/// The size preview images are scaled to
+#[derive(Clone, Copy, Debug)]
+pub struct PreviewSize {
+    pub width: u32,
+
+    /// When not specified, the height is picked to keep the video's aspect ratio
+    pub height: Option<u32>,
+}
+
+/// Decodes video pushed into it and sends each decoded frame out as a JPEG image. Only the
+/// frames pushed in are decoded, so callers control how much decoding work is done by only
+/// pushing in the frames they want images of.
+pub struct JpegEncoder {
+    pipeline: Pipeline,
+    source: AppSrc,
+}
+
+impl JpegEncoder {
+    pub fn new(size: PreviewSize, image_sender: UnboundedSender<Bytes>) -> Result<JpegEncoder> {
+        let pipeline = Pipeline::new(None);
+        let appsrc = create_gst_element("appsrc")?;
+        let queue = create_gst_element("queue")?;
+        let decoder = create_gst_element("decodebin")?;
+        let convert = create_gst_element("videoconvert")?;
+        let scale = create_gst_element("videoscale")?;
+        let capsfilter = create_gst_element("capsfilter")?;
+        let encoder = create_gst_element("jpegenc")?;
+        let appsink = create_gst_element("appsink")?;
+
+        pipeline
+            .add_many(&[
+                &appsrc,
+                &queue,
+                &decoder,
+                &convert,
+                &scale,
+                &capsfilter,
+                &encoder,
+                &appsink,
+            ])
+            .with_context(|| "Failed to add jpeg encoder's elements to pipeline")?;
+
+        Element::link_many(&[&appsrc, &queue, &decoder])
+            .with_context(|| "Failed to link appsrc -> queue -> decoder")?;
+
+        Element::link_many(&[&convert, &scale, &capsfilter, &encoder, &appsink])
+            .with_context(|| "Failed to link convert to sink")?;
+
+        // decodebin's video pad is added dynamically
+        let link_destination = convert;
+        decoder.connect_pad_added(move |src, src_pad| {
+            if src
+                .link_pads(Some(&src_pad.name()), &link_destination, Some("sink"))
+                .is_err()
+            {
+                error!(
+                    src_caps = ?src_pad.caps(),
+                    "Failed to link `decodebin`'s {} pad to videoconvert element",
+                    src_pad.name()
+                );
+            }
+        });
+
+        let mut caps = Caps::builder("video/x-raw").field("width", size.width as i32);
+        if let Some(height) = size.height {
+            caps = caps.field("height", height as i32);
+        }
+
+        capsfilter.set_property("caps", caps.build());
+
+        let appsink = appsink
+            .dynamic_cast::<AppSink>()
+            .map_err(|_| anyhow!("appsink could not be cast to 'AppSink'"))?;
+
+        appsink.set_callbacks(
+            AppSinkCallbacks::builder()
+                .new_sample(move |sink| match SampleResult::from_sink(sink) {
+                    Ok(sample) => {
+                        let _ = image_sender.send(sample.content().clone());
+                        Ok(FlowSuccess::Ok)
+                    }
+
+                    Err(error) => {
+                        error!("new_sample callback error received: {:?}", error);
+                        Err(FlowError::Error)
+                    }
+                })
+                .build(),
+        );
+
+        let appsrc = appsrc
+            .dynamic_cast::<AppSrc>()
+            .map_err(|_| anyhow!("source element could not be cast to 'Appsrc'"))?;
+
+        appsrc.set_format(Format::Time);
+
+        pipeline
+            .set_state(State::Playing)
+            .with_context(|| "Failed to set jpeg encoder pipeline to playing")?;
+
+        Ok(JpegEncoder {
+            pipeline,
+            source: appsrc,
+        })
+    }
+
+    /// Pushes a video frame into the encoder
+    pub fn push_data(
+        &self,
+        payload_type: Arc<String>,
+        data: Bytes,
+        timestamp: VideoTimestamp,
+        is_sequence_header: bool,
+    ) -> Result<()> {
+        let buffer = set_gst_buffer(data, Some(timestamp.dts()), Some(timestamp.pts()))
+            .with_context(|| "Failed to set buffer")?;
+
+        if is_sequence_header {
+            set_source_video_sequence_header(&self.source, payload_type, buffer)
+                .with_context(|| "Failed to set sequence header for jpeg encoder")?;
+        } else {
+            self.source
+                .push_buffer(buffer)
+                .with_context(|| "Failed to push the buffer into the jpeg encoder")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for JpegEncoder {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(State::Null);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GSTREAMER_INIT_RESULT;
+    use gstreamer::Fraction;
+    use mmids_core::codecs::VIDEO_CODEC_H264_AVC;
+    use std::time::{Duration, Instant};
+    use tokio::sync::mpsc::unbounded_channel;
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 64;
+
+    #[test]
+    fn decoded_frames_encoded_as_jpeg_images() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let (sender, mut receiver) = unbounded_channel();
+        let size = PreviewSize {
+            width: 32,
+            height: Some(32),
+        };
+
+        let encoder = JpegEncoder::new(size, sender).unwrap();
+
+        // Raw video is passed straight through `decodebin`
+        let caps = Caps::builder("video/x-raw")
+            .field("format", "I420")
+            .field("width", WIDTH as i32)
+            .field("height", HEIGHT as i32)
+            .field("framerate", Fraction::new(1, 1))
+            .build();
+
+        encoder.source.set_caps(Some(&caps));
+
+        let frame = Bytes::from(vec![128_u8; WIDTH * HEIGHT * 3 / 2]);
+        for second in 0..3 {
+            let time = Duration::from_secs(second);
+            encoder
+                .push_data(
+                    VIDEO_CODEC_H264_AVC.clone(),
+                    frame.clone(),
+                    VideoTimestamp::from_durations(time, time),
+                    false,
+                )
+                .unwrap();
+        }
+
+        let _ = encoder.source.end_of_stream();
+
+        let mut images = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while images.len() < 3 && Instant::now() < deadline {
+            match receiver.try_recv() {
+                Ok(image) => images.push(image),
+                Err(_) => std::thread::sleep(Duration::from_millis(10)),
+            }
+        }
+
+        assert_eq!(images.len(), 3, "Expected one image per frame");
+        for image in images {
+            assert_eq!(
+                &image[..2],
+                &[0xff, 0xd8],
+                "Expected jpeg start of image marker"
+            );
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! The MJPEG preview workflow step serves a low frame rate preview of each stream's video over
+//! HTTP as a `multipart/x-mixed-replace` MJPEG stream. Browsers can display these with a plain
+//! `<img>` tag, which gives dashboards a live preview without needing a full video player.
+//!
+//! Previews are served on the port specified by the required `port` parameter, with each stream's
+//! preview available at `/<stream name>`. To keep the CPU cost low, only keyframes are decoded,
+//! and at most `fps` of them per second (defaulting to 1). Images are scaled to the `width`
+//! parameter (defaulting to 320 pixels), and to the `height` parameter if one is specified.
+//! Otherwise the height is picked to keep the video's aspect ratio.
+//!
+//! All media is passed through to the next step untouched.
+
+mod encoder;
+mod server;
+
+use crate::steps::mjpeg_preview::encoder::{JpegEncoder, PreviewSize};
+use crate::steps::mjpeg_preview::server::PreviewServer;
+use crate::utils::{ensure_elements_available, GstElementError};
+use crate::GSTREAMER_INIT_RESULT;
+use bytes::Bytes;
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::metadata::{MetadataKey, MetadataValue};
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use mmids_core::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use mmids_core::{StreamId, VideoTimestamp};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+pub const PORT: &str = "port";
+pub const FPS: &str = "fps";
+pub const WIDTH: &str = "width";
+pub const HEIGHT: &str = "height";
+
+const DEFAULT_FPS: u32 = 1;
+const DEFAULT_WIDTH: u32 = 320;
+
+const PORT_DETAIL: &str = "port";
+const PREVIEW_STREAMS_DETAIL: &str = "preview_streams";
+
+/// Generates new instances of the MJPEG preview workflow step
+pub struct MjpegPreviewStepGenerator {
+    is_keyframe_metadata_key: MetadataKey,
+    pts_offset_metadata_key: MetadataKey,
+}
+
+struct PreviewStream {
+    stream_name: Arc<String>,
+    image_sender: watch::Sender<Option<Bytes>>,
+    encoder: Option<JpegEncoder>,
+    last_sampled_keyframe: Option<Duration>,
+
+    /// Identifies the current encoder, so images from encoders that have been replaced can be
+    /// ignored
+    encoder_id: u64,
+}
+
+struct MjpegPreviewStep {
+    server: PreviewServer,
+    size: PreviewSize,
+    sample_interval: Duration,
+    is_keyframe_metadata_key: MetadataKey,
+    pts_offset_metadata_key: MetadataKey,
+    streams: HashMap<StreamId, PreviewStream>,
+    next_encoder_id: u64,
+}
+
+enum FutureResult {
+    ImageEncoded {
+        stream_id: StreamId,
+        encoder_id: u64,
+        image: Bytes,
+    },
+
+    // Encoders only stop when they are dropped, so there's nothing to do when this occurs
+    EncoderStopped,
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(thiserror::Error, Debug)]
+enum StepStartupError {
+    #[error("No {} parameter specified", PORT)]
+    NoPort,
+
+    #[error(
+        "Invalid {} value of '{0}'.  A number from 0 to 65535 should be specified",
+        PORT
+    )]
+    InvalidPort(String),
+
+    #[error("Invalid {0} value of '{1}'.  It must be a number greater than zero")]
+    InvalidNumber(&'static str, String),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
+
+    #[error("Previews cannot be created: {0}")]
+    MissingElement(#[from] GstElementError),
+
+    #[error("Failed to start serving previews on port {0}: {1}")]
+    ServerStartFailed(u16, hyper::Error),
+}
+
+impl MjpegPreviewStepGenerator {
+    pub fn new(
+        is_keyframe_metadata_key: MetadataKey,
+        pts_offset_metadata_key: MetadataKey,
+    ) -> Self {
+        MjpegPreviewStepGenerator {
+            is_keyframe_metadata_key,
+            pts_offset_metadata_key,
+        }
+    }
+}
+
+impl StepGenerator for MjpegPreviewStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let port = match definition.parameters.get(PORT) {
+            Some(Some(value)) => match value.trim().parse::<u16>() {
+                Ok(port) => port,
+                Err(_) => return Err(Box::new(StepStartupError::InvalidPort(value.clone()))),
+            },
+
+            _ => return Err(Box::new(StepStartupError::NoPort)),
+        };
+
+        let get_number = |name: &'static str| match definition.parameters.get(name) {
+            Some(Some(value)) => match value.trim().parse::<u32>() {
+                Ok(number) if number > 0 => Ok(Some(number)),
+                _ => Err(StepStartupError::InvalidNumber(name, value.clone())),
+            },
+
+            _ => Ok(None),
+        };
+
+        let fps = get_number(FPS)?.unwrap_or(DEFAULT_FPS);
+        let size = PreviewSize {
+            width: get_number(WIDTH)?.unwrap_or(DEFAULT_WIDTH),
+            height: get_number(HEIGHT)?,
+        };
+
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
+        if let Err(error) = ensure_elements_available(encoder::REQUIRED_ELEMENTS) {
+            return Err(Box::new(StepStartupError::MissingElement(error)));
+        }
+
+        let server = match PreviewServer::start(port) {
+            Ok(server) => server,
+            Err(error) => return Err(Box::new(StepStartupError::ServerStartFailed(port, error))),
+        };
+
+        let step = MjpegPreviewStep {
+            server,
+            size,
+            sample_interval: Duration::from_millis(1000 / fps as u64),
+            is_keyframe_metadata_key: self.is_keyframe_metadata_key,
+            pts_offset_metadata_key: self.pts_offset_metadata_key,
+            streams: HashMap::new(),
+            next_encoder_id: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl MjpegPreviewStep {
+    fn handle_media(
+        &mut self,
+        media: &MediaNotification,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                info!(
+                    stream_id = %media.stream_id.0,
+                    "Serving preview of stream {} at /{}", stream_name, stream_name
+                );
+
+                let image_sender = self.server.add_stream(stream_name.clone());
+                self.streams.insert(
+                    media.stream_id.clone(),
+                    PreviewStream {
+                        stream_name: stream_name.clone(),
+                        image_sender,
+                        encoder: None,
+                        last_sampled_keyframe: None,
+                        encoder_id: 0,
+                    },
+                );
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                if let Some(stream) = self.streams.remove(&media.stream_id) {
+                    self.server.remove_stream(&stream.stream_name);
+                }
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type,
+                timestamp,
+                metadata,
+                data,
+                is_required_for_decoding,
+            } => {
+                let sample_interval = self.sample_interval;
+                let stream = match self.streams.get_mut(&media.stream_id) {
+                    Some(stream) => stream,
+                    None => return,
+                };
+
+                let mut is_keyframe = false;
+                let mut pts_offset = 0;
+                for item in metadata.iter() {
+                    if item.key() == self.is_keyframe_metadata_key {
+                        is_keyframe = matches!(item.value(), MetadataValue::Bool(true));
+                    } else if item.key() == self.pts_offset_metadata_key {
+                        if let MetadataValue::I32(offset) = item.value() {
+                            pts_offset = offset;
+                        }
+                    }
+                }
+
+                let pts = Duration::from_millis(timestamp.as_millis() as u64 + pts_offset as u64);
+                if *is_required_for_decoding {
+                    // Each sequence header may describe a different format, so a new encoder
+                    // is needed to decode it
+                    self.next_encoder_id += 1;
+                    stream.encoder_id = self.next_encoder_id;
+                    stream.last_sampled_keyframe = None;
+                    stream.encoder = start_encoder(
+                        &media.stream_id,
+                        stream.encoder_id,
+                        self.size,
+                        futures_channel,
+                    );
+                } else {
+                    // Only keyframes can be decoded without the frames before them, so only
+                    // sampled keyframes are decoded
+                    let is_sample_due = match stream.last_sampled_keyframe {
+                        Some(last) => pts < last || pts - last >= sample_interval,
+                        None => true,
+                    };
+
+                    if !is_keyframe || !is_sample_due {
+                        return;
+                    }
+
+                    stream.last_sampled_keyframe = Some(pts);
+                }
+
+                if let Some(encoder) = &stream.encoder {
+                    let result = encoder.push_data(
+                        payload_type.clone(),
+                        data.clone(),
+                        VideoTimestamp::from_durations(*timestamp, pts),
+                        *is_required_for_decoding,
+                    );
+
+                    if let Err(error) = result {
+                        warn!(
+                            stream_id = %media.stream_id.0,
+                            "Failed to push video into the preview encoder, no longer updating \
+                            the stream's preview: {:?}", error
+                        );
+
+                        stream.encoder = None;
+                    }
+                }
+            }
+
+            MediaNotificationContent::MediaPayload { .. } => (),
+            MediaNotificationContent::Metadata { .. } => (),
+        }
+    }
+}
+
+impl WorkflowStep for MjpegPreviewStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            match notification.downcast::<FutureResult>() {
+                Ok(result) => match *result {
+                    FutureResult::EncoderStopped => (),
+                    FutureResult::ImageEncoded {
+                        stream_id,
+                        encoder_id,
+                        image,
+                    } => {
+                        if let Some(stream) = self.streams.get(&stream_id) {
+                            if stream.encoder_id == encoder_id {
+                                let _ = stream.image_sender.send(Some(image));
+                            }
+                        }
+                    }
+                },
+
+                Err(_) => {
+                    error!("Received future result that could not be casted to the internal future result type");
+                }
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            self.handle_media(&media, &futures_channel);
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut stream_names = self
+            .streams
+            .values()
+            .map(|stream| stream.stream_name.to_string())
+            .collect::<Vec<_>>();
+
+        stream_names.sort();
+
+        let mut details = HashMap::new();
+        details.insert(
+            PORT_DETAIL.to_string(),
+            self.server.local_address().port().to_string(),
+        );
+
+        details.insert(PREVIEW_STREAMS_DETAIL.to_string(), stream_names.join(", "));
+
+        details
+    }
+
+    fn get_active_pipeline_count(&self) -> usize {
+        self.streams
+            .values()
+            .filter(|stream| stream.encoder.is_some())
+            .count()
+    }
+}
+
+fn start_encoder(
+    stream_id: &StreamId,
+    encoder_id: u64,
+    size: PreviewSize,
+    futures_channel: &WorkflowStepFuturesChannel,
+) -> Option<JpegEncoder> {
+    let (sender, receiver) = unbounded_channel();
+    let encoder = match JpegEncoder::new(size, sender) {
+        Ok(encoder) => encoder,
+        Err(error) => {
+            error!(
+                stream_id = %stream_id.0,
+                "Failed to create preview encoder, no preview will be served for the stream: {:?}",
+                error
+            );
+
+            return None;
+        }
+    };
+
+    let stream_id = stream_id.clone();
+    futures_channel.send_on_generic_unbounded_recv(
+        receiver,
+        move |image| FutureResult::ImageEncoded {
+            stream_id: stream_id.clone(),
+            encoder_id,
+            image,
+        },
+        || FutureResult::EncoderStopped,
+    );
+
+    Some(encoder)
+}
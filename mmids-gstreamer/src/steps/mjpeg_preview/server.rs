@@ -0,0 +1,251 @@
+//! HTTP server that serves the latest preview image of each stream as a
+//! `multipart/x-mixed-replace` MJPEG stream, which browsers can display with a plain `<img>` tag.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use tokio::sync::watch;
+use tracing::{error, info};
+
+/// Separates each image in the MJPEG stream
+const BOUNDARY: &str = "mmidspreviewframe";
+
+type PreviewMap = Arc<Mutex<HashMap<Arc<String>, watch::Receiver<Option<Bytes>>>>>;
+
+/// Serves preview images over HTTP at `/<stream name>`. Clients are sent the most recent image
+/// of the stream as soon as they connect, and then each new image as it's published. A client's
+/// response ends when the stream it's watching is removed.
+pub struct PreviewServer {
+    previews: PreviewMap,
+    local_address: SocketAddr,
+    shutdown_sender: Option<oneshot::Sender<()>>,
+}
+
+impl PreviewServer {
+    /// Starts serving previews on the specified port
+    pub fn start(port: u16) -> Result<PreviewServer, hyper::Error> {
+        let previews = PreviewMap::default();
+        let service_previews = previews.clone();
+        let service = make_service_fn(move |_socket: &AddrStream| {
+            let previews = service_previews.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |request: Request<Body>| {
+                    serve_preview(request, previews.clone())
+                }))
+            }
+        });
+
+        let bind_address = SocketAddr::from(([0, 0, 0, 0], port));
+        let server = Server::try_bind(&bind_address)?.serve(service);
+        let local_address = server.local_addr();
+
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+        let server = server.with_graceful_shutdown(async {
+            let _ = shutdown_receiver.await;
+        });
+
+        info!("Serving MJPEG previews on {}", local_address);
+        tokio::spawn(async move {
+            if let Err(error) = server.await {
+                error!("MJPEG preview server stopped with an error: {:?}", error);
+            }
+        });
+
+        Ok(PreviewServer {
+            previews,
+            local_address,
+            shutdown_sender: Some(shutdown_sender),
+        })
+    }
+
+    /// The address the server is listening on
+    pub fn local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+
+    /// Makes a stream's preview available to clients. Images sent on the returned channel are
+    /// forwarded to every client watching the stream, and dropping the channel disconnects them.
+    pub fn add_stream(&self, stream_name: Arc<String>) -> watch::Sender<Option<Bytes>> {
+        let (sender, receiver) = watch::channel(None);
+        self.previews.lock().unwrap().insert(stream_name, receiver);
+
+        sender
+    }
+
+    /// Stops new clients from watching the stream's preview
+    pub fn remove_stream(&self, stream_name: &Arc<String>) {
+        self.previews.lock().unwrap().remove(stream_name);
+    }
+}
+
+impl Drop for PreviewServer {
+    fn drop(&mut self) {
+        if let Some(sender) = self.shutdown_sender.take() {
+            let _ = sender.send(());
+        }
+    }
+}
+
+async fn serve_preview(
+    request: Request<Body>,
+    previews: PreviewMap,
+) -> Result<Response<Body>, hyper::Error> {
+    let stream_name = request.uri().path().trim_start_matches('/').to_string();
+    let receiver = previews.lock().unwrap().get(&stream_name).cloned();
+    let mut receiver = match receiver {
+        Some(receiver) => receiver,
+        None => {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_FOUND;
+
+            return Ok(response);
+        }
+    };
+
+    let (mut body_sender, body) = Body::channel();
+    tokio::spawn(async move {
+        loop {
+            let image = receiver.borrow_and_update().clone();
+            if let Some(image) = image {
+                if body_sender.send_data(to_multipart(&image)).await.is_err() {
+                    break; // client disconnected
+                }
+            }
+
+            if receiver.changed().await.is_err() {
+                break; // stream is gone
+            }
+        }
+    });
+
+    let mut response = Response::new(body);
+    let headers = response.headers_mut();
+    headers.insert(
+        CONTENT_TYPE,
+        format!("multipart/x-mixed-replace; boundary={}", BOUNDARY)
+            .parse()
+            .unwrap(),
+    );
+
+    headers.insert(CACHE_CONTROL, "no-cache".parse().unwrap());
+
+    Ok(response)
+}
+
+fn to_multipart(image: &Bytes) -> Bytes {
+    let header = format!(
+        "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+        BOUNDARY,
+        image.len()
+    );
+
+    let mut part = BytesMut::with_capacity(header.len() + image.len() + 2);
+    part.put_slice(header.as_bytes());
+    part.put_slice(image);
+    part.put_slice(b"\r\n");
+
+    part.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::time::timeout;
+
+    async fn get(server: &PreviewServer, path: &str) -> TcpStream {
+        let address = SocketAddr::from(([127, 0, 0, 1], server.local_address().port()));
+        let mut client = TcpStream::connect(address)
+            .await
+            .expect("Failed to connect to preview server");
+
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        client
+            .write_all(request.as_bytes())
+            .await
+            .expect("Failed to send request");
+
+        client
+    }
+
+    /// Reads from the client until the response contains the expected bytes
+    async fn read_until(client: &mut TcpStream, expected: &[u8]) -> Vec<u8> {
+        let mut response = Vec::new();
+        let result = timeout(Duration::from_secs(5), async {
+            let mut buffer = [0_u8; 1024];
+            while !response
+                .windows(expected.len())
+                .any(|window| window == expected)
+            {
+                let count = client.read(&mut buffer).await.expect("Failed to read");
+                if count == 0 {
+                    break;
+                }
+
+                response.extend_from_slice(&buffer[..count]);
+            }
+        })
+        .await;
+
+        assert!(result.is_ok(), "Timed out reading the response");
+        response
+    }
+
+    #[tokio::test]
+    async fn client_receives_jpeg_frames_for_stream() {
+        let server = PreviewServer::start(0).expect("Failed to start server");
+        let sender = server.add_stream(Arc::new("abc".to_string()));
+
+        let first_image = Bytes::from_static(&[0xff, 0xd8, 1, 2, 3, 0xff, 0xd9]);
+        sender.send(Some(first_image.clone())).unwrap();
+
+        let mut client = get(&server, "/abc").await;
+        let response = read_until(&mut client, &first_image).await;
+        let response = String::from_utf8_lossy(&response);
+
+        assert!(
+            response.starts_with("HTTP/1.1 200"),
+            "Unexpected response: {}",
+            response
+        );
+
+        assert!(
+            response.contains(&format!("multipart/x-mixed-replace; boundary={}", BOUNDARY)),
+            "Expected multipart content type: {}",
+            response
+        );
+
+        assert!(
+            response.contains(&format!("--{}\r\nContent-Type: image/jpeg", BOUNDARY)),
+            "Expected jpeg frame boundary: {}",
+            response
+        );
+
+        // Later images are sent to clients that are already connected
+        let second_image = Bytes::from_static(&[0xff, 0xd8, 4, 5, 6, 0xff, 0xd9]);
+        sender.send(Some(second_image.clone())).unwrap();
+        read_until(&mut client, &second_image).await;
+    }
+
+    #[tokio::test]
+    async fn not_found_returned_for_unknown_stream() {
+        let server = PreviewServer::start(0).expect("Failed to start server");
+        let mut client = get(&server, "/abc").await;
+        let response = read_until(&mut client, b"\r\n").await;
+        let response = String::from_utf8_lossy(&response);
+
+        assert!(
+            response.starts_with("HTTP/1.1 404"),
+            "Unexpected response: {}",
+            response
+        );
+    }
+}
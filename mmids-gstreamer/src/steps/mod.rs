@@ -1,3 +1,23 @@
 //! Workflow steps dealing with gstreamer based endpoints
 
+pub mod audio_channels;
+pub mod audio_levels;
+pub mod audio_mix;
+pub mod audio_resample;
+pub mod audio_watermark;
 pub mod basic_transcoder;
+pub mod bitrate_normalize;
+pub mod cfr;
+pub mod conditional_transcode;
+pub mod custom_gst;
+pub mod deinterlace;
+pub mod gop_normalize;
+pub mod hdr_tonemap;
+pub mod max_resolution;
+pub mod mjpeg_preview;
+pub mod pip_composite;
+pub mod qc_monitor;
+pub mod quality_measure;
+
+mod filter_encode;
+mod selective_transcode;
@@ -0,0 +1,323 @@
+//! Gstreamer pipeline that decodes two video sources, composites the secondary source on top of
+//! the primary source with gstreamer's `compositor` element, and re-encodes the result with an
+//! encoder from the encoder factory.
+
+use crate::encoders::EncoderFactory;
+use crate::steps::filter_encode::{EncoderPipeline, EncoderSettings};
+use crate::utils::{
+    configure_source_queue, create_gst_element, push_buffer_to_source, set_gst_buffer,
+    set_source_video_sequence_header,
+};
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use gstreamer::prelude::*;
+use gstreamer::{Caps, Element, Format, Fraction, Pad, Pipeline, State};
+use gstreamer_app::AppSrc;
+use mmids_core::workflows::MediaNotificationContent;
+use mmids_core::VideoTimestamp;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::error;
+
+/// Gstreamer elements the compositing pipeline is built from
+pub const REQUIRED_ELEMENTS: &[&str] = &[
+    "appsrc",
+    "queue",
+    "decodebin",
+    "videoconvert",
+    "videorate",
+    "capsfilter",
+    "compositor",
+    "appsink",
+];
+
+/// The video sources being composited together
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompositeSource {
+    Primary,
+    Secondary,
+}
+
+/// Where the secondary video is placed on top of the primary video, in pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PipLayout {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// How the sources are composited and the format of the composited video
+#[derive(Clone, Copy, Debug)]
+pub struct CompositeSettings {
+    pub layout: PipLayout,
+    pub fps: u32,
+}
+
+/// Composites video pushed into it for each source, sending the encoded video out through the
+/// media sender. The encoder sends its sequence header before the first encoded frame.
+///
+/// Both sources are converted to the same frame rate before being composited, and are
+/// composited based on their timestamps, so both sources must be pushed on the same timeline.
+/// The composited video is the size of the primary video, unless the secondary video is placed
+/// past its edges.
+pub struct Compositor {
+    pipeline: Pipeline,
+    primary_source: AppSrc,
+    secondary_source: AppSrc,
+    _encoder: EncoderPipeline,
+}
+
+impl Compositor {
+    pub fn new(
+        settings: CompositeSettings,
+        encoder_settings: &EncoderSettings,
+        encoder_factory: &EncoderFactory,
+        media_sender: UnboundedSender<MediaNotificationContent>,
+    ) -> Result<Compositor> {
+        let pipeline = Pipeline::new(None);
+        let compositor = create_gst_element("compositor")?;
+        let convert = create_gst_element("videoconvert")?;
+        let capsfilter = create_gst_element("capsfilter")?;
+
+        pipeline
+            .add_many(&[&compositor, &convert, &capsfilter])
+            .with_context(|| "Failed to add compositor's elements to pipeline")?;
+
+        Element::link_many(&[&compositor, &convert, &capsfilter])
+            .with_context(|| "Failed to link compositor -> convert -> capsfilter")?;
+
+        // Without this the composited video starts at a timestamp of zero, and would be filled
+        // with black frames up until the timestamp of the first source frame
+        compositor.set_property_from_str("start-time-selection", "first");
+
+        // The compositor outputs a format with an alpha channel when nothing downstream asks
+        // for another one, which encoders can't take
+        let caps = Caps::builder("video/x-raw").field("format", "I420").build();
+        capsfilter.set_property("caps", caps);
+
+        let (primary_source, primary_pad) = add_source_branch(
+            &pipeline,
+            &compositor,
+            CompositeSource::Primary,
+            settings.fps,
+        )?;
+
+        primary_pad.set_property("zorder", 0_u32);
+        primary_pad.set_property("xpos", 0_i32);
+        primary_pad.set_property("ypos", 0_i32);
+
+        let (secondary_source, secondary_pad) = add_source_branch(
+            &pipeline,
+            &compositor,
+            CompositeSource::Secondary,
+            settings.fps,
+        )?;
+
+        // The compositor scales the secondary video to the layout's size
+        secondary_pad.set_property("zorder", 1_u32);
+        secondary_pad.set_property("xpos", settings.layout.x);
+        secondary_pad.set_property("ypos", settings.layout.y);
+        secondary_pad.set_property("width", settings.layout.width);
+        secondary_pad.set_property("height", settings.layout.height);
+
+        let encoder = EncoderPipeline::new(
+            encoder_settings,
+            encoder_factory,
+            &pipeline,
+            &capsfilter,
+            media_sender,
+        )?;
+
+        pipeline
+            .set_state(State::Playing)
+            .with_context(|| "Failed to set compositor pipeline to playing")?;
+
+        Ok(Compositor {
+            pipeline,
+            primary_source,
+            secondary_source,
+            _encoder: encoder,
+        })
+    }
+
+    /// Pushes a video frame from the specified source into the compositor. If the compositor has
+    /// fallen too far behind, the frame is rejected with an `EncoderQueueFullError`.
+    pub fn push_data(
+        &self,
+        source: CompositeSource,
+        payload_type: Arc<String>,
+        data: Bytes,
+        timestamp: VideoTimestamp,
+        is_sequence_header: bool,
+    ) -> Result<()> {
+        let appsrc = match source {
+            CompositeSource::Primary => &self.primary_source,
+            CompositeSource::Secondary => &self.secondary_source,
+        };
+
+        let buffer = set_gst_buffer(data, Some(timestamp.dts()), Some(timestamp.pts()))
+            .with_context(|| "Failed to set buffer")?;
+
+        if is_sequence_header {
+            set_source_video_sequence_header(appsrc, payload_type, buffer)
+                .with_context(|| "Failed to set sequence header for compositor")?;
+        } else {
+            push_buffer_to_source(appsrc, buffer)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Compositor {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(State::Null);
+    }
+}
+
+/// Adds `appsrc -> queue -> decodebin -> videoconvert -> videorate -> capsfilter` to the
+/// pipeline for a source, linked to a new sink pad requested from the compositor. Returns the
+/// source and the compositor pad, so the pad's placement can be configured.
+fn add_source_branch(
+    pipeline: &Pipeline,
+    compositor: &Element,
+    source: CompositeSource,
+    fps: u32,
+) -> Result<(AppSrc, Pad)> {
+    let appsrc = create_gst_element("appsrc")?;
+    let queue = create_gst_element("queue")?;
+    let decoder = create_gst_element("decodebin")?;
+    let convert = create_gst_element("videoconvert")?;
+    let rate = create_gst_element("videorate")?;
+    let capsfilter = create_gst_element("capsfilter")?;
+
+    pipeline
+        .add_many(&[&appsrc, &queue, &decoder, &convert, &rate, &capsfilter])
+        .with_context(|| format!("Failed to add {:?} source's elements to pipeline", source))?;
+
+    Element::link_many(&[&appsrc, &queue, &decoder])
+        .with_context(|| "Failed to link appsrc -> queue -> decoder")?;
+
+    Element::link_many(&[&convert, &rate, &capsfilter])
+        .with_context(|| "Failed to link convert -> rate -> capsfilter")?;
+
+    // decodebin's video pad is added dynamically
+    let link_destination = convert;
+    decoder.connect_pad_added(move |src, src_pad| {
+        if src
+            .link_pads(Some(&src_pad.name()), &link_destination, Some("sink"))
+            .is_err()
+        {
+            error!(
+                src_caps = ?src_pad.caps(),
+                "Failed to link `decodebin`'s {} pad to videoconvert element",
+                src_pad.name()
+            );
+        }
+    });
+
+    // Sources with different frame rates are converted to the same one, so every composited
+    // frame has a matching frame from both sources
+    let caps = Caps::builder("video/x-raw")
+        .field("framerate", Fraction::new(fps as i32, 1))
+        .build();
+
+    capsfilter.set_property("caps", caps);
+
+    let compositor_pad = compositor
+        .request_pad_simple("sink_%u")
+        .with_context(|| "Failed to request a sink pad from the compositor")?;
+
+    capsfilter
+        .static_pad("src")
+        .with_context(|| "capsfilter has no src pad")?
+        .link(&compositor_pad)
+        .with_context(|| format!("Failed to link {:?} source to compositor", source))?;
+
+    let appsrc = appsrc
+        .dynamic_cast::<AppSrc>()
+        .map_err(|_| anyhow!("source element could not be cast to 'Appsrc'"))?;
+
+    appsrc.set_format(Format::Time);
+    configure_source_queue(&appsrc);
+
+    Ok((appsrc, compositor_pad))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoders::VideoDropEncoderGenerator;
+    use crate::GSTREAMER_INIT_RESULT;
+    use mmids_core::workflows::MediaType;
+    use std::collections::HashMap;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    #[test]
+    fn compositor_wired_with_sink_pad_for_each_source() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let mut encoder_factory = EncoderFactory::new();
+        encoder_factory
+            .register_video_encoder("drop", Box::new(VideoDropEncoderGenerator {}))
+            .unwrap();
+
+        let encoder_settings = EncoderSettings {
+            media_type: MediaType::Video,
+            encoder_name: "drop".to_string(),
+            parameters: HashMap::new(),
+        };
+
+        let (sender, _receiver) = unbounded_channel();
+        let settings = CompositeSettings {
+            layout: PipLayout {
+                x: 20,
+                y: 30,
+                width: 320,
+                height: 180,
+            },
+            fps: 30,
+        };
+
+        let compositor =
+            Compositor::new(settings, &encoder_settings, &encoder_factory, sender).unwrap();
+        let element = compositor
+            .pipeline
+            .iterate_elements()
+            .into_iter()
+            .filter_map(|element| element.ok())
+            .find(|element| {
+                element
+                    .factory()
+                    .map(|factory| factory.name() == "compositor")
+                    .unwrap_or_default()
+            })
+            .expect("No compositor element found in the pipeline");
+
+        let sink_pads = element.sink_pads();
+        assert_eq!(sink_pads.len(), 2, "Expected a sink pad for each source");
+        assert!(
+            sink_pads.iter().all(|pad| pad.is_linked()),
+            "Expected every sink pad to be linked to a source"
+        );
+
+        let secondary_pad = sink_pads
+            .iter()
+            .find(|pad| pad.property::<u32>("zorder") == 1)
+            .expect("No sink pad for the secondary source");
+
+        assert_eq!(secondary_pad.property::<i32>("xpos"), 20, "Unexpected x");
+        assert_eq!(secondary_pad.property::<i32>("ypos"), 30, "Unexpected y");
+        assert_eq!(
+            secondary_pad.property::<i32>("width"),
+            320,
+            "Unexpected width"
+        );
+        assert_eq!(
+            secondary_pad.property::<i32>("height"),
+            180,
+            "Unexpected height"
+        );
+    }
+}
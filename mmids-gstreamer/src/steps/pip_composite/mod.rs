@@ -0,0 +1,772 @@
+//! The picture-in-picture composite workflow step places the video of a secondary stream on top
+//! of the video of a primary stream, for basic multi-camera production layouts. The primary
+//! stream is identified by the required `primary_stream` parameter and the secondary stream by
+//! the required `secondary_stream` parameter, both by stream name.
+//!
+//! The secondary video is scaled to `width`x`height` pixels (defaulting to 480x270) and placed
+//! with its top left corner at the `x` and `y` pixel coordinates of the primary video (defaulting
+//! to 0,0). Both sources are decoded, converted to the `fps` frame rate (defaulting to 30),
+//! composited with gstreamer's `compositor` element, and re-encoded. The composited video replaces
+//! the primary stream's video. The composited video is encoded the same way as the basic transcode
+//! step, with the `video` parameter naming the encoder to use and `video_` prefixed parameters
+//! being passed to it.
+//!
+//! The `audio_source` parameter can be `primary` (the default) or `secondary`, and selects which
+//! source's audio is passed on with the composited video. The secondary's timestamps are shifted
+//! onto the primary stream's timeline when compositing starts, so the composited video (and the
+//! secondary's audio) keeps the primary stream's timestamps.
+//!
+//! Compositing starts on the first primary keyframe after both streams have sent video. If the
+//! secondary disconnects or stops sending video for more than a second, the primary stream's own
+//! video and audio are passed through again until the secondary's video resumes. New sequence
+//! headers are sent each time the stream switches between composited and uncomposited media.
+//!
+//! The secondary stream's media is never passed through, while all other streams are passed
+//! through untouched. The layout and whether the sources are being composited are reported
+//! through the step's state details.
+
+mod compositor;
+
+use crate::encoders::{EncoderFactory, EncoderQueueFullError};
+use crate::steps::filter_encode::{EncoderSettings, EncoderSettingsError};
+use crate::steps::pip_composite::compositor::{
+    CompositeSettings, CompositeSource, Compositor, PipLayout,
+};
+use crate::utils::{ensure_elements_available, GstElementError};
+use crate::GSTREAMER_INIT_RESULT;
+use bytes::{Bytes, BytesMut};
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::metadata::{MediaPayloadMetadataCollection, MetadataKey, MetadataValue};
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use mmids_core::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use mmids_core::{StreamId, VideoTimestamp};
+use std::collections::HashMap;
+use std::iter;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::unbounded_channel;
+use tracing::{error, info, warn};
+
+pub const PRIMARY_STREAM: &str = "primary_stream";
+pub const SECONDARY_STREAM: &str = "secondary_stream";
+pub const X: &str = "x";
+pub const Y: &str = "y";
+pub const WIDTH: &str = "width";
+pub const HEIGHT: &str = "height";
+pub const FPS: &str = "fps";
+pub const AUDIO_SOURCE: &str = "audio_source";
+
+const DEFAULT_WIDTH: u32 = 480;
+const DEFAULT_HEIGHT: u32 = 270;
+const DEFAULT_FPS: u32 = 30;
+
+/// How far behind the primary stream's video the secondary's video can fall before the secondary
+/// is considered gone and compositing stops
+const SECONDARY_TIMEOUT: Duration = Duration::from_secs(1);
+
+const IS_COMPOSITING_DETAIL: &str = "is_compositing";
+const PIP_POSITION_DETAIL: &str = "pip_position";
+const PIP_SIZE_DETAIL: &str = "pip_size";
+const AUDIO_SOURCE_DETAIL: &str = "audio_source";
+
+/// Generates new instances of the picture-in-picture composite workflow step
+pub struct PipCompositeStepGenerator {
+    encoder_factory: Arc<EncoderFactory>,
+    is_keyframe_metadata_key: MetadataKey,
+    pts_offset_metadata_key: MetadataKey,
+}
+
+/// Media state of one of the sources being composited
+#[derive(Default)]
+struct SourceState {
+    stream_id: Option<StreamId>,
+    video_sequence_header: Option<(Arc<String>, Bytes)>,
+    audio_sequence_header: Option<(Arc<String>, Bytes)>,
+
+    /// Decoding timestamp of the most recent video frame, on the source's own timeline
+    last_video_dts: Option<Duration>,
+}
+
+struct ActiveComposite {
+    compositor: Compositor,
+
+    /// Identifies the compositor, so video from compositors that have been replaced can be
+    /// ignored
+    compositor_id: u64,
+
+    /// Amount added to the secondary's timestamps to shift them onto the primary's timeline
+    secondary_offset_ms: i64,
+
+    /// Secondary video is only pushed into the compositor once a keyframe has been seen, since
+    /// frames before it can't be decoded
+    has_secondary_keyframe: bool,
+    is_dropping_frames: bool,
+}
+
+struct PipCompositeStep {
+    primary_stream_name: Arc<String>,
+    secondary_stream_name: Arc<String>,
+    audio_source: CompositeSource,
+    settings: CompositeSettings,
+    encoder_settings: EncoderSettings,
+    encoder_factory: Arc<EncoderFactory>,
+    is_keyframe_metadata_key: MetadataKey,
+    pts_offset_metadata_key: MetadataKey,
+    primary: SourceState,
+    secondary: SourceState,
+    composite: Option<ActiveComposite>,
+    next_compositor_id: u64,
+    metadata_buffer: BytesMut,
+}
+
+enum FutureResult {
+    VideoComposited {
+        compositor_id: u64,
+        content: MediaNotificationContent,
+    },
+
+    // Compositors only stop when they are dropped, so there's nothing to do when this occurs
+    CompositorStopped,
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(thiserror::Error, Debug)]
+enum StepStartupError {
+    #[error("No {0} parameter specified")]
+    NoStreamName(&'static str),
+
+    #[error(
+        "The {} and {} parameters must be different streams",
+        PRIMARY_STREAM,
+        SECONDARY_STREAM
+    )]
+    SameStreams,
+
+    #[error("Invalid {0} value of '{1}'.  It must be a number zero or greater")]
+    InvalidPosition(&'static str, String),
+
+    #[error("Invalid {0} value of '{1}'.  It must be a number greater than zero")]
+    InvalidNumber(&'static str, String),
+
+    #[error(
+        "Invalid {} value of '{0}'.  It must be either 'primary' or 'secondary'",
+        AUDIO_SOURCE
+    )]
+    InvalidAudioSource(String),
+
+    #[error(transparent)]
+    InvalidEncoderSettings(#[from] EncoderSettingsError),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
+
+    #[error("Video cannot be composited: {0}")]
+    MissingElement(#[from] GstElementError),
+}
+
+impl PipCompositeStepGenerator {
+    /// Creates the generator, with the encoder factory composited video is encoded with
+    pub fn new(
+        encoder_factory: Arc<EncoderFactory>,
+        is_keyframe_metadata_key: MetadataKey,
+        pts_offset_metadata_key: MetadataKey,
+    ) -> Self {
+        PipCompositeStepGenerator {
+            encoder_factory,
+            is_keyframe_metadata_key,
+            pts_offset_metadata_key,
+        }
+    }
+}
+
+impl StepGenerator for PipCompositeStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        let get_stream_name = |name: &'static str| match definition.parameters.get(name) {
+            Some(Some(value)) if !value.trim().is_empty() => Ok(Arc::new(value.trim().to_string())),
+            _ => Err(StepStartupError::NoStreamName(name)),
+        };
+
+        let primary_stream_name = get_stream_name(PRIMARY_STREAM)?;
+        let secondary_stream_name = get_stream_name(SECONDARY_STREAM)?;
+        if primary_stream_name == secondary_stream_name {
+            return Err(Box::new(StepStartupError::SameStreams));
+        }
+
+        let get_position = |name: &'static str| match definition.parameters.get(name) {
+            Some(Some(value)) => match value.trim().parse::<u32>() {
+                Ok(number) => Ok(number),
+                Err(_) => Err(StepStartupError::InvalidPosition(name, value.clone())),
+            },
+
+            _ => Ok(0),
+        };
+
+        let get_number = |name: &'static str| match definition.parameters.get(name) {
+            Some(Some(value)) => match value.trim().parse::<u32>() {
+                Ok(number) if number > 0 => Ok(Some(number)),
+                _ => Err(StepStartupError::InvalidNumber(name, value.clone())),
+            },
+
+            _ => Ok(None),
+        };
+
+        let audio_source = match definition.parameters.get(AUDIO_SOURCE) {
+            Some(Some(value)) => match value.trim().to_lowercase().as_str() {
+                "primary" => CompositeSource::Primary,
+                "secondary" => CompositeSource::Secondary,
+                _ => {
+                    return Err(Box::new(StepStartupError::InvalidAudioSource(
+                        value.clone(),
+                    )))
+                }
+            },
+
+            _ => CompositeSource::Primary,
+        };
+
+        let settings = CompositeSettings {
+            layout: PipLayout {
+                x: get_position(X)? as i32,
+                y: get_position(Y)? as i32,
+                width: get_number(WIDTH)?.unwrap_or(DEFAULT_WIDTH) as i32,
+                height: get_number(HEIGHT)?.unwrap_or(DEFAULT_HEIGHT) as i32,
+            },
+            fps: get_number(FPS)?.unwrap_or(DEFAULT_FPS),
+        };
+
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
+        if let Err(error) = ensure_elements_available(compositor::REQUIRED_ELEMENTS) {
+            return Err(Box::new(StepStartupError::MissingElement(error)));
+        }
+
+        let encoder_settings =
+            EncoderSettings::video_from_definition(&definition, &self.encoder_factory)
+                .map_err(StepStartupError::from)?;
+
+        let step = PipCompositeStep {
+            primary_stream_name,
+            secondary_stream_name,
+            audio_source,
+            settings,
+            encoder_settings,
+            encoder_factory: self.encoder_factory.clone(),
+            is_keyframe_metadata_key: self.is_keyframe_metadata_key,
+            pts_offset_metadata_key: self.pts_offset_metadata_key,
+            primary: SourceState::default(),
+            secondary: SourceState::default(),
+            composite: None,
+            next_compositor_id: 0,
+            metadata_buffer: BytesMut::new(),
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl PipCompositeStep {
+    fn handle_media(
+        &mut self,
+        media: MediaNotification,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let is_primary = self.primary.stream_id.as_ref() == Some(&media.stream_id);
+        let is_secondary = self.secondary.stream_id.as_ref() == Some(&media.stream_id);
+
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                if *stream_name == self.primary_stream_name {
+                    self.composite = None;
+                    self.primary = SourceState {
+                        stream_id: Some(media.stream_id.clone()),
+                        ..Default::default()
+                    };
+
+                    outputs.media.push(media);
+                } else if *stream_name == self.secondary_stream_name {
+                    self.stop_compositing(outputs);
+                    self.secondary = SourceState {
+                        stream_id: Some(media.stream_id.clone()),
+                        ..Default::default()
+                    };
+                } else {
+                    outputs.media.push(media);
+                }
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                if is_primary {
+                    self.composite = None;
+                    self.primary = SourceState::default();
+                    outputs.media.push(media);
+                } else if is_secondary {
+                    info!(
+                        "Secondary stream {} disconnected",
+                        self.secondary_stream_name
+                    );
+
+                    self.stop_compositing(outputs);
+                    self.secondary = SourceState::default();
+                } else {
+                    outputs.media.push(media);
+                }
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                ..
+            } if is_primary || is_secondary => {
+                let source = if is_primary {
+                    CompositeSource::Primary
+                } else {
+                    CompositeSource::Secondary
+                };
+
+                self.handle_video(source, media, outputs, futures_channel);
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Audio,
+                payload_type,
+                timestamp,
+                data,
+                is_required_for_decoding,
+                ..
+            } if is_primary || is_secondary => {
+                let state = if is_primary {
+                    &mut self.primary
+                } else {
+                    &mut self.secondary
+                };
+
+                if *is_required_for_decoding {
+                    state.audio_sequence_header = Some((payload_type.clone(), data.clone()));
+                }
+
+                let audio_source = match &self.composite {
+                    Some(_) => self.audio_source,
+                    None => CompositeSource::Primary,
+                };
+
+                match (audio_source, &self.composite) {
+                    (CompositeSource::Primary, _) if is_primary => outputs.media.push(media),
+                    (CompositeSource::Secondary, Some(composite)) if is_secondary => {
+                        if let Some(stream_id) = &self.primary.stream_id {
+                            let mut media = media.clone();
+                            media.stream_id = stream_id.clone();
+                            if let MediaNotificationContent::MediaPayload {
+                                timestamp: ts, ..
+                            } = &mut media.content
+                            {
+                                *ts = shift_timestamp(*timestamp, composite.secondary_offset_ms);
+                            }
+
+                            outputs.media.push(media);
+                        }
+                    }
+
+                    _ => (), // audio from the source that isn't being used
+                }
+            }
+
+            _ if is_secondary => (),
+            _ => outputs.media.push(media),
+        }
+    }
+
+    fn handle_video(
+        &mut self,
+        source: CompositeSource,
+        media: MediaNotification,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let (payload_type, timestamp, metadata, data, is_required_for_decoding) =
+            match &media.content {
+                MediaNotificationContent::MediaPayload {
+                    payload_type,
+                    timestamp,
+                    metadata,
+                    data,
+                    is_required_for_decoding,
+                    ..
+                } => (
+                    payload_type.clone(),
+                    *timestamp,
+                    metadata,
+                    data.clone(),
+                    *is_required_for_decoding,
+                ),
+
+                _ => return,
+            };
+
+        let mut is_keyframe = false;
+        let mut pts_offset = 0;
+        for item in metadata.iter() {
+            if item.key() == self.is_keyframe_metadata_key {
+                is_keyframe = matches!(item.value(), MetadataValue::Bool(true));
+            } else if item.key() == self.pts_offset_metadata_key {
+                if let MetadataValue::I32(offset) = item.value() {
+                    pts_offset = offset;
+                }
+            }
+        }
+
+        if is_required_for_decoding {
+            // The compositor can't switch formats part way through, so compositing is restarted
+            // with the new sequence header on the next primary keyframe
+            match source {
+                CompositeSource::Primary => {
+                    self.primary.video_sequence_header = Some((payload_type, data));
+                    self.composite = None;
+                    outputs.media.push(media);
+                }
+
+                CompositeSource::Secondary => {
+                    self.secondary.video_sequence_header = Some((payload_type, data));
+                    self.stop_compositing(outputs);
+                }
+            }
+
+            return;
+        }
+
+        match source {
+            CompositeSource::Primary => self.primary.last_video_dts = Some(timestamp),
+            CompositeSource::Secondary => self.secondary.last_video_dts = Some(timestamp),
+        }
+
+        if source == CompositeSource::Primary {
+            if self.composite.is_some() && self.is_secondary_stale() {
+                warn!(
+                    "No video received from secondary stream {} for over {:?}, passing through \
+                    the primary stream's media until it resumes",
+                    self.secondary_stream_name, SECONDARY_TIMEOUT
+                );
+
+                self.stop_compositing(outputs);
+
+                // Compositing shouldn't restart until new secondary video arrives
+                self.secondary.last_video_dts = None;
+            }
+
+            if self.composite.is_none() && is_keyframe && !self.is_secondary_stale() {
+                self.start_compositing(outputs, futures_channel);
+            }
+        }
+
+        let composite = match &mut self.composite {
+            Some(composite) => composite,
+            None => {
+                if source == CompositeSource::Primary {
+                    outputs.media.push(media);
+                }
+
+                return;
+            }
+        };
+
+        let pts = Duration::from_millis(timestamp.as_millis() as u64 + pts_offset as u64);
+        let video_timestamp = match source {
+            CompositeSource::Primary => VideoTimestamp::from_durations(timestamp, pts),
+            CompositeSource::Secondary => {
+                if !composite.has_secondary_keyframe {
+                    if !is_keyframe {
+                        return;
+                    }
+
+                    composite.has_secondary_keyframe = true;
+                }
+
+                VideoTimestamp::from_durations(
+                    shift_timestamp(timestamp, composite.secondary_offset_ms),
+                    shift_timestamp(pts, composite.secondary_offset_ms),
+                )
+            }
+        };
+
+        let result =
+            composite
+                .compositor
+                .push_data(source, payload_type, data, video_timestamp, false);
+
+        match result {
+            Ok(_) => composite.is_dropping_frames = false,
+            Err(error) if error.downcast_ref::<EncoderQueueFullError>().is_some() => {
+                if !composite.is_dropping_frames {
+                    warn!("Compositor can't keep up, dropping video frames");
+                    composite.is_dropping_frames = true;
+                }
+            }
+
+            Err(error) => {
+                error!(
+                    "Failed to push {:?} video into the compositor, passing through the \
+                    primary stream's media: {:?}",
+                    source, error
+                );
+
+                self.stop_compositing(outputs);
+            }
+        }
+    }
+
+    /// The secondary is stale when its latest video (on the primary's timeline) is too far
+    /// behind the primary's latest video, or when there's no secondary video at all.
+    fn is_secondary_stale(&self) -> bool {
+        let (primary, secondary) =
+            match (self.primary.last_video_dts, self.secondary.last_video_dts) {
+                (Some(primary), Some(secondary)) => (primary, secondary),
+                _ => return true,
+            };
+
+        let secondary = match &self.composite {
+            Some(composite) => shift_timestamp(secondary, composite.secondary_offset_ms),
+            None => return false, // a new composite aligns the secondary to the primary
+        };
+
+        secondary + SECONDARY_TIMEOUT < primary
+    }
+
+    fn start_compositing(
+        &mut self,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let (primary_header, primary_dts) = match &self.primary {
+            SourceState {
+                video_sequence_header: Some(header),
+                last_video_dts: Some(dts),
+                ..
+            } => (header.clone(), *dts),
+
+            _ => return,
+        };
+
+        let (secondary_header, secondary_dts) = match &self.secondary {
+            SourceState {
+                video_sequence_header: Some(header),
+                last_video_dts: Some(dts),
+                ..
+            } => (header.clone(), *dts),
+
+            _ => return,
+        };
+
+        let (sender, receiver) = unbounded_channel();
+        let compositor = Compositor::new(
+            self.settings,
+            &self.encoder_settings,
+            &self.encoder_factory,
+            sender,
+        );
+
+        let compositor = match compositor {
+            Ok(compositor) => compositor,
+            Err(error) => {
+                error!("Failed to create compositor: {:?}", error);
+                return;
+            }
+        };
+
+        let headers = [
+            (CompositeSource::Primary, primary_header),
+            (CompositeSource::Secondary, secondary_header),
+        ];
+
+        for (source, (payload_type, header)) in headers {
+            let timestamp = VideoTimestamp::from_zero();
+            if let Err(error) = compositor.push_data(source, payload_type, header, timestamp, true)
+            {
+                error!(
+                    "Failed to set {:?} sequence header on compositor: {:?}",
+                    source, error
+                );
+
+                return;
+            }
+        }
+
+        self.next_compositor_id += 1;
+        let compositor_id = self.next_compositor_id;
+        futures_channel.send_on_generic_unbounded_recv(
+            receiver,
+            move |content| FutureResult::VideoComposited {
+                compositor_id,
+                content,
+            },
+            || FutureResult::CompositorStopped,
+        );
+
+        info!(
+            "Compositing secondary stream {} onto primary stream {}",
+            self.secondary_stream_name, self.primary_stream_name
+        );
+
+        self.composite = Some(ActiveComposite {
+            compositor,
+            compositor_id,
+            secondary_offset_ms: primary_dts.as_millis() as i64 - secondary_dts.as_millis() as i64,
+            has_secondary_keyframe: false,
+            is_dropping_frames: false,
+        });
+
+        if self.audio_source == CompositeSource::Secondary {
+            if let Some(header) = self.secondary.audio_sequence_header.clone() {
+                self.send_sequence_header(MediaType::Audio, header, outputs);
+            }
+        }
+    }
+
+    /// Stops any active composite, and switches the primary stream back to its own media
+    fn stop_compositing(&mut self, outputs: &mut StepOutputs) {
+        if self.composite.take().is_none() {
+            return;
+        }
+
+        if let Some(header) = self.primary.video_sequence_header.clone() {
+            self.send_sequence_header(MediaType::Video, header, outputs);
+        }
+
+        if self.audio_source == CompositeSource::Secondary {
+            if let Some(header) = self.primary.audio_sequence_header.clone() {
+                self.send_sequence_header(MediaType::Audio, header, outputs);
+            }
+        }
+    }
+
+    fn send_sequence_header(
+        &mut self,
+        media_type: MediaType,
+        (payload_type, data): (Arc<String>, Bytes),
+        outputs: &mut StepOutputs,
+    ) {
+        let stream_id = match &self.primary.stream_id {
+            Some(stream_id) => stream_id.clone(),
+            None => return,
+        };
+
+        outputs.media.push(MediaNotification {
+            stream_id,
+            content: MediaNotificationContent::MediaPayload {
+                media_type,
+                payload_type,
+                timestamp: Duration::from_millis(0),
+                metadata: MediaPayloadMetadataCollection::new(
+                    iter::empty(),
+                    &mut self.metadata_buffer,
+                ),
+                data,
+                is_required_for_decoding: true,
+            },
+            annotations: Default::default(),
+        });
+    }
+
+    fn handle_composited_video(
+        &mut self,
+        compositor_id: u64,
+        content: MediaNotificationContent,
+        outputs: &mut StepOutputs,
+    ) {
+        match &self.composite {
+            Some(composite) if composite.compositor_id == compositor_id => (),
+            _ => return, // video from a compositor that's been replaced
+        }
+
+        if let Some(stream_id) = &self.primary.stream_id {
+            outputs.media.push(MediaNotification {
+                stream_id: stream_id.clone(),
+                content,
+                annotations: Default::default(),
+            });
+        }
+    }
+}
+
+impl WorkflowStep for PipCompositeStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            match notification.downcast::<FutureResult>() {
+                Ok(result) => match *result {
+                    FutureResult::CompositorStopped => (),
+                    FutureResult::VideoComposited {
+                        compositor_id,
+                        content,
+                    } => self.handle_composited_video(compositor_id, content, outputs),
+                },
+
+                Err(_) => {
+                    error!("Received future result that could not be casted to the internal future result type");
+                }
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            self.handle_media(media, outputs, &futures_channel);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let layout = self.settings.layout;
+        let audio_source = match self.audio_source {
+            CompositeSource::Primary => "primary",
+            CompositeSource::Secondary => "secondary",
+        };
+
+        let mut details = HashMap::new();
+        details.insert(
+            IS_COMPOSITING_DETAIL.to_string(),
+            self.composite.is_some().to_string(),
+        );
+
+        details.insert(
+            PIP_POSITION_DETAIL.to_string(),
+            format!("{},{}", layout.x, layout.y),
+        );
+
+        details.insert(
+            PIP_SIZE_DETAIL.to_string(),
+            format!("{}x{}", layout.width, layout.height),
+        );
+
+        details.insert(AUDIO_SOURCE_DETAIL.to_string(), audio_source.to_string());
+
+        details
+    }
+
+    fn get_active_pipeline_count(&self) -> usize {
+        if self.composite.is_some() {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+fn shift_timestamp(timestamp: Duration, offset_ms: i64) -> Duration {
+    let shifted = timestamp.as_millis() as i64 + offset_ms;
+    Duration::from_millis(shifted.max(0) as u64)
+}
@@ -0,0 +1,313 @@
+//! Detection of silent audio and frozen video. Conditions are tracked against the media
+//! timeline, and an alert is only raised once a condition has lasted past its threshold.
+
+use crate::steps::quality_measure::metrics::LumaFrame;
+use mmids_core::event_hub::StreamAlert;
+use std::time::Duration;
+
+/// The average per pixel luma difference at or below which two frames are considered identical.
+/// This leaves some room for encoder noise on an otherwise static picture.
+const FREEZE_DIFFERENCE_TOLERANCE: f64 = 0.5;
+
+/// A change in whether an alert is active
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transition {
+    /// The condition has been present for at least the threshold
+    Raised { duration: Duration },
+
+    /// The condition is no longer present after having been raised
+    Cleared { duration: Duration },
+}
+
+/// Tracks how long a single condition has been continuously present
+pub struct ConditionTracker {
+    threshold: Duration,
+    started_at: Option<Duration>,
+    is_raised: bool,
+}
+
+/// Thresholds used to decide when a stream's media is problematic
+#[derive(Clone, Copy, Debug)]
+pub struct Thresholds {
+    /// Audio with a peak level below this (in dBFS) is considered silent
+    pub silence_level_db: f64,
+    pub silence_duration: Duration,
+    pub freeze_duration: Duration,
+}
+
+/// Monitors a single stream for audio silence and video freezes
+pub struct StreamMonitor {
+    silence_level_db: f64,
+    silence: ConditionTracker,
+    freeze: ConditionTracker,
+    last_frame: Option<(Duration, LumaFrame)>,
+}
+
+impl ConditionTracker {
+    pub fn new(threshold: Duration) -> Self {
+        ConditionTracker {
+            threshold,
+            started_at: None,
+            is_raised: false,
+        }
+    }
+
+    pub fn is_raised(&self) -> bool {
+        self.is_raised
+    }
+
+    /// Records whether the condition was present at `now`. If the condition was not already
+    /// present, it's considered to have started at `since`.
+    pub fn update(
+        &mut self,
+        is_present: bool,
+        since: Duration,
+        now: Duration,
+    ) -> Option<Transition> {
+        if !is_present {
+            let started_at = self.started_at.take();
+            if !std::mem::take(&mut self.is_raised) {
+                return None;
+            }
+
+            let duration = now.saturating_sub(started_at.unwrap_or(now));
+            return Some(Transition::Cleared { duration });
+        }
+
+        let started_at = *self.started_at.get_or_insert(since);
+        let duration = now.saturating_sub(started_at);
+        if !self.is_raised && duration >= self.threshold {
+            self.is_raised = true;
+            return Some(Transition::Raised { duration });
+        }
+
+        None
+    }
+}
+
+impl StreamMonitor {
+    pub fn new(thresholds: Thresholds) -> Self {
+        StreamMonitor {
+            silence_level_db: thresholds.silence_level_db,
+            silence: ConditionTracker::new(thresholds.silence_duration),
+            freeze: ConditionTracker::new(thresholds.freeze_duration),
+            last_frame: None,
+        }
+    }
+
+    /// Returns the alerts that are currently raised
+    pub fn active_alerts(&self) -> Vec<StreamAlert> {
+        let mut alerts = Vec::new();
+        if self.silence.is_raised() {
+            alerts.push(StreamAlert::AudioSilence);
+        }
+
+        if self.freeze.is_raised() {
+            alerts.push(StreamAlert::VideoFreeze);
+        }
+
+        alerts
+    }
+
+    /// Records the peak level of the audio presented at the specified time
+    pub fn add_audio_level(&mut self, pts: Duration, peak_level_db: f64) -> Option<Transition> {
+        let is_silent = peak_level_db < self.silence_level_db;
+        self.silence.update(is_silent, pts, pts)
+    }
+
+    /// Records a sampled video frame. The video is considered frozen from the time of the
+    /// previous sample if both frames are identical.
+    pub fn add_video_frame(&mut self, pts: Duration, frame: LumaFrame) -> Option<Transition> {
+        let (is_frozen, since) = match &self.last_frame {
+            Some((last_pts, last_frame)) => (is_same_picture(last_frame, &frame), *last_pts),
+            None => (false, pts),
+        };
+
+        self.last_frame = Some((pts, frame));
+        self.freeze.update(is_frozen, since, pts)
+    }
+
+    /// Clears any raised alerts, such as when the stream has ended, returning the transitions
+    /// that occurred.
+    pub fn end(&mut self, now: Duration) -> Vec<(StreamAlert, Transition)> {
+        let mut transitions = Vec::new();
+        if let Some(transition) = self.silence.update(false, now, now) {
+            transitions.push((StreamAlert::AudioSilence, transition));
+        }
+
+        if let Some(transition) = self.freeze.update(false, now, now) {
+            transitions.push((StreamAlert::VideoFreeze, transition));
+        }
+
+        self.last_frame = None;
+        transitions
+    }
+}
+
+/// Returns the peak level, in dBFS, of signed 16 bit audio samples
+pub fn peak_level_db(samples: &[i16]) -> f64 {
+    let peak = samples.iter().map(|x| x.unsigned_abs()).max().unwrap_or(0);
+    if peak == 0 {
+        return f64::NEG_INFINITY;
+    }
+
+    20.0 * (peak as f64 / 32768.0).log10()
+}
+
+fn is_same_picture(first: &LumaFrame, second: &LumaFrame) -> bool {
+    let pixel_count = first.width * first.height;
+    if first.width != second.width
+        || first.height != second.height
+        || pixel_count == 0
+        || first.data.len() < pixel_count
+        || second.data.len() < pixel_count
+    {
+        return false;
+    }
+
+    let total_difference: u64 = first.data[..pixel_count]
+        .iter()
+        .zip(&second.data[..pixel_count])
+        .map(|(a, b)| a.abs_diff(*b) as u64)
+        .sum();
+
+    total_difference as f64 / pixel_count as f64 <= FREEZE_DIFFERENCE_TOLERANCE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 16;
+    const HEIGHT: usize = 16;
+
+    fn monitor() -> StreamMonitor {
+        StreamMonitor::new(Thresholds {
+            silence_level_db: -60.0,
+            silence_duration: Duration::from_secs(5),
+            freeze_duration: Duration::from_secs(3),
+        })
+    }
+
+    fn frame(seed: u8) -> LumaFrame {
+        LumaFrame {
+            width: WIDTH,
+            height: HEIGHT,
+            data: (0..WIDTH * HEIGHT)
+                .map(|x| (x as u8).wrapping_mul(seed))
+                .collect(),
+        }
+    }
+
+    fn secs(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn silence_alert_raised_after_threshold_and_cleared_when_audio_resumes() {
+        let mut monitor = monitor();
+        assert_eq!(monitor.add_audio_level(secs(0), -12.0), None);
+
+        // silent segment
+        for second in 1..6 {
+            assert_eq!(
+                monitor.add_audio_level(secs(second), f64::NEG_INFINITY),
+                None,
+                "Unexpected transition at {} seconds",
+                second
+            );
+        }
+
+        assert_eq!(
+            monitor.add_audio_level(secs(6), -80.0),
+            Some(Transition::Raised { duration: secs(5) })
+        );
+
+        assert_eq!(monitor.active_alerts(), vec![StreamAlert::AudioSilence]);
+        assert_eq!(monitor.add_audio_level(secs(7), -90.0), None);
+
+        assert_eq!(
+            monitor.add_audio_level(secs(8), -20.0),
+            Some(Transition::Cleared { duration: secs(7) })
+        );
+
+        assert!(monitor.active_alerts().is_empty(), "Expected no alerts");
+    }
+
+    #[test]
+    fn short_silence_does_not_raise_alert() {
+        let mut monitor = monitor();
+        for second in 0..4 {
+            assert_eq!(monitor.add_audio_level(secs(second), -70.0), None);
+        }
+
+        assert_eq!(monitor.add_audio_level(secs(4), -10.0), None);
+        for second in 5..9 {
+            assert_eq!(monitor.add_audio_level(secs(second), -70.0), None);
+        }
+
+        assert!(monitor.active_alerts().is_empty(), "Expected no alerts");
+    }
+
+    #[test]
+    fn freeze_alert_raised_after_threshold_and_cleared_when_picture_changes() {
+        let mut monitor = monitor();
+        assert_eq!(monitor.add_video_frame(secs(0), frame(1)), None);
+        assert_eq!(monitor.add_video_frame(secs(1), frame(3)), None);
+
+        // frozen segment, starting from the frame at 1 second
+        assert_eq!(monitor.add_video_frame(secs(2), frame(3)), None);
+        assert_eq!(monitor.add_video_frame(secs(3), frame(3)), None);
+        assert_eq!(
+            monitor.add_video_frame(secs(4), frame(3)),
+            Some(Transition::Raised { duration: secs(3) })
+        );
+
+        assert_eq!(monitor.active_alerts(), vec![StreamAlert::VideoFreeze]);
+        assert_eq!(
+            monitor.add_video_frame(secs(5), frame(5)),
+            Some(Transition::Cleared { duration: secs(4) })
+        );
+
+        assert!(monitor.active_alerts().is_empty(), "Expected no alerts");
+    }
+
+    #[test]
+    fn small_differences_between_frames_are_considered_frozen() {
+        let first = frame(3);
+        let mut second = frame(3);
+        second.data[0] = second.data[0].wrapping_add(20);
+
+        assert!(is_same_picture(&first, &second), "Expected same picture");
+        assert!(
+            !is_same_picture(&first, &frame(5)),
+            "Expected different pictures"
+        );
+    }
+
+    #[test]
+    fn ending_stream_clears_raised_alerts() {
+        let mut monitor = monitor();
+        for second in 0..6 {
+            monitor.add_audio_level(secs(second), -70.0);
+        }
+
+        let transitions = monitor.end(secs(10));
+        assert_eq!(
+            transitions,
+            vec![(
+                StreamAlert::AudioSilence,
+                Transition::Cleared { duration: secs(10) }
+            )]
+        );
+
+        assert!(monitor.active_alerts().is_empty(), "Expected no alerts");
+    }
+
+    #[test]
+    fn peak_level_of_samples() {
+        assert_eq!(peak_level_db(&[0, 0, 0]), f64::NEG_INFINITY);
+        assert!((peak_level_db(&[0, -32768, 5]) - 0.0).abs() < 0.001);
+        assert!((peak_level_db(&[16384, 0]) - -6.0206).abs() < 0.001);
+    }
+}
@@ -0,0 +1,536 @@
+//! The QC monitor workflow step watches the content of every stream flowing through it for
+//! prolonged audio silence and frozen video. When either lasts longer than its configured
+//! threshold a `StreamAlertEvent::AlertRaised` event is published to the event hub, and once the
+//! stream's media returns to normal (or the stream ends) a `StreamAlertEvent::AlertCleared` event
+//! is published.
+//!
+//! To limit CPU usage video is sampled instead of being fully decoded. Only keyframes are
+//! decoded, at most once per `sample_interval` seconds (default of 1), and each sampled frame is
+//! compared against the previous sample as a 320x180 luma plane. This means a freeze can't be
+//! detected with more precision than the stream's keyframe interval. Audio is cheap to decode,
+//! so every audio frame is decoded and audio is considered silent while its peak level is below
+//! `silence_threshold` dBFS (default of -60).
+//!
+//! The thresholds are controlled by the `silence_duration` and `freeze_duration` parameters, in
+//! seconds (default of 10 each). Currently raised alerts are reported through the step's state
+//! details. All media is passed to the next step untouched.
+
+mod audio;
+pub(crate) mod detector;
+
+use crate::steps::qc_monitor::audio::{AudioLevel, AudioLevelDecoder};
+use crate::steps::qc_monitor::detector::{StreamMonitor, Thresholds, Transition};
+use crate::steps::quality_measure::decoder::{self, DecodedFrame, LumaDecoder};
+use crate::utils::{ensure_elements_available, GstElementError};
+use crate::GSTREAMER_INIT_RESULT;
+use mmids_core::event_hub::{PublishEventRequest, StreamAlert, StreamAlertEvent};
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::metadata::{MetadataKey, MetadataValue};
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use mmids_core::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use mmids_core::{StreamId, VideoTimestamp};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tracing::{error, info, warn};
+
+pub const SILENCE_THRESHOLD: &str = "silence_threshold";
+pub const SILENCE_DURATION: &str = "silence_duration";
+pub const FREEZE_DURATION: &str = "freeze_duration";
+pub const SAMPLE_INTERVAL: &str = "sample_interval";
+
+const ACTIVE_ALERTS_DETAIL: &str = "active_alerts";
+const ALERTS_RAISED_DETAIL: &str = "alerts_raised";
+const MONITORED_STREAMS_DETAIL: &str = "monitored_streams";
+
+/// Generates new instances of the QC monitor workflow step
+pub struct QcMonitorStepGenerator {
+    is_keyframe_metadata_key: MetadataKey,
+    pts_offset_metadata_key: MetadataKey,
+    event_publisher: UnboundedSender<PublishEventRequest>,
+}
+
+struct MonitoredStream {
+    stream_name: Arc<String>,
+    monitor: StreamMonitor,
+    video_decoder: Option<LumaDecoder>,
+    audio_decoder: Option<AudioLevelDecoder>,
+    last_sampled_keyframe: Option<Duration>,
+    latest_pts: Duration,
+}
+
+struct QcMonitorStep {
+    is_keyframe_metadata_key: MetadataKey,
+    pts_offset_metadata_key: MetadataKey,
+    event_publisher: UnboundedSender<PublishEventRequest>,
+    thresholds: Thresholds,
+    sample_interval: Duration,
+    streams: HashMap<StreamId, MonitoredStream>,
+    alerts_raised: u64,
+}
+
+enum FutureResult {
+    FrameDecoded {
+        stream_id: StreamId,
+        frame: DecodedFrame,
+    },
+
+    AudioLevelMeasured {
+        stream_id: StreamId,
+        level: AudioLevel,
+    },
+
+    // Decoders only stop when they are dropped, so there's nothing to do when this occurs
+    DecoderStopped,
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(thiserror::Error, Debug)]
+enum StepStartupError {
+    #[error(
+        "Invalid {} value of '{0}'.  It must be a number of decibels",
+        SILENCE_THRESHOLD
+    )]
+    InvalidSilenceThreshold(String),
+
+    #[error("Invalid {0} value of '{1}'.  It must be a number of seconds greater than zero")]
+    InvalidDuration(&'static str, String),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
+
+    #[error("Media cannot be decoded: {0}")]
+    MissingElement(#[from] GstElementError),
+}
+
+impl QcMonitorStepGenerator {
+    pub fn new(
+        is_keyframe_metadata_key: MetadataKey,
+        pts_offset_metadata_key: MetadataKey,
+        event_publisher: UnboundedSender<PublishEventRequest>,
+    ) -> Self {
+        QcMonitorStepGenerator {
+            is_keyframe_metadata_key,
+            pts_offset_metadata_key,
+            event_publisher,
+        }
+    }
+}
+
+impl StepGenerator for QcMonitorStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
+        let required_elements = ensure_elements_available(decoder::REQUIRED_ELEMENTS)
+            .and_then(|_| ensure_elements_available(audio::REQUIRED_ELEMENTS));
+
+        if let Err(error) = required_elements {
+            return Err(Box::new(StepStartupError::MissingElement(error)));
+        }
+
+        let silence_level_db = match definition.parameters.get(SILENCE_THRESHOLD) {
+            Some(Some(value)) => match value.parse::<f64>() {
+                Ok(level) if level.is_finite() => level,
+                _ => {
+                    return Err(Box::new(StepStartupError::InvalidSilenceThreshold(
+                        value.clone(),
+                    )))
+                }
+            },
+
+            _ => -60.0,
+        };
+
+        let get_duration =
+            |name: &'static str, default_seconds: u64| match definition.parameters.get(name) {
+                Some(Some(value)) => match value.parse::<f64>() {
+                    Ok(seconds) if seconds.is_finite() && seconds > 0.0 => {
+                        Ok(Duration::from_secs_f64(seconds))
+                    }
+
+                    _ => Err(StepStartupError::InvalidDuration(name, value.clone())),
+                },
+
+                _ => Ok(Duration::from_secs(default_seconds)),
+            };
+
+        let silence_duration = get_duration(SILENCE_DURATION, 10)?;
+        let freeze_duration = get_duration(FREEZE_DURATION, 10)?;
+        let sample_interval = get_duration(SAMPLE_INTERVAL, 1)?;
+
+        let step = QcMonitorStep {
+            is_keyframe_metadata_key: self.is_keyframe_metadata_key,
+            pts_offset_metadata_key: self.pts_offset_metadata_key,
+            event_publisher: self.event_publisher.clone(),
+            thresholds: Thresholds {
+                silence_level_db,
+                silence_duration,
+                freeze_duration,
+            },
+            sample_interval,
+            streams: HashMap::new(),
+            alerts_raised: 0,
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl QcMonitorStep {
+    fn handle_media(
+        &mut self,
+        media: &MediaNotification,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                self.stop_monitoring(&media.stream_id);
+
+                info!(
+                    stream_id = %media.stream_id.0,
+                    stream_name = %stream_name,
+                    "Monitoring stream {} for silence and freezes", stream_name
+                );
+
+                self.streams.insert(
+                    media.stream_id.clone(),
+                    MonitoredStream {
+                        stream_name: stream_name.clone(),
+                        monitor: StreamMonitor::new(self.thresholds),
+                        video_decoder: None,
+                        audio_decoder: None,
+                        last_sampled_keyframe: None,
+                        latest_pts: Duration::new(0, 0),
+                    },
+                );
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.stop_monitoring(&media.stream_id);
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type,
+                timestamp,
+                metadata,
+                data,
+                is_required_for_decoding,
+            } => {
+                let sample_interval = self.sample_interval;
+                let stream = match self.streams.get_mut(&media.stream_id) {
+                    Some(stream) => stream,
+                    None => return,
+                };
+
+                let mut is_keyframe = false;
+                let mut pts_offset = 0;
+                for item in metadata.iter() {
+                    if item.key() == self.is_keyframe_metadata_key {
+                        is_keyframe = matches!(item.value(), MetadataValue::Bool(true));
+                    } else if item.key() == self.pts_offset_metadata_key {
+                        if let MetadataValue::I32(offset) = item.value() {
+                            pts_offset = offset;
+                        }
+                    }
+                }
+
+                let pts = Duration::from_millis(timestamp.as_millis() as u64 + pts_offset as u64);
+                if *is_required_for_decoding {
+                    if stream.video_decoder.is_none() {
+                        stream.video_decoder =
+                            start_video_decoder(&media.stream_id, futures_channel);
+                    }
+                } else {
+                    let is_sample_due = match stream.last_sampled_keyframe {
+                        Some(last) => pts < last || pts - last >= sample_interval,
+                        None => true,
+                    };
+
+                    if !is_keyframe || !is_sample_due {
+                        return;
+                    }
+
+                    stream.last_sampled_keyframe = Some(pts);
+                }
+
+                if let Some(decoder) = &stream.video_decoder {
+                    let result = decoder.push_data(
+                        payload_type.clone(),
+                        data.clone(),
+                        VideoTimestamp::from_durations(*timestamp, pts),
+                        *is_required_for_decoding,
+                    );
+
+                    if let Err(error) = result {
+                        warn!(
+                            stream_id = %media.stream_id.0,
+                            "Failed to push video into the QC decoder, no longer checking the \
+                            stream for freezes: {:?}", error
+                        );
+
+                        stream.video_decoder = None;
+                    }
+                }
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Audio,
+                payload_type,
+                timestamp,
+                data,
+                is_required_for_decoding,
+                ..
+            } => {
+                let stream = match self.streams.get_mut(&media.stream_id) {
+                    Some(stream) => stream,
+                    None => return,
+                };
+
+                if *is_required_for_decoding && stream.audio_decoder.is_none() {
+                    stream.audio_decoder = start_audio_decoder(&media.stream_id, futures_channel);
+                }
+
+                if let Some(decoder) = &stream.audio_decoder {
+                    let result = decoder.push_data(
+                        payload_type.clone(),
+                        data.clone(),
+                        *timestamp,
+                        *is_required_for_decoding,
+                    );
+
+                    if let Err(error) = result {
+                        warn!(
+                            stream_id = %media.stream_id.0,
+                            "Failed to push audio into the QC decoder, no longer checking the \
+                            stream for silence: {:?}", error
+                        );
+
+                        stream.audio_decoder = None;
+                    }
+                }
+            }
+
+            MediaNotificationContent::MediaPayload { .. } => (),
+            MediaNotificationContent::Metadata { .. } => (),
+        }
+    }
+
+    fn stop_monitoring(&mut self, stream_id: &StreamId) {
+        if let Some(mut stream) = self.streams.remove(stream_id) {
+            for (alert, transition) in stream.monitor.end(stream.latest_pts) {
+                self.publish(stream_id, &stream.stream_name, alert, transition);
+            }
+        }
+    }
+
+    fn handle_future_result(&mut self, result: FutureResult) {
+        let (stream_id, transition, alert) = match result {
+            FutureResult::DecoderStopped => return,
+            FutureResult::FrameDecoded { stream_id, frame } => {
+                let stream = match self.streams.get_mut(&stream_id) {
+                    Some(stream) => stream,
+                    None => return, // frame from a stream that's no longer monitored
+                };
+
+                stream.latest_pts = stream.latest_pts.max(frame.pts);
+                let transition = stream.monitor.add_video_frame(frame.pts, frame.frame);
+                (stream_id, transition, StreamAlert::VideoFreeze)
+            }
+
+            FutureResult::AudioLevelMeasured { stream_id, level } => {
+                let stream = match self.streams.get_mut(&stream_id) {
+                    Some(stream) => stream,
+                    None => return,
+                };
+
+                stream.latest_pts = stream.latest_pts.max(level.pts);
+                let transition = stream
+                    .monitor
+                    .add_audio_level(level.pts, level.peak_level_db);
+
+                (stream_id, transition, StreamAlert::AudioSilence)
+            }
+        };
+
+        if let Some(transition) = transition {
+            if let Some(stream_name) = self.streams.get(&stream_id).map(|s| s.stream_name.clone()) {
+                self.publish(&stream_id, &stream_name, alert, transition);
+            }
+        }
+    }
+
+    fn publish(
+        &mut self,
+        stream_id: &StreamId,
+        stream_name: &Arc<String>,
+        alert: StreamAlert,
+        transition: Transition,
+    ) {
+        let event = match transition {
+            Transition::Raised { duration } => {
+                warn!(
+                    stream_id = %stream_id.0,
+                    stream_name = %stream_name,
+                    "{:?} detected on stream {} for {:?}", alert, stream_name, duration
+                );
+
+                self.alerts_raised += 1;
+                StreamAlertEvent::AlertRaised {
+                    stream_id: stream_id.clone(),
+                    stream_name: stream_name.clone(),
+                    alert,
+                    duration,
+                }
+            }
+
+            Transition::Cleared { duration } => {
+                info!(
+                    stream_id = %stream_id.0,
+                    stream_name = %stream_name,
+                    "{:?} on stream {} cleared after {:?}", alert, stream_name, duration
+                );
+
+                StreamAlertEvent::AlertCleared {
+                    stream_id: stream_id.clone(),
+                    stream_name: stream_name.clone(),
+                    alert,
+                    duration,
+                }
+            }
+        };
+
+        let _ = self
+            .event_publisher
+            .send(PublishEventRequest::StreamAlert(event));
+    }
+}
+
+impl WorkflowStep for QcMonitorStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            match notification.downcast::<FutureResult>() {
+                Ok(result) => self.handle_future_result(*result),
+                Err(_) => {
+                    error!("Received future result that could not be casted to the internal future result type");
+                }
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            self.handle_media(&media, &futures_channel);
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut active_alerts = self
+            .streams
+            .values()
+            .flat_map(|stream| {
+                stream
+                    .monitor
+                    .active_alerts()
+                    .into_iter()
+                    .map(move |alert| format!("{}: {:?}", stream.stream_name, alert))
+            })
+            .collect::<Vec<_>>();
+
+        active_alerts.sort();
+
+        let mut details = HashMap::new();
+        details.insert(ACTIVE_ALERTS_DETAIL.to_string(), active_alerts.join(", "));
+        details.insert(
+            ALERTS_RAISED_DETAIL.to_string(),
+            self.alerts_raised.to_string(),
+        );
+
+        details.insert(
+            MONITORED_STREAMS_DETAIL.to_string(),
+            self.streams.len().to_string(),
+        );
+
+        details
+    }
+}
+
+fn start_video_decoder(
+    stream_id: &StreamId,
+    futures_channel: &WorkflowStepFuturesChannel,
+) -> Option<LumaDecoder> {
+    let (sender, receiver) = unbounded_channel();
+    let decoder = match LumaDecoder::new(sender) {
+        Ok(decoder) => decoder,
+        Err(error) => {
+            error!(
+                stream_id = %stream_id.0,
+                "Failed to create QC video decoder: {:?}", error
+            );
+
+            return None;
+        }
+    };
+
+    let stream_id = stream_id.clone();
+    futures_channel.send_on_generic_unbounded_recv(
+        receiver,
+        move |frame| FutureResult::FrameDecoded {
+            stream_id: stream_id.clone(),
+            frame,
+        },
+        || FutureResult::DecoderStopped,
+    );
+
+    Some(decoder)
+}
+
+fn start_audio_decoder(
+    stream_id: &StreamId,
+    futures_channel: &WorkflowStepFuturesChannel,
+) -> Option<AudioLevelDecoder> {
+    let (sender, receiver) = unbounded_channel();
+    let decoder = match AudioLevelDecoder::new(sender) {
+        Ok(decoder) => decoder,
+        Err(error) => {
+            error!(
+                stream_id = %stream_id.0,
+                "Failed to create QC audio decoder: {:?}", error
+            );
+
+            return None;
+        }
+    };
+
+    let stream_id = stream_id.clone();
+    futures_channel.send_on_generic_unbounded_recv(
+        receiver,
+        move |level| FutureResult::AudioLevelMeasured {
+            stream_id: stream_id.clone(),
+            level,
+        },
+        || FutureResult::DecoderStopped,
+    );
+
+    Some(decoder)
+}
@@ -0,0 +1,186 @@
+//! Gstreamer pipeline that decodes a video stream into downscaled luma frames for quality
+//! analysis.
+
+use crate::steps::quality_measure::metrics::LumaFrame;
+use crate::utils::{create_gst_element, set_gst_buffer, set_source_video_sequence_header};
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use gstreamer::prelude::*;
+use gstreamer::{Caps, Element, FlowError, FlowSuccess, Pipeline, State};
+use gstreamer_app::{AppSink, AppSinkCallbacks, AppSrc};
+use mmids_core::VideoTimestamp;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::error;
+
+/// Width all frames are scaled to before they are analyzed
+pub const ANALYSIS_WIDTH: usize = 320;
+
+/// Height all frames are scaled to before they are analyzed
+pub const ANALYSIS_HEIGHT: usize = 180;
+
+/// Gstreamer elements the decoding pipeline is built from
+pub const REQUIRED_ELEMENTS: &[&str] = &[
+    "appsrc",
+    "queue",
+    "decodebin",
+    "videoconvert",
+    "videoscale",
+    "capsfilter",
+    "appsink",
+];
+
+/// A decoded frame and the presentation time it was decoded for
+pub struct DecodedFrame {
+    pub pts: Duration,
+    pub frame: LumaFrame,
+}
+
+/// Decodes video pushed into it, and sends each decoded frame out as a luma plane of
+/// `ANALYSIS_WIDTH` x `ANALYSIS_HEIGHT` pixels.
+pub struct LumaDecoder {
+    pipeline: Pipeline,
+    source: AppSrc,
+}
+
+impl LumaDecoder {
+    pub fn new(frame_sender: UnboundedSender<DecodedFrame>) -> Result<LumaDecoder> {
+        let pipeline = Pipeline::new(None);
+        let appsrc = create_gst_element("appsrc")?;
+        let queue = create_gst_element("queue")?;
+        let decoder = create_gst_element("decodebin")?;
+        let convert = create_gst_element("videoconvert")?;
+        let scale = create_gst_element("videoscale")?;
+        let capsfilter = create_gst_element("capsfilter")?;
+        let appsink = create_gst_element("appsink")?;
+
+        pipeline
+            .add_many(&[
+                &appsrc,
+                &queue,
+                &decoder,
+                &convert,
+                &scale,
+                &capsfilter,
+                &appsink,
+            ])
+            .with_context(|| "Failed to add quality decoder's elements to pipeline")?;
+
+        Element::link_many(&[&appsrc, &queue, &decoder])
+            .with_context(|| "Failed to link appsrc -> queue -> decoder")?;
+
+        Element::link_many(&[&convert, &scale, &capsfilter, &appsink])
+            .with_context(|| "Failed to link convert to sink")?;
+
+        // decodebin's video pad is added dynamically
+        let link_destination = convert;
+        decoder.connect_pad_added(move |src, src_pad| {
+            if src
+                .link_pads(Some(&src_pad.name()), &link_destination, Some("sink"))
+                .is_err()
+            {
+                error!(
+                    src_caps = ?src_pad.caps(),
+                    "Failed to link `decodebin`'s {} pad to videoconvert element",
+                    src_pad.name()
+                );
+            }
+        });
+
+        let caps = Caps::builder("video/x-raw")
+            .field("format", "GRAY8")
+            .field("width", ANALYSIS_WIDTH as i32)
+            .field("height", ANALYSIS_HEIGHT as i32)
+            .build();
+
+        capsfilter.set_property("caps", caps);
+
+        let appsink = appsink
+            .dynamic_cast::<AppSink>()
+            .map_err(|_| anyhow!("appsink could not be cast to 'AppSink'"))?;
+
+        appsink.set_callbacks(
+            AppSinkCallbacks::builder()
+                .new_sample(move |sink| match sample_received(sink, &frame_sender) {
+                    Ok(_) => Ok(FlowSuccess::Ok),
+                    Err(error) => {
+                        error!("new_sample callback error received: {:?}", error);
+                        Err(FlowError::Error)
+                    }
+                })
+                .build(),
+        );
+
+        let appsrc = appsrc
+            .dynamic_cast::<AppSrc>()
+            .map_err(|_| anyhow!("source element could not be cast to 'Appsrc'"))?;
+
+        pipeline
+            .set_state(State::Playing)
+            .with_context(|| "Failed to set quality decoder pipeline to playing")?;
+
+        Ok(LumaDecoder {
+            pipeline,
+            source: appsrc,
+        })
+    }
+
+    /// Pushes a video frame into the decoder
+    pub fn push_data(
+        &self,
+        payload_type: Arc<String>,
+        data: Bytes,
+        timestamp: VideoTimestamp,
+        is_sequence_header: bool,
+    ) -> Result<()> {
+        let buffer = set_gst_buffer(data, Some(timestamp.dts()), Some(timestamp.pts()))
+            .with_context(|| "Failed to set buffer")?;
+
+        if is_sequence_header {
+            set_source_video_sequence_header(&self.source, payload_type, buffer)
+                .with_context(|| "Failed to set sequence header for quality decoder")?;
+        } else {
+            self.source
+                .push_buffer(buffer)
+                .with_context(|| "Failed to push the buffer into the quality decoder")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for LumaDecoder {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(State::Null);
+    }
+}
+
+fn sample_received(sink: &AppSink, frame_sender: &UnboundedSender<DecodedFrame>) -> Result<()> {
+    let sample = sink
+        .pull_sample()
+        .with_context(|| "Sink had no sample available")?;
+
+    let buffer = sample
+        .buffer()
+        .with_context(|| "Sample did not contain a buffer")?;
+
+    let pts = buffer
+        .pts()
+        .with_context(|| "Decoded buffer did not have a pts")?;
+
+    let map = buffer
+        .map_readable()
+        .with_context(|| "Decoded buffer could not be made readable")?;
+
+    let _ = frame_sender.send(DecodedFrame {
+        pts: Duration::from_millis(pts.mseconds()),
+        frame: LumaFrame {
+            width: ANALYSIS_WIDTH,
+            height: ANALYSIS_HEIGHT,
+            data: map.as_slice().to_vec(),
+        },
+    });
+
+    Ok(())
+}
@@ -0,0 +1,174 @@
+//! Calculations of objective video quality metrics between a reference frame and a distorted
+//! frame. Frames are expected to be 8 bit luma planes of the same dimensions.
+
+const MAX_PIXEL_VALUE: f64 = 255.0;
+const SSIM_WINDOW_SIZE: usize = 8;
+const SSIM_C1: f64 = (0.01 * MAX_PIXEL_VALUE) * (0.01 * MAX_PIXEL_VALUE);
+const SSIM_C2: f64 = (0.03 * MAX_PIXEL_VALUE) * (0.03 * MAX_PIXEL_VALUE);
+
+/// A single 8 bit luma plane
+pub struct LumaFrame {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+/// Quality metrics for a single compared frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QualityScore {
+    /// Peak signal to noise ratio, in decibels. Identical frames have an infinite PSNR.
+    pub psnr: f64,
+
+    /// Structural similarity index, where 1.0 means the frames are identical
+    pub ssim: f64,
+}
+
+/// Compares the distorted frame against the reference frame. Returns `None` if the frames do not
+/// have the same dimensions.
+pub fn compare(reference: &LumaFrame, distorted: &LumaFrame) -> Option<QualityScore> {
+    if reference.width != distorted.width
+        || reference.height != distorted.height
+        || reference.data.len() < reference.width * reference.height
+        || distorted.data.len() < distorted.width * distorted.height
+    {
+        return None;
+    }
+
+    Some(QualityScore {
+        psnr: psnr(reference, distorted),
+        ssim: ssim(reference, distorted),
+    })
+}
+
+fn psnr(reference: &LumaFrame, distorted: &LumaFrame) -> f64 {
+    let pixel_count = reference.width * reference.height;
+    let squared_error: f64 = reference.data[..pixel_count]
+        .iter()
+        .zip(&distorted.data[..pixel_count])
+        .map(|(a, b)| {
+            let difference = *a as f64 - *b as f64;
+            difference * difference
+        })
+        .sum();
+
+    let mean_squared_error = squared_error / pixel_count as f64;
+    if mean_squared_error == 0.0 {
+        return f64::INFINITY;
+    }
+
+    10.0 * (MAX_PIXEL_VALUE * MAX_PIXEL_VALUE / mean_squared_error).log10()
+}
+
+/// Mean SSIM over non-overlapping 8x8 windows. Any partial windows at the right and bottom
+/// edges are ignored, unless the frame is smaller than a single window.
+fn ssim(reference: &LumaFrame, distorted: &LumaFrame) -> f64 {
+    let window_width = SSIM_WINDOW_SIZE.min(reference.width);
+    let window_height = SSIM_WINDOW_SIZE.min(reference.height);
+    if window_width == 0 || window_height == 0 {
+        return 1.0;
+    }
+
+    let mut total = 0.0;
+    let mut window_count = 0;
+    for window_y in (0..=reference.height - window_height).step_by(window_height) {
+        for window_x in (0..=reference.width - window_width).step_by(window_width) {
+            let mut sum_a = 0.0;
+            let mut sum_b = 0.0;
+            let mut sum_a_squared = 0.0;
+            let mut sum_b_squared = 0.0;
+            let mut sum_ab = 0.0;
+            for y in window_y..window_y + window_height {
+                let row = y * reference.width;
+                for x in window_x..window_x + window_width {
+                    let a = reference.data[row + x] as f64;
+                    let b = distorted.data[row + x] as f64;
+                    sum_a += a;
+                    sum_b += b;
+                    sum_a_squared += a * a;
+                    sum_b_squared += b * b;
+                    sum_ab += a * b;
+                }
+            }
+
+            let count = (window_width * window_height) as f64;
+            let mean_a = sum_a / count;
+            let mean_b = sum_b / count;
+            let variance_a = sum_a_squared / count - mean_a * mean_a;
+            let variance_b = sum_b_squared / count - mean_b * mean_b;
+            let covariance = sum_ab / count - mean_a * mean_b;
+
+            total += ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+                / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1)
+                    * (variance_a + variance_b + SSIM_C2));
+
+            window_count += 1;
+        }
+    }
+
+    total / window_count as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient_frame(width: usize, height: usize) -> LumaFrame {
+        LumaFrame {
+            width,
+            height,
+            data: (0..width * height)
+                .map(|index| ((index % width) * 255 / width) as u8)
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn identical_frames_have_perfect_scores() {
+        let reference = gradient_frame(64, 32);
+        let distorted = gradient_frame(64, 32);
+
+        let score = compare(&reference, &distorted).expect("Expected a score");
+        assert!(
+            score.psnr.is_infinite(),
+            "Unexpected psnr of {}",
+            score.psnr
+        );
+        assert!(
+            (score.ssim - 1.0).abs() < 1e-9,
+            "Unexpected ssim of {}",
+            score.ssim
+        );
+    }
+
+    #[test]
+    fn distorted_frames_have_lower_scores() {
+        let reference = gradient_frame(64, 32);
+        let mut distorted = gradient_frame(64, 32);
+        for (index, pixel) in distorted.data.iter_mut().enumerate() {
+            if index % 2 == 0 {
+                *pixel = pixel.saturating_add(20);
+            }
+        }
+
+        let score = compare(&reference, &distorted).expect("Expected a score");
+        assert!(
+            score.psnr > 20.0 && score.psnr < 40.0,
+            "Unexpected psnr of {}",
+            score.psnr
+        );
+
+        assert!(
+            score.ssim > 0.0 && score.ssim < 1.0,
+            "Unexpected ssim of {}",
+            score.ssim
+        );
+    }
+
+    #[test]
+    fn frames_with_different_dimensions_are_not_compared() {
+        let reference = gradient_frame(64, 32);
+        let distorted = gradient_frame(32, 32);
+
+        assert_eq!(compare(&reference, &distorted), None);
+    }
+}
@@ -0,0 +1,396 @@
+//! The quality measure workflow step compares a rendition of a stream (e.g. the output of a
+//! transcode) against its source, and reports the PSNR and SSIM of the rendition. This allows
+//! operators to see the effect encoder settings have on visual quality.
+//!
+//! Both streams must flow through the step, and are referenced by either their stream name or
+//! stream id via the `source` and `rendition` parameters. Video of each stream is decoded with
+//! gstreamer and scaled down to a luma plane of 320x180 pixels. Rendition frames are matched with
+//! the source frame presented at the same time, so both streams are expected to share the same
+//! timeline. To bound CPU usage, only one pair of frames is compared per `sample_interval`
+//! seconds (default of 10).
+//!
+//! The latest metrics are reported through the step's state details. All media is passed to the
+//! next step untouched.
+
+pub(crate) mod decoder;
+pub(crate) mod metrics;
+mod sampler;
+
+use crate::steps::quality_measure::decoder::{DecodedFrame, LumaDecoder, REQUIRED_ELEMENTS};
+use crate::steps::quality_measure::sampler::FrameSampler;
+use crate::utils::{ensure_elements_available, GstElementError};
+use crate::GSTREAMER_INIT_RESULT;
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::metadata::{MetadataKey, MetadataValue};
+use mmids_core::workflows::steps::factory::StepGenerator;
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{
+    StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use mmids_core::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use mmids_core::{StreamId, VideoTimestamp};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc::unbounded_channel;
+use tracing::{error, info, warn};
+
+pub const SOURCE: &str = "source";
+pub const RENDITION: &str = "rendition";
+pub const SAMPLE_INTERVAL: &str = "sample_interval";
+
+const PSNR_DETAIL: &str = "psnr";
+const SSIM_DETAIL: &str = "ssim";
+const SAMPLE_COUNT_DETAIL: &str = "sample_count";
+
+/// Generates new instances of the quality measure workflow step
+pub struct QualityMeasureStepGenerator {
+    pts_offset_metadata_key: MetadataKey,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum StreamRole {
+    Source,
+    Rendition,
+}
+
+struct MeasuredStream {
+    stream_id: StreamId,
+    decoder: LumaDecoder,
+}
+
+struct QualityMeasureStep {
+    source_reference: String,
+    rendition_reference: String,
+    pts_offset_metadata_key: MetadataKey,
+    source: Option<MeasuredStream>,
+    rendition: Option<MeasuredStream>,
+    sample_interval: Duration,
+    sampler: FrameSampler,
+}
+
+enum FutureResult {
+    FrameDecoded {
+        role: StreamRole,
+        stream_id: StreamId,
+        frame: DecodedFrame,
+    },
+
+    DecoderStopped {
+        role: StreamRole,
+        stream_id: StreamId,
+    },
+}
+
+impl StepFutureResult for FutureResult {}
+
+#[derive(thiserror::Error, Debug)]
+enum StepStartupError {
+    #[error("No {} stream specified", SOURCE)]
+    NoSourceSpecified,
+
+    #[error("No {} stream specified", RENDITION)]
+    NoRenditionSpecified,
+
+    #[error(
+        "Invalid {} value of '{0}'.  It must be a number of seconds greater than zero",
+        SAMPLE_INTERVAL
+    )]
+    InvalidSampleInterval(String),
+
+    #[error("Gstreamer could not be initialized: {0}")]
+    GstreamerInitFailed(String),
+
+    #[error("Video cannot be decoded: {0}")]
+    MissingElement(#[from] GstElementError),
+}
+
+impl QualityMeasureStepGenerator {
+    pub fn new(pts_offset_metadata_key: MetadataKey) -> Self {
+        QualityMeasureStepGenerator {
+            pts_offset_metadata_key,
+        }
+    }
+}
+
+impl StepGenerator for QualityMeasureStepGenerator {
+    fn generate(
+        &self,
+        definition: WorkflowStepDefinition,
+        _futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepCreationResult {
+        if let Err(error) = (*GSTREAMER_INIT_RESULT).as_ref() {
+            return Err(Box::new(StepStartupError::GstreamerInitFailed(
+                error.to_string(),
+            )));
+        }
+
+        if let Err(error) = ensure_elements_available(REQUIRED_ELEMENTS) {
+            return Err(Box::new(StepStartupError::MissingElement(error)));
+        }
+
+        let source_reference = match definition.parameters.get(SOURCE) {
+            Some(Some(value)) => value.clone(),
+            _ => return Err(Box::new(StepStartupError::NoSourceSpecified)),
+        };
+
+        let rendition_reference = match definition.parameters.get(RENDITION) {
+            Some(Some(value)) => value.clone(),
+            _ => return Err(Box::new(StepStartupError::NoRenditionSpecified)),
+        };
+
+        let sample_interval = match definition.parameters.get(SAMPLE_INTERVAL) {
+            Some(Some(value)) => match value.parse::<f64>() {
+                Ok(seconds) if seconds.is_finite() && seconds > 0.0 => {
+                    Duration::from_secs_f64(seconds)
+                }
+
+                _ => {
+                    return Err(Box::new(StepStartupError::InvalidSampleInterval(
+                        value.clone(),
+                    )))
+                }
+            },
+
+            _ => Duration::from_secs(10),
+        };
+
+        let step = QualityMeasureStep {
+            source_reference,
+            rendition_reference,
+            pts_offset_metadata_key: self.pts_offset_metadata_key,
+            source: None,
+            rendition: None,
+            sample_interval,
+            sampler: FrameSampler::new(sample_interval),
+        };
+
+        Ok((Box::new(step), StepStatus::Active))
+    }
+}
+
+impl QualityMeasureStep {
+    fn measured_stream(&mut self, role: StreamRole) -> &mut Option<MeasuredStream> {
+        match role {
+            StreamRole::Source => &mut self.source,
+            StreamRole::Rendition => &mut self.rendition,
+        }
+    }
+
+    fn role_of(&self, stream_id: &StreamId) -> Option<StreamRole> {
+        let is_stream = |stream: &Option<MeasuredStream>| matches!(stream, Some(stream) if &stream.stream_id == stream_id);
+
+        if is_stream(&self.source) {
+            Some(StreamRole::Source)
+        } else if is_stream(&self.rendition) {
+            Some(StreamRole::Rendition)
+        } else {
+            None
+        }
+    }
+
+    fn start_measuring(
+        &mut self,
+        role: StreamRole,
+        stream_id: &StreamId,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let (sender, receiver) = unbounded_channel();
+        let decoder = match LumaDecoder::new(sender) {
+            Ok(decoder) => decoder,
+            Err(error) => {
+                error!(
+                    stream_id = %stream_id.0,
+                    "Failed to create quality decoder for {:?} stream: {:?}", role, error
+                );
+
+                return;
+            }
+        };
+
+        info!(
+            stream_id = %stream_id.0,
+            "Measuring stream {} as the {:?} stream", stream_id.0, role
+        );
+
+        let recv_stream_id = stream_id.clone();
+        let closed_stream_id = stream_id.clone();
+        futures_channel.send_on_generic_unbounded_recv(
+            receiver,
+            move |frame| FutureResult::FrameDecoded {
+                role,
+                stream_id: recv_stream_id.clone(),
+                frame,
+            },
+            move || FutureResult::DecoderStopped {
+                role,
+                stream_id: closed_stream_id,
+            },
+        );
+
+        *self.measured_stream(role) = Some(MeasuredStream {
+            stream_id: stream_id.clone(),
+            decoder,
+        });
+
+        // Frames from a previous instance of either stream can't be compared against this one
+        self.sampler = FrameSampler::new(self.sample_interval);
+    }
+
+    fn handle_media(
+        &mut self,
+        media: &MediaNotification,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                let matches = |reference: &str| {
+                    reference == stream_name.as_str() || reference == media.stream_id.0.as_str()
+                };
+
+                if matches(&self.source_reference) {
+                    self.start_measuring(StreamRole::Source, &media.stream_id, futures_channel);
+                } else if matches(&self.rendition_reference) {
+                    self.start_measuring(StreamRole::Rendition, &media.stream_id, futures_channel);
+                }
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                if let Some(role) = self.role_of(&media.stream_id) {
+                    *self.measured_stream(role) = None;
+                }
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type: MediaType::Video,
+                payload_type,
+                timestamp,
+                metadata,
+                data,
+                is_required_for_decoding,
+            } => {
+                let role = match self.role_of(&media.stream_id) {
+                    Some(role) => role,
+                    None => return,
+                };
+
+                let pts_offset_metadata_key = self.pts_offset_metadata_key;
+                let pts_offset = metadata
+                    .iter()
+                    .filter(|m| m.key() == pts_offset_metadata_key)
+                    .filter_map(|m| match m.value() {
+                        MetadataValue::I32(num) => Some(num),
+                        _ => None,
+                    })
+                    .next()
+                    .unwrap_or_default();
+
+                let pts = Duration::from_millis(timestamp.as_millis() as u64 + pts_offset as u64);
+                let video_timestamp = VideoTimestamp::from_durations(*timestamp, pts);
+
+                let measured_stream = self.measured_stream(role);
+                if let Some(stream) = measured_stream {
+                    let result = stream.decoder.push_data(
+                        payload_type.clone(),
+                        data.clone(),
+                        video_timestamp,
+                        *is_required_for_decoding,
+                    );
+
+                    if let Err(error) = result {
+                        warn!(
+                            stream_id = %media.stream_id.0,
+                            "Failed to push video into the quality decoder, no longer measuring \
+                            the stream: {:?}", error
+                        );
+
+                        *measured_stream = None;
+                    }
+                }
+            }
+
+            MediaNotificationContent::MediaPayload { .. } => (),
+            MediaNotificationContent::Metadata { .. } => (),
+        }
+    }
+
+    fn handle_decoded_frame(&mut self, role: StreamRole, stream_id: StreamId, frame: DecodedFrame) {
+        if self.role_of(&stream_id).is_none() {
+            return; // frame from a stream that's no longer measured
+        }
+
+        match role {
+            StreamRole::Source => self.sampler.add_source_frame(frame.pts, frame.frame),
+            StreamRole::Rendition => {
+                if let Some(score) = self.sampler.add_rendition_frame(frame.pts, frame.frame) {
+                    info!(
+                        stream_id = %stream_id.0,
+                        psnr = %score.psnr,
+                        ssim = %score.ssim,
+                        "Rendition measured with a PSNR of {:.2} and SSIM of {:.4}",
+                        score.psnr, score.ssim
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl WorkflowStep for QualityMeasureStep {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for notification in inputs.notifications.drain(..) {
+            let future_result = match notification.downcast::<FutureResult>() {
+                Ok(result) => *result,
+                Err(_) => {
+                    error!("Received future result that could not be casted to the internal future result type");
+                    continue;
+                }
+            };
+
+            match future_result {
+                FutureResult::FrameDecoded {
+                    role,
+                    stream_id,
+                    frame,
+                } => self.handle_decoded_frame(role, stream_id, frame),
+
+                FutureResult::DecoderStopped { role, stream_id } => {
+                    if self.role_of(&stream_id).is_some() {
+                        warn!(
+                            stream_id = %stream_id.0,
+                            "Quality decoder for the {:?} stream stopped", role
+                        );
+
+                        *self.measured_stream(role) = None;
+                    }
+                }
+            }
+        }
+
+        for media in inputs.media.drain(..) {
+            self.handle_media(&media, &futures_channel);
+            outputs.media.push(media);
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        details.insert(
+            SAMPLE_COUNT_DETAIL.to_string(),
+            self.sampler.sample_count.to_string(),
+        );
+
+        if let Some(score) = &self.sampler.latest_score {
+            details.insert(PSNR_DETAIL.to_string(), format!("{:.2}", score.psnr));
+            details.insert(SSIM_DETAIL.to_string(), format!("{:.4}", score.ssim));
+        }
+
+        details
+    }
+}
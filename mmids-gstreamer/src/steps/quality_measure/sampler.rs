@@ -0,0 +1,153 @@
+//! Pairs decoded rendition frames with the source frames presented at the same time, and
+//! compares them at a bounded rate.
+
+use crate::steps::quality_measure::metrics::{compare, LumaFrame, QualityScore};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// How far apart a source and rendition frame's timestamps can be while still being considered
+/// the same frame.
+const MATCH_TOLERANCE: Duration = Duration::from_millis(20);
+
+/// Maximum number of source frames held while waiting for their matching rendition frame
+const MAX_PENDING_SOURCE_FRAMES: usize = 300;
+
+pub struct FrameSampler {
+    sample_interval: Duration,
+    pending_source_frames: VecDeque<(Duration, LumaFrame)>,
+    last_sampled_at: Option<Duration>,
+    pub latest_score: Option<QualityScore>,
+    pub sample_count: u64,
+}
+
+impl FrameSampler {
+    pub fn new(sample_interval: Duration) -> Self {
+        FrameSampler {
+            sample_interval,
+            pending_source_frames: VecDeque::new(),
+            last_sampled_at: None,
+            latest_score: None,
+            sample_count: 0,
+        }
+    }
+
+    pub fn add_source_frame(&mut self, pts: Duration, frame: LumaFrame) {
+        if !self.is_sample_due(pts) {
+            return;
+        }
+
+        if self.pending_source_frames.len() >= MAX_PENDING_SOURCE_FRAMES {
+            self.pending_source_frames.pop_front();
+        }
+
+        self.pending_source_frames.push_back((pts, frame));
+    }
+
+    /// Compares the rendition frame against the matching source frame, if one is pending and a
+    /// sample is due. Returns the resulting score if a comparison was made.
+    pub fn add_rendition_frame(&mut self, pts: Duration, frame: LumaFrame) -> Option<QualityScore> {
+        // Source frames older than this rendition frame can no longer be matched
+        while let Some((source_pts, _)) = self.pending_source_frames.front() {
+            if *source_pts + MATCH_TOLERANCE < pts {
+                self.pending_source_frames.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if !self.is_sample_due(pts) {
+            return None;
+        }
+
+        let (source_pts, source_frame) = self.pending_source_frames.front()?;
+        if *source_pts > pts + MATCH_TOLERANCE {
+            return None;
+        }
+
+        let score = compare(source_frame, &frame)?;
+        self.pending_source_frames.clear();
+        self.last_sampled_at = Some(pts);
+        self.latest_score = Some(score);
+        self.sample_count += 1;
+
+        Some(score)
+    }
+
+    fn is_sample_due(&self, pts: Duration) -> bool {
+        match self.last_sampled_at {
+            Some(last) => pts + MATCH_TOLERANCE >= last + self.sample_interval,
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(value: u8) -> LumaFrame {
+        LumaFrame {
+            width: 16,
+            height: 16,
+            data: (0..256)
+                .map(|x| (x as u8 / 2).saturating_add(value))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn metrics_are_produced_once_per_sample_interval() {
+        let mut sampler = FrameSampler::new(Duration::from_millis(100));
+        let mut scores = Vec::new();
+
+        // Rendition frames lag behind the source, as they would when coming out of a transcoder
+        for index in 0..10u64 {
+            let pts = Duration::from_millis(index * 33);
+            sampler.add_source_frame(pts, frame(0));
+            if index >= 2 {
+                let rendition_pts = Duration::from_millis((index - 2) * 33);
+                if let Some(score) = sampler.add_rendition_frame(rendition_pts, frame(3)) {
+                    scores.push((rendition_pts, score));
+                }
+            }
+        }
+
+        let sampled_at = scores.iter().map(|(pts, _)| *pts).collect::<Vec<_>>();
+        assert_eq!(
+            sampled_at,
+            vec![
+                Duration::from_millis(0),
+                Duration::from_millis(99),
+                Duration::from_millis(198)
+            ],
+            "Unexpected sample times"
+        );
+
+        for (_, score) in &scores {
+            assert!(
+                score.psnr.is_finite() && score.psnr > 30.0,
+                "Unexpected psnr {}",
+                score.psnr
+            );
+
+            assert!(score.ssim < 1.0, "Unexpected ssim {}", score.ssim);
+        }
+
+        assert_eq!(sampler.sample_count, 3, "Unexpected sample count");
+        assert_eq!(
+            sampler.latest_score,
+            Some(scores[2].1),
+            "Unexpected latest score"
+        );
+    }
+
+    #[test]
+    fn rendition_frames_without_matching_source_are_not_compared() {
+        let mut sampler = FrameSampler::new(Duration::from_millis(100));
+        sampler.add_source_frame(Duration::from_millis(500), frame(0));
+
+        let score = sampler.add_rendition_frame(Duration::from_millis(100), frame(0));
+        assert_eq!(score, None, "Expected no score");
+        assert_eq!(sampler.sample_count, 0, "Unexpected sample count");
+    }
+}
@@ -0,0 +1,906 @@
+//! Shared machinery for workflow steps that only transcode some of their streams, passing the rest
+//! through untouched. The step owns the transcoding processes, holds media while a stream's
+//! decision is pending, keeps track of sequence headers, and handles switching streams between
+//! passing through and transcoding. What each stream should do is left to a `TranscodeDecider`.
+//!
+//! Transcoding is done the same way as the basic transcode step, with the `video` and `audio`
+//! parameters naming the encoders to use, and `video_` and `audio_` prefixed parameters being
+//! passed to the respective encoders.
+//!
+//! Each switch between passing through and transcoding is signaled with a metadata notification
+//! containing only the `DISCONTINUITY_METADATA_KEY` key, whose value is either `passthrough` or
+//! the decider's transcode mode. Later steps last saw the sequence headers of whichever side was
+//! active, so the other side's sequence headers are always sent along with a switch.
+
+use crate::encoders::{EncoderFactory, EncoderFactoryCreationError};
+use crate::endpoints::gst_transcoder::{
+    GstTranscoderNotification, GstTranscoderRequest, GstTranscoderStoppedCause,
+};
+use crate::steps::basic_transcoder::{
+    AUDIO_ENCODER, AUDIO_PARAM_PREFIX, VIDEO_ENCODER, VIDEO_PARAM_PREFIX,
+};
+use mmids_core::workflows::definitions::WorkflowStepDefinition;
+use mmids_core::workflows::discontinuity::discontinuity_notification;
+use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::{
+    StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
+};
+use mmids_core::workflows::{MediaNotification, MediaNotificationContent, MediaType};
+use mmids_core::StreamId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tracing::{error, info, instrument, warn};
+use uuid::Uuid;
+
+pub(crate) const PASSTHROUGH_MODE: &str = "passthrough";
+
+const PASSTHROUGH_STREAMS_DETAIL: &str = "passthrough_streams";
+
+/// How much media is held for a stream while its decision is pending. Once exceeded the decider
+/// is asked to make a decision without any more information.
+const MAX_PENDING_MEDIA: usize = 300;
+
+/// What should be done with a stream's media
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Decision<T> {
+    Passthrough,
+    Transcode { target: T, reason: String },
+}
+
+/// Where a stream's media is currently going, as reported to the decider for state details
+pub(crate) enum StreamStatus<'a, T> {
+    Pending,
+    Passthrough,
+    Transcoding(&'a T),
+}
+
+/// A snapshot of a stream, as reported to the decider for state details
+pub(crate) struct StreamSummary<'a, S, T> {
+    pub name: &'a str,
+    pub state: &'a S,
+    pub status: StreamStatus<'a, T>,
+}
+
+/// Formats a detail containing a value for each stream, ordered by stream name
+pub(crate) fn per_stream_detail<S, T>(
+    streams: &[StreamSummary<'_, S, T>],
+    value: impl Fn(&StreamSummary<'_, S, T>) -> String,
+) -> String {
+    let mut entries = streams
+        .iter()
+        .map(|stream| format!("{}: {}", stream.name, value(stream)))
+        .collect::<Vec<_>>();
+
+    entries.sort();
+    entries.join("; ")
+}
+
+/// Decides which streams a selective transcode step passes through, and how it transcodes the rest
+pub(crate) trait TranscodeDecider: Send + Sync + 'static {
+    /// What a stream is transcoded to. A stream whose target changes gets a new transcode.
+    type Target: Clone + PartialEq + Send + Sync;
+
+    /// Whatever the decider needs to track for each stream
+    type StreamState: Send + Sync;
+
+    /// The discontinuity value announcing that a stream is now being transcoded
+    const TRANSCODE_MODE: &'static str;
+
+    /// The name of the state detail counting how many streams are being transcoded
+    const TRANSCODING_STREAMS_DETAIL: &'static str;
+
+    /// Whether requests for fresh sequence headers may force the transcode to emit a keyframe
+    const CAN_FORCE_KEY_UNITS: bool = true;
+
+    fn new_stream(&self) -> Self::StreamState;
+
+    /// The decision new streams start with. Media of streams without one is held until
+    /// `evaluate()` makes one.
+    fn initial_decision(&self) -> Option<Decision<Self::Target>> {
+        None
+    }
+
+    /// Looks at a media payload of the stream, before it's routed, and returns a decision if one
+    /// can now be made or has changed. While `is_pending` is true the stream's media is held until
+    /// a decision is returned.
+    fn evaluate(
+        &mut self,
+        stream_id: &StreamId,
+        stream: &mut Self::StreamState,
+        content: &MediaNotificationContent,
+        is_pending: bool,
+    ) -> Option<Decision<Self::Target>>;
+
+    /// Decides what to do with a stream that's held too much media without a decision being made
+    fn undecided(
+        &mut self,
+        _stream_id: &StreamId,
+        _stream: &Self::StreamState,
+    ) -> Decision<Self::Target> {
+        Decision::Passthrough
+    }
+
+    /// Called when a new transcoding process is started for the stream, before any media is sent
+    /// to it
+    fn transcode_started(&mut self, _stream: &mut Self::StreamState) {}
+
+    /// Looks at media that came out of the stream's transcode, before it's passed on
+    fn transcoded_media_received(
+        &mut self,
+        _stream_id: &StreamId,
+        _stream: &mut Self::StreamState,
+        _content: &MediaNotificationContent,
+    ) {
+    }
+
+    /// Adds any target specific parameters to the video encoder's parameters
+    fn configure_video_encoder(
+        &self,
+        _target: &Self::Target,
+        _parameters: &mut HashMap<String, Option<String>>,
+    ) {
+    }
+
+    /// Adds decider specific details to the step's state details
+    fn add_state_details(
+        &self,
+        _streams: &[StreamSummary<'_, Self::StreamState, Self::Target>],
+        _details: &mut HashMap<String, String>,
+    ) {
+    }
+}
+
+/// The encoders, and their parameters, that streams are transcoded with
+pub(crate) struct TranscodeSettings {
+    pub video_encoder_name: String,
+    pub audio_encoder_name: String,
+    pub video_parameters: HashMap<String, Option<String>>,
+    pub audio_parameters: HashMap<String, Option<String>>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum TranscodeSettingsError {
+    #[error("No video encoder specified")]
+    NoVideoEncoderSpecified,
+
+    #[error("No audio encoder specified")]
+    NoAudioEncoderSpecified,
+
+    #[error("Invalid video encoder: {0}")]
+    InvalidVideoEncoder(#[source] EncoderFactoryCreationError),
+
+    #[error("Invalid audio encoder: {0}")]
+    InvalidAudioEncoder(#[source] EncoderFactoryCreationError),
+}
+
+impl TranscodeSettings {
+    /// Reads the encoders and their parameters from the step's definition, validating the
+    /// encoders against the encoder factory the transcode endpoint uses.
+    pub fn from_definition(
+        definition: &WorkflowStepDefinition,
+        encoder_factory: &EncoderFactory,
+    ) -> Result<Self, TranscodeSettingsError> {
+        let video_encoder_name = match definition.parameters.get(VIDEO_ENCODER) {
+            Some(Some(encoder)) => encoder.clone(),
+            _ => return Err(TranscodeSettingsError::NoVideoEncoderSpecified),
+        };
+
+        let audio_encoder_name = match definition.parameters.get(AUDIO_ENCODER) {
+            Some(Some(encoder)) => encoder.clone(),
+            _ => return Err(TranscodeSettingsError::NoAudioEncoderSpecified),
+        };
+
+        encoder_factory
+            .validate_video_encoder(&video_encoder_name)
+            .map_err(TranscodeSettingsError::InvalidVideoEncoder)?;
+
+        encoder_factory
+            .validate_audio_encoder(&audio_encoder_name)
+            .map_err(TranscodeSettingsError::InvalidAudioEncoder)?;
+
+        let mut audio_parameters = HashMap::new();
+        let mut video_parameters = HashMap::new();
+        for (key, value) in &definition.parameters {
+            if key.starts_with(VIDEO_PARAM_PREFIX) && key.len() > VIDEO_PARAM_PREFIX.len() {
+                video_parameters.insert(key[VIDEO_PARAM_PREFIX.len()..].to_string(), value.clone());
+            }
+
+            if key.starts_with(AUDIO_PARAM_PREFIX) && key.len() > AUDIO_PARAM_PREFIX.len() {
+                audio_parameters.insert(key[AUDIO_PARAM_PREFIX.len()..].to_string(), value.clone());
+            }
+        }
+
+        Ok(TranscodeSettings {
+            video_encoder_name,
+            audio_encoder_name,
+            video_parameters,
+            audio_parameters,
+        })
+    }
+}
+
+struct ActiveTranscode<T> {
+    media_sender: UnboundedSender<MediaNotificationContent>,
+    transcode_process_id: Uuid,
+    target: T,
+}
+
+enum StreamMode<T> {
+    Pending(Vec<MediaNotification>),
+    Passthrough,
+    Transcoding(ActiveTranscode<T>),
+}
+
+struct ActiveStream<D: TranscodeDecider> {
+    name: Arc<String>,
+    mode: StreamMode<D::Target>,
+    state: D::StreamState,
+    audio_sequence_header: Option<MediaNotificationContent>,
+    video_sequence_header: Option<MediaNotificationContent>,
+}
+
+/// A workflow step that passes through or transcodes each stream, as its decider decides
+pub(crate) struct SelectiveTranscodeStep<D: TranscodeDecider> {
+    decider: D,
+    transcoder_endpoint: UnboundedSender<GstTranscoderRequest>,
+    settings: TranscodeSettings,
+    active_streams: HashMap<StreamId, ActiveStream<D>>,
+}
+
+enum FutureResult {
+    TranscoderEndpointGone,
+    TranscoderNotificationSenderGone {
+        stream_id: StreamId,
+        process_id: Uuid,
+    },
+
+    TranscoderNotificationReceived {
+        stream_id: StreamId,
+        process_id: Uuid,
+        notification: GstTranscoderNotification,
+    },
+
+    TranscodedMediaReceived {
+        stream_id: StreamId,
+        process_id: Uuid,
+        media: MediaNotificationContent,
+    },
+
+    TranscodedMediaChannelClosed {
+        stream_id: StreamId,
+        process_id: Uuid,
+    },
+}
+
+impl StepFutureResult for FutureResult {}
+
+impl<D: TranscodeDecider> ActiveStream<D> {
+    fn sequence_headers(&self) -> Vec<MediaNotificationContent> {
+        [&self.audio_sequence_header, &self.video_sequence_header]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+}
+
+impl<D: TranscodeDecider> SelectiveTranscodeStep<D> {
+    pub fn new(
+        decider: D,
+        transcoder_endpoint: UnboundedSender<GstTranscoderRequest>,
+        settings: TranscodeSettings,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) -> Self {
+        let endpoint = transcoder_endpoint.clone();
+        futures_channel.send_on_generic_future_completion(async move {
+            endpoint.closed().await;
+            FutureResult::TranscoderEndpointGone
+        });
+
+        SelectiveTranscodeStep {
+            decider,
+            transcoder_endpoint,
+            settings,
+            active_streams: HashMap::new(),
+        }
+    }
+
+    fn stop_all_transcodes(&mut self) {
+        for stream in self.active_streams.values_mut() {
+            if let StreamMode::Transcoding(transcode) = &stream.mode {
+                let _ = self
+                    .transcoder_endpoint
+                    .send(GstTranscoderRequest::StopTranscoding {
+                        id: transcode.transcode_process_id,
+                    });
+
+                stream.mode = StreamMode::Passthrough;
+            }
+        }
+    }
+
+    /// Starts a transcoding process for the stream, sending it the specified sequence headers
+    /// before any other media.
+    #[instrument(skip(self, target, headers, futures_channel))]
+    fn start_transcode(
+        &mut self,
+        stream_id: &StreamId,
+        target: D::Target,
+        headers: Vec<MediaNotificationContent>,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let stream = match self.active_streams.get_mut(stream_id) {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        let (media_sender, media_receiver) = unbounded_channel();
+        let (notification_sender, notification_receiver) = unbounded_channel();
+
+        let mut video_parameters = self.settings.video_parameters.clone();
+        self.decider
+            .configure_video_encoder(&target, &mut video_parameters);
+
+        let process_id = Uuid::new_v4();
+        info!("Starting transcode process id {}", process_id);
+
+        let _ = self
+            .transcoder_endpoint
+            .send(GstTranscoderRequest::StartTranscoding {
+                id: process_id,
+                notification_channel: notification_sender,
+                input_media: media_receiver,
+                video_encoder_name: self.settings.video_encoder_name.clone(),
+                video_parameters,
+                audio_encoder_name: self.settings.audio_encoder_name.clone(),
+                audio_parameters: self.settings.audio_parameters.clone(),
+            });
+
+        self.decider.transcode_started(&mut stream.state);
+        for header in headers {
+            let _ = media_sender.send(header);
+        }
+
+        stream.mode = StreamMode::Transcoding(ActiveTranscode {
+            media_sender,
+            transcode_process_id: process_id,
+            target,
+        });
+
+        let received_stream_id = stream_id.clone();
+        let closed_stream_id = stream_id.clone();
+        futures_channel.send_on_generic_unbounded_recv(
+            notification_receiver,
+            move |notification| FutureResult::TranscoderNotificationReceived {
+                stream_id: received_stream_id.clone(),
+                process_id,
+                notification,
+            },
+            move || FutureResult::TranscoderNotificationSenderGone {
+                stream_id: closed_stream_id,
+                process_id,
+            },
+        );
+    }
+
+    /// Replaces a failed transcoding process with a new one, as the stream's media still needs to
+    /// be transcoded.
+    fn restart_transcode(
+        &mut self,
+        stream_id: &StreamId,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let (target, headers) = match self.active_streams.get(stream_id) {
+            Some(stream) => match &stream.mode {
+                StreamMode::Transcoding(transcode) => {
+                    (transcode.target.clone(), stream.sequence_headers())
+                }
+
+                _ => return,
+            },
+
+            None => return,
+        };
+
+        self.start_transcode(stream_id, target, headers, futures_channel);
+    }
+
+    /// Puts the stream into the mode the decision calls for, and then routes the media that
+    /// prompted the decision.
+    fn apply_decision(
+        &mut self,
+        stream_id: &StreamId,
+        decision: Decision<D::Target>,
+        media: MediaNotification,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        let stream = match self.active_streams.get_mut(stream_id) {
+            Some(stream) => stream,
+            None => {
+                outputs.media.push(media);
+                return;
+            }
+        };
+
+        // A sequence header that prompted a switch is already sent along with the stream's other
+        // sequence headers, so it must not be routed a second time
+        let is_sequence_header = matches!(
+            media.content,
+            MediaNotificationContent::MediaPayload {
+                is_required_for_decoding: true,
+                ..
+            }
+        );
+
+        let previous_mode = std::mem::replace(&mut stream.mode, StreamMode::Passthrough);
+        let mut media_to_route = vec![media];
+        match (previous_mode, decision) {
+            (StreamMode::Pending(mut held), decision) => {
+                // Everything held, including any sequence headers, still needs to be routed
+                held.append(&mut media_to_route);
+                media_to_route = held;
+
+                match decision {
+                    Decision::Transcode { target, reason } => {
+                        info!(stream_id = ?stream_id, "Transcoding stream: {}", reason);
+                        self.start_transcode(stream_id, target, Vec::new(), futures_channel);
+                    }
+
+                    Decision::Passthrough => {
+                        info!(stream_id = ?stream_id, "Passing stream through");
+                    }
+                }
+            }
+
+            (StreamMode::Passthrough, Decision::Passthrough) => (),
+
+            (StreamMode::Transcoding(transcode), Decision::Transcode { target, .. })
+                if transcode.target == target =>
+            {
+                stream.mode = StreamMode::Transcoding(transcode);
+            }
+
+            (StreamMode::Transcoding(transcode), Decision::Transcode { target, reason }) => {
+                info!(stream_id = ?stream_id, "Restarting transcode: {}", reason);
+
+                let _ = self
+                    .transcoder_endpoint
+                    .send(GstTranscoderRequest::StopTranscoding {
+                        id: transcode.transcode_process_id,
+                    });
+
+                if is_sequence_header {
+                    media_to_route.clear();
+                }
+
+                let headers = stream.sequence_headers();
+                outputs.media.push(discontinuity_notification(
+                    stream_id.clone(),
+                    D::TRANSCODE_MODE,
+                ));
+
+                self.start_transcode(stream_id, target, headers, futures_channel);
+            }
+
+            (StreamMode::Transcoding(transcode), Decision::Passthrough) => {
+                info!(stream_id = ?stream_id, "Stream no longer needs to be transcoded");
+
+                let _ = self
+                    .transcoder_endpoint
+                    .send(GstTranscoderRequest::StopTranscoding {
+                        id: transcode.transcode_process_id,
+                    });
+
+                if is_sequence_header {
+                    media_to_route.clear();
+                }
+
+                outputs.media.push(discontinuity_notification(
+                    stream_id.clone(),
+                    PASSTHROUGH_MODE,
+                ));
+
+                for content in stream.sequence_headers() {
+                    outputs.media.push(MediaNotification {
+                        stream_id: stream_id.clone(),
+                        content,
+                        annotations: Default::default(),
+                    });
+                }
+            }
+
+            (StreamMode::Passthrough, Decision::Transcode { target, reason }) => {
+                info!(stream_id = ?stream_id, "Stream must now be transcoded: {}", reason);
+
+                if is_sequence_header {
+                    media_to_route.clear();
+                }
+
+                let headers = stream.sequence_headers();
+                outputs.media.push(discontinuity_notification(
+                    stream_id.clone(),
+                    D::TRANSCODE_MODE,
+                ));
+
+                self.start_transcode(stream_id, target, headers, futures_channel);
+            }
+        }
+
+        for media in media_to_route {
+            self.route_media(media, outputs);
+        }
+    }
+
+    /// Sends stream media to where its current mode requires
+    fn route_media(&mut self, media: MediaNotification, outputs: &mut StepOutputs) {
+        let stream = match self.active_streams.get_mut(&media.stream_id) {
+            Some(stream) => stream,
+            None => {
+                outputs.media.push(media);
+                return;
+            }
+        };
+
+        match &mut stream.mode {
+            StreamMode::Pending(held) => held.push(media),
+            StreamMode::Passthrough => outputs.media.push(media),
+            StreamMode::Transcoding(transcode) => {
+                let _ = transcode.media_sender.send(media.content);
+            }
+        }
+    }
+
+    fn handle_media(
+        &mut self,
+        media: MediaNotification,
+        outputs: &mut StepOutputs,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                let stream_id = media.stream_id.clone();
+                let initial_decision = self.decider.initial_decision();
+                let mode = match initial_decision {
+                    Some(Decision::Passthrough) => StreamMode::Passthrough,
+                    _ => StreamMode::Pending(Vec::new()),
+                };
+
+                self.stop_transcode(&stream_id);
+                self.active_streams.insert(
+                    stream_id.clone(),
+                    ActiveStream {
+                        name: stream_name.clone(),
+                        mode,
+                        state: self.decider.new_stream(),
+                        audio_sequence_header: None,
+                        video_sequence_header: None,
+                    },
+                );
+
+                outputs.media.push(media);
+
+                if let Some(Decision::Transcode { target, reason }) = initial_decision {
+                    info!(stream_id = ?stream_id, "Transcoding stream: {}", reason);
+                    self.start_transcode(&stream_id, target, Vec::new(), futures_channel);
+                }
+            }
+
+            MediaNotificationContent::StreamDisconnected => {
+                self.stop_transcode(&media.stream_id);
+                self.active_streams.remove(&media.stream_id);
+                outputs.media.push(media);
+            }
+
+            MediaNotificationContent::MediaPayload {
+                media_type,
+                is_required_for_decoding,
+                ..
+            } => {
+                let stream_id = media.stream_id.clone();
+                let stream = match self.active_streams.get_mut(&stream_id) {
+                    Some(stream) => stream,
+                    None => {
+                        outputs.media.push(media);
+                        return;
+                    }
+                };
+
+                if *is_required_for_decoding {
+                    match media_type {
+                        MediaType::Audio => {
+                            stream.audio_sequence_header = Some(media.content.clone())
+                        }
+
+                        MediaType::Video => {
+                            stream.video_sequence_header = Some(media.content.clone())
+                        }
+
+                        MediaType::Other => (),
+                    }
+                }
+
+                let held_count = match &stream.mode {
+                    StreamMode::Pending(held) => Some(held.len()),
+                    _ => None,
+                };
+
+                let mut decision = self.decider.evaluate(
+                    &stream_id,
+                    &mut stream.state,
+                    &media.content,
+                    held_count.is_some(),
+                );
+
+                if decision.is_none() && held_count.is_some_and(|count| count >= MAX_PENDING_MEDIA)
+                {
+                    warn!(
+                        stream_id = ?stream_id,
+                        "No decision could be made after holding {} media payloads",
+                        MAX_PENDING_MEDIA,
+                    );
+
+                    decision = Some(self.decider.undecided(&stream_id, &stream.state));
+                }
+
+                match decision {
+                    Some(decision) => {
+                        self.apply_decision(&stream_id, decision, media, outputs, futures_channel)
+                    }
+
+                    None => self.route_media(media, outputs),
+                }
+            }
+
+            MediaNotificationContent::Metadata { .. } => outputs.media.push(media),
+        }
+    }
+
+    #[instrument(skip(self))]
+    fn stop_transcode(&mut self, stream_id: &StreamId) {
+        if let Some(process_id) = self.transcode_process_id(stream_id) {
+            info!("Stopping transcode");
+
+            let _ = self
+                .transcoder_endpoint
+                .send(GstTranscoderRequest::StopTranscoding { id: process_id });
+        }
+    }
+
+    fn handle_transcode_notification(
+        &mut self,
+        stream_id: StreamId,
+        notification: GstTranscoderNotification,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        match notification {
+            GstTranscoderNotification::TranscodingStopped(cause) => {
+                if cause != GstTranscoderStoppedCause::StopRequested {
+                    warn!(
+                        stream_id = ?stream_id,
+                        cause = ?cause,
+                        "Transcoding unexpectedly stopped: {:?}", cause
+                    );
+
+                    // Since the stop wasn't requested, try restarting it
+                    self.restart_transcode(&stream_id, futures_channel);
+                }
+            }
+
+            GstTranscoderNotification::VideoEncoderQueuePressure(_) => (),
+
+            GstTranscoderNotification::TranscodingStarted { output_media } => {
+                let process_id = match self.transcode_process_id(&stream_id) {
+                    Some(process_id) => process_id,
+                    None => return,
+                };
+
+                let received_stream_id = stream_id.clone();
+                futures_channel.send_on_generic_unbounded_recv(
+                    output_media,
+                    move |media| FutureResult::TranscodedMediaReceived {
+                        stream_id: received_stream_id.clone(),
+                        process_id,
+                        media,
+                    },
+                    move || FutureResult::TranscodedMediaChannelClosed {
+                        stream_id,
+                        process_id,
+                    },
+                );
+            }
+        }
+    }
+
+    fn transcode_process_id(&self, stream_id: &StreamId) -> Option<Uuid> {
+        match self
+            .active_streams
+            .get(stream_id)
+            .map(|stream| &stream.mode)
+        {
+            Some(StreamMode::Transcoding(transcode)) => Some(transcode.transcode_process_id),
+            _ => None,
+        }
+    }
+
+    fn handle_transcoded_media(
+        &mut self,
+        stream_id: StreamId,
+        media: MediaNotificationContent,
+        outputs: &mut StepOutputs,
+    ) {
+        if let Some(stream) = self.active_streams.get_mut(&stream_id) {
+            self.decider
+                .transcoded_media_received(&stream_id, &mut stream.state, &media);
+        }
+
+        outputs.media.push(MediaNotification {
+            stream_id,
+            content: media,
+            annotations: Default::default(),
+        });
+    }
+
+    fn transcoding_stream_count(&self) -> usize {
+        self.active_streams
+            .values()
+            .filter(|stream| matches!(stream.mode, StreamMode::Transcoding(_)))
+            .count()
+    }
+}
+
+impl<D: TranscodeDecider> WorkflowStep for SelectiveTranscodeStep<D> {
+    fn execute(
+        &mut self,
+        inputs: &mut StepInputs,
+        outputs: &mut StepOutputs,
+        futures_channel: WorkflowStepFuturesChannel,
+    ) -> StepStatus {
+        for media in inputs.media.drain(..) {
+            self.handle_media(media, outputs, &futures_channel);
+        }
+
+        for future_result in inputs.notifications.drain(..) {
+            let future_result = match future_result.downcast::<FutureResult>() {
+                Ok(result) => result,
+                Err(_) => {
+                    error!("Received future result that could not be casted to the internal future result type");
+                    continue;
+                }
+            };
+
+            match *future_result {
+                FutureResult::TranscoderEndpointGone => {
+                    self.stop_all_transcodes();
+                    return StepStatus::Error {
+                        message: "Transcoder endpoint went away".to_string(),
+                    };
+                }
+
+                // Results from transcodes that have since been stopped or replaced are ignored
+                FutureResult::TranscoderNotificationSenderGone {
+                    stream_id,
+                    process_id,
+                } => {
+                    if self.transcode_process_id(&stream_id) == Some(process_id) {
+                        error!(
+                            stream_id = ?stream_id,
+                            "Transcode notification sender for stream {:?} disappeared",
+                            stream_id,
+                        );
+
+                        self.restart_transcode(&stream_id, &futures_channel);
+                    }
+                }
+
+                FutureResult::TranscodedMediaChannelClosed {
+                    stream_id,
+                    process_id,
+                } => {
+                    if self.transcode_process_id(&stream_id) == Some(process_id) {
+                        error!(
+                            stream_id = ?stream_id,
+                            "Sender of transcoded media for stream {:?} disappeared",
+                            stream_id,
+                        );
+
+                        self.restart_transcode(&stream_id, &futures_channel);
+                    }
+                }
+
+                FutureResult::TranscoderNotificationReceived {
+                    stream_id,
+                    process_id,
+                    notification,
+                } => {
+                    if self.transcode_process_id(&stream_id) == Some(process_id) {
+                        self.handle_transcode_notification(
+                            stream_id,
+                            notification,
+                            &futures_channel,
+                        );
+                    }
+                }
+
+                FutureResult::TranscodedMediaReceived {
+                    stream_id,
+                    process_id,
+                    media,
+                } => {
+                    if self.transcode_process_id(&stream_id) == Some(process_id) {
+                        self.handle_transcoded_media(stream_id, media, outputs);
+                    }
+                }
+            }
+        }
+
+        StepStatus::Active
+    }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        let passthrough_count = self
+            .active_streams
+            .values()
+            .filter(|stream| matches!(stream.mode, StreamMode::Passthrough))
+            .count();
+
+        details.insert(
+            PASSTHROUGH_STREAMS_DETAIL.to_string(),
+            passthrough_count.to_string(),
+        );
+
+        details.insert(
+            D::TRANSCODING_STREAMS_DETAIL.to_string(),
+            self.transcoding_stream_count().to_string(),
+        );
+
+        let streams = self
+            .active_streams
+            .values()
+            .map(|stream| StreamSummary {
+                name: &stream.name,
+                state: &stream.state,
+                status: match &stream.mode {
+                    StreamMode::Pending(_) => StreamStatus::Pending,
+                    StreamMode::Passthrough => StreamStatus::Passthrough,
+                    StreamMode::Transcoding(transcode) => {
+                        StreamStatus::Transcoding(&transcode.target)
+                    }
+                },
+            })
+            .collect::<Vec<_>>();
+
+        self.decider.add_state_details(&streams, &mut details);
+
+        details
+    }
+
+    fn get_active_pipeline_count(&self) -> usize {
+        self.transcoding_stream_count()
+    }
+
+    fn request_sequence_headers(&mut self, stream_id: &StreamId) -> bool {
+        if !D::CAN_FORCE_KEY_UNITS {
+            return false;
+        }
+
+        match self.transcode_process_id(stream_id) {
+            Some(process_id) => {
+                info!(
+                    stream_id = ?stream_id,
+                    "Requesting keyframe from transcode process {}", process_id
+                );
+
+                let _ = self
+                    .transcoder_endpoint
+                    .send(GstTranscoderRequest::ForceKeyUnit { id: process_id });
+
+                true
+            }
+
+            None => false,
+        }
+    }
+}
@@ -1,12 +1,14 @@
 //! Common utility functions that are useful for interacting with gstreamer.  These are mostly
 //! meant for use by code creating custom encoders.
 
+use crate::encoders::{EncoderQueueFullError, EncoderQueueLevel};
 use anyhow::{anyhow, Context, Result};
 use bytes::Bytes;
 use gstreamer::prelude::*;
-use gstreamer::{Buffer, Caps, ClockTime, Element, ElementFactory};
+use gstreamer::{Buffer, Caps, ClockTime, Element, ElementFactory, Format, Pipeline, Sample};
 use gstreamer_app::AppSrc;
-use mmids_core::codecs::{AUDIO_CODEC_AAC_RAW, VIDEO_CODEC_H264_AVC};
+use mmids_core::codecs::{AUDIO_CODEC_AAC_RAW, VIDEO_CODEC_H264_AVC, VIDEO_CODEC_H265_HVCC};
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -42,6 +44,107 @@ pub fn set_gst_buffer(data: Bytes, dts: Option<Duration>, pts: Option<Duration>)
     Ok(buffer)
 }
 
+/// The most bytes of media an encoder's `appsrc` will hold before it stops accepting more
+pub const MAX_SOURCE_QUEUE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Configures an encoder's `appsrc` so pushing media into it never blocks the caller, even when
+/// the encoder can't keep up. Once `MAX_SOURCE_QUEUE_BYTES` are queued, `push_buffer_to_source()`
+/// rejects media instead of growing the queue.
+pub fn configure_source_queue(source: &AppSrc) {
+    source.set_max_bytes(MAX_SOURCE_QUEUE_BYTES);
+    source.set_property("block", false);
+
+    // Leaky queues were added in gstreamer 1.20. When available, have the `appsrc` itself drop
+    // new buffers once it's full in case anything pushes into it directly.
+    if source.find_property("leaky-type").is_some() {
+        source.set_property_from_str("leaky-type", "upstream");
+    }
+}
+
+/// Gets how full an `appsrc`'s internal queue is
+pub fn source_queue_level(source: &AppSrc) -> EncoderQueueLevel {
+    EncoderQueueLevel {
+        queued_bytes: source.property::<u64>("current-level-bytes"),
+        max_bytes: source.max_bytes(),
+    }
+}
+
+/// Pushes a buffer into an `appsrc` without blocking. If the source's queue is already full, the
+/// buffer is not pushed and an `EncoderQueueFullError` is returned.
+pub fn push_buffer_to_source(source: &AppSrc, buffer: Buffer) -> Result<()> {
+    let level = source_queue_level(source);
+    if level.queued_bytes >= level.max_bytes {
+        return Err(EncoderQueueFullError(level).into());
+    }
+
+    source
+        .push_buffer(buffer)
+        .with_context(|| "Failed to push the buffer into the source")?;
+
+    Ok(())
+}
+
+/// Pushes a sample of decoded media into an `appsrc` without blocking, for encoders that encode
+/// raw media. The source's caps are replaced with the sample's whenever the media's format
+/// changes. If the source's queue is already full, the sample is not pushed and an
+/// `EncoderQueueFullError` is returned.
+pub fn push_raw_sample_to_source(source: &AppSrc, sample: Sample) -> Result<()> {
+    let caps = sample.caps().with_context(|| "Sample had no caps")?;
+    if source.caps().as_deref() != Some(caps) {
+        source.set_caps(Some(&caps.to_owned()));
+        source.set_format(Format::Time);
+    }
+
+    let buffer = sample
+        .buffer_owned()
+        .with_context(|| "Sample had no buffer")?;
+
+    push_buffer_to_source(source, buffer)
+}
+
+/// The encoder parameter specifying how many milliseconds of media each buffering element of an
+/// encoder's pipeline may hold
+pub const LATENCY_MS: &str = "latency_ms";
+
+/// Reads the optional `latency_ms` encoder parameter, which must be a whole number of
+/// milliseconds greater than zero.
+pub fn get_latency(parameters: &HashMap<String, Option<String>>) -> Result<Option<Duration>> {
+    match parameters.get(LATENCY_MS) {
+        Some(Some(value)) => match value.trim().parse::<u64>() {
+            Ok(millis) if millis > 0 => Ok(Some(Duration::from_millis(millis))),
+            _ => Err(anyhow!(
+                "{LATENCY_MS} had a value of '{value}', which is not a whole number greater than \
+                zero"
+            )),
+        },
+
+        _ => Ok(None),
+    }
+}
+
+/// Bounds how much media the pipeline's buffering elements (`queue` and `decodebin`) hold by time
+/// alone, and tells the pipeline to use the same latency. Elements without a `max-size-time`
+/// property are ignored.
+///
+/// Lower latencies get media out of the pipeline sooner, but leave less room to absorb jitter in
+/// the incoming stream or spikes in encoding time before the encoder falls behind. Higher
+/// latencies smooth over jittery inputs at the cost of delaying the output.
+pub fn configure_latency(pipeline: &Pipeline, elements: &[&Element], latency: Duration) {
+    let nanos = latency.as_nanos() as u64;
+    for element in elements {
+        if element.find_property("max-size-time").is_none() {
+            continue;
+        }
+
+        // Zero disables the byte and buffer limits, leaving the time limit as the only one
+        element.set_property("max-size-time", nanos);
+        element.set_property("max-size-bytes", 0_u32);
+        element.set_property("max-size-buffers", 0_u32);
+    }
+
+    pipeline.set_property("latency", nanos);
+}
+
 /// Sets up an video encoder's `appsrc`'s caps based on the specified codec.  Since sequence headers
 /// are not valid packets for the codec, we can't just push the sequence header into the appsrc's
 /// buffer.  Instead, different codecs have different mechanisms to pass the sequence header in
@@ -58,6 +161,16 @@ pub fn set_source_video_sequence_header(
 
         source.set_caps(Some(&caps));
 
+        Ok(())
+    } else if payload_type == *VIDEO_CODEC_H265_HVCC {
+        let caps = Caps::builder("video/x-h265")
+            .field("stream-format", "hvc1")
+            .field("alignment", "au")
+            .field("codec_data", buffer)
+            .build();
+
+        source.set_caps(Some(&caps));
+
         Ok(())
     } else {
         Err(anyhow!(
@@ -91,9 +204,76 @@ pub fn set_source_audio_sequence_header(
     }
 }
 
+/// Error raised when a gstreamer element mmids needs is not available on the system, which is
+/// almost always caused by the gstreamer plugin package providing it not being installed.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum GstElementError {
+    #[error("{element} not found; install {package}")]
+    MissingElement {
+        element: String,
+        package: &'static str,
+    },
+
+    #[error("{0} not found; install the gstreamer plugin that provides it")]
+    MissingUnknownElement(String),
+}
+
+impl GstElementError {
+    /// Creates the error for the specified element, including the package known to provide it
+    pub fn missing(element: &str) -> Self {
+        match plugin_package_for_element(element) {
+            Some(package) => GstElementError::MissingElement {
+                element: element.to_string(),
+                package,
+            },
+
+            None => GstElementError::MissingUnknownElement(element.to_string()),
+        }
+    }
+}
+
+/// Returns the name of the distribution package (using debian/ubuntu names) that provides the
+/// specified gstreamer element, if it's one that mmids is known to use.
+pub fn plugin_package_for_element(element: &str) -> Option<&'static str> {
+    match element {
+        "capsfilter" | "queue" | "identity" | "fakesink" | "tee" => Some("gstreamer1.0 (core)"),
+
+        "appsrc" | "appsink" | "decodebin" | "videoconvert" | "videoscale" | "videorate"
+        | "audioconvert" | "audioresample" | "videotestsrc" | "audiotestsrc" | "volume"
+        | "audiomixer" | "compositor" => Some("gstreamer1.0-plugins-base"),
+
+        "aacparse" | "flvmux" | "flvdemux" | "deinterlace" | "jpegenc" => {
+            Some("gstreamer1.0-plugins-good")
+        }
+        "h264parse" | "h265parse" | "x265enc" | "mpegtsmux" => Some("gstreamer1.0-plugins-bad"),
+
+        "x264enc" => Some("gstreamer1.0-plugins-ugly"),
+
+        x if x.starts_with("avenc_") || x.starts_with("avdec_") => Some("gstreamer1.0-libav"),
+
+        _ => None,
+    }
+}
+
+/// Verifies that every specified gstreamer element can be created, returning an error naming the
+/// first one that is missing. Gstreamer must be initialized before this is called.
+pub fn ensure_elements_available(elements: &[&str]) -> Result<(), GstElementError> {
+    match elements
+        .iter()
+        .find(|name| ElementFactory::find(name).is_none())
+    {
+        Some(name) => Err(GstElementError::missing(name)),
+        None => Ok(()),
+    }
+}
+
 /// Quick function to create an un-named gstreamer element, while providing a consumable error
 /// if that fails.
 pub fn create_gst_element(name: &str) -> Result<Element> {
+    if ElementFactory::find(name).is_none() {
+        return Err(GstElementError::missing(name).into());
+    }
+
     ElementFactory::make(name, None).with_context(|| format!("Failed to create element '{}'", name))
 }
 
@@ -130,3 +310,70 @@ pub fn get_codec_data_from_element(element: &Element) -> Result<Bytes> {
 
     Ok(bytes)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GSTREAMER_INIT_RESULT;
+
+    #[test]
+    fn missing_known_element_names_its_package() {
+        let error = GstElementError::missing("x264enc");
+
+        assert_eq!(
+            error.to_string(),
+            "x264enc not found; install gstreamer1.0-plugins-ugly"
+        );
+    }
+
+    #[test]
+    fn bogus_element_is_reported_as_missing() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let result = ensure_elements_available(&["queue", "mmidsbogusenc", "appsink"]);
+
+        assert_eq!(
+            result,
+            Err(GstElementError::MissingUnknownElement(
+                "mmidsbogusenc".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn invalid_latency_returns_error() {
+        let mut parameters = HashMap::new();
+        parameters.insert(LATENCY_MS.to_string(), Some("0".to_string()));
+        assert!(
+            get_latency(&parameters).is_err(),
+            "Expected zero to be rejected"
+        );
+
+        parameters.insert(LATENCY_MS.to_string(), Some("abc".to_string()));
+        assert!(
+            get_latency(&parameters).is_err(),
+            "Expected non-number to be rejected"
+        );
+
+        parameters.insert(LATENCY_MS.to_string(), Some("150".to_string()));
+        assert_eq!(
+            get_latency(&parameters).unwrap(),
+            Some(Duration::from_millis(150)),
+            "Unexpected latency"
+        );
+    }
+
+    #[test]
+    fn creating_bogus_element_returns_missing_element_error() {
+        (*GSTREAMER_INIT_RESULT).as_ref().unwrap();
+
+        let error = create_gst_element("mmidsbogusenc").unwrap_err();
+
+        assert_eq!(
+            error.downcast_ref::<GstElementError>(),
+            Some(&GstElementError::MissingUnknownElement(
+                "mmidsbogusenc".to_string()
+            ))
+        );
+    }
+}
@@ -5,6 +5,7 @@ use async_trait::async_trait;
 use hyper::http::HeaderValue;
 use hyper::{Body, Error, Request, Response, StatusCode};
 use mmids_core::workflows::manager::{WorkflowManagerRequest, WorkflowManagerRequestOperation};
+use mmids_core::workflows::steps::buffer_stats::{BufferLimit, BufferStats};
 use mmids_core::workflows::steps::StepStatus;
 use mmids_core::workflows::{WorkflowState, WorkflowStatus, WorkflowStepState};
 use serde::Serialize;
@@ -29,6 +30,8 @@ pub struct WorkflowStateResponse {
     status: String,
     active_steps: Vec<WorkflowStepStateResponse>,
     pending_steps: Vec<WorkflowStepStateResponse>,
+    watcher_count: usize,
+    max_watchers: Option<usize>,
 }
 
 /// API's response for the details of an individual workflow step
@@ -38,6 +41,21 @@ pub struct WorkflowStepStateResponse {
     step_type: String,
     parameters: HashMap<String, Option<String>>,
     status: String,
+    details: HashMap<String, String>,
+    restart_count: u64,
+    buffer_stats: Option<BufferStatsResponse>,
+}
+
+/// API's response for the media a buffering workflow step is holding onto
+#[derive(Serialize)]
+pub struct BufferStatsResponse {
+    current_bytes: u64,
+    current_packets: usize,
+    current_duration_ms: u128,
+    max_bytes: Option<u64>,
+    max_duration_ms: Option<u128>,
+    max_packets: Option<usize>,
+    drop_count: u64,
 }
 
 impl GetWorkflowDetailsHandler {
@@ -147,6 +165,9 @@ impl From<WorkflowState> for WorkflowStateResponse {
                 .into_iter()
                 .map(WorkflowStepStateResponse::from)
                 .collect(),
+
+            watcher_count: workflow.watcher_count,
+            max_watchers: workflow.max_watchers,
         }
     }
 }
@@ -163,6 +184,30 @@ impl From<WorkflowStepState> for WorkflowStepStateResponse {
                 StepStatus::Error { message } => format!("Error: {}", message),
                 StepStatus::Shutdown => "Shut Down".to_string(),
             },
+            details: step_state.details,
+            restart_count: step_state.restart_count,
+            buffer_stats: step_state.buffer_stats.map(BufferStatsResponse::from),
+        }
+    }
+}
+
+impl From<BufferStats> for BufferStatsResponse {
+    fn from(stats: BufferStats) -> Self {
+        let (max_bytes, max_duration_ms, max_packets) = match stats.max_configured {
+            Some(BufferLimit::Bytes(bytes)) => (Some(bytes), None, None),
+            Some(BufferLimit::Duration(duration)) => (None, Some(duration.as_millis()), None),
+            Some(BufferLimit::Packets(packets)) => (None, None, Some(packets)),
+            None => (None, None, None),
+        };
+
+        BufferStatsResponse {
+            current_bytes: stats.current_bytes,
+            current_packets: stats.current_packets,
+            current_duration_ms: stats.current_duration.as_millis(),
+            max_bytes,
+            max_duration_ms,
+            max_packets,
+            drop_count: stats.drop_count,
         }
     }
 }
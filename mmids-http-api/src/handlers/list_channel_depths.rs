@@ -0,0 +1,61 @@
+//! Contains the handler for getting how backed up mmids' internal channels are
+
+use crate::routing::RouteHandler;
+use async_trait::async_trait;
+use hyper::header::HeaderValue;
+use hyper::{Body, Error, Request, Response, StatusCode};
+use mmids_core::channel_metrics::channel_depths;
+use serde::Serialize;
+use std::collections::HashMap;
+use tracing::error;
+
+/// HTTP handler which provides the depth of each instrumented internal channel, for debugging
+/// backpressure
+pub struct ListChannelDepthsHandler;
+
+/// Defines what data the API will return for each channel
+#[derive(Serialize)]
+pub struct ChannelDepthResponse {
+    name: String,
+    depth: usize,
+    peak_depth: usize,
+}
+
+#[async_trait]
+impl RouteHandler for ListChannelDepthsHandler {
+    async fn execute(
+        &self,
+        _request: &mut Request<Body>,
+        _path_parameters: HashMap<String, String>,
+        _request_id: String,
+    ) -> Result<Response<Body>, Error> {
+        let response = channel_depths()
+            .into_iter()
+            .map(|x| ChannelDepthResponse {
+                name: x.name,
+                depth: x.depth,
+                peak_depth: x.peak_depth,
+            })
+            .collect::<Vec<_>>();
+
+        let json = match serde_json::to_string_pretty(&response) {
+            Ok(json) => json,
+            Err(error) => {
+                error!("Failed to serialize channel depths to json: {:?}", error);
+                let mut response = Response::default();
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+
+                return Ok(response);
+            }
+        };
+
+        let mut response = Response::new(Body::from(json));
+        let headers = response.headers_mut();
+        headers.insert(
+            hyper::http::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+
+        Ok(response)
+    }
+}
@@ -1,6 +1,8 @@
 //! Contains pre-defined implementations of the `RouteHandler` traits for various functionality
 
 pub mod get_workflow_details;
+pub mod list_channel_depths;
 pub mod list_workflows;
+pub mod serve_hls;
 pub mod start_workflow;
 pub mod stop_workflow;
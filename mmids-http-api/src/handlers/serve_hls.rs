@@ -0,0 +1,107 @@
+//! Handler that serves HLS playlists and segments from a directory, such as one the `ffmpeg_hls`
+//! step writes to.
+
+use crate::routing::RouteHandler;
+use async_trait::async_trait;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::{Body, Error, Request, Response, StatusCode};
+use mmids_core::url_signing::UrlSigner;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use tracing::{error, info};
+
+const PLAYLIST_EXTENSION: &str = ".m3u8";
+const UNSIGNED_PLAYLIST_EXTENSION: &str = ".unsigned.m3u8";
+
+/// Handles HTTP requests for HLS files.  It requires a single path parameter named `file` that
+/// contains the name of the file to serve out of the handler's directory.
+///
+/// When a url signer is provided, every file except playlists must be requested with a valid token
+/// (as added by playlists the `ffmpeg_hls` step signed with the same key), otherwise a 403 is
+/// returned.  Playlists are served without a token, as they are how clients are given tokens, but
+/// the unsigned playlists written alongside them are never served.
+pub struct ServeHlsHandler {
+    directory: PathBuf,
+    url_signer: Option<UrlSigner>,
+}
+
+impl ServeHlsHandler {
+    pub fn new(directory: PathBuf, url_signer: Option<UrlSigner>) -> Self {
+        ServeHlsHandler {
+            directory,
+            url_signer,
+        }
+    }
+}
+
+#[async_trait]
+impl RouteHandler for ServeHlsHandler {
+    async fn execute(
+        &self,
+        request: &mut Request<Body>,
+        path_parameters: HashMap<String, String>,
+        _request_id: String,
+    ) -> Result<Response<Body>, Error> {
+        let file = match path_parameters.get("file") {
+            Some(value) => value,
+            None => {
+                error!("Serve HLS endpoint called without a 'file' path parameter");
+                return Ok(status_response(StatusCode::INTERNAL_SERVER_ERROR));
+            }
+        };
+
+        if file.starts_with('.')
+            || file.contains(['/', '\\'])
+            || file.ends_with(UNSIGNED_PLAYLIST_EXTENSION)
+        {
+            return Ok(status_response(StatusCode::NOT_FOUND));
+        }
+
+        let is_playlist = file.ends_with(PLAYLIST_EXTENSION);
+        if let Some(signer) = &self.url_signer {
+            if !is_playlist {
+                let query = request.uri().query();
+                if let Err(error) = signer.validate(file, query, SystemTime::now()) {
+                    info!("Rejected request for HLS file '{}': {}", file, error);
+                    return Ok(status_response(StatusCode::FORBIDDEN));
+                }
+            }
+        }
+
+        let contents = match tokio::fs::read(self.directory.join(file)).await {
+            Ok(contents) => contents,
+            Err(_) => return Ok(status_response(StatusCode::NOT_FOUND)),
+        };
+
+        let mut response = Response::new(Body::from(contents));
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, content_type(file).parse().unwrap());
+
+        if is_playlist {
+            // Playlists are constantly updated, and signed playlists carry expiring tokens
+            response
+                .headers_mut()
+                .insert(CACHE_CONTROL, "no-cache".parse().unwrap());
+        }
+
+        Ok(response)
+    }
+}
+
+fn content_type(file: &str) -> &'static str {
+    match file.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("m3u8") => "application/vnd.apple.mpegurl",
+        Some("ts") => "video/mp2t",
+        Some("mp4") | Some("m4s") => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::default();
+    *response.status_mut() = status;
+
+    response
+}
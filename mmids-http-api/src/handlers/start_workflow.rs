@@ -6,10 +6,13 @@ use bytes::Bytes;
 use hyper::http::HeaderValue;
 use hyper::{Body, Error, Request, Response, StatusCode};
 use mmids_core::workflows::definitions::WorkflowDefinition;
-use mmids_core::workflows::manager::{WorkflowManagerRequest, WorkflowManagerRequestOperation};
+use mmids_core::workflows::manager::{
+    UpsertWorkflowResult, WorkflowManagerRequest, WorkflowManagerRequestOperation,
+};
 use serde::Serialize;
 use std::collections::HashMap;
 use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot::channel;
 use tracing::{error, warn};
 
 const MMIDS_MIME_TYPE: &str = "application/vnd.mmids.workflow";
@@ -20,7 +23,8 @@ const MMIDS_MIME_TYPE: &str = "application/vnd.mmids.workflow";
 ///
 /// A successful result does not mean that the workflow has fully started, only that the workflow
 /// has been submitted to the workflow manager. Status updates of the workflow will need to be
-/// queried to know if it successfully became active.
+/// queried to know if it successfully became active. A `503 Service Unavailable` is returned while
+/// the workflow manager is in maintenance mode, and the request should be retried later.
 ///
 /// The details of the workflow are expected in the passed in via the request body.  The format
 /// that the details come in are based on the `Content-Type` header of the request:
@@ -81,18 +85,36 @@ impl RouteHandler for StartWorkflowHandler {
             }
         };
 
+        let (response_sender, response_receiver) = channel();
         let result = self.manager.send(WorkflowManagerRequest {
             request_id,
             operation: WorkflowManagerRequestOperation::UpsertWorkflow {
                 definition: workflow,
+                response_channel: Some(response_sender),
             },
         });
 
-        match result {
-            Ok(_) => Ok(Response::default()),
+        if result.is_err() {
+            error!("Workflow manager no longer exists");
+            let mut response = Response::default();
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+
+            return Ok(response);
+        }
+
+        match response_receiver.await {
+            Ok(UpsertWorkflowResult::Upserted) => Ok(Response::default()),
+
+            Ok(UpsertWorkflowResult::InMaintenance) => {
+                warn!("Workflow manager is in maintenance mode, workflow not started");
+                let mut response = Response::default();
+                *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+
+                Ok(response)
+            }
 
             Err(_) => {
-                error!("Workflow manager no longer exists");
+                error!("Workflow manager closed the response channel without responding");
                 let mut response = Response::default();
                 *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
 
@@ -4,9 +4,9 @@ use crate::rtmp_server::{
     IpRestriction, RtmpEndpointMediaData, RtmpEndpointMediaMessage,
     RtmpEndpointWatcherNotification, ValidationResponse,
 };
-use bytes::Bytes;
 use mmids_core::net::tcp::TcpSocketResponse;
 use mmids_core::net::ConnectionId;
+use mmids_core::workflows::bootstrap::WatcherBootstrap;
 use mmids_core::StreamId;
 use std::collections::HashMap;
 use std::net::SocketAddr;
@@ -75,14 +75,6 @@ pub struct WatcherRegistrant {
     pub cancellation_notifier: UnboundedReceiver<()>,
 }
 
-pub struct VideoSequenceHeader {
-    pub data: Bytes,
-}
-
-pub struct AudioSequenceHeader {
-    pub data: Bytes,
-}
-
 pub struct WatcherDetails {
     pub media_sender: UnboundedSender<RtmpEndpointMediaData>,
 }
@@ -90,8 +82,7 @@ pub struct WatcherDetails {
 pub struct StreamKeyConnections {
     pub publisher: Option<ConnectionId>,
     pub watchers: HashMap<ConnectionId, WatcherDetails>,
-    pub latest_video_sequence_header: Option<VideoSequenceHeader>,
-    pub latest_audio_sequence_header: Option<AudioSequenceHeader>,
+    pub watcher_bootstrap: WatcherBootstrap<RtmpEndpointMediaData>,
 }
 
 pub struct RtmpAppMapping {
@@ -4,6 +4,9 @@ use anyhow::{anyhow, Result};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use bytes::{BufMut, Bytes, BytesMut};
 use mmids_core::actor_utils::{notify_on_unbounded_closed, notify_on_unbounded_recv};
+use mmids_core::codecs::{
+    VIDEO_CODEC_AV1, VIDEO_CODEC_H264_AVC, VIDEO_CODEC_H265_HVCC, VIDEO_CODEC_VP9,
+};
 use mmids_core::net::tcp::OutboundPacket;
 use mmids_core::net::ConnectionId;
 use rml_rtmp::handshake::{Handshake, HandshakeProcessResult, PeerType};
@@ -15,7 +18,20 @@ use rml_rtmp::time::RtmpTimestamp;
 use std::io::Cursor;
 use std::sync::Arc;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-use tracing::{error, info, instrument};
+use tracing::{error, info, instrument, warn};
+
+const FLV_VIDEO_CODEC_H264: u8 = 0x07;
+const FLV_VIDEO_FRAME_TYPE_KEYFRAME: u8 = 1;
+
+// Enhanced RTMP video tags set the high bit of the first byte, and replace the codec id with a
+// packet type followed by a FourCC identifying the codec
+const EX_VIDEO_HEADER_FLAG: u8 = 0x80;
+const EX_VIDEO_FRAME_TYPE_COMMAND: u8 = 5;
+const EX_VIDEO_PACKET_SEQUENCE_START: u8 = 0;
+const EX_VIDEO_PACKET_CODED_FRAMES: u8 = 1;
+const EX_VIDEO_PACKET_SEQUENCE_END: u8 = 2;
+const EX_VIDEO_PACKET_CODED_FRAMES_X: u8 = 3;
+const EX_VIDEO_PACKET_METADATA: u8 = 4;
 
 pub struct RtmpServerConnectionHandler {
     internal_sender: UnboundedSender<FutureResult>,
@@ -27,6 +43,7 @@ pub struct RtmpServerConnectionHandler {
     request_sender: UnboundedSender<ConnectionRequest>,
     force_disconnect: bool,
     published_event_channel: Option<UnboundedSender<RtmpEndpointPublisherMessage>>,
+    unsupported_video_reported: bool,
 }
 
 #[derive(Debug)]
@@ -107,6 +124,7 @@ pub enum FutureResult {
 }
 
 struct UnwrappedVideo {
+    codec: Arc<String>,
     is_keyframe: bool,
     is_sequence_header: bool,
     data: Bytes,
@@ -135,6 +153,7 @@ impl RtmpServerConnectionHandler {
             request_sender,
             force_disconnect: false,
             published_event_channel: None,
+            unsupported_video_reported: false,
         }
     }
 
@@ -625,9 +644,15 @@ impl RtmpServerConnectionHandler {
                 }
 
                 let unwrapped_video = match unwrap_video_from_flv(data) {
-                    Ok(video) => video,
+                    Ok(Some(video)) => video,
+                    Ok(None) => return, // packet doesn't contain any media
                     Err(error) => {
-                        error!("Video is using an unsupported set of flv video tags: {error}");
+                        // Publishers will keep sending the same video, so only report it once
+                        // instead of for every packet
+                        if !self.unsupported_video_reported {
+                            warn!("Video is using an unsupported set of flv video tags, and will be dropped: {error}");
+                            self.unsupported_video_reported = true;
+                        }
 
                         return;
                     }
@@ -636,6 +661,7 @@ impl RtmpServerConnectionHandler {
                 let _ = self.published_event_channel.as_ref().unwrap().send(
                     RtmpEndpointPublisherMessage::NewVideoData {
                         publisher: self.id.clone(),
+                        codec: unwrapped_video.codec,
                         is_keyframe: unwrapped_video.is_keyframe,
                         is_sequence_header: unwrapped_video.is_sequence_header,
                         data: unwrapped_video.data,
@@ -1002,10 +1028,20 @@ impl RtmpServerConnectionHandler {
     }
 }
 
-fn unwrap_video_from_flv(mut data: Bytes) -> Result<UnwrappedVideo> {
-    if data.len() < 2 {
+/// Unwraps video from an flv video tag, either in the legacy format (h264 only) or the enhanced
+/// RTMP format. `None` is returned for packets that don't contain any media.
+fn unwrap_video_from_flv(mut data: Bytes) -> Result<Option<UnwrappedVideo>> {
+    if data.is_empty() {
+        return Err(anyhow!("FLV segment had no bytes, and thus invalid"));
+    }
+
+    if data[0] & EX_VIDEO_HEADER_FLAG == EX_VIDEO_HEADER_FLAG {
+        return unwrap_enhanced_video_from_flv(data);
+    }
+
+    if data.len() < 5 {
         return Err(anyhow!(
-            "FLV segment had less than 2 bytes, and thus invalid"
+            "FLV segment had less than 5 bytes, and thus invalid"
         ));
     }
 
@@ -1013,7 +1049,7 @@ fn unwrap_video_from_flv(mut data: Bytes) -> Result<UnwrappedVideo> {
     let avc_header = data.split_to(4);
 
     let is_sequence_header = avc_header[0] == 0x00;
-    if flv_tag[0] & 0x07 != 0x07 {
+    if flv_tag[0] & 0x07 != FLV_VIDEO_CODEC_H264 {
         return Err(anyhow!("FLV segment was not h264, and not supported"));
     }
 
@@ -1027,12 +1063,77 @@ fn unwrap_video_from_flv(mut data: Bytes) -> Result<UnwrappedVideo> {
         0
     };
 
-    Ok(UnwrappedVideo {
+    Ok(Some(UnwrappedVideo {
+        codec: VIDEO_CODEC_H264_AVC.clone(),
         is_keyframe,
         is_sequence_header,
         data,
         composition_time_in_ms: composition_time,
-    })
+    }))
+}
+
+fn unwrap_enhanced_video_from_flv(mut data: Bytes) -> Result<Option<UnwrappedVideo>> {
+    let header = data.split_to(1)[0];
+    let frame_type = (header >> 4) & 0x07;
+    let packet_type = header & 0x0f;
+    if frame_type == EX_VIDEO_FRAME_TYPE_COMMAND {
+        return Ok(None);
+    }
+
+    if data.len() < 4 {
+        return Err(anyhow!(
+            "Enhanced RTMP video segment did not contain a FourCC, and thus invalid"
+        ));
+    }
+
+    let fourcc = data.split_to(4);
+    let codec = match &fourcc[..] {
+        b"hvc1" => VIDEO_CODEC_H265_HVCC.clone(),
+        b"av01" => VIDEO_CODEC_AV1.clone(),
+        b"vp09" => VIDEO_CODEC_VP9.clone(),
+        _ => {
+            return Err(anyhow!(
+                "Enhanced RTMP video segment used the FourCC '{}', which is not supported",
+                String::from_utf8_lossy(&fourcc)
+            ))
+        }
+    };
+
+    let (is_sequence_header, composition_time) = match packet_type {
+        EX_VIDEO_PACKET_SEQUENCE_START => (true, 0),
+        EX_VIDEO_PACKET_CODED_FRAMES_X => (false, 0),
+        EX_VIDEO_PACKET_CODED_FRAMES if codec == *VIDEO_CODEC_H265_HVCC => {
+            // Only HEVC coded frames are preceded by a composition time offset
+            if data.len() < 3 {
+                return Err(anyhow!(
+                    "Enhanced RTMP HEVC segment did not contain a composition time, and thus invalid"
+                ));
+            }
+
+            let composition_time = data.split_to(3);
+            let composition_time = Cursor::new(&composition_time[..])
+                .read_i24::<BigEndian>()
+                .unwrap_or_default();
+
+            (false, composition_time)
+        }
+
+        EX_VIDEO_PACKET_CODED_FRAMES => (false, 0),
+        EX_VIDEO_PACKET_SEQUENCE_END | EX_VIDEO_PACKET_METADATA => return Ok(None),
+        packet_type => {
+            return Err(anyhow!(
+                "Enhanced RTMP video segment had a packet type of {packet_type}, which is not supported"
+            ))
+        }
+    };
+
+    Ok(Some(UnwrappedVideo {
+        codec,
+        is_keyframe: frame_type == FLV_VIDEO_FRAME_TYPE_KEYFRAME,
+        is_sequence_header,
+        data,
+        composition_time_in_ms: composition_time,
+    }))
 }
 
 fn wrap_video_into_flv(
@@ -17,11 +17,11 @@ use connection_handler::{ConnectionRequest, RtmpServerConnectionHandler};
 use mmids_core::actor_utils::{
     notify_on_future_completion, notify_on_unbounded_closed, notify_on_unbounded_recv,
 };
-use mmids_core::net::tcp::{TcpSocketRequest, TcpSocketResponse};
+use mmids_core::net::tcp::{SocketOptions, TcpSocketRequest, TcpSocketResponse};
 use mmids_core::net::ConnectionId;
 use mmids_core::reactors::ReactorWorkflowUpdate;
+use mmids_core::workflows::bootstrap::BootstrapMediaKind;
 use mmids_core::StreamId;
-use rml_rtmp::time::RtmpTimestamp;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
@@ -194,7 +194,7 @@ impl RtmpServerEndpointActor {
                             port,
                             rtmp_app,
                             stream_key,
-                            Some(reactor_update_channel),
+                            reactor_update_channel,
                             self.internal_actor.clone(),
                         );
                     }
@@ -220,13 +220,50 @@ impl RtmpServerEndpointActor {
                             port,
                             rtmp_app,
                             stream_key,
-                            Some(reactor_update_channel),
+                            reactor_update_channel,
                             self.internal_actor.clone(),
                         );
                     }
                 }
             }
 
+            ValidationResponse::Redirect {
+                stream_key: redirected_key,
+            } => match &connection.state {
+                ConnectionState::WaitingForWatchValidation {
+                    rtmp_app,
+                    stream_key,
+                } => {
+                    info!(
+                        rtmp_app = %rtmp_app,
+                        stream_key = %stream_key,
+                        redirected_stream_key = %redirected_key,
+                        "Request to watch was redirected",
+                    );
+
+                    let rtmp_app = rtmp_app.clone();
+
+                    connection.received_registrant_approval = true;
+                    handle_connection_request_watch(
+                        connection_id,
+                        port_map,
+                        port,
+                        rtmp_app,
+                        redirected_key,
+                        None,
+                        self.internal_actor.clone(),
+                    );
+                }
+
+                _ => {
+                    warn!("Unexpected redirect for connection not waiting for watch validation");
+
+                    let _ = connection
+                        .response_channel
+                        .send(ConnectionResponse::RequestRejected);
+                }
+            },
+
             ValidationResponse::Reject => {
                 match &connection.state {
                     ConnectionState::None => {
@@ -295,36 +332,29 @@ impl RtmpServerEndpointActor {
                 .or_insert(StreamKeyConnections {
                     watchers: HashMap::new(),
                     publisher: None,
-                    latest_video_sequence_header: None,
-                    latest_audio_sequence_header: None,
+                    watcher_bootstrap: WatcherBootstrap::new(),
                 });
 
-        match &data {
+        let bootstrap_kind = match &data {
             RtmpEndpointMediaData::NewVideoData {
-                data,
-                is_sequence_header,
+                is_sequence_header: true,
                 ..
-            } => {
-                if *is_sequence_header {
-                    key_details.latest_video_sequence_header =
-                        Some(VideoSequenceHeader { data: data.clone() });
-                }
-            }
+            } => BootstrapMediaKind::VideoSequenceHeader,
+
+            RtmpEndpointMediaData::NewVideoData {
+                is_keyframe: true, ..
+            } => BootstrapMediaKind::VideoKeyframe,
 
             RtmpEndpointMediaData::NewAudioData {
-                data,
-                is_sequence_header,
+                is_sequence_header: true,
                 ..
-            } => {
-                if *is_sequence_header {
-                    key_details.latest_audio_sequence_header =
-                        Some(AudioSequenceHeader { data: data.clone() });
-                }
-            }
+            } => BootstrapMediaKind::AudioSequenceHeader,
 
-            _ => (),
+            _ => BootstrapMediaKind::Other,
         };
 
+        key_details.watcher_bootstrap.track(&data, bootstrap_kind);
+
         for watcher_details in key_details.watchers.values() {
             let _ = watcher_details.media_sender.send(data.clone());
         }
@@ -461,10 +491,13 @@ impl RtmpServerEndpointActor {
 
         if new_port_requested {
             let (sender, receiver) = unbounded_channel();
+            // A port is shared by every registrant on it, so socket options can't come from any
+            // single registrant's step and the operating system defaults are used instead
             let request = TcpSocketRequest::OpenPort {
                 port: params.port,
                 response_channel: sender,
                 use_tls: params.use_tls,
+                socket_options: SocketOptions::default(),
             };
 
             let _ = params.socket_sender.send(request);
@@ -983,34 +1016,52 @@ fn handle_connection_stop_watch(connection_id: ConnectionId, port_map: &mut Port
         let rtmp_app = rtmp_app.clone();
         let stream_key = stream_key.clone();
         connection.state = ConnectionState::None;
-        match port_map.rtmp_applications.get_mut(&rtmp_app) {
-            None => (),
-            Some(app_map) => match app_map.active_stream_keys.get_mut(&stream_key) {
-                None => (),
-                Some(active_key) => {
-                    active_key.watchers.remove(&connection_id);
-
-                    if active_key.watchers.is_empty() {
-                        let registrant =
-                            match app_map.watcher_registrants.get(&StreamKeyRegistration::Any) {
-                                Some(x) => Some(x),
-                                None => app_map
-                                    .watcher_registrants
-                                    .get(&StreamKeyRegistration::Exact(stream_key.clone())),
-                            };
-
-                        if let Some(registrant) = registrant {
-                            let _ = registrant.response_channel.send(
-                                RtmpEndpointWatcherNotification::StreamKeyBecameInactive {
-                                    stream_key,
-                                },
-                            );
-                        }
-                    }
-                }
-            },
+        remove_watcher(connection_id, port_map, rtmp_app, stream_key);
+    }
+}
+
+/// Removes a connection that was watching (or waiting for approval to watch) the stream key,
+/// letting the registrant know the connection stopped watching and if the stream key no longer
+/// has any watchers.
+fn remove_watcher(
+    connection_id: ConnectionId,
+    port_map: &mut PortMapping,
+    rtmp_app: Arc<String>,
+    stream_key: Arc<String>,
+) {
+    let app_map = match port_map.rtmp_applications.get_mut(&rtmp_app) {
+        Some(app_map) => app_map,
+        None => return,
+    };
+
+    let registrant = match app_map.watcher_registrants.get(&StreamKeyRegistration::Any) {
+        Some(x) => Some(x),
+        None => app_map
+            .watcher_registrants
+            .get(&StreamKeyRegistration::Exact(stream_key.clone())),
+    };
+
+    if let Some(active_key) = app_map.active_stream_keys.get_mut(&stream_key) {
+        let was_watching = active_key.watchers.remove(&connection_id).is_some();
+        if was_watching && active_key.watchers.is_empty() {
+            if let Some(registrant) = registrant {
+                let _ = registrant.response_channel.send(
+                    RtmpEndpointWatcherNotification::StreamKeyBecameInactive {
+                        stream_key: stream_key.clone(),
+                    },
+                );
+            }
         }
     }
+
+    if let Some(registrant) = registrant {
+        let _ = registrant
+            .response_channel
+            .send(RtmpEndpointWatcherNotification::WatcherStopped {
+                connection_id,
+                stream_key,
+            });
+    }
 }
 
 fn handle_connection_stop_publish(connection_id: ConnectionId, port_map: &mut PortMapping) {
@@ -1046,8 +1097,7 @@ fn handle_connection_stop_publish(connection_id: ConnectionId, port_map: &mut Po
                         Some(publisher_id) => {
                             if *publisher_id == connection_id {
                                 active_key.publisher = None;
-                                active_key.latest_video_sequence_header = None;
-                                active_key.latest_audio_sequence_header = None;
+                                active_key.watcher_bootstrap.clear();
 
                                 let registrant = match app_map
                                     .publisher_registrants
@@ -1176,6 +1226,7 @@ fn handle_connection_request_watch(
             RtmpEndpointWatcherNotification::WatcherRequiringApproval {
                 stream_key,
                 connection_id: connection_id.clone(),
+                client_ip: connection.socket_address.ip(),
                 response_channel: sender,
             },
         );
@@ -1191,8 +1242,7 @@ fn handle_connection_request_watch(
         .or_insert(StreamKeyConnections {
             watchers: HashMap::new(),
             publisher: None,
-            latest_video_sequence_header: None,
-            latest_audio_sequence_header: None,
+            watcher_bootstrap: WatcherBootstrap::new(),
         });
 
     connection.state = ConnectionState::Watching {
@@ -1211,24 +1261,10 @@ fn handle_connection_request_watch(
 
     let (media_sender, media_receiver) = unbounded_channel();
 
-    // If we have a sequence headers available, send it to the client so they can immediately
-    // start decoding video
-    if let Some(sequence_header) = &active_stream_key.latest_video_sequence_header {
-        let _ = media_sender.send(RtmpEndpointMediaData::NewVideoData {
-            is_sequence_header: true,
-            is_keyframe: true,
-            data: sequence_header.data.clone(),
-            timestamp: RtmpTimestamp::new(0),
-            composition_time_offset: 0,
-        });
-    }
-
-    if let Some(sequence_header) = &active_stream_key.latest_audio_sequence_header {
-        let _ = media_sender.send(RtmpEndpointMediaData::NewAudioData {
-            data: sequence_header.data.clone(),
-            is_sequence_header: true,
-            timestamp: RtmpTimestamp::new(0),
-        });
+    // If we have sequence headers and a keyframe available, send them to the client so they can
+    // immediately start decoding video
+    for media in active_stream_key.watcher_bootstrap.bootstrap_sequence() {
+        let _ = media_sender.send(media);
     }
 
     active_stream_key
@@ -1313,8 +1349,7 @@ fn handle_connection_request_publish(
         .or_insert(StreamKeyConnections {
             publisher: None,
             watchers: HashMap::new(),
-            latest_video_sequence_header: None,
-            latest_audio_sequence_header: None,
+            watcher_bootstrap: WatcherBootstrap::new(),
         });
 
     // Is someone already publishing on this stream key?
@@ -1450,7 +1485,11 @@ fn clean_disconnected_connection(connection_id: ConnectionId, port_map: &mut Por
     match connection.state {
         ConnectionState::None => (),
         ConnectionState::WaitingForPublishValidation { .. } => (),
-        ConnectionState::WaitingForWatchValidation { .. } => (),
+        ConnectionState::WaitingForWatchValidation {
+            rtmp_app,
+            stream_key,
+        } => remove_watcher(connection_id, port_map, rtmp_app, stream_key),
+
         ConnectionState::Publishing {
             rtmp_app,
             stream_key,
@@ -1464,8 +1503,7 @@ fn clean_disconnected_connection(connection_id: ConnectionId, port_map: &mut Por
                         Some(publisher_id) => {
                             if *publisher_id == connection_id {
                                 active_key.publisher = None;
-                                active_key.latest_video_sequence_header = None;
-                                active_key.latest_audio_sequence_header = None;
+                                active_key.watcher_bootstrap.clear();
 
                                 let registrant = match app_map
                                     .publisher_registrants
@@ -1494,33 +1532,7 @@ fn clean_disconnected_connection(connection_id: ConnectionId, port_map: &mut Por
         ConnectionState::Watching {
             rtmp_app,
             stream_key,
-        } => match port_map.rtmp_applications.get_mut(&rtmp_app) {
-            None => (),
-            Some(app_map) => match app_map.active_stream_keys.get_mut(&stream_key) {
-                None => (),
-                Some(active_key) => {
-                    active_key.watchers.remove(&connection_id);
-
-                    if active_key.watchers.is_empty() {
-                        let registrant =
-                            match app_map.watcher_registrants.get(&StreamKeyRegistration::Any) {
-                                Some(x) => Some(x),
-                                None => app_map
-                                    .watcher_registrants
-                                    .get(&StreamKeyRegistration::Exact(stream_key.clone())),
-                            };
-
-                        if let Some(registrant) = registrant {
-                            let _ = registrant.response_channel.send(
-                                RtmpEndpointWatcherNotification::StreamKeyBecameInactive {
-                                    stream_key,
-                                },
-                            );
-                        }
-                    }
-                }
-            },
-        },
+        } => remove_watcher(connection_id, port_map, rtmp_app, stream_key),
     };
 }
 
@@ -6,6 +6,7 @@ use crate::rtmp_server::{
     StreamKeyRegistration, ValidationResponse,
 };
 use bytes::Bytes;
+use mmids_core::codecs::{VIDEO_CODEC_AV1, VIDEO_CODEC_H264_AVC, VIDEO_CODEC_H265_HVCC};
 use mmids_core::test_utils;
 use rml_rtmp::sessions::{ClientSessionEvent, StreamMetadata};
 use rml_rtmp::time::RtmpTimestamp;
@@ -885,6 +886,7 @@ async fn notification_raised_when_video_published() {
     match response {
         RtmpEndpointPublisherMessage::NewVideoData {
             publisher,
+            codec: _,
             timestamp: event_timestamp,
             data: event_data,
             is_sequence_header: _,
@@ -938,6 +940,7 @@ async fn published_video_sequence_header_when_h264_and_second_byte_is_zero() {
     match response {
         RtmpEndpointPublisherMessage::NewVideoData {
             publisher: _,
+            codec: _,
             timestamp: _,
             data: _,
             is_sequence_header,
@@ -965,6 +968,7 @@ async fn published_video_not_sequence_header_when_h264_and_second_byte_is_not_ze
     match response {
         RtmpEndpointPublisherMessage::NewVideoData {
             publisher: _,
+            codec: _,
             timestamp: _,
             data: _,
             is_sequence_header,
@@ -992,6 +996,7 @@ async fn published_video_not_key_frame_when_first_4_half_octet_is_not_one() {
     match response {
         RtmpEndpointPublisherMessage::NewVideoData {
             publisher: _,
+            codec: _,
             timestamp: _,
             data: _,
             is_sequence_header: _,
@@ -1019,6 +1024,7 @@ async fn published_video_key_frame_when_first_4_half_octet_is_one() {
     match response {
         RtmpEndpointPublisherMessage::NewVideoData {
             publisher: _,
+            codec: _,
             timestamp: _,
             data: _,
             is_sequence_header: _,
@@ -1032,6 +1038,136 @@ async fn published_video_key_frame_when_first_4_half_octet_is_one() {
     };
 }
 
+#[tokio::test]
+async fn enhanced_rtmp_hevc_sequence_header_published() {
+    let mut context = TestContextBuilder::new().into_publisher().await;
+    context.set_as_active_publisher().await;
+
+    // Extended header flag, keyframe frame type, and sequence start packet type
+    let mut data = vec![0x90];
+    data.extend_from_slice(b"hvc1");
+    data.extend_from_slice(&[1, 2, 3, 4]); // decoder configuration record
+    let data = Bytes::from(data);
+    let timestamp = RtmpTimestamp::new(5);
+    context.client.publish_video(data.clone(), timestamp);
+
+    let receiver = context.publish_receiver.as_mut().unwrap();
+    let response = test_utils::expect_mpsc_response(receiver).await;
+    match response {
+        RtmpEndpointPublisherMessage::NewVideoData {
+            codec,
+            data: event_data,
+            is_sequence_header,
+            is_keyframe,
+            composition_time_offset,
+            ..
+        } => {
+            assert_eq!(codec, *VIDEO_CODEC_H265_HVCC, "Unexpected codec");
+            assert!(is_sequence_header, "Expected a sequence header");
+            assert!(is_keyframe, "Expected a keyframe");
+            assert_eq!(composition_time_offset, 0, "Unexpected composition time");
+            assert_eq!(event_data, data[5..], "Unexpected video data");
+        }
+
+        message => panic!("Unexpected publisher message: {:?}", message),
+    };
+}
+
+#[tokio::test]
+async fn enhanced_rtmp_hevc_coded_frames_read_composition_time() {
+    let mut context = TestContextBuilder::new().into_publisher().await;
+    context.set_as_active_publisher().await;
+
+    // Extended header flag, inter frame type, and coded frames packet type
+    let mut data = vec![0xa1];
+    data.extend_from_slice(b"hvc1");
+    data.extend_from_slice(&[0, 0, 40, 5, 6, 7]);
+    let data = Bytes::from(data);
+    let timestamp = RtmpTimestamp::new(5);
+    context.client.publish_video(data.clone(), timestamp);
+
+    let receiver = context.publish_receiver.as_mut().unwrap();
+    let response = test_utils::expect_mpsc_response(receiver).await;
+    match response {
+        RtmpEndpointPublisherMessage::NewVideoData {
+            codec,
+            data: event_data,
+            is_sequence_header,
+            is_keyframe,
+            composition_time_offset,
+            ..
+        } => {
+            assert_eq!(codec, *VIDEO_CODEC_H265_HVCC, "Unexpected codec");
+            assert!(!is_sequence_header, "Expected not to be a sequence header");
+            assert!(!is_keyframe, "Expected not to be a keyframe");
+            assert_eq!(composition_time_offset, 40, "Unexpected composition time");
+            assert_eq!(event_data, data[8..], "Unexpected video data");
+        }
+
+        message => panic!("Unexpected publisher message: {:?}", message),
+    };
+}
+
+#[tokio::test]
+async fn enhanced_rtmp_av1_coded_frames_have_no_composition_time() {
+    let mut context = TestContextBuilder::new().into_publisher().await;
+    context.set_as_active_publisher().await;
+
+    let mut data = vec![0x91];
+    data.extend_from_slice(b"av01");
+    data.extend_from_slice(&[1, 2, 3]);
+    let data = Bytes::from(data);
+    let timestamp = RtmpTimestamp::new(5);
+    context.client.publish_video(data.clone(), timestamp);
+
+    let receiver = context.publish_receiver.as_mut().unwrap();
+    let response = test_utils::expect_mpsc_response(receiver).await;
+    match response {
+        RtmpEndpointPublisherMessage::NewVideoData {
+            codec,
+            data: event_data,
+            composition_time_offset,
+            ..
+        } => {
+            assert_eq!(codec, *VIDEO_CODEC_AV1, "Unexpected codec");
+            assert_eq!(composition_time_offset, 0, "Unexpected composition time");
+            assert_eq!(event_data, data[5..], "Unexpected video data");
+        }
+
+        message => panic!("Unexpected publisher message: {:?}", message),
+    };
+}
+
+#[tokio::test]
+async fn enhanced_rtmp_video_with_unknown_fourcc_dropped() {
+    let mut context = TestContextBuilder::new().into_publisher().await;
+    context.set_as_active_publisher().await;
+
+    let mut data = vec![0x90];
+    data.extend_from_slice(b"abcd");
+    data.extend_from_slice(&[1, 2, 3]);
+    context
+        .client
+        .publish_video(Bytes::from(data), RtmpTimestamp::new(5));
+
+    // The publisher should stay connected, and still be able to send supported video
+    let data = Bytes::from(vec![0x17, 0, 0, 0, 0, 1, 2, 3]);
+    context.client.publish_video(data, RtmpTimestamp::new(6));
+
+    let receiver = context.publish_receiver.as_mut().unwrap();
+    let response = test_utils::expect_mpsc_response(receiver).await;
+    match response {
+        RtmpEndpointPublisherMessage::NewVideoData {
+            codec, timestamp, ..
+        } => {
+            assert_eq!(codec, *VIDEO_CODEC_H264_AVC, "Unexpected codec");
+            assert_eq!(timestamp, RtmpTimestamp::new(6), "Unexpected timestamp");
+        }
+
+        message => panic!("Unexpected publisher message: {:?}", message),
+    };
+}
+
 #[tokio::test]
 async fn notification_raised_when_metadata_published() {
     let mut context = TestContextBuilder::new().into_publisher().await;
@@ -1244,6 +1380,32 @@ async fn stream_becomes_inactive_when_only_watcher_disconnects() {
     }
 }
 
+#[tokio::test]
+async fn watcher_stopped_notification_sent_after_watcher_stops_playback() {
+    let mut context = TestContextBuilder::new().into_watcher().await;
+    context.set_as_active_watcher().await;
+    context.client.stop_watching().await;
+
+    let receiver = context.watch_receiver.as_mut().unwrap();
+    let _ = test_utils::expect_mpsc_response(receiver).await; // inactive notification
+    let response = test_utils::expect_mpsc_response(receiver).await;
+    match response {
+        RtmpEndpointWatcherNotification::WatcherStopped {
+            connection_id,
+            stream_key,
+        } => {
+            assert_eq!(stream_key.as_str(), "key", "Unexpected stream key");
+            assert_eq!(
+                connection_id.0.as_str(),
+                rtmp_client::CONNECTION_ID,
+                "Unexpected connection id"
+            );
+        }
+
+        message => panic!("Unexpected watcher message received: {:?}", message),
+    }
+}
+
 #[tokio::test]
 async fn watcher_receives_metadata() {
     let mut context = TestContextBuilder::new().into_watcher().await;
@@ -1492,7 +1654,7 @@ async fn consumer_accepts_publisher() {
             let (_sender, receiver) = unbounded_channel();
             response_channel
                 .send(ValidationResponse::Approve {
-                    reactor_update_channel: receiver,
+                    reactor_update_channel: Some(receiver),
                 })
                 .expect("Failed to send approval")
         }
@@ -1593,6 +1755,7 @@ async fn consumer_accepts_watcher() {
             stream_key,
             connection_id,
             response_channel,
+            ..
         } => {
             assert_eq!(stream_key.as_str(), "key", "Unexpected stream key");
             assert_eq!(
@@ -1604,7 +1767,7 @@ async fn consumer_accepts_watcher() {
             let (_sender, receiver) = unbounded_channel();
             response_channel
                 .send(ValidationResponse::Approve {
-                    reactor_update_channel: receiver,
+                    reactor_update_channel: Some(receiver),
                 })
                 .expect("Failed to send approval")
         }
@@ -1654,6 +1817,7 @@ async fn consumer_rejecting_watcher_disconnects_client() {
             stream_key,
             connection_id,
             response_channel,
+            ..
         } => {
             assert_eq!(stream_key.as_str(), "key", "Unexpected stream key");
             assert_eq!(
@@ -1672,3 +1836,87 @@ async fn consumer_rejecting_watcher_disconnects_client() {
 
     context.client.assert_connection_sender_closed().await;
 }
+
+#[tokio::test]
+async fn consumer_redirecting_watcher_makes_redirected_stream_key_active() {
+    let mut context = TestContextBuilder::new()
+        .set_requires_registrant_approval(true)
+        .into_watcher()
+        .await;
+
+    context.client.perform_handshake().await;
+    context
+        .client
+        .connect_to_app(context.rtmp_app.clone(), true)
+        .await;
+
+    context
+        .client
+        .watch_stream_key("key".to_string(), false)
+        .await;
+
+    let receiver = context.watch_receiver.as_mut().unwrap();
+    let response = test_utils::expect_mpsc_response(receiver).await;
+    match response {
+        RtmpEndpointWatcherNotification::WatcherRequiringApproval {
+            response_channel, ..
+        } => {
+            response_channel
+                .send(ValidationResponse::Redirect {
+                    stream_key: Arc::new("full".to_string()),
+                })
+                .expect("Failed to send redirect");
+        }
+
+        message => panic!("Unexpected watcher message received: {:?}", message),
+    }
+
+    let response = test_utils::expect_mpsc_response(receiver).await;
+    match response {
+        RtmpEndpointWatcherNotification::StreamKeyBecameActive { stream_key, .. } => {
+            assert_eq!(stream_key.as_str(), "full", "Unexpected stream key");
+        }
+
+        message => panic!("Unexpected watcher message received: {:?}", message),
+    }
+}
+
+#[tokio::test]
+async fn watcher_stopped_notification_sent_when_disconnecting_while_awaiting_approval() {
+    let mut context = TestContextBuilder::new()
+        .set_requires_registrant_approval(true)
+        .into_watcher()
+        .await;
+
+    context.client.perform_handshake().await;
+    context
+        .client
+        .connect_to_app(context.rtmp_app.clone(), true)
+        .await;
+
+    context
+        .client
+        .watch_stream_key("key".to_string(), false)
+        .await;
+
+    let receiver = context.watch_receiver.as_mut().unwrap();
+    let response = test_utils::expect_mpsc_response(receiver).await;
+    let _response_channel = match response {
+        RtmpEndpointWatcherNotification::WatcherRequiringApproval {
+            response_channel, ..
+        } => response_channel,
+
+        message => panic!("Unexpected watcher message received: {:?}", message),
+    };
+
+    context.client.disconnect();
+
+    let response = test_utils::expect_mpsc_response(receiver).await;
+    match response {
+        RtmpEndpointWatcherNotification::WatcherStopped { stream_key, .. } => {
+            assert_eq!(stream_key.as_str(), "key", "Unexpected stream key");
+        }
+
+        message => panic!("Unexpected watcher message received: {:?}", message),
+    }
+}
@@ -51,6 +51,7 @@ impl RtmpTestClient {
                 port: requested_port,
                 use_tls: requested_tls,
                 response_channel,
+                ..
             } => {
                 assert_eq!(
                     requested_port, port,
@@ -83,6 +84,7 @@ impl RtmpTestClient {
                 port: requested_port,
                 use_tls: requested_tls,
                 response_channel,
+                ..
             } => {
                 assert_eq!(
                     requested_port, port,
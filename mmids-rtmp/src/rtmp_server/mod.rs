@@ -25,11 +25,13 @@ use mmids_core::net::tcp::TcpSocketRequest;
 use mmids_core::net::{ConnectionId, IpAddress};
 use mmids_core::reactors::ReactorWorkflowUpdate;
 use mmids_core::workflows::metadata::{MetadataKey, MetadataValue};
+use mmids_core::workflows::stream_labels::get_announced_label;
 use mmids_core::workflows::MediaNotificationContent;
 use mmids_core::StreamId;
 use rml_rtmp::sessions::StreamMetadata;
 use rml_rtmp::time::RtmpTimestamp;
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::sync::Arc;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::sync::oneshot::Sender;
@@ -70,7 +72,7 @@ pub enum StreamKeyRegistration {
 }
 
 /// Specifies if there are any IP address restrictions as part of an RTMP server registration
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum IpRestriction {
     /// All IP addresses are allowed
     None,
@@ -175,10 +177,19 @@ pub enum RtmpEndpointRequest {
 #[derive(Debug)]
 pub enum ValidationResponse {
     Approve {
-        reactor_update_channel: UnboundedReceiver<ReactorWorkflowUpdate>,
+        /// Channel that receives workflow updates from a reactor, if the approval came from one
+        reactor_update_channel: Option<UnboundedReceiver<ReactorWorkflowUpdate>>,
     },
 
     Reject,
+
+    /// Approves a request to watch, but serves the watcher the media of a different stream key
+    /// instead of the one they requested. The watcher is not validated again for the new stream
+    /// key. Publishers can't be redirected, and a redirect for a publisher is treated as a
+    /// rejection.
+    Redirect {
+        stream_key: Arc<String>,
+    },
 }
 
 /// Messages the rtmp server endpoint will send to publisher registrants.
@@ -237,6 +248,9 @@ pub enum RtmpEndpointPublisherMessage {
     /// An RTMP publisher has sent in new video data
     NewVideoData {
         publisher: ConnectionId,
+
+        /// The codec the video is encoded with, as one of the `mmids_core::codecs` identifiers
+        codec: Arc<String>,
         is_keyframe: bool,
         is_sequence_header: bool,
         data: Bytes,
@@ -272,6 +286,9 @@ pub enum RtmpEndpointWatcherNotification {
         /// The stream key that the connection is requesting to be a watcher of
         stream_key: Arc<String>,
 
+        /// The ip address the connection was made from
+        client_ip: IpAddr,
+
         /// Channel to send the approval or rejection response to
         response_channel: Sender<ValidationResponse>,
     },
@@ -286,6 +303,13 @@ pub enum RtmpEndpointWatcherNotification {
     /// Notifies the registrant that the last watcher has disconnected on the stream key, and
     /// there are no longer anyone watching
     StreamKeyBecameInactive { stream_key: Arc<String> },
+
+    /// Notifies the registrant that a connection has stopped watching the stream key, or
+    /// disconnected while its request to watch was waiting for approval
+    WatcherStopped {
+        connection_id: ConnectionId,
+        stream_key: Arc<String>,
+    },
 }
 
 /// Message watcher registrants send to announce new media data that should be sent to watchers
@@ -334,6 +358,11 @@ impl RtmpEndpointMediaData {
         is_keyframe_metadata_key: MetadataKey,
         pts_offset_metadata_key: MetadataKey,
     ) -> Result<Self, MediaDataConversionFailure> {
+        if get_announced_label(&content).is_some() {
+            // Stream labels are internal to mmids and aren't rtmp metadata
+            return Err(MediaDataConversionFailure::IncompatibleType);
+        }
+
         match content {
             MediaNotificationContent::StreamDisconnected => {
                 Err(MediaDataConversionFailure::IncompatibleType)
@@ -1,5 +1,6 @@
 use downcast_rs::{impl_downcast, Downcast};
 use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::retry::ConnectionState;
 use mmids_core::workflows::steps::StepFutureResult;
 use mmids_core::StreamId;
 
@@ -13,6 +14,12 @@ pub trait ExternalStreamHandler {
         &mut self,
         future: Box<dyn StreamHandlerFutureResult>,
     ) -> ResolvedFutureStatus;
+
+    /// The state of the handler's connection to the external system, for handlers that connect
+    /// to one
+    fn connection_state(&self) -> Option<ConnectionState> {
+        None
+    }
 }
 
 /// Allows creating a new external stream handler for any stream
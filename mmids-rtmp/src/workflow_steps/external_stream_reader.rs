@@ -8,6 +8,7 @@ use crate::workflow_steps::external_stream_handler::{
 };
 use mmids_core::workflows::metadata::MetadataKey;
 use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::retry::ConnectionState;
 use mmids_core::workflows::steps::{StepFutureResult, StepOutputs, StepStatus};
 use mmids_core::workflows::{MediaNotification, MediaNotificationContent};
 use mmids_core::StreamId;
@@ -300,6 +301,20 @@ impl ExternalStreamReader {
         }
     }
 
+    /// Returns the name and connection state of each active stream whose handler connects to an
+    /// external system
+    pub fn connection_states(&self) -> Vec<(Arc<String>, ConnectionState)> {
+        self.active_streams
+            .values()
+            .filter_map(|stream| {
+                stream
+                    .external_stream_handler
+                    .connection_state()
+                    .map(|state| (stream.stream_name.clone(), state))
+            })
+            .collect()
+    }
+
     pub fn stop_all_streams(&mut self) {
         let ids: Vec<StreamId> = self.active_streams.keys().cloned().collect();
         for id in ids {
@@ -374,6 +389,7 @@ impl ExternalStreamReader {
 
                 RtmpEndpointWatcherNotification::StreamKeyBecameActive { .. } => (),
                 RtmpEndpointWatcherNotification::StreamKeyBecameInactive { .. } => (),
+                RtmpEndpointWatcherNotification::WatcherStopped { .. } => (),
 
                 RtmpEndpointWatcherNotification::WatcherRequiringApproval { .. } => {
                     error!("Received request for approval but requests should be auto-approved");
@@ -517,6 +533,7 @@ mod tests {
                 content: MediaNotificationContent::NewIncomingStream {
                     stream_name: Arc::new("def".to_string()),
                 },
+                annotations: Default::default(),
             };
 
             self.external_stream_reader
@@ -572,6 +589,7 @@ mod tests {
             content: MediaNotificationContent::NewIncomingStream {
                 stream_name: Arc::new("def".to_string()),
             },
+            annotations: Default::default(),
         };
 
         context
@@ -618,6 +636,7 @@ mod tests {
             content: MediaNotificationContent::NewIncomingStream {
                 stream_name: Arc::new("def".to_string()),
             },
+            annotations: Default::default(),
         };
 
         context
@@ -647,6 +666,7 @@ mod tests {
         let media = MediaNotification {
             stream_id: StreamId(Arc::new("abc".to_string())),
             content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
         };
 
         context
@@ -678,6 +698,7 @@ mod tests {
             content: MediaNotificationContent::Metadata {
                 data: metadata.clone(),
             },
+            annotations: Default::default(),
         };
 
         context
@@ -742,6 +763,7 @@ mod tests {
         let media = MediaNotification {
             stream_id: StreamId(Arc::new("abc".to_string())),
             content: media_content.clone(),
+            annotations: Default::default(),
         };
 
         context
@@ -776,6 +798,7 @@ mod tests {
                 payload_type: AUDIO_CODEC_AAC_RAW.clone(),
                 metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
             },
+            annotations: Default::default(),
         };
 
         context.external_stream_reader.handle_media(
@@ -807,6 +830,7 @@ mod tests {
             content: MediaNotificationContent::NewIncomingStream {
                 stream_name: Arc::new("def".to_string()),
             },
+            annotations: Default::default(),
         };
 
         context
@@ -853,6 +877,7 @@ mod tests {
         let media = MediaNotification {
             stream_id: StreamId(Arc::new("abc".to_string())),
             content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
         };
 
         context
@@ -870,6 +895,7 @@ mod tests {
         let media = MediaNotification {
             stream_id: StreamId(Arc::new("abc".to_string())),
             content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
         };
 
         context
@@ -892,6 +918,7 @@ mod tests {
         let media = MediaNotification {
             stream_id: StreamId(Arc::new("abc".to_string())),
             content: MediaNotificationContent::Metadata { data: raw_metadata },
+            annotations: Default::default(),
         };
 
         let mut outputs = StepOutputs::new();
@@ -956,6 +983,7 @@ mod tests {
                 data: Bytes::from(vec![1, 2, 3, 4]),
                 metadata,
             },
+            annotations: Default::default(),
         };
 
         let mut outputs = StepOutputs::new();
@@ -1012,6 +1040,7 @@ mod tests {
                 payload_type: AUDIO_CODEC_AAC_RAW.clone(),
                 metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
             },
+            annotations: Default::default(),
         };
 
         let mut outputs = StepOutputs::new();
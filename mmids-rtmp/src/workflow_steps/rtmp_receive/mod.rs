@@ -2,6 +2,25 @@
 //! the specified port, application name, and stream key combination.  Any media packets that
 //! RTMP publishers send in will be sent to the next steps.
 //!
+//! The `port` parameter may contain a comma separated list of ports, allowing publishers to
+//! connect on any of them (e.g. a port for internal publishers and one for external publishers).
+//! Streams from every port flow into the same workflow, and the number of publishers connected on
+//! each port is reported through the step's state details. Stream keys must be unique across all
+//! of the step's ports. When a reactor is used, a publisher whose stream key is already being
+//! published on another port is rejected. Otherwise, the duplicate publisher is ignored and none
+//! of its media is passed to the workflow.
+//!
+//! When a `stall_timeout` is specified, a publisher that stays connected but sends no media for
+//! that many milliseconds has its stream flagged as stalled. An ingest stall alert is raised on the
+//! event hub, and cleared as soon as the publisher's media resumes. Stalled streams are not
+//! disconnected, and the number of them is reported through the step's state details.
+//!
+//! The `stream_key_map` parameter allows simple deployments to control which stream keys may be
+//! published without running a reactor. It's a comma separated list of `key:stream_name` pairs,
+//! usually used with a `*` stream key. Publishers using a stream key in the map have their stream
+//! named after the mapped stream name, while publishers using any other stream key are rejected.
+//! A stream key map can't be used together with a reactor.
+//!
 //! All media packets that come in from previous workflow steps are ignored.
 #[cfg(test)]
 mod tests;
@@ -11,30 +30,35 @@ use crate::rtmp_server::{
     StreamKeyRegistration, ValidationResponse,
 };
 use bytes::BytesMut;
-use mmids_core::codecs::{AUDIO_CODEC_AAC_RAW, VIDEO_CODEC_H264_AVC};
+use mmids_core::codecs::AUDIO_CODEC_AAC_RAW;
+use mmids_core::event_hub::PublishEventRequest;
 use mmids_core::net::{ConnectionId, IpAddress, IpAddressParseError};
 use mmids_core::reactors::manager::ReactorManagerRequest;
 use mmids_core::reactors::ReactorWorkflowUpdate;
+use mmids_core::workflows::annotations::{ArrivalTime, PipelineAnnotations};
 use mmids_core::workflows::definitions::WorkflowStepDefinition;
 use mmids_core::workflows::metadata::{
     MediaPayloadMetadataCollection, MetadataEntry, MetadataKey, MetadataValue,
 };
 use mmids_core::workflows::steps::factory::StepGenerator;
 use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::stall_detection::{
+    read_stall_timeout, StallCheckDue, StallDetector, StallTimeoutError, STALLED_STREAMS_DETAIL,
+};
 use mmids_core::workflows::steps::{
     StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
 };
 use mmids_core::workflows::{MediaNotification, MediaNotificationContent, MediaType};
 use mmids_core::StreamId;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::iter;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use thiserror::Error as ThisError;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::sync::oneshot::Sender;
 use tokio_util::sync::CancellationToken;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 pub const PORT_PROPERTY_NAME: &str = "port";
 pub const APP_PROPERTY_NAME: &str = "rtmp_app";
@@ -43,17 +67,21 @@ pub const IP_ALLOW_PROPERTY_NAME: &str = "allow_ips";
 pub const IP_DENY_PROPERTY_NAME: &str = "deny_ips";
 pub const RTMPS_FLAG: &str = "rtmps";
 pub const REACTOR_NAME: &str = "reactor";
+pub const STREAM_KEY_MAP_PROPERTY_NAME: &str = "stream_key_map";
 
 /// Generates new rtmp receiver workflow step instances based on specified step definitions.
 pub struct RtmpReceiverStepGenerator {
     rtmp_endpoint_sender: UnboundedSender<RtmpEndpointRequest>,
     reactor_manager: UnboundedSender<ReactorManagerRequest>,
+    event_publisher: UnboundedSender<PublishEventRequest>,
     is_keyframe_metadata_key: MetadataKey,
     pts_offset_metadata_key: MetadataKey,
 }
 
 struct ConnectionDetails {
     stream_id: StreamId,
+    stream_key: Arc<String>,
+    port: u16,
 
     // Used to cancel the reactor update future. When a stream disconnects, this cancellation
     // channel will be dropped causing the future waiting for reactor updates to be closed. This
@@ -74,26 +102,38 @@ impl Drop for ConnectionDetails {
 struct RtmpReceiverStep {
     rtmp_endpoint_sender: UnboundedSender<RtmpEndpointRequest>,
     reactor_manager: UnboundedSender<ReactorManagerRequest>,
-    port: u16,
+    ports: Vec<u16>,
+    registered_ports: HashSet<u16>,
     rtmp_app: Arc<String>,
     stream_key: StreamKeyRegistration,
     status: StepStatus,
     connection_details: HashMap<ConnectionId, ConnectionDetails>,
     reactor_name: Option<Arc<String>>,
+    stream_key_map: Option<HashMap<String, Arc<String>>>,
     metadata_buffer: BytesMut,
     is_keyframe_metadata_key: MetadataKey,
     pts_offset_metadata_key: MetadataKey,
+    stall_detector: Option<StallDetector>,
 }
 
 impl StepFutureResult for FutureResult {}
 
 enum FutureResult {
-    RtmpEndpointDroppedRegistration,
+    RtmpEndpointDroppedRegistration {
+        port: u16,
+    },
+
     ReactorManagerGone,
     ReactorGone,
-    RtmpEndpointResponseReceived(RtmpEndpointPublisherMessage),
+
+    RtmpEndpointResponseReceived {
+        port: u16,
+        message: RtmpEndpointPublisherMessage,
+    },
 
     ReactorWorkflowReturned {
+        port: u16,
+        stream_key: Arc<String>,
         is_valid: bool,
         reactor_receiver: UnboundedReceiver<ReactorWorkflowUpdate>,
         response_channel: Sender<ValidationResponse>,
@@ -122,7 +162,8 @@ enum StepStartupError {
     NoStreamKey,
 
     #[error(
-        "Invalid port value of '{0}' specified.  A number from 0 to 65535 should be specified"
+        "Invalid port value of '{0}' specified.  A number from 0 to 65535 (or a comma separated \
+        list of them) should be specified"
     )]
     InvalidPort(String),
 
@@ -135,18 +176,36 @@ enum StepStartupError {
         IP_DENY_PROPERTY_NAME
     )]
     BothDenyAndAllowIpRestrictions,
+
+    #[error(transparent)]
+    InvalidStallTimeout(#[from] StallTimeoutError),
+
+    #[error(
+        "Invalid {} value of '{0}'.  It must be a comma separated list of `key:stream_name` pairs",
+        STREAM_KEY_MAP_PROPERTY_NAME
+    )]
+    InvalidStreamKeyMap(String),
+
+    #[error(
+        "Both {} and {} were specified, but only one is allowed",
+        REACTOR_NAME,
+        STREAM_KEY_MAP_PROPERTY_NAME
+    )]
+    BothReactorAndStreamKeyMap,
 }
 
 impl RtmpReceiverStepGenerator {
     pub fn new(
         rtmp_endpoint_sender: UnboundedSender<RtmpEndpointRequest>,
         reactor_manager: UnboundedSender<ReactorManagerRequest>,
+        event_publisher: UnboundedSender<PublishEventRequest>,
         is_keyframe_metadata_key: MetadataKey,
         pts_offset_metadata_key: MetadataKey,
     ) -> Self {
         RtmpReceiverStepGenerator {
             rtmp_endpoint_sender,
             reactor_manager,
+            event_publisher,
             is_keyframe_metadata_key,
             pts_offset_metadata_key,
         }
@@ -160,19 +219,27 @@ impl StepGenerator for RtmpReceiverStepGenerator {
         futures_channel: WorkflowStepFuturesChannel,
     ) -> StepCreationResult {
         let use_rtmps = definition.parameters.get(RTMPS_FLAG).is_some();
-        let port = match definition.parameters.get(PORT_PROPERTY_NAME) {
-            Some(Some(value)) => match value.parse::<u16>() {
-                Ok(num) => num,
-                Err(_) => {
-                    return Err(Box::new(StepStartupError::InvalidPort(value.clone())));
+        let ports = match definition.parameters.get(PORT_PROPERTY_NAME) {
+            Some(Some(value)) => {
+                let mut ports = Vec::new();
+                for port in value.split(',') {
+                    match port.trim().parse::<u16>() {
+                        Ok(num) if !ports.contains(&num) => ports.push(num),
+                        Ok(_) => (),
+                        Err(_) => {
+                            return Err(Box::new(StepStartupError::InvalidPort(value.clone())));
+                        }
+                    }
                 }
-            },
+
+                ports
+            }
 
             _ => {
                 if use_rtmps {
-                    443
+                    vec![443]
                 } else {
-                    1935
+                    vec![1935]
                 }
             }
         };
@@ -211,14 +278,37 @@ impl StepGenerator for RtmpReceiverStepGenerator {
             _ => None,
         };
 
+        let stream_key_map = match definition.parameters.get(STREAM_KEY_MAP_PROPERTY_NAME) {
+            Some(Some(value)) => match parse_stream_key_map(value) {
+                Some(map) => Some(map),
+                None => {
+                    return Err(Box::new(StepStartupError::InvalidStreamKeyMap(
+                        value.clone(),
+                    )))
+                }
+            },
+
+            _ => None,
+        };
+
+        if reactor_name.is_some() && stream_key_map.is_some() {
+            return Err(Box::new(StepStartupError::BothReactorAndStreamKeyMap));
+        }
+
+        let stall_detector = read_stall_timeout(&definition)
+            .map_err(StepStartupError::from)?
+            .map(|timeout| StallDetector::new(timeout, self.event_publisher.clone()));
+
         let step = RtmpReceiverStep {
             status: StepStatus::Created,
             rtmp_endpoint_sender: self.rtmp_endpoint_sender.clone(),
             reactor_manager: self.reactor_manager.clone(),
-            port,
+            ports,
+            registered_ports: HashSet::new(),
             rtmp_app: app,
             connection_details: HashMap::new(),
             reactor_name,
+            stream_key_map,
             stream_key: if stream_key.as_str() == "*" {
                 StreamKeyRegistration::Any
             } else {
@@ -227,27 +317,32 @@ impl StepGenerator for RtmpReceiverStepGenerator {
             metadata_buffer: BytesMut::new(),
             is_keyframe_metadata_key: self.is_keyframe_metadata_key,
             pts_offset_metadata_key: self.pts_offset_metadata_key,
+            stall_detector,
         };
 
-        let (sender, receiver) = unbounded_channel();
-        let _ = step
-            .rtmp_endpoint_sender
-            .send(RtmpEndpointRequest::ListenForPublishers {
-                message_channel: sender,
-                port: step.port,
-                rtmp_app: step.rtmp_app.clone(),
-                rtmp_stream_key: step.stream_key.clone(),
-                stream_id: None,
-                ip_restrictions: ip_restriction,
-                use_tls: use_rtmps,
-                requires_registrant_approval: step.reactor_name.is_some(),
-            });
-
-        futures_channel.send_on_generic_unbounded_recv(
-            receiver,
-            FutureResult::RtmpEndpointResponseReceived,
-            || FutureResult::RtmpEndpointDroppedRegistration,
-        );
+        for port in &step.ports {
+            let port = *port;
+            let (sender, receiver) = unbounded_channel();
+            let _ = step
+                .rtmp_endpoint_sender
+                .send(RtmpEndpointRequest::ListenForPublishers {
+                    message_channel: sender,
+                    port,
+                    rtmp_app: step.rtmp_app.clone(),
+                    rtmp_stream_key: step.stream_key.clone(),
+                    stream_id: None,
+                    ip_restrictions: ip_restriction.clone(),
+                    use_tls: use_rtmps,
+                    requires_registrant_approval: step.reactor_name.is_some()
+                        || step.stream_key_map.is_some(),
+                });
+
+            futures_channel.send_on_generic_unbounded_recv(
+                receiver,
+                move |message| FutureResult::RtmpEndpointResponseReceived { port, message },
+                move || FutureResult::RtmpEndpointDroppedRegistration { port },
+            );
+        }
 
         let reactor_manager = self.reactor_manager.clone();
         futures_channel.send_on_generic_future_completion(async move {
@@ -264,21 +359,38 @@ impl RtmpReceiverStep {
     fn handle_rtmp_publisher_message(
         &mut self,
         outputs: &mut StepOutputs,
+        port: u16,
         message: RtmpEndpointPublisherMessage,
         futures_channel: &WorkflowStepFuturesChannel,
     ) {
         match message {
             RtmpEndpointPublisherMessage::PublisherRegistrationFailed => {
-                error!("Rtmp receive step failed to register for publish registration");
+                error!(
+                    port = port,
+                    "Rtmp receive step failed to register for publish registration on port {}",
+                    port
+                );
+
                 self.status = StepStatus::Error {
-                    message: "Rtmp receive step failed to register for publish registration"
-                        .to_string(),
+                    message: format!(
+                        "Rtmp receive step failed to register for publish registration on port {}",
+                        port
+                    ),
                 };
             }
 
             RtmpEndpointPublisherMessage::PublisherRegistrationSuccessful => {
-                info!("Rtmp receive step successfully registered for publishing");
-                self.status = StepStatus::Active;
+                info!(
+                    port = port,
+                    "Rtmp receive step successfully registered for publishing on port {}", port
+                );
+
+                self.registered_ports.insert(port);
+                if self.registered_ports.len() == self.ports.len()
+                    && self.status == StepStatus::Created
+                {
+                    self.status = StepStatus::Active;
+                }
             }
 
             RtmpEndpointPublisherMessage::NewPublisherConnected {
@@ -291,9 +403,25 @@ impl RtmpReceiverStep {
                     stream_id = ?stream_id,
                     connection_id = ?connection_id,
                     stream_key = %stream_key,
+                    port = port,
                     "Rtmp receive step seen new publisher: {:?}, {:?}, {:?}", stream_id, connection_id, stream_key
                 );
 
+                if let Some(existing_port) = self.get_publishing_port(&stream_key) {
+                    // The rtmp endpoint has no way to disconnect publishers yet, so the best
+                    // that can be done is to keep the duplicate's media out of the workflow
+                    warn!(
+                        stream_id = ?stream_id,
+                        connection_id = ?connection_id,
+                        stream_key = %stream_key,
+                        port = port,
+                        "Ignoring publisher on port {} as stream key {} is already being \
+                        published on port {}", port, stream_key, existing_port
+                    );
+
+                    return;
+                }
+
                 let cancellation_token = if let Some(update_channel) = reactor_update_channel {
                     let cancellation_token = CancellationToken::new();
                     let connection_id = connection_id.clone();
@@ -313,19 +441,48 @@ impl RtmpReceiverStep {
                     None
                 };
 
+                let stream_name = match &self.stream_key_map {
+                    Some(map) => match map.get(stream_key.as_str()) {
+                        Some(name) => name.clone(),
+                        None => {
+                            // Only approved publishers should connect, so this shouldn't happen
+                            warn!(
+                                stream_id = ?stream_id,
+                                connection_id = ?connection_id,
+                                stream_key = %stream_key,
+                                "Ignoring publisher with stream key {} as it's not in the stream \
+                                key map", stream_key
+                            );
+
+                            return;
+                        }
+                    },
+
+                    None => stream_key.clone(),
+                };
+
                 self.connection_details.insert(
                     connection_id,
                     ConnectionDetails {
                         stream_id: stream_id.clone(),
+                        stream_key,
+                        port,
                         cancellation_token,
                     },
                 );
 
+                if let Some(detector) = &mut self.stall_detector {
+                    detector.stream_started(
+                        stream_id.clone(),
+                        stream_name.clone(),
+                        futures_channel,
+                    );
+                }
+
                 outputs.media.push(MediaNotification {
                     stream_id,
-                    content: MediaNotificationContent::NewIncomingStream {
-                        stream_name: stream_key,
-                    },
+                    content: MediaNotificationContent::NewIncomingStream { stream_name },
+                    annotations: Default::default(),
                 });
             }
 
@@ -340,9 +497,14 @@ impl RtmpReceiverStep {
                             connection_id, connection.stream_id
                         );
 
+                        if let Some(detector) = &mut self.stall_detector {
+                            detector.stream_stopped(&connection.stream_id);
+                        }
+
                         outputs.media.push(MediaNotification {
                             stream_id: connection.stream_id.clone(),
                             content: MediaNotificationContent::StreamDisconnected,
+                            annotations: Default::default(),
                         });
                     }
                 }
@@ -353,16 +515,24 @@ impl RtmpReceiverStep {
                 metadata,
             } => match self.connection_details.get(&publisher) {
                 None => (),
-                Some(connection) => outputs.media.push(MediaNotification {
-                    stream_id: connection.stream_id.clone(),
-                    content: MediaNotificationContent::Metadata {
-                        data: crate::utils::stream_metadata_to_hash_map(metadata),
-                    },
-                }),
+                Some(connection) => {
+                    if let Some(detector) = &mut self.stall_detector {
+                        detector.media_received(&connection.stream_id, futures_channel);
+                    }
+
+                    outputs.media.push(MediaNotification {
+                        stream_id: connection.stream_id.clone(),
+                        content: MediaNotificationContent::Metadata {
+                            data: crate::utils::stream_metadata_to_hash_map(metadata),
+                        },
+                        annotations: Default::default(),
+                    });
+                }
             },
 
             RtmpEndpointPublisherMessage::NewVideoData {
                 publisher,
+                codec,
                 data,
                 timestamp,
                 is_sequence_header,
@@ -371,6 +541,10 @@ impl RtmpReceiverStep {
             } => match self.connection_details.get(&publisher) {
                 None => (),
                 Some(connection) => {
+                    if let Some(detector) = &mut self.stall_detector {
+                        detector.media_received(&connection.stream_id, futures_channel);
+                    }
+
                     let is_keyframe_metadata = MetadataEntry::new(
                         self.is_keyframe_metadata_key,
                         MetadataValue::Bool(is_keyframe),
@@ -394,12 +568,13 @@ impl RtmpReceiverStep {
                         stream_id: connection.stream_id.clone(),
                         content: MediaNotificationContent::MediaPayload {
                             media_type: MediaType::Video,
-                            payload_type: VIDEO_CODEC_H264_AVC.clone(),
+                            payload_type: codec,
                             is_required_for_decoding: is_sequence_header,
                             timestamp: Duration::from_millis(timestamp.value.into()),
                             metadata,
                             data,
                         },
+                        annotations: arrival_annotations(),
                     });
                 }
             },
@@ -412,6 +587,10 @@ impl RtmpReceiverStep {
             } => match self.connection_details.get(&publisher) {
                 None => (),
                 Some(connection) => {
+                    if let Some(detector) = &mut self.stall_detector {
+                        detector.media_received(&connection.stream_id, futures_channel);
+                    }
+
                     outputs.media.push(MediaNotification {
                         stream_id: connection.stream_id.clone(),
                         content: MediaNotificationContent::MediaPayload {
@@ -425,6 +604,7 @@ impl RtmpReceiverStep {
                             is_required_for_decoding: is_sequence_header,
                             data,
                         },
+                        annotations: arrival_annotations(),
                     });
                 }
             },
@@ -434,12 +614,49 @@ impl RtmpReceiverStep {
                 stream_key,
                 response_channel,
             } => {
-                if let Some(name) = &self.reactor_name {
+                if let Some(existing_port) = self.get_publishing_port(&stream_key) {
+                    warn!(
+                        connection_id = %connection_id,
+                        stream_key = %stream_key,
+                        port = port,
+                        "Rejecting publisher on port {} as stream key {} is already being \
+                        published on port {}", port, stream_key, existing_port
+                    );
+
+                    let _ = response_channel.send(ValidationResponse::Reject);
+                } else if let Some(map) = &self.stream_key_map {
+                    match map.get(stream_key.as_str()) {
+                        Some(stream_name) => {
+                            info!(
+                                connection_id = %connection_id,
+                                stream_key = %stream_key,
+                                stream_name = %stream_name,
+                                "Approving publisher with stream key {} as stream {}",
+                                stream_key, stream_name
+                            );
+
+                            let _ = response_channel.send(ValidationResponse::Approve {
+                                reactor_update_channel: None,
+                            });
+                        }
+
+                        None => {
+                            warn!(
+                                connection_id = %connection_id,
+                                stream_key = %stream_key,
+                                "Rejecting publisher as stream key {} is not in the stream key map",
+                                stream_key
+                            );
+
+                            let _ = response_channel.send(ValidationResponse::Reject);
+                        }
+                    }
+                } else if let Some(name) = &self.reactor_name {
                     let (sender, mut receiver) = unbounded_channel();
                     let _ = self.reactor_manager.send(
                         ReactorManagerRequest::CreateWorkflowForStreamName {
                             reactor_name: name.clone(),
-                            stream_name: stream_key,
+                            stream_name: stream_key.clone(),
                             response_channel: sender,
                         },
                     );
@@ -452,6 +669,8 @@ impl RtmpReceiverStep {
                         };
 
                         FutureResult::ReactorWorkflowReturned {
+                            port,
+                            stream_key,
                             is_valid,
                             reactor_receiver: receiver,
                             response_channel,
@@ -470,6 +689,14 @@ impl RtmpReceiverStep {
             }
         }
     }
+
+    /// Gets the port a publisher is already publishing the specified stream key on, if any
+    fn get_publishing_port(&self, stream_key: &Arc<String>) -> Option<u16> {
+        self.connection_details
+            .values()
+            .find(|connection| &connection.stream_key == stream_key)
+            .map(|connection| connection.port)
+    }
 }
 
 impl WorkflowStep for RtmpReceiverStep {
@@ -480,6 +707,18 @@ impl WorkflowStep for RtmpReceiverStep {
         futures_channel: WorkflowStepFuturesChannel,
     ) -> StepStatus {
         for future_result in inputs.notifications.drain(..) {
+            let future_result = match future_result.downcast::<StallCheckDue>() {
+                Ok(check) => {
+                    if let Some(detector) = &mut self.stall_detector {
+                        detector.handle_check_due(*check, &futures_channel);
+                    }
+
+                    continue;
+                }
+
+                Err(future_result) => future_result,
+            };
+
             let future_result = match future_result.downcast::<FutureResult>() {
                 Ok(result) => *result,
                 Err(_) => {
@@ -491,14 +730,20 @@ impl WorkflowStep for RtmpReceiverStep {
             };
 
             match future_result {
-                FutureResult::RtmpEndpointDroppedRegistration => {
+                FutureResult::RtmpEndpointDroppedRegistration { port } => {
                     error!(
-                        "Rtmp receive step stopping as the rtmp endpoint dropped the registration"
+                        port = port,
+                        "Rtmp receive step stopping as the rtmp endpoint dropped the registration \
+                        for port {}",
+                        port
                     );
 
                     return StepStatus::Error {
-                        message: "Rtmp receive step stopping as the rtmp endpoint dropped the registration"
-                            .to_string(),
+                        message: format!(
+                            "Rtmp receive step stopping as the rtmp endpoint dropped the \
+                            registration for port {}",
+                            port
+                        ),
                     };
                 }
 
@@ -521,18 +766,31 @@ impl WorkflowStep for RtmpReceiverStep {
                     };
                 }
 
-                FutureResult::RtmpEndpointResponseReceived(message) => {
-                    self.handle_rtmp_publisher_message(outputs, message, &futures_channel);
+                FutureResult::RtmpEndpointResponseReceived { port, message } => {
+                    self.handle_rtmp_publisher_message(outputs, port, message, &futures_channel);
                 }
 
                 FutureResult::ReactorWorkflowReturned {
+                    port,
+                    stream_key,
                     is_valid,
                     reactor_receiver,
                     response_channel,
                 } => {
-                    if is_valid {
+                    // Another port may have started publishing the same stream key while the
+                    // reactor was being queried
+                    if let Some(existing_port) = self.get_publishing_port(&stream_key) {
+                        warn!(
+                            stream_key = %stream_key,
+                            port = port,
+                            "Rejecting publisher on port {} as stream key {} is already being \
+                            published on port {}", port, stream_key, existing_port
+                        );
+
+                        let _ = response_channel.send(ValidationResponse::Reject);
+                    } else if is_valid {
                         let _ = response_channel.send(ValidationResponse::Approve {
-                            reactor_update_channel: reactor_receiver,
+                            reactor_update_channel: Some(reactor_receiver),
                         });
                     } else {
                         let _ = response_channel.send(ValidationResponse::Reject);
@@ -561,17 +819,73 @@ impl WorkflowStep for RtmpReceiverStep {
 
         self.status.clone()
     }
+
+    fn get_state_details(&self) -> HashMap<String, String> {
+        let mut details = HashMap::new();
+        for port in &self.ports {
+            let connection_count = self
+                .connection_details
+                .values()
+                .filter(|connection| connection.port == *port)
+                .count();
+
+            details.insert(
+                format!("port_{}_connections", port),
+                connection_count.to_string(),
+            );
+        }
+
+        if let Some(detector) = &self.stall_detector {
+            details.insert(
+                STALLED_STREAMS_DETAIL.to_string(),
+                detector.stalled_stream_count().to_string(),
+            );
+        }
+
+        details
+    }
 }
 
 impl Drop for RtmpReceiverStep {
     fn drop(&mut self) {
-        let _ = self
-            .rtmp_endpoint_sender
-            .send(RtmpEndpointRequest::RemoveRegistration {
-                registration_type: RegistrationType::Publisher,
-                port: self.port,
-                rtmp_app: self.rtmp_app.clone(),
-                rtmp_stream_key: self.stream_key.clone(),
-            });
+        for port in &self.ports {
+            let _ = self
+                .rtmp_endpoint_sender
+                .send(RtmpEndpointRequest::RemoveRegistration {
+                    registration_type: RegistrationType::Publisher,
+                    port: *port,
+                    rtmp_app: self.rtmp_app.clone(),
+                    rtmp_stream_key: self.stream_key.clone(),
+                });
+        }
     }
 }
+
+/// Parses a comma separated list of `key:stream_name` pairs. Returns `None` if any pair is
+/// malformed or a key is listed more than once.
+fn parse_stream_key_map(value: &str) -> Option<HashMap<String, Arc<String>>> {
+    let mut map = HashMap::new();
+    for pair in value.split(',') {
+        let (key, stream_name) = pair.split_once(':')?;
+        let (key, stream_name) = (key.trim(), stream_name.trim());
+        if key.is_empty() || stream_name.is_empty() {
+            return None;
+        }
+
+        if map
+            .insert(key.to_string(), Arc::new(stream_name.to_string()))
+            .is_some()
+        {
+            return None;
+        }
+    }
+
+    Some(map)
+}
+
+/// Media payloads enter the mmids instance here, so they are stamped with their arrival time
+fn arrival_annotations() -> PipelineAnnotations {
+    let mut annotations = PipelineAnnotations::new();
+    annotations.insert(ArrivalTime(Instant::now()));
+    annotations
+}
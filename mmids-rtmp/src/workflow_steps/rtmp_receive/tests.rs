@@ -1,12 +1,15 @@
 use super::*;
 use anyhow::Result;
 use bytes::Bytes;
+use mmids_core::codecs::{VIDEO_CODEC_H264_AVC, VIDEO_CODEC_H265_HVCC};
+use mmids_core::event_hub::{StreamAlert, StreamAlertEvent};
 use mmids_core::net::ConnectionId;
 use mmids_core::workflows::definitions::WorkflowStepType;
 use mmids_core::workflows::metadata::common_metadata::{
     get_is_keyframe_metadata_key, get_pts_offset_metadata_key,
 };
 use mmids_core::workflows::metadata::MetadataKeyMap;
+use mmids_core::workflows::steps::stall_detection::STALL_TIMEOUT;
 use mmids_core::workflows::steps::test_utils::StepTestContext;
 use mmids_core::workflows::MediaNotificationContent::StreamDisconnected;
 use mmids_core::workflows::{MediaNotification, MediaNotificationContent};
@@ -22,6 +25,7 @@ struct TestContext {
     step_context: StepTestContext,
     rtmp_endpoint: UnboundedReceiver<RtmpEndpointRequest>,
     reactor_manager: UnboundedReceiver<ReactorManagerRequest>,
+    event_hub: UnboundedReceiver<PublishEventRequest>,
     is_keyframe_metadata_key: MetadataKey,
     pts_offset_metadata_key: MetadataKey,
 }
@@ -31,6 +35,7 @@ struct DefinitionBuilder {
     app: Option<String>,
     key: Option<String>,
     reactor: Option<String>,
+    stream_key_map: Option<String>,
 }
 
 impl DefinitionBuilder {
@@ -40,6 +45,7 @@ impl DefinitionBuilder {
             app: None,
             key: None,
             reactor: None,
+            stream_key_map: None,
         }
     }
 
@@ -63,6 +69,11 @@ impl DefinitionBuilder {
         self
     }
 
+    fn stream_key_map(mut self, map: &str) -> Self {
+        self.stream_key_map = Some(map.to_string());
+        self
+    }
+
     fn build(self) -> WorkflowStepDefinition {
         let mut definition = WorkflowStepDefinition {
             step_type: WorkflowStepType("rtmp_receive".to_string()),
@@ -101,6 +112,12 @@ impl DefinitionBuilder {
                 .insert(REACTOR_NAME.to_string(), Some(reactor));
         }
 
+        if let Some(map) = self.stream_key_map {
+            definition
+                .parameters
+                .insert(STREAM_KEY_MAP_PROPERTY_NAME.to_string(), Some(map));
+        }
+
         definition
     }
 }
@@ -109,6 +126,7 @@ impl TestContext {
     fn new(definition: WorkflowStepDefinition) -> Result<Self> {
         let (reactor_sender, reactor_receiver) = unbounded_channel();
         let (rtmp_sender, rtmp_receiver) = unbounded_channel();
+        let (event_sender, event_receiver) = unbounded_channel();
 
         let mut metadata_key_map = MetadataKeyMap::new();
         let is_keyframe_metadata_key = get_is_keyframe_metadata_key(&mut metadata_key_map);
@@ -117,6 +135,7 @@ impl TestContext {
         let generator = RtmpReceiverStepGenerator {
             reactor_manager: reactor_sender,
             rtmp_endpoint_sender: rtmp_sender,
+            event_publisher: event_sender,
             is_keyframe_metadata_key,
             pts_offset_metadata_key,
         };
@@ -127,6 +146,7 @@ impl TestContext {
             step_context,
             rtmp_endpoint: rtmp_receiver,
             reactor_manager: reactor_receiver,
+            event_hub: event_receiver,
             is_keyframe_metadata_key,
             pts_offset_metadata_key,
         })
@@ -467,6 +487,7 @@ async fn video_notification_received_when_publisher_sends_video() {
     channel
         .send(RtmpEndpointPublisherMessage::NewVideoData {
             publisher: ConnectionId(Arc::new("connection".to_string())),
+            codec: VIDEO_CODEC_H264_AVC.clone(),
             data: Bytes::from(vec![1, 2, 3]),
             timestamp: RtmpTimestamp::new(5),
             is_keyframe: true,
@@ -534,6 +555,65 @@ async fn video_notification_received_when_publisher_sends_video() {
     }
 }
 
+#[tokio::test]
+async fn video_notification_uses_codec_of_published_video() {
+    let definition = DefinitionBuilder::new().build();
+    let mut context = TestContext::new(definition).unwrap();
+    let channel = context.accept_registration().await;
+
+    channel
+        .send(RtmpEndpointPublisherMessage::NewPublisherConnected {
+            stream_id: StreamId(Arc::new("test".to_string())),
+            stream_key: Arc::new("abc".to_string()),
+            connection_id: ConnectionId(Arc::new("connection".to_string())),
+            reactor_update_channel: None,
+        })
+        .expect("Failed to send publisher connected message");
+
+    context.step_context.execute_pending_futures().await;
+
+    channel
+        .send(RtmpEndpointPublisherMessage::NewVideoData {
+            publisher: ConnectionId(Arc::new("connection".to_string())),
+            codec: VIDEO_CODEC_H265_HVCC.clone(),
+            data: Bytes::from(vec![1, 2, 3]),
+            timestamp: RtmpTimestamp::new(5),
+            is_keyframe: true,
+            is_sequence_header: true,
+            composition_time_offset: 0,
+        })
+        .expect("Failed to send video message");
+
+    context.step_context.execute_pending_futures().await;
+
+    assert_eq!(
+        context.step_context.media_outputs.len(),
+        1,
+        "Unexpected number of media outputs"
+    );
+
+    match &context.step_context.media_outputs[0].content {
+        MediaNotificationContent::MediaPayload {
+            payload_type,
+            is_required_for_decoding,
+            ..
+        } => {
+            assert_eq!(
+                *payload_type, *VIDEO_CODEC_H265_HVCC,
+                "Unexpected payload type"
+            );
+
+            // Sequence headers must be marked as required for decoding so they are cached
+            assert!(
+                is_required_for_decoding,
+                "Expected is_required_for_decoding to be true"
+            );
+        }
+
+        content => panic!("Unexpected media content: {:?}", content),
+    }
+}
+
 #[tokio::test]
 async fn audio_notification_received_when_publisher_sends_audio() {
     let definition = DefinitionBuilder::new().build();
@@ -595,6 +675,7 @@ async fn stream_started_notification_passed_as_input_does_not_get_passed_as_outp
             content: MediaNotificationContent::NewIncomingStream {
                 stream_name: Arc::new("name".to_string()),
             },
+            annotations: Default::default(),
         });
 }
 
@@ -608,6 +689,7 @@ async fn stream_disconnected_notification_passed_as_input_does_not_get_passed_as
         .assert_media_not_passed_through(MediaNotification {
             stream_id: StreamId(Arc::new("test".to_string())),
             content: StreamDisconnected,
+            annotations: Default::default(),
         });
 }
 
@@ -623,6 +705,7 @@ async fn metadata_notification_passed_as_input_does_not_get_passed_as_output() {
             content: MediaNotificationContent::Metadata {
                 data: HashMap::new(),
             },
+            annotations: Default::default(),
         });
 }
 
@@ -643,6 +726,7 @@ async fn video_notification_passed_as_input_does_not_get_passed_as_output() {
                 is_required_for_decoding: true,
                 metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
             },
+            annotations: Default::default(),
         });
 }
 
@@ -663,6 +747,7 @@ async fn audio_notification_passed_as_input_does_not_get_passed_as_output() {
                 payload_type: AUDIO_CODEC_AAC_RAW.clone(),
                 metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
             },
+            annotations: Default::default(),
         });
 }
 
@@ -785,3 +870,397 @@ async fn approval_sent_when_reactor_says_stream_is_valid() {
         response => panic!("Unexpected response: {:?}", response),
     }
 }
+
+#[tokio::test]
+async fn streams_from_multiple_ports_reach_workflow() {
+    let mut definition = DefinitionBuilder::new().build();
+    definition.parameters.insert(
+        PORT_PROPERTY_NAME.to_string(),
+        Some("1935, 1936".to_string()),
+    );
+
+    let mut context = TestContext::new(definition).unwrap();
+    let first_channel = context.accept_registration().await;
+    assert_eq!(
+        context.step_context.status,
+        StepStatus::Created,
+        "Expected step to not be active until all ports are registered"
+    );
+
+    let second_channel = context.accept_registration().await;
+    assert_eq!(
+        context.step_context.status,
+        StepStatus::Active,
+        "Unexpected step status"
+    );
+
+    first_channel
+        .send(RtmpEndpointPublisherMessage::NewPublisherConnected {
+            stream_id: StreamId(Arc::new("first".to_string())),
+            stream_key: Arc::new("abc".to_string()),
+            connection_id: ConnectionId(Arc::new("connection1".to_string())),
+            reactor_update_channel: None,
+        })
+        .expect("Failed to send publisher connected message");
+
+    second_channel
+        .send(RtmpEndpointPublisherMessage::NewPublisherConnected {
+            stream_id: StreamId(Arc::new("second".to_string())),
+            stream_key: Arc::new("def".to_string()),
+            connection_id: ConnectionId(Arc::new("connection2".to_string())),
+            reactor_update_channel: None,
+        })
+        .expect("Failed to send publisher connected message");
+
+    context.step_context.execute_pending_futures().await;
+
+    let mut stream_names = context
+        .step_context
+        .media_outputs
+        .iter()
+        .map(|media| match &media.content {
+            MediaNotificationContent::NewIncomingStream { stream_name } => {
+                (media.stream_id.0.to_string(), stream_name.to_string())
+            }
+
+            content => panic!("Unexpected media content: {:?}", content),
+        })
+        .collect::<Vec<_>>();
+
+    stream_names.sort();
+    assert_eq!(
+        stream_names,
+        vec![
+            ("first".to_string(), "abc".to_string()),
+            ("second".to_string(), "def".to_string())
+        ],
+        "Unexpected streams"
+    );
+
+    let details = context.step_context.step.get_state_details();
+    assert_eq!(
+        details.get("port_1935_connections"),
+        Some(&"1".to_string()),
+        "Unexpected connection count for port 1935"
+    );
+
+    assert_eq!(
+        details.get("port_1936_connections"),
+        Some(&"1".to_string()),
+        "Unexpected connection count for port 1936"
+    );
+}
+
+#[tokio::test]
+async fn publisher_with_stream_key_already_published_on_another_port_is_ignored() {
+    let mut definition = DefinitionBuilder::new().build();
+    definition.parameters.insert(
+        PORT_PROPERTY_NAME.to_string(),
+        Some("1935,1936".to_string()),
+    );
+
+    let mut context = TestContext::new(definition).unwrap();
+    let first_channel = context.accept_registration().await;
+    let second_channel = context.accept_registration().await;
+
+    first_channel
+        .send(RtmpEndpointPublisherMessage::NewPublisherConnected {
+            stream_id: StreamId(Arc::new("first".to_string())),
+            stream_key: Arc::new("abc".to_string()),
+            connection_id: ConnectionId(Arc::new("connection1".to_string())),
+            reactor_update_channel: None,
+        })
+        .expect("Failed to send publisher connected message");
+
+    context.step_context.execute_pending_futures().await;
+    context.step_context.media_outputs.clear();
+
+    second_channel
+        .send(RtmpEndpointPublisherMessage::NewPublisherConnected {
+            stream_id: StreamId(Arc::new("second".to_string())),
+            stream_key: Arc::new("abc".to_string()),
+            connection_id: ConnectionId(Arc::new("connection2".to_string())),
+            reactor_update_channel: None,
+        })
+        .expect("Failed to send publisher connected message");
+
+    second_channel
+        .send(RtmpEndpointPublisherMessage::NewAudioData {
+            publisher: ConnectionId(Arc::new("connection2".to_string())),
+            is_sequence_header: true,
+            data: Bytes::from(vec![1, 2, 3]),
+            timestamp: RtmpTimestamp::new(5),
+        })
+        .expect("Failed to send audio message");
+
+    context.step_context.execute_pending_futures().await;
+
+    assert!(
+        context.step_context.media_outputs.is_empty(),
+        "Expected no media from the duplicate publisher"
+    );
+
+    let details = context.step_context.step.get_state_details();
+    assert_eq!(
+        details.get("port_1936_connections"),
+        Some(&"0".to_string()),
+        "Unexpected connection count for port 1936"
+    );
+}
+
+#[tokio::test]
+async fn publisher_requiring_approval_rejected_when_stream_key_published_on_another_port() {
+    let mut definition = DefinitionBuilder::new().reactor_name("reactor").build();
+    definition.parameters.insert(
+        PORT_PROPERTY_NAME.to_string(),
+        Some("1935,1936".to_string()),
+    );
+
+    let mut context = TestContext::new(definition).unwrap();
+    let first_channel = context.accept_registration().await;
+    let second_channel = context.accept_registration().await;
+
+    first_channel
+        .send(RtmpEndpointPublisherMessage::NewPublisherConnected {
+            stream_id: StreamId(Arc::new("first".to_string())),
+            stream_key: Arc::new("abc".to_string()),
+            connection_id: ConnectionId(Arc::new("connection1".to_string())),
+            reactor_update_channel: None,
+        })
+        .expect("Failed to send publisher connected message");
+
+    context.step_context.execute_pending_futures().await;
+
+    let (sender, receiver) = channel();
+    second_channel
+        .send(RtmpEndpointPublisherMessage::PublisherRequiringApproval {
+            stream_key: Arc::new("abc".to_string()),
+            connection_id: ConnectionId(Arc::new("connection2".to_string())),
+            response_channel: sender,
+        })
+        .expect("Failed to send publisher message");
+
+    context.step_context.execute_pending_futures().await;
+
+    let response = test_utils::expect_oneshot_response(receiver).await;
+    match response {
+        ValidationResponse::Reject => (),
+        response => panic!("Unexpected response: {:?}", response),
+    }
+
+    test_utils::expect_mpsc_timeout(&mut context.reactor_manager).await;
+}
+
+#[tokio::test]
+async fn stall_alert_raised_and_cleared_when_connected_publisher_pauses_media() {
+    let mut definition = DefinitionBuilder::new().build();
+    definition
+        .parameters
+        .insert(STALL_TIMEOUT.to_string(), Some("100".to_string()));
+
+    let mut context = TestContext::new(definition).unwrap();
+    let channel = context.accept_registration().await;
+
+    channel
+        .send(RtmpEndpointPublisherMessage::NewPublisherConnected {
+            stream_id: StreamId(Arc::new("test".to_string())),
+            stream_key: Arc::new("abc".to_string()),
+            connection_id: ConnectionId(Arc::new("connection".to_string())),
+            reactor_update_channel: None,
+        })
+        .expect("Failed to send publisher connected message");
+
+    context.step_context.execute_pending_futures().await;
+    context.step_context.media_outputs.clear();
+
+    // The publisher stays connected, but sends nothing
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    context.step_context.execute_pending_futures().await;
+
+    let event = test_utils::expect_mpsc_response(&mut context.event_hub).await;
+    match event {
+        PublishEventRequest::StreamAlert(StreamAlertEvent::AlertRaised {
+            stream_id,
+            stream_name,
+            alert,
+            ..
+        }) => {
+            assert_eq!(stream_id.0.as_str(), "test", "Unexpected stream id");
+            assert_eq!(stream_name.as_str(), "abc", "Unexpected stream name");
+            assert_eq!(alert, StreamAlert::IngestStalled, "Unexpected alert");
+        }
+
+        event => panic!("Unexpected event: {:?}", event),
+    }
+
+    assert!(
+        context.step_context.media_outputs.is_empty(),
+        "Expected stream to not be disconnected"
+    );
+
+    assert_eq!(
+        context.step_context.step.get_state_details()[STALLED_STREAMS_DETAIL],
+        "1",
+        "Unexpected stalled stream count"
+    );
+
+    channel
+        .send(RtmpEndpointPublisherMessage::NewAudioData {
+            publisher: ConnectionId(Arc::new("connection".to_string())),
+            data: Bytes::from(vec![1, 2, 3]),
+            timestamp: RtmpTimestamp::new(5),
+            is_sequence_header: false,
+        })
+        .expect("Failed to send audio message");
+
+    context.step_context.execute_pending_futures().await;
+
+    let event = test_utils::expect_mpsc_response(&mut context.event_hub).await;
+    match event {
+        PublishEventRequest::StreamAlert(StreamAlertEvent::AlertCleared { alert, .. }) => {
+            assert_eq!(alert, StreamAlert::IngestStalled, "Unexpected alert");
+        }
+
+        event => panic!("Unexpected event: {:?}", event),
+    }
+
+    assert_eq!(
+        context.step_context.step.get_state_details()[STALLED_STREAMS_DETAIL],
+        "0",
+        "Unexpected stalled stream count"
+    );
+}
+
+#[tokio::test]
+async fn publisher_with_mapped_stream_key_approved_without_reactor() {
+    let definition = DefinitionBuilder::new()
+        .stream_key_map("abc:studio1,def:studio2")
+        .build();
+
+    let mut context = TestContext::new(definition).unwrap();
+    let publish_channel = context.accept_registration().await;
+
+    let (sender, receiver) = channel();
+    publish_channel
+        .send(RtmpEndpointPublisherMessage::PublisherRequiringApproval {
+            stream_key: Arc::new("def".to_string()),
+            connection_id: ConnectionId(Arc::new("connection".to_string())),
+            response_channel: sender,
+        })
+        .expect("Failed to send publisher message");
+
+    context.step_context.execute_pending_futures().await;
+
+    let response = test_utils::expect_oneshot_response(receiver).await;
+    match response {
+        ValidationResponse::Approve {
+            reactor_update_channel: None,
+        } => (),
+        response => panic!("Unexpected response: {:?}", response),
+    }
+
+    test_utils::expect_mpsc_timeout(&mut context.reactor_manager).await;
+}
+
+#[tokio::test]
+async fn stream_named_after_mapped_stream_name() {
+    let definition = DefinitionBuilder::new()
+        .stream_key_map("abc:studio1,def:studio2")
+        .build();
+
+    let mut context = TestContext::new(definition).unwrap();
+    let channel = context.accept_registration().await;
+
+    channel
+        .send(RtmpEndpointPublisherMessage::NewPublisherConnected {
+            stream_id: StreamId(Arc::new("test".to_string())),
+            stream_key: Arc::new("abc".to_string()),
+            connection_id: ConnectionId(Arc::new("connection".to_string())),
+            reactor_update_channel: None,
+        })
+        .expect("Failed to send publisher connected message");
+
+    context.step_context.execute_pending_futures().await;
+
+    assert_eq!(
+        context.step_context.media_outputs.len(),
+        1,
+        "Unexpected number of media outputs"
+    );
+
+    match &context.step_context.media_outputs[0].content {
+        MediaNotificationContent::NewIncomingStream { stream_name } => {
+            assert_eq!(stream_name.as_str(), "studio1", "Unexpected stream name");
+        }
+
+        content => panic!("Unexpected media content: {:?}", content),
+    }
+}
+
+#[tokio::test]
+async fn publisher_with_unmapped_stream_key_rejected() {
+    let definition = DefinitionBuilder::new()
+        .stream_key_map("abc:studio1")
+        .build();
+    let mut context = TestContext::new(definition).unwrap();
+    let request = test_utils::expect_mpsc_response(&mut context.rtmp_endpoint).await;
+    let publish_channel = match request {
+        RtmpEndpointRequest::ListenForPublishers {
+            message_channel,
+            requires_registrant_approval,
+            ..
+        } => {
+            assert!(
+                requires_registrant_approval,
+                "Expected requires approval to be true"
+            );
+
+            message_channel
+                .send(RtmpEndpointPublisherMessage::PublisherRegistrationSuccessful)
+                .expect("Failed to send registration response");
+
+            message_channel
+        }
+
+        request => panic!("Unexpected rtmp request seen: {:?}", request),
+    };
+
+    context.step_context.execute_pending_futures().await;
+
+    let (sender, receiver) = channel();
+    publish_channel
+        .send(RtmpEndpointPublisherMessage::PublisherRequiringApproval {
+            stream_key: Arc::new("unknown".to_string()),
+            connection_id: ConnectionId(Arc::new("connection".to_string())),
+            response_channel: sender,
+        })
+        .expect("Failed to send publisher message");
+
+    context.step_context.execute_pending_futures().await;
+
+    let response = test_utils::expect_oneshot_response(receiver).await;
+    match response {
+        ValidationResponse::Reject => (),
+        response => panic!("Unexpected response: {:?}", response),
+    }
+}
+
+#[tokio::test]
+async fn error_if_stream_key_map_is_malformed() {
+    let definition = DefinitionBuilder::new().stream_key_map("abc:,def").build();
+    if TestContext::new(definition).is_ok() {
+        panic!("Expected failure");
+    }
+}
+
+#[tokio::test]
+async fn error_if_stream_key_map_used_with_reactor() {
+    let definition = DefinitionBuilder::new()
+        .reactor_name("reactor")
+        .stream_key_map("abc:studio1")
+        .build();
+
+    if TestContext::new(definition).is_ok() {
+        panic!("Expected failure");
+    }
+}
@@ -10,6 +10,15 @@
 //! If an exact stream key is configured, then the first media stream that comes into the step will
 //! be surfaced on that stream key.
 //!
+//! If the step's generator was given a watch authorizer, every client requesting to watch a
+//! stream must be allowed by it before being able to watch. When a reactor is also used, the
+//! reactor is only queried for clients the authorizer allowed.
+//!
+//! Every watcher this step approves is counted against its workflow's watcher limit, which is
+//! shared with all other output steps in the workflow. Once the workflow is full, new watchers are
+//! either rejected or, if the workflow's limit uses the slate behavior and a full slate stream key
+//! is configured, served the stream on the full slate stream key instead.
+//!
 //! All media notifications that are passed into this step are passed onto the next step.
 
 #[cfg(test)]
@@ -22,20 +31,25 @@ use crate::rtmp_server::{
 };
 use crate::utils::hash_map_to_stream_metadata;
 use mmids_core::codecs::{AUDIO_CODEC_AAC_RAW, VIDEO_CODEC_H264_AVC};
-use mmids_core::net::{IpAddress, IpAddressParseError};
+use mmids_core::net::{ConnectionId, IpAddress, IpAddressParseError};
 use mmids_core::reactors::manager::ReactorManagerRequest;
 use mmids_core::reactors::ReactorWorkflowUpdate;
 use mmids_core::workflows::definitions::WorkflowStepDefinition;
 use mmids_core::workflows::metadata::{MetadataKey, MetadataValue};
 use mmids_core::workflows::steps::factory::StepGenerator;
 use mmids_core::workflows::steps::futures_channel::WorkflowStepFuturesChannel;
+use mmids_core::workflows::steps::watch_authorization::{
+    WatchAuthorization, WatchAuthorizationRequest, WatchAuthorizer,
+};
 use mmids_core::workflows::steps::{
     StepCreationResult, StepFutureResult, StepInputs, StepOutputs, StepStatus, WorkflowStep,
 };
+use mmids_core::workflows::stream_labels::get_announced_label;
+use mmids_core::workflows::watchers::{WatcherAdmission, WatcherSlot, WorkflowWatchers};
 use mmids_core::workflows::{MediaNotification, MediaNotificationContent};
 use mmids_core::StreamId;
 use rml_rtmp::time::RtmpTimestamp;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use thiserror::Error as ThisError;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
@@ -50,6 +64,7 @@ pub const IP_ALLOW_PROPERTY_NAME: &str = "allow_ips";
 pub const IP_DENY_PROPERTY_NAME: &str = "deny_ips";
 pub const RTMPS_FLAG: &str = "rtmps";
 pub const REACTOR_NAME: &str = "reactor";
+pub const FULL_SLATE_STREAM_KEY_PROPERTY_NAME: &str = "full_slate_stream_key";
 
 /// Generates new rtmp watch workflow step instances based on a given step definition.
 pub struct RtmpWatchStepGenerator {
@@ -57,6 +72,7 @@ pub struct RtmpWatchStepGenerator {
     reactor_manager: UnboundedSender<ReactorManagerRequest>,
     is_keyframe_metadata_key: MetadataKey,
     pts_offset_metadata_key: MetadataKey,
+    watch_authorizer: Option<Arc<dyn WatchAuthorizer + Send + Sync>>,
 }
 
 struct StreamWatchers {
@@ -79,14 +95,22 @@ struct RtmpWatchStep {
     rtmp_app: Arc<String>,
     stream_key: StreamKeyRegistration,
     reactor_name: Option<Arc<String>>,
+    watch_authorizer: Option<Arc<dyn WatchAuthorizer + Send + Sync>>,
     status: StepStatus,
     rtmp_endpoint_sender: UnboundedSender<RtmpEndpointRequest>,
     reactor_manager: UnboundedSender<ReactorManagerRequest>,
     media_channel: UnboundedSender<RtmpEndpointMediaMessage>,
     stream_id_to_name_map: HashMap<StreamId, Arc<String>>,
     stream_watchers: HashMap<Arc<String>, StreamWatchers>,
+    unsupported_payload_streams: HashSet<StreamId>,
     is_keyframe_metadata_key: MetadataKey,
     pts_offset_metadata_key: MetadataKey,
+    workflow_watchers: Option<WorkflowWatchers>,
+    full_slate_stream_key: Option<Arc<String>>,
+
+    /// Connections that requested to watch, with the slot they take up in the workflow's watcher
+    /// limit once they've been approved
+    watcher_slots: HashMap<ConnectionId, Option<WatcherSlot>>,
 }
 
 impl StepFutureResult for RtmpWatchStepFutureResult {}
@@ -97,8 +121,17 @@ enum RtmpWatchStepFutureResult {
     ReactorGone,
     RtmpWatchNotificationReceived(RtmpEndpointWatcherNotification),
 
+    WatchAuthorizationResponse {
+        authorization: WatchAuthorization,
+        connection_id: ConnectionId,
+        stream_key: Arc<String>,
+        validation_channel: Sender<ValidationResponse>,
+    },
+
     ReactorWorkflowResponse {
         is_valid: bool,
+        connection_id: ConnectionId,
+        stream_key: Arc<String>,
         validation_channel: Sender<ValidationResponse>,
         reactor_update_channel: UnboundedReceiver<ReactorWorkflowUpdate>,
     },
@@ -155,8 +188,18 @@ impl RtmpWatchStepGenerator {
             reactor_manager,
             is_keyframe_metadata_key,
             pts_offset_metadata_key,
+            watch_authorizer: None,
         }
     }
+
+    /// Requires clients to be allowed by the specified authorizer before they can watch streams
+    pub fn with_watch_authorizer(
+        mut self,
+        authorizer: Arc<dyn WatchAuthorizer + Send + Sync>,
+    ) -> Self {
+        self.watch_authorizer = Some(authorizer);
+        self
+    }
 }
 
 impl StepGenerator for RtmpWatchStepGenerator {
@@ -223,6 +266,14 @@ impl StepGenerator for RtmpWatchStepGenerator {
             _ => None,
         };
 
+        let full_slate_stream_key = match definition
+            .parameters
+            .get(FULL_SLATE_STREAM_KEY_PROPERTY_NAME)
+        {
+            Some(Some(value)) => Some(Arc::new(value.trim().to_string())),
+            _ => None,
+        };
+
         let (media_sender, media_receiver) = unbounded_channel();
 
         let step = RtmpWatchStep {
@@ -235,9 +286,14 @@ impl StepGenerator for RtmpWatchStepGenerator {
             stream_key,
             stream_id_to_name_map: HashMap::new(),
             reactor_name,
+            watch_authorizer: self.watch_authorizer.clone(),
             stream_watchers: HashMap::new(),
+            unsupported_payload_streams: HashSet::new(),
             is_keyframe_metadata_key: self.is_keyframe_metadata_key,
             pts_offset_metadata_key: self.pts_offset_metadata_key,
+            workflow_watchers: futures_channel.workflow_watchers().cloned(),
+            full_slate_stream_key,
+            watcher_slots: HashMap::new(),
         };
 
         let (notification_sender, notification_receiver) = unbounded_channel();
@@ -251,7 +307,9 @@ impl StepGenerator for RtmpWatchStepGenerator {
                 notification_channel: notification_sender,
                 ip_restrictions: ip_restriction,
                 use_tls: use_rtmps,
-                requires_registrant_approval: step.reactor_name.is_some(),
+                requires_registrant_approval: step.reactor_name.is_some()
+                    || step.watch_authorizer.is_some()
+                    || step.workflow_watchers.is_some(),
             });
 
         futures_channel.send_on_generic_unbounded_recv(
@@ -342,44 +400,172 @@ impl RtmpWatchStep {
             RtmpEndpointWatcherNotification::WatcherRequiringApproval {
                 connection_id,
                 stream_key,
+                client_ip,
                 response_channel,
             } => {
-                if let Some(reactor) = &self.reactor_name {
-                    let (sender, mut receiver) = unbounded_channel();
-                    let _ = self.reactor_manager.send(
-                        ReactorManagerRequest::CreateWorkflowForStreamName {
-                            reactor_name: reactor.clone(),
-                            stream_name: stream_key,
-                            response_channel: sender,
-                        },
-                    );
+                if self.workflow_watchers.is_some() {
+                    self.watcher_slots.insert(connection_id.clone(), None);
+                }
 
-                    futures_channel.send_on_generic_future_completion(async move {
-                        let is_valid = match receiver.recv().await {
-                            Some(response) => response.is_valid,
-                            None => false, // Assume not valid if channel closed
-                        };
+                if let Some(authorizer) = &self.watch_authorizer {
+                    let mut metadata = HashMap::new();
+                    metadata.insert(APP_PROPERTY_NAME.to_string(), self.rtmp_app.to_string());
 
-                        RtmpWatchStepFutureResult::ReactorWorkflowResponse {
-                            is_valid,
+                    let authorization = authorizer.authorize(WatchAuthorizationRequest {
+                        stream_name: stream_key.clone(),
+                        connection_id: connection_id.clone(),
+                        client_ip,
+                        metadata,
+                    });
+
+                    futures_channel.send_on_generic_future_completion(async move {
+                        RtmpWatchStepFutureResult::WatchAuthorizationResponse {
+                            authorization: authorization.await,
+                            connection_id,
+                            stream_key,
                             validation_channel: response_channel,
-                            reactor_update_channel: receiver,
                         }
                     });
                 } else {
-                    error!(
-                        connection_id = %connection_id,
-                        stream_key = %stream_key,
-                        "Watcher requires approval for stream key {} but no reactor name was set",
-                        stream_key
+                    self.request_reactor_approval(
+                        connection_id,
+                        stream_key,
+                        response_channel,
+                        futures_channel,
                     );
+                }
+            }
 
-                    let _ = response_channel.send(ValidationResponse::Reject);
+            RtmpEndpointWatcherNotification::WatcherStopped { connection_id, .. } => {
+                // Dropping the slot frees it up for another watcher
+                self.watcher_slots.remove(&connection_id);
+            }
+        }
+    }
+
+    /// Approves the watcher if the step's reactor has a workflow for the stream key. When the step
+    /// doesn't use a reactor, watchers are approved as long as the step's authorizer allowed them
+    /// (if it has one) and there's room for them in the workflow.
+    fn request_reactor_approval(
+        &mut self,
+        connection_id: ConnectionId,
+        stream_key: Arc<String>,
+        response_channel: Sender<ValidationResponse>,
+        futures_channel: &WorkflowStepFuturesChannel,
+    ) {
+        if let Some(reactor) = &self.reactor_name {
+            let (sender, mut receiver) = unbounded_channel();
+            let _ = self
+                .reactor_manager
+                .send(ReactorManagerRequest::CreateWorkflowForStreamName {
+                    reactor_name: reactor.clone(),
+                    stream_name: stream_key.clone(),
+                    response_channel: sender,
+                });
+
+            futures_channel.send_on_generic_future_completion(async move {
+                let is_valid = match receiver.recv().await {
+                    Some(response) => response.is_valid,
+                    None => false, // Assume not valid if channel closed
+                };
+
+                RtmpWatchStepFutureResult::ReactorWorkflowResponse {
+                    is_valid,
+                    connection_id,
+                    stream_key,
+                    validation_channel: response_channel,
+                    reactor_update_channel: receiver,
                 }
+            });
+        } else if self.watch_authorizer.is_some() || self.workflow_watchers.is_some() {
+            self.approve_watcher(connection_id, stream_key, None, response_channel);
+        } else {
+            error!(
+                connection_id = %connection_id,
+                stream_key = %stream_key,
+                "Watcher requires approval for stream key {} but no reactor name was set",
+                stream_key
+            );
+
+            self.reject_watcher(&connection_id, response_channel);
+        }
+    }
+
+    /// Approves a watcher that passed all other validation, as long as there's room for them in
+    /// the workflow's watcher limit.
+    fn approve_watcher(
+        &mut self,
+        connection_id: ConnectionId,
+        stream_key: Arc<String>,
+        reactor_update_channel: Option<UnboundedReceiver<ReactorWorkflowUpdate>>,
+        response_channel: Sender<ValidationResponse>,
+    ) {
+        let workflow_watchers = match &self.workflow_watchers {
+            Some(watchers) => watchers,
+            None => {
+                let _ = response_channel.send(ValidationResponse::Approve {
+                    reactor_update_channel,
+                });
+
+                return;
+            }
+        };
+
+        let slot = match self.watcher_slots.get_mut(&connection_id) {
+            Some(slot) => slot,
+            None => {
+                // The connection stopped watching before its approval completed
+                let _ = response_channel.send(ValidationResponse::Reject);
+                return;
+            }
+        };
+
+        match workflow_watchers.try_add_watcher() {
+            WatcherAdmission::Admitted(new_slot) => {
+                *slot = Some(new_slot);
+                let _ = response_channel.send(ValidationResponse::Approve {
+                    reactor_update_channel,
+                });
+            }
+
+            WatcherAdmission::Slate if self.full_slate_stream_key.is_some() => {
+                info!(
+                    connection_id = %connection_id,
+                    stream_key = %stream_key,
+                    "Workflow is full, redirecting watcher of stream key {} to the full slate",
+                    stream_key
+                );
+
+                // Slate watchers are not counted, so the connection no longer needs tracking
+                self.watcher_slots.remove(&connection_id);
+                if let Some(slate_key) = &self.full_slate_stream_key {
+                    let _ = response_channel.send(ValidationResponse::Redirect {
+                        stream_key: slate_key.clone(),
+                    });
+                }
+            }
+
+            WatcherAdmission::Slate | WatcherAdmission::Rejected => {
+                info!(
+                    connection_id = %connection_id,
+                    stream_key = %stream_key,
+                    "Workflow is full, rejecting watcher of stream key {}", stream_key
+                );
+
+                self.reject_watcher(&connection_id, response_channel);
             }
         }
     }
 
+    fn reject_watcher(
+        &mut self,
+        connection_id: &ConnectionId,
+        response_channel: Sender<ValidationResponse>,
+    ) {
+        self.watcher_slots.remove(connection_id);
+        let _ = response_channel.send(ValidationResponse::Reject);
+    }
+
     fn handle_media(&mut self, media: MediaNotification, outputs: &mut StepOutputs) {
         outputs.media.push(media.clone());
 
@@ -433,6 +619,7 @@ impl RtmpWatchStep {
                         stream_id = ?media.stream_id,
                         "Stream disconnected notification received for stream id {:?}", media.stream_id
                     );
+                    self.unsupported_payload_streams.remove(&media.stream_id);
                     match self.stream_id_to_name_map.remove(&media.stream_id) {
                         Some(_) => (),
                         None => {
@@ -445,6 +632,11 @@ impl RtmpWatchStep {
                 }
 
                 MediaNotificationContent::Metadata { data } => {
+                    if get_announced_label(&media.content).is_some() {
+                        // Stream labels are only meaningful to mmids, not to rtmp players
+                        return;
+                    }
+
                     let stream_key = match self.stream_id_to_name_map.get(&media.stream_id) {
                         Some(key) => key,
                         None => return,
@@ -509,7 +701,23 @@ impl RtmpWatchStep {
                             }
                         }
 
-                        _ => return, // Payload type not supported by RTMP
+                        other => {
+                            // Payload type not supported by RTMP (e.g. H265), so only warn once
+                            // per stream instead of on every packet
+                            if self
+                                .unsupported_payload_streams
+                                .insert(media.stream_id.clone())
+                            {
+                                warn!(
+                                    stream_id = ?media.stream_id,
+                                    payload_type = %other,
+                                    "Payload type '{}' is not supported by RTMP and will not be \
+                                        sent to watchers", other
+                                );
+                            }
+
+                            return;
+                        }
                     };
 
                     let rtmp_media = RtmpEndpointMediaMessage {
@@ -573,17 +781,48 @@ impl WorkflowStep for RtmpWatchStep {
                     self.handle_endpoint_notification(notification, &futures_channel);
                 }
 
+                RtmpWatchStepFutureResult::WatchAuthorizationResponse {
+                    authorization,
+                    connection_id,
+                    stream_key,
+                    validation_channel,
+                } => match authorization {
+                    WatchAuthorization::Allow => {
+                        self.request_reactor_approval(
+                            connection_id,
+                            stream_key,
+                            validation_channel,
+                            &futures_channel,
+                        );
+                    }
+
+                    WatchAuthorization::Deny => {
+                        info!(
+                            connection_id = %connection_id,
+                            stream_key = %stream_key,
+                            "Watcher was denied access to stream key {}", stream_key
+                        );
+
+                        self.reject_watcher(&connection_id, validation_channel);
+                    }
+                },
+
                 RtmpWatchStepFutureResult::ReactorWorkflowResponse {
                     is_valid,
+                    connection_id,
+                    stream_key,
                     validation_channel,
                     reactor_update_channel,
                 } => {
                     if is_valid {
-                        let _ = validation_channel.send(ValidationResponse::Approve {
-                            reactor_update_channel,
-                        });
+                        self.approve_watcher(
+                            connection_id,
+                            stream_key,
+                            Some(reactor_update_channel),
+                            validation_channel,
+                        );
                     } else {
-                        let _ = validation_channel.send(ValidationResponse::Reject);
+                        self.reject_watcher(&connection_id, validation_channel);
                     }
                 }
 
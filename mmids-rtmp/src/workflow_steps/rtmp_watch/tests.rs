@@ -4,6 +4,8 @@ use crate::rtmp_server::{
 };
 use anyhow::Result;
 use bytes::{Bytes, BytesMut};
+use futures::future::BoxFuture;
+use futures::FutureExt;
 use mmids_core::net::ConnectionId;
 use mmids_core::test_utils::expect_mpsc_response;
 use mmids_core::workflows::definitions::WorkflowStepType;
@@ -14,11 +16,13 @@ use mmids_core::workflows::metadata::{
     MediaPayloadMetadataCollection, MetadataEntry, MetadataKeyMap,
 };
 use mmids_core::workflows::steps::test_utils::StepTestContext;
+use mmids_core::workflows::watchers::{WatcherLimit, WatcherLimitBehavior};
 use mmids_core::workflows::{MediaNotification, MediaNotificationContent, MediaType};
 use mmids_core::{test_utils, StreamId};
 use rml_rtmp::time::RtmpTimestamp;
 use std::collections::{HashMap, HashSet};
 use std::iter;
+use std::net::{IpAddr, Ipv4Addr};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
@@ -37,6 +41,7 @@ struct DefinitionBuilder {
     app: Option<String>,
     key: Option<String>,
     reactor: Option<String>,
+    full_slate_key: Option<String>,
 }
 
 impl DefinitionBuilder {
@@ -46,6 +51,7 @@ impl DefinitionBuilder {
             app: None,
             key: None,
             reactor: None,
+            full_slate_key: None,
         }
     }
 
@@ -69,6 +75,11 @@ impl DefinitionBuilder {
         self
     }
 
+    fn full_slate_key(mut self, key: &str) -> Self {
+        self.full_slate_key = Some(key.to_string());
+        self
+    }
+
     fn build(self) -> WorkflowStepDefinition {
         let mut definition = WorkflowStepDefinition {
             step_type: WorkflowStepType("rtmp_watch".to_string()),
@@ -107,12 +118,54 @@ impl DefinitionBuilder {
                 .insert(REACTOR_NAME.to_string(), Some(reactor));
         }
 
+        if let Some(key) = self.full_slate_key {
+            definition
+                .parameters
+                .insert(FULL_SLATE_STREAM_KEY_PROPERTY_NAME.to_string(), Some(key));
+        }
+
         definition
     }
 }
 
+struct TestAuthorizer {
+    authorization: WatchAuthorization,
+}
+
+impl WatchAuthorizer for TestAuthorizer {
+    fn authorize(
+        &self,
+        _request: WatchAuthorizationRequest,
+    ) -> BoxFuture<'static, WatchAuthorization> {
+        let authorization = self.authorization;
+        async move { authorization }.boxed()
+    }
+}
+
 impl TestContext {
     fn new(definition: WorkflowStepDefinition) -> Result<Self> {
+        Self::new_with_authorizer(definition, None)
+    }
+
+    fn new_with_authorizer(
+        definition: WorkflowStepDefinition,
+        watch_authorizer: Option<Arc<dyn WatchAuthorizer + Send + Sync>>,
+    ) -> Result<Self> {
+        Self::create(definition, watch_authorizer, None)
+    }
+
+    fn new_with_workflow_watchers(
+        definition: WorkflowStepDefinition,
+        workflow_watchers: WorkflowWatchers,
+    ) -> Result<Self> {
+        Self::create(definition, None, Some(workflow_watchers))
+    }
+
+    fn create(
+        definition: WorkflowStepDefinition,
+        watch_authorizer: Option<Arc<dyn WatchAuthorizer + Send + Sync>>,
+        workflow_watchers: Option<WorkflowWatchers>,
+    ) -> Result<Self> {
         let (reactor_sender, reactor_receiver) = unbounded_channel();
         let (rtmp_sender, rtmp_receiver) = unbounded_channel();
 
@@ -125,9 +178,16 @@ impl TestContext {
             rtmp_endpoint_sender: rtmp_sender,
             is_keyframe_metadata_key,
             pts_offset_metadata_key,
+            watch_authorizer,
         };
 
-        let step_context = StepTestContext::new(Box::new(generator), definition)?;
+        let step_context = match workflow_watchers {
+            Some(watchers) => {
+                StepTestContext::with_workflow_watchers(Box::new(generator), definition, watchers)?
+            }
+
+            None => StepTestContext::new(Box::new(generator), definition)?,
+        };
 
         Ok(TestContext {
             step_context,
@@ -177,6 +237,35 @@ impl TestContext {
     }
 }
 
+async fn request_watch(
+    context: &mut TestContext,
+    notification_channel: &UnboundedSender<RtmpEndpointWatcherNotification>,
+    connection_id: &str,
+) -> ValidationResponse {
+    let (sender, receiver) = channel();
+    notification_channel
+        .send(RtmpEndpointWatcherNotification::WatcherRequiringApproval {
+            stream_key: Arc::new("abc".to_string()),
+            connection_id: ConnectionId(Arc::new(connection_id.to_string())),
+            client_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            response_channel: sender,
+        })
+        .expect("Failed to send approval request");
+
+    context.step_context.execute_pending_futures().await;
+    test_utils::expect_oneshot_response(receiver).await
+}
+
+fn limited_watchers(max_watchers: usize, behavior: WatcherLimitBehavior) -> WorkflowWatchers {
+    let watchers = WorkflowWatchers::new();
+    watchers.set_limit(Some(WatcherLimit {
+        max_watchers,
+        behavior,
+    }));
+
+    watchers
+}
+
 #[tokio::test]
 async fn requests_registration_for_watchers() {
     let definition = DefinitionBuilder::new()
@@ -355,6 +444,7 @@ async fn video_packet_not_sent_to_media_channel_if_new_stream_message_not_receiv
             timestamp: Duration::new(0, 0),
             metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
         },
+        annotations: Default::default(),
     });
 
     test_utils::expect_mpsc_timeout(&mut media_channel).await;
@@ -371,6 +461,7 @@ async fn video_packet_sent_to_media_channel_after_new_stream_message_received()
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let is_keyframe_metadata = MetadataEntry::new(
@@ -402,6 +493,7 @@ async fn video_packet_sent_to_media_channel_after_new_stream_message_received()
             timestamp: Duration::from_millis(5),
             metadata,
         },
+        annotations: Default::default(),
     });
 
     let media = expect_mpsc_response(&mut media_channel).await;
@@ -440,11 +532,13 @@ async fn video_packet_not_sent_to_media_channel_after_stream_disconnection_messa
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     context.step_context.execute_with_media(MediaNotification {
         stream_id: StreamId(Arc::new("abc".to_string())),
         content: MediaNotificationContent::StreamDisconnected,
+        annotations: Default::default(),
     });
 
     context.step_context.execute_with_media(MediaNotification {
@@ -457,6 +551,7 @@ async fn video_packet_not_sent_to_media_channel_after_stream_disconnection_messa
             timestamp: Duration::from_millis(5),
             metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
         },
+        annotations: Default::default(),
     });
 
     test_utils::expect_mpsc_timeout(&mut media_channel).await;
@@ -473,6 +568,7 @@ async fn video_packet_not_sent_to_media_channel_when_new_stream_is_for_different
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     context.step_context.execute_with_media(MediaNotification {
@@ -485,6 +581,7 @@ async fn video_packet_not_sent_to_media_channel_when_new_stream_is_for_different
             timestamp: Duration::from_millis(5),
             metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
         },
+        annotations: Default::default(),
     });
 
     test_utils::expect_mpsc_timeout(&mut media_channel).await;
@@ -501,6 +598,7 @@ async fn audio_packet_sent_to_media_channel_after_new_stream_message_received()
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     context.step_context.execute_with_media(MediaNotification {
@@ -513,6 +611,7 @@ async fn audio_packet_sent_to_media_channel_after_new_stream_message_received()
             payload_type: AUDIO_CODEC_AAC_RAW.clone(),
             metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
         },
+        annotations: Default::default(),
     });
 
     let media = expect_mpsc_response(&mut media_channel).await;
@@ -544,6 +643,7 @@ async fn metadata_packet_sent_to_media_channel_after_new_stream_message_received
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     let mut metadata = HashMap::new();
@@ -552,6 +652,7 @@ async fn metadata_packet_sent_to_media_channel_after_new_stream_message_received
     context.step_context.execute_with_media(MediaNotification {
         stream_id: StreamId(Arc::new("abc".to_string())),
         content: MediaNotificationContent::Metadata { data: metadata },
+        annotations: Default::default(),
     });
 
     let media = expect_mpsc_response(&mut media_channel).await;
@@ -577,6 +678,7 @@ async fn media_message_uses_strict_stream_key_when_exact_key_registered() {
         content: MediaNotificationContent::NewIncomingStream {
             stream_name: Arc::new("def".to_string()),
         },
+        annotations: Default::default(),
     });
 
     context.step_context.execute_with_media(MediaNotification {
@@ -589,6 +691,7 @@ async fn media_message_uses_strict_stream_key_when_exact_key_registered() {
             timestamp: Duration::from_millis(5),
             metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
         },
+        annotations: Default::default(),
     });
 
     let media = expect_mpsc_response(&mut media_channel).await;
@@ -612,6 +715,7 @@ async fn new_stream_message_passed_as_output() {
             content: MediaNotificationContent::NewIncomingStream {
                 stream_name: Arc::new("def".to_string()),
             },
+            annotations: Default::default(),
         });
 }
 
@@ -626,6 +730,7 @@ async fn stream_disconnected_message_passed_as_output() {
         .assert_media_passed_through(MediaNotification {
             stream_id: StreamId(Arc::new("abc".to_string())),
             content: MediaNotificationContent::StreamDisconnected,
+            annotations: Default::default(),
         });
 }
 
@@ -647,6 +752,7 @@ async fn video_message_passed_as_output() {
                 timestamp: Duration::from_millis(5),
                 metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
             },
+            annotations: Default::default(),
         });
 }
 
@@ -668,6 +774,7 @@ async fn audio_message_passed_as_output() {
                 payload_type: AUDIO_CODEC_AAC_RAW.clone(),
                 metadata: MediaPayloadMetadataCollection::new(iter::empty(), &mut BytesMut::new()),
             },
+            annotations: Default::default(),
         });
 }
 
@@ -685,6 +792,7 @@ async fn metadata_message_passed_as_output() {
         .assert_media_passed_through(MediaNotification {
             stream_id: StreamId(Arc::new("abc".to_string())),
             content: MediaNotificationContent::Metadata { data: metadata },
+            annotations: Default::default(),
         });
 }
 
@@ -701,6 +809,7 @@ async fn watchers_requiring_approval_sends_request_to_reactor() {
         .send(RtmpEndpointWatcherNotification::WatcherRequiringApproval {
             stream_key: Arc::new("abc".to_string()),
             connection_id: ConnectionId(Arc::new("def".to_string())),
+            client_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
             response_channel: sender,
         })
         .expect("Failed to send approval request");
@@ -739,6 +848,7 @@ async fn reactor_responding_with_invalid_sends_rejection_response() {
         .send(RtmpEndpointWatcherNotification::WatcherRequiringApproval {
             stream_key: Arc::new("abc".to_string()),
             connection_id: ConnectionId(Arc::new("def".to_string())),
+            client_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
             response_channel: sender,
         })
         .expect("Failed to send approval request");
@@ -774,6 +884,7 @@ async fn reactor_responding_with_valid_sends_approved_response() {
         .send(RtmpEndpointWatcherNotification::WatcherRequiringApproval {
             stream_key: Arc::new("abc".to_string()),
             connection_id: ConnectionId(Arc::new("def".to_string())),
+            client_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
             response_channel: sender,
         })
         .expect("Failed to send approval request");
@@ -795,3 +906,223 @@ async fn reactor_responding_with_valid_sends_approved_response() {
         response => panic!("Unexpected response: {:?}", response),
     }
 }
+
+#[tokio::test]
+async fn watcher_allowed_by_authorizer_is_approved() {
+    let definition = DefinitionBuilder::new().build();
+    let authorizer = TestAuthorizer {
+        authorization: WatchAuthorization::Allow,
+    };
+
+    let mut context =
+        TestContext::new_with_authorizer(definition, Some(Arc::new(authorizer))).unwrap();
+
+    let (notification_channel, _media_channel) = context.accept_registration().await;
+
+    let (sender, receiver) = channel();
+    notification_channel
+        .send(RtmpEndpointWatcherNotification::WatcherRequiringApproval {
+            stream_key: Arc::new("abc".to_string()),
+            connection_id: ConnectionId(Arc::new("def".to_string())),
+            client_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            response_channel: sender,
+        })
+        .expect("Failed to send approval request");
+
+    context.step_context.execute_pending_futures().await;
+    let response = test_utils::expect_oneshot_response(receiver).await;
+    match response {
+        ValidationResponse::Approve { .. } => (),
+        response => panic!("Unexpected response: {:?}", response),
+    }
+}
+
+#[tokio::test]
+async fn watcher_denied_by_authorizer_is_rejected_without_querying_reactor() {
+    let definition = DefinitionBuilder::new()
+        .reactor_name("some_reactor")
+        .build();
+
+    let authorizer = TestAuthorizer {
+        authorization: WatchAuthorization::Deny,
+    };
+
+    let mut context =
+        TestContext::new_with_authorizer(definition, Some(Arc::new(authorizer))).unwrap();
+
+    let (notification_channel, _media_channel) = context.accept_registration().await;
+
+    let (sender, receiver) = channel();
+    notification_channel
+        .send(RtmpEndpointWatcherNotification::WatcherRequiringApproval {
+            stream_key: Arc::new("abc".to_string()),
+            connection_id: ConnectionId(Arc::new("def".to_string())),
+            client_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            response_channel: sender,
+        })
+        .expect("Failed to send approval request");
+
+    context.step_context.execute_pending_futures().await;
+    let response = test_utils::expect_oneshot_response(receiver).await;
+    match response {
+        ValidationResponse::Reject => (),
+        response => panic!("Unexpected response: {:?}", response),
+    }
+
+    test_utils::expect_mpsc_timeout(&mut context.reactor_manager).await;
+}
+
+#[tokio::test]
+async fn registration_requires_approval_when_authorizer_provided() {
+    let definition = DefinitionBuilder::new().build();
+    let authorizer = TestAuthorizer {
+        authorization: WatchAuthorization::Allow,
+    };
+
+    let mut context =
+        TestContext::new_with_authorizer(definition, Some(Arc::new(authorizer))).unwrap();
+
+    let request = test_utils::expect_mpsc_response(&mut context.rtmp_endpoint).await;
+    match request {
+        RtmpEndpointRequest::ListenForWatchers {
+            requires_registrant_approval,
+            ..
+        } => assert!(
+            requires_registrant_approval,
+            "Expected registrant approval to be required"
+        ),
+
+        request => panic!("Unexpected rtmp request seen: {:?}", request),
+    }
+}
+
+#[tokio::test]
+async fn registration_requires_approval_when_part_of_workflow() {
+    let definition = DefinitionBuilder::new().build();
+    let mut context =
+        TestContext::new_with_workflow_watchers(definition, WorkflowWatchers::new()).unwrap();
+
+    let request = test_utils::expect_mpsc_response(&mut context.rtmp_endpoint).await;
+    match request {
+        RtmpEndpointRequest::ListenForWatchers {
+            requires_registrant_approval,
+            ..
+        } => assert!(
+            requires_registrant_approval,
+            "Expected registrant approval to be required"
+        ),
+
+        request => panic!("Unexpected rtmp request seen: {:?}", request),
+    }
+}
+
+#[tokio::test]
+async fn watcher_limit_enforced_across_steps_in_same_workflow() {
+    let watchers = limited_watchers(2, WatcherLimitBehavior::Reject);
+    let mut first_step = TestContext::new_with_workflow_watchers(
+        DefinitionBuilder::new().port(1234).build(),
+        watchers.clone(),
+    )
+    .unwrap();
+
+    let mut second_step = TestContext::new_with_workflow_watchers(
+        DefinitionBuilder::new().port(5678).build(),
+        watchers.clone(),
+    )
+    .unwrap();
+
+    let (first_notifications, _first_media) = first_step.accept_registration().await;
+    let (second_notifications, _second_media) = second_step.accept_registration().await;
+
+    let response = request_watch(&mut first_step, &first_notifications, "a").await;
+    assert!(
+        matches!(response, ValidationResponse::Approve { .. }),
+        "Expected first watcher to be approved"
+    );
+
+    let response = request_watch(&mut second_step, &second_notifications, "b").await;
+    assert!(
+        matches!(response, ValidationResponse::Approve { .. }),
+        "Expected second watcher to be approved"
+    );
+
+    let response = request_watch(&mut first_step, &first_notifications, "c").await;
+    assert!(
+        matches!(response, ValidationResponse::Reject),
+        "Expected third watcher to be rejected by the first step"
+    );
+
+    let response = request_watch(&mut second_step, &second_notifications, "d").await;
+    assert!(
+        matches!(response, ValidationResponse::Reject),
+        "Expected third watcher to be rejected by the second step"
+    );
+
+    assert_eq!(watchers.current_count(), 2, "Unexpected watcher count");
+}
+
+#[tokio::test]
+async fn stopped_watcher_frees_room_in_workflow() {
+    let watchers = limited_watchers(1, WatcherLimitBehavior::Reject);
+    let definition = DefinitionBuilder::new().build();
+    let mut context =
+        TestContext::new_with_workflow_watchers(definition, watchers.clone()).unwrap();
+    let (notification_channel, _media_channel) = context.accept_registration().await;
+
+    let _ = request_watch(&mut context, &notification_channel, "a").await;
+    notification_channel
+        .send(RtmpEndpointWatcherNotification::WatcherStopped {
+            connection_id: ConnectionId(Arc::new("a".to_string())),
+            stream_key: Arc::new("abc".to_string()),
+        })
+        .expect("Failed to send watcher stopped notification");
+
+    context.step_context.execute_pending_futures().await;
+    assert_eq!(watchers.current_count(), 0, "Unexpected watcher count");
+
+    let response = request_watch(&mut context, &notification_channel, "b").await;
+    assert!(
+        matches!(response, ValidationResponse::Approve { .. }),
+        "Expected watcher to be approved after the first one stopped"
+    );
+}
+
+#[tokio::test]
+async fn full_workflow_redirects_watcher_to_full_slate() {
+    let watchers = limited_watchers(1, WatcherLimitBehavior::Slate);
+    let definition = DefinitionBuilder::new().full_slate_key("full").build();
+    let mut context =
+        TestContext::new_with_workflow_watchers(definition, watchers.clone()).unwrap();
+    let (notification_channel, _media_channel) = context.accept_registration().await;
+
+    let _ = request_watch(&mut context, &notification_channel, "a").await;
+    let response = request_watch(&mut context, &notification_channel, "b").await;
+    match response {
+        ValidationResponse::Redirect { stream_key } => {
+            assert_eq!(stream_key.as_str(), "full", "Unexpected stream key");
+        }
+
+        response => panic!("Unexpected response: {:?}", response),
+    }
+
+    assert_eq!(
+        watchers.current_count(),
+        1,
+        "Slate watchers should not be counted"
+    );
+}
+
+#[tokio::test]
+async fn full_workflow_rejects_watcher_when_no_full_slate_configured() {
+    let watchers = limited_watchers(1, WatcherLimitBehavior::Slate);
+    let definition = DefinitionBuilder::new().build();
+    let mut context = TestContext::new_with_workflow_watchers(definition, watchers).unwrap();
+    let (notification_channel, _media_channel) = context.accept_registration().await;
+
+    let _ = request_watch(&mut context, &notification_channel, "a").await;
+    let response = request_watch(&mut context, &notification_channel, "b").await;
+    assert!(
+        matches!(response, ValidationResponse::Reject),
+        "Expected watcher to be rejected"
+    );
+}
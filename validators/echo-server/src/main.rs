@@ -3,7 +3,7 @@ use futures::stream::FuturesUnordered;
 use futures::{FutureExt, StreamExt};
 use log::{debug, error, info, warn};
 use mmids_core::net::tcp::{
-    start_socket_manager, OutboundPacket, TcpSocketRequest, TcpSocketResponse,
+    start_socket_manager, OutboundPacket, SocketOptions, TcpSocketRequest, TcpSocketResponse,
 };
 use mmids_core::net::ConnectionId;
 use std::collections::HashMap;
@@ -30,6 +30,7 @@ pub async fn main() {
         port: 8888,
         response_channel: response_sender,
         use_tls: false,
+        socket_options: SocketOptions::default(),
     };
 
     debug!("Opening port 8888");
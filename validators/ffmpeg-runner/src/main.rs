@@ -58,6 +58,7 @@ fn hls_test() -> FfmpegParams {
     FfmpegParams {
         read_in_real_time: false,
         input: "C:\\users\\me\\Documents\\bbb.flv".to_string(),
+        timed_metadata_input: None,
         video_transcode: VideoTranscodeParams::H264 {
             preset: H264Preset::UltraFast,
         },
@@ -71,6 +72,7 @@ fn hls_test() -> FfmpegParams {
             path: "c:\\temp\\test\\hlstest.m3u8".to_string(),
             max_entries: None,
             segment_length: 2,
+            segment_filename: None,
         },
     }
 }
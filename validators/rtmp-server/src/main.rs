@@ -1,4 +1,5 @@
 use log::{error, info, warn};
+use mmids_core::codecs::VIDEO_CODEC_H264_AVC;
 use mmids_core::net::tcp::start_socket_manager;
 
 use mmids_rtmp::rtmp_server::{
@@ -139,6 +140,7 @@ pub async fn main() {
 
                     RtmpEndpointPublisherMessage::NewVideoData {
                         publisher,
+                        codec,
                         data,
                         timestamp,
                         is_keyframe,
@@ -150,6 +152,11 @@ pub async fn main() {
                             announce_video_data = false;
                         }
 
+                        // Watchers are only sent h264 video
+                        if codec != *VIDEO_CODEC_H264_AVC {
+                            continue;
+                        }
+
                         let stream_key = match publisher_stream_key_map.get(&publisher) {
                             Some(x) => x,
                             None => {